@@ -13,10 +13,45 @@
  * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
  */
 
+use std::cell::RefCell;
+use std::cmp::max;
+use std::iter::repeat;
 use std::mem::size_of;
+use std::sync::Arc;
 
 use bytes::{BufMut, Bytes, BytesMut};
 
+use super::amplify::WriteKind;
+use super::compress::{self, Compression};
+use super::dedup::MIN_DEDUP_LEN;
+use super::hot::{HotArena, HotStats};
+use super::journal::{crc32, crc32_update};
+use super::limits::{Limits, HARD_MAX_KEY_LEN};
+use super::table::{Entry, Object, Table, INLINE_CAP};
+
+// The bit in an object's key length that is set if it's value is compressed. Keys can never be
+// this long; refer to limits::HARD_MAX_KEY_LEN.
+const COMPRESSED: u16 = 1 << 15;
+
+// The bit in an object's key length that is set if the key length is followed by a checksum.
+const CHECKSUMMED: u16 = 1 << 14;
+
+// Every bit in an object's key length that is not part of the length.
+const FLAGS: u16 = COMPRESSED | CHECKSUMMED;
+
+// The size of the checksum on a checksummed object.
+const CHECKSUM_LEN: usize = 4;
+
+// The size of each core's buffer for decompressed values. Decompressed values are carved out of
+// this buffer, and a new one is allocated once it fills up; the old one is freed once every value
+// carved out of it is dropped. Values larger than this get a buffer of their own.
+const SCRATCH_SIZE: usize = 64 * 1024;
+
+thread_local! {
+    // The buffer decompressed values on this core are carved out of.
+    static SCRATCH: RefCell<BytesMut> = RefCell::new(BytesMut::new());
+}
+
 /// This type represents the memory allocator in Sandstorm. The allocator
 /// allocates and initializes objects that can then be inserted into a
 /// particular tenant's hash table. Each allocated object has the following
@@ -26,7 +61,26 @@ use bytes::{BufMut, Bytes, BytesMut};
 ///     | Tenant-ID | Table-ID  | Key-Length |     Key     |       Value       |
 ///     |___________|___________|____________|_____________|___________________|
 ///        4 Bytes     8 Bytes     2 Bytes      Var Length       Var Length
-pub struct Allocator {}
+///
+/// If the top bit of the key length is set, then the value is compressed, and
+/// consists of it's raw length (4 Bytes, little-endian) followed by an LZ4 block.
+/// If the next bit is set, then the key length is followed by a CRC32 (4 Bytes,
+/// little-endian) of the rest of the object, with the value as stored; refer
+/// to Allocator::checksum().
+///
+/// Tables index objects by their key. By default, the index holds a slice of
+/// the object's key. On tables with split keys (Table::set_split_keys()), it
+/// holds a copy of the key instead, so that lookups never touch objects. The
+/// object keeps it's own copy of the key either way, so it resolves the same.
+pub struct Allocator {
+    // The largest keys and values the server accepts. Checked by callers before they allocate
+    // an object, so that they can tell which of the two was too large. The allocator itself
+    // only refuses keys past limits::HARD_MAX_KEY_LEN.
+    limits: Limits,
+
+    // The arena the hottest objects are moved into, if there is one. Refer to hot.
+    hot: Option<Arc<HotArena>>,
+}
 
 // Implementation of methods on Allocator.
 impl Allocator {
@@ -36,7 +90,57 @@ impl Allocator {
     /// # Return
     /// An allocator of type `Allocator`.
     pub fn new() -> Allocator {
-        Allocator {}
+        Allocator {
+            limits: Limits::default(),
+            hot: None,
+        }
+    }
+
+    /// Returns the largest keys and values the server accepts.
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    /// Sets the largest keys and values the server accepts. Refer to limits::Limits.
+    ///
+    /// # Arguments
+    ///
+    /// * `limits`: The new limits.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Returns the arena the hottest objects are moved into, if there is one.
+    pub fn hot_arena(&self) -> Option<&Arc<HotArena>> {
+        self.hot.as_ref()
+    }
+
+    /// Sets the arena the hottest objects are moved into. Objects are only moved by a walk over
+    /// the tables; refer to hot::Walk.
+    ///
+    /// # Arguments
+    ///
+    /// * `arena`: The arena, or None if objects should stay where they were allocated.
+    pub fn set_hot_arena(&mut self, arena: Option<Arc<HotArena>>) {
+        self.hot = arena;
+    }
+
+    /// Counts a native get() against the hot arena, if there is one, along with whether the
+    /// object it read was served out of the arena.
+    ///
+    /// # Arguments
+    ///
+    /// * `object`: The object the get() read, as returned by Table::get().
+    #[inline]
+    pub fn count_hot(&self, object: &[u8]) {
+        if let Some(ref arena) = self.hot {
+            arena.count(object);
+        }
+    }
+
+    /// Returns the counters of the hot arena, or None if there is no arena.
+    pub fn hot_stats(&self) -> Option<HotStats> {
+        self.hot.as_ref().map(|arena| arena.stats())
     }
 
     /// This method allocates space for an object, and writes metadata and only
@@ -62,10 +166,16 @@ impl Allocator {
     ///
     /// # Return
     /// A `BytesMut` to the underlying allocation. Any writes to this handle
-    /// will be added to the object's value.
+    /// will be added to the object's value. None if the key is longer than
+    /// limits::HARD_MAX_KEY_LEN.
     pub fn raw(&self, tenant: u32, table: u64, key: &[u8], val_len: u64)
                -> Option<BytesMut>
     {
+        // The key's length would not fit in the object's metadata.
+        if key.len() > HARD_MAX_KEY_LEN {
+            return None;
+        }
+
         // Allocate space for the object.
         match self.alloc(tenant, table, key.len() as u16, val_len) {
             // The allocation was successfull.
@@ -102,10 +212,16 @@ impl Allocator {
     /// A tupule corresponding to the allocated object. The first member is a
     /// `Bytes` handle over the underlying object's key. The second, is again a
     /// `Bytes` handle to the entire object. Returning both these handles allows
-    /// for easy insertion into the tenant's table.
+    /// for easy insertion into the tenant's table. None if the key is longer
+    /// than limits::HARD_MAX_KEY_LEN.
     pub fn object(&self, tenant: u32, table: u64, key: &[u8], val: &[u8])
                   -> Option<(Bytes, Bytes)>
     {
+        // The key's length would not fit in the object's metadata.
+        if key.len() > HARD_MAX_KEY_LEN {
+            return None;
+        }
+
         // Allocate space for the object.
         match self.alloc(tenant, table, key.len() as u16, val.len() as u64) {
             // The allocation was successfull.
@@ -164,7 +280,9 @@ impl Allocator {
     }
 
     /// This method takes in a previously allocated object, and returns a handle
-    /// to it's key, and a handle to it's value.
+    /// to it's key, and a handle to it's value. If the value is compressed, it
+    /// is decompressed into a buffer owned by the returned handle, so callers
+    /// always see the raw value.
     ///
     /// # Arguments
     ///
@@ -174,28 +292,441 @@ impl Allocator {
     /// A tupule consisting of two `Bytes`. The first is a handle to the passed
     /// in object's key, and the second is a handle to it's value.
     pub fn resolve(&self, object: Bytes) -> Option<(Bytes, Bytes)> {
-        // Read the two bytes corresponding to the key length from the object.
+        // Read the offsets of the key and value off the object's metadata,
+        // and return a Bytes handle to the object's key and a Bytes handle
+        // to the object's value.
+        let (key, val) = self.offsets(&object)?;
+
+        let key = object.slice(key, val);
+        if !self.is_compressed(&object) {
+            return Some((key, object.slice_from(val)));
+        }
+
+        self.decompress(&object[val..]).map(| value | (key, value))
+    }
+
+    /// This method returns a handle to a previously allocated object's key.
+    /// Unlike resolve(), it only reads the object's metadata and key, and
+    /// never touches or decompresses the value.
+    ///
+    /// # Arguments
+    ///
+    /// * `object`: A previously allocated object.
+    ///
+    /// # Return
+    /// A `Bytes` handle to the object's key.
+    pub fn resolve_key(&self, object: &Bytes) -> Option<Bytes> {
+        let (key, val) = self.offsets(object)?;
+        Some(object.slice(key, val))
+    }
+
+    /// This method returns the length of a previously allocated object's
+    /// value, as resolve() would return it. Only a compressed value is read,
+    /// and only for it's raw length; it is never decompressed.
+    ///
+    /// # Arguments
+    ///
+    /// * `object`: A previously allocated object.
+    pub fn value_len(&self, object: &[u8]) -> Option<usize> {
+        let (_, val) = self.offsets(object)?;
+        if !self.is_compressed(object) {
+            return Some(object.len() - val);
+        }
+
+        if object.len() - val < 4 {
+            return None;
+        }
+        Some((0..4).fold(0, | acc, i | acc | (object[val + i] as usize) << (8 * i)))
+    }
+
+    /// This method returns true if an object's value is compressed. Code that
+    /// writes into an object's value in place must check this first, since a
+    /// compressed value cannot be modified without being decompressed.
+    ///
+    /// # Arguments
+    ///
+    /// * `object`: A previously allocated object.
+    pub fn is_compressed(&self, object: &[u8]) -> bool {
+        object.get(self.meta_size() - 1)
+              .map_or(false, | b | (*b as u16) << 8 & COMPRESSED != 0)
+    }
+
+    /// This method returns true if an object carries a checksum. Refer to
+    /// checksum() and verify().
+    ///
+    /// # Arguments
+    ///
+    /// * `object`: A previously allocated object.
+    pub fn is_checksummed(&self, object: &[u8]) -> bool {
+        object.get(self.meta_size() - 1)
+              .map_or(false, | b | (*b as u16) << 8 & CHECKSUMMED != 0)
+    }
+
+    /// This method adds a checksum to an object. The checksum is a CRC32 of
+    /// the object's metadata, key and value as stored (compressed, if it is),
+    /// so it must be added after the value is compressed. The object is
+    /// copied, since the checksum sits right after the key length. Objects
+    /// that already have a checksum, or that do not resolve, are returned as
+    /// is.
+    ///
+    /// # Arguments
+    ///
+    /// * `key`:    A handle to the object's key.
+    /// * `object`: The object, without a checksum.
+    ///
+    /// # Return
+    /// A tupule consisting of a handle to the key and a handle to the object
+    /// that should be written to the table.
+    pub fn checksum(&self, key: Bytes, object: Bytes) -> (Bytes, Bytes) {
+        let meta = self.meta_size();
+        let (key_len, val) = match (self.raw_key_len(&object), self.offsets(&object)) {
+            (Some(key_len), Some((_, val))) if key_len & CHECKSUMMED == 0 => (key_len, val),
+
+            _ => return (key, object),
+        };
+
+        // Copy over the metadata with the checksummed bit set, followed by the
+        // checksum and the key and value.
+        let mut sealed = BytesMut::with_capacity(object.len() + CHECKSUM_LEN);
+        sealed.put_slice(&object[..meta - 2]);
+        sealed.put_u16_le(key_len | CHECKSUMMED);
+        let crc = crc32_update(crc32(&sealed[..]), &object[meta..]);
+        sealed.put_u32_le(crc);
+        sealed.put_slice(&object[meta..]);
+        let sealed = sealed.freeze();
+
+        (sealed.slice(meta + CHECKSUM_LEN, val + CHECKSUM_LEN), sealed)
+    }
+
+    /// This method checks an object against it's checksum. Reads do not call
+    /// this on their own, since it costs a pass over the whole object.
+    ///
+    /// # Arguments
+    ///
+    /// * `object`: A previously allocated object.
+    ///
+    /// # Return
+    /// None if the object does not carry a checksum. Otherwise, true if the
+    /// object matches it's checksum, and false if it does not.
+    pub fn verify(&self, object: &[u8]) -> Option<bool> {
+        if !self.is_checksummed(object) {
+            return None;
+        }
+
+        // A key length that runs past the end of the object is corrupt too.
+        let meta = self.meta_size();
+        if self.offsets(object).is_none() {
+            return Some(false);
+        }
+
+        let stored = (0..CHECKSUM_LEN).fold(0, | acc, i | {
+            acc | (object[meta + i] as u32) << (8 * i)
+        });
+        let crc = crc32_update(crc32(&object[..meta]), &object[meta + CHECKSUM_LEN..]);
+        Some(crc == stored)
+    }
+
+    /// This method compresses an object's value if the table it is being
+    /// written to is configured to do so, and the value is large and
+    /// compressible enough. Otherwise, the object is returned as is.
+    ///
+    /// # Arguments
+    ///
+    /// * `key`:         A handle to the object's key.
+    /// * `object`:      The object, with a raw value.
+    /// * `compression`: The compression settings of the table.
+    ///
+    /// # Return
+    /// A tupule consisting of a handle to the key and a handle to the object
+    /// that should be written to the table.
+    pub fn compress(&self, key: Bytes, object: Bytes, compression: &Compression)
+                    -> (Bytes, Bytes)
+    {
         let meta = self.meta_size();
-        let (left, right) = (object.get(meta - 2), object.get(meta - 1));
+        let val = meta + key.len();
+        if object.len() < val + compression.threshold || key.len() > HARD_MAX_KEY_LEN
+            || self.is_compressed(&object) || self.is_checksummed(&object)
+        {
+            return (key, object);
+        }
+
+        // The compressed value, prefixed with it's raw length.
+        let raw_len = object.len() - val;
+        let mut block = Vec::with_capacity(raw_len);
+        for i in 0..4 {
+            block.push((raw_len >> (8 * i)) as u8);
+        }
+        compress::compress(&object[val..], &mut block);
 
-        match (left, right) {
-            // The above read was successfull. Compute the key length assuming
-            // little endian, and return Bytes handle to the object's key and a
-            // Bytes handle to the object's value.
-            (Some(lb), Some(rb)) => {
-                let key_len = (*lb as u16) + (*rb as u16) * 256;
+        if (raw_len as f64) < compression.min_ratio * block.len() as f64 {
+            compress::record_rejected();
+            return (key, object);
+        }
+        compress::record_compressed(raw_len, block.len());
+
+        // Copy over the metadata and key, setting the compressed bit on the key length.
+        let key_len = key.len() as u16 | COMPRESSED;
+        let mut compressed = BytesMut::with_capacity(val + block.len());
+        compressed.put_slice(&object[..meta - 2]);
+        compressed.put_u16_le(key_len);
+        compressed.put_slice(&key);
+        compressed.put_slice(&block);
+        let compressed = compressed.freeze();
+
+        (compressed.slice(meta, val), compressed)
+    }
 
-                Some((object.slice(meta, meta + key_len as usize),
-                    object.slice_from(meta + key_len as usize)))
+    /// This method writes an object into a table, compressing it's value
+    /// first if the table is configured to do so. The write is accounted for
+    /// on the table's WriteStats, as a put of the object's value. On a table
+    /// that shares values, a value identical to one already on it allocates
+    /// nothing but the object's metadata and key.
+    ///
+    /// # Arguments
+    ///
+    /// * `table`:  The table the object is being written to.
+    /// * `key`:    A handle to the object's key.
+    /// * `object`: The object, with a raw value.
+    ///
+    /// # Return
+    /// Refer to `Table::put()`.
+    pub fn store(&self, table: &Table, key: Bytes, object: Bytes)
+                 -> Option<Entry>
+    {
+        let logical = self.value_len(&object).unwrap_or(0);
+        let (key, object) = self.prepare(table, key, object);
+        let (key, object, physical) = self.dedup(table, key, object);
+        if !table.read_only() {
+            table.writes().record(WriteKind::Put, &key, logical, physical);
+        }
+        table.put_object(key, object)
+    }
+
+    /// This method writes an object into a table like store() does, but only
+    /// if the value currently under it's key is the one expected. The lock on
+    /// the key's bucket is held from the comparison until the write (refer to
+    /// Table::update()), so no other write to the key interleaves with it.
+    /// The value is never shared with other objects, even on a table that
+    /// shares values.
+    ///
+    /// # Arguments
+    ///
+    /// * `table`:    The table the object is being written to.
+    /// * `key`:      A handle to the object's key.
+    /// * `object`:   The object, with a raw value.
+    /// * `expected`: The value expected under the key, or None if the key is
+    ///               expected to not exist.
+    ///
+    /// # Return
+    /// What store() would have returned if the object was written, or an
+    /// error if the value under the key was not the one expected. None if
+    /// the table cannot be written to.
+    pub fn compare_and_store(
+        &self,
+        table: &Table,
+        key: Bytes,
+        object: Bytes,
+        expected: Option<&[u8]>,
+    ) -> Option<Result<Option<Entry>, ()>> {
+        let logical = self.value_len(&object).unwrap_or(0);
+        let lookup = key.clone();
+        table.update(&lookup, |old| {
+            let current = old.and_then(|entry| self.resolve(entry.value).map(|(_, value)| value));
+            if current.as_ref().map(|value| &value[..]) != expected {
+                return Err(());
             }
 
-            // The key length could not be read from the passed in object.
-            _ => {
-                return None;
+            let (key, object) = self.prepare(table, key, object);
+            table
+                .writes()
+                .record(WriteKind::Put, &key, logical, object.len());
+            Ok((key, object))
+        })
+    }
+
+    /// This method writes a batch of objects into a table such that readers
+    /// see either none or all of them, compressing values first if the table
+    /// is configured to do so. Each write is accounted for on the table's
+    /// WriteStats.
+    ///
+    /// # Arguments
+    ///
+    /// * `table`:   The table the objects are being written to.
+    /// * `objects`: A handle to the key of each object, along with the
+    ///              object itself, with a raw value.
+    ///
+    /// # Return
+    /// Refer to `Table::put_many()`.
+    pub fn store_many(&self, table: &Table, objects: Vec<(Bytes, Bytes)>)
+                      -> Option<Vec<Option<Entry>>>
+    {
+        let read_only = table.read_only();
+        let objects = objects.into_iter()
+                             .map(| (key, object) | {
+                                 let logical = self.value_len(&object).unwrap_or(0);
+                                 let (key, object) = self.prepare(table, key, object);
+                                 let (key, object, physical) = self.dedup(table, key, object);
+                                 if !read_only {
+                                     table.writes().record(WriteKind::MultiPut, &key,
+                                                           logical, physical);
+                                 }
+                                 (key, object)
+                             })
+                             .collect();
+        table.put_objects(objects)
+    }
+
+    /// This method deletes an object from a table. The delete is accounted
+    /// for on the table's WriteStats like store() accounts for a put. On a
+    /// table that shares values, the object's hold on it's value is released
+    /// along with it, so a value no other object holds is freed.
+    ///
+    /// # Arguments
+    ///
+    /// * `table`: The table the object is being deleted from.
+    /// * `key`:   The key of the object to be deleted.
+    ///
+    /// # Return
+    /// Refer to `Table::delete()`.
+    pub fn delete(&self, table: &Table, key: &[u8]) -> bool {
+        if !table.delete(key) {
+            return false;
+        }
+
+        table.writes().record(WriteKind::Delete, key, 0, 0);
+        true
+    }
+
+    /// This method compresses an object, adds a checksum to it, and copies
+    /// out it's key, as configured on the table it is about to be written to. store() does
+    /// this on it's own; it is only needed by writes that go straight to the
+    /// table (ex: through Table::update()).
+    ///
+    /// # Arguments
+    ///
+    /// * `table`:  The table the object is about to be written to.
+    /// * `key`:    A handle to the object's key.
+    /// * `object`: The object, with a raw value.
+    ///
+    /// # Return
+    /// The key and object to be written to the table.
+    pub fn prepare(&self, table: &Table, key: Bytes, object: Bytes) -> (Bytes, Bytes) {
+        let (key, object) = match table.compression() {
+            Some(compression) => self.compress(key, object, compression),
+            None => (key, object),
+        };
+
+        // The checksum covers the value as stored, so it goes on last.
+        let (key, object) = match table.checksums() {
+            true => self.checksum(key, object),
+            false => (key, object),
+        };
+
+        // Index the object by a copy of it's key if the table asks for it.
+        // Copies of short keys are held inline by the handle itself.
+        let key = match table.split_keys() {
+            true => Bytes::from(&key[..]),
+            false => key,
+        };
+
+        (key, object)
+    }
+
+    // This method splits an object's value off to be shared with identical
+    // values on the table, if the table shares values (refer to
+    // Table::set_dedup()). Values that are short, or whose object is held
+    // inline, are left in place. If an identical value is already on the
+    // table, the object's metadata and key are copied out so that the rest
+    // of it can be freed.
+    //
+    // - `return`: The key and object to be written to the table, along with
+    //             the number of bytes allocated for the object that it holds
+    //             on to.
+    fn dedup(&self, table: &Table, key: Bytes, object: Bytes) -> (Bytes, Object, usize) {
+        let len = object.len();
+        let inline = len <= INLINE_CAP && table.inline_values();
+        let (key_off, val_off) = match self.offsets(&object) {
+            Some((key, val)) if len - val >= MIN_DEDUP_LEN && !inline => (key, val),
+
+            _ => return (key, Object::Whole(object), len),
+        };
+        let dedup = match table.dedup() {
+            Some(dedup) => dedup,
+            None => return (key, Object::Whole(object), len),
+        };
+
+        let (share, shared) = dedup.share(object.slice_from(val_off));
+        if !shared {
+            return (key, Object::Split(object.slice_to(val_off), share), len);
+        }
+
+        let head = Bytes::from(&object[..val_off]);
+        let key = match table.split_keys() {
+            true => key,
+            false => head.slice(key_off, val_off),
+        };
+        let len = head.len();
+        (key, Object::Split(head, share), len)
+    }
+
+    // This method decompresses a value into this core's scratch buffer.
+    //
+    // - `block`: The raw length of the value followed by the compressed value.
+    //
+    // - `return`: A handle to the decompressed value, or None if the value is
+    //             corrupt.
+    fn decompress(&self, block: &[u8]) -> Option<Bytes> {
+        if block.len() < 4 {
+            return None;
+        }
+        let raw_len = (0..4).fold(0, | acc, i | acc | (block[i] as usize) << (8 * i));
+
+        SCRATCH.with(| scratch | {
+            let mut scratch = scratch.borrow_mut();
+            if scratch.capacity() < raw_len {
+                *scratch = BytesMut::with_capacity(max(SCRATCH_SIZE, raw_len));
+            }
+
+            scratch.extend(repeat(0).take(raw_len));
+            match compress::decompress(&block[4..], &mut scratch[..]) {
+                Some(()) => Some(scratch.split_to(raw_len).freeze()),
+
+                None => {
+                    scratch.clear();
+                    None
+                }
             }
+        })
+    }
+
+    // This method reads the length of an object's key off it's metadata,
+    // along with the flags kept in it's top bits.
+    fn raw_key_len(&self, object: &[u8]) -> Option<u16> {
+        let meta = self.meta_size();
+        match (object.get(meta - 2), object.get(meta - 1)) {
+            (Some(lb), Some(rb)) => Some((*lb as u16) + (*rb as u16) * 256),
+
+            _ => None,
         }
     }
 
+    // This method returns the offsets of an object's key and value, or None
+    // if the object is too short to hold it's metadata and key.
+    fn offsets(&self, object: &[u8]) -> Option<(usize, usize)> {
+        let key_len = self.raw_key_len(object)?;
+        let key = match key_len & CHECKSUMMED {
+            0 => self.meta_size(),
+            _ => self.meta_size() + CHECKSUM_LEN,
+        };
+
+        let val = key + (key_len & !FLAGS) as usize;
+        if val > object.len() {
+            return None;
+        }
+
+        Some((key, val))
+    }
+
     // This method returns the amount of metadata on each allocated object.
     #[inline]
     fn meta_size(&self) -> usize {
@@ -210,7 +741,12 @@ impl Allocator {
 #[cfg(test)]
 mod tests {
     use super::Allocator;
+    use super::super::amplify::WriteKind;
+    use super::super::compress::Compression;
+    use super::super::limits::HARD_MAX_KEY_LEN;
+    use super::super::table::Table;
     use bytes::{BufMut, BytesMut};
+    use rand::{Rng, SeedableRng, XorShiftRng};
 
     // This unit test verifies the return value of the "meta_size()" method
     // on Allocator.
@@ -220,6 +756,22 @@ mod tests {
         assert_eq!(14, heap.meta_size());
     }
 
+    // This unit test verifies that keys whose length would not fit in an
+    // object's metadata are refused, instead of having their length truncated.
+    #[test]
+    fn test_hard_max_key_len() {
+        let heap = Allocator::new();
+        let key = vec![7; HARD_MAX_KEY_LEN];
+
+        let (_, obj) = heap.object(1, 1, &key, &[1]).unwrap();
+        let (k, v) = heap.resolve(obj).unwrap();
+        assert_eq!((&key[..], &[1][..]), (&k[..], &v[..]));
+
+        let key = vec![7; HARD_MAX_KEY_LEN + 1];
+        assert!(heap.object(1, 1, &key, &[1]).is_none());
+        assert!(heap.raw(1, 1, &key, 1).is_none());
+    }
+
     // This unit test tests the functionality of the "resolve()" method on
     // Allocator.
     #[test]
@@ -343,4 +895,287 @@ mod tests {
             }
         }
     }
+
+    // Writes a value into a table through store(), reads it back through
+    // resolve(), and returns whether it was stored compressed.
+    fn store_and_resolve(heap: &Allocator, table: &Table, val: &[u8]) -> bool {
+        let key: &[u8] = &[1, 2, 3, 4];
+        let (k, obj) = heap.object(0, 11, key, val)
+                            .expect("Failed to allocate object.");
+        heap.store(table, k, obj);
+
+        let entry = table.get(key).expect("Failed to lookup object.");
+        let (k, v) = heap.resolve(entry.value.clone())
+                            .expect("Failed to resolve object.");
+        assert_eq!(key[..], k[..]);
+        assert_eq!(val[..], v[..]);
+
+        heap.is_compressed(&entry.value)
+    }
+
+    // This unit test round trips values through a table with compression
+    // enabled, and verifies which of them are stored compressed.
+    #[test]
+    fn test_compressed_round_trip() {
+        let heap = Allocator::new();
+        let table = Table::with_compression(Compression::new(64, 2.0));
+
+        // Compressible values are compressed once they reach the threshold.
+        assert!(!store_and_resolve(&heap, &table, &[7; 63]));
+        assert!(store_and_resolve(&heap, &table, &[7; 64]));
+        assert!(store_and_resolve(&heap, &table, &[7; 100000]));
+
+        // Values that do not shrink by the minimum ratio are stored raw.
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        let val: Vec<u8> = (0..1024).map(|_| rng.gen::<u8>()).collect();
+        assert!(!store_and_resolve(&heap, &table, &val));
+
+        // Tables without compression always store values raw.
+        assert!(!store_and_resolve(&heap, &Table::default(), &[7; 1024]));
+    }
+
+    // This unit test verifies that a corrupt compressed value fails to
+    // resolve instead of returning garbage.
+    #[test]
+    fn test_resolve_corrupt() {
+        let heap = Allocator::new();
+        let compression = Compression::new(16, 0.0).unwrap();
+
+        let (k, obj) = heap.object(0, 11, &[1, 2], &[9; 256])
+                            .expect("Failed to allocate object.");
+        let (_, obj) = heap.compress(k, obj, &compression);
+        assert!(heap.is_compressed(&obj));
+
+        let truncated = obj.slice_to(obj.len() - 1);
+        assert!(heap.resolve(truncated).is_none());
+    }
+
+    // This unit test verifies that objects stored into a table with split
+    // keys resolve exactly as they would out of a regular table.
+    #[test]
+    fn test_split_keys() {
+        let heap = Allocator::new();
+        let table = Table::default();
+        table.set_split_keys(true);
+
+        assert!(!store_and_resolve(&heap, &table, &[7; 100]));
+
+        // Overwrites go to the same entry, and are visible to readers.
+        let (k, obj) = heap.object(0, 11, &[1, 2, 3, 4], &[8; 10])
+                            .expect("Failed to allocate object.");
+        heap.store(&table, k, obj);
+        let entry = table.get(&[1, 2, 3, 4]).expect("Failed to lookup object.");
+        assert_eq!(2, entry.version.0);
+
+        let (k, v) = heap.resolve(entry.value).expect("Failed to resolve object.");
+        assert_eq!([1, 2, 3, 4], k[..]);
+        assert_eq!([8; 10], v[..]);
+    }
+
+    // This unit test verifies Allocator's "resolve_key()" and "value_len()"
+    // methods on both raw and compressed objects.
+    #[test]
+    fn test_resolve_key_value_len() {
+        let heap = Allocator::new();
+        let compression = Compression::new(16, 0.0).unwrap();
+
+        let (k, obj) = heap.object(0, 11, &[1, 2], &[9; 256])
+                            .expect("Failed to allocate object.");
+        assert_eq!([1, 2], heap.resolve_key(&obj).unwrap()[..]);
+        assert_eq!(Some(256), heap.value_len(&obj));
+
+        let (_, obj) = heap.compress(k, obj, &compression);
+        assert!(heap.is_compressed(&obj));
+        assert_eq!([1, 2], heap.resolve_key(&obj).unwrap()[..]);
+        assert_eq!(Some(256), heap.value_len(&obj));
+
+        // Objects too short to hold their metadata or key do not resolve.
+        assert!(heap.resolve_key(&obj.slice_to(heap.meta_size())).is_none());
+        assert!(heap.value_len(&obj[..heap.meta_size() - 1]).is_none());
+    }
+
+    // This unit test verifies that checksummed objects resolve exactly like
+    // raw ones, compressed or not, and fail to verify once any of their
+    // bytes change.
+    #[test]
+    fn test_checksum() {
+        let heap = Allocator::new();
+        let (k, obj) = heap.object(3, 11, &[1, 2], &[9; 256])
+                            .expect("Failed to allocate object.");
+        assert_eq!(None, heap.verify(&obj));
+
+        let (k, sealed) = heap.checksum(k, obj.clone());
+        assert!(heap.is_checksummed(&sealed) && !heap.is_compressed(&sealed));
+        assert_eq!(Some(true), heap.verify(&sealed));
+        assert_eq!([1, 2], k[..]);
+        assert_eq!([1, 2], heap.resolve_key(&sealed).unwrap()[..]);
+        assert_eq!(Some(256), heap.value_len(&sealed));
+        assert_eq!(heap.resolve(obj).unwrap(), heap.resolve(sealed.clone()).unwrap());
+
+        // A second checksum is not added, and checksummed objects are not compressed.
+        let compression = Compression::new(16, 0.0).unwrap();
+        assert_eq!(sealed, heap.checksum(k.clone(), sealed.clone()).1);
+        assert_eq!(sealed, heap.compress(k, sealed.clone(), &compression).1);
+
+        // Changing any byte, the metadata included, fails the object.
+        for i in 0..sealed.len() {
+            let mut corrupt = BytesMut::from(&sealed[..]);
+            corrupt[i] ^= 0x10;
+            assert_eq!(Some(false), heap.verify(&corrupt), "byte {}", i);
+        }
+
+        // Tables with checksums compress values first, and checksum them as stored.
+        let table = Table::with_compression(Compression::new(64, 2.0));
+        table.set_checksums(true);
+        assert!(store_and_resolve(&heap, &table, &[7; 1024]));
+        let entry = table.get(&[1, 2, 3, 4]).expect("Failed to lookup object.");
+        assert!(heap.is_checksummed(&entry.value));
+        assert_eq!(Some(true), heap.verify(&entry.value));
+        assert_eq!(Some(1024), heap.value_len(&entry.value));
+    }
+
+    // This unit test verifies that store() and store_many() account for the
+    // value of each object written against the object allocated for it.
+    #[test]
+    fn test_write_accounting() {
+        let heap = Allocator::new();
+        let table = Table::default();
+
+        for len in [100, 10].iter() {
+            let (k, obj) = heap.object(0, 11, &[1, 2, 3, 4], &vec![7; *len])
+                                .expect("Failed to allocate object.");
+            heap.store(&table, k, obj);
+        }
+        let batch = (0..3u8).map(| i | heap.object(0, 11, &[i], &[i; 20]).unwrap())
+                            .collect();
+        heap.store_many(&table, batch);
+
+        let totals = table.writes().totals();
+        assert_eq!((2, 110, 2 * 14 + 2 * 4 + 110),
+                   (totals[0].count, totals[0].logical, totals[0].physical));
+        assert_eq!((3, 60, 3 * 14 + 3 + 60),
+                   (totals[1].count, totals[1].logical, totals[1].physical));
+
+        // Compressed objects are accounted for at their compressed size.
+        let table = Table::with_compression(Compression::new(64, 2.0));
+        let (k, obj) = heap.object(0, 11, &[1], &[7; 1024]).unwrap();
+        heap.store(&table, k, obj);
+        let totals = table.writes().totals();
+        let stored = table.get(&[1]).unwrap().value.len() as u64;
+        assert_eq!((1, 1024, stored), (totals[0].count, totals[0].logical, totals[0].physical));
+        assert!(stored < 1024);
+    }
+
+    // This unit test verifies that identical values on a table with dedup
+    // enabled share memory, that they are freed with the last object holding
+    // them, and that mutating one object leaves the others as they were.
+    #[test]
+    fn test_dedup_lifecycle() {
+        let heap = Allocator::new();
+        let table = Table::default();
+        table.set_dedup(Some(16));
+        table.set_checksums(true);
+
+        for key in [[1], [2], [3]].iter() {
+            let (k, obj) = heap.object(0, 11, key, &[7; 100]).unwrap();
+            heap.store(&table, k, obj);
+        }
+        let stats = table.dedup_stats().unwrap();
+        assert_eq!((2, 1, 200), (stats.hits, stats.misses, stats.bytes_saved));
+
+        // Shared objects resolve and verify like any other.
+        for key in [[1], [2], [3]].iter() {
+            let entry = table.get(key).unwrap();
+            assert_eq!(Some(true), heap.verify(&entry.value));
+            let (k, v) = heap.resolve(entry.value).unwrap();
+            assert_eq!((&key[..], &[7; 100][..]), (&k[..], &v[..]));
+        }
+
+        // Updates write a new object instead of modifying the shared value.
+        table.update(&[2], | old | {
+            let (_, v) = heap.resolve(old.unwrap().value).unwrap();
+            let v: Vec<u8> = v.iter().map(| b | b + 1).collect();
+            Ok::<_, ()>(heap.object(0, 11, &[2], &v).unwrap())
+        }).unwrap().unwrap();
+        assert_eq!([8; 100], heap.resolve(table.get(&[2]).unwrap().value).unwrap().1[..]);
+        assert_eq!([7; 100], heap.resolve(table.get(&[3]).unwrap().value).unwrap().1[..]);
+        assert_eq!(100, table.dedup_stats().unwrap().bytes_saved);
+
+        // Once every holder is gone, the value is no longer shared.
+        let (k, obj) = heap.object(0, 11, &[1], &[9; 100]).unwrap();
+        heap.store(&table, k, obj);
+        assert!(heap.delete(&table, &[3]));
+        assert_eq!(0, table.dedup_stats().unwrap().bytes_saved);
+        assert_eq!(1, table.writes().totals()[WriteKind::Delete as usize].count);
+        assert!(!heap.delete(&table, &[3]));
+        assert_eq!(1, table.writes().totals()[WriteKind::Delete as usize].count);
+        let (k, obj) = heap.object(0, 11, &[4], &[7; 100]).unwrap();
+        heap.store(&table, k, obj);
+        assert_eq!(3, table.dedup_stats().unwrap().misses);
+
+        // Short values are never shared.
+        for key in [[5], [6]].iter() {
+            let (k, obj) = heap.object(0, 11, key, &[7; 10]).unwrap();
+            heap.store(&table, k, obj);
+        }
+        assert_eq!(3, table.dedup_stats().unwrap().misses);
+    }
+
+    // Tests that an object is only written by compare_and_store() if the
+    // value under it's key is the one expected, including on a table that
+    // compresses values.
+    #[test]
+    fn test_compare_and_store() {
+        let heap = Allocator::new();
+        let compressed = Table::with_compression(Compression::new(64, 2.0));
+        for table in [Table::default(), compressed].iter() {
+            let write = |val: &[u8], expected: Option<&[u8]>| {
+                let (k, obj) = heap.object(0, 1, &[1], val).unwrap();
+                heap.compare_and_store(table, k, obj, expected)
+                    .expect("Table is read-only")
+                    .is_ok()
+            };
+
+            // The write only goes through against the value now on the table.
+            assert!(!write(&[1; 100], Some(&[])));
+            assert!(write(&[1; 100], None));
+            assert!(!write(&[2; 100], None));
+            assert!(!write(&[2; 100], Some(&[2; 100])));
+            assert!(write(&[2; 100], Some(&[1; 100])));
+
+            let (_, v) = heap.resolve(table.get(&[1]).unwrap().value).unwrap();
+            assert_eq!(&[2; 100][..], &v[..]);
+        }
+    }
+
+    // This unit test fills a table with a million identical values, and
+    // verifies that the table holds one copy of the value.
+    #[test]
+    fn test_dedup_fill() {
+        let heap = Allocator::new();
+        let table = Table::default();
+        table.set_dedup(Some(0));
+        table.set_split_keys(true);
+
+        const N: u64 = 1000 * 1000;
+        let val = [42; 100];
+        for i in 0..N as u32 {
+            let key = [i as u8, (i >> 8) as u8, (i >> 16) as u8, (i >> 24) as u8];
+            let (k, obj) = heap.object(0, 11, &key, &val).unwrap();
+            heap.store(&table, k, obj);
+        }
+
+        let stats = table.dedup_stats().unwrap();
+        assert_eq!((N - 1, 1, 0), (stats.hits, stats.misses, stats.collisions));
+        assert_eq!((N - 1) * 100, stats.bytes_saved);
+        assert!((stats.hit_rate() - 1.0).abs() < 1e-5);
+
+        // Only the first object allocated room for the value.
+        let totals = table.writes().totals();
+        assert_eq!(N * 100, totals[0].logical);
+        assert_eq!(N * 18 + 100, totals[0].physical);
+
+        let (k, v) = heap.resolve(table.get(&[1, 2, 3, 0]).unwrap().value).unwrap();
+        assert_eq!((&[1, 2, 3, 0][..], &val[..]), (&k[..], &v[..]));
+    }
 }
@@ -0,0 +1,531 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use std::cmp;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use spin::Mutex;
+
+use super::cycles;
+
+/// The number of keys the server tracks the write volume of per table, if the server config
+/// does not say otherwise. 0 means that keys are not tracked.
+pub const DEFAULT_HOT_KEYS: usize = 0;
+
+// The number of keys tracked per table. Shared by all tables, so that it can be set once at
+// startup instead of on every table as it is created.
+static HOT_KEYS: AtomicUsize = AtomicUsize::new(DEFAULT_HOT_KEYS);
+
+/// Sets the number of keys whose write volume is tracked on each table. Tracking takes a lock on
+/// every write, so it is meant for debugging; 0 turns it off.
+///
+/// # Arguments
+///
+/// * `capacity`: The number of keys tracked per table.
+pub fn set_hot_keys(capacity: usize) {
+    HOT_KEYS.store(capacity, Ordering::Relaxed);
+}
+
+/// Returns the number of keys whose write volume is tracked on each table.
+pub fn hot_keys() -> usize {
+    HOT_KEYS.load(Ordering::Relaxed)
+}
+
+/// The interval at which the volumes of tracked keys are halved, in milliseconds, if the server
+/// config does not say otherwise. 0 means that volumes never decay.
+pub const DEFAULT_HOT_KEYS_DECAY_MS: u64 = 0;
+
+// The interval at which the volumes of tracked keys are halved, in milliseconds. Shared by all
+// tables like HOT_KEYS.
+static HOT_KEYS_DECAY_MS: AtomicUsize = AtomicUsize::new(DEFAULT_HOT_KEYS_DECAY_MS as usize);
+
+/// Sets the interval at which the volumes of the keys tracked on each table are halved, so that
+/// the hottest keys are the ones written to the most recently instead of since the server
+/// started. Can be changed while the server runs; the current epoch of each table is cut short
+/// or stretched to the new interval.
+///
+/// # Arguments
+///
+/// * `ms`: The interval, in milliseconds. 0 stops volumes from decaying.
+pub fn set_hot_keys_decay_ms(ms: u64) {
+    HOT_KEYS_DECAY_MS.store(ms as usize, Ordering::Relaxed);
+}
+
+/// Returns the interval at which the volumes of tracked keys are halved, in milliseconds.
+pub fn hot_keys_decay_ms() -> u64 {
+    HOT_KEYS_DECAY_MS.load(Ordering::Relaxed) as u64
+}
+
+// Returns the interval at which the volumes of tracked keys are halved, in cycles.
+fn decay_cycles() -> u64 {
+    hot_keys_decay_ms() * cycles::cycles_per_second() / 1000
+}
+
+/// The kinds of writes accounted for by WriteStats.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WriteKind {
+    /// A single object written whole, by a put() or by an extension.
+    Put = 0,
+
+    /// An object written whole as part of a batch, by a multiput() or by an extension.
+    MultiPut = 1,
+
+    /// A delta merged into an object, which is rewritten whole. Refer to merge::merge().
+    Merge = 2,
+
+    /// An object removed by a delete() or by an extension. Nothing is written or allocated for
+    /// it, so only the count goes up.
+    Delete = 3,
+}
+
+/// The number of kinds of writes.
+pub const N_WRITE_KINDS: usize = 4;
+
+/// Every kind of write, in the order WriteStats::totals() returns them in.
+pub const WRITE_KINDS: [WriteKind; N_WRITE_KINDS] = [
+    WriteKind::Put,
+    WriteKind::MultiPut,
+    WriteKind::Merge,
+    WriteKind::Delete,
+];
+
+/// The writes of one kind made to a table since the server started.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct WriteTotals {
+    /// The number of writes.
+    pub count: u64,
+
+    /// The bytes the writers asked to write: the value on a put, the delta on a merge, and
+    /// nothing on a delete.
+    pub logical: u64,
+
+    /// The bytes allocated for the objects written, including their keys and metadata, after
+    /// compression.
+    pub physical: u64,
+}
+
+impl WriteTotals {
+    /// Returns the bytes allocated per byte asked to be written, or 0 if nothing was.
+    pub fn ratio(&self) -> f64 {
+        match self.logical {
+            0 => 0.0,
+            logical => self.physical as f64 / logical as f64,
+        }
+    }
+}
+
+/// A key whose physical write volume is tracked by WriteStats.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HotKey {
+    /// The key.
+    pub key: Vec<u8>,
+
+    /// The physical bytes written to the key. Never below the true volume.
+    pub bytes: u64,
+
+    /// The most `bytes` can be over the true volume by. The key may have been written to before
+    /// it was tracked, and is charged with the volume of the key it displaced.
+    pub error: u64,
+}
+
+/// How the volumes of the keys tracked by HotKeys decay, so that whoever reads them knows the
+/// window of writes they cover.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Decay {
+    /// The interval at which volumes are halved, in milliseconds. 0 if they never decay, in
+    /// which case they cover every write since the keys started being tracked.
+    pub interval_ms: u64,
+
+    /// The number of times volumes were halved. A write made `n` epochs ago counts for 1/2^n of
+    /// it's bytes.
+    pub epochs: u64,
+
+    /// How far into the current epoch the volumes were read, in milliseconds.
+    pub elapsed_ms: u64,
+}
+
+/// Finds the keys with the highest physical write volume, using Space-Saving (Metwally et al.)
+/// weighted by bytes: a bounded set of keys is tracked, and a write to an untracked key
+/// displaces the one with the least volume, taking over it's count. Any key whose true volume
+/// is over 1/capacity of the total is always tracked.
+///
+/// If asked to, the volumes decay: time is cut into epochs, and at the end of each one every
+/// volume is halved, so a key that was hot a while ago falls behind one that is hot now. The
+/// halving is done on the next write or read after an epoch ends instead of by a sweep, which
+/// is cheap since there are atmost `capacity` keys, and safe since it's done under the same
+/// lock as the writes.
+pub struct HotKeys {
+    keys: Vec<HotKey>,
+
+    // The time-stamp the current epoch started at, in cycles, or None until the keys are first
+    // written to or read.
+    epoch: Option<u64>,
+
+    // The number of times volumes were halved.
+    epochs: u64,
+}
+
+impl HotKeys {
+    /// Returns a set with no keys tracked.
+    pub fn new() -> HotKeys {
+        HotKeys {
+            keys: Vec::new(),
+            epoch: None,
+            epochs: 0,
+        }
+    }
+
+    /// Accounts for a write to a key.
+    ///
+    /// # Arguments
+    ///
+    /// * `key`:      The key written to.
+    /// * `bytes`:    The physical bytes written.
+    /// * `capacity`: The most keys tracked. Keys over it are dropped if it shrinks.
+    /// * `interval`: The length of an epoch, in cycles. 0 if volumes don't decay.
+    /// * `now`:      The current time-stamp, in cycles.
+    pub fn record(&mut self, key: &[u8], bytes: u64, capacity: usize, interval: u64, now: u64) {
+        self.decay(interval, now);
+        if let Some(hot) = self.keys.iter_mut().find(|hot| &hot.key[..] == key) {
+            hot.bytes += bytes;
+            return;
+        }
+
+        if self.keys.len() < capacity {
+            self.keys.push(HotKey {
+                key: key.to_vec(),
+                bytes: bytes,
+                error: 0,
+            });
+            return;
+        }
+
+        self.keys.sort_by(|a, b| b.bytes.cmp(&a.bytes));
+        self.keys.truncate(capacity);
+        if let Some(min) = self.keys.last_mut() {
+            min.key = key.to_vec();
+            min.error = min.bytes;
+            min.bytes += bytes;
+        }
+    }
+
+    /// Returns the tracked keys, highest volume first.
+    ///
+    /// # Arguments
+    ///
+    /// * `interval`: The length of an epoch, in cycles. 0 if volumes don't decay.
+    /// * `now`:      The current time-stamp, in cycles.
+    pub fn top(&mut self, interval: u64, now: u64) -> Vec<HotKey> {
+        self.decay(interval, now);
+        let mut top = self.keys.clone();
+        top.sort_by(|a, b| b.bytes.cmp(&a.bytes));
+        top
+    }
+
+    /// Returns the number of times volumes were halved, and how far into the current epoch a
+    /// time-stamp is, in cycles.
+    ///
+    /// # Arguments
+    ///
+    /// * `now`: The time-stamp, in cycles.
+    pub fn epochs(&self, now: u64) -> (u64, u64) {
+        let start = self.epoch.unwrap_or(now);
+        (self.epochs, now.saturating_sub(start))
+    }
+
+    // Halves every volume once for each epoch that ended since the current one started, and
+    // drops the keys whose volume decayed to nothing. Volumes don't decay while the interval is
+    // 0; the epoch restarts at `now` instead, so that decay turned on later starts from then.
+    fn decay(&mut self, interval: u64, now: u64) {
+        let start = match (self.epoch, interval) {
+            (Some(start), interval) if interval > 0 => start,
+            _ => {
+                self.epoch = Some(now);
+                return;
+            }
+        };
+
+        let ended = now.saturating_sub(start) / interval;
+        if ended == 0 {
+            return;
+        }
+
+        self.epoch = Some(start + ended * interval);
+        self.epochs += ended;
+        let shift = cmp::min(ended, 64) as u32;
+        for hot in self.keys.iter_mut() {
+            hot.bytes = hot.bytes.checked_shr(shift).unwrap_or(0);
+            hot.error = hot.error.checked_shr(shift).unwrap_or(0);
+        }
+        self.keys.retain(|hot| hot.bytes > 0);
+    }
+}
+
+// The counters of one kind of write. Relaxed atomics, so that writers on every core add to them
+// without taking a lock.
+#[derive(Default)]
+struct Counters {
+    count: AtomicUsize,
+    logical: AtomicUsize,
+    physical: AtomicUsize,
+}
+
+/// Accounts for the writes made to a table, comparing the bytes writers asked to write against
+/// the bytes allocated for the objects they wrote. A merge rewrites an object whole to change
+/// a few bytes of it, so merge-heavy tables allocate far more than they are asked to.
+#[derive(Default)]
+pub struct WriteStats {
+    kinds: [Counters; N_WRITE_KINDS],
+    hot: Mutex<Option<HotKeys>>,
+}
+
+impl WriteStats {
+    /// Accounts for a write.
+    ///
+    /// # Arguments
+    ///
+    /// * `kind`:     The kind of write.
+    /// * `key`:      The key written to. Only read if keys are tracked, refer to set_hot_keys().
+    /// * `logical`:  The bytes the writer asked to write.
+    /// * `physical`: The bytes allocated for the object written.
+    pub fn record(&self, kind: WriteKind, key: &[u8], logical: usize, physical: usize) {
+        let counters = &self.kinds[kind as usize];
+        counters.count.fetch_add(1, Ordering::Relaxed);
+        counters.logical.fetch_add(logical, Ordering::Relaxed);
+        counters.physical.fetch_add(physical, Ordering::Relaxed);
+
+        let capacity = hot_keys();
+        if capacity > 0 {
+            let (interval, now) = (decay_cycles(), cycles::rdtsc());
+            let mut hot = self.hot.lock();
+            let hot = hot.get_or_insert_with(HotKeys::new);
+            hot.record(key, physical as u64, capacity, interval, now);
+        }
+    }
+
+    /// Returns the totals of each kind of write, in the order of WRITE_KINDS.
+    pub fn totals(&self) -> [WriteTotals; N_WRITE_KINDS] {
+        let mut totals = [WriteTotals::default(); N_WRITE_KINDS];
+        for (total, counters) in totals.iter_mut().zip(self.kinds.iter()) {
+            total.count = counters.count.load(Ordering::Relaxed) as u64;
+            total.logical = counters.logical.load(Ordering::Relaxed) as u64;
+            total.physical = counters.physical.load(Ordering::Relaxed) as u64;
+        }
+        totals
+    }
+
+    /// Returns the keys with the highest physical write volume, highest first. Empty unless keys
+    /// are tracked, refer to set_hot_keys(). Volumes decay if asked to, refer to
+    /// set_hot_keys_decay_ms().
+    pub fn hot_keys(&self) -> Vec<HotKey> {
+        let (interval, now) = (decay_cycles(), cycles::rdtsc());
+        self.hot
+            .lock()
+            .as_mut()
+            .map_or(Vec::new(), |hot| hot.top(interval, now))
+    }
+
+    /// Returns how the volumes returned by hot_keys() decay.
+    pub fn hot_keys_decay(&self) -> Decay {
+        let (hz, now) = (cycles::cycles_per_second(), cycles::rdtsc());
+        let (epochs, elapsed) = self
+            .hot
+            .lock()
+            .as_ref()
+            .map_or((0, 0), |hot| hot.epochs(now));
+        Decay {
+            interval_ms: hot_keys_decay_ms(),
+            epochs: epochs,
+            elapsed_ms: elapsed * 1000 / hz,
+        }
+    }
+}
+
+// This module contains unit tests for WriteStats and HotKeys.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+    use std::thread;
+
+    // Tests that a scripted sequence of writes adds up to hand computed totals.
+    #[test]
+    fn test_write_totals() {
+        let stats = WriteStats::default();
+        stats.record(WriteKind::Put, b"a", 100, 120);
+        stats.record(WriteKind::Put, b"b", 50, 70);
+        stats.record(WriteKind::MultiPut, b"c", 10, 30);
+        for i in 0..10 {
+            stats.record(WriteKind::Merge, b"a", 4, 124 + 4 * i);
+        }
+
+        let totals = stats.totals();
+        let expected = WriteTotals {
+            count: 2,
+            logical: 150,
+            physical: 190,
+        };
+        assert_eq!(expected, totals[WriteKind::Put as usize]);
+        assert_eq!(
+            WriteTotals {
+                count: 1,
+                logical: 10,
+                physical: 30,
+            },
+            totals[WriteKind::MultiPut as usize]
+        );
+        assert_eq!(
+            WriteTotals {
+                count: 10,
+                logical: 40,
+                physical: 1420,
+            },
+            totals[WriteKind::Merge as usize]
+        );
+        assert_eq!(35.5, totals[WriteKind::Merge as usize].ratio());
+        assert_eq!(0.0, WriteTotals::default().ratio());
+    }
+
+    // Tests that a key written to far more than the rest is tracked with about it's true
+    // volume, even though there are many more keys than slots.
+    #[test]
+    fn test_hot_keys() {
+        let mut hot = HotKeys::new();
+        let mut total = 0;
+        for i in 0..1000u32 {
+            let cold = [(i >> 8) as u8, i as u8, 1];
+            hot.record(&cold, 10, 8, 0, 0);
+
+            // The hot key is first written once every slot is taken, so it displaces a key.
+            if i % 10 == 9 {
+                hot.record(b"hot", 100, 8, 0, 0);
+                total += 100;
+            }
+        }
+
+        let top = hot.top(0, 0);
+        assert_eq!(8, top.len());
+        assert_eq!(b"hot".to_vec(), top[0].key);
+        assert!(top[0].bytes >= total);
+        assert!(top[0].bytes - top[0].error <= total);
+        assert!(top[0].error <= (10 * 1000 + total) / 8);
+    }
+
+    // Tests that keys are not tracked unless asked to be.
+    #[test]
+    fn test_hot_keys_disabled() {
+        let stats = WriteStats::default();
+        stats.record(WriteKind::Put, b"a", 1, 20);
+        assert!(stats.hot_keys().is_empty());
+    }
+
+    // Tests that a write to an untracked key displaces the key with the least volume, and
+    // takes over it's volume as error.
+    #[test]
+    fn test_hot_keys_displace() {
+        let mut hot = HotKeys::new();
+        hot.record(b"a", 20, 2, 0, 0);
+        hot.record(b"b", 10, 2, 0, 0);
+        hot.record(b"a", 5, 2, 0, 0);
+        hot.record(b"c", 1, 2, 0, 0);
+        assert_eq!(
+            vec![
+                HotKey {
+                    key: b"a".to_vec(),
+                    bytes: 25,
+                    error: 0,
+                },
+                HotKey {
+                    key: b"c".to_vec(),
+                    bytes: 11,
+                    error: 10,
+                },
+            ],
+            hot.top(0, 0)
+        );
+    }
+
+    // Tests that a key written to in a burst decays behind a key written to since, by half for
+    // every epoch that ended, and that the decay is reported.
+    #[test]
+    fn test_hot_keys_decay() {
+        let mut hot = HotKeys::new();
+        hot.record(b"a", 1600, 8, 1000, 0);
+        for epoch in 1..5 {
+            hot.record(b"b", 100, 8, 1000, epoch * 1000 + 1);
+        }
+
+        let top = hot.top(1000, 4001);
+        assert_eq!(b"b".to_vec(), top[0].key);
+        assert_eq!(50 + 25 + 12 + 100, top[0].bytes);
+        assert_eq!(b"a".to_vec(), top[1].key);
+        assert_eq!(1600 / 16, top[1].bytes);
+        assert_eq!((4, 1), hot.epochs(4001));
+
+        // Epochs with no writes at all decay the volumes just the same.
+        let top = hot.top(1000, 6500);
+        assert_eq!((187 / 4, 100 / 4), (top[0].bytes, top[1].bytes));
+        assert_eq!((6, 500), hot.epochs(6500));
+
+        // Keys that decay to nothing are no longer tracked.
+        assert!(hot.top(1000, 70_000).is_empty());
+        assert_eq!(70, hot.epochs(70_000).0);
+    }
+
+    // Tests that volumes don't decay while the interval is 0, and that the first epoch starts
+    // when decay is turned on instead of when the keys were first written to.
+    #[test]
+    fn test_hot_keys_no_decay() {
+        let mut hot = HotKeys::new();
+        hot.record(b"a", 1600, 8, 0, 0);
+        hot.record(b"a", 400, 8, 0, 1_000_000);
+        assert_eq!(2000, hot.top(0, 2_000_000)[0].bytes);
+        assert_eq!((0, 0), hot.epochs(2_000_000));
+
+        assert_eq!(2000, hot.top(1000, 2_000_999)[0].bytes);
+        assert_eq!(1000, hot.top(1000, 2_001_000)[0].bytes);
+        assert_eq!((1, 0), hot.epochs(2_001_000));
+    }
+
+    // Tests that epochs ending while several threads write to the same keys neither lose
+    // epochs nor leave a key with more than was written to it.
+    #[test]
+    fn test_hot_keys_decay_concurrent() {
+        let hot = Arc::new(Mutex::new(HotKeys::new()));
+        let threads: Vec<_> = (0..4u64)
+            .map(|t| {
+                let hot = Arc::clone(&hot);
+                thread::spawn(move || {
+                    for i in 0..10_000u64 {
+                        let key = [(i % 16) as u8];
+                        hot.lock().record(&key, 1 + t, 8, 1000, i * 10 + t);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().expect("Writer panicked");
+        }
+
+        // The first epoch starts at whichever thread wrote first, within 4 cycles of 0.
+        let mut hot = hot.lock();
+        let top = hot.top(1000, 100_500);
+        assert_eq!(100, hot.epochs(100_500).0);
+        assert!(top.len() <= 8);
+        assert!(top.iter().all(|key| key.bytes >= key.error));
+        assert!(top.iter().map(|key| key.bytes).sum::<u64>() <= 10_000 * (1 + 2 + 3 + 4));
+    }
+}
@@ -0,0 +1,412 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use std::cmp;
+use std::collections::VecDeque;
+use std::sync::atomic::{fence, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// The number of entries an audit log holds, if not set in the server config.
+pub const DEFAULT_ENTRIES: usize = 1024;
+
+/// The number of stripes invocations are recorded into. Each scheduler thread records into a
+/// stripe of it's own, so long as there are no more threads than stripes.
+const STRIPES: usize = 16;
+
+// Hands out stripes to threads, round robin.
+static NEXT_STRIPE: AtomicUsize = AtomicUsize::new(0);
+
+thread_local!(
+    // The stripe this thread records invocations into.
+    static STRIPE: usize = NEXT_STRIPE.fetch_add(1, Ordering::Relaxed) % STRIPES;
+);
+
+/// What an audit log records about a completed invocation. Entries never carry keys, values, or
+/// arguments; only metadata about the invocation.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct AuditEntry {
+    /// The position of the entry in the tenant's log. Assigned when the entry is merged into the
+    /// log, starting at 1; later entries have larger numbers.
+    pub seq: u64,
+
+    /// The time-stamp, in server cycles, at which the invocation completed.
+    pub stamp: u64,
+
+    /// A hash of the name of the extension invoked. Refer to name_hash().
+    pub name_hash: u64,
+
+    /// The identifier on the invocation's RPC.
+    pub id: u64,
+
+    /// The status the invocation completed with, as an RpcStatus.
+    pub status: u8,
+
+    /// The length of the arguments on the invocation.
+    pub args_len: u32,
+
+    /// The length of the response to the invocation, header excluded.
+    pub res_len: u32,
+
+    /// The number of cycles the invocation ran for.
+    pub cycles: u64,
+}
+
+/// Hashes the name of an extension for an audit entry (64 bit FNV-1a). Tenants can hash the
+/// names of their own extensions to tell entries apart.
+pub fn name_hash(name: &[u8]) -> u64 {
+    name.iter().fold(0xcbf29ce484222325, |hash, b| {
+        (hash ^ *b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+// A slot on a stripe. `seq` is 0 while the slot is being written, and the number of the write
+// plus one once it has been; readers check it before and after copying the slot out.
+struct Slot {
+    seq: AtomicU64,
+    words: [AtomicU64; 6],
+}
+
+impl Slot {
+    fn new() -> Slot {
+        Slot {
+            seq: AtomicU64::new(0),
+            words: Default::default(),
+        }
+    }
+}
+
+// A ring of slots written to by a single thread (or a few, if there are more threads than
+// stripes).
+struct Stripe {
+    // The number of slots claimed so far.
+    head: AtomicU64,
+
+    // The slots. Slot `i % len` holds the `i`th write.
+    slots: Box<[Slot]>,
+}
+
+// The entries merged in off the stripes.
+struct Merged {
+    // The newest entries, oldest first.
+    entries: VecDeque<AuditEntry>,
+
+    // The number of writes merged in off each stripe.
+    taken: [u64; STRIPES],
+
+    // The number the next entry merged in is assigned.
+    next_seq: u64,
+}
+
+/// A tenant's audit log: a bounded ring of entries for the tenant's most recent invocations.
+///
+/// Completions record into per-thread stripes without taking a lock: a slot is claimed off an
+/// uncontended counter, and written with a handful of stores. Stripes are merged into the log
+/// lazily, under a lock, whenever the log is read. Each stripe is as long as the log, so an
+/// entry is never lost to a stripe wrapping unless it would have fallen out of the log anyway.
+pub struct AuditLog {
+    // The number of entries the log holds.
+    capacity: usize,
+
+    // The stripes completions are recorded into.
+    stripes: Vec<Stripe>,
+
+    // The entries merged in so far.
+    merged: Mutex<Merged>,
+}
+
+impl AuditLog {
+    /// Creates an empty audit log.
+    ///
+    /// # Arguments
+    ///
+    /// * `capacity`: The number of entries the log holds. Older entries are dropped. 0 means
+    ///               DEFAULT_ENTRIES.
+    pub fn new(capacity: usize) -> AuditLog {
+        let capacity = match capacity {
+            0 => DEFAULT_ENTRIES,
+            n => n,
+        };
+
+        AuditLog {
+            capacity: capacity,
+            stripes: (0..STRIPES)
+                .map(|_| Stripe {
+                    head: AtomicU64::new(0),
+                    slots: (0..capacity)
+                        .map(|_| Slot::new())
+                        .collect::<Vec<_>>()
+                        .into(),
+                })
+                .collect(),
+            merged: Mutex::new(Merged {
+                entries: VecDeque::with_capacity(capacity),
+                taken: [0; STRIPES],
+                next_seq: 1,
+            }),
+        }
+    }
+
+    /// Returns the number of entries the log holds.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Records a completed invocation. Never blocks.
+    ///
+    /// # Arguments
+    ///
+    /// * `entry`: The invocation. Its `seq` is ignored; one is assigned when it is merged.
+    #[inline]
+    pub fn record(&self, entry: &AuditEntry) {
+        let stripe = &self.stripes[STRIPE.with(|s| *s)];
+        let n = stripe.head.fetch_add(1, Ordering::Relaxed);
+        let slot = &stripe.slots[(n % stripe.slots.len() as u64) as usize];
+
+        slot.seq.store(0, Ordering::Relaxed);
+        fence(Ordering::Release);
+
+        let words = [
+            entry.stamp,
+            entry.name_hash,
+            entry.id,
+            entry.status as u64 | (entry.args_len as u64) << 8,
+            entry.res_len as u64,
+            entry.cycles,
+        ];
+        for (word, value) in slot.words.iter().zip(words.iter()) {
+            word.store(*value, Ordering::Relaxed);
+        }
+
+        slot.seq.store(n + 1, Ordering::Release);
+    }
+
+    // Copies out write `n` off a slot, or returns None if it is still being written, or was
+    // already overwritten by a later one.
+    fn read(slot: &Slot, n: u64) -> Option<AuditEntry> {
+        if slot.seq.load(Ordering::Acquire) != n + 1 {
+            return None;
+        }
+
+        let mut words = [0; 6];
+        for (word, value) in slot.words.iter().zip(words.iter_mut()) {
+            *value = word.load(Ordering::Relaxed);
+        }
+
+        fence(Ordering::Acquire);
+        if slot.seq.load(Ordering::Relaxed) != n + 1 {
+            return None;
+        }
+
+        Some(AuditEntry {
+            seq: 0,
+            stamp: words[0],
+            name_hash: words[1],
+            id: words[2],
+            status: words[3] as u8,
+            args_len: (words[3] >> 8) as u32,
+            res_len: words[4] as u32,
+            cycles: words[5],
+        })
+    }
+
+    // Merges whatever was recorded since the last merge into the log, in the order the entries
+    // were stamped.
+    fn merge(&self, merged: &mut Merged) {
+        let mut fresh = Vec::new();
+        for (i, stripe) in self.stripes.iter().enumerate() {
+            let head = stripe.head.load(Ordering::Acquire);
+            let len = stripe.slots.len() as u64;
+            let mut n = cmp::max(merged.taken[i], head.saturating_sub(len));
+
+            while n < head {
+                let slot = &stripe.slots[(n % len) as usize];
+                match AuditLog::read(slot, n) {
+                    Some(entry) => fresh.push(entry),
+
+                    // Overwritten already; the entry is too old to keep anyway.
+                    None if slot.seq.load(Ordering::Acquire) > n + 1 => {}
+
+                    // Still being written. Pick it up on the next merge.
+                    None => break,
+                }
+                n += 1;
+            }
+
+            merged.taken[i] = n;
+        }
+
+        fresh.sort_by_key(|e| e.stamp);
+        for mut entry in fresh.into_iter() {
+            entry.seq = merged.next_seq;
+            merged.next_seq += 1;
+            if merged.entries.len() == self.capacity {
+                merged.entries.pop_front();
+            }
+            merged.entries.push_back(entry);
+        }
+    }
+
+    /// Returns a page of the log, newest entry first.
+    ///
+    /// # Arguments
+    ///
+    /// * `cursor`: 0 for the newest entries. Otherwise, only entries older than the one numbered
+    ///             `cursor` are returned; pass in the cursor returned with the previous page.
+    /// * `max`:    The most entries on the page. At least one is always returned, if any are left.
+    ///
+    /// # Return
+    ///
+    /// The entries, and the cursor to fetch the next page with; 0 if there are no older entries.
+    pub fn page(&self, cursor: u64, max: usize) -> (Vec<AuditEntry>, u64) {
+        let mut merged = self.merged.lock().unwrap();
+        self.merge(&mut merged);
+
+        let mut older = merged
+            .entries
+            .iter()
+            .rev()
+            .filter(|e| cursor == 0 || e.seq < cursor)
+            .cloned();
+        let page: Vec<AuditEntry> = older.by_ref().take(cmp::max(max, 1)).collect();
+
+        let next = match (older.next(), page.last()) {
+            (Some(_), Some(last)) => last.seq,
+            _ => 0,
+        };
+        (page, next)
+    }
+}
+
+// This module contains unit tests for AuditLog.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+    use std::thread;
+
+    fn entry(id: u64, stamp: u64) -> AuditEntry {
+        AuditEntry {
+            seq: 0,
+            stamp: stamp,
+            name_hash: name_hash(b"auth"),
+            id: id,
+            status: 1,
+            args_len: 40,
+            res_len: 8,
+            cycles: 1000 + id,
+        }
+    }
+
+    // Pages through a log the way a tenant would, returning the ids seen, newest first.
+    fn page_through(log: &AuditLog, max: usize) -> (Vec<u64>, usize) {
+        let (mut ids, mut pages, mut cursor) = (Vec::new(), 0, 0);
+        loop {
+            let (page, next) = log.page(cursor, max);
+            ids.extend(page.iter().map(|e| e.id));
+            pages += 1;
+
+            match next {
+                0 => return (ids, pages),
+                next => cursor = next,
+            }
+        }
+    }
+
+    // Tests that entries are returned newest first, with every field intact.
+    #[test]
+    fn test_audit_order() {
+        let log = AuditLog::new(8);
+        for i in 1..4 {
+            log.record(&entry(i, i * 10));
+        }
+
+        let (page, next) = log.page(0, 8);
+        assert_eq!(0, next);
+        assert_eq!(
+            vec![3, 2, 1],
+            page.iter().map(|e| e.seq).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            AuditEntry {
+                seq: 3,
+                ..entry(3, 30)
+            },
+            page[0]
+        );
+    }
+
+    // Tests that the log keeps only the newest entries once it wraps.
+    #[test]
+    fn test_audit_wrap() {
+        let log = AuditLog::new(4);
+        for i in 1..11 {
+            log.record(&entry(i, i));
+        }
+        assert_eq!((vec![10, 9, 8, 7], 1), page_through(&log, 16));
+
+        // Entries recorded after a read are merged in after the ones already read.
+        log.record(&entry(11, 11));
+        let (page, _) = log.page(0, 1);
+        assert_eq!((11, 11), (page[0].id, page[0].seq));
+        assert_eq!(4, log.page(0, 16).0.len());
+    }
+
+    // Tests that the cursor pages through the log without skipping or repeating entries, even
+    // as new entries are recorded between pages.
+    #[test]
+    fn test_audit_pagination() {
+        let log = AuditLog::new(64);
+        for i in 1..31 {
+            log.record(&entry(i, i));
+        }
+        let (ids, pages) = page_through(&log, 7);
+        assert_eq!((1..31).rev().collect::<Vec<u64>>(), ids);
+        assert_eq!(5, pages);
+
+        let (first, cursor) = log.page(0, 10);
+        log.record(&entry(31, 31));
+        let (second, _) = log.page(cursor, 100);
+        let ids: Vec<u64> = first.iter().chain(second.iter()).map(|e| e.id).collect();
+        assert_eq!((1..31).rev().collect::<Vec<u64>>(), ids);
+
+        assert_eq!((vec![], 0), log.page(1, 10));
+    }
+
+    // Tests that entries recorded concurrently by several threads are all merged in, in the
+    // order they were stamped.
+    #[test]
+    fn test_audit_threads() {
+        let log = Arc::new(AuditLog::new(4 * 100));
+        let handles: Vec<_> = (0..4)
+            .map(|t| {
+                let log = Arc::clone(&log);
+                thread::spawn(move || {
+                    for i in 0..100 {
+                        log.record(&entry(t * 1000 + i, i * 4 + t));
+                    }
+                })
+            })
+            .collect();
+        for handle in handles.into_iter() {
+            handle.join().unwrap();
+        }
+
+        let (page, next) = log.page(0, 1000);
+        assert_eq!((400, 0), (page.len(), next));
+        let stamps: Vec<u64> = page.iter().map(|e| e.stamp).collect();
+        assert_eq!((0..400).rev().collect::<Vec<u64>>(), stamps);
+    }
+}
@@ -0,0 +1,433 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use std::cmp::min;
+use std::mem;
+use std::sync::atomic::{spin_loop_hint, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use libc;
+
+use spin::RwLock;
+
+/// The longest pause a core can be configured to back off for, in microseconds. The server
+/// takes a core that hasn't made a scheduling decision for a millisecond to be stuck on a
+/// misbehaving task, so pauses have to stay well short of that.
+pub const MAX_PAUSE_US: u64 = 500;
+
+/// The number of empty polls in a row after which a core starts to back off, if the config
+/// does not say otherwise.
+pub const DEFAULT_THRESHOLD: u32 = 256;
+
+/// The number of tiers a pause can fall in. Refer to Tier.
+pub const N_TIERS: usize = 2;
+
+// The first pause a core backs off for, in nanoseconds. Every empty poll after doubles it.
+const FIRST_PAUSE_NS: u64 = 250;
+
+// Pauses shorter than this spin; longer ones sleep.
+const SLEEP_MIN_NS: u64 = 10_000;
+
+/// How a core waits out a pause.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Tier {
+    /// Spins on the clock, hinting the CPU that it is spinning. Wakes up on time, but keeps the
+    /// core busy.
+    Spin = 0,
+
+    /// Sleeps, handing the core to the OS. Wakes up a little late.
+    Sleep = 1,
+}
+
+/// How a scheduler core backs off polling once it keeps finding nothing to do.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BackoffPolicy {
+    /// The longest pause, in microseconds. Bounds the latency backoff adds to a request that
+    /// arrives during a pause. 0 disables backoff, and the core spins.
+    pub max_pause_us: u64,
+
+    /// The number of empty polls in a row after which the core starts to pause.
+    pub threshold: u32,
+}
+
+impl BackoffPolicy {
+    /// Returns true if a core running this policy ever pauses.
+    pub fn enabled(&self) -> bool {
+        self.max_pause_us > 0
+    }
+}
+
+impl Default for BackoffPolicy {
+    fn default() -> BackoffPolicy {
+        BackoffPolicy {
+            max_pause_us: 0,
+            threshold: DEFAULT_THRESHOLD,
+        }
+    }
+}
+
+/// The pauses a core took in one tier.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TierStats {
+    /// The number of pauses.
+    pub pauses: u64,
+
+    /// The time spent in them, in nanoseconds.
+    pub nanos: u64,
+}
+
+/// The pauses a core took in each tier, as reported by the core_stats() RPC.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CoreSummary {
+    /// The core.
+    pub core: i32,
+
+    /// The pauses taken in each tier, indexed by Tier.
+    pub tiers: [TierStats; N_TIERS],
+}
+
+/// Counts the pauses a single core took in each tier. Only written to by the core itself, on
+/// polls that found nothing to do.
+pub struct CoreBackoff {
+    // The core the counters belong to.
+    core: i32,
+
+    // The number of pauses, and the nanoseconds spent in them, indexed by Tier.
+    pauses: [AtomicUsize; N_TIERS],
+    nanos: [AtomicUsize; N_TIERS],
+}
+
+impl CoreBackoff {
+    /// Returns counters for a core that has not paused yet.
+    pub fn new(core: i32) -> CoreBackoff {
+        CoreBackoff {
+            core: core,
+            pauses: [AtomicUsize::new(0), AtomicUsize::new(0)],
+            nanos: [AtomicUsize::new(0), AtomicUsize::new(0)],
+        }
+    }
+
+    /// Returns the pauses the core took so far.
+    pub fn summary(&self) -> CoreSummary {
+        let mut summary = CoreSummary {
+            core: self.core,
+            ..CoreSummary::default()
+        };
+
+        for (tier, stats) in summary.tiers.iter_mut().enumerate() {
+            stats.pauses = self.pauses[tier].load(Ordering::Relaxed) as u64;
+            stats.nanos = self.nanos[tier].load(Ordering::Relaxed) as u64;
+        }
+        summary
+    }
+
+    fn record(&self, tier: Tier, nanos: u64) {
+        self.pauses[tier as usize].fetch_add(1, Ordering::Relaxed);
+        self.nanos[tier as usize].fetch_add(nanos as usize, Ordering::Relaxed);
+    }
+}
+
+/// The backoff counters of every scheduler core on the server.
+pub struct Cores {
+    cores: RwLock<Vec<Arc<CoreBackoff>>>,
+}
+
+impl Cores {
+    /// Returns an empty set of counters.
+    pub fn new() -> Cores {
+        Cores {
+            cores: RwLock::new(Vec::new()),
+        }
+    }
+
+    /// Returns the counters of a core so that it can count it's pauses, adding them if the core
+    /// has none yet. A core whose scheduler was replaced keeps counting where it left off.
+    pub fn register(&self, core: i32) -> Arc<CoreBackoff> {
+        let mut cores = self.cores.write();
+        if let Some(stats) = cores.iter().find(|c| c.core == core) {
+            return Arc::clone(stats);
+        }
+
+        let stats = Arc::new(CoreBackoff::new(core));
+        cores.push(Arc::clone(&stats));
+        stats
+    }
+
+    /// Returns the pauses each core took so far, in the order the cores were registered.
+    pub fn summaries(&self) -> Vec<CoreSummary> {
+        self.cores.read().iter().map(|c| c.summary()).collect()
+    }
+}
+
+/// Backs a core off polling once it keeps finding nothing to do, so that an idle server
+/// doesn't keep every core spinning.
+///
+/// Past `threshold` empty polls in a row, each empty poll pauses the core, for twice as long as
+/// the previous one, upto the longest pause in the policy. Short pauses spin, and longer ones
+/// sleep. The first poll that finds something to do resets the count, and the core goes back
+/// to spinning. Polls that do find something only ever test the count against 0; it is only
+/// written to on empty polls, and on the first busy poll after.
+pub struct Backoff {
+    // The number of empty polls in a row.
+    idle: u32,
+
+    // The number of empty polls in a row after which the core starts to pause.
+    threshold: u32,
+
+    // The longest pause, in nanoseconds. 0 if the core never pauses.
+    max_pause_ns: u64,
+
+    // Counters of the pauses taken, shared with the core_stats() RPC.
+    stats: Arc<CoreBackoff>,
+}
+
+impl Backoff {
+    /// Returns a backoff for a core that is busy.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy`: How the core backs off.
+    /// * `stats`:  The core's counters. Refer to Cores::register().
+    pub fn new(policy: BackoffPolicy, stats: Arc<CoreBackoff>) -> Backoff {
+        Backoff {
+            idle: 0,
+            threshold: policy.threshold,
+            max_pause_ns: min(policy.max_pause_us, MAX_PAUSE_US) * 1000,
+            stats: stats,
+        }
+    }
+
+    /// Returns true if the core ever pauses.
+    #[inline]
+    pub fn enabled(&self) -> bool {
+        self.max_pause_ns > 0
+    }
+
+    /// Returns the number of empty polls in a row.
+    #[inline]
+    pub fn idle(&self) -> u32 {
+        self.idle
+    }
+
+    /// Notes that a poll found something to do.
+    #[inline]
+    pub fn busy(&mut self) {
+        if self.idle != 0 {
+            self.idle = 0;
+        }
+    }
+
+    /// Notes that a poll found nothing to do, pausing the core if there have been enough empty
+    /// polls in a row.
+    ///
+    /// # Return
+    ///
+    /// The tier the core paused in and for how many nanoseconds, or None if it did not pause.
+    pub fn empty(&mut self) -> Option<(Tier, u64)> {
+        if !self.enabled() {
+            return None;
+        }
+
+        self.idle = self.idle.saturating_add(1);
+        match self.pause_ns() {
+            0 => None,
+            ns => {
+                let (tier, nanos) = pause(ns);
+                self.stats.record(tier, nanos);
+                Some((tier, nanos))
+            }
+        }
+    }
+
+    /// Returns how long the core pauses for on it's current empty poll, in nanoseconds. 0 if it
+    /// does not pause.
+    pub fn pause_ns(&self) -> u64 {
+        if !self.enabled() || self.idle <= self.threshold {
+            return 0;
+        }
+
+        let doublings = min(self.idle - self.threshold - 1, 32);
+        min(FIRST_PAUSE_NS << doublings, self.max_pause_ns)
+    }
+}
+
+// Pauses the calling thread for `ns` nanoseconds, and returns the tier it paused in and how
+// long it actually paused for.
+fn pause(ns: u64) -> (Tier, u64) {
+    let start = Instant::now();
+    let tier = match ns < SLEEP_MIN_NS {
+        true => {
+            let ns = Duration::from_nanos(ns);
+            while start.elapsed() < ns {
+                spin_loop_hint();
+            }
+            Tier::Spin
+        }
+
+        false => {
+            thread::sleep(Duration::from_nanos(ns));
+            Tier::Sleep
+        }
+    };
+
+    let elapsed = start.elapsed();
+    (
+        tier,
+        elapsed.as_secs() * 1_000_000_000 + elapsed.subsec_nanos() as u64,
+    )
+}
+
+/// Lowers the timer slack of the calling thread to a nanosecond, so that it's sleeps end close
+/// to when they were asked to instead of upto 50 microseconds late.
+pub fn tighten_timer_slack() {
+    let slack: libc::c_ulong = 1;
+    unsafe {
+        libc::prctl(libc::PR_SET_TIMERSLACK, slack);
+    }
+}
+
+/// Returns the CPU time the server process has used so far, in user and in system mode, in
+/// microseconds.
+pub fn cpu_time() -> (u64, u64) {
+    let mut usage: libc::rusage = unsafe { mem::zeroed() };
+    if unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) } != 0 {
+        return (0, 0);
+    }
+
+    let micros = |t: libc::timeval| t.tv_sec as u64 * 1_000_000 + t.tv_usec as u64;
+    (micros(usage.ru_utime), micros(usage.ru_stime))
+}
+
+// This module contains unit tests for Backoff.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_backoff(max_pause_us: u64, threshold: u32) -> Backoff {
+        let policy = BackoffPolicy {
+            max_pause_us: max_pause_us,
+            threshold: threshold,
+        };
+        Backoff::new(policy, Arc::new(CoreBackoff::new(3)))
+    }
+
+    // Tests that pauses start past the threshold, double, and stop at the longest pause.
+    #[test]
+    fn test_backoff_schedule() {
+        let mut backoff = new_backoff(2, 2);
+        let mut pauses = Vec::new();
+        for _ in 0..8 {
+            pauses.push(backoff.empty().map(|(tier, _)| tier));
+            if backoff.idle() > 2 {
+                assert!(backoff.pause_ns() <= 2000);
+            }
+        }
+
+        let spin = Some(Tier::Spin);
+        assert_eq!(vec![None, None, spin, spin, spin, spin, spin, spin], pauses);
+        assert_eq!(2000, backoff.pause_ns());
+
+        let summary = backoff.stats.summary();
+        assert_eq!(3, summary.core);
+        assert_eq!(6, summary.tiers[Tier::Spin as usize].pauses);
+        assert!(summary.tiers[Tier::Spin as usize].nanos >= 250 + 500 + 1000 + 3 * 2000);
+        assert_eq!(TierStats::default(), summary.tiers[Tier::Sleep as usize]);
+    }
+
+    // Tests that long pauses sleep.
+    #[test]
+    fn test_backoff_sleeps() {
+        let mut backoff = new_backoff(20, 0);
+        let tiers: Vec<Tier> = (0..8)
+            .filter_map(|_| backoff.empty())
+            .map(|p| p.0)
+            .collect();
+        assert_eq!(8, tiers.len());
+        assert_eq!(Tier::Spin, tiers[0]);
+        assert_eq!(Tier::Sleep, tiers[7]);
+        assert_eq!(20_000, backoff.pause_ns());
+
+        let summary = backoff.stats.summary();
+        assert_eq!(2, summary.tiers[Tier::Sleep as usize].pauses);
+        assert!(summary.tiers[Tier::Sleep as usize].nanos >= 2 * 16_000);
+    }
+
+    // Tests that a busy poll resets the count, and that a disabled backoff never pauses.
+    #[test]
+    fn test_backoff_busy_disabled() {
+        let mut backoff = new_backoff(1, 1);
+        assert!(backoff.empty().is_none());
+        assert!(backoff.empty().is_some());
+        backoff.busy();
+        assert_eq!(0, backoff.idle());
+        assert!(backoff.empty().is_none());
+
+        let mut backoff = new_backoff(0, 0);
+        assert!(!backoff.enabled());
+        for _ in 0..100 {
+            assert!(backoff.empty().is_none());
+        }
+        assert_eq!(0, backoff.idle());
+        assert_eq!(CoreSummary::default().tiers, backoff.stats.summary().tiers);
+    }
+
+    // Tests that pauses are capped at MAX_PAUSE_US, however long the policy asks for.
+    #[test]
+    fn test_backoff_cap() {
+        let mut backoff = new_backoff(1_000_000, 0);
+        backoff.idle = 64;
+        assert_eq!(MAX_PAUSE_US * 1000, backoff.pause_ns());
+    }
+
+    // Tests that every core's counters are reported once, in the order they were registered.
+    #[test]
+    fn test_cores() {
+        let cores = Cores::new();
+        let (a, b) = (cores.register(2), cores.register(1));
+        a.record(Tier::Sleep, 100);
+        a.record(Tier::Sleep, 50);
+        b.record(Tier::Spin, 7);
+        cores.register(2).record(Tier::Spin, 1);
+
+        let summaries = cores.summaries();
+        assert_eq!(
+            vec![2, 1],
+            summaries.iter().map(|s| s.core).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            TierStats {
+                pauses: 2,
+                nanos: 150
+            },
+            summaries[0].tiers[Tier::Sleep as usize]
+        );
+        assert_eq!(1, summaries[0].tiers[Tier::Spin as usize].pauses);
+        assert_eq!(1, summaries[1].tiers[Tier::Spin as usize].pauses);
+    }
+
+    // Tests that the process is charged some CPU time.
+    #[test]
+    fn test_cpu_time() {
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_millis(20) {
+            spin_loop_hint();
+        }
+
+        let (user, system) = cpu_time();
+        assert!(user + system > 0);
+    }
+}
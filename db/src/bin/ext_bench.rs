@@ -61,7 +61,7 @@ fn main() {
                                         .map(| i | format!("test{}", i))
                                         .collect();
     for p in proc_names.iter() {
-        let mut ext = ext_manager.get(0, p.to_string())
+        let mut ext = ext_manager.get(0, p.as_bytes())
                                     .unwrap()
                                     .get(Rc::clone(&db) as Rc<DB>);
         unsafe { ext.resume() };
@@ -70,6 +70,44 @@ fn main() {
     db.assert_messages(expected.as_slice());
     db.clear_messages();
 
+    // Benchmark looking up the extensions by name, and by the id each one is
+    // interned under (INVOKE_FLAG_BY_ID).
+    let ids: Vec<(Vec<u8>, u32)> = ext_manager
+        .list(0)
+        .into_iter()
+        .map(|e| (e.name, e.id))
+        .collect();
+    let mut by_name = Vec::with_capacity(ids.len() * 10000);
+    let mut by_id = Vec::with_capacity(ids.len() * 10000);
+
+    for _ in 0..10000 {
+        for &(ref name, id) in ids.iter() {
+            let l = rdtsc();
+            ext_manager.get(0, name).unwrap();
+            let r = rdtsc();
+            by_name.push(r - l);
+
+            let l = rdtsc();
+            ext_manager.get_by_id(0, id).unwrap();
+            let r = rdtsc();
+            by_id.push(r - l);
+        }
+    }
+
+    by_name.sort();
+    by_id.sort();
+
+    let nm = by_name[by_name.len() / 2];
+    let im = by_id[by_id.len() / 2];
+
+    println!(
+        "Lookup by name: {} cycles {} ns, by id: {} cycles {} ns",
+        nm,
+        to_seconds(nm) * 1e9,
+        im,
+        to_seconds(im) * 1e9,
+    );
+
     // Then, benchmark the amount of time it takes to call into
     // these extensions.
     let expected : Vec<String> = (0..n)
@@ -82,7 +120,7 @@ fn main() {
     for _ in 0..1000000 {
         for p in proc_names.iter() {
             let l = rdtsc();
-            let mut ext = ext_manager.get(0, p.to_string())
+            let mut ext = ext_manager.get(0, p.as_bytes())
                                         .unwrap()
                                         .get(Rc::clone(&db) as Rc<DB>);
             let r = rdtsc();
@@ -103,7 +141,7 @@ fn main() {
         panic!("Failed to load test extension!");
     }
 
-    let mut ext = ext_manager.get(0, String::from("test")).unwrap().get(Rc::clone(&db) as Rc<DB>);
+    let mut ext = ext_manager.get(0, b"test").unwrap().get(Rc::clone(&db) as Rc<DB>);
 
     unsafe { while ext.resume() != GeneratorState::Complete(0) {} };
 
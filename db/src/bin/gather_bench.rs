@@ -0,0 +1,137 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Measures the cycles taken to build a put() request on a 1 KB value made up of three segments
+//! (a header template, a body and a checksum trailer), when the segments are written straight
+//! into the packet, against when they are first copied into a single buffer. Before measuring,
+//! checks that both build the exact same packet for several ways of splitting the value,
+//! including empty segments and a single segment. Requests are built in DPDK memory, but never
+//! touch the network, so no NIC is required.
+
+extern crate db;
+
+use db::cycles;
+use db::e2d2::common::EmptyMetadata;
+use db::e2d2::headers::*;
+use db::e2d2::interface::dpdk::init_system_wl;
+use db::e2d2::interface::Packet;
+use db::rpc;
+
+// The number of put() requests built per run of the benchmark.
+const N_ITERS: u64 = 1 << 20;
+
+// The number of put() requests built before measurements begin, to warm up the caches.
+const N_WARMUP: u64 = 1 << 10;
+
+// The lengths of the segments the value is made up of. They add up to 1 KB.
+const HEADER_LEN: usize = 64;
+const BODY_LEN: usize = 944;
+const TRAILER_LEN: usize = 16;
+
+// Builds a put() request on a value made up of segments. If `gather` is false, the segments are
+// first copied into `buf`, and the request is built off it.
+fn build(
+    key: &[u8],
+    segments: &[&[u8]],
+    buf: &mut Vec<u8>,
+    gather: bool,
+) -> Packet<UdpHeader, EmptyMetadata> {
+    let (mac, ip, udp) = (MacHeader::new(), IpHeader::new(), UdpHeader::new());
+    let req = match gather {
+        true => rpc::create_put_rpc_gather(&mac, &ip, &udp, 1, 1, key, segments, 0, 0, 0),
+
+        false => {
+            buf.clear();
+            for segment in segments.iter() {
+                buf.extend_from_slice(segment);
+            }
+            rpc::create_put_rpc(&mac, &ip, &udp, 1, 1, key, &buf[..], 0, 0, 0)
+        }
+    };
+
+    req.parse_header::<UdpHeader>()
+}
+
+// Checks that building a request off segments gives the exact same packet as building it off
+// their concatenation, for several ways of splitting a value.
+fn check(key: &[u8], val: &[u8]) {
+    let mut buf = Vec::with_capacity(val.len());
+    let splits: Vec<Vec<&[u8]>> = vec![
+        vec![val],
+        vec![
+            &val[..HEADER_LEN],
+            &val[HEADER_LEN..val.len() - TRAILER_LEN],
+            &val[val.len() - TRAILER_LEN..],
+        ],
+        vec![&val[..0], val, &val[..0]],
+        vec![&val[..1], &val[1..1], &val[1..]],
+        vec![&val[..0]],
+    ];
+
+    for segments in splits.iter() {
+        let gathered = build(key, segments, &mut buf, true);
+        let copied = build(key, segments, &mut buf, false);
+        assert_eq!(
+            copied.get_payload(),
+            gathered.get_payload(),
+            "Packets differ for segments of lengths {:?}",
+            segments.iter().map(|s| s.len()).collect::<Vec<usize>>()
+        );
+        gathered.free_packet();
+        copied.free_packet();
+    }
+}
+
+// Builds put() requests, and returns the average cycles taken to build one. Freeing packets is
+// excluded.
+fn run(key: &[u8], segments: &[&[u8]], gather: bool, n: u64) -> f64 {
+    let mut buf = Vec::with_capacity(HEADER_LEN + BODY_LEN + TRAILER_LEN);
+    let mut total = 0;
+
+    for _ in 0..n {
+        let start = cycles::rdtsc();
+        let req = build(key, segments, &mut buf, gather);
+        total += cycles::rdtsc() - start;
+        req.free_packet();
+    }
+
+    total as f64 / n as f64
+}
+
+fn main() {
+    // Initialize DPDK's memory pools without attaching to any NIC.
+    init_system_wl("gather_bench", 0, &[]);
+
+    let key = [7; 30];
+    let header = [1; HEADER_LEN];
+    let body: Vec<u8> = (0..BODY_LEN).map(|i| i as u8).collect();
+    let trailer = [2; TRAILER_LEN];
+    let segments: [&[u8]; 3] = [&header, &body, &trailer];
+
+    let mut val = Vec::new();
+    for segment in segments.iter() {
+        val.extend_from_slice(segment);
+    }
+    check(&key, &val);
+
+    for &(name, gather) in [("copy", false), ("gather", true)].iter() {
+        run(&key, &segments, gather, N_WARMUP);
+        println!(
+            "{:>6}: {:.0} cycles/put",
+            name,
+            run(&key, &segments, gather, N_ITERS)
+        );
+    }
+}
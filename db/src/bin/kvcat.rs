@@ -0,0 +1,117 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Prints a file of key/value records written by db::kvformat as text, for a person to read;
+//! a table checkpoint, an export by the migrate tool, or the input to mkimage. Refer to
+//! kvformat::write_text() for what is printed.
+//!
+//! Flags are of the form `--name=value`:
+//!
+//! * `--input`:        The file of records. Required.
+//! * `--max-value`:    The most bytes of each value to print. Defaults to 64.
+//! * `--skip-corrupt`: Skip records that fail their checksum instead of stopping at the first.
+//!
+//! Exits with 0 if the whole file was printed, and 1 if it could not be read to the end.
+
+extern crate db;
+
+use std::env;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::process;
+
+use db::kvformat::{self, OnCorrupt, Reader};
+
+// What to print, and how.
+#[derive(Debug, PartialEq)]
+struct Options {
+    input: String,
+    max_value: usize,
+    on_corrupt: OnCorrupt,
+}
+
+// Parses flags of the form `--name=value`.
+fn parse_args<I: Iterator<Item = String>>(args: I) -> Result<Options, String> {
+    let mut opts = Options {
+        input: String::new(),
+        max_value: 64,
+        on_corrupt: OnCorrupt::Abort,
+    };
+
+    for arg in args {
+        let mut parts = arg.splitn(2, '=');
+        let bad = || format!("Invalid flag {}", arg);
+        match (parts.next(), parts.next()) {
+            (Some("--input"), Some(v)) => opts.input = v.to_string(),
+            (Some("--max-value"), Some(v)) => opts.max_value = v.parse().map_err(|_| bad())?,
+            (Some("--skip-corrupt"), None) => opts.on_corrupt = OnCorrupt::Skip,
+            _ => return Err(bad()),
+        }
+    }
+
+    match opts.input.is_empty() {
+        true => Err("--input is required".to_string()),
+        false => Ok(opts),
+    }
+}
+
+fn main() {
+    let opts = match parse_args(env::args().skip(1)) {
+        Ok(opts) => opts,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let res = File::open(&opts.input)
+        .map_err(kvformat::Error::Io)
+        .and_then(|file| Reader::new(BufReader::new(file), opts.on_corrupt))
+        .and_then(|mut input| kvformat::write_text(&mut input, &mut out, opts.max_value));
+
+    if let Err(e) = res {
+        eprintln!("Failed to read {}: {}", opts.input, e);
+        process::exit(1);
+    }
+}
+
+// This module contains tests for the tool's flags.
+#[cfg(test)]
+mod tests {
+    use super::{parse_args, OnCorrupt, Options};
+
+    fn args(args: &[&str]) -> Result<Options, String> {
+        parse_args(args.iter().map(|a| a.to_string()))
+    }
+
+    // Tests that flags are parsed, and that the input is required.
+    #[test]
+    fn test_parse_args() {
+        let opts = args(&["--input=in"]).unwrap();
+        assert_eq!((64, OnCorrupt::Abort), (opts.max_value, opts.on_corrupt));
+
+        let opts = args(&["--input=in", "--max-value=8", "--skip-corrupt"]).unwrap();
+        assert_eq!(
+            ("in", 8, OnCorrupt::Skip),
+            (&opts.input[..], opts.max_value, opts.on_corrupt)
+        );
+
+        assert!(args(&["--max-value=8"]).is_err());
+        assert!(args(&["--input=in", "--max-value=x"]).is_err());
+        assert!(args(&["--input=in", "--skip-corrupt=yes"]).is_err());
+    }
+}
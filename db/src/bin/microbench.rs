@@ -0,0 +1,725 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Profiles the storage layer in-process, without a NIC or a client. Tenants are filled through
+//! Master, after which lookups and writes are issued directly against their tables and Master's
+//! allocator, so that regressions in Table or Allocator show up here before they do end-to-end.
+//!
+//! Flags are of the form `--name=value`:
+//!
+//! * `--phases`:     Comma separated phases to run, from "fill", "read", "exists", "miss",
+//!                   "mixed" and "churn". "exists" looks up keys and the length of their value
+//!                   without reading the value, and "miss" looks up keys that were not filled.
+//! * `--threads`:    Comma separated thread counts to run the timed phases with. The fill runs
+//!                   on the largest.
+//! * `--tenants`:    The number of tenants, each with one table.
+//! * `--n-keys`:     The number of objects per table.
+//! * `--key-len`:    The length of every key in Bytes. Atleast 4.
+//! * `--value-len`:  The length of every value in Bytes. Atleast 4.
+//! * `--skew`:       Skew of the Zipf distribution keys are drawn from. 0 is uniform.
+//! * `--put-pct`:    The percentage of writes in the mixed phase.
+//! * `--phase-ms`:   How long each timed phase runs for.
+//! * `--cores`:      Cores to pin threads to, ex: "0-3,8". Refer to config::parse_cores().
+//! * `--split-keys`: "true" to index objects by a copy of their key. Refer to split_keys in
+//!                   the server config.
+//! * `--inline`:     "true" to hold small objects inside the index. Refer to inline_values in
+//!                   the server config.
+//! * `--hot-arena`:  The size in Bytes of the arena the hottest objects are moved into, by a
+//!                   thread that walks the tables for the whole run. 0 means there is no arena.
+//!                   Refer to hot_arena_bytes in the server config. Comparing the cycles per get
+//!                   of a read phase on a skewed workload with and without it shows what the
+//!                   arena saves.
+//! * `--hot-window-ms`: How often the walk passes over the tables. Refer to hot_window_ms in
+//!                   the server config.
+
+extern crate db;
+extern crate libc;
+extern crate rand;
+extern crate sandstorm;
+
+use std::env;
+use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::Duration;
+
+use db::config;
+use db::cyclecounter::CycleCounter;
+use db::cycles;
+use db::hot::{self, HotPolicy, HotStats};
+use db::master::Master;
+use db::table::{self, Table};
+
+use rand::{Rng, SeedableRng, XorShiftRng};
+
+use sandstorm::common::{TableId, TenantId};
+
+// The table filled on every tenant.
+const TABLE: TableId = 1;
+
+// One in these many operations has it's latency sampled for percentiles.
+const SAMPLE_EVERY: u64 = 16;
+
+// The clock is checked against the phase's deadline once every these many operations.
+const CHECK_EVERY: u64 = 256;
+
+// The phases supported by the benchmark.
+const PHASES: [&str; 6] = ["fill", "read", "exists", "miss", "mixed", "churn"];
+
+// Parameters of a run of the benchmark.
+#[derive(Clone, Debug)]
+struct Options {
+    phases: Vec<String>,
+    threads: Vec<usize>,
+    tenants: u32,
+    n_keys: u32,
+    key_len: usize,
+    value_len: usize,
+    skew: f64,
+    put_pct: u32,
+    phase_ms: u64,
+    cores: Vec<i32>,
+    split_keys: bool,
+    inline: bool,
+    hot_arena: usize,
+    hot_window_ms: u64,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            phases: PHASES.iter().map(|p| p.to_string()).collect(),
+            threads: vec![1, 2, 4, 8],
+            tenants: 8,
+            n_keys: 1 << 20,
+            key_len: 30,
+            value_len: 100,
+            skew: 0.99,
+            put_pct: 50,
+            phase_ms: 2000,
+            cores: vec![],
+            split_keys: false,
+            inline: false,
+            hot_arena: 0,
+            hot_window_ms: 10,
+        }
+    }
+}
+
+// Parses command line flags into options. Flags that are not passed in keep their default.
+//
+// # Arguments
+//
+// * `args`: The flags, excluding the name of the binary.
+//
+// # Return
+//
+// The options, or a description of the first malformed flag.
+fn parse_args<I: Iterator<Item = String>>(args: I) -> Result<Options, String> {
+    let mut opts = Options::default();
+
+    for arg in args {
+        let mut parts = arg.trim_left_matches("--").splitn(2, '=');
+        let (name, value) = match (parts.next(), parts.next()) {
+            (Some(name), Some(value)) => (name.to_string(), value.to_string()),
+            _ => return Err(format!("Expected --name=value, got {}", arg)),
+        };
+
+        let bad = || format!("Invalid value for --{}: {}", name, value);
+        match name.as_str() {
+            "phases" => {
+                opts.phases = value.split(',').map(|p| p.trim().to_string()).collect();
+                if opts.phases.iter().any(|p| !PHASES.contains(&p.as_str())) {
+                    return Err(bad());
+                }
+            }
+
+            "threads" => {
+                let threads: Result<Vec<usize>, _> =
+                    value.split(',').map(|t| t.trim().parse()).collect();
+                opts.threads = threads.map_err(|_| bad())?;
+                if opts.threads.is_empty() || opts.threads.contains(&0) {
+                    return Err(bad());
+                }
+            }
+
+            "tenants" => opts.tenants = value.parse().map_err(|_| bad())?,
+            "n-keys" => opts.n_keys = value.parse().map_err(|_| bad())?,
+            "key-len" => opts.key_len = value.parse().map_err(|_| bad())?,
+            "value-len" => opts.value_len = value.parse().map_err(|_| bad())?,
+            "skew" => opts.skew = value.parse().map_err(|_| bad())?,
+            "put-pct" => opts.put_pct = value.parse().map_err(|_| bad())?,
+            "phase-ms" => opts.phase_ms = value.parse().map_err(|_| bad())?,
+            "cores" => opts.cores = config::parse_cores(&value).ok_or_else(bad)?,
+            "split-keys" => opts.split_keys = value.parse().map_err(|_| bad())?,
+            "inline" => opts.inline = value.parse().map_err(|_| bad())?,
+            "hot-arena" => opts.hot_arena = value.parse().map_err(|_| bad())?,
+            "hot-window-ms" => opts.hot_window_ms = value.parse().map_err(|_| bad())?,
+            _ => return Err(format!("Unknown flag --{}", name)),
+        }
+    }
+
+    if opts.tenants == 0 || opts.n_keys == 0 {
+        return Err("--tenants and --n-keys must be non-zero".to_string());
+    }
+    if opts.key_len < 4 || opts.value_len < 4 {
+        return Err("--key-len and --value-len must be atleast 4".to_string());
+    }
+    if opts.skew < 0.0 || opts.skew >= 1.0 {
+        return Err("--skew must be in [0, 1)".to_string());
+    }
+    if opts.put_pct > 100 {
+        return Err("--put-pct must be atmost 100".to_string());
+    }
+
+    // The timed phases run against the tables the fill populates.
+    if opts.phases.iter().any(|p| p != "fill") && opts.phases[0] != "fill" {
+        return Err("The timed phases need the fill phase to run first".to_string());
+    }
+
+    Ok(opts)
+}
+
+// Draws object ids from [1, n] following a Zipf distribution, using the method described in
+// "Quickly Generating Billion-Record Synthetic Databases" by Gray et al. Id 1 is the hottest.
+struct Zipf {
+    n: u64,
+    theta: f64,
+    alpha: f64,
+    zetan: f64,
+    eta: f64,
+}
+
+impl Zipf {
+    // Returns a distribution over [1, n] with skew theta, where 0 <= theta < 1.
+    fn new(n: u64, theta: f64) -> Zipf {
+        let zeta = |n: u64| {
+            (1..(n + 1))
+                .map(|i| 1.0 / (i as f64).powf(theta))
+                .sum::<f64>()
+        };
+        let (zeta2, zetan) = (zeta(2), zeta(n));
+        Zipf {
+            n: n,
+            theta: theta,
+            alpha: 1.0 / (1.0 - theta),
+            zetan: zetan,
+            eta: (1.0 - (2.0 / n as f64).powf(1.0 - theta)) / (1.0 - zeta2 / zetan),
+        }
+    }
+
+    // Returns the next id.
+    fn sample<R: Rng>(&self, rng: &mut R) -> u64 {
+        if self.theta == 0.0 || self.n < 3 {
+            return rng.gen_range(1, self.n + 1);
+        }
+
+        let u = rng.next_f64();
+        let uz = u * self.zetan;
+        if uz < 1.0 {
+            return 1;
+        }
+        if uz < 1.0 + 0.5f64.powf(self.theta) {
+            return 2;
+        }
+
+        let id = 1 + (self.n as f64 * (self.eta * u - self.eta + 1.0).powf(self.alpha)) as u64;
+        std::cmp::min(id, self.n)
+    }
+}
+
+// What a single thread did during a phase.
+#[derive(Default)]
+struct Work {
+    // The number of operations the thread issued.
+    issued: u64,
+
+    // The number of reads that found their object.
+    hits: u64,
+
+    // The number of reads that did not.
+    misses: u64,
+
+    // The number of objects written.
+    writes: u64,
+
+    // The number of cycles the thread spent issuing operations.
+    cycles: u64,
+
+    // Cycles taken by a sample of the operations.
+    samples: Vec<u64>,
+}
+
+// The outcome of a phase, summed across threads.
+struct Report {
+    phase: String,
+    threads: usize,
+    issued: u64,
+    ops: u64,
+    hits: u64,
+    misses: u64,
+    writes: u64,
+    secs: f64,
+    mean: u64,
+    percentiles: [u64; 3],
+}
+
+impl Report {
+    // Sums up the work done by each thread during a phase.
+    fn new(phase: &str, work: Vec<Work>, secs: f64) -> Report {
+        let threads = work.len();
+        let mut samples = Vec::new();
+        let (mut issued, mut hits, mut misses, mut writes) = (0, 0, 0, 0);
+        let mut counter = CycleCounter::new();
+        for w in work.into_iter() {
+            issued += w.issued;
+            hits += w.hits;
+            misses += w.misses;
+            writes += w.writes;
+            counter.total_cycles(w.cycles, w.issued);
+            samples.extend(w.samples);
+        }
+
+        samples.sort();
+        let pct = |p: f64| match samples.len() {
+            0 => 0,
+            n => samples[std::cmp::min(n - 1, (p * n as f64) as usize)],
+        };
+
+        Report {
+            phase: phase.to_string(),
+            threads: threads,
+            issued: issued,
+            ops: hits + misses + writes,
+            hits: hits,
+            misses: misses,
+            writes: writes,
+            secs: secs,
+            mean: counter.get_average(),
+            percentiles: [pct(0.5), pct(0.99), pct(0.999)],
+        }
+    }
+
+    fn print(&self) {
+        println!(
+            "{:>5} {:>2} threads: {:.0} ops/s, {} ops ({} hits, {} misses, {} writes), \
+             cycles/op mean {} p50 {} p99 {} p99.9 {}",
+            self.phase,
+            self.threads,
+            self.ops as f64 / self.secs,
+            self.ops,
+            self.hits,
+            self.misses,
+            self.writes,
+            self.mean,
+            self.percentiles[0],
+            self.percentiles[1],
+            self.percentiles[2]
+        );
+    }
+}
+
+// Pins the calling thread to a core. Failures are reported, but do not stop the benchmark.
+fn pin(core: i32) {
+    unsafe {
+        let mut set: libc::cpu_set_t = mem::zeroed();
+        libc::CPU_SET(core as usize, &mut set);
+        if libc::sched_setaffinity(0, mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            println!("Failed to pin thread to core {}", core);
+        }
+    }
+}
+
+// Writes the little endian encoding of an object id into the head of a key or value, the way
+// Master::fill_test_sized() lays out objects.
+fn encode(id: u32, buf: &mut [u8]) {
+    let temp: [u8; 4] = unsafe { mem::transmute(id.to_le()) };
+    buf[0..4].copy_from_slice(&temp);
+}
+
+// Fills every tenant's table through Master, splitting tenants across threads. Master fills a
+// table in one go, so the cycles of an operation are amortized over it's tenant's fill.
+fn fill(master: &Arc<Master>, opts: &Options, n_threads: usize) -> Report {
+    let barrier = Arc::new(Barrier::new(n_threads));
+    let start = cycles::rdtsc();
+
+    let threads: Vec<_> = (0..n_threads)
+        .map(|idx| {
+            let (master, opts, barrier) = (Arc::clone(master), opts.clone(), Arc::clone(&barrier));
+            thread::spawn(move || {
+                if !opts.cores.is_empty() {
+                    pin(opts.cores[idx % opts.cores.len()]);
+                }
+                barrier.wait();
+
+                let mut work = Work::default();
+                let mut counter = CycleCounter::new();
+                let n = opts.n_keys as u64;
+                for tenant in (1..(opts.tenants + 1)).filter(|t| *t as usize % n_threads == idx) {
+                    counter.start();
+                    master.fill_test_sized(
+                        tenant,
+                        TABLE,
+                        opts.n_keys,
+                        opts.key_len,
+                        opts.value_len,
+                    );
+                    let elapsed = counter.stop(n);
+
+                    work.issued += n;
+                    work.writes += n;
+                    work.cycles += elapsed;
+                    work.samples.push(elapsed / n);
+                }
+                work
+            })
+        })
+        .collect();
+
+    let work = threads
+        .into_iter()
+        .map(|t| t.join().expect("ERROR: Thread join failed."))
+        .collect();
+    Report::new("fill", work, cycles::to_seconds(cycles::rdtsc() - start))
+}
+
+// Runs one of the timed phases. Every thread draws tenants uniformly and keys from a Zipf
+// distribution, and writes `put_pct` percent of the time. The "miss" phase draws keys past the
+// ones filled, and the "exists" phase reads the length of values instead of values.
+fn timed(
+    master: &Arc<Master>,
+    opts: &Options,
+    phase: &str,
+    n_threads: usize,
+    put_pct: u32,
+) -> Report {
+    // Tables are resolved once, so that the phase measures Table and Allocator alone.
+    let tables: Arc<Vec<(TenantId, Arc<Table>)>> = Arc::new(
+        (1..(opts.tenants + 1))
+            .map(|t| {
+                let table = master
+                    .get_tenant(t)
+                    .and_then(|tenant| tenant.get_table(TABLE))
+                    .expect("Phase run before the fill");
+                (t, table)
+            })
+            .collect(),
+    );
+    let zipf = Arc::new(Zipf::new(opts.n_keys as u64, opts.skew));
+    let barrier = Arc::new(Barrier::new(n_threads));
+    let duration = opts.phase_ms * cycles::cycles_per_second() / 1000;
+    let (exists, offset) = match phase {
+        "exists" => (true, 0),
+        "miss" => (false, opts.n_keys),
+        _ => (false, 0),
+    };
+
+    let threads: Vec<_> = (0..n_threads)
+        .map(|idx| {
+            let (master, opts) = (Arc::clone(master), opts.clone());
+            let (tables, zipf, barrier) =
+                (Arc::clone(&tables), Arc::clone(&zipf), Arc::clone(&barrier));
+            thread::spawn(move || {
+                if !opts.cores.is_empty() {
+                    pin(opts.cores[idx % opts.cores.len()]);
+                }
+
+                let seed = [idx as u32 + 1, 0x9e3779b9, 0x85ebca6b, 0xc2b2ae35];
+                let mut rng: XorShiftRng = SeedableRng::from_seed(seed);
+                let mut key = vec![0; opts.key_len];
+                let mut val = vec![0; opts.value_len];
+                let mut work = Work::default();
+                let mut counter = CycleCounter::new();
+                let heap = master.heap();
+
+                barrier.wait();
+                let (start, mut now) = (cycles::rdtsc(), cycles::rdtsc());
+                while now - start < duration {
+                    let (tenant, table) = {
+                        let entry = &tables[rng.gen_range(0, tables.len())];
+                        (entry.0, &entry.1)
+                    };
+                    let id = zipf.sample(&mut rng) as u32 + offset;
+                    encode(id, &mut key);
+                    let write = rng.gen_range(0, 100) < put_pct;
+
+                    counter.start();
+                    if write {
+                        encode(id ^ work.issued as u32, &mut val);
+                        let obj = heap
+                            .object(tenant, TABLE, &key, &val)
+                            .expect("Failed to allocate object.");
+                        heap.store(table, obj.0, obj.1);
+                    } else {
+                        let found = match exists {
+                            true => table
+                                .get(&key)
+                                .and_then(|entry| heap.value_len(&entry.value))
+                                .is_some(),
+                            false => table
+                                .get(&key)
+                                .and_then(|entry| {
+                                    heap.count_hot(&entry.value);
+                                    heap.resolve(entry.value)
+                                })
+                                .is_some(),
+                        };
+                        match found {
+                            true => work.hits += 1,
+                            false => work.misses += 1,
+                        }
+                    }
+                    let elapsed = counter.stop(1);
+
+                    if write {
+                        work.writes += 1;
+                    }
+                    work.issued += 1;
+                    work.cycles += elapsed;
+                    if work.issued % SAMPLE_EVERY == 0 {
+                        work.samples.push(elapsed);
+                    }
+                    if work.issued % CHECK_EVERY == 0 {
+                        now = cycles::rdtsc();
+                    }
+                }
+                work
+            })
+        })
+        .collect();
+
+    let work = threads
+        .into_iter()
+        .map(|t| t.join().expect("ERROR: Thread join failed."))
+        .collect();
+    Report::new(phase, work, opts.phase_ms as f64 / 1000.0)
+}
+
+// Passes over every table once a window, moving objects in and out of the hot arena, until told
+// to stop. Stands in for the server's background task; refer to hot::task().
+fn walk(master: Arc<Master>, mut walk: hot::Walk, done: Arc<AtomicBool>) {
+    while !done.load(Ordering::Relaxed) {
+        if !walk.done() {
+            walk.step();
+        } else if walk.due() {
+            walk.start(master.writable_tables());
+        } else {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
+
+// Runs the phases in the order they were given, returning a report per phase and thread count,
+// along with the counters of the hot arena if there is one.
+fn run(opts: &Options) -> (Vec<Report>, Option<HotStats>) {
+    let mut master = Master::new();
+    master.set_split_keys(opts.split_keys);
+    master.set_inline_values(opts.inline);
+    master.set_hot_arena(HotPolicy::new(
+        opts.hot_arena,
+        true,
+        hot::DEFAULT_READS,
+        opts.hot_window_ms,
+        hot::DEFAULT_WINDOWS,
+    ));
+    let master = Arc::new(master);
+    let max = *opts.threads.iter().max().unwrap_or(&1);
+    let mut reports = Vec::new();
+
+    let done = Arc::new(AtomicBool::new(false));
+    let walker = master.hot_walk().map(|w| {
+        let (master, done) = (Arc::clone(&master), Arc::clone(&done));
+        thread::spawn(move || walk(master, w, done))
+    });
+
+    for phase in opts.phases.iter() {
+        match phase.as_str() {
+            "fill" => reports.push(fill(&master, opts, max)),
+            "read" | "exists" | "miss" | "mixed" | "churn" => {
+                let put_pct = match phase.as_str() {
+                    "read" | "exists" | "miss" => 0,
+                    "mixed" => opts.put_pct,
+                    _ => 100,
+                };
+                for n in opts.threads.iter() {
+                    reports.push(timed(&master, opts, phase, *n, put_pct));
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    done.store(true, Ordering::Relaxed);
+    if let Some(walker) = walker {
+        walker.join().expect("ERROR: Walk failed.");
+    }
+    (reports, master.heap().hot_stats())
+}
+
+fn main() {
+    let opts = match parse_args(env::args().skip(1)) {
+        Ok(opts) => opts,
+        Err(e) => {
+            println!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    println!("Running storage microbenchmark with {:?}", opts);
+
+    let (reports, hot) = run(&opts);
+    for report in reports.iter() {
+        report.print();
+    }
+
+    let stats = table::inline_stats();
+    println!(
+        "Objects written: {} inline, {} on the heap",
+        stats.inlined, stats.heap
+    );
+    if let Some(hot) = hot {
+        println!(
+            "Hot arena: {} promoted, {} demoted, {} of {} bytes used, {:.3} of reads hit it",
+            hot.promotions,
+            hot.demotions,
+            hot.used,
+            hot.capacity,
+            hot.hit_fraction()
+        );
+    }
+}
+
+// This module contains smoke tests that run each phase briefly.
+#[cfg(test)]
+mod tests {
+    use super::{parse_args, run, Options, Zipf};
+
+    use rand::{SeedableRng, XorShiftRng};
+
+    // Returns options for a short run.
+    fn small() -> Options {
+        Options {
+            threads: vec![1, 2],
+            tenants: 3,
+            n_keys: 512,
+            phase_ms: 20,
+            ..Options::default()
+        }
+    }
+
+    // Tests that every phase runs with each table layout, and that the operations reported add
+    // up to those issued.
+    #[test]
+    fn test_phases() {
+        for &(split_keys, inline) in [(false, false), (true, false), (false, true)].iter() {
+            let opts = Options {
+                split_keys: split_keys,
+                inline: inline,
+                ..small()
+            };
+            let (reports, hot) = run(&opts);
+            assert_eq!(11, reports.len());
+            assert!(hot.is_none());
+
+            for report in reports.iter() {
+                assert!(report.issued > 0);
+                assert_eq!(report.issued, report.ops);
+                assert_eq!(report.ops, report.hits + report.misses + report.writes);
+            }
+
+            // The fill writes every object, every read hits unless it is meant to miss.
+            assert_eq!((3 * 512, 3 * 512), (reports[0].ops, reports[0].writes));
+            for report in reports[1..5].iter() {
+                assert_eq!((0, 0), (report.misses, report.writes));
+            }
+            for report in reports[5..7].iter() {
+                assert_eq!(report.ops, report.misses);
+            }
+            for report in reports[7..9].iter() {
+                assert_eq!(0, report.misses);
+            }
+            for report in reports[9..11].iter() {
+                assert_eq!(report.ops, report.writes);
+            }
+        }
+    }
+
+    // Tests that the hottest objects are moved into the arena during a skewed read phase, and
+    // that reads are served out of it.
+    #[test]
+    fn test_hot_arena() {
+        let opts = Options {
+            phases: vec!["fill".to_string(), "read".to_string()],
+            threads: vec![1],
+            phase_ms: 200,
+            hot_arena: 1 << 20,
+            hot_window_ms: 1,
+            ..small()
+        };
+        let (_, hot) = run(&opts);
+        let hot = hot.expect("No hot arena");
+        assert!(hot.promotions > 0);
+        assert!(hot.hits > 0 && hot.hits <= hot.gets);
+        assert!(hot.used <= 1 << 20);
+    }
+
+    // Tests that flags override the defaults, and that malformed flags are rejected.
+    #[test]
+    fn test_parse_args() {
+        let args = |s: &[&str]| parse_args(s.iter().map(|a| a.to_string()));
+
+        let opts = args(&[
+            "--threads=1,3",
+            "--skew=0",
+            "--cores=0-1",
+            "--phases=fill,read,miss",
+            "--split-keys=true",
+            "--inline=true",
+            "--hot-arena=4096",
+            "--hot-window-ms=5",
+        ])
+        .expect("Failed to parse flags");
+        assert_eq!(vec![1, 3], opts.threads);
+        assert_eq!(vec![0, 1], opts.cores);
+        assert_eq!(vec!["fill", "read", "miss"], opts.phases);
+        assert_eq!(0.0, opts.skew);
+        assert!(opts.split_keys && opts.inline);
+        assert_eq!((4096, 5), (opts.hot_arena, opts.hot_window_ms));
+
+        assert!(args(&["--threads=0"]).is_err());
+        assert!(args(&["--skew=1.0"]).is_err());
+        assert!(args(&["--key-len=2"]).is_err());
+        assert!(args(&["--phases=scan"]).is_err());
+        assert!(args(&["--split-keys=1"]).is_err());
+        assert!(args(&["--phases=read,fill"]).is_err());
+        assert!(args(&["--tenants"]).is_err());
+        assert!(args(&["--bogus=1"]).is_err());
+    }
+
+    // Tests that Zipf draws stay in range, and favour low ids.
+    #[test]
+    fn test_zipf() {
+        let mut rng: XorShiftRng = SeedableRng::from_seed([1, 2, 3, 4]);
+        for &theta in [0.0, 0.5, 0.99].iter() {
+            let zipf = Zipf::new(1000, theta);
+            let draws: Vec<u64> = (0..10000).map(|_| zipf.sample(&mut rng)).collect();
+            assert!(draws.iter().all(|d| *d >= 1 && *d <= 1000));
+            if theta > 0.0 {
+                let hot = draws.iter().filter(|d| **d <= 10).count();
+                assert!(hot > 1000);
+            }
+        }
+    }
+}
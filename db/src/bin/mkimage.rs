@@ -0,0 +1,194 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Converts a file of key/value records into a read-only table image that the server maps in
+//! at startup (refer to image_path in the server config). The input is a file written by
+//! db::kvformat, like a table checkpoint or an export by the migrate tool, with the records in
+//! any order. The whole input is refused if any record on it is corrupt.
+//!
+//! Flags are of the form `--name=value`:
+//!
+//! * `--input`:  The file of records.
+//! * `--output`: The image to be written. Overwritten if it exists.
+//! * `--tenant`: The tenant the image will be registered under. Defaults to the one on the
+//!               input's header.
+//! * `--table`:  The identifier the image will be registered under. Defaults to the one on the
+//!               input's header.
+
+extern crate db;
+extern crate sandstorm;
+
+use std::env;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter};
+use std::path::Path;
+
+use db::image::{self, ReadOnlyTable};
+use db::kvformat::{OnCorrupt, Reader};
+
+use sandstorm::common::{TableId, TenantId};
+
+// What to convert, and what the image is registered as.
+#[derive(Debug, PartialEq)]
+struct Options {
+    input: String,
+    output: String,
+    tenant: Option<TenantId>,
+    table: Option<TableId>,
+}
+
+// Parses flags of the form `--name=value`. The input and output are required.
+fn parse_args<I: Iterator<Item = String>>(args: I) -> Result<Options, String> {
+    let (mut input, mut output, mut tenant, mut table) = (None, None, None, None);
+    for arg in args {
+        let (name, value) = match arg.find('=') {
+            Some(i) => (&arg[..i], &arg[i + 1..]),
+            None => return Err(format!("Malformed flag {}", arg)),
+        };
+
+        let bad = || format!("Malformed value for {}: {}", name, value);
+        match name {
+            "--input" => input = Some(value.to_string()),
+            "--output" => output = Some(value.to_string()),
+            "--tenant" => tenant = Some(value.parse().map_err(|_| bad())?),
+            "--table" => table = Some(value.parse().map_err(|_| bad())?),
+            _ => return Err(format!("Unknown flag {}", name)),
+        }
+    }
+
+    match (input, output) {
+        (Some(input), Some(output)) => Ok(Options {
+            input: input,
+            output: output,
+            tenant: tenant,
+            table: table,
+        }),
+        _ => Err("--input and --output are required".to_string()),
+    }
+}
+
+// Writes the image, and maps it back in to make sure it loads. Returns the number of objects
+// on it, and the tenant and table it was registered under.
+fn convert(opts: &Options) -> io::Result<(usize, TenantId, TableId)> {
+    let mut input = Reader::new(BufReader::new(File::open(&opts.input)?), OnCorrupt::Abort)?;
+    let tenant = opts.tenant.unwrap_or(input.header().tenant);
+    let table = opts.table.unwrap_or(input.header().table);
+    let num = {
+        let mut out = BufWriter::new(File::create(&opts.output)?);
+        image::build(&mut out, tenant, table, &mut input)?
+    };
+
+    ReadOnlyTable::open(Path::new(&opts.output))?;
+    Ok((num, tenant, table))
+}
+
+fn main() {
+    let opts = match parse_args(env::args().skip(1)) {
+        Ok(opts) => opts,
+        Err(e) => {
+            println!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    match convert(&opts) {
+        Ok((num, tenant, table)) => println!(
+            "Wrote {} objects to {} for tenant {} table {}",
+            num, opts.output, tenant, table
+        ),
+
+        Err(e) => {
+            println!("Failed to convert {}: {}", opts.input, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+// This module contains tests for the tool's flags and a conversion through files.
+#[cfg(test)]
+mod tests {
+    use super::{convert, parse_args, Options};
+
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    use db::image::ReadOnlyTable;
+    use db::kvformat::{Header, Writer};
+
+    fn args(args: &[&str]) -> Result<Options, String> {
+        parse_args(args.iter().map(|a| a.to_string()))
+    }
+
+    // Tests that every flag is parsed, and that the input and output are required.
+    #[test]
+    fn test_parse_args() {
+        let opts = args(&["--input=in", "--output=out", "--tenant=1", "--table=2"]).unwrap();
+        assert_eq!(
+            ("in", "out", Some(1), Some(2)),
+            (&opts.input[..], &opts.output[..], opts.tenant, opts.table)
+        );
+
+        let opts = args(&["--input=in", "--output=out"]).unwrap();
+        assert_eq!((None, None), (opts.tenant, opts.table));
+
+        assert!(args(&["--input=in", "--tenant=1", "--table=2"]).is_err());
+        assert!(args(&["--input=in", "--output=out", "--tenant=x", "--table=2"]).is_err());
+        assert!(args(&["--input", "--output=out", "--tenant=1", "--table=2"]).is_err());
+        assert!(args(&["--in=in", "--output=out", "--tenant=1", "--table=2"]).is_err());
+    }
+
+    // Tests that a file of records is converted into an image that loads.
+    #[test]
+    fn test_convert() {
+        let dir = env::temp_dir();
+        let input = dir.join(format!("sandstorm-mkimage-in-{}", process::id()));
+        let output = dir.join(format!("sandstorm-mkimage-out-{}", process::id()));
+
+        let mut writer = Writer::new(Vec::new(), Header::new(1, 2)).unwrap();
+        for i in 0..32u8 {
+            writer.write(&[i; 4], &[i; 100]).unwrap();
+        }
+        fs::write(&input, &writer.finish().unwrap()).unwrap();
+
+        // The tenant and table default to the ones on the input.
+        let mut opts = Options {
+            input: input.to_string_lossy().into_owned(),
+            output: output.to_string_lossy().into_owned(),
+            tenant: None,
+            table: None,
+        };
+        assert_eq!((32, 1, 2), convert(&opts).unwrap());
+
+        let table = ReadOnlyTable::open(&output).unwrap();
+        assert_eq!((1, 2, 32), (table.tenant(), table.table_id(), table.len()));
+        assert!(table.get(&[7; 4]).is_some());
+
+        // The image is mapped in, so the second one is written to a new file.
+        let again = dir.join(format!("sandstorm-mkimage-again-{}", process::id()));
+        opts.output = again.to_string_lossy().into_owned();
+        opts.table = Some(5);
+        assert_eq!((32, 1, 5), convert(&opts).unwrap());
+        assert_eq!(5, ReadOnlyTable::open(&again).unwrap().table_id());
+
+        // An input that is not a file of records is refused.
+        fs::write(&input, b"not a file of records").unwrap();
+        assert!(convert(&opts).is_err());
+
+        let _ = fs::remove_file(&input);
+        let _ = fs::remove_file(&output);
+        let _ = fs::remove_file(&again);
+    }
+}
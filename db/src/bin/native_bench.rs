@@ -0,0 +1,190 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Measures the heap allocations and time taken to create, run and tear down a native get()
+//! task, on the generator based path and on the pooled path. Requests are built in DPDK
+//! memory, but never touch the network, so no NIC is required.
+
+extern crate db;
+extern crate sandstorm;
+
+use std::time::{Duration, Instant};
+
+use db::e2d2::headers::*;
+use db::e2d2::interface::dpdk::init_system_wl;
+use db::e2d2::interface::new_packet;
+use db::master::Master;
+use db::pool;
+use db::rpc;
+use db::task::TaskState;
+use db::wireformat::{GetGenerator, GetResponse, RpcStatus};
+
+use sandstorm::common::{TableId, TenantId, PACKET_UDP_LEN};
+
+// A global allocator that counts the number of allocations made by the current thread.
+mod alloc {
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+
+    thread_local!(static ALLOCS: Cell<u64> = Cell::new(0));
+
+    pub struct Counting;
+
+    unsafe impl GlobalAlloc for Counting {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let _ = ALLOCS.try_with(|a| a.set(a.get() + 1));
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static GLOBAL: Counting = Counting;
+
+    // Returns the number of allocations made by this thread so far.
+    pub fn count() -> u64 {
+        ALLOCS.with(|a| a.get())
+    }
+}
+
+// The number of get() requests issued per run of the benchmark.
+const N_ITERS: u64 = 1 << 20;
+
+// The number of get() requests issued before measurements begin, to warm up the pool.
+const N_WARMUP: u64 = 1 << 10;
+
+// The number of records in the table.
+const N_KEYS: u64 = 1 << 16;
+
+// The tenant and table the records are added to.
+const TENANT: TenantId = 1;
+const TABLE: TableId = 1;
+
+// Converts a Duration type to floating point in seconds.
+//
+// # Arguments
+//
+// * `d`: The duration to be converted.
+//
+// # Return
+//
+// The duration in seconds, as an f64 type.
+fn to_seconds(d: &Duration) -> f64 {
+    d.as_secs() as f64 + (d.subsec_nanos() as f64 / 1e9)
+}
+
+// Issues get() requests to a master, running each one to completion.
+//
+// # Arguments
+//
+// * `master`: The master to issue requests to.
+// * `n`:      The number of requests to issue.
+//
+// # Return
+//
+// A tupule of the form (u64, Duration). The first member is the number of heap allocations
+// made while creating, running and tearing down tasks. The second member is the time spent
+// doing so. Building and freeing packets is excluded from both.
+fn run(master: &Master, n: u64) -> (u64, Duration) {
+    let mut allocs = 0;
+    let mut time = Duration::new(0, 0);
+    let mut key = vec![0; 30];
+
+    for i in 0..n {
+        let k = ((i % N_KEYS) + 1) as u32;
+        for b in 0..4 {
+            key[b] = (k >> (8 * b)) as u8;
+        }
+
+        let req = rpc::create_get_rpc(
+            &MacHeader::new(),
+            &IpHeader::new(),
+            &UdpHeader::new(),
+            TENANT,
+            TABLE,
+            &key,
+            i,
+            0,
+            0,
+            GetGenerator::SandstormClient,
+        ).parse_header::<UdpHeader>();
+
+        let res = new_packet()
+            .expect("Failed to allocate response packet.")
+            .push_header(&MacHeader::new())
+            .expect("Failed to push MAC header into response.")
+            .push_header(&IpHeader::new())
+            .expect("Failed to push IP header into response.")
+            .push_header(&UdpHeader::new())
+            .expect("Failed to push UDP header into response.");
+
+        let (start, before) = (Instant::now(), alloc::count());
+
+        let mut task = match master.get_resolved(req, res, master.resolve_table(TENANT, TABLE)) {
+            Ok(task) => task,
+            Err(_) => panic!("Failed to create a task for get() {}.", i),
+        };
+        assert!(task.run().0 == TaskState::COMPLETED);
+        let (req, res) = unsafe { task.tear() }.expect("Task returned no packets.");
+        task.recycle();
+
+        allocs += alloc::count() - before;
+        time += start.elapsed();
+
+        let res = res.parse_header::<GetResponse>();
+        assert!(res.get_header().common_header.status == RpcStatus::StatusOk);
+        req.free_packet();
+        res.deparse_header(PACKET_UDP_LEN as usize).free_packet();
+    }
+
+    (allocs, time)
+}
+
+// Runs the benchmark on a master, and prints out the results.
+//
+// # Arguments
+//
+// * `name`:   The name of the path being benchmarked.
+// * `pooled`: True if the master should run get() requests on pooled tasks.
+fn bench(name: &str, pooled: bool) {
+    let mut master = Master::new();
+    master.set_pooled_native(pooled);
+    master.fill_test(TENANT, TABLE, N_KEYS as u32);
+
+    run(&master, N_WARMUP);
+
+    let stats = pool::stats();
+    let (allocs, time) = run(&master, N_ITERS);
+    let after = pool::stats();
+
+    println!(
+        "{:>9}: {:.2} allocs/get ({:.2} per pool counters), {:.0} ns/get",
+        name,
+        allocs as f64 / N_ITERS as f64,
+        (after.allocs - stats.allocs) as f64 / (after.tasks - stats.tasks) as f64,
+        to_seconds(&time) * 1e9 / N_ITERS as f64
+    );
+}
+
+fn main() {
+    // Initialize DPDK's memory pools without attaching to any NIC.
+    init_system_wl("native_bench", 0, &[]);
+
+    bench("generator", false);
+    bench("pooled", true);
+}
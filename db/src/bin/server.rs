@@ -21,10 +21,13 @@ extern crate nix;
 extern crate spin;
 extern crate util;
 
+use std::mem::transmute;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::thread::{sleep, spawn};
 use std::time::Duration;
 
+use db::epoch;
 use db::log::*;
 
 use db::e2d2::allocators::CacheAligned;
@@ -35,13 +38,26 @@ use db::e2d2::scheduler::Executable;
 use db::e2d2::scheduler::NetBricksContext as NetbricksContext;
 use db::e2d2::scheduler::*;
 
+use db::amplify;
+use db::backoff::{self, Tier};
 use db::config;
+use db::crash;
 use db::cycles::*;
 use db::dispatch::{Dispatch, FAST_PATH};
+use db::hint;
+use db::hot;
 use db::install::Installer;
+use db::integrity;
+use db::latency::{self, LatencySummary};
+use db::limits;
 use db::master::Master;
-use db::sched::RoundRobin;
+use db::memcache;
+use db::prefault;
+use db::sched::{PushbackPolicy, RoundRobin};
+use db::stream;
 use db::task::TaskPriority;
+use db::validate;
+use db::wireformat::OpCode;
 
 use spin::RwLock;
 
@@ -60,6 +76,22 @@ const MALICIOUS_LIMIT_MS: f64 = 1f64;
 /// The identifier of the core that all misbehaving schedulers will be migrated to.
 const GHETTO: u64 = 20;
 
+/// The core Netbricks runs the server's parent thread on.
+const NET_PRIMARY_CORE: i32 = 19;
+
+/// The cores Netbricks runs schedulers on, one per receive queue.
+const NET_CORES: [i32; 8] = [10, 11, 12, 13, 14, 15, 16, 17];
+
+/// Tasks still running this many milliseconds after a drain starts are abandoned, if not set in
+/// the server config.
+const DRAIN_DEADLINE_MS: u64 = 5000;
+
+/// Interval in milliseconds at which the progress of a drain is logged.
+const DRAIN_LOG_INTERVAL_MS: u64 = 1000;
+
+/// Set on receiving SIGINT or SIGTERM. The watchdog starts draining the server once it is set.
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+
 /// A simple wrapper around the scheduler, allowing it to be added to a Netbricks pipeline.
 struct Server {
     scheduler: Arc<RoundRobin>,
@@ -113,11 +145,46 @@ fn setup_server<S>(
         std::process::exit(1);
     }
 
+    // Prefault this core's share of the heap before it serves any requests. The heap is per
+    // thread, so this has to happen here, on the scheduler's own thread.
+    if config.heap_prefault_mb > 0 {
+        let done = prefault::prefault_heap(config.heap_prefault_mb << 20, core);
+        info!(
+            "Prefaulted {} pages ({} MB) of heap on core {} in {:.1} ms",
+            done.pages,
+            done.bytes >> 20,
+            core,
+            to_seconds(done.cycles) * 1e3
+        );
+    }
+
+    // Sleeps the core backs off polling for are only a few microseconds long, and would end
+    // late under the default timer slack.
+    if config.backoff().enabled() {
+        backoff::tighten_timer_slack();
+    }
+
+    // Responses are timed on the thread that sends them out, so this core's latency counters
+    // have to be installed here, on the scheduler's own thread. The same goes for the epochs
+    // stamped onto responses that failed to find a key, table or tenant.
+    latency::install(master.latencies().register(core));
+    epoch::install(Arc::clone(master.epochs()));
+
     // Get identifier of the thread this scheduler will run on.
     let tid = unsafe { zcsi::get_thread_id() };
 
     // Create a dispatcher for the server if needed.
-    let sched = Arc::new(RoundRobin::new(tid, core));
+    let policy = PushbackPolicy {
+        depth_product: config.pushback_depth_product,
+        delay_product: config.pushback_delay_product,
+        ..PushbackPolicy::default()
+    };
+    let sched = Arc::new(
+        RoundRobin::with_policy(tid, core, policy)
+            .with_runs(Arc::clone(master.runs()))
+            .with_quarantines(Arc::clone(master.quarantines()))
+            .with_fairness(config.fairness()),
+    );
     let dispatch = Dispatch::new(
         config,
         ports[0].clone(),
@@ -128,6 +195,24 @@ fn setup_server<S>(
     );
     sched.enqueue(Box::new(dispatch));
 
+    // Resume any durable invocations that were recovered from the journal. Only the first
+    // scheduler to get here picks them up.
+    for task in master.recover_durable().into_iter() {
+        sched.enqueue(task);
+    }
+
+    // Start checking objects against their checksums in the background, if configured. Only the
+    // first scheduler to get here runs the sweep.
+    if let Some(sweep) = master.integrity_sweep() {
+        sched.enqueue(integrity::task(Arc::clone(master), sweep));
+    }
+
+    // Move the hottest objects in and out of the hot arena in the background, if there is one.
+    // Only the first scheduler to get here runs the walk.
+    if let Some(walk) = master.hot_walk() {
+        sched.enqueue(hot::task(Arc::clone(master), walk));
+    }
+
     // Add the scheduler to the passed in `handles` vector.
     handles.write().push(Arc::clone(&sched));
 
@@ -187,8 +272,8 @@ fn get_default_netbricks_config(config: &config::ServerConfig) -> NetbricksConfi
     // General arguments supplied to netbricks.
     let net_config_name = String::from("server");
     let dpdk_secondary: bool = false;
-    let net_primary_core: i32 = 19;
-    let net_cores: Vec<i32> = vec![10, 11, 12, 13, 14, 15, 16, 17];
+    let net_primary_core: i32 = NET_PRIMARY_CORE;
+    let net_cores: Vec<i32> = NET_CORES.to_vec();
     let net_strict_cores: bool = true;
     let net_pool_size: u32 = 8192 - 1;
     let net_cache_size: u32 = 128;
@@ -266,6 +351,12 @@ extern "C" fn handle_sigsegv(
     loop {}
 }
 
+/// Signal handler for SIGINT and SIGTERM. Only sets a flag, the drain itself is run by the
+/// watchdog on the main thread.
+extern "C" fn handle_shutdown(_signum: i32) {
+    SHUTDOWN.store(true, Ordering::Relaxed);
+}
+
 /// Print server startup information.
 fn print_info() {
     if cfg!(feature = "pushback") {
@@ -301,6 +392,21 @@ fn main() {
             .expect("Failed to install custom handler for stack overflow.");
     }
 
+    // Next, install a signal handler that drains the server on SIGINT and SIGTERM instead of
+    // killing it with requests still in flight.
+    let sig_action = signal::SigAction::new(
+        signal::SigHandler::Handler(handle_shutdown),
+        signal::SaFlags::empty(),
+        signal::SigSet::empty(),
+    );
+
+    unsafe {
+        let _ret = signal::sigaction(signal::SIGINT, &sig_action)
+            .expect("Failed to install handler for SIGINT.");
+        let _ret = signal::sigaction(signal::SIGTERM, &sig_action)
+            .expect("Failed to install handler for SIGTERM.");
+    }
+
     // Basic setup and initialization.
     db::env_logger::init().expect("ERROR: failed to initialize logger!");
 
@@ -309,7 +415,79 @@ fn main() {
     let config = config::ServerConfig::load();
     info!("Starting up Sandstorm server with config {:?}", config);
 
-    let master = Arc::new(Master::new());
+    // Refuse to start if the config has problems, rather than populating tables first.
+    let mut cores = NET_CORES.to_vec();
+    cores.extend_from_slice(&[NET_PRIMARY_CORE, GHETTO as i32]);
+    validate::validate_server(&config, &cores).enforce("server.toml");
+
+    // Pick and calibrate the clock before any scheduler reads it. The rate is sent to clients on
+    // echo() responses, so that they can convert the time-stamps put on responses.
+    db::cycles::init();
+
+    // Report panics on scheduler cores with what the core was doing, and then abort or isolate
+    // the extension that panicked, as configured.
+    crash::install(&config);
+
+    let mut master = Master::with_opcodes(config.opcodes());
+    info!("Serving opcodes {:#018x}", master.opcodes());
+    if master.extensions.is_none() {
+        info!("Extension related opcodes are disabled; extensions will not be loaded");
+    }
+    master.set_limits(config.limits());
+    info!(
+        "Accepting keys of up to {} bytes and values of up to {} bytes",
+        master.limits().max_key_len(),
+        master.limits().max_value_len()
+    );
+    let fairness = config.fairness();
+    if fairness.enabled() {
+        info!(
+            "Sharing cores between atmost {} tenants each, with a {} us quantum",
+            fairness.max_tenants, fairness.quantum_us
+        );
+    }
+    master
+        .enable_durable(&config)
+        .expect("Failed to recover durable invocations.");
+    master.set_pooled_native(config.pooled_native);
+    master.set_coalesce_gets(config.coalesce_gets);
+    master.set_tenant_cache(config.tenant_cache_entries);
+    master.set_split_keys(config.split_keys);
+    master.set_inline_values(config.inline_values);
+    master.enable_compression(&config);
+    master.enable_checksums(&config);
+    master.enable_dedup(&config);
+    master.enable_replay(&config);
+    master.enable_profiling(&config);
+    master.set_run_stats_cap(config.run_stats_cap);
+    master.set_quarantine_policy(config.quarantine());
+    master.set_max_stream_bytes(config.max_stream_bytes());
+    master.set_max_deferred(config.max_deferred());
+    master.set_presize(config.presize());
+    master.set_mailbox(config.mailbox());
+    master.set_hot_arena(config.hot_arena());
+    amplify::set_hot_keys(config.write_hot_keys);
+    amplify::set_hot_keys_decay_ms(config.write_hot_keys_decay_ms);
+    hint::set_hot_reads(config.hint_hot_reads);
+    hint::set_max_ttl_us(config.hint_max_ttl_us);
+    let master = Arc::new(master);
+
+    if config.heap_huge_pages {
+        match prefault::transparent_huge_pages() {
+            Some(ref mode) if mode == "always" => {
+                info!("The heap is backed by transparent huge pages")
+            }
+            Some(mode) => warn!(
+                "Huge pages requested, but transparent huge pages are \"{}\"; the heap can only \
+                 use them if they are \"always\" on",
+                mode
+            ),
+            None => warn!("Huge pages requested, but transparent huge pages are unavailable"),
+        }
+    }
+
+    // Count the page faults taken while populating tables, booting, and serving requests.
+    let mut faults = prefault::FaultMeter::new();
 
     // Create tenants with data and extensions.
     match config.workload.as_str() {
@@ -329,8 +507,10 @@ fn main() {
                 "Populating TAO data, {} tenants, {} records/tenant",
                 config.num_tenants, config.num_records
             );
+            let fanout = config.tao_fanout();
+            info!("TAO association fanout {:?}", fanout);
             for tenant in 1..(config.num_tenants + 1) {
-                master.fill_tao(tenant, config.num_records);
+                master.fill_tao_with(tenant, config.num_records, &fanout);
                 master.load_test(tenant);
             }
         }
@@ -370,12 +550,13 @@ fn main() {
         }
 
         "AUTH" => {
+            let layout = config.auth_layout();
             info!(
-                "Populating AUTH data, {} tenants, {} records/tenant",
-                config.num_tenants, config.num_records
+                "Populating AUTH data, {} tenants, {} records/tenant, {:?}",
+                config.num_tenants, config.num_records, layout
             );
             for tenant in 1..(config.num_tenants + 1) {
-                master.fill_auth(tenant, 1, config.num_records);
+                master.fill_auth_with(tenant, 1, config.num_records, &layout);
                 master.load_test(tenant);
             }
         }
@@ -399,6 +580,60 @@ fn main() {
         }
     }
 
+    // Fills from here on share tables with requests, so they are capped.
+    master.fill_rate().set(config.fill_objects_per_sec);
+    if config.fill_objects_per_sec > 0 {
+        info!(
+            "Filling tables at atmost {} objects/s",
+            config.fill_objects_per_sec
+        );
+    }
+
+    // Map in the read-only image now, so that populating the workload doesn't replace it's
+    // tenant. It can then be shared like any other table.
+    master
+        .load_image(&config)
+        .expect("Failed to map in read-only table image.");
+
+    // Share tables read-only across tenants now that they have been populated.
+    for shared in config.shared_tables().into_iter() {
+        if let Err(status) =
+            master.share_table(shared.owner, shared.table, shared.tenant, shared.alias)
+        {
+            warn!("Failed to share table {:?}: {:?}", shared, status);
+        }
+    }
+    for (tenant, alias, owner, table, live) in master.table_aliases().into_iter() {
+        info!(
+            "Tenant {} table {} aliases tenant {} table {} (live {})",
+            tenant, alias, owner, table, live
+        );
+    }
+
+    // Bound the configured tables to their capacity targets, evicting whatever they hold over it.
+    for cache in config.cache_tables().into_iter() {
+        match master.set_cache_target(cache.tenant, cache.table, Some(cache.target)) {
+            Ok(()) => info!(
+                "Tenant {} table {} bounded to {:?}",
+                cache.tenant, cache.table, cache.target
+            ),
+            Err(status) => warn!("Failed to bound table {:?}: {:?}", cache, status),
+        }
+    }
+
+    // Audit the configured tenants' invokes now that they exist.
+    for tenant in config.audit_tenants().into_iter() {
+        match master.enable_audit(tenant, config.audit_entries) {
+            Ok(()) => info!("Auditing tenant {}", tenant),
+            Err(status) => warn!("Failed to audit tenant {}: {:?}", tenant, status),
+        }
+    }
+
+    faults.phase("populate");
+
+    // Serve memcached gets and sets off the populated tables, if a port is configured.
+    let _memcache = memcache::spawn(&master, &config).expect("Failed to bind memcached port.");
+
     // Setup Netbricks.
     let mut net_context: NetbricksContext = config_and_init_netbricks(&config);
 
@@ -413,6 +648,14 @@ fn main() {
     // Copy out the network address that install() RPCs will be received on.
     let install_addr = config.install_addr.clone();
 
+    // Copy out the drain deadline, converted to cycles.
+    let drain_deadline = match config.drain_deadline_ms {
+        0 => DRAIN_DEADLINE_MS,
+        ms => ms,
+    };
+    let drain_deadline = (drain_deadline as f64 / 1000f64) * (cycles_per_second() as f64);
+    let drain_log = (DRAIN_LOG_INTERVAL_MS as f64 / 1000f64) * (cycles_per_second() as f64);
+
     // Setup the server pipeline.
     net_context.start_schedulers();
     net_context.add_pipeline_to_run(Arc::new(
@@ -421,31 +664,79 @@ fn main() {
         },
     ));
 
-    // Create a thread to handle the install() RPC request.
+    // Create a thread to handle the install() RPC request, unless install() is disabled.
     let imaster = Arc::clone(&master);
-    let _install = spawn(move || {
-        // Pin to the ghetto core.
-        let tid = unsafe { zcsi::get_thread_id() };
-        unsafe { zcsi::set_affinity(tid, GHETTO) };
+    let _install = match imaster.serves(&OpCode::SandstormInstallRpc) {
+        true => Some(spawn(move || {
+            // Pin to the ghetto core.
+            let tid = unsafe { zcsi::get_thread_id() };
+            unsafe { zcsi::set_affinity(tid, GHETTO) };
 
-        // Run the installer.
-        let mut installer = Installer::new(imaster, install_addr);
-        installer.execute();
-    });
+            // Run the installer.
+            let mut installer = Installer::new(imaster, install_addr);
+            installer.execute();
+        })),
+
+        false => None,
+    };
 
     // Run the server, and give it some time to bootup.
     net_context.execute();
     sleep(Duration::from_millis(1000));
+    faults.phase("boot");
 
     // Convert to cycles.
     let limit = (MALICIOUS_LIMIT_MS / 1000f64) * (cycles_per_second() as f64);
 
+    // The time-stamps at which the drain started and it's progress was last logged.
+    let mut drain_start = None;
+    let mut drain_logged = 0;
+
     // Check for misbehaving tasks here.
     loop {
         // Scan schedulers every few milliseconds.
         sleep(Duration::from_millis(SCAN_INTERVAL_MS));
 
+        // Start draining on SIGINT or SIGTERM. A drain can also be started over a drain() RPC.
+        if SHUTDOWN.load(Ordering::Relaxed) && master.drain().start() {
+            info!("Draining server on signal");
+        }
+
+        // Once draining, check on every scheduler until they have all drained or the deadline
+        // has passed.
+        if master.drain().draining() {
+            let now = rdtsc();
+            let start = *drain_start.get_or_insert(now);
+            let expired = now - start > drain_deadline as u64;
+
+            let scheds = handles.read();
+            if let Some(abandoned) = master.drain().check(scheds.iter().map(|s| &**s), expired) {
+                if abandoned > 0 {
+                    warn!("Drain deadline passed, abandoning {} tasks", abandoned);
+                }
+
+                // Stop any scheduler that is yet to drain so that it returns to Netbricks.
+                for sched in scheds.iter() {
+                    sched.compromised();
+                }
+                break;
+            }
+
+            if now - drain_logged > drain_log as u64 {
+                info!(
+                    "Draining, tasks remaining per core {:?}",
+                    master.drain().progress()
+                );
+                drain_logged = now;
+            }
+        }
+
         for sched in handles.write().iter_mut() {
+            // A scheduler that has drained stopped running tasks on purpose.
+            if sched.drained() {
+                continue;
+            }
+
             // Get the current time stamp to compare scheduler time stamps against.
             let current = rdtsc();
 
@@ -474,10 +765,11 @@ fn main() {
                 cycles_per_second(),
             );
 
-            // There might be an uncooperative task on this scheduler. Dequeue it's tasks and any
-            // pending response packets.
+            // There might be an uncooperative task on this scheduler. Dequeue it's tasks, any
+            // pending response packets, and the work deferred behind them.
             let mut tasks = sched.dequeue_all();
             let mut resps = sched.responses();
+            let deferred = sched.deferred();
 
             // Retain only non-dispatch tasks.
             tasks.retain(|task| task.priority() != TaskPriority::DISPATCH);
@@ -515,7 +807,7 @@ fn main() {
             // Wait for the new scheduler to be created.
             while temp.read().len() == 0 {}
 
-            // Enqueue all tasks and response packets from the previous scheduler.
+            // Enqueue all tasks, response packets and deferred work from the previous scheduler.
             let new = temp
                 .write()
                 .pop()
@@ -523,10 +815,84 @@ fn main() {
             *sched = new;
             sched.enqueue_many(tasks);
             sched.append_resps(&mut resps);
+            sched.append_deferred(deferred);
         }
     }
 
-    // Stop the server.
-    // net_context.stop();
+    // Stop the server. The journal and any other buffers were flushed by the drain's shutdown
+    // hooks.
+    info!("Server drained, shutting down");
+    faults.phase("run");
+    let refused = limits::stats();
+    info!(
+        "Refused {} writes with keys and {} with values over the limits",
+        refused.keys_refused, refused.values_refused
+    );
+    let streamed = stream::stats();
+    if streamed.streams > 0 {
+        info!(
+            "Streamed {} results over {} partial responses, {} went over the cap",
+            streamed.streams, streamed.chunks, streamed.refused
+        );
+    }
+    let checked = integrity::stats();
+    if checked.verified > 0 || checked.corrupt > 0 {
+        info!(
+            "Verified {} objects over {} passes, {} were corrupt and {} quarantined",
+            checked.verified, checked.passes, checked.corrupt, checked.quarantined
+        );
+    }
+    if let Some(hot) = master.heap().hot_stats() {
+        info!(
+            "Promoted {} objects into the hot arena and demoted {}, {:.3} of gets hit it",
+            hot.promotions,
+            hot.demotions,
+            hot.hit_fraction()
+        );
+    }
+    if config.backoff().enabled() {
+        for core in master.cores().summaries() {
+            let spin = core.tiers[Tier::Spin as usize];
+            let sleep = core.tiers[Tier::Sleep as usize];
+            info!(
+                "Core {} backed off {} times spinning for {} us, {} times sleeping for {} us",
+                core.core,
+                spin.pauses,
+                spin.nanos / 1000,
+                sleep.pauses,
+                sleep.nanos / 1000
+            );
+        }
+    }
+    for (opcode, hist) in master.latencies().opcodes() {
+        // Latencies are only recorded for valid opcodes.
+        let opcode: OpCode = unsafe { transmute(opcode) };
+        let summary = LatencySummary::new(&hist, cycles_per_second());
+        info!(
+            "Responded to {} {:?} requests, p50 {} ns, p99 {} ns, p99.9 {} ns",
+            summary.count, opcode, summary.p50, summary.p99, summary.p999
+        );
+    }
+    let (user, system) = backoff::cpu_time();
+    info!(
+        "Used {} ms of user and {} ms of system CPU time",
+        user / 1000,
+        system / 1000
+    );
+    if let Some(ref extensions) = master.extensions {
+        let top = match config.profile_top {
+            0 => 10,
+            top => top,
+        };
+        for (tenant, name, symbols) in extensions.profiles(top) {
+            info!(
+                "Extension {} of tenant {} spent the most cycles in {:?}",
+                String::from_utf8_lossy(&name),
+                tenant,
+                symbols
+            );
+        }
+    }
+    net_context.stop();
     // _install.join();
 }
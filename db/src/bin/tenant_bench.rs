@@ -0,0 +1,138 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Measures the cycles a request spends finding it's tenant and table, through the tenant map
+//! and through the per-core tenant cache, with tenants drawn from a skewed distribution. Every
+//! thread looks tenants up concurrently, like scheduler cores do.
+
+extern crate db;
+extern crate rand;
+extern crate sandstorm;
+
+use std::sync::{Arc, Barrier};
+use std::thread;
+
+use db::cycles;
+use db::master::Master;
+use db::tenant_cache;
+
+use rand::{Rng, SeedableRng, XorShiftRng};
+
+use sandstorm::common::{TableId, TenantId};
+
+// The number of lookups per thread per run of the benchmark.
+const N_ITERS: usize = 1 << 22;
+
+// The number of threads looking tenants up.
+const N_THREADS: usize = 4;
+
+// The number of tenants, and the skew tenants are drawn with.
+const N_TENANTS: u32 = 1024;
+const SKEW: f64 = 0.99;
+
+// The table every tenant holds.
+const TABLE: TableId = 1;
+
+// Returns a sequence of `n` tenants drawn from a zipfian distribution over [1, N_TENANTS].
+//
+// # Arguments
+//
+// * `seed`: Seeds the draws.
+// * `n`:    The number of tenants to draw.
+fn draw(seed: u32, n: usize) -> Vec<TenantId> {
+    let mut cdf = Vec::with_capacity(N_TENANTS as usize);
+    let mut sum = 0.0;
+    for i in 1..(N_TENANTS + 1) {
+        sum += 1.0 / (i as f64).powf(SKEW);
+        cdf.push(sum);
+    }
+
+    let mut rng = XorShiftRng::from_seed([seed, 1, 2, 3]);
+    (0..n)
+        .map(|_| {
+            let u = rng.next_f64() * sum;
+            let i = match cdf.binary_search_by(|p| p.partial_cmp(&u).unwrap()) {
+                Ok(i) => i,
+                Err(i) => i,
+            };
+            (i as u32).min(N_TENANTS - 1) + 1
+        }).collect()
+}
+
+// Looks up the tenants in a sequence on several threads at once.
+//
+// # Arguments
+//
+// * `master`: The master holding the tenants.
+// * `cached`: If true, tenants are looked up through the per-core cache.
+//
+// # Return
+//
+// The average number of cycles per lookup, and the fraction of lookups served off the cache.
+fn run(master: &Arc<Master>, cached: bool) -> (f64, f64) {
+    let barrier = Arc::new(Barrier::new(N_THREADS));
+    let threads: Vec<_> = (0..N_THREADS)
+        .map(|t| {
+            let (master, barrier) = (Arc::clone(master), Arc::clone(&barrier));
+            thread::spawn(move || {
+                let tenants = draw(t as u32 + 1, N_ITERS);
+                barrier.wait();
+
+                let before = tenant_cache::stats();
+                let start = cycles::rdtsc();
+                for tenant in tenants.iter() {
+                    let found = match cached {
+                        true => master.resolve_table(*tenant, TABLE).is_ok(),
+                        false => master
+                            .get_tenant(*tenant)
+                            .map_or(false, |t| t.readable_native_table(TABLE).is_ok()),
+                    };
+                    assert!(found);
+                }
+                let cycles = cycles::rdtsc() - start;
+
+                let after = tenant_cache::stats();
+                let hits = after.hits - before.hits;
+                (cycles, hits)
+            })
+        }).collect();
+
+    let (mut cycles, mut hits) = (0, 0);
+    for thread in threads {
+        let (c, h) = thread.join().expect("Benchmark thread panicked.");
+        cycles += c;
+        hits += h;
+    }
+
+    let lookups = (N_THREADS * N_ITERS) as f64;
+    (cycles as f64 / lookups, hits as f64 / lookups)
+}
+
+fn main() {
+    let master = Arc::new(Master::new());
+    for tenant in 1..(N_TENANTS + 1) {
+        master.fill_test(tenant, TABLE, 0);
+    }
+
+    for &(name, cached) in [("map", false), ("cached", true)].iter() {
+        let (cycles, hits) = run(&master, cached);
+        println!(
+            "{:>6}: {:.1} cycles/lookup, {:.1}% hits",
+            name,
+            cycles,
+            hits * 100.0
+        );
+    }
+}
@@ -0,0 +1,494 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use std::cell::{Cell, RefCell};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::mem::transmute;
+use std::rc::Rc;
+
+use hashbrown::HashMap;
+
+use super::rpc::{append_record, finish_get_response, ResponseBuf};
+use super::table::Version;
+use super::wireformat::{GetGenerator, GetResponse, RpcStatus};
+
+use bytes::Bytes;
+
+use sandstorm::buf;
+use sandstorm::common::{TableId, TenantId};
+use sandstorm::pack::pack;
+
+/// The outcome of looking up a get()'s key; the object's key, value and version, or the status
+/// the lookup failed with.
+pub type Outcome = Result<(Bytes, Bytes, Version), RpcStatus>;
+
+thread_local! {
+    // The get()s on this core whose lookup is yet to run, keyed by a hash of their tenant, table
+    // and key.
+    static PENDING: RefCell<HashMap<u64, Rc<Pending>>> = RefCell::new(HashMap::new());
+
+    // Counters of the get()s coalesced on this core.
+    static STATS: Cell<CoalesceStats> = Cell::new(CoalesceStats::default());
+}
+
+/// Counters of the get() requests coalesced on a core.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CoalesceStats {
+    /// The number of get()s that looked their key up for others to share.
+    pub leaders: u64,
+
+    /// The number of get()s answered with a leader's lookup.
+    pub coalesced: u64,
+
+    /// The number of get()s that attached to a leader, but looked their key up themselves
+    /// because the leader had not run by the time they did.
+    pub fallbacks: u64,
+}
+
+/// Returns the counters of the calling core.
+pub fn stats() -> CoalesceStats {
+    STATS.with(|s| s.get())
+}
+
+// Updates the counters of the calling core.
+fn count<F: FnOnce(&mut CoalesceStats)>(f: F) {
+    STATS.with(|s| {
+        let mut stats = s.get();
+        f(&mut stats);
+        s.set(stats);
+    });
+}
+
+/// A get() whose lookup can be shared by later get()s for the same key on the same core.
+pub struct Pending {
+    // The hash this get() is registered under.
+    hash: u64,
+
+    // The key this get() looks up.
+    tenant: TenantId,
+    table: TableId,
+    key: Vec<u8>,
+
+    // The outcome of the lookup, once the leader has run.
+    outcome: RefCell<Option<Outcome>>,
+}
+
+/// The part a get() plays in coalescing.
+pub enum Role {
+    /// The get() looks it's key up, and publishes the outcome for followers.
+    Leader(Leader),
+
+    /// The get() uses the outcome it's leader published, if the leader ran first.
+    Follower(Rc<Pending>),
+}
+
+/// A get() that followers attach to. Later get()s stop attaching once it is dropped, whether or
+/// not it published an outcome.
+pub struct Leader(Rc<Pending>);
+
+impl Leader {
+    /// Hands the outcome of the lookup to the followers. A leader never waits on it's
+    /// followers; they pick the outcome up whenever they run.
+    ///
+    /// # Arguments
+    ///
+    /// * `outcome`: The outcome of the leader's lookup.
+    pub fn publish(self, outcome: &Outcome) {
+        *self.0.outcome.borrow_mut() = Some(outcome.clone());
+    }
+}
+
+impl Drop for Leader {
+    fn drop(&mut self) {
+        PENDING.with(|p| {
+            let mut pending = p.borrow_mut();
+            if pending
+                .get(&self.0.hash)
+                .map_or(false, |p| Rc::ptr_eq(p, &self.0))
+            {
+                pending.remove(&self.0.hash);
+            }
+        });
+    }
+}
+
+/// Registers a get() on the calling core. The first get() for a key becomes it's leader, and
+/// every get() for the key registered until the leader runs becomes a follower.
+///
+/// # Arguments
+///
+/// * `tenant`: The tenant issuing the get().
+/// * `table`:  The table the get() reads.
+/// * `key`:    The key the get() looks up.
+///
+/// # Return
+///
+/// The get()'s role, or None if a get() for a different key with the same hash is pending, in
+/// which case the get() is not coalesced at all.
+pub fn join(tenant: TenantId, table: TableId, key: &[u8]) -> Option<Role> {
+    let mut hasher = DefaultHasher::new();
+    (tenant, table, key).hash(&mut hasher);
+    let hash = hasher.finish();
+
+    PENDING.with(|p| {
+        let mut pending = p.borrow_mut();
+        if let Some(leader) = pending.get(&hash) {
+            if leader.tenant == tenant && leader.table == table && &leader.key[..] == key {
+                return Some(Role::Follower(Rc::clone(leader)));
+            }
+            return None;
+        }
+
+        let leader = Rc::new(Pending {
+            hash: hash,
+            tenant: tenant,
+            table: table,
+            key: key.to_vec(),
+            outcome: RefCell::new(None),
+        });
+        pending.insert(hash, Rc::clone(&leader));
+        count(|s| s.leaders += 1);
+        Some(Role::Leader(Leader(leader)))
+    })
+}
+
+/// Returns the outcome a follower's leader published, or None if the leader has not run yet.
+/// The follower must then look it's key up itself.
+///
+/// # Arguments
+///
+/// * `pending`: The leader the follower attached to.
+pub fn outcome(pending: &Pending) -> Option<Outcome> {
+    let outcome = pending.outcome.borrow().clone();
+    match outcome {
+        Some(_) => count(|s| s.coalesced += 1),
+        None => count(|s| s.fallbacks += 1),
+    }
+    outcome
+}
+
+/// Writes the outcome of a get()'s lookup into it's response; the value, preceded by the
+/// version and key if the get() came from an extension. Only the part of the value covered by
+/// the request's projection is written, but the full length of the value is reported on the
+/// header. The status and value length on the response header are updated to match. The id and
+/// stamp on the header are left untouched.
+///
+/// # Arguments
+///
+/// * `res`:        The response to the get().
+/// * `generator`:  Whether the get() came from a client or from an extension on a client.
+/// * `projection`: The offset and length of the part of the value requested. Refer to
+///                 sandstorm::buf::projection().
+/// * `outcome`:    The outcome of the lookup.
+pub fn fill_response<B: ResponseBuf<GetResponse>>(
+    res: &mut B,
+    generator: &GetGenerator,
+    projection: (u32, u32),
+    outcome: &Outcome,
+) {
+    let status = match *outcome {
+        Ok((ref key, ref value, version)) => {
+            // The record is either appended in full or not at all, so that a response that ran
+            // out of room is never sent out with a partial value.
+            let optype: u8 = 0x1; // OpType::SandstormRead
+            let version: [u8; 8] = unsafe { transmute::<Version, [u8; 8]>(version) };
+            let full = value.len() as u32;
+            let value = &value[buf::projection(value.len(), projection.0, projection.1)];
+            let appended = match *generator {
+                GetGenerator::SandstormExtension => {
+                    append_record(res, &[pack(&optype), &version, &key[..], value])
+                }
+                _ => append_record(res, &[value]),
+            };
+            match appended {
+                true => {
+                    res.header().set_full_length(full);
+                    RpcStatus::StatusOk
+                }
+                false => RpcStatus::StatusInternalError,
+            }
+        }
+
+        Err(ref status) => status.clone(),
+    };
+
+    finish_get_response(res, status);
+}
+
+// This module contains unit tests for coalescing get()s, and for filling their responses.
+#[cfg(test)]
+mod tests {
+    use super::{fill_response, join, outcome, stats, Outcome, Role};
+
+    use std::mem::size_of;
+
+    use super::super::limits::HARD_MAX_VALUE_LEN;
+    use super::super::rpc::ResponseBuf;
+    use super::super::table::Table;
+    use super::super::wireformat::*;
+
+    use bytes::Bytes;
+
+    use sandstorm::common::PACKET_IP_LEN;
+
+    // A response that keeps it's payload in memory, upto `room` bytes of it.
+    struct Response {
+        hdr: GetResponse,
+        payload: Vec<u8>,
+        room: usize,
+    }
+
+    impl Response {
+        fn new(id: u64, stamp: u64) -> Response {
+            Response {
+                hdr: GetResponse::new(id, stamp, OpCode::SandstormGetRpc, 7),
+                payload: Vec::new(),
+                room: usize::max_value(),
+            }
+        }
+
+        // Returns a response with as much room as a 1500 byte frame leaves for the payload.
+        fn framed(id: u64, stamp: u64) -> Response {
+            let mut res = Response::new(id, stamp);
+            res.room = 1500 - PACKET_IP_LEN as usize - size_of::<GetResponse>();
+            res
+        }
+    }
+
+    impl ResponseBuf<GetResponse> for Response {
+        fn header(&mut self) -> &mut GetResponse {
+            &mut self.hdr
+        }
+
+        fn payload_len(&self) -> usize {
+            self.payload.len()
+        }
+
+        fn append(&mut self, data: &[u8]) -> bool {
+            if data.len() > self.room - self.payload.len() {
+                return false;
+            }
+
+            self.payload.extend_from_slice(data);
+            true
+        }
+
+        fn truncate(&mut self, len: usize) {
+            self.payload.truncate(len);
+        }
+    }
+
+    // Returns the outcome of a lookup that found `value` under `key`.
+    fn found(key: &[u8], value: &[u8]) -> Outcome {
+        let table = Table::default();
+        table.put(Bytes::from(key), Bytes::from(value));
+        let entry = table.get(key).unwrap();
+        Ok((Bytes::from(key), entry.value, entry.version))
+    }
+
+    // Tests that get()s for a key pending on the core follow the first one, and that get()s
+    // registered after the leader is gone lead again.
+    #[test]
+    fn test_join() {
+        let before = stats();
+        let leader = match join(1, 2, b"hot") {
+            Some(Role::Leader(leader)) => leader,
+            _ => panic!("First get() did not lead"),
+        };
+        let follower = match join(1, 2, b"hot") {
+            Some(Role::Follower(pending)) => pending,
+            _ => panic!("Second get() did not follow"),
+        };
+
+        // Different keys, tables and tenants are not coalesced.
+        for &(tenant, table, key) in [
+            (1, 2, &b"cold"[..]),
+            (1, 3, &b"hot"[..]),
+            (2, 2, &b"hot"[..]),
+        ]
+        .iter()
+        {
+            match join(tenant, table, key) {
+                Some(Role::Follower(_)) => panic!("get() followed a different key"),
+                _ => (),
+            }
+        }
+
+        // Before the leader runs, followers fall back to their own lookup.
+        assert!(outcome(&follower).is_none());
+        leader.publish(&Err(RpcStatus::StatusObjectDoesNotExist));
+        assert_eq!(
+            Some(Err(RpcStatus::StatusObjectDoesNotExist)),
+            outcome(&follower).map(|o| o.map(|_| ()))
+        );
+
+        match join(1, 2, b"hot") {
+            Some(Role::Leader(_)) => (),
+            _ => panic!("get() followed a leader that already ran"),
+        }
+
+        let after = stats();
+        assert!(after.leaders - before.leaders >= 2);
+        assert_eq!(1, after.coalesced - before.coalesced);
+        assert_eq!(1, after.fallbacks - before.fallbacks);
+    }
+
+    // Tests that a dropped leader stops later get()s from following it.
+    #[test]
+    fn test_leader_dropped() {
+        let leader = join(3, 1, b"key");
+        assert!(match join(3, 1, b"key") {
+            Some(Role::Follower(_)) => true,
+            _ => false,
+        });
+
+        drop(leader);
+        assert!(match join(3, 1, b"key") {
+            Some(Role::Leader(_)) => true,
+            _ => false,
+        });
+    }
+
+    // Tests that followers filled from a leader's outcome get the same payload the leader does,
+    // while keeping their own id and stamp.
+    #[test]
+    fn test_fill_followers() {
+        let outcome = found(b"key0", &[9; 100]);
+        for generator in [
+            GetGenerator::SandstormClient,
+            GetGenerator::SandstormExtension,
+        ]
+        .iter()
+        {
+            let mut leader = Response::new(1, 100);
+            fill_response(&mut leader, generator, (0, 0), &outcome);
+            let len = leader.hdr.value_length() as usize;
+            assert_eq!(RpcStatus::StatusOk, leader.hdr.common_header.status);
+            assert_eq!(leader.payload.len(), len);
+
+            for i in 2..5 {
+                let mut follower = Response::new(i, 100 + i);
+                fill_response(&mut follower, generator, (0, 0), &outcome);
+                let hdr = &follower.hdr.common_header;
+                assert_eq!(leader.payload, follower.payload);
+                assert_eq!(len, follower.hdr.value_length() as usize);
+                assert_eq!(RpcStatus::StatusOk, hdr.status);
+                assert_eq!((i, 100 + i), (hdr.id(), hdr.stamp()));
+            }
+        }
+    }
+
+    // Tests that a failed lookup fails every response filled from it.
+    #[test]
+    fn test_fill_failure() {
+        let outcome = Err(RpcStatus::StatusObjectDoesNotExist);
+        for i in 1..4 {
+            let mut res = Response::new(i, i);
+            fill_response(&mut res, &GetGenerator::SandstormClient, (0, 0), &outcome);
+            assert_eq!(
+                RpcStatus::StatusObjectDoesNotExist,
+                res.hdr.common_header.status
+            );
+            assert_eq!((0, 0), (res.payload.len(), res.hdr.value_length() as usize));
+            assert_eq!(i, res.hdr.common_header.id() as u64);
+        }
+    }
+
+    // Tests that the largest value the server accepts fits in a single frame when read by a
+    // client, and that a value one byte larger would not.
+    #[test]
+    fn test_fill_largest_value() {
+        let mut res = Response::framed(1, 1);
+        let outcome = found(b"key", &vec![7; HARD_MAX_VALUE_LEN]);
+        fill_response(&mut res, &GetGenerator::SandstormClient, (0, 0), &outcome);
+        assert_eq!(RpcStatus::StatusOk, res.hdr.common_header.status);
+        assert_eq!(vec![7; HARD_MAX_VALUE_LEN], res.payload);
+        assert_eq!(HARD_MAX_VALUE_LEN, res.hdr.value_length() as usize);
+
+        let mut res = Response::framed(2, 2);
+        let outcome = found(b"key", &vec![7; HARD_MAX_VALUE_LEN + 1]);
+        fill_response(&mut res, &GetGenerator::SandstormClient, (0, 0), &outcome);
+        assert_eq!(RpcStatus::StatusInternalError, res.hdr.common_header.status);
+        assert_eq!((0, 0), (res.payload.len(), res.hdr.value_length() as usize));
+    }
+
+    // Tests that a projection writes just the requested part of the value into the response,
+    // clamped to the end of the value, while reporting the full length of the value.
+    #[test]
+    fn test_fill_projection() {
+        let value: Vec<u8> = (0..100).collect();
+        let outcome = found(b"key", &value);
+        let fill = |projection| {
+            let mut res = Response::new(1, 1);
+            fill_response(
+                &mut res,
+                &GetGenerator::SandstormClient,
+                projection,
+                &outcome,
+            );
+            assert_eq!(RpcStatus::StatusOk, res.hdr.common_header.status);
+            assert_eq!(res.payload.len(), res.hdr.value_length() as usize);
+            assert_eq!(100, res.hdr.full_length() as usize);
+            res.payload
+        };
+
+        // The default projection, and one that spans the value, return the whole value.
+        assert_eq!(value, fill((0, 0)));
+        assert_eq!(value, fill((0, 100)));
+
+        assert_eq!(&value[10..30], &fill((10, 20))[..]);
+        assert_eq!(&value[40..], &fill((40, 0))[..]);
+        assert_eq!(&value[90..], &fill((90, 20))[..]);
+        assert!(fill((100, 0)).is_empty());
+        assert!(fill((120, 8)).is_empty());
+
+        // An extension still gets the version and key ahead of the projected value.
+        let mut res = Response::new(1, 1);
+        fill_response(
+            &mut res,
+            &GetGenerator::SandstormExtension,
+            (0, 0),
+            &outcome,
+        );
+        let prefix = res.payload.len() - value.len();
+        let mut res = Response::new(2, 2);
+        fill_response(
+            &mut res,
+            &GetGenerator::SandstormExtension,
+            (10, 20),
+            &outcome,
+        );
+        assert_eq!(&value[10..30], &res.payload[prefix..]);
+        assert_eq!(100, res.hdr.full_length() as usize);
+    }
+
+    // Tests that a projection that fits in a frame is served even when the full value would not
+    // have fit, and that a failed get() does not report a full length.
+    #[test]
+    fn test_fill_projection_fits() {
+        let outcome = found(b"key", &vec![7; HARD_MAX_VALUE_LEN + 1]);
+        let mut res = Response::framed(1, 1);
+        fill_response(&mut res, &GetGenerator::SandstormClient, (8, 40), &outcome);
+        assert_eq!(RpcStatus::StatusOk, res.hdr.common_header.status);
+        assert_eq!(vec![7; 40], res.payload);
+        assert_eq!(HARD_MAX_VALUE_LEN + 1, res.hdr.full_length() as usize);
+
+        let mut res = Response::framed(2, 2);
+        fill_response(&mut res, &GetGenerator::SandstormClient, (0, 0), &outcome);
+        assert_eq!(RpcStatus::StatusInternalError, res.hdr.common_header.status);
+        assert_eq!(0, res.hdr.full_length() as usize);
+    }
+}
@@ -0,0 +1,377 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use std::cmp::min;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// The smallest match that is worth encoding; shorter runs are written out as literals.
+const MIN_MATCH: usize = 4;
+
+// The number of bits in an index into the table of previously seen sequences.
+const HASH_LOG: usize = 12;
+
+// The farthest back a match can be. Offsets are encoded in two bytes.
+const MAX_OFFSET: usize = 65535;
+
+// The last match must end at least these many bytes before the end of the input.
+const LAST_LITERALS: usize = 5;
+
+// A match cannot start within these many bytes of the end of the input.
+const MF_LIMIT: usize = 12;
+
+// The compression ratio a value must achieve to be stored compressed, if not set in the server
+// config.
+const MIN_RATIO: f64 = 1.25;
+
+/// Per-table compression settings. Values written to a table with these settings are compressed
+/// if they are atleast `threshold` bytes long, and are stored raw if compression does not shrink
+/// them by atleast `min_ratio`.
+#[derive(Clone, Copy, Debug)]
+pub struct Compression {
+    /// The length of the smallest value that will be compressed.
+    pub threshold: usize,
+
+    /// The smallest ratio of raw to compressed length worth storing compressed.
+    pub min_ratio: f64,
+}
+
+impl Compression {
+    /// Returns compression settings for a table.
+    ///
+    /// # Arguments
+    ///
+    /// * `threshold`: The length of the smallest value that will be compressed. 0 disables
+    ///                compression.
+    /// * `min_ratio`: The smallest ratio of raw to compressed length worth storing compressed.
+    ///                0 means 1.25.
+    ///
+    /// # Return
+    ///
+    /// Compression settings, or None if compression is disabled.
+    pub fn new(threshold: usize, min_ratio: f64) -> Option<Compression> {
+        if threshold == 0 {
+            return None;
+        }
+
+        Some(Compression {
+            threshold: threshold,
+            min_ratio: if min_ratio <= 0.0 {
+                MIN_RATIO
+            } else {
+                min_ratio
+            },
+        })
+    }
+}
+
+// Counters reported by stats(). Shared by all cores.
+static COMPRESSED: AtomicUsize = AtomicUsize::new(0);
+static REJECTED: AtomicUsize = AtomicUsize::new(0);
+static RAW_BYTES: AtomicUsize = AtomicUsize::new(0);
+static STORED_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+/// Counters of the values considered for compression since the server started.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CompressionStats {
+    /// The number of values stored compressed.
+    pub compressed: usize,
+
+    /// The number of values that were compressed, but stored raw because they did not shrink
+    /// enough.
+    pub rejected: usize,
+
+    /// The total raw length of the values stored compressed.
+    pub raw_bytes: usize,
+
+    /// The total length those values were stored in.
+    pub stored_bytes: usize,
+}
+
+impl CompressionStats {
+    /// Returns the ratio of raw to stored bytes across all compressed values, or 1 if no value
+    /// has been compressed yet.
+    pub fn ratio(&self) -> f64 {
+        if self.stored_bytes == 0 {
+            return 1.0;
+        }
+
+        self.raw_bytes as f64 / self.stored_bytes as f64
+    }
+}
+
+/// Returns the compression counters.
+pub fn stats() -> CompressionStats {
+    CompressionStats {
+        compressed: COMPRESSED.load(Ordering::Relaxed),
+        rejected: REJECTED.load(Ordering::Relaxed),
+        raw_bytes: RAW_BYTES.load(Ordering::Relaxed),
+        stored_bytes: STORED_BYTES.load(Ordering::Relaxed),
+    }
+}
+
+/// Records a value that was stored compressed.
+///
+/// # Arguments
+///
+/// * `raw`:    The raw length of the value.
+/// * `stored`: The length the value was stored in.
+pub fn record_compressed(raw: usize, stored: usize) {
+    COMPRESSED.fetch_add(1, Ordering::Relaxed);
+    RAW_BYTES.fetch_add(raw, Ordering::Relaxed);
+    STORED_BYTES.fetch_add(stored, Ordering::Relaxed);
+}
+
+/// Records a value that was stored raw because it did not compress well enough.
+pub fn record_rejected() {
+    REJECTED.fetch_add(1, Ordering::Relaxed);
+}
+
+// Reads four bytes off a slice as a little-endian integer.
+#[inline]
+fn read_u32(src: &[u8], i: usize) -> u32 {
+    (src[i] as u32)
+        | (src[i + 1] as u32) << 8
+        | (src[i + 2] as u32) << 16
+        | (src[i + 3] as u32) << 24
+}
+
+// Hashes a four byte sequence into an index into the table of previously seen sequences.
+#[inline]
+fn hash(seq: u32) -> usize {
+    (seq.wrapping_mul(2654435761) >> (32 - HASH_LOG)) as usize
+}
+
+// Writes out the part of a length that does not fit in a token.
+fn write_len(dst: &mut Vec<u8>, mut len: usize) {
+    while len >= 255 {
+        dst.push(255);
+        len -= 255;
+    }
+    dst.push(len as u8);
+}
+
+// Writes out a run of literals, followed by a match if there is one.
+fn write_sequence(dst: &mut Vec<u8>, literals: &[u8], m: Option<(usize, usize)>) {
+    let ml = m.map_or(0, |(_, len)| len - MIN_MATCH);
+    dst.push((min(literals.len(), 15) << 4 | min(ml, 15)) as u8);
+    if literals.len() >= 15 {
+        write_len(dst, literals.len() - 15);
+    }
+    dst.extend_from_slice(literals);
+
+    if let Some((offset, _)) = m {
+        dst.push(offset as u8);
+        dst.push((offset >> 8) as u8);
+        if ml >= 15 {
+            write_len(dst, ml - 15);
+        }
+    }
+}
+
+/// Compresses a slice of bytes into the LZ4 block format.
+///
+/// # Arguments
+///
+/// * `src`: The bytes to be compressed.
+/// * `dst`: The vector the compressed bytes are appended to.
+pub fn compress(src: &[u8], dst: &mut Vec<u8>) {
+    let mut table = [0u32; 1 << HASH_LOG];
+    let mut anchor = 0;
+    let mut i = 0;
+
+    if src.len() > MF_LIMIT {
+        let limit = src.len() - MF_LIMIT;
+        let end = src.len() - LAST_LITERALS;
+
+        while i < limit {
+            let seq = read_u32(src, i);
+            let h = hash(seq);
+            let candidate = table[h] as usize;
+            table[h] = i as u32;
+
+            // Entries can be stale or collide, so a candidate is only a hint; it's bytes are checked.
+            if candidate < i && i - candidate <= MAX_OFFSET && read_u32(src, candidate) == seq {
+                let mut len = MIN_MATCH;
+                while i + len < end && src[candidate + len] == src[i + len] {
+                    len += 1;
+                }
+
+                write_sequence(dst, &src[anchor..i], Some((i - candidate, len)));
+                i += len;
+                anchor = i;
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    write_sequence(dst, &src[anchor..], None);
+}
+
+// Reads the part of a length that did not fit in a token.
+fn read_len(src: &[u8], i: &mut usize, mut len: usize) -> Option<usize> {
+    loop {
+        let b = *src.get(*i)?;
+        *i += 1;
+        len += b as usize;
+        if b != 255 {
+            return Some(len);
+        }
+    }
+}
+
+/// Decompresses a block produced by `compress()`.
+///
+/// # Arguments
+///
+/// * `src`: The compressed bytes.
+/// * `dst`: The slice the bytes are decompressed into. It's length must be the exact raw
+///          length of the block.
+///
+/// # Return
+///
+/// None if the block is corrupt, or does not decompress to exactly the length of `dst`.
+pub fn decompress(src: &[u8], dst: &mut [u8]) -> Option<()> {
+    let (mut i, mut o) = (0, 0);
+
+    loop {
+        let token = *src.get(i)? as usize;
+        i += 1;
+
+        // Copy out the literals.
+        let mut lits = token >> 4;
+        if lits == 15 {
+            lits = read_len(src, &mut i, lits)?;
+        }
+        if i + lits > src.len() || o + lits > dst.len() {
+            return None;
+        }
+        dst[o..o + lits].copy_from_slice(&src[i..i + lits]);
+        i += lits;
+        o += lits;
+
+        // The last sequence has no match.
+        if i == src.len() {
+            break;
+        }
+
+        // Copy out the match. It can overlap with the bytes it produces, so this is done a byte
+        // at a time.
+        if i + 2 > src.len() {
+            return None;
+        }
+        let offset = src[i] as usize | (src[i + 1] as usize) << 8;
+        i += 2;
+
+        let mut len = token & 15;
+        if len == 15 {
+            len = read_len(src, &mut i, len)?;
+        }
+        len += MIN_MATCH;
+
+        if offset == 0 || offset > o || o + len > dst.len() {
+            return None;
+        }
+        for j in o..o + len {
+            dst[j] = dst[j - offset];
+        }
+        o += len;
+    }
+
+    if o == dst.len() {
+        Some(())
+    } else {
+        None
+    }
+}
+
+// This module contains unit tests for the compressor.
+#[cfg(test)]
+mod tests {
+    use super::{compress, decompress, Compression, MIN_RATIO};
+
+    use rand::{Rng, SeedableRng, XorShiftRng};
+
+    // Compresses and decompresses a slice, checking that it round trips.
+    fn round_trip(src: &[u8]) -> usize {
+        let mut compressed = Vec::new();
+        compress(src, &mut compressed);
+
+        let mut dst = vec![0; src.len()];
+        assert_eq!(Some(()), decompress(&compressed, &mut dst));
+        assert_eq!(src, &dst[..]);
+
+        compressed.len()
+    }
+
+    // This unit test round trips short inputs, which are all literals.
+    #[test]
+    fn test_compress_short() {
+        for len in 0..20 {
+            let src: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            round_trip(&src);
+        }
+    }
+
+    // This unit test round trips highly compressible inputs, including long runs that produce
+    // overlapping matches and lengths that do not fit in a token.
+    #[test]
+    fn test_compress_compressible() {
+        assert!(round_trip(&[0; 4096]) < 64);
+
+        let json: Vec<u8> = (0..200)
+            .flat_map(|i| format!("{{\"id\": {}, \"name\": \"user\"}},", i % 7).into_bytes())
+            .collect();
+        assert!(round_trip(&json) * 4 < json.len());
+    }
+
+    // This unit test round trips random bytes, which do not compress.
+    #[test]
+    fn test_compress_incompressible() {
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        let src: Vec<u8> = (0..4096).map(|_| rng.gen::<u8>()).collect();
+        assert!(round_trip(&src) >= src.len());
+    }
+
+    // This unit test verifies that corrupt or truncated blocks are rejected.
+    #[test]
+    fn test_decompress_corrupt() {
+        let src = vec![7; 256];
+        let mut compressed = Vec::new();
+        compress(&src, &mut compressed);
+
+        // Truncated block.
+        let mut dst = vec![0; src.len()];
+        let n = compressed.len();
+        assert_eq!(None, decompress(&compressed[..n - 1], &mut dst));
+
+        // Wrong raw length.
+        let mut dst = vec![0; src.len() + 1];
+        assert_eq!(None, decompress(&compressed, &mut dst));
+
+        // A match reaching back before the start of the output.
+        let mut dst = vec![0; 8];
+        assert_eq!(None, decompress(&[0x14, 1, 0x09, 0x00], &mut dst));
+    }
+
+    // This unit test verifies the defaults applied to compression settings.
+    #[test]
+    fn test_compression_new() {
+        assert!(Compression::new(0, 2.0).is_none());
+
+        let c = Compression::new(64, 0.0).unwrap();
+        assert_eq!((64, MIN_RATIO), (c.threshold, c.min_ratio));
+    }
+}
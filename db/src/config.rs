@@ -18,8 +18,30 @@ use std::fmt;
 use std::fs::File;
 use std::io::Read;
 
+use super::backoff::{BackoffPolicy, DEFAULT_THRESHOLD};
+use super::defer::DEFAULT_MAX_DEFERRED;
 use super::e2d2::headers::*;
+use super::evict::{CacheTarget, EvictPolicy};
+use super::fair::{FairPolicy, DEFAULT_MAX_TENANTS};
+use super::hot::{self, HotPolicy};
+use super::limits::Limits;
+use super::mailbox::{self, Mailbox};
+use super::presize::{self, Presize};
+use super::quarantine::{
+    QuarantinePolicy, DEFAULT_MAX_QUARANTINE_MS, DEFAULT_QUARANTINE_MS, DEFAULT_WINDOW_MS,
+};
+use super::stream::DEFAULT_MAX_STREAM_BYTES;
 use super::toml;
+use super::wireformat::{OpCode, OPCODES_ALL, OPCODES_REQUIRED};
+
+use sandstorm::auth;
+use sandstorm::tao::{self, Fanout};
+
+/// The limits assoc_ranges are drawn from by the TAO client if `tao_range_limits` is empty.
+pub const DEFAULT_TAO_RANGE_LIMITS: &str = "10x60,50x30,1000x10";
+
+/// The number of ids a TAO client pipeline reserves at a time if `tao_reserve_ids` is 0.
+pub const DEFAULT_TAO_RESERVE_IDS: u32 = 4096;
 
 /// To show the error while parsing the MAC address.
 #[derive(Debug, Clone)]
@@ -52,6 +74,223 @@ pub fn parse_mac(mac: &str) -> Result<MacAddress, ParseError> {
     }
 }
 
+/// A table shared read-only by it's owner with another tenant. Refer to Master::share_table().
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SharedTable {
+    /// The tenant that owns the table.
+    pub owner: u32,
+    /// The identifier of the table under the owner.
+    pub table: u64,
+    /// The tenant the table is shared with.
+    pub tenant: u32,
+    /// The identifier the table goes by under `tenant`.
+    pub alias: u64,
+}
+
+/// Parses a comma separated list of shared tables, each formatted as
+/// "OWNER:TABLE@TENANT:ALIAS". An empty string is an empty list.
+pub fn parse_shared_tables(spec: &str) -> Option<Vec<SharedTable>> {
+    let pair = |s: &str| {
+        let mut parts = s.trim().split(':');
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(a), Some(b), None) => match (a.parse::<u32>(), b.parse::<u64>()) {
+                (Ok(a), Ok(b)) => Some((a, b)),
+                _ => None,
+            },
+            _ => None,
+        }
+    };
+
+    spec.split(',')
+        .filter(|s| !s.trim().is_empty())
+        .map(|s| {
+            let mut sides = s.split('@');
+            match (sides.next(), sides.next(), sides.next()) {
+                (Some(from), Some(to), None) => pair(from).and_then(|(owner, table)| {
+                    pair(to).map(|(tenant, alias)| SharedTable {
+                        owner: owner,
+                        table: table,
+                        tenant: tenant,
+                        alias: alias,
+                    })
+                }),
+                _ => None,
+            }
+        }).collect()
+}
+
+/// A table bounded to a capacity target once the workload is populated. Refer to
+/// Master::set_cache_target().
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CacheTable {
+    /// The tenant that owns the table.
+    pub tenant: u32,
+    /// The identifier of the table under the tenant.
+    pub table: u64,
+    /// The target the table is bounded to.
+    pub target: CacheTarget,
+}
+
+/// Parses a comma separated list of cache-mode tables, each formatted as
+/// "TENANT:TABLE:BYTES:OBJECTS:POLICY", where POLICY is "written" or "read". Either bound can be
+/// 0, but not both. An empty string is an empty list.
+pub fn parse_cache_tables(spec: &str) -> Option<Vec<CacheTable>> {
+    spec.split(',')
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .map(|s| {
+            let parts: Vec<&str> = s.split(':').collect();
+            if parts.len() != 5 {
+                return None;
+            }
+
+            let policy = EvictPolicy::parse(parts[4])?;
+            let target = CacheTarget::new(parts[2].parse().ok()?, parts[3].parse().ok()?, policy)?;
+            Some(CacheTable {
+                tenant: parts[0].parse().ok()?,
+                table: parts[1].parse().ok()?,
+                target: target,
+            })
+        }).collect()
+}
+
+/// Parses a comma separated list of tenant ids. An empty string is an empty list.
+pub fn parse_tenants(spec: &str) -> Option<Vec<u32>> {
+    spec.split(',')
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .map(|s| s.parse::<u32>().ok())
+        .collect()
+}
+
+/// Parses a comma separated list of table ids. An empty string is an empty list.
+pub fn parse_tables(spec: &str) -> Option<Vec<u64>> {
+    spec.split(',')
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .map(|s| s.parse::<u64>().ok())
+        .collect()
+}
+
+/// Parses a comma separated list of TAO association types. An empty string is an empty list.
+pub fn parse_atypes(spec: &str) -> Option<Vec<u16>> {
+    spec.split(',')
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .map(|s| s.parse::<u16>().ok())
+        .collect()
+}
+
+/// Parses a comma separated list of tenant shares, each of the form "tenant:share" with a share
+/// of atleast 1. An empty string is an empty list.
+pub fn parse_shares(spec: &str) -> Option<Vec<(u32, u32)>> {
+    spec.split(',')
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .map(|s| {
+            let mut parts = s.split(':');
+            match (parts.next(), parts.next(), parts.next()) {
+                (Some(tenant), Some(share), None) => {
+                    let tenant = tenant.trim().parse::<u32>().ok()?;
+                    match share.trim().parse::<u32>().ok()? {
+                        0 => None,
+                        share => Some((tenant, share)),
+                    }
+                }
+                _ => None,
+            }
+        }).collect()
+}
+
+/// Parses a comma separated list of cores, each either a single core id or an
+/// inclusive range such as "10-17". An empty string is an empty list.
+pub fn parse_cores(spec: &str) -> Option<Vec<i32>> {
+    let mut cores = Vec::new();
+    for part in spec.split(',').map(|s| s.trim()).filter(|s| !s.is_empty()) {
+        let mut ends = part.split('-');
+        let range = match (ends.next(), ends.next(), ends.next()) {
+            (Some(a), None, None) => a.trim().parse::<i32>().ok().map(|a| (a, a)),
+            (Some(a), Some(b), None) => match (a.trim().parse::<i32>(), b.trim().parse::<i32>()) {
+                (Ok(a), Ok(b)) if a <= b => Some((a, b)),
+                _ => None,
+            },
+            _ => None,
+        };
+
+        match range {
+            Some((first, last)) if first >= 0 => cores.extend(first..(last + 1)),
+            _ => return None,
+        }
+    }
+
+    Some(cores)
+}
+
+/// Parses a comma separated list of opcode names ("get", "put", "invoke", "install", "multiget",
+/// "list_ext", "table_access", "run_stats", "merge", "set_merge", "routed_invoke", "set_route",
+/// "audit", "dump", "multiput", "write_stats", "core_stats", "latency_stats", "quarantine",
+/// "create_table", "fetch_result", "delete", "scan", "cas", "drop_table") into a mask of
+/// OpCode::bit(). An empty string is every opcode. echo() and drain() are always in the mask,
+/// whether named or not.
+pub fn parse_opcodes(spec: &str) -> Option<u64> {
+    if spec.trim().is_empty() {
+        return Some(OPCODES_ALL);
+    }
+
+    let mut mask = OPCODES_REQUIRED;
+    for name in spec.split(',').map(|s| s.trim()).filter(|s| !s.is_empty()) {
+        let op = match name {
+            "get" => OpCode::SandstormGetRpc,
+            "put" => OpCode::SandstormPutRpc,
+            "invoke" => OpCode::SandstormInvokeRpc,
+            "install" => OpCode::SandstormInstallRpc,
+            "multiget" => OpCode::SandstormMultiGetRpc,
+            "echo" => OpCode::SandstormEchoRpc,
+            "list_ext" => OpCode::SandstormListExtRpc,
+            "drain" => OpCode::SandstormDrainRpc,
+            "table_access" => OpCode::SandstormTableAccessRpc,
+            "run_stats" => OpCode::SandstormRunStatsRpc,
+            "merge" => OpCode::SandstormMergeRpc,
+            "set_merge" => OpCode::SandstormSetMergeRpc,
+            "routed_invoke" => OpCode::SandstormRoutedInvokeRpc,
+            "set_route" => OpCode::SandstormSetRouteRpc,
+            "audit" => OpCode::SandstormAuditRpc,
+            "dump" => OpCode::SandstormDumpRpc,
+            "multiput" => OpCode::SandstormMultiPutRpc,
+            "write_stats" => OpCode::SandstormWriteStatsRpc,
+            "core_stats" => OpCode::SandstormCoreStatsRpc,
+            "latency_stats" => OpCode::SandstormLatencyStatsRpc,
+            "quarantine" => OpCode::SandstormQuarantineRpc,
+            "create_table" => OpCode::SandstormCreateTableRpc,
+            "fetch_result" => OpCode::SandstormFetchResultRpc,
+            "delete" => OpCode::SandstormDeleteRpc,
+            "scan" => OpCode::SandstormScanRpc,
+            "cas" => OpCode::SandstormCasRpc,
+            "drop_table" => OpCode::SandstormDropTableRpc,
+            _ => return None,
+        };
+        mask |= op.bit();
+    }
+
+    Some(mask)
+}
+
+/// Returns the layout of the AUTH workload's records, out of the auth_cost, auth_hash_len and
+/// auth_salt_len fields of a config. A field that is 0 takes it's value off the default layout
+/// (see sandstorm::auth::Layout). None if a field is out of range.
+pub fn auth_layout(cost: u8, hash_len: usize, salt_len: usize) -> Option<auth::Layout> {
+    let default = auth::Layout::default();
+    let or = |field: usize, default: usize| match field {
+        0 => default,
+        field => field,
+    };
+    auth::Layout::new(
+        or(cost as usize, default.cost() as usize) as u8,
+        or(hash_len, default.hash_len()),
+        or(salt_len, default.salt_len()),
+    )
+}
+
 /// Load a config from `filename` otherwise return a default structure.
 fn load_config(filename: &str) -> ServerConfig {
     let mut contents = String::new();
@@ -108,6 +347,299 @@ pub struct ServerConfig {
     pub workload: String,
     /// Number of records in the table for each tenant.
     pub num_records: u32,
+    /// Fanout distribution of association lists under the TAO workload; see Fanout::parse().
+    /// Every object gets four associations if empty.
+    #[serde(default)]
+    pub tao_fanout: String,
+    /// Tables shared read-only across tenants once the workload is populated; see
+    /// parse_shared_tables(). Nothing is shared if empty.
+    #[serde(default)]
+    pub shared_tables: String,
+    /// Tables bounded to a capacity target once the workload is populated, and evicted off to
+    /// stay under it; see parse_cache_tables(). No table is bounded if empty.
+    #[serde(default)]
+    pub cache_tables: String,
+    /// Read-only table image mapped in at startup; see image::ReadOnlyTable. Nothing is mapped
+    /// if empty.
+    #[serde(default)]
+    pub image_path: String,
+    /// The tenant the image's table is registered under. Must match the image.
+    #[serde(default)]
+    pub image_tenant: u32,
+    /// The identifier the image's table is registered under. Must match the image.
+    #[serde(default)]
+    pub image_table: u64,
+
+    /// Journal that durable invocations checkpoint to. Durability is disabled if empty.
+    #[serde(default)]
+    pub durable_journal: String,
+    /// The journal is fsync'ed once these many records are appended. 0 leaves it to the OS.
+    #[serde(default)]
+    pub durable_fsync_every: u32,
+    /// The largest state a durable invocation can checkpoint, in bytes. 0 means 4 KB.
+    #[serde(default)]
+    pub durable_max_state: usize,
+    /// The largest the durable journal can grow, in bytes. 0 means 64 MB.
+    #[serde(default)]
+    pub durable_max_journal: usize,
+
+    /// If true, get() and put() requests run on pooled tasks instead of boxed generators.
+    #[serde(default)]
+    pub pooled_native: bool,
+    /// If true, get() requests for a key that another get() on the same core is about to look up
+    /// share that lookup.
+    #[serde(default)]
+    pub coalesce_gets: bool,
+    /// Values at least these many bytes long are compressed when written. 0 disables compression.
+    #[serde(default)]
+    pub compress_threshold: usize,
+    /// Values are stored raw unless compression shrinks them by atleast this ratio. 0 means 1.25.
+    #[serde(default)]
+    pub compress_min_ratio: f64,
+    /// If true, the tables created by Master index objects by a copy of their key held apart
+    /// from the object, instead of by a slice into it.
+    #[serde(default)]
+    pub split_keys: bool,
+    /// If true, the tables created by Master hold objects of upto table::INLINE_CAP bytes inside
+    /// their index instead of on the heap.
+    #[serde(default)]
+    pub inline_values: bool,
+
+    /// Tasks still running this many milliseconds after a drain starts are abandoned. 0 means
+    /// 5 seconds.
+    #[serde(default)]
+    pub drain_deadline_ms: u64,
+    /// The maximum number of client runs whose requests, errors and pushbacks are counted at
+    /// once. The least recently active run is evicted beyond this. 0 means 64.
+    #[serde(default)]
+    pub run_stats_cap: usize,
+    /// Under pushback, a yielded invoke is pushed back if it's estimated remaining time in
+    /// microseconds times the number of tasks waiting on it's core exceeds this. 0 disables.
+    #[serde(default)]
+    pub pushback_depth_product: f64,
+    /// Under pushback, a yielded invoke is pushed back if it's estimated remaining time in
+    /// microseconds times the moving average queueing delay on it's core in microseconds exceeds
+    /// this. 0 disables.
+    #[serde(default)]
+    pub pushback_delay_product: f64,
+    /// The number of megabytes of heap each scheduler core prefaults at startup, so that requests
+    /// early in a run don't take the page faults. 0 disables prefaulting.
+    #[serde(default)]
+    pub heap_prefault_mb: usize,
+    /// If true, the server reports whether the heap is backed by transparent huge pages.
+    #[serde(default)]
+    pub heap_huge_pages: bool,
+    /// The opcodes the server serves; see parse_opcodes(). Requests with any other opcode are
+    /// rejected with StatusOperationDisabled. Every opcode is served if empty.
+    #[serde(default)]
+    pub enabled_opcodes: String,
+    /// The opcodes whose retransmits are answered with the original's response instead of being
+    /// executed again; see parse_opcodes() and replay::ReplayTable. Nothing is replayed if empty.
+    #[serde(default)]
+    pub replay_opcodes: String,
+    /// The number of requests each tenant's replay table on a core remembers. 0 means 1024.
+    #[serde(default)]
+    pub replay_window: usize,
+    /// The number of milliseconds a request is remembered for. 0 means 1000.
+    #[serde(default)]
+    pub replay_age_ms: u64,
+    /// The largest response held for replay, in bytes. Retransmits of requests with longer
+    /// responses are executed again. 0 means 256.
+    #[serde(default)]
+    pub replay_max_bytes: usize,
+    /// If true, the symbols of every loaded extension are written to /tmp/perf-<pid>.map so that
+    /// perf resolves them, and the cycles each extension spends in it's symbols are sampled.
+    #[serde(default)]
+    pub profile_extensions: bool,
+    /// The number of symbols reported per profiled extension when the server shuts down. 0
+    /// means 10.
+    #[serde(default)]
+    pub profile_top: usize,
+    /// The file crash reports are appended to when a scheduler core panics. Reports always go
+    /// to stderr; they are only written to stderr if empty.
+    #[serde(default)]
+    pub crash_file: String,
+    /// What the server does after a core panics; "abort" (the default if empty) or "isolate".
+    /// See CrashPolicy.
+    #[serde(default)]
+    pub crash_policy: String,
+    /// The UDP port memcached text protocol gets and sets are served on; see memcache::Adapter.
+    /// Nothing is bound if 0.
+    #[serde(default)]
+    pub memcache_port: u16,
+    /// The tenant whose table memcached commands are served off. 0 means 1.
+    #[serde(default)]
+    pub memcache_tenant: u32,
+    /// The table memcached commands are served off. 0 means 1.
+    #[serde(default)]
+    pub memcache_table: u64,
+    /// The tenants whose invocations are recorded into an audit log, as a comma separated list
+    /// of ids; see audit::AuditLog. No tenant is audited if empty.
+    #[serde(default)]
+    pub audit_tenants: String,
+    /// The number of invocations each audited tenant's log holds. 0 means 1024.
+    #[serde(default)]
+    pub audit_entries: usize,
+    /// The longest key an object can be written with, in bytes. 0 means 1024. Atmost 16383.
+    #[serde(default)]
+    pub max_key_len: usize,
+    /// The largest value an object can be written with, in bytes. 0 means the largest value a
+    /// get() response can carry, which is also the most this can be.
+    #[serde(default)]
+    pub max_value_len: usize,
+    /// The microseconds of CPU time each tenant's requests get per round on a scheduler core,
+    /// times it's share; see fair::RunQueue. 0 runs tasks first come first served.
+    #[serde(default)]
+    pub fair_quantum_us: f64,
+    /// The number of tenants that get a queue of their own on each core. Requests from other
+    /// tenants share a queue. 0 means 16.
+    #[serde(default)]
+    pub fair_max_tenants: usize,
+    /// The share of each tenant's requests on a core, as a comma separated list of
+    /// "tenant:share"; see parse_shares(). Tenants not listed get a share of 1.
+    #[serde(default)]
+    pub tenant_shares: String,
+    /// The most bytes an invocation can stream back across all of it's responses; see
+    /// stream::Stream. 0 means 1 MB.
+    #[serde(default)]
+    pub max_stream_bytes: usize,
+    /// The most deferred invocations each tenant can have queued or running at once; see
+    /// defer::Deferrals. Work deferred past this is dropped. 0 means 1024.
+    #[serde(default)]
+    pub max_deferred: usize,
+    /// How long the result of an invocation that asked for it to be stored is kept for, in
+    /// milliseconds; see mailbox::Mailbox. 0 means 10 seconds.
+    #[serde(default)]
+    pub mailbox_ttl_ms: u64,
+    /// The largest response payload stored for an invocation, in bytes. Larger results are not
+    /// stored. 0 means 1024.
+    #[serde(default)]
+    pub mailbox_max_result: usize,
+    /// The most bytes of stored results each tenant can have at once. The least recently stored
+    /// are evicted past this. 0 means 1 MB.
+    #[serde(default)]
+    pub mailbox_quota_bytes: u64,
+    /// The fraction of the slots reserved on a table created for a known number of objects that
+    /// they fill; see presize::Presize. 0 means presize::DEFAULT_LOAD_FACTOR. Atleast
+    /// presize::MIN_LOAD_FACTOR, and atmost 1.
+    #[serde(default)]
+    pub table_load_factor: f64,
+    /// The most objects a table can be created for. Larger tables are refused with
+    /// StatusTableTooLarge. 0 means as many as fit in a quarter of the machine's memory.
+    #[serde(default)]
+    pub table_presize_max: u64,
+    /// The number of keys per table whose physical write volume is tracked, for write_stats()
+    /// to report the hottest of; see amplify::HotKeys. 0 turns tracking off.
+    #[serde(default)]
+    pub write_hot_keys: usize,
+    /// The interval at which the volumes of the keys tracked per table are halved, in
+    /// milliseconds, so that write_stats() reports the keys hot recently instead of since the
+    /// server started; see amplify::set_hot_keys_decay_ms(). 0 means volumes never decay.
+    #[serde(default)]
+    pub write_hot_keys_decay_ms: u64,
+    /// The number of reads since an object was last written after which native get()s of it
+    /// are hinted hot, so that clients can cache it; see hint::hint(). 0 turns hints off, and
+    /// reads are not counted.
+    #[serde(default)]
+    pub hint_hot_reads: usize,
+    /// The longest a client is told it can cache a hot object for, in microseconds. 0 means
+    /// hint::DEFAULT_MAX_TTL_US. Atmost hint::MAX_TTL_US.
+    #[serde(default)]
+    pub hint_max_ttl_us: u32,
+    /// The size of the arena the hottest objects are moved into, in bytes; see hot::HotArena.
+    /// Split into segments of hot::SEGMENT_SIZE. 0 means there is no arena.
+    #[serde(default)]
+    pub hot_arena_bytes: usize,
+    /// If true, the hot arena asks to be backed by transparent huge pages.
+    #[serde(default)]
+    pub hot_arena_huge_pages: bool,
+    /// The number of reads in a window after which an object is hot. 0 means
+    /// hot::DEFAULT_READS.
+    #[serde(default)]
+    pub hot_reads: usize,
+    /// The length of a window, in milliseconds. 0 means hot::DEFAULT_WINDOW_MS.
+    #[serde(default)]
+    pub hot_window_ms: u64,
+    /// The number of windows in a row an object must be hot to be moved into the hot arena, or
+    /// cold to be moved back out. 0 means hot::DEFAULT_WINDOWS.
+    #[serde(default)]
+    pub hot_windows: usize,
+    /// The longest pause, in microseconds, a core that keeps finding nothing to do backs off
+    /// polling for; see backoff::Backoff. 0 disables backoff. Atmost backoff::MAX_PAUSE_US.
+    #[serde(default)]
+    pub poll_backoff_max_us: u64,
+    /// The number of empty polls in a row after which a core starts to back off. 0 means 256.
+    #[serde(default)]
+    pub poll_backoff_threshold: u32,
+    /// The tables whose objects carry a checksum, as a comma separated list of table ids. Applies
+    /// to the table under each id on every tenant. No table is checksummed if empty.
+    #[serde(default)]
+    pub checksum_tables: String,
+    /// The number of objects a second the background sweep checks against their checksums; see
+    /// integrity::Sweep. 0 disables the sweep.
+    #[serde(default)]
+    pub verify_objects_per_sec: u64,
+    /// If true, objects that fail to verify are quarantined, and reads of them fail with
+    /// StatusDataCorrupted until they are overwritten. Otherwise, they are only logged.
+    #[serde(default)]
+    pub verify_quarantine: bool,
+    /// The number of tenant handles each core caches, so that requests from the tenants it keeps
+    /// serving skip the tenant map; see tenant_cache::TenantCache. Rounded up to a power of two.
+    /// 0 means tenant_cache::DEFAULT_ENTRIES. Atmost tenant_cache::MAX_ENTRIES.
+    #[serde(default)]
+    pub tenant_cache_entries: usize,
+    /// The most objects a second fills write into a table once the server is serving requests;
+    /// see fill::Pacer. Tables populated at startup are filled uncapped. 0 leaves fills uncapped.
+    /// Atmost fill::MAX_RATE. Can be changed while the server runs through Master::fill_rate().
+    #[serde(default)]
+    pub fill_objects_per_sec: u64,
+    /// The tables whose identical values share memory instead of being copied into every object
+    /// written with them, as a comma separated list of table ids; see dedup::Dedup. Applies to the
+    /// table under each id on every tenant. No table shares values if empty.
+    #[serde(default)]
+    pub dedup_tables: String,
+    /// The number of values each table in `dedup_tables` remembers to share new writes with.
+    /// 0 means dedup::DEFAULT_DEDUP_ENTRIES. Atmost dedup::MAX_DEDUP_ENTRIES.
+    #[serde(default)]
+    pub dedup_entries: usize,
+    /// The bcrypt cost the AUTH workload's records are hashed at; each increment doubles the
+    /// work of checking a password. 0 means 1. Atmost sandstorm::auth::MAX_COST.
+    #[serde(default)]
+    pub auth_cost: u8,
+    /// The length of the hash kept on each AUTH record, atmost 24 bytes. 0 keeps all 24.
+    #[serde(default)]
+    pub auth_hash_len: usize,
+    /// The length of the salt on each AUTH record, atmost 16 bytes. 0 means 16.
+    #[serde(default)]
+    pub auth_salt_len: usize,
+    /// The most malformed requests (too short, or with arguments that don't fit) a tenant can
+    /// send within `quarantine_window_ms` before it is quarantined; see quarantine::Quarantines.
+    /// 0 never quarantines a tenant for malformed requests.
+    #[serde(default)]
+    pub quarantine_malformed: u64,
+    /// The most invocations from a tenant that can panic, stream back too much, or run a disabled
+    /// extension within `quarantine_window_ms` before it is quarantined. 0 never quarantines a
+    /// tenant for them.
+    #[serde(default)]
+    pub quarantine_ext_failures: u64,
+    /// The most requests a tenant can send within `quarantine_window_ms` that it isn't allowed
+    /// to, such as writes to read-only tables, before it is quarantined. 0 never quarantines a
+    /// tenant for them.
+    #[serde(default)]
+    pub quarantine_auth_failures: u64,
+    /// The length of the sliding window failures are counted over, in milliseconds. 0 means
+    /// quarantine::DEFAULT_WINDOW_MS.
+    #[serde(default)]
+    pub quarantine_window_ms: u64,
+    /// How long a tenant is quarantined for the first time, in milliseconds. Each quarantine
+    /// after that lasts twice as long, upto `quarantine_max_ms`. 0 means
+    /// quarantine::DEFAULT_QUARANTINE_MS.
+    #[serde(default)]
+    pub quarantine_ms: u64,
+    /// The longest a tenant is quarantined for, in milliseconds. 0 means
+    /// quarantine::DEFAULT_MAX_QUARANTINE_MS.
+    #[serde(default)]
+    pub quarantine_max_ms: u64,
 }
 
 impl ServerConfig {
@@ -117,6 +649,16 @@ impl ServerConfig {
         load_config("server.toml")
     }
 
+    /// Returns `mac_address` as it appears in the config, so that it can be validated.
+    pub fn mac_address(&self) -> &str {
+        &self.mac_address
+    }
+
+    /// Returns `client_mac` as it appears in the config, so that it can be validated.
+    pub fn client_mac(&self) -> &str {
+        &self.client_mac
+    }
+
     /// Parse `mac_address` into NetBrick's format or panic if malformed.
     /// Linear time, so ideally we'd store this in ServerConfig, but TOML parsing makes that tricky.
     pub fn parse_mac(&self) -> MacAddress {
@@ -130,6 +672,210 @@ impl ServerConfig {
         parse_mac(&self.client_mac)
             .expect("Missing or malformed mac_address field in server config.")
     }
+
+    /// Parse `tao_fanout` into a Fanout, or panic if malformed.
+    pub fn tao_fanout(&self) -> Fanout {
+        match self.tao_fanout.as_str() {
+            "" => Fanout::Constant(4),
+            spec => Fanout::parse(spec).expect("Malformed tao_fanout field in server config."),
+        }
+    }
+
+    /// Parse `shared_tables` into a list of tables to be shared, or panic if malformed.
+    pub fn shared_tables(&self) -> Vec<SharedTable> {
+        parse_shared_tables(&self.shared_tables)
+            .expect("Malformed shared_tables field in server config.")
+    }
+
+    /// Parse `cache_tables` into a list of tables to be bounded, or panic if malformed.
+    pub fn cache_tables(&self) -> Vec<CacheTable> {
+        parse_cache_tables(&self.cache_tables)
+            .expect("Malformed cache_tables field in server config.")
+    }
+
+    /// Parse `audit_tenants` into a list of tenant ids, or panic if malformed.
+    pub fn audit_tenants(&self) -> Vec<u32> {
+        parse_tenants(&self.audit_tenants).expect("Malformed audit_tenants field in server config.")
+    }
+
+    /// Parse `checksum_tables` into a list of table ids, or panic if malformed.
+    pub fn checksum_tables(&self) -> Vec<u64> {
+        parse_tables(&self.checksum_tables)
+            .expect("Malformed checksum_tables field in server config.")
+    }
+
+    /// Parse `dedup_tables` into a list of table ids, or panic if malformed.
+    pub fn dedup_tables(&self) -> Vec<u64> {
+        parse_tables(&self.dedup_tables).expect("Malformed dedup_tables field in server config.")
+    }
+
+    /// Returns the layout the AUTH workload's records are filled in, out of `auth_cost`,
+    /// `auth_hash_len` and `auth_salt_len`, or panics if one is out of range.
+    pub fn auth_layout(&self) -> auth::Layout {
+        auth_layout(self.auth_cost, self.auth_hash_len, self.auth_salt_len)
+            .expect("Out of range auth_cost, auth_hash_len or auth_salt_len in server config.")
+    }
+
+    /// Returns the largest keys and values objects can be written with, out of `max_key_len`
+    /// and `max_value_len`. Refer to limits::Limits.
+    pub fn limits(&self) -> Limits {
+        let default = Limits::default();
+        let key = match self.max_key_len {
+            0 => default.max_key_len(),
+            len => len,
+        };
+        let value = match self.max_value_len {
+            0 => default.max_value_len(),
+            len => len,
+        };
+        Limits::new(key, value)
+    }
+
+    /// Returns the most bytes an invocation can stream back, out of `max_stream_bytes`.
+    pub fn max_stream_bytes(&self) -> usize {
+        match self.max_stream_bytes {
+            0 => DEFAULT_MAX_STREAM_BYTES,
+            cap => cap,
+        }
+    }
+
+    /// Returns the most deferred invocations a tenant can have outstanding, out of
+    /// `max_deferred`.
+    pub fn max_deferred(&self) -> usize {
+        match self.max_deferred {
+            0 => DEFAULT_MAX_DEFERRED,
+            cap => cap,
+        }
+    }
+
+    /// Returns how the results invocations ask to store are kept, out of `mailbox_ttl_ms`,
+    /// `mailbox_max_result` and `mailbox_quota_bytes`.
+    pub fn mailbox(&self) -> Mailbox {
+        let ttl = match self.mailbox_ttl_ms {
+            0 => mailbox::DEFAULT_TTL_MS,
+            ttl => ttl,
+        };
+        let max = match self.mailbox_max_result {
+            0 => mailbox::DEFAULT_MAX_RESULT,
+            max => max,
+        };
+        let quota = match self.mailbox_quota_bytes {
+            0 => mailbox::DEFAULT_QUOTA_BYTES,
+            quota => quota,
+        };
+        Mailbox::new(ttl, max, quota)
+    }
+
+    /// Returns the size of the hot arena and when objects are moved in and out of it, out of
+    /// `hot_arena_bytes`, `hot_arena_huge_pages`, `hot_reads`, `hot_window_ms` and `hot_windows`.
+    pub fn hot_arena(&self) -> HotPolicy {
+        let reads = match self.hot_reads {
+            0 => hot::DEFAULT_READS,
+            reads => reads,
+        };
+        let window_ms = match self.hot_window_ms {
+            0 => hot::DEFAULT_WINDOW_MS,
+            window_ms => window_ms,
+        };
+        let windows = match self.hot_windows {
+            0 => hot::DEFAULT_WINDOWS,
+            windows => windows,
+        };
+        HotPolicy::new(
+            self.hot_arena_bytes,
+            self.hot_arena_huge_pages,
+            reads,
+            window_ms,
+            windows,
+        )
+    }
+
+    /// Returns how tables are pre-sized, out of `table_load_factor` and `table_presize_max`.
+    pub fn presize(&self) -> Presize {
+        let load = if self.table_load_factor == 0.0 {
+            presize::DEFAULT_LOAD_FACTOR
+        } else {
+            self.table_load_factor
+        };
+        let max = match self.table_presize_max {
+            0 => Presize::default_max_records(load),
+            max => max,
+        };
+        Presize::new(load, max)
+    }
+
+    /// Returns how each core is shared between tenants, out of `fair_quantum_us`,
+    /// `fair_max_tenants` and `tenant_shares`. Panics if `tenant_shares` is malformed.
+    pub fn fairness(&self) -> FairPolicy {
+        let shares = parse_shares(&self.tenant_shares)
+            .expect("Malformed tenant_shares field in server config.");
+        FairPolicy {
+            quantum_us: self.fair_quantum_us,
+            max_tenants: match self.fair_max_tenants {
+                0 => DEFAULT_MAX_TENANTS,
+                max => max,
+            },
+            shares: shares.into_iter().collect(),
+        }
+    }
+
+    /// Returns how each core backs off polling, out of `poll_backoff_max_us` and
+    /// `poll_backoff_threshold`.
+    pub fn backoff(&self) -> BackoffPolicy {
+        BackoffPolicy {
+            max_pause_us: self.poll_backoff_max_us,
+            threshold: match self.poll_backoff_threshold {
+                0 => DEFAULT_THRESHOLD,
+                threshold => threshold,
+            },
+        }
+    }
+
+    /// Returns when tenants are quarantined and for how long, out of the `quarantine_*` fields.
+    pub fn quarantine(&self) -> QuarantinePolicy {
+        let or = |v: u64, d: u64| if v == 0 { d } else { v };
+        QuarantinePolicy {
+            thresholds: [
+                self.quarantine_malformed,
+                self.quarantine_ext_failures,
+                self.quarantine_auth_failures,
+            ],
+            window_ms: or(self.quarantine_window_ms, DEFAULT_WINDOW_MS),
+            quarantine_ms: or(self.quarantine_ms, DEFAULT_QUARANTINE_MS),
+            max_quarantine_ms: or(self.quarantine_max_ms, DEFAULT_MAX_QUARANTINE_MS),
+        }
+    }
+
+    /// Parse `enabled_opcodes` into a mask of OpCode::bit(), or panic if malformed.
+    pub fn opcodes(&self) -> u64 {
+        parse_opcodes(&self.enabled_opcodes)
+            .expect("Malformed enabled_opcodes field in server config.")
+    }
+
+    /// Parse `crash_policy` into a CrashPolicy, or panic if it is not recognized.
+    pub fn crash_policy(&self) -> CrashPolicy {
+        match self.crash_policy.as_str() {
+            "" | "abort" => CrashPolicy::Abort,
+            "isolate" => CrashPolicy::Isolate,
+            _ => panic!(
+                "Unknown crash_policy {} in server config.",
+                self.crash_policy
+            ),
+        }
+    }
+}
+
+/// What the server does after a scheduler core panics, once the panic has been reported.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CrashPolicy {
+    /// The whole process is aborted. The safest choice, since the panic could have left shared
+    /// state half way through an update.
+    Abort,
+
+    /// If the panic was inside an extension, and so caught at it's boundary, the extension is
+    /// disabled and further invokes of it are refused with StatusExtensionDisabled. The server
+    /// keeps serving everything else. Any other panic aborts the process.
+    Isolate,
 }
 
 /// All of the various configuration options needed to run a client, both optional and required.
@@ -158,6 +904,10 @@ pub struct ClientConfig {
 
     /// This parameter decides the requests type; native or extension.
     pub use_invoke: bool,
+    /// If true, the auth client re-enables native access to it's tenants' auth tables, which
+    /// the server fills invoke-only. Required for native gets and client-side pushback.
+    #[serde(default)]
+    pub auth_native_override: bool,
 
     /// Length of the key for requests generation.
     pub key_len: usize,
@@ -190,6 +940,27 @@ pub struct ClientConfig {
     pub combined: bool,
     /// The percentage of assoc_range() requests.
     pub assocs_p: usize,
+    /// The operation mix of invoke() based TAO runs, as comma separated "op:percentage" pairs;
+    /// see sandstorm::tao::parse_mix(). Empty means the mix published for Facebook's TAO. Native
+    /// runs only issue obj_gets and assoc_gets, split by `assocs_p`.
+    #[serde(default)]
+    pub tao_mix: String,
+    /// Multiplies the share of writes in `tao_mix`, shrinking the reads to make room. Writes
+    /// are capped at the whole mix. 0 means 1.
+    #[serde(default)]
+    pub tao_write_scale: f64,
+    /// The association types TAO operations are drawn from, as a comma separated list. Empty
+    /// means the type the server populates.
+    #[serde(default)]
+    pub tao_atypes: String,
+    /// The limits on assoc_ranges, as weighted buckets "LIMITxWEIGHT,...". Empty means
+    /// DEFAULT_TAO_RANGE_LIMITS.
+    #[serde(default)]
+    pub tao_range_limits: String,
+    /// The number of ids a TAO client pipeline reserves from the tao extension at a time, for
+    /// the objects it adds. 0 means DEFAULT_TAO_RESERVE_IDS.
+    #[serde(default)]
+    pub tao_reserve_ids: u32,
 
     /// The percentage of invoke() based requests that are long running.
     pub long_pct: usize,
@@ -198,6 +969,227 @@ pub struct ClientConfig {
 
     ///The number of bad requests to generate for every 10 million operations.
     pub bad_ptm: usize,
+
+    /// The transport used to reach the server; "dpdk" (the default if empty) or "udp".
+    #[serde(default)]
+    pub transport: String,
+    /// The first local port used by the kernel UDP transport. Pipeline `i` binds to this + i.
+    #[serde(default)]
+    pub udp_client_port: u16,
+    /// The first server port targeted by the kernel UDP transport.
+    #[serde(default)]
+    pub udp_server_port: u16,
+
+    /// If true, the outstanding window is paced using the load piggybacked on responses.
+    #[serde(default)]
+    pub congestion_aware: bool,
+    /// The smallest outstanding window congestion aware pacing will back off to. 0 means 1.
+    #[serde(default)]
+    pub window_min: u32,
+    /// The largest outstanding window congestion aware pacing will grow to. 0 means the
+    /// client's usual fixed window.
+    #[serde(default)]
+    pub window_max: u32,
+    /// Server load below which the window is grown additively. 0 means the default (4).
+    #[serde(default)]
+    pub load_low: u16,
+    /// Server load above which the window is cut multiplicatively. 0 means the default (16).
+    #[serde(default)]
+    pub load_high: u16,
+
+    /// The distribution keys are drawn from; "zipf" (the default if empty, uses `skew`),
+    /// "uniform", "hotspot" or "sequential".
+    #[serde(default)]
+    pub key_dist: String,
+    /// Fraction of the keyspace that is hot under the "hotspot" key distribution.
+    #[serde(default)]
+    pub hot_fraction: f64,
+    /// Fraction of operations that go to hot keys under the "hotspot" key distribution.
+    #[serde(default)]
+    pub hot_ops_fraction: f64,
+    /// The distribution tenants are drawn from. Same options as `key_dist`, but uses
+    /// `tenant_skew` for "zipf".
+    #[serde(default)]
+    pub tenant_dist: String,
+    /// Fraction of tenants that are hot under the "hotspot" tenant distribution.
+    #[serde(default)]
+    pub tenant_hot_fraction: f64,
+    /// Fraction of operations issued by hot tenants under the "hotspot" tenant distribution.
+    #[serde(default)]
+    pub tenant_hot_ops_fraction: f64,
+    /// The number of most popular key ranks whose keys are permuted every rotation epoch. 0
+    /// disables hot set rotation.
+    #[serde(default)]
+    pub hot_rotate_ranks: usize,
+    /// The length of a rotation epoch in seconds. Takes precedence over `hot_rotate_ops`.
+    #[serde(default)]
+    pub hot_rotate_secs: f64,
+    /// The length of a rotation epoch in keys drawn across all pipelines on the client.
+    #[serde(default)]
+    pub hot_rotate_ops: u64,
+
+    /// The number of records the analytics client asks the TopK extension for.
+    #[serde(default)]
+    pub topk: u32,
+    /// If true, clients that support it check responses against a locally recomputed result.
+    #[serde(default)]
+    pub validate: bool,
+
+    /// If true, requests ask the server to stamp it's receive and transmit times onto responses,
+    /// so that server-side and network latency can be reported separately.
+    #[serde(default)]
+    pub server_stamps: bool,
+
+    /// If true, a put is only issued once the previous put to the same key from the same
+    /// pipeline has completed, so that the server cannot apply them out of order.
+    #[serde(default)]
+    pub key_order: bool,
+    /// If true (and `key_order` is set), gets also wait for outstanding puts to the same key.
+    #[serde(default)]
+    pub read_your_writes: bool,
+    /// The number of requests a pipeline can hold back for ordering at once. 0 means 1024.
+    #[serde(default)]
+    pub key_order_deferred: usize,
+
+    /// The probability with which an outgoing request has a fault injected into it. Fault
+    /// injection is disabled if 0.
+    #[serde(default)]
+    pub fault_probability: f64,
+    /// Comma separated list of faults to inject; see fault::Fault::parse().
+    #[serde(default)]
+    pub faults: String,
+    /// Seed for fault injection. 0 picks a random seed.
+    #[serde(default)]
+    pub fault_seed: u32,
+
+    /// Responses to requests that completed within these many completions are dropped as
+    /// duplicates. 0 means 4096.
+    #[serde(default)]
+    pub dup_window: usize,
+
+    /// The maximum number of requests in a chain of dependent requests. 0 means 8.
+    #[serde(default)]
+    pub max_chain_depth: usize,
+    /// If true, requests are tagged with `run_id`, so that the server counts them against this
+    /// run and can report what the run did.
+    #[serde(default)]
+    pub tag_run: bool,
+    /// The id requests are tagged with if `tag_run` is set. 0 picks a random id.
+    #[serde(default)]
+    pub run_id: u64,
+
+    /// The latency of one in these many requests is recorded. Pushbacks and errors are always
+    /// recorded. 0 means 1, ie. every request.
+    #[serde(default)]
+    pub latency_sample: u64,
+
+    /// The staleness budget on get()s, in microseconds, unless one is passed in on the request.
+    /// A get() can be served off a copy of the object that lags behind the primary by atmost
+    /// this much. 0 means strictly fresh.
+    #[serde(default)]
+    pub get_staleness_us: u32,
+
+    /// The seed the workload generators derive their random numbers from. A run is reproduced
+    /// exactly by rerunning it with the same seed and config. 0 picks a random seed, which is
+    /// logged.
+    #[serde(default)]
+    pub seed: u64,
+
+    /// The most requests a sender holds back to send out the NIC in a single burst. 0 and 1
+    /// send every request out as soon as it is generated.
+    #[serde(default)]
+    pub tx_burst: usize,
+    /// The longest a sender holds back a request to fill a burst, in microseconds.
+    #[serde(default)]
+    pub tx_hold_us: u64,
+
+    /// The IP address of a backup server, filled with the same records as the primary. If set,
+    /// the client moves to it once the primary stops responding. Empty means no backup.
+    #[serde(default)]
+    pub backup_server_ip_address: String,
+    /// The first UDP port of the backup server. 0 means `udp_server_port`.
+    #[serde(default)]
+    pub backup_udp_server_port: u16,
+    /// How long a request waits for it's response before it is given up on, in microseconds,
+    /// when a backup is configured. 0 means 100 milliseconds.
+    #[serde(default)]
+    pub failover_timeout_us: u64,
+    /// The number of timeouts in a row after which the primary is declared dead. 0 means 3.
+    #[serde(default)]
+    pub failover_threshold: u32,
+
+    /// The bcrypt cost the server hashed the AUTH workload's records at; must match the
+    /// server's auth_cost. Native gets of a record hashed at any other cost fail verification.
+    /// 0 means 1.
+    #[serde(default)]
+    pub auth_cost: u8,
+    /// The length of the hash on each AUTH record; must match the server's auth_hash_len. 0
+    /// means 24.
+    #[serde(default)]
+    pub auth_hash_len: usize,
+    /// The length of the salt on each AUTH record; must match the server's auth_salt_len. 0
+    /// means 16.
+    #[serde(default)]
+    pub auth_salt_len: usize,
+
+    /// If true, each pipeline counts cycles, instructions, and cache and branch misses on it's
+    /// thread using the CPU's hardware counters, and reports them with it's results. Pipelines
+    /// that can't open the counters report why instead.
+    #[serde(default)]
+    pub perf_counters: bool,
+
+    /// The number of objects a client caches off get() responses the server hinted hot; see
+    /// splinter::cache. Only native YCSB runs cache objects. 0 turns caching off.
+    #[serde(default)]
+    pub hint_cache: usize,
+
+    /// The order in which invocations the server pushed back are run on the client; "fifo" (the
+    /// default if empty) or "srf" for shortest remaining first. Refer to QueuePolicy.
+    #[serde(default)]
+    pub pushback_queue: String,
+}
+
+/// The transport a client uses to send requests to, and receive responses from, a server.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Transport {
+    /// Packets are sent and received over a DPDK managed NIC.
+    Dpdk,
+
+    /// Packets are sent and received over ordinary kernel UDP sockets.
+    Udp,
+}
+
+impl Transport {
+    /// Returns a label for the transport, used to tag statistics.
+    pub fn label(&self) -> &'static str {
+        match *self {
+            Transport::Dpdk => "dpdk",
+            Transport::Udp => "udp",
+        }
+    }
+}
+
+/// The order in which a client runs the invocations the server pushed back to it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QueuePolicy {
+    /// Invocations run in the order they were pushed back, and go to the back of the queue
+    /// every time they yield.
+    Fifo,
+
+    /// The invocation estimated to have the least time left to run goes first, off the moving
+    /// average cost of it's extension. Invocations without an estimate go after every other,
+    /// in the order they were queued.
+    ShortestRemaining,
+}
+
+impl QueuePolicy {
+    /// Returns a label for the policy, used to tag statistics.
+    pub fn label(&self) -> &'static str {
+        match *self {
+            QueuePolicy::Fifo => "fifo",
+            QueuePolicy::ShortestRemaining => "srf",
+        }
+    }
 }
 
 impl ClientConfig {
@@ -207,6 +1199,16 @@ impl ClientConfig {
         load_config_cl("client.toml")
     }
 
+    /// Returns `mac_address` as it appears in the config, so that it can be validated.
+    pub fn mac_address(&self) -> &str {
+        &self.mac_address
+    }
+
+    /// Returns `server_mac_address` as it appears in the config, so that it can be validated.
+    pub fn server_mac_address(&self) -> &str {
+        &self.server_mac_address
+    }
+
     /// Parse `mac_address` into NetBrick's format or panic if malformed.
     /// Linear time, so ideally we'd store this in ClientConfig, but TOML parsing makes that tricky.
     pub fn parse_mac(&self) -> MacAddress {
@@ -214,17 +1216,82 @@ impl ClientConfig {
             .expect("Missing or malformed mac_address field in client config.")
     }
 
+    /// Parse `transport` into a Transport, or panic if it is not recognized.
+    pub fn transport(&self) -> Transport {
+        match self.transport.as_str() {
+            "" | "dpdk" => Transport::Dpdk,
+            "udp" => Transport::Udp,
+            _ => panic!("Unknown transport {} in client config.", self.transport),
+        }
+    }
+
+    /// Parse `pushback_queue` into a QueuePolicy, or panic if it is not recognized.
+    pub fn pushback_queue(&self) -> QueuePolicy {
+        match self.pushback_queue.as_str() {
+            "" | "fifo" => QueuePolicy::Fifo,
+            "srf" => QueuePolicy::ShortestRemaining,
+            _ => panic!(
+                "Unknown pushback_queue {} in client config.",
+                self.pushback_queue
+            ),
+        }
+    }
+
     /// Parse `server_mac_address` into NetBrick's format or panic if malformed.
     /// Linear time, so ideally we'd store this in ClientConfig, but TOML parsing makes that tricky.
     pub fn parse_server_mac(&self) -> MacAddress {
         parse_mac(&self.server_mac_address)
             .expect("Missing or malformed server_mac_address field in client config.")
     }
+
+    /// Parse `tao_mix` into the percentage of each operation in order of tao::OPS, or panic if
+    /// malformed. `tao_write_scale` is not applied.
+    pub fn tao_mix(&self) -> [f64; 8] {
+        match self.tao_mix.as_str() {
+            "" => tao::published_mix(),
+            spec => tao::parse_mix(spec).expect("Malformed tao_mix field in client config."),
+        }
+    }
+
+    /// Parse `tao_atypes` into a list of association types, or panic if malformed.
+    pub fn tao_atypes(&self) -> Vec<u16> {
+        match parse_atypes(&self.tao_atypes) {
+            Some(ref atypes) if atypes.is_empty() => vec![tao::FILL_ATYPE],
+            Some(atypes) => atypes,
+            None => panic!("Malformed tao_atypes field in client config."),
+        }
+    }
+
+    /// Returns the layout of the AUTH workload's records, out of `auth_cost`, `auth_hash_len`
+    /// and `auth_salt_len`, or panics if one is out of range.
+    pub fn auth_layout(&self) -> auth::Layout {
+        auth_layout(self.auth_cost, self.auth_hash_len, self.auth_salt_len)
+            .expect("Out of range auth_cost, auth_hash_len or auth_salt_len in client config.")
+    }
+
+    /// Parse `tao_range_limits` into (limit, weight) buckets, or panic if malformed.
+    pub fn tao_range_limits(&self) -> Vec<(u32, u32)> {
+        let spec = match self.tao_range_limits.as_str() {
+            "" => DEFAULT_TAO_RANGE_LIMITS,
+            spec => spec,
+        };
+        tao::parse_buckets(spec).expect("Malformed tao_range_limits field in client config.")
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::parse_mac;
+    use super::{
+        parse_cache_tables, parse_cores, parse_mac, parse_opcodes, parse_shared_tables,
+        parse_shares, parse_tenants, CacheTable, ServerConfig, SharedTable,
+    };
+    use backoff::{BackoffPolicy, DEFAULT_THRESHOLD};
+    use evict::{CacheTarget, EvictPolicy};
+    use hot::HotPolicy;
+    use mailbox::Mailbox;
+    use presize::Presize;
+    use toml;
+    use wireformat::{OpCode, OPCODES_ALL, OPCODES_REQUIRED};
 
     #[test]
     fn empty_str() {
@@ -269,4 +1336,191 @@ mod tests {
         }
     }
 
+    #[test]
+    fn shared_tables() {
+        let shared = |owner, table, tenant, alias| SharedTable {
+            owner: owner,
+            table: table,
+            tenant: tenant,
+            alias: alias,
+        };
+
+        assert_eq!(Some(vec![]), parse_shared_tables(""));
+        assert_eq!(
+            Some(vec![shared(1, 1, 2, 7), shared(1, 1, 3, 7)]),
+            parse_shared_tables("1:1@2:7, 1:1@3:7")
+        );
+        assert_eq!(None, parse_shared_tables("1:1"));
+        assert_eq!(None, parse_shared_tables("1:1@2"));
+        assert_eq!(None, parse_shared_tables("1:1@2:7@3:7"));
+        assert_eq!(None, parse_shared_tables("1:x@2:7"));
+    }
+
+    #[test]
+    fn cache_tables() {
+        let cache = |tenant, table, bytes, objects, policy| CacheTable {
+            tenant: tenant,
+            table: table,
+            target: CacheTarget::new(bytes, objects, policy).unwrap(),
+        };
+
+        assert_eq!(Some(vec![]), parse_cache_tables(""));
+        assert_eq!(
+            Some(vec![
+                cache(1, 1, 1 << 20, 0, EvictPolicy::Read),
+                cache(2, 7, 0, 500, EvictPolicy::Written),
+            ]),
+            parse_cache_tables("1:1:1048576:0:read, 2:7:0:500:written")
+        );
+        assert_eq!(None, parse_cache_tables("1:1:0:0:read"));
+        assert_eq!(None, parse_cache_tables("1:1:100:0:lru"));
+        assert_eq!(None, parse_cache_tables("1:1:100:0"));
+        assert_eq!(None, parse_cache_tables("1:x:100:0:read"));
+    }
+
+    #[test]
+    fn cores() {
+        assert_eq!(Some(vec![]), parse_cores(""));
+        assert_eq!(Some(vec![3]), parse_cores("3"));
+        assert_eq!(Some(vec![0, 1, 2, 8, 10, 11]), parse_cores("0-2, 8,10-11"));
+        assert_eq!(None, parse_cores("2-1"));
+        assert_eq!(None, parse_cores("1-2-3"));
+        assert_eq!(None, parse_cores("-1"));
+        assert_eq!(None, parse_cores("a"));
+    }
+
+    #[test]
+    fn tenants() {
+        assert_eq!(Some(vec![]), parse_tenants(""));
+        assert_eq!(Some(vec![1, 7]), parse_tenants("1, 7,"));
+        assert_eq!(None, parse_tenants("1,-2"));
+        assert_eq!(None, parse_tenants("1:2"));
+    }
+
+    #[test]
+    fn shares() {
+        assert_eq!(Some(vec![]), parse_shares(""));
+        assert_eq!(Some(vec![(1, 4), (7, 1)]), parse_shares("1:4, 7 : 1,"));
+        assert_eq!(None, parse_shares("1:0"));
+        assert_eq!(None, parse_shares("1"));
+        assert_eq!(None, parse_shares("1:2:3"));
+        assert_eq!(None, parse_shares("a:2"));
+    }
+
+    #[test]
+    fn opcodes() {
+        let get = OpCode::SandstormGetRpc.bit();
+        let put = OpCode::SandstormPutRpc.bit();
+        assert_eq!(Some(OPCODES_ALL), parse_opcodes(""));
+        assert_eq!(Some(OPCODES_REQUIRED | get), parse_opcodes("get"));
+        assert_eq!(
+            Some(OPCODES_REQUIRED | get | put),
+            parse_opcodes("get, put,")
+        );
+        assert_eq!(Some(OPCODES_REQUIRED), parse_opcodes("echo,drain"));
+        assert_eq!(None, parse_opcodes("get,range"));
+
+        let delete = OpCode::SandstormDeleteRpc.bit();
+        assert_eq!(Some(OPCODES_REQUIRED | delete), parse_opcodes("delete"));
+        let scan = OpCode::SandstormScanRpc.bit();
+        assert_eq!(Some(OPCODES_REQUIRED | scan), parse_opcodes("scan"));
+        let cas = OpCode::SandstormCasRpc.bit();
+        assert_eq!(Some(OPCODES_REQUIRED | cas), parse_opcodes("cas"));
+        let drop_table = OpCode::SandstormDropTableRpc.bit();
+        assert_eq!(
+            Some(OPCODES_REQUIRED | drop_table),
+            parse_opcodes("drop_table")
+        );
+    }
+
+    // Tests that the prefault settings are read off the server config, and are off by default.
+    #[test]
+    fn prefault() {
+        let example = include_str!("../server.toml-example");
+        let config: ServerConfig = toml::from_str(example).expect("Malformed example config.");
+        assert_eq!(0, config.heap_prefault_mb);
+        assert!(!config.heap_huge_pages);
+
+        let example = example
+            .replace("heap_prefault_mb = 0", "heap_prefault_mb = 256")
+            .replace("heap_huge_pages = false", "heap_huge_pages = true");
+        let config: ServerConfig = toml::from_str(&example).expect("Malformed example config.");
+        assert_eq!(256, config.heap_prefault_mb);
+        assert!(config.heap_huge_pages);
+    }
+
+    // Tests that polling backoff is off by default, and that the threshold defaults to 256.
+    #[test]
+    fn backoff() {
+        let example = include_str!("../server.toml-example");
+        let config: ServerConfig = toml::from_str(example).expect("Malformed example config.");
+        assert!(!config.backoff().enabled());
+        assert_eq!(DEFAULT_THRESHOLD, config.backoff().threshold);
+
+        let example = example
+            .replace("poll_backoff_max_us = 0", "poll_backoff_max_us = 50")
+            .replace("poll_backoff_threshold = 0", "poll_backoff_threshold = 16");
+        let config: ServerConfig = toml::from_str(&example).expect("Malformed example config.");
+        assert_eq!(
+            BackoffPolicy {
+                max_pause_us: 50,
+                threshold: 16,
+            },
+            config.backoff()
+        );
+    }
+
+    // Tests that results are kept by the default policy unless configured.
+    #[test]
+    fn mailbox() {
+        let example = include_str!("../server.toml-example");
+        let config: ServerConfig = toml::from_str(example).expect("Malformed example config.");
+        assert_eq!(Mailbox::default(), config.mailbox());
+
+        let example = example
+            .replace("mailbox_ttl_ms = 0", "mailbox_ttl_ms = 500")
+            .replace("mailbox_max_result = 0", "mailbox_max_result = 64")
+            .replace("mailbox_quota_bytes = 0", "mailbox_quota_bytes = 4096");
+        let config: ServerConfig = toml::from_str(&example).expect("Malformed example config.");
+        assert_eq!(Mailbox::new(500, 64, 4096), config.mailbox());
+    }
+
+    // Tests that there is no hot arena unless configured, and that objects are moved in and out
+    // of one by the default policy unless configured.
+    #[test]
+    fn hot_arena() {
+        let example = include_str!("../server.toml-example");
+        let config: ServerConfig = toml::from_str(example).expect("Malformed example config.");
+        assert_eq!(HotPolicy::default(), config.hot_arena());
+        assert!(config.hot_arena().arena().is_none());
+
+        let example = example
+            .replace("hot_arena_bytes = 0", "hot_arena_bytes = 4194304")
+            .replace(
+                "hot_arena_huge_pages = false",
+                "hot_arena_huge_pages = true",
+            )
+            .replace("hot_reads = 0", "hot_reads = 8")
+            .replace("hot_window_ms = 0", "hot_window_ms = 50")
+            .replace("hot_windows = 0", "hot_windows = 2");
+        let config: ServerConfig = toml::from_str(&example).expect("Malformed example config.");
+        assert_eq!(HotPolicy::new(4 << 20, true, 8, 50, 2), config.hot_arena());
+        assert_eq!(4 << 20, config.hot_arena().arena().unwrap().capacity());
+    }
+
+    // Tests that tables are pre-sized at the default load factor unless configured, and that
+    // the cap on pre-sizing is read off the server config.
+    #[test]
+    fn presize() {
+        let example = include_str!("../server.toml-example");
+        let config: ServerConfig = toml::from_str(example).expect("Malformed example config.");
+        assert_eq!(Presize::default(), config.presize());
+
+        let example = example
+            .replace("table_load_factor = 0.0", "table_load_factor = 0.5")
+            .replace("table_presize_max = 0", "table_presize_max = 1000");
+        let config: ServerConfig = toml::from_str(&example).expect("Malformed example config.");
+        assert_eq!(Presize::new(0.5, 1000), config.presize());
+    }
+
 }
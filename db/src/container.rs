@@ -14,21 +14,29 @@
  */
 
 use std::cell::Cell;
+use std::mem;
 use std::ops::{Generator, GeneratorState};
-use std::panic::*;
 use std::rc::Rc;
+use std::sync::Arc;
 use std::thread;
 
+use super::audit::{AuditEntry, AuditLog};
 use super::context::Context;
+use super::crash::{self, Breadcrumb};
 use super::cycles;
+use super::defer::Deferred;
+use super::rpc::ResponseBuf;
 use super::task::TaskState::*;
 use super::task::{Task, TaskPriority, TaskState};
+use super::wireformat::{OpCode, RpcStatus};
 
 use e2d2::common::EmptyMetadata;
 use e2d2::headers::UdpHeader;
 use e2d2::interface::Packet;
 
-use sandstorm::common::PACKET_UDP_LEN;
+use sandstorm::common::{TenantId, PACKET_UDP_LEN};
+use sandstorm::ext::Extension;
+use sandstorm::profile;
 
 /// A container for untrusted code that can be scheduled by the database.
 pub struct Container<'a> {
@@ -55,6 +63,36 @@ pub struct Container<'a> {
     // The actual generator/coroutine containing the extension's code to be
     // executed inside the database.
     gen: Option<Box<Generator<Yield = u64, Return = u64>>>,
+
+    // The extension the generator came from, if known. It's moving average cost is updated
+    // when the container completes, and estimates how long the container has left to run.
+    ext: Option<Arc<Extension>>,
+
+    // The address the generator is resumed through, if the extension is being profiled. Every
+    // run's cycles outside the database are sampled at this address.
+    profile: Option<usize>,
+
+    // The time-stamp, in cycles, at which the container was enqueued on a scheduler.
+    enqueued: Option<u64>,
+
+    // The tenant whose request the container serves, if known.
+    tenant: Option<TenantId>,
+
+    // The invocation the container runs, as reported if the extension panics.
+    crumb: Breadcrumb,
+
+    // The tenant's audit log, and what is known about the invocation before it runs, if the
+    // tenant is being audited. The rest of the entry is filled in on completion.
+    audit: Option<(Arc<AuditLog>, AuditEntry)>,
+
+    // Set once the extension has streamed back part of it's response, or deferred work.
+    streamed: bool,
+
+    // The work the extension deferred, taken out of the context once it completes successfully.
+    deferred: Vec<Deferred>,
+
+    // Set if the extension panicked. It's response then fails with StatusExtensionError.
+    panicked: bool,
 }
 
 // Implementation of methods on Container.
@@ -88,8 +126,55 @@ impl<'a> Container<'a> {
             db_time: 0,
             db: Cell::new(Some(context)),
             gen: Some(gen),
+            ext: None,
+            profile: None,
+            enqueued: None,
+            tenant: None,
+            crumb: Breadcrumb::new(OpCode::SandstormInvokeRpc as u8, 0, 0, 0, 0),
+            audit: None,
+            streamed: false,
+            deferred: Vec::new(),
+            panicked: false,
         }
     }
+
+    /// Accounts the cycles this container spends inside the extension to the extension: they
+    /// are folded into it's moving average cost on completion, and sampled into it's profile
+    /// if it is being profiled.
+    ///
+    /// # Arguments
+    ///
+    /// * `ext`: The extension the container's generator was retrieved from.
+    pub fn extension(&mut self, ext: Arc<Extension>) {
+        if ext.profile().is_some() {
+            if let Some(ref gen) = self.gen {
+                self.profile = Some(profile::resume_address(&**gen));
+            }
+        }
+
+        self.ext = Some(ext);
+    }
+
+    /// Sets the invocation the container runs, so that a panic inside the extension is reported
+    /// along with it.
+    ///
+    /// # Arguments
+    ///
+    /// * `crumb`: The tenant, RPC identifier, and name of the extension invoked.
+    pub fn breadcrumb(&mut self, crumb: Breadcrumb) {
+        self.crumb = crumb;
+    }
+
+    /// Records the invocation into the tenant's audit log once it completes (or is pushed back).
+    ///
+    /// # Arguments
+    ///
+    /// * `log`:   The audit log of the tenant that issued the invocation.
+    /// * `entry`: The name hash, RPC identifier, and argument length of the invocation. The
+    ///            remaining fields are filled in on completion.
+    pub fn audit(&mut self, log: Arc<AuditLog>, entry: AuditEntry) {
+        self.audit = Some((log, entry));
+    }
 }
 
 // Implementation of the Task trait for Container.
@@ -97,6 +182,9 @@ impl<'a> Task for Container<'a> {
     /// Refer to the Task trait for Documentation.
     fn run(&mut self) -> (TaskState, u64) {
         let start = cycles::rdtsc();
+        let db_time = self.db_time;
+        let crumb = self.crumb;
+        let mut finished = false;
 
         // Resume the task if need be. The task needs to be run/resumed only
         // if it is in the INITIALIZED or YIELDED state. Nothing needs to be
@@ -107,7 +195,7 @@ impl<'a> Task for Container<'a> {
             // As of 04/02/2018, calling resume() on a generator requires an unsafe block.
             unsafe {
                 // Catch any panics thrown from within the extension.
-                let res = catch_unwind(AssertUnwindSafe(|| match self.gen.as_mut() {
+                let res = crash::contain(&crumb, || match self.gen.as_mut() {
                     Some(gen) => match gen.resume() {
                         GeneratorState::Yielded(_) => {
                             if let Some(db) = self.db.get_mut() {
@@ -121,18 +209,29 @@ impl<'a> Task for Container<'a> {
                                 self.db_time = db.db_credit();
                             }
                             self.state = COMPLETED;
+                            finished = true;
                         }
                     },
 
                     None => {
                         panic!("No generator available for extension execution");
                     }
-                }));
+                });
 
                 // If there was a panic thrown, then mark the container as COMPLETED so that it
-                // does not get run again.
+                // does not get run again. If the crash policy isolates panics, the extension is
+                // disabled too.
                 if let Err(_) = res {
                     self.state = COMPLETED;
+                    self.panicked = true;
+                    if let Some(db) = self.db.get_mut() {
+                        db.take_deferred();
+                    }
+                    if crash::isolate() {
+                        if let Some(ref ext) = self.ext {
+                            ext.disable();
+                        }
+                    }
                     if thread::panicking() {
                         // Wait for 100 millisecond so that the thread is moved to the GHETTO core.
                         let start = cycles::rdtsc();
@@ -148,6 +247,19 @@ impl<'a> Task for Container<'a> {
         // Update the total execution time of the task.
         self.time += exec;
 
+        // Sample the time spent inside the extension itself, i.e. outside the database. Panics
+        // are left out of the extension's cost; they don't say how long it usually runs for.
+        if let Some(ref ext) = self.ext {
+            if let (Some(addr), Some(profile)) = (self.profile, ext.profile()) {
+                let db = self.db_time.saturating_sub(db_time);
+                profile.sample(addr, exec.saturating_sub(db));
+            }
+
+            if finished {
+                ext.record_cost(self.time.saturating_sub(self.db_time));
+            }
+        }
+
         // Return the state and the amount of time the task executed for.
         return (self.state, exec);
     }
@@ -191,9 +303,47 @@ impl<'a> Task for Container<'a> {
                 // If the task is stopped without completion, set the status as StatusPushback.
                 if self.state == STOPPED {
                     db.prepare_for_pushback();
+                } else {
+                    db.complete_durable();
+                    // The result is stored before the response is handed back to be sent out,
+                    // so that a tenant that never receives the response always finds it.
+                    db.complete_mailbox(self.panicked);
                 }
 
+                // A durable invocation resumed after a restart has no one to respond to, and
+                // neither does one running deferred work.
+                let detached = db.is_detached();
+                let deferral = db.deferral();
+                let deferred = db.take_deferred();
                 let (req, mut res) = db.commit();
+                if self.panicked {
+                    res.truncate(0);
+                    res.get_mut_header().common_header.status = RpcStatus::StatusExtensionError;
+                }
+
+                // Deferred work runs only if the invocation succeeded.
+                let ok = res.get_header().common_header.status == RpcStatus::StatusOk;
+                if ok {
+                    self.deferred = deferred;
+                }
+                if let Some(deferrals) = deferral {
+                    deferrals.release(ok);
+                }
+
+                // Only the header and payload lengths are read off the response; never it's bytes.
+                if let Some((ref log, mut entry)) = self.audit {
+                    entry.stamp = cycles::rdtsc();
+                    entry.status = res.get_header().common_header.status.clone() as u8;
+                    entry.res_len = res.get_payload().len() as u32;
+                    entry.cycles = self.time;
+                    log.record(&entry);
+                }
+
+                if detached {
+                    req.free_packet();
+                    res.free_packet();
+                    return None;
+                }
 
                 let req = req.deparse_header(PACKET_UDP_LEN as usize);
                 let res = res.deparse_header(PACKET_UDP_LEN as usize);
@@ -214,4 +364,54 @@ impl<'a> Task for Container<'a> {
 
     /// Refer to the `Task` trait for Documentation.
     fn update_cache(&mut self, _record: &[u8], _keylen: usize) {}
+
+    /// Refer to the `Task` trait for Documentation.
+    fn set_enqueued(&mut self, at: u64) {
+        self.enqueued = Some(at);
+    }
+
+    /// Refer to the `Task` trait for Documentation.
+    fn enqueued(&self) -> Option<u64> {
+        self.enqueued
+    }
+
+    /// Refer to the `Task` trait for Documentation.
+    fn set_tenant(&mut self, tenant: TenantId) {
+        self.tenant = Some(tenant);
+    }
+
+    /// Refer to the `Task` trait for Documentation.
+    fn tenant(&self) -> Option<TenantId> {
+        self.tenant
+    }
+
+    /// Refer to the `Task` trait for Documentation.
+    fn partials(&mut self) -> Vec<Packet<UdpHeader, EmptyMetadata>> {
+        match self.db.get_mut() {
+            Some(db) => {
+                self.streamed |= db.streamed() || db.deferring();
+                db.partials()
+            }
+
+            None => Vec::new(),
+        }
+    }
+
+    /// Refer to the `Task` trait for Documentation.
+    fn streamed(&self) -> bool {
+        self.streamed
+    }
+
+    /// Refer to the `Task` trait for Documentation.
+    fn deferred(&mut self) -> Vec<Deferred> {
+        mem::replace(&mut self.deferred, Vec::new())
+    }
+
+    /// Refer to the `Task` trait for Documentation.
+    fn remaining(&self) -> Option<u64> {
+        match self.ext.as_ref().map(|ext| ext.cost()) {
+            None | Some(0) => None,
+            Some(cost) => Some(cost.saturating_sub(self.time.saturating_sub(self.db_time))),
+        }
+    }
 }
@@ -13,23 +13,40 @@
  * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
  */
 
-use std::cell::{Cell, RefCell};
+use std::cell::{Cell, Ref, RefCell};
 use std::sync::Arc;
-use std::{mem, slice, str};
+use std::{cmp, mem, slice, str};
 
 use super::alloc::Allocator;
 use super::cycles::*;
-use super::table::Version;
+use super::defer::{Deferrals, Deferred, MAX_DEFERRED};
+use super::journal::{Checkpoint, Journal};
+use super::mailbox::Mailbox;
+use super::merge;
+use super::rpc::{self, ResponseBuf};
+use super::snapshot;
+use super::stream::Stream;
+use super::table::{Table, Version, N_BUCKETS};
 use super::tenant::Tenant;
+use super::trailer::{self, TrailerWriter, TRAILER_RWSET};
 use super::tx::TX;
-use super::wireformat::{InvokeRequest, InvokeResponse, OpType, Record, RpcStatus};
+use super::wireformat::{
+    InvokeRequest, InvokeResponse, OpType, Record, RpcStatus, DURABLE_RESULTS_TABLE,
+    RESPONSE_FLAG_TRAILERS, STREAM_FLAG_MORE,
+};
 use util::model::Model;
 
-use sandstorm::buf::{MultiReadBuf, ReadBuf, WriteBuf};
+use bytes::Bytes;
+
+use sandstorm::buf::{projection, MultiReadBuf, ReadBuf, WriteBuf};
 use sandstorm::common::*;
-use sandstorm::db::DB;
+use sandstorm::db::{PutManyError, Versioned, DB};
+use sandstorm::defer::MAX_DEFER_ARGS;
+use sandstorm::invoke;
+use sandstorm::pack::pack;
 
 use e2d2::common::EmptyMetadata;
+use e2d2::headers::UdpHeader;
 use e2d2::interface::Packet;
 
 /// The maximum number of bytes that can be allocated by an instance of an
@@ -39,6 +56,25 @@ const MAX_ALLOC: usize = 10240;
 /// The flag to enable-disable including the RW set in the pushback response.
 const INCLUDE_RWSET: bool = true;
 
+/// State required by a Context to run an invocation durably.
+pub struct Durable {
+    /// The journal checkpoints are written to.
+    pub journal: Arc<Journal>,
+
+    /// The name of the invoked extension.
+    pub name: Vec<u8>,
+
+    /// Hash over the name and arguments identifying the invocation.
+    pub args_hash: u64,
+
+    /// The checkpoint the invocation is being resumed from, if any.
+    pub restored: Option<Checkpoint>,
+
+    /// True if the invocation was resumed after a restart. There is no tenant waiting on the
+    /// response of such an invocation; it's result is only written to the results table.
+    pub detached: bool,
+}
+
 /// This type is passed into the init method of every extension. The methods
 /// on this type form the interface allowing extensions to read and write
 /// data from and to the database. The constructors for this type (new() and
@@ -58,12 +94,24 @@ pub struct Context<'a> {
     // request packet/buffer's payload.
     args_length: usize,
 
+    // The offset inside the extension's arguments of the field next_arg() reads
+    // next.
+    arg_cursor: Cell<usize>,
+
     // A pre-populated RPC response packet/buffer for the invoked extension.
     // This is required because the extension might need to return something
     // to the issuing client/tenant. For example, a get() extension will need
     // to return a value to the issuing client/tenant.
     response: RefCell<Packet<InvokeResponse, EmptyMetadata>>,
 
+    // Set if something the extension wrote to the response did not fit into it. Nothing more is
+    // written to the response once this is set, and the invocation fails on commit.
+    truncated: Cell<bool>,
+
+    // Set if the extension tried to write to a table that the tenant can only read. The
+    // invocation fails with StatusReadOnlyTable on commit.
+    read_only: Cell<bool>,
+
     // The tenant that invoked this extension. Required to access the tenant's
     // data, and potentially for accounting.
     tenant: Arc<Tenant>,
@@ -84,6 +132,40 @@ pub struct Context<'a> {
 
     // The model for a given extension which is stored based on the name of the extension.
     model: Option<Arc<Model>>,
+
+    // Journal and identity of the invocation if it is durable.
+    durable: Option<Durable>,
+
+    // How the invocation's result is stored, if the request asked for it to be.
+    mailbox: Option<Mailbox>,
+
+    // The responses the extension has streamed back so far, if it is allowed to stream.
+    stream: RefCell<Option<Stream>>,
+
+    // Partial responses flushed by the extension that have not been handed to the scheduler
+    // yet. Parsed upto their UDP header.
+    partials: RefCell<Vec<Packet<UdpHeader, EmptyMetadata>>>,
+
+    // The arguments of the work the extension deferred through defer(), in order.
+    deferred: RefCell<Vec<Vec<u8>>>,
+
+    // The tenant's deferral counters, if this invocation runs work deferred by an earlier one.
+    deferral: Option<Arc<Deferrals>>,
+
+    // The name of the invoked extension, if the request carried it's interned id in place of
+    // the name.
+    name: Option<Arc<Vec<u8>>>,
+}
+
+// Packs a record an extension read or wrote onto the read-write set sent back with a pushback
+// response, as it's optype, version, key and object.
+fn pack_record(buf: &mut Vec<u8>, record: &Record) {
+    let ptr = &record.get_optype() as *const _ as *const u8;
+    buf.extend_from_slice(unsafe { slice::from_raw_parts(ptr, mem::size_of::<OpType>()) });
+    let ptr = &record.get_version() as *const _ as *const u8;
+    buf.extend_from_slice(unsafe { slice::from_raw_parts(ptr, mem::size_of::<Version>()) });
+    buf.extend_from_slice(record.get_key().as_ref());
+    buf.extend_from_slice(record.get_object().as_ref());
 }
 
 // Methods on Context.
@@ -119,19 +201,199 @@ impl<'a> Context<'a> {
             request: req,
             args_offset: args_off,
             args_length: args_len,
+            arg_cursor: Cell::new(0),
             response: RefCell::new(res),
+            truncated: Cell::new(false),
+            read_only: Cell::new(false),
             tenant: tenant,
             heap: alloc,
             allocs: Cell::new(0),
             tx: RefCell::new(TX::new()),
             db_credit: RefCell::new(0),
             model: model,
+            durable: None,
+            mailbox: None,
+            stream: RefCell::new(None),
+            partials: RefCell::new(Vec::new()),
+            deferred: RefCell::new(Vec::new()),
+            deferral: None,
+            name: None,
+        }
+    }
+
+    /// This method makes the invocation durable. Checkpoints taken by the extension will be
+    /// written to the journal, and it's result to the tenant's DURABLE_RESULTS_TABLE.
+    ///
+    /// # Arguments
+    ///
+    /// * `durable`: The journal, and the identity of the invocation within it.
+    pub fn set_durable(&mut self, durable: Durable) {
+        self.durable = Some(durable);
+    }
+
+    /// This method asks for the invocation's result to be stored in the tenant's mailbox under
+    /// the id of the invoke() request, before it's response is sent out. Such an invocation
+    /// should not be allowed to stream.
+    ///
+    /// # Arguments
+    ///
+    /// * `mailbox`: How the result is stored.
+    pub fn set_mailbox(&mut self, mailbox: Mailbox) {
+        self.mailbox = Some(mailbox);
+    }
+
+    /// This method sets the name of the invoked extension, for a request that named it by the
+    /// id it is interned under instead. Work the extension defers is invoked under this name.
+    ///
+    /// # Arguments
+    ///
+    /// * `name`: The name the id was interned for. Refer to ExtensionManager::get_by_id().
+    pub fn set_name(&mut self, name: Arc<Vec<u8>>) {
+        self.name = Some(name);
+    }
+
+    /// This method marks the invocation as running work deferred by an earlier one. Nothing is
+    /// sent out for it, and it cannot defer work of it's own.
+    ///
+    /// # Arguments
+    ///
+    /// * `deferrals`: The tenant's deferral counters. The invocation's slot under the tenant's
+    ///                cap is released once it completes.
+    pub fn set_deferral(&mut self, deferrals: Arc<Deferrals>) {
+        self.deferral = Some(deferrals);
+    }
+
+    /// Returns the tenant's deferral counters if this invocation runs deferred work.
+    pub fn deferral(&self) -> Option<Arc<Deferrals>> {
+        self.deferral.as_ref().map(|d| Arc::clone(d))
+    }
+
+    /// Returns true if the extension has deferred work. Such an invocation cannot be pushed back
+    /// to the tenant, since the work would never run.
+    pub fn deferring(&self) -> bool {
+        !self.deferred.borrow().is_empty()
+    }
+
+    /// Returns the work deferred by the extension, on behalf of the tenant that invoked it.
+    pub fn take_deferred(&self) -> Vec<Deferred> {
+        let name = match self.name {
+            Some(ref name) => &name[..],
+            None => &self.request.get_payload()[..self.args_offset],
+        };
+        mem::replace(&mut *self.deferred.borrow_mut(), Vec::new())
+            .into_iter()
+            .map(|args| Deferred {
+                tenant: self.tenant.id(),
+                name: name.to_vec(),
+                args: args,
+            }).collect()
+    }
+
+    /// This method allows the extension to stream it's result back over several responses
+    /// using resp_flush(). Durable invocations write their result to a table, and should not be
+    /// allowed to stream.
+    ///
+    /// # Arguments
+    ///
+    /// * `cap`: The most bytes the extension can stream back across all of it's responses.
+    pub fn stream(&mut self, cap: usize) {
+        self.stream = RefCell::new(Some(Stream::new(cap)));
+    }
+
+    /// Returns true if the extension has flushed a partial response. Such an invocation
+    /// cannot be pushed back to the tenant.
+    pub fn streamed(&self) -> bool {
+        self.stream.borrow().as_ref().map_or(false, |s| s.started())
+    }
+
+    /// Returns the partial responses flushed by the extension since this method was last
+    /// called, in the order they were flushed. Each one is parsed upto it's UDP header, and is
+    /// ready to be fixed up and sent out.
+    pub fn partials(&self) -> Vec<Packet<UdpHeader, EmptyMetadata>> {
+        mem::replace(&mut *self.partials.borrow_mut(), Vec::new())
+    }
+
+    /// Returns true if the response to this invocation should not be sent out the network:
+    /// it is either a durable invocation resumed after a restart, or runs deferred work.
+    pub fn is_detached(&self) -> bool {
+        self.durable.as_ref().map_or(false, |d| d.detached) || self.deferral.is_some()
+    }
+
+    /// This method completes a durable invocation. The response payload written by the
+    /// extension is added to the tenant's results table, after which the invocation is
+    /// marked complete in the journal so that it is not resumed after a restart. Does nothing
+    /// if the invocation is not durable.
+    pub fn complete_durable(&self) {
+        if let Some(ref durable) = self.durable {
+            let tenant = self.tenant.id();
+            let hash = durable.args_hash.to_le();
+            let key = pack(&hash);
+
+            self.tenant.create_table(DURABLE_RESULTS_TABLE);
+            let written = self
+                .tenant
+                .get_table(DURABLE_RESULTS_TABLE)
+                .and_then(|table| {
+                    let response = self.response.borrow();
+                    self.heap
+                        .object(tenant, DURABLE_RESULTS_TABLE, key, response.get_payload())
+                        .map(|(k, obj)| {
+                            table.put(k, obj);
+                        })
+                }).is_some();
+
+            if !written {
+                error!(
+                    "Failed to write result of durable invocation {}",
+                    durable.args_hash
+                );
+            }
+
+            durable
+                .journal
+                .done(tenant, &durable.name, durable.args_hash);
+        }
+    }
+
+    /// This method stores the result of an invocation that asked for it in the tenant's mailbox.
+    /// The status stored is the one commit() will send the response out with, and the payload
+    /// is dropped if that is a failure. Results over the mailbox's cap are not stored. Does
+    /// nothing if the invocation did not ask for it's result to be stored.
+    ///
+    /// # Arguments
+    ///
+    /// * `panicked`: True if the extension panicked, in which case the response is sent out
+    ///               with StatusExtensionError.
+    pub fn complete_mailbox(&self, panicked: bool) {
+        if let Some(ref mailbox) = self.mailbox {
+            let response = self.response.borrow();
+            let failed = match (panicked, self.read_only.get(), self.truncated.get()) {
+                (true, _, _) => Some(RpcStatus::StatusExtensionError),
+                (false, true, _) => Some(RpcStatus::StatusReadOnlyTable),
+                (false, false, true) => Some(RpcStatus::StatusInternalError),
+                (false, false, false) => None,
+            };
+            let (status, payload) = match failed {
+                Some(status) => (status, &[][..]),
+                None => (
+                    response.get_header().common_header.status.clone(),
+                    response.get_payload(),
+                ),
+            };
+
+            let id = self.request.get_header().common_header.id();
+            mailbox.store(&self.tenant, self.heap, id, status, payload, rdtsc());
         }
     }
 
     /// This method commits any changes made by an extension to the database.
     /// It consumes the context, and returns the request and response
-    /// packets/buffers to the caller.
+    /// packets/buffers to the caller. If the response was truncated, then
+    /// it's payload is dropped and it's status set to StatusInternalError.
+    /// If the extension tried to write to a read-only table, then the status
+    /// is set to StatusReadOnlyTable instead. If the extension streamed it's
+    /// result, the response is marked as the last of the stream, and fails
+    /// with StatusStreamTooLarge if the stream went over it's cap.
     ///
     /// # Return
     /// A tupule whose first member is the request packet/buffer for the
@@ -143,13 +405,56 @@ impl<'a> Context<'a> {
         Packet<InvokeRequest, EmptyMetadata>,
         Packet<InvokeResponse, EmptyMetadata>,
     ) {
-        return (self.request, self.response.into_inner());
+        let mut response = self.response.into_inner();
+        let streamed = match self.stream.into_inner() {
+            Some(mut stream) => {
+                let (flags, seq, status) = stream.finish(response.get_payload().len());
+                let hdr = response.get_mut_header();
+                hdr.flags = flags;
+                hdr.set_seq(seq);
+                status
+            }
+
+            None => Ok(()),
+        };
+        let failed = match (self.read_only.get(), self.truncated.get(), streamed) {
+            (true, _, _) => Some(RpcStatus::StatusReadOnlyTable),
+            (false, true, _) => Some(RpcStatus::StatusInternalError),
+            (false, false, Err(status)) => Some(status),
+            (false, false, Ok(())) => None,
+        };
+        if let Some(status) = failed {
+            response.truncate(0);
+            let hdr = &mut response.get_mut_header().common_header;
+            hdr.status = status;
+            hdr.flags &= !RESPONSE_FLAG_TRAILERS;
+        }
+
+        return (self.request, response);
+    }
+
+    /// This method returns the records the extension has read and written so far, as the
+    /// read-set and the write-set, each in the order it's records were made. Records made past
+    /// tx::MAX_RECORDS are dropped; refer to recordset_truncated().
+    pub fn recordset(&self) -> (Ref<[Record]>, Ref<[Record]>) {
+        (
+            Ref::map(self.tx.borrow(), |tx| tx.reads()),
+            Ref::map(self.tx.borrow(), |tx| tx.writes()),
+        )
+    }
+
+    /// This method returns true if records were dropped from recordset() because more than
+    /// tx::MAX_RECORDS were made.
+    pub fn recordset_truncated(&self) -> bool {
+        self.tx.borrow().truncated()
     }
 
     /// This method modifies the response for the pushback. It changes the status in the response
     /// from StatusOk to StatusPushback. Besides that the function also modifies the response
     /// packet to remove the old response and attach the records which the extension has read or
-    /// written(Read Write Set), so that the client can resume the execution on its end.
+    /// written(Read Write Set), so that the client can resume the execution on its end. If the
+    /// records don't fit on the response, or some were dropped, the status is set to
+    /// StatusRwSetTooLarge instead.
     pub fn prepare_for_pushback(&self) {
         self.response
             .borrow_mut()
@@ -174,29 +479,25 @@ impl<'a> Context<'a> {
                     );
                 }
             }
+            self.truncated.set(false);
 
-            // Add the read-set to the pushback response.
-            for record in self.tx.borrow_mut().reads().iter() {
-                let ptr = &record.get_optype() as *const _ as *const u8;
-                let optype = unsafe { slice::from_raw_parts(ptr, mem::size_of::<OpType>()) };
-                self.resp(optype);
-                let ptr = &record.get_version() as *const _ as *const u8;
-                let version = unsafe { slice::from_raw_parts(ptr, mem::size_of::<Version>()) };
-                self.resp(version);
-                self.resp(record.get_key().as_ref());
-                self.resp(record.get_object().as_ref());
+            // The read-set followed by the write-set is sent back as a trailer, so that it does
+            // not collide with any other trailer on the response.
+            let mut rwset = Vec::new();
+            let (reads, writes) = self.recordset();
+            for record in reads.iter().chain(writes.iter()) {
+                pack_record(&mut rwset, record);
             }
 
-            // Add the write-set to the pushback response.
-            for record in self.tx.borrow_mut().writes().iter() {
-                let ptr = &record.get_optype() as *const _ as *const u8;
-                let optype = unsafe { slice::from_raw_parts(ptr, mem::size_of::<OpType>()) };
-                self.resp(optype);
-                let ptr = &record.get_version() as *const _ as *const u8;
-                let version = unsafe { slice::from_raw_parts(ptr, mem::size_of::<Version>()) };
-                self.resp(version);
-                self.resp(record.get_key().as_ref());
-                self.resp(record.get_object().as_ref());
+            let room = trailer::room(mem::size_of::<InvokeResponse>());
+            let mut trailers = TrailerWriter::new(room);
+            let fits = !self.recordset_truncated() && trailers.append(TRAILER_RWSET, &rwset);
+            let mut response = self.response.borrow_mut();
+            if fits && response.append(&trailers.finish()) {
+                response.get_mut_header().common_header.flags |= RESPONSE_FLAG_TRAILERS;
+            } else {
+                // The tenant can't resume the extension off an incomplete set.
+                response.get_mut_header().common_header.status = RpcStatus::StatusRwSetTooLarge;
             }
         }
     }
@@ -212,6 +513,21 @@ impl<'a> Context<'a> {
     pub fn db_credit(&self) -> u64 {
         self.db_credit.borrow().clone()
     }
+
+    // Returns a table the tenant can write to. An attempt to write to a read-only table is
+    // remembered so that the invocation fails on commit.
+    fn writable_table(&self, table_id: TableId) -> Option<Arc<Table>> {
+        match self.tenant.writable_table(table_id) {
+            Ok(table) => Some(table),
+
+            Err(RpcStatus::StatusReadOnlyTable) => {
+                self.read_only.set(true);
+                None
+            }
+
+            Err(_) => None,
+        }
+    }
 }
 
 // The DB trait for Context.
@@ -221,29 +537,98 @@ impl<'a> DB for Context<'a> {
         // Lookup the database for the key value pair. If it exists, then update
         // the read set and return the value.
         let start = rdtsc();
-        self.tenant.get_table(table_id)
-                    .and_then(| table | { table.get(key) })
-                    // The object exists in the database. Get a handle to it's
-                    // key and value.
-                    .and_then(| entry | { Some((self.heap.resolve(entry.value), entry.version)) })
-                    // Return the value wrapped up inside a safe type.
-                    .and_then(| (opt, version) | {
-                        if let Some(opt) = opt {
-                            let (k, v) = opt;
-                            self.tx.borrow_mut().record_get(Record::new(OpType::SandstormRead, version, k.clone(), v.clone()));
-                            *self.db_credit.borrow_mut() += rdtsc() - start + GET_CREDIT;
-                            unsafe { Some(ReadBuf::new(v)) }
-                        } else{
-                            *self.db_credit.borrow_mut() += rdtsc() - start + GET_CREDIT;
-                            None
-                        }
-                    })
+        self.tenant
+            .get_table(table_id)
+            .and_then(|table| table.get(key))
+            // The object exists in the database. Get a handle to it's
+            // key and value.
+            .and_then(|entry| Some((self.heap.resolve(entry.value), entry.version)))
+            // Return the value wrapped up inside a safe type.
+            .and_then(|(opt, version)| {
+                if let Some(opt) = opt {
+                    let (k, v) = opt;
+                    self.tx.borrow_mut().record_get(Record::new(
+                        OpType::SandstormRead,
+                        table_id,
+                        version,
+                        k.clone(),
+                        v.clone(),
+                    ));
+                    *self.db_credit.borrow_mut() += rdtsc() - start + GET_CREDIT;
+                    unsafe { Some(ReadBuf::new(v)) }
+                } else {
+                    *self.db_credit.borrow_mut() += rdtsc() - start + GET_CREDIT;
+                    None
+                }
+            })
+    }
+
+    /// Lookup the `DB` trait for documentation on this method. Only the projected bytes of the
+    /// value are handed out. An offset past the end of the value is caught off the object's
+    /// metadata, before the value is resolved (and decompressed, if it is compressed).
+    fn get_slice(&self, table_id: u64, key: &[u8], offset: u32, length: u32) -> Option<ReadBuf> {
+        let start = rdtsc();
+        let slice = self
+            .tenant
+            .get_table(table_id)
+            .and_then(|table| table.get(key))
+            .filter(|entry| {
+                self.heap
+                    .value_len(&entry.value)
+                    .map_or(false, |len| offset as usize <= len)
+            })
+            .and_then(|entry| {
+                let version = entry.version;
+                let (k, v) = self.heap.resolve(entry.value)?;
+                let range = projection(v.len(), offset, length);
+                let slice = v.slice(range.start, range.end);
+
+                // The whole value goes on the read-set, so that an extension pushed back to the
+                // client can read any part of it there.
+                self.tx.borrow_mut().record_get(Record::new(
+                    OpType::SandstormRead,
+                    table_id,
+                    version,
+                    k,
+                    v,
+                ));
+                Some(slice)
+            });
+
+        *self.db_credit.borrow_mut() += rdtsc() - start + GET_CREDIT;
+        slice.map(|v| unsafe { ReadBuf::new(v) })
+    }
+
+    /// Lookup the `DB` trait for documentation on this method. Like get(), the object is added
+    /// to the read-set.
+    fn value_len(&self, table_id: u64, key: &[u8]) -> Option<usize> {
+        let start = rdtsc();
+        let len = self
+            .tenant
+            .get_table(table_id)
+            .and_then(|table| table.get(key))
+            .and_then(|entry| {
+                let version = entry.version;
+                let (k, v) = self.heap.resolve(entry.value)?;
+                let len = v.len();
+                self.tx.borrow_mut().record_get(Record::new(
+                    OpType::SandstormRead,
+                    table_id,
+                    version,
+                    k,
+                    v,
+                ));
+                Some(len)
+            });
+
+        *self.db_credit.borrow_mut() += rdtsc() - start + GET_CREDIT;
+        len
     }
 
     /// Lookup the `DB` trait for documentation on this method.
     fn multiget(&self, table_id: u64, key_len: u16, keys: &[u8]) -> Option<MultiReadBuf> {
-        // Lookup the database for each key in the supplied list of keys. If all exist,
-        // return a MultiReadBuf to the extension.
+        // Lookup the database for each key in the supplied list of keys. Keys that do not
+        // exist keep their entry in the returned MultiReadBuf, marked as missing.
         let start = rdtsc();
         if let Some(table) = self.tenant.get_table(table_id) {
             let mut objs = Vec::new();
@@ -258,38 +643,73 @@ impl<'a> DB for Context<'a> {
                     .get(key)
                     .and_then(|entry| Some((self.heap.resolve(entry.value), entry.version)))
                     .and_then(|(opt, version)| {
-                        if let Some(opt) = opt {
-                            let (k, v) = opt;
-                            self.tx.borrow_mut().record_get(Record::new(
-                                OpType::SandstormRead,
-                                version,
-                                k.clone(),
-                                v.clone(),
-                            ));
-                            objs.push(v);
-                            Some(())
-                        } else {
-                            None
-                        }
+                        let (k, v) = opt?;
+                        self.tx.borrow_mut().record_get(Record::new(
+                            OpType::SandstormRead,
+                            table_id,
+                            version,
+                            k.clone(),
+                            v.clone(),
+                        ));
+                        Some(v)
                     });
-
-                if r.is_none() {
-                    *self.db_credit.borrow_mut() += rdtsc() - start + MULTIGET_CREDIT;
-                    return None;
-                }
+                objs.push(r);
             }
 
             unsafe {
                 *self.db_credit.borrow_mut() += rdtsc() - start + MULTIGET_CREDIT;
-                return Some(MultiReadBuf::new(objs));
+                return Some(MultiReadBuf::with_missing(objs));
             }
         }
         *self.db_credit.borrow_mut() += rdtsc() - start + MULTIGET_CREDIT;
         return None;
     }
 
+    /// Lookup the `DB` trait for documentation on this method.
+    fn multi_get_versions(&self, table_id: u64, key_len: u16, keys: &[u8]) -> Option<Versioned> {
+        let start = rdtsc();
+        let num_keys = keys.len() / cmp::max(key_len as usize, 1);
+        let snap = self.tenant.get_table(table_id).and_then(|table| {
+            snapshot::keys(keys, key_len, num_keys as u32)
+                .and_then(|keys| snapshot::read(&table, &keys))
+        });
+
+        // Record every object read into the transaction, exactly like multiget() does.
+        let r = snap.and_then(|snap| {
+            let mut objs = Vec::with_capacity(snap.entries.len());
+            let mut versions = Vec::with_capacity(snap.entries.len());
+            for entry in snap.entries.into_iter() {
+                let (k, v) = self.heap.resolve(entry.value)?;
+                self.tx.borrow_mut().record_get(Record::new(
+                    OpType::SandstormRead,
+                    table_id,
+                    entry.version,
+                    k,
+                    v.clone(),
+                ));
+                versions.push(entry.version.number());
+                objs.push(v);
+            }
+
+            Some(Versioned {
+                values: unsafe { MultiReadBuf::new(objs) },
+                versions: versions,
+                racy: snap.racy,
+            })
+        });
+
+        *self.db_credit.borrow_mut() += rdtsc() - start + MULTIGET_CREDIT;
+        r
+    }
+
     /// Lookup the `DB` trait for documentation on this method.
     fn alloc(&self, table_id: u64, key: &[u8], val_len: u64) -> Option<WriteBuf> {
+        // Objects over the server's limits are refused before anything is allocated.
+        let limits = self.heap.limits();
+        if limits.check(key.len(), val_len as usize).is_err() {
+            return None;
+        }
+
         // If the extension has exceeded it's quota, do not allow any more allocs.
         if self.allocs.get() >= MAX_ALLOC {
             return None;
@@ -297,8 +717,7 @@ impl<'a> DB for Context<'a> {
 
         // Check if the tenant owns a table with the requested identifier.
         // If it does, perform and return an allocation.
-        self.tenant
-            .get_table(table_id)
+        self.writable_table(table_id)
             .and_then(|_table| self.heap.raw(self.tenant.id(), table_id, key, val_len))
             .and_then(|buf| {
                 self.allocs.set(self.allocs.get() + buf.len());
@@ -313,11 +732,12 @@ impl<'a> DB for Context<'a> {
         let (table_id, buf) = unsafe { buf.freeze() };
 
         // If the table exists, write to the database.
-        if let Some(table) = self.tenant.get_table(table_id) {
+        if let Some(table) = self.writable_table(table_id) {
             return self.heap.resolve(buf.clone()).map_or(false, |(k, _v)| {
-                if let Some(entry) = table.put(k.clone(), buf.clone()) {
+                if let Some(entry) = self.heap.store(&table, k.clone(), buf.clone()) {
                     self.tx.borrow_mut().record_put(Record::new(
                         OpType::SandstormWrite,
+                        table_id,
                         entry.version,
                         k.clone(),
                         buf.clone(),
@@ -336,10 +756,120 @@ impl<'a> DB for Context<'a> {
     }
 
     /// Lookup the `DB` trait for documentation on this method.
-    fn del(&self, table_id: u64, key: &[u8]) {
-        // Delete the key-value pair from the database
-        if let Some(table) = self.tenant.get_table(table_id) {
-            table.delete(key);
+    fn put_many(&self, bufs: Vec<WriteBuf>) -> Result<(), PutManyError> {
+        let start = rdtsc();
+        let credit = PUT_CREDIT * bufs.len() as u64;
+
+        // Resolve every buffer and it's table up front, so that a bad one leaves the database
+        // untouched.
+        let mut batch: Vec<(u64, Arc<Table>, Bytes, Bytes)> = Vec::with_capacity(bufs.len());
+        for (i, buf) in bufs.into_iter().enumerate() {
+            let (table_id, buf) = unsafe { buf.freeze() };
+
+            let table = match self.writable_table(table_id) {
+                Some(table) => table,
+                None => {
+                    *self.db_credit.borrow_mut() += rdtsc() - start + credit;
+                    return Err(PutManyError::TableNotWritable(i));
+                }
+            };
+
+            let key = match self.heap.resolve(buf.clone()) {
+                Some((k, _v)) => k,
+                None => {
+                    *self.db_credit.borrow_mut() += rdtsc() - start + credit;
+                    return Err(PutManyError::InvalidBuffer(i));
+                }
+            };
+
+            batch.push((table_id, table, key, buf));
+        }
+
+        // Tables are written one after the other, in order of id. The sort is stable, so writes
+        // to the same key keep their order.
+        batch.sort_by_key(|b| b.0);
+        let mut first = 0;
+        while first < batch.len() {
+            let table_id = batch[first].0;
+            let last = first + batch[first..].iter().take_while(|b| b.0 == table_id).count();
+            let group = &batch[first..last];
+
+            let objects = group.iter().map(|b| (b.2.clone(), b.3.clone())).collect();
+            if let Some(entries) = self.heap.store_many(&group[0].1, objects) {
+                for (b, entry) in group.iter().zip(entries.into_iter()) {
+                    if let Some(entry) = entry {
+                        self.tx.borrow_mut().record_put(Record::new(
+                            OpType::SandstormWrite,
+                            table_id,
+                            entry.version,
+                            b.2.clone(),
+                            b.3.clone(),
+                        ));
+                    }
+                }
+            }
+
+            first = last;
+        }
+
+        *self.db_credit.borrow_mut() += rdtsc() - start + credit;
+        Ok(())
+    }
+
+    /// Lookup the `DB` trait for documentation on this method.
+    fn cas(
+        &self,
+        table_id: u64,
+        key: &[u8],
+        expected: Option<&[u8]>,
+        buf: WriteBuf,
+    ) -> Result<bool, ()> {
+        let start = rdtsc();
+        let (table, buf) = unsafe { buf.freeze() };
+
+        // The buffer must have been allocated for the key being compared.
+        let table = self.writable_table(table_id).filter(|_| table == table_id);
+        let key = self
+            .heap
+            .resolve(buf.clone())
+            .map(|(k, _v)| k)
+            .filter(|k| &k[..] == key);
+        let res = match (table, key) {
+            (Some(table), Some(k)) => {
+                match self
+                    .heap
+                    .compare_and_store(&table, k.clone(), buf.clone(), expected)
+                {
+                    Some(Ok(entry)) => {
+                        if let Some(entry) = entry {
+                            self.tx.borrow_mut().record_put(Record::new(
+                                OpType::SandstormWrite,
+                                table_id,
+                                entry.version,
+                                k,
+                                buf,
+                            ));
+                        }
+                        Ok(true)
+                    }
+                    Some(Err(())) => Ok(false),
+                    None => Err(()),
+                }
+            }
+            _ => Err(()),
+        };
+
+        *self.db_credit.borrow_mut() += rdtsc() - start + PUT_CREDIT;
+        res
+    }
+
+    /// Lookup the `DB` trait for documentation on this method.
+    fn del(&self, table_id: u64, key: &[u8]) -> bool {
+        // Delete the key-value pair from the database. The object is freed once
+        // the last handle to it (ex: a ReadBuf held by a reader) is dropped.
+        match self.writable_table(table_id) {
+            Some(table) => self.heap.delete(&table, key),
+            None => false,
         }
     }
 
@@ -355,13 +885,70 @@ impl<'a> DB for Context<'a> {
             .0
     }
 
+    /// Lookup the `DB` trait for documentation on this method.
+    fn next_arg(&self) -> Option<&[u8]> {
+        invoke::next_arg(self.args(), &self.arg_cursor)
+    }
+
     /// Lookup the `DB` trait for documentation on this method.
     fn resp(&self, data: &[u8]) {
-        // Write the passed in data to the response packet/buffer.
-        self.response
+        // Write the passed in data to the response packet/buffer. If it does not fit, then
+        // remember so instead of sending out a partial response.
+        if !self.truncated.get() && !self.response.borrow_mut().append(data) {
+            self.truncated.set(true);
+        }
+    }
+
+    /// Lookup the `DB` trait for documentation on this method. The response has to fit in a
+    /// single packet, and a truncated one has no room left at all.
+    fn resp_capacity(&self) -> usize {
+        if self.truncated.get() {
+            return 0;
+        }
+
+        let len = self.response.borrow().get_payload().len();
+        trailer::room(mem::size_of::<InvokeResponse>() + len)
+    }
+
+    /// Lookup the `DB` trait for documentation on this method. The next response is allocated
+    /// before anything is sent out, so that a failed allocation leaves the current one intact.
+    fn resp_flush(&self) -> bool {
+        // A response that is already going to fail is not streamed any further.
+        if self.truncated.get() || self.read_only.get() {
+            return false;
+        }
+
+        let mut stream = self.stream.borrow_mut();
+        let stream = match stream.as_mut() {
+            Some(stream) => stream,
+            None => return false,
+        };
+
+        let mut next = match rpc::next_response(&self.response.borrow()) {
+            Some(next) => next,
+            None => return false,
+        };
+
+        let len = self.response.borrow().get_payload().len();
+        let seq = match stream.flush(len) {
+            Some(seq) => seq,
+            None => {
+                next.free_packet();
+                return false;
+            }
+        };
+
+        let mut flushed = mem::replace(&mut *self.response.borrow_mut(), next);
+        {
+            let hdr = flushed.get_mut_header();
+            hdr.flags = STREAM_FLAG_MORE;
+            hdr.set_seq(seq);
+        }
+
+        self.partials
             .borrow_mut()
-            .add_to_payload_tail(data.len(), data)
-            .unwrap();
+            .push(flushed.deparse_header(PACKET_UDP_LEN as usize));
+        true
     }
 
     /// Lookup the `DB` trait for documentation on this method.
@@ -387,4 +974,77 @@ impl<'a> DB for Context<'a> {
             None => None,
         }
     }
+
+    /// Lookup the `DB` trait for documentation on this method.
+    fn checkpoint(&self, progress: u64, state: &[u8]) -> bool {
+        match self.durable {
+            Some(ref d) => {
+                d.journal
+                    .checkpoint(self.tenant.id(), &d.name, d.args_hash, progress, state)
+            }
+
+            None => false,
+        }
+    }
+
+    /// Lookup the `DB` trait for documentation on this method. The work is only recorded here;
+    /// it is handed to the scheduler once the invocation completes.
+    fn defer(&self, args: &[u8]) -> bool {
+        let mut deferred = self.deferred.borrow_mut();
+        if self.deferral.is_some() || deferred.len() >= MAX_DEFERRED || args.len() > MAX_DEFER_ARGS
+        {
+            return false;
+        }
+
+        deferred.push(args.to_vec());
+        true
+    }
+
+    /// Lookup the `DB` trait for documentation on this method.
+    fn restore(&self) -> Option<(u64, Vec<u8>)> {
+        self.durable
+            .as_ref()
+            .and_then(|d| d.restored.as_ref())
+            .map(|c| (c.progress, c.state.clone()))
+    }
+
+    /// Lookup the `DB` trait for documentation on this method. Each slice is one bucket of the
+    /// table. Like get(), the time spent scanning counts towards the extension's db credit.
+    fn scan(&self, table_id: u64, cursor: u64, visit: &mut FnMut(&[u8], &[u8])) -> Option<u64> {
+        let start = rdtsc();
+        let table = match self.tenant.get_table(table_id) {
+            Some(table) => table,
+            None => return None,
+        };
+
+        if cursor >= N_BUCKETS as u64 {
+            return None;
+        }
+
+        for entry in table.bucket_entries(cursor as usize) {
+            if let Some((k, v)) = self.heap.resolve(entry.value) {
+                visit(&k, &v);
+            }
+        }
+        *self.db_credit.borrow_mut() += rdtsc() - start;
+
+        if cursor + 1 < N_BUCKETS as u64 {
+            Some(cursor + 1)
+        } else {
+            None
+        }
+    }
+
+    /// Lookup the `DB` trait for documentation on this method. The merged value is written
+    /// straight to the table; like a put(), it is not undone if the invocation fails later. The
+    /// time spent merging, including inside the merge extension, counts towards the extension's
+    /// db credit.
+    fn merge(&self, table_id: u64, key: &[u8], delta: &[u8]) -> bool {
+        let start = rdtsc();
+        let merged = self.writable_table(table_id).map_or(false, |table| {
+            merge::merge(self.heap, &table, self.tenant.id(), table_id, key, delta).is_ok()
+        });
+        *self.db_credit.borrow_mut() += rdtsc() - start + PUT_CREDIT;
+        merged
+    }
 }
@@ -0,0 +1,571 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use std::cell::{Cell, RefCell};
+use std::cmp;
+use std::fmt::Write as FmtWrite;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::mem::{size_of, transmute};
+use std::panic::{self, AssertUnwindSafe, PanicInfo};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use std::thread;
+
+use super::config::{CrashPolicy, ServerConfig};
+use super::cycles;
+use super::wireformat::{
+    CasRequest, DeleteRequest, GetRequest, MultiGetRequest, OpCode, PutRequest, RpcRequestHeader,
+};
+
+/// The number of requests each core remembers, most recent last.
+pub const RING_LEN: usize = 16;
+
+/// The number of bytes of an extension's name kept on a breadcrumb. Longer names are cut.
+pub const NAME_LEN: usize = 24;
+
+/// What a core remembers about a request it dispatched, or a task it started running. Fixed
+/// size and Copy, so that recording one never allocates.
+#[derive(Clone, Copy)]
+pub struct Breadcrumb {
+    /// The opcode on the request.
+    pub opcode: u8,
+
+    /// The tenant that sent the request.
+    pub tenant: u32,
+
+    /// The table the request was for. 0 for opcodes that don't name one.
+    pub table: u64,
+
+    /// A hash of the key the request was for. 0 for opcodes that don't carry one.
+    pub key_hash: u64,
+
+    /// The RPC identifier on the request.
+    pub id: u64,
+
+    // The name of the extension the request invoked, cut at NAME_LEN bytes.
+    name: [u8; NAME_LEN],
+
+    // The number of bytes of `name` in use.
+    name_len: u8,
+}
+
+impl Breadcrumb {
+    /// Returns a breadcrumb for a request that did not invoke an extension.
+    pub fn new(opcode: u8, tenant: u32, table: u64, key_hash: u64, id: u64) -> Breadcrumb {
+        Breadcrumb {
+            opcode: opcode,
+            tenant: tenant,
+            table: table,
+            key_hash: key_hash,
+            id: id,
+            name: [0; NAME_LEN],
+            name_len: 0,
+        }
+    }
+
+    /// Returns a breadcrumb for an invocation of an extension.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`: The tenant that invoked the extension.
+    /// * `id`:     The RPC identifier on the invoke() request.
+    /// * `name`:   The name of the extension.
+    pub fn invoke(tenant: u32, id: u64, name: &[u8]) -> Breadcrumb {
+        let mut crumb = Breadcrumb::new(OpCode::SandstormInvokeRpc as u8, tenant, 0, 0, id);
+        let len = cmp::min(name.len(), NAME_LEN);
+        crumb.name[..len].copy_from_slice(&name[..len]);
+        crumb.name_len = len as u8;
+        crumb
+    }
+
+    /// Returns the name of the extension the request invoked, or an empty slice if it did not
+    /// invoke one.
+    pub fn name(&self) -> &[u8] {
+        &self.name[..self.name_len as usize]
+    }
+
+    // Writes out the breadcrumb on a single line.
+    fn write(&self, out: &mut String) {
+        let opcode = match self.opcode < OpCode::InvalidOperation as u8 {
+            true => format!("{:?}", unsafe { transmute::<u8, OpCode>(self.opcode) }),
+            false => format!("{:#04x}", self.opcode),
+        };
+
+        let _ = write!(
+            out,
+            "opcode {} tenant {} table {} key {:#018x} id {}",
+            opcode, self.tenant, self.table, self.key_hash, self.id
+        );
+        if self.name_len > 0 {
+            let _ = write!(out, " extension {}", String::from_utf8_lossy(self.name()));
+        }
+        out.push('\n');
+    }
+}
+
+// The requests a core dispatched, and the task it is running.
+struct Ring {
+    // The last RING_LEN requests, written round-robin.
+    crumbs: [Breadcrumb; RING_LEN],
+
+    // The total number of requests dispatched on the core. The next one goes at this index,
+    // modulo RING_LEN.
+    requests: u64,
+
+    // The task the core is running, if any. Set by started(), and cleared by stopped().
+    task: Option<Breadcrumb>,
+}
+
+impl Ring {
+    // Returns the requests in the ring, oldest first.
+    fn recent(&self) -> Vec<Breadcrumb> {
+        let n = cmp::min(self.requests, RING_LEN as u64);
+        (self.requests - n..self.requests)
+            .map(|i| self.crumbs[i as usize % RING_LEN])
+            .collect()
+    }
+}
+
+thread_local! {
+    // What this core has been doing. Only cores that dispatch requests or run tasks have one;
+    // panics on any other thread are left to the default hook.
+    static RING: RefCell<Option<Ring>> = RefCell::new(None);
+
+    // Set by the hook if the panic it reported is to be isolated: the extension the core was
+    // running is disabled, instead of the process being aborted. Read by isolate().
+    static ISOLATE: Cell<bool> = Cell::new(false);
+}
+
+// The time-stamp, in cycles, at which the hook was installed.
+static STARTED: AtomicUsize = ATOMIC_USIZE_INIT;
+
+// The number of panics reported by the hook, across all cores.
+static PANICS: AtomicUsize = ATOMIC_USIZE_INIT;
+
+// Runs `f` on this core's ring, creating it if need be.
+#[inline]
+fn with_ring<F: FnOnce(&mut Ring)>(f: F) {
+    RING.with(|ring| {
+        let mut ring = ring.borrow_mut();
+        let ring = ring.get_or_insert_with(|| Ring {
+            crumbs: [Breadcrumb::new(0, 0, 0, 0, 0); RING_LEN],
+            requests: 0,
+            task: None,
+        });
+        f(ring);
+    });
+}
+
+/// Remembers a request on this core's ring.
+#[inline]
+pub fn record(crumb: Breadcrumb) {
+    with_ring(|ring| {
+        ring.crumbs[ring.requests as usize % RING_LEN] = crumb;
+        ring.requests += 1;
+    });
+}
+
+/// Remembers a request being dispatched on this core. The tenant, RPC identifier, and for
+/// get(), put(), multiget(), delete() and cas() requests the table and key, are read straight
+/// off the request without parsing it.
+///
+/// # Arguments
+///
+/// * `opcode`:  The opcode on the request.
+/// * `payload`: The request, starting at it's RpcRequestHeader.
+#[inline]
+pub fn dispatched(opcode: &OpCode, payload: &[u8]) {
+    if let Some(crumb) = parse(opcode, payload) {
+        record(crumb);
+    }
+}
+
+// Reads a breadcrumb off a request. None if the request is too short to hold it's header.
+fn parse(opcode: &OpCode, payload: &[u8]) -> Option<Breadcrumb> {
+    let common = size_of::<RpcRequestHeader>();
+    if payload.len() < common {
+        return None;
+    }
+
+    // Wireformat headers are packed, so the pointer does not have to be aligned.
+    let (tenant, id) = {
+        let hdr = unsafe { &*(payload.as_ptr() as *const RpcRequestHeader) };
+        (hdr.tenant(), hdr.id())
+    };
+
+    // The table id immediately follows the common header, and is followed by the key length.
+    // The key is right after the opcode's header.
+    let key = match *opcode {
+        OpCode::SandstormGetRpc => Some(size_of::<GetRequest>()),
+        OpCode::SandstormPutRpc => Some(size_of::<PutRequest>()),
+        OpCode::SandstormMultiGetRpc => Some(size_of::<MultiGetRequest>()),
+        OpCode::SandstormDeleteRpc => Some(size_of::<DeleteRequest>()),
+        OpCode::SandstormCasRpc => Some(size_of::<CasRequest>()),
+        _ => None,
+    };
+
+    let (table, key_hash) = match key {
+        Some(key) if payload.len() >= key => {
+            let mut table = [0; 8];
+            table.copy_from_slice(&payload[common..common + 8]);
+            let mut len = [0; 2];
+            len.copy_from_slice(&payload[common + 8..common + 10]);
+
+            let len = u16::from_le(unsafe { transmute(len) }) as usize;
+            let key = &payload[key..cmp::min(key + len, payload.len())];
+            (u64::from_le(unsafe { transmute(table) }), hash(key))
+        }
+
+        _ => (0, 0),
+    };
+
+    Some(Breadcrumb::new(
+        opcode.clone() as u8,
+        tenant,
+        table,
+        key_hash,
+        id,
+    ))
+}
+
+// Hashes a key off it's length and first eight bytes, which is a couple of word operations and
+// enough to tell keys apart in a crash report.
+#[inline]
+fn hash(key: &[u8]) -> u64 {
+    let mut word = [0; 8];
+    let n = cmp::min(key.len(), 8);
+    word[..n].copy_from_slice(&key[..n]);
+
+    let word: u64 = unsafe { transmute(word) };
+    (word ^ (key.len() as u64).rotate_left(56)).wrapping_mul(0x9e3779b97f4a7c15)
+}
+
+/// Marks a task as running on this core. A panic before stopped() is attributed to it, and
+/// is contained by the task's catch boundary.
+///
+/// # Arguments
+///
+/// * `crumb`: The task's identity.
+#[inline]
+pub fn started(crumb: &Breadcrumb) {
+    with_ring(|ring| ring.task = Some(*crumb));
+}
+
+/// Marks the task started on this core as no longer running.
+#[inline]
+pub fn stopped() {
+    with_ring(|ring| ring.task = None);
+}
+
+/// Runs a task under it's catch boundary: a panic inside `f` is attributed to the task, and
+/// caught instead of unwinding into the scheduler.
+///
+/// # Arguments
+///
+/// * `crumb`: The task's identity.
+/// * `f`:     Runs the task.
+///
+/// # Return
+///
+/// What `f` returned, or the panic it raised.
+pub fn contain<R, F: FnOnce() -> R>(crumb: &Breadcrumb, f: F) -> thread::Result<R> {
+    started(crumb);
+    let res = panic::catch_unwind(AssertUnwindSafe(f));
+    stopped();
+    res
+}
+
+/// Returns true if the panic the hook just reported on this core is to be isolated, in which
+/// case the extension that panicked must be disabled. Called at the catch boundary, once the
+/// panic was caught. Clears the mark, so a later panic is decided on it's own.
+pub fn isolate() -> bool {
+    ISOLATE.with(|isolate| isolate.replace(false))
+}
+
+/// What the hook does once it has reported a panic.
+#[derive(Debug, PartialEq)]
+pub enum Action {
+    /// The process is aborted.
+    Abort,
+
+    /// The extension that panicked is disabled, and the core keeps serving requests.
+    Isolate,
+}
+
+/// Decides what happens after a panic. Isolation is only attempted if the policy asks for it,
+/// and the panic is inside a task, and so will be caught by it's catch boundary; anything else
+/// could leave the core half way through an update to shared state.
+///
+/// # Arguments
+///
+/// * `policy`:    The crash policy.
+/// * `contained`: True if the panic is inside a task.
+pub fn decide(policy: CrashPolicy, contained: bool) -> Action {
+    match (policy, contained) {
+        (CrashPolicy::Isolate, true) => Action::Isolate,
+        _ => Action::Abort,
+    }
+}
+
+/// Installs the panic hook, following the crash policy and writing reports to the crash file
+/// in the server config. Reports always go to stderr.
+pub fn install(config: &ServerConfig) {
+    install_with(config.crash_policy(), config.crash_file.clone(), abort);
+}
+
+// Aborts the process. Passed into install_with() by install().
+fn abort() {
+    process::abort();
+}
+
+/// Installs the panic hook. Refer to install().
+///
+/// # Arguments
+///
+/// * `policy`: The crash policy.
+/// * `path`:   The file reports are appended to. Empty if they only go to stderr.
+/// * `exit`:   Called instead of returning from the hook when the process is to be aborted.
+pub fn install_with(policy: CrashPolicy, path: String, exit: fn()) {
+    STARTED.store(cycles::rdtsc() as usize, Ordering::SeqCst);
+
+    let previous = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        // The default hook prints the message and backtrace.
+        previous(info);
+
+        let mut ring = None;
+        let _ = RING.try_with(|r| ring = r.try_borrow().ok().map(|r| r.as_ref().map(snapshot)));
+        let (task, recent, requests) = match ring {
+            Some(Some(ring)) => ring,
+            _ => return,
+        };
+
+        let action = decide(policy, task.is_some());
+        let report = report(info, task, &recent, requests, &action);
+        let _ = io::stderr().write_all(report.as_bytes());
+        if !path.is_empty() {
+            let _ = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .and_then(|mut file| {
+                    file.write_all(report.as_bytes())
+                        .and_then(|_| file.sync_all())
+                });
+        }
+
+        match action {
+            Action::Abort => exit(),
+            Action::Isolate => ISOLATE.with(|isolate| isolate.set(true)),
+        }
+    }));
+}
+
+// Copies out what the hook needs off a ring.
+fn snapshot(ring: &Ring) -> (Option<Breadcrumb>, Vec<Breadcrumb>, u64) {
+    (ring.task, ring.recent(), ring.requests)
+}
+
+// Writes out a crash report.
+fn report(
+    info: &PanicInfo,
+    task: Option<Breadcrumb>,
+    recent: &[Breadcrumb],
+    requests: u64,
+    action: &Action,
+) -> String {
+    let panics = PANICS.fetch_add(1, Ordering::SeqCst) + 1;
+    let started = STARTED.load(Ordering::SeqCst) as u64;
+    let uptime = cycles::to_seconds(cycles::rdtsc().saturating_sub(started));
+
+    let message = match info.payload().downcast_ref::<&str>() {
+        Some(message) => message.to_string(),
+        None => match info.payload().downcast_ref::<String>() {
+            Some(message) => message.clone(),
+            None => "Box<Any>".to_string(),
+        },
+    };
+
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "=== crash on thread {} after {:.3} s: {}",
+        thread::current().name().unwrap_or("<unnamed>"),
+        uptime,
+        message
+    );
+    if let Some(location) = info.location() {
+        let _ = writeln!(out, "location: {}:{}", location.file(), location.line());
+    }
+    let _ = writeln!(
+        out,
+        "requests dispatched on this core: {}, panics: {}",
+        requests, panics
+    );
+
+    out.push_str("task: ");
+    match task {
+        Some(task) => task.write(&mut out),
+        None => out.push_str("none\n"),
+    }
+
+    let _ = writeln!(out, "last {} requests, oldest first:", recent.len());
+    for crumb in recent.iter() {
+        out.push_str("  ");
+        crumb.write(&mut out);
+    }
+
+    let _ = writeln!(
+        out,
+        "policy: {}",
+        match *action {
+            Action::Abort => "abort",
+            Action::Isolate => "isolate, extension disabled",
+        }
+    );
+    out
+}
+
+// This module contains unit tests for crash reports. The hook is installed for the whole test
+// binary, but only reports panics on threads that recorded breadcrumbs.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::fs;
+
+    use super::super::wireformat::GetGenerator;
+
+    // The number of times the hook asked for the process to be aborted.
+    static EXITS: AtomicUsize = ATOMIC_USIZE_INIT;
+
+    fn exit() {
+        EXITS.fetch_add(1, Ordering::SeqCst);
+    }
+
+    // Returns the bytes of a get() request for a key.
+    fn get(tenant: u32, table: u64, key: &[u8], id: u64) -> Vec<u8> {
+        let hdr = GetRequest::new(
+            tenant,
+            table,
+            key.len() as u16,
+            id,
+            0,
+            GetGenerator::SandstormClient,
+        );
+        let hdr: [u8; size_of::<GetRequest>()] = unsafe { transmute(hdr) };
+        let mut req = hdr.to_vec();
+        req.extend_from_slice(key);
+        req
+    }
+
+    // Tests that the ring keeps the last RING_LEN requests, oldest first, and that the table
+    // and key are read off get()s.
+    #[test]
+    fn test_crash_ring() {
+        let handle = thread::spawn(|| {
+            for id in 0..(RING_LEN as u64 + 4) {
+                dispatched(&OpCode::SandstormGetRpc, &get(3, 9, b"user42", id));
+            }
+            dispatched(&OpCode::SandstormGetRpc, &[0; 4]);
+
+            let mut recent = Vec::new();
+            RING.with(|ring| recent = ring.borrow().as_ref().unwrap().recent());
+            recent
+        });
+        let recent = handle.join().unwrap();
+
+        assert_eq!(RING_LEN, recent.len());
+        let ids: Vec<u64> = recent.iter().map(|c| c.id).collect();
+        assert_eq!((4..RING_LEN as u64 + 4).collect::<Vec<u64>>(), ids);
+        assert!(recent.iter().all(|c| c.tenant == 3 && c.table == 9));
+        assert_eq!(hash(b"user42"), recent[0].key_hash);
+        assert!(hash(b"user42") != hash(b"user43"));
+        assert!(hash(b"user4200") != hash(b"user42"));
+    }
+
+    // Tests that names longer than NAME_LEN are cut.
+    #[test]
+    fn test_crash_name() {
+        let crumb = Breadcrumb::invoke(1, 2, b"auth");
+        assert_eq!(b"auth", crumb.name());
+        let long = [b'x'; NAME_LEN + 8];
+        assert_eq!(&long[..NAME_LEN], Breadcrumb::invoke(1, 2, &long).name());
+    }
+
+    // Tests that isolation is only attempted if it is the policy, and the panic is contained.
+    #[test]
+    fn test_crash_decide() {
+        assert_eq!(Action::Abort, decide(CrashPolicy::Abort, true));
+        assert_eq!(Action::Abort, decide(CrashPolicy::Abort, false));
+        assert_eq!(Action::Isolate, decide(CrashPolicy::Isolate, true));
+        assert_eq!(Action::Abort, decide(CrashPolicy::Isolate, false));
+    }
+
+    // Tests that a panic inside an extension is reported with the request that invoked it and
+    // the requests before it, and isolated, and that a panic outside of one aborts.
+    #[test]
+    fn test_crash_report() {
+        let mut path = env::temp_dir();
+        path.push(format!("sandstorm-crash-{}", process::id()));
+        let _ = fs::remove_file(&path);
+        install_with(
+            CrashPolicy::Isolate,
+            path.to_string_lossy().into_owned(),
+            exit,
+        );
+
+        let handle = thread::Builder::new()
+            .name("core-3".to_string())
+            .spawn(|| {
+                dispatched(&OpCode::SandstormGetRpc, &get(5, 1, b"user:alice", 40));
+                record(Breadcrumb::invoke(5, 41, b"panicky"));
+
+                // The extension panics inside it's catch boundary; it is isolated.
+                let crumb = Breadcrumb::invoke(5, 41, b"panicky");
+                let res = contain(&crumb, || -> u64 { panic!("controlled panic") });
+                let isolated = isolate();
+
+                // A panic outside any task can't be isolated.
+                let res2 = panic::catch_unwind(|| panic!("uncontained panic"));
+                (res.is_err(), isolated, res2.is_err(), isolate())
+            })
+            .unwrap();
+
+        assert_eq!((true, true, true, false), handle.join().unwrap());
+        assert_eq!(1, EXITS.load(Ordering::SeqCst));
+
+        let report = fs::read_to_string(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        let (first, second) = report.split_at(report.find("uncontained").unwrap());
+
+        assert!(first.contains("crash on thread core-3"));
+        assert!(first.contains("controlled panic"));
+        assert!(first.contains("requests dispatched on this core: 2"));
+        assert!(first.contains("task: opcode SandstormInvokeRpc tenant 5 table 0"));
+        assert!(first.contains("id 41 extension panicky"));
+        let get = format!(
+            "opcode SandstormGetRpc tenant 5 table 1 key {:#018x} id 40",
+            hash(b"user:alice")
+        );
+        assert!(first.contains(&get));
+        assert!(first.contains("policy: isolate, extension disabled"));
+
+        assert!(second.contains("task: none"));
+        assert!(second.contains("policy: abort"));
+    }
+}
@@ -13,63 +13,225 @@
  * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
  */
 
-use time::PreciseTime;
+use std::fmt;
+use std::fs;
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
 use std::sync::{Once, ONCE_INIT};
 
+use libc;
+
+#[cfg(any(test, feature = "sim"))]
+use std::cell::Cell;
+
+/// The length of each round of calibrating the time-stamp counter against the monotonic clock,
+/// in nanoseconds.
+pub const CALIBRATE_ROUND_NS: u64 = 50 * 1000 * 1000;
+
+/// The number of rounds the time-stamp counter is calibrated over. The median round is taken,
+/// so that a round interrupted midway, or migrated to another socket, does not skew the result.
+pub const CALIBRATE_ROUNDS: usize = 5;
+
+// The file the kernel names the clock it keeps time off in.
+const KERNEL_CLOCKSOURCE: &str = "/sys/devices/system/clocksource/clocksource0/current_clocksource";
+
+// The clock selected by init(); 0 until it has run.
+const SOURCE_UNSET: usize = 0;
+const SOURCE_TSC: usize = 1;
+const SOURCE_MONOTONIC_RAW: usize = 2;
+
+static SOURCE: AtomicUsize = ATOMIC_USIZE_INIT;
 static mut CYCLES_PER_SECOND: u64 = 0;
 static INIT: Once = ONCE_INIT;
 
-/// Perform once-only overall initialization for the cycles module, such
-/// as calibrating the clock frequency.  This method is invoked automatically
-/// during initialization.
-/// Stolen from the RAMCloud code base. Thanks, John.
-fn init() -> u64 {
-    // Compute the frequency of the fine-grained CPU timer: to do this,
-    // take parallel time readings using both rdtsc and PreciseTime.
-    // After 10ms have elapsed, take the ratio between these readings.
-
-    // There is one tricky aspect, which is that we could get interrupted
-    // between calling gettimeofday and reading the cycle counter, in which
-    // case we won't have corresponding readings.  To handle this (unlikely)
-    // case, compute the overall result repeatedly, and wait until we get
-    // two successive calculations that are within 0.1% of each other.
-    let mut old_cycles = 0.;
-    let mut cycles_per_second;
-    loop {
-        let start_time = PreciseTime::now();
-        let start_cycles = rdtsc();
-        loop {
-            let nanos = start_time.to(PreciseTime::now()).num_nanoseconds().unwrap() as f64;
-            if nanos > 10000000. {
-                cycles_per_second = (rdtsc() - start_cycles) as f64 * 1000000000.0 / nanos;
-                break;
-            }
+/// The clock time-stamps are read off.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ClockSource {
+    /// The CPU's time-stamp counter, read with the rdtsc instruction.
+    Tsc,
+
+    /// clock_gettime(CLOCK_MONOTONIC_RAW), ticking once a nanosecond. Slower to read than the
+    /// time-stamp counter, but consistent across every core and socket, and unaffected by power
+    /// management.
+    MonotonicRaw,
+}
+
+impl fmt::Display for ClockSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ClockSource::Tsc => write!(f, "tsc"),
+            ClockSource::MonotonicRaw => write!(f, "monotonic_raw"),
         }
-        let delta = cycles_per_second / 1000.0;
-        if (old_cycles > (cycles_per_second - delta)) && (old_cycles < (cycles_per_second + delta)) {
-            return cycles_per_second as u64;
+    }
+}
+
+/// What a machine says about it's time-stamp counter. Refer to select().
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Caps {
+    /// True if the CPU reports an invariant time-stamp counter, one that ticks at a constant rate
+    /// no matter the frequency or power state of the core.
+    pub invariant_tsc: bool,
+
+    /// True if the kernel keeps time off the time-stamp counter. The kernel checks that the
+    /// counters on every core and socket are in sync before it does so, and stops if it ever
+    /// finds them skewed.
+    pub kernel_tsc: bool,
+}
+
+impl Caps {
+    /// Probes the machine the calling process runs on.
+    pub fn probe() -> Caps {
+        let kernel = fs::read_to_string(KERNEL_CLOCKSOURCE).unwrap_or_default();
+        Caps {
+            invariant_tsc: cpu_invariant_tsc(),
+            kernel_tsc: kernel.trim() == "tsc",
         }
-        old_cycles = cycles_per_second;
     }
 }
 
-/// Return the CPU cycles per second for the executing processor.
+/// Returns the clock a machine reads time-stamps off: the time-stamp counter if it is invariant
+/// and in sync across cores, so that stamps taken on different threads can be compared, and
+/// CLOCK_MONOTONIC_RAW otherwise.
+///
+/// # Arguments
+///
+/// * `caps`: What the machine says about it's time-stamp counter.
+pub fn select(caps: &Caps) -> ClockSource {
+    match caps.invariant_tsc && caps.kernel_tsc {
+        true => ClockSource::Tsc,
+        false => ClockSource::MonotonicRaw,
+    }
+}
+
+/// Selects the clock time-stamps are read off, and calibrates it. Done once per process, the
+/// first time any time-stamp is read; calling this at startup keeps calibration (a quarter
+/// second) off any path that is measured.
+///
+/// # Return
+///
+/// The clock selected.
+pub fn init() -> ClockSource {
+    INIT.call_once(|| {
+        let source = select(&Caps::probe());
+        let hz = match source {
+            ClockSource::Tsc => calibrate(),
+            ClockSource::MonotonicRaw => 1_000_000_000,
+        };
+        info!("Reading time-stamps off {} at {} Hz", source, hz);
+
+        unsafe {
+            CYCLES_PER_SECOND = hz;
+        }
+        SOURCE.store(
+            match source {
+                ClockSource::Tsc => SOURCE_TSC,
+                ClockSource::MonotonicRaw => SOURCE_MONOTONIC_RAW,
+            },
+            Ordering::Release,
+        );
+    });
+
+    source()
+}
+
+/// Returns the clock time-stamps are read off, selecting it first if it wasn't already.
+pub fn source() -> ClockSource {
+    match SOURCE.load(Ordering::Acquire) {
+        SOURCE_TSC => ClockSource::Tsc,
+        SOURCE_MONOTONIC_RAW => ClockSource::MonotonicRaw,
+        _ => init(),
+    }
+}
+
+// Measures the frequency of the time-stamp counter against CLOCK_MONOTONIC_RAW, over
+// CALIBRATE_ROUNDS rounds of CALIBRATE_ROUND_NS each.
+fn calibrate() -> u64 {
+    let rounds = (0..CALIBRATE_ROUNDS)
+        .map(|_| {
+            let (start_ns, start_cycles) = (monotonic_raw_ns(), hw_rdtsc());
+            loop {
+                let nanos = monotonic_raw_ns() - start_ns;
+                if nanos >= CALIBRATE_ROUND_NS {
+                    // The counter may run backwards if the thread moved to another socket.
+                    let cycles = hw_rdtsc().saturating_sub(start_cycles);
+                    return cycles as f64 * 1e9 / nanos as f64;
+                }
+            }
+        }).collect();
+    median_hz(rounds)
+}
+
+// Returns the median of a set of frequency estimates, in cycles per second.
+fn median_hz(mut estimates: Vec<f64>) -> u64 {
+    estimates.retain(|hz| hz.is_finite());
+    if estimates.is_empty() {
+        return 0;
+    }
+
+    estimates.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    estimates[estimates.len() / 2] as u64
+}
+
+// Returns the calibrated frequency of the selected clock.
+fn hw_cycles_per_second() -> u64 {
+    init();
+    unsafe { CYCLES_PER_SECOND }
+}
+
+// Reads the selected clock.
+#[inline]
+fn hw_now() -> u64 {
+    match SOURCE.load(Ordering::Relaxed) {
+        SOURCE_TSC => hw_rdtsc(),
+        SOURCE_MONOTONIC_RAW => monotonic_raw_ns(),
+        _ => {
+            init();
+            hw_now()
+        }
+    }
+}
+
+/// Return the number of ticks per second of the clock time-stamps are read off; CPU cycles if
+/// it is the time-stamp counter.
 ///
 /// # Return
 ///
 /// Number of CPU cycles per second.
+#[cfg(not(any(test, feature = "sim")))]
+#[inline]
 pub fn cycles_per_second() -> u64 {
-    unsafe {
-        INIT.call_once(|| {
-            CYCLES_PER_SECOND = init();
-        });
-        CYCLES_PER_SECOND
-    }
+    hw_cycles_per_second()
 }
 
-/// Return a 64-bit timestamp using the rdtsc instruction.
-#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+/// Return the number of ticks per second of the clock time-stamps are read off, or the
+/// frequency of the virtual clock if one was installed on the calling thread.
+///
+/// # Return
+///
+/// Number of CPU cycles per second.
+#[cfg(any(test, feature = "sim"))]
+pub fn cycles_per_second() -> u64 {
+    virt::hz().unwrap_or_else(hw_cycles_per_second)
+}
+
+/// Return a 64-bit time-stamp off the selected clock: the rdtsc instruction, or
+/// CLOCK_MONOTONIC_RAW if the time-stamp counter can't be trusted. Refer to select().
+#[cfg(not(any(test, feature = "sim")))]
+#[inline]
+pub fn rdtsc() -> u64 {
+    hw_now()
+}
+
+/// Return a 64-bit time-stamp off the selected clock, or the current time on the virtual clock
+/// if one was installed on the calling thread.
+#[cfg(any(test, feature = "sim"))]
 pub fn rdtsc() -> u64 {
+    virt::now().unwrap_or_else(hw_now)
+}
+
+// Reads the hardware cycle counter.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[inline]
+fn hw_rdtsc() -> u64 {
     unsafe {
         let lo: u32;
         let hi: u32;
@@ -78,6 +240,36 @@ pub fn rdtsc() -> u64 {
     }
 }
 
+// Reads CLOCK_MONOTONIC_RAW, in nanoseconds.
+#[inline]
+fn monotonic_raw_ns() -> u64 {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe {
+        libc::clock_gettime(libc::CLOCK_MONOTONIC_RAW, &mut ts);
+    }
+    ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64
+}
+
+// Returns true if the CPU reports an invariant time-stamp counter (CPUID leaf 0x80000007,
+// bit 8 of EDX).
+#[cfg(target_arch = "x86_64")]
+fn cpu_invariant_tsc() -> bool {
+    use std::arch::x86_64::{__cpuid, __get_cpuid_max};
+
+    unsafe {
+        let (max, _) = __get_cpuid_max(0x8000_0000);
+        max >= 0x8000_0007 && __cpuid(0x8000_0007).edx & (1 << 8) != 0
+    }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn cpu_invariant_tsc() -> bool {
+    false
+}
+
 /// Converts the number of CPU cycles to seconds.
 ///
 /// # Arguments
@@ -89,6 +281,73 @@ pub fn to_seconds(cycles: u64) -> f64 {
     cycles as f64 / cycles_per_second() as f64
 }
 
+/// A per-thread virtual clock for simulations. Once installed on a thread, rdtsc() and
+/// cycles_per_second() on that thread read the virtual clock instead of the hardware, letting
+/// the scheduler be driven in simulated time. This module is only compiled into tests and
+/// builds with the "sim" feature; everywhere else rdtsc() reads the hardware directly.
+#[cfg(any(test, feature = "sim"))]
+pub mod virt {
+    use super::Cell;
+
+    thread_local!(
+        // The current time and frequency of the virtual clock, if one was installed.
+        static CLOCK: Cell<Option<(u64, u64)>> = Cell::new(None)
+    );
+
+    /// Installs a virtual clock on the calling thread.
+    ///
+    /// # Arguments
+    ///
+    /// * `start`: The time-stamp, in cycles, the clock starts at.
+    /// * `hz`:    The number of virtual cycles per second.
+    pub fn install(start: u64, hz: u64) {
+        CLOCK.with(|c| c.set(Some((start, hz))));
+    }
+
+    /// Removes the virtual clock from the calling thread. Time is read from the hardware again.
+    pub fn uninstall() {
+        CLOCK.with(|c| c.set(None));
+    }
+
+    /// Returns the current time on the calling thread's virtual clock, if one was installed.
+    pub fn now() -> Option<u64> {
+        CLOCK.with(|c| c.get().map(|(now, _)| now))
+    }
+
+    /// Returns the frequency of the calling thread's virtual clock, if one was installed.
+    pub fn hz() -> Option<u64> {
+        CLOCK.with(|c| c.get().map(|(_, hz)| hz))
+    }
+
+    /// Moves the calling thread's virtual clock forward. Does nothing if no clock was installed.
+    ///
+    /// # Arguments
+    ///
+    /// * `cycles`: The number of cycles to advance the clock by.
+    pub fn advance(cycles: u64) {
+        CLOCK.with(|c| {
+            if let Some((now, hz)) = c.get() {
+                c.set(Some((now + cycles, hz)));
+            }
+        });
+    }
+
+    /// Sets the calling thread's virtual clock to a time-stamp if it lies in the future.
+    ///
+    /// # Arguments
+    ///
+    /// * `time`: The time-stamp, in cycles, to move the clock to.
+    pub fn advance_to(time: u64) {
+        CLOCK.with(|c| {
+            if let Some((now, hz)) = c.get() {
+                if time > now {
+                    c.set(Some((time, hz)));
+                }
+            }
+        });
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -97,8 +356,44 @@ mod test {
 
     #[test]
     fn test_init() {
-        assert!(cycles_per_second() > 1000000000);
+        assert!(cycles_per_second() >= 1000000000);
         assert!(cycles_per_second() < 5000000000);
+        assert_eq!(init(), source());
+    }
+
+    // Tests that the time-stamp counter is only read off if it is invariant and the kernel keeps
+    // time off it, and that everything else falls back to the monotonic clock.
+    #[test]
+    fn test_select() {
+        let caps = |invariant, kernel| Caps {
+            invariant_tsc: invariant,
+            kernel_tsc: kernel,
+        };
+        assert_eq!(ClockSource::Tsc, select(&caps(true, true)));
+        assert_eq!(ClockSource::MonotonicRaw, select(&caps(false, true)));
+        assert_eq!(ClockSource::MonotonicRaw, select(&caps(true, false)));
+        assert_eq!(ClockSource::MonotonicRaw, select(&caps(false, false)));
+        assert_eq!("monotonic_raw", ClockSource::MonotonicRaw.to_string());
+    }
+
+    // Tests that calibration takes the median round, so that a round or two gone wrong doesn't
+    // skew the result.
+    #[test]
+    fn test_median_hz() {
+        let hz = median_hz(vec![2.0e9, 2.001e9, 0.0, 1.999e9, 9.0e12]);
+        assert_eq!(2_000_000_000, hz);
+        assert_eq!(3, median_hz(vec![1.0, ::std::f64::NAN, 3.0]));
+        assert_eq!(0, median_hz(vec![]));
+    }
+
+    // Tests that the monotonic clock never runs backwards and ticks in nanoseconds.
+    #[test]
+    fn test_monotonic_raw() {
+        let start = monotonic_raw_ns();
+        thread::sleep(Duration::from_millis(10));
+        let stop = monotonic_raw_ns();
+        assert!(stop - start >= 10 * 1000 * 1000);
+        assert!(stop - start < 1000 * 1000 * 1000);
     }
 
     #[test]
@@ -108,4 +403,19 @@ mod test {
         let stop = rdtsc();
         assert!(to_seconds(stop - start) - 1.0 < 0.0001);
     }
+
+    #[test]
+    fn test_virtual_clock() {
+        virt::install(100, 1000);
+        assert_eq!(100, rdtsc());
+        assert_eq!(1000, cycles_per_second());
+        virt::advance(50);
+        assert_eq!(150, rdtsc());
+        virt::advance_to(120);
+        assert_eq!(150, rdtsc());
+        virt::advance_to(500);
+        assert_eq!(0.5, to_seconds(rdtsc()));
+        virt::uninstall();
+        assert!(cycles_per_second() >= 1000000000);
+    }
 }
@@ -0,0 +1,413 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::VecDeque;
+use std::hash::Hasher;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Weak};
+
+use bytes::Bytes;
+use hashbrown::HashMap;
+use spin::Mutex;
+
+/// The number of values a table's dedup index remembers, if not set in the server config.
+pub const DEFAULT_DEDUP_ENTRIES: usize = 64 * 1024;
+
+/// The most values a table's dedup index can be configured to remember.
+pub const MAX_DEDUP_ENTRIES: usize = 16 * 1024 * 1024;
+
+/// Values shorter than this are never shared. The handle to a shared value, and the separate
+/// allocation for the object's metadata and key, cost more than a short value saves.
+pub const MIN_DEDUP_LEN: usize = 64;
+
+// A 128 bit hash of a value, out of two independent 64 bit hashes.
+type Hash = (u64, u64);
+
+// Hashes a value with 64 bit FNV-1a and SipHash.
+fn hash(value: &[u8]) -> Hash {
+    let mut fnv: u64 = 0xcbf29ce484222325;
+    for b in value.iter() {
+        fnv ^= *b as u64;
+        fnv = fnv.wrapping_mul(0x100000001b3);
+    }
+
+    let mut sip = DefaultHasher::new();
+    sip.write(value);
+    (fnv, sip.finish())
+}
+
+// Counters shared by a dedup index and every value shared through it.
+#[derive(Default)]
+struct Counters {
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+    collisions: AtomicUsize,
+    evicted: AtomicUsize,
+    saved: AtomicUsize,
+}
+
+/// Counters of a table's dedup index. Refer to Dedup.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DedupStats {
+    /// The number of values written that shared an allocation with an identical one.
+    pub hits: u64,
+
+    /// The number of values written that got an allocation of their own, because no identical
+    /// value was remembered.
+    pub misses: u64,
+
+    /// The number of values whose hash matched a remembered value with different bytes. These
+    /// are counted as misses too.
+    pub collisions: u64,
+
+    /// The number of values forgotten to make room for new ones.
+    pub evicted: u64,
+
+    /// The bytes of values currently in the table that are held by another object's allocation
+    /// instead of their own.
+    pub bytes_saved: u64,
+
+    /// The number of values the index remembers.
+    pub entries: u64,
+}
+
+impl DedupStats {
+    /// Returns the fraction of values written that were shared, or 0 if none were written.
+    pub fn hit_rate(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            n => self.hits as f64 / n as f64,
+        }
+    }
+}
+
+/// A value held by one or more objects on a table with deduplication enabled.
+pub struct SharedValue {
+    // The value, as stored (compressed and checksummed along with it's object, if it is).
+    value: Bytes,
+
+    // The number of Shares on the value.
+    refs: AtomicUsize,
+
+    // The counters of the index the value was shared through.
+    counters: Arc<Counters>,
+}
+
+/// An object's reference to a SharedValue. The value is freed once the last Share on it is
+/// dropped, when the last object holding it is overwritten or deleted; the dedup index only
+/// remembers it for as long as it is alive.
+pub struct Share(Arc<SharedValue>);
+
+impl Share {
+    // Adds a reference to a value, counting the bytes it saves if it already had one.
+    fn new(shared: Arc<SharedValue>) -> Share {
+        if shared.refs.fetch_add(1, Ordering::AcqRel) > 0 {
+            shared
+                .counters
+                .saved
+                .fetch_add(shared.value.len(), Ordering::Relaxed);
+        }
+        Share(shared)
+    }
+
+    /// Returns the value.
+    pub fn value(&self) -> &Bytes {
+        &self.0.value
+    }
+
+    /// Returns the number of objects holding the value.
+    pub fn refs(&self) -> usize {
+        self.0.refs.load(Ordering::Acquire)
+    }
+}
+
+impl Drop for Share {
+    fn drop(&mut self) {
+        if self.0.refs.fetch_sub(1, Ordering::AcqRel) > 1 {
+            self.0
+                .counters
+                .saved
+                .fetch_sub(self.0.value.len(), Ordering::Relaxed);
+        }
+    }
+}
+
+// The values a dedup index remembers.
+struct Index {
+    // Every value remembered, by hash, along with the time it was last shared.
+    entries: HashMap<Hash, (u64, Weak<SharedValue>)>,
+
+    // The hash and time of every share, oldest first. May hold stale pairs for values that were
+    // shared again since; those are skipped when evicting.
+    order: VecDeque<(Hash, u64)>,
+
+    // Ticks once per share.
+    clock: u64,
+}
+
+impl Index {
+    // Marks a value as just shared. Drops stale pairs off `order` once they make up most of it,
+    // given that atmost `cap` values are remembered.
+    fn touch(&mut self, hash: Hash, cap: usize) {
+        self.clock += 1;
+        let clock = self.clock;
+        if let Some(entry) = self.entries.get_mut(&hash) {
+            entry.0 = clock;
+        }
+        self.order.push_back((hash, clock));
+
+        if self.order.len() > 2 * cap + 16 {
+            let entries = &self.entries;
+            self.order
+                .retain(|&(hash, clock)| entries.get(&hash).map_or(false, |e| e.0 == clock));
+        }
+    }
+
+    // Forgets the least recently shared values until atmost `cap` are remembered, and returns
+    // the number forgotten.
+    fn evict(&mut self, cap: usize) -> usize {
+        let mut evicted = 0;
+        while self.entries.len() > cap {
+            let (hash, clock) = match self.order.pop_front() {
+                Some(pair) => pair,
+                None => break,
+            };
+            if self.entries.get(&hash).map_or(false, |e| e.0 == clock) {
+                self.entries.remove(&hash);
+                evicted += 1;
+            }
+        }
+
+        evicted
+    }
+}
+
+/// The dedup index of a table. Maps the hash of each value recently written to the table to the
+/// allocation holding it, so that objects written with an identical value share that allocation
+/// instead of copying the value. A hash match is only shared after the bytes are compared, so a
+/// collision costs a copy, never a wrong value.
+///
+/// The index remembers atmost a configured number of values, forgetting the least recently
+/// shared one to make room for a new one. It is a best-effort space saver: a value that was
+/// forgotten, or whose every holder was overwritten, is copied again by the next write of it.
+pub struct Dedup {
+    // The most values remembered.
+    cap: usize,
+
+    // The values remembered.
+    index: Mutex<Index>,
+
+    // Counters shared with every value shared through this index.
+    counters: Arc<Counters>,
+
+    // Hashes values. Only ever swapped out by tests, to force collisions.
+    hash: fn(&[u8]) -> Hash,
+}
+
+impl Dedup {
+    /// Returns an index that remembers nothing yet.
+    ///
+    /// # Arguments
+    ///
+    /// * `cap`: The most values remembered. 0 means DEFAULT_DEDUP_ENTRIES.
+    pub fn new(cap: usize) -> Dedup {
+        Dedup::with_hash(cap, hash)
+    }
+
+    // Returns an index that hashes values with `hash`.
+    fn with_hash(cap: usize, hash: fn(&[u8]) -> Hash) -> Dedup {
+        let cap = match cap {
+            0 => DEFAULT_DEDUP_ENTRIES,
+            cap => cap.min(MAX_DEDUP_ENTRIES),
+        };
+
+        Dedup {
+            cap: cap,
+            index: Mutex::new(Index {
+                entries: HashMap::new(),
+                order: VecDeque::new(),
+                clock: 0,
+            }),
+            counters: Arc::new(Counters::default()),
+            hash: hash,
+        }
+    }
+
+    /// Returns the most values the index remembers.
+    pub fn cap(&self) -> usize {
+        self.cap
+    }
+
+    /// Returns a reference to an allocation holding a value. If an identical value is
+    /// remembered, it's allocation is shared, and `value` is dropped. Otherwise, `value` itself
+    /// is remembered, and shared with the writes of it that follow.
+    ///
+    /// # Arguments
+    ///
+    /// * `value`: The value being written, as stored.
+    ///
+    /// # Return
+    ///
+    /// The reference, and true if it shares an allocation held by an existing object.
+    pub fn share(&self, value: Bytes) -> (Share, bool) {
+        let hash = (self.hash)(&value);
+        let mut index = self.index.lock();
+
+        let existing = index
+            .entries
+            .get(&hash)
+            .and_then(|&(_, ref weak)| weak.upgrade());
+        match existing {
+            Some(ref shared) if shared.value == value => {
+                let share = Share::new(Arc::clone(shared));
+                index.touch(hash, self.cap);
+                self.counters.hits.fetch_add(1, Ordering::Relaxed);
+                return (share, true);
+            }
+
+            // The remembered value stays; it is likelier to be written again than this one.
+            Some(_) => {
+                self.counters.collisions.fetch_add(1, Ordering::Relaxed);
+                self.counters.misses.fetch_add(1, Ordering::Relaxed);
+                return (self.unshared(value), false);
+            }
+
+            None => {}
+        }
+
+        let shared = Arc::new(SharedValue {
+            value: value,
+            refs: AtomicUsize::new(0),
+            counters: Arc::clone(&self.counters),
+        });
+        index.entries.insert(hash, (0, Arc::downgrade(&shared)));
+        index.touch(hash, self.cap);
+        let evicted = index.evict(self.cap);
+        self.counters.evicted.fetch_add(evicted, Ordering::Relaxed);
+        self.counters.misses.fetch_add(1, Ordering::Relaxed);
+
+        (Share::new(shared), false)
+    }
+
+    // Returns a reference to a value that is not remembered by the index.
+    fn unshared(&self, value: Bytes) -> Share {
+        Share::new(Arc::new(SharedValue {
+            value: value,
+            refs: AtomicUsize::new(0),
+            counters: Arc::clone(&self.counters),
+        }))
+    }
+
+    /// Returns the index's counters.
+    pub fn stats(&self) -> DedupStats {
+        let entries = self.index.lock().entries.len();
+        DedupStats {
+            hits: self.counters.hits.load(Ordering::Relaxed) as u64,
+            misses: self.counters.misses.load(Ordering::Relaxed) as u64,
+            collisions: self.counters.collisions.load(Ordering::Relaxed) as u64,
+            evicted: self.counters.evicted.load(Ordering::Relaxed) as u64,
+            bytes_saved: self.counters.saved.load(Ordering::Relaxed) as u64,
+            entries: entries as u64,
+        }
+    }
+}
+
+// This module contains unit tests for Dedup. Tests of objects sharing values on a table are
+// with the Allocator.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Hashes every value to the same hash.
+    fn collide(_value: &[u8]) -> Hash {
+        (7, 7)
+    }
+
+    // Tests that identical values share an allocation, and that it is freed with the last share.
+    #[test]
+    fn test_share_lifecycle() {
+        let dedup = Dedup::new(4);
+        let (a, shared) = dedup.share(Bytes::from(vec![1; 100]));
+        assert!(!shared);
+        let (b, shared) = dedup.share(Bytes::from(vec![1; 100]));
+        assert!(shared);
+        assert_eq!(a.value().as_ptr(), b.value().as_ptr());
+        assert_eq!(2, a.refs());
+        assert_eq!(100, dedup.stats().bytes_saved);
+
+        drop(a);
+        assert_eq!((1, 0), (b.refs(), dedup.stats().bytes_saved));
+
+        // Once the last share is dropped, the value is written out again.
+        drop(b);
+        let (c, shared) = dedup.share(Bytes::from(vec![1; 100]));
+        assert!(!shared);
+        assert_eq!(1, c.refs());
+
+        let stats = dedup.stats();
+        assert_eq!((1, 2, 0), (stats.hits, stats.misses, stats.collisions));
+        assert!((stats.hit_rate() - 1.0 / 3.0).abs() < 1e-9);
+    }
+
+    // Tests that values whose hashes collide are compared before they are shared.
+    #[test]
+    fn test_collision() {
+        let dedup = Dedup::with_hash(4, collide);
+        let (a, _) = dedup.share(Bytes::from(vec![1; 100]));
+        let (b, shared) = dedup.share(Bytes::from(vec![2; 100]));
+        assert!(!shared);
+        assert_eq!(&[2; 100][..], &b.value()[..]);
+        assert_eq!(&[1; 100][..], &a.value()[..]);
+
+        // The first value is still the one that is shared.
+        let (c, shared) = dedup.share(Bytes::from(vec![1; 100]));
+        assert!(shared);
+        assert_eq!(a.value().as_ptr(), c.value().as_ptr());
+        assert_eq!(1, b.refs());
+
+        let stats = dedup.stats();
+        assert_eq!((1, 2, 1), (stats.hits, stats.misses, stats.collisions));
+        assert_eq!((100, 1), (stats.bytes_saved, stats.entries));
+    }
+
+    // Tests that the least recently shared value is forgotten once the index is full.
+    #[test]
+    fn test_eviction() {
+        let dedup = Dedup::new(2);
+        let mut shares = Vec::new();
+        let mut share = |i: u8| {
+            let (share, shared) = dedup.share(Bytes::from(vec![i; 100]));
+            shares.push(share);
+            shared
+        };
+        assert!(!share(0) && !share(1) && !share(2));
+
+        // 0 was forgotten. Sharing 1 again makes 2 the least recently shared.
+        assert!(share(1));
+        assert!(!share(0));
+        assert!(!share(2));
+        assert!(share(0));
+
+        // Touches pile up stale pairs, which are dropped before they outgrow the index.
+        for _ in 0..100 {
+            assert!(share(0));
+        }
+        assert!(dedup.index.lock().order.len() <= 2 * 2 + 16);
+
+        let stats = dedup.stats();
+        assert_eq!((3, 2, 102), (stats.evicted, stats.entries, stats.hits));
+    }
+}
@@ -0,0 +1,146 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use sandstorm::common::TenantId;
+
+/// The most work a single invocation can defer. Calls to defer() past this fail.
+pub const MAX_DEFERRED: usize = 8;
+
+/// The most deferred invocations a tenant can have queued or running at once, by default.
+pub const DEFAULT_MAX_DEFERRED: usize = 1024;
+
+/// Work deferred by an invocation through defer(), to be run once it's response has been sent
+/// out.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Deferred {
+    /// The tenant that issued the invocation. The deferred work runs on it's behalf.
+    pub tenant: TenantId,
+
+    /// The name of the extension that deferred the work.
+    pub name: Vec<u8>,
+
+    /// The arguments passed to defer(), without the marker.
+    pub args: Vec<u8>,
+}
+
+/// Counts the deferred work of a tenant. Work is admitted only while fewer than a cap of it's
+/// deferred invocations are queued or running; the rest is dropped and counted.
+pub struct Deferrals {
+    // The number of deferred invocations admitted that have not completed yet.
+    queued: AtomicUsize,
+
+    // The number of deferred invocations that ran to completion.
+    ran: AtomicUsize,
+
+    // The number of deferred invocations dropped because the tenant was over it's cap.
+    dropped: AtomicUsize,
+}
+
+impl Deferrals {
+    /// Returns counters for a tenant that has not deferred any work yet.
+    pub fn new() -> Deferrals {
+        Deferrals {
+            queued: AtomicUsize::new(0),
+            ran: AtomicUsize::new(0),
+            dropped: AtomicUsize::new(0),
+        }
+    }
+
+    /// Admits a deferred invocation if the tenant has fewer than `cap` of them outstanding.
+    /// An admitted invocation must be release()d once it completes or fails.
+    ///
+    /// # Arguments
+    ///
+    /// * `cap`: The most deferred invocations the tenant can have outstanding.
+    ///
+    /// # Return
+    ///
+    /// True if the invocation was admitted. False if it was dropped.
+    pub fn acquire(&self, cap: usize) -> bool {
+        let mut queued = self.queued.load(Ordering::Relaxed);
+        loop {
+            if queued >= cap {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                return false;
+            }
+
+            match self.queued.compare_exchange_weak(
+                queued,
+                queued + 1,
+                Ordering::AcqRel,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return true,
+                Err(current) => queued = current,
+            }
+        }
+    }
+
+    /// Releases a deferred invocation admitted by acquire().
+    ///
+    /// # Arguments
+    ///
+    /// * `ran`: True if the invocation ran to completion.
+    pub fn release(&self, ran: bool) {
+        self.queued.fetch_sub(1, Ordering::AcqRel);
+        if ran {
+            self.ran.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Returns the number of deferred invocations admitted that have not completed yet.
+    pub fn queued(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of deferred invocations that ran to completion.
+    pub fn ran(&self) -> usize {
+        self.ran.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of deferred invocations dropped because the tenant was over it's cap.
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+// This module contains unit tests for Deferrals.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Tests that work past the cap is dropped and counted, and that released slots are reused.
+    #[test]
+    fn test_deferrals_cap() {
+        let deferrals = Deferrals::new();
+        assert!(deferrals.acquire(2));
+        assert!(deferrals.acquire(2));
+        assert!(!deferrals.acquire(2));
+        assert_eq!(2, deferrals.queued());
+        assert_eq!(1, deferrals.dropped());
+
+        deferrals.release(true);
+        deferrals.release(false);
+        assert_eq!(0, deferrals.queued());
+        assert_eq!(1, deferrals.ran());
+
+        assert!(deferrals.acquire(2));
+        assert!(!deferrals.acquire(0));
+        assert_eq!(1, deferrals.queued());
+        assert_eq!(2, deferrals.dropped());
+    }
+}
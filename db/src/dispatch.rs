@@ -16,12 +16,15 @@
 #[cfg(feature = "dispatch")]
 use std::cell::RefCell;
 use std::fmt::Display;
+use std::mem::size_of;
 use std::net::Ipv4Addr;
 use std::option::Option;
 use std::str::FromStr;
 use std::sync::Arc;
 
+use super::backoff::Backoff;
 use super::config;
+use super::crash;
 #[cfg(feature = "dispatch")]
 use super::cyclecounter::CycleCounter;
 use super::cycles;
@@ -38,11 +41,71 @@ use super::e2d2::headers::*;
 use super::e2d2::interface::*;
 
 use sandstorm::common;
+use sandstorm::common::{TableId, TenantId};
 
 /// This flag enables or disables fast path for native requests.
 /// Later, it will be set from the server.toml file probably.
 pub const FAST_PATH: bool = false;
 
+/// This flag enables or disables grouping of get() requests received in the same burst by
+/// (tenant, table), so that the tenant and table are resolved once per group instead of once per
+/// request.
+pub const GROUP_GETS: bool = true;
+
+/// Appends a value to the group identified by `key`, creating the group if it does not exist.
+/// Groups are kept in a vector because a receive burst is small (at most 32 packets); a linear
+/// scan is cheaper than hashing here.
+///
+/// # Arguments
+///
+/// * `groups`: The groups formed so far.
+/// * `key`:    The key identifying the group the value belongs to.
+/// * `value`:  The value to be added to the group.
+///
+/// # Return
+///
+/// The index of the group if the value started a new one, None if it joined an existing group.
+fn group_insert<K: PartialEq, V>(groups: &mut Vec<(K, Vec<V>)>, key: K, value: V) -> Option<usize> {
+    for &mut (ref k, ref mut members) in groups.iter_mut() {
+        if *k == key {
+            members.push(value);
+            return None;
+        }
+    }
+
+    groups.push((key, vec![value]));
+    Some(groups.len() - 1)
+}
+
+/// A place in the order a burst of requests is dispatched in.
+enum Slot<V, G> {
+    /// A request that is dispatched on it's own.
+    One(V),
+
+    /// A group of requests, dispatched together at the position of the first one to arrive.
+    Group(G),
+}
+
+/// Lays out a burst in the order it is dispatched in. Requests dispatched on their own keep
+/// their position in the burst, and each group takes the position of it's first member. Members
+/// stay in the order they were added to the group in, which is the order they arrived in.
+///
+/// # Arguments
+///
+/// * `order`:  The burst in arrival order, with groups referred to by their index in `groups`.
+/// * `groups`: The groups formed by group_insert().
+fn in_order<V, G>(order: Vec<Slot<V, usize>>, groups: Vec<G>) -> Vec<Slot<V, G>> {
+    let mut groups: Vec<Option<G>> = groups.into_iter().map(Some).collect();
+    order
+        .into_iter()
+        .filter_map(|slot| match slot {
+            Slot::One(value) => Some(Slot::One(value)),
+
+            Slot::Group(index) => groups[index].take().map(Slot::Group),
+        })
+        .collect()
+}
+
 /// This is a thread local variable to count the number of occurrences
 /// of cycle counting to average for 1 M events.
 #[cfg(feature = "dispatch")]
@@ -147,6 +210,16 @@ where
     /// Unique identifier for a Dispatch task. Currently required for measurement purposes.
     id: i32,
 
+    /// The number of get() requests that were dispatched as part of a group whose tenant and
+    /// table were resolved once for the entire group.
+    grouped: u64,
+
+    /// The number of requests that were dispatched individually.
+    ungrouped: u64,
+
+    /// Backs the core off polling once the dispatcher keeps finding nothing to do.
+    backoff: Backoff,
+
     /// The CPU cycle counter to count the number of cycles per event. Need to use start() and
     /// stop() a code block or function call to profile the events.
     #[cfg(feature = "dispatch")]
@@ -227,6 +300,8 @@ where
         mac_header.dst = mac_dst_addr;
         mac_header.set_etype(mac_etype);
 
+        let backoff = Backoff::new(config.backoff(), master.cores().register(sched.core()));
+
         Dispatch {
             master_service: master,
             scheduler: sched,
@@ -244,11 +319,121 @@ where
             time: 0,
             priority: TaskPriority::DISPATCH,
             id: id,
+            grouped: 0,
+            ungrouped: 0,
+            backoff: backoff,
             #[cfg(feature = "dispatch")]
             cycle_counter: DispatchCounters::new(),
         }
     }
 
+    /// Returns the number of requests dispatched as part of a group, and the number of requests
+    /// dispatched individually, since this dispatcher was created.
+    pub fn grouping_counters(&self) -> (u64, u64) {
+        (self.grouped, self.ungrouped)
+    }
+
+    // Enqueues the task for a request on the scheduler, under the tenant that issued the request
+    // so that the scheduler can share the core fairly between tenants.
+    fn enqueue(&self, tenant: Option<TenantId>, mut task: Box<Task>) {
+        if let Some(tenant) = tenant {
+            task.set_tenant(tenant);
+        }
+        self.scheduler.enqueue(task);
+    }
+
+    /// Dispatches the requests `dispatch_requests()` handed to Master, in the order laid out by
+    /// `in_order()`. A group of get() requests with more than one request has it's tenant and
+    /// table resolved once, and the handle is shared by all the tasks in the group. A group with
+    /// a single request takes the regular path.
+    ///
+    /// # Arguments
+    ///
+    /// * `burst`:          Requests dispatched on their own with their opcode and tenant, and
+    ///                     get() requests grouped by (tenant, table), along with their response
+    ///                     packets.
+    /// * `ignore_packets`: Packets that need to be freed because the request failed.
+    fn dispatch_burst(
+        &mut self,
+        burst: Vec<
+            Slot<
+                (
+                    wireformat::OpCode,
+                    Option<TenantId>,
+                    Packet<UdpHeader, EmptyMetadata>,
+                    Packet<UdpHeader, EmptyMetadata>,
+                ),
+                (
+                    (TenantId, TableId),
+                    Vec<(
+                        Packet<UdpHeader, EmptyMetadata>,
+                        Packet<UdpHeader, EmptyMetadata>,
+                    )>,
+                ),
+            >,
+        >,
+        ignore_packets: &mut Vec<Packet<UdpHeader, EmptyMetadata>>,
+    ) {
+        for slot in burst.into_iter() {
+            let ((tenant, table), members) = match slot {
+                Slot::One((opcode, tenant, request, response)) => {
+                    self.ungrouped += 1;
+                    match self.master_service.dispatch(opcode, request, response) {
+                        Ok(task) => {
+                            self.enqueue(tenant, task);
+                        }
+
+                        Err((req, res)) => {
+                            // Master returned an error. The allocated request and response packets
+                            // need to be freed up.
+                            ignore_packets.push(req);
+                            ignore_packets.push(res);
+                        }
+                    }
+                    continue;
+                }
+
+                Slot::Group(group) => group,
+            };
+
+            let mut resolved = None;
+            if members.len() > 1 {
+                resolved = Some(self.master_service.resolve_table(tenant, table));
+                self.grouped += members.len() as u64;
+            } else {
+                self.ungrouped += 1;
+            }
+
+            for (request, response) in members.into_iter() {
+                let result = match resolved {
+                    Some(ref table) => {
+                        self.master_service
+                            .get_resolved(request, response, table.clone())
+                    }
+
+                    None => self.master_service.dispatch(
+                        wireformat::OpCode::SandstormGetRpc,
+                        request,
+                        response,
+                    ),
+                };
+
+                match result {
+                    Ok(task) => {
+                        self.enqueue(Some(tenant), task);
+                    }
+
+                    Err((req, res)) => {
+                        // Master returned an error. The allocated request and response packets
+                        // need to be freed up.
+                        ignore_packets.push(req);
+                        ignore_packets.push(res);
+                    }
+                }
+            }
+        }
+    }
+
     /// This function attempts to receive a batch of packets from the
     /// dispatcher's network port.
     ///
@@ -621,7 +806,7 @@ where
     /// * `requests`: A vector of packets parsed upto and including their UDP
     ///               headers that will be dispatched to the appropriate
     ///               service.
-    fn dispatch_requests(&mut self, mut requests: Vec<Packet<UdpHeader, EmptyMetadata>>) {
+    fn dispatch_requests(&mut self, requests: Vec<Packet<UdpHeader, EmptyMetadata>>) {
         // This vector will hold the set of packets that were for either an invalid service or
         // operation.
         let mut ignore_packets = Vec::with_capacity(self.max_rx_packets as usize);
@@ -631,7 +816,31 @@ where
         // so these reponses can be sent out next time dispatch task is run.
         let mut native_responses = Vec::new();
 
-        while let Some(request) = requests.pop() {
+        // This vector will hold get() requests grouped by (tenant, table). These are dispatched
+        // once the entire burst has been looked at.
+        let mut groups = Vec::new();
+
+        // This vector will hold the requests handed to Master and the groups above in the order
+        // they arrived in, so that they are dispatched in that order.
+        let mut order = Vec::new();
+
+        // The time-stamp at which this burst of requests was received. Stamped onto responses to
+        // requests that ask for it.
+        let rx = cycles::rdtsc();
+
+        // While draining, every request goes to Master so that it can be rejected.
+        let draining = self.master_service.drain().draining();
+
+        // While any tenant is quarantined, requests are checked against their tenant's quarantine,
+        // and are not grouped.
+        let quarantining = self.master_service.quarantines().active(rx);
+
+        // Get requests are only grouped if they are served; Master rejects them otherwise.
+        let gets = self
+            .master_service
+            .serves(&wireformat::OpCode::SandstormGetRpc);
+
+        for request in requests.into_iter() {
             // Set the destination ip address on the response IP header.
             let ip = request.deparse_header(common::IP_HDR_LEN);
             self.resp_ip_header.set_dst(ip.get_header().src());
@@ -656,30 +865,101 @@ where
                 response
                     .get_mut_header()
                     .set_dst_port(request.get_header().src_port());
+                rpc::stamp_response(&request, &mut response, rx);
 
                 if parse_rpc_service(&request) == wireformat::Service::MasterService {
                     // The request is for Master, get it's opcode, and call into Master.
                     let opcode = parse_rpc_opcode(&request);
-                    if !FAST_PATH {
-                        match self.master_service.dispatch(opcode, request, response) {
-                            Ok(task) => {
-                                self.scheduler.enqueue(task);
+                    let tenant = parse_rpc_tenant(&request);
+                    crash::dispatched(&opcode, request.get_payload());
+
+                    // Get requests are grouped by the tenant and table they are for.
+                    let group = match opcode {
+                        wireformat::OpCode::SandstormGetRpc
+                            if GROUP_GETS && !FAST_PATH && !draining && !quarantining && gets =>
+                        {
+                            parse_get_tenant_table(&request)
+                        }
+
+                        _ => None,
+                    };
+
+                    if quarantining
+                        && tenant.map_or(false, |t| self.master_service.quarantined(t, rx))
+                    {
+                        // Requests from quarantined tenants are refused right away.
+                        match self
+                            .master_service
+                            .reject_quarantined_native(opcode, request, response)
+                        {
+                            Ok((req, res)) => {
+                                rpc::record_run(self.master_service.runs(), &req, &res);
+                                req.free_packet();
+                                native_responses.push(rpc::fixup_response(res));
                             }
 
                             Err((req, res)) => {
-                                // Master returned an error. The allocated request and response packets
-                                // need to be freed up.
                                 ignore_packets.push(req);
                                 ignore_packets.push(res);
                             }
                         }
+                    } else if opcode == wireformat::OpCode::SandstormEchoRpc && !draining {
+                        // Echo requests are answered right away, without involving Master.
+                        match self.service_echo(request, response) {
+                            Ok(res) => native_responses.push(res),
+
+                            Err((req, res)) => {
+                                ignore_packets.push(req);
+                                ignore_packets.push(res);
+                            }
+                        }
+                    } else if let Some(key) = group {
+                        if let Some(index) = group_insert(&mut groups, key, (request, response)) {
+                            order.push(Slot::Group(index));
+                        }
+                    } else if !FAST_PATH {
+                        order.push(Slot::One((opcode, tenant, request, response)));
                     } else {
                         match opcode {
                             wireformat::OpCode::SandstormInvokeRpc => {
                                 // The request is for invoke. Dispatch RPC to its handler.
                                 match self.master_service.dispatch_invoke(request, response) {
                                     Ok(task) => {
-                                        self.scheduler.enqueue(task);
+                                        self.enqueue(tenant, task);
+                                    }
+
+                                    Err((req, res)) => {
+                                        // Master returned an error. The allocated request and response packets
+                                        // need to be freed up.
+                                        ignore_packets.push(req);
+                                        ignore_packets.push(res);
+                                    }
+                                }
+                            }
+
+                            wireformat::OpCode::SandstormRoutedInvokeRpc => {
+                                // The request is for an invoke, but the extension is yet to be
+                                // picked. Master checks whether it can be served, and routes it.
+                                match self.master_service.dispatch(opcode, request, response) {
+                                    Ok(task) => {
+                                        self.enqueue(tenant, task);
+                                    }
+
+                                    Err((req, res)) => {
+                                        // Master returned an error. The allocated request and response packets
+                                        // need to be freed up.
+                                        ignore_packets.push(req);
+                                        ignore_packets.push(res);
+                                    }
+                                }
+                            }
+
+                            wireformat::OpCode::SandstormScanRpc => {
+                                // Scans can read many records, so they are scheduled as tasks
+                                // that yield in between batches instead of serviced right away.
+                                match self.master_service.dispatch(opcode, request, response) {
+                                    Ok(task) => {
+                                        self.enqueue(tenant, task);
                                     }
 
                                     Err((req, res)) => {
@@ -693,23 +973,46 @@ where
 
                             wireformat::OpCode::SandstormGetRpc
                             | wireformat::OpCode::SandstormPutRpc
-                            | wireformat::OpCode::SandstormMultiGetRpc => {
+                            | wireformat::OpCode::SandstormMultiGetRpc
+                            | wireformat::OpCode::SandstormListExtRpc
+                            | wireformat::OpCode::SandstormDrainRpc
+                            | wireformat::OpCode::SandstormTableAccessRpc
+                            | wireformat::OpCode::SandstormRunStatsRpc
+                            | wireformat::OpCode::SandstormMergeRpc
+                            | wireformat::OpCode::SandstormSetMergeRpc
+                            | wireformat::OpCode::SandstormSetRouteRpc
+                            | wireformat::OpCode::SandstormAuditRpc
+                            | wireformat::OpCode::SandstormDumpRpc
+                            | wireformat::OpCode::SandstormMultiPutRpc
+                            | wireformat::OpCode::SandstormWriteStatsRpc
+                            | wireformat::OpCode::SandstormCoreStatsRpc
+                            | wireformat::OpCode::SandstormLatencyStatsRpc
+                            | wireformat::OpCode::SandstormQuarantineRpc
+                            | wireformat::OpCode::SandstormCreateTableRpc
+                            | wireformat::OpCode::SandstormFetchResultRpc
+                            | wireformat::OpCode::SandstormDeleteRpc
+                            | wireformat::OpCode::SandstormCasRpc
+                            | wireformat::OpCode::SandstormDropTableRpc => {
                                 // The request is native. Service it right away.
                                 match self
                                     .master_service
                                     .service_native(opcode, request, response)
                                 {
                                     Ok((req, res)) => {
+                                        rpc::record_run(self.master_service.runs(), &req, &res);
+                                        self.master_service.observe(&req, &res, rx);
+
                                         // Free request packet.
                                         req.free_packet();
 
                                         // Push response packet on the local queue of responses that are ready to be sent out.
-                                        native_responses.push(rpc::fixup_header_length_fields(res));
+                                        native_responses.push(rpc::fixup_response(res));
                                     }
 
                                     Err((req, res)) => {
                                         // Master returned an error. The allocated request and response packets
                                         // need to be freed up.
+                                        self.master_service.malformed(&req, rx);
                                         ignore_packets.push(req);
                                         ignore_packets.push(res);
                                     }
@@ -734,6 +1037,10 @@ where
             }
         }
 
+        // Dispatch requests handed to Master, and get() requests that were grouped by tenant and
+        // table, in the order they arrived in.
+        self.dispatch_burst(in_order(order, groups), &mut ignore_packets);
+
         // Enqueue completed native resps on to scheduler's responses queue
         self.scheduler.append_resps(&mut native_responses);
 
@@ -741,6 +1048,64 @@ where
         self.free_packets(ignore_packets);
     }
 
+    /// This method responds to an echo() RPC with the rate at which this server's cycle counter
+    /// ticks, allowing clients to convert the time-stamps on responses into seconds.
+    ///
+    /// # Arguments
+    ///
+    /// * `request`:  The echo() request, parsed upto it's UDP header.
+    /// * `response`: The response allocated for the request, parsed upto it's UDP header.
+    ///
+    /// # Return
+    ///
+    /// The response parsed upto it's IP header and ready to be sent out, or the request and
+    /// response if the request was malformed. The request is freed on success.
+    fn service_echo(
+        &self,
+        request: Packet<UdpHeader, EmptyMetadata>,
+        response: Packet<UdpHeader, EmptyMetadata>,
+    ) -> Result<
+        Packet<IpHeader, EmptyMetadata>,
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+    > {
+        if request.get_payload().len() < size_of::<wireformat::EchoRequest>() {
+            return Err((request, response));
+        }
+
+        let request = request.parse_header::<wireformat::EchoRequest>();
+        let (tenant, id, stamp) = {
+            let hdr = request.get_header();
+            (
+                hdr.common_header.tenant(),
+                hdr.common_header.id(),
+                hdr.common_header.stamp(),
+            )
+        };
+        request
+            .deparse_header(common::PACKET_UDP_LEN as usize)
+            .free_packet();
+
+        let limits = self.master_service.limits();
+        let response = response
+            .push_header(&wireformat::EchoResponse::new(
+                id,
+                stamp,
+                tenant,
+                cycles::cycles_per_second(),
+                self.master_service.opcodes(),
+                limits.max_key_len() as u32,
+                limits.max_value_len() as u32,
+            ))
+            .expect("Failed to push EchoResponse");
+
+        Ok(rpc::fixup_response(
+            response.deparse_header(common::PACKET_UDP_LEN as usize),
+        ))
+    }
+
     /// This method polls the dispatchers network port for any received packets,
     /// dispatches them to the appropriate service, and sends out responses over
     /// the network port.
@@ -758,6 +1123,20 @@ where
             self.try_send_packets(responses);
         }
 
+        // Work deferred by the tasks whose responses were just sent out runs in the background.
+        for work in self.scheduler.deferred() {
+            let tenant = work.tenant;
+            if let Some(task) = self.master_service.defer(work) {
+                self.enqueue(Some(tenant), task);
+            }
+        }
+
+        // Requests that failed on the scheduler count against the tenants that sent them.
+        let now = cycles::rdtsc();
+        for (tenant, signal) in self.scheduler.signals() {
+            self.master_service.signal(tenant, signal, now);
+        }
+
         // Next, try to receive packets from the network.
         if let Some(packets) = self.try_receive_packets() {
             #[cfg(feature = "dispatch")]
@@ -817,6 +1196,15 @@ where
         let exec = cycles::rdtsc() - start;
 
         self.time += exec;
+
+        // Back off if neither this poll nor the scheduler had anything to do. The pause is left
+        // out of the time the dispatcher ran for. A poll that found packets only resets the
+        // count of empty polls, and only if it was not already zero.
+        if _count == 0 && self.backoff.enabled() && self.scheduler.idle() {
+            self.backoff.empty();
+        } else {
+            self.backoff.busy();
+        }
         #[cfg(feature = "dispatch")]
         self.cycle_counter.poll.total_cycles(exec, _count);
 
@@ -886,9 +1274,143 @@ where
     T: PacketRx + PacketTx + Display + Clone + 'static,
 {
     fn drop(&mut self) {
+        info!(
+            "Dispatcher {} grouped {} ungrouped {} requests",
+            self.id, self.grouped, self.ungrouped
+        );
+
         let responses = self.scheduler.responses();
         if responses.len() > 0 {
             self.try_send_packets(responses);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{group_insert, in_order, Slot};
+
+    use super::super::master::Master;
+    use super::super::wireformat::RpcStatus;
+
+    use std::mem::transmute;
+
+    // Tests that a burst of requests for different tables is grouped without any request ending
+    // up in the group of a different table.
+    #[test]
+    fn test_group_insert_mixed() {
+        let burst: Vec<((u32, u64), usize)> = vec![
+            ((1, 1), 0),
+            ((1, 2), 1),
+            ((2, 1), 2),
+            ((1, 1), 3),
+            ((2, 1), 4),
+            ((1, 1), 5),
+        ];
+
+        let mut groups = Vec::new();
+        for &(key, id) in burst.iter() {
+            group_insert(&mut groups, key, (key, id));
+        }
+
+        assert_eq!(3, groups.len());
+        for &(ref key, ref members) in groups.iter() {
+            for &(k, _) in members.iter() {
+                assert_eq!(*key, k);
+            }
+        }
+
+        assert_eq!(vec![((1, 1), 0), ((1, 1), 3), ((1, 1), 5)], groups[0].1);
+        assert_eq!(vec![((1, 2), 1)], groups[1].1);
+        assert_eq!(vec![((2, 1), 2), ((2, 1), 4)], groups[2].1);
+    }
+
+    // Tests that a burst for a single table forms a single group.
+    #[test]
+    fn test_group_insert_single() {
+        let mut groups = Vec::new();
+        for id in 0..32 {
+            group_insert(&mut groups, (1u32, 1u64), id);
+        }
+
+        assert_eq!(1, groups.len());
+        assert_eq!((0..32).collect::<Vec<_>>(), groups[0].1);
+    }
+
+    // Tests that a burst routed through Master is dispatched with every request that isn't
+    // grouped in it's place, and every group at the place of it's first member with it's members
+    // in arrival order, and that each request gets back the object it asked for.
+    #[test]
+    fn test_dispatch_order_master() {
+        let master = Master::new();
+        master.fill_test(1, 1, 100);
+        master.fill_test(2, 1, 100);
+
+        // Each request is (arrival, tenant, table, object). Requests with a group key are
+        // grouped gets; the rest are dispatched on their own.
+        let burst = vec![
+            (Some((1, 1)), (0, 1, 1, 5)),
+            (None, (1, 2, 1, 9)),
+            (Some((2, 1)), (2, 2, 1, 7)),
+            (Some((1, 1)), (3, 1, 1, 11)),
+            (Some((1, 2)), (4, 1, 2, 3)),
+            (None, (5, 1, 1, 13)),
+            (Some((1, 1)), (6, 1, 1, 17)),
+            (Some((2, 1)), (7, 2, 1, 19)),
+        ];
+
+        let mut groups = Vec::new();
+        let mut order = Vec::new();
+        for (group, request) in burst.into_iter() {
+            match group {
+                Some(key) => {
+                    if let Some(index) = group_insert(&mut groups, key, request) {
+                        order.push(Slot::Group(index));
+                    }
+                }
+
+                None => order.push(Slot::One(request)),
+            }
+        }
+
+        // Route the laid out burst through Master, resolving each group's table once.
+        let key = |object: u32| {
+            let temp: [u8; 4] = unsafe { transmute(object.to_le()) };
+            let mut key = vec![0; 30];
+            key[0..4].copy_from_slice(&temp);
+            key
+        };
+        let mut responses = Vec::new();
+        for slot in in_order(order, groups).into_iter() {
+            match slot {
+                Slot::One((arrival, tenant, table, object)) => {
+                    let value = master.get_value(tenant, table, &key(object));
+                    responses.push((arrival, value));
+                }
+
+                Slot::Group(((tenant, table), members)) => {
+                    let resolved = master.resolve_table(tenant, table);
+                    for (arrival, _, _, object) in members.into_iter() {
+                        let value = master.get_value_resolved(resolved.clone(), &key(object));
+                        responses.push((arrival, value));
+                    }
+                }
+            }
+        }
+
+        let arrivals: Vec<usize> = responses.iter().map(|&(arrival, _)| arrival).collect();
+        assert_eq!(vec![0, 3, 6, 1, 2, 7, 4, 5], arrivals);
+
+        let objects = [5, 9, 7, 11, 0, 13, 17, 19];
+        for (arrival, value) in responses.into_iter() {
+            if arrival == 4 {
+                assert_eq!(Err(RpcStatus::StatusTableDoesNotExist), value);
+                continue;
+            }
+
+            let value = value.expect("Failed to get object.");
+            assert_eq!(100, value.len());
+            assert_eq!(&key(objects[arrival])[0..4], &value[0..4]);
+        }
+    }
+}
@@ -0,0 +1,224 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use super::sched::RoundRobin;
+
+use spin::{Mutex, RwLock};
+
+/// A hook run once when the server shuts down, for example to flush a journal.
+pub type ShutdownHook = Box<Fn() + Send + Sync>;
+
+/// Tracks a graceful shutdown of the server. Once a drain starts, new requests are rejected
+/// with StatusServerDraining while tasks that were already running are allowed to complete.
+/// Once they have (or a deadline passes), shutdown() runs every registered hook exactly once.
+pub struct Drain {
+    // Set once a drain has started. Never cleared.
+    draining: AtomicBool,
+
+    // Set once shutdown() has run the hooks.
+    shutdown: AtomicBool,
+
+    // Hooks to be run on shutdown, in the order they were registered.
+    hooks: Mutex<Vec<ShutdownHook>>,
+
+    // The number of tasks remaining on each core, as last reported by the server.
+    progress: RwLock<Vec<u32>>,
+}
+
+impl Drain {
+    /// Creates a Drain that has not started.
+    pub fn new() -> Drain {
+        Drain {
+            draining: AtomicBool::new(false),
+            shutdown: AtomicBool::new(false),
+            hooks: Mutex::new(Vec::new()),
+            progress: RwLock::new(Vec::new()),
+        }
+    }
+
+    /// Starts draining.
+    ///
+    /// # Return
+    ///
+    /// True if this call started the drain, false if it was already underway.
+    pub fn start(&self) -> bool {
+        !self.draining.swap(true, Ordering::SeqCst)
+    }
+
+    /// Returns true if a drain has started.
+    #[inline]
+    pub fn draining(&self) -> bool {
+        self.draining.load(Ordering::Relaxed)
+    }
+
+    /// Records the number of tasks remaining on each core, so that it can be reported on
+    /// responses to drain() RPCs.
+    ///
+    /// # Arguments
+    ///
+    /// * `remaining`: The number of tasks remaining on each core.
+    pub fn set_progress(&self, remaining: Vec<u32>) {
+        *self.progress.write() = remaining;
+    }
+
+    /// Returns the number of tasks remaining on each core, as last recorded by set_progress().
+    pub fn progress(&self) -> Vec<u32> {
+        self.progress.read().clone()
+    }
+
+    /// Registers a hook to be run on shutdown. Hooks registered after shutdown() has run are
+    /// never run.
+    ///
+    /// # Arguments
+    ///
+    /// * `hook`: The hook to be run.
+    pub fn on_shutdown(&self, hook: ShutdownHook) {
+        self.hooks.lock().push(hook);
+    }
+
+    /// Runs every registered hook. Only the first call runs them; later calls do nothing.
+    ///
+    /// # Return
+    ///
+    /// The number of hooks that were run by this call.
+    pub fn shutdown(&self) -> usize {
+        if self.shutdown.swap(true, Ordering::SeqCst) {
+            return 0;
+        }
+
+        let hooks: Vec<ShutdownHook> = self.hooks.lock().drain(..).collect();
+        for hook in hooks.iter() {
+            hook();
+        }
+
+        hooks.len()
+    }
+
+    /// Checks on the progress of a drain across a set of schedulers, and shuts down once every
+    /// scheduler has drained or the deadline has passed. Schedulers that are not draining yet
+    /// are told to, so this must be called periodically once a drain starts.
+    ///
+    /// # Arguments
+    ///
+    /// * `scheds`:  The schedulers being drained.
+    /// * `expired`: True if the drain's deadline has passed. Tasks still on a scheduler that has
+    ///              not drained are abandoned.
+    ///
+    /// # Return
+    ///
+    /// None while the drain is underway. Once every scheduler has drained or the deadline has
+    /// passed, the number of tasks that were abandoned.
+    pub fn check<'a, I>(&self, scheds: I, expired: bool) -> Option<usize>
+    where
+        I: IntoIterator<Item = &'a RoundRobin>,
+    {
+        let mut done = true;
+        let mut remaining = Vec::new();
+        for sched in scheds.into_iter() {
+            sched.drain();
+            if sched.drained() {
+                remaining.push(0);
+            } else {
+                done = false;
+                remaining.push(sched.pending() as u32);
+            }
+        }
+
+        let abandoned = remaining.iter().map(|r| *r as usize).sum();
+        self.set_progress(remaining);
+        if !done && !expired {
+            return None;
+        }
+
+        self.shutdown();
+        Some(abandoned)
+    }
+}
+
+// This module contains unit tests for Drain.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_drain_start() {
+        let drain = Drain::new();
+        assert!(!drain.draining());
+        assert!(drain.start());
+        assert!(drain.draining());
+        assert!(!drain.start());
+        assert!(drain.draining());
+    }
+
+    #[test]
+    fn test_drain_progress() {
+        let drain = Drain::new();
+        assert!(drain.progress().is_empty());
+        drain.set_progress(vec![3, 0, 1]);
+        assert_eq!(vec![3, 0, 1], drain.progress());
+    }
+
+    // Tests that shutdown hooks run in order, and exactly once.
+    #[test]
+    fn test_drain_shutdown_once() {
+        let drain = Drain::new();
+        let runs = Arc::new(AtomicUsize::new(0));
+        for i in 0..3 {
+            let runs = Arc::clone(&runs);
+            drain.on_shutdown(Box::new(move || {
+                assert_eq!(i, runs.fetch_add(1, Ordering::SeqCst));
+            }));
+        }
+
+        assert_eq!(3, drain.shutdown());
+        assert_eq!(0, drain.shutdown());
+        assert_eq!(3, runs.load(Ordering::SeqCst));
+
+        // Hooks registered too late are dropped.
+        let late = Arc::clone(&runs);
+        drain.on_shutdown(Box::new(move || {
+            late.fetch_add(1, Ordering::SeqCst);
+        }));
+        assert_eq!(0, drain.shutdown());
+        assert_eq!(3, runs.load(Ordering::SeqCst));
+    }
+
+    // Tests that checking on a drain tells every scheduler to drain, and that the drain shuts
+    // down once it's deadline passes even if a scheduler has not drained.
+    #[test]
+    fn test_drain_check_expired() {
+        let drain = Drain::new();
+        let runs = Arc::new(AtomicUsize::new(0));
+        let hook = Arc::clone(&runs);
+        drain.on_shutdown(Box::new(move || {
+            hook.fetch_add(1, Ordering::SeqCst);
+        }));
+
+        let scheds = vec![RoundRobin::new(0, 0), RoundRobin::new(1, 1)];
+        assert_eq!(None, drain.check(scheds.iter(), false));
+        assert!(scheds.iter().all(|s| s.draining() && !s.drained()));
+        assert_eq!(vec![0, 0], drain.progress());
+        assert_eq!(0, runs.load(Ordering::SeqCst));
+
+        assert_eq!(Some(0), drain.check(scheds.iter(), true));
+        assert_eq!(Some(0), drain.check(scheds.iter(), true));
+        assert_eq!(1, runs.load(Ordering::SeqCst));
+    }
+}
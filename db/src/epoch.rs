@@ -0,0 +1,242 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Epochs of the tenants and tables on a server. Truncating or dropping a table moves the table
+//! and it's tenant to a new epoch, and removing a tenant moves the tenant and the server's tenant
+//! namespace to one. A response that failed because a key, table or tenant does not exist
+//! carries the epoch of the scope it was looked up in on it's header; the tenant's for
+//! StatusObjectDoesNotExist and StatusTableDoesNotExist, and the server's for
+//! StatusTenantDoesNotExist. The header does not say which table a request named, so a key is
+//! looked up in the tenant's epoch rather than it's table's. A client retrying the request can
+//! then tell something that was destroyed in the meantime, and will not come back, from
+//! something that doesn't exist yet.
+//!
+//! Epochs are drawn off a single clock per server, so that a tenant or table created again after
+//! being destroyed starts out at a later epoch than the one before it ever got to. The clock
+//! starts at 1, leaving an epoch of 0 on a response to mean that it carries none.
+
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use hashbrown::HashMap;
+use spin::RwLock;
+
+use super::tenant::Tenant;
+use super::wireformat::RpcStatus;
+
+use sandstorm::common::TenantId;
+
+/// The epochs a write_stats() RPC reports for a table. Refer to rpc::encode_epochs().
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct EpochStats {
+    /// The epoch of the server's tenant namespace.
+    pub server: u64,
+
+    /// The epoch of the tenant owning the table.
+    pub tenant: u64,
+
+    /// The epoch of the table.
+    pub table: u64,
+}
+
+/// The epochs of a server, shared by all of it's cores.
+pub struct Epochs {
+    // The clock epochs are drawn off. Advanced by every destructive operation.
+    clock: AtomicU64,
+
+    // The epoch of the tenant namespace; the clock when a tenant was last removed.
+    server: AtomicU64,
+
+    // Every tenant on the server, so that the epoch of the tenant a response is destined for can
+    // be looked up on any core.
+    tenants: RwLock<HashMap<TenantId, Arc<Tenant>>>,
+}
+
+impl Epochs {
+    /// Returns the epochs of a server without any tenants.
+    pub fn new() -> Epochs {
+        Epochs {
+            clock: AtomicU64::new(1),
+            server: AtomicU64::new(1),
+            tenants: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the epoch the clock is at.
+    pub fn now(&self) -> u64 {
+        self.clock.load(Ordering::Relaxed)
+    }
+
+    /// Advances the clock, and returns the epoch it advanced to. Scopes that a destructive
+    /// operation destroyed something in move to this epoch.
+    pub fn advance(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// Returns the epoch of the server's tenant namespace.
+    pub fn server(&self) -> u64 {
+        self.server.load(Ordering::Relaxed)
+    }
+
+    /// Returns the epoch of a tenant, or None if the tenant isn't on the server.
+    pub fn tenant(&self, tenant_id: TenantId) -> Option<u64> {
+        self.tenants
+            .read()
+            .get(&tenant_id)
+            .map(|tenant| tenant.epoch())
+    }
+
+    /// Adds a tenant to the server, moving it and the tables it already has to the epoch the
+    /// clock is at. Tables added to the tenant from here on start out at it.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`: The tenant. Replaces any tenant with the same identifier.
+    pub fn register(&self, tenant: &Arc<Tenant>) {
+        tenant.start_epoch(self.now());
+        self.tenants.write().insert(tenant.id(), Arc::clone(tenant));
+    }
+
+    /// Removes a tenant from the server, moving it and the tenant namespace to a new epoch.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant_id`: The identifier of the tenant that was removed.
+    pub fn remove(&self, tenant_id: TenantId) {
+        let epoch = self.advance();
+        if let Some(tenant) = self.tenants.write().remove(&tenant_id) {
+            tenant.set_epoch(epoch);
+        }
+        self.server.store(epoch, Ordering::Relaxed);
+    }
+
+    /// Returns the epoch a response should carry.
+    ///
+    /// # Arguments
+    ///
+    /// * `status`:    The status on the response.
+    /// * `tenant_id`: The tenant the response is destined for.
+    ///
+    /// # Return
+    ///
+    /// The tenant's epoch if the response failed with StatusObjectDoesNotExist or
+    /// StatusTableDoesNotExist and the tenant is on the server, the server's if it failed with
+    /// StatusTenantDoesNotExist. None otherwise.
+    pub fn scope(&self, status: &RpcStatus, tenant_id: TenantId) -> Option<u64> {
+        match *status {
+            RpcStatus::StatusObjectDoesNotExist => self.tenant(tenant_id),
+            RpcStatus::StatusTableDoesNotExist => self.tenant(tenant_id),
+            RpcStatus::StatusTenantDoesNotExist => Some(self.server()),
+            _ => None,
+        }
+    }
+}
+
+thread_local! {
+    // The epochs of the server this thread sends out responses for. Set once by the scheduler's
+    // thread when it starts up, and read by rpc::fixup_response().
+    static CORE_EPOCHS: RefCell<Option<Arc<Epochs>>> = RefCell::new(None);
+}
+
+/// Sets the epochs that responses handed off on the calling thread carry. Threads that never
+/// call this put no epochs on responses.
+///
+/// # Arguments
+///
+/// * `epochs`: The server's epochs. Refer to Master::epochs().
+pub fn install(epochs: Arc<Epochs>) {
+    CORE_EPOCHS.with(|e| *e.borrow_mut() = Some(epochs));
+}
+
+/// Returns the epoch a response handed off on the calling thread should carry, if epochs were
+/// installed. Refer to Epochs::scope().
+#[inline]
+pub fn lookup(status: &RpcStatus, tenant_id: TenantId) -> Option<u64> {
+    CORE_EPOCHS.with(|e| match *e.borrow() {
+        Some(ref epochs) => epochs.scope(status, tenant_id),
+        None => None,
+    })
+}
+
+// This module contains unit tests for Epochs.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Tests that responses carry the epoch of the scope they failed to find something in, and
+    // that it moves forward when something in the scope is destroyed.
+    #[test]
+    fn test_epochs_scope() {
+        let epochs = Epochs::new();
+        let tenant = Arc::new(Tenant::new(1));
+        epochs.register(&tenant);
+
+        let key = RpcStatus::StatusObjectDoesNotExist;
+        let table = RpcStatus::StatusTableDoesNotExist;
+        let missing = RpcStatus::StatusTenantDoesNotExist;
+        assert_eq!(Some(1), epochs.scope(&key, 1));
+        assert_eq!(Some(1), epochs.scope(&table, 1));
+        assert_eq!(None, epochs.scope(&table, 2));
+        assert_eq!(Some(1), epochs.scope(&missing, 2));
+        assert_eq!(None, epochs.scope(&RpcStatus::StatusOk, 1));
+
+        tenant.set_epoch(epochs.advance());
+        assert_eq!(Some(2), epochs.scope(&key, 1));
+        assert_eq!(Some(2), epochs.scope(&table, 1));
+        assert_eq!(Some(1), epochs.scope(&missing, 1));
+
+        epochs.remove(1);
+        assert_eq!((3, 3), (tenant.epoch(), epochs.server()));
+        assert_eq!(None, epochs.scope(&table, 1));
+        assert_eq!(Some(3), epochs.scope(&missing, 1));
+    }
+
+    // Tests that a tenant added again after it was removed starts out past every epoch the
+    // removed one was at, along with the tables added to it.
+    #[test]
+    fn test_epochs_recreate() {
+        let epochs = Epochs::new();
+        let tenant = Arc::new(Tenant::new(1));
+        epochs.register(&tenant);
+        tenant.set_epoch(epochs.advance());
+        epochs.advance();
+        assert_eq!(Some(2), epochs.tenant(1));
+
+        epochs.remove(1);
+        let tenant = Tenant::new(1);
+        tenant.create_table(1);
+        let tenant = Arc::new(tenant);
+        epochs.register(&tenant);
+        assert_eq!(Some(4), epochs.tenant(1));
+        tenant.create_table(2);
+        assert_eq!(4, tenant.get_table(1).unwrap().epoch());
+        assert_eq!(4, tenant.get_table(2).unwrap().epoch());
+    }
+
+    // Tests that threads that did not install epochs put none on responses.
+    #[test]
+    fn test_epochs_install() {
+        let missing = RpcStatus::StatusTenantDoesNotExist;
+        assert_eq!(None, lookup(&missing, 1));
+
+        let epochs = Arc::new(Epochs::new());
+        epochs.advance();
+        install(Arc::clone(&epochs));
+        assert_eq!(Some(1), lookup(&missing, 1));
+        epochs.remove(1);
+        assert_eq!(Some(3), lookup(&missing, 1));
+    }
+}
@@ -0,0 +1,364 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Cache-mode tables. A table given a capacity target holds atmost that many bytes of objects
+//! (or that many objects), and evicts objects to stay under it instead of growing. Every write
+//! that takes the table over it's target evicts a small batch of objects before it returns, so
+//! there is no background sweeper, and puts are never refused for the table's size.
+//!
+//! Objects are chosen by CLOCK, an approximation of LRU: every slot carries a referenced bit,
+//! set when the object is written (and when it is read, under EvictPolicy::Read). An eviction
+//! walks the table's buckets in order from where the last one stopped, clearing the bits it
+//! finds set, and evicts the objects whose bit was already clear; an object survives atleast
+//! one full pass of the walk after it was last touched. There is no global ordering of objects,
+//! so the only cost on the get() path is a single store to the bit, and tables that are not in
+//! cache mode skip even that.
+//!
+//! An evicted object is removed from the index the way a delete() would remove it; it's memory
+//! is released once the last reference to it (ex: a response still being sent out) is dropped,
+//! and a read of it's key misses. Tenants have no memory quota of their own yet, so the targets
+//! on a tenant's tables are what bound the memory it's cache tables take.
+
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::wireformat::{CACHE_EVICT_NONE, CACHE_EVICT_READ, CACHE_EVICT_WRITTEN};
+
+/// The most objects evicted by a single write that takes a table over it's target. Writes add
+/// atmost one object each, so a table converges back to it's target after a few writes even if
+/// some of the objects evicted are smaller than the ones written.
+pub const EVICT_BATCH: usize = 16;
+
+/// Which accesses keep an object on a cache-mode table.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EvictPolicy {
+    /// Evict the least recently written objects. Reads do not touch objects at all.
+    Written,
+
+    /// Evict the least recently read or written objects.
+    Read,
+}
+
+impl EvictPolicy {
+    /// Parses a policy named "written" or "read".
+    pub fn parse(name: &str) -> Option<EvictPolicy> {
+        match name {
+            "written" => Some(EvictPolicy::Written),
+            "read" => Some(EvictPolicy::Read),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for EvictPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EvictPolicy::Written => write!(f, "written"),
+            EvictPolicy::Read => write!(f, "read"),
+        }
+    }
+}
+
+/// The capacity target of a cache-mode table, and how objects are evicted to stay under it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CacheTarget {
+    /// The most bytes of objects the table holds, counting each object's key and metadata. 0
+    /// does not bound the bytes.
+    pub max_bytes: u64,
+
+    /// The most objects the table holds. 0 does not bound the number of objects.
+    pub max_objects: u64,
+
+    /// Which accesses keep an object on the table.
+    pub policy: EvictPolicy,
+}
+
+impl CacheTarget {
+    /// Returns a target, or None if it bounds neither the bytes nor the number of objects.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_bytes`:   The most bytes of objects the table holds. 0 for no bound.
+    /// * `max_objects`: The most objects the table holds. 0 for no bound.
+    /// * `policy`:      Which accesses keep an object on the table.
+    pub fn new(max_bytes: u64, max_objects: u64, policy: EvictPolicy) -> Option<CacheTarget> {
+        match max_bytes == 0 && max_objects == 0 {
+            true => None,
+            false => Some(CacheTarget {
+                max_bytes: max_bytes,
+                max_objects: max_objects,
+                policy: policy,
+            }),
+        }
+    }
+
+    /// Returns the target carried on a create_table() request, None if the request does not
+    /// ask for a cache-mode table, or an error if the target on it is malformed.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_bytes`:   The bytes bound on the request.
+    /// * `max_objects`: The objects bound on the request.
+    /// * `evict`:       CACHE_EVICT_NONE, CACHE_EVICT_WRITTEN or CACHE_EVICT_READ.
+    pub fn from_wire(
+        max_bytes: u64,
+        max_objects: u64,
+        evict: u8,
+    ) -> Result<Option<CacheTarget>, ()> {
+        let policy = match evict {
+            CACHE_EVICT_NONE if max_bytes == 0 && max_objects == 0 => return Ok(None),
+            CACHE_EVICT_WRITTEN => EvictPolicy::Written,
+            CACHE_EVICT_READ => EvictPolicy::Read,
+            _ => return Err(()),
+        };
+        CacheTarget::new(max_bytes, max_objects, policy)
+            .map(Some)
+            .ok_or(())
+    }
+
+    /// Returns the bounds and policy as they are carried on a create_table() request.
+    pub fn to_wire(target: Option<CacheTarget>) -> (u64, u64, u8) {
+        match target {
+            None => (0, 0, CACHE_EVICT_NONE),
+            Some(t) => (
+                t.max_bytes,
+                t.max_objects,
+                match t.policy {
+                    EvictPolicy::Written => CACHE_EVICT_WRITTEN,
+                    EvictPolicy::Read => CACHE_EVICT_READ,
+                },
+            ),
+        }
+    }
+}
+
+/// The counters of a cache-mode table, reported by the write_stats() RPC.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CacheStats {
+    /// The table's target.
+    pub target: CacheTarget,
+
+    /// The bytes of objects on the table.
+    pub bytes: u64,
+
+    /// The number of objects on the table.
+    pub objects: u64,
+
+    /// The number of objects evicted off the table.
+    pub evicted: u64,
+
+    /// The bytes of objects evicted off the table.
+    pub evicted_bytes: u64,
+}
+
+// The values `policy` takes on Cache. A table evicts nothing under POLICY_OFF.
+const POLICY_OFF: usize = 0;
+const POLICY_WRITTEN: usize = 1;
+const POLICY_READ: usize = 2;
+
+/// The target and counters of a table, kept on every table so that checking whether it is in
+/// cache mode is a single load. Refer to Table::set_cache().
+pub struct Cache {
+    // POLICY_OFF unless the table is in cache mode, along with the target's bounds.
+    policy: AtomicUsize,
+    max_bytes: AtomicUsize,
+    max_objects: AtomicUsize,
+
+    // The bytes of objects on the table and the number of them. Only kept in cache mode.
+    bytes: AtomicUsize,
+    objects: AtomicUsize,
+
+    // The objects evicted, and the bytes they took.
+    evicted: AtomicUsize,
+    evicted_bytes: AtomicUsize,
+
+    // The bucket the next eviction starts at.
+    hand: AtomicUsize,
+}
+
+impl Default for Cache {
+    fn default() -> Cache {
+        Cache {
+            policy: AtomicUsize::new(POLICY_OFF),
+            max_bytes: AtomicUsize::new(0),
+            max_objects: AtomicUsize::new(0),
+            bytes: AtomicUsize::new(0),
+            objects: AtomicUsize::new(0),
+            evicted: AtomicUsize::new(0),
+            evicted_bytes: AtomicUsize::new(0),
+            hand: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl Cache {
+    /// Returns true if the table is in cache mode.
+    #[inline]
+    pub fn on(&self) -> bool {
+        self.policy.load(Ordering::Relaxed) != POLICY_OFF
+    }
+
+    /// Returns true if reads touch objects on the table.
+    #[inline]
+    pub fn touch_reads(&self) -> bool {
+        self.policy.load(Ordering::Relaxed) == POLICY_READ
+    }
+
+    /// Returns the table's target, None if it is not in cache mode.
+    pub fn target(&self) -> Option<CacheTarget> {
+        let policy = match self.policy.load(Ordering::Relaxed) {
+            POLICY_WRITTEN => EvictPolicy::Written,
+            POLICY_READ => EvictPolicy::Read,
+            _ => return None,
+        };
+
+        Some(CacheTarget {
+            max_bytes: self.max_bytes.load(Ordering::Relaxed) as u64,
+            max_objects: self.max_objects.load(Ordering::Relaxed) as u64,
+            policy: policy,
+        })
+    }
+
+    /// Returns the table's counters, None if it is not in cache mode.
+    pub fn stats(&self) -> Option<CacheStats> {
+        self.target().map(|target| CacheStats {
+            target: target,
+            bytes: self.bytes.load(Ordering::Relaxed) as u64,
+            objects: self.objects.load(Ordering::Relaxed) as u64,
+            evicted: self.evicted.load(Ordering::Relaxed) as u64,
+            evicted_bytes: self.evicted_bytes.load(Ordering::Relaxed) as u64,
+        })
+    }
+
+    // Puts the table in cache mode with a target, or takes it out of cache mode, starting the
+    // count of it's objects at the ones passed in. Eviction counters carry over.
+    pub(crate) fn set(&self, target: Option<CacheTarget>, bytes: usize, objects: usize) {
+        self.policy.store(POLICY_OFF, Ordering::Relaxed);
+        let target = match target {
+            Some(target) => target,
+            None => return,
+        };
+
+        self.max_bytes
+            .store(target.max_bytes as usize, Ordering::Relaxed);
+        self.max_objects
+            .store(target.max_objects as usize, Ordering::Relaxed);
+        self.bytes.store(bytes, Ordering::Relaxed);
+        self.objects.store(objects, Ordering::Relaxed);
+        let policy = match target.policy {
+            EvictPolicy::Written => POLICY_WRITTEN,
+            EvictPolicy::Read => POLICY_READ,
+        };
+        self.policy.store(policy, Ordering::Relaxed);
+    }
+
+    // Counts an object written to the table, replacing one of `old` bytes if `replaced`.
+    pub(crate) fn written(&self, bytes: usize, old: usize, replaced: bool) {
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+        self.bytes.fetch_sub(old, Ordering::Relaxed);
+        if !replaced {
+            self.objects.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    // Counts an object removed from the table, by a delete() or an eviction.
+    pub(crate) fn removed(&self, bytes: usize, evicted: bool) {
+        self.bytes.fetch_sub(bytes, Ordering::Relaxed);
+        self.objects.fetch_sub(1, Ordering::Relaxed);
+        if evicted {
+            self.evicted.fetch_add(1, Ordering::Relaxed);
+            self.evicted_bytes.fetch_add(bytes, Ordering::Relaxed);
+        }
+    }
+
+    // Returns true if the table would still be over it's target with `bytes` bytes of objects
+    // and `objects` objects fewer on it.
+    pub(crate) fn over(&self, bytes: usize, objects: usize) -> bool {
+        let (max_bytes, max_objects) = (
+            self.max_bytes.load(Ordering::Relaxed),
+            self.max_objects.load(Ordering::Relaxed),
+        );
+        let held = self.bytes.load(Ordering::Relaxed).saturating_sub(bytes);
+        let count = self.objects.load(Ordering::Relaxed).saturating_sub(objects);
+
+        self.on()
+            && ((max_bytes > 0 && held > max_bytes) || (max_objects > 0 && count > max_objects))
+    }
+
+    // Returns the bucket an eviction visits next, out of `buckets`, and moves the hand past it.
+    pub(crate) fn advance(&self, buckets: usize) -> usize {
+        self.hand.fetch_add(1, Ordering::Relaxed) % buckets
+    }
+}
+
+// This module contains unit tests for cache targets. Eviction itself is tested along with Table.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Tests that targets must bound something, and go over the wire and back unchanged.
+    #[test]
+    fn test_cache_target_wire() {
+        assert_eq!(None, CacheTarget::new(0, 0, EvictPolicy::Read));
+        assert_eq!(Ok(None), CacheTarget::from_wire(0, 0, CACHE_EVICT_NONE));
+        assert_eq!(Err(()), CacheTarget::from_wire(0, 0, CACHE_EVICT_READ));
+        assert_eq!(Err(()), CacheTarget::from_wire(100, 0, CACHE_EVICT_NONE));
+        assert_eq!(Err(()), CacheTarget::from_wire(100, 0, 7));
+
+        for &policy in [EvictPolicy::Written, EvictPolicy::Read].iter() {
+            let target = CacheTarget::new(1 << 20, 1000, policy);
+            let (max_bytes, max_objects, evict) = CacheTarget::to_wire(target);
+            assert_eq!(
+                Ok(target),
+                CacheTarget::from_wire(max_bytes, max_objects, evict)
+            );
+            assert_eq!(Some(policy), EvictPolicy::parse(&policy.to_string()));
+        }
+        assert_eq!((0, 0, CACHE_EVICT_NONE), CacheTarget::to_wire(None));
+        assert_eq!(None, EvictPolicy::parse("lru"));
+    }
+
+    // Tests that a table is only over it's target once it exceeds one of the bounds it has.
+    #[test]
+    fn test_cache_over() {
+        let cache = Cache::default();
+        assert!(!cache.on() && cache.stats().is_none());
+
+        cache.set(CacheTarget::new(1000, 0, EvictPolicy::Written), 900, 9);
+        assert!(cache.on() && !cache.touch_reads());
+        assert!(!cache.over(0, 0));
+        cache.written(150, 0, false);
+        assert!(cache.over(0, 0));
+        assert!(cache.over(49, 0));
+        assert!(!cache.over(50, 1));
+
+        // Replacing an object only counts the difference.
+        cache.written(100, 150, true);
+        assert!(!cache.over(0, 0));
+
+        cache.set(CacheTarget::new(0, 10, EvictPolicy::Read), 2000, 10);
+        assert!(cache.touch_reads() && !cache.over(0, 0));
+        cache.written(10, 0, false);
+        assert!(cache.over(0, 0) && !cache.over(0, 1));
+        cache.removed(10, true);
+
+        let stats = cache.stats().unwrap();
+        assert_eq!((2000, 10), (stats.bytes, stats.objects));
+        assert_eq!((1, 10), (stats.evicted, stats.evicted_bytes));
+
+        cache.set(None, 0, 0);
+        assert!(!cache.on() && !cache.over(0, 0));
+    }
+}
@@ -0,0 +1,614 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use std::cmp::{max, min};
+use std::collections::VecDeque;
+
+use hashbrown::HashMap;
+
+use sandstorm::common::TenantId;
+
+use super::cycles;
+use super::task::{Task, TaskPriority};
+
+/// The number of tenants that get a queue of their own on a core, if the config does not say
+/// otherwise.
+pub const DEFAULT_MAX_TENANTS: usize = 16;
+
+/// How a scheduler shares it's core between the tenants whose requests it runs.
+#[derive(Clone, Debug)]
+pub struct FairPolicy {
+    /// The microseconds of CPU time each tenant's requests get per round on a core, times the
+    /// tenant's share. 0 runs every task first come first served, off a single queue.
+    pub quantum_us: f64,
+
+    /// The number of tenants that get a queue of their own on a core. Requests from tenants past
+    /// this share a queue with the dispatcher and background tasks.
+    pub max_tenants: usize,
+
+    /// The share of each tenant whose share isn't 1.
+    pub shares: HashMap<TenantId, u32>,
+}
+
+impl FairPolicy {
+    /// Returns true if tenants are scheduled by deficit round-robin.
+    pub fn enabled(&self) -> bool {
+        self.quantum_us > 0.0
+    }
+
+    /// Returns the share of a tenant; 1 unless configured otherwise.
+    pub fn share(&self, tenant: TenantId) -> u32 {
+        self.shares.get(&tenant).map(|share| *share).unwrap_or(1)
+    }
+}
+
+impl Default for FairPolicy {
+    fn default() -> FairPolicy {
+        FairPolicy {
+            quantum_us: 0.0,
+            max_tenants: DEFAULT_MAX_TENANTS,
+            shares: HashMap::new(),
+        }
+    }
+}
+
+/// How long a tenant's tasks waited on a core's run-queue each time they were picked to run.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TenantDelay {
+    /// The number of times one of the tenant's tasks was picked to run.
+    pub picks: u64,
+
+    /// The total time in cycles the tasks waited before being picked.
+    pub total: u64,
+
+    /// The longest time in cycles a task waited before being picked.
+    pub max: u64,
+}
+
+impl TenantDelay {
+    /// Returns the mean time in cycles a task waited before being picked.
+    pub fn mean(&self) -> u64 {
+        match self.picks {
+            0 => 0,
+            picks => self.total / picks,
+        }
+    }
+}
+
+// A task on the run-queue, along with the time-stamp at which it was queued.
+struct Entry {
+    at: u64,
+    task: Box<Task>,
+}
+
+// The tasks of a single tenant, or the shared queue if `tenant` is None.
+struct Flow {
+    tenant: Option<TenantId>,
+    tasks: VecDeque<Entry>,
+
+    // The cycles the flow can still run for this round, and the cycles it gets each round.
+    deficit: i64,
+    quantum: i64,
+}
+
+impl Flow {
+    fn new(tenant: Option<TenantId>, quantum: i64) -> Flow {
+        Flow {
+            tenant: tenant,
+            tasks: VecDeque::new(),
+            deficit: 0,
+            quantum: quantum,
+        }
+    }
+}
+
+/// The run-queue of a scheduler. Under a FairPolicy, request tasks are queued per tenant, and
+/// the tenants take turns running by deficit round-robin; each turn, a tenant runs tasks until
+/// they have used up it's quantum, so that a burst from one tenant delays another's requests by
+/// atmost a quantum. The dispatcher, background tasks, tasks that don't name their tenant and
+/// tasks of tenants past `max_tenants` share a queue that takes a turn like any tenant does.
+///
+/// Tasks are charged for the time they ran after they run. A tenant that overran it's quantum
+/// has the overrun taken off it's next turn, upto a quantum of it, so that every tenant with
+/// tasks waiting runs atleast every other round. Each pick takes time linear in the number of
+/// tenants with tasks waiting.
+///
+/// Without a FairPolicy, every task is on the shared queue, and tasks run first come first served.
+pub struct RunQueue {
+    // The flows with tasks waiting, or running. The shared queue is always first; tenants follow
+    // in the order they started waiting.
+    flows: Vec<Flow>,
+
+    // The flow whose turn it is, and the number of tasks it can still run this turn. The turn
+    // has not yet started if None; it starts with as many tasks as the flow has waiting, so that
+    // no task runs twice in one turn.
+    turn: usize,
+    left: Option<usize>,
+
+    // The number of tasks waiting.
+    len: usize,
+
+    // The quantum in cycles, 0 if tenants aren't scheduled fairly, and the policy it came from.
+    quantum: i64,
+    policy: FairPolicy,
+
+    // The time tasks waited to run, by tenant, and the number of tasks queued on the shared queue
+    // because `max_tenants` tenants already had queues.
+    delays: HashMap<TenantId, TenantDelay>,
+    spilled: u64,
+}
+
+impl RunQueue {
+    /// Returns an empty run-queue.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy`: How the core is shared between tenants.
+    pub fn new(policy: FairPolicy) -> RunQueue {
+        let quantum = policy.quantum_us * cycles::cycles_per_second() as f64 / 1e6;
+        let quantum = match policy.enabled() {
+            true => max(quantum as i64, 1),
+            false => 0,
+        };
+
+        RunQueue {
+            flows: vec![Flow::new(None, quantum)],
+            turn: 0,
+            left: None,
+            len: 0,
+            quantum: quantum,
+            policy: policy,
+            delays: HashMap::new(),
+            spilled: 0,
+        }
+    }
+
+    /// Returns the number of tasks waiting.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns the number of tenants with a queue of their own.
+    pub fn active(&self) -> usize {
+        self.flows.len() - 1
+    }
+
+    /// Returns the number of tasks that were queued on the shared queue because too many tenants
+    /// already had a queue of their own.
+    pub fn spilled(&self) -> u64 {
+        self.spilled
+    }
+
+    /// Returns how long each tenant's tasks waited to run, ordered by tenant.
+    pub fn delays(&self) -> Vec<(TenantId, TenantDelay)> {
+        let mut delays: Vec<(TenantId, TenantDelay)> =
+            self.delays.iter().map(|(t, d)| (*t, *d)).collect();
+        delays.sort_by_key(|&(tenant, _)| tenant);
+        delays
+    }
+
+    /// Queues a task behind every other task of it's tenant.
+    ///
+    /// # Arguments
+    ///
+    /// * `task`: The task.
+    /// * `now`:  The current time-stamp in cycles.
+    pub fn push_back(&mut self, task: Box<Task>, now: u64) {
+        let flow = match self.tenant(&*task) {
+            Some(tenant) => match self.find(tenant) {
+                Some(flow) => flow,
+
+                None if self.active() < self.policy.max_tenants => {
+                    let quantum = self.quantum * self.policy.share(tenant) as i64;
+                    self.flows.push(Flow::new(Some(tenant), quantum));
+                    self.flows.len() - 1
+                }
+
+                None => {
+                    self.spilled += 1;
+                    0
+                }
+            },
+
+            None => 0,
+        };
+
+        self.flows[flow].tasks.push_back(Entry {
+            at: now,
+            task: task,
+        });
+        self.len += 1;
+    }
+
+    /// Picks the next task to run. Without a FairPolicy, this is the task at the head of the
+    /// queue.
+    ///
+    /// # Arguments
+    ///
+    /// * `now`: The current time-stamp in cycles.
+    ///
+    /// # Return
+    ///
+    /// The task, or None if there are no tasks waiting.
+    pub fn pop_front(&mut self, now: u64) -> Option<Box<Task>> {
+        // Tenants give up their queues once they have no tasks waiting.
+        if self.len == 0 {
+            self.flows.truncate(1);
+            self.turn = 0;
+            self.left = None;
+            return None;
+        }
+
+        loop {
+            let (turn, left) = (self.turn, self.left);
+            let left = {
+                let flow = &mut self.flows[turn];
+                let left = match left {
+                    Some(left) => left,
+
+                    // The flow's turn starts. Time it did not use last turn is not carried over.
+                    None => {
+                        flow.deficit = min(flow.deficit, 0) + flow.quantum;
+                        flow.tasks.len()
+                    }
+                };
+
+                if left > 0 && (flow.deficit > 0 || self.quantum == 0) {
+                    if let Some(entry) = flow.tasks.pop_front() {
+                        self.left = Some(left - 1);
+                        self.len -= 1;
+                        self.picked(&entry, now);
+                        return Some(entry.task);
+                    }
+                }
+
+                left
+            };
+
+            // The flow's turn is over. Tenants with no tasks waiting give up their queue; the
+            // shared queue is never given up.
+            if turn > 0 && self.flows[turn].tasks.is_empty() {
+                self.flows.remove(turn);
+            } else if left == 0 || self.quantum > 0 {
+                self.turn += 1;
+            }
+
+            if self.turn >= self.flows.len() {
+                self.turn = 0;
+            }
+            self.left = None;
+        }
+    }
+
+    /// Charges a task's tenant for the time the task just ran.
+    ///
+    /// # Arguments
+    ///
+    /// * `task`:   The task, as returned by pop_front().
+    /// * `cycles`: The time the task ran for, in cycles.
+    pub fn charge(&mut self, task: &Task, cycles: u64) {
+        if self.quantum == 0 {
+            return;
+        }
+
+        let flow = self.tenant(task).and_then(|t| self.find(t)).unwrap_or(0);
+        let flow = &mut self.flows[flow];
+        flow.deficit = max(flow.deficit - cycles as i64, -flow.quantum);
+    }
+
+    /// Removes every task the pushback policy picks from the queue. The remaining tasks keep
+    /// their place.
+    ///
+    /// # Arguments
+    ///
+    /// * `pick`: Returns why a task should be removed, or None if it should stay.
+    ///
+    /// # Return
+    ///
+    /// The removed tasks, along with why each was removed.
+    pub fn extract<T, F>(&mut self, mut pick: F) -> Vec<(T, Box<Task>)>
+    where
+        F: FnMut(&Task) -> Option<T>,
+    {
+        let mut picked = Vec::new();
+        for flow in self.flows.iter_mut() {
+            let mut kept = VecDeque::with_capacity(flow.tasks.len());
+            for entry in flow.tasks.drain(..) {
+                match pick(&*entry.task) {
+                    Some(reason) => picked.push((reason, entry.task)),
+                    None => kept.push_back(entry),
+                }
+            }
+            flow.tasks = kept;
+        }
+
+        self.len -= picked.len();
+        picked
+    }
+
+    /// Removes every task from the queue, those on the shared queue first, and every tenant's in
+    /// the order they were queued in.
+    pub fn drain(&mut self) -> VecDeque<Box<Task>> {
+        let mut tasks = VecDeque::with_capacity(self.len);
+        for flow in self.flows.iter_mut() {
+            tasks.extend(flow.tasks.drain(..).map(|entry| entry.task));
+        }
+
+        self.flows.truncate(1);
+        self.turn = 0;
+        self.left = None;
+        self.len = 0;
+        tasks
+    }
+
+    // Returns the tenant a task is queued under; None if it belongs on the shared queue.
+    fn tenant(&self, task: &Task) -> Option<TenantId> {
+        match (self.quantum, task.priority()) {
+            (0, _) => None,
+            (_, TaskPriority::REQUEST) => task.tenant(),
+            _ => None,
+        }
+    }
+
+    // Returns the index of a tenant's flow, if it has one.
+    fn find(&self, tenant: TenantId) -> Option<usize> {
+        self.flows
+            .iter()
+            .skip(1)
+            .position(|flow| flow.tenant == Some(tenant))
+            .map(|i| i + 1)
+    }
+
+    // Records how long a request task waited to run against it's tenant.
+    fn picked(&mut self, entry: &Entry, now: u64) {
+        if entry.task.priority() != TaskPriority::REQUEST {
+            return;
+        }
+
+        if let Some(tenant) = entry.task.tenant() {
+            let waited = now.saturating_sub(entry.at);
+            let delay = self
+                .delays
+                .entry(tenant)
+                .or_insert_with(TenantDelay::default);
+            delay.picks += 1;
+            delay.total += waited;
+            delay.max = max(delay.max, waited);
+        }
+    }
+}
+
+// This module contains tests for RunQueue.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use super::super::task::TaskState;
+    use super::super::task::TaskState::*;
+
+    use e2d2::common::EmptyMetadata;
+    use e2d2::headers::UdpHeader;
+    use e2d2::interface::Packet;
+
+    // A task that names it's tenant, and completes in one run.
+    struct Job {
+        id: usize,
+        tenant: Option<TenantId>,
+        priority: TaskPriority,
+    }
+
+    impl Task for Job {
+        fn run(&mut self) -> (TaskState, u64) {
+            (COMPLETED, 0)
+        }
+
+        fn state(&self) -> TaskState {
+            INITIALIZED
+        }
+
+        fn time(&self) -> u64 {
+            0
+        }
+
+        fn db_time(&self) -> u64 {
+            0
+        }
+
+        fn priority(&self) -> TaskPriority {
+            self.priority.clone()
+        }
+
+        unsafe fn tear(
+            &mut self,
+        ) -> Option<(
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        )> {
+            None
+        }
+
+        fn set_state(&mut self, _state: TaskState) {}
+
+        fn update_cache(&mut self, _record: &[u8], _keylen: usize) {}
+
+        fn tenant(&self) -> Option<TenantId> {
+            self.tenant
+        }
+    }
+
+    fn job(id: usize, tenant: TenantId) -> Box<Task> {
+        Box::new(Job {
+            id: id,
+            tenant: Some(tenant),
+            priority: TaskPriority::REQUEST,
+        })
+    }
+
+    fn id(task: &Box<Task>) -> usize {
+        unsafe { &*(&**task as *const Task as *const Job) }.id
+    }
+
+    // Returns a policy whose quantum is exactly `quantum` cycles.
+    fn policy(quantum: u64) -> FairPolicy {
+        FairPolicy {
+            quantum_us: quantum as f64 * 1e6 / cycles::cycles_per_second() as f64,
+            ..FairPolicy::default()
+        }
+    }
+
+    // Pops every task, charging each `cost` cycles, and returns their ids in the order they ran.
+    fn run_all(queue: &mut RunQueue, cost: u64) -> Vec<usize> {
+        let mut order = Vec::new();
+        while let Some(task) = queue.pop_front(0) {
+            queue.charge(&*task, cost);
+            order.push(id(&task));
+        }
+        order
+    }
+
+    // Tests that tasks run first come first served without a policy, whatever their tenant.
+    #[test]
+    fn test_fair_disabled() {
+        let mut queue = RunQueue::new(FairPolicy::default());
+        for i in 0..6 {
+            queue.push_back(job(i, (i % 2) as TenantId), 0);
+        }
+        assert_eq!(0, queue.active());
+        assert_eq!(vec![0, 1, 2, 3, 4, 5], run_all(&mut queue, 100));
+        assert_eq!(0, queue.len());
+    }
+
+    // Tests that a tenant with a burst of tasks runs a quantum's worth of them, and then gives
+    // way to the other tenant.
+    #[test]
+    fn test_fair_round_robin() {
+        let mut queue = RunQueue::new(policy(300));
+        for i in 0..10 {
+            queue.push_back(job(i, 1), 0);
+        }
+        queue.push_back(job(10, 2), 0);
+        queue.push_back(job(11, 2), 0);
+        assert_eq!(2, queue.active());
+
+        // Each task costs 100 cycles, so tenant 1 runs 3 tasks per turn.
+        let order = run_all(&mut queue, 100);
+        assert_eq!(vec![0, 1, 2, 10, 11, 3, 4, 5, 6, 7, 8, 9], order);
+        assert_eq!(0, queue.active());
+    }
+
+    // Tests that tenants get turns in proportion to their shares.
+    #[test]
+    fn test_fair_shares() {
+        let mut policy = policy(100);
+        policy.shares.insert(1, 2);
+        let mut queue = RunQueue::new(policy);
+        for i in 0..4 {
+            queue.push_back(job(i, 1), 0);
+            queue.push_back(job(10 + i, 2), 0);
+        }
+
+        assert_eq!(vec![0, 1, 10, 2, 3, 11, 12, 13], run_all(&mut queue, 100));
+    }
+
+    // Tests that a task that overran it's tenant's quantum has the overrun taken off the
+    // tenant's next turn, but by no more than a quantum.
+    #[test]
+    fn test_fair_overrun() {
+        let mut queue = RunQueue::new(policy(100));
+        for i in 0..3 {
+            queue.push_back(job(i, 1), 0);
+            queue.push_back(job(10 + i, 2), 0);
+        }
+
+        let mut order = Vec::new();
+        while let Some(task) = queue.pop_front(0) {
+            let cost = if id(&task) == 0 { 10000 } else { 100 };
+            queue.charge(&*task, cost);
+            order.push(id(&task));
+        }
+        assert_eq!(vec![0, 10, 11, 1, 12, 2], order);
+    }
+
+    // Tests that the dispatcher, background tasks, and tenants past max_tenants share a queue,
+    // which takes turns with the tenants.
+    #[test]
+    fn test_fair_spill() {
+        let mut policy = policy(100);
+        policy.max_tenants = 1;
+        let mut queue = RunQueue::new(policy);
+        queue.push_back(job(0, 1), 0);
+        queue.push_back(job(1, 1), 0);
+        queue.push_back(job(2, 2), 0);
+        queue.push_back(
+            Box::new(Job {
+                id: 3,
+                tenant: Some(1),
+                priority: TaskPriority::DISPATCH,
+            }),
+            0,
+        );
+        assert_eq!((1, 1), (queue.active(), queue.spilled()));
+        assert_eq!(vec![2, 0, 3, 1], run_all(&mut queue, 100));
+    }
+
+    // Tests that tasks are removed by extract() without disturbing the rest, and that drain()
+    // empties the queue.
+    #[test]
+    fn test_fair_extract_drain() {
+        let mut queue = RunQueue::new(policy(100));
+        for i in 0..6 {
+            queue.push_back(job(i, (i % 3) as TenantId), 0);
+        }
+
+        let picked = queue.extract(|task| match task.tenant() {
+            Some(1) => Some(task.tenant()),
+            _ => None,
+        });
+        assert_eq!(2, picked.len());
+        assert_eq!(4, queue.len());
+
+        let drained: Vec<usize> = queue.drain().iter().map(id).collect();
+        assert_eq!(vec![0, 3, 2, 5], drained);
+        assert_eq!((0, 0), (queue.len(), queue.active()));
+        assert!(queue.pop_front(0).is_none());
+    }
+
+    // Tests that the time tasks waited is recorded against their tenant.
+    #[test]
+    fn test_fair_delays() {
+        let mut queue = RunQueue::new(policy(100));
+        queue.push_back(job(0, 1), 10);
+        queue.push_back(job(1, 1), 20);
+        queue.push_back(job(2, 2), 30);
+        while let Some(task) = queue.pop_front(100) {
+            queue.charge(&*task, 10);
+        }
+
+        let delays = queue.delays();
+        assert_eq!(2, delays.len());
+        assert_eq!(
+            (
+                1,
+                TenantDelay {
+                    picks: 2,
+                    total: 170,
+                    max: 90,
+                }
+            ),
+            delays[0]
+        );
+        assert_eq!((2, 70), (delays[1].0, delays[1].1.mean()));
+    }
+}
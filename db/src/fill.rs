@@ -0,0 +1,497 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use std::mem::transmute;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use super::alloc::Allocator;
+use super::cycles::{cycles_per_second, rdtsc};
+use super::master::Master;
+use super::native::Native;
+use super::table::Table;
+use super::task::{Task, TaskPriority};
+
+use sandstorm::common::{TableId, TenantId};
+
+/// The most objects a fill writes before it yields the core, or checks it's rate again. Each
+/// object is written on it's own, holding only the lock on it's bucket, exactly like a put().
+pub const FILL_BATCH: u64 = 64;
+
+/// The highest rate a fill can be capped at. Fills are left uncapped with a rate of 0 instead.
+pub const MAX_RATE: u64 = 100 * 1000 * 1000;
+
+// The longest a fill thread sleeps before checking it's rate again, so that a raised rate is
+// picked up promptly.
+const MAX_PAUSE_US: u64 = 1000;
+
+// Counters reported by filled(). Shared by all fills.
+static FILLED: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns the number of objects written by fills since the server started.
+pub fn filled() -> usize {
+    FILLED.load(Ordering::Relaxed)
+}
+
+/// The objects a fill writes, as a key and a value each.
+pub type Objects = Box<Iterator<Item = (Vec<u8>, Vec<u8>)> + Send>;
+
+/// Returns the objects Master::fill_test_sized() populates tables with. The key (and value) of
+/// object `i` starts with the little endian encoding of `i`; the rest of it is zeroed.
+///
+/// # Arguments
+///
+/// * `first`:   The number of the first object.
+/// * `num`:     The number of objects.
+/// * `key_len`: The length of each object's key. Must be atleast 4 Bytes.
+/// * `val_len`: The length of each object's value. Must be atleast 4 Bytes.
+pub fn numbered(first: u32, num: u32, key_len: usize, val_len: usize) -> Objects {
+    Box::new((first..first.saturating_add(num)).map(move |i| {
+        let temp: [u8; 4] = unsafe { transmute(i.to_le()) };
+        let (mut key, mut val) = (vec![0; key_len], vec![0; val_len]);
+        key[0..4].copy_from_slice(&temp);
+        val[0..4].copy_from_slice(&temp);
+        (key, val)
+    }))
+}
+
+/// The most objects a second fills write. Every fill reads it on every step, so it can be
+/// changed while fills are running, trading the speed of a fill for the latency of requests to
+/// the table being filled.
+pub struct FillRate(AtomicU64);
+
+impl FillRate {
+    /// Returns a cap on the rate of fills.
+    ///
+    /// # Arguments
+    ///
+    /// * `rate`: The most objects a second. 0 leaves fills uncapped.
+    pub fn new(rate: u64) -> FillRate {
+        FillRate(AtomicU64::new(rate))
+    }
+
+    /// Returns the most objects a second, or 0 if fills are uncapped.
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Changes the cap, taking effect on the next step of every fill.
+    ///
+    /// # Arguments
+    ///
+    /// * `rate`: The most objects a second. 0 leaves fills uncapped. Atmost MAX_RATE.
+    pub fn set(&self, rate: u64) {
+        self.0.store(rate.min(MAX_RATE), Ordering::Relaxed);
+    }
+}
+
+/// Meters a fill out at a capped rate, letting through atmost a batch of objects at a time
+/// however long the fill went without running.
+pub struct Pacer {
+    // The cap the pacer meters out at.
+    rate: Arc<FillRate>,
+
+    // The objects that can be written before the fill has to wait for the rate to allow more,
+    // and the time-stamp in cycles at which they were last topped up.
+    tokens: f64,
+    last: u64,
+}
+
+impl Pacer {
+    /// Returns a pacer that has not let any objects through yet.
+    ///
+    /// # Arguments
+    ///
+    /// * `rate`: The cap to meter out at.
+    pub fn new(rate: Arc<FillRate>) -> Pacer {
+        Pacer {
+            rate: rate,
+            tokens: 0.0,
+            last: rdtsc(),
+        }
+    }
+
+    /// Returns the number of objects the rate allows to be written since the last call, atmost
+    /// FILL_BATCH. The caller is expected to write that many, if it has them.
+    pub fn grant(&mut self) -> u64 {
+        let now = rdtsc();
+        let rate = self.rate.get();
+        let elapsed = now.saturating_sub(self.last) as f64;
+        self.last = now;
+
+        if rate == 0 {
+            self.tokens = 0.0;
+            return FILL_BATCH;
+        }
+
+        let earned = elapsed * rate as f64 / cycles_per_second() as f64;
+        self.tokens = (self.tokens + earned).min(FILL_BATCH as f64);
+        let granted = self.tokens as u64;
+        self.tokens -= granted as f64;
+        granted
+    }
+
+    /// Returns how long until the rate allows the next object to be written, atmost a
+    /// millisecond.
+    pub fn delay(&self) -> Duration {
+        let rate = self.rate.get();
+        if rate == 0 {
+            return Duration::from_micros(0);
+        }
+
+        let us = (1.0 - self.tokens).max(0.0) * 1e6 / rate as f64;
+        Duration::from_micros((us.ceil() as u64).min(MAX_PAUSE_US))
+    }
+}
+
+/// Populates a table that may already be serving requests. Objects are written one at a time
+/// through Allocator::store(), the same path a put() takes, so a request to the table waits on
+/// atmost a single object being written. Requests see the objects written so far, and none of
+/// the rest.
+pub struct Fill {
+    // The tenant owning the table, and the identifier of the table.
+    tenant: TenantId,
+    table_id: TableId,
+    table: Arc<Table>,
+
+    // The objects yet to be written.
+    objects: Objects,
+    pacer: Pacer,
+
+    // The number of objects written so far, and whether there are any left.
+    written: u64,
+    done: bool,
+}
+
+impl Fill {
+    /// Returns a fill that has not written anything yet. Refer to Master::fill().
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`:   The tenant owning the table.
+    /// * `table_id`: The identifier of the table.
+    /// * `table`:    The table to be populated.
+    /// * `objects`:  The objects to populate it with, in the order they are written in.
+    /// * `rate`:     The cap on the rate they are written at.
+    pub fn new(
+        tenant: TenantId,
+        table_id: TableId,
+        table: Arc<Table>,
+        objects: Objects,
+        rate: Arc<FillRate>,
+    ) -> Fill {
+        Fill {
+            tenant: tenant,
+            table_id: table_id,
+            table: table,
+            objects: objects,
+            pacer: Pacer::new(rate),
+            written: 0,
+            done: false,
+        }
+    }
+
+    /// Returns true once every object has been written.
+    pub fn done(&self) -> bool {
+        self.done
+    }
+
+    /// Returns the number of objects written so far.
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+
+    /// Returns how long a fill thread should wait for once a step writes nothing. Refer to
+    /// Pacer::delay().
+    pub fn delay(&self) -> Duration {
+        self.pacer.delay()
+    }
+
+    /// Writes as many objects as the rate allows since the last call, and atmost FILL_BATCH.
+    ///
+    /// # Arguments
+    ///
+    /// * `heap`: The allocator the table's objects are allocated on.
+    ///
+    /// # Return
+    ///
+    /// The number of objects written.
+    pub fn step(&mut self, heap: &Allocator) -> u64 {
+        let granted = self.pacer.grant();
+
+        let mut written = 0;
+        while written < granted {
+            let (key, val) = match self.objects.next() {
+                Some(object) => object,
+                None => {
+                    self.done = true;
+                    break;
+                }
+            };
+
+            let (key, obj) = heap
+                .object(self.tenant, self.table_id, &key, &val)
+                .expect("Failed to allocate object for fill.");
+            heap.store(&self.table, key, obj);
+            written += 1;
+        }
+
+        self.written += written;
+        FILLED.fetch_add(written as usize, Ordering::Relaxed);
+        written
+    }
+
+    /// Writes every object from the calling thread, sleeping whenever the rate does not allow
+    /// any more to be written yet.
+    ///
+    /// # Arguments
+    ///
+    /// * `heap`: The allocator the table's objects are allocated on.
+    ///
+    /// # Return
+    ///
+    /// The number of objects written.
+    pub fn run(&mut self, heap: &Allocator) -> u64 {
+        while !self.done {
+            if self.step(heap) == 0 && !self.done {
+                thread::sleep(self.delay());
+            }
+        }
+
+        self.written
+    }
+}
+
+/// Returns a task that runs a fill at background priority, yielding after every step, until
+/// every object is written or the server drains.
+///
+/// # Arguments
+///
+/// * `master`: The service owning the table being filled.
+/// * `fill`:   The fill to run. Refer to Master::fill().
+///
+/// # Return
+///
+/// A task that never sends out a response.
+pub fn task(master: Arc<Master>, mut fill: Fill) -> Box<Task> {
+    let gen = Box::new(move || {
+        while !fill.done() && !master.drain().draining() {
+            fill.step(master.heap());
+            yield 0;
+        }
+
+        return None;
+    });
+
+    Box::new(Native::new(TaskPriority::BACKGROUND, gen))
+}
+
+// This module contains tests for fills. Rates are checked on a virtual clock; fills alongside
+// other traffic run on real threads.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::AtomicBool;
+
+    use super::super::cycles::virt;
+    use super::super::rpc;
+    use super::super::wireformat::RpcStatus;
+
+    // The virtual clock ticks once a microsecond.
+    const HZ: u64 = 1_000_000;
+
+    // Returns an empty table, and a fill of `num` objects into it at a rate.
+    fn fill(num: u32, rate: &Arc<FillRate>) -> (Arc<Table>, Fill) {
+        let table = Arc::new(Table::default());
+        let objects = numbered(1, num, 30, 100);
+        let fill = Fill::new(1, 1, Arc::clone(&table), objects, Arc::clone(rate));
+        (table, fill)
+    }
+
+    // Tests that a capped fill writes at it's rate, atmost a batch at a time, and that changing
+    // the rate takes effect on the fill while it runs.
+    #[test]
+    fn test_fill_rate() {
+        virt::install(0, HZ);
+        let heap = Allocator::new();
+        let rate = Arc::new(FillRate::new(1000));
+        let (table, mut fill) = fill(1000, &rate);
+
+        // 1000 objects a second is one object a millisecond.
+        let mut written = Vec::new();
+        for _ in 0..100 {
+            virt::advance(HZ / 1000);
+            written.push(fill.step(&heap));
+        }
+        assert!(written.iter().all(|w| *w == 1));
+        assert_eq!(100, fill.written());
+
+        // A long pause only lets a batch through.
+        virt::advance(HZ);
+        assert_eq!(FILL_BATCH, fill.step(&heap));
+        assert_eq!(0, fill.step(&heap));
+
+        rate.set(10 * 1000);
+        virt::advance(HZ / 1000);
+        assert_eq!(10, fill.step(&heap));
+
+        // Uncapped, the fill runs to completion a batch at a time.
+        rate.set(0);
+        let mut steps = 0;
+        while !fill.done() {
+            assert!(fill.step(&heap) <= FILL_BATCH);
+            steps += 1;
+        }
+        virt::uninstall();
+
+        assert_eq!(1000, fill.written());
+        assert_eq!((1000 - 174) / FILL_BATCH + 1, steps);
+        for i in 1..1001u32 {
+            let (key, _) = numbered(i, 1, 30, 100).next().unwrap();
+            assert!(table.get(&key).is_some());
+        }
+    }
+
+    // Tests that a pacer asks a fill thread to wait until the next object is due, and never
+    // longer than a millisecond.
+    #[test]
+    fn test_pacer_delay() {
+        virt::install(0, HZ);
+        let rate = Arc::new(FillRate::new(10 * 1000));
+        let mut pacer = Pacer::new(Arc::clone(&rate));
+        assert_eq!(0, pacer.grant());
+        assert_eq!(Duration::from_micros(100), pacer.delay());
+
+        rate.set(10);
+        assert_eq!(Duration::from_micros(MAX_PAUSE_US), pacer.delay());
+
+        rate.set(0);
+        assert_eq!(Duration::from_micros(0), pacer.delay());
+        assert_eq!(FILL_BATCH, pacer.grant());
+
+        rate.set(MAX_RATE + 1);
+        assert_eq!(MAX_RATE, rate.get());
+        virt::uninstall();
+    }
+
+    // Tests that a fill thread holds it's rate on the real clock.
+    #[test]
+    fn test_fill_thread() {
+        let heap = Allocator::new();
+        let rate = Arc::new(FillRate::new(20 * 1000));
+        let (_, mut fill) = fill(2000, &rate);
+
+        let start = ::std::time::Instant::now();
+        assert_eq!(2000, fill.run(&heap));
+        let elapsed = start.elapsed();
+
+        // 2000 objects at 20,000 a second take 100 milliseconds, less the first batch.
+        assert!(elapsed >= Duration::from_millis(90), "{:?}", elapsed);
+        assert!(filled() >= 2000);
+    }
+
+    // Stresses a capped fill into a table that is being read, written, dumped and migrated into
+    // at the same time. Every object written by anyone must be there at the end, and readers
+    // must never find a key the fill wrote go missing once they have seen it.
+    #[test]
+    fn test_fill_concurrent() {
+        const N_FILL: u32 = 20 * 1000;
+        const N_BASE: u32 = 1000;
+
+        let master = Arc::new(Master::new());
+        master.fill_test(1, 1, N_BASE);
+        master.fill_rate().set(200 * 1000);
+
+        // The fill writes keys past the ones fill_test() wrote.
+        let mut fill = master
+            .fill(1, 1, numbered(N_BASE + 1, N_FILL, 30, 100))
+            .expect("Failed to start fill.");
+        let stop = Arc::new(AtomicBool::new(false));
+
+        // Readers watch the fill's progress from both ends of it's key space.
+        let readers: Vec<_> = (0..2)
+            .map(|r| {
+                let (master, stop) = (Arc::clone(&master), Arc::clone(&stop));
+                thread::spawn(move || {
+                    let mut seen = 0;
+                    while !stop.load(Ordering::Relaxed) {
+                        let i = match r {
+                            0 => N_BASE + 1 + seen,
+                            _ => N_BASE + N_FILL - seen,
+                        };
+                        let (key, val) = numbered(i, 1, 30, 100).next().unwrap();
+                        match master.get_value(1, 1, &key) {
+                            Ok(v) => {
+                                assert_eq!(&val[..], &v[..]);
+                                seen = (seen + 1).min(N_FILL - 1);
+                            }
+                            Err(status) => {
+                                assert_eq!(RpcStatus::StatusObjectDoesNotExist, status)
+                            }
+                        }
+
+                        let (base, _) = numbered(1 + i % N_BASE, 1, 30, 100).next().unwrap();
+                        assert!(master.get_value(1, 1, &base).is_ok());
+                    }
+                })
+            })
+            .collect();
+
+        // A writer overwrites the base objects, and a migration copies batches of the table into
+        // another tenant's table while both are being written to.
+        master.fill_test(2, 1, 0);
+        let migrator = {
+            let (master, stop) = (Arc::clone(&master), Arc::clone(&stop));
+            thread::spawn(move || {
+                let mut copied = 0;
+                while !stop.load(Ordering::Relaxed) {
+                    let mut cursor = (0, Vec::new());
+                    loop {
+                        let (page, num, bucket) = master
+                            .dump_table(1, 1, cursor.0, &cursor.1, 1024)
+                            .expect("Failed to dump table.");
+                        if num == 0 {
+                            break;
+                        }
+
+                        let records = rpc::parse_kvs(&page, num).unwrap();
+                        cursor = (bucket, records.last().unwrap().0.to_vec());
+                        assert_eq!(RpcStatus::StatusOk, master.put_records(2, 1, &page, num));
+                        copied += num;
+                    }
+
+                    let (key, _) = numbered(1 + copied % N_BASE, 1, 30, 100).next().unwrap();
+                    assert_eq!(RpcStatus::StatusOk, master.put_value(1, 1, &key, &[7; 100]));
+                }
+                copied
+            })
+        };
+
+        assert_eq!(N_FILL as u64, fill.run(master.heap()));
+        stop.store(true, Ordering::Relaxed);
+        for reader in readers {
+            reader.join().expect("Reader panicked.");
+        }
+        assert!(migrator.join().expect("Migrator panicked.") > 0);
+
+        for i in 1..(N_BASE + N_FILL + 1) {
+            let (key, _) = numbered(i, 1, 30, 100).next().unwrap();
+            assert!(master.get_value(1, 1, &key).is_ok());
+        }
+    }
+}
@@ -0,0 +1,263 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Caching hints on get() responses. Once a threshold is set, every object counts the reads made
+//! of it since it was last written, and a native get() of an object read atleast that many times
+//! is flagged GET_HINT_HOT on it's response, along with the object's version and a TTL clients
+//! can cache it for. Clients revalidate a cached object past it's TTL with a multiget() that asks
+//! for versions only; refer to splinter::cache.
+//!
+//! The TTL is the mean interval between writes to the object's table, so that objects on tables
+//! that are rarely written are cached for longer. It is capped at `max_ttl_us`.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::usize;
+
+use super::cycles;
+use super::table::{Entry, Table};
+use super::wireformat::{GetResponse, RpcStatus, GET_HINT_HOT};
+
+/// The number of reads after which an object is hinted hot, if the server config does not say
+/// otherwise. 0 means that objects are never hinted hot, and reads are not counted.
+pub const DEFAULT_HOT_READS: usize = 0;
+
+/// The longest TTL suggested on a hint, in microseconds, if the server config does not say
+/// otherwise.
+pub const DEFAULT_MAX_TTL_US: u32 = 1_000_000;
+
+/// The longest TTL that can be configured, in microseconds.
+pub const MAX_TTL_US: u32 = 10_000_000;
+
+// The number of reads after which an object is hinted hot, or usize::MAX if reads are not
+// counted. Shared by all tables, so that it can be set once at startup.
+static HOT_READS: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+// The longest TTL suggested on a hint, in microseconds.
+static MAX_TTL: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_TTL_US as usize);
+
+/// Sets the number of reads after which an object is hinted hot. Counting costs an atomic
+/// increment on every read, so it is meant for caching experiments; 0 turns it off.
+///
+/// # Arguments
+///
+/// * `reads`: The number of reads, counted since the object was last written.
+pub fn set_hot_reads(reads: usize) {
+    let reads = match reads {
+        0 => usize::MAX,
+        reads => reads,
+    };
+    HOT_READS.store(reads, Ordering::Relaxed);
+}
+
+/// Returns true if reads are counted against objects.
+#[inline]
+pub fn counting() -> bool {
+    HOT_READS.load(Ordering::Relaxed) != usize::MAX
+}
+
+/// Sets the longest TTL suggested on a hint.
+///
+/// # Arguments
+///
+/// * `ttl_us`: The TTL in microseconds. 0 means DEFAULT_MAX_TTL_US.
+pub fn set_max_ttl_us(ttl_us: u32) {
+    let ttl_us = match ttl_us {
+        0 => DEFAULT_MAX_TTL_US,
+        ttl_us => ttl_us,
+    };
+    MAX_TTL.store(ttl_us as usize, Ordering::Relaxed);
+}
+
+/// A caching hint, put on the response to a get() of a hot object.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Hint {
+    /// The version of the object read.
+    pub version: u64,
+
+    /// How long the client can cache the object for before revalidating it, in microseconds.
+    pub ttl_us: u32,
+}
+
+/// Returns the TTL to suggest for objects on a table: the mean interval between writes to it,
+/// capped at `max_ttl_us`.
+///
+/// # Arguments
+///
+/// * `writes`:     The number of writes made to the table.
+/// * `elapsed_us`: The time over which they were made, in microseconds.
+/// * `max_ttl_us`: The longest TTL to suggest.
+pub fn ttl_us(writes: u64, elapsed_us: u64, max_ttl_us: u32) -> u32 {
+    match writes {
+        0 => max_ttl_us,
+        writes => (elapsed_us / writes).min(max_ttl_us as u64) as u32,
+    }
+}
+
+/// Returns the hint for a get() that read an object, or None if the object is not hot. Objects
+/// that are not hot cost only a compare of the reads counted against them; reads are not counted
+/// if hints are off, so no object reaches the threshold.
+///
+/// # Arguments
+///
+/// * `table`: The table the object was read off.
+/// * `entry`: The object, as returned by Table::get().
+#[inline]
+pub fn hint(table: &Table, entry: &Entry) -> Option<Hint> {
+    if entry.reads < HOT_READS.load(Ordering::Relaxed) {
+        return None;
+    }
+
+    let writes = table.writes().totals().iter().map(|t| t.count).sum();
+    let elapsed = cycles::rdtsc().saturating_sub(table.created());
+    let elapsed_us = (cycles::to_seconds(elapsed) * 1e6) as u64;
+    let max_ttl_us = MAX_TTL.load(Ordering::Relaxed) as u32;
+    match ttl_us(writes, elapsed_us, max_ttl_us) {
+        0 => None,
+        ttl_us => Some(Hint {
+            version: entry.version.number(),
+            ttl_us: ttl_us,
+        }),
+    }
+}
+
+/// Puts a hint on the response to a get(), if the get() succeeded.
+///
+/// # Arguments
+///
+/// * `hdr`:  The header on the response, with it's status already set.
+/// * `hint`: The hint for the object read, if it was hot.
+pub fn apply(hdr: &mut GetResponse, hint: Option<Hint>) {
+    if let Some(hint) = hint {
+        if hdr.common_header.status == RpcStatus::StatusOk {
+            hdr.hint |= GET_HINT_HOT;
+            hdr.set_version(hint.version);
+            hdr.set_ttl_us(hint.ttl_us);
+        }
+    }
+}
+
+// This module contains unit tests for caching hints.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bytes::Bytes;
+
+    use super::super::amplify::WriteKind;
+    use super::super::cycles::virt;
+    use super::super::wireformat::OpCode;
+
+    // Turns hints back off, even if a test fails.
+    struct Off;
+
+    impl Drop for Off {
+        fn drop(&mut self) {
+            set_hot_reads(0);
+            set_max_ttl_us(0);
+            virt::uninstall();
+        }
+    }
+
+    // Tests that the TTL is the mean interval between writes to the table, capped.
+    #[test]
+    fn test_hint_ttl() {
+        assert_eq!(1000, ttl_us(0, 0, 1000));
+        assert_eq!(1000, ttl_us(0, 5_000_000, 1000));
+        assert_eq!(250, ttl_us(4, 1000, 1000));
+        assert_eq!(1000, ttl_us(4, 1_000_000, 1000));
+        assert_eq!(0, ttl_us(2000, 1000, 1000));
+    }
+
+    // Tests that hints are only put on responses that succeeded.
+    #[test]
+    fn test_hint_apply() {
+        let hint = Hint {
+            version: 7,
+            ttl_us: 1000,
+        };
+
+        let mut hdr = GetResponse::new(1, 2, OpCode::SandstormGetRpc, 3);
+        apply(&mut hdr, None);
+        assert_eq!((0, 0, 0), (hdr.hint, hdr.version(), hdr.ttl_us()));
+        apply(&mut hdr, Some(hint));
+        assert_eq!(
+            (GET_HINT_HOT, 7, 1000),
+            (hdr.hint, hdr.version(), hdr.ttl_us())
+        );
+
+        let mut hdr = GetResponse::new(1, 2, OpCode::SandstormGetRpc, 3);
+        hdr.common_header.status = RpcStatus::StatusInternalError;
+        apply(&mut hdr, Some(hint));
+        assert_eq!(0, hdr.hint);
+    }
+
+    // Tests when objects are hinted hot, and the TTL they are hinted with. The threshold is
+    // shared by every table, so everything that sets it is tested here, one step at a time.
+    #[test]
+    fn test_hint_threshold() {
+        let _off = Off;
+        virt::install(1_000_000_000, 1_000_000_000);
+        let table = Table::default();
+        table.put(Bytes::from(&b"key"[..]), Bytes::from(&b"value"[..]));
+
+        // Reads are not counted, and objects never hinted, unless a threshold is set.
+        assert!(!counting());
+        for _ in 0..10 {
+            let entry = table.get(b"key").unwrap();
+            assert_eq!(0, entry.reads);
+            assert_eq!(None, hint(&table, &entry));
+        }
+
+        // An object is hinted from the read that takes it to the threshold on. Reads made before
+        // the threshold was set are not counted.
+        set_hot_reads(3);
+        assert!(counting());
+        for read in 1..6 {
+            let entry = table.get(b"key").unwrap();
+            assert_eq!(read, entry.reads);
+            assert_eq!(read >= 3, hint(&table, &entry).is_some(), "read {}", read);
+        }
+
+        // A write starts the count over. The hint carries the version of the object read.
+        table.put(Bytes::from(&b"key"[..]), Bytes::from(&b"other"[..]));
+        let entry = table.get(b"key").unwrap();
+        assert_eq!(1, entry.reads);
+        assert_eq!(None, hint(&table, &entry));
+
+        table.get(b"key");
+        let entry = table.get(b"key").unwrap();
+        let version = entry.version.number();
+        assert_eq!(Some(version), hint(&table, &entry).map(|h| h.version));
+
+        // The table's writes were not accounted for (they did not go through the allocator),
+        // so the TTL is the cap.
+        set_max_ttl_us(500_000);
+        let ttl = |table: &Table| hint(table, &table.get(b"key").unwrap()).map(|h| h.ttl_us);
+        assert_eq!(Some(500_000), ttl(&table));
+
+        // 10 writes over a second, a write every 100 ms.
+        for _ in 0..10 {
+            table.writes().record(WriteKind::Put, b"key", 5, 5);
+        }
+        virt::advance(1_000_000_000);
+        assert_eq!(Some(100_000), ttl(&table));
+
+        // A table written more often than once a microsecond is too hot to hint at all.
+        for _ in 0..1_000_000 {
+            table.writes().record(WriteKind::Put, b"key", 5, 5);
+        }
+        assert_eq!(None, ttl(&table));
+    }
+}
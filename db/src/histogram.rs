@@ -0,0 +1,340 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+/// Scales the two-sample Kolmogorov-Smirnov critical value at a significance level of 0.01.
+const KS_C_ALPHA: f64 = 1.628;
+
+// The number of bits below the most significant one that pick a sub-bucket. Each power of two
+// is split into 4 buckets, so a bucket's bounds are atmost 25% apart.
+const SUB_BITS: u32 = 2;
+
+// Samples of this many bits or more all fall into the last bucket; 2^32 cycles is over a
+// second on any server this runs on.
+const MAX_BITS: u32 = 32;
+
+/// The number of buckets in a LogHistogram.
+pub const LOG_BUCKETS: usize = ((MAX_BITS - SUB_BITS + 1) << SUB_BITS) as usize;
+
+/// A latency histogram. Bucket `i` counts the samples that were atmost `bounds[i]` nanoseconds,
+/// and larger than `bounds[i - 1]`. Shared by clients, which fill it off end-to-end latencies,
+/// and the server, which converts it's LogHistograms into it.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Histogram {
+    /// Upper bound of each bucket in nanoseconds, in ascending order.
+    pub bounds: Vec<u64>,
+
+    /// The number of samples in each bucket.
+    pub counts: Vec<u64>,
+}
+
+impl Histogram {
+    /// Returns the number of samples in the histogram.
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    // Returns the fraction of samples that were atmost `bound` nanoseconds. Buckets that straddle
+    // the bound are left out.
+    fn cdf(&self, bound: u64) -> f64 {
+        let total = self.total();
+        if total == 0 {
+            return 0.0;
+        }
+
+        let below: u64 = self
+            .bounds
+            .iter()
+            .zip(self.counts.iter())
+            .take_while(|&(b, _)| *b <= bound)
+            .map(|(_, c)| *c)
+            .sum();
+        below as f64 / total as f64
+    }
+
+    /// Returns the upper bound of the bucket that the `p`th fraction of samples fall into.
+    ///
+    /// # Arguments
+    ///
+    /// * `p`: The percentile as a fraction, ex: 0.99.
+    pub fn percentile(&self, p: f64) -> u64 {
+        let target = p * self.total() as f64;
+        let mut seen = 0;
+        for (bound, count) in self.bounds.iter().zip(self.counts.iter()) {
+            seen += *count;
+            if seen as f64 >= target && seen > 0 {
+                return *bound;
+            }
+        }
+
+        self.bounds.last().cloned().unwrap_or(0)
+    }
+
+    // Returns the bucket bounds both histograms share, merging finer buckets into the coarsest
+    // ones that both resolve. Histograms that share no bounds are compared at the bounds of
+    // either.
+    fn merged_bounds(&self, other: &Histogram) -> Vec<u64> {
+        let shared: Vec<u64> = self
+            .bounds
+            .iter()
+            .filter(|b| other.bounds.binary_search(b).is_ok())
+            .cloned()
+            .collect();
+        if !shared.is_empty() {
+            return shared;
+        }
+
+        let mut bounds: Vec<u64> = self
+            .bounds
+            .iter()
+            .chain(other.bounds.iter())
+            .cloned()
+            .collect();
+        bounds.sort();
+        bounds.dedup();
+        bounds
+    }
+
+    /// Returns the Kolmogorov-Smirnov statistic between this histogram and another; the largest
+    /// difference between their cumulative distributions at the bucket bounds they share. 0
+    /// means identical, 1 means disjoint.
+    pub fn ks(&self, other: &Histogram) -> f64 {
+        self.merged_bounds(other)
+            .iter()
+            .map(|b| (self.cdf(*b) - other.cdf(*b)).abs())
+            .fold(0.0, f64::max)
+    }
+
+    /// Returns the signed area between the cumulative distributions of another histogram and
+    /// this one, in nanoseconds. This approximates how much lower the other histogram's mean is;
+    /// positive if it's samples are faster.
+    pub fn shift(&self, other: &Histogram) -> f64 {
+        let bounds = self.merged_bounds(other);
+        bounds
+            .windows(2)
+            .map(|w| (other.cdf(w[0]) - self.cdf(w[0])) * (w[1] - w[0]) as f64)
+            .sum()
+    }
+
+    /// Returns the smallest KS statistic that is significant at the 0.01 level, given the
+    /// number of samples in this histogram and another.
+    pub fn ks_critical(&self, other: &Histogram) -> f64 {
+        let (n, m) = (self.total() as f64, other.total() as f64);
+        if n == 0.0 || m == 0.0 {
+            return 1.0;
+        }
+
+        KS_C_ALPHA * ((n + m) / (n * m)).sqrt()
+    }
+}
+
+/// Counts samples in cycles, in buckets whose width grows with the samples they hold. Each power
+/// of two is split into 4 equally wide buckets, so percentiles read off it are within 25% of the
+/// samples they stand for, whatever their magnitude. Every LogHistogram has the same buckets,
+/// so they can be merged by adding up their counts.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogHistogram {
+    // The number of samples in each bucket.
+    counts: Vec<u64>,
+}
+
+impl LogHistogram {
+    /// Returns a histogram without any samples.
+    pub fn new() -> LogHistogram {
+        LogHistogram {
+            counts: vec![0; LOG_BUCKETS],
+        }
+    }
+
+    /// Returns the bucket a sample falls into.
+    ///
+    /// # Arguments
+    ///
+    /// * `cycles`: The sample.
+    #[inline]
+    pub fn bucket(cycles: u64) -> usize {
+        if cycles < (1 << SUB_BITS) {
+            return cycles as usize;
+        }
+
+        let msb = 63 - cycles.leading_zeros();
+        if msb >= MAX_BITS {
+            return LOG_BUCKETS - 1;
+        }
+
+        let sub = (cycles >> (msb - SUB_BITS)) as usize & ((1 << SUB_BITS) - 1);
+        (((msb - SUB_BITS + 1) << SUB_BITS) as usize) + sub
+    }
+
+    /// Returns the largest sample in cycles that falls into a bucket. The last bucket also holds
+    /// every sample past it's bound.
+    ///
+    /// # Arguments
+    ///
+    /// * `bucket`: The bucket, less than LOG_BUCKETS.
+    pub fn bound(bucket: usize) -> u64 {
+        if bucket < (1 << SUB_BITS) {
+            return bucket as u64;
+        }
+
+        let msb = (bucket >> SUB_BITS) as u32 + SUB_BITS - 1;
+        let sub = (bucket & ((1 << SUB_BITS) - 1)) as u64;
+        let width = 1u64 << (msb - SUB_BITS);
+        (((1 << SUB_BITS) + sub) << (msb - SUB_BITS)) + width - 1
+    }
+
+    /// Counts a sample.
+    ///
+    /// # Arguments
+    ///
+    /// * `cycles`: The sample.
+    #[inline]
+    pub fn record(&mut self, cycles: u64) {
+        self.counts[LogHistogram::bucket(cycles)] += 1;
+    }
+
+    /// Adds samples to a bucket. Buckets past the last one are ignored.
+    ///
+    /// # Arguments
+    ///
+    /// * `bucket`: The bucket.
+    /// * `count`:  The number of samples.
+    pub fn add(&mut self, bucket: usize, count: u64) {
+        if let Some(c) = self.counts.get_mut(bucket) {
+            *c += count;
+        }
+    }
+
+    /// Adds the samples in another histogram to this one.
+    pub fn merge(&mut self, other: &LogHistogram) {
+        for (c, o) in self.counts.iter_mut().zip(other.counts.iter()) {
+            *c += *o;
+        }
+    }
+
+    /// Returns the number of samples in each bucket.
+    pub fn counts(&self) -> &[u64] {
+        &self.counts
+    }
+
+    /// Returns the number of samples in the histogram.
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Converts the histogram to nanoseconds. Buckets whose bounds round to the same number of
+    /// nanoseconds are merged, and empty buckets past the last sample are left out.
+    ///
+    /// # Arguments
+    ///
+    /// * `cycles_per_second`: The rate of the clock the samples were taken off.
+    pub fn to_nanos(&self, cycles_per_second: u64) -> Histogram {
+        let last = match self.counts.iter().rposition(|c| *c > 0) {
+            Some(last) => last,
+            None => return Histogram::default(),
+        };
+
+        let mut hist = Histogram::default();
+        for (bucket, count) in self.counts[..last + 1].iter().enumerate() {
+            let cycles = LogHistogram::bound(bucket) as f64;
+            let bound = (cycles * 1e9 / cycles_per_second.max(1) as f64).ceil() as u64;
+            match hist.bounds.last() == Some(&bound) {
+                true => *hist.counts.last_mut().unwrap() += *count,
+                false => {
+                    hist.bounds.push(bound);
+                    hist.counts.push(*count);
+                }
+            }
+        }
+
+        hist
+    }
+}
+
+// This module contains unit tests for Histogram and LogHistogram.
+#[cfg(test)]
+mod tests {
+    use super::{Histogram, LogHistogram, LOG_BUCKETS};
+
+    // Tests that histograms with different buckets are compared over their merged bounds.
+    #[test]
+    fn test_merged_buckets() {
+        let a = Histogram {
+            bounds: vec![10, 20, 30, 40],
+            counts: vec![25, 25, 25, 25],
+        };
+        let b = Histogram {
+            bounds: vec![20, 40],
+            counts: vec![50, 50],
+        };
+        assert_eq!(0.0, a.ks(&b));
+
+        let c = Histogram {
+            bounds: vec![20, 40],
+            counts: vec![75, 25],
+        };
+        assert_eq!(0.25, a.ks(&c));
+        assert_eq!(0.25, c.ks(&a));
+    }
+
+    // Tests that every sample falls into the bucket whose bounds bracket it, and that buckets
+    // are atmost 25% wide.
+    #[test]
+    fn test_log_buckets() {
+        assert_eq!(124, LOG_BUCKETS);
+        for cycles in (0..4096).chain((12..32).map(|b| (1u64 << b) + 12345)) {
+            let bucket = LogHistogram::bucket(cycles);
+            assert!(cycles <= LogHistogram::bound(bucket), "{}", cycles);
+            if bucket > 0 {
+                let lower = LogHistogram::bound(bucket - 1);
+                assert!(cycles > lower, "{}", cycles);
+                assert!(LogHistogram::bound(bucket) - lower <= lower / 4 + 1);
+            }
+        }
+
+        assert_eq!(LOG_BUCKETS - 1, LogHistogram::bucket((1 << 32) - 1));
+        assert_eq!(LOG_BUCKETS - 1, LogHistogram::bucket(1 << 40));
+        assert_eq!((1 << 32) - 1, LogHistogram::bound(LOG_BUCKETS - 1));
+    }
+
+    // Tests that merged histograms add up, and that percentiles in nanoseconds bracket the
+    // samples they were read off.
+    #[test]
+    fn test_log_to_nanos() {
+        let (mut a, mut b) = (LogHistogram::new(), LogHistogram::new());
+        for _ in 0..90 {
+            a.record(2_000);
+        }
+        for _ in 0..10 {
+            b.record(200_000);
+        }
+        a.merge(&b);
+        assert_eq!(100, a.total());
+
+        // At 2 GHz, the samples are 1us and 100us.
+        let hist = a.to_nanos(2_000_000_000);
+        assert_eq!(100, hist.total());
+        let p50 = hist.percentile(0.5);
+        let p99 = hist.percentile(0.99);
+        assert!(p50 >= 1_000 && p50 <= 1_250, "{}", p50);
+        assert!(p99 >= 100_000 && p99 <= 125_000, "{}", p99);
+
+        // Small buckets round to the same nanosecond and are merged.
+        assert!(hist.bounds.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(
+            Histogram::default(),
+            LogHistogram::new().to_nanos(2_000_000_000)
+        );
+    }
+}
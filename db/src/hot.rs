@@ -0,0 +1,896 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! The hot arena. Objects are allocated wherever malloc puts them, so the handful of objects a
+//! skewed workload keeps reading end up spread across the whole heap, each on a page of it's own
+//! competing with cold objects for the TLB. The arena is a small, bounded region the hottest
+//! objects are copied into, so that they share a few (optionally huge) pages.
+//!
+//! A background walk passes over every table once a window, folding the reads counted against
+//! each object since it's last pass into the object's heat (refer to fold()). An object read
+//! atleast `reads` times in each of `windows` windows in a row is promoted: it is copied into the
+//! arena, and the table's slot is swapped over to the copy. An object in the arena that was read
+//! fewer times than that in `windows` windows in a row is demoted back onto the heap the same
+//! way. The swap is made under the bucket's write lock, the same one puts take, and only if the
+//! object was not written since it was copied (refer to Table::relocate()); a put that races
+//! with a relocation wins, and the copy is simply never used. Versions and read counts carry
+//! over, and readers holding on to the old object keep it alive until they drop it.
+//!
+//! The arena is split into segments, carved from front to back. Once the last segment is full,
+//! the arena moves back to the first; a segment is only reused once every object carved off it
+//! is gone, so the arena never takes more memory than configured. Until then, promotions are
+//! refused, and the walk demotes every object still on the segment, whether hot or not; hot
+//! objects are promoted back onto a fresh segment on later passes.
+
+use std::cmp::min;
+use std::iter::repeat;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::usize;
+
+use bytes::{Bytes, BytesMut};
+use libc;
+use spin::Mutex;
+
+use super::cycles;
+use super::master::Master;
+use super::native::Native;
+use super::prefault;
+use super::table::{Table, N_BUCKETS};
+use super::task::{Task, TaskPriority};
+
+use sandstorm::common::{TableId, TenantId};
+
+/// The number of reads in a window after which an object is hot, if the server config does not
+/// say otherwise.
+pub const DEFAULT_READS: usize = 64;
+
+/// The length of a window in milliseconds, if the server config does not say otherwise.
+pub const DEFAULT_WINDOW_MS: u64 = 100;
+
+/// The number of windows in a row an object must be hot to be promoted, or cold to be demoted,
+/// if the server config does not say otherwise.
+pub const DEFAULT_WINDOWS: usize = 4;
+
+/// The size of the segments the arena is split into; the size of a huge page. An arena smaller
+/// than this is a single segment.
+pub const SEGMENT_SIZE: usize = 2 << 20;
+
+// Objects larger than this fraction of a segment are never promoted, so that a few large objects
+// cannot crowd out the small ones the arena is meant for.
+const MAX_OBJECT_FRACTION: usize = 8;
+
+// The layout of an object's heat: the number of windows in a row it was hot or cold for in the
+// low bits, whether it was hot in the next one up, and the reads counted against it when it was
+// last folded in the rest.
+const STREAK_BITS: usize = 8;
+const STREAK_MAX: usize = (1 << STREAK_BITS) - 1;
+const HOT_BIT: usize = 1 << STREAK_BITS;
+const SEEN_SHIFT: usize = STREAK_BITS + 1;
+
+// Marks that no segment is being drained.
+const NOT_DRAINING: usize = usize::MAX;
+
+/// Folds the reads counted against an object since it was last folded into it's heat. Reads are
+/// counted since the object was last written (refer to Table::get()), so a count lower than the
+/// one last seen means the object was written in between, and every read was made since.
+///
+/// # Arguments
+///
+/// * `heat`:      The object's heat. 0 for an object that was never folded.
+/// * `reads`:     The reads counted against the object.
+/// * `threshold`: The number of reads after which the object is hot.
+///
+/// # Return
+///
+/// The object's new heat, and the number of windows in a row it was hot for, or the negative of
+/// the number it was cold for.
+pub fn fold(heat: usize, reads: usize, threshold: usize) -> (usize, isize) {
+    let seen = heat >> SEEN_SHIFT;
+    let window = match reads >= seen {
+        true => reads - seen,
+        false => reads,
+    };
+
+    let hot = window >= threshold;
+    let streak = match (heat & HOT_BIT != 0) == hot && heat & STREAK_MAX != 0 {
+        true => min((heat & STREAK_MAX) + 1, STREAK_MAX),
+        false => 1,
+    };
+
+    let heat = (reads << SEEN_SHIFT) | streak | if hot { HOT_BIT } else { 0 };
+    match hot {
+        true => (heat, streak as isize),
+        false => (heat, -(streak as isize)),
+    }
+}
+
+/// How large the hot arena is, whether it is backed by huge pages, and when objects are moved in
+/// and out of it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HotPolicy {
+    bytes: usize,
+    huge_pages: bool,
+    reads: usize,
+    window_ms: u64,
+    windows: usize,
+}
+
+impl HotPolicy {
+    /// Returns a policy for the hot arena.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes`:      The size of the arena. 0 means there is no arena.
+    /// * `huge_pages`: True if the arena should be backed by transparent huge pages.
+    /// * `reads`:      The number of reads in a window after which an object is hot.
+    /// * `window_ms`:  The length of a window in milliseconds; the walk passes over every table
+    ///                 atmost once a window.
+    /// * `windows`:    The number of windows in a row an object must be hot to be promoted, or
+    ///                 cold to be demoted. Atleast 1.
+    pub fn new(
+        bytes: usize,
+        huge_pages: bool,
+        reads: usize,
+        window_ms: u64,
+        windows: usize,
+    ) -> HotPolicy {
+        HotPolicy {
+            bytes: bytes,
+            huge_pages: huge_pages,
+            reads: reads,
+            window_ms: window_ms,
+            windows: windows,
+        }
+    }
+
+    /// Returns the size of the arena. 0 if there is none.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Returns true if the arena should be backed by transparent huge pages.
+    pub fn huge_pages(&self) -> bool {
+        self.huge_pages
+    }
+
+    /// Returns the number of reads in a window after which an object is hot.
+    pub fn reads(&self) -> usize {
+        self.reads
+    }
+
+    /// Returns the length of a window in milliseconds.
+    pub fn window_ms(&self) -> u64 {
+        self.window_ms
+    }
+
+    /// Returns the number of windows in a row an object must be hot or cold to be moved.
+    pub fn windows(&self) -> usize {
+        self.windows
+    }
+
+    /// Returns an arena split into segments of SEGMENT_SIZE, or None if the policy gives it no
+    /// room.
+    pub fn arena(&self) -> Option<HotArena> {
+        match self.bytes {
+            0 => None,
+            bytes => Some(HotArena::new(*self, min(bytes, SEGMENT_SIZE))),
+        }
+    }
+}
+
+impl Default for HotPolicy {
+    fn default() -> HotPolicy {
+        HotPolicy::new(0, false, DEFAULT_READS, DEFAULT_WINDOW_MS, DEFAULT_WINDOWS)
+    }
+}
+
+/// The counters of the hot arena since the server started.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct HotStats {
+    /// The most bytes the arena holds.
+    pub capacity: u64,
+
+    /// The bytes carved off segments that are not yet reused, including those of objects that
+    /// were since demoted or overwritten.
+    pub used: u64,
+
+    /// The number of objects copied into the arena.
+    pub promotions: u64,
+
+    /// The number of objects copied back out of it.
+    pub demotions: u64,
+
+    /// The number of hot objects that were not promoted, because they were too large or the
+    /// arena was full.
+    pub refused: u64,
+
+    /// The number of objects that were copied, but written or deleted before the copy could be
+    /// swapped in.
+    pub raced: u64,
+
+    /// The number of native get()s counted, and the number of them served out of the arena.
+    pub gets: u64,
+    pub hits: u64,
+}
+
+impl HotStats {
+    /// Returns the fraction of native get()s that were served out of the arena.
+    pub fn hit_fraction(&self) -> f64 {
+        match self.gets {
+            0 => 0.0,
+            gets => self.hits as f64 / gets as f64,
+        }
+    }
+}
+
+// A segment of the arena. Objects are carved off the front of `free`. `pin` holds the bytes in
+// front of the first object, so that once it is the only handle left on the segment's memory,
+// every object carved off the segment is known to be gone.
+struct Segment {
+    pin: Bytes,
+    free: BytesMut,
+    used: usize,
+}
+
+// The segments of the arena, and the one objects are being carved off. Segments are opened
+// lazily, so a slot is None until the arena first gets to it.
+struct Ring {
+    segments: Vec<Option<Segment>>,
+    current: usize,
+}
+
+/// A bounded region of memory the hottest objects are copied into. Refer to the module's
+/// documentation.
+pub struct HotArena {
+    policy: HotPolicy,
+
+    // The size of each segment, and the alignment of it's first object.
+    segment: usize,
+    align: usize,
+
+    ring: Mutex<Ring>,
+
+    // The start and end address of each segment, so that an object can be looked up without
+    // taking the lock. Both are 0 if the segment is not open.
+    ranges: Vec<(AtomicUsize, AtomicUsize)>,
+
+    // The segment whose objects the walk demotes, so that it can be reused, or NOT_DRAINING.
+    draining: AtomicUsize,
+
+    // Counters reported by stats().
+    used: AtomicUsize,
+    promotions: AtomicUsize,
+    demotions: AtomicUsize,
+    refused: AtomicUsize,
+    raced: AtomicUsize,
+    gets: AtomicUsize,
+    hits: AtomicUsize,
+}
+
+impl HotArena {
+    /// Returns an arena. No memory is allocated until objects are promoted into it.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy`:  The size of the arena, and when objects are moved in and out of it.
+    /// * `segment`: The size of each segment. The arena holds as many as fit in it's size, and
+    ///              atleast one.
+    pub fn new(policy: HotPolicy, segment: usize) -> HotArena {
+        let count = (policy.bytes / segment).max(1);
+        let align = match policy.huge_pages && segment >= SEGMENT_SIZE {
+            true => SEGMENT_SIZE,
+            false => prefault::page_size(),
+        };
+
+        HotArena {
+            policy: policy,
+            segment: segment,
+            align: align,
+            ring: Mutex::new(Ring {
+                segments: (0..count).map(|_| None).collect(),
+                current: count - 1,
+            }),
+            ranges: (0..count)
+                .map(|_| (AtomicUsize::new(0), AtomicUsize::new(0)))
+                .collect(),
+            draining: AtomicUsize::new(NOT_DRAINING),
+            used: AtomicUsize::new(0),
+            promotions: AtomicUsize::new(0),
+            demotions: AtomicUsize::new(0),
+            refused: AtomicUsize::new(0),
+            raced: AtomicUsize::new(0),
+            gets: AtomicUsize::new(0),
+            hits: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the policy the arena was created with.
+    pub fn policy(&self) -> &HotPolicy {
+        &self.policy
+    }
+
+    /// Returns the most bytes the arena holds.
+    pub fn capacity(&self) -> usize {
+        self.segment * self.ranges.len()
+    }
+
+    /// Returns true if an object is in the arena.
+    ///
+    /// # Arguments
+    ///
+    /// * `object`: The object, as held by a table.
+    pub fn contains(&self, object: &[u8]) -> bool {
+        (0..self.ranges.len()).any(|i| self.within(i, object))
+    }
+
+    /// Returns true if an object is on the segment being drained, and should be demoted whether
+    /// it is hot or not.
+    ///
+    /// # Arguments
+    ///
+    /// * `object`: The object, as held by a table.
+    pub fn draining(&self, object: &[u8]) -> bool {
+        match self.draining.load(Ordering::Relaxed) {
+            NOT_DRAINING => false,
+            i => self.within(i, object),
+        }
+    }
+
+    /// Copies an object into the arena, moving on to the next segment if the current one is
+    /// full. Refused if the object is too large, or if the next segment still has objects on
+    /// it, in which case it starts being drained.
+    ///
+    /// # Arguments
+    ///
+    /// * `object`: The object to copy.
+    ///
+    /// # Return
+    ///
+    /// A handle to the copy, or None if it was refused.
+    pub fn carve(&self, object: &[u8]) -> Option<Bytes> {
+        let len = object.len();
+        if len == 0 || len > self.segment / MAX_OBJECT_FRACTION {
+            self.refused.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+
+        let mut ring = self.ring.lock();
+        loop {
+            let current = ring.current;
+            if let Some(ref mut segment) = ring.segments[current] {
+                if segment.free.len() >= len {
+                    let mut copy = segment.free.split_to(len);
+                    copy.copy_from_slice(object);
+                    segment.used += len;
+                    self.used.fetch_add(len, Ordering::Relaxed);
+                    return Some(copy.freeze());
+                }
+
+                // The rest of the segment is let go of, so that it can tell once it's objects
+                // are gone.
+                segment.free = BytesMut::new();
+            }
+
+            let next = (current + 1) % ring.segments.len();
+            if !self.reclaim(&mut ring, next) {
+                self.draining.store(next, Ordering::Relaxed);
+                self.refused.fetch_add(1, Ordering::Relaxed);
+                return None;
+            }
+            ring.current = next;
+        }
+    }
+
+    /// Counts a native get(), and whether the object it read was served out of the arena.
+    ///
+    /// # Arguments
+    ///
+    /// * `object`: The object the get() read, as returned by Table::get().
+    pub fn count(&self, object: &[u8]) {
+        self.gets.fetch_add(1, Ordering::Relaxed);
+        if self.contains(object) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Returns the arena's counters.
+    pub fn stats(&self) -> HotStats {
+        HotStats {
+            capacity: self.capacity() as u64,
+            used: self.used.load(Ordering::Relaxed) as u64,
+            promotions: self.promotions.load(Ordering::Relaxed) as u64,
+            demotions: self.demotions.load(Ordering::Relaxed) as u64,
+            refused: self.refused.load(Ordering::Relaxed) as u64,
+            raced: self.raced.load(Ordering::Relaxed) as u64,
+            gets: self.gets.load(Ordering::Relaxed) as u64,
+            hits: self.hits.load(Ordering::Relaxed) as u64,
+        }
+    }
+
+    // Returns true if an object lies on a segment.
+    fn within(&self, i: usize, object: &[u8]) -> bool {
+        let addr = object.as_ptr() as usize;
+        let (ref start, ref end) = self.ranges[i];
+        addr >= start.load(Ordering::Relaxed) && addr < end.load(Ordering::Relaxed)
+    }
+
+    // Opens a fresh segment in a slot on the ring, if the one there has no objects left on it.
+    // The caller must hold the ring's lock, and must have let go of the slot's free bytes.
+    fn reclaim(&self, ring: &mut Ring, i: usize) -> bool {
+        if let Some(segment) = ring.segments[i].take() {
+            let Segment { pin, free, used } = segment;
+            drop(free);
+            match pin.try_mut() {
+                Ok(_) => {
+                    self.used.fetch_sub(used, Ordering::Relaxed);
+                }
+
+                Err(pin) => {
+                    ring.segments[i] = Some(Segment {
+                        pin: pin,
+                        free: BytesMut::new(),
+                        used: used,
+                    });
+                    return false;
+                }
+            }
+        }
+
+        ring.segments[i] = Some(self.open(i));
+        let _ =
+            self.draining
+                .compare_exchange(i, NOT_DRAINING, Ordering::Relaxed, Ordering::Relaxed);
+        true
+    }
+
+    // Allocates a segment, aligning it's first object to a page (a huge page if asked for), and
+    // faults every page of it in up front.
+    fn open(&self, i: usize) -> Segment {
+        let mut buf = BytesMut::with_capacity(self.segment + self.align);
+        let pad = self.align - buf.as_ptr() as usize % self.align;
+        if self.align == SEGMENT_SIZE {
+            let start = unsafe { buf.as_ptr().offset(pad as isize) };
+            let advised = unsafe {
+                libc::madvise(
+                    start as *mut libc::c_void,
+                    self.segment,
+                    libc::MADV_HUGEPAGE,
+                )
+            };
+            if advised != 0 {
+                warn!("Failed to back a segment of the hot arena with huge pages");
+            }
+        }
+        buf.extend(repeat(0).take(pad + self.segment));
+
+        let pin = buf.split_to(pad).freeze();
+        let start = buf.as_ptr() as usize;
+        let (ref lo, ref hi) = self.ranges[i];
+        hi.store(0, Ordering::Relaxed);
+        lo.store(start, Ordering::Relaxed);
+        hi.store(start + self.segment, Ordering::Relaxed);
+
+        Segment {
+            pin: pin,
+            free: buf,
+            used: 0,
+        }
+    }
+}
+
+/// A walk over every table, moving objects in and out of the hot arena. The walk goes one table
+/// bucket at a time, and a new pass starts atmost once a window, so that the reads folded into
+/// each object's heat on a pass are roughly those of a window.
+pub struct Walk {
+    arena: Arc<HotArena>,
+
+    // The tables walked on this pass, along with the tenant owning each.
+    tables: Vec<(TenantId, TableId, Arc<Table>)>,
+
+    // The table on `tables`, and the bucket on it, the walk is at.
+    table: usize,
+    bucket: usize,
+
+    // The time-stamp in cycles at which the current pass started.
+    started: u64,
+}
+
+impl Walk {
+    /// Returns a walk that has no tables to walk yet. Refer to start().
+    ///
+    /// # Arguments
+    ///
+    /// * `arena`: The arena objects are moved in and out of.
+    pub fn new(arena: Arc<HotArena>) -> Walk {
+        Walk {
+            arena: arena,
+            tables: Vec::new(),
+            table: 0,
+            bucket: 0,
+            started: 0,
+        }
+    }
+
+    /// Starts a new pass over a set of tables, abandoning the current one. Reads are counted on
+    /// every table from here on.
+    ///
+    /// # Arguments
+    ///
+    /// * `tables`: The tables to walk, along with the tenant owning each. Refer to
+    ///             Master::writable_tables().
+    pub fn start(&mut self, tables: Vec<(TenantId, TableId, Arc<Table>)>) {
+        for &(_, _, ref table) in tables.iter() {
+            table.set_count_reads(true);
+        }
+
+        self.tables = tables;
+        self.table = 0;
+        self.bucket = 0;
+        self.started = cycles::rdtsc();
+    }
+
+    /// Returns true once every bucket on the current pass has been walked.
+    pub fn done(&self) -> bool {
+        self.table >= self.tables.len()
+    }
+
+    /// Returns true once a window has gone by since the current pass started.
+    pub fn due(&self) -> bool {
+        let window = self.arena.policy.window_ms * cycles::cycles_per_second() / 1000;
+        cycles::rdtsc().saturating_sub(self.started) >= window
+    }
+
+    /// Walks the next bucket on the pass, promoting the objects on it that stayed hot and
+    /// demoting the ones that stayed cold or are on the segment being drained.
+    ///
+    /// # Return
+    ///
+    /// The number of objects moved in or out of the arena.
+    pub fn step(&mut self) -> usize {
+        if self.done() {
+            return 0;
+        }
+
+        let arena = Arc::clone(&self.arena);
+        let table = Arc::clone(&self.tables[self.table].2);
+        let windows = arena.policy.windows as isize;
+        let candidates =
+            table.bucket_heat(
+                self.bucket,
+                arena.policy.reads,
+                |object, streak| match arena.contains(object) {
+                    true => streak <= -windows || arena.draining(object),
+                    false => streak >= windows,
+                },
+            );
+
+        let mut moved = 0;
+        for (key, version, object) in candidates.into_iter() {
+            let (copy, counter) = match arena.contains(&object) {
+                true => (Some(Bytes::from(&object[..])), &arena.demotions),
+                false => (arena.carve(&object), &arena.promotions),
+            };
+
+            let copy = match copy {
+                Some(copy) => copy,
+                None => continue,
+            };
+
+            match table.relocate(&key, version, &object, copy) {
+                true => {
+                    counter.fetch_add(1, Ordering::Relaxed);
+                    moved += 1;
+                }
+
+                false => {
+                    arena.raced.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+
+        self.bucket += 1;
+        if self.bucket == N_BUCKETS {
+            self.table += 1;
+            self.bucket = 0;
+        }
+        moved
+    }
+}
+
+/// Returns a task that runs a walk at background priority until the server drains. A new pass
+/// starts over the tables at the time once the last one completes and a window has gone by, so
+/// tables created in the meantime are picked up.
+///
+/// # Arguments
+///
+/// * `master`: The service whose tables are walked.
+/// * `walk`:   The walk to run. Refer to Master::hot_walk().
+///
+/// # Return
+///
+/// A task that never sends out a response.
+pub fn task(master: Arc<Master>, mut walk: Walk) -> Box<Task> {
+    let gen = Box::new(move || {
+        while !master.drain().draining() {
+            if walk.done() && walk.due() {
+                walk.start(master.writable_tables());
+            }
+
+            walk.step();
+            yield 0;
+        }
+
+        return None;
+    });
+
+    Box::new(Native::new(TaskPriority::BACKGROUND, gen))
+}
+
+// This module contains tests for the hot arena. Objects are made hot by reading them between
+// passes of the walk, which are started by hand instead of once a window.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::AtomicBool;
+    use std::thread;
+
+    use super::super::alloc::Allocator;
+
+    // Returns a policy under which an object read atleast three times between passes, two
+    // passes in a row, is promoted, and one read less than that two passes in a row is demoted.
+    fn policy(bytes: usize) -> HotPolicy {
+        HotPolicy::new(bytes, false, 3, 0, 2)
+    }
+
+    // Returns a table holding `num` objects with 32 byte values, keyed by their index.
+    fn table(heap: &Allocator, num: u8) -> Arc<Table> {
+        let table = Arc::new(Table::default());
+        for i in 0..num {
+            let (k, obj) = heap.object(1, 7, &[i, 0], &[i; 32]).unwrap();
+            heap.store(&table, k, obj);
+        }
+        table
+    }
+
+    // Runs a full pass of a walk over a table.
+    fn pass(walk: &mut Walk, table: &Arc<Table>) -> usize {
+        walk.start(vec![(1, 7, Arc::clone(table))]);
+        let mut moved = 0;
+        while !walk.done() {
+            moved += walk.step();
+        }
+        moved
+    }
+
+    // Tests that an object's heat counts the windows in a row it was hot or cold for, and sees
+    // through writes that reset the reads counted against it.
+    #[test]
+    fn test_fold() {
+        let (heat, streak) = fold(0, 0, 2);
+        assert_eq!(-1, streak);
+        let (heat, streak) = fold(heat, 1, 2);
+        assert_eq!(-2, streak);
+        let (heat, streak) = fold(heat, 3, 2);
+        assert_eq!(1, streak);
+        let (heat, streak) = fold(heat, 5, 2);
+        assert_eq!(2, streak);
+
+        // A write reset the count; the 4 reads counted were all made since.
+        let (heat, streak) = fold(heat, 4, 2);
+        assert_eq!(3, streak);
+        let (_, streak) = fold(heat, 4, 2);
+        assert_eq!(-1, streak);
+
+        // Streaks stop growing instead of wrapping around.
+        let mut heat = 0;
+        for i in 0..1000 {
+            heat = fold(heat, i * 2, 2).0;
+        }
+        assert_eq!(STREAK_MAX as isize, fold(heat, 2000, 2).1);
+    }
+
+    // Tests that the arena carves objects off one segment after the other, refuses objects once
+    // it is full or when they are too large, and only reuses a segment once every object on it
+    // is gone.
+    #[test]
+    fn test_arena_bounded() {
+        let arena = HotArena::new(policy(8192), 4096);
+        assert_eq!(8192, arena.capacity());
+        assert!(arena.carve(&[1; 4096 / 8 + 1]).is_none());
+
+        let mut objects = Vec::new();
+        while let Some(object) = arena.carve(&[7; 512]) {
+            assert!(arena.contains(&object));
+            assert_eq!(&[7; 512][..], &object[..]);
+            objects.push(object);
+        }
+        assert_eq!(16, objects.len());
+        assert_eq!(8192, arena.stats().used);
+        assert!(!arena.contains(&[7; 512]));
+
+        // The first segment is drained, but is only reused once the last object on it is gone.
+        assert!(arena.draining(&objects[0]));
+        assert!(!arena.draining(&objects[8]));
+        objects.truncate(8 + 1);
+        objects.drain(..7);
+        assert!(arena.carve(&[8; 512]).is_none());
+        objects.remove(0);
+        let object = arena.carve(&[8; 512]).expect("Segment was not reused");
+        assert!(!arena.draining(&objects[0]));
+        assert_eq!(
+            (4096 + 512, 2),
+            (arena.stats().used, arena.stats().refused - 1)
+        );
+        assert_eq!(&[8; 512][..], &object[..]);
+    }
+
+    // Tests that objects read enough in a row are promoted, that reads are served off the copy
+    // at the same version, and that objects that cool down are demoted again.
+    #[test]
+    fn test_promote_demote() {
+        let mut heap = Allocator::new();
+        let arena = Arc::new(HotArena::new(policy(1 << 16), 4096));
+        heap.set_hot_arena(Some(Arc::clone(&arena)));
+        let table = table(&heap, 16);
+        let mut walk = Walk::new(Arc::clone(&arena));
+        let version = table.get(&[3, 0]).unwrap().version;
+
+        // Reads are only counted from the first pass on.
+        pass(&mut walk, &table);
+        for _ in 0..2 {
+            for _ in 0..4 {
+                table.get(&[3, 0]);
+            }
+            table.get(&[4, 0]);
+            pass(&mut walk, &table);
+        }
+
+        let entry = table.get(&[3, 0]).unwrap();
+        assert!(arena.contains(&entry.value));
+        assert!(version == entry.version);
+        assert_eq!(
+            Some(Bytes::from(&[3; 32][..])),
+            heap.resolve(entry.value).map(|kv| kv.1)
+        );
+        assert!(!arena.contains(&table.get(&[4, 0]).unwrap().value));
+        assert_eq!(1, arena.stats().promotions);
+
+        // A get of the object counts as a hit.
+        heap.count_hot(&table.get(&[3, 0]).unwrap().value);
+        heap.count_hot(&table.get(&[4, 0]).unwrap().value);
+        assert_eq!(0.5, heap.hot_stats().unwrap().hit_fraction());
+
+        // The object cools down.
+        pass(&mut walk, &table);
+        assert_eq!(0, arena.stats().demotions);
+        pass(&mut walk, &table);
+        let entry = table.get(&[3, 0]).unwrap();
+        assert!(!arena.contains(&entry.value));
+        assert!(version == entry.version);
+        assert_eq!(1, arena.stats().demotions);
+    }
+
+    // Tests that relocating an object keeps it's version, and does not leave the table's index
+    // holding on to the old object through a key that slices into it, and that a write made
+    // after an object was copied wins over the copy.
+    #[test]
+    fn test_relocate() {
+        let heap = Allocator::new();
+        let table = table(&heap, 1);
+        let entry = table.get(&[0, 0]).unwrap();
+        let (version, old) = (entry.version, entry.value);
+        let copy = Bytes::from(&old[..]);
+
+        assert!(table.relocate(&[0, 0], version, &old, copy.clone()));
+        let relocated = table.get(&[0, 0]).unwrap();
+        assert!(version == relocated.version);
+        assert_eq!(copy.as_ptr(), relocated.value.as_ptr());
+        assert!(old.try_mut().is_ok());
+
+        let (k, obj) = heap.object(1, 7, &[0, 0], &[9; 32]).unwrap();
+        heap.store(&table, k, obj);
+        let latest = table.get(&[0, 0]).unwrap();
+        let (k, obj) = heap.object(1, 7, &[0, 0], &[8; 32]).unwrap();
+        heap.store(&table, k, obj);
+
+        let copy = Bytes::from(&latest.value[..]);
+        assert!(!table.relocate(&[0, 0], latest.version, &latest.value, copy.clone()));
+        assert!(!table.relocate(&[1, 0], latest.version, &latest.value, copy));
+        let (_, value) = heap.resolve(table.get(&[0, 0]).unwrap().value).unwrap();
+        assert_eq!(&[8; 32][..], &value[..]);
+    }
+
+    // Tests that objects moved in and out of a small arena by a walk that never stops are never
+    // lost or seen going back in time by readers, and that no write racing a relocation is lost.
+    #[test]
+    fn test_relocation_races() {
+        let mut heap = Allocator::new();
+        let arena = Arc::new(HotArena::new(HotPolicy::new(8192, false, 1, 0, 1), 4096));
+        heap.set_hot_arena(Some(Arc::clone(&arena)));
+        let heap = Arc::new(heap);
+        let table = table(&heap, 64);
+        let done = Arc::new(AtomicBool::new(false));
+
+        let walker = {
+            let (table, done) = (Arc::clone(&table), Arc::clone(&done));
+            let mut walk = Walk::new(Arc::clone(&arena));
+            thread::spawn(move || {
+                while !done.load(Ordering::Relaxed) {
+                    pass(&mut walk, &table);
+                }
+            })
+        };
+
+        // Each writer bumps a counter at the head of the values of it's own keys.
+        let writers: Vec<_> = (0..2u8)
+            .map(|w| {
+                let (table, heap) = (Arc::clone(&table), Arc::clone(&heap));
+                thread::spawn(move || {
+                    for _ in 0..2000 {
+                        for i in (0..8u8).map(|i| i * 2 + w) {
+                            table.update(&[i, 0], |old| {
+                                let (_, value) = heap.resolve(old.unwrap().value).unwrap();
+                                let mut value = value.to_vec();
+                                value[0] = value[0].wrapping_add(1);
+                                let (k, obj) = heap.object(1, 7, &[i, 0], &value).unwrap();
+                                Ok::<_, ()>(heap.prepare(&table, k, obj))
+                            });
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        let readers: Vec<_> = (0..2)
+            .map(|_| {
+                let (table, heap) = (Arc::clone(&table), Arc::clone(&heap));
+                thread::spawn(move || {
+                    let mut versions = vec![0; 64];
+                    for _ in 0..20000 {
+                        for i in 0..64u8 {
+                            let entry = table.get(&[i, 0]).expect("Object was lost");
+                            let number = entry.version.number();
+                            assert!(number >= versions[i as usize]);
+                            versions[i as usize] = number;
+
+                            let (key, value) = heap.resolve(entry.value).unwrap();
+                            assert_eq!(&[i, 0][..], &key[..]);
+                            assert!(value[1..].iter().all(|b| *b == i));
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        for handle in writers.into_iter().chain(readers.into_iter()) {
+            handle.join().expect("Thread failed");
+        }
+        done.store(true, Ordering::Relaxed);
+        walker.join().expect("Walk failed");
+
+        // 2000 increments of each written key; 2000 % 256 is 208.
+        for i in 0..16u8 {
+            let (_, value) = heap.resolve(table.get(&[i, 0]).unwrap().value).unwrap();
+            assert_eq!(i.wrapping_add(208), value[0], "key {}", i);
+            assert_eq!(2001, table.version(&[i, 0]).unwrap().number());
+        }
+
+        let stats = arena.stats();
+        assert!(stats.promotions > 0 && stats.demotions > 0);
+        assert!(stats.used <= 8192);
+    }
+}
@@ -0,0 +1,648 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+use std::cmp::max;
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::ops::Bound;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::{ptr, slice};
+
+use bytes::Bytes;
+use libc;
+
+use sandstorm::common::{TableId, TenantId};
+
+use super::journal::{crc32, crc32_update, put_le};
+use super::kvformat::Reader;
+use super::table::Table;
+
+/// Identifies a file as a read-only table image.
+pub const MAGIC: [u8; 8] = *b"SPLTIMG\0";
+
+/// The version of the image format written by build(). Images of any other version are refused.
+pub const VERSION: u32 = 1;
+
+/// The length of the header at the front of every image.
+pub const HEADER_LEN: usize = 64;
+
+// The length of each entry in the index; the offset of an object in the values section (8
+// bytes), it's length (4 bytes), and 4 reserved bytes.
+const ENTRY_LEN: usize = 16;
+
+// The metadata at the head of every object; the tenant (4 bytes), the table (8 bytes), and the
+// length of the key (2 bytes). Objects are laid out exactly as Allocator lays them out on the
+// heap, so that Allocator::resolve() works on them unchanged.
+const META_LEN: usize = 14;
+
+// Keys at least this long would set the bits Allocator uses to mark a value compressed or an
+// object checksummed.
+const MAX_KEY_LEN: usize = 1 << 14;
+
+/// A table mapped in from an image built by build(). The image holds a header, followed by an
+/// index of every object sorted by key, followed by the objects themselves. The header is laid
+/// out as (all little endian)
+///
+/// |magic 8|version 4|header_len 4|tenant 4|reserved 4|table 8|num_objects 8|values_len 8|
+/// |index_crc 4|values_crc 4|reserved 4|header_crc 4|
+///
+/// where the header CRC covers every field before it. Objects are ordered by the bucket their
+/// key falls into on a Table, and then by key, so that a bucket of the table is a contiguous run
+/// of the index that can be scanned in key order.
+///
+/// Images are never unmapped, even once the table is dropped. Objects are handed out as Bytes
+/// referring directly into the mapping, and these can outlive the table.
+pub struct ReadOnlyTable {
+    // The tenant and table the image was built for.
+    tenant: TenantId,
+    table_id: TableId,
+
+    // The number of objects in the image.
+    num: usize,
+
+    // The index, and the section holding the objects the index points into.
+    index: &'static [u8],
+    values: &'static [u8],
+}
+
+impl ReadOnlyTable {
+    /// This function maps in an image from a file and validates it. The file must not be
+    /// modified while the server is running.
+    ///
+    /// # Arguments
+    ///
+    /// * `path`: The path to an image built by build().
+    ///
+    /// # Return
+    ///
+    /// The table, or an error if the file could not be mapped or is not a valid image.
+    pub fn open(path: &Path) -> io::Result<ReadOnlyTable> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len() as usize;
+        if len < HEADER_LEN {
+            return Err(invalid("image is shorter than it's header"));
+        }
+
+        let addr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if addr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        // The mapping is never unmapped, so it lives for as long as the process does.
+        let image = unsafe { slice::from_raw_parts(addr as *const u8, len) };
+        let table = ReadOnlyTable::from_static(image);
+        if table.is_err() {
+            unsafe {
+                libc::munmap(addr, len);
+            }
+        }
+        table
+    }
+
+    /// This function validates an image that is already in memory. Every checksum is verified,
+    /// and every entry in the index is checked to lie within the image and to be in order, so
+    /// that lookups never have to check either.
+    ///
+    /// # Arguments
+    ///
+    /// * `image`: The image, as written by build().
+    ///
+    /// # Return
+    ///
+    /// The table, or an error describing what is wrong with the image.
+    pub fn from_static(image: &'static [u8]) -> io::Result<ReadOnlyTable> {
+        if image.len() < HEADER_LEN {
+            return Err(invalid("image is shorter than it's header"));
+        }
+        if image[0..8] != MAGIC {
+            return Err(invalid("bad magic; not a table image"));
+        }
+
+        let version = le(&image[8..12]) as u32;
+        if version != VERSION {
+            return Err(invalid(&format!(
+                "image version {} is not supported; expected {}",
+                version, VERSION
+            )));
+        }
+        if le(&image[60..64]) as u32 != crc32(&image[0..60]) {
+            return Err(invalid("header checksum mismatch"));
+        }
+
+        let header_len = le(&image[12..16]) as usize;
+        let num = le(&image[32..40]) as usize;
+        let values_len = le(&image[40..48]) as usize;
+        let expected = num
+            .checked_mul(ENTRY_LEN)
+            .and_then(|index| index.checked_add(values_len))
+            .and_then(|body| body.checked_add(header_len));
+        if header_len < HEADER_LEN || expected != Some(image.len()) {
+            return Err(invalid("image length does not match it's header"));
+        }
+
+        let (index, values) = image[header_len..].split_at(num * ENTRY_LEN);
+        if le(&image[48..52]) as u32 != crc32(index) {
+            return Err(invalid("index checksum mismatch"));
+        }
+        if le(&image[52..56]) as u32 != crc32(values) {
+            return Err(invalid("values checksum mismatch"));
+        }
+
+        let table = ReadOnlyTable {
+            tenant: le(&image[16..20]) as TenantId,
+            table_id: le(&image[24..32]) as TableId,
+            num: num,
+            index: index,
+            values: values,
+        };
+
+        for i in 0..num {
+            let entry = &index[i * ENTRY_LEN..(i + 1) * ENTRY_LEN];
+            let (offset, len) = (le(&entry[0..8]) as usize, le(&entry[8..12]) as usize);
+            if offset > values.len() || len > values.len() - offset || len < META_LEN {
+                return Err(invalid(&format!("object {} lies outside the image", i)));
+            }
+
+            let object = &values[offset..offset + len];
+            let key_len = le(&object[META_LEN - 2..META_LEN]) as usize;
+            if key_len == 0 || key_len >= MAX_KEY_LEN || META_LEN + key_len > len {
+                return Err(invalid(&format!("object {} has a malformed key", i)));
+            }
+
+            if i > 0 && order(table.key(i - 1)) >= order(table.key(i)) {
+                return Err(invalid(&format!("object {} is out of order", i)));
+            }
+        }
+
+        Ok(table)
+    }
+
+    /// This function returns the tenant the image was built for.
+    pub fn tenant(&self) -> TenantId {
+        self.tenant
+    }
+
+    /// This function returns the identifier of the table the image was built for.
+    pub fn table_id(&self) -> TableId {
+        self.table_id
+    }
+
+    /// This function returns the number of objects in the image.
+    pub fn len(&self) -> usize {
+        self.num
+    }
+
+    /// This function returns the length of the longest key and of the largest value in the
+    /// image, so that an image with objects the server would refuse can be refused too.
+    pub fn largest(&self) -> (usize, usize) {
+        (0..self.num).fold((0, 0), |(key, val), i| {
+            let (len, key_len) = (self.object(i).len(), self.key(i).len());
+            (max(key, key_len), max(val, len - META_LEN - key_len))
+        })
+    }
+
+    /// This function looks up an object by binary searching the index.
+    ///
+    /// # Arguments
+    ///
+    /// * `key`: The object's key.
+    ///
+    /// # Return
+    ///
+    /// A handle to the object referring directly into the image if it exists. The object is
+    /// laid out as Allocator lays out objects, metadata and key included.
+    pub fn get(&self, key: &[u8]) -> Option<Bytes> {
+        if key.is_empty() {
+            return None;
+        }
+
+        let target = order(key);
+        let i = self.partition(|k| order(k) < target);
+        match i < self.num && self.key(i) == key {
+            true => Some(Bytes::from_static(self.object(i))),
+            false => None,
+        }
+    }
+
+    /// This function returns every object whose key falls into one bucket of a Table.
+    ///
+    /// # Arguments
+    ///
+    /// * `bucket`: The index of the bucket, less than table::N_BUCKETS.
+    ///
+    /// # Return
+    ///
+    /// Handles to the objects in the bucket in key order, referring directly into the image.
+    pub fn bucket(&self, bucket: usize) -> Vec<Bytes> {
+        let start = self.partition(|k| Table::bucket(k) < bucket);
+        let end = self.partition(|k| Table::bucket(k) <= bucket);
+        (start..end)
+            .map(|i| Bytes::from_static(self.object(i)))
+            .collect()
+    }
+
+    /// This function returns the objects whose keys fall into one bucket of a Table and within
+    /// a range, along with their keys. Refer to bucket(). Takes time proportional to the number
+    /// of objects returned, not the size of the bucket.
+    ///
+    /// # Arguments
+    ///
+    /// * `bucket`: The index of the bucket, less than table::N_BUCKETS.
+    /// * `from`:   Only objects with keys within this bound are returned.
+    /// * `to`:     Only objects with keys within this bound are returned.
+    /// * `max`:    The most objects returned.
+    ///
+    /// # Return
+    ///
+    /// The key and object of atmost `max` objects in the bucket and range, the ones with the
+    /// smallest keys, in key order. Both refer directly into the image.
+    pub fn range(
+        &self,
+        bucket: usize,
+        from: Bound<&[u8]>,
+        to: Bound<&[u8]>,
+        max: usize,
+    ) -> Vec<(Bytes, Bytes)> {
+        let below = |k: &[u8]| match from {
+            Bound::Included(start) => k < start,
+            Bound::Excluded(start) => k <= start,
+            Bound::Unbounded => false,
+        };
+        let within = |k: &[u8]| match to {
+            Bound::Included(end) => k <= end,
+            Bound::Excluded(end) => k < end,
+            Bound::Unbounded => true,
+        };
+
+        let start = self
+            .partition(|k| Table::bucket(k) < bucket || (Table::bucket(k) == bucket && below(k)));
+        (start..self.num)
+            .take_while(|&i| Table::bucket(self.key(i)) == bucket && within(self.key(i)))
+            .take(max)
+            .map(|i| {
+                (
+                    Bytes::from_static(self.key(i)),
+                    Bytes::from_static(self.object(i)),
+                )
+            })
+            .collect()
+    }
+
+    // Returns the first object in the index whose key is not `before`. Every key that is
+    // `before` must sort ahead of every key that is not.
+    fn partition<F: Fn(&[u8]) -> bool>(&self, before: F) -> usize {
+        let (mut lo, mut hi) = (0, self.num);
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            match before(self.key(mid)) {
+                true => lo = mid + 1,
+                false => hi = mid,
+            }
+        }
+        lo
+    }
+
+    // Returns the i'th object in the index. from_static() has checked that it is in bounds.
+    fn object(&self, i: usize) -> &'static [u8] {
+        let entry = &self.index[i * ENTRY_LEN..(i + 1) * ENTRY_LEN];
+        let offset = le(&entry[0..8]) as usize;
+        &self.values[offset..offset + le(&entry[8..12]) as usize]
+    }
+
+    // Returns the key of the i'th object in the index.
+    fn key(&self, i: usize) -> &'static [u8] {
+        let object = self.object(i);
+        let key_len = le(&object[META_LEN - 2..META_LEN]) as usize;
+        &object[META_LEN..META_LEN + key_len]
+    }
+}
+
+/// This function builds a read-only table image out of a file of records written by
+/// kvformat::Writer. The records are read in whole before the image is written, since the image
+/// orders them by key. The output must be seekable since the header, which carries checksums
+/// over the rest of the image, is written last.
+///
+/// # Arguments
+///
+/// * `out`:      The image is written here.
+/// * `tenant`:   The tenant the image's table will be registered under.
+/// * `table_id`: The identifier the image's table will be registered under.
+/// * `input`:    The records to be written to the image, in any order.
+///
+/// # Return
+///
+/// The number of objects written, or an error if the input could not be read (refer to
+/// kvformat::Reader::next()), has overlong or duplicate keys, or the image could not be written.
+pub fn build<W: Write + Seek, R: Read>(
+    out: &mut W,
+    tenant: TenantId,
+    table_id: TableId,
+    input: &mut Reader<R>,
+) -> io::Result<usize> {
+    let mut records = Vec::new();
+    while let Some((key, val)) = input.next()? {
+        if key.len() >= MAX_KEY_LEN {
+            return Err(invalid(&format!(
+                "record {} has a bad key length",
+                records.len()
+            )));
+        }
+        records.push((key.to_vec(), val.to_vec()));
+    }
+
+    records.sort_by(|a, b| order(&a.0).cmp(&order(&b.0)));
+    for pair in records.windows(2) {
+        if pair[0].0 == pair[1].0 {
+            return Err(invalid("duplicate key"));
+        }
+    }
+
+    // The header is written once the checksums are known.
+    out.seek(SeekFrom::Start(0))?;
+    out.write_all(&[0; HEADER_LEN])?;
+
+    let mut index = Vec::with_capacity(records.len() * ENTRY_LEN);
+    let mut offset = 0;
+    for &(ref key, ref val) in records.iter() {
+        let len = META_LEN + key.len() + val.len();
+        if len > u32::max_value() as usize {
+            return Err(invalid("object does not fit in 4 GB"));
+        }
+
+        put_le(&mut index, offset as u64, 8);
+        put_le(&mut index, len as u64, 4);
+        put_le(&mut index, 0, 4);
+        offset += len;
+    }
+    out.write_all(&index)?;
+
+    let mut values_crc = 0;
+    let mut meta = Vec::with_capacity(META_LEN);
+    for &(ref key, ref val) in records.iter() {
+        meta.clear();
+        put_le(&mut meta, tenant as u64, 4);
+        put_le(&mut meta, table_id, 8);
+        put_le(&mut meta, key.len() as u64, 2);
+
+        values_crc = crc32_update(crc32_update(values_crc, &meta), key);
+        values_crc = crc32_update(values_crc, val);
+        out.write_all(&meta)?;
+        out.write_all(key)?;
+        out.write_all(val)?;
+    }
+
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    header.extend_from_slice(&MAGIC);
+    put_le(&mut header, VERSION as u64, 4);
+    put_le(&mut header, HEADER_LEN as u64, 4);
+    put_le(&mut header, tenant as u64, 4);
+    put_le(&mut header, 0, 4);
+    put_le(&mut header, table_id, 8);
+    put_le(&mut header, records.len() as u64, 8);
+    put_le(&mut header, offset as u64, 8);
+    put_le(&mut header, crc32(&index) as u64, 4);
+    put_le(&mut header, values_crc as u64, 4);
+    put_le(&mut header, 0, 4);
+    let header_crc = crc32(&header);
+    put_le(&mut header, header_crc as u64, 4);
+
+    out.seek(SeekFrom::Start(0))?;
+    out.write_all(&header)?;
+    out.flush()?;
+
+    Ok(records.len())
+}
+
+// The order objects are laid out in on an image; by the bucket their key falls into on a Table,
+// and then by key.
+fn order(key: &[u8]) -> (usize, &[u8]) {
+    (Table::bucket(key), key)
+}
+
+// Reads a little endian integer that takes up all of `buf`.
+fn le(buf: &[u8]) -> u64 {
+    buf.iter()
+        .enumerate()
+        .fold(0, |acc, (i, b)| acc | (*b as u64) << (8 * i))
+}
+
+// Returns an error for an image or input that is malformed.
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::io::Cursor;
+    use std::path::PathBuf;
+    use std::process;
+
+    use super::super::alloc::Allocator;
+    use super::super::kvformat::{Header, OnCorrupt, Writer};
+    use super::super::table::N_BUCKETS;
+
+    // Returns the records of a small dataset; keys 1 to 200 as 4 byte little endian integers,
+    // each with a value repeating the key's low byte.
+    fn records() -> Vec<(Vec<u8>, Vec<u8>)> {
+        (1..201u32)
+            .rev()
+            .map(|i| {
+                let key = vec![i as u8, (i >> 8) as u8, 0, 0];
+                (key, vec![i as u8; 8 + i as usize % 5])
+            })
+            .collect()
+    }
+
+    // Writes a set of records out to a file of them.
+    fn input(records: &[(Vec<u8>, Vec<u8>)]) -> Vec<u8> {
+        let mut writer = Writer::new(Vec::new(), Header::new(7, 3)).unwrap();
+        for &(ref key, ref val) in records.iter() {
+            writer.write(key, val).unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    // Builds an image out of a file of records.
+    fn build_from(input: &[u8]) -> io::Result<Vec<u8>> {
+        let mut out = Cursor::new(Vec::new());
+        let mut reader = Reader::new(input, OnCorrupt::Abort)?;
+        build(&mut out, 7, 3, &mut reader)?;
+        Ok(out.into_inner())
+    }
+
+    // Builds an image out of a set of records, leaking it so that it can be loaded.
+    fn build_image(records: &[(Vec<u8>, Vec<u8>)]) -> &'static mut [u8] {
+        let image = build_from(&input(records)).unwrap();
+        Box::leak(image.into_boxed_slice())
+    }
+
+    // Returns a path to a fresh image file for a test.
+    fn path(test: &str) -> PathBuf {
+        let mut path = env::temp_dir();
+        path.push(format!("sandstorm-image-{}-{}", test, process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    // Tests that every object can be looked up and resolved, and that missing keys miss.
+    #[test]
+    fn test_image_lookup() {
+        let table = ReadOnlyTable::from_static(build_image(&records())).unwrap();
+        assert_eq!((7, 3, 200), (table.tenant(), table.table_id(), table.len()));
+
+        let heap = Allocator::new();
+        for i in 1..201u32 {
+            let key = [i as u8, (i >> 8) as u8, 0, 0];
+            let (k, v) = heap.resolve(table.get(&key).unwrap()).unwrap();
+            assert_eq!(&key[..], &k[..]);
+            assert_eq!(vec![i as u8; 8 + i as usize % 5], &v[..]);
+        }
+
+        let misses = [
+            &[0u8, 0, 0, 0][..],
+            &[201, 0, 0, 0][..],
+            &[1, 0, 0][..],
+            &[1, 0, 0, 0, 0][..],
+            &[][..],
+        ];
+        for key in misses.iter() {
+            assert!(table.get(key).is_none());
+        }
+    }
+
+    // Tests that each bucket of a table holds the objects whose keys fall into it in key order,
+    // and that every object is in exactly one bucket.
+    #[test]
+    fn test_image_scan() {
+        let table = ReadOnlyTable::from_static(build_image(&records())).unwrap();
+        let heap = Allocator::new();
+
+        let mut found = 0;
+        for bucket in 0..N_BUCKETS {
+            let keys: Vec<Bytes> = table
+                .bucket(bucket)
+                .into_iter()
+                .map(|object| heap.resolve(object).unwrap().0)
+                .collect();
+            assert!(keys.iter().all(|k| Table::bucket(k) == bucket));
+            assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+            found += keys.len();
+        }
+        assert_eq!(200, found);
+
+        // Keys 1 and 129 fall into bucket 1; so do 257 and beyond, but there are none.
+        assert_eq!(2, table.bucket(1).len());
+    }
+
+    // Tests that the longest key and largest value are found even when on different objects.
+    #[test]
+    fn test_image_largest() {
+        let table = ReadOnlyTable::from_static(build_image(&records())).unwrap();
+        assert_eq!((4, 12), table.largest());
+
+        let mut buf = records();
+        buf.push((vec![7; 30], b"v".to_vec()));
+        buf.push((b"k".to_vec(), vec![7; 300]));
+        let table = ReadOnlyTable::from_static(build_image(&buf)).unwrap();
+        assert_eq!((30, 300), table.largest());
+
+        let table = ReadOnlyTable::from_static(build_image(&[])).unwrap();
+        assert_eq!((0, 0), table.largest());
+    }
+
+    // Tests that malformed inputs are refused by the tool.
+    #[test]
+    fn test_image_build_malformed() {
+        let mut dup = records();
+        dup.push((vec![5, 0, 0, 0], b"again".to_vec()));
+
+        let mut long = records();
+        long.push((vec![1; MAX_KEY_LEN], b"value".to_vec()));
+
+        let mut truncated = input(&records());
+        let len = truncated.len();
+        truncated.truncate(len - 1);
+
+        let mut corrupt = input(&records());
+        corrupt[100] ^= 0x1;
+
+        for input in [input(&dup), input(&long), truncated, corrupt].iter() {
+            assert!(build_from(input).is_err());
+        }
+
+        let table = ReadOnlyTable::from_static(build_image(&[]));
+        assert!(table.unwrap().get(b"key").is_none());
+    }
+
+    // Tests that corrupting any part of an image, or truncating it, is detected on load.
+    #[test]
+    fn test_image_corrupt() {
+        let len = build_image(&records()).len();
+        let index = HEADER_LEN + 10 * ENTRY_LEN + 3;
+        for &(at, what) in [
+            (0, "magic"),
+            (8, "version"),
+            (33, "header checksum"),
+            (index, "index checksum"),
+            (len - 1, "values checksum"),
+        ]
+        .iter()
+        {
+            let image = build_image(&records());
+            image[at] ^= 0x1;
+            match ReadOnlyTable::from_static(image) {
+                Ok(_) => panic!("Corrupt {} was not detected", what),
+                Err(e) => assert!(e.to_string().contains(what.split(' ').next().unwrap())),
+            }
+        }
+
+        let image: &'static [u8] = build_image(&records());
+        assert!(ReadOnlyTable::from_static(&image[..len - 1]).is_err());
+        assert!(ReadOnlyTable::from_static(&image[..HEADER_LEN - 1]).is_err());
+    }
+
+    // Tests that an image written to a file can be mapped in.
+    #[test]
+    fn test_image_open() {
+        let path = path("open");
+        {
+            let mut file = File::create(&path).unwrap();
+            let input = input(&records());
+            let mut reader = Reader::new(&input[..], OnCorrupt::Abort).unwrap();
+            assert_eq!(200, build(&mut file, 7, 3, &mut reader).unwrap());
+        }
+
+        let table = ReadOnlyTable::open(&path).unwrap();
+        assert_eq!(200, table.len());
+        assert!(table.get(&[42, 0, 0, 0]).is_some());
+
+        fs::write(&path, b"not an image").unwrap();
+        assert!(ReadOnlyTable::open(&path).is_err());
+        let _ = fs::remove_file(&path);
+    }
+}
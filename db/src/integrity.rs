@@ -0,0 +1,462 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use super::alloc::Allocator;
+use super::audit::name_hash;
+use super::cycles::{cycles_per_second, rdtsc};
+use super::master::Master;
+use super::native::Native;
+use super::table::{Entry, Table, N_BUCKETS};
+use super::task::{Task, TaskPriority};
+use super::wireformat::RpcStatus;
+
+use sandstorm::common::{TableId, TenantId};
+
+// The most objects the sweep checks before yielding the core, so that requests on the core are
+// held up by atmost a handful of checksums.
+const BATCH: u64 = 16;
+
+// Counters reported by stats(). Shared by all cores.
+static VERIFIED: AtomicUsize = AtomicUsize::new(0);
+static CORRUPT: AtomicUsize = AtomicUsize::new(0);
+static QUARANTINED: AtomicUsize = AtomicUsize::new(0);
+static PASSES: AtomicUsize = AtomicUsize::new(0);
+
+/// Counters of the objects checked against their checksums since the server started.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct IntegrityStats {
+    /// The number of checksummed objects the sweep checked.
+    pub verified: usize,
+
+    /// The number of objects that did not match their checksum, whether found by the sweep or
+    /// by a verified read. An object that is not quarantined is counted each time it is found.
+    pub corrupt: usize,
+
+    /// The number of those objects that were quarantined.
+    pub quarantined: usize,
+
+    /// The number of passes the sweep completed over every checksummed table.
+    pub passes: usize,
+}
+
+/// Returns the integrity counters since the server started.
+pub fn stats() -> IntegrityStats {
+    IntegrityStats {
+        verified: VERIFIED.load(Ordering::Relaxed),
+        corrupt: CORRUPT.load(Ordering::Relaxed),
+        quarantined: QUARANTINED.load(Ordering::Relaxed),
+        passes: PASSES.load(Ordering::Relaxed),
+    }
+}
+
+/// Records an object that did not match it's checksum, and quarantines it if asked to. The
+/// tenant, table and a hash of the key are logged; the key itself is not, since it is tenant
+/// data. An object whose key cannot be read off it is logged, but cannot be quarantined.
+///
+/// # Arguments
+///
+/// * `heap`:       The allocator the object was allocated on.
+/// * `tenant`:     The tenant owning the table.
+/// * `table_id`:   The identifier of the table.
+/// * `table`:      The table the object was read from.
+/// * `entry`:      The object, along with the version it was read at.
+/// * `quarantine`: True if the object should be quarantined.
+///
+/// # Return
+///
+/// True if the object was quarantined. False if it was not asked to be, or was overwritten since
+/// it was read.
+pub fn record_corrupt(
+    heap: &Allocator,
+    tenant: TenantId,
+    table_id: TableId,
+    table: &Table,
+    entry: &Entry,
+    quarantine: bool,
+) -> bool {
+    CORRUPT.fetch_add(1, Ordering::Relaxed);
+
+    let key = match heap.resolve_key(&entry.value) {
+        Some(key) => key,
+        None => {
+            warn!(
+                "Object with an unreadable key failed to verify on tenant {} table {}",
+                tenant, table_id
+            );
+            return false;
+        }
+    };
+
+    let quarantined = quarantine && table.quarantine(&key, entry.version);
+    if quarantined {
+        QUARANTINED.fetch_add(1, Ordering::Relaxed);
+    }
+
+    warn!(
+        "Object failed to verify on tenant {} table {} key hash {:#018x} version {}{}",
+        tenant,
+        table_id,
+        name_hash(&key),
+        entry.version.number(),
+        if quarantined { ", quarantined" } else { "" }
+    );
+    quarantined
+}
+
+/// Checks an object a read is about to return against it's checksum, for requests that asked
+/// for a verified read (refer to REQUEST_FLAG_VERIFY). An object that does not match is recorded
+/// and quarantined as record_corrupt() would. Objects without a checksum pass unchecked.
+///
+/// # Arguments
+///
+/// * `heap`:       The allocator the object was allocated on.
+/// * `tenant`:     The tenant the read was issued by.
+/// * `table_id`:   The identifier of the table.
+/// * `table`:      The table the object was read from.
+/// * `entry`:      The object, along with the version it was read at.
+/// * `quarantine`: True if the object should be quarantined if it does not match.
+///
+/// # Return
+///
+/// StatusDataCorrupted if the object does not match it's checksum.
+pub fn verify_read(
+    heap: &Allocator,
+    tenant: TenantId,
+    table_id: TableId,
+    table: &Table,
+    entry: &Entry,
+    quarantine: bool,
+) -> Result<(), RpcStatus> {
+    match heap.verify(&entry.value) {
+        Some(false) => {
+            record_corrupt(heap, tenant, table_id, table, entry, quarantine);
+            Err(RpcStatus::StatusDataCorrupted)
+        }
+
+        _ => Ok(()),
+    }
+}
+
+/// A walk over every checksummed table, checking objects against their checksums at a bounded
+/// rate. The walk goes one table bucket at a time, holding the bucket's lock only while it's
+/// objects are collected, so writers are never held up by the checks themselves. Objects written
+/// to a bucket after it was collected are checked on the next pass.
+pub struct Sweep {
+    // The most objects checked a second.
+    rate: u64,
+
+    // If true, objects that fail to verify are quarantined.
+    quarantine: bool,
+
+    // The tables walked on this pass, along with the tenant owning each.
+    tables: Vec<(TenantId, TableId, Arc<Table>)>,
+
+    // The table on `tables`, and the bucket on it, the walk is at.
+    table: usize,
+    bucket: usize,
+
+    // The objects collected off the current bucket that are yet to be checked.
+    pending: Vec<Entry>,
+
+    // The objects that can be checked before the walk has to wait for the rate to allow more,
+    // and the time-stamp in cycles at which they were last topped up.
+    tokens: f64,
+    last: u64,
+}
+
+impl Sweep {
+    /// Returns a sweep that has no tables to walk yet. Refer to start().
+    ///
+    /// # Arguments
+    ///
+    /// * `rate`:       The most objects checked a second. Must be atleast 1.
+    /// * `quarantine`: True if objects that fail to verify should be quarantined.
+    pub fn new(rate: u64, quarantine: bool) -> Sweep {
+        Sweep {
+            rate: rate,
+            quarantine: quarantine,
+            tables: Vec::new(),
+            table: 0,
+            bucket: 0,
+            pending: Vec::new(),
+            tokens: 0.0,
+            last: rdtsc(),
+        }
+    }
+
+    /// Starts a new pass over a set of tables, abandoning the current one.
+    ///
+    /// # Arguments
+    ///
+    /// * `tables`: The tables to walk, along with the tenant owning each. Refer to
+    ///             Master::checksummed_tables().
+    pub fn start(&mut self, tables: Vec<(TenantId, TableId, Arc<Table>)>) {
+        self.tables = tables;
+        self.table = 0;
+        self.bucket = 0;
+        self.pending.clear();
+    }
+
+    /// Returns true once every object on the current pass has been checked.
+    pub fn done(&self) -> bool {
+        self.table >= self.tables.len()
+    }
+
+    /// Checks as many objects as the rate allows since the last call, and atmost a small batch.
+    /// Objects without a checksum count against the rate, but are not checked.
+    ///
+    /// # Arguments
+    ///
+    /// * `heap`: The allocator the tables' objects were allocated on.
+    ///
+    /// # Return
+    ///
+    /// The number of objects walked over.
+    pub fn step(&mut self, heap: &Allocator) -> u64 {
+        let now = rdtsc();
+        let earned = now.saturating_sub(self.last) as f64 * self.rate as f64;
+        self.tokens = (self.tokens + earned / cycles_per_second() as f64).min(BATCH as f64);
+        self.last = now;
+
+        let mut walked = 0;
+        while self.tokens >= 1.0 {
+            let entry = match self.next() {
+                Some(entry) => entry,
+                None => break,
+            };
+            self.tokens -= 1.0;
+            walked += 1;
+
+            let (tenant, table_id, ref table) = self.tables[self.table];
+            match heap.verify(&entry.value) {
+                None => {}
+
+                Some(true) => {
+                    VERIFIED.fetch_add(1, Ordering::Relaxed);
+                }
+
+                Some(false) => {
+                    VERIFIED.fetch_add(1, Ordering::Relaxed);
+                    record_corrupt(heap, tenant, table_id, table, &entry, self.quarantine);
+                }
+            }
+        }
+
+        walked
+    }
+
+    // Returns the next object on the pass, collecting the next non-empty bucket if the current
+    // one is used up. Counts the pass once every table is used up.
+    fn next(&mut self) -> Option<Entry> {
+        loop {
+            if let Some(entry) = self.pending.pop() {
+                return Some(entry);
+            }
+
+            if self.done() {
+                return None;
+            }
+
+            if self.bucket == N_BUCKETS {
+                self.table += 1;
+                self.bucket = 0;
+                if self.done() {
+                    PASSES.fetch_add(1, Ordering::Relaxed);
+                    return None;
+                }
+            }
+
+            self.pending = self.tables[self.table].2.bucket_entries(self.bucket);
+            self.bucket += 1;
+        }
+    }
+}
+
+/// Returns a task that runs a sweep at background priority until the server drains. A new pass
+/// starts over the tables checksummed at the time as soon as the last one completes, so tables
+/// created in the meantime are picked up.
+///
+/// # Arguments
+///
+/// * `master`: The service whose tables are walked.
+/// * `sweep`:  The sweep to run. Refer to Master::integrity_sweep().
+///
+/// # Return
+///
+/// A task that never sends out a response.
+pub fn task(master: Arc<Master>, mut sweep: Sweep) -> Box<Task> {
+    let gen = Box::new(move || {
+        while !master.drain().draining() {
+            if sweep.done() {
+                sweep.start(master.checksummed_tables());
+            }
+
+            sweep.step(master.heap());
+            yield 0;
+        }
+
+        return None;
+    });
+
+    Box::new(Native::new(TaskPriority::BACKGROUND, gen))
+}
+
+// This module contains tests for the integrity sweep. Objects are corrupted by writing straight
+// into the table's heap, the way a stray write would.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use super::super::cycles::virt;
+
+    // The virtual clock ticks once a microsecond.
+    const HZ: u64 = 1_000_000;
+
+    // Returns a checksummed table holding `num` objects, keyed by their index.
+    fn table(heap: &Allocator, num: u16) -> Arc<Table> {
+        let table = Arc::new(Table::default());
+        table.set_checksums(true);
+        for i in 0..num {
+            let key = [(i & 0xff) as u8, (i >> 8) as u8];
+            let (k, obj) = heap.object(1, 7, &key, &[i as u8; 32]).unwrap();
+            heap.store(&table, k, obj);
+        }
+        table
+    }
+
+    // Flips a byte of the value stored under a key, in place.
+    fn corrupt(table: &Table, key: &[u8]) {
+        let object = table.get(key).unwrap().value;
+        unsafe {
+            let last = object.as_ptr().offset(object.len() as isize - 1) as *mut u8;
+            *last ^= 0xff;
+        }
+    }
+
+    // Steps a sweep once a millisecond of virtual time until it completes it's pass.
+    //
+    // - `return`: The milliseconds it took, and the objects walked over each time.
+    fn run(sweep: &mut Sweep, heap: &Allocator) -> (u64, Vec<u64>) {
+        let mut walked = Vec::new();
+        let mut ms = 0;
+        while !sweep.done() {
+            virt::advance(HZ / 1000);
+            walked.push(sweep.step(heap));
+            ms += 1;
+        }
+        (ms, walked)
+    }
+
+    // Tests that a sweep walks a table at the configured rate, and finds a corrupt object on
+    // it within the time the rate budgets for the table.
+    #[test]
+    fn test_sweep_rate() {
+        virt::install(0, HZ);
+        let heap = Allocator::new();
+        let table = table(&heap, 200);
+        corrupt(&table, &[199, 0]);
+
+        let before = stats();
+        let mut sweep = Sweep::new(1000, false);
+        sweep.start(vec![(1, 7, Arc::clone(&table))]);
+        let (ms, walked) = run(&mut sweep, &heap);
+        virt::uninstall();
+
+        // 1000 objects a second is one object a millisecond; the step after the last object
+        // finds the pass complete.
+        assert_eq!(201, ms);
+        assert!(walked.iter().all(|w| *w <= 1));
+        assert_eq!(200, walked.iter().sum::<u64>());
+
+        let after = stats();
+        assert!(after.verified >= before.verified + 200);
+        assert!(after.corrupt >= before.corrupt + 1);
+        assert!(after.passes >= before.passes + 1);
+        assert!(!table.is_quarantined(&[199, 0]));
+    }
+
+    // Tests that a sweep never walks more than a batch of objects at once, however long it
+    // went without running.
+    #[test]
+    fn test_sweep_batch() {
+        virt::install(0, HZ);
+        let heap = Allocator::new();
+        let mut sweep = Sweep::new(1000, false);
+        sweep.start(vec![(1, 7, table(&heap, 100))]);
+
+        virt::advance(HZ);
+        assert_eq!(BATCH, sweep.step(&heap));
+        assert_eq!(0, sweep.step(&heap));
+        virt::uninstall();
+    }
+
+    // Tests that a corrupt object found by a sweep is quarantined, and that overwriting it lifts
+    // the quarantine.
+    #[test]
+    fn test_sweep_quarantine() {
+        virt::install(0, HZ);
+        let heap = Allocator::new();
+        let table = table(&heap, 50);
+        corrupt(&table, &[10, 0]);
+
+        let mut sweep = Sweep::new(100_000, true);
+        sweep.start(vec![(1, 7, Arc::clone(&table))]);
+        run(&mut sweep, &heap);
+        assert!(table.is_quarantined(&[10, 0]));
+        assert_eq!(1, table.quarantined());
+
+        let (k, obj) = heap.object(1, 7, &[10, 0], &[10; 32]).unwrap();
+        heap.store(&table, k, obj);
+        assert!(!table.is_quarantined(&[10, 0]));
+
+        sweep.start(vec![(1, 7, Arc::clone(&table))]);
+        run(&mut sweep, &heap);
+        virt::uninstall();
+        assert_eq!(0, table.quarantined());
+    }
+
+    // Tests that verified reads fail on a corrupt object, and quarantine it only if asked to.
+    #[test]
+    fn test_verify_read() {
+        let heap = Allocator::new();
+        let table = table(&heap, 4);
+        corrupt(&table, &[2, 0]);
+
+        let good = table.get(&[1, 0]).unwrap();
+        assert_eq!(Ok(()), verify_read(&heap, 1, 7, &table, &good, true));
+
+        let bad = table.get(&[2, 0]).unwrap();
+        assert_eq!(
+            Err(RpcStatus::StatusDataCorrupted),
+            verify_read(&heap, 1, 7, &table, &bad, false)
+        );
+        assert!(!table.is_quarantined(&[2, 0]));
+        assert_eq!(
+            Err(RpcStatus::StatusDataCorrupted),
+            verify_read(&heap, 1, 7, &table, &bad, true)
+        );
+        assert!(table.is_quarantined(&[2, 0]));
+
+        // Objects without a checksum pass unchecked.
+        let raw = Table::default();
+        let (k, obj) = heap.object(1, 7, &[1], &[1; 8]).unwrap();
+        heap.store(&raw, k, obj);
+        let entry = raw.get(&[1]).unwrap();
+        assert_eq!(Ok(()), verify_read(&heap, 1, 7, &raw, &entry, true));
+    }
+}
@@ -0,0 +1,587 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use sandstorm::common::TenantId;
+
+/// Record kind written when a durable invocation is accepted. Carries the arguments.
+const BEGIN: u8 = 1;
+
+/// Record kind written every time a durable invocation checkpoints. Carries it's state.
+const CHECKPOINT: u8 = 2;
+
+/// Record kind written once a durable invocation has completed.
+const DONE: u8 = 3;
+
+/// The length of the frame preceding every record; a 4 byte length and a 4 byte CRC.
+const FRAME_LEN: usize = 8;
+
+/// The state a durable invocation last checkpointed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Checkpoint {
+    /// An opaque progress marker supplied by the extension.
+    pub progress: u64,
+
+    /// The state supplied by the extension.
+    pub state: Vec<u8>,
+}
+
+/// A durable invocation that had not completed when the journal was last closed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PendingTask {
+    /// The tenant that invoked the extension.
+    pub tenant: TenantId,
+
+    /// The name of the invoked extension.
+    pub name: Vec<u8>,
+
+    /// Hash over the name and arguments, identifying the invocation. See args_hash().
+    pub args_hash: u64,
+
+    /// The arguments the extension was invoked with.
+    pub args: Vec<u8>,
+
+    /// The last checkpoint taken by the invocation, if any.
+    pub checkpoint: Option<Checkpoint>,
+}
+
+// The open journal file, and how much has been written to it.
+struct JournalFile {
+    file: File,
+    bytes: usize,
+    unsynced: u32,
+}
+
+/// An append-only journal of checkpoints taken by durable invocations. Every record is framed
+/// by it's length and a CRC32 over it's contents, so that a torn write at the tail (or any
+/// other corruption) is detected on recovery, and everything from that point on is discarded.
+pub struct Journal {
+    // The file records are appended to.
+    file: Mutex<JournalFile>,
+
+    // Records are fsync'ed once these many are written. Zero leaves flushing to the OS.
+    fsync_every: u32,
+
+    // The largest state an extension is allowed to checkpoint, in bytes.
+    max_state: usize,
+
+    // The largest the journal file is allowed to grow to, in bytes.
+    max_bytes: usize,
+}
+
+impl Journal {
+    /// Opens a journal, recovering any durable invocations that had not completed. The journal
+    /// is compacted on open so that it only holds records for these invocations.
+    ///
+    /// # Arguments
+    ///
+    /// * `path`:        The journal file. Created if it does not exist.
+    /// * `fsync_every`: The number of records after which the journal is fsync'ed. Zero leaves
+    ///                  flushing to the OS.
+    /// * `max_state`:   The largest state, in bytes, an invocation is allowed to checkpoint.
+    /// * `max_bytes`:   The largest, in bytes, the journal file is allowed to grow.
+    ///
+    /// # Return
+    ///
+    /// The journal, and the invocations that were recovered from it in the order in which
+    /// they were originally invoked.
+    pub fn open(
+        path: &Path,
+        fsync_every: u32,
+        max_state: usize,
+        max_bytes: usize,
+    ) -> io::Result<(Journal, Vec<PendingTask>)> {
+        let mut contents = Vec::new();
+        match File::open(path) {
+            Ok(mut file) => {
+                file.read_to_end(&mut contents)?;
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        let pending = replay(&contents);
+
+        // Compact the journal by rewriting just the pending invocations into a fresh file, and
+        // then atomically replacing the old one with it.
+        let mut compacted = Vec::new();
+        for task in pending.iter() {
+            encode(
+                &mut compacted,
+                BEGIN,
+                task.tenant,
+                &task.name,
+                task.args_hash,
+                0,
+                &task.args,
+            );
+            if let Some(ref c) = task.checkpoint {
+                encode(
+                    &mut compacted,
+                    CHECKPOINT,
+                    task.tenant,
+                    &task.name,
+                    task.args_hash,
+                    c.progress,
+                    &c.state,
+                );
+            }
+        }
+
+        let mut tmp = PathBuf::from(path);
+        tmp.set_extension("tmp");
+        {
+            let mut file = File::create(&tmp)?;
+            file.write_all(&compacted)?;
+            file.sync_all()?;
+        }
+        fs::rename(&tmp, path)?;
+
+        let file = OpenOptions::new().append(true).open(path)?;
+        let journal = Journal {
+            file: Mutex::new(JournalFile {
+                file: file,
+                bytes: compacted.len(),
+                unsynced: 0,
+            }),
+            fsync_every: fsync_every,
+            max_state: max_state,
+            max_bytes: max_bytes,
+        };
+
+        Ok((journal, pending))
+    }
+
+    /// Records that a durable invocation was accepted.
+    ///
+    /// # Return
+    ///
+    /// True if the record was appended. False if the journal is full or could not be written.
+    pub fn begin(&self, tenant: TenantId, name: &[u8], args_hash: u64, args: &[u8]) -> bool {
+        self.append(BEGIN, tenant, name, args_hash, 0, args)
+    }
+
+    /// Records a checkpoint taken by a durable invocation.
+    ///
+    /// # Return
+    ///
+    /// True if the record was appended. False if the state is larger than allowed, or if the
+    /// journal is full or could not be written.
+    pub fn checkpoint(
+        &self,
+        tenant: TenantId,
+        name: &[u8],
+        args_hash: u64,
+        progress: u64,
+        state: &[u8],
+    ) -> bool {
+        if state.len() > self.max_state {
+            return false;
+        }
+
+        self.append(CHECKPOINT, tenant, name, args_hash, progress, state)
+    }
+
+    /// Records that a durable invocation completed. It will not be recovered after this.
+    ///
+    /// # Return
+    ///
+    /// True if the record was appended. False if the journal could not be written.
+    pub fn done(&self, tenant: TenantId, name: &[u8], args_hash: u64) -> bool {
+        self.append(DONE, tenant, name, args_hash, 0, &[])
+    }
+
+    /// Flushes every record appended so far to disk, irrespective of the sync policy. Called on
+    /// shutdown so that the tail of the journal is not lost.
+    pub fn sync(&self) -> io::Result<()> {
+        let mut journal = self.file.lock().unwrap();
+        if journal.unsynced > 0 {
+            journal.file.sync_data()?;
+            journal.unsynced = 0;
+        }
+
+        Ok(())
+    }
+
+    // Frames and appends a record to the journal.
+    fn append(
+        &self,
+        kind: u8,
+        tenant: TenantId,
+        name: &[u8],
+        args_hash: u64,
+        progress: u64,
+        data: &[u8],
+    ) -> bool {
+        let mut record = Vec::with_capacity(FRAME_LEN + 27 + name.len() + data.len());
+        encode(&mut record, kind, tenant, name, args_hash, progress, data);
+
+        let mut journal = self.file.lock().unwrap();
+
+        // Completion records are always let through so that a full journal can still drain.
+        if kind != DONE && journal.bytes + record.len() > self.max_bytes {
+            return false;
+        }
+
+        if let Err(e) = journal.file.write_all(&record) {
+            error!("Failed to append to durable journal: {}", e);
+            return false;
+        }
+        journal.bytes += record.len();
+
+        journal.unsynced += 1;
+        if self.fsync_every > 0 && journal.unsynced >= self.fsync_every {
+            if let Err(e) = journal.file.sync_data() {
+                error!("Failed to sync durable journal: {}", e);
+                return false;
+            }
+            journal.unsynced = 0;
+        }
+
+        true
+    }
+}
+
+/// Returns a hash over an extension's name and arguments. A durable invocation is identified by
+/// it's tenant, and this hash. This is also the key the invocation's result is written under.
+pub fn args_hash(name: &[u8], args: &[u8]) -> u64 {
+    // 64 bit FNV-1a. The name's length is mixed in so that (name, args) pairs don't collide
+    // just because their concatenations are equal.
+    let mut hash: u64 = 0xcbf29ce484222325;
+    let len = [name.len() as u8, (name.len() >> 8) as u8];
+    for b in len.iter().chain(name.iter()).chain(args.iter()) {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+// Computes a CRC32 (IEEE) over a slice of bytes.
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    crc32_update(0, data)
+}
+
+// Extends a CRC32 computed over some bytes with the bytes that follow them, so that a CRC can
+// be computed over data that is not all in memory at once. crc32_update(0, ..) starts a CRC.
+pub(crate) fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    let mut crc: u32 = !crc;
+    for b in data.iter() {
+        crc ^= *b as u32;
+        for _ in 0..8 {
+            let mask = (!(crc & 1)).wrapping_add(1);
+            crc = (crc >> 1) ^ (0xedb88320 & mask);
+        }
+    }
+    !crc
+}
+
+// Appends a framed record to `buf`. The body is laid out as kind (1 byte), tenant (4), args
+// hash (8), name length (2), name, progress (8), data length (4), data; all little endian.
+fn encode(
+    buf: &mut Vec<u8>,
+    kind: u8,
+    tenant: TenantId,
+    name: &[u8],
+    args_hash: u64,
+    progress: u64,
+    data: &[u8],
+) {
+    let mut body = Vec::with_capacity(27 + name.len() + data.len());
+    body.push(kind);
+    put_le(&mut body, tenant as u64, 4);
+    put_le(&mut body, args_hash, 8);
+    put_le(&mut body, name.len() as u64, 2);
+    body.extend_from_slice(name);
+    put_le(&mut body, progress, 8);
+    put_le(&mut body, data.len() as u64, 4);
+    body.extend_from_slice(data);
+
+    put_le(buf, body.len() as u64, 4);
+    put_le(buf, crc32(&body) as u64, 4);
+    buf.extend_from_slice(&body);
+}
+
+// Appends the lower `n` bytes of `v` to `buf` in little endian order.
+pub(crate) fn put_le(buf: &mut Vec<u8>, v: u64, n: usize) {
+    for i in 0..n {
+        buf.push((v >> (i << 3)) as u8);
+    }
+}
+
+// Reads an `n` byte little endian integer off the front of `buf`, advancing it.
+fn take_le(buf: &mut &[u8], n: usize) -> Option<u64> {
+    if buf.len() < n {
+        return None;
+    }
+
+    let (v, rest) = buf.split_at(n);
+    *buf = rest;
+    Some(
+        v.iter()
+            .enumerate()
+            .fold(0, |a, (i, b)| a | ((*b as u64) << (i << 3))),
+    )
+}
+
+// Reads `n` bytes off the front of `buf`, advancing it.
+fn take<'a>(buf: &mut &'a [u8], n: usize) -> Option<&'a [u8]> {
+    if buf.len() < n {
+        return None;
+    }
+
+    let (v, rest) = buf.split_at(n);
+    *buf = rest;
+    Some(v)
+}
+
+// Decodes the body of a record. See encode() for the layout.
+fn decode(mut body: &[u8]) -> Option<(u8, TenantId, u64, Vec<u8>, u64, Vec<u8>)> {
+    let kind = take_le(&mut body, 1)? as u8;
+    let tenant = take_le(&mut body, 4)? as TenantId;
+    let hash = take_le(&mut body, 8)?;
+    let name_len = take_le(&mut body, 2)? as usize;
+    let name = take(&mut body, name_len)?.to_vec();
+    let progress = take_le(&mut body, 8)?;
+    let data_len = take_le(&mut body, 4)? as usize;
+    let data = take(&mut body, data_len)?.to_vec();
+    Some((kind, tenant, hash, name, progress, data))
+}
+
+// Replays the records in a journal, returning invocations that began but never completed.
+// Stops at the first record that is truncated or fails it's CRC.
+fn replay(mut contents: &[u8]) -> Vec<PendingTask> {
+    let mut pending: HashMap<(TenantId, Vec<u8>, u64), (usize, PendingTask)> = HashMap::new();
+    let mut seq = 0;
+
+    loop {
+        let body = match (take_le(&mut contents, 4), take_le(&mut contents, 4)) {
+            (Some(len), Some(crc)) => match take(&mut contents, len as usize) {
+                Some(body) if crc32(body) as u64 == crc => body,
+                _ => break,
+            },
+            _ => break,
+        };
+
+        let (kind, tenant, hash, name, progress, data) = match decode(body) {
+            Some(record) => record,
+            None => break,
+        };
+
+        let key = (tenant, name, hash);
+        match kind {
+            BEGIN => {
+                let task = PendingTask {
+                    tenant: tenant,
+                    name: key.1.clone(),
+                    args_hash: hash,
+                    args: data,
+                    checkpoint: None,
+                };
+                pending.entry(key).or_insert((seq, task));
+                seq += 1;
+            }
+
+            CHECKPOINT => {
+                if let Some(&mut (_, ref mut task)) = pending.get_mut(&key) {
+                    task.checkpoint = Some(Checkpoint {
+                        progress: progress,
+                        state: data,
+                    });
+                }
+            }
+
+            _ => {
+                pending.remove(&key);
+            }
+        }
+    }
+
+    let mut pending: Vec<(usize, PendingTask)> = pending.into_iter().map(|(_, v)| v).collect();
+    pending.sort_by_key(|&(seq, _)| seq);
+    pending.into_iter().map(|(_, task)| task).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    // Returns a path to a fresh journal file for a test.
+    fn path(test: &str) -> PathBuf {
+        let mut path = env::temp_dir();
+        path.push(format!("sandstorm-journal-{}-{}", test, process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn open(path: &Path) -> (Journal, Vec<PendingTask>) {
+        Journal::open(path, 1, 64, 1 << 20).expect("Failed to open journal")
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(0xcbf43926, crc32(b"123456789"));
+        assert_eq!(0, crc32(b""));
+        assert_eq!(0xcbf43926, crc32_update(crc32(b"1234"), b"56789"));
+    }
+
+    #[test]
+    fn test_args_hash() {
+        assert_eq!(
+            args_hash(b"aggregate", b"12"),
+            args_hash(b"aggregate", b"12")
+        );
+        assert!(args_hash(b"aggregate", b"12") != args_hash(b"aggregate", b"13"));
+        assert!(args_hash(b"ab", b"c") != args_hash(b"a", b"bc"));
+    }
+
+    // Tests that the latest checkpoint of an incomplete invocation is recovered, and that
+    // completed invocations are not.
+    #[test]
+    fn test_journal_recover() {
+        let path = path("recover");
+        {
+            let (journal, pending) = open(&path);
+            assert!(pending.is_empty());
+
+            assert!(journal.begin(1, b"aggregate", 7, b"args"));
+            assert!(journal.begin(2, b"scan", 8, b""));
+            assert!(journal.checkpoint(1, b"aggregate", 7, 1, b"first"));
+            assert!(journal.checkpoint(2, b"scan", 8, 1, b"scan"));
+            assert!(journal.checkpoint(1, b"aggregate", 7, 2, b"second"));
+            assert!(journal.done(2, b"scan", 8));
+        }
+
+        let (_journal, pending) = open(&path);
+        assert_eq!(
+            vec![PendingTask {
+                tenant: 1,
+                name: b"aggregate".to_vec(),
+                args_hash: 7,
+                args: b"args".to_vec(),
+                checkpoint: Some(Checkpoint {
+                    progress: 2,
+                    state: b"second".to_vec(),
+                }),
+            }],
+            pending
+        );
+
+        // The compacted journal should recover to the same thing.
+        let (_journal, again) = open(&path);
+        assert_eq!(pending, again);
+
+        let _ = fs::remove_file(&path);
+    }
+
+    // Tests that a torn record at the tail of the journal is discarded, along with anything
+    // after it, and everything before it is recovered.
+    #[test]
+    fn test_journal_torn_tail() {
+        let path = path("torn");
+        {
+            let (journal, _) = open(&path);
+            assert!(journal.begin(1, b"aggregate", 7, b""));
+            assert!(journal.checkpoint(1, b"aggregate", 7, 1, b"first"));
+            assert!(journal.checkpoint(1, b"aggregate", 7, 2, b"second"));
+        }
+
+        // Chop the last few bytes off the final checkpoint.
+        let len = fs::metadata(&path).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(len - 3)
+            .unwrap();
+
+        let (_journal, pending) = open(&path);
+        assert_eq!(1, pending.len());
+        assert_eq!(
+            Some(Checkpoint {
+                progress: 1,
+                state: b"first".to_vec(),
+            }),
+            pending[0].checkpoint
+        );
+
+        let _ = fs::remove_file(&path);
+    }
+
+    // Tests that a record with a bad CRC ends recovery.
+    #[test]
+    fn test_journal_bad_crc() {
+        let mut contents = Vec::new();
+        encode(&mut contents, BEGIN, 1, b"a", 1, 0, b"");
+        let good = contents.len();
+        encode(&mut contents, BEGIN, 2, b"b", 2, 0, b"");
+        encode(&mut contents, BEGIN, 3, b"c", 3, 0, b"");
+
+        // Flip a bit inside the second record's body.
+        contents[good + FRAME_LEN + 1] ^= 0x1;
+
+        let pending = replay(&contents);
+        assert_eq!(1, pending.len());
+        assert_eq!(1, pending[0].tenant);
+    }
+
+    // Tests that checkpoints and the journal are size capped, but that completions are not.
+    #[test]
+    fn test_journal_caps() {
+        let path = path("caps");
+        let (journal, _) = Journal::open(&path, 0, 8, 128).unwrap();
+
+        assert!(journal.begin(1, b"a", 1, b""));
+        assert!(!journal.checkpoint(1, b"a", 1, 1, &[0; 9]));
+        assert!(journal.checkpoint(1, b"a", 1, 1, &[0; 8]));
+
+        let mut appended = 0;
+        while journal.checkpoint(1, b"a", 1, 1, &[0; 8]) {
+            appended += 1;
+        }
+        assert!(appended < 4);
+        assert!(!journal.begin(2, b"b", 2, b""));
+        assert!(journal.done(1, b"a", 1));
+
+        let _ = fs::remove_file(&path);
+    }
+
+    // Tests that sync() flushes records written under a policy that leaves flushing to the OS.
+    #[test]
+    fn test_journal_sync() {
+        let path = path("sync");
+        {
+            let (journal, _) = Journal::open(&path, 0, 64, 1 << 20).unwrap();
+            assert!(journal.begin(1, b"aggregate", 7, b""));
+            assert!(journal.checkpoint(1, b"aggregate", 7, 1, b"first"));
+            assert_eq!(2, journal.file.lock().unwrap().unsynced);
+
+            journal.sync().expect("Failed to sync journal");
+            assert_eq!(0, journal.file.lock().unwrap().unsynced);
+            journal.sync().expect("Failed to sync journal");
+        }
+
+        let (_journal, pending) = open(&path);
+        assert_eq!(1, pending.len());
+
+        let _ = fs::remove_file(&path);
+    }
+}
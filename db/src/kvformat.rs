@@ -0,0 +1,905 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! The file format every tool that saves or loads the contents of a table uses: table
+//! checkpoints (Master::checkpoint_table()), exports and imports by the migration tool, and
+//! the input to read-only table images (image::build()). Files are written and read as
+//! streams, one record at a time, so that neither side ever holds the whole file in memory.
+//!
+//! A file is a header, followed by the records, followed by a trailer. Integers are little
+//! endian. The header is laid out as
+//!
+//! |magic 8|version 4|header_len 4|tenant 4|flags 4|table 8|records 8|key_schema_len 2|
+//! |value_schema_len 2|key_schema|value_schema|header_crc 4|
+//!
+//! where `records` is u64::MAX if the writer did not know how many records it would write, the
+//! schemas are descriptors encoded by sandstorm::schema (empty if none was declared), and the
+//! header CRC covers every field before it. Each record is laid out as
+//!
+//! |key_len 2|value_len 4|key|value|record_crc 4|
+//!
+//! where the record CRC covers every field before it on the record. Keys are never empty, so a
+//! key length of 0 marks the trailer:
+//!
+//! |0 2|TRAILER 4|records 8|file_crc 4|
+//!
+//! where the file CRC covers every byte of the file before it.
+
+use std::ascii;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::u16;
+
+use sandstorm::common::{TableId, TenantId};
+use sandstorm::schema::Schema;
+
+use super::journal::{crc32, crc32_update, put_le};
+
+/// Identifies a file as a file of records.
+pub const MAGIC: [u8; 8] = *b"SPLTKV\0\0";
+
+/// The version of the format written by Writer. Files of any other version are refused.
+pub const VERSION: u32 = 1;
+
+/// Set on files whose records are in the order a table is dumped in; bucket by bucket, and in
+/// key order within a bucket. Refer to Master::dump_table().
+pub const FLAG_DUMP_ORDER: u32 = 0x1;
+
+// Marks the trailer, right after the key length of 0 that ends the records.
+const TRAILER: [u8; 4] = *b"KVND";
+
+// The length of the header less the schemas and the header CRC.
+const FIXED_LEN: usize = 44;
+
+// The value of `records` on the header of a file whose writer did not know how many records
+// it would write.
+const UNKNOWN: u64 = u64::max_value();
+
+/// What a Reader does with a record that fails it's checksum.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OnCorrupt {
+    /// Stop reading, and return Error::Corrupt.
+    Abort,
+
+    /// Skip the record and carry on, counting it. Refer to Reader::skipped(). A record whose
+    /// lengths were corrupted can't be skipped over reliably; the records after it then usually
+    /// fail their checksums too, until the file turns out truncated.
+    Skip,
+}
+
+/// Why a file could not be written or read.
+#[derive(Debug)]
+pub enum Error {
+    /// The underlying file could not be read or written.
+    Io(io::Error),
+
+    /// The file does not start with MAGIC.
+    NotKv,
+
+    /// The file was written in a version of the format other than VERSION.
+    Version(u32),
+
+    /// The header is malformed, or fails it's checksum.
+    Header(String),
+
+    /// The file ends at the given offset, before it's trailer.
+    Truncated(u64),
+
+    /// The given record, starting at the given offset, fails it's checksum. Records are
+    /// counted from 0, including the ones that were skipped.
+    Corrupt { record: u64, offset: u64 },
+
+    /// The trailer is malformed, does not match the records read, or the whole-file checksum
+    /// does not match.
+    Trailer(String),
+
+    /// A record could not be written; it's key is empty or too long, or the record does not fit
+    /// the schema declared on the header.
+    Record { record: u64, why: String },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref e) => write!(f, "{}", e),
+            Error::NotKv => write!(f, "not a file of records; bad magic"),
+            Error::Version(v) => write!(f, "version {} is not supported, only {}", v, VERSION),
+            Error::Header(ref why) => write!(f, "malformed header; {}", why),
+            Error::Truncated(offset) => write!(f, "truncated at offset {}", offset),
+            Error::Corrupt { record, offset } => write!(
+                f,
+                "record {} at offset {} fails it's checksum",
+                record, offset
+            ),
+            Error::Trailer(ref why) => write!(f, "malformed trailer; {}", why),
+            Error::Record { record, ref why } => write!(f, "record {} refused; {}", record, why),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(e: Error) -> io::Error {
+        match e {
+            Error::Io(e) => e,
+            e => io::Error::new(io::ErrorKind::InvalidData, e.to_string()),
+        }
+    }
+}
+
+/// What a file holds, as declared on it's header.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Header {
+    /// The tenant the records were saved from, or are meant for.
+    pub tenant: TenantId,
+
+    /// The table the records were saved from, or are meant for.
+    pub table: TableId,
+
+    /// The layout every key fits, if one was declared.
+    pub key_schema: Option<Schema>,
+
+    /// The layout every value fits, if one was declared.
+    pub value_schema: Option<Schema>,
+
+    /// The number of records on the file, if the writer knew it up front. Files that don't
+    /// hold exactly these many records are refused by Reader.
+    pub records: Option<u64>,
+
+    /// FLAG_DUMP_ORDER, if set. Other bits are carried along but not interpreted.
+    pub flags: u32,
+}
+
+impl Header {
+    /// Returns a header for a file of records from a table, without schemas, flags, or a
+    /// record count.
+    pub fn new(tenant: TenantId, table: TableId) -> Header {
+        Header {
+            tenant: tenant,
+            table: table,
+            key_schema: None,
+            value_schema: None,
+            records: None,
+            flags: 0,
+        }
+    }
+
+    // Encodes the header, including it's CRC.
+    fn encode(&self) -> Vec<u8> {
+        let key_schema = self.key_schema.as_ref().map_or(Vec::new(), |s| s.encode());
+        let value_schema = self
+            .value_schema
+            .as_ref()
+            .map_or(Vec::new(), |s| s.encode());
+        let len = FIXED_LEN + key_schema.len() + value_schema.len() + 4;
+
+        let mut buf = Vec::with_capacity(len);
+        buf.extend_from_slice(&MAGIC);
+        put_le(&mut buf, VERSION as u64, 4);
+        put_le(&mut buf, len as u64, 4);
+        put_le(&mut buf, self.tenant as u64, 4);
+        put_le(&mut buf, self.flags as u64, 4);
+        put_le(&mut buf, self.table, 8);
+        put_le(&mut buf, self.records.unwrap_or(UNKNOWN), 8);
+        put_le(&mut buf, key_schema.len() as u64, 2);
+        put_le(&mut buf, value_schema.len() as u64, 2);
+        buf.extend_from_slice(&key_schema);
+        buf.extend_from_slice(&value_schema);
+        let crc = crc32(&buf);
+        put_le(&mut buf, crc as u64, 4);
+        buf
+    }
+}
+
+/// Writes a file of records to an output, one record at a time. Nothing is buffered; wrap the
+/// output in a BufWriter if it is a file.
+pub struct Writer<W: Write> {
+    // Where the file is written to.
+    out: W,
+
+    // The header written at the front of the file.
+    header: Header,
+
+    // The CRC over every byte written so far.
+    crc: u32,
+
+    // The number of records written so far.
+    records: u64,
+
+    // Holds each record while it is encoded.
+    buf: Vec<u8>,
+}
+
+impl<W: Write> Writer<W> {
+    /// Writes the header of a file, and returns a Writer for it's records.
+    ///
+    /// # Arguments
+    ///
+    /// * `out`:    Where the file is written to.
+    /// * `header`: What the file holds.
+    pub fn new(mut out: W, header: Header) -> Result<Writer<W>, Error> {
+        let buf = header.encode();
+        out.write_all(&buf)?;
+
+        Ok(Writer {
+            out: out,
+            header: header,
+            crc: crc32(&buf),
+            records: 0,
+            buf: Vec::new(),
+        })
+    }
+
+    /// Writes a record. Records are refused if their key is empty or 64 KB or longer, if their
+    /// value is 4 GB or longer, or if they don't fit a schema declared on the header.
+    ///
+    /// # Arguments
+    ///
+    /// * `key`:   The record's key.
+    /// * `value`: The record's value.
+    pub fn write(&mut self, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        let why = if key.is_empty() {
+            Some("empty key".to_string())
+        } else if key.len() > u16::MAX as usize {
+            Some(format!("{} byte key", key.len()))
+        } else if value.len() > u32::max_value() as usize {
+            Some(format!("{} byte value", value.len()))
+        } else if let Some(Err(e)) = self.header.key_schema.as_ref().map(|s| s.check(key)) {
+            Some(format!("key does not fit the schema; {}", e))
+        } else if let Some(Err(e)) = self.header.value_schema.as_ref().map(|s| s.check(value)) {
+            Some(format!("value does not fit the schema; {}", e))
+        } else {
+            None
+        };
+        if let Some(why) = why {
+            return Err(Error::Record {
+                record: self.records,
+                why: why,
+            });
+        }
+
+        self.buf.clear();
+        put_le(&mut self.buf, key.len() as u64, 2);
+        put_le(&mut self.buf, value.len() as u64, 4);
+        let crc = crc32_update(crc32_update(crc32(&self.buf), key), value);
+        put_le(&mut self.buf, crc as u64, 4);
+
+        self.out.write_all(&self.buf[..6])?;
+        self.out.write_all(key)?;
+        self.out.write_all(value)?;
+        self.out.write_all(&self.buf[6..])?;
+
+        self.crc = crc32_update(self.crc, &self.buf[..6]);
+        self.crc = crc32_update(crc32_update(self.crc, key), value);
+        self.crc = crc32_update(self.crc, &self.buf[6..]);
+        self.records += 1;
+        Ok(())
+    }
+
+    /// Returns the number of records written so far.
+    pub fn records(&self) -> u64 {
+        self.records
+    }
+
+    /// Writes the trailer, and flushes the output. A file that is never finished is refused by
+    /// Reader as truncated.
+    ///
+    /// # Return
+    ///
+    /// The output, or Error::Trailer without writing the trailer if the header declared a
+    /// different number of records than were written.
+    pub fn finish(mut self) -> Result<W, Error> {
+        if let Some(records) = self.header.records {
+            if records != self.records {
+                return Err(Error::Trailer(format!(
+                    "header declares {} records, {} were written",
+                    records, self.records
+                )));
+            }
+        }
+
+        let mut buf = Vec::with_capacity(18);
+        put_le(&mut buf, 0, 2);
+        buf.extend_from_slice(&TRAILER);
+        put_le(&mut buf, self.records, 8);
+        let crc = crc32_update(self.crc, &buf);
+        put_le(&mut buf, crc as u64, 4);
+
+        self.out.write_all(&buf)?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// Reads a file of records off an input, one record at a time. Only the record last read is
+/// held in memory; wrap the input in a BufReader if it is a file.
+pub struct Reader<R: Read> {
+    // Where the file is read from.
+    input: R,
+
+    // The header read off the front of the file.
+    header: Header,
+
+    // What to do with records that fail their checksum.
+    on_corrupt: OnCorrupt,
+
+    // The CRC over every byte read so far, and the number of bytes read so far.
+    crc: u32,
+    offset: u64,
+
+    // The number of records read, and skipped, so far.
+    records: u64,
+    skipped: u64,
+
+    // True once the trailer was read and checked.
+    done: bool,
+
+    // The key and value of the record last read.
+    key: Vec<u8>,
+    value: Vec<u8>,
+}
+
+impl<R: Read> Reader<R> {
+    /// Reads and checks the header of a file, and returns a Reader for it's records.
+    ///
+    /// # Arguments
+    ///
+    /// * `input`:      Where the file is read from.
+    /// * `on_corrupt`: What to do with records that fail their checksum.
+    ///
+    /// # Return
+    ///
+    /// The Reader, or Error::NotKv, Error::Version, Error::Header or Error::Truncated if the
+    /// header is not a valid header of this version of the format.
+    pub fn new(mut input: R, on_corrupt: OnCorrupt) -> Result<Reader<R>, Error> {
+        let (mut crc, mut offset) = (0, 0);
+
+        let mut fixed = [0; FIXED_LEN];
+        read(&mut input, &mut crc, &mut offset, &mut fixed[..12])?;
+        if fixed[0..8] != MAGIC {
+            return Err(Error::NotKv);
+        }
+        let version = le(&fixed[8..12]) as u32;
+        if version != VERSION {
+            return Err(Error::Version(version));
+        }
+
+        read(&mut input, &mut crc, &mut offset, &mut fixed[12..])?;
+        let key_schema_len = le(&fixed[40..42]) as usize;
+        let value_schema_len = le(&fixed[42..44]) as usize;
+        let len = FIXED_LEN + key_schema_len + value_schema_len + 4;
+        if le(&fixed[12..16]) as usize != len {
+            return Err(Error::Header(format!(
+                "header length {} does not match it's schemas",
+                le(&fixed[12..16])
+            )));
+        }
+
+        let mut schemas = vec![0; key_schema_len + value_schema_len];
+        read(&mut input, &mut crc, &mut offset, &mut schemas)?;
+        let expected = crc;
+        let mut stored = [0; 4];
+        read(&mut input, &mut crc, &mut offset, &mut stored)?;
+        if le(&stored) as u32 != expected {
+            return Err(Error::Header("header fails it's checksum".to_string()));
+        }
+
+        let (key_schema, value_schema) = schemas.split_at(key_schema_len);
+        let header = Header {
+            tenant: le(&fixed[16..20]) as TenantId,
+            flags: le(&fixed[20..24]) as u32,
+            table: le(&fixed[24..32]),
+            records: match le(&fixed[32..40]) {
+                UNKNOWN => None,
+                records => Some(records),
+            },
+            key_schema: schema(key_schema, "key")?,
+            value_schema: schema(value_schema, "value")?,
+        };
+
+        Ok(Reader {
+            input: input,
+            header: header,
+            on_corrupt: on_corrupt,
+            crc: crc,
+            offset: offset,
+            records: 0,
+            skipped: 0,
+            done: false,
+            key: Vec::new(),
+            value: Vec::new(),
+        })
+    }
+
+    /// Returns the header read off the front of the file.
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// Reads the next record. Once the last record was read, the trailer is read and checked
+    /// against the records read before the end of the file is reported.
+    ///
+    /// # Return
+    ///
+    /// The key and value of the record, or None once every record was read. Error::Truncated
+    /// if the file ends before it's trailer, Error::Corrupt if a record fails it's checksum
+    /// and the Reader aborts on corruption, and Error::Trailer if the trailer is malformed or
+    /// does not match what was read. The whole-file checksum is only checked if no record was
+    /// skipped, since a skipped record fails it anyway.
+    pub fn next(&mut self) -> Result<Option<(&[u8], &[u8])>, Error> {
+        loop {
+            if self.done {
+                return Ok(None);
+            }
+
+            let start = self.offset;
+            let mut lens = [0; 6];
+            read(&mut self.input, &mut self.crc, &mut self.offset, &mut lens)?;
+            let key_len = le(&lens[0..2]) as usize;
+            if key_len == 0 {
+                self.trailer(&lens[2..6])?;
+                continue;
+            }
+
+            let val_len = le(&lens[2..6]) as u64;
+            self.key.resize(key_len, 0);
+            read(
+                &mut self.input,
+                &mut self.crc,
+                &mut self.offset,
+                &mut self.key,
+            )?;
+
+            // The value is read without sizing it up front, so that a corrupt length does not
+            // allocate upto 4 GB before the file turns out to be too short.
+            self.value.clear();
+            let got = (&mut self.input)
+                .take(val_len)
+                .read_to_end(&mut self.value)?;
+            self.crc = crc32_update(self.crc, &self.value);
+            self.offset += got as u64;
+            if (got as u64) < val_len {
+                return Err(Error::Truncated(self.offset));
+            }
+
+            let expected = crc32_update(crc32_update(crc32(&lens), &self.key), &self.value);
+            let mut stored = [0; 4];
+            read(
+                &mut self.input,
+                &mut self.crc,
+                &mut self.offset,
+                &mut stored,
+            )?;
+            if le(&stored) as u32 == expected {
+                self.records += 1;
+                break;
+            }
+
+            match self.on_corrupt {
+                OnCorrupt::Abort => {
+                    return Err(Error::Corrupt {
+                        record: self.records + self.skipped,
+                        offset: start,
+                    })
+                }
+                OnCorrupt::Skip => self.skipped += 1,
+            }
+        }
+
+        Ok(Some((&self.key, &self.value)))
+    }
+
+    /// Returns the number of records read so far.
+    pub fn records(&self) -> u64 {
+        self.records
+    }
+
+    /// Returns the number of records skipped so far because they failed their checksum.
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+
+    // Reads the rest of the trailer, after the key length of 0 that marks it, and checks it
+    // against what was read. Nothing may follow the trailer.
+    fn trailer(&mut self, marker: &[u8]) -> Result<(), Error> {
+        if marker != TRAILER {
+            return Err(Error::Trailer("bad marker".to_string()));
+        }
+
+        let mut records = [0; 8];
+        read(
+            &mut self.input,
+            &mut self.crc,
+            &mut self.offset,
+            &mut records,
+        )?;
+        let expected = self.crc;
+        let mut stored = [0; 4];
+        read(
+            &mut self.input,
+            &mut self.crc,
+            &mut self.offset,
+            &mut stored,
+        )?;
+
+        let records = le(&records);
+        let seen = self.records + self.skipped;
+        if records != seen {
+            return Err(Error::Trailer(format!(
+                "trailer counts {} records, {} were found",
+                records, seen
+            )));
+        }
+        if self
+            .header
+            .records
+            .map_or(false, |declared| declared != records)
+        {
+            return Err(Error::Trailer(format!(
+                "header declares {} records, trailer counts {}",
+                self.header.records.unwrap(),
+                records
+            )));
+        }
+        if self.skipped == 0 && le(&stored) as u32 != expected {
+            return Err(Error::Trailer("file fails it's checksum".to_string()));
+        }
+
+        let mut rest = [0; 1];
+        if self.input.read(&mut rest)? > 0 {
+            return Err(Error::Trailer(format!(
+                "data follows the trailer at offset {}",
+                self.offset
+            )));
+        }
+
+        self.done = true;
+        Ok(())
+    }
+}
+
+/// Writes a file of records out as text, for a person to read. The header is written out as
+/// comments, followed by a line per record with the record's index, key and value; bytes that
+/// are not printable are escaped, and values are cut short after `max_value` bytes.
+///
+/// # Arguments
+///
+/// * `input`:     The file of records.
+/// * `out`:       Where the text is written to.
+/// * `max_value`: The most bytes of each value to write out.
+///
+/// # Return
+///
+/// The number of records written out, or the error the file could not be read with. The text
+/// written upto the error is left on the output.
+pub fn write_text<R: Read, W: Write>(
+    input: &mut Reader<R>,
+    out: &mut W,
+    max_value: usize,
+) -> Result<u64, Error> {
+    {
+        let h = input.header();
+        let records = h.records.map_or("unknown".to_string(), |n| n.to_string());
+        writeln!(
+            out,
+            "# tenant {} table {} records {} flags {:#x}",
+            h.tenant, h.table, records, h.flags
+        )?;
+        if let Some(ref schema) = h.key_schema {
+            writeln!(out, "# key schema {:?}", schema.fields())?;
+        }
+        if let Some(ref schema) = h.value_schema {
+            writeln!(out, "# value schema {:?}", schema.fields())?;
+        }
+    }
+
+    let mut i = 0;
+    loop {
+        let line = match input.next()? {
+            Some((key, value)) => {
+                let cut = &value[..value.len().min(max_value)];
+                let more = match value.len() > cut.len() {
+                    true => format!("... ({} bytes)", value.len()),
+                    false => String::new(),
+                };
+                format!("{} {} {}{}", i, escape(key), escape(cut), more)
+            }
+
+            None => break,
+        };
+        writeln!(out, "{}", line)?;
+        i += 1;
+    }
+
+    writeln!(out, "# {} records, {} skipped", i, input.skipped())?;
+    Ok(i)
+}
+
+// Reads exactly enough bytes to fill `buf`, folding them into a CRC and an offset. Running out
+// of input is reported as truncation at the offset.
+fn read<R: Read>(
+    input: &mut R,
+    crc: &mut u32,
+    offset: &mut u64,
+    buf: &mut [u8],
+) -> Result<(), Error> {
+    match input.read_exact(buf) {
+        Ok(()) => {
+            *crc = crc32_update(*crc, buf);
+            *offset += buf.len() as u64;
+            Ok(())
+        }
+
+        Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => Err(Error::Truncated(*offset)),
+        Err(e) => Err(Error::Io(e)),
+    }
+}
+
+// Parses a schema off the header. Empty if none was declared.
+fn schema(buf: &[u8], what: &str) -> Result<Option<Schema>, Error> {
+    match buf.is_empty() {
+        true => Ok(None),
+        false => Schema::parse(buf)
+            .map(Some)
+            .map_err(|e| Error::Header(format!("bad {} schema; {}", what, e))),
+    }
+}
+
+// Escapes the bytes that are not printable.
+fn escape(bytes: &[u8]) -> String {
+    let escaped: Vec<u8> = bytes
+        .iter()
+        .flat_map(|b| ascii::escape_default(*b))
+        .collect();
+    String::from_utf8_lossy(&escaped).into_owned()
+}
+
+// Reads a little endian integer that takes up all of `buf`.
+fn le(buf: &[u8]) -> u64 {
+    buf.iter()
+        .enumerate()
+        .fold(0, |acc, (i, b)| acc | (*b as u64) << (8 * i))
+}
+
+// This module contains round-trip and corruption tests for the format.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sandstorm::schema::Field;
+
+    // Returns records of several lengths, including a value longer than 64 KB.
+    fn records() -> Vec<(Vec<u8>, Vec<u8>)> {
+        let mut records: Vec<(Vec<u8>, Vec<u8>)> = (0..50u8)
+            .map(|i| (vec![i; 1 + i as usize % 30], vec![!i; i as usize * 7]))
+            .collect();
+        records.push((b"large".to_vec(), vec![7; 70_000]));
+        records
+    }
+
+    // Writes a file with a header and records.
+    fn file(header: Header, records: &[(Vec<u8>, Vec<u8>)]) -> Vec<u8> {
+        let mut writer = Writer::new(Vec::new(), header).unwrap();
+        for &(ref key, ref value) in records.iter() {
+            writer.write(key, value).unwrap();
+        }
+        assert_eq!(records.len() as u64, writer.records());
+        writer.finish().unwrap()
+    }
+
+    // Reads every record off a file, along with the number skipped.
+    fn read_all(
+        buf: &[u8],
+        on_corrupt: OnCorrupt,
+    ) -> Result<(Vec<(Vec<u8>, Vec<u8>)>, u64), Error> {
+        let mut reader = Reader::new(buf, on_corrupt)?;
+        let mut records = Vec::new();
+        while let Some((key, value)) = reader.next()? {
+            records.push((key.to_vec(), value.to_vec()));
+        }
+        assert_eq!(records.len() as u64, reader.records());
+        Ok((records, reader.skipped()))
+    }
+
+    // Returns the offset of the i'th record on a file without schemas.
+    fn offset(records: &[(Vec<u8>, Vec<u8>)], i: usize) -> usize {
+        FIXED_LEN
+            + 4
+            + records[..i]
+                .iter()
+                .map(|r| 10 + r.0.len() + r.1.len())
+                .sum::<usize>()
+    }
+
+    // Tests that records and headers round trip, with and without schemas and record counts.
+    #[test]
+    fn test_kvformat_roundtrip() {
+        let records = records();
+
+        let mut declared = Header::new(3, 9);
+        declared.records = Some(records.len() as u64);
+        declared.flags = FLAG_DUMP_ORDER | 0x100;
+        declared.key_schema = Some(Schema::new(vec![Field::Blob(1, 30)]).unwrap());
+        declared.value_schema = Some(Schema::new(vec![Field::Blob(0, 100_000)]).unwrap());
+
+        for header in [Header::new(1, 2), declared].iter() {
+            let buf = file(header.clone(), &records);
+            let mut reader = Reader::new(&buf[..], OnCorrupt::Abort).unwrap();
+            assert_eq!(header, reader.header());
+
+            for &(ref key, ref value) in records.iter() {
+                assert_eq!(Some((&key[..], &value[..])), reader.next().unwrap());
+            }
+            assert_eq!(None, reader.next().unwrap());
+            assert_eq!(None, reader.next().unwrap());
+            assert_eq!(
+                (records.len() as u64, 0),
+                (reader.records(), reader.skipped())
+            );
+        }
+
+        // A file without any records is a header and a trailer.
+        let buf = file(Header::new(1, 2), &[]);
+        assert_eq!(FIXED_LEN + 4 + 18, buf.len());
+        assert_eq!(0, read_all(&buf, OnCorrupt::Abort).unwrap().0.len());
+    }
+
+    // Tests that records the format can't hold, or that don't fit the declared schemas, are
+    // refused, and that a file can't be finished with fewer records than it declared.
+    #[test]
+    fn test_kvformat_refused() {
+        let mut header = Header::new(1, 2);
+        header.key_schema = Some(Schema::new(vec![Field::U32]).unwrap());
+        let mut writer = Writer::new(Vec::new(), header).unwrap();
+        writer.write(b"abcd", b"").unwrap();
+        for key in [&b""[..], &b"abc"[..], &[0; 70_000][..]].iter() {
+            match writer.write(key, b"value") {
+                Err(Error::Record { record: 1, .. }) => {}
+                r => panic!("Key of {} bytes was not refused: {:?}", key.len(), r),
+            }
+        }
+        assert_eq!(1, writer.records());
+
+        let mut header = Header::new(1, 2);
+        header.records = Some(2);
+        let mut writer = Writer::new(Vec::new(), header).unwrap();
+        writer.write(b"key", b"value").unwrap();
+        match writer.finish() {
+            Err(Error::Trailer(_)) => {}
+            r => panic!("Short file was finished: {:?}", r),
+        }
+    }
+
+    // Tests that every way of cutting a file short is reported as truncation.
+    #[test]
+    fn test_kvformat_truncated() {
+        let records: Vec<(Vec<u8>, Vec<u8>)> = records().into_iter().take(5).collect();
+        let buf = file(Header::new(1, 2), &records);
+        for len in 0..buf.len() {
+            match read_all(&buf[..len], OnCorrupt::Skip) {
+                Err(Error::Truncated(offset)) => assert!(offset <= len as u64),
+                r => panic!(
+                    "File cut to {} bytes was not truncated: {:?}",
+                    len,
+                    r.map(|r| r.1)
+                ),
+            }
+        }
+        assert!(read_all(&buf, OnCorrupt::Skip).is_ok());
+    }
+
+    // Tests that a corrupt record aborts the read or is skipped, as asked, and that corruption
+    // anywhere else fails the whole file.
+    #[test]
+    fn test_kvformat_corrupt() {
+        let records = records();
+        for &i in [0, 7, records.len() - 1].iter() {
+            let mut buf = file(Header::new(1, 2), &records);
+            let at = offset(&records, i) + 6 + records[i].0.len();
+            buf[at] ^= 0x1;
+
+            match read_all(&buf, OnCorrupt::Abort) {
+                Err(Error::Corrupt { record, offset: o }) => {
+                    assert_eq!((i as u64, offset(&records, i) as u64), (record, o))
+                }
+                r => panic!(
+                    "Corrupt record {} was not detected: {:?}",
+                    i,
+                    r.map(|r| r.1)
+                ),
+            }
+
+            let (read, skipped) = read_all(&buf, OnCorrupt::Skip).unwrap();
+            let mut expected = records.clone();
+            expected.remove(i);
+            assert_eq!((expected, 1), (read, skipped));
+        }
+
+        let len = file(Header::new(1, 2), &records).len();
+        let corrupt = |at: usize| {
+            let mut buf = file(Header::new(1, 2), &records);
+            buf[at] ^= 0x1;
+            read_all(&buf, OnCorrupt::Skip).map(|r| r.1)
+        };
+        match corrupt(0) {
+            Err(Error::NotKv) => {}
+            r => panic!("Bad magic was not detected: {:?}", r),
+        }
+        match corrupt(8) {
+            Err(Error::Version(0)) => {}
+            r => panic!("Bad version was not detected: {:?}", r),
+        }
+        for &at in [16, 30, FIXED_LEN + 1].iter() {
+            match corrupt(at) {
+                Err(Error::Header(_)) => {}
+                r => panic!("Corrupt header at {} was not detected: {:?}", at, r),
+            }
+        }
+        for &at in [len - 16, len - 10, len - 1].iter() {
+            match corrupt(at) {
+                Err(Error::Trailer(_)) => {}
+                r => panic!("Corrupt trailer at {} was not detected: {:?}", at, r),
+            }
+        }
+
+        let mut buf = file(Header::new(1, 2), &records);
+        buf.push(0);
+        match read_all(&buf, OnCorrupt::Abort) {
+            Err(Error::Trailer(_)) => {}
+            r => panic!(
+                "Data after the trailer was not detected: {:?}",
+                r.map(|r| r.1)
+            ),
+        }
+    }
+
+    // Tests that files of other versions are refused before anything else is read off them.
+    #[test]
+    fn test_kvformat_version() {
+        let mut buf = file(Header::new(1, 2), &records());
+        buf[8] = 2;
+        match Reader::new(&buf[..12], OnCorrupt::Abort) {
+            Err(Error::Version(2)) => {}
+            r => panic!("Version 2 was not refused: {:?}", r.map(|r| r.records())),
+        }
+        assert!(Error::Version(2).to_string().contains("version 2"));
+    }
+
+    // Tests the text a file is written out as.
+    #[test]
+    fn test_kvformat_text() {
+        let records = vec![
+            (b"key".to_vec(), b"value".to_vec()),
+            (vec![0, 255], b"a\nlonger value".to_vec()),
+        ];
+        let buf = file(Header::new(1, 2), &records);
+
+        let mut text = Vec::new();
+        let mut reader = Reader::new(&buf[..], OnCorrupt::Abort).unwrap();
+        assert_eq!(2, write_text(&mut reader, &mut text, 8).unwrap());
+        assert_eq!(
+            "# tenant 1 table 2 records unknown flags 0x0\n\
+             0 key value\n\
+             1 \\x00\\xff a\\nlonger... (14 bytes)\n\
+             # 2 records, 0 skipped\n",
+            String::from_utf8(text).unwrap()
+        );
+    }
+}
@@ -0,0 +1,411 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use spin::{Mutex, RwLock};
+
+use super::histogram::{LogHistogram, LOG_BUCKETS};
+use super::wireformat::OpCode;
+
+/// The number of opcodes latency is recorded for.
+pub const N_OPCODES: usize = OpCode::InvalidOperation as usize;
+
+/// The most extensions a core records the latency of invocations for. Past this, the extension
+/// that was least recently invoked on the core is evicted to make room for a new one.
+pub const MAX_EXTENSIONS: usize = 32;
+
+/// The latency of an opcode or extension, merged across cores, in nanoseconds. Each percentile
+/// is the upper bound of the bucket it fell into.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LatencySummary {
+    /// The number of requests recorded.
+    pub count: u64,
+
+    /// The median latency.
+    pub p50: u64,
+
+    /// The 99th percentile latency.
+    pub p99: u64,
+
+    /// The 99.9th percentile latency.
+    pub p999: u64,
+}
+
+impl LatencySummary {
+    /// Summarizes a histogram of latencies in cycles.
+    ///
+    /// # Arguments
+    ///
+    /// * `hist`:              The histogram.
+    /// * `cycles_per_second`: The rate of the clock the latencies were measured off.
+    pub fn new(hist: &LogHistogram, cycles_per_second: u64) -> LatencySummary {
+        let nanos = hist.to_nanos(cycles_per_second);
+        LatencySummary {
+            count: nanos.total(),
+            p50: nanos.percentile(0.5),
+            p99: nanos.percentile(0.99),
+            p999: nanos.percentile(0.999),
+        }
+    }
+}
+
+// The latency of invocations of one extension on a core.
+struct ExtLatency {
+    // The name of the extension.
+    name: Vec<u8>,
+
+    // When the extension was last invoked, on the core's count of invocations. Decides which
+    // extension is evicted once the core tracks MAX_EXTENSIONS of them.
+    used: u64,
+
+    // The latency of each invocation, in cycles.
+    hist: LogHistogram,
+}
+
+// The extensions a core records latency for, and the number of invocations it recorded.
+struct Extensions {
+    invokes: u64,
+    exts: Vec<ExtLatency>,
+}
+
+/// Records the time a single core took to respond to requests, from the time their burst was
+/// received to the time their response was handed off, in cycles. Only written to by the core
+/// itself, and read by the latency_stats() RPC on any core.
+pub struct CoreLatency {
+    // The core the latencies were recorded on.
+    core: i32,
+
+    // The histogram of each opcode, laid out back to back; bucket `b` of opcode `o` is at
+    // `o * LOG_BUCKETS + b`.
+    ops: Vec<AtomicU64>,
+
+    // The latency of invocations of each extension, atmost MAX_EXTENSIONS of them.
+    exts: Mutex<Extensions>,
+}
+
+impl CoreLatency {
+    /// Returns the latencies of a core that has not responded to any request yet.
+    pub fn new(core: i32) -> CoreLatency {
+        CoreLatency {
+            core: core,
+            ops: (0..N_OPCODES * LOG_BUCKETS)
+                .map(|_| AtomicU64::new(0))
+                .collect(),
+            exts: Mutex::new(Extensions {
+                invokes: 0,
+                exts: Vec::new(),
+            }),
+        }
+    }
+
+    /// Returns the core the latencies were recorded on.
+    pub fn core(&self) -> i32 {
+        self.core
+    }
+
+    /// Records the time taken to respond to a request.
+    ///
+    /// # Arguments
+    ///
+    /// * `opcode`: The opcode on the request. Invalid opcodes are not recorded.
+    /// * `cycles`: The time taken to respond.
+    /// * `name`:   The name of the extension the request invoked, if it named one.
+    #[inline]
+    pub fn record(&self, opcode: u8, cycles: u64, name: Option<&[u8]>) {
+        let opcode = opcode as usize;
+        if opcode >= N_OPCODES {
+            return;
+        }
+
+        let bucket = opcode * LOG_BUCKETS + LogHistogram::bucket(cycles);
+        self.ops[bucket].fetch_add(1, Ordering::Relaxed);
+
+        if let Some(name) = name {
+            self.record_ext(name, cycles);
+        }
+    }
+
+    // Records the time taken by an invocation of an extension, evicting the least recently
+    // invoked extension if the core already tracks MAX_EXTENSIONS of them.
+    fn record_ext(&self, name: &[u8], cycles: u64) {
+        let mut exts = self.exts.lock();
+        exts.invokes += 1;
+        let now = exts.invokes;
+
+        if let Some(ext) = exts.exts.iter_mut().find(|e| e.name == name) {
+            ext.used = now;
+            ext.hist.record(cycles);
+            return;
+        }
+
+        if exts.exts.len() >= MAX_EXTENSIONS {
+            let lru = exts
+                .exts
+                .iter()
+                .enumerate()
+                .min_by_key(|&(_, e)| e.used)
+                .map(|(i, _)| i);
+            if let Some(lru) = lru {
+                exts.exts.swap_remove(lru);
+            }
+        }
+
+        let mut hist = LogHistogram::new();
+        hist.record(cycles);
+        exts.exts.push(ExtLatency {
+            name: name.to_vec(),
+            used: now,
+            hist: hist,
+        });
+    }
+
+    /// Returns the latencies recorded for an opcode so far.
+    ///
+    /// # Arguments
+    ///
+    /// * `opcode`: The opcode, less than N_OPCODES.
+    pub fn opcode(&self, opcode: usize) -> LogHistogram {
+        let mut hist = LogHistogram::new();
+        let ops = &self.ops[opcode * LOG_BUCKETS..(opcode + 1) * LOG_BUCKETS];
+        for (bucket, count) in ops.iter().enumerate() {
+            hist.add(bucket, count.load(Ordering::Relaxed));
+        }
+        hist
+    }
+
+    /// Returns the latencies recorded for each extension the core still tracks.
+    pub fn extensions(&self) -> Vec<(Vec<u8>, LogHistogram)> {
+        self.exts
+            .lock()
+            .exts
+            .iter()
+            .map(|e| (e.name.clone(), e.hist.clone()))
+            .collect()
+    }
+}
+
+/// The latencies recorded on every scheduler core on the server.
+pub struct Latencies {
+    cores: RwLock<Vec<Arc<CoreLatency>>>,
+}
+
+impl Latencies {
+    /// Returns an empty set of latencies.
+    pub fn new() -> Latencies {
+        Latencies {
+            cores: RwLock::new(Vec::new()),
+        }
+    }
+
+    /// Returns the latencies of a core so that it can record them, adding them if the core has
+    /// none yet. A core whose scheduler was replaced keeps recording where it left off.
+    pub fn register(&self, core: i32) -> Arc<CoreLatency> {
+        let mut cores = self.cores.write();
+        if let Some(latency) = cores.iter().find(|c| c.core == core) {
+            return Arc::clone(latency);
+        }
+
+        let latency = Arc::new(CoreLatency::new(core));
+        cores.push(Arc::clone(&latency));
+        latency
+    }
+
+    /// Returns the latencies of every opcode that was recorded on any core, merged across
+    /// cores, ordered by opcode.
+    pub fn opcodes(&self) -> Vec<(u8, LogHistogram)> {
+        let cores = self.cores.read();
+        (0..N_OPCODES)
+            .map(|op| {
+                let mut hist = LogHistogram::new();
+                for core in cores.iter() {
+                    hist.merge(&core.opcode(op));
+                }
+                (op as u8, hist)
+            }).filter(|&(_, ref hist)| hist.total() > 0)
+            .collect()
+    }
+
+    /// Returns the latencies of an opcode, merged across cores.
+    ///
+    /// # Arguments
+    ///
+    /// * `opcode`: The opcode. Opcodes that are invalid have no latencies.
+    pub fn opcode(&self, opcode: u8) -> LogHistogram {
+        let mut hist = LogHistogram::new();
+        if (opcode as usize) < N_OPCODES {
+            for core in self.cores.read().iter() {
+                hist.merge(&core.opcode(opcode as usize));
+            }
+        }
+        hist
+    }
+
+    /// Returns the latencies of every extension tracked on any core, merged across cores, most
+    /// invoked first.
+    pub fn extensions(&self) -> Vec<(Vec<u8>, LogHistogram)> {
+        let mut merged: Vec<(Vec<u8>, LogHistogram)> = Vec::new();
+        for core in self.cores.read().iter() {
+            for (name, hist) in core.extensions().into_iter() {
+                match merged.iter().position(|&(ref n, _)| *n == name) {
+                    Some(at) => merged[at].1.merge(&hist),
+                    None => merged.push((name, hist)),
+                }
+            }
+        }
+
+        merged.sort_by(|a, b| b.1.total().cmp(&a.1.total()).then(a.0.cmp(&b.0)));
+        merged
+    }
+
+    /// Returns the latencies of an extension, merged across the cores that still track it.
+    ///
+    /// # Arguments
+    ///
+    /// * `name`: The name of the extension.
+    pub fn extension(&self, name: &[u8]) -> LogHistogram {
+        let mut hist = LogHistogram::new();
+        for core in self.cores.read().iter() {
+            for (n, h) in core.extensions().iter() {
+                if &n[..] == name {
+                    hist.merge(h);
+                }
+            }
+        }
+        hist
+    }
+}
+
+thread_local! {
+    // The latencies of the core this thread is running on. Set once by the scheduler's thread
+    // when it starts up, and recorded into by rpc::fixup_response().
+    static CORE_LATENCY: RefCell<Option<Arc<CoreLatency>>> = RefCell::new(None);
+}
+
+/// Sets the latencies that responses handed off on the calling thread are recorded into.
+/// Threads that never call this record nothing.
+///
+/// # Arguments
+///
+/// * `latency`: The latencies of the core the thread runs on. Refer to Latencies::register().
+pub fn install(latency: Arc<CoreLatency>) {
+    CORE_LATENCY.with(|l| *l.borrow_mut() = Some(latency));
+}
+
+/// Records the time taken to respond to a request into the calling thread's latencies, if any
+/// were installed. Refer to CoreLatency::record().
+#[inline]
+pub fn record(opcode: u8, cycles: u64, name: Option<&[u8]>) {
+    CORE_LATENCY.with(|l| {
+        if let Some(ref latency) = *l.borrow() {
+            latency.record(opcode, cycles, name);
+        }
+    });
+}
+
+// This module contains unit tests for CoreLatency and Latencies.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    // The clock rate the tests convert cycles at; 1 cycle per nanosecond.
+    const HZ: u64 = 1_000_000_000;
+
+    // Tests that known delays recorded on a core are bracketed by the percentiles read back.
+    #[test]
+    fn test_core_percentiles() {
+        let latencies = Latencies::new();
+        let core = latencies.register(0);
+        let get = OpCode::SandstormGetRpc as u8;
+        for i in 0..1000 {
+            let delay = if i < 990 { 5_000 } else { 800_000 };
+            core.record(get, delay, None);
+        }
+        core.record(OpCode::InvalidOperation as u8, 5_000, None);
+
+        let ops = latencies.opcodes();
+        assert_eq!(1, ops.len());
+        assert_eq!(get, ops[0].0);
+
+        let summary = LatencySummary::new(&ops[0].1, HZ);
+        assert_eq!(1000, summary.count);
+        assert!(summary.p50 >= 5_000 && summary.p50 <= 6_250);
+        assert!(summary.p99 >= 5_000 && summary.p99 <= 6_250);
+        assert!(summary.p999 >= 800_000 && summary.p999 <= 1_000_000);
+    }
+
+    // Tests that the latencies of each core are merged, and that a core registered twice keeps
+    // recording into the same histograms.
+    #[test]
+    fn test_merge_cores() {
+        let latencies = Arc::new(Latencies::new());
+        let put = OpCode::SandstormPutRpc as u8;
+
+        let handles: Vec<_> = (0..4)
+            .map(|core| {
+                let latencies = Arc::clone(&latencies);
+                thread::spawn(move || {
+                    install(latencies.register(core));
+                    for _ in 0..100 {
+                        record(put, 1_000 * (core as u64 + 1), None);
+                    }
+                })
+            }).collect();
+        for handle in handles.into_iter() {
+            handle.join().expect("Failed to record latencies.");
+        }
+        latencies.register(3).record(put, 1_000, None);
+
+        let hist = latencies.opcode(put);
+        assert_eq!(401, hist.total());
+        let summary = LatencySummary::new(&hist, HZ);
+        assert!(summary.p50 >= 2_000 && summary.p50 <= 2_500);
+        assert!(summary.p99 >= 4_000 && summary.p99 <= 5_000);
+        assert_eq!(0, latencies.opcode(OpCode::InvalidOperation as u8).total());
+
+        // Nothing is recorded on threads that never installed latencies.
+        record(put, 1_000, None);
+        assert_eq!(401, latencies.opcode(put).total());
+    }
+
+    // Tests that extensions are merged across cores by name, and that the least recently invoked
+    // one is evicted once a core tracks too many.
+    #[test]
+    fn test_extensions() {
+        let latencies = Latencies::new();
+        let invoke = OpCode::SandstormInvokeRpc as u8;
+        let (a, b) = (latencies.register(0), latencies.register(1));
+        a.record(invoke, 10_000, Some(b"tao"));
+        b.record(invoke, 20_000, Some(b"tao"));
+        b.record(invoke, 30_000, Some(b"get"));
+
+        let exts = latencies.extensions();
+        assert_eq!(2, exts.len());
+        assert_eq!((b"tao".to_vec(), 2), (exts[0].0.clone(), exts[0].1.total()));
+        assert_eq!((b"get".to_vec(), 1), (exts[1].0.clone(), exts[1].1.total()));
+        assert_eq!(3, latencies.opcode(invoke).total());
+
+        for i in 0..MAX_EXTENSIONS {
+            a.record(invoke, 1_000, Some(format!("ext{}", i).as_bytes()));
+        }
+        assert_eq!(MAX_EXTENSIONS, a.extensions().len());
+        assert_eq!(1, latencies.extension(b"tao").total());
+        assert_eq!(1, latencies.extension(b"ext0").total());
+    }
+}
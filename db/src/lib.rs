@@ -30,8 +30,10 @@ extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 extern crate bincode;
-extern crate crypto;
 extern crate hashbrown;
+extern crate libc;
+#[cfg(any(test, feature = "sim"))]
+extern crate rand;
 extern crate spin;
 extern crate time;
 extern crate toml;
@@ -46,34 +48,113 @@ pub extern crate log;
 mod alloc;
 mod container;
 mod context;
+mod merge;
 mod native;
-mod service;
+mod route;
 mod tenant;
 
 // Public modules for binaries.
+/// This module accounts for the bytes written to each table against the bytes allocated for them.
+pub mod amplify;
+/// This module records the invocations issued by audited tenants.
+pub mod audit;
+/// This module backs scheduler cores off polling when they keep finding nothing to do.
+pub mod backoff;
+/// This module coalesces concurrent get() requests for the same key on a core.
+pub mod coalesce;
+/// This module compresses and decompresses values stored in tables.
+pub mod compress;
 /// This module is needed to parse the server and config file.
 pub mod config;
+/// This module reports panics on scheduler cores, along with the requests that led up to them.
+pub mod crash;
 /// This module is needed to add cycles counters at various place in the code.
 #[allow(dead_code)]
 #[allow(unused_imports)]
 pub mod cyclecounter;
 /// This module provides functionality to manipulate CPU cycle ticks.
 pub mod cycles;
+/// This module shares identical values written to a table, instead of each object copying them.
+pub mod dedup;
+/// This module counts the work tenants defer until after their responses have been sent out.
+pub mod defer;
 /// This module provides functionality to send and receive packets over the network.
 pub mod dispatch;
+/// This module tracks a graceful drain and shutdown of the server.
+pub mod drain;
+/// This module tracks the epochs tenants and tables move to when something in them is destroyed.
+pub mod epoch;
+/// This module bounds cache-mode tables to a capacity target by evicting objects off them.
+pub mod evict;
+/// This module shares each scheduler core fairly between the tenants whose requests it runs.
+pub mod fair;
+/// This module populates tables at a bounded rate while they are serving requests.
+pub mod fill;
+/// This module puts caching hints on get() responses for objects that are read often.
+pub mod hint;
+/// This module provides the latency histograms shared by clients and the server.
+pub mod histogram;
+/// This module moves the hottest objects into a small arena of their own, and back out once they
+/// cool down.
+pub mod hot;
+/// This module maps in read-only tables from images built ahead of time.
+pub mod image;
 /// This module provides functionality to install a new extension on the server.
 pub mod install;
+/// This module checks the objects stored in tables against their checksums in the background.
+pub mod integrity;
+/// This module provides the journal that durable invocations checkpoint to.
+pub mod journal;
+/// This module defines the file format table contents are saved to and loaded from.
+pub mod kvformat;
+/// This module records how long the server takes to respond to each opcode, on each core.
+pub mod latency;
+/// This module bounds the lengths of the keys and values the server accepts.
+pub mod limits;
+/// This module stores the results of invocations for clients that lost their response.
+pub mod mailbox;
 /// This module helps in initializing the tables and task creation for each extension.
 pub mod master;
+/// This module serves memcached text protocol gets and sets off a table.
+pub mod memcache;
+/// This module quarantines tenants whose requests keep failing.
+pub mod quarantine;
+/// This module replays responses to retransmitted requests instead of executing them again.
+pub mod replay;
 /// This module helps in parsing the rpc arguments from the packets.
 pub mod rpc;
+/// This module counts the requests, errors and pushbacks of each client run.
+pub mod runs;
+/// This module prefaults the table heap and counts page faults taken by the server.
+pub mod prefault;
+/// This module pre-sizes tables for the number of objects they are expected to hold.
+pub mod presize;
+/// This module provides pooled, generator-free tasks for native get() and put() requests.
+pub mod pool;
 /// This module helps in task scheduling on the server threads.
 pub mod sched;
+/// This module has the trait through which RPC requests are turned into tasks.
+pub mod service;
+/// This module simulates the scheduler and pushback policy on a virtual clock.
+#[cfg(any(test, feature = "sim"))]
+pub mod sim;
+/// This module reads several keys off a table, and tells whether they changed while being read.
+pub mod snapshot;
+/// This module decides whether a get() can be served off a copy that lags behind the primary.
+pub mod stale;
+/// This module tracks the partial responses an invocation streams back to it's tenant.
+pub mod stream;
 /// This module provides functionality related to the tables.
 pub mod table;
 /// This modules has a trait which should be implemented by each task instance.
 pub mod task;
+/// This module caches the tenant handles looked up on each core.
+pub mod tenant_cache;
+/// This module packs and reads the optional metadata appended to responses after their payload.
+pub mod trailer;
 /// This module contains the transaction related code.
 pub mod tx;
+/// This module validates server and client configs at startup.
+pub mod validate;
 /// This module contains the wireformat realted to the various functionalities.
 pub mod wireformat;
@@ -0,0 +1,191 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use std::cmp::min;
+use std::mem::size_of;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use sandstorm::common::PACKET_IP_LEN;
+use sandstorm::put;
+
+use super::wireformat::{GetResponse, InvokeRequest, RpcStatus};
+
+/// The longest key an object can have. The allocator keeps a key's length in 16 bits, and uses
+/// the top two to mark the object's value compressed and the object checksummed.
+pub const HARD_MAX_KEY_LEN: usize = (1 << 14) - 1;
+
+/// The largest value a get() response can carry. Responses are sent out in a single frame, and
+/// the server does not use jumbo frames, so the value must fit in a 1500 Byte IP packet along
+/// with the IP, UDP and response headers.
+pub const HARD_MAX_VALUE_LEN: usize = 1500 - PACKET_IP_LEN as usize - size_of::<GetResponse>();
+
+/// The longest key an object can have if the server config does not say otherwise.
+pub const DEFAULT_MAX_KEY_LEN: usize = 1024;
+
+/// Returns the largest value an invoke() of the put extension can write under a key of this
+/// length. The request is sent out in a single frame too, so the extension's name, it's
+/// arguments and the value must fit in a 1500 Byte IP packet along with the IP, UDP and invoke
+/// headers. Refer to sandstorm::put.
+pub fn max_invoke_put_len(key_len: usize) -> usize {
+    let room = 1500
+        - PACKET_IP_LEN as usize
+        - size_of::<InvokeRequest>()
+        - put::NAME.len()
+        - put::HEADER_LEN;
+    min(HARD_MAX_VALUE_LEN, room.saturating_sub(key_len))
+}
+
+// Counters reported by stats(). Shared by all cores.
+static KEYS_REFUSED: AtomicUsize = AtomicUsize::new(0);
+static VALUES_REFUSED: AtomicUsize = AtomicUsize::new(0);
+
+/// Counters of the writes refused for being too large since the server started.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LimitStats {
+    /// The number of writes refused because of their key.
+    pub keys_refused: usize,
+
+    /// The number of writes refused because of their value.
+    pub values_refused: usize,
+}
+
+/// Returns the counters of writes refused for being too large.
+pub fn stats() -> LimitStats {
+    LimitStats {
+        keys_refused: KEYS_REFUSED.load(Ordering::Relaxed),
+        values_refused: VALUES_REFUSED.load(Ordering::Relaxed),
+    }
+}
+
+/// The largest keys and values the server accepts. Every path that writes an object into a
+/// table checks them before allocating it, so that an object that could not be read back is
+/// never written.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Limits {
+    max_key_len: usize,
+    max_value_len: usize,
+}
+
+impl Limits {
+    /// Returns limits on key and value lengths. Both are clamped to the hard maximums.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_key_len`:   The longest key, in bytes.
+    /// * `max_value_len`: The largest value, in bytes.
+    pub fn new(max_key_len: usize, max_value_len: usize) -> Limits {
+        Limits {
+            max_key_len: min(max_key_len, HARD_MAX_KEY_LEN),
+            max_value_len: min(max_value_len, HARD_MAX_VALUE_LEN),
+        }
+    }
+
+    /// Returns the longest key accepted, in bytes.
+    pub fn max_key_len(&self) -> usize {
+        self.max_key_len
+    }
+
+    /// Returns the largest value accepted, in bytes.
+    pub fn max_value_len(&self) -> usize {
+        self.max_value_len
+    }
+
+    /// Checks whether an object can be written.
+    ///
+    /// # Arguments
+    ///
+    /// * `key_len`: The length of the object's key.
+    /// * `val_len`: The length of the object's value.
+    ///
+    /// # Return
+    ///
+    /// StatusKeyTooLarge or StatusValueTooLarge if the object is over the limits. The key is
+    /// checked first.
+    pub fn check(&self, key_len: usize, val_len: usize) -> Result<(), RpcStatus> {
+        if key_len > self.max_key_len {
+            KEYS_REFUSED.fetch_add(1, Ordering::Relaxed);
+            return Err(RpcStatus::StatusKeyTooLarge);
+        }
+
+        if val_len > self.max_value_len {
+            VALUES_REFUSED.fetch_add(1, Ordering::Relaxed);
+            return Err(RpcStatus::StatusValueTooLarge);
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits::new(DEFAULT_MAX_KEY_LEN, HARD_MAX_VALUE_LEN)
+    }
+}
+
+// This module contains tests for Limits.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Tests objects right at, and one byte over each limit.
+    #[test]
+    fn test_limits_check() {
+        let limits = Limits::new(16, 100);
+        let before = stats();
+
+        assert_eq!(Ok(()), limits.check(16, 100));
+        assert_eq!(Err(RpcStatus::StatusKeyTooLarge), limits.check(17, 100));
+        assert_eq!(Err(RpcStatus::StatusValueTooLarge), limits.check(16, 101));
+        assert_eq!(Err(RpcStatus::StatusKeyTooLarge), limits.check(17, 101));
+
+        let after = stats();
+        assert!(after.keys_refused >= before.keys_refused + 2);
+        assert!(after.values_refused >= before.values_refused + 1);
+    }
+
+    // Tests that limits past the hard maximums are clamped to them.
+    #[test]
+    fn test_limits_clamped() {
+        let limits = Limits::new(usize::max_value(), usize::max_value());
+        assert_eq!(HARD_MAX_KEY_LEN, limits.max_key_len());
+        assert_eq!(HARD_MAX_VALUE_LEN, limits.max_value_len());
+        assert_eq!(Ok(()), limits.check(HARD_MAX_KEY_LEN, HARD_MAX_VALUE_LEN));
+        assert_eq!(
+            Err(RpcStatus::StatusKeyTooLarge),
+            limits.check(HARD_MAX_KEY_LEN + 1, 0)
+        );
+
+        let limits = Limits::default();
+        assert_eq!(DEFAULT_MAX_KEY_LEN, limits.max_key_len());
+        assert_eq!(HARD_MAX_VALUE_LEN, limits.max_value_len());
+    }
+
+    // Tests that an invoke() of the put extension carrying the largest value it can fills a
+    // frame exactly, and that the value can be read back by a get().
+    #[test]
+    fn test_max_invoke_put_len() {
+        for &key_len in [1, 30, 100].iter() {
+            let val_len = max_invoke_put_len(key_len);
+            let args_len = put::HEADER_LEN + key_len + val_len;
+            let frame_len =
+                PACKET_IP_LEN as usize + size_of::<InvokeRequest>() + put::NAME.len() + args_len;
+            assert!(frame_len <= 1500);
+            assert!(val_len <= HARD_MAX_VALUE_LEN);
+            assert!(frame_len == 1500 || val_len == HARD_MAX_VALUE_LEN);
+        }
+
+        assert_eq!(0, max_invoke_put_len(1500));
+    }
+}
@@ -0,0 +1,314 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! The result mailbox. An invoke() request flagged with INVOKE_FLAG_STORE_RESULT has it's result
+//! written to the tenant's MAILBOX_TABLE under the request's id before the response is sent out,
+//! so that a tenant whose response was lost can fetch the result with a fetch_result() instead
+//! of invoking the extension again; what matters for extensions that aren't idempotent, or are
+//! expensive to run. Fetching a result removes it.
+//!
+//! Results are ordinary objects on an ordinary table, allocated off the table heap. The table is
+//! created in cache mode the first time a tenant stores a result, with the tenant's quota as it's
+//! capacity target (see evict::CacheTarget); the bytes a tenant's results take are counted in the
+//! table's cache stats, and the least recently stored results are evicted to stay under the
+//! quota. A result expires a TTL after it was stored, and results larger than the cap are never
+//! stored. Either way, a fetch of it fails with StatusObjectDoesNotExist, just as if the request
+//! never asked for it's result to be stored.
+
+use std::mem::transmute;
+use std::sync::Arc;
+
+use super::alloc::Allocator;
+use super::cycles;
+use super::evict::{CacheTarget, EvictPolicy};
+use super::table::Table;
+use super::tenant::Tenant;
+use super::wireformat::{RpcStatus, MAILBOX_TABLE};
+
+use sandstorm::pack::pack;
+
+/// How long a result is kept for by default, in milliseconds.
+pub const DEFAULT_TTL_MS: u64 = 10_000;
+
+/// The largest response payload stored by default, in bytes.
+pub const DEFAULT_MAX_RESULT: usize = 1024;
+
+/// The most bytes of results each tenant's mailbox holds by default.
+pub const DEFAULT_QUOTA_BYTES: u64 = 1 << 20;
+
+// The bytes in front of the payload on a stored result: the time-stamp it expires at in cycles,
+// and the status the invocation completed with.
+const RESULT_META: usize = 9;
+
+/// How long results are kept for, the largest one stored, and how many bytes of them each tenant
+/// can have stored at once.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mailbox {
+    ttl_ms: u64,
+    max_result: usize,
+    quota: u64,
+}
+
+impl Mailbox {
+    /// Returns a mailbox policy.
+    ///
+    /// # Arguments
+    ///
+    /// * `ttl_ms`:     How long a result is kept for after it was stored, in milliseconds.
+    /// * `max_result`: The largest response payload stored, in bytes.
+    /// * `quota`:      The most bytes of results a tenant's mailbox holds, counting each result's
+    ///                 key and metadata. Atleast one result of `max_result` bytes should fit.
+    pub fn new(ttl_ms: u64, max_result: usize, quota: u64) -> Mailbox {
+        Mailbox {
+            ttl_ms: ttl_ms,
+            max_result: max_result,
+            quota: quota,
+        }
+    }
+
+    /// Returns how long a result is kept for, in cycles.
+    pub fn ttl(&self) -> u64 {
+        self.ttl_ms * cycles::cycles_per_second() / 1000
+    }
+
+    /// Returns the largest response payload stored, in bytes.
+    pub fn max_result(&self) -> usize {
+        self.max_result
+    }
+
+    /// Returns the most bytes of results a tenant's mailbox holds.
+    pub fn quota(&self) -> u64 {
+        self.quota
+    }
+
+    /// Returns a tenant's mailbox table, creating it if the tenant has not stored a result yet.
+    /// The table cannot be accessed by native RPCs.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`: The tenant whose mailbox to return.
+    pub fn table(&self, tenant: &Tenant) -> Option<Arc<Table>> {
+        if let Some(table) = tenant.get_table(MAILBOX_TABLE) {
+            return Some(table);
+        }
+
+        // Another core may be creating the table at the same time, in which case it's table is
+        // the one kept.
+        let table = Table::default();
+        table.set_native_access(false, false);
+        table.set_cache(CacheTarget::new(self.quota, 0, EvictPolicy::Written));
+        tenant.insert_table(MAILBOX_TABLE, table);
+        tenant.get_table(MAILBOX_TABLE)
+    }
+
+    /// Stores the result of an invocation in the tenant's mailbox, replacing anything stored
+    /// under the same request id.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`:  The tenant that issued the invocation.
+    /// * `heap`:    The allocator the result is allocated off.
+    /// * `id`:      The id of the invoke() request.
+    /// * `status`:  The status the invocation completed with.
+    /// * `payload`: The payload of the invoke() response.
+    /// * `now`:     The current time-stamp in cycles.
+    ///
+    /// # Return
+    ///
+    /// True if the result was stored. False if the payload is over the cap, or the result could
+    /// not be allocated.
+    pub fn store(
+        &self,
+        tenant: &Tenant,
+        heap: &Allocator,
+        id: u64,
+        status: RpcStatus,
+        payload: &[u8],
+        now: u64,
+    ) -> bool {
+        if payload.len() > self.max_result {
+            return false;
+        }
+
+        let table = match self.table(tenant) {
+            Some(table) => table,
+            None => return false,
+        };
+
+        let expires: [u8; 8] = unsafe { transmute((now + self.ttl()).to_le()) };
+        let mut value = Vec::with_capacity(RESULT_META + payload.len());
+        value.extend_from_slice(&expires);
+        value.push(status as u8);
+        value.extend_from_slice(payload);
+
+        let id = id.to_le();
+        heap.object(tenant.id(), MAILBOX_TABLE, pack(&id), &value)
+            .map(|(k, obj)| {
+                table.put(k, obj);
+            })
+            .is_some()
+    }
+
+    /// Removes a result from the tenant's mailbox, and returns it.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`: The tenant that issued the invocation.
+    /// * `heap`:   The allocator the result was allocated off.
+    /// * `id`:     The id of the invoke() request.
+    /// * `now`:    The current time-stamp in cycles.
+    ///
+    /// # Return
+    ///
+    /// The status the invocation completed with and the payload of it's response, or
+    /// StatusObjectDoesNotExist if nothing is stored under the request id or it expired.
+    pub fn fetch(
+        &self,
+        tenant: &Tenant,
+        heap: &Allocator,
+        id: u64,
+        now: u64,
+    ) -> Result<(RpcStatus, Vec<u8>), RpcStatus> {
+        let missing = RpcStatus::StatusObjectDoesNotExist;
+        let table = tenant.get_table(MAILBOX_TABLE).ok_or(missing.clone())?;
+
+        let id = id.to_le();
+        let key = pack(&id);
+        let value = table
+            .get(key)
+            .and_then(|entry| heap.resolve(entry.value))
+            .map(|(_, value)| value)
+            .ok_or(missing.clone())?;
+        table.delete(key);
+
+        if value.len() < RESULT_META {
+            return Err(missing);
+        }
+
+        let mut expires = [0u8; 8];
+        expires.copy_from_slice(&value[..8]);
+        if now >= u64::from_le(unsafe { transmute(expires) }) {
+            return Err(missing);
+        }
+
+        // The status was written by store(), so it's always a valid RpcStatus.
+        let status: RpcStatus = unsafe { transmute(value[8]) };
+        Ok((status, value[RESULT_META..].to_vec()))
+    }
+}
+
+impl Default for Mailbox {
+    fn default() -> Mailbox {
+        Mailbox::new(DEFAULT_TTL_MS, DEFAULT_MAX_RESULT, DEFAULT_QUOTA_BYTES)
+    }
+}
+
+// This module contains unit tests for Mailbox.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Tests that a stored result is fetched once, and that results that were never stored, or
+    // were over the cap, are not found.
+    #[test]
+    fn test_mailbox_fetch() {
+        let (tenant, heap) = (Tenant::new(1), Allocator::new());
+        let mailbox = Mailbox::new(1000, 8, 1 << 16);
+        let missing = Err(RpcStatus::StatusObjectDoesNotExist);
+
+        assert_eq!(missing, mailbox.fetch(&tenant, &heap, 7, 0));
+        assert!(mailbox.store(&tenant, &heap, 7, RpcStatus::StatusOk, b"result", 0));
+        assert!(mailbox.store(&tenant, &heap, 8, RpcStatus::StatusExtensionError, b"", 0));
+        assert!(!mailbox.store(&tenant, &heap, 9, RpcStatus::StatusOk, b"too large", 0));
+
+        let table = tenant
+            .get_table(MAILBOX_TABLE)
+            .expect("Mailbox does not exist");
+        assert!(!table.readable_native() && !table.writable_native());
+
+        assert_eq!(
+            Ok((RpcStatus::StatusOk, b"result".to_vec())),
+            mailbox.fetch(&tenant, &heap, 7, 1)
+        );
+        assert_eq!(missing, mailbox.fetch(&tenant, &heap, 7, 1));
+        assert_eq!(
+            Ok((RpcStatus::StatusExtensionError, vec![])),
+            mailbox.fetch(&tenant, &heap, 8, 1)
+        );
+        assert_eq!(missing, mailbox.fetch(&tenant, &heap, 9, 1));
+        assert!(Tenant::new(2).get_table(MAILBOX_TABLE).is_none());
+    }
+
+    // Tests that a result can't be fetched once it's TTL has passed, and that it is removed.
+    #[test]
+    fn test_mailbox_ttl() {
+        let (tenant, heap) = (Tenant::new(1), Allocator::new());
+        let mailbox = Mailbox::new(1000, 8, 1 << 16);
+        let ttl = mailbox.ttl();
+        assert!(ttl > 0);
+
+        assert!(mailbox.store(&tenant, &heap, 1, RpcStatus::StatusOk, b"a", 100));
+        assert!(mailbox.store(&tenant, &heap, 2, RpcStatus::StatusOk, b"b", 100));
+        assert!(mailbox.fetch(&tenant, &heap, 1, 100 + ttl - 1).is_ok());
+        assert_eq!(
+            Err(RpcStatus::StatusObjectDoesNotExist),
+            mailbox.fetch(&tenant, &heap, 2, 100 + ttl)
+        );
+
+        let table = tenant
+            .get_table(MAILBOX_TABLE)
+            .expect("Mailbox does not exist");
+        assert_eq!(
+            0,
+            table.cache_stats().expect("Mailbox is not a cache").objects
+        );
+    }
+
+    // Tests that the bytes of stored results are counted against the tenant's quota, and that
+    // the least recently stored results are evicted to stay under it.
+    #[test]
+    fn test_mailbox_quota() {
+        let (tenant, heap) = (Tenant::new(1), Allocator::new());
+        let mailbox = Mailbox::new(1000, 100, 1024);
+        let payload = [0xaau8; 100];
+
+        assert!(mailbox.store(&tenant, &heap, 0, RpcStatus::StatusOk, &payload, 0));
+        let table = tenant
+            .get_table(MAILBOX_TABLE)
+            .expect("Mailbox does not exist");
+        let stats = table.cache_stats().expect("Mailbox is not a cache");
+        assert_eq!(
+            CacheTarget::new(1024, 0, EvictPolicy::Written),
+            Some(stats.target)
+        );
+        assert_eq!(1, stats.objects);
+        let each = stats.bytes;
+        assert!(each as usize > payload.len() + RESULT_META);
+
+        for id in 1..32 {
+            assert!(mailbox.store(&tenant, &heap, id, RpcStatus::StatusOk, &payload, 0));
+        }
+        let stats = table.cache_stats().expect("Mailbox is not a cache");
+        assert!(stats.bytes <= 1024);
+        assert_eq!(stats.objects * each, stats.bytes);
+        assert_eq!(32, stats.objects + stats.evicted);
+        assert_eq!(stats.evicted * each, stats.evicted_bytes);
+
+        // The last result stored is never the one evicted, and fetching it releases it's bytes.
+        assert!(mailbox.fetch(&tenant, &heap, 31, 1).is_ok());
+        let after = table.cache_stats().expect("Mailbox is not a cache");
+        assert_eq!(stats.bytes - each, after.bytes);
+    }
+}
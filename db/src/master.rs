@@ -14,39 +14,85 @@
  */
 
 use bincode::serialize;
-use crypto::bcrypt::bcrypt;
 use hashbrown::HashMap;
 
+use std::cmp;
 use std::fs::File;
-use std::io::Write;
+use std::io::{self, Read, Write};
 use std::mem::{size_of, transmute};
+use std::ops::Bound;
+use std::path::Path;
 use std::rc::Rc;
-use std::str::from_utf8;
 use std::str::FromStr;
+use std::str::{from_utf8, from_utf8_unchecked};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
 
 use super::alloc::Allocator;
+use super::amplify;
+use super::audit::{self, AuditEntry};
+use super::backoff::{self, Cores};
+use super::coalesce;
+use super::compress::Compression;
+use super::config::ServerConfig;
 use super::container::Container;
-use super::context::Context;
+use super::context::{Context, Durable};
+use super::crash::Breadcrumb;
+use super::cycles;
+use super::defer::{Deferrals, Deferred, DEFAULT_MAX_DEFERRED};
+use super::drain::Drain;
+use super::epoch::{EpochStats, Epochs};
+use super::evict::CacheTarget;
+use super::fill::{self, Fill, FillRate, Objects};
+use super::hint;
+use super::hot::{HotPolicy, Walk};
+use super::image::ReadOnlyTable;
+use super::integrity::{self, Sweep};
+use super::journal::{args_hash, Journal, PendingTask};
+use super::kvformat::{self, OnCorrupt, Reader, Writer, FLAG_DUMP_ORDER};
+use super::latency::{self, Latencies, LatencySummary};
+use super::limits::Limits;
+use super::mailbox::Mailbox;
+use super::merge::{self, MergeError};
 use super::native::Native;
+use super::pool::{self, GetOp, Op, Pooled, PutOp};
+use super::presize::Presize;
+use super::quarantine::{self, QuarantinePolicy, Quarantines, Signal};
+use super::replay::{Admit, Replay};
+use super::route;
+use super::rpc::{
+    self, append_record, append_versioned_record, finish_get_response, finish_multiget_response,
+    ResponseBuf,
+};
+use super::runs::RunStats;
 use super::service::Service;
-use super::table::Version;
+use super::snapshot;
+use super::stream::DEFAULT_MAX_STREAM_BYTES;
+use super::table::{Table, N_BUCKETS};
 use super::task::{Task, TaskPriority};
 use super::tenant::Tenant;
+use super::tenant_cache::{self, Epoch};
+use super::trailer;
 use super::wireformat::*;
 
 use util::common::TESTING_DATASET;
 use util::model::{get_raw_data, insert_global_model, run_ml_application, GLOBAL_MODEL};
 
+use bytes::Bytes;
+
 use e2d2::common::EmptyMetadata;
-use e2d2::headers::UdpHeader;
-use e2d2::interface::Packet;
+use e2d2::headers::{IpHeader, MacHeader, UdpHeader};
+use e2d2::interface::{new_packet, Packet};
 use spin::RwLock;
 
+use sandstorm::auth::{self, Layout};
+use sandstorm::buf;
 use sandstorm::common::{TableId, TenantId, PACKET_UDP_LEN};
 use sandstorm::db::DB;
+use sandstorm::defer;
 use sandstorm::ext::*;
-use sandstorm::pack::pack;
+use sandstorm::profile::PerfMap;
+use sandstorm::tao::{self, Fanout};
 
 /// Convert a raw pointer for Allocator into a Allocator reference. This can be used to pass
 /// the allocator reference across closures without cloning the allocator object.
@@ -54,9 +100,379 @@ pub fn accessor<'a>(alloc: *const Allocator) -> &'a Allocator {
     unsafe { &*alloc }
 }
 
+// Returns a task that hands a request, and it's already populated response, back to the
+// scheduler. Used for requests that are cheap enough to be serviced right away.
+fn respond(
+    req: Packet<UdpHeader, EmptyMetadata>,
+    res: Packet<UdpHeader, EmptyMetadata>,
+) -> Box<Task> {
+    let gen = Box::new(move || {
+        return Some((req, res));
+
+        // XXX: This yield is required to get the compiler to compile this closure into a
+        // generator. It is unreachable and benign.
+        yield 0;
+    });
+
+    Box::new(Native::new(TaskPriority::REQUEST, gen))
+}
+
+// Removes an object off one of a tenant's tables exactly like a native delete() does. Takes the
+// tenant and allocator instead of Master so that it can run inside the generator
+// Master::delete() returns.
+fn delete_object(
+    heap: &Allocator,
+    tenant: Option<Arc<Tenant>>,
+    table_id: TableId,
+    key: &[u8],
+) -> RpcStatus {
+    match tenant {
+        Some(tenant) => match tenant.writable_native_table(table_id) {
+            Ok(table) => match heap.delete(&table, key) {
+                true => RpcStatus::StatusOk,
+                false => RpcStatus::StatusObjectDoesNotExist,
+            },
+
+            Err(err) => err,
+        },
+
+        None => RpcStatus::StatusTenantDoesNotExist,
+    }
+}
+
+// A page of the records in a table being read in key order, for the scan() RPC. Records are read
+// SCAN_BATCH at a time, so that the task serving the scan can yield in between batches.
+struct ScanPage {
+    // The key the records still to be read start from, and whether it is itself excluded. Once
+    // there are records on the page, the key of the last one.
+    from: Vec<u8>,
+    after: bool,
+
+    // The most records on the page, and the most bytes they and the continuation token can take.
+    max: usize,
+    budget: usize,
+
+    // The records on the page packed by rpc::append_kv(), and their number.
+    records: Vec<u8>,
+    num: u32,
+
+    // The number of records passed over because each was too large for a page by itself.
+    skipped: u32,
+
+    // Set once the table has no records after the last one on the page.
+    end: bool,
+}
+
+impl ScanPage {
+    // Returns an empty page starting from a key. A `max` of 0 is as many records as fit in the
+    // budget.
+    fn new(start: &[u8], after: bool, max: u32, budget: usize) -> ScanPage {
+        ScanPage {
+            from: start.to_vec(),
+            after: after,
+            max: match max {
+                0 => usize::max_value(),
+                max => max as usize,
+            },
+            budget: budget,
+            records: Vec::new(),
+            num: 0,
+            skipped: 0,
+            end: false,
+        }
+    }
+
+    // Reads a batch of records off a table onto the page. Returns true once the page is complete.
+    // A record too large for any page is passed over and counted instead, so that the scan moves
+    // past it. StatusValueTooLarge if not even the key of the first record fits in the budget.
+    fn step(&mut self, table: &Table, heap: &Allocator) -> Result<bool, RpcStatus> {
+        if self.num as usize == self.max {
+            return Ok(true);
+        }
+
+        let batch = {
+            let from = match self.after {
+                true => Bound::Excluded(&self.from[..]),
+                false => Bound::Included(&self.from[..]),
+            };
+            table.scan(from, SCAN_BATCH)
+        };
+        let end = batch.len() < SCAN_BATCH;
+
+        for (key, entry) in batch.into_iter() {
+            if self.num as usize == self.max {
+                return Ok(true);
+            }
+
+            let value = match heap.resolve(entry.value) {
+                Some((_, value)) => value,
+                None => continue,
+            };
+
+            // The key of the last record on the page is repeated as the continuation token. A
+            // record that fits on a page of it's own is left for the next one. One that doesn't
+            // is passed over, as long as it's key fits as the token.
+            let len = rpc::KV_OVERHEAD + 2 * key.len() + value.len();
+            if self.records.len() + len <= self.budget {
+                rpc::append_kv(&mut self.records, &key, &value);
+                self.num += 1;
+            } else if len > self.budget && self.records.len() + key.len() <= self.budget {
+                self.skipped += 1;
+            } else {
+                return match self.num + self.skipped {
+                    0 => Err(RpcStatus::StatusValueTooLarge),
+                    _ => Ok(true),
+                };
+            }
+
+            self.from = key.to_vec();
+            self.after = true;
+        }
+
+        self.end = end;
+        Ok(end)
+    }
+
+    // Returns the records on the page, their number, the number passed over, and the
+    // continuation token; the key of the last record read, or empty once the scan has reached the
+    // end of the table.
+    fn finish(self) -> (Vec<u8>, u32, u32, Vec<u8>) {
+        let token = match self.end || self.num + self.skipped == 0 {
+            true => Vec::new(),
+            false => self.from,
+        };
+        (self.records, self.num, self.skipped, token)
+    }
+}
+
+// Writes an object onto one of a tenant's tables exactly like a native cas() does, if the value
+// under it's key is the expected one. Takes the tenant and allocator instead of Master so that it
+// can run inside the generator Master::cas() returns.
+fn cas_object(
+    heap: &Allocator,
+    tenant: Option<Arc<Tenant>>,
+    tenant_id: TenantId,
+    table_id: TableId,
+    key: &[u8],
+    expected: Option<&[u8]>,
+    val: &[u8],
+) -> RpcStatus {
+    let table = match tenant {
+        Some(tenant) => match tenant.writable_native_table(table_id) {
+            Ok(table) => table,
+            Err(err) => return err,
+        },
+
+        None => return RpcStatus::StatusTenantDoesNotExist,
+    };
+
+    if let Err(err) = heap.limits().check(key.len(), val.len()) {
+        return err;
+    }
+
+    if val.is_empty() {
+        return RpcStatus::StatusMalformedRequest;
+    }
+
+    match heap.object(tenant_id, table_id, key, val) {
+        Some((key, obj)) => match heap.compare_and_store(&table, key, obj, expected) {
+            Some(Ok(_)) => RpcStatus::StatusOk,
+            Some(Err(())) => RpcStatus::StatusCompareFailed,
+            None => RpcStatus::StatusReadOnlyTable,
+        },
+
+        None => RpcStatus::StatusInternalError,
+    }
+}
+
+// Splits the payload of a parsed cas() request into it's key, expected value (None if the request
+// expects the key to be absent), and new value.
+fn split_cas<'a>(hdr: &CasRequest, payload: &'a [u8]) -> (&'a [u8], Option<&'a [u8]>, &'a [u8]) {
+    let (key, rest) = payload.split_at(hdr.key_length() as usize);
+    let (expected, val) = rest.split_at(hdr.expected_length() as usize);
+    match hdr.flags & CAS_FLAG_EXPECT_ABSENT {
+        0 => (key, Some(expected), val),
+        _ => (key, None, val),
+    }
+}
+
+// Builds an invoke() request, and a response for it, for an invocation that did not come in over
+// the network. None of the network headers matter since no response is ever sent out for it.
+fn detached_packets(
+    tenant: TenantId,
+    name: &[u8],
+    args: &[u8],
+) -> (
+    Packet<UdpHeader, EmptyMetadata>,
+    Packet<UdpHeader, EmptyMetadata>,
+) {
+    let mut payload = name.to_vec();
+    payload.extend_from_slice(args);
+    let req = rpc::create_invoke_rpc(
+        &MacHeader::new(),
+        &IpHeader::new(),
+        &UdpHeader::new(),
+        tenant,
+        name.len() as u32,
+        &payload,
+        0,
+        0,
+        0,
+    ).parse_header::<UdpHeader>();
+
+    let mut res = new_packet()
+        .expect("Failed to allocate packet for detached invocation!")
+        .push_header(&MacHeader::new())
+        .expect("Failed to push MAC header into detached invocation!")
+        .push_header(&IpHeader::new())
+        .expect("Failed to push IP header into detached invocation!")
+        .push_header(&UdpHeader::new())
+        .expect("Failed to push UDP header into detached invocation!");
+    rpc::stamp_response(&req, &mut res, 0);
+
+    (req, res)
+}
+
+// The name an extension looked up by id is bound under, if it was looked up by id. Refer to
+// INVOKE_FLAG_BY_ID.
+type Interned = Option<Arc<Vec<u8>>>;
+
+// Returns the name of the extension an invoke() request invoked; the name on the request, or the
+// name it's interned id was looked up under if it carried one instead. Refer to INVOKE_FLAG_BY_ID.
+fn invoked_name<'a>(
+    req: &'a Packet<InvokeRequest, EmptyMetadata>,
+    name_length: usize,
+    interned: &'a Interned,
+) -> &'a [u8] {
+    match *interned {
+        Some(ref name) => &name[..],
+        None => &req.get_payload()[..name_length],
+    }
+}
+
+// Serves a multiget() that asked for versions: the keys are read as a snapshot, and a versioned
+// record is appended for each, along with the object's value unless only versions were asked
+// for. The response is flagged racy if the read was. Returns the status of the RPC and the
+// number of records appended.
+fn multiget_versions<B: ResponseBuf<MultiGetResponse>>(
+    table: &Table,
+    heap: &Allocator,
+    keys: &[u8],
+    key_len: u16,
+    num_keys: u32,
+    flags: u8,
+    res: &mut B,
+) -> (RpcStatus, u32) {
+    let snap = match snapshot::keys(keys, key_len, num_keys) {
+        Some(keys) => snapshot::read(table, &keys),
+        None => return (RpcStatus::StatusMalformedRequest, 0),
+    };
+
+    let snap = match snap {
+        Some(snap) => snap,
+        None => return (RpcStatus::StatusObjectDoesNotExist, 0),
+    };
+
+    let values = flags & MULTIGET_FLAG_VERSIONS_ONLY == 0;
+    for entry in snap.entries.into_iter() {
+        let version = entry.version.number();
+        let appended = match values {
+            true => match heap.resolve(entry.value) {
+                Some((_k, value)) => append_versioned_record(res, version, Some(&value[..])),
+                None => return (RpcStatus::StatusObjectDoesNotExist, 0),
+            },
+
+            false => append_versioned_record(res, version, None),
+        };
+
+        if !appended {
+            return (RpcStatus::StatusInternalError, 0);
+        }
+    }
+
+    res.header().racy = snap.racy as u8;
+    (RpcStatus::StatusOk, num_keys)
+}
+
+/// The extensions loaded by Master::load_test(), as (path, name) pairs. Paths are relative to
+/// the directory the server is started from.
+pub const TEST_EXTENSIONS: [(&str, &str); 12] = [
+    ("../ext/get/target/release/libget.so", "get"),
+    ("../ext/put/target/release/libput.so", "put"),
+    ("../ext/tao/target/release/libtao.so", "tao"),
+    ("../ext/bad/target/release/libbad.so", "bad"),
+    ("../ext/long/target/release/liblong.so", "long"),
+    (
+        "../ext/aggregate/target/release/libaggregate.so",
+        "aggregate",
+    ),
+    ("../ext/pushback/target/release/libpushback.so", "pushback"),
+    ("../ext/scan/target/release/libscan.so", "scan"),
+    ("../ext/analysis/target/release/libanalysis.so", "analysis"),
+    ("../ext/auth/target/release/libauth.so", "auth"),
+    (
+        "../ext/analytics/target/release/libanalytics.so",
+        "analytics",
+    ),
+    ("../ext/dump/target/release/libdump.so", "dump"),
+];
+
 // The number of buckets in the `tenants` hashtable inside of Master.
 const TENANT_BUCKETS: usize = 32;
 
+// The largest state a durable invocation can checkpoint, if not set in the server config.
+const DURABLE_MAX_STATE: usize = 4096;
+
+// The largest the durable journal can grow, if not set in the server config.
+const DURABLE_MAX_JOURNAL: usize = 64 * 1024 * 1024;
+
+// The opcodes that need extensions. Master only builds an extension manager if one of these is
+// enabled.
+const EXTENSION_OPCODES: u64 = (1 << OpCode::SandstormInvokeRpc as u8)
+    | (1 << OpCode::SandstormInstallRpc as u8)
+    | (1 << OpCode::SandstormListExtRpc as u8)
+    | (1 << OpCode::SandstormMergeRpc as u8)
+    | (1 << OpCode::SandstormSetMergeRpc as u8)
+    | (1 << OpCode::SandstormRoutedInvokeRpc as u8)
+    | (1 << OpCode::SandstormSetRouteRpc as u8);
+
+// The number of bytes of entries on a response to a list_extensions() RPC. Responses are a
+// single packet, so longer listings are paginated.
+const LIST_EXT_BUDGET: usize = 1024;
+
+// The largest number of entries on a response to an audit() RPC. Each entry takes 49 bytes, so
+// a response fits in a single packet.
+const AUDIT_PAGE_MAX: usize = 24;
+
+// The number of bytes of records on a response to a dump() RPC. Responses are a single packet,
+// so a table is dumped over many requests.
+const DUMP_BUDGET: usize = 1024;
+
+// The number of records a scan() reads off a table at a time. The task serving a scan yields to
+// the scheduler in between batches, so that a large scan does not hold up a core.
+const SCAN_BATCH: usize = 64;
+
+// The number of bytes of records dumped at a time while a table is checkpointed, and the number
+// of records written at a time while one is restored.
+const CHECKPOINT_BUDGET: usize = 64 * 1024;
+const RESTORE_BATCH: u32 = 64;
+
+// The most bytes of keys a write_stats() response for a table's hot keys carries. Keys that do
+// not fit are left off.
+const WRITE_STATS_BUDGET: usize = 1024;
+
+// The largest number of runs on a response to a run_stats() RPC. Each run takes 40 bytes, so
+// a response fits in a single packet.
+const RUN_STATS_MAX: usize = 32;
+
+// The largest number of cores on a response to a core_stats() RPC. Each core takes 36 bytes, so
+// a response fits in a single packet.
+const CORE_STATS_MAX: usize = 32;
+
+// The most bytes of extensions a latency_stats() response carries. Extensions that do not fit
+// are left off.
+const LATENCY_STATS_BUDGET: usize = 1024;
+
 /// The primary service in Sandstorm. Master is responsible managing tenants, extensions, and
 /// the database. It implements the Service trait, allowing it to generate schedulable tasks
 /// for data and extension related RPC requests.
@@ -65,13 +481,125 @@ pub struct Master {
     /// will require a lookup on this map.
     tenants: [RwLock<HashMap<TenantId, Arc<Tenant>>>; TENANT_BUCKETS],
 
+    /// The epochs of the server's tenants and tables, moved forward when tables are truncated
+    /// or dropped and tenants removed. Each core installs them on it's thread, so that
+    /// responses that failed to find a key, table or tenant carry them.
+    epochs: Arc<Epochs>,
+
+    /// The version of the tenant map, bumped whenever a tenant is added or removed. Requests
+    /// look tenants up through a per-core cache of handles, which only serves handles cached
+    /// under the current version.
+    epoch: Epoch,
+
+    /// The number of tenant handles cached per core. Refer to tenant_cache::TenantCache.
+    tenant_cache: usize,
+
     /// An extension manager maintaining state concerning extensions loaded into the system.
     /// Required to retrieve and determine if an extension belongs to a particular tenant while
-    /// handling an invocation request.
-    pub extensions: ExtensionManager,
+    /// handling an invocation request. None if the server does not serve any extension related
+    /// opcode, in which case extensions are never loaded.
+    pub extensions: Option<ExtensionManager>,
+
+    /// The opcodes this server serves, as a mask of OpCode::bit(). Requests with any other
+    /// opcode are rejected with StatusOperationDisabled.
+    opcodes: u64,
 
     /// Manager of the table heap. Required to allow writes to the database.
     heap: Allocator,
+
+    /// The number of invoke() requests rejected because the extension name on them was not
+    /// valid UTF-8.
+    rejected_names: AtomicUsize,
+
+    /// Journal that durable invocations checkpoint to. None if durability is disabled.
+    journal: Option<Arc<Journal>>,
+
+    /// Durable invocations recovered from the journal that are yet to be resumed.
+    recovered: RwLock<Vec<PendingTask>>,
+
+    /// If true, get() and put() requests run on pooled tasks instead of boxed generators.
+    pooled: bool,
+
+    /// If true, get() requests for a key that another get() on the same core is about to look
+    /// up share that lookup.
+    coalesce: bool,
+
+    /// Compression settings applied to the tables created by Master. None if compression is
+    /// disabled.
+    compression: Option<Compression>,
+
+    /// The requests whose retransmits are answered with the original's response. None if
+    /// nothing is replayed.
+    replay: Option<Replay>,
+
+    /// If true, the tables created by Master index objects by a copy of their key instead of a
+    /// slice into the object.
+    split_keys: bool,
+
+    /// If true, the tables created by Master hold small objects inside their index instead of
+    /// on the heap.
+    inline_values: bool,
+
+    /// The identifiers of the tables whose objects carry a checksum, on every tenant.
+    checksum_tables: Vec<TableId>,
+
+    /// The number of objects a second the integrity sweep checks. 0 if there is no sweep.
+    verify_rate: u64,
+
+    /// If true, objects that fail to verify are quarantined.
+    quarantine: bool,
+
+    /// The identifiers of the tables whose identical values are shared, on every tenant.
+    dedup_tables: Vec<TableId>,
+
+    /// The number of values each of those tables remembers to share. 0 means the default.
+    dedup_entries: usize,
+
+    /// Set once the integrity sweep has been handed out to a scheduler.
+    sweeping: AtomicBool,
+
+    /// Set once the hot arena's walk has been handed out to a scheduler.
+    relocating: AtomicBool,
+
+    /// The most objects a second fills write into tables. Shared with every fill, which reads
+    /// it as it runs.
+    fill_rate: Arc<FillRate>,
+
+    /// The number of bytes of entries on a response to a list_extensions() RPC.
+    list_ext_budget: usize,
+
+    /// The most bytes an invocation can stream back across all of it's responses.
+    max_stream_bytes: usize,
+
+    /// The most deferred invocations each tenant can have queued or running at once.
+    max_deferred: usize,
+
+    /// How the tables Master creates are pre-sized for the objects they are expected to hold,
+    /// and the most objects a table can be sized for.
+    presize: Presize,
+
+    /// How the results invocations ask to store are kept. Refer to mailbox::Mailbox.
+    mailbox: Mailbox,
+
+    /// Tracks a graceful shutdown of the server. Once draining, every request other than a
+    /// drain() RPC is rejected with StatusServerDraining.
+    drain: Drain,
+
+    /// Counters of each client run that tagged it's requests with a run id. Shared with the
+    /// schedulers, which count requests as they complete.
+    runs: Arc<RunStats>,
+
+    /// Decides which tenants are quarantined, and counts their quarantines. Shared with the
+    /// schedulers, which report requests that failed as they complete.
+    quarantines: Arc<Quarantines>,
+
+    /// Counters of the time each scheduler core spent backing off polling. Each core's
+    /// dispatcher registers it's counters here when it is created.
+    cores: Cores,
+
+    /// The time each scheduler core took to respond to requests. Each core registers it's
+    /// latencies here, and installs them on it's thread, when it's scheduler is set up.
+    latencies: Latencies,
 }
 
 // Implementation of methods on Master.
@@ -82,6 +610,27 @@ impl Master {
     ///
     /// A Master service capable of creating schedulable tasks out of RPC requests.
     pub fn new() -> Master {
+        Master::with_opcodes(OPCODES_ALL)
+    }
+
+    /// Creates and returns a new Master service that only serves a subset of opcodes. Requests
+    /// with any other opcode are rejected, and the subsystems they would need are never set up.
+    /// echo() and drain() are always served.
+    ///
+    /// # Arguments
+    ///
+    /// * `opcodes`: The opcodes to serve, as a mask of OpCode::bit().
+    ///
+    /// # Return
+    ///
+    /// A Master service capable of creating schedulable tasks out of RPC requests.
+    pub fn with_opcodes(opcodes: u64) -> Master {
+        let opcodes = opcodes | OPCODES_REQUIRED;
+        let extensions = match opcodes & EXTENSION_OPCODES {
+            0 => None,
+            _ => Some(ExtensionManager::new()),
+        };
+
         Master {
             // Cannot use copy constructor because of the Arc<Tenant>.
             tenants: [
@@ -118,169 +667,892 @@ impl Master {
                 RwLock::new(HashMap::new()),
                 RwLock::new(HashMap::new()),
             ],
-            extensions: ExtensionManager::new(),
+            epochs: Arc::new(Epochs::new()),
+            epoch: Epoch::new(),
+            tenant_cache: tenant_cache::DEFAULT_ENTRIES,
+            extensions: extensions,
+            opcodes: opcodes,
             heap: Allocator::new(),
+            rejected_names: AtomicUsize::new(0),
+            journal: None,
+            recovered: RwLock::new(Vec::new()),
+            pooled: false,
+            coalesce: false,
+            compression: None,
+            replay: None,
+            split_keys: false,
+            inline_values: false,
+            checksum_tables: Vec::new(),
+            verify_rate: 0,
+            quarantine: false,
+            dedup_tables: Vec::new(),
+            dedup_entries: 0,
+            sweeping: AtomicBool::new(false),
+            relocating: AtomicBool::new(false),
+            fill_rate: Arc::new(FillRate::new(0)),
+            list_ext_budget: LIST_EXT_BUDGET,
+            max_stream_bytes: DEFAULT_MAX_STREAM_BYTES,
+            max_deferred: DEFAULT_MAX_DEFERRED,
+            presize: Presize::default(),
+            mailbox: Mailbox::default(),
+            drain: Drain::new(),
+            runs: Arc::new(RunStats::new(0)),
+            quarantines: Arc::new(Quarantines::new(QuarantinePolicy::default())),
+            cores: Cores::new(),
+            latencies: Latencies::new(),
         }
     }
 
-    /// Adds a tenant and a table full of objects.
+    /// Enables value compression on the tables Master creates, if configured in the server
+    /// config. Values are compressed when they are written, and decompressed transparently
+    /// when they are read.
     ///
     /// # Arguments
     ///
-    /// * `tenant_id`: Identifier of the tenant to be added. Any existing tenant with the same
-    ///                identifier will be overwritten.
-    /// * `table_id`:  Identifier of the table to be added to the tenant. This table will contain
-    ///                all the objects.
-    /// * `num`:       The number of objects to be added to the data table.
-    pub fn fill_test(&self, tenant_id: TenantId, table_id: TableId, num: u32) {
-        // Create a tenant containing the table.
-        let tenant = Tenant::new(tenant_id);
-        tenant.create_table(table_id);
+    /// * `config`: The server config containing the size threshold and minimum ratio.
+    pub fn enable_compression(&mut self, config: &ServerConfig) {
+        self.compression = Compression::new(config.compress_threshold, config.compress_min_ratio);
+    }
 
-        let table = tenant
-            .get_table(table_id)
-            .expect("Failed to init test table.");
+    /// Enables object checksums on the tables Master creates, and the sweep that checks objects
+    /// against them, if configured in the server config. Refer to integrity::Sweep.
+    ///
+    /// # Arguments
+    ///
+    /// * `config`: The server config containing the tables, the sweep's rate, and whether objects
+    ///             that fail to verify are quarantined.
+    pub fn enable_checksums(&mut self, config: &ServerConfig) {
+        self.checksum_tables = config.checksum_tables();
+        self.verify_rate = config.verify_objects_per_sec;
+        self.quarantine = config.verify_quarantine;
+    }
 
-        let mut key = vec![0; 30];
-        let mut val = vec![0; 100];
+    /// Enables sharing identical values on the tables Master creates, if configured in the
+    /// server config. Refer to Table::set_dedup().
+    ///
+    /// # Arguments
+    ///
+    /// * `config`: The server config containing the tables and the size of their dedup index.
+    pub fn enable_dedup(&mut self, config: &ServerConfig) {
+        self.dedup_tables = config.dedup_tables();
+        self.dedup_entries = config.dedup_entries;
+    }
 
-        // Allocate objects, and fill up the above table. Each object consists of a 30 Byte key
-        // and a 100 Byte value.
-        for i in 1..(num + 1) {
-            let temp: [u8; 4] = unsafe { transmute(i.to_le()) };
-            &key[0..4].copy_from_slice(&temp);
-            &val[0..4].copy_from_slice(&temp);
+    /// Enables replaying responses to retransmitted requests, if configured in the server config.
+    /// A retransmit of a request that was recently executed on the same core is answered with
+    /// the original's response instead of being executed again, so that requests that aren't
+    /// idempotent are applied once. Refer to replay::ReplayTable for what "recently" means.
+    ///
+    /// # Arguments
+    ///
+    /// * `config`: The server config containing the opcodes covered and the window.
+    pub fn enable_replay(&mut self, config: &ServerConfig) {
+        self.replay = Replay::from_config(config);
+    }
 
-            let obj = self
-                .heap
-                .object(tenant_id, table_id, &key, &val)
-                .expect("Failed to create test object.");
-            table.put(obj.0, obj.1);
+    /// Enables profiling of extensions loaded from here on, if configured in the server config.
+    /// Does nothing if this server does not serve extensions. Refer to
+    /// ExtensionManager::enable_profiling().
+    ///
+    /// # Arguments
+    ///
+    /// * `config`: The server config containing the profiling flag.
+    pub fn enable_profiling(&mut self, config: &ServerConfig) {
+        if !config.profile_extensions {
+            return;
         }
 
-        // Add the tenant.
-        self.insert_tenant(tenant);
+        if let Some(ref mut extensions) = self.extensions {
+            extensions.enable_profiling(PerfMap::new());
+        }
     }
 
-    /// Populates the TAO dataset.
+    /// Sets whether get() and put() requests run on pooled tasks. Pooled tasks run the request
+    /// directly instead of through a boxed generator, and are recycled through a per-core pool,
+    /// so that these requests do not allocate once the pool has warmed up.
     ///
     /// # Arguments
     ///
-    /// * `tenant_id`: Identifier of the tenant to be added. Any existing tenant with the same
-    ///                identifier will be overwritten.
-    /// * `num`:       The number of objects to be added to the data table.
-    pub fn fill_tao(&self, tenant_id: TenantId, num: u32) {
-        // Create a tenant containing two tables, one for objects, and one for
-        // associations.
-        let tenant = Tenant::new(tenant_id);
-        tenant.create_table(1); // Holds tao objects.
-        tenant.create_table(2); // Holds tao assocs.
+    /// * `pooled`: True if get() and put() requests should run on pooled tasks.
+    pub fn set_pooled_native(&mut self, pooled: bool) {
+        self.pooled = pooled;
+    }
 
-        // First, fill up the object table.
-        let table = tenant.get_table(1).expect("Failed to init test table.");
+    /// Sets whether get() requests are coalesced. A get() for a key that an earlier get() on the
+    /// same core is yet to look up is answered with that lookup, instead of performing it's own.
+    /// Each request still gets it's own response.
+    ///
+    /// # Arguments
+    ///
+    /// * `coalesce`: True if get() requests should be coalesced.
+    pub fn set_coalesce_gets(&mut self, coalesce: bool) {
+        self.coalesce = coalesce;
+    }
 
-        // Objects are identified by an 8 byte key.
-        let mut key = vec![0; 8];
-        // Objects contain a 4 byte otype, 8 byte version, 4 byte update time, and
-        // 16 byte payload, all of which are zero.
-        let val = vec![0; 32];
+    /// Sets the number of tenant handles each core caches. Only applies to cores that are yet
+    /// to look a tenant up.
+    ///
+    /// # Arguments
+    ///
+    /// * `entries`: The number of handles. Refer to tenant_cache::TenantCache::new().
+    pub fn set_tenant_cache(&mut self, entries: usize) {
+        self.tenant_cache = entries;
+    }
 
-        // Setup the object table with num objects.
-        for i in 1..(num + 1) {
-            let temp: [u8; 4] = unsafe { transmute(i.to_le()) };
-            &key[0..4].copy_from_slice(&temp);
+    /// Sets the key layout of the tables Master creates. Tables with split keys hold a copy of
+    /// each object's key in their index, so that lookups do not touch the objects themselves.
+    ///
+    /// # Arguments
+    ///
+    /// * `split`: True if tables should hold keys apart from objects.
+    pub fn set_split_keys(&mut self, split: bool) {
+        self.split_keys = split;
+    }
 
-            let obj = self
-                .heap
-                .object(tenant_id, 1, &key, &val)
-                .expect("Failed to create test object.");
-            table.put(obj.0, obj.1);
-        }
+    /// Sets whether the tables Master creates hold objects of upto table::INLINE_CAP bytes
+    /// inside their index. Reads copy such objects out instead of following a pointer to them.
+    ///
+    /// # Arguments
+    ///
+    /// * `inline`: True if tables should hold small objects inside their index.
+    pub fn set_inline_values(&mut self, inline: bool) {
+        self.inline_values = inline;
+    }
 
-        // Next, fill up the assoc table.
-        let table = tenant.get_table(2).expect("Failed to init test table.");
+    /// Sets the number of bytes of entries on a response to a list_extensions() RPC. Listings
+    /// that do not fit are paginated.
+    ///
+    /// # Arguments
+    ///
+    /// * `budget`: The number of bytes of entries on a response.
+    pub fn set_list_ext_budget(&mut self, budget: usize) {
+        self.list_ext_budget = budget;
+    }
 
-        // Assocs are identified by an 8 byte object 1 id, 2 byte association
-        // type (always zero), and 8 byte object 2 id.
-        let mut key = vec![0; 18];
-        // Assocs have a 22 byte value (all zeros).
-        let val = vec![0; 22];
+    /// Sets the most bytes an invocation can stream back across all of it's responses. Refer to
+    /// stream::Stream.
+    ///
+    /// # Arguments
+    ///
+    /// * `cap`: The cap in bytes, usually out of ServerConfig::max_stream_bytes().
+    pub fn set_max_stream_bytes(&mut self, cap: usize) {
+        self.max_stream_bytes = cap;
+    }
 
-        // Populate the assoc table. Each object gets four assocs to it's
-        // neighbours.
-        for i in 1..(num + 1) {
-            let temp: [u8; 4] = unsafe { transmute(i.to_le()) };
-            &key[0..4].copy_from_slice(&temp);
+    /// Sets the most deferred invocations each tenant can have queued or running at once. Work
+    /// deferred past this is dropped. Refer to defer::Deferrals.
+    ///
+    /// # Arguments
+    ///
+    /// * `cap`: The cap, usually out of ServerConfig::max_deferred().
+    pub fn set_max_deferred(&mut self, cap: usize) {
+        self.max_deferred = cap;
+    }
 
-            // Assoc list for this particular object.
-            let mut list: Vec<u8> = Vec::new();
+    /// Sets how tables are pre-sized when they are created for a known number of objects, and
+    /// the most objects a table can be created for. Refer to presize::Presize.
+    ///
+    /// # Arguments
+    ///
+    /// * `presize`: The policy, usually out of ServerConfig::presize().
+    pub fn set_presize(&mut self, presize: Presize) {
+        self.presize = presize;
+    }
 
-            for a in 1u32..5u32 {
-                let temp: [u8; 4] = unsafe { transmute(((i + a) % num).to_le()) };
-                &key[10..14].copy_from_slice(&temp);
-                list.extend_from_slice(&temp);
-                list.extend_from_slice(&[0; 12]);
+    /// Sets how long the results invocations ask to store are kept, the largest one stored, and
+    /// how many bytes of them each tenant can have stored. Refer to mailbox::Mailbox.
+    ///
+    /// # Arguments
+    ///
+    /// * `mailbox`: The policy, usually out of ServerConfig::mailbox().
+    pub fn set_mailbox(&mut self, mailbox: Mailbox) {
+        self.mailbox = mailbox;
+    }
 
-                // Add this assoc to the assoc table.
-                let obj = self
-                    .heap
-                    .object(tenant_id, 2, &key, &val)
-                    .expect("Failed to create test object.");
-                table.put(obj.0, obj.1);
-            }
+    /// Sets the maximum number of client runs whose counters are tracked at once. Must be
+    /// called before the counters are handed out by runs().
+    ///
+    /// # Arguments
+    ///
+    /// * `cap`: The maximum number of runs tracked. 0 means runs::RUN_STATS_CAP.
+    pub fn set_run_stats_cap(&mut self, cap: usize) {
+        self.runs = Arc::new(RunStats::new(cap));
+    }
 
-            // Add the assoc list to the table too.
-            let obj = self
-                .heap
-                .object(tenant_id, 2, &key[0..10], &list)
-                .expect("Failed to create test object.");
-            table.put(obj.0, obj.1);
-        }
+    /// Sets when tenants are quarantined, and for how long. Refer to quarantine::Quarantines.
+    /// Must be called before the quarantines are handed out by quarantines().
+    ///
+    /// # Arguments
+    ///
+    /// * `policy`: The policy, usually out of ServerConfig::quarantine().
+    pub fn set_quarantine_policy(&mut self, policy: QuarantinePolicy) {
+        self.quarantines = Arc::new(Quarantines::new(policy));
+    }
 
-        // Add the tenant.
-        self.insert_tenant(tenant);
+    /// Sets the largest keys and values objects can be written with. Refer to limits::Limits.
+    ///
+    /// # Arguments
+    ///
+    /// * `limits`: The limits, usually out of ServerConfig::limits().
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.heap.set_limits(limits);
     }
 
-    /// Populates the aggregate dataset.
+    /// Sets up the arena the hottest objects are moved into, if the policy gives it any room.
+    /// Refer to hot::HotArena.
     ///
     /// # Arguments
     ///
-    /// * `tenant_id`: Identifier of the tenant to be added. Any existing tenant with the same
-    ///                identifier will be overwritten.
-    /// * `table_id`:  Identifier of the table to be added to the tenant. This table will contain
-    ///                all the objects.
-    /// * `num`:       The number of objects to be added to the data table.
-    pub fn fill_aggregate(&self, tenant_id: TenantId, table_id: TableId, num: u32) {
-        // One table for the tenant. Both, objects and indirection lists will be
-        // stored in here.
-        let tenant = Tenant::new(tenant_id);
-        tenant.create_table(table_id);
+    /// * `policy`: The policy, usually out of ServerConfig::hot_arena().
+    pub fn set_hot_arena(&mut self, policy: HotPolicy) {
+        self.heap.set_hot_arena(policy.arena().map(Arc::new));
+    }
 
-        let table = tenant
-            .get_table(table_id)
-            .expect("Failed to init test table.");
+    /// Returns the largest keys and values objects can be written with.
+    pub fn limits(&self) -> Limits {
+        *self.heap.limits()
+    }
 
-        // The number of records per aggregation.
-        const N_AGG: u32 = 12;
-        // The length of each record's key.
-        const K_LEN: u32 = 30;
-        // The length of each record's value.
-        const V_LEN: u32 = 100;
+    /// Returns the opcodes this server serves, as a mask of OpCode::bit().
+    pub fn opcodes(&self) -> u64 {
+        self.opcodes
+    }
 
-        // The total number of records.
-        let records: u32 = 4 * (num + 1);
+    /// Returns true if this server serves an opcode.
+    ///
+    /// # Arguments
+    ///
+    /// * `op`: The opcode.
+    #[inline]
+    pub fn serves(&self, op: &OpCode) -> bool {
+        self.opcodes & op.bit() != 0
+    }
 
-        // First, add in the indirection records. Keys are 8 bytes, and values are
-        // lists of 30 Byte keys.
-        for i in 1..(num + 1) {
-            let mut key = vec![0; 8];
-            let mut val = vec![];
+    /// Returns the counters of each client run. Completed requests tagged with a run id should
+    /// be counted on these using rpc::record_run().
+    pub fn runs(&self) -> &Arc<RunStats> {
+        &self.runs
+    }
 
-            let temp: [u8; 4] = unsafe { transmute(i.to_le()) };
-            &key[0..4].copy_from_slice(&temp);
+    /// Returns the tenants' quarantines. Requests that fail as they complete should be reported
+    /// through Master::signal() if these are enabled.
+    pub fn quarantines(&self) -> &Arc<Quarantines> {
+        &self.quarantines
+    }
 
-            for e in 0..N_AGG {
-                let mut k = vec![0; K_LEN as usize];
+    /// Returns true if requests from a tenant are to be refused with StatusTenantQuarantined at
+    /// a point in time. While no tenant is quarantined, this does not look the tenant up. Tenant
+    /// 0 is never quarantined.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`: The tenant on the request.
+    /// * `now`:    The cycle counter.
+    #[inline]
+    pub fn quarantined(&self, tenant: TenantId, now: u64) -> bool {
+        if tenant == 0 || !self.quarantines.active(now) {
+            return false;
+        }
+
+        match self.tenant(tenant) {
+            Some(tenant) => self.quarantines.check(&tenant, now),
+            None => false,
+        }
+    }
+
+    /// Counts a failed request against the tenant that sent it, quarantining the tenant if it's
+    /// requests failed too often. Does nothing unless tenants are quarantined automatically.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`: The tenant that sent the request.
+    /// * `signal`: The kind of failure the request ran into.
+    /// * `now`:    The cycle counter.
+    pub fn signal(&self, tenant: TenantId, signal: Signal, now: u64) {
+        if tenant == 0 || !self.quarantines.enabled() {
+            return;
+        }
+
+        if let Some(tenant) = self.tenant(tenant) {
+            self.quarantines.signal(&tenant, signal, now);
+        }
+    }
+
+    /// Counts a completed request against the tenant that sent it, if the status on the
+    /// response says it failed in a way that counts towards a quarantine.
+    ///
+    /// # Arguments
+    ///
+    /// * `req`: The request, parsed upto it's UDP header.
+    /// * `res`: The response to the request, parsed upto it's UDP header.
+    /// * `now`: The cycle counter.
+    #[inline]
+    pub fn observe(
+        &self,
+        req: &Packet<UdpHeader, EmptyMetadata>,
+        res: &Packet<UdpHeader, EmptyMetadata>,
+        now: u64,
+    ) {
+        if !self.quarantines.enabled() {
+            return;
+        }
+
+        if let Some((tenant, signal)) = quarantine::observe(req, res) {
+            self.signal(tenant, signal, now);
+        }
+    }
+
+    /// Counts a request that was dropped without a response, because it was too short or named
+    /// an unknown opcode, as a malformed request against the tenant that sent it.
+    ///
+    /// # Arguments
+    ///
+    /// * `req`: The request, parsed upto it's UDP header.
+    /// * `now`: The cycle counter.
+    #[inline]
+    pub fn malformed(&self, req: &Packet<UdpHeader, EmptyMetadata>, now: u64) {
+        if !self.quarantines.enabled() {
+            return;
+        }
+
+        if let Some(tenant) = rpc::parse_rpc_tenant(req) {
+            self.signal(tenant, Signal::Malformed, now);
+        }
+    }
+
+    /// Returns the backoff counters of each scheduler core. A dispatcher should register it's
+    /// core on these using Cores::register().
+    pub fn cores(&self) -> &Cores {
+        &self.cores
+    }
+
+    /// Returns the latencies recorded on each scheduler core. A scheduler's thread should
+    /// register it's core on these, and install them using latency::install().
+    pub fn latencies(&self) -> &Latencies {
+        &self.latencies
+    }
+
+    /// Enables durable invocations by opening the journal configured in the server config.
+    /// Durable invocations that had not completed before the server went down are recovered
+    /// from the journal, and can be resumed using recover_durable(). Does nothing if no
+    /// journal is configured.
+    ///
+    /// # Arguments
+    ///
+    /// * `config`: The server config containing the journal's path, sync policy and size caps.
+    ///
+    /// # Return
+    ///
+    /// An error if the journal could not be opened or recovered.
+    pub fn enable_durable(&mut self, config: &ServerConfig) -> io::Result<()> {
+        if config.durable_journal.is_empty() {
+            return Ok(());
+        }
+
+        // Only invocations can be durable, so the journal is of no use without them.
+        if !self.serves(&OpCode::SandstormInvokeRpc) {
+            info!(
+                "invoke() is disabled; not opening durable journal {}",
+                config.durable_journal
+            );
+            return Ok(());
+        }
+
+        let or = |v: usize, d: usize| if v == 0 { d } else { v };
+        let (journal, recovered) = Journal::open(
+            Path::new(&config.durable_journal),
+            config.durable_fsync_every,
+            or(config.durable_max_state, DURABLE_MAX_STATE),
+            or(config.durable_max_journal, DURABLE_MAX_JOURNAL),
+        )?;
+
+        info!(
+            "Recovered {} incomplete durable invocations from {}",
+            recovered.len(),
+            config.durable_journal
+        );
+
+        // Records appended since the last sync would be lost if the server stopped abruptly,
+        // so the journal is flushed on shutdown.
+        let journal = Arc::new(journal);
+        let flush = Arc::clone(&journal);
+        self.drain.on_shutdown(Box::new(move || {
+            if let Err(e) = flush.sync() {
+                error!("Failed to sync durable journal on shutdown: {}", e);
+            }
+        }));
+
+        self.journal = Some(journal);
+        *self.recovered.write() = recovered;
+        Ok(())
+    }
+
+    /// Returns the allocator objects are allocated on. Lets benchmarks drive the storage layer
+    /// directly, without building RPCs.
+    pub fn heap(&self) -> &Allocator {
+        &self.heap
+    }
+
+    /// Returns the epochs of the server's tenants and tables. A scheduler's thread should
+    /// install them using epoch::install().
+    pub fn epochs(&self) -> &Arc<Epochs> {
+        &self.epochs
+    }
+
+    /// Returns the drain tracking a graceful shutdown of the server. A drain can be started
+    /// either through this handle or over a drain() RPC.
+    pub fn drain(&self) -> &Drain {
+        &self.drain
+    }
+
+    /// Creates tasks that resume durable invocations recovered from the journal. Each recovered
+    /// invocation is handed out only once, so this can be called from every scheduler; only
+    /// the first caller gets any tasks. Invocations whose tenant or extension no longer exists
+    /// are dropped from the journal.
+    ///
+    /// # Return
+    ///
+    /// Tasks that resume recovered invocations from their last checkpoint. These tasks do not
+    /// send out a response; their result is written to the tenant's DURABLE_RESULTS_TABLE.
+    pub fn recover_durable(&self) -> Vec<Box<Task>> {
+        let recovered: Vec<PendingTask> = self.recovered.write().drain(..).collect();
+
+        let mut tasks = Vec::with_capacity(recovered.len());
+        for task in recovered.into_iter() {
+            // Rebuild the original request.
+            let (req, res) = detached_packets(task.tenant, &task.name, &task.args);
+
+            let (tenant, name, hash) = (task.tenant, task.name.clone(), task.args_hash);
+            match self.invoke_task(req, res, Some(task), None) {
+                Ok(task) => tasks.push(task),
+
+                Err((req, res)) => {
+                    warn!(
+                        "Dropping durable invocation {} of {:?} by tenant {}",
+                        hash, name, tenant
+                    );
+                    req.free_packet();
+                    res.free_packet();
+                    if let Some(ref journal) = self.journal {
+                        journal.done(tenant, &name, hash);
+                    }
+                }
+            }
+        }
+
+        tasks
+    }
+
+    /// Creates a task that runs work deferred by an invocation, once the invocation's response
+    /// has been sent out. The extension that deferred the work is invoked again in the
+    /// background on behalf of the same tenant, with the work's arguments behind
+    /// sandstorm::defer's marker. No response is sent out for the task.
+    ///
+    /// # Arguments
+    ///
+    /// * `work`: The deferred work, as handed out by the scheduler.
+    ///
+    /// # Return
+    ///
+    /// A task that runs the work, or None if the work was dropped: the tenant no longer exists,
+    /// already has as much deferred work outstanding as it is allowed to, or the extension could
+    /// not be invoked.
+    pub fn defer(&self, work: Deferred) -> Option<Box<Task>> {
+        let deferrals = match self.tenant(work.tenant) {
+            Some(tenant) => tenant.deferrals(),
+            None => return None,
+        };
+        if !deferrals.acquire(self.max_deferred) {
+            return None;
+        }
+
+        let args = defer::encode_args(&work.args);
+        let (req, res) = detached_packets(work.tenant, &work.name, &args);
+        match self.invoke_task(req, res, None, Some(Arc::clone(&deferrals))) {
+            Ok(task) => Some(task),
+
+            Err((req, res)) => {
+                warn!(
+                    "Dropping work deferred by {:?} for tenant {}",
+                    work.name, work.tenant
+                );
+                req.free_packet();
+                res.free_packet();
+                deferrals.release(false);
+                None
+            }
+        }
+    }
+
+    /// Makes an invocation durable, recording it in the journal if it is new.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant_id`: The tenant that invoked the extension.
+    /// * `name`:      The name of the invoked extension. Refer to invoked_name().
+    /// * `args`:      The extension's arguments on the request.
+    /// * `recovered`: The invocation recovered from the journal if it is being resumed.
+    ///
+    /// # Return
+    ///
+    /// The state the Context needs to run the invocation durably, or None if durability is
+    /// disabled or the invocation could not be recorded in the journal.
+    fn durable(
+        &self,
+        tenant_id: TenantId,
+        name: &[u8],
+        args: &[u8],
+        recovered: Option<PendingTask>,
+    ) -> Option<Durable> {
+        let journal = match self.journal {
+            Some(ref journal) => journal,
+            None => return None,
+        };
+
+        match recovered {
+            Some(task) => Some(Durable {
+                journal: Arc::clone(journal),
+                name: task.name,
+                args_hash: task.args_hash,
+                restored: task.checkpoint,
+                detached: true,
+            }),
+
+            None => {
+                let hash = args_hash(name, args);
+                match journal.begin(tenant_id, name, hash, args) {
+                    true => Some(Durable {
+                        journal: Arc::clone(journal),
+                        name: name.to_vec(),
+                        args_hash: hash,
+                        restored: None,
+                        detached: false,
+                    }),
+                    false => None,
+                }
+            }
+        }
+    }
+
+    /// Returns the number of invoke() requests rejected because the extension name on them was
+    /// not valid UTF-8, or because the id they named it by was not 4 bytes long.
+    pub fn rejected_names(&self) -> usize {
+        self.rejected_names.load(Ordering::Relaxed)
+    }
+
+    /// Looks up the extension an invoke() request names; by the name on it, or by the id the
+    /// extension is interned under if the request has INVOKE_FLAG_BY_ID set. Neither copies the
+    /// name, and looking up by id does not validate or hash it either.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant_id`: The tenant that issued the request.
+    /// * `name`:      The name on the request, or the 4 byte little endian id in it's place.
+    /// * `by_id`:     True if the request has INVOKE_FLAG_BY_ID set.
+    ///
+    /// # Return
+    ///
+    /// The extension, along with the name it is bound under if it was looked up by id.
+    /// StatusMalformedRequest if the name is not valid UTF-8 or the id is not 4 bytes long,
+    /// StatusInvalidExtension if nothing is bound under the name, and StatusStaleExtensionId if
+    /// nothing is interned under the id anymore.
+    fn lookup_extension(
+        &self,
+        tenant_id: TenantId,
+        name: &[u8],
+        by_id: bool,
+    ) -> Result<(Arc<Extension>, Interned), RpcStatus> {
+        let extensions = self.extensions.as_ref();
+        if by_id {
+            if name.len() != 4 {
+                return Err(RpcStatus::StatusMalformedRequest);
+            }
+
+            let mut id = [0; 4];
+            id.copy_from_slice(name);
+            let id = u32::from_le(unsafe { transmute(id) });
+            return extensions
+                .and_then(|e| e.get_by_id(tenant_id, id))
+                .map(|(ext, name)| (ext, Some(name)))
+                .ok_or(RpcStatus::StatusStaleExtensionId);
+        }
+
+        // Extensions are loaded under UTF-8 names, so a name that isn't valid UTF-8 can never
+        // match one. Validation doesn't copy the name.
+        from_utf8(name).map_err(|_| RpcStatus::StatusMalformedRequest)?;
+        extensions
+            .and_then(|e| e.get(tenant_id, name))
+            .map(|ext| (ext, None))
+            .ok_or(RpcStatus::StatusInvalidExtension)
+    }
+
+    /// Resolves the tenant and extension an invoke() request is for, and checks that the
+    /// extension can be run on the request's arguments. Names that aren't valid UTF-8, and ids
+    /// that aren't 4 bytes long, are counted on rejected_names().
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant_id`:   The tenant that issued the request.
+    /// * `payload`:     The payload of the request; the name (or id) followed by the arguments.
+    /// * `name_length`: The length of the name on the payload.
+    /// * `args_length`: The length of the arguments on the payload.
+    /// * `by_id`:       True if the request has INVOKE_FLAG_BY_ID set.
+    /// * `deferred`:    True if the request runs deferred work. It's arguments were passed by the
+    ///                  extension to itself, and aren't checked against it's layout.
+    ///
+    /// # Return
+    ///
+    /// The tenant, the extension, and the name the extension is bound under if it was looked up
+    /// by id. Otherwise, the status to respond with, along with that name if it was found.
+    fn resolve_invoke(
+        &self,
+        tenant_id: TenantId,
+        payload: &[u8],
+        name_length: usize,
+        args_length: usize,
+        by_id: bool,
+        deferred: bool,
+    ) -> Result<(Arc<Tenant>, Arc<Extension>, Interned), (RpcStatus, Interned)> {
+        if payload.len() < name_length + args_length {
+            return Err((RpcStatus::StatusMalformedRequest, None));
+        }
+
+        // The extension is looked up directly on the bytes in the payload, whether they hold it's
+        // name or the id it is interned under. Refer to lookup_extension().
+        let lookup = self.lookup_extension(tenant_id, &payload[..name_length], by_id);
+        if let Err(RpcStatus::StatusMalformedRequest) = lookup {
+            self.rejected_names.fetch_add(1, Ordering::Relaxed);
+            return Err((RpcStatus::StatusMalformedRequest, None));
+        }
+
+        let tenant = match self.tenant(tenant_id) {
+            Some(tenant) => tenant,
+            None => return Err((RpcStatus::StatusTenantDoesNotExist, None)),
+        };
+        let (ext, interned) = lookup.map_err(|missing| (missing, None))?;
+
+        // Extensions are disabled after they panic.
+        if ext.disabled() {
+            return Err((RpcStatus::StatusExtensionDisabled, interned));
+        }
+
+        // Arguments that don't fit the layout the extension declared are refused here, so that
+        // the extension never sees them.
+        let args = &payload[name_length..name_length + args_length];
+        if !deferred
+            && ext
+                .schema()
+                .map_or(false, |schema| schema.check(args).is_err())
+        {
+            return Err((RpcStatus::StatusArgsMismatch, interned));
+        }
+
+        Ok((tenant, ext, interned))
+    }
+
+    /// Adds a tenant and a table full of objects.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant_id`: Identifier of the tenant to be added. Any existing tenant with the same
+    ///                identifier will be overwritten.
+    /// * `table_id`:  Identifier of the table to be added to the tenant. This table will contain
+    ///                all the objects.
+    /// * `num`:       The number of objects to be added to the data table.
+    pub fn fill_test(&self, tenant_id: TenantId, table_id: TableId, num: u32) {
+        self.fill_test_sized(tenant_id, table_id, num, 30, 100);
+    }
+
+    /// Adds a tenant and a table full of objects with the given key and value lengths. The
+    /// key (and value) of object `i` starts with the little endian encoding of `i`, where `i`
+    /// ranges from 1 to `num`; the rest of it is zeroed. The tenant is added before the table
+    /// is populated, so that requests to it are served while objects are being written, and the
+    /// objects are written at the rate set on fill_rate(). Refer to fill().
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant_id`: Identifier of the tenant to be added. Any existing tenant with the same
+    ///                identifier will be overwritten.
+    /// * `table_id`:  Identifier of the table to be added to the tenant. This table will contain
+    ///                all the objects.
+    /// * `num`:       The number of objects to be added to the data table.
+    /// * `key_len`:   The length of each object's key. Must be atleast 4 Bytes.
+    /// * `val_len`:   The length of each object's value. Must be atleast 4 Bytes.
+    pub fn fill_test_sized(
+        &self,
+        tenant_id: TenantId,
+        table_id: TableId,
+        num: u32,
+        key_len: usize,
+        val_len: usize,
+    ) {
+        // Create and add a tenant containing the table.
+        let tenant = Tenant::new(tenant_id);
+        self.create_table(&tenant, table_id);
+        self.presize_fill(&tenant, table_id, num as u64);
+        self.insert_tenant(tenant);
+
+        // Fill up the above table. Each object consists of a `key_len` Byte key and a `val_len`
+        // Byte value.
+        let objects = fill::numbered(1, num, key_len, val_len);
+        self.fill(tenant_id, table_id, objects)
+            .expect("Failed to init test table.")
+            .run(&self.heap);
+    }
+
+    /// Returns a fill that populates one of a tenant's tables, which may already be serving
+    /// requests. Objects are written one at a time, exactly like a put(), and atmost at the rate
+    /// set on fill_rate(). The fill can be run to completion on a thread with Fill::run(), or
+    /// in the background on a scheduler with fill::task().
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant_id`: The identifier of the tenant owning the table.
+    /// * `table_id`:  The identifier of the table to be populated.
+    /// * `objects`:   The objects to populate it with.
+    ///
+    /// # Return
+    ///
+    /// The fill, StatusTenantDoesNotExist, or the status Tenant::writable_table() fails with.
+    pub fn fill(
+        &self,
+        tenant_id: TenantId,
+        table_id: TableId,
+        objects: Objects,
+    ) -> Result<Fill, RpcStatus> {
+        let table = self
+            .get_tenant(tenant_id)
+            .ok_or(RpcStatus::StatusTenantDoesNotExist)?
+            .writable_table(table_id)?;
+
+        Ok(Fill::new(
+            tenant_id,
+            table_id,
+            table,
+            objects,
+            Arc::clone(&self.fill_rate),
+        ))
+    }
+
+    /// Returns the cap on the rate at which fills write objects into tables; see
+    /// fill::FillRate. It is uncapped unless set. Fills read it as they run, so setting it
+    /// takes effect on fills that are already running.
+    pub fn fill_rate(&self) -> &Arc<FillRate> {
+        &self.fill_rate
+    }
+
+    /// Populates the TAO dataset. Every object gets four associations.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant_id`: Identifier of the tenant to be added. Any existing tenant with the same
+    ///                identifier will be overwritten.
+    /// * `num`:       The number of objects to be added to the data table.
+    pub fn fill_tao(&self, tenant_id: TenantId, num: u32) {
+        self.fill_tao_with(tenant_id, num, &Fanout::Constant(4));
+    }
+
+    /// Populates the TAO dataset, giving each object a number of associations drawn from a
+    /// fanout distribution. Association lists are written out pre-packed in the layout the
+    /// TAO extension reads (see sandstorm::tao), so large graphs don't have to be built up
+    /// through assoc_add invocations.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant_id`: Identifier of the tenant to be added. Any existing tenant with the same
+    ///                identifier will be overwritten.
+    /// * `num`:       The number of objects to be added to the data table.
+    /// * `fanout`:    The number of associations each object gets.
+    pub fn fill_tao_with(&self, tenant_id: TenantId, num: u32, fanout: &Fanout) {
+        // Create a tenant containing three tables, one for objects, one for
+        // associations, and one for the hot counters of association lists.
+        let tenant = Tenant::new(tenant_id);
+        self.create_table(&tenant, tao::OBJECT_TABLE);
+        self.create_table(&tenant, tao::ASSOC_TABLE);
+        self.create_table(&tenant, tao::HOT_TABLE);
+        self.presize_fill(&tenant, tao::OBJECT_TABLE, num as u64);
+
+        let objects = tenant
+            .get_table(tao::OBJECT_TABLE)
+            .expect("Failed to init test table.");
+        let assocs = tenant
+            .get_table(tao::ASSOC_TABLE)
+            .expect("Failed to init test table.");
+
+        // Objects are generated a tenth at a time, so that progress can be logged on large
+        // graphs.
+        let step = cmp::max(num / 10, 1);
+        let (mut first, mut edges) = (1, 0);
+        while first <= num {
+            let last = cmp::min(first + step, num + 1);
+            edges += tao::fill(num, first..last, fanout, |table_id, key, val| {
+                let table = match table_id {
+                    tao::OBJECT_TABLE => &objects,
+                    _ => &assocs,
+                };
+
+                let obj = self
+                    .heap
+                    .object(tenant_id, table_id, key, val)
+                    .expect("Failed to create test object.");
+                self.heap.store(table, obj.0, obj.1);
+            });
+
+            info!(
+                "Populated {}/{} TAO objects with {} assocs for tenant {}",
+                last - 1,
+                num,
+                edges,
+                tenant_id
+            );
+            first = last;
+        }
+
+        // Add the tenant.
+        self.insert_tenant(tenant);
+    }
+
+    /// Populates the aggregate dataset.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant_id`: Identifier of the tenant to be added. Any existing tenant with the same
+    ///                identifier will be overwritten.
+    /// * `table_id`:  Identifier of the table to be added to the tenant. This table will contain
+    ///                all the objects.
+    /// * `num`:       The number of objects to be added to the data table.
+    pub fn fill_aggregate(&self, tenant_id: TenantId, table_id: TableId, num: u32) {
+        // One table for the tenant. Both, objects and indirection lists will be
+        // stored in here.
+        let tenant = Tenant::new(tenant_id);
+        self.create_table(&tenant, table_id);
+        self.presize_fill(&tenant, table_id, num as u64 + 4 * (num as u64 + 1));
+
+        let table = tenant
+            .get_table(table_id)
+            .expect("Failed to init test table.");
+
+        // The number of records per aggregation.
+        const N_AGG: u32 = 12;
+        // The length of each record's key.
+        const K_LEN: u32 = 30;
+        // The length of each record's value.
+        const V_LEN: u32 = 100;
+
+        // The total number of records.
+        let records: u32 = 4 * (num + 1);
+
+        // First, add in the indirection records. Keys are 8 bytes, and values are
+        // lists of 30 Byte keys.
+        for i in 1..(num + 1) {
+            let mut key = vec![0; 8];
+            let mut val = vec![];
+
+            let temp: [u8; 4] = unsafe { transmute(i.to_le()) };
+            &key[0..4].copy_from_slice(&temp);
+
+            for e in 0..N_AGG {
+                let mut k = vec![0; K_LEN as usize];
                 let t: [u8; 4] = unsafe { transmute(((i * N_AGG + e) % records).to_le()) };
                 &k[0..4].copy_from_slice(&t);
 
@@ -291,7 +1563,7 @@ impl Master {
                 .heap
                 .object(tenant_id, table_id, &key, &val)
                 .expect("Failed to create test object.");
-            table.put(obj.0, obj.1);
+            self.heap.store(&table, obj.0, obj.1);
         }
 
         // Next, populate the actual records.
@@ -307,7 +1579,7 @@ impl Master {
                 .heap
                 .object(tenant_id, table_id, &key, &val)
                 .expect("Failed to create test object.");
-            table.put(obj.0, obj.1);
+            self.heap.store(&table, obj.0, obj.1);
         }
 
         self.insert_tenant(tenant);
@@ -330,7 +1602,7 @@ impl Master {
         for tenant_id in 1..(num_tenants + 1) {
             // Create a tenant containing the table.
             let tenant = Tenant::new(tenant_id);
-            tenant.create_table(table_id);
+            self.create_table(&tenant, table_id);
 
             let table = tenant
                 .get_table(table_id)
@@ -354,7 +1626,7 @@ impl Master {
                     .heap
                     .object(tenant_id, table_id, &key, &serialized)
                     .expect("Failed to create test object.");
-                table.put(obj.0, obj.1);
+                self.heap.store(&table, obj.0, obj.1);
             }
 
             // Add the tenant.
@@ -362,7 +1634,10 @@ impl Master {
         }
     }
 
-    /// Populates the authentication dataset.
+    /// Populates the authentication dataset, with cost 1 hashes kept whole and 16 byte salts.
+    /// The table holds password hashes, so it is created invoke-only; only the auth extension
+    /// can read it, and native RPCs on it are refused unless a table_access() RPC from the
+    /// tenant re-enables them.
     ///
     /// # Arguments
     ///
@@ -372,42 +1647,54 @@ impl Master {
     ///                all the objects.
     /// * `num`:       The number of objects to be added to the data table.
     pub fn fill_auth(&self, tenant_id: TenantId, table_id: TableId, num: u32) {
+        self.fill_auth_with(tenant_id, table_id, num, &Layout::default());
+    }
+
+    /// Populates the authentication dataset, hashing passwords at the cost and into the record
+    /// layout passed in. Records name their cost, so the auth extension and clients check
+    /// passwords against them without being told the layout (see sandstorm::auth). The table
+    /// is created invoke-only, like under fill_auth().
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant_id`: Identifier of the tenant to be added. Any existing tenant with the same
+    ///                identifier will be overwritten.
+    /// * `table_id`:  Identifier of the table to be added to the tenant. This table will contain
+    ///                all the objects.
+    /// * `num`:       The number of objects to be added to the data table.
+    /// * `layout`:    The bcrypt cost, and the length of the hash and salt on each record.
+    pub fn fill_auth_with(
+        &self,
+        tenant_id: TenantId,
+        table_id: TableId,
+        num: u32,
+        layout: &Layout,
+    ) {
         // Create a tenant containing the table.
         let tenant = Tenant::new(tenant_id);
-        tenant.create_table(table_id);
+        tenant.create_table_with_access(table_id, self.compression, false, false);
+        self.set_layout(&tenant, table_id);
+        self.presize_fill(&tenant, table_id, num as u64);
+        self.fill_auth_table(&tenant, table_id, num, layout);
+
+        // Add the tenant.
+        self.insert_tenant(tenant);
+    }
 
+    // Fills a tenant's table with the records of users 1 to `num` of the AUTH dataset. Each
+    // object consists of a 30 byte username and a record laid out as per `layout`.
+    fn fill_auth_table(&self, tenant: &Tenant, table_id: TableId, num: u32, layout: &Layout) {
         let table = tenant
             .get_table(table_id)
             .expect("Failed to init test table.");
 
-        let mut username = vec![0; 30];
-        let mut password = vec![0; 72];
-        let mut hash_salt = vec![0; 40];
-        let mut salt = vec![0; 16];
-
-        // Allocate objects, and fill up the above table. Each object consists of a 30 Byte key
-        // and a 40 Byte value(24 byte HASH followed by 16 byte SALT).
-        for i in 1..(num + 1) {
-            let temp: [u8; 4] = unsafe { transmute(i.to_le()) };
-            &username[0..4].copy_from_slice(&temp);
-            &password[0..4].copy_from_slice(&temp);
-            &hash_salt[24..28].copy_from_slice(&temp);
-            &salt[0..4].copy_from_slice(&temp);
-
-            let output: &mut [u8] = &mut [0; 24];
-            bcrypt(1, &salt, &password, output);
-            &hash_salt[0..24].copy_from_slice(&output);
-
-            // Add a mapping of the username and (HASH+SALT) in the table.
+        auth::fill(layout, 1..(num + 1), |username, record| {
             let obj = self
                 .heap
-                .object(tenant_id, table_id, &username, &hash_salt)
+                .object(tenant.id(), table_id, username, record)
                 .expect("Failed to create test object.");
-            table.put(obj.0, obj.1);
-        }
-
-        // Add the tenant.
-        self.insert_tenant(tenant);
+            self.heap.store(&table, obj.0, obj.1);
+        });
     }
 
     /// Populates the ANALYSIS, AUTH, and PUSHBACK OR YCSB dataset.
@@ -431,9 +1718,9 @@ impl Master {
         for tenant_id in 1..(num_tenants + 1) {
             // Create a tenant containing the table.
             let tenant = Tenant::new(tenant_id);
-            tenant.create_table(table_id);
-            tenant.create_table(auth_table_id);
-            tenant.create_table(fake_table_id);
+            self.create_table(&tenant, table_id);
+            self.create_table(&tenant, auth_table_id);
+            self.create_table(&tenant, fake_table_id);
 
             //-----------------------Fill ANALYSIS----------------------------------------------------------//
             let table = tenant
@@ -458,39 +1745,12 @@ impl Master {
                     .heap
                     .object(tenant_id, table_id, &key, &serialized)
                     .expect("Failed to create test object.");
-                table.put(obj.0, obj.1);
+                self.heap.store(&table, obj.0, obj.1);
             }
 
             //----------------------------Fill Auth--------------------------------------------------//
             let num_records_auth: u32 = 1000;
-            let table = tenant
-                .get_table(auth_table_id)
-                .expect("Failed to init test table.");
-            let mut username = vec![0; 30];
-            let mut password = vec![0; 72];
-            let mut hash_salt = vec![0; 40];
-            let mut salt = vec![0; 16];
-
-            // Allocate objects, and fill up the above table. Each object consists of a 30 Byte key
-            // and a 40 Byte value(24 byte HASH followed by 16 byte SALT).
-            for i in 1..(num_records_auth + 1) {
-                let temp: [u8; 4] = unsafe { transmute(i.to_le()) };
-                &username[0..4].copy_from_slice(&temp);
-                &password[0..4].copy_from_slice(&temp);
-                &hash_salt[24..28].copy_from_slice(&temp);
-                &salt[0..4].copy_from_slice(&temp);
-
-                let output: &mut [u8] = &mut [0; 24];
-                bcrypt(1, &salt, &password, output);
-                &hash_salt[0..24].copy_from_slice(&output);
-
-                // Add a mapping of the username and (HASH+SALT) in the table.
-                let obj = self
-                    .heap
-                    .object(tenant_id, auth_table_id, &username, &hash_salt)
-                    .expect("Failed to create test object.");
-                table.put(obj.0, obj.1);
-            }
+            self.fill_auth_table(&tenant, auth_table_id, num_records_auth, &Layout::default());
 
             //-------------------------------Fill Test-----------------------------------------------//
             let table = tenant
@@ -510,108 +1770,274 @@ impl Master {
                     .heap
                     .object(tenant_id, fake_table_id, &key, &val)
                     .expect("Failed to create test object.");
-                table.put(obj.0, obj.1);
+                self.heap.store(&table, obj.0, obj.1);
             }
             // Add the tenant.
             self.insert_tenant(tenant);
         }
     }
 
-    /// Loads the get(), put(), tao(), and bad() extensions.
+    /// Loads the get(), put(), tao(), and bad() extensions. Does nothing if this server does not
+    /// serve extensions.
     ///
     /// # Arguments
     ///
     /// * `tenant`: Identifier of the tenant to load the extension for.
     pub fn load_test(&self, tenant: TenantId) {
-        // Load the get() extension.
-        let name = "../ext/get/target/release/libget.so";
-        if self.extensions.load(name, tenant, "get") == false {
-            panic!("Failed to load get() extension.");
-        }
-
-        // Load the put() extension.
-        let name = "../ext/put/target/release/libput.so";
-        if self.extensions.load(name, tenant, "put") == false {
-            panic!("Failed to load put() extension.");
-        }
-
-        // Load the tao() extension.
-        let name = "../ext/tao/target/release/libtao.so";
-        if self.extensions.load(name, tenant, "tao") == false {
-            panic!("Failed to load tao() extension.");
-        }
-
-        // Load the bad() extension.
-        let name = "../ext/bad/target/release/libbad.so";
-        if self.extensions.load(name, tenant, "bad") == false {
-            panic!("Failed to load bad() extension.");
-        }
-
-        // Load the long() extension.
-        let name = "../ext/long/target/release/liblong.so";
-        if self.extensions.load(name, tenant, "long") == false {
-            panic!("Failed to load long() extension.");
-        }
-
-        // Load the aggregate() extension.
-        let name = "../ext/aggregate/target/release/libaggregate.so";
-        if self.extensions.load(name, tenant, "aggregate") == false {
-            panic!("Failed to load aggregate() extension.");
-        }
-
-        // Load the pushback() extension.
-        let name = "../ext/pushback/target/release/libpushback.so";
-        if self.extensions.load(name, tenant, "pushback") == false {
-            panic!("Failed to load pushback() extension.");
-        }
-
-        // Load the scan() extension.
-        let name = "../ext/scan/target/release/libscan.so";
-        if self.extensions.load(name, tenant, "scan") == false {
-            panic!("Failed to load scan() extension.");
-        }
-
-        // Load the analysis() extension.
-        let name = "../ext/analysis/target/release/libanalysis.so";
-        if self.extensions.load(name, tenant, "analysis") == false {
-            panic!("Failed to load analysis() extension.");
-        }
-
-        // Load the pushback() extension.
-        let name = "../ext/auth/target/release/libauth.so";
-        if self.extensions.load(name, tenant, "auth") == false {
-            panic!("Failed to load auth() extension.");
+        let extensions = match self.extensions {
+            Some(ref extensions) => extensions,
+            None => return,
+        };
+
+        for &(path, name) in TEST_EXTENSIONS.iter() {
+            if extensions.load(path, tenant, name) == false {
+                panic!("Failed to load {}() extension.", name);
+            }
         }
     }
 
     /// Loads the get(), put(), and tao() extensions once, and shares them across multiple tenants.
+    /// Does nothing if this server does not serve extensions.
     ///
     /// # Arguments
     ///
     /// * `tenants`: The number of tenants that should share the above three extensions.
     pub fn load_test_shared(&self, tenants: u32) {
+        let extensions = match self.extensions {
+            Some(ref extensions) => extensions,
+            None => return,
+        };
+
         // First, load up the get, put, and tao extensions for tenant 1.
         self.load_test(0);
 
         // Next, share these extensions with the other tenants.
         for tenant in 1..tenants {
             // Share the get() extension.
-            if self.extensions.share(0, tenant, "get") == false {
+            if extensions.share(0, tenant, "get") == false {
                 panic!("Failed to share get() extension.");
             }
 
             // Share the put() extension.
-            if self.extensions.share(0, tenant, "put") == false {
+            if extensions.share(0, tenant, "put") == false {
                 panic!("Failed to share put() extension.");
             }
 
             // Share the tao() extension.
-            if self.extensions.share(0, tenant, "tao") == false {
+            if extensions.share(0, tenant, "tao") == false {
                 panic!("Failed to share tao() extension.");
             }
         }
     }
 
+    /// Shares a table read-only with another tenant, under an identifier in that tenant's table
+    /// namespace. Reads through the alias see the owner's table, and writes through it fail with
+    /// StatusReadOnlyTable. The tenant is created if it does not exist yet.
+    ///
+    /// # Arguments
+    ///
+    /// * `owner_id`:  Identifier of the tenant that owns the table.
+    /// * `table_id`:  Identifier of the table under the owner.
+    /// * `tenant_id`: Identifier of the tenant the table is being shared with.
+    /// * `alias_id`:  Identifier the table goes by under `tenant_id`.
+    ///
+    /// # Return
+    ///
+    /// Ok if the table was shared. StatusTenantDoesNotExist or StatusTableDoesNotExist if the
+    /// owner or it's table do not exist, and StatusInvalidOperation if the alias conflicts with
+    /// an existing table or alias under `tenant_id`.
+    pub fn share_table(
+        &self,
+        owner_id: TenantId,
+        table_id: TableId,
+        tenant_id: TenantId,
+        alias_id: TableId,
+    ) -> Result<(), RpcStatus> {
+        let owner = self
+            .get_tenant(owner_id)
+            .ok_or(RpcStatus::StatusTenantDoesNotExist)?;
+        if owner.get_table(table_id).is_none() {
+            return Err(RpcStatus::StatusTableDoesNotExist);
+        }
+
+        if self.get_tenant(tenant_id).is_none() {
+            self.insert_tenant(Tenant::new(tenant_id));
+        }
+        let tenant = self
+            .get_tenant(tenant_id)
+            .ok_or(RpcStatus::StatusTenantDoesNotExist)?;
+
+        match tenant.attach_alias(alias_id, owner, table_id) {
+            true => Ok(()),
+            false => Err(RpcStatus::StatusInvalidOperation),
+        }
+    }
+
+    /// Starts recording the invocations a tenant issues into an audit log, which the tenant can
+    /// page through with the audit() RPC. Does nothing if the tenant is already being audited.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant_id`: The tenant to be audited.
+    /// * `capacity`:  The number of entries the tenant's log holds. 0 means
+    ///                audit::DEFAULT_ENTRIES.
+    ///
+    /// # Return
+    ///
+    /// StatusTenantDoesNotExist if there is no such tenant.
+    pub fn enable_audit(&self, tenant_id: TenantId, capacity: usize) -> Result<(), RpcStatus> {
+        let tenant = self
+            .get_tenant(tenant_id)
+            .ok_or(RpcStatus::StatusTenantDoesNotExist)?;
+        tenant.enable_audit(capacity);
+        Ok(())
+    }
+
+    /// Returns a page of a tenant's audit log, newest entry first. Refer to AuditLog::page().
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant_id`: The tenant whose log is paged through.
+    /// * `cursor`:    0 for the newest entries, or the cursor returned with the previous page.
+    ///
+    /// # Return
+    ///
+    /// The entries and the cursor to fetch the next page with, or StatusAuditDisabled if the
+    /// tenant isn't being audited.
+    pub fn audit_page(
+        &self,
+        tenant_id: TenantId,
+        cursor: u64,
+    ) -> Result<(Vec<AuditEntry>, u64), RpcStatus> {
+        let tenant = self
+            .get_tenant(tenant_id)
+            .ok_or(RpcStatus::StatusTenantDoesNotExist)?;
+        let log = tenant.audit().ok_or(RpcStatus::StatusAuditDisabled)?;
+        Ok(log.page(cursor, AUDIT_PAGE_MAX))
+    }
+
+    // Records an invoke that was refused before it ran into the issuing tenant's audit log, if
+    // the tenant is being audited.
+    fn audit_refused(
+        &self,
+        tenant_id: TenantId,
+        name: &[u8],
+        id: u64,
+        args_len: usize,
+        status: RpcStatus,
+    ) {
+        if let Some(tenant) = self.get_tenant(tenant_id) {
+            if let Some(log) = tenant.audit() {
+                log.record(&AuditEntry {
+                    seq: 0,
+                    stamp: cycles::rdtsc(),
+                    name_hash: audit::name_hash(name),
+                    id: id,
+                    status: status as u8,
+                    args_len: args_len as u32,
+                    res_len: 0,
+                    cycles: 0,
+                });
+            }
+        }
+    }
+
+    /// Maps in the read-only table image configured in the server config, and registers it
+    /// under the configured tenant and table. The tenant is created if it does not exist. Does
+    /// nothing if no image is configured.
+    ///
+    /// # Arguments
+    ///
+    /// * `config`: The server config containing the image's path, tenant and table.
+    ///
+    /// # Return
+    ///
+    /// An error if the image could not be mapped in, is corrupt, was built for a different
+    /// tenant or table, holds an object over the server's limits, or if the tenant already has a
+    /// table with the same identifier.
+    pub fn load_image(&self, config: &ServerConfig) -> io::Result<()> {
+        if config.image_path.is_empty() {
+            return Ok(());
+        }
+
+        let image = ReadOnlyTable::open(Path::new(&config.image_path))?;
+        if (image.tenant(), image.table_id()) != (config.image_tenant, config.image_table) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "image was built for tenant {} table {}, not tenant {} table {}",
+                    image.tenant(),
+                    image.table_id(),
+                    config.image_tenant,
+                    config.image_table
+                ),
+            ));
+        }
+
+        // Objects in the image are served exactly like any other, so one the server would have
+        // refused to write could not be read back either.
+        let (key_len, val_len) = image.largest();
+        let limits = self.limits();
+        if key_len > limits.max_key_len() || val_len > limits.max_value_len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "image has a {} byte key and a {} byte value; the server allows {} and {}",
+                    key_len,
+                    val_len,
+                    limits.max_key_len(),
+                    limits.max_value_len()
+                ),
+            ));
+        }
+
+        info!(
+            "Mapped in {} objects from {} as tenant {} table {}",
+            image.len(),
+            config.image_path,
+            image.tenant(),
+            image.table_id()
+        );
+
+        if self.get_tenant(config.image_tenant).is_none() {
+            self.insert_tenant(Tenant::new(config.image_tenant));
+        }
+        let tenant = self
+            .get_tenant(config.image_tenant)
+            .expect("Failed to create tenant for image.");
+        match tenant.insert_table(config.image_table, Table::from_image(image)) {
+            true => Ok(()),
+            false => Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!(
+                    "tenant {} already has a table {}",
+                    config.image_tenant, config.image_table
+                ),
+            )),
+        }
+    }
+
+    /// Returns every read-only table alias in the system, for reporting.
+    ///
+    /// # Return
+    ///
+    /// A tuple for each alias consisting of the tenant holding it, the identifier it goes by,
+    /// the owning tenant, the owner's identifier for the table, and whether the owner's table
+    /// still exists.
+    pub fn table_aliases(&self) -> Vec<(TenantId, TableId, TenantId, TableId, bool)> {
+        let mut aliases = Vec::new();
+        for bucket in self.tenants.iter() {
+            for (id, tenant) in bucket.read().iter() {
+                for (alias, owner, table, live) in tenant.aliases().into_iter() {
+                    aliases.push((*id, alias, owner, table, live));
+                }
+            }
+        }
+
+        aliases.sort();
+        aliases
+    }
+
     /// This method returns a handle to a tenant if it exists.
     ///
     /// # Arguments
@@ -621,7 +2047,7 @@ impl Master {
     /// # Return
     ///
     /// An atomic reference counted handle to the tenant if it exists.
-    fn get_tenant(&self, tenant_id: TenantId) -> Option<Arc<Tenant>> {
+    pub fn get_tenant(&self, tenant_id: TenantId) -> Option<Arc<Tenant>> {
         // Acquire a read lock. The bucket is determined by the least significant byte of the
         // tenant id.
         let bucket = (tenant_id & 0xff) as usize & (TENANT_BUCKETS - 1);
@@ -632,6 +2058,23 @@ impl Master {
             .and_then(|tenant| Some(Arc::clone(tenant)))
     }
 
+    /// Returns a handle to a tenant if it exists, looking it up through the calling core's cache
+    /// of tenant handles first. Used by every request that names a tenant, so that the tenants
+    /// a core keeps serving are found without taking a lock.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant_id`: The identifier for the tenant to be returned.
+    ///
+    /// # Return
+    ///
+    /// An atomic reference counted handle to the tenant if it exists.
+    fn tenant(&self, tenant_id: TenantId) -> Option<Arc<Tenant>> {
+        tenant_cache::lookup(&self.epoch, self.tenant_cache, tenant_id, || {
+            self.get_tenant(tenant_id)
+        })
+    }
+
     /// This method adds a tenant to Master.
     ///
     /// # Arguments
@@ -643,8 +2086,310 @@ impl Master {
         let bucket = (tenant.id() & 0xff) as usize & (TENANT_BUCKETS - 1);
         let mut map = self.tenants[bucket].write();
 
-        // Insert the tenant and return.
-        map.insert(tenant.id(), Arc::new(tenant));
+        // Insert the tenant, and invalidate the handles cached on every core.
+        let tenant = Arc::new(tenant);
+        self.epochs.register(&tenant);
+        map.insert(tenant.id(), tenant);
+        self.epoch.bump();
+    }
+
+    /// This method removes a tenant from Master. Requests that looked the tenant up before it
+    /// was removed run to completion on their handle, but later requests fail to find it, on
+    /// every core. The server's tenant namespace moves to a new epoch.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant_id`: The identifier of the tenant to be removed.
+    ///
+    /// # Return
+    ///
+    /// A handle to the tenant, if it existed.
+    pub fn remove_tenant(&self, tenant_id: TenantId) -> Option<Arc<Tenant>> {
+        let bucket = (tenant_id & 0xff) as usize & (TENANT_BUCKETS - 1);
+        let removed = self.tenants[bucket].write().remove(&tenant_id);
+        self.epoch.bump();
+        if removed.is_some() {
+            self.epochs.remove(tenant_id);
+        }
+        removed
+    }
+
+    /// This method creates a table for a tenant with the compression settings and layout
+    /// configured on Master. If the table already exists, only it's layout is set.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`:   The tenant to create the table for.
+    /// * `table_id`: The identifier of the table to be created.
+    fn create_table(&self, tenant: &Tenant, table_id: TableId) {
+        tenant.create_table_with(table_id, self.compression);
+        self.set_layout(tenant, table_id);
+    }
+
+    /// Creates a table for a tenant, pre-sized for the number of objects it is expected to hold,
+    /// for the create_table() RPC. If the table already exists, it is grown to fit them instead.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant_id`: The identifier of the tenant to create the table for.
+    /// * `table_id`:  The identifier of the table to be created.
+    /// * `records`:   The number of objects the table is expected to hold. 0 if not known, in
+    ///                which case the table is not pre-sized.
+    /// * `cache`:     The capacity target of a cache-mode table (refer to evict). None creates
+    ///                an ordinary table, and leaves the target of an existing table as it is.
+    ///
+    /// # Return
+    ///
+    /// The number of slots reserved on the table. Otherwise StatusTenantDoesNotExist,
+    /// StatusTableTooLarge if the table can't be sized for these many objects, in which case it
+    /// is not created, or StatusReadOnlyTable if the identifier refers to an alias or a table
+    /// backed by an image.
+    pub fn create_table_sized(
+        &self,
+        tenant_id: TenantId,
+        table_id: TableId,
+        records: u64,
+        cache: Option<CacheTarget>,
+    ) -> Result<usize, RpcStatus> {
+        let tenant = self
+            .get_tenant(tenant_id)
+            .ok_or(RpcStatus::StatusTenantDoesNotExist)?;
+        self.presize.slots(records)?;
+
+        if let Err(RpcStatus::StatusReadOnlyTable) = tenant.writable_table(table_id) {
+            return Err(RpcStatus::StatusReadOnlyTable);
+        }
+
+        self.create_table(&tenant, table_id);
+        let slots = self.presize_table(&tenant, table_id, records)?;
+        if cache.is_some() {
+            self.set_cache_target(tenant_id, table_id, cache)?;
+        }
+        Ok(slots)
+    }
+
+    /// Drops one of a tenant's tables, for the drop_table() RPC. The table's objects are freed
+    /// once the last handle to the table is dropped, so requests and extensions already holding
+    /// one run to completion against it. The table and the tenant move to a new epoch.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant_id`: The identifier of the tenant owning the table.
+    /// * `table_id`:  The identifier of the table to be dropped.
+    ///
+    /// # Return
+    ///
+    /// StatusOk if the table was dropped. Otherwise StatusTenantDoesNotExist,
+    /// StatusTableDoesNotExist, or StatusReadOnlyTable if the identifier refers to an alias or
+    /// a table backed by an image.
+    pub fn drop_table(&self, tenant_id: TenantId, table_id: TableId) -> RpcStatus {
+        let tenant = match self.get_tenant(tenant_id) {
+            Some(tenant) => tenant,
+            None => return RpcStatus::StatusTenantDoesNotExist,
+        };
+
+        if let Err(err) = tenant.writable_table(table_id) {
+            return err;
+        }
+
+        match tenant.remove_table(table_id) {
+            Some(table) => {
+                let epoch = self.epochs.advance();
+                table.set_epoch(epoch);
+                tenant.set_epoch(epoch);
+                RpcStatus::StatusOk
+            }
+
+            None => RpcStatus::StatusTableDoesNotExist,
+        }
+    }
+
+    /// Truncates one of a tenant's tables, removing every object on it exactly as if each were
+    /// deleted. The table and the tenant move to a new epoch.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant_id`: The identifier of the tenant owning the table.
+    /// * `table_id`:  The identifier of the table to be truncated.
+    ///
+    /// # Return
+    ///
+    /// StatusOk if the table was truncated. Otherwise StatusTenantDoesNotExist,
+    /// StatusTableDoesNotExist, or StatusReadOnlyTable if the identifier refers to an alias.
+    pub fn truncate_table(&self, tenant_id: TenantId, table_id: TableId) -> RpcStatus {
+        let tenant = match self.get_tenant(tenant_id) {
+            Some(tenant) => tenant,
+            None => return RpcStatus::StatusTenantDoesNotExist,
+        };
+
+        match tenant.writable_table(table_id) {
+            Ok(table) => {
+                table.truncate();
+                let epoch = self.epochs.advance();
+                table.set_epoch(epoch);
+                tenant.set_epoch(epoch);
+                RpcStatus::StatusOk
+            }
+
+            Err(err) => err,
+        }
+    }
+
+    /// Removes the result an invocation stored in a tenant's mailbox, and returns it, for the
+    /// fetch_result() RPC. Refer to mailbox::Mailbox::fetch().
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant_id`:  The identifier of the tenant that issued the invocation.
+    /// * `request_id`: The id of the invoke() request.
+    ///
+    /// # Return
+    ///
+    /// The status the invocation completed with and the payload of it's response. Otherwise
+    /// StatusTenantDoesNotExist, or StatusObjectDoesNotExist if no result is stored under the
+    /// request id or it expired.
+    pub fn fetch_result(
+        &self,
+        tenant_id: TenantId,
+        request_id: u64,
+    ) -> Result<(RpcStatus, Vec<u8>), RpcStatus> {
+        let tenant = self
+            .get_tenant(tenant_id)
+            .ok_or(RpcStatus::StatusTenantDoesNotExist)?;
+        self.mailbox
+            .fetch(&tenant, &self.heap, request_id, cycles::rdtsc())
+    }
+
+    /// Puts one of a tenant's tables in cache mode, bounding it to a capacity target it evicts
+    /// objects to stay under, or takes it out of cache mode. Objects already on the table over
+    /// the target are evicted right away. Refer to Table::set_cache().
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant_id`: The identifier of the tenant the table belongs to.
+    /// * `table_id`:  The identifier of the table.
+    /// * `target`:    The capacity target, or None to stop evicting objects off the table.
+    ///
+    /// # Return
+    ///
+    /// StatusTenantDoesNotExist or StatusTableDoesNotExist if there is no such table, or
+    /// StatusReadOnlyTable if the identifier refers to an alias or a table backed by an image.
+    pub fn set_cache_target(
+        &self,
+        tenant_id: TenantId,
+        table_id: TableId,
+        target: Option<CacheTarget>,
+    ) -> Result<(), RpcStatus> {
+        let tenant = self
+            .get_tenant(tenant_id)
+            .ok_or(RpcStatus::StatusTenantDoesNotExist)?;
+        tenant.writable_table(table_id)?.set_cache(target);
+        Ok(())
+    }
+
+    // Pre-sizes one of a tenant's tables for the number of objects it is expected to hold.
+    // Returns the number of slots reserved on it, 0 if records is 0.
+    fn presize_table(
+        &self,
+        tenant: &Tenant,
+        table_id: TableId,
+        records: u64,
+    ) -> Result<usize, RpcStatus> {
+        let slots = self.presize.slots(records)?;
+        let table = tenant.writable_table(table_id)?;
+        if slots > 0 {
+            table.presize(slots);
+        }
+        Ok(slots)
+    }
+
+    // Pre-sizes a table that is about to be filled. Tables too large to be pre-sized are filled
+    // anyway, and grow as they are.
+    fn presize_fill(&self, tenant: &Tenant, table_id: TableId, records: u64) {
+        if let Err(status) = self.presize_table(tenant, table_id, records) {
+            warn!(
+                "Not pre-sizing table {} of tenant {} for {} objects: {:?}",
+                table_id,
+                tenant.id(),
+                records,
+                status
+            );
+        }
+    }
+
+    /// This method sets the layout of a tenant's table to the one configured on Master. Objects
+    /// already in the table keep the layout they were written with.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`:   The tenant owning the table.
+    /// * `table_id`: The identifier of the table.
+    fn set_layout(&self, tenant: &Tenant, table_id: TableId) {
+        if let Some(table) = tenant.get_table(table_id) {
+            table.set_split_keys(self.split_keys);
+            table.set_inline_values(self.inline_values);
+            table.set_checksums(self.checksum_tables.contains(&table_id));
+            table.set_dedup(match self.dedup_tables.contains(&table_id) {
+                true => Some(self.dedup_entries),
+                false => None,
+            });
+        }
+    }
+
+    /// Returns every table whose objects carry a checksum, along with the tenant owning it.
+    /// Aliases are left out, since their tables are returned under their owner.
+    pub fn checksummed_tables(&self) -> Vec<(TenantId, TableId, Arc<Table>)> {
+        let mut tables = Vec::new();
+        for bucket in self.tenants.iter() {
+            for (id, tenant) in bucket.read().iter() {
+                for (table_id, table) in tenant.tables().into_iter() {
+                    if table.checksums() {
+                        tables.push((*id, table_id, table));
+                    }
+                }
+            }
+        }
+
+        tables
+    }
+
+    /// Hands out the integrity sweep, if one is configured. Only the first caller gets it, so
+    /// this can be called from every scheduler. Refer to integrity::task().
+    pub fn integrity_sweep(&self) -> Option<Sweep> {
+        if self.verify_rate == 0 || self.sweeping.swap(true, Ordering::Relaxed) {
+            return None;
+        }
+
+        Some(Sweep::new(self.verify_rate, self.quarantine))
+    }
+
+    /// Returns every table that can be written to, along with the tenant owning it, for the hot
+    /// arena's walk. Tables mapped in from images never move.
+    pub fn writable_tables(&self) -> Vec<(TenantId, TableId, Arc<Table>)> {
+        let mut tables = Vec::new();
+        for bucket in self.tenants.iter() {
+            for (id, tenant) in bucket.read().iter() {
+                for (table_id, table) in tenant.tables().into_iter() {
+                    if !table.read_only() {
+                        tables.push((*id, table_id, table));
+                    }
+                }
+            }
+        }
+
+        tables
+    }
+
+    /// Hands out the walk that moves objects in and out of the hot arena, if there is one. Only
+    /// the first caller gets it, so this can be called from every scheduler. Refer to
+    /// hot::task().
+    pub fn hot_walk(&self) -> Option<Walk> {
+        let arena = self.heap.hot_arena()?;
+        if self.relocating.swap(true, Ordering::Relaxed) {
+            return None;
+        }
+
+        Some(Walk::new(Arc::clone(arena)))
     }
 
     /// Handles the Get() RPC request.
@@ -663,8 +2408,6 @@ impl Master {
     ///
     /// A Native task that can be scheduled by the database. In the case of an error, the passed
     /// in request and response packets are returned with the response status appropriately set.
-    #[allow(unreachable_code)]
-    #[allow(unused_assignments)]
     fn get(
         &self,
         req: Packet<UdpHeader, EmptyMetadata>,
@@ -676,127 +2419,705 @@ impl Master {
             Packet<UdpHeader, EmptyMetadata>,
         ),
     > {
-        // First, parse the request packet.
-        let req = req.parse_header::<GetRequest>();
+        self.get_task(req, res, None)
+    }
 
-        // Read fields off the request header.
-        let mut tenant_id: TenantId = 0;
-        let mut table_id: TableId = 0;
-        let mut key_length = 0;
-        let mut rpc_stamp = 0;
-        let mut req_generator = GetGenerator::InvalidGenerator;
+    /// Resolves a tenant and table once, so that a group of get() requests for the same table
+    /// can skip per-request resolution. Refer to `get_resolved()`.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant_id`: The identifier of the tenant the table belongs to.
+    /// * `table_id`:  The identifier of the table to be resolved.
+    ///
+    /// # Return
+    ///
+    /// A handle to the table if it exists. Otherwise, the status a get() on the table would have
+    /// failed with.
+    pub fn resolve_table(
+        &self,
+        tenant_id: TenantId,
+        table_id: TableId,
+    ) -> Result<Arc<Table>, RpcStatus> {
+        match self.tenant(tenant_id) {
+            Some(tenant) => tenant.readable_native_table(table_id),
 
-        {
-            let hdr = req.get_header();
-            tenant_id = hdr.common_header.tenant as TenantId;
-            table_id = hdr.table_id as TableId;
-            key_length = hdr.key_length;
-            rpc_stamp = hdr.common_header.stamp;
-            req_generator = hdr.generator.clone();
+            None => Err(RpcStatus::StatusTenantDoesNotExist),
         }
+    }
 
-        // Next, add a header to the response packet.
-        let mut res = res
-            .push_header(&GetResponse::new(
-                rpc_stamp,
-                OpCode::SandstormGetRpc,
-                tenant_id,
-            )).expect("Failed to setup GetResponse");
+    /// Looks up a key exactly like a native get() would, for requests that don't arrive as RPCs
+    /// (ex: memcache::Adapter).
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant_id`: The identifier of the tenant the table belongs to.
+    /// * `table_id`:  The identifier of the table to look the key up in.
+    /// * `key`:       The key to look up.
+    ///
+    /// # Return
+    ///
+    /// The object's value, or the status a native get() would have failed with. Reads are never
+    /// verified, but quarantined objects are not returned.
+    pub fn get_value(
+        &self,
+        tenant_id: TenantId,
+        table_id: TableId,
+        key: &[u8],
+    ) -> Result<Bytes, RpcStatus> {
+        self.get_value_resolved(self.resolve_table(tenant_id, table_id), key)
+    }
 
-        // If the payload size is less than the key length, return an error.
-        if req.get_payload().len() < key_length as usize {
-            res.get_mut_header().common_header.status = RpcStatus::StatusMalformedRequest;
-            return Err((
-                req.deparse_header(PACKET_UDP_LEN as usize),
-                res.deparse_header(PACKET_UDP_LEN as usize),
-            ));
+    /// Looks up a key exactly like get_value(), off a table that was already resolved with
+    /// resolve_table(). Lets a group of lookups for the same table resolve it only once.
+    ///
+    /// # Arguments
+    ///
+    /// * `table`: The table to look the key up in, or the status resolving it failed with.
+    /// * `key`:   The key to look up.
+    ///
+    /// # Return
+    ///
+    /// The object's value, or the status a native get() would have failed with.
+    pub fn get_value_resolved(
+        &self,
+        table: Result<Arc<Table>, RpcStatus>,
+        key: &[u8],
+    ) -> Result<Bytes, RpcStatus> {
+        let table = table?;
+        let object = table.get(key).ok_or(RpcStatus::StatusObjectDoesNotExist)?;
+        if table.is_quarantined(key) {
+            return Err(RpcStatus::StatusDataCorrupted);
         }
 
-        // Lookup the tenant, and get a handle to the allocator. Required to avoid capturing a
-        // reference to Master in the generator below.
+        self.heap
+            .resolve(object.value)
+            .map(|(_k, value)| value)
+            .ok_or(RpcStatus::StatusInternalError)
+    }
+
+    /// Writes an object exactly like a native put() would, for requests that don't arrive as
+    /// RPCs (ex: memcache::Adapter).
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant_id`: The identifier of the tenant the table belongs to.
+    /// * `table_id`:  The identifier of the table to write the object to.
+    /// * `key`:       The object's key.
+    /// * `val`:       The object's value. Must not be empty.
+    ///
+    /// # Return
+    ///
+    /// StatusOk if the object was written, or the status a native put() would have failed with.
+    /// StatusKeyTooLarge or StatusValueTooLarge if the object is over the server's limits.
+    pub fn put_value(
+        &self,
+        tenant_id: TenantId,
+        table_id: TableId,
+        key: &[u8],
+        val: &[u8],
+    ) -> RpcStatus {
+        let table = match self.get_tenant(tenant_id) {
+            Some(tenant) => match tenant.writable_native_table(table_id) {
+                Ok(table) => table,
+                Err(err) => return err,
+            },
+
+            None => return RpcStatus::StatusTenantDoesNotExist,
+        };
+
+        if let Err(err) = self.heap.limits().check(key.len(), val.len()) {
+            return err;
+        }
+
+        if val.is_empty() {
+            return RpcStatus::StatusMalformedRequest;
+        }
+
+        match self.heap.object(tenant_id, table_id, key, val) {
+            Some((key, obj)) => {
+                self.heap.store(&table, key, obj);
+                RpcStatus::StatusOk
+            }
+
+            None => RpcStatus::StatusInternalError,
+        }
+    }
+
+    /// Removes an object exactly like a native delete() would, for requests that don't arrive
+    /// as RPCs (ex: memcache::Adapter).
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant_id`: The identifier of the tenant the table belongs to.
+    /// * `table_id`:  The identifier of the table to remove the object from.
+    /// * `key`:       The object's key.
+    ///
+    /// # Return
+    ///
+    /// StatusOk if the object was removed, StatusObjectDoesNotExist if there was no such object,
+    /// or the status a native delete() would have failed with.
+    pub fn delete_value(&self, tenant_id: TenantId, table_id: TableId, key: &[u8]) -> RpcStatus {
+        delete_object(&self.heap, self.get_tenant(tenant_id), table_id, key)
+    }
+
+    /// Writes an object exactly like a native cas() would, for requests that don't arrive as
+    /// RPCs (ex: memcache::Adapter).
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant_id`: The identifier of the tenant the table belongs to.
+    /// * `table_id`:  The identifier of the table to write the object to.
+    /// * `key`:       The object's key.
+    /// * `expected`:  The value expected under the key, or None if the key is expected to not
+    ///                exist.
+    /// * `val`:       The object's new value.
+    ///
+    /// # Return
+    ///
+    /// StatusOk if the object was written, StatusCompareFailed if the value under the key was
+    /// not the expected one, or the status a native cas() would have failed with.
+    pub fn cas_value(
+        &self,
+        tenant_id: TenantId,
+        table_id: TableId,
+        key: &[u8],
+        expected: Option<&[u8]>,
+        val: &[u8],
+    ) -> RpcStatus {
         let tenant = self.get_tenant(tenant_id);
-        let alloc: *const Allocator = &self.heap;
+        cas_object(&self.heap, tenant, tenant_id, table_id, key, expected, val)
+    }
 
-        // Create a generator for this request.
-        let gen = Box::new(move || {
-            let mut status: RpcStatus = RpcStatus::StatusTenantDoesNotExist;
-            let optype: u8 = 0x1; // OpType::SandstormRead
+    /// Returns a page of the records in a table, for the dump() RPC. Records are dumped bucket
+    /// by bucket, and in key order within a bucket, so that a dump can be continued off the
+    /// bucket and key of the last record it returned. Every record on a page comes from the same
+    /// bucket. Records written while a table is being dumped may or may not be returned.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant_id`: The identifier of the tenant the table belongs to.
+    /// * `table_id`:  The identifier of the table to be dumped.
+    /// * `bucket`:    The bucket to continue the dump in. 0 to start it.
+    /// * `after`:     Only records in `bucket` with keys larger than this one are returned, along
+    ///                with records in later buckets. Empty to start the dump.
+    /// * `budget`:    The number of bytes of records on the page. A record that does not fit in
+    ///                the budget by itself is still returned, so that the dump makes progress.
+    ///
+    /// # Return
+    ///
+    /// The records packed by rpc::append_kv(), the number of records, and the bucket they came
+    /// from. No records once the whole table has been dumped. Otherwise, the status a get() on
+    /// the table would have failed with.
+    pub fn dump_table(
+        &self,
+        tenant_id: TenantId,
+        table_id: TableId,
+        bucket: u32,
+        after: &[u8],
+        budget: usize,
+    ) -> Result<(Vec<u8>, u32, u32), RpcStatus> {
+        let table = self.resolve_table(tenant_id, table_id)?;
+
+        let mut after = after;
+        for bucket in (bucket as usize)..N_BUCKETS {
+            // The bucket is sorted on every page, so that the dump can be continued off a key
+            // without holding anything at the server in between requests.
+            let mut objects: Vec<(Bytes, Bytes)> = table
+                .bucket_entries(bucket)
+                .into_iter()
+                .filter_map(|entry| self.heap.resolve(entry.value))
+                .filter(|&(ref key, _)| &key[..] > after)
+                .collect();
+            if objects.is_empty() {
+                after = &[];
+                continue;
+            }
+            objects.sort_by(|a, b| a.0.cmp(&b.0));
 
-            let outcome =
-                // Check if the tenant exists. If it does, then check if the
-                // table exists, and update the status of the rpc.
-                tenant.and_then(| tenant | {
-                                status = RpcStatus::StatusTableDoesNotExist;
-                                tenant.get_table(table_id)
-                            })
-                // If the table exists, lookup the provided key, and update
-                // the status of the rpc.
-                .and_then(| table | {
-                                status = RpcStatus::StatusObjectDoesNotExist;
-                                let (key, _) = req.get_payload().split_at(key_length as usize);
-                                table.get(key)
-                            })
-                // If the lookup succeeded, obtain the value, and update the
-                // status of the rpc.
-                .and_then(| entry | {
-                                status = RpcStatus::StatusInternalError;
-                                let alloc: &Allocator = accessor(alloc);
-                                Some((alloc.resolve(entry.value), entry.version))
-                            })
-                // If the value was obtained, then write to the response packet
-                // and update the status of the rpc.
-                .and_then(| (opt, version) | {
-                    if let Some(opt) = opt {
-                                let (k, value) = &opt;
-                                let mut result = Ok(());
-                                status = RpcStatus::StatusInternalError;
-                                if req_generator == GetGenerator::SandstormExtension {
-                                    let _result = res.add_to_payload_tail(1, pack(&optype));
-                                    let _ = res.add_to_payload_tail(size_of::<Version>(), &unsafe { transmute::<Version, [u8; 8]>(version) });
-                                    result = res.add_to_payload_tail(k.len(), &k[..]);
-                                }
-                                match result {
-                                    Ok(()) => {
-                                        res.add_to_payload_tail(value.len(), &value[..]).ok()
-                                    }
+            let mut buf = Vec::new();
+            let mut num = 0;
+            for &(ref key, ref val) in objects.iter() {
+                if num > 0 && buf.len() + rpc::KV_OVERHEAD + key.len() + val.len() > budget {
+                    break;
+                }
+                rpc::append_kv(&mut buf, key, val);
+                num += 1;
+            }
 
-                                    Err(_) => {
-                                        Some(())
-                                    }
-                                }
-                            } else {
-                                None
-                            }
-                            })
-                // If the value was written to the response payload,
-                // update the status of the rpc.
-                .and_then(| _ | {
-                                status = RpcStatus::StatusOk;
-                                Some(())
-                            });
+            return Ok((buf, num, bucket as u32));
+        }
+
+        Ok((Vec::new(), 0, N_BUCKETS as u32))
+    }
+
+    /// Returns a page of the records in a table in key order, exactly like the scan() RPC does.
+    /// Records written while a table is being scanned may or may not be returned, and
+    /// quarantined objects are skipped.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant_id`: The identifier of the tenant the table belongs to.
+    /// * `table_id`:  The identifier of the table to be scanned.
+    /// * `start`:     The key the scan starts from. Empty to start at the first key.
+    /// * `after`:     If true, the record under `start` itself is not returned.
+    /// * `max`:       The most records on the page. 0 for as many as fit in the budget.
+    /// * `budget`:    The number of bytes of records and continuation token on the page.
+    ///
+    /// # Return
+    ///
+    /// The records packed by rpc::append_kv(), the number of records, the number of records
+    /// passed over because each was too large to fit in the budget by itself, and the
+    /// continuation token; the key of the last record read, or empty once the scan has reached
+    /// the end of the table. Otherwise, the status a get() on the table would have failed with,
+    /// or StatusValueTooLarge if not even the key of the first record fits in the budget.
+    pub fn scan_table(
+        &self,
+        tenant_id: TenantId,
+        table_id: TableId,
+        start: &[u8],
+        after: bool,
+        max: u32,
+        budget: usize,
+    ) -> Result<(Vec<u8>, u32, u32, Vec<u8>), RpcStatus> {
+        let table = self.resolve_table(tenant_id, table_id)?;
+
+        let mut page = ScanPage::new(start, after, max, budget);
+        while !page.step(&table, &self.heap)? {}
+        Ok(page.finish())
+    }
+
+    /// Writes a batch of records to a table, for the multiput() RPC. Each record is written
+    /// exactly like a native put() would, one at a time, so that a large batch (like one from a
+    /// migration) never holds up requests to the table for longer than a single put(). Readers
+    /// may see part of a batch while it is being written.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant_id`: The identifier of the tenant the table belongs to.
+    /// * `table_id`:  The identifier of the table to write the records to.
+    /// * `records`:   The records, packed by rpc::append_kv().
+    /// * `num`:       The number of records packed into `records`.
+    ///
+    /// # Return
+    ///
+    /// StatusOk if the records were written, or the status a native put() would have failed
+    /// with. StatusMalformedRequest if a record could not be unpacked, or has an empty key or
+    /// value, and StatusKeyTooLarge or StatusValueTooLarge if a record is over the server's
+    /// limits.
+    pub fn put_records(
+        &self,
+        tenant_id: TenantId,
+        table_id: TableId,
+        records: &[u8],
+        num: u32,
+    ) -> RpcStatus {
+        let table = match self.get_tenant(tenant_id) {
+            Some(tenant) => match tenant.writable_native_table(table_id) {
+                Ok(table) => table,
+                Err(err) => return err,
+            },
+
+            None => return RpcStatus::StatusTenantDoesNotExist,
+        };
+
+        let records = match rpc::parse_kvs(records, num) {
+            Some(records) => records,
+            None => return RpcStatus::StatusMalformedRequest,
+        };
+
+        // Every object is allocated before any is stored, so that a bad record or running out
+        // of memory doesn't leave the batch half written.
+        let mut objects = Vec::with_capacity(records.len());
+        for &(key, val) in records.iter() {
+            if let Err(err) = self.heap.limits().check(key.len(), val.len()) {
+                return err;
+            }
+
+            if key.is_empty() || val.is_empty() {
+                return RpcStatus::StatusMalformedRequest;
+            }
+
+            match self.heap.object(tenant_id, table_id, key, val) {
+                Some(object) => objects.push(object),
+                None => return RpcStatus::StatusInternalError,
+            }
+        }
+
+        if table.read_only() {
+            return RpcStatus::StatusReadOnlyTable;
+        }
+
+        for (key, obj) in objects.into_iter() {
+            self.heap.store(&table, key, obj);
+        }
+        RpcStatus::StatusOk
+    }
+
+    /// Saves every record in a table to a file of records (refer to kvformat), as a checkpoint
+    /// the table can be restored from with restore_table(). The table is dumped page by page
+    /// the way the dump() RPC dumps it, so records written while it is being saved may or may
+    /// not make it onto the checkpoint.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant_id`: The identifier of the tenant the table belongs to.
+    /// * `table_id`:  The identifier of the table to be saved.
+    /// * `out`:       Where the checkpoint is written to.
+    ///
+    /// # Return
+    ///
+    /// The output and the number of records saved, or the error the checkpoint failed with. A
+    /// table that can't be read fails with the status a dump() would have been refused with.
+    pub fn checkpoint_table<W: Write>(
+        &self,
+        tenant_id: TenantId,
+        table_id: TableId,
+        out: W,
+    ) -> io::Result<(W, u64)> {
+        let mut header = kvformat::Header::new(tenant_id, table_id);
+        header.flags = FLAG_DUMP_ORDER;
+        let mut writer = Writer::new(out, header)?;
+
+        let (mut bucket, mut after) = (0, Vec::new());
+        loop {
+            let (page, num, b) = self
+                .dump_table(tenant_id, table_id, bucket, &after, CHECKPOINT_BUDGET)
+                .map_err(|status| {
+                    let why = format!("table {} can't be read: {:?}", table_id, status);
+                    io::Error::new(io::ErrorKind::Other, why)
+                })?;
+            if num == 0 {
+                break;
+            }
+
+            let records = rpc::parse_kvs(&page, num).expect("Failed to parse a dumped page.");
+            for &(key, val) in records.iter() {
+                writer.write(key, val)?;
+            }
+            bucket = b;
+            after = records[records.len() - 1].0.to_vec();
+        }
 
-            match outcome {
-                // The RPC completed successfully. Update the response header with
-                // the status and value length.
-                Some(()) => {
-                    let val_len = res.get_payload().len() as u32;
+        let records = writer.records();
+        Ok((writer.finish()?, records))
+    }
 
-                    let hdr: &mut GetResponse = res.get_mut_header();
-                    hdr.value_length = val_len;
-                    hdr.common_header.status = status;
+    /// Restores the records saved on a checkpoint by checkpoint_table() into a table, or loads
+    /// any other file of records into it. Records are written in batches as they are read, like
+    /// a multiput() would write them, so a checkpoint that turns out corrupt or truncated part
+    /// way through leaves the records before the bad one written.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant_id`: The identifier of the tenant the table belongs to.
+    /// * `table_id`:  The identifier of the table to write the records to. It need not be the
+    ///                table the checkpoint was saved from.
+    /// * `input`:     Where the checkpoint is read from.
+    ///
+    /// # Return
+    ///
+    /// The number of records written, or the error the restore failed with. A batch refused by
+    /// the table fails with the status put_records() refused it with.
+    pub fn restore_table<R: Read>(
+        &self,
+        tenant_id: TenantId,
+        table_id: TableId,
+        input: R,
+    ) -> io::Result<u64> {
+        let mut reader = Reader::new(input, OnCorrupt::Abort)?;
+        let (mut batch, mut num, mut restored) = (Vec::new(), 0, 0);
+
+        loop {
+            let more = match reader.next()? {
+                Some((key, val)) => {
+                    rpc::append_kv(&mut batch, key, val);
+                    num += 1;
+                    true
                 }
 
-                // The RPC failed. Update the response header with the status.
-                None => {
-                    res.get_mut_header().common_header.status = status;
+                None => false,
+            };
+
+            if num == RESTORE_BATCH || (!more && num > 0) {
+                match self.put_records(tenant_id, table_id, &batch, num) {
+                    RpcStatus::StatusOk => restored += num as u64,
+                    status => {
+                        let why = format!("table {} refused records: {:?}", table_id, status);
+                        return Err(io::Error::new(io::ErrorKind::Other, why));
+                    }
                 }
+                batch.clear();
+                num = 0;
             }
 
-            // Deparse request and response packets down to UDP, and return from the generator.
-            return Some((
+            if !more {
+                return Ok(restored);
+            }
+        }
+    }
+
+    /// Returns the accounting of the writes made to a table, for the write_stats() RPC.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant_id`: The identifier of the tenant the table belongs to.
+    /// * `table_id`:  The identifier of the table.
+    /// * `query`:     WRITE_STATS_TOTALS for the totals of each kind of write,
+    ///                WRITE_STATS_HOT_KEYS for the keys with the highest physical write volume,
+    ///                WRITE_STATS_DEDUP for the counters of the table's dedup index,
+    ///                WRITE_STATS_SIZING for how the table's index is sized,
+    ///                WRITE_STATS_CACHE for the counters of a cache-mode table,
+    ///                WRITE_STATS_HOT_ARENA for the counters of the server's hot arena, or
+    ///                WRITE_STATS_EPOCHS for the epochs of the table and it's tenant.
+    ///
+    /// # Return
+    ///
+    /// The entries packed by rpc::encode_write_totals(), rpc::encode_hot_keys(),
+    /// rpc::encode_dedup_stats(), rpc::encode_sizing(), rpc::encode_cache_stats(),
+    /// rpc::encode_hot_stats() or rpc::encode_epochs(), and the number of entries. Otherwise,
+    /// the status a get() on the table would have failed with, StatusOperationDisabled if hot keys were asked for and are
+    /// not being tracked, dedup counters were asked for and the table does not share values,
+    /// cache counters were asked for and the table is not in cache mode, or arena counters were
+    /// asked for and there is no arena, or StatusMalformedRequest if the query is unknown.
+    pub fn write_stats(
+        &self,
+        tenant_id: TenantId,
+        table_id: TableId,
+        query: u8,
+    ) -> Result<(Vec<u8>, u32), RpcStatus> {
+        let table = self.resolve_table(tenant_id, table_id)?;
+        match query {
+            WRITE_STATS_TOTALS => {
+                let totals = table.writes().totals();
+                Ok((rpc::encode_write_totals(&totals), totals.len() as u32))
+            }
+
+            WRITE_STATS_HOT_KEYS => {
+                if amplify::hot_keys() == 0 {
+                    return Err(RpcStatus::StatusOperationDisabled);
+                }
+                let writes = table.writes();
+                let (keys, decay) = (writes.hot_keys(), writes.hot_keys_decay());
+                Ok(rpc::encode_hot_keys(&keys, &decay, WRITE_STATS_BUDGET))
+            }
+
+            WRITE_STATS_DEDUP => match table.dedup_stats() {
+                Some(stats) => Ok((rpc::encode_dedup_stats(&stats), 1)),
+                None => Err(RpcStatus::StatusOperationDisabled),
+            },
+
+            WRITE_STATS_SIZING => Ok((rpc::encode_sizing(&table.sizing()), 1)),
+
+            WRITE_STATS_CACHE => match table.cache_stats() {
+                Some(stats) => Ok((rpc::encode_cache_stats(&stats), 1)),
+                None => Err(RpcStatus::StatusOperationDisabled),
+            },
+
+            WRITE_STATS_HOT_ARENA => match self.heap.hot_stats() {
+                Some(stats) => Ok((rpc::encode_hot_stats(&stats), 1)),
+                None => Err(RpcStatus::StatusOperationDisabled),
+            },
+
+            WRITE_STATS_EPOCHS => {
+                let stats = EpochStats {
+                    server: self.epochs.server(),
+                    tenant: self.epochs.tenant(tenant_id).unwrap_or(0),
+                    table: table.epoch(),
+                };
+                Ok((rpc::encode_epochs(&stats), 1))
+            }
+
+            _ => Err(RpcStatus::StatusMalformedRequest),
+        }
+    }
+
+    /// Returns the time the server took to respond to requests, merged across it's cores.
+    ///
+    /// # Arguments
+    ///
+    /// * `query`:  What is asked for. Either LATENCY_STATS_OPCODES for the percentiles of every
+    ///             opcode, LATENCY_STATS_EXTENSIONS for the percentiles of every extension, or
+    ///             LATENCY_STATS_HISTOGRAM for the histogram of an opcode or extension.
+    /// * `opcode`: The opcode whose histogram is asked for.
+    /// * `name`:   The extension whose histogram is asked for. Empty for an opcode's histogram.
+    ///
+    /// # Return
+    ///
+    /// The entries packed as on a latency_stats() response, and the number of them. An error if
+    /// the query is unknown.
+    pub fn latency_stats(
+        &self,
+        query: u8,
+        opcode: u8,
+        name: &[u8],
+    ) -> Result<(Vec<u8>, u32), RpcStatus> {
+        let hz = cycles::cycles_per_second();
+        match query {
+            LATENCY_STATS_OPCODES => {
+                let ops: Vec<(u8, LatencySummary)> = self
+                    .latencies
+                    .opcodes()
+                    .iter()
+                    .map(|&(op, ref hist)| (op, LatencySummary::new(hist, hz)))
+                    .collect();
+                Ok((rpc::encode_latency_summaries(&ops), ops.len() as u32))
+            }
+
+            LATENCY_STATS_EXTENSIONS => {
+                let exts: Vec<(Vec<u8>, LatencySummary)> = self
+                    .latencies
+                    .extensions()
+                    .into_iter()
+                    .map(|(name, hist)| (name, LatencySummary::new(&hist, hz)))
+                    .collect();
+                Ok(rpc::encode_ext_latencies(&exts, LATENCY_STATS_BUDGET))
+            }
+
+            LATENCY_STATS_HISTOGRAM => match name.is_empty() {
+                true => Ok(rpc::encode_latency_histogram(
+                    &self.latencies.opcode(opcode),
+                )),
+                false => Ok(rpc::encode_latency_histogram(
+                    &self.latencies.extension(name),
+                )),
+            },
+
+            _ => Err(RpcStatus::StatusMalformedRequest),
+        }
+    }
+
+    /// Handles a get() RPC request whose tenant and table were already resolved by a call to
+    /// `resolve_table()`. Behaves exactly like `get()` otherwise. The passed in handle keeps the
+    /// table alive even if it is dropped before the generated task runs.
+    ///
+    /// # Arguments
+    ///
+    /// * `req`:   The RPC request packet sent by the client, parsed upto it's UDP header.
+    /// * `res`:   The RPC response packet, with pre-allocated headers upto UDP.
+    /// * `table`: The result of resolving the request's tenant and table.
+    ///
+    /// # Return
+    ///
+    /// A Native task that can be scheduled by the database. In the case of an error, the passed
+    /// in request and response packets are returned with the response status appropriately set.
+    pub fn get_resolved(
+        &self,
+        req: Packet<UdpHeader, EmptyMetadata>,
+        res: Packet<UdpHeader, EmptyMetadata>,
+        table: Result<Arc<Table>, RpcStatus>,
+    ) -> Result<
+        Box<Task>,
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+    > {
+        self.get_task(req, res, Some(table))
+    }
+
+    // Common implementation of get() and get_resolved(). If `table` is None, then the tenant is
+    // looked up here and the table is looked up by the generated task.
+    #[allow(unreachable_code)]
+    #[allow(unused_assignments)]
+    fn get_task(
+        &self,
+        req: Packet<UdpHeader, EmptyMetadata>,
+        res: Packet<UdpHeader, EmptyMetadata>,
+        table: Option<Result<Arc<Table>, RpcStatus>>,
+    ) -> Result<
+        Box<Task>,
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+    > {
+        // First, parse the request packet.
+        let req = req.parse_header::<GetRequest>();
+
+        // Read fields off the request header.
+        let mut tenant_id: TenantId = 0;
+        let mut table_id: TableId = 0;
+        let mut key_length = 0;
+        let mut rpc_id = 0;
+        let mut rpc_stamp = 0;
+        let mut req_generator = GetGenerator::InvalidGenerator;
+        let mut projection = (0, 0);
+        let mut verify = false;
+
+        {
+            let hdr = req.get_header();
+            tenant_id = hdr.common_header.tenant() as TenantId;
+            table_id = hdr.table_id() as TableId;
+            key_length = hdr.key_length();
+            rpc_id = hdr.common_header.id();
+            rpc_stamp = hdr.common_header.stamp();
+            req_generator = hdr.generator.clone();
+            projection = (hdr.value_offset(), hdr.value_length());
+            verify = hdr.common_header.flags & REQUEST_FLAG_VERIFY != 0;
+        }
+
+        // Next, add a header to the response packet. The get() is served off the primary copy
+        // in the table, which meets any staleness budget on the request, so the age on the
+        // response is left at 0 (see stale::serve()).
+        let mut res = res
+            .push_header(&GetResponse::new(
+                rpc_id,
+                rpc_stamp,
+                OpCode::SandstormGetRpc,
+                tenant_id,
+            )).expect("Failed to setup GetResponse");
+
+        // If the payload size is less than the key length, return an error.
+        if req.get_payload().len() < key_length as usize {
+            res.get_mut_header().common_header.status = RpcStatus::StatusMalformedRequest;
+            return Err((
                 req.deparse_header(PACKET_UDP_LEN as usize),
                 res.deparse_header(PACKET_UDP_LEN as usize),
             ));
+        }
+
+        // Lookup the tenant (unless the table was already resolved), and get a handle to the
+        // allocator. Required to avoid capturing a reference to Master in the generator below.
+        let tenant = match table {
+            Some(_) => None,
+            None => self.tenant(tenant_id),
+        };
+        let alloc: *const Allocator = &self.heap;
+
+        // Attach the request to an earlier get() for the same key on this core, or let later
+        // ones attach to it. A verified read cannot share an earlier lookup that did not verify.
+        let coalesce = match self.coalesce && !verify {
+            true => coalesce::join(
+                tenant_id,
+                table_id,
+                &req.get_payload()[..key_length as usize],
+            ),
+            false => None,
+        };
+
+        let op = GetOp {
+            alloc: alloc,
+            tenant: tenant,
+            table: table,
+            table_id: table_id,
+            key_length: key_length,
+            generator: req_generator,
+            projection: projection,
+            verify: if verify { Some(self.quarantine) } else { None },
+            coalesce: coalesce,
+            req: req,
+            res: res,
+        };
+
+        // Return a pooled task if enabled.
+        if self.pooled {
+            return Ok(Pooled::new(Op::Get(op)));
+        }
+
+        // Otherwise, create a generator for this request.
+        let gen = Box::new(move || {
+            return Some(op.execute());
 
             // XXX: This yield is required to get the compiler to compile this closure into a
             // generator. It is unreachable and benign.
@@ -804,6 +3125,7 @@ impl Master {
         });
 
         // Return a native task.
+        pool::count(2);
         return Ok(Box::new(Native::new(TaskPriority::REQUEST, gen)));
     }
 
@@ -831,21 +3153,28 @@ impl Master {
         let mut tenant_id: TenantId = 0;
         let mut table_id: TableId = 0;
         let mut key_length = 0;
+        let mut rpc_id = 0;
         let mut rpc_stamp = 0;
         let mut req_generator = GetGenerator::InvalidGenerator;
+        let mut projection = (0, 0);
+        let mut verify = false;
 
         {
             let hdr = req.get_header();
-            tenant_id = hdr.common_header.tenant as TenantId;
-            table_id = hdr.table_id as TableId;
-            key_length = hdr.key_length;
-            rpc_stamp = hdr.common_header.stamp;
+            tenant_id = hdr.common_header.tenant() as TenantId;
+            table_id = hdr.table_id() as TableId;
+            key_length = hdr.key_length();
+            rpc_id = hdr.common_header.id();
+            rpc_stamp = hdr.common_header.stamp();
             req_generator = hdr.generator.clone();
+            projection = (hdr.value_offset(), hdr.value_length());
+            verify = hdr.common_header.flags & REQUEST_FLAG_VERIFY != 0;
         }
 
         // Next, add a header to the response packet.
         let mut res = res
             .push_header(&GetResponse::new(
+                rpc_id,
                 rpc_stamp,
                 OpCode::SandstormGetRpc,
                 tenant_id,
@@ -862,24 +3191,48 @@ impl Master {
 
         // Lookup the tenant, and get a handle to the allocator. Required to avoid capturing a
         // reference to Master in the generator below.
-        let tenant = self.get_tenant(tenant_id);
+        let tenant = self.tenant(tenant_id);
 
         //let gen = Box::new(move || {
         let mut status: RpcStatus = RpcStatus::StatusTenantDoesNotExist;
+        let mut hint = None;
 
         let outcome =
-                // Check if the tenant exists. If it does, then check if the
-                // table exists, and update the status of the rpc.
-                tenant.and_then(| tenant | {
-                                status = RpcStatus::StatusTableDoesNotExist;
-                                tenant.get_table(table_id)
+                // Check if the tenant exists. If it does, then check if the table
+                // exists and can be read natively, and update the status of the rpc.
+                tenant.and_then(| tenant | match tenant.readable_native_table(table_id) {
+                                Ok(table) => Some(table),
+                                Err(err) => {
+                                    status = err;
+                                    None
+                                }
                             })
                 // If the table exists, lookup the provided key, and update
                 // the status of the rpc.
                 .and_then(| table | {
                                 status = RpcStatus::StatusObjectDoesNotExist;
                                 let (key, _) = req.get_payload().split_at(key_length as usize);
-                                table.get(key)
+                                table.get(key).map(| object | (table, object))
+                            })
+                // Check that the object is not quarantined, and verify it if the request asked
+                // for it. Update the status of the rpc.
+                .and_then(| (table, object) | {
+                                status = RpcStatus::StatusDataCorrupted;
+                                let (key, _) = req.get_payload().split_at(key_length as usize);
+                                if table.is_quarantined(key) {
+                                    return None;
+                                }
+
+                                hint = hint::hint(&table, &object);
+                                self.heap.count_hot(&object.value);
+                                match verify {
+                                    true => integrity::verify_read(&self.heap, tenant_id,
+                                                                   table_id, &table, &object,
+                                                                   self.quarantine)
+                                                .ok()
+                                                .map(| _ | object),
+                                    false => Some(object),
+                                }
                             })
                 // If the lookup succeeded, obtain the value, and update the
                 // status of the rpc.
@@ -887,47 +3240,40 @@ impl Master {
                                 status = RpcStatus::StatusInternalError;
                                 self.heap.resolve(object.value)
                             })
-                // If the value was obtained, then write to the response packet
-                // and update the status of the rpc.
+                // If the value was obtained, then write the part of it covered by the request's
+                // projection to the response packet, along with the length of the whole value.
+                // The record is either appended in full or not at all.
                 .and_then(| (k, value) | {
-                                let mut result = Ok(());
                                 status = RpcStatus::StatusInternalError;
-                                if req_generator == GetGenerator::SandstormExtension {
-                                    result = res.add_to_payload_tail(k.len(), &k[..]);
-                                }
-                                match result {
-                                    Ok(()) => {
-                                        res.add_to_payload_tail(value.len(), &value[..]).ok()
+                                let full = value.len() as u32;
+                                let (offset, length) = projection;
+                                let value = &value[buf::projection(value.len(), offset, length)];
+                                let appended = match req_generator {
+                                    GetGenerator::SandstormExtension => {
+                                        append_record(&mut res, &[&k[..], value])
                                     }
 
-                                    Err(_) => {
+                                    _ => append_record(&mut res, &[value]),
+                                };
+                                match appended {
+                                    true => {
+                                        res.get_mut_header().set_full_length(full);
                                         Some(())
                                     }
+                                    false => None,
                                 }
-                            })
-                // If the value was written to the response payload,
-                // update the status of the rpc.
-                .and_then(| _ | {
-                                status = RpcStatus::StatusOk;
-                                Some(())
                             });
 
-        match outcome {
-            // The RPC completed successfully. Update the response header with
-            // the status and value length.
-            Some(()) => {
-                let val_len = res.get_payload().len() as u32;
+        // If the value was written to the response payload, update the status of the rpc.
+        if outcome.is_some() {
+            status = RpcStatus::StatusOk;
+        }
 
-                let hdr: &mut GetResponse = res.get_mut_header();
-                hdr.value_length = val_len;
-                hdr.common_header.status = status;
-            }
-
-            // The RPC failed. Update the response header with the status.
-            None => {
-                res.get_mut_header().common_header.status = status;
-            }
-        }
+        // Update the response header with the status and a value length derived from the
+        // payload. If the RPC failed, the payload is dropped and the value length is zero. The
+        // caching hint is only put on responses that succeeded.
+        finish_get_response(&mut res, status);
+        hint::apply(res.get_mut_header(), hint);
 
         return Ok((
             req.deparse_header(PACKET_UDP_LEN as usize),
@@ -969,19 +3315,22 @@ impl Master {
         let mut tenant_id: TenantId = 0;
         let mut table_id: TableId = 0;
         let mut key_length = 0;
+        let mut rpc_id = 0;
         let mut rpc_stamp = 0;
 
         {
             let hdr = req.get_header();
-            tenant_id = hdr.common_header.tenant as TenantId;
-            table_id = hdr.table_id as TableId;
-            key_length = hdr.key_length;
-            rpc_stamp = hdr.common_header.stamp;
+            tenant_id = hdr.common_header.tenant() as TenantId;
+            table_id = hdr.table_id() as TableId;
+            key_length = hdr.key_length();
+            rpc_id = hdr.common_header.id();
+            rpc_stamp = hdr.common_header.stamp();
         }
 
         // Next, write a header into the response packet.
         let mut res = res
             .push_header(&PutResponse::new(
+                rpc_id,
                 rpc_stamp,
                 OpCode::SandstormPutRpc,
                 tenant_id,
@@ -998,51 +3347,27 @@ impl Master {
 
         // Lookup the tenant, and get a handle to the allocator. Required to avoid capturing a
         // reference to Master in the generator below.
-        let tenant = self.get_tenant(tenant_id);
+        let tenant = self.tenant(tenant_id);
         let alloc: *const Allocator = &self.heap;
 
-        // Create a generator for this request.
-        let gen = Box::new(move || {
-            let mut status: RpcStatus = RpcStatus::StatusTenantDoesNotExist;
-
-            // If the tenant exists, check if it has a table with the given id,
-            // and update the status of the rpc.
-            let outcome = tenant.and_then(|tenant| {
-                status = RpcStatus::StatusTableDoesNotExist;
-                tenant.get_table(table_id)
-            });
-
-            // If the table exists, update the status of the rpc, and allocate an
-            // object.
-            if let Some(table) = outcome {
-                // Get a reference to the key and value.
-                status = RpcStatus::StatusMalformedRequest;
-                let (key, val) = req.get_payload().split_at(key_length as usize);
-
-                // If there is a value, then write it in.
-                if val.len() > 0 {
-                    status = RpcStatus::StatusInternalError;
-                    let alloc: &Allocator = accessor(alloc);
-                    let _result = alloc.object(tenant_id, table_id, key, val)
-                                    // If the allocation succeeds, update the
-                                    // status of the rpc, and insert the object
-                                    // into the table.
-                                    .and_then(| (key, obj) | {
-                                        status = RpcStatus::StatusOk;
-                                        table.put(key, obj);
-                                        Some(())
-                                    });
-                }
-            }
-
-            // Update the response header.
-            res.get_mut_header().common_header.status = status;
+        let op = PutOp {
+            alloc: alloc,
+            tenant: tenant,
+            tenant_id: tenant_id,
+            table_id: table_id,
+            key_length: key_length,
+            req: req,
+            res: res,
+        };
+
+        // Return a pooled task if enabled.
+        if self.pooled {
+            return Ok(Pooled::new(Op::Put(op)));
+        }
 
-            // Deparse request and response packets to UDP, and return from the generator.
-            return Some((
-                req.deparse_header(PACKET_UDP_LEN as usize),
-                res.deparse_header(PACKET_UDP_LEN as usize),
-            ));
+        // Otherwise, create a generator for this request.
+        let gen = Box::new(move || {
+            return Some(op.execute());
 
             // XXX: This yield is required to get the compiler to compile this closure into a
             // generator. It is unreachable and benign.
@@ -1050,6 +3375,7 @@ impl Master {
         });
 
         // Create and return a native task.
+        pool::count(2);
         return Ok(Box::new(Native::new(TaskPriority::REQUEST, gen)));
     }
 
@@ -1077,19 +3403,22 @@ impl Master {
         let mut tenant_id: TenantId = 0;
         let mut table_id: TableId = 0;
         let mut key_length = 0;
+        let mut rpc_id = 0;
         let mut rpc_stamp = 0;
 
         {
             let hdr = req.get_header();
-            tenant_id = hdr.common_header.tenant as TenantId;
-            table_id = hdr.table_id as TableId;
-            key_length = hdr.key_length;
-            rpc_stamp = hdr.common_header.stamp;
+            tenant_id = hdr.common_header.tenant() as TenantId;
+            table_id = hdr.table_id() as TableId;
+            key_length = hdr.key_length();
+            rpc_id = hdr.common_header.id();
+            rpc_stamp = hdr.common_header.stamp();
         }
 
         // Next, write a header into the response packet.
         let mut res = res
             .push_header(&PutResponse::new(
+                rpc_id,
                 rpc_stamp,
                 OpCode::SandstormPutRpc,
                 tenant_id,
@@ -1106,16 +3435,19 @@ impl Master {
 
         // Lookup the tenant, and get a handle to the allocator. Required to avoid capturing a
         // reference to Master in the generator below.
-        let tenant = self.get_tenant(tenant_id);
+        let tenant = self.tenant(tenant_id);
 
         //let gen = Box::new(move || {
         let mut status: RpcStatus = RpcStatus::StatusTenantDoesNotExist;
 
-        // If the tenant exists, check if it has a table with the given id,
-        // and update the status of the rpc.
-        let outcome = tenant.and_then(|tenant| {
-            status = RpcStatus::StatusTableDoesNotExist;
-            tenant.get_table(table_id)
+        // If the tenant exists, check if it has a table with the given id
+        // that it can write to natively, and update the status of the rpc.
+        let outcome = tenant.and_then(|tenant| match tenant.writable_native_table(table_id) {
+            Ok(table) => Some(table),
+            Err(err) => {
+                status = err;
+                None
+            }
         });
 
         // If the table exists, update the status of the rpc, and allocate an
@@ -1125,8 +3457,10 @@ impl Master {
             status = RpcStatus::StatusMalformedRequest;
             let (key, val) = req.get_payload().split_at(key_length as usize);
 
-            // If there is a value, then write it in.
-            if val.len() > 0 {
+            // If the object is within the server's limits and there is a value, then write it in.
+            if let Err(err) = self.heap.limits().check(key.len(), val.len()) {
+                status = err;
+            } else if val.len() > 0 {
                 status = RpcStatus::StatusInternalError;
                 let _result = self.heap.object(tenant_id, table_id, key, val)
                                     // If the allocation succeeds, update the
@@ -1134,7 +3468,7 @@ impl Master {
                                     // into the table.
                                     .and_then(| (key, obj) | {
                                         status = RpcStatus::StatusOk;
-                                        table.put(key, obj);
+                                        self.heap.store(&table, key, obj);
                                         Some(())
                                     });
             }
@@ -1149,6 +3483,273 @@ impl Master {
         ));
     }
 
+    /// Handles the delete() RPC request.
+    ///
+    /// If the issuing tenant is valid, the key on the request is removed from one of it's
+    /// tables, if both the table and the key exist.
+    ///
+    /// # Arguments
+    ///
+    /// * `req`: The RPC request packet sent by the client, parsed upto it's UDP header.
+    /// * `res`: The RPC response packet, with pre-allocated headers upto UDP.
+    ///
+    /// # Return
+    ///
+    /// A Native task that can be scheduled by the database. In the case of an error, the passed
+    /// in request and response packets are returned with the response status appropriately set.
+    #[allow(unreachable_code)]
+    fn delete(
+        &self,
+        req: Packet<UdpHeader, EmptyMetadata>,
+        res: Packet<UdpHeader, EmptyMetadata>,
+    ) -> Result<
+        Box<Task>,
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+    > {
+        let (req, mut res) = self.parse_delete(req, res)?;
+
+        // Lookup the tenant, and get a handle to the allocator. Required to avoid capturing a
+        // reference to Master in the generator below.
+        let tenant = self.tenant(req.get_header().common_header.tenant());
+        let alloc: *const Allocator = &self.heap;
+        let gen = Box::new(move || {
+            let status = {
+                let hdr = req.get_header();
+                let key = &req.get_payload()[..hdr.key_length() as usize];
+                delete_object(accessor(alloc), tenant, hdr.table_id(), key)
+            };
+            res.get_mut_header().common_header.status = status;
+
+            return Some((
+                req.deparse_header(PACKET_UDP_LEN as usize),
+                res.deparse_header(PACKET_UDP_LEN as usize),
+            ));
+
+            // XXX: This yield is required to get the compiler to compile this closure into a
+            // generator. It is unreachable and benign.
+            yield 0;
+        });
+
+        Ok(Box::new(Native::new(TaskPriority::REQUEST, gen)))
+    }
+
+    // This function processes delete() requests without creating a generator.
+    fn delete_native(
+        &self,
+        req: Packet<UdpHeader, EmptyMetadata>,
+        res: Packet<UdpHeader, EmptyMetadata>,
+    ) -> Result<
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+    > {
+        let (req, mut res) = self.parse_delete(req, res)?;
+        let status = {
+            let hdr = req.get_header();
+            let key = &req.get_payload()[..hdr.key_length() as usize];
+            let tenant = self.tenant(hdr.common_header.tenant());
+            delete_object(&self.heap, tenant, hdr.table_id(), key)
+        };
+        res.get_mut_header().common_header.status = status;
+
+        return Ok((
+            req.deparse_header(PACKET_UDP_LEN as usize),
+            res.deparse_header(PACKET_UDP_LEN as usize),
+        ));
+    }
+
+    // Parses a delete() request, and pushes a header onto it's response. If the payload is
+    // shorter than the key, the packets are returned with StatusMalformedRequest on the response.
+    fn parse_delete(
+        &self,
+        req: Packet<UdpHeader, EmptyMetadata>,
+        res: Packet<UdpHeader, EmptyMetadata>,
+    ) -> Result<
+        (
+            Packet<DeleteRequest, EmptyMetadata>,
+            Packet<DeleteResponse, EmptyMetadata>,
+        ),
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+    > {
+        if req.get_payload().len() < size_of::<DeleteRequest>() {
+            return Err((req, res));
+        }
+
+        // Read fields off the request header.
+        let req = req.parse_header::<DeleteRequest>();
+        let (tenant, id, stamp, key_length) = {
+            let hdr = req.get_header();
+            (
+                hdr.common_header.tenant(),
+                hdr.common_header.id(),
+                hdr.common_header.stamp(),
+                hdr.key_length() as usize,
+            )
+        };
+
+        let mut res = res
+            .push_header(&DeleteResponse::new(id, stamp, tenant))
+            .expect("Failed to push DeleteResponse");
+
+        // If the payload size is less than the key length, return an error.
+        if req.get_payload().len() < key_length {
+            res.get_mut_header().common_header.status = RpcStatus::StatusMalformedRequest;
+            return Err((
+                req.deparse_header(PACKET_UDP_LEN as usize),
+                res.deparse_header(PACKET_UDP_LEN as usize),
+            ));
+        }
+
+        Ok((req, res))
+    }
+
+    /// Handles the cas() RPC request.
+    ///
+    /// If the issuing tenant is valid, a new key-value pair is allocated, and written to a table
+    /// if it exists and the value under the key is the one expected by the request.
+    ///
+    /// # Arguments
+    ///
+    /// * `req`: The RPC request packet sent by the client, parsed upto it's UDP header.
+    /// * `res`: The RPC response packet, with pre-allocated headers upto UDP.
+    ///
+    /// # Return
+    ///
+    /// A Native task that can be scheduled by the database. In the case of an error, the passed
+    /// in request and response packets are returned with the response status appropriately set.
+    #[allow(unreachable_code)]
+    fn cas(
+        &self,
+        req: Packet<UdpHeader, EmptyMetadata>,
+        res: Packet<UdpHeader, EmptyMetadata>,
+    ) -> Result<
+        Box<Task>,
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+    > {
+        let (req, mut res) = self.parse_cas(req, res)?;
+
+        // Lookup the tenant, and get a handle to the allocator. Required to avoid capturing a
+        // reference to Master in the generator below.
+        let tenant_id = req.get_header().common_header.tenant() as TenantId;
+        let tenant = self.tenant(tenant_id);
+        let alloc: *const Allocator = &self.heap;
+        let gen = Box::new(move || {
+            let status = {
+                let hdr = req.get_header();
+                let (key, expected, val) = split_cas(hdr, req.get_payload());
+                let heap = accessor(alloc);
+                cas_object(heap, tenant, tenant_id, hdr.table_id(), key, expected, val)
+            };
+            res.get_mut_header().common_header.status = status;
+
+            return Some((
+                req.deparse_header(PACKET_UDP_LEN as usize),
+                res.deparse_header(PACKET_UDP_LEN as usize),
+            ));
+
+            // XXX: This yield is required to get the compiler to compile this closure into a
+            // generator. It is unreachable and benign.
+            yield 0;
+        });
+
+        Ok(Box::new(Native::new(TaskPriority::REQUEST, gen)))
+    }
+
+    // This function processes cas() requests without creating a generator.
+    fn cas_native(
+        &self,
+        req: Packet<UdpHeader, EmptyMetadata>,
+        res: Packet<UdpHeader, EmptyMetadata>,
+    ) -> Result<
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+    > {
+        let (req, mut res) = self.parse_cas(req, res)?;
+        let status = {
+            let hdr = req.get_header();
+            let (key, expected, val) = split_cas(hdr, req.get_payload());
+            let tenant_id = hdr.common_header.tenant() as TenantId;
+            let tenant = self.tenant(tenant_id);
+            let table_id = hdr.table_id();
+            cas_object(&self.heap, tenant, tenant_id, table_id, key, expected, val)
+        };
+        res.get_mut_header().common_header.status = status;
+
+        return Ok((
+            req.deparse_header(PACKET_UDP_LEN as usize),
+            res.deparse_header(PACKET_UDP_LEN as usize),
+        ));
+    }
+
+    // Parses a cas() request, and pushes a header onto it's response. If the payload is shorter
+    // than the key and expected value, or the request expects both a value and the key to be
+    // absent, the packets are returned with StatusMalformedRequest on the response.
+    fn parse_cas(
+        &self,
+        req: Packet<UdpHeader, EmptyMetadata>,
+        res: Packet<UdpHeader, EmptyMetadata>,
+    ) -> Result<
+        (
+            Packet<CasRequest, EmptyMetadata>,
+            Packet<CasResponse, EmptyMetadata>,
+        ),
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+    > {
+        if req.get_payload().len() < size_of::<CasRequest>() {
+            return Err((req, res));
+        }
+
+        // Read fields off the request header.
+        let req = req.parse_header::<CasRequest>();
+        let (tenant, id, stamp, prefix, absent) = {
+            let hdr = req.get_header();
+            (
+                hdr.common_header.tenant(),
+                hdr.common_header.id(),
+                hdr.common_header.stamp(),
+                hdr.key_length() as usize + hdr.expected_length() as usize,
+                hdr.flags & CAS_FLAG_EXPECT_ABSENT != 0 && hdr.expected_length() != 0,
+            )
+        };
+
+        let mut res = res
+            .push_header(&CasResponse::new(id, stamp, tenant))
+            .expect("Failed to push CasResponse");
+
+        if req.get_payload().len() < prefix || absent {
+            res.get_mut_header().common_header.status = RpcStatus::StatusMalformedRequest;
+            return Err((
+                req.deparse_header(PACKET_UDP_LEN as usize),
+                res.deparse_header(PACKET_UDP_LEN as usize),
+            ));
+        }
+
+        Ok((req, res))
+    }
+
     /// Handles the multiget() RPC request.
     ///
     /// If issued by a valid tenant for a valid table, lookups up a list of keys and returns
@@ -1184,20 +3785,25 @@ impl Master {
         let mut table_id: TableId = 0;
         let mut key_length = 0;
         let mut num_keys = 0;
+        let mut flags = 0;
+        let mut rpc_id = 0;
         let mut rpc_stamp = 0;
 
         {
             let hdr = req.get_header();
-            tenant_id = hdr.common_header.tenant as TenantId;
-            table_id = hdr.table_id as TableId;
-            key_length = hdr.key_len;
-            num_keys = hdr.num_keys;
-            rpc_stamp = hdr.common_header.stamp;
+            tenant_id = hdr.common_header.tenant() as TenantId;
+            table_id = hdr.table_id() as TableId;
+            key_length = hdr.key_len();
+            num_keys = hdr.num_keys();
+            flags = hdr.flags;
+            rpc_id = hdr.common_header.id();
+            rpc_stamp = hdr.common_header.stamp();
         }
 
         // Next, add a header to the response packet.
         let mut res = res
             .push_header(&MultiGetResponse::new(
+                rpc_id,
                 rpc_stamp,
                 OpCode::SandstormMultiGetRpc,
                 tenant_id,
@@ -1215,7 +3821,7 @@ impl Master {
 
         // Lookup the tenant, and get a handle to the allocator. Required to avoid capturing a
         // reference to Master in the generator below.
-        let tenant = self.get_tenant(tenant_id);
+        let tenant = self.tenant(tenant_id);
         let alloc: *const Allocator = &self.heap;
 
         // Create a generator for this request.
@@ -1224,59 +3830,80 @@ impl Master {
             let mut status: RpcStatus = RpcStatus::StatusTenantDoesNotExist;
 
             let outcome =
-                // Check if the tenant exists. If it does, then check if the
-                // table exists, and update the status of the rpc.
-                tenant.and_then(| tenant | {
-                                status = RpcStatus::StatusTableDoesNotExist;
-                                tenant.get_table(table_id)
+                // Check if the tenant exists. If it does, then check if the table
+                // exists and can be read natively, and update the status of the rpc.
+                tenant.and_then(| tenant | match tenant.readable_native_table(table_id) {
+                                Ok(table) => Some(table),
+                                Err(err) => {
+                                    status = err;
+                                    None
+                                }
                             });
 
-            // If the table exists, then lookup the keys in the database.
+            // If the table exists, then lookup the keys in the database. Keys whose versions
+            // were asked for are read as a snapshot.
+            let versions = flags & (MULTIGET_FLAG_VERSIONS | MULTIGET_FLAG_VERSIONS_ONLY) != 0;
             if let Some(table) = outcome {
-                status = RpcStatus::StatusObjectDoesNotExist;
-
-                // Iterate across keys in the request payload. There are `num_keys` keys, each
-                // of length `key_length`.
-                let mut n = 0;
-                for key in req.get_payload().chunks(key_length as usize) {
-                    n += 1;
-                    // Corner case: We've either already seen `num_keys` keys or the current key
-                    // is not `key_length` bytes long.
-                    if n > num_keys || key.len() != key_length as usize {
-                        break;
-                    }
-
-                    // Lookup the key, and add it to the response payload.
+                if versions {
                     let alloc: &Allocator = accessor(alloc);
-                    let res = table
-                        .get(key)
-                        .and_then(|entry| alloc.resolve(entry.value))
-                        .and_then(|(_k, value)| {
-                            res.add_to_payload_tail(value.len(), &value[..]).ok()
-                        });
-
-                    // If the current lookup failed, then stop all lookups.
-                    match res {
-                        Some(_) => n_recs += 1,
+                    let (s, n) = multiget_versions(
+                        &table,
+                        alloc,
+                        req.get_payload(),
+                        key_length,
+                        num_keys,
+                        flags,
+                        &mut res,
+                    );
+                    status = s;
+                    n_recs = n;
+                } else {
+                    status = RpcStatus::StatusObjectDoesNotExist;
+
+                    // Iterate across keys in the request payload. There are `num_keys` keys, each
+                    // of length `key_length`.
+                    let mut n = 0;
+                    for key in req.get_payload().chunks(key_length as usize) {
+                        n += 1;
+                        // Corner case: We've either already seen `num_keys` keys or the current key
+                        // is not `key_length` bytes long.
+                        if n > num_keys || key.len() != key_length as usize {
+                            break;
+                        }
+
+                        // Quarantined objects are not returned.
+                        if table.is_quarantined(key) {
+                            status = RpcStatus::StatusDataCorrupted;
+                            break;
+                        }
+
+                        // Lookup the key, and add it to the response payload. If the current lookup
+                        // failed, or the value did not fit in the response, then stop all lookups.
+                        let alloc: &Allocator = accessor(alloc);
+                        let value = table.get(key).and_then(|entry| alloc.resolve(entry.value));
+                        match value {
+                            Some((_k, value)) => {
+                                if !append_record(&mut res, &[&value[..]]) {
+                                    status = RpcStatus::StatusInternalError;
+                                    break;
+                                }
+                                n_recs += 1;
+                            }
 
-                        None => break,
+                            None => break,
+                        }
                     }
-                }
 
-                // Success if all keys could be looked up at the database.
-                if n_recs == num_keys {
-                    status = RpcStatus::StatusOk;
+                    // Success if all keys could be looked up at the database.
+                    if n_recs == num_keys {
+                        status = RpcStatus::StatusOk;
+                    }
                 }
             }
 
-            // Write the status into the RPC response header.
-            res.get_mut_header().common_header.status = status.clone();
-
-            // If the RPC was handled successfully, then update the response header with the number
-            // of records that were read from the database.
-            if status == RpcStatus::StatusOk {
-                res.get_mut_header().num_records = n_recs;
-            }
+            // Write the status and number of records into the RPC response header. If the RPC
+            // failed, then any records already written into the response are dropped.
+            finish_multiget_response(&mut res, status, n_recs);
 
             // Deparse request and response packets to UDP, and return from the generator.
             return Some((
@@ -1318,20 +3945,25 @@ impl Master {
         let mut table_id: TableId = 0;
         let mut key_length = 0;
         let mut num_keys = 0;
+        let mut flags = 0;
+        let mut rpc_id = 0;
         let mut rpc_stamp = 0;
 
         {
             let hdr = req.get_header();
-            tenant_id = hdr.common_header.tenant as TenantId;
-            table_id = hdr.table_id as TableId;
-            key_length = hdr.key_len;
-            num_keys = hdr.num_keys;
-            rpc_stamp = hdr.common_header.stamp;
+            tenant_id = hdr.common_header.tenant() as TenantId;
+            table_id = hdr.table_id() as TableId;
+            key_length = hdr.key_len();
+            num_keys = hdr.num_keys();
+            flags = hdr.flags;
+            rpc_id = hdr.common_header.id();
+            rpc_stamp = hdr.common_header.stamp();
         }
 
         // Next, add a header to the response packet.
         let mut res = res
             .push_header(&MultiGetResponse::new(
+                rpc_id,
                 rpc_stamp,
                 OpCode::SandstormMultiGetRpc,
                 tenant_id,
@@ -1349,72 +3981,98 @@ impl Master {
 
         // Lookup the tenant, and get a handle to the allocator. Required to avoid capturing a
         // reference to Master in the generator below.
-        let tenant = self.get_tenant(tenant_id);
+        let tenant = self.tenant(tenant_id);
 
         let mut n_recs: u32 = 0;
         let mut status: RpcStatus = RpcStatus::StatusTenantDoesNotExist;
 
         let outcome =
-                // Check if the tenant exists. If it does, then check if the
-                // table exists, and update the status of the rpc.
-                tenant.and_then(| tenant | {
-                                status = RpcStatus::StatusTableDoesNotExist;
-                                tenant.get_table(table_id)
+                // Check if the tenant exists. If it does, then check if the table
+                // exists and can be read natively, and update the status of the rpc.
+                tenant.and_then(| tenant | match tenant.readable_native_table(table_id) {
+                                Ok(table) => Some(table),
+                                Err(err) => {
+                                    status = err;
+                                    None
+                                }
                             });
 
-        // If the table exists, then lookup the keys in the database.
+        // If the table exists, then lookup the keys in the database. Keys whose versions were
+        // asked for are read as a snapshot.
+        let versions = flags & (MULTIGET_FLAG_VERSIONS | MULTIGET_FLAG_VERSIONS_ONLY) != 0;
         if let Some(table) = outcome {
-            status = RpcStatus::StatusObjectDoesNotExist;
-
-            // Iterate across keys in the request payload. There are `num_keys` keys, each
-            // of length `key_length`.
-            let mut n = 0;
-            for key in req.get_payload().chunks(key_length as usize) {
-                n += 1;
-                // Corner case: We've either already seen `num_keys` keys or the current key
-                // is not `key_length` bytes long.
-                if n > num_keys || key.len() != key_length as usize {
-                    break;
-                }
-
-                // Lookup the key, and add it to the response payload.
-                let res = table
-                    .get(key)
-                    .and_then(|object| self.heap.resolve(object.value))
-                    .and_then(|(_k, value)| res.add_to_payload_tail(value.len(), &value[..]).ok());
-
-                // If the current lookup failed, then stop all lookups.
-                match res {
-                    Some(_) => n_recs += 1,
-
-                    None => break,
-                }
-            }
-
-            // Success if all keys could be looked up at the database.
-            if n_recs == num_keys {
-                status = RpcStatus::StatusOk;
-            }
-        }
+            if versions {
+                let (s, n) = multiget_versions(
+                    &table,
+                    &self.heap,
+                    req.get_payload(),
+                    key_length,
+                    num_keys,
+                    flags,
+                    &mut res,
+                );
+                status = s;
+                n_recs = n;
+            } else {
+                status = RpcStatus::StatusObjectDoesNotExist;
 
-        // Write the status into the RPC response header.
-        res.get_mut_header().common_header.status = status.clone();
+                // Iterate across keys in the request payload. There are `num_keys` keys, each
+                // of length `key_length`.
+                let mut n = 0;
+                for key in req.get_payload().chunks(key_length as usize) {
+                    n += 1;
+                    // Corner case: We've either already seen `num_keys` keys or the current key
+                    // is not `key_length` bytes long.
+                    if n > num_keys || key.len() != key_length as usize {
+                        break;
+                    }
 
-        // If the RPC was handled successfully, then update the response header with the number
-        // of records that were read from the database.
-        if status == RpcStatus::StatusOk {
-            res.get_mut_header().num_records = n_recs;
+                    // Quarantined objects are not returned.
+                    if table.is_quarantined(key) {
+                        status = RpcStatus::StatusDataCorrupted;
+                        break;
+                    }
+
+                    // Lookup the key, and add it to the response payload. If the current lookup
+                    // failed, or the value did not fit in the response, then stop all lookups.
+                    let value = table
+                        .get(key)
+                        .and_then(|object| self.heap.resolve(object.value));
+                    match value {
+                        Some((_k, value)) => {
+                            if !append_record(&mut res, &[&value[..]]) {
+                                status = RpcStatus::StatusInternalError;
+                                break;
+                            }
+                            n_recs += 1;
+                        }
+
+                        None => break,
+                    }
+                }
+
+                // Success if all keys could be looked up at the database.
+                if n_recs == num_keys {
+                    status = RpcStatus::StatusOk;
+                }
+            }
         }
 
+        // Write the status and number of records into the RPC response header. If the RPC
+        // failed, then any records already written into the response are dropped.
+        finish_multiget_response(&mut res, status, n_recs);
+
         return Ok((
             req.deparse_header(PACKET_UDP_LEN as usize),
             res.deparse_header(PACKET_UDP_LEN as usize),
         ));
     }
 
-    /// Handles the invoke RPC request.
+    /// Handles the list_extensions() RPC request.
     ///
-    /// If issued by a valid tenant for a valid extension, invokes the extension.
+    /// Lists the extensions the requesting tenant can invoke, including those shared with it,
+    /// starting at the index on the request. The response carries a continuation index if the
+    /// listing did not fit.
     ///
     /// # Arguments
     ///
@@ -1423,10 +4081,10 @@ impl Master {
     ///
     /// # Return
     ///
-    /// A Container task that can be scheduled by the database. In the case of an error, the passed
-    /// in request and response packets are returned with the response status appropriately set.
-    #[allow(unused_assignments)]
-    fn invoke(
+    /// A Native task that can be scheduled by the database. In the case of an error, the passed
+    /// in request and response packets are returned.
+    #[allow(unreachable_code)]
+    fn list_extensions(
         &self,
         req: Packet<UdpHeader, EmptyMetadata>,
         res: Packet<UdpHeader, EmptyMetadata>,
@@ -1437,171 +4095,174 @@ impl Master {
             Packet<UdpHeader, EmptyMetadata>,
         ),
     > {
-        // First, parse the request packet.
-        let req = req.parse_header::<InvokeRequest>();
-
-        // Read fields of the request header.
-        let mut tenant_id: TenantId = 0;
-        let mut name_length: usize = 0;
-        let mut args_length: usize = 0;
-        let mut rpc_stamp = 0;
+        // The listing is cheap, so it is built right away. The generator only hands the packets
+        // back to the scheduler.
+        let (req, res) = self.list_extensions_native(req, res)?;
+        let gen = Box::new(move || {
+            return Some((req, res));
 
-        {
-            let hdr = req.get_header();
-            tenant_id = hdr.common_header.tenant as TenantId;
-            name_length = hdr.name_length as usize;
-            args_length = hdr.args_length as usize;
-            rpc_stamp = hdr.common_header.stamp;
-        }
+            // XXX: This yield is required to get the compiler to compile this closure into a
+            // generator. It is unreachable and benign.
+            yield 0;
+        });
 
-        // Next, add a header to the response packet.
-        let mut res = res
-            .push_header(&InvokeResponse::new(
-                rpc_stamp,
-                OpCode::SandstormInvokeRpc,
-                tenant_id,
-            )).expect("Failed to push InvokeResponse");
+        return Ok(Box::new(Native::new(TaskPriority::REQUEST, gen)));
+    }
 
-        // If the payload size is less than the sum of the name and args
-        // length, return an error.
-        if req.get_payload().len() < name_length + args_length {
-            res.get_mut_header().common_header.status = RpcStatus::StatusMalformedRequest;
-            return Err((
-                req.deparse_header(PACKET_UDP_LEN as usize),
-                res.deparse_header(PACKET_UDP_LEN as usize),
-            ));
+    // This function processes list_extensions() requests without creating a generator.
+    fn list_extensions_native(
+        &self,
+        req: Packet<UdpHeader, EmptyMetadata>,
+        res: Packet<UdpHeader, EmptyMetadata>,
+    ) -> Result<
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+    > {
+        if req.get_payload().len() < size_of::<ListExtRequest>() {
+            return Err((req, res));
         }
 
-        // Read the extension's name from the request payload.
-        let mut name = Vec::new();
-        name.extend_from_slice(req.get_payload().split_at(name_length).0);
-        let name: String = String::from_utf8(name).expect("ERROR: Failed to get ext name.");
-
-        let mut status = RpcStatus::StatusTenantDoesNotExist;
-
-        // Check if the request was issued by a valid tenant.
-        if let Some(tenant) = self.get_tenant(tenant_id) {
-            let alloc = accessor(&self.heap as *const Allocator);
-            // If the tenant is valid, check if the extension exists inside the database after
-            // setting the RPC status appropriately.
-            status = RpcStatus::StatusInvalidExtension;
-
-            // Get the model for the given extension.
-            let mut model = None;
-            // If the extension doesn't need an ML model, don't waste CPU cycles in lookup.
-            if cfg!(feature = "ml-model") {
-                GLOBAL_MODEL.with(|a_model| {
-                    if let Some(a_model) = (*a_model).borrow().get(&name) {
-                        model = Some(Arc::clone(a_model));
-                    }
-                });
-            }
-
-            // Create a Container for an extension and return.
-            if let Some(ext) = self.extensions.get(tenant_id, name) {
-                let db = Rc::new(Context::new(
-                    req,
-                    name_length,
-                    args_length,
-                    res,
-                    tenant,
-                    alloc,
-                    model,
-                ));
-                let gen = ext.get(Rc::clone(&db) as Rc<DB>);
-
-                return Ok(Box::new(Container::new(TaskPriority::REQUEST, db, gen)));
-            }
-        }
+        // Read fields off the request header.
+        let req = req.parse_header::<ListExtRequest>();
+        let (tenant, start, id, stamp) = {
+            let hdr = req.get_header();
+            (
+                hdr.common_header.tenant(),
+                hdr.start(),
+                hdr.common_header.id(),
+                hdr.common_header.stamp(),
+            )
+        };
+
+        let list = match self.extensions {
+            Some(ref extensions) => extensions.list(tenant as TenantId),
+            None => Vec::new(),
+        };
+        let (entries, num, next) =
+            rpc::encode_ext_listing(&list, start as usize, self.list_ext_budget);
+
+        let mut hdr = ListExtResponse::new(id, stamp, tenant);
+        hdr.set_num_entries(num);
+        hdr.set_next(next);
 
-        // A Task could not be created. Set the status of the RPC and return.
-        res.get_mut_header().common_header.status = status;
+        let mut res = res
+            .push_header(&hdr)
+            .expect("Failed to push ListExtResponse");
+        res.add_to_payload_tail(entries.len(), &entries)
+            .expect("Failed to write extension listing into response!");
 
-        return Err((
+        return Ok((
             req.deparse_header(PACKET_UDP_LEN as usize),
             res.deparse_header(PACKET_UDP_LEN as usize),
         ));
     }
 
-    /// Handles the install() RPC request.
+    /// Handles the drain RPC request.
     ///
-    /// If issued by a valid tenant, installs (loads) an extension into the database.
+    /// If issued by tenant 0, starts draining the server. The response reports how many tasks
+    /// are yet to complete on each core, so the RPC can be re-issued to watch the drain's progress.
     ///
     /// # Arguments
     ///
-    /// * `buf`: The RPC buffer consisting of the request header followed by the payload.
+    /// * `req`: The RPC request packet sent by the client, parsed upto it's UDP header.
+    /// * `res`: The RPC response packet, with pre-allocated headers upto UDP.
     ///
     /// # Return
     ///
-    /// A response buffer that can be sent back to the tenant.
-    pub fn install(&self, buf: Vec<u8>) -> Vec<u8> {
-        // First off, parse the RPC header.
-        let hdr = buf.as_ptr() as *const InstallRequest;
-
-        let tenant: TenantId;
-        let name_l: usize;
-        let extn_l: usize;
-        let tstamp: u64;
+    /// A Native task that sends out the response. If the request was malformed, the passed in
+    /// request and response packets are returned.
+    fn start_drain(
+        &self,
+        req: Packet<UdpHeader, EmptyMetadata>,
+        res: Packet<UdpHeader, EmptyMetadata>,
+    ) -> Result<
+        Box<Task>,
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+    > {
+        let (req, res) = self.start_drain_native(req, res)?;
+        Ok(respond(req, res))
+    }
 
-        unsafe {
-            tenant = (*hdr).common_header.tenant as TenantId;
-            name_l = (*hdr).name_length as usize;
-            extn_l = (*hdr).extn_length as usize;
-            tstamp = (*hdr).common_header.stamp;
+    // This function processes drain() requests without creating a generator.
+    fn start_drain_native(
+        &self,
+        req: Packet<UdpHeader, EmptyMetadata>,
+        res: Packet<UdpHeader, EmptyMetadata>,
+    ) -> Result<
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+    > {
+        if req.get_payload().len() < size_of::<DrainRequest>() {
+            return Err((req, res));
         }
 
-        // Create a response for the tenant.
-        let mut res = InstallResponse::new(tstamp, OpCode::SandstormInstallRpc, tenant as u32);
-        res.common_header.status = RpcStatus::StatusTenantDoesNotExist;
+        // Read fields off the request header.
+        let req = req.parse_header::<DrainRequest>();
+        let (tenant, id, stamp) = {
+            let hdr = req.get_header();
+            (
+                hdr.common_header.tenant(),
+                hdr.common_header.id(),
+                hdr.common_header.stamp(),
+            )
+        };
+
+        let mut hdr = DrainResponse::new(id, stamp, tenant);
+        let mut progress = Vec::new();
+        if tenant != 0 {
+            hdr.common_header.status = RpcStatus::StatusInvalidOperation;
+        } else {
+            if self.drain.start() {
+                info!("Draining server on request");
+            }
 
-        // Check if the tenant provided lengths match the actual request length.
-        if buf.len() != size_of::<InstallRequest>() + name_l + extn_l {
-            res.common_header.status = RpcStatus::StatusMalformedRequest;
-            let res: [u8; size_of::<InstallResponse>()] = unsafe { transmute(res) };
-            let mut ret: Vec<u8> = Vec::new();
-            ret.extend_from_slice(&res);
-            return ret;
+            progress = self.drain.progress();
+            hdr.set_num_cores(progress.len() as u32);
+            hdr.set_remaining(progress.iter().sum());
         }
 
-        // Save the extension to a .so file. If all goes well, load it into the server.
-        if let Some(_) = self.get_tenant(tenant) {
-            res.common_header.status = RpcStatus::StatusInternalError;
-
-            let (_, payload) = buf.split_at(size_of::<InstallRequest>());
-            let (name, payload) = payload.split_at(name_l);
-            let (extn, _) = payload.split_at(extn_l);
-
-            if let Ok(name) = from_utf8(name) {
-                let mut path = String::new();
-                path.push_str("/tmp/");
-                path.push_str(name);
-                path.push_str(".so");
-
-                // No need for error handling here. If a file could not be created or
-                // written to, then it might be better to just crash the server and recover.
-                let mut file = File::create(path.clone()).unwrap();
-                let _ = file.write_all(extn).unwrap();
-                let _ = file.sync_all().unwrap();
-
-                if self.extensions.load(&path, tenant, name) {
-                    res.common_header.status = RpcStatus::StatusOk;
-                }
-            }
-        }
+        let payload = rpc::encode_drain_progress(&progress);
+        let mut res = res.push_header(&hdr).expect("Failed to push DrainResponse");
+        res.add_to_payload_tail(payload.len(), &payload)
+            .expect("Failed to write drain progress into response!");
 
-        let res: [u8; size_of::<InstallResponse>()] = unsafe { transmute(res) };
-        let mut ret: Vec<u8> = Vec::new();
-        ret.extend_from_slice(&res);
-        return ret;
+        return Ok((
+            req.deparse_header(PACKET_UDP_LEN as usize),
+            res.deparse_header(PACKET_UDP_LEN as usize),
+        ));
     }
-}
 
-/// Implementation of the Service trait for Master, allowing it to service RPC requests.
-impl Service for Master {
-    /// Lookup the Service trait for documentation.
-    fn dispatch(
+    /// Handles the table_access RPC request.
+    ///
+    /// Sets whether one of the issuing tenant's tables can be read and written by native RPCs.
+    /// The change applies to requests that reach Master after it, without a restart.
+    ///
+    /// # Arguments
+    ///
+    /// * `req`: The RPC request packet sent by the client, parsed upto it's UDP header.
+    /// * `res`: The RPC response packet, with pre-allocated headers upto UDP.
+    ///
+    /// # Return
+    ///
+    /// A Native task that sends out the response. If the request was malformed, the passed in
+    /// request and response packets are returned.
+    fn table_access(
         &self,
-        op: OpCode,
         req: Packet<UdpHeader, EmptyMetadata>,
         res: Packet<UdpHeader, EmptyMetadata>,
     ) -> Result<
@@ -1611,31 +4272,78 @@ impl Service for Master {
             Packet<UdpHeader, EmptyMetadata>,
         ),
     > {
-        // Based on the opcode, call the relevant RPC handler.
-        match op {
-            OpCode::SandstormGetRpc => {
-                return self.get(req, res);
-            }
-
-            OpCode::SandstormPutRpc => {
-                return self.put(req, res);
-            }
-
-            OpCode::SandstormMultiGetRpc => {
-                return self.multiget(req, res);
-            }
-
-            OpCode::SandstormInvokeRpc => {
-                return self.invoke(req, res);
-            }
+        let (req, res) = self.table_access_native(req, res)?;
+        Ok(respond(req, res))
+    }
 
-            _ => {
-                return Err((req, res));
-            }
+    // This function processes table_access() requests without creating a generator.
+    fn table_access_native(
+        &self,
+        req: Packet<UdpHeader, EmptyMetadata>,
+        res: Packet<UdpHeader, EmptyMetadata>,
+    ) -> Result<
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+    > {
+        if req.get_payload().len() < size_of::<TableAccessRequest>() {
+            return Err((req, res));
         }
+
+        // Read fields off the request header.
+        let req = req.parse_header::<TableAccessRequest>();
+        let (tenant, id, stamp, table_id, readable, writable) = {
+            let hdr = req.get_header();
+            (
+                hdr.common_header.tenant(),
+                hdr.common_header.id(),
+                hdr.common_header.stamp(),
+                hdr.table_id(),
+                hdr.readable_native != 0,
+                hdr.writable_native != 0,
+            )
+        };
+
+        // Only the tenant owning the table can change it's access.
+        let mut hdr = TableAccessResponse::new(id, stamp, tenant);
+        hdr.common_header.status = match self.get_tenant(tenant) {
+            Some(owner) => match owner.set_native_access(table_id, readable, writable) {
+                Ok(()) => RpcStatus::StatusOk,
+                Err(err) => err,
+            },
+
+            None => RpcStatus::StatusTenantDoesNotExist,
+        };
+
+        let res = res
+            .push_header(&hdr)
+            .expect("Failed to push TableAccessResponse");
+        return Ok((
+            req.deparse_header(PACKET_UDP_LEN as usize),
+            res.deparse_header(PACKET_UDP_LEN as usize),
+        ));
     }
 
-    fn dispatch_invoke(
+    /// Handles the merge() RPC request.
+    ///
+    /// Merges the delta on the request into the value stored under the key, using the merge
+    /// extension registered for the table. Refer to merge::merge().
+    ///
+    /// # Arguments
+    ///
+    /// * `req`: The RPC request packet sent by the client, parsed upto it's UDP header.
+    /// * `res`: The RPC response packet, with pre-allocated headers upto UDP.
+    ///
+    /// # Return
+    ///
+    /// A Native task that sends out the response. If the request was malformed, the passed in
+    /// request and response packets are returned.
+    fn merge(
         &self,
         req: Packet<UdpHeader, EmptyMetadata>,
         res: Packet<UdpHeader, EmptyMetadata>,
@@ -1646,13 +4354,14 @@ impl Service for Master {
             Packet<UdpHeader, EmptyMetadata>,
         ),
     > {
-        return self.invoke(req, res);
+        let (req, res) = self.merge_native(req, res)?;
+        Ok(respond(req, res))
     }
 
-    #[inline]
-    fn service_native(
+    // This function processes merge() requests without creating a generator. The merge runs to
+    // completion right here, under the lock on the key's bucket.
+    fn merge_native(
         &self,
-        op: OpCode,
         req: Packet<UdpHeader, EmptyMetadata>,
         res: Packet<UdpHeader, EmptyMetadata>,
     ) -> Result<
@@ -1665,23 +4374,3586 @@ impl Service for Master {
             Packet<UdpHeader, EmptyMetadata>,
         ),
     > {
-        // Based on the opcode, call the relevant RPC handler.
-        match op {
-            OpCode::SandstormGetRpc => {
-                return self.get_native(req, res);
-            }
+        if req.get_payload().len() < size_of::<MergeRequest>() {
+            return Err((req, res));
+        }
 
-            OpCode::SandstormPutRpc => {
-                return self.put_native(req, res);
+        // Read fields off the request header.
+        let req = req.parse_header::<MergeRequest>();
+        let (tenant, id, stamp, table_id, key_length) = {
+            let hdr = req.get_header();
+            (
+                hdr.common_header.tenant(),
+                hdr.common_header.id(),
+                hdr.common_header.stamp(),
+                hdr.table_id(),
+                hdr.key_length() as usize,
+            )
+        };
+
+        let mut frame = Vec::new();
+        let mut hdr = MergeResponse::new(id, stamp, tenant);
+        hdr.common_header.status = match req.get_payload().len() < key_length {
+            true => RpcStatus::StatusMalformedRequest,
+
+            false => match self.tenant(tenant) {
+                Some(tenant) => match tenant.writable_native_table(table_id) {
+                    Ok(table) => {
+                        let (key, delta) = req.get_payload().split_at(key_length);
+                        match merge::merge(&self.heap, &table, tenant.id(), table_id, key, delta) {
+                            Ok(()) => RpcStatus::StatusOk,
+                            Err(MergeError::NoMergeExtension) => RpcStatus::StatusInvalidExtension,
+                            Err(MergeError::ReadOnly) => RpcStatus::StatusReadOnlyTable,
+                            Err(MergeError::EmptyKey) => RpcStatus::StatusMalformedRequest,
+                            Err(MergeError::Alloc) => RpcStatus::StatusInternalError,
+                            Err(MergeError::TooLarge(status)) => status,
+                            Err(MergeError::Failed(out)) => {
+                                frame = out;
+                                RpcStatus::StatusExtensionError
+                            }
+                        }
+                    }
+
+                    Err(err) => err,
+                },
+
+                None => RpcStatus::StatusTenantDoesNotExist,
+            },
+        };
+
+        let mut res = res
+            .push_header(&hdr)
+            .expect("Failed to push MergeResponse");
+        if !frame.is_empty() {
+            res.add_to_payload_tail(frame.len(), &frame)
+                .expect("Failed to write merge error frame");
+        }
+
+        return Ok((
+            req.deparse_header(PACKET_UDP_LEN as usize),
+            res.deparse_header(PACKET_UDP_LEN as usize),
+        ));
+    }
+
+    /// Handles the set_merge() RPC request.
+    ///
+    /// Registers the named extension as the merge extension of one of the issuing tenant's
+    /// tables, or clears the table's merge extension if the name is empty. The extension must
+    /// be loaded for (or shared with) the tenant, and is resolved once, here.
+    ///
+    /// # Arguments
+    ///
+    /// * `req`: The RPC request packet sent by the client, parsed upto it's UDP header.
+    /// * `res`: The RPC response packet, with pre-allocated headers upto UDP.
+    ///
+    /// # Return
+    ///
+    /// A Native task that sends out the response. If the request was malformed, the passed in
+    /// request and response packets are returned.
+    fn set_merge(
+        &self,
+        req: Packet<UdpHeader, EmptyMetadata>,
+        res: Packet<UdpHeader, EmptyMetadata>,
+    ) -> Result<
+        Box<Task>,
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+    > {
+        let (req, res) = self.set_merge_native(req, res)?;
+        Ok(respond(req, res))
+    }
+
+    // This function processes set_merge() requests without creating a generator.
+    fn set_merge_native(
+        &self,
+        req: Packet<UdpHeader, EmptyMetadata>,
+        res: Packet<UdpHeader, EmptyMetadata>,
+    ) -> Result<
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+    > {
+        if req.get_payload().len() < size_of::<SetMergeRequest>() {
+            return Err((req, res));
+        }
+
+        // Read fields off the request header.
+        let req = req.parse_header::<SetMergeRequest>();
+        let (tenant, id, stamp, table_id, name_length) = {
+            let hdr = req.get_header();
+            (
+                hdr.common_header.tenant(),
+                hdr.common_header.id(),
+                hdr.common_header.stamp(),
+                hdr.table_id(),
+                hdr.name_length() as usize,
+            )
+        };
+
+        // Only the tenant owning the table can set it's merge extension.
+        let mut hdr = SetMergeResponse::new(id, stamp, tenant);
+        hdr.common_header.status = match self.get_tenant(tenant) {
+            Some(_) if req.get_payload().len() < name_length => {
+                RpcStatus::StatusMalformedRequest
+            }
+
+            Some(owner) => {
+                let name = &req.get_payload()[..name_length];
+                let merge = match name.is_empty() {
+                    true => Ok(None),
+                    false => self
+                        .extensions
+                        .as_ref()
+                        .and_then(|e| e.get(tenant, name))
+                        .map(|ext| Some((name.to_vec(), ext)))
+                        .ok_or(RpcStatus::StatusInvalidExtension),
+                };
+
+                match merge.and_then(|merge| owner.set_merge(table_id, merge)) {
+                    Ok(()) => RpcStatus::StatusOk,
+                    Err(err) => err,
+                }
+            }
+
+            None => RpcStatus::StatusTenantDoesNotExist,
+        };
+
+        let res = res
+            .push_header(&hdr)
+            .expect("Failed to push SetMergeResponse");
+        return Ok((
+            req.deparse_header(PACKET_UDP_LEN as usize),
+            res.deparse_header(PACKET_UDP_LEN as usize),
+        ));
+    }
+
+    /// Handles the set_route() RPC request.
+    ///
+    /// Installs a rule routing keys with the prefix on the request to the named extension, for
+    /// routed invoke() requests issued by the same tenant. Replaces any rule already on the
+    /// prefix, or removes it if the name is empty. The extension must be loaded for (or shared
+    /// with) the tenant when the rule is installed. Refer to route::Routes.
+    ///
+    /// # Arguments
+    ///
+    /// * `req`: The RPC request packet sent by the client, parsed upto it's UDP header.
+    /// * `res`: The RPC response packet, with pre-allocated headers upto UDP.
+    ///
+    /// # Return
+    ///
+    /// A Native task that sends out the response. If the request was malformed, the passed in
+    /// request and response packets are returned.
+    fn set_route(
+        &self,
+        req: Packet<UdpHeader, EmptyMetadata>,
+        res: Packet<UdpHeader, EmptyMetadata>,
+    ) -> Result<
+        Box<Task>,
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+    > {
+        let (req, res) = self.set_route_native(req, res)?;
+        Ok(respond(req, res))
+    }
+
+    // This function processes set_route() requests without creating a generator.
+    fn set_route_native(
+        &self,
+        req: Packet<UdpHeader, EmptyMetadata>,
+        res: Packet<UdpHeader, EmptyMetadata>,
+    ) -> Result<
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+    > {
+        if req.get_payload().len() < size_of::<SetRouteRequest>() {
+            return Err((req, res));
+        }
+
+        // Read fields off the request header.
+        let req = req.parse_header::<SetRouteRequest>();
+        let (tenant, id, stamp, prefix_length, name_length) = {
+            let hdr = req.get_header();
+            (
+                hdr.common_header.tenant(),
+                hdr.common_header.id(),
+                hdr.common_header.stamp(),
+                hdr.prefix_length() as usize,
+                hdr.name_length() as usize,
+            )
+        };
+
+        let mut hdr = SetRouteResponse::new(id, stamp, tenant);
+        hdr.common_header.status = match self.get_tenant(tenant) {
+            Some(_) if req.get_payload().len() < prefix_length + name_length => {
+                RpcStatus::StatusMalformedRequest
+            }
+
+            Some(owner) => {
+                let (prefix, name) = req.get_payload().split_at(prefix_length);
+                let name = &name[..name_length];
+                let loaded = name.is_empty()
+                    || self
+                        .extensions
+                        .as_ref()
+                        .and_then(|e| e.get(tenant, name))
+                        .is_some();
+
+                match loaded {
+                    true => match owner.set_route(prefix, name) {
+                        Ok(()) => RpcStatus::StatusOk,
+                        Err(err) => err,
+                    },
+
+                    false => RpcStatus::StatusInvalidExtension,
+                }
+            }
+
+            None => RpcStatus::StatusTenantDoesNotExist,
+        };
+
+        let res = res
+            .push_header(&hdr)
+            .expect("Failed to push SetRouteResponse");
+        return Ok((
+            req.deparse_header(PACKET_UDP_LEN as usize),
+            res.deparse_header(PACKET_UDP_LEN as usize),
+        ));
+    }
+
+    /// Handles the audit() RPC request.
+    ///
+    /// Responds with a page of the requesting tenant's audit log, newest invocation first,
+    /// starting at the cursor on the request. The response carries the cursor the next page
+    /// starts at, if there are older entries. Tenants can only page through their own log.
+    ///
+    /// # Arguments
+    ///
+    /// * `req`: The RPC request packet sent by the client, parsed upto it's UDP header.
+    /// * `res`: The RPC response packet, with pre-allocated headers upto UDP.
+    ///
+    /// # Return
+    ///
+    /// A Native task that sends out the response. If the request was malformed, the passed in
+    /// request and response packets are returned.
+    fn audit(
+        &self,
+        req: Packet<UdpHeader, EmptyMetadata>,
+        res: Packet<UdpHeader, EmptyMetadata>,
+    ) -> Result<
+        Box<Task>,
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+    > {
+        let (req, res) = self.audit_native(req, res)?;
+        Ok(respond(req, res))
+    }
+
+    // This function processes audit() requests without creating a generator.
+    fn audit_native(
+        &self,
+        req: Packet<UdpHeader, EmptyMetadata>,
+        res: Packet<UdpHeader, EmptyMetadata>,
+    ) -> Result<
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+    > {
+        if req.get_payload().len() < size_of::<AuditRequest>() {
+            return Err((req, res));
+        }
+
+        // Read fields off the request header.
+        let req = req.parse_header::<AuditRequest>();
+        let (tenant, cursor, id, stamp) = {
+            let hdr = req.get_header();
+            (
+                hdr.common_header.tenant(),
+                hdr.cursor(),
+                hdr.common_header.id(),
+                hdr.common_header.stamp(),
+            )
+        };
+
+        let mut hdr = AuditResponse::new(id, stamp, tenant);
+        let entries = match self.audit_page(tenant, cursor) {
+            Ok((page, next)) => {
+                hdr.set_num_entries(page.len() as u32);
+                hdr.set_next(next);
+                rpc::encode_audit_page(&page)
+            }
+
+            Err(status) => {
+                hdr.common_header.status = status;
+                Vec::new()
+            }
+        };
+
+        let mut res = res.push_header(&hdr).expect("Failed to push AuditResponse");
+        if entries.len() > 0 {
+            res.add_to_payload_tail(entries.len(), &entries)
+                .expect("Failed to write audit entries into response!");
+        }
+
+        return Ok((
+            req.deparse_header(PACKET_UDP_LEN as usize),
+            res.deparse_header(PACKET_UDP_LEN as usize),
+        ));
+    }
+
+    /// Handles the dump() RPC request.
+    ///
+    /// Responds with a page of the records in one of the requesting tenant's tables, continuing
+    /// the dump from the bucket and key on the request. Refer to dump_table().
+    ///
+    /// # Arguments
+    ///
+    /// * `req`: The RPC request packet sent by the client, parsed upto it's UDP header.
+    /// * `res`: The RPC response packet, with pre-allocated headers upto UDP.
+    ///
+    /// # Return
+    ///
+    /// A Native task that sends out the response. If the request was malformed, the passed in
+    /// request and response packets are returned.
+    fn dump(
+        &self,
+        req: Packet<UdpHeader, EmptyMetadata>,
+        res: Packet<UdpHeader, EmptyMetadata>,
+    ) -> Result<
+        Box<Task>,
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+    > {
+        let (req, res) = self.dump_native(req, res)?;
+        Ok(respond(req, res))
+    }
+
+    // This function processes dump() requests without creating a generator.
+    fn dump_native(
+        &self,
+        req: Packet<UdpHeader, EmptyMetadata>,
+        res: Packet<UdpHeader, EmptyMetadata>,
+    ) -> Result<
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+    > {
+        if req.get_payload().len() < size_of::<DumpRequest>() {
+            return Err((req, res));
+        }
+
+        // Read fields off the request header.
+        let req = req.parse_header::<DumpRequest>();
+        let (tenant, table, bucket, key_length, id, stamp) = {
+            let hdr = req.get_header();
+            (
+                hdr.common_header.tenant(),
+                hdr.table_id(),
+                hdr.bucket(),
+                hdr.key_length() as usize,
+                hdr.common_header.id(),
+                hdr.common_header.stamp(),
+            )
+        };
+
+        let mut hdr = DumpResponse::new(id, stamp, tenant);
+        let records = match req.get_payload().len() == key_length {
+            true => {
+                let after = req.get_payload();
+                match self.dump_table(tenant, table, bucket, after, DUMP_BUDGET) {
+                    Ok((records, num, bucket)) => {
+                        hdr.set_num_records(num);
+                        hdr.set_bucket(bucket);
+                        records
+                    }
+
+                    Err(status) => {
+                        hdr.common_header.status = status;
+                        Vec::new()
+                    }
+                }
+            }
+
+            false => {
+                hdr.common_header.status = RpcStatus::StatusMalformedRequest;
+                Vec::new()
+            }
+        };
+
+        let mut res = res.push_header(&hdr).expect("Failed to push DumpResponse");
+        if records.len() > 0 {
+            res.add_to_payload_tail(records.len(), &records)
+                .expect("Failed to write records into response!");
+        }
+
+        return Ok((
+            req.deparse_header(PACKET_UDP_LEN as usize),
+            res.deparse_header(PACKET_UDP_LEN as usize),
+        ));
+    }
+
+    /// Handles the scan() RPC request.
+    ///
+    /// Responds with as many of the records in one of the requesting tenant's tables as fit on a
+    /// single response, in key order from the key on the request, along with a continuation
+    /// token to resume the scan from. Refer to scan_table().
+    ///
+    /// # Arguments
+    ///
+    /// * `req`: The RPC request packet sent by the client, parsed upto it's UDP header.
+    /// * `res`: The RPC response packet, with pre-allocated headers upto UDP.
+    ///
+    /// # Return
+    ///
+    /// A Native task that reads the records SCAN_BATCH at a time, yielding in between batches.
+    /// If the request was malformed, the passed in request and response packets are returned.
+    fn scan(
+        &self,
+        req: Packet<UdpHeader, EmptyMetadata>,
+        res: Packet<UdpHeader, EmptyMetadata>,
+    ) -> Result<
+        Box<Task>,
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+    > {
+        if req.get_payload().len() < size_of::<ScanRequest>() {
+            return Err((req, res));
+        }
+
+        // Read fields off the request header.
+        let req = req.parse_header::<ScanRequest>();
+        let (tenant, table_id, max, key_length, flags, id, stamp) = {
+            let hdr = req.get_header();
+            (
+                hdr.common_header.tenant(),
+                hdr.table_id(),
+                hdr.max_records(),
+                hdr.key_length() as usize,
+                hdr.flags,
+                hdr.common_header.id(),
+                hdr.common_header.stamp(),
+            )
+        };
+
+        let mut res = res
+            .push_header(&ScanResponse::new(id, stamp, tenant))
+            .expect("Failed to push ScanResponse");
+
+        if req.get_payload().len() != key_length {
+            res.get_mut_header().common_header.status = RpcStatus::StatusMalformedRequest;
+            return Ok(respond(
+                req.deparse_header(PACKET_UDP_LEN as usize),
+                res.deparse_header(PACKET_UDP_LEN as usize),
+            ));
+        }
+
+        // Lookup the table, and get a handle to the allocator. Required to avoid capturing a
+        // reference to Master in the generator below.
+        let table = self.resolve_table(tenant, table_id);
+        let alloc: *const Allocator = &self.heap;
+        let mut page = ScanPage::new(
+            req.get_payload(),
+            flags & SCAN_FLAG_AFTER_START != 0,
+            max,
+            trailer::room(size_of::<ScanResponse>()),
+        );
+
+        let gen = Box::new(move || {
+            let mut status = RpcStatus::StatusOk;
+            match table {
+                Ok(table) => loop {
+                    match page.step(&table, accessor(alloc)) {
+                        Ok(false) => {}
+
+                        Ok(true) => break,
+
+                        Err(err) => {
+                            status = err;
+                            break;
+                        }
+                    }
+
+                    // Let other tasks run in between batches.
+                    yield 0;
+                },
+
+                Err(err) => status = err,
+            }
+
+            // Records are only sent back if the whole page could be read.
+            let (records, num, skipped, token) = page.finish();
+            if status == RpcStatus::StatusOk {
+                if append_record(&mut res, &[&records[..], &token[..]]) {
+                    let hdr = res.get_mut_header();
+                    hdr.set_num_records(num);
+                    hdr.set_token_length(token.len() as u16);
+                    hdr.set_skipped(skipped);
+                } else {
+                    status = RpcStatus::StatusInternalError;
+                }
+            }
+            res.get_mut_header().common_header.status = status;
+
+            return Some((
+                req.deparse_header(PACKET_UDP_LEN as usize),
+                res.deparse_header(PACKET_UDP_LEN as usize),
+            ));
+        });
+
+        Ok(Box::new(Native::new(TaskPriority::REQUEST, gen)))
+    }
+
+    /// Handles the multiput() RPC request.
+    ///
+    /// Writes the records on the request to one of the requesting tenant's tables. Refer to
+    /// put_records().
+    ///
+    /// # Arguments
+    ///
+    /// * `req`: The RPC request packet sent by the client, parsed upto it's UDP header.
+    /// * `res`: The RPC response packet, with pre-allocated headers upto UDP.
+    ///
+    /// # Return
+    ///
+    /// A Native task that sends out the response. If the request was malformed, the passed in
+    /// request and response packets are returned.
+    fn multiput(
+        &self,
+        req: Packet<UdpHeader, EmptyMetadata>,
+        res: Packet<UdpHeader, EmptyMetadata>,
+    ) -> Result<
+        Box<Task>,
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+    > {
+        let (req, res) = self.multiput_native(req, res)?;
+        Ok(respond(req, res))
+    }
+
+    // This function processes multiput() requests without creating a generator.
+    fn multiput_native(
+        &self,
+        req: Packet<UdpHeader, EmptyMetadata>,
+        res: Packet<UdpHeader, EmptyMetadata>,
+    ) -> Result<
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+    > {
+        if req.get_payload().len() < size_of::<MultiPutRequest>() {
+            return Err((req, res));
+        }
+
+        // Read fields off the request header.
+        let req = req.parse_header::<MultiPutRequest>();
+        let (tenant, table, num, id, stamp) = {
+            let hdr = req.get_header();
+            (
+                hdr.common_header.tenant(),
+                hdr.table_id(),
+                hdr.num_records(),
+                hdr.common_header.id(),
+                hdr.common_header.stamp(),
+            )
+        };
+
+        let mut hdr = MultiPutResponse::new(id, stamp, tenant);
+        hdr.common_header.status = self.put_records(tenant, table, req.get_payload(), num);
+        if hdr.common_header.status == RpcStatus::StatusOk {
+            hdr.set_num_written(num);
+        }
+
+        let res = res
+            .push_header(&hdr)
+            .expect("Failed to push MultiPutResponse");
+        return Ok((
+            req.deparse_header(PACKET_UDP_LEN as usize),
+            res.deparse_header(PACKET_UDP_LEN as usize),
+        ));
+    }
+
+    /// Handles the write_stats() RPC request.
+    ///
+    /// Responds with the accounting of the writes made to one of the requesting tenant's
+    /// tables. Refer to write_stats().
+    ///
+    /// # Arguments
+    ///
+    /// * `req`: The RPC request packet sent by the client, parsed upto it's UDP header.
+    /// * `res`: The RPC response packet, with pre-allocated headers upto UDP.
+    ///
+    /// # Return
+    ///
+    /// A Native task that sends out the response. If the request was malformed, the passed in
+    /// request and response packets are returned.
+    fn write_stats_rpc(
+        &self,
+        req: Packet<UdpHeader, EmptyMetadata>,
+        res: Packet<UdpHeader, EmptyMetadata>,
+    ) -> Result<
+        Box<Task>,
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+    > {
+        let (req, res) = self.write_stats_native(req, res)?;
+        Ok(respond(req, res))
+    }
+
+    // This function processes write_stats() requests without creating a generator.
+    fn write_stats_native(
+        &self,
+        req: Packet<UdpHeader, EmptyMetadata>,
+        res: Packet<UdpHeader, EmptyMetadata>,
+    ) -> Result<
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+    > {
+        if req.get_payload().len() < size_of::<WriteStatsRequest>() {
+            return Err((req, res));
+        }
+
+        // Read fields off the request header.
+        let req = req.parse_header::<WriteStatsRequest>();
+        let (tenant, table, query, id, stamp) = {
+            let hdr = req.get_header();
+            (
+                hdr.common_header.tenant(),
+                hdr.table_id(),
+                hdr.query,
+                hdr.common_header.id(),
+                hdr.common_header.stamp(),
+            )
+        };
+
+        let mut hdr = WriteStatsResponse::new(id, stamp, tenant);
+        let entries = match self.write_stats(tenant, table, query) {
+            Ok((entries, num)) => {
+                hdr.set_num_entries(num);
+                entries
+            }
+
+            Err(status) => {
+                hdr.common_header.status = status;
+                Vec::new()
+            }
+        };
+
+        let mut res = res
+            .push_header(&hdr)
+            .expect("Failed to push WriteStatsResponse");
+        if entries.len() > 0 {
+            res.add_to_payload_tail(entries.len(), &entries)
+                .expect("Failed to write write stats into response!");
+        }
+
+        return Ok((
+            req.deparse_header(PACKET_UDP_LEN as usize),
+            res.deparse_header(PACKET_UDP_LEN as usize),
+        ));
+    }
+
+    /// Handles the run_stats RPC request.
+    ///
+    /// If issued by tenant 0, responds with the counters of the run on the request, or of the
+    /// most recently active runs if the request is not for a particular one. Runs that are not
+    /// tracked (or have been evicted) are left off the response.
+    ///
+    /// # Arguments
+    ///
+    /// * `req`: The RPC request packet sent by the client, parsed upto it's UDP header.
+    /// * `res`: The RPC response packet, with pre-allocated headers upto UDP.
+    ///
+    /// # Return
+    ///
+    /// A Native task that sends out the response. If the request was malformed, the passed in
+    /// request and response packets are returned.
+    fn run_stats(
+        &self,
+        req: Packet<UdpHeader, EmptyMetadata>,
+        res: Packet<UdpHeader, EmptyMetadata>,
+    ) -> Result<
+        Box<Task>,
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+    > {
+        let (req, res) = self.run_stats_native(req, res)?;
+        Ok(respond(req, res))
+    }
+
+    // This function processes run_stats() requests without creating a generator.
+    fn run_stats_native(
+        &self,
+        req: Packet<UdpHeader, EmptyMetadata>,
+        res: Packet<UdpHeader, EmptyMetadata>,
+    ) -> Result<
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+    > {
+        if req.get_payload().len() < size_of::<RunStatsRequest>() {
+            return Err((req, res));
+        }
+
+        // Read fields off the request header.
+        let req = req.parse_header::<RunStatsRequest>();
+        let (tenant, id, stamp, filter) = {
+            let hdr = req.get_header();
+            (
+                hdr.common_header.tenant(),
+                hdr.common_header.id(),
+                hdr.common_header.stamp(),
+                hdr.filter(),
+            )
+        };
+
+        let mut hdr = RunStatsResponse::new(id, stamp, tenant);
+        let mut runs = Vec::new();
+        if tenant != 0 {
+            hdr.common_header.status = RpcStatus::StatusInvalidOperation;
+        } else {
+            runs = match filter {
+                0 => self.runs.recent(RUN_STATS_MAX),
+                run => self.runs.get(run).into_iter().collect(),
+            };
+            hdr.set_num_runs(runs.len() as u32);
+        }
+
+        let payload = rpc::encode_run_stats(&runs);
+        let mut res = res
+            .push_header(&hdr)
+            .expect("Failed to push RunStatsResponse");
+        res.add_to_payload_tail(payload.len(), &payload)
+            .expect("Failed to write run counters into response!");
+
+        return Ok((
+            req.deparse_header(PACKET_UDP_LEN as usize),
+            res.deparse_header(PACKET_UDP_LEN as usize),
+        ));
+    }
+
+    /// Handles the core_stats RPC request.
+    ///
+    /// If issued by tenant 0, responds with the number of pauses each core took while backing
+    /// off polling and the time it spent in them, in each tier, along with the CPU time the
+    /// server process has used.
+    ///
+    /// # Arguments
+    ///
+    /// * `req`: The RPC request packet sent by the client, parsed upto it's UDP header.
+    /// * `res`: The RPC response packet, with pre-allocated headers upto UDP.
+    ///
+    /// # Return
+    ///
+    /// A Native task that sends out the response. If the request was malformed, the passed in
+    /// request and response packets are returned.
+    fn core_stats(
+        &self,
+        req: Packet<UdpHeader, EmptyMetadata>,
+        res: Packet<UdpHeader, EmptyMetadata>,
+    ) -> Result<
+        Box<Task>,
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+    > {
+        let (req, res) = self.core_stats_native(req, res)?;
+        Ok(respond(req, res))
+    }
+
+    // This function processes core_stats() requests without creating a generator.
+    fn core_stats_native(
+        &self,
+        req: Packet<UdpHeader, EmptyMetadata>,
+        res: Packet<UdpHeader, EmptyMetadata>,
+    ) -> Result<
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+    > {
+        if req.get_payload().len() < size_of::<CoreStatsRequest>() {
+            return Err((req, res));
+        }
+
+        // Read fields off the request header.
+        let req = req.parse_header::<CoreStatsRequest>();
+        let (tenant, id, stamp) = {
+            let hdr = req.get_header();
+            (
+                hdr.common_header.tenant(),
+                hdr.common_header.id(),
+                hdr.common_header.stamp(),
+            )
+        };
+
+        let mut hdr = CoreStatsResponse::new(id, stamp, tenant);
+        let mut cores = Vec::new();
+        if tenant != 0 {
+            hdr.common_header.status = RpcStatus::StatusInvalidOperation;
+        } else {
+            cores = self.cores.summaries();
+            cores.truncate(CORE_STATS_MAX);
+            let (user, system) = backoff::cpu_time();
+            hdr.set_num_cores(cores.len() as u32);
+            hdr.set_user_us(user);
+            hdr.set_system_us(system);
+        }
+
+        let payload = rpc::encode_core_stats(&cores);
+        let mut res = res
+            .push_header(&hdr)
+            .expect("Failed to push CoreStatsResponse");
+        res.add_to_payload_tail(payload.len(), &payload)
+            .expect("Failed to write core counters into response!");
+
+        return Ok((
+            req.deparse_header(PACKET_UDP_LEN as usize),
+            res.deparse_header(PACKET_UDP_LEN as usize),
+        ));
+    }
+
+    /// Handles the latency_stats RPC request.
+    ///
+    /// If issued by tenant 0, responds with the time the server took to respond to each opcode
+    /// or extension, merged across it's cores. Refer to latency_stats().
+    ///
+    /// # Arguments
+    ///
+    /// * `req`: The RPC request packet sent by the client, parsed upto it's UDP header.
+    /// * `res`: The RPC response packet, with pre-allocated headers upto UDP.
+    ///
+    /// # Return
+    ///
+    /// A Native task that sends out the response. If the request was malformed, the passed in
+    /// request and response packets are returned.
+    fn latency_stats_rpc(
+        &self,
+        req: Packet<UdpHeader, EmptyMetadata>,
+        res: Packet<UdpHeader, EmptyMetadata>,
+    ) -> Result<
+        Box<Task>,
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+    > {
+        let (req, res) = self.latency_stats_native(req, res)?;
+        Ok(respond(req, res))
+    }
+
+    // This function processes latency_stats() requests without creating a generator.
+    fn latency_stats_native(
+        &self,
+        req: Packet<UdpHeader, EmptyMetadata>,
+        res: Packet<UdpHeader, EmptyMetadata>,
+    ) -> Result<
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+    > {
+        if req.get_payload().len() < size_of::<LatencyStatsRequest>() {
+            return Err((req, res));
+        }
+
+        // Read fields off the request header.
+        let req = req.parse_header::<LatencyStatsRequest>();
+        let (tenant, query, opcode, id, stamp) = {
+            let hdr = req.get_header();
+            (
+                hdr.common_header.tenant(),
+                hdr.query,
+                hdr.opcode,
+                hdr.common_header.id(),
+                hdr.common_header.stamp(),
+            )
+        };
+
+        let mut hdr = LatencyStatsResponse::new(id, stamp, tenant);
+        let stats = match tenant {
+            0 => self.latency_stats(query, opcode, req.get_payload()),
+            _ => Err(RpcStatus::StatusInvalidOperation),
+        };
+        let entries = match stats {
+            Ok((entries, num)) => {
+                hdr.set_num_entries(num);
+                hdr.set_cycles_per_second(cycles::cycles_per_second());
+                entries
+            }
+
+            Err(status) => {
+                hdr.common_header.status = status;
+                Vec::new()
+            }
+        };
+
+        let mut res = res
+            .push_header(&hdr)
+            .expect("Failed to push LatencyStatsResponse");
+        if entries.len() > 0 {
+            res.add_to_payload_tail(entries.len(), &entries)
+                .expect("Failed to write latencies into response!");
+        }
+
+        return Ok((
+            req.deparse_header(PACKET_UDP_LEN as usize),
+            res.deparse_header(PACKET_UDP_LEN as usize),
+        ));
+    }
+
+    /// Handles the quarantine RPC request.
+    ///
+    /// If issued by tenant 0, quarantines the target tenant, releases it, or leaves it be, and
+    /// responds with the target's quarantine along with the quarantines counted across the
+    /// server. Refer to quarantine::Quarantines.
+    ///
+    /// # Arguments
+    ///
+    /// * `req`: The RPC request packet sent by the client, parsed upto it's UDP header.
+    /// * `res`: The RPC response packet, with pre-allocated headers upto UDP.
+    ///
+    /// # Return
+    ///
+    /// A Native task that sends out the response. If the request was malformed, the passed in
+    /// request and response packets are returned.
+    fn quarantine_rpc(
+        &self,
+        req: Packet<UdpHeader, EmptyMetadata>,
+        res: Packet<UdpHeader, EmptyMetadata>,
+    ) -> Result<
+        Box<Task>,
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+    > {
+        let (req, res) = self.quarantine_native(req, res)?;
+        Ok(respond(req, res))
+    }
+
+    // This function processes quarantine() requests without creating a generator.
+    fn quarantine_native(
+        &self,
+        req: Packet<UdpHeader, EmptyMetadata>,
+        res: Packet<UdpHeader, EmptyMetadata>,
+    ) -> Result<
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+    > {
+        if req.get_payload().len() < size_of::<QuarantineRequest>() {
+            return Err((req, res));
+        }
+
+        // Read fields off the request header.
+        let req = req.parse_header::<QuarantineRequest>();
+        let (tenant, id, stamp, action, target, duration) = {
+            let hdr = req.get_header();
+            (
+                hdr.common_header.tenant(),
+                hdr.common_header.id(),
+                hdr.common_header.stamp(),
+                hdr.action,
+                hdr.target(),
+                hdr.duration_ms(),
+            )
+        };
+
+        let mut hdr = QuarantineResponse::new(id, stamp, tenant);
+        let status = match self.quarantine_tenant(tenant, action, target, duration) {
+            Ok(Some(target)) => {
+                let now = cycles::rdtsc();
+                let quarantine = target.quarantine();
+                let remaining = match quarantine.active(now) {
+                    true => quarantine.until() - now,
+                    false => 0,
+                };
+                hdr.set_strikes(quarantine.strikes() as u32);
+                hdr.set_remaining_ms((cycles::to_seconds(remaining) * 1000.0) as u64);
+                RpcStatus::StatusOk
+            }
+
+            Ok(None) => RpcStatus::StatusOk,
+
+            Err(status) => status,
+        };
+        hdr.common_header.status = status;
+
+        let stats = self.quarantines.stats();
+        hdr.set_quarantines(stats.quarantines);
+        hdr.set_rejected(stats.rejected);
+
+        let res = res
+            .push_header(&hdr)
+            .expect("Failed to push QuarantineResponse");
+
+        return Ok((
+            req.deparse_header(PACKET_UDP_LEN as usize),
+            res.deparse_header(PACKET_UDP_LEN as usize),
+        ));
+    }
+
+    /// Handles the create_table RPC request.
+    ///
+    /// Creates one of the issuing tenant's tables, pre-sized for the number of objects on the
+    /// request, and responds with the number of slots reserved on it. Refer to
+    /// create_table_sized().
+    ///
+    /// # Arguments
+    ///
+    /// * `req`: The RPC request packet sent by the client, parsed upto it's UDP header.
+    /// * `res`: The RPC response packet, with pre-allocated headers upto UDP.
+    ///
+    /// # Return
+    ///
+    /// A Native task that sends out the response. If the request was malformed, the passed in
+    /// request and response packets are returned.
+    fn create_table_rpc(
+        &self,
+        req: Packet<UdpHeader, EmptyMetadata>,
+        res: Packet<UdpHeader, EmptyMetadata>,
+    ) -> Result<
+        Box<Task>,
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+    > {
+        let (req, res) = self.create_table_native(req, res)?;
+        Ok(respond(req, res))
+    }
+
+    // This function processes create_table() requests without creating a generator.
+    fn create_table_native(
+        &self,
+        req: Packet<UdpHeader, EmptyMetadata>,
+        res: Packet<UdpHeader, EmptyMetadata>,
+    ) -> Result<
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+    > {
+        if req.get_payload().len() < size_of::<CreateTableRequest>() {
+            return Err((req, res));
+        }
+
+        // Read fields off the request header.
+        let req = req.parse_header::<CreateTableRequest>();
+        let (tenant, id, stamp, table_id, records, cache) = {
+            let hdr = req.get_header();
+            (
+                hdr.common_header.tenant(),
+                hdr.common_header.id(),
+                hdr.common_header.stamp(),
+                hdr.table_id(),
+                hdr.records(),
+                CacheTarget::from_wire(hdr.max_bytes(), hdr.max_objects(), hdr.evict),
+            )
+        };
+
+        // A request with a malformed target creates nothing.
+        let mut hdr = CreateTableResponse::new(id, stamp, tenant);
+        let created = cache
+            .map_err(|_| RpcStatus::StatusMalformedRequest)
+            .and_then(|cache| self.create_table_sized(tenant, table_id, records, cache));
+        hdr.common_header.status = match created {
+            Ok(slots) => {
+                hdr.set_slots(slots as u64);
+                RpcStatus::StatusOk
+            }
+
+            Err(status) => status,
+        };
+
+        let res = res
+            .push_header(&hdr)
+            .expect("Failed to push CreateTableResponse");
+
+        return Ok((
+            req.deparse_header(PACKET_UDP_LEN as usize),
+            res.deparse_header(PACKET_UDP_LEN as usize),
+        ));
+    }
+
+    /// Handles the drop_table RPC request.
+    ///
+    /// Drops one of the issuing tenant's tables. Refer to drop_table().
+    ///
+    /// # Arguments
+    ///
+    /// * `req`: The RPC request packet sent by the client, parsed upto it's UDP header.
+    /// * `res`: The RPC response packet, with pre-allocated headers upto UDP.
+    ///
+    /// # Return
+    ///
+    /// A Native task that sends out the response. If the request was malformed, the passed in
+    /// request and response packets are returned.
+    fn drop_table_rpc(
+        &self,
+        req: Packet<UdpHeader, EmptyMetadata>,
+        res: Packet<UdpHeader, EmptyMetadata>,
+    ) -> Result<
+        Box<Task>,
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+    > {
+        let (req, res) = self.drop_table_native(req, res)?;
+        Ok(respond(req, res))
+    }
+
+    // This function processes drop_table() requests without creating a generator.
+    fn drop_table_native(
+        &self,
+        req: Packet<UdpHeader, EmptyMetadata>,
+        res: Packet<UdpHeader, EmptyMetadata>,
+    ) -> Result<
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+    > {
+        if req.get_payload().len() < size_of::<DropTableRequest>() {
+            return Err((req, res));
+        }
+
+        // Read fields off the request header.
+        let req = req.parse_header::<DropTableRequest>();
+        let (tenant, id, stamp, table_id) = {
+            let hdr = req.get_header();
+            (
+                hdr.common_header.tenant(),
+                hdr.common_header.id(),
+                hdr.common_header.stamp(),
+                hdr.table_id(),
+            )
+        };
+
+        let mut hdr = DropTableResponse::new(id, stamp, tenant);
+        hdr.common_header.status = self.drop_table(tenant, table_id);
+        let res = res
+            .push_header(&hdr)
+            .expect("Failed to push DropTableResponse");
+
+        return Ok((
+            req.deparse_header(PACKET_UDP_LEN as usize),
+            res.deparse_header(PACKET_UDP_LEN as usize),
+        ));
+    }
+
+    /// Handles the fetch_result RPC request.
+    ///
+    /// Removes the result an invocation stored in the issuing tenant's mailbox, and responds
+    /// with it. Refer to fetch_result().
+    ///
+    /// # Arguments
+    ///
+    /// * `req`: The RPC request packet sent by the client, parsed upto it's UDP header.
+    /// * `res`: The RPC response packet, with pre-allocated headers upto UDP.
+    ///
+    /// # Return
+    ///
+    /// A Native task that sends out the response. If the request was malformed, the passed in
+    /// request and response packets are returned.
+    fn fetch_result_rpc(
+        &self,
+        req: Packet<UdpHeader, EmptyMetadata>,
+        res: Packet<UdpHeader, EmptyMetadata>,
+    ) -> Result<
+        Box<Task>,
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+    > {
+        let (req, res) = self.fetch_result_native(req, res)?;
+        Ok(respond(req, res))
+    }
+
+    // This function processes fetch_result() requests without creating a generator.
+    fn fetch_result_native(
+        &self,
+        req: Packet<UdpHeader, EmptyMetadata>,
+        res: Packet<UdpHeader, EmptyMetadata>,
+    ) -> Result<
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+    > {
+        if req.get_payload().len() < size_of::<FetchResultRequest>() {
+            return Err((req, res));
+        }
+
+        // Read fields off the request header.
+        let req = req.parse_header::<FetchResultRequest>();
+        let (tenant, id, stamp, request_id) = {
+            let hdr = req.get_header();
+            (
+                hdr.common_header.tenant(),
+                hdr.common_header.id(),
+                hdr.common_header.stamp(),
+                hdr.request_id(),
+            )
+        };
+
+        let mut hdr = FetchResultResponse::new(id, stamp, tenant);
+        let payload = match self.fetch_result(tenant, request_id) {
+            Ok((result, payload)) => {
+                hdr.result = result;
+                payload
+            }
+
+            Err(status) => {
+                hdr.common_header.status = status;
+                Vec::new()
+            }
+        };
+
+        let mut res = res
+            .push_header(&hdr)
+            .expect("Failed to push FetchResultResponse");
+        if payload.len() > 0 {
+            res.add_to_payload_tail(payload.len(), &payload)
+                .expect("Failed to write result into response!");
+        }
+
+        return Ok((
+            req.deparse_header(PACKET_UDP_LEN as usize),
+            res.deparse_header(PACKET_UDP_LEN as usize),
+        ));
+    }
+
+    /// Quarantines a tenant, releases it, or leaves it be, on behalf of an operator.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`:   The tenant that asked. Only tenant 0 is allowed to.
+    /// * `action`:   QUARANTINE_QUERY, QUARANTINE_ENTER or QUARANTINE_RELEASE.
+    /// * `target`:   The tenant to act on. 0 only reports the counters across the server, and
+    ///               is only allowed with QUARANTINE_QUERY.
+    /// * `duration`: How long to quarantine the target for in milliseconds, on
+    ///               QUARANTINE_ENTER. 0 picks the duration the policy would have.
+    ///
+    /// # Return
+    ///
+    /// The target tenant once the action was applied, None if the target was 0, or the status
+    /// to fail the request with.
+    pub fn quarantine_tenant(
+        &self,
+        tenant: TenantId,
+        action: u8,
+        target: TenantId,
+        duration: u64,
+    ) -> Result<Option<Arc<Tenant>>, RpcStatus> {
+        if tenant != 0 {
+            return Err(RpcStatus::StatusInvalidOperation);
+        }
+
+        match action {
+            QUARANTINE_QUERY | QUARANTINE_ENTER | QUARANTINE_RELEASE => {}
+            _ => return Err(RpcStatus::StatusMalformedRequest),
+        }
+
+        // Tenant 0 is never quarantined, so it can only be queried.
+        match (action, target) {
+            (QUARANTINE_QUERY, 0) => return Ok(None),
+            (_, 0) => return Err(RpcStatus::StatusInvalidOperation),
+            _ => {}
+        }
+
+        let target = self
+            .get_tenant(target)
+            .ok_or(RpcStatus::StatusTenantDoesNotExist)?;
+        let now = cycles::rdtsc();
+        match action {
+            QUARANTINE_ENTER => {
+                self.quarantines.quarantine(&target, duration, now);
+            }
+
+            QUARANTINE_RELEASE => self.quarantines.release(&target, now),
+
+            _ => {}
+        }
+
+        Ok(Some(target))
+    }
+
+    /// Rejects a request received while the server is draining.
+    ///
+    /// # Arguments
+    ///
+    /// * `op`:  The opcode on the request.
+    /// * `req`: The RPC request packet sent by the client, parsed upto it's UDP header.
+    /// * `res`: The RPC response packet, with pre-allocated headers upto UDP.
+    ///
+    /// # Return
+    ///
+    /// A Native task that sends out a response with StatusServerDraining. If the request was
+    /// malformed, the passed in request and response packets are returned.
+    fn reject_draining(
+        &self,
+        op: OpCode,
+        req: Packet<UdpHeader, EmptyMetadata>,
+        res: Packet<UdpHeader, EmptyMetadata>,
+    ) -> Result<
+        Box<Task>,
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+    > {
+        let (req, res) = self.reject_draining_native(op, req, res)?;
+        Ok(respond(req, res))
+    }
+
+    // This function rejects requests received while draining without creating a generator.
+    // Responses carry a DrainResponse header with the request's opcode on it, so that clients
+    // also learn how many tasks are left.
+    fn reject_draining_native(
+        &self,
+        op: OpCode,
+        req: Packet<UdpHeader, EmptyMetadata>,
+        res: Packet<UdpHeader, EmptyMetadata>,
+    ) -> Result<
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+    > {
+        if req.get_payload().len() < size_of::<RpcRequestHeader>() {
+            return Err((req, res));
+        }
+
+        // Wireformat headers are packed, so the pointer does not have to be aligned.
+        let (tenant, id, stamp) = {
+            let hdr = unsafe { &*(req.get_payload().as_ptr() as *const RpcRequestHeader) };
+            (hdr.tenant(), hdr.id(), hdr.stamp())
+        };
+
+        let mut hdr = DrainResponse::new(id, stamp, tenant);
+        hdr.common_header.status = RpcStatus::StatusServerDraining;
+        hdr.common_header.opcode = op;
+        hdr.set_remaining(self.drain.progress().iter().sum());
+
+        let res = res.push_header(&hdr).expect("Failed to push DrainResponse");
+        return Ok((req, res.deparse_header(PACKET_UDP_LEN as usize)));
+    }
+
+    // This function rejects requests with an opcode the server is configured not to serve.
+    fn reject_disabled(
+        &self,
+        op: OpCode,
+        req: Packet<UdpHeader, EmptyMetadata>,
+        res: Packet<UdpHeader, EmptyMetadata>,
+    ) -> Result<
+        Box<Task>,
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+    > {
+        let (req, res) = self.reject_disabled_native(op, req, res)?;
+        Ok(respond(req, res))
+    }
+
+    // This function rejects requests with an opcode the server is configured not to serve,
+    // without creating a generator. Responses carry just the common header, with the request's
+    // opcode and StatusOperationDisabled on it.
+    fn reject_disabled_native(
+        &self,
+        op: OpCode,
+        req: Packet<UdpHeader, EmptyMetadata>,
+        res: Packet<UdpHeader, EmptyMetadata>,
+    ) -> Result<
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+    > {
+        if req.get_payload().len() < size_of::<RpcRequestHeader>() {
+            return Err((req, res));
+        }
+
+        // Wireformat headers are packed, so the pointer does not have to be aligned.
+        let (tenant, id, stamp) = {
+            let hdr = unsafe { &*(req.get_payload().as_ptr() as *const RpcRequestHeader) };
+            (hdr.tenant(), hdr.id(), hdr.stamp())
+        };
+
+        let mut hdr = RpcResponseHeader::new(id, stamp, op, tenant);
+        hdr.status = RpcStatus::StatusOperationDisabled;
+
+        let res = res
+            .push_header(&hdr)
+            .expect("Failed to push RpcResponseHeader");
+        return Ok((req, res.deparse_header(PACKET_UDP_LEN as usize)));
+    }
+
+    /// Refuses a request from a quarantined tenant, without creating a task or allocating.
+    /// Responses carry just the common header, with the request's opcode and
+    /// StatusTenantQuarantined on it; get() responses carry an empty GetResponse header, so that
+    /// their value length matches.
+    ///
+    /// # Arguments
+    ///
+    /// * `op`:  The opcode on the request.
+    /// * `req`: The RPC request packet sent by the client, parsed upto it's UDP header.
+    /// * `res`: The RPC response packet, with pre-allocated headers upto UDP.
+    ///
+    /// # Return
+    ///
+    /// The request and the response, which is ready to be sent out. If the request is too short
+    /// to carry a common header, the passed in request and response packets are returned.
+    pub fn reject_quarantined_native(
+        &self,
+        op: OpCode,
+        req: Packet<UdpHeader, EmptyMetadata>,
+        res: Packet<UdpHeader, EmptyMetadata>,
+    ) -> Result<
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+    > {
+        if req.get_payload().len() < size_of::<RpcRequestHeader>() {
+            return Err((req, res));
+        }
+
+        // Wireformat headers are packed, so the pointer does not have to be aligned.
+        let (tenant, id, stamp) = {
+            let hdr = unsafe { &*(req.get_payload().as_ptr() as *const RpcRequestHeader) };
+            (hdr.tenant(), hdr.id(), hdr.stamp())
+        };
+        self.quarantines.rejected();
+
+        let res = match op {
+            OpCode::SandstormGetRpc => {
+                let mut hdr = GetResponse::new(id, stamp, op, tenant);
+                hdr.common_header.status = RpcStatus::StatusTenantQuarantined;
+                res.push_header(&hdr)
+                    .expect("Failed to push GetResponse")
+                    .deparse_header(PACKET_UDP_LEN as usize)
+            }
+
+            _ => {
+                let mut hdr = RpcResponseHeader::new(id, stamp, op, tenant);
+                hdr.status = RpcStatus::StatusTenantQuarantined;
+                res.push_header(&hdr)
+                    .expect("Failed to push RpcResponseHeader")
+                    .deparse_header(PACKET_UDP_LEN as usize)
+            }
+        };
+        return Ok((req, res));
+    }
+
+    /// Handles the invoke RPC request.
+    ///
+    /// If issued by a valid tenant for a valid extension, invokes the extension.
+    ///
+    /// # Arguments
+    ///
+    /// * `req`: The RPC request packet sent by the client, parsed upto it's UDP header.
+    /// * `res`: The RPC response packet, with pre-allocated headers upto UDP.
+    ///
+    /// # Return
+    ///
+    /// A Container task that can be scheduled by the database. In the case of an error, the passed
+    /// in request and response packets are returned with the response status appropriately set.
+    fn invoke(
+        &self,
+        req: Packet<UdpHeader, EmptyMetadata>,
+        res: Packet<UdpHeader, EmptyMetadata>,
+    ) -> Result<
+        Box<Task>,
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+    > {
+        self.invoke_task(req, res, None, None)
+    }
+
+    /// Handles the routed invoke() RPC request.
+    ///
+    /// Picks the extension off the issuing tenant's routing rules, by the longest prefix of the
+    /// key on the request. The request is then laid out in place as an invoke() of that
+    /// extension (see route::to_invoke()), and proceeds exactly like one. The extension receives
+    /// the key and arguments along with the length of the prefix that matched; refer to
+    /// sandstorm::route.
+    ///
+    /// # Arguments
+    ///
+    /// * `req`: The RPC request packet sent by the client, parsed upto it's UDP header.
+    /// * `res`: The RPC response packet, with pre-allocated headers upto UDP.
+    ///
+    /// # Return
+    ///
+    /// Refer to invoke(). If the request could not be routed, a Native task that sends out a
+    /// response with the reason on it (ex: StatusNoRouteMatched).
+    fn routed_invoke(
+        &self,
+        req: Packet<UdpHeader, EmptyMetadata>,
+        res: Packet<UdpHeader, EmptyMetadata>,
+    ) -> Result<
+        Box<Task>,
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+    > {
+        if req.get_payload().len() < size_of::<RoutedInvokeRequest>() {
+            return Err((req, res));
+        }
+
+        // Wireformat headers are packed, so the pointer does not have to be aligned.
+        let (tenant_id, id, stamp, key_length, args_length) = {
+            let hdr = unsafe { &*(req.get_payload().as_ptr() as *const RoutedInvokeRequest) };
+            (
+                hdr.common_header.tenant(),
+                hdr.common_header.id(),
+                hdr.common_header.stamp(),
+                hdr.key_length() as usize,
+                hdr.args_length() as usize,
+            )
+        };
+
+        let mut req = req;
+        let key = size_of::<RoutedInvokeRequest>();
+        let end = key + key_length + args_length;
+        let status = match self.tenant(tenant_id) {
+            Some(_) if req.get_payload().len() < end => RpcStatus::StatusMalformedRequest,
+
+            Some(tenant) => {
+                let routes = tenant.routes();
+                let matched = routes.lookup(&req.get_payload()[key..key + key_length]);
+                match matched {
+                    Some((prefix_length, name)) => {
+                        // The request grows by the name of the extension and the header on it's
+                        // arguments. It is too long to be routed if the mbuf has no room left.
+                        let grow = route::growth(name.len());
+                        match req.increase_payload_size(grow) == grow {
+                            true => {
+                                route::to_invoke(
+                                    &mut req.get_mut_payload()[..end + grow],
+                                    name,
+                                    prefix_length,
+                                );
+                                RpcStatus::StatusOk
+                            }
+
+                            false => RpcStatus::StatusMalformedRequest,
+                        }
+                    }
+
+                    None => RpcStatus::StatusNoRouteMatched,
+                }
+            }
+
+            None => RpcStatus::StatusTenantDoesNotExist,
+        };
+
+        if status == RpcStatus::StatusOk {
+            return self.invoke(req, res);
+        }
+
+        let mut hdr = InvokeResponse::new(id, stamp, OpCode::SandstormRoutedInvokeRpc, tenant_id);
+        hdr.common_header.status = status;
+        let res = res
+            .push_header(&hdr)
+            .expect("Failed to push InvokeResponse");
+        Ok(respond(req, res.deparse_header(PACKET_UDP_LEN as usize)))
+    }
+
+    /// Common implementation of invoke(), recover_durable() and defer().
+    ///
+    /// # Arguments
+    ///
+    /// * `req`:       The RPC request packet, parsed upto it's UDP header.
+    /// * `res`:       The RPC response packet, with pre-allocated headers upto UDP.
+    /// * `recovered`: The durable invocation being resumed, if the request was rebuilt from the
+    ///                journal. None for requests received over the network.
+    /// * `deferral`:  The tenant's deferral counters, if the request runs deferred work. The
+    ///                caller releases the slot taken on them if this fails.
+    ///
+    /// # Return
+    ///
+    /// Refer to invoke().
+    #[allow(unused_assignments)]
+    fn invoke_task(
+        &self,
+        req: Packet<UdpHeader, EmptyMetadata>,
+        res: Packet<UdpHeader, EmptyMetadata>,
+        recovered: Option<PendingTask>,
+        deferral: Option<Arc<Deferrals>>,
+    ) -> Result<
+        Box<Task>,
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+    > {
+        // First, parse the request packet.
+        let req = req.parse_header::<InvokeRequest>();
+
+        // Read fields of the request header.
+        let mut tenant_id: TenantId = 0;
+        let mut name_length: usize = 0;
+        let mut args_length: usize = 0;
+        let mut rpc_id = 0;
+        let mut rpc_stamp = 0;
+        let mut durable = recovered.is_some();
+        let mut store = false;
+        let mut by_id = false;
+
+        {
+            let hdr = req.get_header();
+            tenant_id = hdr.common_header.tenant() as TenantId;
+            name_length = hdr.name_length() as usize;
+            args_length = hdr.args_length() as usize;
+            rpc_id = hdr.common_header.id();
+            rpc_stamp = hdr.common_header.stamp();
+            durable |= hdr.flags & INVOKE_FLAG_DURABLE != 0;
+            store = hdr.flags & INVOKE_FLAG_STORE_RESULT != 0;
+            by_id = hdr.flags & INVOKE_FLAG_BY_ID != 0;
+        }
+
+        // Next, add a header to the response packet.
+        let mut res = res
+            .push_header(&InvokeResponse::new(
+                rpc_id,
+                rpc_stamp,
+                OpCode::SandstormInvokeRpc,
+                tenant_id,
+            )).expect("Failed to push InvokeResponse");
+
+        // Resolve the tenant and extension, and create a Container for the extension. If either
+        // fails, the status to respond with is set on the response further below.
+        let resolved = self.resolve_invoke(
+            tenant_id,
+            req.get_payload(),
+            name_length,
+            args_length,
+            by_id,
+            deferral.is_some(),
+        );
+        let (status, interned) = match resolved {
+            Err(refused) => refused,
+
+            Ok((tenant, ext, interned)) => {
+                let alloc = accessor(&self.heap as *const Allocator);
+
+                // Durable invocations are recorded in the journal, and run in the
+                // background. If durability is disabled, the durable flag is ignored.
+                let journaled = match durable && self.journal.is_some() {
+                    true => {
+                        let name = invoked_name(&req, name_length, &interned);
+                        let args = &req.get_payload()[name_length..][..args_length];
+                        self.durable(tenant_id, name, args, recovered).map(Some)
+                    }
+                    false => Some(None),
+                };
+
+                match journaled {
+                    // The invocation could not be recorded in the journal.
+                    None => (RpcStatus::StatusInternalError, interned),
+
+                    // Deferred work runs in the background too.
+                    Some(journaled) => {
+                        let prio = match journaled.is_some() || deferral.is_some() {
+                            true => TaskPriority::BACKGROUND,
+                            false => TaskPriority::REQUEST,
+                        };
+
+                        let crumb = Breadcrumb::invoke(
+                            tenant_id,
+                            rpc_id,
+                            invoked_name(&req, name_length, &interned),
+                        );
+                        let audit = tenant.audit().map(|log| {
+                            let name = invoked_name(&req, name_length, &interned);
+                            let entry = AuditEntry {
+                                name_hash: audit::name_hash(name),
+                                id: rpc_id,
+                                args_len: args_length as u32,
+                                ..Default::default()
+                            };
+                            (log, entry)
+                        });
+
+                        // Get the model for the given extension. If the extension doesn't
+                        // need an ML model, don't waste CPU cycles in lookup.
+                        let mut model = None;
+                        if cfg!(feature = "ml-model") {
+                            // The lookup validated the name, or it was loaded as a &str.
+                            let name = invoked_name(&req, name_length, &interned);
+                            let name = unsafe { from_utf8_unchecked(name) };
+                            GLOBAL_MODEL.with(|a_model| {
+                                if let Some(a_model) = (*a_model).borrow().get(name) {
+                                    model = Some(Arc::clone(a_model));
+                                }
+                            });
+                        }
+
+                        let mut context =
+                            Context::new(req, name_length, args_length, res, tenant, alloc, model);
+                        if let Some(name) = interned {
+                            context.set_name(name);
+                        }
+                        // Durable invocations write their result to a table, and so do
+                        // those that asked for it to be stored, while deferred work has no
+                        // one to respond to; only the others can stream.
+                        match (journaled, deferral) {
+                            (Some(journaled), _) => context.set_durable(journaled),
+                            (None, Some(deferrals)) => context.set_deferral(deferrals),
+                            (None, None) if store => context.set_mailbox(self.mailbox),
+                            (None, None) => context.stream(self.max_stream_bytes),
+                        }
+
+                        let db = Rc::new(context);
+                        let gen = ext.get(Rc::clone(&db) as Rc<DB>);
+
+                        let mut container = Container::new(prio, db, gen);
+                        container.extension(ext);
+                        container.breadcrumb(crumb);
+                        if let Some((log, entry)) = audit {
+                            container.audit(log, entry);
+                        }
+                        return Ok(Box::new(container));
+                    }
+                }
+            }
+        };
+
+        // A Task could not be created. Set the status of the RPC and return. Requests too short
+        // to hold their name and arguments aren't audited.
+        if req.get_payload().len() >= name_length + args_length {
+            self.audit_refused(
+                tenant_id,
+                invoked_name(&req, name_length, &interned),
+                rpc_id,
+                args_length,
+                status.clone(),
+            );
+        }
+        res.get_mut_header().common_header.status = status;
+
+        return Err((
+            req.deparse_header(PACKET_UDP_LEN as usize),
+            res.deparse_header(PACKET_UDP_LEN as usize),
+        ));
+    }
+
+    /// Handles the install() RPC request.
+    ///
+    /// If issued by a valid tenant, installs (loads) an extension into the database.
+    ///
+    /// # Arguments
+    ///
+    /// * `buf`: The RPC buffer consisting of the request header followed by the payload.
+    ///
+    /// # Return
+    ///
+    /// A response buffer that can be sent back to the tenant.
+    pub fn install(&self, buf: Vec<u8>) -> Vec<u8> {
+        // First off, parse the RPC header.
+        let hdr = buf.as_ptr() as *const InstallRequest;
+
+        let tenant: TenantId;
+        let name_l: usize;
+        let extn_l: usize;
+        let id: u64;
+        let tstamp: u64;
+
+        unsafe {
+            tenant = (*hdr).common_header.tenant() as TenantId;
+            name_l = (*hdr).name_length() as usize;
+            extn_l = (*hdr).extn_length() as usize;
+            id = (*hdr).common_header.id();
+            tstamp = (*hdr).common_header.stamp();
+        }
+
+        // Create a response for the tenant.
+        let mut res =
+            InstallResponse::new(id, tstamp, OpCode::SandstormInstallRpc, tenant as u32);
+        res.common_header.status = RpcStatus::StatusTenantDoesNotExist;
+
+        // Check if the tenant provided lengths match the actual request length.
+        if buf.len() != size_of::<InstallRequest>() + name_l + extn_l {
+            res.common_header.status = RpcStatus::StatusMalformedRequest;
+            let res: [u8; size_of::<InstallResponse>()] = unsafe { transmute(res) };
+            let mut ret: Vec<u8> = Vec::new();
+            ret.extend_from_slice(&res);
+            return ret;
+        }
+
+        // Save the extension to a .so file. If all goes well, load it into the server.
+        if let Some(_) = self.get_tenant(tenant) {
+            res.common_header.status = RpcStatus::StatusInternalError;
+
+            let (_, payload) = buf.split_at(size_of::<InstallRequest>());
+            let (name, payload) = payload.split_at(name_l);
+            let (extn, _) = payload.split_at(extn_l);
+
+            if let Ok(name) = from_utf8(name) {
+                let mut path = String::new();
+                path.push_str("/tmp/");
+                path.push_str(name);
+                path.push_str(".so");
+
+                // No need for error handling here. If a file could not be created or
+                // written to, then it might be better to just crash the server and recover.
+                let mut file = File::create(path.clone()).unwrap();
+                let _ = file.write_all(extn).unwrap();
+                let _ = file.sync_all().unwrap();
+
+                let loaded = match self.extensions {
+                    Some(ref extensions) => extensions.load(&path, tenant, name),
+                    None => false,
+                };
+                if loaded {
+                    res.common_header.status = RpcStatus::StatusOk;
+                }
+            }
+        }
+
+        let res: [u8; size_of::<InstallResponse>()] = unsafe { transmute(res) };
+        let mut ret: Vec<u8> = Vec::new();
+        ret.extend_from_slice(&res);
+        return ret;
+    }
+
+    // This function calls the handler of a request's opcode.
+    fn handle(
+        &self,
+        op: OpCode,
+        req: Packet<UdpHeader, EmptyMetadata>,
+        res: Packet<UdpHeader, EmptyMetadata>,
+    ) -> Result<
+        Box<Task>,
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+    > {
+        match op {
+            OpCode::SandstormGetRpc => {
+                return self.get(req, res);
+            }
+
+            OpCode::SandstormPutRpc => {
+                return self.put(req, res);
+            }
+
+            OpCode::SandstormMultiGetRpc => {
+                return self.multiget(req, res);
+            }
+
+            OpCode::SandstormInvokeRpc => {
+                return self.invoke(req, res);
+            }
+
+            OpCode::SandstormListExtRpc => {
+                return self.list_extensions(req, res);
+            }
+
+            OpCode::SandstormDrainRpc => {
+                return self.start_drain(req, res);
+            }
+
+            OpCode::SandstormTableAccessRpc => {
+                return self.table_access(req, res);
+            }
+
+            OpCode::SandstormRunStatsRpc => {
+                return self.run_stats(req, res);
+            }
+
+            OpCode::SandstormMergeRpc => {
+                return self.merge(req, res);
+            }
+
+            OpCode::SandstormSetMergeRpc => {
+                return self.set_merge(req, res);
+            }
+
+            OpCode::SandstormRoutedInvokeRpc => {
+                return self.routed_invoke(req, res);
+            }
+
+            OpCode::SandstormSetRouteRpc => {
+                return self.set_route(req, res);
+            }
+
+            OpCode::SandstormAuditRpc => {
+                return self.audit(req, res);
+            }
+
+            OpCode::SandstormDumpRpc => {
+                return self.dump(req, res);
+            }
+
+            OpCode::SandstormMultiPutRpc => {
+                return self.multiput(req, res);
+            }
+
+            OpCode::SandstormWriteStatsRpc => {
+                return self.write_stats_rpc(req, res);
+            }
+
+            OpCode::SandstormCoreStatsRpc => {
+                return self.core_stats(req, res);
+            }
+
+            OpCode::SandstormLatencyStatsRpc => {
+                return self.latency_stats_rpc(req, res);
+            }
+
+            OpCode::SandstormQuarantineRpc => {
+                return self.quarantine_rpc(req, res);
+            }
+
+            OpCode::SandstormCreateTableRpc => {
+                return self.create_table_rpc(req, res);
+            }
+
+            OpCode::SandstormFetchResultRpc => {
+                return self.fetch_result_rpc(req, res);
+            }
+
+            OpCode::SandstormDeleteRpc => {
+                return self.delete(req, res);
+            }
+
+            OpCode::SandstormCasRpc => {
+                return self.cas(req, res);
+            }
+
+            OpCode::SandstormDropTableRpc => {
+                return self.drop_table_rpc(req, res);
+            }
+
+            OpCode::SandstormScanRpc => {
+                return self.scan(req, res);
+            }
+
+            _ => {
+                return Err((req, res));
+            }
+        }
+    }
+}
+
+/// Implementation of the Service trait for Master, allowing it to service RPC requests.
+impl Service for Master {
+    /// Lookup the Service trait for documentation.
+    fn dispatch(
+        &self,
+        op: OpCode,
+        req: Packet<UdpHeader, EmptyMetadata>,
+        res: Packet<UdpHeader, EmptyMetadata>,
+    ) -> Result<
+        Box<Task>,
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+    > {
+        // A draining server rejects everything but drain() RPCs.
+        if op != OpCode::SandstormDrainRpc && self.drain.draining() {
+            return self.reject_draining(op, req, res);
+        }
+
+        // One AND decides whether the server is configured to serve the request.
+        if !self.serves(&op) {
+            return self.reject_disabled(op, req, res);
+        }
+
+        // Retransmits of requests that were already executed are answered from the replay table.
+        if let Some(ref replay) = self.replay {
+            if replay.covers(&op) {
+                match replay.admit(&req) {
+                    Admit::Execute => {}
+
+                    Admit::Replay(response) => {
+                        let mut res = res;
+                        res.add_to_payload_tail(response.len(), &response)
+                            .expect("Failed to write replayed response!");
+                        return Ok(respond(req, res));
+                    }
+
+                    Admit::InFlight => return Err((req, res)),
+                }
+            }
+        }
+
+        // Based on the opcode, call the relevant RPC handler. Requests the handler could not make
+        // sense of are dropped, and count against the tenant that sent them.
+        let handled = self.handle(op, req, res);
+        if let Err((ref req, _)) = handled {
+            self.malformed(req, cycles::rdtsc());
+        }
+        handled
+    }
+
+    fn dispatch_invoke(
+        &self,
+        req: Packet<UdpHeader, EmptyMetadata>,
+        res: Packet<UdpHeader, EmptyMetadata>,
+    ) -> Result<
+        Box<Task>,
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+    > {
+        if self.drain.draining() {
+            return self.reject_draining(OpCode::SandstormInvokeRpc, req, res);
+        }
+
+        if !self.serves(&OpCode::SandstormInvokeRpc) {
+            return self.reject_disabled(OpCode::SandstormInvokeRpc, req, res);
+        }
+
+        return self.invoke(req, res);
+    }
+
+    #[inline]
+    fn service_native(
+        &self,
+        op: OpCode,
+        req: Packet<UdpHeader, EmptyMetadata>,
+        res: Packet<UdpHeader, EmptyMetadata>,
+    ) -> Result<
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+        (
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        ),
+    > {
+        // A draining server rejects everything but drain() RPCs.
+        if op != OpCode::SandstormDrainRpc && self.drain.draining() {
+            return self.reject_draining_native(op, req, res);
+        }
+
+        if !self.serves(&op) {
+            return self.reject_disabled_native(op, req, res);
+        }
+
+        // Based on the opcode, call the relevant RPC handler.
+        match op {
+            OpCode::SandstormGetRpc => {
+                return self.get_native(req, res);
+            }
+
+            OpCode::SandstormPutRpc => {
+                return self.put_native(req, res);
             }
 
             OpCode::SandstormMultiGetRpc => {
                 return self.multiget_native(req, res);
             }
 
+            OpCode::SandstormListExtRpc => {
+                return self.list_extensions_native(req, res);
+            }
+
+            OpCode::SandstormDrainRpc => {
+                return self.start_drain_native(req, res);
+            }
+
+            OpCode::SandstormTableAccessRpc => {
+                return self.table_access_native(req, res);
+            }
+
+            OpCode::SandstormRunStatsRpc => {
+                return self.run_stats_native(req, res);
+            }
+
+            OpCode::SandstormMergeRpc => {
+                return self.merge_native(req, res);
+            }
+
+            OpCode::SandstormSetMergeRpc => {
+                return self.set_merge_native(req, res);
+            }
+
+            OpCode::SandstormSetRouteRpc => {
+                return self.set_route_native(req, res);
+            }
+
+            OpCode::SandstormAuditRpc => {
+                return self.audit_native(req, res);
+            }
+
+            OpCode::SandstormDumpRpc => {
+                return self.dump_native(req, res);
+            }
+
+            OpCode::SandstormMultiPutRpc => {
+                return self.multiput_native(req, res);
+            }
+
+            OpCode::SandstormWriteStatsRpc => {
+                return self.write_stats_native(req, res);
+            }
+
+            OpCode::SandstormCoreStatsRpc => {
+                return self.core_stats_native(req, res);
+            }
+
+            OpCode::SandstormLatencyStatsRpc => {
+                return self.latency_stats_native(req, res);
+            }
+
+            OpCode::SandstormQuarantineRpc => {
+                return self.quarantine_native(req, res);
+            }
+
+            OpCode::SandstormCreateTableRpc => {
+                return self.create_table_native(req, res);
+            }
+
+            OpCode::SandstormFetchResultRpc => {
+                return self.fetch_result_native(req, res);
+            }
+
+            OpCode::SandstormDeleteRpc => {
+                return self.delete_native(req, res);
+            }
+
+            OpCode::SandstormCasRpc => {
+                return self.cas_native(req, res);
+            }
+
+            OpCode::SandstormDropTableRpc => {
+                return self.drop_table_native(req, res);
+            }
+
             _ => {
                 return Err((req, res));
             }
         }
     }
 }
+
+// This module contains unit tests for the opcodes Master serves.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::fs;
+    use std::ops::{Generator, GeneratorState};
+    use std::process;
+    use std::thread;
+
+    use super::super::evict::EvictPolicy;
+    use super::super::limits;
+    use super::super::task::TaskState;
+
+    use sandstorm::entry::ExtError;
+    use sandstorm::mock::MockDB;
+    use sandstorm::put;
+
+    // Every opcode a client can send.
+    const OPCODES: [OpCode; 27] = [
+        OpCode::SandstormGetRpc,
+        OpCode::SandstormPutRpc,
+        OpCode::SandstormInvokeRpc,
+        OpCode::SandstormInstallRpc,
+        OpCode::SandstormMultiGetRpc,
+        OpCode::SandstormEchoRpc,
+        OpCode::SandstormListExtRpc,
+        OpCode::SandstormDrainRpc,
+        OpCode::SandstormTableAccessRpc,
+        OpCode::SandstormRunStatsRpc,
+        OpCode::SandstormMergeRpc,
+        OpCode::SandstormSetMergeRpc,
+        OpCode::SandstormRoutedInvokeRpc,
+        OpCode::SandstormSetRouteRpc,
+        OpCode::SandstormAuditRpc,
+        OpCode::SandstormDumpRpc,
+        OpCode::SandstormMultiPutRpc,
+        OpCode::SandstormWriteStatsRpc,
+        OpCode::SandstormCoreStatsRpc,
+        OpCode::SandstormLatencyStatsRpc,
+        OpCode::SandstormQuarantineRpc,
+        OpCode::SandstormCreateTableRpc,
+        OpCode::SandstormFetchResultRpc,
+        OpCode::SandstormDeleteRpc,
+        OpCode::SandstormScanRpc,
+        OpCode::SandstormCasRpc,
+        OpCode::SandstormDropTableRpc,
+    ];
+
+    // Tests that a Master configured with a single opcode rejects every other one, except for
+    // echo() and drain().
+    #[test]
+    fn test_opcodes_rejected() {
+        for enabled in OPCODES.iter() {
+            let master = Master::with_opcodes(enabled.bit());
+            for op in OPCODES.iter() {
+                let required = OPCODES_REQUIRED & op.bit() != 0;
+                assert_eq!(
+                    op == enabled || required,
+                    master.serves(op),
+                    "{:?} with only {:?} enabled",
+                    op,
+                    enabled
+                );
+            }
+        }
+
+        let master = Master::new();
+        assert!(OPCODES.iter().all(|op| master.serves(op)));
+    }
+
+    // Tests that the extension manager is only built if an extension related opcode is served.
+    #[test]
+    fn test_opcodes_extensions_skipped() {
+        let native = OpCode::SandstormGetRpc.bit() | OpCode::SandstormPutRpc.bit();
+        let master = Master::with_opcodes(native);
+        assert!(master.extensions.is_none());
+
+        // Loading extensions is a no-op, even though none of them are built.
+        master.load_test(1);
+        master.load_test_shared(2);
+
+        let extension_ops = [
+            OpCode::SandstormInvokeRpc,
+            OpCode::SandstormInstallRpc,
+            OpCode::SandstormListExtRpc,
+            OpCode::SandstormMergeRpc,
+            OpCode::SandstormSetMergeRpc,
+            OpCode::SandstormRoutedInvokeRpc,
+            OpCode::SandstormSetRouteRpc,
+        ];
+        for op in extension_ops.iter() {
+            assert!(Master::with_opcodes(native | op.bit()).extensions.is_some());
+        }
+        assert!(Master::new().extensions.is_some());
+    }
+
+    // Tests that the durable journal isn't opened if invoke() isn't served.
+    #[test]
+    fn test_opcodes_journal_skipped() {
+        let mut path = env::temp_dir();
+        path.push(format!("sandstorm-journal-opcodes-{}", process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut config = ServerConfig::default();
+        config.durable_journal = path.to_string_lossy().into_owned();
+
+        let mut master = Master::with_opcodes(OpCode::SandstormGetRpc.bit());
+        master
+            .enable_durable(&config)
+            .expect("Failed to skip journal.");
+        assert!(master.journal.is_none());
+        assert!(!path.exists());
+    }
+
+    // Tests that only invokes issued by an audited tenant make it into it's audit log, and that
+    // the tenant pages through the latest of them, newest first.
+    #[test]
+    fn test_audit_tenants() {
+        let master = Master::new();
+        master.insert_tenant(Tenant::new(1));
+        master.insert_tenant(Tenant::new(2));
+
+        assert_eq!(
+            Err(RpcStatus::StatusTenantDoesNotExist),
+            master.enable_audit(3, 32)
+        );
+        assert_eq!(Ok(()), master.enable_audit(1, 32));
+        assert!(master.get_tenant(2).unwrap().audit().is_none());
+
+        // Completed invokes are recorded the way Container::tear() records them. Every third
+        // invoke is refused before it runs.
+        let log = master.get_tenant(1).unwrap().audit().unwrap();
+        for i in 0..40 {
+            for tenant in 1..3 {
+                let id = tenant as u64 * 1000 + i;
+                if i % 3 == 0 {
+                    let status = RpcStatus::StatusInvalidExtension;
+                    master.audit_refused(tenant, b"nope", id, 8, status);
+                } else if tenant == 1 {
+                    log.record(&AuditEntry {
+                        stamp: cycles::rdtsc(),
+                        name_hash: audit::name_hash(b"auth"),
+                        id: id,
+                        status: RpcStatus::StatusOk as u8,
+                        args_len: 8,
+                        res_len: 16,
+                        cycles: 500,
+                        ..Default::default()
+                    });
+                }
+            }
+        }
+
+        // The log wrapped; only the 32 latest invokes are left, over two pages.
+        let (first, cursor) = master.audit_page(1, 0).unwrap();
+        let (second, last) = master.audit_page(1, cursor).unwrap();
+        assert_eq!((24, 8, 0), (first.len(), second.len(), last));
+
+        let entries: Vec<AuditEntry> = first.into_iter().chain(second.into_iter()).collect();
+        let ids: Vec<u64> = entries.iter().map(|e| e.id).collect();
+        assert_eq!((1008..1040).rev().collect::<Vec<u64>>(), ids);
+        assert!(entries.windows(2).all(|w| w[0].seq == w[1].seq + 1));
+
+        for entry in entries.iter() {
+            let (name, status, res_len) = match entry.id % 3 {
+                0 => (b"nope", RpcStatus::StatusInvalidExtension, 0),
+                _ => (b"auth", RpcStatus::StatusOk, 16),
+            };
+            assert_eq!(audit::name_hash(name), entry.name_hash);
+            assert_eq!(status as u8, entry.status);
+            assert_eq!((8, res_len), (entry.args_len, entry.res_len));
+        }
+
+        assert_eq!(Err(RpcStatus::StatusAuditDisabled), master.audit_page(2, 0));
+    }
+
+    // Dumps a whole table page by page, the way a client would.
+    fn dump_all(master: &Master, tenant: TenantId, budget: usize) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let (mut records, mut bucket, mut after) = (Vec::new(), 0, Vec::new());
+        loop {
+            let (page, num, b) = master
+                .dump_table(tenant, 1, bucket, &after, budget)
+                .expect("Failed to dump table.");
+            if num == 0 {
+                return records;
+            }
+
+            let page = rpc::parse_kvs(&page, num).expect("Malformed page.");
+            assert!(page.windows(2).all(|w| w[0].0 < w[1].0));
+            bucket = b;
+            after = page[page.len() - 1].0.to_vec();
+            records.extend(page.into_iter().map(|(k, v)| (k.to_vec(), v.to_vec())));
+        }
+    }
+
+    // Tests that paging through a dump returns every record in a table exactly once, and that
+    // the records can be written to another table in batches.
+    #[test]
+    fn test_dump_multiput() {
+        let master = Master::new();
+        master.fill_test(1, 1, 500);
+        master.fill_test(2, 1, 0);
+
+        let records = dump_all(&master, 1, 256);
+        assert_eq!(500, records.len());
+        let mut keys: Vec<&Vec<u8>> = records.iter().map(|r| &r.0).collect();
+        keys.sort();
+        keys.dedup();
+        assert_eq!(500, keys.len());
+        assert_eq!(records, dump_all(&master, 1, 1));
+
+        for chunk in records.chunks(64) {
+            let mut batch = Vec::new();
+            for &(ref key, ref val) in chunk.iter() {
+                rpc::append_kv(&mut batch, key, val);
+            }
+            let num = chunk.len() as u32;
+            assert_eq!(RpcStatus::StatusOk, master.put_records(2, 1, &batch, num));
+        }
+        assert_eq!(records, dump_all(&master, 2, 1024));
+
+        assert_eq!(
+            Err(RpcStatus::StatusTableDoesNotExist),
+            master.dump_table(1, 2, 0, &[], 1024)
+        );
+    }
+
+    // Tests that paging through a scan returns every record in a table exactly once and in key
+    // order, whether pages are cut short by their budget, their number of records, or span
+    // several batches, and that a scan can start from any key.
+    #[test]
+    fn test_scan_table() {
+        let master = Master::new();
+        master.fill_test(1, 1, 500);
+
+        let mut sorted = dump_all(&master, 1, 1024);
+        sorted.sort();
+        for &(max, budget) in [(0, 1400), (7, 1400), (0, 300), (0, 16 * 1024)].iter() {
+            let (mut records, mut token, mut after) = (Vec::new(), Vec::new(), false);
+            loop {
+                let (page, num, skipped, next) = master
+                    .scan_table(1, 1, &token, after, max, budget)
+                    .expect("Failed to scan table.");
+                assert!(max == 0 || num <= max);
+                assert_eq!(0, skipped);
+                assert!(page.len() + next.len() <= budget);
+
+                let page = rpc::parse_kvs(&page, num).expect("Malformed page.");
+                records.extend(page.into_iter().map(|(k, v)| (k.to_vec(), v.to_vec())));
+                if next.is_empty() {
+                    break;
+                }
+                assert_eq!(records[records.len() - 1].0, next);
+                token = next;
+                after = true;
+            }
+            assert_eq!(sorted, records);
+        }
+
+        // The key a scan starts from is returned unless the scan is resumed after it.
+        let first = |after| {
+            let (page, num, _, _) = master
+                .scan_table(1, 1, &sorted[100].0, after, 1, 1400)
+                .unwrap();
+            rpc::parse_kvs(&page, num).unwrap()[0].0.to_vec()
+        };
+        assert_eq!(sorted[100].0, first(false));
+        assert_eq!(sorted[101].0, first(true));
+
+        // Records too large for any page are passed over. Not even a key fits in 20 bytes.
+        assert_eq!(
+            Ok((Vec::new(), 0, 500, Vec::new())),
+            master.scan_table(1, 1, &[], false, 0, 100)
+        );
+        assert_eq!(
+            Err(RpcStatus::StatusValueTooLarge),
+            master.scan_table(1, 1, &[], false, 0, 20)
+        );
+        assert_eq!(
+            Err(RpcStatus::StatusTableDoesNotExist),
+            master.scan_table(1, 2, &[], false, 0, 1400)
+        );
+    }
+
+    // Tests that a scan moves past records too large to fit on a page by themselves, counting
+    // them, and returns every other record exactly once.
+    #[test]
+    fn test_scan_table_oversized() {
+        let master = Master::new();
+        master.fill_test(1, 1, 500);
+        master
+            .fill(1, 1, fill::numbered(501, 3, 30, 2000))
+            .expect("Failed to add large objects.")
+            .run(&master.heap);
+
+        // With no budget to speak of, a single page holds every record.
+        let (all, num, _, _) = master
+            .scan_table(1, 1, &[], false, 0, usize::max_value())
+            .unwrap();
+        let sorted: Vec<(Vec<u8>, Vec<u8>)> = rpc::parse_kvs(&all, num)
+            .unwrap()
+            .into_iter()
+            .filter(|&(_, value)| value.len() <= 100)
+            .map(|(k, v)| (k.to_vec(), v.to_vec()))
+            .collect();
+        assert_eq!((503, 500), (num, sorted.len()));
+
+        let (mut records, mut skipped, mut token, mut after) = (Vec::new(), 0, Vec::new(), false);
+        loop {
+            let (page, num, passed, next) = master
+                .scan_table(1, 1, &token, after, 0, 1400)
+                .expect("Failed to scan table.");
+            let page = rpc::parse_kvs(&page, num).expect("Malformed page.");
+            records.extend(page.into_iter().map(|(k, v)| (k.to_vec(), v.to_vec())));
+            skipped += passed;
+            if next.is_empty() {
+                break;
+            }
+            token = next;
+            after = true;
+        }
+
+        assert_eq!(3, skipped);
+        assert_eq!(sorted, records);
+    }
+
+    // Tests that a table restored from a checkpoint holds exactly the records on the table the
+    // checkpoint was saved from, and that bad checkpoints are refused.
+    #[test]
+    fn test_checkpoint_restore() {
+        let master = Master::new();
+        master.fill_test(1, 1, 500);
+        master.fill_test(2, 1, 0);
+
+        let (checkpoint, saved) = master.checkpoint_table(1, 1, Vec::new()).unwrap();
+        assert_eq!(500, saved);
+        let header = Reader::new(&checkpoint[..], OnCorrupt::Abort)
+            .unwrap()
+            .header()
+            .clone();
+        assert_eq!(
+            (1, 1, FLAG_DUMP_ORDER),
+            (header.tenant, header.table, header.flags)
+        );
+
+        assert_eq!(500, master.restore_table(2, 1, &checkpoint[..]).unwrap());
+        assert_eq!(dump_all(&master, 1, 1024), dump_all(&master, 2, 1024));
+
+        // A corrupt record fails the restore; so do tables that can't be saved or written to.
+        let mut corrupt = checkpoint.clone();
+        let len = corrupt.len();
+        corrupt[len / 2] ^= 0x1;
+        let err = master.restore_table(2, 1, &corrupt[..]).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+
+        assert!(master.checkpoint_table(1, 2, Vec::new()).is_err());
+        assert!(master.restore_table(2, 2, &checkpoint[..]).is_err());
+    }
+
+    // Tests that a batch with a bad record, or to a table that can't be written to, is refused
+    // without writing any of it's records.
+    #[test]
+    fn test_multiput_refused() {
+        let master = Master::new();
+        master.fill_test(1, 1, 0);
+
+        let mut batch = Vec::new();
+        rpc::append_kv(&mut batch, b"key1", b"value");
+        rpc::append_kv(&mut batch, b"key2", b"");
+        assert_eq!(
+            RpcStatus::StatusMalformedRequest,
+            master.put_records(1, 1, &batch, 2)
+        );
+        assert_eq!(
+            RpcStatus::StatusMalformedRequest,
+            master.put_records(1, 1, &batch, 3)
+        );
+        assert!(dump_all(&master, 1, 1024).is_empty());
+
+        let tenant = master.get_tenant(1).unwrap();
+        assert_eq!(Ok(()), tenant.set_native_access(1, true, false));
+        assert_eq!(
+            RpcStatus::StatusPermissionDenied,
+            master.put_records(1, 1, &batch[..batch.len() - 10], 1)
+        );
+        assert_eq!(
+            RpcStatus::StatusTableDoesNotExist,
+            master.put_records(1, 2, &[], 0)
+        );
+    }
+
+    // Tests that puts and batches of records right at the server's limits are written, and
+    // that ones a byte over them are refused without writing anything.
+    #[test]
+    fn test_limits_enforced() {
+        let mut master = Master::new();
+        master.set_limits(Limits::new(8, 64));
+        master.fill_test(1, 1, 0);
+
+        let (key, val) = (vec![b'k'; 8], vec![b'v'; 64]);
+        assert_eq!(RpcStatus::StatusOk, master.put_value(1, 1, &key, &val));
+        assert_eq!(
+            Ok(&val[..]),
+            master.get_value(1, 1, &key).as_ref().map(|v| &v[..])
+        );
+        assert_eq!(
+            RpcStatus::StatusKeyTooLarge,
+            master.put_value(1, 1, &[b'k'; 9], b"v")
+        );
+        assert_eq!(
+            RpcStatus::StatusValueTooLarge,
+            master.put_value(1, 1, b"k", &[b'v'; 65])
+        );
+        assert_eq!(1, dump_all(&master, 1, 1024).len());
+
+        let mut batch = Vec::new();
+        rpc::append_kv(&mut batch, b"key1", &val);
+        rpc::append_kv(&mut batch, b"key2", &[b'v'; 65]);
+        assert_eq!(
+            RpcStatus::StatusValueTooLarge,
+            master.put_records(1, 1, &batch, 2)
+        );
+        assert_eq!(RpcStatus::StatusOk, master.put_records(1, 1, &batch, 1));
+        assert_eq!(2, dump_all(&master, 1, 1024).len());
+    }
+
+    // Tests that delete_value() removes an object once, and fails like a native delete() on
+    // tenants, tables and keys that don't exist.
+    #[test]
+    fn test_delete_value() {
+        let master = Master::new();
+        master.fill_test(1, 1, 0);
+
+        assert_eq!(RpcStatus::StatusOk, master.put_value(1, 1, b"key", b"val"));
+        assert_eq!(RpcStatus::StatusOk, master.delete_value(1, 1, b"key"));
+        assert_eq!(
+            Some(RpcStatus::StatusObjectDoesNotExist),
+            master.get_value(1, 1, b"key").err()
+        );
+        assert_eq!(
+            RpcStatus::StatusObjectDoesNotExist,
+            master.delete_value(1, 1, b"key")
+        );
+        assert_eq!(
+            RpcStatus::StatusTableDoesNotExist,
+            master.delete_value(1, 2, b"key")
+        );
+        assert_eq!(
+            RpcStatus::StatusTenantDoesNotExist,
+            master.delete_value(2, 1, b"key")
+        );
+    }
+
+    // Tests that cas_value() only writes when the value under the key is the expected one, and
+    // fails like a native put() on tenants and tables that don't exist.
+    #[test]
+    fn test_cas_value() {
+        let master = Master::new();
+        master.fill_test(1, 1, 0);
+
+        let cas =
+            |expected: Option<&[u8]>, val: &[u8]| master.cas_value(1, 1, b"key", expected, val);
+        assert_eq!(RpcStatus::StatusCompareFailed, cas(Some(b"one"), b"two"));
+        assert_eq!(RpcStatus::StatusOk, cas(None, b"one"));
+        assert_eq!(RpcStatus::StatusCompareFailed, cas(None, b"two"));
+        assert_eq!(RpcStatus::StatusCompareFailed, cas(Some(b"two"), b"two"));
+        assert_eq!(RpcStatus::StatusOk, cas(Some(b"one"), b"two"));
+        assert_eq!(
+            Ok(&b"two"[..]),
+            master.get_value(1, 1, b"key").as_ref().map(|v| &v[..])
+        );
+
+        assert_eq!(RpcStatus::StatusMalformedRequest, cas(Some(b"two"), b""));
+        assert_eq!(
+            RpcStatus::StatusTableDoesNotExist,
+            master.cas_value(1, 2, b"key", None, b"val")
+        );
+        assert_eq!(
+            RpcStatus::StatusTenantDoesNotExist,
+            master.cas_value(2, 1, b"key", None, b"val")
+        );
+    }
+
+    // Tests that puts, batches and deletes made through the server are accounted for on their
+    // table, and returned by write_stats().
+    #[test]
+    fn test_write_stats() {
+        let master = Master::new();
+        master.fill_test(1, 1, 0);
+
+        assert_eq!(
+            RpcStatus::StatusOk,
+            master.put_value(1, 1, b"key", &[1; 100])
+        );
+        assert_eq!(
+            RpcStatus::StatusOk,
+            master.put_value(1, 1, b"key", &[2; 10])
+        );
+        let mut batch = Vec::new();
+        rpc::append_kv(&mut batch, b"key1", &[3; 20]);
+        rpc::append_kv(&mut batch, b"key2", &[4; 30]);
+        assert_eq!(RpcStatus::StatusOk, master.put_records(1, 1, &batch, 2));
+        assert_eq!(RpcStatus::StatusOk, master.delete_value(1, 1, b"key1"));
+        assert_eq!(
+            RpcStatus::StatusObjectDoesNotExist,
+            master.delete_value(1, 1, b"key1")
+        );
+
+        let (entries, num) = master
+            .write_stats(1, 1, WRITE_STATS_TOTALS)
+            .expect("Failed to get write stats.");
+        let totals = rpc::parse_write_totals(&entries, num).expect("Malformed totals.");
+        assert_eq!(amplify::N_WRITE_KINDS, totals.len());
+
+        // Each object is 14 bytes of metadata, the key and the value.
+        let put = totals[amplify::WriteKind::Put as usize];
+        let multiput = totals[amplify::WriteKind::MultiPut as usize];
+        let merge = totals[amplify::WriteKind::Merge as usize];
+        let delete = totals[amplify::WriteKind::Delete as usize];
+        assert_eq!(
+            (2, 110, 2 * 17 + 110),
+            (put.count, put.logical, put.physical)
+        );
+        assert_eq!(
+            (2, 50, 2 * 18 + 50),
+            (multiput.count, multiput.logical, multiput.physical)
+        );
+        assert_eq!(0, merge.count);
+        assert_eq!((1, 0, 0), (delete.count, delete.logical, delete.physical));
+
+        assert_eq!(
+            Err(RpcStatus::StatusOperationDisabled),
+            master.write_stats(1, 1, WRITE_STATS_HOT_KEYS)
+        );
+        assert_eq!(
+            Err(RpcStatus::StatusMalformedRequest),
+            master.write_stats(1, 1, 7)
+        );
+        assert_eq!(
+            Err(RpcStatus::StatusTableDoesNotExist),
+            master.write_stats(1, 2, WRITE_STATS_TOTALS)
+        );
+    }
+
+    // Tests that puts to tables configured to share values do, and that write_stats() reports
+    // the bytes saved on them.
+    #[test]
+    fn test_write_stats_dedup() {
+        let mut config = ServerConfig::default();
+        config.dedup_tables = "1".to_string();
+        let mut master = Master::new();
+        master.enable_dedup(&config);
+        master.fill_test(1, 1, 0);
+        master.fill_test(1, 2, 0);
+
+        for key in [b"key1", b"key2", b"key3"].iter() {
+            assert_eq!(RpcStatus::StatusOk, master.put_value(1, 1, *key, &[7; 100]));
+        }
+        let mut batch = Vec::new();
+        rpc::append_kv(&mut batch, b"key4", &[7; 100]);
+        assert_eq!(RpcStatus::StatusOk, master.put_records(1, 1, &batch, 1));
+
+        let (entries, num) = master
+            .write_stats(1, 1, WRITE_STATS_DEDUP)
+            .expect("Failed to get dedup stats.");
+        assert_eq!(1, num);
+        let stats = rpc::parse_dedup_stats(&entries).expect("Malformed dedup stats.");
+        assert_eq!((3, 1, 300), (stats.hits, stats.misses, stats.bytes_saved));
+
+        // Only the first put allocated room for the value.
+        let (entries, num) = master.write_stats(1, 1, WRITE_STATS_TOTALS).unwrap();
+        let totals = rpc::parse_write_totals(&entries, num).unwrap();
+        let put = totals[amplify::WriteKind::Put as usize];
+        assert_eq!((300, 3 * 18 + 100), (put.logical, put.physical));
+
+        assert_eq!(
+            Err(RpcStatus::StatusOperationDisabled),
+            master.write_stats(1, 2, WRITE_STATS_DEDUP)
+        );
+    }
+
+    // Tests that a table filled after being sized for it's objects never grows it's buckets, and
+    // ends up holding exactly what a table that was not sized does.
+    #[test]
+    fn test_presize_fill() {
+        let master = Master::new();
+        master.fill_test(1, 1, 10_000);
+        let (entries, num) = master
+            .write_stats(1, 1, WRITE_STATS_SIZING)
+            .expect("Failed to get table sizing.");
+        assert_eq!(1, num);
+        let sizing = rpc::parse_sizing(&entries).expect("Malformed sizing.");
+        assert_eq!(
+            (16384, 10_000, 0),
+            (sizing.presized, sizing.objects, sizing.resizes)
+        );
+        assert!(sizing.capacity >= 16384);
+
+        // The same objects filled into a table that was not sized grow every bucket repeatedly.
+        let grown = Master::new();
+        grown.fill_test(1, 1, 0);
+        grown
+            .fill(1, 1, fill::numbered(1, 10_000, 30, 100))
+            .expect("Failed to init test table.")
+            .run(&grown.heap);
+        let sizing = grown.resolve_table(1, 1).unwrap().sizing();
+        assert_eq!((0, 10_000), (sizing.presized, sizing.objects));
+        assert!(sizing.resizes > N_BUCKETS as u64);
+
+        assert_eq!(dump_all(&master, 1, 1024), dump_all(&grown, 1, 1024));
+    }
+
+    // Tests that create_table() pre-sizes the tables it creates, and refuses tables too large
+    // for the cap without creating them.
+    #[test]
+    fn test_create_table_sized() {
+        let mut master = Master::new();
+        master.set_presize(Presize::new(0.75, 1000));
+        assert_eq!(
+            Err(RpcStatus::StatusTenantDoesNotExist),
+            master.create_table_sized(1, 2, 100, None)
+        );
+
+        master.fill_test(1, 1, 0);
+        assert_eq!(
+            Err(RpcStatus::StatusTableTooLarge),
+            master.create_table_sized(1, 2, 1001, None)
+        );
+        assert_eq!(
+            Err(RpcStatus::StatusTableDoesNotExist),
+            master.write_stats(1, 2, WRITE_STATS_SIZING)
+        );
+
+        // 1000 objects at a load factor of 0.75 need 1334 slots, rounded up to 2048.
+        assert_eq!(Ok(2048), master.create_table_sized(1, 2, 1000, None));
+        let (entries, _) = master.write_stats(1, 2, WRITE_STATS_SIZING).unwrap();
+        let sizing = rpc::parse_sizing(&entries).expect("Malformed sizing.");
+        assert_eq!(
+            (2048, 0, 0),
+            (sizing.presized, sizing.objects, sizing.resizes)
+        );
+        assert!(sizing.capacity >= 2048);
+
+        // Creating a table that exists leaves it as it is, unless it is sized again.
+        assert_eq!(Ok(0), master.create_table_sized(1, 2, 0, None));
+        assert_eq!(2048, master.resolve_table(1, 2).unwrap().sizing().presized);
+        assert_eq!(Ok(256), master.create_table_sized(1, 1, 100, None));
+        assert_eq!(256, master.resolve_table(1, 1).unwrap().sizing().presized);
+    }
+
+    // Tests that drop_table() removes a table and it's objects, while a handle to the table held
+    // from before the drop still reads them.
+    #[test]
+    fn test_drop_table() {
+        let master = Master::new();
+        master.fill_test(1, 1, 0);
+        assert_eq!(Ok(0), master.create_table_sized(1, 2, 0, None));
+        assert_eq!(RpcStatus::StatusOk, master.put_value(1, 2, b"key", b"val"));
+
+        let held = master.resolve_table(1, 2).unwrap();
+        assert_eq!(RpcStatus::StatusOk, master.drop_table(1, 2));
+        assert_eq!(
+            Some(RpcStatus::StatusTableDoesNotExist),
+            master.get_value(1, 2, b"key").err()
+        );
+        assert!(held.get(b"key").is_some());
+
+        assert_eq!(RpcStatus::StatusTableDoesNotExist, master.drop_table(1, 2));
+        assert_eq!(RpcStatus::StatusTenantDoesNotExist, master.drop_table(2, 1));
+        assert_eq!(RpcStatus::StatusOk, master.put_value(1, 1, b"key", b"val"));
+    }
+
+    // Tests that truncating and dropping tables and removing tenants move them to new epochs,
+    // that write_stats() reports the epochs, and that not-found statuses are stamped with the
+    // epoch of the scope they were looked up in.
+    #[test]
+    fn test_write_stats_epochs() {
+        let master = Master::new();
+        master.fill_test(1, 1, 0);
+        assert_eq!(Ok(0), master.create_table_sized(1, 2, 0, None));
+        let epochs = |table| {
+            let (entries, num) = master
+                .write_stats(1, table, WRITE_STATS_EPOCHS)
+                .expect("Failed to get epochs.");
+            assert_eq!(1, num);
+            rpc::parse_epochs(&entries).expect("Malformed epochs.")
+        };
+        let start = EpochStats {
+            server: 1,
+            tenant: 1,
+            table: 1,
+        };
+        assert_eq!(start, epochs(1));
+
+        let missing = RpcStatus::StatusTableDoesNotExist;
+        assert_eq!(Some(1), master.epochs().scope(&missing, 1));
+        assert_eq!(RpcStatus::StatusOk, master.drop_table(1, 2));
+        assert_eq!(Some(2), master.epochs().scope(&missing, 1));
+        assert_eq!(
+            EpochStats {
+                server: 1,
+                tenant: 2,
+                table: 1,
+            },
+            epochs(1)
+        );
+
+        // A table created after the drop starts out at the tenant's epoch, and moves on when
+        // it is truncated.
+        assert_eq!(Ok(0), master.create_table_sized(1, 2, 0, None));
+        assert_eq!(2, epochs(2).table);
+        assert_eq!(RpcStatus::StatusOk, master.truncate_table(1, 2));
+        assert_eq!((3, 3), (epochs(2).tenant, epochs(2).table));
+
+        let tenant = master.remove_tenant(1).expect("Tenant not removed.");
+        assert_eq!(4, tenant.epoch());
+        let missing = RpcStatus::StatusTenantDoesNotExist;
+        assert_eq!(Some(4), master.epochs().scope(&missing, 1));
+        assert_eq!(None, master.epochs().tenant(1));
+
+        // The tenant added back, and the tables it is added with, start out past the removal.
+        master.fill_test(1, 1, 0);
+        assert_eq!(
+            EpochStats {
+                server: 4,
+                tenant: 4,
+                table: 4,
+            },
+            epochs(1)
+        );
+    }
+
+    // Tests that the walk Master hands out moves objects read on every pass into the hot arena,
+    // and that the arena's counters are reported through write_stats() once there is one.
+    #[test]
+    fn test_hot_arena() {
+        let master = Master::new();
+        master.fill_test(1, 1, 100);
+        assert!(master.hot_walk().is_none());
+        assert_eq!(
+            Err(RpcStatus::StatusOperationDisabled),
+            master.write_stats(1, 1, WRITE_STATS_HOT_ARENA)
+        );
+
+        let mut master = Master::new();
+        master.set_hot_arena(HotPolicy::new(1 << 16, false, 1, 0, 1));
+        master.fill_test(1, 1, 100);
+        let mut walk = master.hot_walk().expect("No walk handed out.");
+        assert!(master.hot_walk().is_none());
+
+        let table = master.resolve_table(1, 1).unwrap();
+        let keys: Vec<Bytes> = (0..N_BUCKETS)
+            .flat_map(|b| table.bucket_entries(b).into_iter())
+            .filter_map(|entry| master.heap.resolve_key(&entry.value))
+            .collect();
+        assert_eq!(100, keys.len());
+
+        for _ in 0..2 {
+            walk.start(master.writable_tables());
+            while !walk.done() {
+                walk.step();
+            }
+            for key in keys.iter() {
+                master.heap.count_hot(&table.get(key).unwrap().value);
+            }
+        }
+
+        let (entries, num) = master
+            .write_stats(1, 1, WRITE_STATS_HOT_ARENA)
+            .expect("Failed to get arena counters.");
+        assert_eq!(1, num);
+        let stats = rpc::parse_hot_stats(&entries).expect("Malformed arena counters.");
+        assert_eq!(
+            (1 << 16, 100, 0, 200, 100),
+            (
+                stats.capacity,
+                stats.promotions,
+                stats.demotions,
+                stats.gets,
+                stats.hits
+            )
+        );
+    }
+
+    // Tests that a table created in cache mode stays at it's target as it is filled, and reports
+    // it's counters through write_stats(); tables not in cache mode have none to report.
+    #[test]
+    fn test_create_cache_table() {
+        let master = Master::new();
+        master.fill_test(1, 1, 0);
+        let target = CacheTarget::new(0, 100, EvictPolicy::Read);
+        assert_eq!(Ok(0), master.create_table_sized(1, 2, 0, target));
+        master
+            .fill(1, 2, fill::numbered(1, 1000, 30, 100))
+            .expect("Failed to fill cache table.")
+            .run(&master.heap);
+
+        let (entries, num) = master
+            .write_stats(1, 2, WRITE_STATS_CACHE)
+            .expect("Failed to get cache counters.");
+        assert_eq!(1, num);
+        let stats = rpc::parse_cache_stats(&entries).expect("Malformed cache counters.");
+        assert_eq!(
+            (target, 100, 900),
+            (Some(stats.target), stats.objects, stats.evicted)
+        );
+        assert_eq!(100, master.resolve_table(1, 2).unwrap().sizing().objects);
+
+        assert_eq!(
+            Err(RpcStatus::StatusOperationDisabled),
+            master.write_stats(1, 1, WRITE_STATS_CACHE)
+        );
+        assert_eq!(
+            Err(RpcStatus::StatusTableDoesNotExist),
+            master.set_cache_target(1, 3, target)
+        );
+
+        // Taking the table out of cache mode stops the counters.
+        assert_eq!(Ok(()), master.set_cache_target(1, 2, None));
+        assert_eq!(
+            Err(RpcStatus::StatusOperationDisabled),
+            master.write_stats(1, 2, WRITE_STATS_CACHE)
+        );
+    }
+
+    // Tests that AUTH records filled at several costs and layouts parse, and check the password
+    // a client derives from the username, through the codec the auth extension uses.
+    #[test]
+    fn test_fill_auth_layout() {
+        let master = Master::new();
+        let layouts = [
+            Layout::default(),
+            Layout::new(4, 12, 8).unwrap(),
+            Layout::new(6, 4, 2).unwrap(),
+        ];
+        for (tenant, layout) in layouts.iter().enumerate() {
+            let tenant = tenant as TenantId + 1;
+            master.fill_auth_with(tenant, 1, 4, layout);
+
+            for i in 1..5 {
+                let value = master.get_value(tenant, 1, &test_key(i)).unwrap();
+                assert_eq!(layout.record_len(), value.len());
+
+                let record = auth::parse(&value).expect("Malformed AUTH record.");
+                assert_eq!(layout.cost(), record.cost);
+                assert!(record.matches(&auth::password(&test_key(i))));
+                assert!(!record.matches(&auth::password(&test_key(i + 1))));
+            }
+        }
+    }
+
+    // Tests that the auth extension checks a password a few bcrypt rounds at a time, yielding
+    // to the scheduler in between, and only yields on it's get() at the default cost.
+    #[test]
+    fn test_auth_yields() {
+        let ext =
+            Extension::load("../ext/auth/target/release/libauth.so").expect("Failed to load auth");
+        let username = test_key(1);
+
+        for &(cost, password, status) in [(1, 1, 0x02), (8, 1, 0x02), (8, 2, 0x03)].iter() {
+            let mut record = Vec::new();
+            let layout = Layout::new(cost, auth::MAX_HASH_LEN, auth::MAX_SALT_LEN).unwrap();
+            auth::fill(&layout, 1..2, |_, r| record = r.to_vec());
+
+            let mut args = vec![1, 0, 0, 0, 0, 0, 0, 0];
+            args.extend_from_slice(&username);
+            args.extend_from_slice(&auth::password(&test_key(password)));
+            let db = Rc::new(MockDB::with_args(&args));
+            db.insert(1, &username, &record);
+
+            let mut gen = ext.get(Rc::clone(&db) as Rc<DB>);
+            let mut task = Native::new(
+                TaskPriority::REQUEST,
+                Box::new(move || loop {
+                    let state = unsafe { gen.resume() };
+                    match state {
+                        GeneratorState::Yielded(time) => yield time,
+                        GeneratorState::Complete(_) => return None,
+                    }
+                }),
+            );
+
+            let mut yields = 0;
+            while task.run().0 == TaskState::YIELDED {
+                yields += 1;
+            }
+            assert!(task.state() == TaskState::COMPLETED);
+
+            // One yield is on the get(), the rest between steps of the key schedule.
+            let rounds = 1 << cost;
+            assert_eq!(rounds / auth::ROUNDS_PER_STEP, yields);
+            assert_eq!(vec![status], db.response());
+        }
+    }
+
+    // Tests that the put extension writes values from a byte up to the largest an invoke() can
+    // carry, and that it always runs to completion without yielding. The server only pushes back
+    // invocations that have yielded, so a put is never re-executed on the client, and the object
+    // is written exactly once. Refer to sandstorm::put.
+    #[test]
+    fn test_put_ext() {
+        let ext =
+            Extension::load("../ext/put/target/release/libput.so").expect("Failed to load put");
+        let key = test_key(1);
+        let max = limits::max_invoke_put_len(key.len());
+
+        for &len in [1, 2, 100, max - 1, max].iter() {
+            let val: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+            let db = Rc::new(MockDB::with_args(&put::encode_args(1, &key, &val)));
+            db.create_table(1);
+
+            let mut gen = ext.get(Rc::clone(&db) as Rc<DB>);
+            match unsafe { gen.resume() } {
+                GeneratorState::Complete(status) => assert_eq!(0, status),
+                GeneratorState::Yielded(_) => panic!("put() of {} bytes yielded", len),
+            }
+            assert_eq!(put::SUCCESS, &db.response()[..]);
+            assert_eq!(&val[..], db.get(1, &key).unwrap().read());
+        }
+
+        // Arguments cut off in the middle of the key write nothing.
+        let args = put::encode_args(1, &key, b"value");
+        let db = Rc::new(MockDB::with_args(&args[..put::HEADER_LEN + 1]));
+        db.create_table(1);
+        let mut gen = ext.get(Rc::clone(&db) as Rc<DB>);
+        match unsafe { gen.resume() } {
+            GeneratorState::Complete(status) => assert_eq!(1, status),
+            GeneratorState::Yielded(_) => panic!("put() yielded"),
+        }
+        assert_eq!(ExtError::InvalidArgs.frame(), db.response());
+        assert!(db.get(1, &key).is_none());
+    }
+
+    // Tests that latencies recorded on separate cores are merged, and that the percentiles the
+    // server computes bracket the time tasks were known to take. Tasks run on a virtual clock
+    // that ticks once a nanosecond.
+    #[test]
+    fn test_latency_stats() {
+        const HZ: u64 = 1_000_000_000;
+        let master = Arc::new(Master::new());
+        let get = OpCode::SandstormGetRpc as u8;
+        let invoke = OpCode::SandstormInvokeRpc as u8;
+
+        // Each core responds to 99 get()s after a microsecond and one after 100 microseconds,
+        // and to an invocation of "tao" after 5 microseconds.
+        let threads: Vec<_> = (0..4)
+            .map(|core| {
+                let master = Arc::clone(&master);
+                thread::spawn(move || {
+                    cycles::virt::install(0, HZ);
+                    latency::install(master.latencies().register(core));
+                    for i in 0..100 {
+                        let rx = cycles::rdtsc();
+                        cycles::virt::advance(if i == 99 { 100_000 } else { 1_000 });
+                        latency::record(get, cycles::rdtsc() - rx, None);
+                    }
+
+                    let rx = cycles::rdtsc();
+                    cycles::virt::advance(5_000);
+                    latency::record(invoke, cycles::rdtsc() - rx, Some(b"tao"));
+                    cycles::virt::uninstall();
+                })
+            }).collect();
+        for thread in threads {
+            thread.join().expect("Failed to join task thread.");
+        }
+
+        // Every percentile lies in the bucket of the delay it stands for, whose upper bound is
+        // atmost a quarter past it.
+        let within = |delay: u64, nanos: u64| delay <= nanos && nanos <= delay * 5 / 4;
+
+        cycles::virt::install(0, HZ);
+        let (entries, num) = master
+            .latency_stats(LATENCY_STATS_OPCODES, 0, &[])
+            .expect("Failed to get opcode latencies.");
+        let ops = rpc::parse_latency_summaries(&entries, num).expect("Malformed summaries.");
+        assert_eq!(2, ops.len());
+        let (op, gets) = ops[0];
+        assert_eq!((get, 400), (op, gets.count));
+        assert!(within(1_000, gets.p50), "p50 {}", gets.p50);
+        assert!(within(1_000, gets.p99), "p99 {}", gets.p99);
+        assert!(within(100_000, gets.p999), "p99.9 {}", gets.p999);
+        let (op, invokes) = ops[1];
+        assert_eq!((invoke, 4), (op, invokes.count));
+        assert!(within(5_000, invokes.p50), "p50 {}", invokes.p50);
+
+        let (entries, num) = master
+            .latency_stats(LATENCY_STATS_EXTENSIONS, 0, &[])
+            .expect("Failed to get extension latencies.");
+        let exts = rpc::parse_ext_latencies(&entries, num).expect("Malformed extensions.");
+        assert_eq!(1, exts.len());
+        assert_eq!(b"tao".to_vec(), exts[0].0);
+        assert_eq!(invokes, exts[0].1);
+
+        let (entries, num) = master
+            .latency_stats(LATENCY_STATS_HISTOGRAM, get, &[])
+            .expect("Failed to get the get() histogram.");
+        let hist = rpc::parse_latency_histogram(&entries, num).expect("Malformed histogram.");
+        assert_eq!(400, hist.total());
+        let (entries, num) = master
+            .latency_stats(LATENCY_STATS_HISTOGRAM, invoke, b"tao")
+            .expect("Failed to get the tao histogram.");
+        let hist = rpc::parse_latency_histogram(&entries, num).expect("Malformed histogram.");
+        assert_eq!(4, hist.total());
+
+        assert_eq!(
+            Err(RpcStatus::StatusMalformedRequest),
+            master.latency_stats(7, 0, &[])
+        );
+        cycles::virt::uninstall();
+    }
+
+    // Returns the key of object `i` in a table filled by fill_test().
+    fn test_key(i: u32) -> Vec<u8> {
+        let mut key = vec![0; 30];
+        key[0..4].copy_from_slice(&unsafe { transmute::<u32, [u8; 4]>(i.to_le()) });
+        key
+    }
+
+    // Tests that requests find a tenant off the core's cache, and stop finding it once it has
+    // been removed.
+    #[test]
+    fn test_tenant_cache_removed() {
+        let master = Master::new();
+        master.fill_test(1, 1, 4);
+
+        let before = tenant_cache::stats();
+        for _ in 0..4 {
+            assert!(master.get_value(1, 1, &test_key(2)).is_ok());
+        }
+        let after = tenant_cache::stats();
+        assert_eq!(
+            (3, 1),
+            (after.hits - before.hits, after.misses - before.misses)
+        );
+
+        assert!(master.remove_tenant(1).is_some());
+        assert!(master.remove_tenant(1).is_none());
+        for _ in 0..2 {
+            assert_eq!(
+                Err(RpcStatus::StatusTenantDoesNotExist),
+                master.get_value(1, 1, &test_key(2))
+            );
+        }
+
+        // A tenant added back under the same id is found again.
+        master.fill_test(1, 1, 1);
+        assert_eq!(
+            Err(RpcStatus::StatusObjectDoesNotExist),
+            master.get_value(1, 1, &test_key(2))
+        );
+        assert!(master.get_value(1, 1, &test_key(1)).is_ok());
+    }
+
+    // Tests that requests never see a stale or wrong tenant while tenants are being added on
+    // another core. Tenant `t` holds objects 1 to `t`, so a lookup of object `t` on tenant `t`
+    // only succeeds on the right tenant.
+    #[test]
+    fn test_tenant_cache_concurrent_inserts() {
+        let master = Arc::new(Master::new());
+        let n: u32 = 256;
+
+        let filler = {
+            let master = Arc::clone(&master);
+            thread::spawn(move || {
+                for t in 1..n + 1 {
+                    master.fill_test(t, 1, t);
+                }
+            })
+        };
+
+        let mut found = 0;
+        while found < n {
+            found = 0;
+            for t in 1..n + 1 {
+                match master.get_value(t, 1, &test_key(t)) {
+                    Ok(_) => found += 1,
+                    Err(RpcStatus::StatusTenantDoesNotExist) => {}
+                    Err(status) => panic!("Tenant {} looked up as {:?}", t, status),
+                }
+            }
+        }
+        filler.join().unwrap();
+    }
+
+    // Counts the allocations made by each thread, so that tests can check that a path does not
+    // allocate.
+    mod alloc {
+        use std::alloc::{GlobalAlloc, Layout, System};
+        use std::cell::Cell;
+
+        thread_local!(static ALLOCS: Cell<usize> = Cell::new(0));
+
+        pub struct Counting;
+
+        unsafe impl GlobalAlloc for Counting {
+            unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+                let _ = ALLOCS.try_with(|a| a.set(a.get() + 1));
+                System.alloc(layout)
+            }
+
+            unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+                System.dealloc(ptr, layout)
+            }
+        }
+
+        #[global_allocator]
+        static GLOBAL: Counting = Counting;
+
+        // Returns the number of allocations made by this thread so far.
+        pub fn count() -> usize {
+            ALLOCS.with(|a| a.get())
+        }
+    }
+
+    // Tests that failures reported against a tenant quarantine it once they cross a threshold,
+    // leave other tenants and tenant 0 be, and that the quarantine runs out on time.
+    #[test]
+    fn test_quarantine_signals() {
+        const HZ: u64 = 1_000_000;
+        cycles::virt::install(1, HZ);
+
+        let mut master = Master::new();
+        master.set_quarantine_policy(QuarantinePolicy {
+            thresholds: [3, 0, 3],
+            window_ms: 100,
+            quarantine_ms: 1000,
+            max_quarantine_ms: 8000,
+        });
+        for tenant in 0..3 {
+            master.insert_tenant(Tenant::new(tenant));
+        }
+
+        // Extension failures are not counted under this policy.
+        let now = cycles::rdtsc();
+        for tenant in 0..3 {
+            for _ in 0..3 {
+                master.signal(tenant, Signal::Auth, now);
+                master.signal(tenant, Signal::Extension, now);
+            }
+        }
+        assert!((0..3).all(|tenant| !master.quarantined(tenant, now)));
+
+        // Tenant 0 is exempt, and tenant 2 stays at the threshold.
+        for tenant in 0..2 {
+            master.signal(tenant, Signal::Auth, now);
+        }
+        assert!(!master.quarantined(0, now));
+        assert!(master.quarantined(1, now));
+        assert!(!master.quarantined(2, now));
+        assert!(!master.quarantined(3, now));
+
+        cycles::virt::advance(HZ - 1);
+        assert!(master.quarantined(1, cycles::rdtsc()));
+        cycles::virt::advance(1);
+        assert!(!master.quarantined(1, cycles::rdtsc()));
+        assert_eq!(1, master.quarantines().stats().quarantines);
+        cycles::virt::uninstall();
+    }
+
+    // Tests that only tenant 0 can quarantine and release tenants, that it cannot quarantine
+    // itself, and that manual quarantines apply even though automatic ones are off.
+    #[test]
+    fn test_quarantine_manual() {
+        let master = Master::new();
+        master.insert_tenant(Tenant::new(1));
+        let act = |tenant, action, target, duration| {
+            master
+                .quarantine_tenant(tenant, action, target, duration)
+                .map(|target| target.map(|t| t.id()))
+        };
+
+        let invalid = Err(RpcStatus::StatusInvalidOperation);
+        assert_eq!(invalid, act(1, QUARANTINE_ENTER, 1, 0));
+        assert_eq!(invalid, act(0, QUARANTINE_ENTER, 0, 0));
+        assert_eq!(invalid, act(0, QUARANTINE_RELEASE, 0, 0));
+        assert_eq!(Err(RpcStatus::StatusMalformedRequest), act(0, 7, 1, 0));
+        assert_eq!(
+            Err(RpcStatus::StatusTenantDoesNotExist),
+            act(0, QUARANTINE_ENTER, 2, 0)
+        );
+        assert_eq!(Ok(None), act(0, QUARANTINE_QUERY, 0, 0));
+        assert!(!master.quarantined(1, cycles::rdtsc()));
+
+        assert_eq!(Ok(Some(1)), act(0, QUARANTINE_ENTER, 1, 60_000));
+        assert!(master.quarantined(1, cycles::rdtsc()));
+        assert_eq!(1, master.get_tenant(1).unwrap().quarantine().strikes());
+
+        assert_eq!(Ok(Some(1)), act(0, QUARANTINE_RELEASE, 1, 0));
+        assert!(!master.quarantined(1, cycles::rdtsc()));
+        assert_eq!(0, master.get_tenant(1).unwrap().quarantine().strikes());
+    }
+
+    // Tests that checking requests against their tenant's quarantine does not allocate, whether
+    // or not the tenant is quarantined, once the tenant's handle is cached on the core.
+    #[test]
+    fn test_quarantined_no_alloc() {
+        let master = Master::new();
+        master.insert_tenant(Tenant::new(1));
+        master.insert_tenant(Tenant::new(2));
+        let entered = master.quarantine_tenant(0, QUARANTINE_ENTER, 1, 60_000);
+        assert!(entered.is_ok());
+
+        let now = cycles::rdtsc();
+        assert!(master.quarantined(1, now));
+        assert!(!master.quarantined(2, now));
+
+        let before = alloc::count();
+        for _ in 0..1000 {
+            let now = cycles::rdtsc();
+            assert!(master.quarantined(1, now));
+            assert!(!master.quarantined(2, now));
+            assert!(!master.quarantined(0, now));
+            master.quarantines().rejected();
+        }
+        assert_eq!(before, alloc::count());
+        assert_eq!(1000, master.quarantines().stats().rejected);
+    }
+
+    // Tests that an extension is found the same way by it's name and by the id it is interned
+    // under, that the id goes stale once the name is loaded again or unloaded, and that neither
+    // lookup allocates.
+    #[test]
+    fn test_lookup_extension_by_id() {
+        const TEST: &str = "../ext/test/target/release/libtest.so";
+        let master = Master::new();
+        let extensions = master.extensions.as_ref().unwrap();
+        assert!(extensions.load(TEST, 1, "test"));
+        let id: [u8; 4] = unsafe { transmute(extensions.list(1)[0].id.to_le()) };
+
+        let (by_name, name) = master.lookup_extension(1, b"test", false).unwrap();
+        assert!(name.is_none());
+        let (by_id, name) = master.lookup_extension(1, &id, true).unwrap();
+        assert!(Arc::ptr_eq(&by_name, &by_id));
+        assert_eq!(Some(&b"test"[..]), name.as_ref().map(|n| &n[..]));
+
+        let before = alloc::count();
+        for _ in 0..1000 {
+            assert!(master.lookup_extension(1, b"test", false).is_ok());
+            assert!(master.lookup_extension(1, &id, true).is_ok());
+        }
+        assert_eq!(before, alloc::count());
+
+        // Ids are interned per tenant, and must be exactly 4 bytes long.
+        let err = |name: &[u8], by_id| master.lookup_extension(1, name, by_id).err();
+        assert_eq!(Some(RpcStatus::StatusMalformedRequest), err(&id[..3], true));
+        assert_eq!(
+            Some(RpcStatus::StatusMalformedRequest),
+            err(&[0xff, 0xfe], false)
+        );
+        assert_eq!(Some(RpcStatus::StatusInvalidExtension), err(b"xyz", false));
+        assert_eq!(
+            Some(RpcStatus::StatusStaleExtensionId),
+            master.lookup_extension(2, &id, true).err()
+        );
+
+        // Loading the name again hands out a new id, and the old one is not reused.
+        assert!(extensions.load(TEST, 1, "test"));
+        assert_eq!(Some(RpcStatus::StatusStaleExtensionId), err(&id, true));
+        let reloaded: [u8; 4] = unsafe { transmute(extensions.list(1)[0].id.to_le()) };
+        assert!(reloaded != id);
+        let (by_id, _) = master.lookup_extension(1, &reloaded, true).unwrap();
+        assert!(!Arc::ptr_eq(&by_name, &by_id));
+
+        assert_eq!(Some(0), extensions.unload(1, b"test"));
+        assert_eq!(
+            Some(RpcStatus::StatusStaleExtensionId),
+            err(&reloaded, true)
+        );
+        assert_eq!(Some(RpcStatus::StatusInvalidExtension), err(b"test", false));
+    }
+
+    // Tests that invoke() requests whose name isn't valid UTF-8, whose id isn't 4 bytes long, or
+    // whose payload is cut short are refused with StatusMalformedRequest, that malformed names
+    // are counted, and that requests after them are still served.
+    #[test]
+    fn test_invoke_malformed_names() {
+        const TEST: &str = "../ext/test/target/release/libtest.so";
+        let master = Master::new();
+        master.fill_test(1, 1, 0);
+        assert!(master.extensions.as_ref().unwrap().load(TEST, 1, "test"));
+
+        let resolve = |payload: &[u8], name_length: usize, by_id: bool| {
+            let args_length = payload.len().saturating_sub(name_length);
+            master
+                .resolve_invoke(1, payload, name_length, args_length, by_id, false)
+                .map(|(tenant, _, _)| tenant.id())
+                .map_err(|(status, _)| status)
+        };
+        let malformed = Err(RpcStatus::StatusMalformedRequest);
+
+        assert_eq!(Ok(1), resolve(b"testargs", 4, false));
+        assert_eq!(malformed, resolve(&[0xff, 0xfe, 0x00, 0x01], 2, false));
+        assert_eq!(malformed, resolve(&[b't', b'e', 0xc3, b't'], 4, false));
+        assert_eq!(malformed, resolve(&[1, 0, 0], 3, true));
+        assert_eq!(3, master.rejected_names());
+
+        // Names that are valid UTF-8 are looked up as usual, and a payload shorter than the
+        // name and arguments is refused without being looked up.
+        assert_eq!(
+            Err(RpcStatus::StatusInvalidExtension),
+            resolve("tést".as_bytes(), 5, false)
+        );
+        assert_eq!(
+            malformed,
+            master
+                .resolve_invoke(1, b"test", 4, 1, false, false)
+                .map(|_| ())
+                .map_err(|(status, _)| status)
+        );
+        assert_eq!(
+            Err(RpcStatus::StatusTenantDoesNotExist),
+            master
+                .resolve_invoke(2, b"test", 4, 0, false, false)
+                .map(|_| ())
+                .map_err(|(status, _)| status)
+        );
+        assert_eq!(3, master.rejected_names());
+
+        // The server keeps serving the tenant.
+        assert_eq!(Ok(1), resolve(b"test", 4, false));
+    }
+}
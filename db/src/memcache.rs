@@ -0,0 +1,648 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use std::cmp;
+use std::io::{self, Write};
+use std::net::{Ipv4Addr, SocketAddrV4, UdpSocket};
+use std::str;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use super::config::ServerConfig;
+use super::master::Master;
+use super::wireformat::RpcStatus;
+
+use sandstorm::common::{TableId, TenantId};
+
+/// The longest key a command can carry, as in memcached.
+pub const MAX_KEY_LEN: usize = 250;
+
+/// The largest value a set can carry, as in memcached.
+pub const MAX_VALUE_LEN: usize = 1 << 20;
+
+/// The length of the frame header on every datagram of memcached's UDP protocol: a request id,
+/// a sequence number, the total number of datagrams, and two reserved bytes, all big endian.
+pub const FRAME_LEN: usize = 8;
+
+/// The most bytes of a response carried by a single datagram, frame header excluded. Longer
+/// responses are split over several datagrams, like memcached does.
+pub const DATAGRAM_LEN: usize = 1400;
+
+/// A command of the memcached text protocol. Only get and set are understood.
+#[derive(Debug, PartialEq)]
+pub enum Command<'a> {
+    /// get <key>*\r\n
+    Get(Vec<&'a [u8]>),
+
+    /// set <key> <flags> <exptime> <bytes> [noreply]\r\n<data>\r\n
+    Set {
+        key: &'a [u8],
+        flags: u32,
+        exptime: u32,
+        data: &'a [u8],
+        noreply: bool,
+    },
+}
+
+/// Why a command could not be parsed.
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// The command is neither get nor set. Answered with ERROR.
+    Unknown,
+
+    /// The command is malformed. Answered with CLIENT_ERROR and the message.
+    Client(&'static str),
+}
+
+// Returns the index of the first "\r\n" on a buffer.
+fn find_crlf(buf: &[u8]) -> Option<usize> {
+    buf.windows(2).position(|w| w == b"\r\n")
+}
+
+// Parses a decimal number off a token.
+fn number(token: &[u8]) -> Option<u32> {
+    str::from_utf8(token).ok().and_then(|t| t.parse().ok())
+}
+
+// Keys are printable, and can't contain spaces or control characters.
+fn valid_key(key: &[u8]) -> bool {
+    key.len() <= MAX_KEY_LEN && key.iter().all(|b| *b > b' ' && *b != 0x7f)
+}
+
+/// Parses the command at the head of a buffer.
+///
+/// # Arguments
+///
+/// * `buf`: The commands, back to back.
+///
+/// # Return
+///
+/// None if the buffer is empty. Otherwise the command, or the reason it could not be parsed,
+/// along with the number of bytes it took up. A command cut short by the end of the buffer
+/// takes up the rest of it, since nothing after it can be told apart from it's data.
+pub fn parse(buf: &[u8]) -> Option<(Result<Command, Error>, usize)> {
+    if buf.is_empty() {
+        return None;
+    }
+
+    let end = match find_crlf(buf) {
+        Some(end) => end,
+        None => return Some((Err(Error::Client("line not terminated")), buf.len())),
+    };
+
+    let line = end + 2;
+    let mut tokens = buf[..end].split(|b| *b == b' ').filter(|t| !t.is_empty());
+    let command = tokens.next().unwrap_or(&[]);
+
+    if command == b"get" {
+        let keys: Vec<&[u8]> = tokens.collect();
+        let res = match (keys.is_empty(), keys.iter().all(|k| valid_key(k))) {
+            (true, _) => Err(Error::Client("bad command line format")),
+            (false, false) => Err(Error::Client("bad key")),
+            (false, true) => Ok(Command::Get(keys)),
+        };
+        return Some((res, line));
+    }
+
+    if command != b"set" {
+        return Some((Err(Error::Unknown), line));
+    }
+
+    let args: Vec<&[u8]> = tokens.collect();
+    let noreply = match args.len() {
+        4 => false,
+        5 if args[4] == b"noreply" => true,
+        _ => return Some((Err(Error::Client("bad command line format")), line)),
+    };
+
+    let (key, flags, exptime, bytes) = match (number(args[1]), number(args[2]), number(args[3])) {
+        (Some(flags), Some(exptime), Some(bytes)) => (args[0], flags, exptime, bytes as usize),
+        _ => return Some((Err(Error::Client("bad command line format")), line)),
+    };
+
+    if !valid_key(key) {
+        return Some((Err(Error::Client("bad key")), line));
+    }
+
+    if bytes > MAX_VALUE_LEN {
+        return Some((Err(Error::Client("object too large for cache")), line));
+    }
+
+    // The data block must be followed by it's own "\r\n".
+    let data = line + bytes;
+    if buf.len() < data + 2 {
+        return Some((Err(Error::Client("bad data chunk")), buf.len()));
+    }
+
+    if &buf[data..data + 2] != b"\r\n" {
+        return Some((Err(Error::Client("bad data chunk")), data + 2));
+    }
+
+    let set = Command::Set {
+        key: key,
+        flags: flags,
+        exptime: exptime,
+        data: &buf[line..data],
+        noreply: noreply,
+    };
+    Some((Ok(set), data + 2))
+}
+
+/// Splits a response into datagrams of memcached's UDP protocol.
+///
+/// # Arguments
+///
+/// * `id`:       The request id on the request's frame header, echoed on every datagram.
+/// * `response`: The response.
+///
+/// # Return
+///
+/// The datagrams, each with a frame header. Empty if the response is.
+pub fn frames(id: u16, response: &[u8]) -> Vec<Vec<u8>> {
+    let total = (response.len() + DATAGRAM_LEN - 1) / DATAGRAM_LEN;
+    response
+        .chunks(DATAGRAM_LEN)
+        .enumerate()
+        .map(|(seq, chunk)| {
+            let mut datagram = Vec::with_capacity(FRAME_LEN + chunk.len());
+            for half in [id, seq as u16, total as u16, 0].iter() {
+                datagram.push((half >> 8) as u8);
+                datagram.push(*half as u8);
+            }
+            datagram.extend_from_slice(chunk);
+            datagram
+        })
+        .collect()
+}
+
+/// Serves the get and set commands of memcached's text protocol off a tenant's table, so that
+/// load generators written for memcached (ex: memtier, mc-crusher) can drive the server.
+///
+/// Gets and sets run exactly like native get() and put() RPCs on the table; refer to
+/// Master::get_value() and Master::put_value(). Keys are used as is. The table has nowhere to
+/// keep flags, so values are always returned with flags 0. Objects don't expire; exptimes are
+/// ignored, and counted.
+pub struct Adapter {
+    // The server the table is on.
+    master: Arc<Master>,
+
+    // The tenant the table belongs to.
+    tenant: TenantId,
+
+    // The table commands are served off.
+    table: TableId,
+
+    // The number of sets whose non-zero exptime was ignored.
+    ignored_exptime: AtomicUsize,
+
+    // The number of commands and datagrams that could not be parsed.
+    malformed: AtomicUsize,
+}
+
+impl Adapter {
+    /// Returns an adapter serving commands off a table.
+    ///
+    /// # Arguments
+    ///
+    /// * `master`: The server the table is on.
+    /// * `tenant`: The tenant the table belongs to.
+    /// * `table`:  The table.
+    pub fn new(master: Arc<Master>, tenant: TenantId, table: TableId) -> Adapter {
+        Adapter {
+            master: master,
+            tenant: tenant,
+            table: table,
+            ignored_exptime: AtomicUsize::new(0),
+            malformed: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the number of sets whose non-zero exptime was ignored.
+    pub fn ignored_exptime(&self) -> usize {
+        self.ignored_exptime.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of commands and datagrams that could not be parsed.
+    pub fn malformed(&self) -> usize {
+        self.malformed.load(Ordering::Relaxed)
+    }
+
+    /// Runs every command on a buffer, in order.
+    ///
+    /// # Arguments
+    ///
+    /// * `buf`: The commands, back to back.
+    ///
+    /// # Return
+    ///
+    /// The responses to the commands, back to back.
+    pub fn execute(&self, buf: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut rest = buf;
+        while let Some((command, n)) = parse(rest) {
+            rest = &rest[n..];
+            match command {
+                Ok(Command::Get(keys)) => self.get(&keys, &mut out),
+
+                Ok(Command::Set {
+                    key,
+                    exptime,
+                    data,
+                    noreply,
+                    ..
+                }) => {
+                    if exptime != 0 {
+                        self.ignored_exptime.fetch_add(1, Ordering::Relaxed);
+                    }
+
+                    let reply = self.set(key, data, &mut out);
+                    if noreply {
+                        out.truncate(reply);
+                    }
+                }
+
+                Err(err) => {
+                    self.malformed.fetch_add(1, Ordering::Relaxed);
+                    match err {
+                        Error::Unknown => out.extend_from_slice(b"ERROR\r\n"),
+                        Error::Client(msg) => {
+                            let _ = write!(out, "CLIENT_ERROR {}\r\n", msg);
+                        }
+                    }
+                }
+            }
+        }
+
+        out
+    }
+
+    // Writes out a VALUE line for every key that exists, followed by END. Keys that don't exist
+    // are skipped, as in memcached.
+    fn get(&self, keys: &[&[u8]], out: &mut Vec<u8>) {
+        for key in keys.iter() {
+            match self.master.get_value(self.tenant, self.table, key) {
+                Ok(value) => {
+                    out.extend_from_slice(b"VALUE ");
+                    out.extend_from_slice(key);
+                    let _ = write!(out, " 0 {}\r\n", value.len());
+                    out.extend_from_slice(&value);
+                    out.extend_from_slice(b"\r\n");
+                }
+
+                Err(RpcStatus::StatusObjectDoesNotExist) => {}
+
+                Err(status) => {
+                    let _ = write!(out, "SERVER_ERROR {:?}\r\n", status);
+                    return;
+                }
+            }
+        }
+
+        out.extend_from_slice(b"END\r\n");
+    }
+
+    // Writes an object, and the reply to the set. Returns the length of `out` before the reply
+    // was written, so that it can be dropped.
+    fn set(&self, key: &[u8], data: &[u8], out: &mut Vec<u8>) -> usize {
+        let reply = out.len();
+        match self.master.put_value(self.tenant, self.table, key, data) {
+            RpcStatus::StatusOk => out.extend_from_slice(b"STORED\r\n"),
+
+            // Native puts can't write empty values.
+            RpcStatus::StatusMalformedRequest => out.extend_from_slice(b"NOT_STORED\r\n"),
+
+            // The reply memcached sends for objects larger than it's item size.
+            RpcStatus::StatusKeyTooLarge | RpcStatus::StatusValueTooLarge => {
+                out.extend_from_slice(b"SERVER_ERROR object too large for cache\r\n")
+            }
+
+            status => {
+                let _ = write!(out, "SERVER_ERROR {:?}\r\n", status);
+            }
+        }
+        reply
+    }
+
+    /// Runs the commands on a datagram of memcached's UDP protocol.
+    ///
+    /// # Arguments
+    ///
+    /// * `datagram`: The datagram, starting at it's frame header.
+    ///
+    /// # Return
+    ///
+    /// The datagrams to send back. Empty if the datagram was too short to hold a frame header,
+    /// or if every command on it asked for no reply.
+    pub fn handle(&self, datagram: &[u8]) -> Vec<Vec<u8>> {
+        if datagram.len() < FRAME_LEN {
+            self.malformed.fetch_add(1, Ordering::Relaxed);
+            return Vec::new();
+        }
+
+        let id = (datagram[0] as u16) << 8 | datagram[1] as u16;
+        let total = (datagram[4] as u16) << 8 | datagram[5] as u16;
+        let response = match total {
+            1 => self.execute(&datagram[FRAME_LEN..]),
+            _ => {
+                self.malformed.fetch_add(1, Ordering::Relaxed);
+                b"SERVER_ERROR multi-datagram requests are not supported\r\n".to_vec()
+            }
+        };
+
+        frames(id, &response)
+    }
+
+    /// Receives one datagram off a socket, and sends the responses back to where it came from.
+    ///
+    /// # Arguments
+    ///
+    /// * `socket`: The socket the adapter is bound to.
+    /// * `buf`:    Room for the datagram. Datagrams longer than this are cut short.
+    pub fn serve_one(&self, socket: &UdpSocket, buf: &mut [u8]) -> io::Result<()> {
+        let (len, from) = socket.recv_from(buf)?;
+        for datagram in self.handle(&buf[..len]).iter() {
+            socket.send_to(datagram, from)?;
+        }
+        Ok(())
+    }
+
+    /// Serves datagrams off a socket until receiving fails.
+    ///
+    /// # Arguments
+    ///
+    /// * `socket`: The socket the adapter is bound to.
+    pub fn serve(&self, socket: &UdpSocket) -> io::Error {
+        let mut buf = vec![0; 1 << 16];
+        loop {
+            match self.serve_one(socket, &mut buf) {
+                Ok(()) => {}
+
+                // A client that went away can't be sent it's responses; keep serving the others.
+                Err(ref e) if e.kind() == io::ErrorKind::ConnectionRefused => {}
+
+                Err(e) => return e,
+            }
+        }
+    }
+}
+
+/// Binds the memcached port in the server config, and serves it on a thread of it's own. Does
+/// nothing if the port is 0, so that an unconfigured server never binds it.
+///
+/// # Arguments
+///
+/// * `master`: The server commands are served off.
+/// * `config`: The server config. `memcache_tenant` and `memcache_table` name the table, 0
+///             meaning 1.
+///
+/// # Return
+///
+/// The thread serving the port, or None if the adapter is disabled.
+pub fn spawn(master: &Arc<Master>, config: &ServerConfig) -> io::Result<Option<JoinHandle<()>>> {
+    if config.memcache_port == 0 {
+        return Ok(None);
+    }
+
+    let addr = SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), config.memcache_port);
+    let socket = UdpSocket::bind(addr)?;
+    let tenant = cmp::max(config.memcache_tenant, 1);
+    let table = cmp::max(config.memcache_table, 1);
+    let adapter = Adapter::new(Arc::clone(master), tenant, table);
+    info!(
+        "Serving memcached gets and sets on udp port {} off tenant {} table {}",
+        config.memcache_port, tenant, table
+    );
+
+    thread::Builder::new()
+        .name("memcache".to_string())
+        .spawn(move || {
+            let err = adapter.serve(&socket);
+            error!("Stopped serving memcached commands: {}", err);
+        })
+        .map(Some)
+}
+
+// This module contains unit tests for the memcached adapter.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    use super::super::limits::Limits;
+
+    use rand::{Rng, SeedableRng, XorShiftRng};
+
+    fn adapter() -> Adapter {
+        let master = Master::new();
+        master.fill_test(1, 1, 0);
+        Adapter::new(Arc::new(master), 1, 1)
+    }
+
+    // Parses every command off a buffer, checking that each one takes up some of it, and no
+    // more than what is left.
+    fn parse_all(buf: &[u8]) -> Vec<Result<Command, Error>> {
+        let mut commands = Vec::new();
+        let mut rest = buf;
+        while let Some((command, n)) = parse(rest) {
+            assert!(n > 0 && n <= rest.len());
+            rest = &rest[n..];
+            commands.push(command);
+        }
+        commands
+    }
+
+    // Tests that gets and sets are parsed, back to back.
+    #[test]
+    fn test_memcache_parse() {
+        let buf = b"get a bc\r\nset k 3 60 5 noreply\r\nv\r\nxx\r\nset  k 0 0 0\r\n\r\n";
+        let set = |data: &'static [u8], flags, exptime, noreply| Command::Set {
+            key: b"k",
+            flags: flags,
+            exptime: exptime,
+            data: data,
+            noreply: noreply,
+        };
+        assert_eq!(
+            vec![
+                Ok(Command::Get(vec![&b"a"[..], &b"bc"[..]])),
+                Ok(set(b"v\r\nxx", 3, 60, true)),
+                Ok(set(b"", 0, 0, false)),
+            ],
+            parse_all(buf)
+        );
+
+        assert_eq!(None, parse(b""));
+        assert_eq!(Some((Err(Error::Unknown), 10)), parse(b"incr k 1\r\n"));
+    }
+
+    // Tests that malformed commands are told apart, and take up what they should.
+    #[test]
+    fn test_memcache_parse_malformed() {
+        let long = format!("get {}\r\n", "k".repeat(MAX_KEY_LEN + 1));
+        let huge = format!("set k 0 0 {}\r\n", MAX_VALUE_LEN + 1);
+        let cases: &[(&[u8], &str, usize)] = &[
+            (b"get k", "line not terminated", 5),
+            (b"get\r\nget k\r\n", "bad command line format", 5),
+            (b"get k\x01\r\n", "bad key", 8),
+            (long.as_bytes(), "bad key", long.len()),
+            (b"set k 0 0\r\n", "bad command line format", 11),
+            (b"set k 0 0 1 yes\r\n", "bad command line format", 17),
+            (b"set k 0 0 -1\r\n", "bad command line format", 14),
+            (huge.as_bytes(), "object too large for cache", huge.len()),
+            (b"set k 0 0 4\r\nab", "bad data chunk", 15),
+            (b"set k 0 0 1\r\nabc\r\nget k\r\n", "bad data chunk", 16),
+        ];
+
+        for &(buf, msg, n) in cases.iter() {
+            assert_eq!(Some((Err(Error::Client(msg)), n)), parse(buf));
+        }
+    }
+
+    // Tests that random bytes, and random mutations of a valid pipeline of commands, never
+    // panic the parser or the adapter, and are always fully consumed.
+    #[test]
+    fn test_memcache_fuzz() {
+        let adapter = adapter();
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        let tokens: [&[u8]; 10] = [
+            b"get", b"set", b" ", b"\r\n", b"\r", b"\n", b"0", b"5", b"k", b"noreply",
+        ];
+
+        for _ in 0..2000 {
+            let mut buf = Vec::new();
+            for _ in 0..rng.gen_range(0, 32) {
+                match rng.gen_range(0, 4) {
+                    0 => buf.push(rng.gen()),
+                    _ => buf.extend_from_slice(rng.choose(&tokens).unwrap()),
+                }
+            }
+            parse_all(&buf);
+            adapter.execute(&buf);
+        }
+
+        let script = b"set k 0 0 3\r\nabc\r\nget k x\r\nset y 1 2 1 noreply\r\nz\r\nget y\r\n";
+        for _ in 0..2000 {
+            let mut buf = script.to_vec();
+            for _ in 0..rng.gen_range(1, 4) {
+                let i = rng.gen_range(0, buf.len());
+                match rng.gen_range(0, 3) {
+                    0 => buf[i] = rng.gen(),
+                    1 => buf.truncate(i),
+                    _ => buf.insert(i, rng.gen()),
+                }
+                if buf.is_empty() {
+                    break;
+                }
+            }
+            parse_all(&buf);
+            adapter.execute(&buf);
+        }
+
+        let datagram: Vec<u8> = (0..rng.gen_range(0, FRAME_LEN))
+            .map(|_| rng.gen())
+            .collect();
+        assert!(adapter.handle(&datagram).is_empty());
+        assert!(adapter.malformed() > 0);
+    }
+
+    // Tests that sets of objects right at the server's limits are stored and read back, and
+    // that sets one byte over them are refused without storing anything.
+    #[test]
+    fn test_memcache_too_large() {
+        let mut master = Master::new();
+        master.set_limits(Limits::new(8, 32));
+        master.fill_test(1, 1, 0);
+        let adapter = Adapter::new(Arc::new(master), 1, 1);
+
+        let value = vec![b'v'; 32];
+        let set = format!("set kkkkkkkk 0 0 32\r\n{}\r\n", "v".repeat(32));
+        assert_eq!(b"STORED\r\n".to_vec(), adapter.execute(set.as_bytes()));
+        let mut hit = b"VALUE kkkkkkkk 0 32\r\n".to_vec();
+        hit.extend_from_slice(&value);
+        hit.extend_from_slice(b"\r\nEND\r\n");
+        assert_eq!(hit, adapter.execute(b"get kkkkkkkk\r\n"));
+
+        let refused = b"SERVER_ERROR object too large for cache\r\n".to_vec();
+        assert_eq!(refused, adapter.execute(b"set kkkkkkkkk 0 0 1\r\nv\r\n"));
+        let set = format!("set k 0 0 33\r\n{}\r\n", "v".repeat(33));
+        assert_eq!(refused, adapter.execute(set.as_bytes()));
+        assert_eq!(b"END\r\n".to_vec(), adapter.execute(b"get kkkkkkkkk k\r\n"));
+    }
+
+    // Tests that long responses are split over datagrams, each with it's own frame header.
+    #[test]
+    fn test_memcache_frames() {
+        assert!(frames(7, b"").is_empty());
+
+        let response = vec![b'x'; DATAGRAM_LEN + 1];
+        let datagrams = frames(0x0102, &response);
+        assert_eq!(2, datagrams.len());
+        assert_eq!(&[1, 2, 0, 0, 0, 2, 0, 0][..], &datagrams[0][..FRAME_LEN]);
+        assert_eq!(&[1, 2, 0, 1, 0, 2, 0, 0][..], &datagrams[1][..FRAME_LEN]);
+        assert_eq!(FRAME_LEN + DATAGRAM_LEN, datagrams[0].len());
+        assert_eq!(FRAME_LEN + 1, datagrams[1].len());
+    }
+
+    // Tests a scripted sequence of sets and gets, hits and misses over loopback, down to the
+    // exact bytes on every response.
+    #[test]
+    fn test_memcache_loopback() {
+        let adapter = Arc::new(adapter());
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        client.connect(server.local_addr().unwrap()).unwrap();
+
+        let script: &[(&[u8], &[u8])] = &[
+            (b"set foo 5 0 3\r\nbar\r\n", b"STORED\r\n"),
+            (b"get foo\r\n", b"VALUE foo 0 3\r\nbar\r\nEND\r\n"),
+            (b"get nope\r\n", b"END\r\n"),
+            (
+                b"set a 0 100 1\r\n1\r\nset b 0 0 2 noreply\r\n22\r\nget a nope b\r\n",
+                b"STORED\r\nVALUE a 0 1\r\n1\r\nVALUE b 0 2\r\n22\r\nEND\r\n",
+            ),
+            (b"set e 0 0 0\r\n\r\nbogus\r\n", b"NOT_STORED\r\nERROR\r\n"),
+            (b"get\r\n", b"CLIENT_ERROR bad command line format\r\n"),
+        ];
+
+        let n = script.len();
+        let served = Arc::clone(&adapter);
+        let handle = thread::spawn(move || {
+            let mut buf = vec![0; 1 << 16];
+            for _ in 0..n {
+                served.serve_one(&server, &mut buf).unwrap();
+            }
+        });
+
+        let mut buf = vec![0; 1 << 16];
+        for (i, &(request, response)) in script.iter().enumerate() {
+            let mut datagram = vec![0, i as u8, 0, 0, 0, 1, 0, 0];
+            datagram.extend_from_slice(request);
+            client.send(&datagram).unwrap();
+
+            let len = client.recv(&mut buf).unwrap();
+            assert_eq!(&[0, i as u8, 0, 0, 0, 1, 0, 0][..], &buf[..FRAME_LEN]);
+            assert_eq!(response, &buf[FRAME_LEN..len]);
+        }
+
+        handle.join().unwrap();
+        assert_eq!(1, adapter.ignored_exptime());
+        assert_eq!(2, adapter.malformed());
+    }
+}
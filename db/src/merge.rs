@@ -0,0 +1,378 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use std::cell::RefCell;
+use std::ops::GeneratorState;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::rc::Rc;
+use std::sync::Arc;
+
+use super::alloc::Allocator;
+use super::amplify::WriteKind;
+use super::table::Table;
+use super::wireformat::RpcStatus;
+
+use sandstorm::buf::{MultiReadBuf, ReadBuf, WriteBuf};
+use sandstorm::common::{TableId, TenantId};
+use sandstorm::db::{PutManyError, DB};
+use sandstorm::ext::Extension;
+use sandstorm::merge::encode_args;
+use util::model::Model;
+
+/// The reason a merge wrote nothing.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MergeError {
+    /// The table has no merge extension registered.
+    NoMergeExtension,
+
+    /// The table cannot be written to (ex: it is backed by a read-only image).
+    ReadOnly,
+
+    /// The key is empty.
+    EmptyKey,
+
+    /// The new value could not be allocated.
+    Alloc,
+
+    /// The key, or the merged value is over the allocator's limits. Carries StatusKeyTooLarge
+    /// or StatusValueTooLarge.
+    TooLarge(RpcStatus),
+
+    /// The merge extension failed. Carries whatever it wrote out.
+    Failed(Vec<u8>),
+}
+
+// The database a merge extension runs against. A merge extension is a function of the old
+// value and the delta, so it only sees it's arguments; every table access fails.
+struct MergeDB {
+    args: Vec<u8>,
+    resp: RefCell<Vec<u8>>,
+}
+
+impl DB for MergeDB {
+    fn get(&self, _table: u64, _key: &[u8]) -> Option<ReadBuf> {
+        None
+    }
+
+    fn multiget(&self, _table: u64, _key_len: u16, _keys: &[u8]) -> Option<MultiReadBuf> {
+        None
+    }
+
+    fn alloc(&self, _table: u64, _key: &[u8], _val_len: u64) -> Option<WriteBuf> {
+        None
+    }
+
+    fn put(&self, _buf: WriteBuf) -> bool {
+        false
+    }
+
+    fn put_many(&self, bufs: Vec<WriteBuf>) -> Result<(), PutManyError> {
+        match bufs.len() {
+            0 => Ok(()),
+            _ => Err(PutManyError::TableNotWritable(0)),
+        }
+    }
+
+    fn cas(
+        &self,
+        _table_id: u64,
+        _key: &[u8],
+        _expected: Option<&[u8]>,
+        _buf: WriteBuf,
+    ) -> Result<bool, ()> {
+        Err(())
+    }
+
+    fn del(&self, _table: u64, _key: &[u8]) -> bool {
+        false
+    }
+
+    fn args(&self) -> &[u8] {
+        &self.args
+    }
+
+    fn resp(&self, data: &[u8]) {
+        self.resp.borrow_mut().extend_from_slice(data);
+    }
+
+    fn debug_log(&self, _message: &str) {}
+
+    fn search_get_in_cache(&self, _table: u64, _key: &[u8]) -> (bool, bool, Option<ReadBuf>) {
+        (true, false, None)
+    }
+
+    fn search_multiget_in_cache(
+        &self,
+        _table: u64,
+        _key_len: u16,
+        _keys: &[u8],
+    ) -> (bool, bool, Option<MultiReadBuf>) {
+        (true, false, None)
+    }
+
+    fn get_model(&self) -> Option<Arc<Model>> {
+        None
+    }
+}
+
+/// Runs a merge extension to completion on an old value and a delta. Yields are resumed right
+/// away; a merge runs start to finish without being descheduled.
+///
+/// # Arguments
+///
+/// * `ext`:   The merge extension.
+/// * `old`:   The value stored under the key. Empty if the key does not exist.
+/// * `delta`: The delta to merge into it.
+///
+/// # Return
+///
+/// The new value, or what the extension wrote out if it failed (returned anything but 0, or
+/// panicked).
+pub fn run(ext: &Extension, old: &[u8], delta: &[u8]) -> Result<Vec<u8>, Vec<u8>> {
+    let db = Rc::new(MergeDB {
+        args: encode_args(old, delta),
+        resp: RefCell::new(Vec::new()),
+    });
+
+    let mut gen = ext.get(Rc::clone(&db) as Rc<DB>);
+    let ret = catch_unwind(AssertUnwindSafe(|| loop {
+        match unsafe { gen.resume() } {
+            GeneratorState::Yielded(_) => continue,
+            GeneratorState::Complete(ret) => return ret,
+        }
+    }));
+    drop(gen);
+
+    let out = db.resp.replace(Vec::new());
+    match ret {
+        Ok(0) => Ok(out),
+        _ => Err(out),
+    }
+}
+
+/// Merges a delta into the value stored under a key, using the table's merge extension. An
+/// absent key is merged into as if it's value were empty. The merge runs under the lock on the
+/// key's bucket (see Table::update()), so concurrent merges and puts to the key serialize, and
+/// none of them is lost. If the merge extension fails, the stored value is left untouched.
+///
+/// # Arguments
+///
+/// * `heap`:     The allocator the new value is allocated from.
+/// * `table`:    The table holding the key.
+/// * `tenant`:   The tenant owning the table.
+/// * `table_id`: The identifier of the table.
+/// * `key`:      The key to merge into.
+/// * `delta`:    The delta passed to the merge extension.
+pub fn merge(
+    heap: &Allocator,
+    table: &Table,
+    tenant: TenantId,
+    table_id: TableId,
+    key: &[u8],
+    delta: &[u8],
+) -> Result<(), MergeError> {
+    if key.is_empty() {
+        return Err(MergeError::EmptyKey);
+    }
+    heap.limits()
+        .check(key.len(), 0)
+        .map_err(MergeError::TooLarge)?;
+
+    let ext = table
+        .merge_extension()
+        .ok_or(MergeError::NoMergeExtension)?;
+
+    let res = table.update(key, |old| {
+        let old = old.and_then(|entry| heap.resolve(entry.value).map(|(_, value)| value));
+        let new = run(&ext, old.as_ref().map_or(&[][..], |v| &v[..]), delta)
+            .map_err(MergeError::Failed)?;
+        heap.limits()
+            .check(key.len(), new.len())
+            .map_err(MergeError::TooLarge)?;
+        let (key, object) = heap
+            .object(tenant, table_id, key, &new)
+            .ok_or(MergeError::Alloc)?;
+        let (key, object) = heap.prepare(table, key, object);
+        table
+            .writes()
+            .record(WriteKind::Merge, &key, delta.len(), object.len());
+        Ok((key, object))
+    });
+
+    match res {
+        Some(res) => res.map(|_| ()),
+        None => Err(MergeError::ReadOnly),
+    }
+}
+
+// This module contains tests for merges. They use the merge_counter extension, which sums
+// little-endian u64s.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    use super::super::limits::Limits;
+
+    use sandstorm::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+    const COUNTER: &str = "../ext/merge_counter/target/release/libmerge_counter.so";
+
+    fn table() -> Table {
+        let table = Table::default();
+        let ext = Extension::load(COUNTER).expect("Failed to load merge_counter");
+        table.set_merge(Some((b"counter".to_vec(), Arc::new(ext))));
+        table
+    }
+
+    fn u64_le(v: u64) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(8);
+        buf.write_u64::<LittleEndian>(v).unwrap();
+        buf
+    }
+
+    // Reads the counter stored under a key.
+    fn read(heap: &Allocator, table: &Table, key: &[u8]) -> Option<u64> {
+        table
+            .get(key)
+            .and_then(|entry| heap.resolve(entry.value))
+            .map(|(_, value)| (&value[..]).read_u64::<LittleEndian>().unwrap())
+    }
+
+    // Tests that a merge into an absent key merges into an empty value, and later merges
+    // combine with what was stored.
+    #[test]
+    fn test_merge_absent() {
+        let (heap, table) = (Allocator::new(), table());
+        assert_eq!(None, read(&heap, &table, b"k"));
+
+        assert_eq!(Ok(()), merge(&heap, &table, 1, 1, b"k", &u64_le(5)));
+        assert_eq!(Some(5), read(&heap, &table, b"k"));
+
+        assert_eq!(Ok(()), merge(&heap, &table, 1, 1, b"k", &u64_le(7)));
+        assert_eq!(Some(12), read(&heap, &table, b"k"));
+    }
+
+    // Tests that each merge is accounted for as it's delta against the whole object it
+    // rewrote, and that failed merges are not accounted for.
+    #[test]
+    fn test_merge_accounting() {
+        let (heap, table) = (Allocator::new(), table());
+        for i in 0..10 {
+            assert_eq!(Ok(()), merge(&heap, &table, 1, 1, b"k", &u64_le(i)));
+        }
+        assert!(merge(&heap, &table, 1, 1, b"k", &[1, 2, 3]).is_err());
+
+        // Each object is 14 bytes of metadata, the key and the 8 byte counter.
+        let totals = table.writes().totals()[WriteKind::Merge as usize];
+        assert_eq!(
+            (10, 80, 10 * (14 + 1 + 8)),
+            (totals.count, totals.logical, totals.physical)
+        );
+        assert_eq!(23.0 / 8.0, totals.ratio());
+    }
+
+    // Tests that merges are refused if the key or the merged value is over the allocator's
+    // limits, and leave the stored value untouched.
+    #[test]
+    fn test_merge_too_large() {
+        let (mut heap, table) = (Allocator::new(), table());
+        heap.set_limits(Limits::new(2, 8));
+        assert_eq!(Ok(()), merge(&heap, &table, 1, 1, b"kk", &u64_le(5)));
+        assert_eq!(
+            Err(MergeError::TooLarge(RpcStatus::StatusKeyTooLarge)),
+            merge(&heap, &table, 1, 1, b"kkk", &u64_le(5))
+        );
+
+        heap.set_limits(Limits::new(2, 7));
+        assert_eq!(
+            Err(MergeError::TooLarge(RpcStatus::StatusValueTooLarge)),
+            merge(&heap, &table, 1, 1, b"kk", &u64_le(5))
+        );
+        assert_eq!(Some(5), read(&heap, &table, b"kk"));
+    }
+
+    // Tests that concurrent merges into a single key are serialized, and none is lost.
+    #[test]
+    fn test_merge_concurrent() {
+        let table = Arc::new(table());
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let table = Arc::clone(&table);
+                thread::spawn(move || {
+                    let heap = Allocator::new();
+                    for _ in 0..1000 {
+                        merge(&heap, &table, 1, 1, b"k", &u64_le(1)).unwrap();
+                    }
+                })
+            }).collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+
+        assert_eq!(Some(4000), read(&Allocator::new(), &table, b"k"));
+    }
+
+    // Tests that a failed merge returns the extension's error frame and leaves the stored
+    // value untouched.
+    #[test]
+    fn test_merge_error() {
+        let (heap, table) = (Allocator::new(), table());
+        assert_eq!(Ok(()), merge(&heap, &table, 1, 1, b"k", &u64_le(3)));
+
+        // merge_counter refuses deltas that aren't 8 bytes long.
+        match merge(&heap, &table, 1, 1, b"k", &[1, 2, 3]) {
+            Err(MergeError::Failed(frame)) => assert!(!frame.is_empty()),
+            res => panic!("Unexpected merge result {:?}", res),
+        }
+        assert_eq!(Some(3), read(&heap, &table, b"k"));
+    }
+
+    // Tests that every object a merge rewrites on a checksummed table carries a checksum that
+    // matches it, and that a merge into a quarantined object lifts the quarantine.
+    #[test]
+    fn test_merge_checksums() {
+        let (heap, table) = (Allocator::new(), table());
+        table.set_checksums(true);
+        for i in 0..10 {
+            assert_eq!(Ok(()), merge(&heap, &table, 1, 1, b"k", &u64_le(i)));
+            let entry = table.get(b"k").unwrap();
+            assert_eq!(Some(true), heap.verify(&entry.value));
+        }
+        assert_eq!(Some(45), read(&heap, &table, b"k"));
+
+        let version = table.get(b"k").unwrap().version;
+        assert!(table.quarantine(b"k", version));
+        assert_eq!(Ok(()), merge(&heap, &table, 1, 1, b"k", &u64_le(5)));
+        assert!(!table.is_quarantined(b"k"));
+        assert_eq!(Some(true), heap.verify(&table.get(b"k").unwrap().value));
+        assert_eq!(Some(50), read(&heap, &table, b"k"));
+    }
+
+    // Tests that tables without a merge extension, and empty keys, are refused.
+    #[test]
+    fn test_merge_refused() {
+        let (heap, table) = (Allocator::new(), Table::default());
+        assert_eq!(
+            Err(MergeError::NoMergeExtension),
+            merge(&heap, &table, 1, 1, b"k", &u64_le(1))
+        );
+        assert_eq!(
+            Err(MergeError::EmptyKey),
+            merge(&heap, &table, 1, 1, b"", &u64_le(1))
+        );
+    }
+}
@@ -24,6 +24,8 @@ use e2d2::interface::Packet;
 use e2d2::headers::UdpHeader;
 use e2d2::common::EmptyMetadata;
 
+use sandstorm::common::TenantId;
+
 // The expected type signature on a generator for a native operation (ex: get()). The return
 // value is an optional tuple consisting of a request and response packet parsed/deparsed upto
 // their UDP headers. This is to allow for operations that might not require a response packet
@@ -56,6 +58,9 @@ pub struct Native {
     // The underlying generator for the task. Running the task effectively runs this generator.
     gen: NativeGenerator,
 
+    // The tenant whose request the task serves, if known.
+    tenant: Option<TenantId>,
+
     // The result (if any) returned by the generator once it completes execution.
     res: Cell<
         Option<(
@@ -86,6 +91,7 @@ impl Native {
             db_time: 0,
             priority: prio,
             gen: generator,
+            tenant: None,
             res: Cell::new(None),
         }
     }
@@ -163,4 +169,14 @@ impl Task for Native {
 
     /// Refer to the `Task` trait for Documentation.
     fn update_cache(&mut self, _record: &[u8], _keylen: usize) {}
+
+    /// Refer to the `Task` trait for Documentation.
+    fn set_tenant(&mut self, tenant: TenantId) {
+        self.tenant = Some(tenant);
+    }
+
+    /// Refer to the `Task` trait for Documentation.
+    fn tenant(&self) -> Option<TenantId> {
+        self.tenant
+    }
 }
@@ -0,0 +1,818 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use std::cell::{Cell, RefCell};
+use std::sync::Arc;
+
+use super::alloc::Allocator;
+use super::coalesce::{self, Outcome, Role};
+use super::cycles;
+use super::hint::{self, Hint};
+use super::integrity;
+use super::master::accessor;
+use super::table::Table;
+use super::task::TaskState::*;
+use super::task::{Task, TaskPriority, TaskState};
+use super::tenant::Tenant;
+use super::wireformat::*;
+
+use e2d2::common::EmptyMetadata;
+use e2d2::headers::UdpHeader;
+use e2d2::interface::Packet;
+
+use sandstorm::common::{TableId, TenantId, PACKET_UDP_LEN};
+
+// The maximum number of idle tasks a core's pool holds on to. A core never has more tasks in
+// flight than it's scheduler has queued up, so this is plenty; any more are freed.
+const POOL_CAPACITY: usize = 1024;
+
+thread_local! {
+    // Idle tasks, ready to be handed out to the next get() or put() on this core.
+    static POOL: RefCell<Vec<Box<Pooled>>> = RefCell::new(Vec::with_capacity(POOL_CAPACITY));
+
+    // Counters of the get() and put() tasks created on this core.
+    static STATS: Cell<PoolStats> = Cell::new(PoolStats::default());
+}
+
+/// Counters of the tasks created for native get() and put() requests on a core.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PoolStats {
+    /// The number of tasks created.
+    pub tasks: u64,
+
+    /// The number of heap allocations made to create those tasks. A generator based task costs
+    /// two (the generator and the task), a pooled task costs one, and only if the pool was empty.
+    pub allocs: u64,
+}
+
+/// Returns the counters of the calling core.
+pub fn stats() -> PoolStats {
+    STATS.with(|s| s.get())
+}
+
+/// Records a task created on the calling core.
+///
+/// # Arguments
+///
+/// * `allocs`: The number of heap allocations made to create the task.
+pub fn count(allocs: u64) {
+    STATS.with(|s| {
+        let mut stats = s.get();
+        stats.tasks += 1;
+        stats.allocs += allocs;
+        s.set(stats);
+    });
+}
+
+/// A get() request whose headers have been parsed, and whose response header has been pushed.
+pub struct GetOp {
+    /// The allocator the table's objects live on.
+    pub alloc: *const Allocator,
+
+    /// The issuing tenant. Only required if `table` is None.
+    pub tenant: Option<Arc<Tenant>>,
+
+    /// The result of resolving the table at dispatch, or None if it has to be looked up here.
+    pub table: Option<Result<Arc<Table>, RpcStatus>>,
+
+    /// The table to lookup, if it was not resolved at dispatch.
+    pub table_id: TableId,
+
+    /// The length of the key at the head of the request payload.
+    pub key_length: u16,
+
+    /// Whether the request came from a client or from an extension on the client.
+    pub generator: GetGenerator,
+
+    /// The offset and length of the part of the value the request asked for.
+    pub projection: (u32, u32),
+
+    /// Set if the request asked for a verified read, to whether an object that fails to verify
+    /// is quarantined. Refer to integrity::verify_read().
+    pub verify: Option<bool>,
+
+    /// The request's part in get() coalescing, or None if it is not coalesced.
+    pub coalesce: Option<Role>,
+
+    /// The request packet.
+    pub req: Packet<GetRequest, EmptyMetadata>,
+
+    /// The response packet.
+    pub res: Packet<GetResponse, EmptyMetadata>,
+}
+
+// Resolves the table a get() reads, and looks it's key up. Quarantined objects, and objects that
+// fail to verify on a verified read, are not returned. If the object is hot, the caching hint for
+// it is written to `hint`.
+fn lookup(
+    alloc: &Allocator,
+    tenant_id: TenantId,
+    tenant: Option<Arc<Tenant>>,
+    table: Option<Result<Arc<Table>, RpcStatus>>,
+    table_id: TableId,
+    key: &[u8],
+    verify: Option<bool>,
+    hint: &mut Option<Hint>,
+) -> Outcome {
+    let table = match table {
+        // The table was resolved when the request was dispatched.
+        Some(table) => table?,
+
+        // Check if the tenant exists. If it does, then check if the table exists and can be read
+        // natively.
+        None => match tenant {
+            Some(tenant) => tenant.readable_native_table(table_id)?,
+            None => return Err(RpcStatus::StatusTenantDoesNotExist),
+        },
+    };
+
+    let entry = table.get(key).ok_or(RpcStatus::StatusObjectDoesNotExist)?;
+    if table.is_quarantined(key) {
+        return Err(RpcStatus::StatusDataCorrupted);
+    }
+    if let Some(quarantine) = verify {
+        integrity::verify_read(alloc, tenant_id, table_id, &table, &entry, quarantine)?;
+    }
+
+    *hint = hint::hint(&table, &entry);
+    let (key, value) = alloc
+        .resolve(entry.value)
+        .ok_or(RpcStatus::StatusInternalError)?;
+    Ok((key, value, entry.version))
+}
+
+impl GetOp {
+    /// Looks up the key, and writes the value (along with the version and key, if requested by
+    /// an extension) and status into the response. A coalesced request that follows a leader
+    /// that already ran uses the leader's lookup instead.
+    ///
+    /// # Return
+    ///
+    /// The request and response packets, deparsed upto their UDP headers.
+    pub fn execute(
+        self,
+    ) -> (
+        Packet<UdpHeader, EmptyMetadata>,
+        Packet<UdpHeader, EmptyMetadata>,
+    ) {
+        let GetOp {
+            alloc,
+            tenant,
+            table,
+            table_id,
+            key_length,
+            generator,
+            projection,
+            verify,
+            coalesce,
+            req,
+            mut res,
+        } = self;
+
+        let shared = match coalesce {
+            Some(Role::Follower(ref leader)) => coalesce::outcome(leader),
+            _ => None,
+        };
+
+        // Only requests that look their key up get a caching hint; followers go without.
+        let mut hint = None;
+        let outcome = match shared {
+            Some(outcome) => outcome,
+            None => {
+                let tenant_id = req.get_header().common_header.tenant() as TenantId;
+                let (key, _) = req.get_payload().split_at(key_length as usize);
+                lookup(
+                    accessor(alloc),
+                    tenant_id,
+                    tenant,
+                    table,
+                    table_id,
+                    key,
+                    verify,
+                    &mut hint,
+                )
+            }
+        };
+
+        // A leader hands it's outcome to followers before writing it's own response.
+        if let Some(Role::Leader(leader)) = coalesce {
+            leader.publish(&outcome);
+        }
+
+        // Write the outcome into the response, and update the response header with the status
+        // and a value length derived from the payload. If the RPC failed, the payload is dropped
+        // and the value length is zero. Followers share the leader's lookup, but each projects
+        // the value on it's own.
+        coalesce::fill_response(&mut res, &generator, projection, &outcome);
+        hint::apply(res.get_mut_header(), hint);
+
+        // Deparse request and response packets down to UDP.
+        (
+            req.deparse_header(PACKET_UDP_LEN as usize),
+            res.deparse_header(PACKET_UDP_LEN as usize),
+        )
+    }
+}
+
+/// A put() request whose headers have been parsed, and whose response header has been pushed.
+pub struct PutOp {
+    /// The allocator the new object will be allocated on.
+    pub alloc: *const Allocator,
+
+    /// The issuing tenant, if it exists.
+    pub tenant: Option<Arc<Tenant>>,
+
+    /// The issuing tenant's identifier.
+    pub tenant_id: TenantId,
+
+    /// The table to write to.
+    pub table_id: TableId,
+
+    /// The length of the key at the head of the request payload.
+    pub key_length: u16,
+
+    /// The request packet.
+    pub req: Packet<PutRequest, EmptyMetadata>,
+
+    /// The response packet.
+    pub res: Packet<PutResponse, EmptyMetadata>,
+}
+
+impl PutOp {
+    /// Allocates an object for the key and value, inserts it into the table, and writes the
+    /// status into the response.
+    ///
+    /// # Return
+    ///
+    /// The request and response packets, deparsed upto their UDP headers.
+    pub fn execute(
+        self,
+    ) -> (
+        Packet<UdpHeader, EmptyMetadata>,
+        Packet<UdpHeader, EmptyMetadata>,
+    ) {
+        let PutOp {
+            alloc,
+            tenant,
+            tenant_id,
+            table_id,
+            key_length,
+            req,
+            mut res,
+        } = self;
+
+        let mut status: RpcStatus = RpcStatus::StatusTenantDoesNotExist;
+
+        // If the tenant exists, check if it has a table with the given id
+        // that it can write to natively, and update the status of the rpc.
+        let outcome = tenant.and_then(|tenant| match tenant.writable_native_table(table_id) {
+            Ok(table) => Some(table),
+            Err(err) => {
+                status = err;
+                None
+            }
+        });
+
+        // If the table exists, update the status of the rpc, and allocate an
+        // object.
+        if let Some(table) = outcome {
+            // Get a reference to the key and value.
+            status = RpcStatus::StatusMalformedRequest;
+            let (key, val) = req.get_payload().split_at(key_length as usize);
+
+            // If the object is within the server's limits and there is a value, then write it in.
+            let alloc: &Allocator = accessor(alloc);
+            if let Err(err) = alloc.limits().check(key.len(), val.len()) {
+                status = err;
+            } else if val.len() > 0 {
+                status = RpcStatus::StatusInternalError;
+                let _result = alloc
+                    .object(tenant_id, table_id, key, val)
+                    // If the allocation succeeds, update the status of the rpc, and insert the
+                    // object into the table.
+                    .and_then(|(key, obj)| {
+                        status = RpcStatus::StatusOk;
+                        alloc.store(&table, key, obj);
+                        Some(())
+                    });
+            }
+        }
+
+        // Update the response header.
+        res.get_mut_header().common_header.status = status;
+
+        // Deparse request and response packets to UDP.
+        (
+            req.deparse_header(PACKET_UDP_LEN as usize),
+            res.deparse_header(PACKET_UDP_LEN as usize),
+        )
+    }
+}
+
+/// A native operation that can be run by a pooled task.
+pub enum Op {
+    /// A get() request.
+    Get(GetOp),
+
+    /// A put() request.
+    Put(PutOp),
+}
+
+impl Op {
+    /// Runs the operation to completion.
+    ///
+    /// # Return
+    ///
+    /// The request and response packets, deparsed upto their UDP headers.
+    pub fn execute(
+        self,
+    ) -> (
+        Packet<UdpHeader, EmptyMetadata>,
+        Packet<UdpHeader, EmptyMetadata>,
+    ) {
+        match self {
+            Op::Get(op) => op.execute(),
+            Op::Put(op) => op.execute(),
+        }
+    }
+}
+
+/// A task for a native get() or put() request that runs it's operation directly instead of
+/// through a boxed generator. Once the scheduler is done with it, the task is returned to the
+/// pool of the core it ran on, so that steady state requests do not allocate at all.
+///
+/// Neither operation ever yields, so a pooled task goes through exactly the same states as the
+/// Native task it replaces: it completes on it's first run.
+pub struct Pooled {
+    // The current execution state of the task.
+    state: TaskState,
+
+    // The total amount of time for which the task has run on the CPU in cycles.
+    time: u64,
+
+    // The operation to be run. Taken when the task runs.
+    op: Option<Op>,
+
+    // The tenant whose request the task serves, if known.
+    tenant: Option<TenantId>,
+
+    // The request and response packets, once the operation has run.
+    res: Option<(
+        Packet<UdpHeader, EmptyMetadata>,
+        Packet<UdpHeader, EmptyMetadata>,
+    )>,
+}
+
+impl Pooled {
+    /// Takes a task from the calling core's pool, allocating one if the pool is empty.
+    ///
+    /// # Arguments
+    ///
+    /// * `op`: The operation the task will run.
+    ///
+    /// # Return
+    ///
+    /// A task that can be handed off to, and run by the scheduler.
+    pub fn new(op: Op) -> Box<Task> {
+        let mut task = match POOL.with(|pool| pool.borrow_mut().pop()) {
+            Some(task) => {
+                count(0);
+                task
+            }
+
+            None => {
+                count(1);
+                Box::new(Pooled {
+                    state: INITIALIZED,
+                    time: 0,
+                    op: None,
+                    tenant: None,
+                    res: None,
+                })
+            }
+        };
+
+        task.state = INITIALIZED;
+        task.time = 0;
+        task.op = Some(op);
+        task.tenant = None;
+        task
+    }
+}
+
+// Implementation of the Task trait on Pooled.
+impl Task for Pooled {
+    /// Refer to the Task trait for documentation.
+    fn run(&mut self) -> (TaskState, u64) {
+        let start = cycles::rdtsc();
+
+        if self.state == INITIALIZED {
+            self.state = RUNNING;
+            self.res = self.op.take().map(|op| op.execute());
+            self.state = COMPLETED;
+        }
+
+        // Get the continuous time this task executed for.
+        let exec = cycles::rdtsc() - start;
+
+        // Update the total time this task has executed for and return.
+        self.time += exec;
+
+        return (self.state.clone(), exec);
+    }
+
+    /// Refer to the Task trait for documentation.
+    fn state(&self) -> TaskState {
+        self.state.clone()
+    }
+
+    /// Refer to the Task trait for documentation.
+    fn time(&self) -> u64 {
+        self.time.clone()
+    }
+
+    /// Refer to the Task trait for documentation.
+    fn db_time(&self) -> u64 {
+        0
+    }
+
+    /// Refer to the Task trait for documentation.
+    fn priority(&self) -> TaskPriority {
+        TaskPriority::REQUEST
+    }
+
+    /// Refer to the Task trait for documentation.
+    unsafe fn tear(
+        &mut self,
+    ) -> Option<(
+        Packet<UdpHeader, EmptyMetadata>,
+        Packet<UdpHeader, EmptyMetadata>,
+    )> {
+        self.res.take()
+    }
+
+    /// Refer to the `Task` trait for Documentation.
+    fn set_state(&mut self, state: TaskState) {
+        self.state = state;
+    }
+
+    /// Refer to the `Task` trait for Documentation.
+    fn update_cache(&mut self, _record: &[u8], _keylen: usize) {}
+
+    /// Refer to the `Task` trait for Documentation.
+    fn set_tenant(&mut self, tenant: TenantId) {
+        self.tenant = Some(tenant);
+    }
+
+    /// Refer to the `Task` trait for Documentation.
+    fn tenant(&self) -> Option<TenantId> {
+        self.tenant
+    }
+
+    /// Refer to the `Task` trait for Documentation.
+    fn recycle(mut self: Box<Self>) {
+        // Drop anything the scheduler did not take, so that the pool does not hold on to packets.
+        self.op = None;
+        self.res = None;
+
+        POOL.with(|pool| {
+            let mut pool = pool.borrow_mut();
+            if pool.len() < POOL_CAPACITY {
+                pool.push(self);
+            }
+        });
+    }
+}
+
+// This module contains unit tests for pooled tasks. Requests are built in DPDK memory, but never
+// touch the network, so no NIC is required.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Once, ONCE_INIT};
+
+    use super::super::native::Native;
+    use super::super::rpc;
+
+    use e2d2::headers::{EndOffset, IpHeader, MacHeader};
+    use e2d2::interface::dpdk::init_system_wl;
+    use e2d2::interface::new_packet;
+
+    // The tenant and table requests are issued against.
+    const TENANT: TenantId = 1;
+    const TABLE: TableId = 7;
+
+    static DPDK: Once = ONCE_INIT;
+
+    // Sets up DPDK's memory pools, so that packets can be allocated.
+    fn init() {
+        DPDK.call_once(|| init_system_wl("pool_tests", 0, &[]));
+    }
+
+    // Returns the value written under key [1].
+    fn value() -> Vec<u8> {
+        (0..32).collect()
+    }
+
+    // Returns an allocator, and a tenant with a checksummed table. The table holds an object
+    // under key [1], and one under key [2] whose value was corrupted in place.
+    fn world() -> (Allocator, Arc<Tenant>) {
+        init();
+        let heap = Allocator::new();
+        let tenant = Tenant::new(TENANT);
+        tenant.create_table(TABLE);
+        let table = tenant.get_table(TABLE).unwrap();
+        table.set_checksums(true);
+        for &(key, ref val) in [(1, value()), (2, vec![2; 32])].iter() {
+            let (k, obj) = heap.object(TENANT, TABLE, &[key], val).unwrap();
+            heap.store(&table, k, obj);
+        }
+
+        let object = table.get(&[2]).unwrap().value;
+        unsafe {
+            let last = object.as_ptr().offset(object.len() as isize - 1) as *mut u8;
+            *last ^= 0xff;
+        }
+        (heap, Arc::new(tenant))
+    }
+
+    // Returns a packet with headers pushed upto UDP, for a response to be built on.
+    fn response() -> Packet<UdpHeader, EmptyMetadata> {
+        new_packet()
+            .expect("Failed to allocate response packet.")
+            .push_header(&MacHeader::new())
+            .expect("Failed to push MAC header into response.")
+            .push_header(&IpHeader::new())
+            .expect("Failed to push IP header into response.")
+            .push_header(&UdpHeader::new())
+            .expect("Failed to push UDP header into response.")
+    }
+
+    // Returns the operation for a get() request, with it's response header pushed.
+    fn get_op(
+        heap: &Allocator,
+        tenant: &Arc<Tenant>,
+        table_id: TableId,
+        key: &[u8],
+        generator: GetGenerator,
+        projection: (u32, u32),
+        verify: Option<bool>,
+    ) -> Op {
+        let (mac, ip, udp) = (MacHeader::new(), IpHeader::new(), UdpHeader::new());
+        let req = rpc::create_get_rpc_with_projection(
+            &mac,
+            &ip,
+            &udp,
+            TENANT,
+            table_id,
+            key,
+            1,
+            0,
+            0,
+            generator.clone(),
+            0,
+            projection,
+        );
+        let res = GetResponse::new(1, 0, OpCode::SandstormGetRpc, TENANT);
+
+        Op::Get(GetOp {
+            alloc: heap,
+            tenant: Some(Arc::clone(tenant)),
+            table: None,
+            table_id: table_id,
+            key_length: key.len() as u16,
+            generator: generator,
+            projection: projection,
+            verify: verify,
+            coalesce: None,
+            req: req.parse_header::<UdpHeader>().parse_header::<GetRequest>(),
+            res: response()
+                .push_header(&res)
+                .expect("Failed to setup GetResponse"),
+        })
+    }
+
+    // Returns the operation for a put() request, with it's response header pushed.
+    fn put_op(
+        heap: &Allocator,
+        tenant: &Arc<Tenant>,
+        table_id: TableId,
+        key: &[u8],
+        val: &[u8],
+    ) -> Op {
+        let (mac, ip, udp) = (MacHeader::new(), IpHeader::new(), UdpHeader::new());
+        let req = rpc::create_put_rpc(&mac, &ip, &udp, TENANT, table_id, key, val, 1, 0, 0);
+        let res = PutResponse::new(1, 0, OpCode::SandstormPutRpc, TENANT);
+
+        Op::Put(PutOp {
+            alloc: heap,
+            tenant: Some(Arc::clone(tenant)),
+            tenant_id: TENANT,
+            table_id: table_id,
+            key_length: key.len() as u16,
+            req: req.parse_header::<UdpHeader>().parse_header::<PutRequest>(),
+            res: response()
+                .push_header(&res)
+                .expect("Failed to setup PutResponse"),
+        })
+    }
+
+    // Returns a pooled task for an operation, or a Native task wrapping it in a generator the
+    // way Master does.
+    #[allow(unreachable_code)]
+    fn task(op: Op, pooled: bool) -> Box<Task> {
+        match pooled {
+            true => Pooled::new(op),
+            false => Box::new(Native::new(
+                TaskPriority::REQUEST,
+                Box::new(move || {
+                    return Some(op.execute());
+                    yield 0;
+                }),
+            )),
+        }
+    }
+
+    // Runs a task to completion, hands it back to the scheduler's pool, and returns the status
+    // and payload of it's response. The response is read through `R`'s header.
+    fn run<R: EndOffset<PreviousHeader = UdpHeader>>(
+        mut task: Box<Task>,
+        status: fn(&R) -> RpcStatus,
+    ) -> (RpcStatus, Vec<u8>) {
+        assert!(task.run().0 == COMPLETED);
+        assert!(task.state() == COMPLETED);
+        let (req, res) = unsafe { task.tear() }.expect("Task returned no packets.");
+        task.recycle();
+
+        let res = res.parse_header::<R>();
+        let out = (status(res.get_header()), res.get_payload().to_vec());
+        req.free_packet();
+        res.deparse_header(PACKET_UDP_LEN as usize).free_packet();
+        out
+    }
+
+    // Issues a get() on a fresh table, and returns the status and payload of it's response.
+    fn get(
+        pooled: bool,
+        table_id: TableId,
+        key: &[u8],
+        generator: GetGenerator,
+        projection: (u32, u32),
+        verify: Option<bool>,
+    ) -> (RpcStatus, Vec<u8>) {
+        let (heap, tenant) = world();
+        let op = get_op(&heap, &tenant, table_id, key, generator, projection, verify);
+        run(task(op, pooled), |hdr: &GetResponse| {
+            hdr.common_header.status.clone()
+        })
+    }
+
+    // Issues a put() on a fresh table, and returns the status of it's response along with the
+    // value left under the key.
+    fn put(
+        pooled: bool,
+        table_id: TableId,
+        key: &[u8],
+        val: &[u8],
+    ) -> (RpcStatus, Option<Vec<u8>>) {
+        let (heap, tenant) = world();
+        let op = put_op(&heap, &tenant, table_id, key, val);
+        let (status, _) = run(task(op, pooled), |hdr: &PutResponse| {
+            hdr.common_header.status.clone()
+        });
+
+        let value = tenant
+            .get_table(TABLE)
+            .and_then(|table| table.get(key))
+            .and_then(|entry| heap.resolve(entry.value))
+            .map(|(_, value)| value.to_vec());
+        (status, value)
+    }
+
+    // Tests that get()s get the same statuses and payloads on a pooled task as on a Native one;
+    // on a hit, a miss, a missing table, a projection, a read by an extension, and verified
+    // reads of a sound and a corrupt object.
+    #[test]
+    fn test_pooled_get() {
+        let client = GetGenerator::SandstormClient;
+        let extension = GetGenerator::SandstormExtension;
+        let cases = vec![
+            (TABLE, 1, client.clone(), (0, 0), None),
+            (TABLE, 3, client.clone(), (0, 0), None),
+            (TABLE + 1, 1, client.clone(), (0, 0), None),
+            (TABLE, 1, client.clone(), (4, 8), None),
+            (TABLE, 1, extension, (0, 0), None),
+            (TABLE, 1, client.clone(), (0, 0), Some(false)),
+            (TABLE, 2, client.clone(), (0, 0), Some(false)),
+        ];
+
+        let mut outcomes = Vec::new();
+        for (table_id, key, generator, projection, verify) in cases.into_iter() {
+            let pooled = get(
+                true,
+                table_id,
+                &[key],
+                generator.clone(),
+                projection,
+                verify,
+            );
+            let native = get(false, table_id, &[key], generator, projection, verify);
+            assert_eq!(native, pooled);
+            outcomes.push(pooled);
+        }
+
+        let statuses: Vec<RpcStatus> = outcomes.iter().map(|o| o.0.clone()).collect();
+        assert_eq!(
+            vec![
+                RpcStatus::StatusOk,
+                RpcStatus::StatusObjectDoesNotExist,
+                RpcStatus::StatusTableDoesNotExist,
+                RpcStatus::StatusOk,
+                RpcStatus::StatusOk,
+                RpcStatus::StatusOk,
+                RpcStatus::StatusDataCorrupted,
+            ],
+            statuses
+        );
+        assert_eq!(value(), outcomes[0].1);
+        assert_eq!(&value()[4..12], &outcomes[3].1[..]);
+        assert!(outcomes[4].1.ends_with(&value()));
+    }
+
+    // Tests that put()s get the same statuses on a pooled task as on a Native one, and leave the
+    // same value behind; on an overwrite, an insert, a missing value and a missing table.
+    #[test]
+    fn test_pooled_put() {
+        let cases = vec![
+            (TABLE, 1, vec![9; 16]),
+            (TABLE, 3, vec![9; 16]),
+            (TABLE, 3, vec![]),
+            (TABLE + 1, 3, vec![9; 16]),
+        ];
+
+        let mut outcomes = Vec::new();
+        for (table_id, key, val) in cases.into_iter() {
+            let pooled = put(true, table_id, &[key], &val);
+            let native = put(false, table_id, &[key], &val);
+            assert_eq!(native, pooled);
+            outcomes.push(pooled);
+        }
+
+        assert_eq!(
+            vec![
+                (RpcStatus::StatusOk, Some(vec![9; 16])),
+                (RpcStatus::StatusOk, Some(vec![9; 16])),
+                (RpcStatus::StatusMalformedRequest, None),
+                (RpcStatus::StatusTableDoesNotExist, None),
+            ],
+            outcomes
+        );
+    }
+
+    // Tests that completed tasks are returned to the pool of the core they ran on, and that
+    // tasks are only allocated while the pool is empty.
+    #[test]
+    fn test_pooled_recycle() {
+        let (heap, tenant) = world();
+        POOL.with(|pool| pool.borrow_mut().clear());
+        let idle = || POOL.with(|pool| pool.borrow().len());
+        let status = |hdr: &PutResponse| hdr.common_header.status.clone();
+
+        let before = stats();
+        for _ in 0..3 {
+            let op = put_op(&heap, &tenant, TABLE, &[3], &[9; 16]);
+            assert_eq!(RpcStatus::StatusOk, run(Pooled::new(op), status).0);
+            assert_eq!(1, idle());
+        }
+
+        let after = stats();
+        assert_eq!(3, after.tasks - before.tasks);
+        assert_eq!(1, after.allocs - before.allocs);
+
+        // Tasks handed out while others are still in flight each need one of their own.
+        let tasks: Vec<Box<Task>> = (0..3)
+            .map(|_| Pooled::new(put_op(&heap, &tenant, TABLE, &[3], &[9; 16])))
+            .collect();
+        assert_eq!(0, idle());
+        assert_eq!(2, stats().allocs - after.allocs);
+
+        for task in tasks.into_iter() {
+            run(task, status);
+        }
+        assert_eq!(3, idle());
+        assert_eq!(6, stats().tasks - before.tasks);
+    }
+}
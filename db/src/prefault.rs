@@ -0,0 +1,323 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use std::fs::File;
+use std::io::Read;
+use std::ptr;
+use std::sync::{Once, ONCE_INIT};
+
+use super::cycles;
+
+use libc::{self, c_int};
+
+/// The page size assumed if the OS can't be asked for it.
+pub const DEFAULT_PAGE_SIZE: usize = 4096;
+
+// The size of each allocation the heap is prefaulted with. Must stay below malloc's mmap
+// threshold (128 KB by default), so that allocations come out of (and are freed back into) the
+// calling thread's arena instead of being mapped in and out on their own.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+// Progress is logged every time these many bytes have been prefaulted on a core.
+const PROGRESS_BYTES: usize = 1 << 30;
+
+// glibc's mallopt() parameter controlling how much free memory at the top of an arena is kept
+// around instead of being returned to the OS.
+const M_TRIM_THRESHOLD: c_int = -1;
+
+extern "C" {
+    fn mallopt(param: c_int, value: c_int) -> c_int;
+}
+
+// Trimming is disabled exactly once, by the first call to prefault_heap().
+static NO_TRIM: Once = ONCE_INIT;
+
+/// Returns the size of a page, as reported by the OS.
+pub fn page_size() -> usize {
+    match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+        size if size > 0 => size as usize,
+        _ => DEFAULT_PAGE_SIZE,
+    }
+}
+
+/// Returns the number of pages a region of memory spans, counting the pages it only partly
+/// covers at either end.
+///
+/// # Arguments
+///
+/// * `addr`: The address the region starts at.
+/// * `len`:  The length of the region in bytes.
+/// * `page`: The page size. Must be a power of two.
+pub fn pages_spanned(addr: usize, len: usize, page: usize) -> usize {
+    if len == 0 {
+        return 0;
+    }
+
+    let first = addr & !(page - 1);
+    let last = (addr + len - 1) & !(page - 1);
+    (last - first) / page + 1
+}
+
+/// Writes a zero into every page a region of memory spans, forcing the OS to back all of it.
+/// The first byte of the region and the first byte of every later page are written to.
+///
+/// # Arguments
+///
+/// * `region`: The region to touch. It's contents are clobbered.
+/// * `page`:   The page size. Must be a power of two.
+///
+/// # Return
+///
+/// The number of pages touched.
+pub fn touch(region: &mut [u8], page: usize) -> usize {
+    if region.is_empty() {
+        return 0;
+    }
+
+    let addr = region.as_ptr() as usize;
+    let mut offset = 0;
+    let mut touched = 0;
+    while offset < region.len() {
+        // Volatile, so that the writes aren't optimized away.
+        unsafe { ptr::write_volatile(&mut region[offset], 0) };
+        touched += 1;
+
+        // Move to the start of the next page.
+        offset = ((addr + offset) & !(page - 1)) + page - addr;
+    }
+
+    touched
+}
+
+/// What a call to prefault_heap() did.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Prefault {
+    /// The number of pages touched.
+    pub pages: usize,
+
+    /// The number of bytes touched.
+    pub bytes: usize,
+
+    /// The number of cycles taken.
+    pub cycles: u64,
+}
+
+/// Prefaults the heap of the calling thread. The table heap is carved out of malloc, which hands
+/// each thread an arena of it's own, so this must be called on every thread that will allocate
+/// objects (ex: on every scheduler core) for all of them to benefit. Memory is allocated in
+/// chunks, touched one page at a time, and then freed back into the arena, where it stays backed
+/// for later allocations since trimming is disabled for the rest of the process.
+///
+/// Only affects how quickly pages are backed; nothing allocated here is ever handed out.
+///
+/// # Arguments
+///
+/// * `bytes`: The number of bytes of heap to prefault.
+/// * `core`:  The core the calling thread runs on. Only used for logging.
+pub fn prefault_heap(bytes: usize, core: i32) -> Prefault {
+    NO_TRIM.call_once(|| {
+        if unsafe { mallopt(M_TRIM_THRESHOLD, c_int::max_value()) } != 1 {
+            warn!("Failed to disable heap trimming; prefaulted pages may be returned to the OS");
+        }
+    });
+
+    let start = cycles::rdtsc();
+    let page = page_size();
+    let mut chunks: Vec<Vec<u8>> = Vec::with_capacity(bytes / CHUNK_SIZE + 1);
+    let mut result = Prefault::default();
+
+    while result.bytes < bytes {
+        let mut chunk = vec![0u8; CHUNK_SIZE];
+        result.pages += touch(&mut chunk[..], page);
+        result.bytes += CHUNK_SIZE;
+        chunks.push(chunk);
+
+        if result.bytes % PROGRESS_BYTES == 0 {
+            info!(
+                "Prefaulted {} of {} MB of heap on core {}",
+                result.bytes >> 20,
+                bytes >> 20,
+                core
+            );
+        }
+    }
+
+    drop(chunks);
+    result.cycles = cycles::rdtsc() - start;
+    result
+}
+
+/// Returns the mode transparent huge pages are in ("always", "madvise" or "never"), or None if it
+/// can't be read. The table heap can't ask for huge pages itself; it only gets them if they are
+/// "always" on.
+pub fn transparent_huge_pages() -> Option<String> {
+    let mut enabled = String::new();
+    File::open("/sys/kernel/mm/transparent_hugepage/enabled")
+        .and_then(|mut f| f.read_to_string(&mut enabled))
+        .ok()?;
+    parse_huge_pages(&enabled).map(|mode| mode.to_string())
+}
+
+// Parses the selected mode out of /sys/kernel/mm/transparent_hugepage/enabled, which looks like
+// "always [madvise] never".
+fn parse_huge_pages(enabled: &str) -> Option<&str> {
+    enabled
+        .split_whitespace()
+        .find(|m| m.starts_with('[') && m.ends_with(']'))
+        .map(|m| &m[1..(m.len() - 1)])
+}
+
+/// Returns the number of minor page faults the process has taken so far, or None if
+/// /proc/self/stat can't be read (ex: on anything but Linux).
+pub fn minor_faults() -> Option<u64> {
+    let mut stat = String::new();
+    File::open("/proc/self/stat")
+        .and_then(|mut f| f.read_to_string(&mut stat))
+        .ok()?;
+    parse_minor_faults(&stat)
+}
+
+// Parses the number of minor faults (the 10th field) out of /proc/self/stat. The second field is
+// the command name in parentheses, which can itself hold spaces and parentheses, so fields are
+// counted from the last closing parenthesis.
+fn parse_minor_faults(stat: &str) -> Option<u64> {
+    let rest = &stat[(stat.rfind(')')? + 1)..];
+    rest.split_whitespace().nth(7)?.parse().ok()
+}
+
+/// Counts the minor page faults taken by the process between phases of a run (ex: populating
+/// tables, booting schedulers, serving requests).
+pub struct FaultMeter {
+    // The count at the end of the last phase. None if faults can't be counted.
+    last: Option<u64>,
+}
+
+impl FaultMeter {
+    /// Starts counting faults from now.
+    pub fn new() -> FaultMeter {
+        FaultMeter {
+            last: minor_faults(),
+        }
+    }
+
+    /// Ends a phase, logging and returning the number of minor faults taken during it.
+    ///
+    /// # Arguments
+    ///
+    /// * `phase`: The name of the phase that just ended.
+    ///
+    /// # Return
+    ///
+    /// The number of faults taken since the last phase ended, or None if faults can't be counted.
+    pub fn phase(&mut self, phase: &str) -> Option<u64> {
+        let delta = self.diff(minor_faults());
+        if let Some(delta) = delta {
+            info!("Minor page faults during {}: {}", phase, delta);
+        }
+        delta
+    }
+
+    // Diffs a new count against the last one, and makes it the last one.
+    fn diff(&mut self, now: Option<u64>) -> Option<u64> {
+        let delta = match (self.last, now) {
+            (Some(last), Some(now)) => Some(now.saturating_sub(last)),
+            _ => None,
+        };
+        self.last = now;
+        delta
+    }
+}
+
+// This module contains unit tests for the page walk and fault counting.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Tests that regions are counted against every page they touch, including partial ones.
+    #[test]
+    fn test_pages_spanned() {
+        assert_eq!(0, pages_spanned(4096, 0, 4096));
+        assert_eq!(1, pages_spanned(4096, 1, 4096));
+        assert_eq!(1, pages_spanned(4096, 4096, 4096));
+        assert_eq!(2, pages_spanned(4096, 4097, 4096));
+        assert_eq!(2, pages_spanned(4095, 2, 4096));
+        assert_eq!(3, pages_spanned(4000, 8193, 4096));
+    }
+
+    // Tests that touch() writes once into every page a region spans, wherever it starts.
+    #[test]
+    fn test_touch() {
+        let page = 64;
+        let mut buf = vec![1u8; 16 * page];
+        for &(start, len) in [(0, 16 * page), (1, 64), (63, 2), (10, 5 * page)].iter() {
+            for b in buf.iter_mut() {
+                *b = 1;
+            }
+
+            let region = &mut buf[start..(start + len)];
+            let addr = region.as_ptr() as usize;
+            let touched = touch(region, page);
+            assert_eq!(pages_spanned(addr, len, page), touched);
+            assert_eq!(touched, region.iter().filter(|b| **b == 0).count());
+        }
+
+        assert_eq!(0, touch(&mut [], page));
+    }
+
+    // Tests that the selected huge page mode is parsed out.
+    #[test]
+    fn test_parse_huge_pages() {
+        assert_eq!(
+            Some("madvise"),
+            parse_huge_pages("always [madvise] never\n")
+        );
+        assert_eq!(Some("always"), parse_huge_pages("[always] madvise never"));
+        assert_eq!(None, parse_huge_pages("always madvise never"));
+    }
+
+    // Tests that minor faults are parsed out of /proc/self/stat, even with an odd command name.
+    #[test]
+    fn test_parse_minor_faults() {
+        let stat = "1234 (a) b (c)) S 1 1234 1234 0 -1 4194560 5678 0 12 0 3 1 0 0 20 0 1 0";
+        assert_eq!(Some(5678), parse_minor_faults(stat));
+        assert_eq!(None, parse_minor_faults("1234 (server) S 1"));
+        assert_eq!(None, parse_minor_faults("garbage"));
+    }
+
+    // Tests that phases are diffed against the end of the previous phase.
+    #[test]
+    fn test_fault_meter_diff() {
+        let mut meter = FaultMeter { last: Some(100) };
+        assert_eq!(Some(50), meter.diff(Some(150)));
+        assert_eq!(Some(0), meter.diff(Some(150)));
+        assert_eq!(None, meter.diff(None));
+        assert_eq!(None, meter.diff(Some(200)));
+        assert_eq!(Some(10), meter.diff(Some(210)));
+    }
+
+    // Tests that faults are counted on Linux, and go up once fresh memory is touched.
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_minor_faults_linux() {
+        let before = minor_faults().expect("Failed to read /proc/self/stat");
+
+        // Large enough to be mapped in on it's own, so none of it is backed yet.
+        let mut buf = vec![0u8; 8 << 20];
+        touch(&mut buf[..], page_size());
+
+        let after = minor_faults().expect("Failed to read /proc/self/stat");
+        assert!(after > before, "{} faults before, {} after", before, after);
+    }
+}
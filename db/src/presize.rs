@@ -0,0 +1,199 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Pre-sizing tables for the number of objects they are expected to hold. Each of a table's
+//! buckets is a hash map that grows by moving every object it holds into one twice the size,
+//! with the bucket's write lock held; filling millions of objects into an empty table grows
+//! every bucket over and over. A table created with an expected record count reserves room for
+//! them up front instead, and is never grown while it is filled.
+
+use libc;
+
+use super::table::{N_BUCKETS, SLOT_BYTES};
+use super::wireformat::RpcStatus;
+
+/// The fraction of the slots reserved on a table that it's expected objects fill, if the server
+/// config does not say otherwise. The slack absorbs objects spreading unevenly across buckets.
+pub const DEFAULT_LOAD_FACTOR: f64 = 0.75;
+
+/// The lowest load factor that can be configured. Anything lower mostly reserves memory that is
+/// never used.
+pub const MIN_LOAD_FACTOR: f64 = 0.1;
+
+// The fraction of the machine's memory the index of a single pre-sized table can take, if the
+// server config does not cap pre-sizing itself.
+const DEFAULT_MEMORY_FRACTION: f64 = 0.25;
+
+/// Returns the bytes of physical memory on the machine, or 0 if they can't be read.
+pub fn physical_memory() -> usize {
+    let pages = unsafe { libc::sysconf(libc::_SC_PHYS_PAGES) };
+    let size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    match pages > 0 && size > 0 {
+        true => pages as usize * size as usize,
+        false => 0,
+    }
+}
+
+/// How tables are pre-sized: the load factor they are sized for, and the most objects a table
+/// can be sized for. Tables asked to be sized for more are refused with StatusTableTooLarge
+/// when they are created, instead of the server running out of memory while they are filled.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Presize {
+    load: f64,
+    max_records: u64,
+}
+
+impl Presize {
+    /// Returns a pre-sizing policy.
+    ///
+    /// # Arguments
+    ///
+    /// * `load`:        The fraction of the reserved slots the expected objects fill. Clamped
+    ///                  to between MIN_LOAD_FACTOR and 1.
+    /// * `max_records`: The most objects a table can be sized for.
+    pub fn new(load: f64, max_records: u64) -> Presize {
+        Presize {
+            load: load.max(MIN_LOAD_FACTOR).min(1.0),
+            max_records: max_records,
+        }
+    }
+
+    /// Returns the most objects a table can be sized for by default: as many as fit in an index
+    /// that takes a quarter of the machine's memory at the given load factor.
+    ///
+    /// # Arguments
+    ///
+    /// * `load`: The load factor tables are sized for.
+    pub fn default_max_records(load: f64) -> u64 {
+        let bytes = physical_memory() as f64 * DEFAULT_MEMORY_FRACTION;
+        (bytes / SLOT_BYTES as f64 * load) as u64
+    }
+
+    /// Returns the load factor tables are sized for.
+    pub fn load(&self) -> f64 {
+        self.load
+    }
+
+    /// Returns the most objects a table can be sized for.
+    pub fn max_records(&self) -> u64 {
+        self.max_records
+    }
+
+    /// Returns the number of slots to reserve on a table expected to hold a number of objects:
+    /// enough for them to fill the slots to the load factor, rounded up to a power of two so
+    /// that every bucket reserves the same number. Atleast one slot per bucket.
+    ///
+    /// # Arguments
+    ///
+    /// * `records`: The number of objects the table is expected to hold. 0 if not known.
+    ///
+    /// # Return
+    ///
+    /// The number of slots, 0 if the table is not to be pre-sized, or StatusTableTooLarge if
+    /// the table can't be sized for these many objects.
+    pub fn slots(&self, records: u64) -> Result<usize, RpcStatus> {
+        if records == 0 {
+            return Ok(0);
+        }
+
+        if records > self.max_records {
+            return Err(RpcStatus::StatusTableTooLarge);
+        }
+
+        let slots = (records as f64 / self.load).ceil() as usize;
+        Ok(slots.next_power_of_two().max(N_BUCKETS))
+    }
+}
+
+impl Default for Presize {
+    fn default() -> Presize {
+        let max = Presize::default_max_records(DEFAULT_LOAD_FACTOR);
+        Presize::new(DEFAULT_LOAD_FACTOR, max)
+    }
+}
+
+/// How a table's index is sized, against the objects it holds. Refer to Table::sizing().
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Sizing {
+    /// The number of slots the table was pre-sized to. 0 if it was never pre-sized.
+    pub presized: u64,
+
+    /// The number of objects the table's buckets can hold before one of them has to grow.
+    pub capacity: u64,
+
+    /// The number of objects on the table.
+    pub objects: u64,
+
+    /// The number of times one of the table's buckets grew to make room for more objects.
+    pub resizes: u64,
+}
+
+impl Sizing {
+    /// Returns the fraction of the table's capacity it's objects fill.
+    pub fn load(&self) -> f64 {
+        match self.capacity {
+            0 => 0.0,
+            capacity => self.objects as f64 / capacity as f64,
+        }
+    }
+}
+
+// This module contains unit tests for Presize.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Tests that tables are sized to a power of two atleast one slot per bucket, and that tables
+    // too large for the cap are refused.
+    #[test]
+    fn test_presize_slots() {
+        let presize = Presize::new(0.75, 1_000_000);
+        assert_eq!(Ok(0), presize.slots(0));
+        assert_eq!(Ok(N_BUCKETS), presize.slots(1));
+        assert_eq!(Ok(N_BUCKETS), presize.slots(96));
+        assert_eq!(Ok(2 * N_BUCKETS), presize.slots(97));
+        assert_eq!(Ok(16384), presize.slots(10_000));
+        assert_eq!(Ok(1 << 21), presize.slots(1_000_000));
+        assert_eq!(
+            Err(RpcStatus::StatusTableTooLarge),
+            presize.slots(1_000_001)
+        );
+
+        // A full load factor reserves exactly as many slots as objects, if that's a power of two.
+        assert_eq!(Ok(1 << 20), Presize::new(1.0, 1 << 20).slots(1 << 20));
+    }
+
+    // Tests that load factors are clamped, and that the default cap follows the machine's memory.
+    #[test]
+    fn test_presize_load() {
+        assert_eq!(MIN_LOAD_FACTOR, Presize::new(0.0, 1).load());
+        assert_eq!(1.0, Presize::new(2.0, 1).load());
+
+        let max = Presize::default_max_records(DEFAULT_LOAD_FACTOR);
+        assert!(physical_memory() > 0);
+        assert!(max > 0);
+        assert!(max as usize * SLOT_BYTES < physical_memory());
+        assert_eq!(max, Presize::default().max_records());
+
+        let sizing = Sizing {
+            presized: 256,
+            capacity: 400,
+            objects: 300,
+            resizes: 0,
+        };
+        assert_eq!(0.75, sizing.load());
+        assert_eq!(0.0, Sizing::default().load());
+    }
+}
@@ -0,0 +1,755 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use std::cmp;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::cycles;
+use super::rpc::{parse_rpc_status, parse_rpc_tenant};
+use super::tenant::Tenant;
+use super::wireformat::RpcStatus;
+
+use e2d2::common::EmptyMetadata;
+use e2d2::headers::UdpHeader;
+use e2d2::interface::Packet;
+
+use sandstorm::common::TenantId;
+
+/// The length of the window failures are counted over, by default.
+pub const DEFAULT_WINDOW_MS: u64 = 1000;
+
+/// How long a tenant is quarantined for the first time, by default.
+pub const DEFAULT_QUARANTINE_MS: u64 = 1000;
+
+/// The longest a tenant is quarantined for, however many times it was before, by default.
+pub const DEFAULT_MAX_QUARANTINE_MS: u64 = 60_000;
+
+/// The number of kinds of Signal.
+pub const N_SIGNALS: usize = 3;
+
+/// A kind of failed request that counts towards quarantining the tenant that sent it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Signal {
+    /// The request was malformed, or it's arguments did not fit the extension it invoked.
+    Malformed = 0,
+
+    /// An extension the request ran panicked, was disabled after panicking earlier, or streamed
+    /// back more than it is allowed to.
+    Extension = 1,
+
+    /// The request asked for something the tenant is not allowed to do, such as writing to a
+    /// read-only table or issuing an RPC only tenant 0 can.
+    Auth = 2,
+}
+
+impl Signal {
+    /// Returns the kind of failure a request that completed with a status ran into, or None if
+    /// the status says nothing about how the tenant behaves (ex: StatusObjectDoesNotExist).
+    ///
+    /// # Arguments
+    ///
+    /// * `status`: The status on the request's response.
+    pub fn of(status: &RpcStatus) -> Option<Signal> {
+        match *status {
+            RpcStatus::StatusMalformedRequest | RpcStatus::StatusArgsMismatch => {
+                Some(Signal::Malformed)
+            }
+
+            RpcStatus::StatusExtensionError
+            | RpcStatus::StatusExtensionDisabled
+            | RpcStatus::StatusStreamTooLarge => Some(Signal::Extension),
+
+            RpcStatus::StatusInvalidOperation
+            | RpcStatus::StatusPermissionDenied
+            | RpcStatus::StatusReadOnlyTable => Some(Signal::Auth),
+
+            _ => None,
+        }
+    }
+}
+
+/// Returns the tenant that sent a completed request, and the kind of failure it ran into, if
+/// any. Requests that succeeded, or that are too short to name a tenant, return None.
+///
+/// # Arguments
+///
+/// * `request`:  The request, parsed upto it's UDP header.
+/// * `response`: The response to the request, parsed upto it's UDP header.
+#[inline]
+pub fn observe(
+    request: &Packet<UdpHeader, EmptyMetadata>,
+    response: &Packet<UdpHeader, EmptyMetadata>,
+) -> Option<(TenantId, Signal)> {
+    let signal = Signal::of(&parse_rpc_status(response))?;
+    parse_rpc_tenant(request).map(|tenant| (tenant, signal))
+}
+
+/// When tenants are quarantined, and for how long.
+#[derive(Clone, Debug, PartialEq)]
+pub struct QuarantinePolicy {
+    /// The most failures of each kind, indexed by Signal, a tenant's requests can run into within
+    /// a window before the tenant is quarantined. 0 never quarantines on that kind of failure.
+    pub thresholds: [u64; N_SIGNALS],
+
+    /// The length of the window failures are counted over, in milliseconds.
+    pub window_ms: u64,
+
+    /// How long a tenant is quarantined for the first time, in milliseconds.
+    pub quarantine_ms: u64,
+
+    /// The longest a tenant is quarantined for, in milliseconds.
+    pub max_quarantine_ms: u64,
+}
+
+impl Default for QuarantinePolicy {
+    // Tenants are never quarantined automatically by default.
+    fn default() -> QuarantinePolicy {
+        QuarantinePolicy {
+            thresholds: [0; N_SIGNALS],
+            window_ms: DEFAULT_WINDOW_MS,
+            quarantine_ms: DEFAULT_QUARANTINE_MS,
+            max_quarantine_ms: DEFAULT_MAX_QUARANTINE_MS,
+        }
+    }
+}
+
+impl QuarantinePolicy {
+    /// Returns true if tenants are ever quarantined automatically.
+    pub fn enabled(&self) -> bool {
+        self.thresholds.iter().any(|t| *t > 0)
+    }
+
+    /// Returns how long a tenant that was quarantined `strikes` times before is quarantined
+    /// for, in milliseconds. Each quarantine lasts twice as long as the one before it, upto
+    /// `max_quarantine_ms`.
+    ///
+    /// # Arguments
+    ///
+    /// * `strikes`: The number of times the tenant was quarantined before.
+    pub fn duration_ms(&self, strikes: u64) -> u64 {
+        let mut duration = self.quarantine_ms;
+        for _ in 0..strikes {
+            if duration >= self.max_quarantine_ms {
+                break;
+            }
+            duration = duration.saturating_mul(2);
+        }
+
+        cmp::min(duration, self.max_quarantine_ms)
+    }
+}
+
+// Converts milliseconds into cycles on the calling thread's clock. Never returns 0, so that a
+// converted deadline always lies in the future.
+fn to_cycles(ms: u64) -> u64 {
+    cmp::max(1, ms.saturating_mul(cycles::cycles_per_second()) / 1000)
+}
+
+/// The quarantine of a single tenant, and the failures it's requests ran into recently. Kept on
+/// the tenant. Failures are counted over a sliding window, estimated off the counts of the
+/// current fixed window and the one before it, the latter weighed by how much of it the sliding
+/// window still covers. Every field is updated with relaxed atomics, so counts raced on by
+/// several cores are approximate.
+pub struct Quarantine {
+    // The cycle counter at which the tenant's quarantine runs out. 0 if it isn't quarantined.
+    until: AtomicU64,
+
+    // The number of times the tenant was quarantined since an operator last released it.
+    strikes: AtomicU64,
+
+    // The cycle counter at which the current window started.
+    start: AtomicU64,
+
+    // The failures of each kind counted in the current window, and in the one before it.
+    current: [AtomicU64; N_SIGNALS],
+    previous: [AtomicU64; N_SIGNALS],
+}
+
+impl Quarantine {
+    /// Returns the state of a tenant that was never quarantined, and has no failures counted.
+    pub fn new() -> Quarantine {
+        Quarantine {
+            until: AtomicU64::new(0),
+            strikes: AtomicU64::new(0),
+            start: AtomicU64::new(0),
+            current: [AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0)],
+            previous: [AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0)],
+        }
+    }
+
+    /// Returns true if the tenant is quarantined at a point in time.
+    ///
+    /// # Arguments
+    ///
+    /// * `now`: The cycle counter.
+    #[inline]
+    pub fn active(&self, now: u64) -> bool {
+        let until = self.until.load(Ordering::Relaxed);
+        until != 0 && now < until
+    }
+
+    /// Returns the cycle counter at which the tenant's quarantine runs out, or 0 if it isn't
+    /// quarantined. A quarantine that ran out may still be returned until expire() is called.
+    pub fn until(&self) -> u64 {
+        self.until.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of times the tenant was quarantined since an operator last released
+    /// it.
+    pub fn strikes(&self) -> u64 {
+        self.strikes.load(Ordering::Relaxed)
+    }
+
+    /// Returns the failures of a kind counted over the sliding window ending at a point in time.
+    ///
+    /// # Arguments
+    ///
+    /// * `signal`: The kind of failure.
+    /// * `window`: The length of the window in cycles.
+    /// * `now`:    The cycle counter.
+    pub fn count(&self, signal: Signal, window: u64, now: u64) -> f64 {
+        let i = signal as usize;
+        let start = self.start.load(Ordering::Relaxed);
+        let (current, previous) = match now.saturating_sub(start) {
+            elapsed if elapsed >= 2 * window => return 0.0,
+
+            elapsed if elapsed >= window => (0, self.current[i].load(Ordering::Relaxed)),
+
+            _ => (
+                self.current[i].load(Ordering::Relaxed),
+                self.previous[i].load(Ordering::Relaxed),
+            ),
+        };
+
+        // The previous window is weighed by how much of it the sliding window still covers.
+        let elapsed = now.saturating_sub(start) % window;
+        let covered = (window - elapsed) as f64 / window as f64;
+        current as f64 + previous as f64 * covered
+    }
+
+    /// Releases the tenant if it's quarantine has run out, and forgets the failures counted
+    /// before it. Strikes are kept, so that the next quarantine lasts longer.
+    ///
+    /// # Arguments
+    ///
+    /// * `now`: The cycle counter.
+    ///
+    /// # Return
+    ///
+    /// True if this call released the tenant.
+    pub fn expire(&self, now: u64) -> bool {
+        let until = self.until.load(Ordering::Relaxed);
+        if until == 0 || now < until {
+            return false;
+        }
+
+        if self.until.compare_and_swap(until, 0, Ordering::Relaxed) != until {
+            return false;
+        }
+
+        self.reset(now);
+        true
+    }
+
+    /// Counts a failure against the tenant, and quarantines it if that takes it over the
+    /// policy's threshold for the kind of failure. Failures while the tenant is quarantined
+    /// are not counted.
+    ///
+    /// # Arguments
+    ///
+    /// * `signal`: The kind of failure.
+    /// * `policy`: The thresholds, window and durations of quarantines.
+    /// * `now`:    The cycle counter.
+    ///
+    /// # Return
+    ///
+    /// The length in milliseconds of the quarantine the tenant was put in, if it was.
+    pub fn signal(&self, signal: Signal, policy: &QuarantinePolicy, now: u64) -> Option<u64> {
+        let threshold = policy.thresholds[signal as usize];
+        if threshold == 0 || self.active(now) {
+            return None;
+        }
+
+        let window = to_cycles(policy.window_ms);
+        self.roll(window, now);
+        self.current[signal as usize].fetch_add(1, Ordering::Relaxed);
+        if self.count(signal, window, now) <= threshold as f64 {
+            return None;
+        }
+
+        let duration = policy.duration_ms(self.strikes());
+        match self.enter(duration, now) {
+            true => Some(duration),
+            false => None,
+        }
+    }
+
+    /// Quarantines the tenant for a while, whether or not it already is. Counts as a strike.
+    ///
+    /// # Arguments
+    ///
+    /// * `duration_ms`: How long to quarantine the tenant for, in milliseconds.
+    /// * `now`:         The cycle counter.
+    ///
+    /// # Return
+    ///
+    /// False if another core quarantined the tenant in the meantime, in which case this call
+    /// did nothing.
+    pub fn enter(&self, duration_ms: u64, now: u64) -> bool {
+        let until = self.until.load(Ordering::Relaxed);
+        let deadline = now.saturating_add(to_cycles(duration_ms));
+        let prev = self
+            .until
+            .compare_and_swap(until, deadline, Ordering::Relaxed);
+        if prev != until {
+            return false;
+        }
+
+        self.strikes.fetch_add(1, Ordering::Relaxed);
+        true
+    }
+
+    /// Releases the tenant right away, and forgets both the failures counted against it and the
+    /// times it was quarantined before.
+    ///
+    /// # Arguments
+    ///
+    /// * `now`: The cycle counter.
+    ///
+    /// # Return
+    ///
+    /// True if the tenant was quarantined.
+    pub fn release(&self, now: u64) -> bool {
+        let active = self.active(now);
+        self.until.store(0, Ordering::Relaxed);
+        self.strikes.store(0, Ordering::Relaxed);
+        self.reset(now);
+        active
+    }
+
+    // Starts a new window if the current one has run out, moving it's counts into the previous
+    // one. Windows stay aligned to the first one, so that the weight of the previous window
+    // only depends on the time.
+    fn roll(&self, window: u64, now: u64) {
+        let start = self.start.load(Ordering::Relaxed);
+        let elapsed = now.saturating_sub(start);
+        if elapsed < window {
+            return;
+        }
+
+        let next = start + elapsed - elapsed % window;
+        if self.start.compare_and_swap(start, next, Ordering::Relaxed) != start {
+            return;
+        }
+
+        // Counts older than a window before the new one fall out of the sliding window.
+        for i in 0..N_SIGNALS {
+            let current = self.current[i].swap(0, Ordering::Relaxed);
+            let previous = match elapsed < 2 * window {
+                true => current,
+                false => 0,
+            };
+            self.previous[i].store(previous, Ordering::Relaxed);
+        }
+    }
+
+    // Forgets every failure counted, and starts a new window.
+    fn reset(&self, now: u64) {
+        for i in 0..N_SIGNALS {
+            self.current[i].store(0, Ordering::Relaxed);
+            self.previous[i].store(0, Ordering::Relaxed);
+        }
+        self.start.store(now, Ordering::Relaxed);
+    }
+}
+
+/// The counters kept by Quarantines, as reported by stats().
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct QuarantineStats {
+    /// The number of times any tenant was quarantined, automatically or by an operator.
+    pub quarantines: u64,
+
+    /// The number of requests refused with StatusTenantQuarantined.
+    pub rejected: u64,
+}
+
+/// Decides which tenants are quarantined, and counts their quarantines across the server. The
+/// state of each tenant is kept on the tenant itself (see Tenant::quarantine()); this only holds
+/// the policy, the counters, and how long any tenant can still be quarantined for, so that the
+/// dispatcher only looks tenants up while one might be. Tenant 0 is never quarantined.
+pub struct Quarantines {
+    // When tenants are quarantined, and for how long.
+    policy: QuarantinePolicy,
+
+    // The latest cycle counter at which any tenant's quarantine runs out.
+    horizon: AtomicU64,
+
+    // The number of times any tenant was quarantined.
+    quarantines: AtomicU64,
+
+    // The number of requests refused with StatusTenantQuarantined.
+    rejected: AtomicU64,
+}
+
+impl Quarantines {
+    /// Creates counters that quarantine tenants under a policy.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy`: When tenants are quarantined, and for how long.
+    pub fn new(policy: QuarantinePolicy) -> Quarantines {
+        Quarantines {
+            policy: policy,
+            horizon: AtomicU64::new(0),
+            quarantines: AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the policy tenants are quarantined under.
+    pub fn policy(&self) -> &QuarantinePolicy {
+        &self.policy
+    }
+
+    /// Returns true if tenants are ever quarantined automatically, i.e. if failed requests
+    /// should be reported through signal().
+    pub fn enabled(&self) -> bool {
+        self.policy.enabled()
+    }
+
+    /// Returns true if any tenant might be quarantined at a point in time. If not, requests need
+    /// not be checked against their tenant's quarantine.
+    ///
+    /// # Arguments
+    ///
+    /// * `now`: The cycle counter.
+    #[inline]
+    pub fn active(&self, now: u64) -> bool {
+        now < self.horizon.load(Ordering::Relaxed)
+    }
+
+    /// Returns true if requests from a tenant are to be refused at a point in time. Releases the
+    /// tenant if it's quarantine has run out.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`: The tenant.
+    /// * `now`:    The cycle counter.
+    #[inline]
+    pub fn check(&self, tenant: &Tenant, now: u64) -> bool {
+        let quarantine = tenant.quarantine();
+        if quarantine.active(now) {
+            return true;
+        }
+
+        if quarantine.expire(now) {
+            info!("Tenant {} released from quarantine", tenant.id());
+        }
+        false
+    }
+
+    /// Counts a failed request against the tenant that sent it, and quarantines the tenant if it
+    /// went over the policy's threshold. Does nothing for tenant 0.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`: The tenant that sent the request.
+    /// * `signal`: The kind of failure the request ran into.
+    /// * `now`:    The cycle counter.
+    pub fn signal(&self, tenant: &Tenant, signal: Signal, now: u64) {
+        if tenant.id() == 0 {
+            return;
+        }
+
+        let quarantine = tenant.quarantine();
+        if quarantine.expire(now) {
+            info!("Tenant {} released from quarantine", tenant.id());
+        }
+
+        if let Some(duration) = quarantine.signal(signal, &self.policy, now) {
+            warn!(
+                "Tenant {} quarantined for {} ms after too many {:?} failures ({} strikes)",
+                tenant.id(),
+                duration,
+                signal,
+                quarantine.strikes()
+            );
+            self.entered(quarantine);
+        }
+    }
+
+    /// Quarantines a tenant on an operator's behalf, whether or not it already is.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`:      The tenant to quarantine. Must not be tenant 0.
+    /// * `duration_ms`: How long to quarantine the tenant for, in milliseconds. 0 picks the
+    ///                  duration the policy would have for the tenant's next quarantine.
+    /// * `now`:         The cycle counter.
+    ///
+    /// # Return
+    ///
+    /// False if the tenant is tenant 0, which is never quarantined.
+    pub fn quarantine(&self, tenant: &Tenant, duration_ms: u64, now: u64) -> bool {
+        if tenant.id() == 0 {
+            return false;
+        }
+
+        let quarantine = tenant.quarantine();
+        let duration = match duration_ms {
+            0 => self.policy.duration_ms(quarantine.strikes()),
+            duration => duration,
+        };
+
+        // Only a concurrent quarantine can get in the way, which leaves the tenant quarantined.
+        if quarantine.enter(duration, now) {
+            warn!(
+                "Tenant {} quarantined for {} ms by request",
+                tenant.id(),
+                duration
+            );
+            self.entered(quarantine);
+        }
+        true
+    }
+
+    /// Releases a tenant on an operator's behalf, and forgets the times it was quarantined.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`: The tenant to release.
+    /// * `now`:    The cycle counter.
+    pub fn release(&self, tenant: &Tenant, now: u64) {
+        if tenant.quarantine().release(now) {
+            info!("Tenant {} released from quarantine by request", tenant.id());
+        }
+    }
+
+    /// Counts a request refused because it's tenant was quarantined.
+    #[inline]
+    pub fn rejected(&self) {
+        self.rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the quarantines and refused requests counted so far.
+    pub fn stats(&self) -> QuarantineStats {
+        QuarantineStats {
+            quarantines: self.quarantines.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+        }
+    }
+
+    // Counts a quarantine, and makes sure requests are checked against it until it runs out.
+    fn entered(&self, quarantine: &Quarantine) {
+        self.quarantines.fetch_add(1, Ordering::Relaxed);
+        self.horizon
+            .fetch_max(quarantine.until(), Ordering::Relaxed);
+    }
+}
+
+// This module contains unit tests for Quarantine and Quarantines.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use super::super::cycles::virt;
+
+    // The frequency of the virtual clock the tests run on: a cycle a microsecond.
+    const HZ: u64 = 1_000_000;
+
+    // Returns the number of cycles in a number of milliseconds on the virtual clock.
+    fn ms(ms: u64) -> u64 {
+        ms * HZ / 1000
+    }
+
+    // Returns a policy that quarantines a tenant after more than 3 malformed requests in 100 ms,
+    // for 1 second the first time and atmost 4 seconds.
+    fn policy() -> QuarantinePolicy {
+        QuarantinePolicy {
+            thresholds: [3, 0, 0],
+            window_ms: 100,
+            quarantine_ms: 1000,
+            max_quarantine_ms: 4000,
+        }
+    }
+
+    // Tests that statuses are classified into the kinds of failure they stand for.
+    #[test]
+    fn test_signal_of() {
+        assert_eq!(
+            Some(Signal::Malformed),
+            Signal::of(&RpcStatus::StatusMalformedRequest)
+        );
+        assert_eq!(
+            Some(Signal::Extension),
+            Signal::of(&RpcStatus::StatusExtensionDisabled)
+        );
+        assert_eq!(
+            Some(Signal::Auth),
+            Signal::of(&RpcStatus::StatusReadOnlyTable)
+        );
+        assert_eq!(None, Signal::of(&RpcStatus::StatusOk));
+        assert_eq!(None, Signal::of(&RpcStatus::StatusObjectDoesNotExist));
+        assert_eq!(None, Signal::of(&RpcStatus::StatusTenantQuarantined));
+    }
+
+    // Tests that each quarantine lasts twice as long as the one before it, upto the cap.
+    #[test]
+    fn test_policy_duration() {
+        let policy = policy();
+        let durations: Vec<u64> = (0..5).map(|s| policy.duration_ms(s)).collect();
+        assert_eq!(vec![1000, 2000, 4000, 4000, 4000], durations);
+        assert_eq!(4000, policy.duration_ms(u64::max_value()));
+        assert!(policy.enabled());
+        assert!(!QuarantinePolicy::default().enabled());
+    }
+
+    // Tests that failures spread out over more than a window never add up to a quarantine,
+    // while the same failures within a window do.
+    #[test]
+    fn test_quarantine_window() {
+        virt::install(1, HZ);
+        let policy = policy();
+        let quarantine = Quarantine::new();
+
+        // One failure every 60 ms keeps the sliding count under 3.
+        for _ in 0..20 {
+            let now = cycles::rdtsc();
+            assert_eq!(None, quarantine.signal(Signal::Malformed, &policy, now));
+            virt::advance(ms(60));
+        }
+
+        // Other kinds of failure are not counted under this policy.
+        for _ in 0..10 {
+            let now = cycles::rdtsc();
+            assert_eq!(None, quarantine.signal(Signal::Auth, &policy, now));
+        }
+        assert!(!quarantine.active(cycles::rdtsc()));
+
+        // Four failures in a row go over the threshold.
+        virt::advance(ms(500));
+        let now = cycles::rdtsc();
+        for _ in 0..3 {
+            assert_eq!(None, quarantine.signal(Signal::Malformed, &policy, now));
+        }
+        let duration = quarantine.signal(Signal::Malformed, &policy, now);
+        assert_eq!(Some(1000), duration);
+        assert!(quarantine.active(now));
+        assert_eq!(now + ms(1000), quarantine.until());
+        assert_eq!(1, quarantine.strikes());
+        virt::uninstall();
+    }
+
+    // Tests that the sliding count weighs the previous window by how much of it is covered.
+    #[test]
+    fn test_quarantine_count() {
+        let quarantine = Quarantine::new();
+        let window = 100;
+        quarantine.roll(window, 1000);
+        for _ in 0..10 {
+            quarantine.current[0].fetch_add(1, Ordering::Relaxed);
+        }
+
+        assert_eq!(10.0, quarantine.count(Signal::Malformed, window, 1050));
+        assert_eq!(7.5, quarantine.count(Signal::Malformed, window, 1125));
+        assert_eq!(0.0, quarantine.count(Signal::Malformed, window, 1200));
+
+        quarantine.roll(window, 1125);
+        assert_eq!(7.5, quarantine.count(Signal::Malformed, window, 1125));
+        assert_eq!(0.0, quarantine.count(Signal::Auth, window, 1125));
+    }
+
+    // Tests that quarantines run out on time, reset the failures counted, and escalate upto the
+    // cap, until an operator releases the tenant.
+    #[test]
+    fn test_quarantine_escalation() {
+        virt::install(1, HZ);
+        let quarantines = Quarantines::new(policy());
+        let tenant = Tenant::new(1);
+        assert!(!quarantines.active(cycles::rdtsc()));
+
+        for &duration in [1000, 2000, 4000, 4000].iter() {
+            for _ in 0..4 {
+                quarantines.signal(&tenant, Signal::Malformed, cycles::rdtsc());
+            }
+            let start = cycles::rdtsc();
+            assert!(quarantines.active(start));
+            assert!(quarantines.check(&tenant, start));
+
+            // Failures while quarantined are not counted.
+            quarantines.signal(&tenant, Signal::Malformed, start);
+
+            virt::advance(ms(duration) - 1);
+            assert!(quarantines.check(&tenant, cycles::rdtsc()));
+            virt::advance(1);
+            assert!(!quarantines.active(cycles::rdtsc()));
+            assert!(!quarantines.check(&tenant, cycles::rdtsc()));
+            assert_eq!(0, tenant.quarantine().until());
+            let count = tenant.quarantine().count(Signal::Malformed, ms(100), start);
+            assert_eq!(0.0, count);
+        }
+        assert_eq!(4, tenant.quarantine().strikes());
+        assert_eq!(4, quarantines.stats().quarantines);
+
+        // Once released by an operator, the next quarantine is back to the shortest.
+        quarantines.release(&tenant, cycles::rdtsc());
+        assert_eq!(0, tenant.quarantine().strikes());
+        for _ in 0..4 {
+            quarantines.signal(&tenant, Signal::Malformed, cycles::rdtsc());
+        }
+        assert_eq!(cycles::rdtsc() + ms(1000), tenant.quarantine().until());
+        virt::uninstall();
+    }
+
+    // Tests that tenant 0 is never quarantined, and that operators can quarantine and release
+    // other tenants whatever the policy.
+    #[test]
+    fn test_quarantine_manual() {
+        virt::install(1, HZ);
+        let quarantines = Quarantines::new(QuarantinePolicy::default());
+        let root = Tenant::new(0);
+        let tenant = Tenant::new(1);
+
+        assert!(!quarantines.quarantine(&root, 100, cycles::rdtsc()));
+        let policy = policy();
+        for _ in 0..10 {
+            Quarantines::new(policy.clone()).signal(&root, Signal::Malformed, cycles::rdtsc());
+        }
+        assert!(!root.quarantine().active(cycles::rdtsc()));
+
+        // Automatic quarantines are off, but manual ones still apply.
+        for _ in 0..10 {
+            quarantines.signal(&tenant, Signal::Malformed, cycles::rdtsc());
+        }
+        assert!(!quarantines.active(cycles::rdtsc()));
+        assert!(quarantines.quarantine(&tenant, 0, cycles::rdtsc()));
+        assert!(quarantines.check(&tenant, cycles::rdtsc()));
+        assert_eq!(
+            cycles::rdtsc() + ms(DEFAULT_QUARANTINE_MS),
+            tenant.quarantine().until()
+        );
+
+        quarantines.release(&tenant, cycles::rdtsc());
+        assert!(!quarantines.check(&tenant, cycles::rdtsc()));
+        assert_eq!(
+            QuarantineStats {
+                quarantines: 1,
+                rejected: 0,
+            },
+            quarantines.stats()
+        );
+        virt::uninstall();
+    }
+}
@@ -0,0 +1,585 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::mem::size_of;
+
+use hashbrown::HashMap;
+
+use super::config::{parse_opcodes, ServerConfig};
+use super::cycles;
+use super::rpc::{parse_rpc_flags, parse_rpc_run, parse_rpc_source};
+use super::wireformat::*;
+
+use e2d2::common::EmptyMetadata;
+use e2d2::headers::UdpHeader;
+use e2d2::interface::Packet;
+
+use sandstorm::common::TenantId;
+
+/// The number of requests each tenant's table on a core remembers, if not set in the server
+/// config.
+pub const REPLAY_WINDOW: usize = 1024;
+
+/// The number of milliseconds a request is remembered for, if not set in the server config.
+pub const REPLAY_AGE_MS: u64 = 1000;
+
+/// The largest response that is held for replay, in bytes, if not set in the server config.
+pub const REPLAY_MAX_BYTES: usize = 256;
+
+thread_local! {
+    // The requests recently executed on this core, by tenant.
+    static TABLES: RefCell<HashMap<TenantId, ReplayTable>> = RefCell::new(HashMap::new());
+}
+
+/// The client a request came from. Request ids are only unique on the client that generated
+/// them, so a replay table tells requests apart by their client and id.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Client {
+    /// A client run that tagged the request with it's id; see REQUEST_FLAG_RUN.
+    Run(u64),
+
+    /// The IP address and UDP port an untagged request was sent from.
+    Addr(u32, u16),
+}
+
+impl Client {
+    /// Returns the client a request came from: the run on it if it is tagged with one, or else
+    /// the address and port it was sent from.
+    ///
+    /// # Arguments
+    ///
+    /// * `request`: The request, parsed upto it's UDP header.
+    pub fn of(request: &Packet<UdpHeader, EmptyMetadata>) -> Client {
+        match parse_rpc_run(request) {
+            Some(run) => Client::Run(run),
+            None => {
+                let (addr, port) = parse_rpc_source(request);
+                Client::Addr(addr, port)
+            }
+        }
+    }
+}
+
+/// What to do with a request covered by a replay table.
+#[derive(Debug, PartialEq)]
+pub enum Admit {
+    /// The request wasn't executed within the window. Execute it, and hand it's response to
+    /// complete() once done.
+    Execute,
+
+    /// The request was executed already. Send this response instead of executing it again.
+    Replay(Vec<u8>),
+
+    /// The request is still executing. Drop it; the original's response is on it's way.
+    InFlight,
+}
+
+/// Counters of the requests a replay table has seen.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ReplayStats {
+    /// The number of requests executed, including ones executed again.
+    pub executed: u64,
+
+    /// The number of retransmits (requests with REQUEST_FLAG_RETRY) answered with the response
+    /// of the original.
+    pub replayed: u64,
+
+    /// The number of requests without REQUEST_FLAG_RETRY that carried the client and id of a
+    /// request still remembered. Only retransmits are replayed, so these were executed again.
+    pub duplicates: u64,
+
+    /// The number of retransmits dropped because the original was executing.
+    pub in_flight: u64,
+
+    /// The number of retransmits executed because the original wasn't remembered; either it was
+    /// evicted before the retransmit arrived, or it never reached the server.
+    pub misses: u64,
+
+    /// The number of retransmits executed again because the original's response was too large
+    /// to be held.
+    pub oversized: u64,
+
+    /// The number of requests forgotten because the table was full or they were too old.
+    pub evicted: u64,
+}
+
+impl ReplayStats {
+    // Adds another table's counters to these.
+    fn add(&mut self, other: &ReplayStats) {
+        self.executed += other.executed;
+        self.replayed += other.replayed;
+        self.duplicates += other.duplicates;
+        self.in_flight += other.in_flight;
+        self.misses += other.misses;
+        self.oversized += other.oversized;
+        self.evicted += other.evicted;
+    }
+}
+
+// What is known about a request in a replay table.
+enum State {
+    // The request is executing.
+    Running,
+
+    // The request completed with this response.
+    Done(Vec<u8>),
+
+    // The request completed, but it's response was too large to be held.
+    Uncached,
+}
+
+/// The requests a tenant recently executed on a core, and their responses. Lets a retransmit of
+/// a request that isn't idempotent (ex: an invoke() that increments a counter) be answered with
+/// the response of the original, instead of being applied twice.
+///
+/// Requests are identified by the client that sent them and the id it put on them, and are
+/// remembered for a bounded window: atmost `cap` requests, none older than `age` cycles. Only
+/// requests the client marked as retransmits are answered from the table; a request without
+/// REQUEST_FLAG_RETRY is executed even if it's id was seen before. A retransmit that arrives
+/// after the original has left the window is executed again. Tables live in memory on a single core,
+/// so nothing is remembered across restarts, and a retransmit steered to a different core than
+/// the original is executed again too.
+pub struct ReplayTable {
+    // The maximum number of requests remembered.
+    cap: usize,
+
+    // The number of cycles a request is remembered for.
+    age: u64,
+
+    // The largest response held, in bytes.
+    max_bytes: usize,
+
+    // Every request remembered, by client and id, along with the cycle counter when it was
+    // admitted.
+    entries: HashMap<(Client, u64), (u64, State)>,
+
+    // The client, id, and admission time of every request remembered, oldest first. May hold
+    // stale pairs for requests that were admitted again; those are skipped when evicting.
+    order: VecDeque<((Client, u64), u64)>,
+
+    stats: ReplayStats,
+}
+
+impl ReplayTable {
+    /// Creates an empty table.
+    ///
+    /// # Arguments
+    ///
+    /// * `cap`:       The maximum number of requests remembered. Atleast 1.
+    /// * `age`:       The number of cycles a request is remembered for.
+    /// * `max_bytes`: The largest response held, in bytes.
+    pub fn new(cap: usize, age: u64, max_bytes: usize) -> ReplayTable {
+        ReplayTable {
+            cap: cap.max(1),
+            age: age,
+            max_bytes: max_bytes,
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+            stats: ReplayStats::default(),
+        }
+    }
+
+    /// Decides what to do with a request.
+    ///
+    /// # Arguments
+    ///
+    /// * `client`: The client that sent the request.
+    /// * `id`:     The id on the request.
+    /// * `retry`:  True if the client marked the request as a retransmit.
+    /// * `now`:    The cycle counter when the request was received.
+    pub fn admit(&mut self, client: Client, id: u64, retry: bool, now: u64) -> Admit {
+        self.evict(now);
+
+        let key = (client, id);
+        let admit = match (retry, self.entries.get(&key)) {
+            // Only retransmits are answered from the table. A request that isn't one is executed,
+            // even if it's id was seen before.
+            (false, entry) => {
+                if entry.is_some() {
+                    self.stats.duplicates += 1;
+                }
+                None
+            }
+
+            (true, Some(&(_, State::Running))) => Some(Admit::InFlight),
+            (true, Some(&(_, State::Done(ref response)))) => Some(Admit::Replay(response.clone())),
+
+            (true, Some(&(_, State::Uncached))) => {
+                self.stats.oversized += 1;
+                None
+            }
+
+            (true, None) => {
+                self.stats.misses += 1;
+                None
+            }
+        };
+
+        match admit {
+            Some(Admit::InFlight) => self.stats.in_flight += 1,
+            Some(_) => self.stats.replayed += 1,
+            None => {
+                self.stats.executed += 1;
+                self.entries.insert(key, (now, State::Running));
+                self.order.push_back((key, now));
+            }
+        }
+
+        admit.unwrap_or(Admit::Execute)
+    }
+
+    /// Records the response of a request that was admitted for execution. Does nothing if the
+    /// request is no longer remembered.
+    ///
+    /// # Arguments
+    ///
+    /// * `client`:   The client that sent the request.
+    /// * `id`:       The id on the request.
+    /// * `response`: The response, starting at it's RpcResponseHeader. Not held if it's status
+    ///               is StatusPushback, since the request didn't finish on the server.
+    pub fn complete(&mut self, client: Client, id: u64, response: &[u8]) {
+        let key = (client, id);
+        if response.first() == Some(&(RpcStatus::StatusPushback as u8)) {
+            self.entries.remove(&key);
+            return;
+        }
+
+        if let Some(&mut (_, ref mut state)) = self.entries.get_mut(&key) {
+            *state = match response.len() <= self.max_bytes {
+                true => State::Done(response.to_vec()),
+                false => State::Uncached,
+            };
+        }
+    }
+
+    /// Returns the number of requests remembered.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns true if no request is remembered.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the table's counters.
+    pub fn stats(&self) -> ReplayStats {
+        self.stats
+    }
+
+    // Forgets requests that are too old, and then the oldest ones until there is room for one
+    // more.
+    fn evict(&mut self, now: u64) {
+        while let Some(&(key, stamp)) = self.order.front() {
+            let full = self.entries.len() >= self.cap;
+            if !full && now.saturating_sub(stamp) <= self.age {
+                break;
+            }
+
+            self.order.pop_front();
+            if self.entries.get(&key).map_or(false, |e| e.0 == stamp) {
+                self.entries.remove(&key);
+                self.stats.evicted += 1;
+            }
+        }
+    }
+}
+
+/// Which requests are covered by replay tables, and how large the tables are. Every core keeps
+/// a table per tenant; see ReplayTable.
+pub struct Replay {
+    // The opcodes covered, as a mask of OpCode::bit().
+    opcodes: u64,
+
+    // The maximum number of requests each table remembers.
+    cap: usize,
+
+    // The number of cycles a request is remembered for.
+    age: u64,
+
+    // The largest response held, in bytes.
+    max_bytes: usize,
+}
+
+impl Replay {
+    /// Creates a Replay.
+    ///
+    /// # Arguments
+    ///
+    /// * `opcodes`:   The opcodes covered, as a mask of OpCode::bit(). echo() and drain() are
+    ///                never covered.
+    /// * `cap`:       The maximum number of requests each table remembers. 0 means REPLAY_WINDOW.
+    /// * `age_ms`:    How long a request is remembered for. 0 means REPLAY_AGE_MS.
+    /// * `max_bytes`: The largest response held. 0 means REPLAY_MAX_BYTES.
+    pub fn new(opcodes: u64, cap: usize, age_ms: u64, max_bytes: usize) -> Replay {
+        let or = |v: usize, d: usize| if v == 0 { d } else { v };
+        let age_ms = if age_ms == 0 { REPLAY_AGE_MS } else { age_ms };
+        Replay {
+            opcodes: opcodes & !OPCODES_REQUIRED,
+            cap: or(cap, REPLAY_WINDOW),
+            age: cycles::cycles_per_second() / 1000 * age_ms,
+            max_bytes: or(max_bytes, REPLAY_MAX_BYTES),
+        }
+    }
+
+    /// Creates a Replay off the server config, or returns None if `replay_opcodes` is empty.
+    /// Panics if `replay_opcodes` is malformed.
+    pub fn from_config(config: &ServerConfig) -> Option<Replay> {
+        if config.replay_opcodes.trim().is_empty() {
+            return None;
+        }
+
+        let opcodes = parse_opcodes(&config.replay_opcodes)
+            .expect("Malformed replay_opcodes field in server config.");
+        Some(Replay::new(
+            opcodes,
+            config.replay_window,
+            config.replay_age_ms,
+            config.replay_max_bytes,
+        ))
+    }
+
+    /// Returns true if requests with an opcode are covered.
+    #[inline]
+    pub fn covers(&self, op: &OpCode) -> bool {
+        self.opcodes & op.bit() != 0
+    }
+
+    /// Decides what to do with a request, using the calling core's table for the tenant that
+    /// sent it. Requests are told apart by the client they came from (see Client::of()) and their
+    /// id. The response on Admit::Replay carries the time-stamp on this request, not the
+    /// original.
+    ///
+    /// # Arguments
+    ///
+    /// * `request`: The request, parsed upto it's UDP header.
+    pub fn admit(&self, request: &Packet<UdpHeader, EmptyMetadata>) -> Admit {
+        let payload = request.get_payload();
+        if payload.len() < size_of::<RpcRequestHeader>() {
+            // Too short to be executed; the handler will reject it.
+            return Admit::Execute;
+        }
+
+        // Wireformat headers are packed, so the pointer does not have to be aligned.
+        let (tenant, id, stamp) = {
+            let hdr = unsafe { &*(payload.as_ptr() as *const RpcRequestHeader) };
+            (hdr.tenant(), hdr.id(), hdr.stamp())
+        };
+        let retry = parse_rpc_flags(request) & REQUEST_FLAG_RETRY != 0;
+        let client = Client::of(request);
+
+        let admit = TABLES.with(|t| {
+            t.borrow_mut()
+                .entry(tenant as TenantId)
+                .or_insert_with(|| ReplayTable::new(self.cap, self.age, self.max_bytes))
+                .admit(client, id, retry, cycles::rdtsc())
+        });
+
+        match admit {
+            Admit::Replay(mut response) => {
+                if response.len() >= size_of::<RpcResponseHeader>() {
+                    let hdr = response.as_mut_ptr() as *mut RpcResponseHeader;
+                    unsafe { (*hdr).set_stamp(stamp) };
+                }
+                Admit::Replay(response)
+            }
+
+            admit => admit,
+        }
+    }
+}
+
+/// Records the response of a request admitted by Replay::admit() on the calling core. Does
+/// nothing for requests that weren't.
+///
+/// # Arguments
+///
+/// * `request`:  The request, parsed upto it's UDP header.
+/// * `response`: The response to the request, parsed upto it's UDP header.
+#[inline]
+pub fn complete(
+    request: &Packet<UdpHeader, EmptyMetadata>,
+    response: &Packet<UdpHeader, EmptyMetadata>,
+) {
+    let payload = request.get_payload();
+    if payload.len() < size_of::<RpcRequestHeader>() {
+        return;
+    }
+
+    let (tenant, id) = {
+        let hdr = unsafe { &*(payload.as_ptr() as *const RpcRequestHeader) };
+        (hdr.tenant(), hdr.id())
+    };
+
+    let client = Client::of(request);
+    TABLES.with(|t| {
+        if let Some(table) = t.borrow_mut().get_mut(&(tenant as TenantId)) {
+            table.complete(client, id, response.get_payload());
+        }
+    });
+}
+
+/// Returns the counters of every table on the calling core, added up.
+pub fn stats() -> ReplayStats {
+    TABLES.with(|t| {
+        let mut stats = ReplayStats::default();
+        for table in t.borrow().values() {
+            stats.add(&table.stats());
+        }
+        stats
+    })
+}
+
+// This module contains unit tests for ReplayTable.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The client every request in these tests comes from, unless a test says otherwise.
+    const C: Client = Client::Run(1);
+
+    // A response with a status, and a payload of `len` bytes.
+    fn response(status: RpcStatus, len: usize) -> Vec<u8> {
+        let mut res = vec![0; size_of::<RpcResponseHeader>() + len];
+        res[0] = status as u8;
+        res
+    }
+
+    // Tests that a retransmit of a completed request is replayed, and that one of a running
+    // request is dropped.
+    #[test]
+    fn test_replay_retransmit() {
+        let mut table = ReplayTable::new(16, 1000, 64);
+        assert_eq!(Admit::Execute, table.admit(C, 7, false, 1));
+        assert_eq!(Admit::InFlight, table.admit(C, 7, true, 2));
+
+        let res = response(RpcStatus::StatusOk, 8);
+        table.complete(C, 7, &res);
+        assert_eq!(Admit::Replay(res.clone()), table.admit(C, 7, true, 3));
+        assert_eq!(Admit::Replay(res.clone()), table.admit(C, 7, true, 4));
+
+        let stats = table.stats();
+        assert_eq!(1, stats.executed);
+        assert_eq!(2, stats.replayed);
+        assert_eq!(0, stats.duplicates);
+        assert_eq!(1, stats.in_flight);
+        assert_eq!(0, stats.misses);
+    }
+
+    // Tests that a request without REQUEST_FLAG_RETRY is executed even if it's id was seen
+    // before, whether the original is running or completed, and that a retransmit after it
+    // is answered with the latest response.
+    #[test]
+    fn test_replay_not_retry() {
+        let mut table = ReplayTable::new(16, 1000, 64);
+        assert_eq!(Admit::Execute, table.admit(C, 7, false, 1));
+        assert_eq!(Admit::Execute, table.admit(C, 7, false, 2));
+        table.complete(C, 7, &response(RpcStatus::StatusOk, 8));
+        assert_eq!(Admit::Execute, table.admit(C, 7, false, 3));
+
+        let res = response(RpcStatus::StatusOk, 0);
+        table.complete(C, 7, &res);
+        assert_eq!(Admit::Replay(res), table.admit(C, 7, true, 4));
+
+        let stats = table.stats();
+        assert_eq!(3, stats.executed);
+        assert_eq!(2, stats.duplicates);
+        assert_eq!(1, stats.replayed);
+        assert_eq!(0, stats.in_flight);
+    }
+
+    // Tests that requests from different clients with the same id are told apart, so that a
+    // retransmit is never answered with another client's response.
+    #[test]
+    fn test_replay_clients() {
+        let mut table = ReplayTable::new(16, 1000, 64);
+        let a = Client::Addr(0x0a000001, 5000);
+        let b = Client::Addr(0x0a000002, 5000);
+        let port = Client::Addr(0x0a000001, 5001);
+        let res = response(RpcStatus::StatusOk, 8);
+
+        assert_eq!(Admit::Execute, table.admit(a, 7, false, 1));
+        table.complete(a, 7, &res);
+        assert_eq!(Admit::Execute, table.admit(b, 7, true, 2));
+        assert_eq!(Admit::Execute, table.admit(port, 7, true, 3));
+        assert_eq!(Admit::Execute, table.admit(Client::Run(7), 7, true, 4));
+        assert_eq!(Admit::Replay(res), table.admit(a, 7, true, 5));
+
+        let stats = table.stats();
+        assert_eq!(4, stats.executed);
+        assert_eq!(3, stats.misses);
+        assert_eq!(1, stats.replayed);
+        assert_eq!(0, stats.duplicates);
+    }
+
+    // Tests that requests are forgotten once they age out, or the table fills up, and that a
+    // retransmit of a forgotten request is executed again.
+    #[test]
+    fn test_replay_evict() {
+        let mut table = ReplayTable::new(4, 100, 64);
+        for id in 0..4 {
+            assert_eq!(Admit::Execute, table.admit(C, id, false, 10));
+            table.complete(C, id, &response(RpcStatus::StatusOk, 0));
+        }
+        assert_eq!(4, table.len());
+
+        // A fifth request pushes out the oldest.
+        assert_eq!(Admit::Execute, table.admit(C, 4, false, 20));
+        assert_eq!(Admit::Execute, table.admit(C, 0, true, 20));
+        assert_eq!(1, table.stats().misses);
+
+        // Everything admitted at 10 is too old by 111.
+        assert_eq!(Admit::Execute, table.admit(C, 1, true, 111));
+        assert_eq!(3, table.len());
+        assert_eq!(2, table.stats().misses);
+        assert_eq!(4, table.stats().evicted);
+    }
+
+    // Tests that re-admitting a request leaves a stale pair behind that doesn't evict it early.
+    #[test]
+    fn test_replay_readmit() {
+        let mut table = ReplayTable::new(4, 100, 48);
+        table.admit(C, 1, false, 10);
+        table.complete(C, 1, &response(RpcStatus::StatusOk, 64));
+        assert_eq!(Admit::Execute, table.admit(C, 1, true, 50));
+        table.complete(C, 1, &response(RpcStatus::StatusOk, 0));
+
+        // The pair from 10 is too old by 111, but the one from 50 is not.
+        assert_eq!(Admit::Execute, table.admit(C, 2, false, 111));
+        assert_eq!(
+            Admit::Replay(response(RpcStatus::StatusOk, 0)),
+            table.admit(C, 1, true, 112)
+        );
+        assert_eq!(0, table.stats().evicted);
+    }
+
+    // Tests that oversized and pushed back responses aren't held.
+    #[test]
+    fn test_replay_not_held() {
+        let mut table = ReplayTable::new(16, 1000, 64);
+        table.admit(C, 1, false, 1);
+        table.complete(C, 1, &response(RpcStatus::StatusOk, 64));
+        assert_eq!(Admit::Execute, table.admit(C, 1, true, 2));
+        assert_eq!(1, table.stats().oversized);
+
+        table.admit(C, 2, false, 3);
+        table.complete(C, 2, &response(RpcStatus::StatusPushback, 0));
+        assert_eq!(Admit::Execute, table.admit(C, 2, true, 4));
+        assert_eq!(1, table.stats().misses);
+        assert_eq!(4, table.stats().executed);
+    }
+}
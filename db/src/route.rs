@@ -0,0 +1,272 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use std::mem::{size_of, transmute};
+
+use super::wireformat::{InvokeRequest, RoutedInvokeRequest, RpcStatus};
+
+use sandstorm::route::{write_header, ROUTE_HEADER_LEN};
+
+/// The most routing rules a tenant can have.
+pub const MAX_ROUTES: usize = 16;
+
+/// The longest key prefix a routing rule can match on, in bytes.
+pub const MAX_PREFIX_LEN: usize = 32;
+
+/// A tenant's routing rules, which pick the extension a routed invoke() runs off the prefix of
+/// it's key. The longest matching prefix wins. An empty prefix matches every key, and so is
+/// a fallback for keys no other rule matches.
+///
+/// A tenant is expected to have a handful of rules, so they are kept in a vector ordered by
+/// descending prefix length, and a lookup compares the key against each in turn; the first one
+/// that matches is the longest. Prefixes are bounded by MAX_PREFIX_LEN, so a lookup touches
+/// atmost MAX_ROUTES * MAX_PREFIX_LEN bytes of the key.
+#[derive(Default)]
+pub struct Routes {
+    // The rules as (prefix, extension name), ordered by descending prefix length, and then by
+    // prefix.
+    rules: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl Routes {
+    /// Returns an empty set of rules, under which no key is routed.
+    pub fn new() -> Routes {
+        Routes { rules: Vec::new() }
+    }
+
+    /// Installs the rule for a prefix, replacing any rule already on the prefix, or removes the
+    /// rule for the prefix if the name is empty. The extension isn't looked up here; a routed
+    /// invoke() looks it up by name, exactly like an invoke() naming it would.
+    ///
+    /// # Arguments
+    ///
+    /// * `prefix`: The key prefix the rule matches on.
+    /// * `name`:   The name of the extension keys with the prefix are routed to.
+    ///
+    /// # Return
+    ///
+    /// StatusMalformedRequest if the prefix is longer than MAX_PREFIX_LEN, and
+    /// StatusTooManyRoutes if there already are MAX_ROUTES rules on other prefixes.
+    pub fn set(&mut self, prefix: &[u8], name: &[u8]) -> Result<(), RpcStatus> {
+        if prefix.len() > MAX_PREFIX_LEN {
+            return Err(RpcStatus::StatusMalformedRequest);
+        }
+
+        let pos = self.rules.binary_search_by(|&(ref p, _)| {
+            prefix.len().cmp(&p.len()).then_with(|| p[..].cmp(prefix))
+        });
+
+        match (pos, name.is_empty()) {
+            (Ok(i), true) => {
+                self.rules.remove(i);
+            }
+
+            (Ok(i), false) => self.rules[i].1 = name.to_vec(),
+
+            (Err(_), true) => {}
+
+            (Err(_), false) if self.rules.len() >= MAX_ROUTES => {
+                return Err(RpcStatus::StatusTooManyRoutes);
+            }
+
+            (Err(i), false) => self.rules.insert(i, (prefix.to_vec(), name.to_vec())),
+        }
+
+        Ok(())
+    }
+
+    /// Looks up the rule with the longest prefix matching a key.
+    ///
+    /// # Return
+    ///
+    /// The length of the prefix that matched, and the name of the extension the key is routed
+    /// to. None if no rule matched.
+    #[inline]
+    pub fn lookup(&self, key: &[u8]) -> Option<(usize, &[u8])> {
+        self.rules
+            .iter()
+            .find(|&&(ref prefix, _)| key.starts_with(prefix))
+            .map(|&(ref prefix, ref name)| (prefix.len(), &name[..]))
+    }
+}
+
+/// Returns the number of bytes a routed invoke() request grows by when it is laid out as an
+/// invoke() of an extension whose name is `name_length` bytes long.
+pub fn growth(name_length: usize) -> usize {
+    size_of::<InvokeRequest>() + name_length + ROUTE_HEADER_LEN - size_of::<RoutedInvokeRequest>()
+}
+
+/// Lays out a routed invoke() request in place as an invoke() request of the extension it was
+/// routed to, with the arguments laid out by sandstorm::route. The key and arguments are moved
+/// back to make room for the extension's name and the header on the arguments; the invoke()
+/// that follows reads them straight off the request, like it would on any other.
+///
+/// # Arguments
+///
+/// * `buf`:           The request, starting at it's RoutedInvokeRequest header and ending at
+///                    the end of it's arguments, followed by growth(name.len()) bytes of room.
+/// * `name`:          The name of the extension the request was routed to.
+/// * `prefix_length`: The length of the prefix that matched.
+pub fn to_invoke(buf: &mut [u8], name: &[u8], prefix_length: usize) {
+    // Wireformat headers are packed, so the pointer does not have to be aligned.
+    let (tenant, id, stamp, table_id, key_length, args_length) = {
+        let hdr = unsafe { &*(buf.as_ptr() as *const RoutedInvokeRequest) };
+        (
+            hdr.common_header.tenant(),
+            hdr.common_header.id(),
+            hdr.common_header.stamp(),
+            hdr.table_id(),
+            hdr.key_length(),
+            hdr.args_length() as usize,
+        )
+    };
+
+    buf[size_of::<RoutedInvokeRequest>()..].rotate_right(growth(name.len()));
+
+    let hdr = InvokeRequest::new(
+        tenant,
+        name.len() as u32,
+        (ROUTE_HEADER_LEN + key_length as usize + args_length) as u32,
+        id,
+        stamp,
+    );
+    let hdr: [u8; size_of::<InvokeRequest>()] = unsafe { transmute(hdr) };
+
+    let (head, rest) = buf.split_at_mut(hdr.len());
+    head.copy_from_slice(&hdr);
+    let (head, rest) = rest.split_at_mut(name.len());
+    head.copy_from_slice(name);
+    write_header(rest, prefix_length as u16, table_id, key_length);
+}
+
+// This module contains unit tests for routing rules, and for the layout of routed requests.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sandstorm::route::encode_args;
+
+    fn routes(rules: &[(&str, &str)]) -> Routes {
+        let mut routes = Routes::new();
+        for &(prefix, name) in rules.iter() {
+            routes.set(prefix.as_bytes(), name.as_bytes()).unwrap();
+        }
+        routes
+    }
+
+    // Tests that the longest matching prefix wins over the shorter ones it overlaps, whatever
+    // order the rules were installed in.
+    #[test]
+    fn test_routes_longest_match() {
+        let rules = [
+            ("u", "get"),
+            ("usr:admin:", "admin"),
+            ("usr:", "auth"),
+            ("obj:", "tao"),
+        ];
+        let mut reversed = rules;
+        reversed.reverse();
+
+        for routes in [routes(&rules), routes(&reversed)].iter() {
+            assert_eq!(Some((10, &b"admin"[..])), routes.lookup(b"usr:admin:root"));
+            assert_eq!(Some((4, &b"auth"[..])), routes.lookup(b"usr:admin"));
+            assert_eq!(Some((4, &b"auth"[..])), routes.lookup(b"usr:"));
+            assert_eq!(Some((1, &b"get"[..])), routes.lookup(b"us"));
+            assert_eq!(Some((4, &b"tao"[..])), routes.lookup(b"obj:42"));
+        }
+    }
+
+    // Tests that keys no rule matches aren't routed, unless there is a rule on the empty prefix.
+    #[test]
+    fn test_routes_no_match() {
+        let mut routes = routes(&[("usr:", "auth"), ("obj:", "tao")]);
+        assert_eq!(None, routes.lookup(b"ob"));
+        assert_eq!(None, routes.lookup(b"Usr:alice"));
+        assert_eq!(None, routes.lookup(b""));
+        assert_eq!(None, Routes::new().lookup(b"usr:alice"));
+
+        routes.set(b"", b"get").unwrap();
+        assert_eq!(Some((0, &b"get"[..])), routes.lookup(b"ob"));
+        assert_eq!(Some((4, &b"tao"[..])), routes.lookup(b"obj:1"));
+    }
+
+    // Tests that installing a rule on a prefix replaces the one already on it, and that an empty
+    // name removes it.
+    #[test]
+    fn test_routes_replace() {
+        let mut routes = routes(&[("usr:", "auth"), ("u", "get")]);
+        routes.set(b"usr:", b"tao").unwrap();
+        assert_eq!(2, routes.rules.len());
+        assert_eq!(Some((4, &b"tao"[..])), routes.lookup(b"usr:alice"));
+
+        routes.set(b"usr:", b"").unwrap();
+        assert_eq!(1, routes.rules.len());
+        assert_eq!(Some((1, &b"get"[..])), routes.lookup(b"usr:alice"));
+
+        // Removing a rule that isn't there does nothing.
+        routes.set(b"obj:", b"").unwrap();
+        assert_eq!(1, routes.rules.len());
+    }
+
+    // Tests that the number of rules and the length of prefixes are bounded.
+    #[test]
+    fn test_routes_bounded() {
+        let mut routes = Routes::new();
+        for i in 0..MAX_ROUTES {
+            routes.set(format!("{}:", i).as_bytes(), b"get").unwrap();
+        }
+        assert_eq!(
+            Err(RpcStatus::StatusTooManyRoutes),
+            routes.set(b"obj:", b"tao")
+        );
+
+        // Rules already in place can still be replaced.
+        routes.set(b"0:", b"tao").unwrap();
+        assert_eq!(Some((2, &b"tao"[..])), routes.lookup(b"0:1"));
+
+        let long = vec![b'k'; MAX_PREFIX_LEN + 1];
+        assert_eq!(
+            Err(RpcStatus::StatusMalformedRequest),
+            Routes::new().set(&long, b"get")
+        );
+        assert_eq!(MAX_ROUTES, routes.rules.len());
+    }
+
+    // Tests that a routed request is laid out exactly like an invoke() request naming the
+    // extension it was routed to, so that both run, and respond, identically.
+    #[test]
+    fn test_routed_layout() {
+        let (key, args) = (&b"usr:alice"[..], &b"password"[..]);
+        let routes = routes(&[("usr:", "auth"), ("obj:", "tao")]);
+        let (prefix_length, name) = routes.lookup(key).unwrap();
+
+        let hdr = RoutedInvokeRequest::new(5, 1, key.len() as u16, args.len() as u32, 99, 1234);
+        let hdr: [u8; size_of::<RoutedInvokeRequest>()] = unsafe { transmute(hdr) };
+        let mut routed = hdr.to_vec();
+        routed.extend_from_slice(key);
+        routed.extend_from_slice(args);
+        routed.extend_from_slice(&vec![0xff; growth(name.len())]);
+        to_invoke(&mut routed, name, prefix_length);
+
+        let args = encode_args(4, 1, key, args);
+        let hdr = InvokeRequest::new(5, name.len() as u32, args.len() as u32, 99, 1234);
+        let hdr: [u8; size_of::<InvokeRequest>()] = unsafe { transmute(hdr) };
+        let mut direct = hdr.to_vec();
+        direct.extend_from_slice(b"auth");
+        direct.extend_from_slice(&args);
+
+        assert_eq!(direct, routed);
+    }
+}
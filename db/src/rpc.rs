@@ -13,14 +13,32 @@
  * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
  */
 
+use std::cell::Cell;
 use std::mem::{size_of, transmute};
+use std::ptr;
 
+use super::amplify::{Decay, HotKey, WriteTotals};
+use super::audit::AuditEntry;
+use super::backoff::{CoreSummary, TierStats, N_TIERS};
+use super::cycles;
+use super::dedup::DedupStats;
+use super::epoch::{self, EpochStats};
+use super::evict::{CacheStats, CacheTarget};
+use super::histogram::{LogHistogram, LOG_BUCKETS};
+use super::hot::HotStats;
+use super::latency::{self, LatencySummary};
+use super::presize::Sizing;
+use super::runs::{RunStats, RunSummary};
+use super::trailer::{self, Trace, TrailerReader, TrailerWriter, TRAILER_TRACE};
 use super::wireformat::*;
 
 use e2d2::common::EmptyMetadata;
 use e2d2::headers::{IpHeader, MacHeader, UdpHeader};
 use e2d2::interface::*;
 
+use sandstorm::ext::{ExtensionInfo, Provenance};
+use sandstorm::schema::Schema;
+
 /// This function looks into a packet corresponding to an RPC request, and
 /// reads it's service (assumed to be the first byte after the end of the
 /// UDP header).
@@ -77,6 +95,320 @@ pub fn parse_rpc_opcode(request: &Packet<UdpHeader, EmptyMetadata>) -> OpCode {
     }
 }
 
+/// This function looks into a packet corresponding to a get() RPC request, and
+/// reads the tenant and table the request is for without parsing the packet
+/// upto it's GetRequest header.
+///
+/// # Arguments
+///
+/// * `request`: A reference to a packet corresponding to a get() RPC request.
+///              The packet should have been parsed upto it's UDP header.
+///
+/// # Return
+///
+/// The tenant id and table id on the request, if the payload is large enough to
+/// hold a GetRequest header.
+pub fn parse_get_tenant_table(request: &Packet<UdpHeader, EmptyMetadata>) -> Option<(u32, u64)> {
+    let payload = request.get_payload();
+    if payload.len() < size_of::<GetRequest>() {
+        return None;
+    }
+
+    // The table id immediately follows the common request header.
+    let mut tenant = [0; 4];
+    tenant.copy_from_slice(&payload[REQUEST_TENANT_OFFSET..(REQUEST_TENANT_OFFSET + 4)]);
+    let tenant: u32 = u32::from_le(unsafe { transmute(tenant) });
+
+    let offset = size_of::<RpcRequestHeader>();
+    let mut table = [0; 8];
+    table.copy_from_slice(&payload[offset..(offset + 8)]);
+    let table: u64 = u64::from_le(unsafe { transmute(table) });
+
+    Some((tenant, table))
+}
+
+/// This function looks into a packet corresponding to an RPC request, and reads the tenant on
+/// it's common header.
+///
+/// # Arguments
+///
+/// * `request`: A reference to a packet corresponding to an RPC request.
+///              The packet should have been parsed upto it's UDP header.
+///
+/// # Return
+///
+/// The tenant id on the request, if the payload is large enough to hold a common header.
+pub fn parse_rpc_tenant(request: &Packet<UdpHeader, EmptyMetadata>) -> Option<u32> {
+    let payload = request.get_payload();
+    if payload.len() < size_of::<RpcRequestHeader>() {
+        return None;
+    }
+
+    let mut tenant = [0; 4];
+    tenant.copy_from_slice(&payload[REQUEST_TENANT_OFFSET..(REQUEST_TENANT_OFFSET + 4)]);
+    Some(u32::from_le(unsafe { transmute(tenant) }))
+}
+
+/// This function looks into a packet corresponding to an RPC request, and reads the flags on it's
+/// common header.
+///
+/// # Arguments
+///
+/// * `request`: A reference to a packet corresponding to an RPC request.
+///              The packet should have been parsed upto it's UDP header.
+///
+/// # Return
+///
+/// The flags on the request, or 0 if the request is too short to carry a common header.
+pub fn parse_rpc_flags(request: &Packet<UdpHeader, EmptyMetadata>) -> u8 {
+    let payload = request.get_payload();
+    if payload.len() < size_of::<RpcRequestHeader>() {
+        return 0;
+    }
+
+    payload[REQUEST_FLAGS_OFFSET]
+}
+
+/// This function looks into a packet corresponding to an RPC request, and reads the run id on
+/// it's common header.
+///
+/// # Arguments
+///
+/// * `request`: A reference to a packet corresponding to an RPC request.
+///              The packet should have been parsed upto it's UDP header.
+///
+/// # Return
+///
+/// The run id on the request, or None if the request does not have REQUEST_FLAG_RUN set.
+#[inline]
+pub fn parse_rpc_run(request: &Packet<UdpHeader, EmptyMetadata>) -> Option<u64> {
+    if parse_rpc_flags(request) & REQUEST_FLAG_RUN == 0 {
+        return None;
+    }
+
+    let mut run = [0; 8];
+    run.copy_from_slice(&request.get_payload()[REQUEST_RUN_OFFSET..(REQUEST_RUN_OFFSET + 8)]);
+    Some(u64::from_le(unsafe { transmute(run) }))
+}
+
+/// This function looks into a packet corresponding to an RPC response, and reads the id of the
+/// request it responds to without parsing the packet upto it's response header.
+///
+/// # Arguments
+///
+/// * `response`: A reference to a packet corresponding to an RPC response.
+///               The packet should have been parsed upto it's UDP header.
+///
+/// # Return
+///
+/// The request id on the response, if the payload is large enough to hold a common header.
+pub fn parse_rpc_id(response: &Packet<UdpHeader, EmptyMetadata>) -> Option<u64> {
+    let payload = response.get_payload();
+    if payload.len() < size_of::<RpcResponseHeader>() {
+        return None;
+    }
+
+    let mut id = [0; 8];
+    id.copy_from_slice(&payload[RESPONSE_ID_OFFSET..(RESPONSE_ID_OFFSET + 8)]);
+    Some(u64::from_le(unsafe { transmute(id) }))
+}
+
+/// This function looks into a packet corresponding to an RPC request, and reads the address and
+/// port it was sent from.
+///
+/// # Arguments
+///
+/// * `request`: A reference to a packet corresponding to an RPC request.
+///              The packet should have been parsed upto it's UDP header, from a packet that was
+///              parsed upto it's IP header before that.
+///
+/// # Return
+///
+/// The source IP address and UDP port on the request.
+pub fn parse_rpc_source(request: &Packet<UdpHeader, EmptyMetadata>) -> (u32, u16) {
+    // The IP header sits right in front of the UDP header. Wireformat headers are packed, so
+    // the pointer does not have to be aligned.
+    let udp = request.get_header();
+    let ip = unsafe {
+        &*((udp as *const UdpHeader as *const u8).offset(-(size_of::<IpHeader>() as isize))
+            as *const IpHeader)
+    };
+    (ip.src(), udp.src_port())
+}
+
+/// Sets flags on the common header of an RPC request created by one of the create_*_rpc()
+/// functions below.
+///
+/// # Arguments
+///
+/// * `request`: A request parsed upto it's IP header.
+/// * `flags`:   The flags to be OR'ed into the request's common header.
+///
+/// # Return
+///
+/// The request parsed upto it's IP header.
+pub fn set_rpc_flags(
+    request: Packet<IpHeader, EmptyMetadata>,
+    flags: u8,
+) -> Packet<IpHeader, EmptyMetadata> {
+    let mut request = request.parse_header::<UdpHeader>();
+    {
+        let payload = request.get_mut_payload();
+        if payload.len() >= size_of::<RpcRequestHeader>() {
+            payload[size_of::<RpcRequestHeader>() - 1] |= flags;
+        }
+    }
+
+    request.deparse_header(size_of::<IpHeader>())
+}
+
+/// Tags an RPC request created by one of the create_*_rpc() functions below with the id of the
+/// client run sending it, and sets REQUEST_FLAG_RUN on it.
+///
+/// # Arguments
+///
+/// * `request`: A request parsed upto it's IP header.
+/// * `run`:     The id of the run sending the request.
+///
+/// # Return
+///
+/// The request parsed upto it's IP header.
+pub fn set_rpc_run(
+    request: Packet<IpHeader, EmptyMetadata>,
+    run: u64,
+) -> Packet<IpHeader, EmptyMetadata> {
+    let mut request = request.parse_header::<UdpHeader>();
+    {
+        let payload = request.get_mut_payload();
+        if payload.len() >= size_of::<RpcRequestHeader>() {
+            // Wireformat headers are packed, so the pointer does not have to be aligned.
+            let hdr = payload.as_mut_ptr() as *mut RpcRequestHeader;
+            unsafe {
+                (*hdr).set_run(run);
+                (*hdr).flags |= REQUEST_FLAG_RUN;
+            }
+        }
+    }
+
+    request.deparse_header(size_of::<IpHeader>())
+}
+
+/// Overwrites the time-stamp on an RPC request created by one of the create_*_rpc() functions
+/// below. Used when a request is sent out later than it was created (ex: held on a tx burst), so
+/// that latency is measured from when it actually left the client.
+///
+/// # Arguments
+///
+/// * `request`: A request parsed upto it's IP header.
+/// * `stamp`:   The time-stamp at which the request is being sent out.
+///
+/// # Return
+///
+/// The request parsed upto it's IP header.
+pub fn set_rpc_stamp(
+    request: Packet<IpHeader, EmptyMetadata>,
+    stamp: u64,
+) -> Packet<IpHeader, EmptyMetadata> {
+    let mut request = request.parse_header::<UdpHeader>();
+    {
+        let payload = request.get_mut_payload();
+        if payload.len() >= size_of::<RpcRequestHeader>() {
+            // Wireformat headers are packed, so the pointer does not have to be aligned.
+            let hdr = payload.as_mut_ptr() as *mut RpcRequestHeader;
+            unsafe {
+                (*hdr).set_stamp(stamp);
+            }
+        }
+    }
+
+    request.deparse_header(size_of::<IpHeader>())
+}
+
+/// Counts a completed request against the client run it was tagged with. Requests that aren't
+/// tagged with a run are not counted, and `runs` is left untouched.
+///
+/// # Arguments
+///
+/// * `runs`:     The counters of every run tracked by the server.
+/// * `request`:  The request, parsed upto it's UDP header.
+/// * `response`: The response to the request, parsed upto it's UDP header.
+#[inline]
+pub fn record_run(
+    runs: &RunStats,
+    request: &Packet<UdpHeader, EmptyMetadata>,
+    response: &Packet<UdpHeader, EmptyMetadata>,
+) {
+    if let Some(run) = parse_rpc_run(request) {
+        runs.record(run, &parse_rpc_status(response));
+    }
+}
+
+/// This function looks into a packet corresponding to an RPC response, and reads the status on
+/// it's common header.
+///
+/// # Arguments
+///
+/// * `response`: A reference to a packet corresponding to an RPC response.
+///               The packet should have been parsed upto it's UDP header.
+///
+/// # Return
+///
+/// The status on the response, or StatusInternalError if the response is empty or carries a
+/// status this server does not know of.
+#[inline]
+pub fn parse_rpc_status(response: &Packet<UdpHeader, EmptyMetadata>) -> RpcStatus {
+    // The status is the first byte on the response header.
+    let status = response.get_payload().first().cloned().unwrap_or(0);
+    match status != 0 && status <= RpcStatus::StatusRwSetTooLarge as u8 {
+        true => unsafe { transmute(status) },
+        false => RpcStatus::StatusInternalError,
+    }
+}
+
+// Set on the receive time-stamp held in a response's metadata if the request asked for
+// time-stamps to be stamped onto it's response. Cycle counters never get this far.
+const STAMP_RX: u64 = 1 << 63;
+
+// Set on the receive time-stamp held in a response's metadata if the request asked for a trace
+// trailer on it's response.
+const STAMP_TRACE: u64 = 1 << 62;
+
+/// Records the time-stamp at which a request was received on the response pre-allocated for it.
+/// The time-stamp is held in the response mbuf's metadata until fixup_response() reads it, so it
+/// does not need to be threaded through the task that services the request. It is always used
+/// to record the time the server took to respond, and is copied into the response header or a
+/// trace trailer if the request asked for either.
+///
+/// # Arguments
+///
+/// * `request`:  The request, parsed upto it's UDP header.
+/// * `response`: The response allocated for the request.
+/// * `rx`:       The cycle counter when the request was received. 0 if the response should
+///               neither be stamped nor have it's latency recorded.
+#[inline]
+pub fn stamp_response(
+    request: &Packet<UdpHeader, EmptyMetadata>,
+    response: &mut Packet<UdpHeader, EmptyMetadata>,
+    rx: u64,
+) {
+    // Mbufs are recycled, so metadata is always written to avoid picking up a stale stamp.
+    let flags = parse_rpc_flags(request);
+    let rx = match rx {
+        0 => 0,
+        rx if flags & REQUEST_FLAG_STAMPS != 0 => rx | STAMP_RX,
+        rx => rx,
+    };
+    let rx = match rx {
+        0 => 0,
+        rx if flags & REQUEST_FLAG_TRACE != 0 => rx | STAMP_TRACE,
+        rx => rx,
+    };
+
+    response
+        .write_metadata(&rx)
+        .expect("Failed to write receive time-stamp into response!");
+}
+
 /// This function looks into the records encapsulated into the payload corresponding to an RPC
 /// request, and reads it's optype (assumed to be the first byte in each record in optype).
 ///
@@ -140,6 +472,238 @@ fn create_request(
     return packet;
 }
 
+/// Allocates the next response of a streamed invoke() result. The new response carries the same
+/// network headers and InvokeResponse header as the one passed in, and an empty payload. Only
+/// the first response of a stream carries the receive time-stamp; fixup_response() leaves it off
+/// the ones allocated here.
+///
+/// # Arguments
+///
+/// * `response`: The response currently being written to, parsed upto it's InvokeResponse
+///               header.
+///
+/// # Return
+///
+/// The next response parsed upto it's InvokeResponse header, or None if a packet could not be
+/// allocated.
+pub fn next_response(
+    response: &Packet<InvokeResponse, EmptyMetadata>,
+) -> Option<Packet<InvokeResponse, EmptyMetadata>> {
+    // The network headers sit right before the InvokeResponse header in the mbuf. Wireformat
+    // and network headers are packed, so they are read without assuming any alignment.
+    let (mac, ip, udp) = unsafe {
+        let hdr = response.get_header() as *const InvokeResponse as *const u8;
+        let udp = hdr.offset(-(size_of::<UdpHeader>() as isize));
+        let ip = udp.offset(-(size_of::<IpHeader>() as isize));
+        let mac = ip.offset(-(size_of::<MacHeader>() as isize));
+        (
+            ptr::read_unaligned(mac as *const MacHeader),
+            ptr::read_unaligned(ip as *const IpHeader),
+            ptr::read_unaligned(udp as *const UdpHeader),
+        )
+    };
+
+    let mut next = new_packet()?
+        .push_header(&mac)?
+        .push_header(&ip)?
+        .push_header(&udp)?;
+    if next.write_metadata(&0u64).is_err() {
+        next.free_packet();
+        return None;
+    }
+
+    next.push_header(response.get_header())
+}
+
+thread_local! {
+    // The load on the core this thread is running on. Updated by the scheduler, and stamped onto
+    // every response sent out by this core.
+    static CORE_LOAD: Cell<u16> = Cell::new(0);
+}
+
+/// Records the load on the calling thread's core. Subsequent responses fixed up on this thread
+/// using fixup_response() will carry this value.
+///
+/// # Arguments
+///
+/// * `load`: The load on the core (the number of tasks waiting to run). Saturated at u16::MAX.
+#[inline]
+pub fn set_core_load(load: usize) {
+    let load = if load > u16::max_value() as usize {
+        u16::max_value()
+    } else {
+        load as u16
+    };
+    CORE_LOAD.with(|l| l.set(load));
+}
+
+/// Returns the load last recorded on the calling thread's core using set_core_load().
+#[inline]
+pub fn core_load() -> u16 {
+    CORE_LOAD.with(|l| l.get())
+}
+
+/// Stamps the load on the calling thread's core onto a response, and then sets the length fields
+/// on it's UDP and IP headers. In debug builds, asserts that the length on a get() response
+/// matches it's payload. The time since the request was received is recorded against it's
+/// opcode on the calling thread's core (see latency::record()), and if the request asked for
+/// them, the receive and transmit time-stamps are stamped onto the response as well.
+/// Responses that failed to find a key, table or tenant are stamped with the epoch of the
+/// scope it was looked up in (see epoch::lookup()).
+///
+/// # Arguments
+///
+/// * `response`: A response packet parsed upto it's UDP header. The payload is expected to start
+///               with an RpcResponseHeader; if it is too short, nothing is stamped.
+///
+/// # Return
+///
+/// A packet parsed upto it's IP headers with the load and length fields set.
+#[inline]
+pub fn fixup_response(
+    response: Packet<UdpHeader, EmptyMetadata>,
+) -> Packet<IpHeader, EmptyMetadata> {
+    fixup(response, None)
+}
+
+/// Fixes up the response to a request that completed, exactly like fixup_response(). The latency
+/// of an invoke() is recorded against the extension it invoked as well.
+///
+/// # Arguments
+///
+/// * `request`:  The request, parsed upto it's UDP header.
+/// * `response`: The response to the request, parsed upto it's UDP header.
+///
+/// # Return
+///
+/// A packet parsed upto it's IP headers with the load and length fields set.
+#[inline]
+pub fn fixup_completed(
+    request: &Packet<UdpHeader, EmptyMetadata>,
+    response: Packet<UdpHeader, EmptyMetadata>,
+) -> Packet<IpHeader, EmptyMetadata> {
+    fixup(response, parse_invoke_name(request))
+}
+
+// Common implementation of fixup_response() and fixup_completed(). `name` is the extension the
+// request invoked, if any.
+#[inline]
+fn fixup(
+    response: Packet<UdpHeader, EmptyMetadata>,
+    name: Option<&[u8]>,
+) -> Packet<IpHeader, EmptyMetadata> {
+    // The receive time-stamp was written into the mbuf's metadata by stamp_response().
+    let response = response.reinterpret_metadata::<u64>();
+    let stamp = *response.read_metadata();
+    let mut response = response.reinterpret_metadata::<EmptyMetadata>();
+    let rx = stamp & !(STAMP_RX | STAMP_TRACE);
+    let mut tx = 0;
+
+    {
+        let payload = response.get_mut_payload();
+        debug_assert!(
+            response_length_ok(payload),
+            "Response length does not match it's payload"
+        );
+        if payload.len() >= size_of::<RpcResponseHeader>() {
+            // The opcode is the second byte on the response header.
+            let opcode = payload[1];
+
+            // Wireformat headers are packed, so the pointer does not have to be aligned.
+            let hdr = payload.as_mut_ptr() as *mut RpcResponseHeader;
+            unsafe {
+                (*hdr).set_load(core_load());
+                (*hdr).set_epoch(epoch::lookup(&(*hdr).status, (*hdr).tenant()).unwrap_or(0));
+                if rx != 0 {
+                    tx = cycles::rdtsc();
+                    latency::record(opcode, tx.saturating_sub(rx), name);
+
+                    // Only the low 32 bits are sent; clients difference them with wrap-around.
+                    if stamp & STAMP_RX != 0 {
+                        (*hdr).set_rx_stamp(rx as u32);
+                        (*hdr).set_tx_stamp(tx as u32);
+                    }
+                }
+            }
+        }
+    }
+
+    if stamp & STAMP_TRACE != 0 {
+        let trace = Trace {
+            rx: rx,
+            tx: tx,
+            hz: cycles::cycles_per_second(),
+        };
+        append_trailer(&mut response, TRAILER_TRACE, &trace.encode());
+    }
+
+    fixup_header_length_fields(response)
+}
+
+/// Adds a trailer to a response, after any trailers already on it. Refer to trailer.rs.
+///
+/// # Arguments
+///
+/// * `response`: The response, parsed upto it's UDP header.
+/// * `kind`:     The type of the trailer.
+/// * `data`:     The trailer.
+///
+/// # Return
+///
+/// True if the trailer was added. False if there wasn't room for it, in which case it's type is
+/// marked truncated on the response, or if the response is malformed.
+pub fn append_trailer<M: Sized + Send>(
+    response: &mut Packet<UdpHeader, M>,
+    kind: u8,
+    data: &[u8],
+) -> bool {
+    let flags = match response.get_payload().get(RESPONSE_FLAGS_OFFSET) {
+        Some(&flags) => flags,
+        None => return false,
+    };
+
+    // Trailers already on the response are taken off, and put back along with the new one.
+    let (len, region) = {
+        let payload = response.get_payload();
+        match TrailerReader::split(flags, payload) {
+            Some((primary, _)) => (primary.len(), payload[primary.len()..].to_vec()),
+            None => return false,
+        }
+    };
+    let mut trailers = match region.is_empty() {
+        true => TrailerWriter::new(trailer::room(len)),
+        false => match TrailerWriter::resume(&region, trailer::room(len)) {
+            Some(trailers) => trailers,
+            None => return false,
+        },
+    };
+
+    let added = trailers.append(kind, data);
+    response.truncate(len);
+    if !response.append(&trailers.finish()) {
+        response.append(&region);
+        return false;
+    }
+    response.get_mut_payload()[RESPONSE_FLAGS_OFFSET] |= RESPONSE_FLAG_TRAILERS;
+    added
+}
+
+// Returns the name of the extension an invoke() request named, or None if the request is not an
+// invoke() or is malformed.
+fn parse_invoke_name(request: &Packet<UdpHeader, EmptyMetadata>) -> Option<&[u8]> {
+    let payload = request.get_payload();
+    if payload.len() < size_of::<InvokeRequest>() || payload[1] != OpCode::SandstormInvokeRpc as u8
+    {
+        return None;
+    }
+
+    // Wireformat headers are packed, so the pointer does not have to be aligned.
+    let hdr = payload.as_ptr() as *const InvokeRequest;
+    let start = size_of::<InvokeRequest>();
+    let len = unsafe { (*hdr).name_length() } as usize;
+    payload.get(start..start + len)
+}
+
 /// Sets the length fields on the UDP and IP headers of a packet.
 ///
 /// # Arguments
@@ -166,7 +730,8 @@ pub fn fixup_header_length_fields(
     return request;
 }
 
-/// Allocate and populate a packet that requests a server "get" operation.
+/// Allocate and populate a packet that requests a server "echo" operation. The response carries
+/// the rate at which the server's cycle counter ticks.
 ///
 /// # Panic
 ///
@@ -175,50 +740,34 @@ pub fn fixup_header_length_fields(
 ///
 /// # Arguments
 ///
-/// * `mac`:      Reference to the MAC header to be added to the request.
-/// * `ip` :      Reference to the IP header to be added to the request.
-/// * `udp`:      Reference to the UDP header to be added to the request.
-/// * `tenant`:   Id of the tenant requesting the item.
-/// * `table_id`: Id of the table from which the key is looked up.
-/// * `key`:      Byte string of key whose value is to be fetched. Limit 64 KB.
-/// * `id`:       RPC identifier.
-/// * `dst`:      The UDP port on the server the RPC is destined for.
-/// * `generator`: The issuer of the get() request(Client or Extension).
+/// * `mac`:    Reference to the MAC header to be added to the request.
+/// * `ip`:     Reference to the IP header to be added to the request.
+/// * `udp`:    Reference to the UDP header to be added to the request.
+/// * `tenant`: Id of the tenant requesting the echo.
+/// * `id`:     RPC identifier.
+/// * `stamp`:  The time-stamp at which the request is being sent out.
+/// * `dst`:    The UDP port on the server the RPC is destined for.
 ///
 /// # Return
 ///
 /// Packet populated with the request parameters.
-#[inline]
-pub fn create_get_rpc(
+pub fn create_echo_rpc(
     mac: &MacHeader,
     ip: &IpHeader,
     udp: &UdpHeader,
     tenant: u32,
-    table_id: u64,
-    key: &[u8],
     id: u64,
+    stamp: u64,
     dst: u16,
-    generator: GetGenerator,
 ) -> Packet<IpHeader, EmptyMetadata> {
-    // Key length cannot be more than 16 bits. Required to construct the RPC header.
-    if key.len() > u16::max_value() as usize {
-        panic!("Key too long ({} bytes).", key.len());
-    }
-
-    // Allocate a packet, write the header and payload into it, and set fields on it's UDP and IP
-    // header.
-    let mut request = create_request(mac, ip, udp, dst)
-        .push_header(&GetRequest::new(tenant, table_id, key.len() as u16, id, generator))
+    let request = create_request(mac, ip, udp, dst)
+        .push_header(&EchoRequest::new(tenant, id, stamp))
         .expect("Failed to push RPC header into request!");
 
-    request
-        .add_to_payload_tail(key.len(), &key)
-        .expect("Failed to write key into get() request!");
-
     fixup_header_length_fields(request.deparse_header(size_of::<UdpHeader>()))
 }
 
-/// Allocate and populate a packet that requests a server "put" operation.
+/// Allocate and populate a packet that requests a server "get" operation.
 ///
 /// # Panic
 ///
@@ -230,96 +779,219 @@ pub fn create_get_rpc(
 /// * `mac`:      Reference to the MAC header to be added to the request.
 /// * `ip` :      Reference to the IP header to be added to the request.
 /// * `udp`:      Reference to the UDP header to be added to the request.
-/// * `tenant`:   Id of the tenant requesting the insertion.
-/// * `table_id`: Id of the table into which the key-value pair is to be inserted.
-/// * `key`:      Byte string of key whose value is to be inserted. Limit 64 KB.
-/// * `val`:      Byte string of the value to be inserted.
+/// * `tenant`:   Id of the tenant requesting the item.
+/// * `table_id`: Id of the table from which the key is looked up.
+/// * `key`:      Byte string of key whose value is to be fetched. Limit 64 KB.
 /// * `id`:       RPC identifier.
+/// * `stamp`:    The time-stamp at which the RPC is being sent out.
 /// * `dst`:      The UDP port on the server the RPC is destined for.
+/// * `generator`: The issuer of the get() request(Client or Extension).
 ///
 /// # Return
 ///
 /// Packet populated with the request parameters.
 #[inline]
-pub fn create_put_rpc(
+pub fn create_get_rpc(
     mac: &MacHeader,
     ip: &IpHeader,
     udp: &UdpHeader,
     tenant: u32,
     table_id: u64,
     key: &[u8],
-    val: &[u8],
     id: u64,
+    stamp: u64,
     dst: u16,
+    generator: GetGenerator,
 ) -> Packet<IpHeader, EmptyMetadata> {
-    // Key length cannot be more than 16 bits. Required to construct the RPC header.
-    if key.len() > u16::max_value() as usize {
-        panic!("Key too long ({} bytes).", key.len());
-    }
-
-    // Allocate a packet, write the header and payload into it, and set fields on it's UDP and IP
-    // header.
-    let mut request = create_request(mac, ip, udp, dst)
-        .push_header(&PutRequest::new(tenant, table_id, key.len() as u16, id))
-        .expect("Failed to push RPC header into request!");
+    create_get_rpc_with_staleness(
+        mac, ip, udp, tenant, table_id, key, id, stamp, dst, generator, 0,
+    )
+}
 
-    let mut payload = Vec::with_capacity(key.len() + val.len());
-    payload.extend_from_slice(key);
-    payload.extend_from_slice(val);
+/// Allocate and populate a packet that requests a server "get" operation that can be served
+/// off a copy of the object lagging behind the primary by atmost `staleness_us`. Refer to
+/// create_get_rpc(), which is this with a budget of 0, ie. strictly fresh.
+///
+/// # Arguments
+///
+/// * `staleness_us`: The staleness budget on the get(), in microseconds.
+#[inline]
+pub fn create_get_rpc_with_staleness(
+    mac: &MacHeader,
+    ip: &IpHeader,
+    udp: &UdpHeader,
+    tenant: u32,
+    table_id: u64,
+    key: &[u8],
+    id: u64,
+    stamp: u64,
+    dst: u16,
+    generator: GetGenerator,
+    staleness_us: u32,
+) -> Packet<IpHeader, EmptyMetadata> {
+    create_get_rpc_with_projection(
+        mac,
+        ip,
+        udp,
+        tenant,
+        table_id,
+        key,
+        id,
+        stamp,
+        dst,
+        generator,
+        staleness_us,
+        (0, 0),
+    )
+}
+
+/// Allocate and populate a packet that requests a server "get" operation that only returns part
+/// of the value. Refer to create_get_rpc_with_staleness(), which is this with a projection of
+/// (0, 0), ie. the whole value.
+///
+/// # Arguments
+///
+/// * `projection`: The offset into the value the response should start at, and the number of
+///                 bytes it should carry. A length of 0 runs upto the end of the value.
+#[inline]
+pub fn create_get_rpc_with_projection(
+    mac: &MacHeader,
+    ip: &IpHeader,
+    udp: &UdpHeader,
+    tenant: u32,
+    table_id: u64,
+    key: &[u8],
+    id: u64,
+    stamp: u64,
+    dst: u16,
+    generator: GetGenerator,
+    staleness_us: u32,
+    projection: (u32, u32),
+) -> Packet<IpHeader, EmptyMetadata> {
+    // Key length cannot be more than 16 bits. Required to construct the RPC header.
+    if key.len() > u16::max_value() as usize {
+        panic!("Key too long ({} bytes).", key.len());
+    }
+
+    // Allocate a packet, write the header and payload into it, and set fields on it's UDP and IP
+    // header.
+    let mut request = create_request(mac, ip, udp, dst)
+        .push_header(
+            &GetRequest::new(tenant, table_id, key.len() as u16, id, stamp, generator)
+                .with_staleness(staleness_us)
+                .with_projection(projection.0, projection.1),
+        )
+        .expect("Failed to push RPC header into request!");
 
     request
-        .add_to_payload_tail(payload.len(), &payload)
-        .expect("Failed to write key into put() request!");
+        .add_to_payload_tail(key.len(), &key)
+        .expect("Failed to write key into get() request!");
 
     fixup_header_length_fields(request.deparse_header(size_of::<UdpHeader>()))
 }
 
-/// Allocate and populate a packet that requests a server "multiget" operation.
+/// Allocate and populate a packet that requests a server "put" operation.
+///
+/// # Panic
+///
+/// May panic if there is a problem allocating the packet or constructing
+/// headers.
 ///
 /// # Arguments
 ///
 /// * `mac`:      Reference to the MAC header to be added to the request.
 /// * `ip` :      Reference to the IP header to be added to the request.
 /// * `udp`:      Reference to the UDP header to be added to the request.
-/// * `tenant`:   Id of the tenant requesting the item.
-/// * `table_id`: Id of the table from which the key is looked up.
-/// * `key_len`:  The length of each key to be looked up at the server. All keys are
-///               assumed to be of equal length.
-/// * `num_keys`: The number of keys to be looked up at the server.
-/// * `keys`:     Byte string of key whose values are to be fetched.
+/// * `tenant`:   Id of the tenant requesting the insertion.
+/// * `table_id`: Id of the table into which the key-value pair is to be inserted.
+/// * `key`:      Byte string of key whose value is to be inserted. Limit 64 KB.
+/// * `val`:      Byte string of the value to be inserted.
 /// * `id`:       RPC identifier.
+/// * `stamp`:    The time-stamp at which the RPC is being sent out.
 /// * `dst`:      The UDP port on the server the RPC is destined for.
 ///
 /// # Return
 ///
 /// Packet populated with the request parameters.
 #[inline]
-pub fn create_multiget_rpc(
+pub fn create_put_rpc(
     mac: &MacHeader,
     ip: &IpHeader,
     udp: &UdpHeader,
     tenant: u32,
     table_id: u64,
-    key_len: u16,
-    num_keys: u32,
-    keys: &[u8],
+    key: &[u8],
+    val: &[u8],
+    id: u64,
+    stamp: u64,
+    dst: u16,
+) -> Packet<IpHeader, EmptyMetadata> {
+    create_put_rpc_gather(mac, ip, udp, tenant, table_id, key, &[val], id, stamp, dst)
+}
+
+/// Allocate and populate a packet that requests a server "put" operation, on a value made up of
+/// several segments. The segments are written into the packet one after the other, so the value
+/// never has to be assembled into a single buffer first; the server sees one contiguous value.
+///
+/// # Panic
+///
+/// May panic if there is a problem allocating the packet or constructing
+/// headers.
+///
+/// # Arguments
+///
+/// * `mac`:      Reference to the MAC header to be added to the request.
+/// * `ip` :      Reference to the IP header to be added to the request.
+/// * `udp`:      Reference to the UDP header to be added to the request.
+/// * `tenant`:   Id of the tenant requesting the insertion.
+/// * `table_id`: Id of the table into which the key-value pair is to be inserted.
+/// * `key`:      Byte string of key whose value is to be inserted. Limit 64 KB.
+/// * `segments`: The segments of the value to be inserted, in order. Segments can be empty.
+/// * `id`:       RPC identifier.
+/// * `stamp`:    The time-stamp at which the RPC is being sent out.
+/// * `dst`:      The UDP port on the server the RPC is destined for.
+///
+/// # Return
+///
+/// Packet populated with the request parameters.
+#[inline]
+pub fn create_put_rpc_gather(
+    mac: &MacHeader,
+    ip: &IpHeader,
+    udp: &UdpHeader,
+    tenant: u32,
+    table_id: u64,
+    key: &[u8],
+    segments: &[&[u8]],
     id: u64,
+    stamp: u64,
     dst: u16,
 ) -> Packet<IpHeader, EmptyMetadata> {
+    // Key length cannot be more than 16 bits. Required to construct the RPC header.
+    if key.len() > u16::max_value() as usize {
+        panic!("Key too long ({} bytes).", key.len());
+    }
+
     // Allocate a packet, write the header and payload into it, and set fields on it's UDP and IP
     // header.
     let mut request = create_request(mac, ip, udp, dst)
-        .push_header(&MultiGetRequest::new(tenant, table_id, key_len, num_keys, id))
+        .push_header(&PutRequest::new(
+            tenant,
+            table_id,
+            key.len() as u16,
+            id,
+            stamp,
+        ))
         .expect("Failed to push RPC header into request!");
 
-    request
-        .add_to_payload_tail(keys.len(), &keys)
-        .expect("Failed to write key into multiget() request!");
+    if !add_segments(&mut request, &[key]) || !add_segments(&mut request, segments) {
+        panic!("Failed to write key-value into put() request!");
+    }
 
     fixup_header_length_fields(request.deparse_header(size_of::<UdpHeader>()))
 }
 
-/// Allocate and populate a packet that requests a server "invoke" operation.
+/// Allocate and populate a packet that requests a server "delete" operation.
 ///
 /// # Panic
 ///
@@ -331,50 +1003,3163 @@ pub fn create_multiget_rpc(
 /// * `mac`:      Reference to the MAC header to be added to the request.
 /// * `ip` :      Reference to the IP header to be added to the request.
 /// * `udp`:      Reference to the UDP header to be added to the request.
-/// * `tenant`:   Id of the tenant requesting the invocation.
-/// * `name_len`: Number of bytes at the head of the payload identifying the extension.
-/// * `payload`:  The RPC payload to be written into the packet. Should contain the name of the
-///               extension, followed by it's arguments.
+/// * `tenant`:   Id of the tenant requesting the deletion.
+/// * `table_id`: Id of the table the key-value pair is to be removed from.
+/// * `key`:      Byte string of key whose key-value pair is to be removed. Limit 64 KB.
 /// * `id`:       RPC identifier.
-/// * `dst`:      The destination port on the server the RPC is destined for.
+/// * `stamp`:    The time-stamp at which the RPC is being sent out.
+/// * `dst`:      The UDP port on the server the RPC is destined for.
 ///
 /// # Return
 ///
 /// Packet populated with the request parameters.
 #[inline]
-pub fn create_invoke_rpc(
+pub fn create_delete_rpc(
     mac: &MacHeader,
     ip: &IpHeader,
     udp: &UdpHeader,
     tenant: u32,
-    name_len: u32,
-    payload: &[u8],
+    table_id: u64,
+    key: &[u8],
     id: u64,
+    stamp: u64,
     dst: u16,
 ) -> Packet<IpHeader, EmptyMetadata> {
-    // The Arguments to the procedure cannot be more that 4 GB long.
-    if payload.len() - name_len as usize > u32::max_value() as usize {
-        panic!(
-            "Args too long ({} bytes).",
-            payload.len() - name_len as usize
-        );
+    // Key length cannot be more than 16 bits. Required to construct the RPC header.
+    if key.len() > u16::max_value() as usize {
+        panic!("Key too long ({} bytes).", key.len());
     }
 
-    // Allocate a packet, write the header and payload into it, and set fields on it's UDP and IP
-    // header. Since the payload contains both, the name and arguments in it, args_len can be
-    // calculated as payload length - name_len.
     let mut request = create_request(mac, ip, udp, dst)
-        .push_header(&InvokeRequest::new(
+        .push_header(&DeleteRequest::new(
             tenant,
-            name_len,
-            (payload.len() - name_len as usize) as u32,
+            table_id,
+            key.len() as u16,
             id,
+            stamp,
         ))
         .expect("Failed to push RPC header into request!");
 
     request
-        .add_to_payload_tail(payload.len(), &payload)
-        .expect("Failed to write args into invoke() request!");
+        .add_to_payload_tail(key.len(), &key)
+        .expect("Failed to write key into delete() request!");
+
+    fixup_header_length_fields(request.deparse_header(size_of::<UdpHeader>()))
+}
+
+/// Allocate and populate a packet that requests a server "cas" operation.
+///
+/// # Panic
+///
+/// May panic if there is a problem allocating the packet or constructing
+/// headers.
+///
+/// # Arguments
+///
+/// * `mac`:      Reference to the MAC header to be added to the request.
+/// * `ip` :      Reference to the IP header to be added to the request.
+/// * `udp`:      Reference to the UDP header to be added to the request.
+/// * `tenant`:   Id of the tenant requesting the write.
+/// * `table_id`: Id of the table into which the key-value pair is to be written.
+/// * `key`:      Byte string of key whose value is to be written. Limit 64 KB.
+/// * `expected`: The value expected under the key, or None if the key is expected to not exist.
+/// * `val`:      Byte string of the value to be written.
+/// * `id`:       RPC identifier.
+/// * `stamp`:    The time-stamp at which the RPC is being sent out.
+/// * `dst`:      The UDP port on the server the RPC is destined for.
+///
+/// # Return
+///
+/// Packet populated with the request parameters.
+#[inline]
+pub fn create_cas_rpc(
+    mac: &MacHeader,
+    ip: &IpHeader,
+    udp: &UdpHeader,
+    tenant: u32,
+    table_id: u64,
+    key: &[u8],
+    expected: Option<&[u8]>,
+    val: &[u8],
+    id: u64,
+    stamp: u64,
+    dst: u16,
+) -> Packet<IpHeader, EmptyMetadata> {
+    // Key length cannot be more than 16 bits. Required to construct the RPC header.
+    if key.len() > u16::max_value() as usize {
+        panic!("Key too long ({} bytes).", key.len());
+    }
+
+    let (flags, expected) = match expected {
+        Some(expected) => (0, expected),
+        None => (CAS_FLAG_EXPECT_ABSENT, &[][..]),
+    };
+
+    let hdr = CasRequest::new(
+        tenant,
+        table_id,
+        key.len() as u16,
+        expected.len() as u32,
+        id,
+        stamp,
+    )
+    .with_flags(flags);
+    let mut request = create_request(mac, ip, udp, dst)
+        .push_header(&hdr)
+        .expect("Failed to push RPC header into request!");
+
+    if !add_segments(&mut request, &[key, expected, val]) {
+        panic!("Failed to write key-value into cas() request!");
+    }
+
+    fixup_header_length_fields(request.deparse_header(size_of::<UdpHeader>()))
+}
+
+// Writes segments to the tail of a packet's payload, one after the other. Empty segments are
+// skipped. Returns false if the packet ran out of room.
+fn add_segments<H: EndOffset>(request: &mut Packet<H, EmptyMetadata>, segments: &[&[u8]]) -> bool {
+    segments
+        .iter()
+        .filter(|s| !s.is_empty())
+        .all(|s| request.add_to_payload_tail(s.len(), s).is_ok())
+}
+
+/// Allocate and populate a packet that requests a server "multiget" operation. Only values are
+/// looked up; refer to create_multiget_rpc_with_flags().
+///
+/// # Arguments
+///
+/// * `mac`:      Reference to the MAC header to be added to the request.
+/// * `ip` :      Reference to the IP header to be added to the request.
+/// * `udp`:      Reference to the UDP header to be added to the request.
+/// * `tenant`:   Id of the tenant requesting the item.
+/// * `table_id`: Id of the table from which the key is looked up.
+/// * `key_len`:  The length of each key to be looked up at the server. All keys are
+///               assumed to be of equal length.
+/// * `num_keys`: The number of keys to be looked up at the server.
+/// * `keys`:     Byte string of key whose values are to be fetched.
+/// * `id`:       RPC identifier.
+/// * `stamp`:    The time-stamp at which the RPC is being sent out.
+/// * `dst`:      The UDP port on the server the RPC is destined for.
+///
+/// # Return
+///
+/// Packet populated with the request parameters.
+#[inline]
+pub fn create_multiget_rpc(
+    mac: &MacHeader,
+    ip: &IpHeader,
+    udp: &UdpHeader,
+    tenant: u32,
+    table_id: u64,
+    key_len: u16,
+    num_keys: u32,
+    keys: &[u8],
+    id: u64,
+    stamp: u64,
+    dst: u16,
+) -> Packet<IpHeader, EmptyMetadata> {
+    create_multiget_rpc_with_flags(
+        mac, ip, udp, tenant, table_id, key_len, num_keys, keys, 0, id, stamp, dst,
+    )
+}
+
+/// Allocate and populate a packet that requests a server "multiget" operation that can ask for
+/// the version of each object along with, or instead of, it's value.
+///
+/// # Arguments
+///
+/// * `mac`:      Reference to the MAC header to be added to the request.
+/// * `ip` :      Reference to the IP header to be added to the request.
+/// * `udp`:      Reference to the UDP header to be added to the request.
+/// * `tenant`:   Id of the tenant requesting the item.
+/// * `table_id`: Id of the table from which the key is looked up.
+/// * `key_len`:  The length of each key to be looked up at the server. All keys are
+///               assumed to be of equal length.
+/// * `num_keys`: The number of keys to be looked up at the server.
+/// * `keys`:     Byte string of key whose values are to be fetched.
+/// * `flags`:    MULTIGET_FLAG_VERSIONS, MULTIGET_FLAG_VERSIONS_ONLY, or 0 for values only.
+/// * `id`:       RPC identifier.
+/// * `stamp`:    The time-stamp at which the RPC is being sent out.
+/// * `dst`:      The UDP port on the server the RPC is destined for.
+///
+/// # Return
+///
+/// Packet populated with the request parameters.
+#[inline]
+pub fn create_multiget_rpc_with_flags(
+    mac: &MacHeader,
+    ip: &IpHeader,
+    udp: &UdpHeader,
+    tenant: u32,
+    table_id: u64,
+    key_len: u16,
+    num_keys: u32,
+    keys: &[u8],
+    flags: u8,
+    id: u64,
+    stamp: u64,
+    dst: u16,
+) -> Packet<IpHeader, EmptyMetadata> {
+    // Allocate a packet, write the header and payload into it, and set fields on it's UDP and IP
+    // header.
+    let hdr = MultiGetRequest::new(tenant, table_id, key_len, num_keys, id, stamp);
+    let mut request = create_request(mac, ip, udp, dst)
+        .push_header(&hdr.with_flags(flags))
+        .expect("Failed to push RPC header into request!");
+
+    request
+        .add_to_payload_tail(keys.len(), &keys)
+        .expect("Failed to write key into multiget() request!");
 
     fixup_header_length_fields(request.deparse_header(size_of::<UdpHeader>()))
 }
+
+/// Allocate and populate a packet that requests a listing of the extensions a tenant can invoke.
+///
+/// # Panic
+///
+/// May panic if there is a problem allocating the packet or constructing
+/// headers.
+///
+/// # Arguments
+///
+/// * `mac`:    Reference to the MAC header to be added to the request.
+/// * `ip`:     Reference to the IP header to be added to the request.
+/// * `udp`:    Reference to the UDP header to be added to the request.
+/// * `tenant`: Id of the tenant whose extensions must be listed.
+/// * `start`:  The index of the first extension to be listed; the continuation index on the
+///             previous response, or 0.
+/// * `id`:     RPC identifier.
+/// * `stamp`:  The time-stamp at which the RPC is being sent out.
+/// * `dst`:    The UDP port on the server the RPC is destined for.
+///
+/// # Return
+///
+/// Packet populated with the request parameters.
+pub fn create_list_ext_rpc(
+    mac: &MacHeader,
+    ip: &IpHeader,
+    udp: &UdpHeader,
+    tenant: u32,
+    start: u32,
+    id: u64,
+    stamp: u64,
+    dst: u16,
+) -> Packet<IpHeader, EmptyMetadata> {
+    let request = create_request(mac, ip, udp, dst)
+        .push_header(&ListExtRequest::new(tenant, start, id, stamp))
+        .expect("Failed to push RPC header into request!");
+
+    fixup_header_length_fields(request.deparse_header(size_of::<UdpHeader>()))
+}
+
+/// Packs a page of an extension listing into the payload of a list_extensions() response. Each
+/// entry is the 2 byte length of the extension's name, the name, the 4 byte version, the 8 byte
+/// load time-stamp, the 8 byte invocation count, and the provenance: a byte that is 1 if the
+/// extension was shared with the tenant and 0 otherwise, followed by the 4 byte id of the tenant
+/// that shared it (0 if it wasn't), the 4 byte id the extension is interned under (refer to
+/// INVOKE_FLAG_BY_ID), and the 2 byte length of the layout the extension declared for it's
+/// arguments followed by the layout (0 if it declared none; refer to sandstorm::schema). All
+/// fields are little-endian.
+///
+/// # Arguments
+///
+/// * `list`:   The tenant's extensions, in the order returned by ExtensionManager::list().
+/// * `start`:  The index of the first entry to be packed.
+/// * `budget`: The number of bytes available for entries. An entry that does not fit in the
+///             budget by itself is still packed so that the listing always makes progress.
+///
+/// # Return
+///
+/// A tupule consisting of the packed entries, the number of entries packed, and the index to
+/// continue the listing from (0 if the listing is complete).
+pub fn encode_ext_listing(
+    list: &[ExtensionInfo],
+    start: usize,
+    budget: usize,
+) -> (Vec<u8>, u32, u32) {
+    let mut buf = Vec::new();
+    let mut idx = start;
+
+    while idx < list.len() {
+        let ext = &list[idx];
+        let schema = ext
+            .schema
+            .as_ref()
+            .map(Schema::encode)
+            .unwrap_or_else(Vec::new);
+        let len = 2 + ext.name.len() + 4 + 8 + 8 + 1 + 4 + 4 + 2 + schema.len();
+        if idx > start && buf.len() + len > budget {
+            break;
+        }
+
+        let name_len: [u8; 2] = unsafe { transmute((ext.name.len() as u16).to_le()) };
+        let version: [u8; 4] = unsafe { transmute(ext.version.to_le()) };
+        let loaded: [u8; 8] = unsafe { transmute(ext.loaded.to_le()) };
+        let invocations: [u8; 8] = unsafe { transmute(ext.invocations.to_le()) };
+        let (shared, owner) = match ext.provenance {
+            Provenance::Private => (0u8, 0),
+            Provenance::Shared(owner) => (1u8, owner),
+        };
+        let owner: [u8; 4] = unsafe { transmute(owner.to_le()) };
+        let id: [u8; 4] = unsafe { transmute(ext.id.to_le()) };
+        let schema_len: [u8; 2] = unsafe { transmute((schema.len() as u16).to_le()) };
+        buf.extend_from_slice(&name_len);
+        buf.extend_from_slice(&ext.name);
+        buf.extend_from_slice(&version);
+        buf.extend_from_slice(&loaded);
+        buf.extend_from_slice(&invocations);
+        buf.push(shared);
+        buf.extend_from_slice(&owner);
+        buf.extend_from_slice(&id);
+        buf.extend_from_slice(&schema_len);
+        buf.extend_from_slice(&schema);
+
+        idx += 1;
+    }
+
+    let next = if idx < list.len() { idx as u32 } else { 0 };
+    (buf, (idx - start) as u32, next)
+}
+
+/// Unpacks the entries on the payload of a list_extensions() response. Refer to
+/// encode_ext_listing() for the format.
+///
+/// # Arguments
+///
+/// * `payload`: The payload following the ListExtResponse header.
+/// * `num`:     The number of entries on the header.
+///
+/// # Return
+///
+/// The entries, or None if the payload does not hold exactly `num` well formed entries. An entry
+/// with a layout that doesn't parse is malformed.
+pub fn parse_ext_listing(payload: &[u8], num: u32) -> Option<Vec<ExtensionInfo>> {
+    let mut list = Vec::with_capacity(num as usize);
+    let mut rest = payload;
+
+    for _ in 0..num {
+        if rest.len() < 2 {
+            return None;
+        }
+        let mut name_len = [0; 2];
+        name_len.copy_from_slice(&rest[0..2]);
+        let name_len = u16::from_le(unsafe { transmute(name_len) }) as usize;
+
+        if rest.len() < 2 + name_len + 31 {
+            return None;
+        }
+        let (name, fields) = rest[2..].split_at(name_len);
+
+        let mut version = [0; 4];
+        let mut loaded = [0; 8];
+        let mut invocations = [0; 8];
+        let mut owner = [0; 4];
+        let mut id = [0; 4];
+        version.copy_from_slice(&fields[0..4]);
+        loaded.copy_from_slice(&fields[4..12]);
+        invocations.copy_from_slice(&fields[12..20]);
+        owner.copy_from_slice(&fields[21..25]);
+        id.copy_from_slice(&fields[25..29]);
+
+        let mut schema_len = [0; 2];
+        schema_len.copy_from_slice(&fields[29..31]);
+        let schema_len = u16::from_le(unsafe { transmute(schema_len) }) as usize;
+        if fields.len() < 31 + schema_len {
+            return None;
+        }
+        let schema = match schema_len {
+            0 => None,
+            len => Some(Schema::parse(&fields[31..31 + len]).ok()?),
+        };
+
+        let owner = u32::from_le(unsafe { transmute(owner) });
+        let provenance = match fields[20] {
+            0 => Provenance::Private,
+            1 => Provenance::Shared(owner),
+            _ => return None,
+        };
+
+        list.push(ExtensionInfo {
+            name: name.to_vec(),
+            version: u32::from_le(unsafe { transmute(version) }),
+            loaded: u64::from_le(unsafe { transmute(loaded) }),
+            invocations: u64::from_le(unsafe { transmute(invocations) }),
+            provenance: provenance,
+            schema: schema,
+            id: u32::from_le(unsafe { transmute(id) }),
+        });
+        rest = &fields[31 + schema_len..];
+    }
+
+    match rest.len() {
+        0 => Some(list),
+        _ => None,
+    }
+}
+
+/// Allocate and populate a packet that puts the server into drain mode, or reports the progress
+/// of a drain that is already underway.
+///
+/// # Panic
+///
+/// May panic if there is a problem allocating the packet or constructing
+/// headers.
+///
+/// # Arguments
+///
+/// * `mac`:    Reference to the MAC header to be added to the request.
+/// * `ip`:     Reference to the IP header to be added to the request.
+/// * `udp`:    Reference to the UDP header to be added to the request.
+/// * `tenant`: Id of the tenant issuing the drain. The server only accepts drains from tenant 0.
+/// * `id`:     RPC identifier.
+/// * `stamp`:  The time-stamp at which the RPC is being sent out.
+/// * `dst`:    The UDP port on the server the RPC is destined for.
+///
+/// # Return
+///
+/// Packet populated with the request parameters.
+pub fn create_drain_rpc(
+    mac: &MacHeader,
+    ip: &IpHeader,
+    udp: &UdpHeader,
+    tenant: u32,
+    id: u64,
+    stamp: u64,
+    dst: u16,
+) -> Packet<IpHeader, EmptyMetadata> {
+    let request = create_request(mac, ip, udp, dst)
+        .push_header(&DrainRequest::new(tenant, id, stamp))
+        .expect("Failed to push RPC header into request!");
+
+    fixup_header_length_fields(request.deparse_header(size_of::<UdpHeader>()))
+}
+
+/// Allocate and populate a packet that sets whether one of the tenant's tables can be read and
+/// written by native RPCs.
+///
+/// # Panic
+///
+/// May panic if there is a problem allocating the packet or constructing
+/// headers.
+///
+/// # Arguments
+///
+/// * `mac`:      Reference to the MAC header to be added to the request.
+/// * `ip`:       Reference to the IP header to be added to the request.
+/// * `udp`:      Reference to the UDP header to be added to the request.
+/// * `tenant`:   Id of the tenant owning the table.
+/// * `table_id`: Id of the table whose access is being set.
+/// * `readable`: Whether native get() and multiget() RPCs can read the table.
+/// * `writable`: Whether native put(), delete() and cas() RPCs can write to the table.
+/// * `id`:       RPC identifier.
+/// * `stamp`:    The time-stamp at which the RPC is being sent out.
+/// * `dst`:      The UDP port on the server the RPC is destined for.
+///
+/// # Return
+///
+/// Packet populated with the request parameters.
+pub fn create_table_access_rpc(
+    mac: &MacHeader,
+    ip: &IpHeader,
+    udp: &UdpHeader,
+    tenant: u32,
+    table_id: u64,
+    readable: bool,
+    writable: bool,
+    id: u64,
+    stamp: u64,
+    dst: u16,
+) -> Packet<IpHeader, EmptyMetadata> {
+    let request = create_request(mac, ip, udp, dst)
+        .push_header(&TableAccessRequest::new(
+            tenant, table_id, readable, writable, id, stamp,
+        ))
+        .expect("Failed to push RPC header into request!");
+
+    fixup_header_length_fields(request.deparse_header(size_of::<UdpHeader>()))
+}
+
+/// Allocate and populate a packet that requests a server "merge" operation, merging a delta into
+/// the value stored under a key with the table's merge extension.
+///
+/// # Panic
+///
+/// May panic if there is a problem allocating the packet or constructing
+/// headers.
+///
+/// # Arguments
+///
+/// * `mac`:      Reference to the MAC header to be added to the request.
+/// * `ip`:       Reference to the IP header to be added to the request.
+/// * `udp`:      Reference to the UDP header to be added to the request.
+/// * `tenant`:   Id of the tenant owning the table.
+/// * `table_id`: Id of the table holding the key.
+/// * `key`:      Byte string of the key to merge into. Limit 64 KB.
+/// * `delta`:    Byte string of the delta passed to the merge extension.
+/// * `id`:       RPC identifier.
+/// * `stamp`:    The time-stamp at which the RPC is being sent out.
+/// * `dst`:      The UDP port on the server the RPC is destined for.
+///
+/// # Return
+///
+/// Packet populated with the request parameters.
+pub fn create_merge_rpc(
+    mac: &MacHeader,
+    ip: &IpHeader,
+    udp: &UdpHeader,
+    tenant: u32,
+    table_id: u64,
+    key: &[u8],
+    delta: &[u8],
+    id: u64,
+    stamp: u64,
+    dst: u16,
+) -> Packet<IpHeader, EmptyMetadata> {
+    // Key length cannot be more than 16 bits. Required to construct the RPC header.
+    if key.len() > u16::max_value() as usize {
+        panic!("Key too long ({} bytes).", key.len());
+    }
+
+    let mut request = create_request(mac, ip, udp, dst)
+        .push_header(&MergeRequest::new(
+            tenant,
+            table_id,
+            key.len() as u16,
+            id,
+            stamp,
+        ))
+        .expect("Failed to push RPC header into request!");
+
+    let mut payload = Vec::with_capacity(key.len() + delta.len());
+    payload.extend_from_slice(key);
+    payload.extend_from_slice(delta);
+
+    request
+        .add_to_payload_tail(payload.len(), &payload)
+        .expect("Failed to write payload into merge() request!");
+
+    fixup_header_length_fields(request.deparse_header(size_of::<UdpHeader>()))
+}
+
+/// Allocate and populate a packet that registers one of the tenant's installed extensions as the
+/// merge extension of one of it's tables. An empty name clears the registration.
+///
+/// # Panic
+///
+/// May panic if there is a problem allocating the packet or constructing
+/// headers.
+///
+/// # Arguments
+///
+/// * `mac`:      Reference to the MAC header to be added to the request.
+/// * `ip`:       Reference to the IP header to be added to the request.
+/// * `udp`:      Reference to the UDP header to be added to the request.
+/// * `tenant`:   Id of the tenant owning the table.
+/// * `table_id`: Id of the table whose merge extension is being set.
+/// * `name`:     The name of the extension.
+/// * `id`:       RPC identifier.
+/// * `stamp`:    The time-stamp at which the RPC is being sent out.
+/// * `dst`:      The UDP port on the server the RPC is destined for.
+///
+/// # Return
+///
+/// Packet populated with the request parameters.
+pub fn create_set_merge_rpc(
+    mac: &MacHeader,
+    ip: &IpHeader,
+    udp: &UdpHeader,
+    tenant: u32,
+    table_id: u64,
+    name: &[u8],
+    id: u64,
+    stamp: u64,
+    dst: u16,
+) -> Packet<IpHeader, EmptyMetadata> {
+    let mut request = create_request(mac, ip, udp, dst)
+        .push_header(&SetMergeRequest::new(
+            tenant,
+            table_id,
+            name.len() as u32,
+            id,
+            stamp,
+        ))
+        .expect("Failed to push RPC header into request!");
+
+    // add_to_payload_tail() indexes into data, so an empty name cannot go through it.
+    if !name.is_empty() {
+        request
+            .add_to_payload_tail(name.len(), name)
+            .expect("Failed to write name into set_merge() request!");
+    }
+
+    fixup_header_length_fields(request.deparse_header(size_of::<UdpHeader>()))
+}
+
+/// Allocate and populate a packet that requests a server "routed invoke" operation. The server
+/// picks the extension off the tenant's routing rules by the key's prefix.
+///
+/// # Panic
+///
+/// May panic if there is a problem allocating the packet or constructing
+/// headers.
+///
+/// # Arguments
+///
+/// * `mac`:      Reference to the MAC header to be added to the request.
+/// * `ip`:       Reference to the IP header to be added to the request.
+/// * `udp`:      Reference to the UDP header to be added to the request.
+/// * `tenant`:   Id of the tenant issuing the request.
+/// * `table_id`: Id of the table passed on to the extension.
+/// * `key`:      Byte string of the key the extension is picked by. Limit 64 KB.
+/// * `args`:     Byte string of the arguments passed on to the extension.
+/// * `id`:       RPC identifier.
+/// * `stamp`:    The time-stamp at which the RPC is being sent out.
+/// * `dst`:      The UDP port on the server the RPC is destined for.
+///
+/// # Return
+///
+/// Packet populated with the request parameters.
+pub fn create_routed_invoke_rpc(
+    mac: &MacHeader,
+    ip: &IpHeader,
+    udp: &UdpHeader,
+    tenant: u32,
+    table_id: u64,
+    key: &[u8],
+    args: &[u8],
+    id: u64,
+    stamp: u64,
+    dst: u16,
+) -> Packet<IpHeader, EmptyMetadata> {
+    // Key length cannot be more than 16 bits. Required to construct the RPC header.
+    if key.len() > u16::max_value() as usize {
+        panic!("Key too long ({} bytes).", key.len());
+    }
+
+    let mut request = create_request(mac, ip, udp, dst)
+        .push_header(&RoutedInvokeRequest::new(
+            tenant,
+            table_id,
+            key.len() as u16,
+            args.len() as u32,
+            id,
+            stamp,
+        ))
+        .expect("Failed to push RPC header into request!");
+
+    let mut payload = Vec::with_capacity(key.len() + args.len());
+    payload.extend_from_slice(key);
+    payload.extend_from_slice(args);
+
+    // add_to_payload_tail() indexes into data, so an empty payload cannot go through it.
+    if !payload.is_empty() {
+        request
+            .add_to_payload_tail(payload.len(), &payload)
+            .expect("Failed to write payload into routed invoke() request!");
+    }
+
+    fixup_header_length_fields(request.deparse_header(size_of::<UdpHeader>()))
+}
+
+/// Allocate and populate a packet that installs one of the tenant's routing rules, routing keys
+/// with a prefix to one of the tenant's installed extensions. An empty name removes the rule on
+/// the prefix.
+///
+/// # Panic
+///
+/// May panic if there is a problem allocating the packet or constructing
+/// headers.
+///
+/// # Arguments
+///
+/// * `mac`:    Reference to the MAC header to be added to the request.
+/// * `ip`:     Reference to the IP header to be added to the request.
+/// * `udp`:    Reference to the UDP header to be added to the request.
+/// * `tenant`: Id of the tenant whose rule is being installed.
+/// * `prefix`: The key prefix the rule matches on. Limit 64 KB.
+/// * `name`:   The name of the extension.
+/// * `id`:     RPC identifier.
+/// * `stamp`:  The time-stamp at which the RPC is being sent out.
+/// * `dst`:    The UDP port on the server the RPC is destined for.
+///
+/// # Return
+///
+/// Packet populated with the request parameters.
+pub fn create_set_route_rpc(
+    mac: &MacHeader,
+    ip: &IpHeader,
+    udp: &UdpHeader,
+    tenant: u32,
+    prefix: &[u8],
+    name: &[u8],
+    id: u64,
+    stamp: u64,
+    dst: u16,
+) -> Packet<IpHeader, EmptyMetadata> {
+    if prefix.len() > u16::max_value() as usize {
+        panic!("Prefix too long ({} bytes).", prefix.len());
+    }
+
+    let mut request = create_request(mac, ip, udp, dst)
+        .push_header(&SetRouteRequest::new(
+            tenant,
+            prefix.len() as u16,
+            name.len() as u32,
+            id,
+            stamp,
+        ))
+        .expect("Failed to push RPC header into request!");
+
+    let mut payload = Vec::with_capacity(prefix.len() + name.len());
+    payload.extend_from_slice(prefix);
+    payload.extend_from_slice(name);
+
+    // add_to_payload_tail() indexes into data, so an empty payload cannot go through it.
+    if !payload.is_empty() {
+        request
+            .add_to_payload_tail(payload.len(), &payload)
+            .expect("Failed to write payload into set_route() request!");
+    }
+
+    fixup_header_length_fields(request.deparse_header(size_of::<UdpHeader>()))
+}
+
+/// Allocate and populate a packet that fetches a page of the records in a table. Refer to
+/// Master::dump_table().
+///
+/// # Panic
+///
+/// May panic if there is a problem allocating the packet or constructing
+/// headers.
+///
+/// # Arguments
+///
+/// * `mac`:    Reference to the MAC header to be added to the request.
+/// * `ip`:     Reference to the IP header to be added to the request.
+/// * `udp`:    Reference to the UDP header to be added to the request.
+/// * `tenant`: Id of the tenant whose table is dumped.
+/// * `table`:  Id of the table to be dumped.
+/// * `bucket`: The bucket the dump continues in; the bucket on the previous response, or 0.
+/// * `after`:  The key the dump continues after; the key of the last record on the previous
+///             response, or empty. Limit 64 KB.
+/// * `id`:     RPC identifier.
+/// * `stamp`:  The time-stamp at which the RPC is being sent out.
+/// * `dst`:    The UDP port on the server the RPC is destined for.
+///
+/// # Return
+///
+/// Packet populated with the request parameters.
+pub fn create_dump_rpc(
+    mac: &MacHeader,
+    ip: &IpHeader,
+    udp: &UdpHeader,
+    tenant: u32,
+    table: u64,
+    bucket: u32,
+    after: &[u8],
+    id: u64,
+    stamp: u64,
+    dst: u16,
+) -> Packet<IpHeader, EmptyMetadata> {
+    if after.len() > u16::max_value() as usize {
+        panic!("Key too long ({} bytes).", after.len());
+    }
+
+    let mut request = create_request(mac, ip, udp, dst)
+        .push_header(&DumpRequest::new(
+            tenant,
+            table,
+            bucket,
+            after.len() as u16,
+            id,
+            stamp,
+        ))
+        .expect("Failed to push RPC header into request!");
+
+    // add_to_payload_tail() indexes into data, so an empty payload cannot go through it.
+    if !after.is_empty() {
+        request
+            .add_to_payload_tail(after.len(), after)
+            .expect("Failed to write key into dump() request!");
+    }
+
+    fixup_header_length_fields(request.deparse_header(size_of::<UdpHeader>()))
+}
+
+/// Allocate and populate a packet that reads a page of the records in a table in key order.
+/// Refer to Master::scan_table().
+///
+/// # Panic
+///
+/// May panic if there is a problem allocating the packet or constructing
+/// headers.
+///
+/// # Arguments
+///
+/// * `mac`:    Reference to the MAC header to be added to the request.
+/// * `ip`:     Reference to the IP header to be added to the request.
+/// * `udp`:    Reference to the UDP header to be added to the request.
+/// * `tenant`: Id of the tenant whose table is scanned.
+/// * `table`:  Id of the table to be scanned.
+/// * `start`:  The key the scan starts from; the continuation token on the previous response, or
+///             empty to start at the first key. Limit 64 KB.
+/// * `after`:  If true, the record under `start` is not returned. Set when resuming a scan.
+/// * `max`:    The most records on the response. 0 for as many as fit on it.
+/// * `id`:     RPC identifier.
+/// * `stamp`:  The time-stamp at which the RPC is being sent out.
+/// * `dst`:    The UDP port on the server the RPC is destined for.
+///
+/// # Return
+///
+/// Packet populated with the request parameters.
+pub fn create_scan_rpc(
+    mac: &MacHeader,
+    ip: &IpHeader,
+    udp: &UdpHeader,
+    tenant: u32,
+    table: u64,
+    start: &[u8],
+    after: bool,
+    max: u32,
+    id: u64,
+    stamp: u64,
+    dst: u16,
+) -> Packet<IpHeader, EmptyMetadata> {
+    if start.len() > u16::max_value() as usize {
+        panic!("Key too long ({} bytes).", start.len());
+    }
+
+    let flags = match after {
+        true => SCAN_FLAG_AFTER_START,
+        false => 0,
+    };
+    let hdr = ScanRequest::new(tenant, table, start.len() as u16, max, id, stamp).with_flags(flags);
+    let mut request = create_request(mac, ip, udp, dst)
+        .push_header(&hdr)
+        .expect("Failed to push RPC header into request!");
+
+    // add_to_payload_tail() indexes into data, so an empty payload cannot go through it.
+    if !start.is_empty() {
+        request
+            .add_to_payload_tail(start.len(), start)
+            .expect("Failed to write key into scan() request!");
+    }
+
+    fixup_header_length_fields(request.deparse_header(size_of::<UdpHeader>()))
+}
+
+/// Allocate and populate a packet that writes a batch of records to a table.
+///
+/// # Panic
+///
+/// May panic if there is a problem allocating the packet or constructing
+/// headers.
+///
+/// # Arguments
+///
+/// * `mac`:     Reference to the MAC header to be added to the request.
+/// * `ip`:      Reference to the IP header to be added to the request.
+/// * `udp`:     Reference to the UDP header to be added to the request.
+/// * `tenant`:  Id of the tenant whose table is written to.
+/// * `table`:   Id of the table the records are written to.
+/// * `records`: The records, each packed by append_kv().
+/// * `num`:     The number of records packed into `records`.
+/// * `id`:      RPC identifier.
+/// * `stamp`:   The time-stamp at which the RPC is being sent out.
+/// * `dst`:     The UDP port on the server the RPC is destined for.
+///
+/// # Return
+///
+/// Packet populated with the request parameters.
+pub fn create_multiput_rpc(
+    mac: &MacHeader,
+    ip: &IpHeader,
+    udp: &UdpHeader,
+    tenant: u32,
+    table: u64,
+    records: &[u8],
+    num: u32,
+    id: u64,
+    stamp: u64,
+    dst: u16,
+) -> Packet<IpHeader, EmptyMetadata> {
+    let mut request = create_request(mac, ip, udp, dst)
+        .push_header(&MultiPutRequest::new(tenant, table, num, id, stamp))
+        .expect("Failed to push RPC header into request!");
+
+    // add_to_payload_tail() indexes into data, so an empty payload cannot go through it.
+    if !records.is_empty() {
+        request
+            .add_to_payload_tail(records.len(), records)
+            .expect("Failed to write records into multiput() request!");
+    }
+
+    fixup_header_length_fields(request.deparse_header(size_of::<UdpHeader>()))
+}
+
+/// The number of bytes a record packed by append_kv() takes over it's key and value.
+pub const KV_OVERHEAD: usize = 2 + 4;
+
+/// Packs a record onto the payload of a dump() response or multiput() request: the 2 byte
+/// length of the key, the 4 byte length of the value, the key, and the value. Lengths are
+/// little-endian.
+///
+/// # Arguments
+///
+/// * `buf`: The payload the record is appended to.
+/// * `key`: The record's key. Limit 64 KB.
+/// * `val`: The record's value.
+pub fn append_kv(buf: &mut Vec<u8>, key: &[u8], val: &[u8]) {
+    let key_len: [u8; 2] = unsafe { transmute((key.len() as u16).to_le()) };
+    let val_len: [u8; 4] = unsafe { transmute((val.len() as u32).to_le()) };
+    buf.extend_from_slice(&key_len);
+    buf.extend_from_slice(&val_len);
+    buf.extend_from_slice(key);
+    buf.extend_from_slice(val);
+}
+
+/// Unpacks the records on the payload of a dump() response or multiput() request. Refer to
+/// append_kv() for the format.
+///
+/// # Arguments
+///
+/// * `payload`: The payload following the request or response header.
+/// * `num`:     The number of records on the header.
+///
+/// # Return
+///
+/// The key and value of each record, or None if the payload does not hold exactly `num` well
+/// formed records.
+pub fn parse_kvs(payload: &[u8], num: u32) -> Option<Vec<(&[u8], &[u8])>> {
+    let mut records = Vec::with_capacity(num as usize);
+    let mut rest = payload;
+
+    for _ in 0..num {
+        if rest.len() < KV_OVERHEAD {
+            return None;
+        }
+        let mut key_len = [0; 2];
+        let mut val_len = [0; 4];
+        key_len.copy_from_slice(&rest[0..2]);
+        val_len.copy_from_slice(&rest[2..6]);
+        let key_len = u16::from_le(unsafe { transmute(key_len) }) as usize;
+        let val_len = u32::from_le(unsafe { transmute(val_len) }) as usize;
+
+        if rest.len() < KV_OVERHEAD + key_len + val_len {
+            return None;
+        }
+        let (key, rest_) = rest[KV_OVERHEAD..].split_at(key_len);
+        let (val, rest_) = rest_.split_at(val_len);
+        records.push((key, val));
+        rest = rest_;
+    }
+
+    match rest.len() {
+        0 => Some(records),
+        _ => None,
+    }
+}
+
+/// Unpacks the records and continuation token on the payload of a scan() response.
+///
+/// # Arguments
+///
+/// * `payload`:      The payload following the ScanResponse header.
+/// * `num`:          The number of records on the header.
+/// * `token_length`: The length of the continuation token on the header.
+///
+/// # Return
+///
+/// The key and value of each record in key order, and the continuation token; empty once the
+/// scan has reached the end of the table. None if the payload does not hold exactly `num` well
+/// formed records followed by the token.
+pub fn parse_scan(
+    payload: &[u8],
+    num: u32,
+    token_length: u16,
+) -> Option<(Vec<(&[u8], &[u8])>, &[u8])> {
+    let token_length = token_length as usize;
+    if payload.len() < token_length {
+        return None;
+    }
+
+    let (records, token) = payload.split_at(payload.len() - token_length);
+    parse_kvs(records, num).map(|records| (records, token))
+}
+
+/// Packs the number of tasks remaining on each core into the payload of a drain() response.
+/// Each count is a little-endian u32.
+///
+/// # Arguments
+///
+/// * `remaining`: The number of tasks remaining on each core.
+pub fn encode_drain_progress(remaining: &[u32]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(remaining.len() * size_of::<u32>());
+    for count in remaining.iter() {
+        let count: [u8; 4] = unsafe { transmute(count.to_le()) };
+        buf.extend_from_slice(&count);
+    }
+
+    buf
+}
+
+/// Unpacks the per-core counts on the payload of a drain() response. Refer to
+/// encode_drain_progress() for the format.
+///
+/// # Arguments
+///
+/// * `payload`: The payload following the DrainResponse header.
+/// * `num`:     The number of cores on the header.
+///
+/// # Return
+///
+/// The number of tasks remaining on each core, or None if the payload does not hold exactly
+/// `num` counts.
+pub fn parse_drain_progress(payload: &[u8], num: u32) -> Option<Vec<u32>> {
+    if payload.len() != num as usize * size_of::<u32>() {
+        return None;
+    }
+
+    Some(
+        payload
+            .chunks(size_of::<u32>())
+            .map(|chunk| {
+                let mut count = [0; 4];
+                count.copy_from_slice(chunk);
+                u32::from_le(unsafe { transmute(count) })
+            }).collect(),
+    )
+}
+
+/// Allocate and populate a packet that requests the counters the server keeps for client runs.
+///
+/// # Panic
+///
+/// May panic if there is a problem allocating the packet or constructing
+/// headers.
+///
+/// # Arguments
+///
+/// * `mac`:    Reference to the MAC header to be added to the request.
+/// * `ip`:     Reference to the IP header to be added to the request.
+/// * `udp`:    Reference to the UDP header to be added to the request.
+/// * `tenant`: Id of the tenant issuing the request. The server only accepts it from tenant 0.
+/// * `filter`: The run whose counters are requested, or 0 for the most recently active runs.
+/// * `id`:     RPC identifier.
+/// * `stamp`:  The time-stamp at which the RPC is being sent out.
+/// * `dst`:    The UDP port on the server the RPC is destined for.
+///
+/// # Return
+///
+/// Packet populated with the request parameters.
+pub fn create_run_stats_rpc(
+    mac: &MacHeader,
+    ip: &IpHeader,
+    udp: &UdpHeader,
+    tenant: u32,
+    filter: u64,
+    id: u64,
+    stamp: u64,
+    dst: u16,
+) -> Packet<IpHeader, EmptyMetadata> {
+    let request = create_request(mac, ip, udp, dst)
+        .push_header(&RunStatsRequest::new(tenant, filter, id, stamp))
+        .expect("Failed to push RPC header into request!");
+
+    fixup_header_length_fields(request.deparse_header(size_of::<UdpHeader>()))
+}
+
+/// Packs the counters of a set of runs into the payload of a run_stats() response. Each run is
+/// packed as five little-endian u64s: the run id, requests, errors, pushbacks and quarantined
+/// requests.
+///
+/// # Arguments
+///
+/// * `runs`: The counters of each run.
+pub fn encode_run_stats(runs: &[RunSummary]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(runs.len() * 5 * size_of::<u64>());
+    for run in runs.iter() {
+        let fields = [
+            run.run,
+            run.requests,
+            run.errors,
+            run.pushbacks,
+            run.quarantined,
+        ];
+        for field in fields.iter() {
+            let field: [u8; 8] = unsafe { transmute(field.to_le()) };
+            buf.extend_from_slice(&field);
+        }
+    }
+
+    buf
+}
+
+/// Unpacks the counters on the payload of a run_stats() response. Refer to encode_run_stats()
+/// for the format.
+///
+/// # Arguments
+///
+/// * `payload`: The payload following the RunStatsResponse header.
+/// * `num`:     The number of runs on the header.
+///
+/// # Return
+///
+/// The counters of each run, or None if the payload does not hold exactly `num` runs.
+pub fn parse_run_stats(payload: &[u8], num: u32) -> Option<Vec<RunSummary>> {
+    let size = 5 * size_of::<u64>();
+    if payload.len() != num as usize * size {
+        return None;
+    }
+
+    let field = |buf: &[u8]| {
+        let mut field = [0; 8];
+        field.copy_from_slice(buf);
+        u64::from_le(unsafe { transmute(field) })
+    };
+
+    Some(
+        payload
+            .chunks(size)
+            .map(|chunk| RunSummary {
+                run: field(&chunk[0..8]),
+                requests: field(&chunk[8..16]),
+                errors: field(&chunk[16..24]),
+                pushbacks: field(&chunk[24..32]),
+                quarantined: field(&chunk[32..40]),
+            }).collect(),
+    )
+}
+
+/// Allocate and populate a packet that fetches a page of the requesting tenant's audit log.
+///
+/// # Panic
+///
+/// May panic if there is a problem allocating the packet or constructing
+/// headers.
+///
+/// # Arguments
+///
+/// * `mac`:    Reference to the MAC header to be added to the request.
+/// * `ip`:     Reference to the IP header to be added to the request.
+/// * `udp`:    Reference to the UDP header to be added to the request.
+/// * `tenant`: Id of the tenant whose audit log is requested.
+/// * `cursor`: Where the page starts; 0 for the newest entries. Refer to AuditLog::page().
+/// * `id`:     RPC identifier.
+/// * `stamp`:  The time-stamp at which the RPC is being sent out.
+/// * `dst`:    The UDP port on the server the RPC is destined for.
+///
+/// # Return
+///
+/// Packet populated with the request parameters.
+pub fn create_audit_rpc(
+    mac: &MacHeader,
+    ip: &IpHeader,
+    udp: &UdpHeader,
+    tenant: u32,
+    cursor: u64,
+    id: u64,
+    stamp: u64,
+    dst: u16,
+) -> Packet<IpHeader, EmptyMetadata> {
+    let request = create_request(mac, ip, udp, dst)
+        .push_header(&AuditRequest::new(tenant, cursor, id, stamp))
+        .expect("Failed to push RPC header into request!");
+
+    fixup_header_length_fields(request.deparse_header(size_of::<UdpHeader>()))
+}
+
+/// The length of an audit entry packed by encode_audit_page().
+pub const AUDIT_ENTRY_LEN: usize = 5 * 8 + 2 * 4 + 1;
+
+/// Packs a page of an audit log into the payload of an audit() response. Each entry is packed
+/// as the 8 byte sequence number, time-stamp, name hash, request id and cycles, the 4 byte
+/// argument and response lengths, and the 1 byte status, all little-endian.
+///
+/// # Arguments
+///
+/// * `entries`: The entries on the page, in the order returned by AuditLog::page().
+pub fn encode_audit_page(entries: &[AuditEntry]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(entries.len() * AUDIT_ENTRY_LEN);
+    for entry in entries.iter() {
+        let longs = [
+            entry.seq,
+            entry.stamp,
+            entry.name_hash,
+            entry.id,
+            entry.cycles,
+        ];
+        for field in longs.iter() {
+            let field: [u8; 8] = unsafe { transmute(field.to_le()) };
+            buf.extend_from_slice(&field);
+        }
+        for field in [entry.args_len, entry.res_len].iter() {
+            let field: [u8; 4] = unsafe { transmute(field.to_le()) };
+            buf.extend_from_slice(&field);
+        }
+        buf.push(entry.status);
+    }
+
+    buf
+}
+
+/// Unpacks the entries on the payload of an audit() response. Refer to encode_audit_page() for
+/// the format.
+///
+/// # Arguments
+///
+/// * `payload`: The payload following the AuditResponse header.
+/// * `num`:     The number of entries on the header.
+///
+/// # Return
+///
+/// The entries, or None if the payload does not hold exactly `num` entries.
+pub fn parse_audit_page(payload: &[u8], num: u32) -> Option<Vec<AuditEntry>> {
+    if payload.len() != num as usize * AUDIT_ENTRY_LEN {
+        return None;
+    }
+
+    let long = |buf: &[u8]| {
+        let mut field = [0; 8];
+        field.copy_from_slice(buf);
+        u64::from_le(unsafe { transmute(field) })
+    };
+    let word = |buf: &[u8]| {
+        let mut field = [0; 4];
+        field.copy_from_slice(buf);
+        u32::from_le(unsafe { transmute(field) })
+    };
+
+    Some(
+        payload
+            .chunks(AUDIT_ENTRY_LEN)
+            .map(|chunk| AuditEntry {
+                seq: long(&chunk[0..8]),
+                stamp: long(&chunk[8..16]),
+                name_hash: long(&chunk[16..24]),
+                id: long(&chunk[24..32]),
+                cycles: long(&chunk[32..40]),
+                args_len: word(&chunk[40..44]),
+                res_len: word(&chunk[44..48]),
+                status: chunk[48],
+            }).collect(),
+    )
+}
+
+/// Allocate and populate a packet that fetches the accounting of the writes made to one of the
+/// requesting tenant's tables.
+///
+/// # Panic
+///
+/// May panic if there is a problem allocating the packet or constructing
+/// headers.
+///
+/// # Arguments
+///
+/// * `mac`:      Reference to the MAC header to be added to the request.
+/// * `ip`:       Reference to the IP header to be added to the request.
+/// * `udp`:      Reference to the UDP header to be added to the request.
+/// * `tenant`:   Id of the tenant owning the table.
+/// * `table_id`: Id of the table whose writes are asked for.
+/// * `query`:    WRITE_STATS_TOTALS, WRITE_STATS_HOT_KEYS or WRITE_STATS_DEDUP.
+/// * `id`:       RPC identifier.
+/// * `stamp`:    The time-stamp at which the RPC is being sent out.
+/// * `dst`:      The UDP port on the server the RPC is destined for.
+///
+/// # Return
+///
+/// Packet populated with the request parameters.
+pub fn create_write_stats_rpc(
+    mac: &MacHeader,
+    ip: &IpHeader,
+    udp: &UdpHeader,
+    tenant: u32,
+    table_id: u64,
+    query: u8,
+    id: u64,
+    stamp: u64,
+    dst: u16,
+) -> Packet<IpHeader, EmptyMetadata> {
+    let request = create_request(mac, ip, udp, dst)
+        .push_header(&WriteStatsRequest::new(tenant, table_id, query, id, stamp))
+        .expect("Failed to push RPC header into request!");
+
+    fixup_header_length_fields(request.deparse_header(size_of::<UdpHeader>()))
+}
+
+/// The length of the totals of a kind of write packed by encode_write_totals().
+pub const WRITE_TOTALS_LEN: usize = 3 * 8;
+
+/// The length of the decay packed by encode_hot_keys() ahead of the keys.
+pub const HOT_KEYS_DECAY_LEN: usize = 3 * 8;
+
+/// The length of a hot key packed by encode_hot_keys(), not counting the key.
+pub const HOT_KEY_OVERHEAD: usize = 2 * 8 + 2;
+
+/// The length of the counters of a dedup index packed by encode_dedup_stats().
+pub const DEDUP_STATS_LEN: usize = 6 * 8;
+
+/// The length of the sizing of a table packed by encode_sizing().
+pub const SIZING_LEN: usize = 4 * 8;
+
+/// The length of the counters of a cache-mode table packed by encode_cache_stats().
+pub const CACHE_STATS_LEN: usize = 6 * 8 + 1;
+
+/// The length of the counters of the hot arena packed by encode_hot_stats().
+pub const HOT_STATS_LEN: usize = 8 * 8;
+
+/// The length of the epochs packed by encode_epochs().
+pub const EPOCH_STATS_LEN: usize = 3 * 8;
+
+// Appends a little-endian u64 to a buffer.
+fn put_u64(buf: &mut Vec<u8>, v: u64) {
+    let field: [u8; 8] = unsafe { transmute(v.to_le()) };
+    buf.extend_from_slice(&field);
+}
+
+// Reads a little-endian u64 off the first 8 bytes of a slice.
+fn get_u64(buf: &[u8]) -> u64 {
+    let mut field = [0; 8];
+    field.copy_from_slice(&buf[..8]);
+    u64::from_le(unsafe { transmute(field) })
+}
+
+/// Packs the totals of each kind of write made to a table into the payload of a write_stats()
+/// response. Each kind is packed as the 8 byte count, logical bytes and physical bytes, all
+/// little-endian, in the order of amplify::WRITE_KINDS.
+///
+/// # Arguments
+///
+/// * `totals`: The totals, as returned by WriteStats::totals().
+pub fn encode_write_totals(totals: &[WriteTotals]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(totals.len() * WRITE_TOTALS_LEN);
+    for total in totals.iter() {
+        put_u64(&mut buf, total.count);
+        put_u64(&mut buf, total.logical);
+        put_u64(&mut buf, total.physical);
+    }
+
+    buf
+}
+
+/// Unpacks the totals on the payload of a write_stats() response. Refer to
+/// encode_write_totals() for the format.
+///
+/// # Arguments
+///
+/// * `payload`: The payload following the WriteStatsResponse header.
+/// * `num`:     The number of entries on the header.
+///
+/// # Return
+///
+/// The totals, or None if the payload does not hold exactly `num` of them.
+pub fn parse_write_totals(payload: &[u8], num: u32) -> Option<Vec<WriteTotals>> {
+    if payload.len() != num as usize * WRITE_TOTALS_LEN {
+        return None;
+    }
+
+    Some(
+        payload
+            .chunks(WRITE_TOTALS_LEN)
+            .map(|chunk| WriteTotals {
+                count: get_u64(&chunk[0..8]),
+                logical: get_u64(&chunk[8..16]),
+                physical: get_u64(&chunk[16..24]),
+            }).collect(),
+    )
+}
+
+/// Packs the hottest keys of a table into the payload of a write_stats() response. The keys are
+/// preceded by how their volumes decay, as the 8 byte interval, epochs and elapsed time of
+/// amplify::Decay. Each key is packed as the 8 byte volume and error, the 2 byte key length, all
+/// little-endian, and the key.
+///
+/// # Arguments
+///
+/// * `keys`:   The keys, hottest first, as returned by WriteStats::hot_keys().
+/// * `decay`:  How their volumes decay, as returned by WriteStats::hot_keys_decay().
+/// * `budget`: The most bytes packed. Keys past the first one that does not fit are left off.
+///
+/// # Return
+///
+/// The packed decay and keys, and the number of keys.
+pub fn encode_hot_keys(keys: &[HotKey], decay: &Decay, budget: usize) -> (Vec<u8>, u32) {
+    let mut buf = Vec::new();
+    put_u64(&mut buf, decay.interval_ms);
+    put_u64(&mut buf, decay.epochs);
+    put_u64(&mut buf, decay.elapsed_ms);
+
+    let mut num = 0;
+    for hot in keys.iter() {
+        if buf.len() + HOT_KEY_OVERHEAD + hot.key.len() > budget {
+            break;
+        }
+
+        put_u64(&mut buf, hot.bytes);
+        put_u64(&mut buf, hot.error);
+        buf.push(hot.key.len() as u8);
+        buf.push((hot.key.len() >> 8) as u8);
+        buf.extend_from_slice(&hot.key);
+        num += 1;
+    }
+
+    (buf, num)
+}
+
+/// Unpacks the decay and keys on the payload of a write_stats() response. Refer to
+/// encode_hot_keys() for the format.
+///
+/// # Arguments
+///
+/// * `payload`: The payload following the WriteStatsResponse header.
+/// * `num`:     The number of entries on the header.
+///
+/// # Return
+///
+/// The decay and the keys, or None if the payload does not hold exactly `num` of them.
+pub fn parse_hot_keys(payload: &[u8], num: u32) -> Option<(Decay, Vec<HotKey>)> {
+    if payload.len() < HOT_KEYS_DECAY_LEN {
+        return None;
+    }
+
+    let decay = Decay {
+        interval_ms: get_u64(&payload[0..8]),
+        epochs: get_u64(&payload[8..16]),
+        elapsed_ms: get_u64(&payload[16..24]),
+    };
+    let mut keys = Vec::with_capacity(num as usize);
+    let mut rest = &payload[HOT_KEYS_DECAY_LEN..];
+    for _ in 0..num {
+        if rest.len() < HOT_KEY_OVERHEAD {
+            return None;
+        }
+
+        let len = rest[16] as usize | (rest[17] as usize) << 8;
+        if rest.len() < HOT_KEY_OVERHEAD + len {
+            return None;
+        }
+
+        keys.push(HotKey {
+            key: rest[HOT_KEY_OVERHEAD..HOT_KEY_OVERHEAD + len].to_vec(),
+            bytes: get_u64(&rest[0..8]),
+            error: get_u64(&rest[8..16]),
+        });
+        rest = &rest[HOT_KEY_OVERHEAD + len..];
+    }
+
+    match rest.is_empty() {
+        true => Some((decay, keys)),
+        false => None,
+    }
+}
+
+/// Packs the counters of a table's dedup index into the payload of a write_stats() response, as
+/// the 8 byte hits, misses, collisions, evicted, bytes saved and entries, all little-endian.
+///
+/// # Arguments
+///
+/// * `stats`: The counters, as returned by Table::dedup_stats().
+pub fn encode_dedup_stats(stats: &DedupStats) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(DEDUP_STATS_LEN);
+    put_u64(&mut buf, stats.hits);
+    put_u64(&mut buf, stats.misses);
+    put_u64(&mut buf, stats.collisions);
+    put_u64(&mut buf, stats.evicted);
+    put_u64(&mut buf, stats.bytes_saved);
+    put_u64(&mut buf, stats.entries);
+    buf
+}
+
+/// Unpacks the counters on the payload of a write_stats() response. Refer to
+/// encode_dedup_stats() for the format.
+///
+/// # Arguments
+///
+/// * `payload`: The payload following the WriteStatsResponse header.
+///
+/// # Return
+///
+/// The counters, or None if the payload does not hold exactly one set of them.
+pub fn parse_dedup_stats(payload: &[u8]) -> Option<DedupStats> {
+    if payload.len() != DEDUP_STATS_LEN {
+        return None;
+    }
+
+    Some(DedupStats {
+        hits: get_u64(&payload[0..8]),
+        misses: get_u64(&payload[8..16]),
+        collisions: get_u64(&payload[16..24]),
+        evicted: get_u64(&payload[24..32]),
+        bytes_saved: get_u64(&payload[32..40]),
+        entries: get_u64(&payload[40..48]),
+    })
+}
+
+/// Packs how a table's index is sized into the payload of a write_stats() response, as the 8
+/// byte slots it was pre-sized to, capacity, objects and resizes, all little-endian.
+///
+/// # Arguments
+///
+/// * `sizing`: The sizing, as returned by Table::sizing().
+pub fn encode_sizing(sizing: &Sizing) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(SIZING_LEN);
+    put_u64(&mut buf, sizing.presized);
+    put_u64(&mut buf, sizing.capacity);
+    put_u64(&mut buf, sizing.objects);
+    put_u64(&mut buf, sizing.resizes);
+    buf
+}
+
+/// Unpacks the sizing on the payload of a write_stats() response. Refer to encode_sizing() for
+/// the format.
+///
+/// # Arguments
+///
+/// * `payload`: The payload following the WriteStatsResponse header.
+///
+/// # Return
+///
+/// The sizing, or None if the payload does not hold exactly one.
+pub fn parse_sizing(payload: &[u8]) -> Option<Sizing> {
+    if payload.len() != SIZING_LEN {
+        return None;
+    }
+
+    Some(Sizing {
+        presized: get_u64(&payload[0..8]),
+        capacity: get_u64(&payload[8..16]),
+        objects: get_u64(&payload[16..24]),
+        resizes: get_u64(&payload[24..32]),
+    })
+}
+
+/// Packs the target and counters of a cache-mode table into the payload of a write_stats()
+/// response, as the 8 byte max_bytes, max_objects, bytes, objects, evicted and evicted_bytes,
+/// all little-endian, followed by the eviction policy as it goes on a create_table() request.
+///
+/// # Arguments
+///
+/// * `stats`: The counters, as returned by Table::cache_stats().
+pub fn encode_cache_stats(stats: &CacheStats) -> Vec<u8> {
+    let (max_bytes, max_objects, evict) = CacheTarget::to_wire(Some(stats.target));
+    let mut buf = Vec::with_capacity(CACHE_STATS_LEN);
+    put_u64(&mut buf, max_bytes);
+    put_u64(&mut buf, max_objects);
+    put_u64(&mut buf, stats.bytes);
+    put_u64(&mut buf, stats.objects);
+    put_u64(&mut buf, stats.evicted);
+    put_u64(&mut buf, stats.evicted_bytes);
+    buf.push(evict);
+    buf
+}
+
+/// Unpacks the counters of a cache-mode table on the payload of a write_stats() response. Refer
+/// to encode_cache_stats() for the format.
+///
+/// # Arguments
+///
+/// * `payload`: The payload following the WriteStatsResponse header.
+///
+/// # Return
+///
+/// The counters, or None if the payload does not hold exactly one set of them.
+pub fn parse_cache_stats(payload: &[u8]) -> Option<CacheStats> {
+    if payload.len() != CACHE_STATS_LEN {
+        return None;
+    }
+
+    let (max_bytes, max_objects) = (get_u64(&payload[0..8]), get_u64(&payload[8..16]));
+    let target = CacheTarget::from_wire(max_bytes, max_objects, payload[48]).ok()??;
+    Some(CacheStats {
+        target: target,
+        bytes: get_u64(&payload[16..24]),
+        objects: get_u64(&payload[24..32]),
+        evicted: get_u64(&payload[32..40]),
+        evicted_bytes: get_u64(&payload[40..48]),
+    })
+}
+
+/// Packs the counters of the hot arena into the payload of a write_stats() response, as the
+/// 8 byte capacity, used, promotions, demotions, refused, raced, gets and hits, all
+/// little-endian.
+///
+/// # Arguments
+///
+/// * `stats`: The counters, as returned by Allocator::hot_stats().
+pub fn encode_hot_stats(stats: &HotStats) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(HOT_STATS_LEN);
+    put_u64(&mut buf, stats.capacity);
+    put_u64(&mut buf, stats.used);
+    put_u64(&mut buf, stats.promotions);
+    put_u64(&mut buf, stats.demotions);
+    put_u64(&mut buf, stats.refused);
+    put_u64(&mut buf, stats.raced);
+    put_u64(&mut buf, stats.gets);
+    put_u64(&mut buf, stats.hits);
+    buf
+}
+
+/// Unpacks the counters of the hot arena on the payload of a write_stats() response. Refer to
+/// encode_hot_stats() for the format.
+///
+/// # Arguments
+///
+/// * `payload`: The payload following the WriteStatsResponse header.
+///
+/// # Return
+///
+/// The counters, or None if the payload does not hold exactly one set of them.
+pub fn parse_hot_stats(payload: &[u8]) -> Option<HotStats> {
+    if payload.len() != HOT_STATS_LEN {
+        return None;
+    }
+
+    Some(HotStats {
+        capacity: get_u64(&payload[0..8]),
+        used: get_u64(&payload[8..16]),
+        promotions: get_u64(&payload[16..24]),
+        demotions: get_u64(&payload[24..32]),
+        refused: get_u64(&payload[32..40]),
+        raced: get_u64(&payload[40..48]),
+        gets: get_u64(&payload[48..56]),
+        hits: get_u64(&payload[56..64]),
+    })
+}
+
+/// Packs the epochs of a table into the payload of a write_stats() response, as the 8 byte
+/// epochs of the server's tenant namespace, the tenant and the table, all little-endian.
+///
+/// # Arguments
+///
+/// * `stats`: The epochs.
+pub fn encode_epochs(stats: &EpochStats) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(EPOCH_STATS_LEN);
+    put_u64(&mut buf, stats.server);
+    put_u64(&mut buf, stats.tenant);
+    put_u64(&mut buf, stats.table);
+    buf
+}
+
+/// Unpacks the epochs of a table on the payload of a write_stats() response. Refer to
+/// encode_epochs() for the format.
+///
+/// # Arguments
+///
+/// * `payload`: The payload following the WriteStatsResponse header.
+///
+/// # Return
+///
+/// The epochs, or None if the payload does not hold exactly one set of them.
+pub fn parse_epochs(payload: &[u8]) -> Option<EpochStats> {
+    if payload.len() != EPOCH_STATS_LEN {
+        return None;
+    }
+
+    Some(EpochStats {
+        server: get_u64(&payload[0..8]),
+        tenant: get_u64(&payload[8..16]),
+        table: get_u64(&payload[16..24]),
+    })
+}
+
+/// Allocate and populate a packet that asks the server how long each of it's cores spent
+/// backing off polling.
+///
+/// # Panic
+///
+/// May panic if there is a problem allocating the packet or constructing
+/// headers.
+///
+/// # Arguments
+///
+/// * `mac`:    Reference to the MAC header to be added to the request.
+/// * `ip`:     Reference to the IP header to be added to the request.
+/// * `udp`:    Reference to the UDP header to be added to the request.
+/// * `tenant`: Id of the tenant issuing the request. The server only accepts it from tenant 0.
+/// * `id`:     RPC identifier.
+/// * `stamp`:  The time-stamp at which the RPC is being sent out.
+/// * `dst`:    The UDP port on the server the RPC is destined for.
+///
+/// # Return
+///
+/// Packet populated with the request parameters.
+pub fn create_core_stats_rpc(
+    mac: &MacHeader,
+    ip: &IpHeader,
+    udp: &UdpHeader,
+    tenant: u32,
+    id: u64,
+    stamp: u64,
+    dst: u16,
+) -> Packet<IpHeader, EmptyMetadata> {
+    let request = create_request(mac, ip, udp, dst)
+        .push_header(&CoreStatsRequest::new(tenant, id, stamp))
+        .expect("Failed to push RPC header into request!");
+
+    fixup_header_length_fields(request.deparse_header(size_of::<UdpHeader>()))
+}
+
+/// The length of the backoff counters of a core packed by encode_core_stats().
+pub const CORE_STATS_LEN: usize = 4 + N_TIERS * 2 * 8;
+
+/// Packs the backoff counters of a set of cores into the payload of a core_stats() response.
+/// Each core is packed as the 4 byte core, followed by the 8 byte pauses and nanoseconds of each
+/// tier in the order of backoff::Tier, all little-endian.
+///
+/// # Arguments
+///
+/// * `cores`: The counters of each core, as returned by Cores::summaries().
+pub fn encode_core_stats(cores: &[CoreSummary]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(cores.len() * CORE_STATS_LEN);
+    for core in cores.iter() {
+        let id: [u8; 4] = unsafe { transmute((core.core as u32).to_le()) };
+        buf.extend_from_slice(&id);
+        for tier in core.tiers.iter() {
+            put_u64(&mut buf, tier.pauses);
+            put_u64(&mut buf, tier.nanos);
+        }
+    }
+
+    buf
+}
+
+/// Unpacks the backoff counters on the payload of a core_stats() response. Refer to
+/// encode_core_stats() for the format.
+///
+/// # Arguments
+///
+/// * `payload`: The payload following the CoreStatsResponse header.
+/// * `num`:     The number of cores on the header.
+///
+/// # Return
+///
+/// The counters of each core, or None if the payload does not hold exactly `num` cores.
+pub fn parse_core_stats(payload: &[u8], num: u32) -> Option<Vec<CoreSummary>> {
+    if payload.len() != num as usize * CORE_STATS_LEN {
+        return None;
+    }
+
+    Some(
+        payload
+            .chunks(CORE_STATS_LEN)
+            .map(|chunk| {
+                let mut id = [0; 4];
+                id.copy_from_slice(&chunk[0..4]);
+                let mut core = CoreSummary {
+                    core: u32::from_le(unsafe { transmute(id) }) as i32,
+                    tiers: [TierStats::default(); N_TIERS],
+                };
+                for (i, tier) in core.tiers.iter_mut().enumerate() {
+                    let at = 4 + i * 16;
+                    tier.pauses = get_u64(&chunk[at..at + 8]);
+                    tier.nanos = get_u64(&chunk[at + 8..at + 16]);
+                }
+                core
+            }).collect(),
+    )
+}
+
+/// Allocate and populate a packet that asks the server how long it took to respond to each
+/// opcode or extension.
+///
+/// # Panic
+///
+/// May panic if there is a problem allocating the packet or constructing
+/// headers.
+///
+/// # Arguments
+///
+/// * `mac`:    Reference to the MAC header to be added to the request.
+/// * `ip`:     Reference to the IP header to be added to the request.
+/// * `udp`:    Reference to the UDP header to be added to the request.
+/// * `tenant`: Id of the tenant issuing the request. The server only accepts it from tenant 0.
+/// * `query`:  What is asked for. Refer to LATENCY_STATS_OPCODES.
+/// * `opcode`: The opcode whose histogram is asked for.
+/// * `name`:   The extension whose histogram is asked for. Empty for an opcode's histogram.
+/// * `id`:     RPC identifier.
+/// * `stamp`:  The time-stamp at which the RPC is being sent out.
+/// * `dst`:    The UDP port on the server the RPC is destined for.
+///
+/// # Return
+///
+/// Packet populated with the request parameters.
+pub fn create_latency_stats_rpc(
+    mac: &MacHeader,
+    ip: &IpHeader,
+    udp: &UdpHeader,
+    tenant: u32,
+    query: u8,
+    opcode: u8,
+    name: &[u8],
+    id: u64,
+    stamp: u64,
+    dst: u16,
+) -> Packet<IpHeader, EmptyMetadata> {
+    let mut request = create_request(mac, ip, udp, dst)
+        .push_header(&LatencyStatsRequest::new(tenant, query, opcode, id, stamp))
+        .expect("Failed to push RPC header into request!");
+    if !name.is_empty() {
+        request
+            .add_to_payload_tail(name.len(), name)
+            .expect("Failed to write name into latency_stats() request!");
+    }
+
+    fixup_header_length_fields(request.deparse_header(size_of::<UdpHeader>()))
+}
+
+/// Allocate and populate a packet that quarantines a tenant, releases it, or asks for it's
+/// quarantine.
+///
+/// # Panic
+///
+/// May panic if there is a problem allocating the packet or constructing
+/// headers.
+///
+/// # Arguments
+///
+/// * `mac`:         Reference to the MAC header to be added to the request.
+/// * `ip`:          Reference to the IP header to be added to the request.
+/// * `udp`:         Reference to the UDP header to be added to the request.
+/// * `tenant`:      Id of the tenant issuing the request. The server only accepts it from
+///                  tenant 0.
+/// * `action`:      What is asked for. Refer to QUARANTINE_QUERY.
+/// * `target`:      The tenant to quarantine, release, or report on.
+/// * `duration_ms`: How long to quarantine the tenant for, on QUARANTINE_ENTER. 0 leaves it to
+///                  the server.
+/// * `id`:          RPC identifier.
+/// * `stamp`:       The time-stamp at which the RPC is being sent out.
+/// * `dst`:         The UDP port on the server the RPC is destined for.
+///
+/// # Return
+///
+/// Packet populated with the request parameters.
+pub fn create_quarantine_rpc(
+    mac: &MacHeader,
+    ip: &IpHeader,
+    udp: &UdpHeader,
+    tenant: u32,
+    action: u8,
+    target: u32,
+    duration_ms: u64,
+    id: u64,
+    stamp: u64,
+    dst: u16,
+) -> Packet<IpHeader, EmptyMetadata> {
+    let hdr = QuarantineRequest::new(tenant, action, target, duration_ms, id, stamp);
+    let request = create_request(mac, ip, udp, dst)
+        .push_header(&hdr)
+        .expect("Failed to push RPC header into request!");
+
+    fixup_header_length_fields(request.deparse_header(size_of::<UdpHeader>()))
+}
+
+/// Allocate and populate a packet that asks the server to create one of the tenant's tables,
+/// pre-sized for the number of objects it is expected to hold.
+///
+/// # Panic
+///
+/// May panic if there is a problem allocating the packet or constructing
+/// headers.
+///
+/// # Arguments
+///
+/// * `mac`:      Reference to the MAC header to be added to the request.
+/// * `ip`:       Reference to the IP header to be added to the request.
+/// * `udp`:      Reference to the UDP header to be added to the request.
+/// * `tenant`:   Id of the tenant the table is created for.
+/// * `table_id`: Id of the table to create.
+/// * `records`:  The number of objects the table is expected to hold. 0 does not pre-size it.
+/// * `cache`:    The capacity target of a cache-mode table. None creates an ordinary table.
+/// * `id`:       RPC identifier.
+/// * `stamp`:    The time-stamp at which the RPC is being sent out.
+/// * `dst`:      The UDP port on the server the RPC is destined for.
+///
+/// # Return
+///
+/// Packet populated with the request parameters.
+pub fn create_create_table_rpc(
+    mac: &MacHeader,
+    ip: &IpHeader,
+    udp: &UdpHeader,
+    tenant: u32,
+    table_id: u64,
+    records: u64,
+    cache: Option<CacheTarget>,
+    id: u64,
+    stamp: u64,
+    dst: u16,
+) -> Packet<IpHeader, EmptyMetadata> {
+    let hdr = create_table_header(tenant, table_id, records, cache, id, stamp);
+    let request = create_request(mac, ip, udp, dst)
+        .push_header(&hdr)
+        .expect("Failed to push RPC header into request!");
+
+    fixup_header_length_fields(request.deparse_header(size_of::<UdpHeader>()))
+}
+
+/// Allocate and populate a packet that asks the server to drop one of the tenant's tables.
+///
+/// # Panic
+///
+/// May panic if there is a problem allocating the packet or constructing
+/// headers.
+///
+/// # Arguments
+///
+/// * `mac`:      Reference to the MAC header to be added to the request.
+/// * `ip`:       Reference to the IP header to be added to the request.
+/// * `udp`:      Reference to the UDP header to be added to the request.
+/// * `tenant`:   Id of the tenant owning the table.
+/// * `table_id`: Id of the table to drop.
+/// * `id`:       RPC identifier.
+/// * `stamp`:    The time-stamp at which the RPC is being sent out.
+/// * `dst`:      The UDP port on the server the RPC is destined for.
+///
+/// # Return
+///
+/// Packet populated with the request parameters.
+pub fn create_drop_table_rpc(
+    mac: &MacHeader,
+    ip: &IpHeader,
+    udp: &UdpHeader,
+    tenant: u32,
+    table_id: u64,
+    id: u64,
+    stamp: u64,
+    dst: u16,
+) -> Packet<IpHeader, EmptyMetadata> {
+    let hdr = DropTableRequest::new(tenant, table_id, id, stamp);
+    let request = create_request(mac, ip, udp, dst)
+        .push_header(&hdr)
+        .expect("Failed to push RPC header into request!");
+
+    fixup_header_length_fields(request.deparse_header(size_of::<UdpHeader>()))
+}
+
+/// Returns the header of a create_table() RPC request, carrying the target of a cache-mode
+/// table if there is one. Refer to create_create_table_rpc().
+pub fn create_table_header(
+    tenant: u32,
+    table_id: u64,
+    records: u64,
+    cache: Option<CacheTarget>,
+    id: u64,
+    stamp: u64,
+) -> CreateTableRequest {
+    let mut hdr = CreateTableRequest::new(tenant, table_id, records, id, stamp);
+    let (max_bytes, max_objects, evict) = CacheTarget::to_wire(cache);
+    hdr.set_max_bytes(max_bytes);
+    hdr.set_max_objects(max_objects);
+    hdr.evict = evict;
+    hdr
+}
+
+/// Allocate and populate a packet that asks the server for the result an invocation stored in
+/// the tenant's mailbox, removing it. Refer to mailbox::Mailbox.
+///
+/// # Panic
+///
+/// May panic if there is a problem allocating the packet or constructing
+/// headers.
+///
+/// # Arguments
+///
+/// * `mac`:        Reference to the MAC header to be added to the request.
+/// * `ip`:         Reference to the IP header to be added to the request.
+/// * `udp`:        Reference to the UDP header to be added to the request.
+/// * `tenant`:     Id of the tenant that issued the invocation.
+/// * `request_id`: The id of the invoke() request whose result to fetch.
+/// * `id`:         RPC identifier.
+/// * `stamp`:      The time-stamp at which the RPC is being sent out.
+/// * `dst`:        The UDP port on the server the RPC is destined for.
+///
+/// # Return
+///
+/// Packet populated with the request parameters.
+pub fn create_fetch_result_rpc(
+    mac: &MacHeader,
+    ip: &IpHeader,
+    udp: &UdpHeader,
+    tenant: u32,
+    request_id: u64,
+    id: u64,
+    stamp: u64,
+    dst: u16,
+) -> Packet<IpHeader, EmptyMetadata> {
+    let request = create_request(mac, ip, udp, dst)
+        .push_header(&FetchResultRequest::new(tenant, request_id, id, stamp))
+        .expect("Failed to push RPC header into request!");
+
+    fixup_header_length_fields(request.deparse_header(size_of::<UdpHeader>()))
+}
+
+/// The length of the percentiles of an opcode packed by encode_latency_summaries().
+pub const LATENCY_SUMMARY_LEN: usize = 1 + 4 * 8;
+
+// Packs a summary as it's 8 byte count, median, 99th and 99.9th percentile.
+fn put_summary(buf: &mut Vec<u8>, summary: &LatencySummary) {
+    put_u64(buf, summary.count);
+    put_u64(buf, summary.p50);
+    put_u64(buf, summary.p99);
+    put_u64(buf, summary.p999);
+}
+
+// Unpacks a summary packed by put_summary().
+fn get_summary(buf: &[u8]) -> LatencySummary {
+    LatencySummary {
+        count: get_u64(&buf[0..8]),
+        p50: get_u64(&buf[8..16]),
+        p99: get_u64(&buf[16..24]),
+        p999: get_u64(&buf[24..32]),
+    }
+}
+
+/// Packs the percentiles of each opcode into the payload of a latency_stats() response. Each
+/// opcode is packed as it's 1 byte value, followed by the 8 byte count of requests, and median,
+/// 99th and 99.9th percentile in nanoseconds, all little-endian.
+///
+/// # Arguments
+///
+/// * `ops`: The percentiles of each opcode.
+pub fn encode_latency_summaries(ops: &[(u8, LatencySummary)]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(ops.len() * LATENCY_SUMMARY_LEN);
+    for &(opcode, ref summary) in ops.iter() {
+        buf.push(opcode);
+        put_summary(&mut buf, summary);
+    }
+
+    buf
+}
+
+/// Unpacks the percentiles on the payload of a latency_stats() response. Refer to
+/// encode_latency_summaries() for the format.
+///
+/// # Arguments
+///
+/// * `payload`: The payload following the LatencyStatsResponse header.
+/// * `num`:     The number of entries on the header.
+///
+/// # Return
+///
+/// The percentiles of each opcode, or None if the payload does not hold exactly `num` of them.
+pub fn parse_latency_summaries(payload: &[u8], num: u32) -> Option<Vec<(u8, LatencySummary)>> {
+    if payload.len() != num as usize * LATENCY_SUMMARY_LEN {
+        return None;
+    }
+
+    Some(
+        payload
+            .chunks(LATENCY_SUMMARY_LEN)
+            .map(|chunk| (chunk[0], get_summary(&chunk[1..])))
+            .collect(),
+    )
+}
+
+/// Packs the percentiles of as many extensions as fit in `budget` bytes into the payload of a
+/// latency_stats() response. Each extension is packed as the 1 byte length of it's name, the
+/// name, and then it's percentiles in the format of encode_latency_summaries(). Names longer than
+/// 255 bytes are cut.
+///
+/// # Arguments
+///
+/// * `exts`:   The percentiles of each extension, in the order they should be packed.
+/// * `budget`: The most bytes to pack.
+///
+/// # Return
+///
+/// The packed extensions, and the number of them.
+pub fn encode_ext_latencies(exts: &[(Vec<u8>, LatencySummary)], budget: usize) -> (Vec<u8>, u32) {
+    let mut buf = Vec::new();
+    let mut num = 0;
+    for &(ref name, ref summary) in exts.iter() {
+        let name = &name[..name.len().min(u8::max_value() as usize)];
+        if buf.len() + LATENCY_SUMMARY_LEN + name.len() > budget {
+            break;
+        }
+
+        buf.push(name.len() as u8);
+        buf.extend_from_slice(name);
+        put_summary(&mut buf, summary);
+        num += 1;
+    }
+
+    (buf, num)
+}
+
+/// Unpacks the extensions on the payload of a latency_stats() response. Refer to
+/// encode_ext_latencies() for the format.
+///
+/// # Arguments
+///
+/// * `payload`: The payload following the LatencyStatsResponse header.
+/// * `num`:     The number of entries on the header.
+///
+/// # Return
+///
+/// The percentiles of each extension, or None if the payload does not hold exactly `num` of
+/// them.
+pub fn parse_ext_latencies(payload: &[u8], num: u32) -> Option<Vec<(Vec<u8>, LatencySummary)>> {
+    let mut exts = Vec::with_capacity(num as usize);
+    let mut rest = payload;
+    for _ in 0..num {
+        let len = *rest.first()? as usize;
+        if rest.len() < LATENCY_SUMMARY_LEN + len {
+            return None;
+        }
+
+        exts.push((rest[1..1 + len].to_vec(), get_summary(&rest[1 + len..])));
+        rest = &rest[LATENCY_SUMMARY_LEN + len..];
+    }
+
+    match rest.is_empty() {
+        true => Some(exts),
+        false => None,
+    }
+}
+
+/// The length of a bucket packed by encode_latency_histogram().
+pub const LATENCY_BUCKET_LEN: usize = 1 + 8;
+
+/// Packs the buckets of a histogram that hold samples into the payload of a latency_stats()
+/// response. Each bucket is packed as it's 1 byte index, followed by the 8 byte little-endian
+/// count of samples in it. A histogram has LOG_BUCKETS buckets, so it always fits in a single
+/// response.
+///
+/// # Arguments
+///
+/// * `hist`: The histogram, in cycles.
+///
+/// # Return
+///
+/// The packed buckets, and the number of them.
+pub fn encode_latency_histogram(hist: &LogHistogram) -> (Vec<u8>, u32) {
+    let mut buf = Vec::new();
+    let mut num = 0;
+    for (bucket, count) in hist.counts().iter().enumerate() {
+        if *count > 0 {
+            buf.push(bucket as u8);
+            put_u64(&mut buf, *count);
+            num += 1;
+        }
+    }
+
+    (buf, num)
+}
+
+/// Unpacks the histogram on the payload of a latency_stats() response. Refer to
+/// encode_latency_histogram() for the format.
+///
+/// # Arguments
+///
+/// * `payload`: The payload following the LatencyStatsResponse header.
+/// * `num`:     The number of entries on the header.
+///
+/// # Return
+///
+/// The histogram in cycles, or None if the payload does not hold exactly `num` buckets, or
+/// names a bucket that does not exist.
+pub fn parse_latency_histogram(payload: &[u8], num: u32) -> Option<LogHistogram> {
+    if payload.len() != num as usize * LATENCY_BUCKET_LEN {
+        return None;
+    }
+
+    let mut hist = LogHistogram::new();
+    for chunk in payload.chunks(LATENCY_BUCKET_LEN) {
+        if chunk[0] as usize >= LOG_BUCKETS {
+            return None;
+        }
+        hist.add(chunk[0] as usize, get_u64(&chunk[1..]));
+    }
+
+    Some(hist)
+}
+
+/// A response that is being written into. Implemented by packets, and lets the functions below
+/// that keep a response's length fields consistent with it's payload be used on other buffers.
+pub trait ResponseBuf<H> {
+    /// Returns a mutable reference to the response header.
+    fn header(&mut self) -> &mut H;
+
+    /// Returns the number of bytes in the payload following the response header.
+    fn payload_len(&self) -> usize;
+
+    /// Appends `data` to the payload. Returns false if the payload was left unchanged because
+    /// there wasn't enough room for all of `data`.
+    fn append(&mut self, data: &[u8]) -> bool;
+
+    /// Shrinks the payload to `len` bytes. Does nothing if the payload is already shorter.
+    fn truncate(&mut self, len: usize);
+}
+
+impl<H: EndOffset, M: Sized + Send> ResponseBuf<H> for Packet<H, M> {
+    #[inline]
+    fn header(&mut self) -> &mut H {
+        self.get_mut_header()
+    }
+
+    #[inline]
+    fn payload_len(&self) -> usize {
+        self.get_payload().len()
+    }
+
+    #[inline]
+    fn append(&mut self, data: &[u8]) -> bool {
+        // add_to_payload_tail() indexes into data, so empty appends cannot go through it.
+        data.len() == 0 || self.add_to_payload_tail(data.len(), data).is_ok()
+    }
+
+    #[inline]
+    fn truncate(&mut self, len: usize) {
+        let curr = self.get_payload().len();
+        if curr > len {
+            self.trim_payload_size(curr - len);
+        }
+    }
+}
+
+/// Appends a record made up of a sequence of byte slices to a response's payload. Either the
+/// entire record is appended, or none of it is.
+///
+/// # Arguments
+///
+/// * `res`:   The response to append the record to.
+/// * `parts`: The slices that together make up the record, in order.
+///
+/// # Return
+///
+/// True if the record was appended. False if it did not fit, in which case the payload is left
+/// exactly as it was before the call.
+pub fn append_record<H, B: ResponseBuf<H>>(res: &mut B, parts: &[&[u8]]) -> bool {
+    let start = res.payload_len();
+    for part in parts.iter() {
+        if !res.append(part) {
+            res.truncate(start);
+            return false;
+        }
+    }
+
+    true
+}
+
+/// Writes the status and value length onto the header of a response to a get() RPC. The length
+/// is derived from the payload that was actually appended. If the RPC did not complete
+/// successfully, then the payload is dropped and the length is zero.
+///
+/// # Arguments
+///
+/// * `res`:    The response to a get() RPC, with it's payload fully written.
+/// * `status`: The status the RPC completed with.
+pub fn finish_get_response<B: ResponseBuf<GetResponse>>(res: &mut B, status: RpcStatus) {
+    if status != RpcStatus::StatusOk {
+        res.truncate(0);
+    }
+
+    let len = res.payload_len() as u32;
+    let hdr = res.header();
+    hdr.set_value_length(len);
+    hdr.common_header.status = status;
+}
+
+/// Writes the status and number of records onto the header of a response to a multiget() RPC.
+/// If the RPC did not complete successfully, then the payload is dropped, the number of records
+/// is zero, and the response isn't flagged racy.
+///
+/// # Arguments
+///
+/// * `res`:         The response to a multiget() RPC, with it's payload fully written.
+/// * `status`:      The status the RPC completed with.
+/// * `num_records`: The number of records appended to the payload.
+pub fn finish_multiget_response<B: ResponseBuf<MultiGetResponse>>(
+    res: &mut B,
+    status: RpcStatus,
+    num_records: u32,
+) {
+    let num_records = match status {
+        RpcStatus::StatusOk => num_records,
+        _ => {
+            res.truncate(0);
+            res.header().racy = 0;
+            0
+        }
+    };
+
+    let hdr = res.header();
+    hdr.set_num_records(num_records);
+    hdr.common_header.status = status;
+}
+
+/// Appends a record to the response to a multiget() that asked for versions. Refer to
+/// MULTIGET_FLAG_VERSIONS for the layout. Either the entire record is appended, or none of it is.
+///
+/// # Arguments
+///
+/// * `res`:     The response to append the record to.
+/// * `version`: The version of the object.
+/// * `value`:   The object's value, or None if only versions were asked for.
+///
+/// # Return
+///
+/// True if the record was appended. False if it did not fit.
+pub fn append_versioned_record<H, B: ResponseBuf<H>>(
+    res: &mut B,
+    version: u64,
+    value: Option<&[u8]>,
+) -> bool {
+    let version: [u8; 8] = unsafe { transmute(version.to_le()) };
+    match value {
+        Some(value) => {
+            let len: [u8; 4] = unsafe { transmute((value.len() as u32).to_le()) };
+            append_record(res, &[&version, &len, value])
+        }
+
+        None => append_record(res, &[&version]),
+    }
+}
+
+/// Parses the records on the response to a multiget() that asked for versions.
+///
+/// # Arguments
+///
+/// * `payload`: The payload following the MultiGetResponse header.
+/// * `num`:     The number of records on the header.
+/// * `values`:  False if the request had MULTIGET_FLAG_VERSIONS_ONLY set.
+///
+/// # Return
+///
+/// The version of each object, and it's value unless only versions were asked for. None if the
+/// payload does not hold exactly `num` records.
+pub fn parse_versioned_records(
+    payload: &[u8],
+    num: u32,
+    values: bool,
+) -> Option<Vec<(u64, Option<&[u8]>)>> {
+    let mut records = Vec::with_capacity(num as usize);
+    let mut rest = payload;
+    for _ in 0..num {
+        if rest.len() < 8 {
+            return None;
+        }
+
+        let mut version = [0; 8];
+        version.copy_from_slice(&rest[0..8]);
+        let version = u64::from_le(unsafe { transmute(version) });
+        rest = &rest[8..];
+
+        if !values {
+            records.push((version, None));
+            continue;
+        }
+
+        if rest.len() < 4 {
+            return None;
+        }
+
+        let mut len = [0; 4];
+        len.copy_from_slice(&rest[0..4]);
+        let len = u32::from_le(unsafe { transmute(len) }) as usize;
+        if rest.len() < 4 + len {
+            return None;
+        }
+
+        records.push((version, Some(&rest[4..4 + len])));
+        rest = &rest[4 + len..];
+    }
+
+    match rest.is_empty() {
+        true => Some(records),
+        false => None,
+    }
+}
+
+/// Checks that the length on a response to a get() RPC matches the payload it was received with.
+/// Responses to other RPCs are not checked.
+///
+/// # Arguments
+///
+/// * `buf`: The response, starting at the RPC response header.
+///
+/// # Return
+///
+/// False if `buf` is a get() response whose value length does not match it's payload, or whose
+/// status is an error but still carries a payload. True otherwise. Trailers after the payload
+/// are not counted against the length.
+pub fn response_length_ok(buf: &[u8]) -> bool {
+    if buf.len() < size_of::<RpcResponseHeader>() || buf[1] != OpCode::SandstormGetRpc as u8 {
+        return true;
+    }
+
+    let buf = match TrailerReader::split(buf[RESPONSE_FLAGS_OFFSET], buf) {
+        Some((primary, _)) => primary,
+        None => return false,
+    };
+    if buf.len() < size_of::<GetResponse>() {
+        return false;
+    }
+
+    // Wireformat headers are packed, so the pointer does not have to be aligned. The status is
+    // the first byte of the header.
+    let hdr: &GetResponse = unsafe { &*(buf.as_ptr() as *const GetResponse) };
+    let len = hdr.value_length() as usize;
+    let payload = buf.len() - size_of::<GetResponse>();
+
+    match buf[0] == RpcStatus::StatusOk as u8 {
+        true => len == payload,
+        false => len == 0 && payload == 0,
+    }
+}
+
+/// Allocate and populate a packet that requests a server "invoke" operation.
+///
+/// # Panic
+///
+/// May panic if there is a problem allocating the packet or constructing
+/// headers.
+///
+/// # Arguments
+///
+/// * `mac`:      Reference to the MAC header to be added to the request.
+/// * `ip` :      Reference to the IP header to be added to the request.
+/// * `udp`:      Reference to the UDP header to be added to the request.
+/// * `tenant`:   Id of the tenant requesting the invocation.
+/// * `name_len`: Number of bytes at the head of the payload identifying the extension.
+/// * `payload`:  The RPC payload to be written into the packet. Should contain the name of the
+///               extension, followed by it's arguments.
+/// * `id`:       RPC identifier.
+/// * `stamp`:    The time-stamp at which the RPC is being sent out.
+/// * `dst`:      The destination port on the server the RPC is destined for.
+///
+/// # Return
+///
+/// Packet populated with the request parameters.
+#[inline]
+pub fn create_invoke_rpc(
+    mac: &MacHeader,
+    ip: &IpHeader,
+    udp: &UdpHeader,
+    tenant: u32,
+    name_len: u32,
+    payload: &[u8],
+    id: u64,
+    stamp: u64,
+    dst: u16,
+) -> Packet<IpHeader, EmptyMetadata> {
+    create_invoke_rpc_gather(mac, ip, udp, tenant, name_len, &[payload], id, stamp, dst)
+}
+
+/// Allocate and populate a packet that requests a server "invoke" operation, on a payload made
+/// up of several segments, ex: a template holding the extension's name and fixed arguments,
+/// followed by the bytes that vary per request. The segments are written into the packet one
+/// after the other, so the payload never has to be assembled into a single buffer first.
+///
+/// # Panic
+///
+/// May panic if there is a problem allocating the packet or constructing
+/// headers.
+///
+/// # Arguments
+///
+/// * `mac`:      Reference to the MAC header to be added to the request.
+/// * `ip` :      Reference to the IP header to be added to the request.
+/// * `udp`:      Reference to the UDP header to be added to the request.
+/// * `tenant`:   Id of the tenant requesting the invocation.
+/// * `name_len`: Number of bytes at the head of the payload identifying the extension. The name
+///               can span segments.
+/// * `segments`: The segments of the RPC payload, in order. Together, they should contain the
+///               name of the extension followed by it's arguments. Segments can be empty.
+/// * `id`:       RPC identifier.
+/// * `stamp`:    The time-stamp at which the RPC is being sent out.
+/// * `dst`:      The destination port on the server the RPC is destined for.
+///
+/// # Return
+///
+/// Packet populated with the request parameters.
+#[inline]
+pub fn create_invoke_rpc_gather(
+    mac: &MacHeader,
+    ip: &IpHeader,
+    udp: &UdpHeader,
+    tenant: u32,
+    name_len: u32,
+    segments: &[&[u8]],
+    id: u64,
+    stamp: u64,
+    dst: u16,
+) -> Packet<IpHeader, EmptyMetadata> {
+    let len = segments.iter().fold(0, |len, s| len + s.len());
+
+    // The Arguments to the procedure cannot be more that 4 GB long.
+    if len - name_len as usize > u32::max_value() as usize {
+        panic!("Args too long ({} bytes).", len - name_len as usize);
+    }
+
+    // Allocate a packet, write the header and payload into it, and set fields on it's UDP and IP
+    // header. Since the payload contains both, the name and arguments in it, args_len can be
+    // calculated as payload length - name_len.
+    let mut request = create_request(mac, ip, udp, dst)
+        .push_header(&InvokeRequest::new(
+            tenant,
+            name_len,
+            (len - name_len as usize) as u32,
+            id,
+            stamp,
+        ))
+        .expect("Failed to push RPC header into request!");
+
+    if !add_segments(&mut request, segments) {
+        panic!("Failed to write args into invoke() request!");
+    }
+
+    fixup_header_length_fields(request.deparse_header(size_of::<UdpHeader>()))
+}
+
+// This module contains unit tests for packing and unpacking extension listings, drain progress
+// and run counters, and for keeping the length fields on responses consistent with their payloads.
+#[cfg(test)]
+mod tests {
+    use super::{
+        append_kv, append_record, append_versioned_record, encode_audit_page, encode_cache_stats,
+        encode_core_stats, encode_dedup_stats, encode_drain_progress, encode_epochs,
+        encode_ext_latencies, encode_ext_listing, encode_hot_keys, encode_hot_stats,
+        encode_latency_histogram, encode_latency_summaries, encode_run_stats, encode_sizing,
+        encode_write_totals, finish_get_response, finish_multiget_response, parse_audit_page,
+        parse_cache_stats, parse_core_stats, parse_dedup_stats, parse_drain_progress, parse_epochs,
+        parse_ext_latencies, parse_ext_listing, parse_hot_keys, parse_hot_stats, parse_kvs,
+        parse_latency_histogram, parse_latency_summaries, parse_run_stats, parse_sizing,
+        parse_versioned_records, parse_write_totals, response_length_ok, ResponseBuf,
+        AUDIT_ENTRY_LEN, CACHE_STATS_LEN, CORE_STATS_LEN, DEDUP_STATS_LEN, EPOCH_STATS_LEN,
+        HOT_KEYS_DECAY_LEN, HOT_KEY_OVERHEAD, HOT_STATS_LEN, KV_OVERHEAD, LATENCY_BUCKET_LEN,
+        LATENCY_SUMMARY_LEN, SIZING_LEN, WRITE_TOTALS_LEN,
+    };
+
+    use std::mem::size_of;
+    use std::slice;
+
+    use super::super::amplify::{Decay, HotKey, WriteTotals};
+    use super::super::audit::AuditEntry;
+    use super::super::backoff::{CoreSummary, TierStats};
+    use super::super::dedup::DedupStats;
+    use super::super::epoch::EpochStats;
+    use super::super::evict::{CacheStats, CacheTarget, EvictPolicy};
+    use super::super::histogram::{LogHistogram, LOG_BUCKETS};
+    use super::super::hot::HotStats;
+    use super::super::latency::LatencySummary;
+    use super::super::presize::Sizing;
+    use super::super::runs::RunSummary;
+    use super::super::trailer::{Trace, TrailerWriter, TRAILER_TRACE};
+    use super::super::wireformat::*;
+
+    use sandstorm::ext::{ExtensionInfo, Provenance};
+    use sandstorm::schema::{Field, Schema, SCHEMA_VERSION};
+
+    // A response that stands in for a packet with room for `room` bytes of payload. Like an mbuf,
+    // an append that does not fit fails without writing anything.
+    struct Fallible<H> {
+        hdr: H,
+        payload: Vec<u8>,
+        room: usize,
+    }
+
+    impl<H> Fallible<H> {
+        fn new(hdr: H, room: usize) -> Fallible<H> {
+            Fallible {
+                hdr: hdr,
+                payload: Vec::new(),
+                room: room,
+            }
+        }
+
+        // Returns the bytes that would have been sent out, starting at the response header.
+        fn bytes(&self) -> Vec<u8> {
+            let hdr = &self.hdr as *const H as *const u8;
+            let mut buf = unsafe { slice::from_raw_parts(hdr, size_of::<H>()) }.to_vec();
+            buf.extend_from_slice(&self.payload);
+            buf
+        }
+    }
+
+    impl<H> ResponseBuf<H> for Fallible<H> {
+        fn header(&mut self) -> &mut H {
+            &mut self.hdr
+        }
+
+        fn payload_len(&self) -> usize {
+            self.payload.len()
+        }
+
+        fn append(&mut self, data: &[u8]) -> bool {
+            if self.payload.len() + data.len() > self.room {
+                return false;
+            }
+            self.payload.extend_from_slice(data);
+            true
+        }
+
+        fn truncate(&mut self, len: usize) {
+            self.payload.truncate(len);
+        }
+    }
+
+    // Returns a listing of `n` extensions.
+    fn listing(n: usize) -> Vec<ExtensionInfo> {
+        (0..n)
+            .map(|i| ExtensionInfo {
+                name: format!("ext{:02}", i).into_bytes(),
+                version: 0,
+                loaded: 1_500_000_000 + i as u64,
+                invocations: i as u64 * 1000,
+                provenance: match i % 2 {
+                    0 => Provenance::Private,
+                    _ => Provenance::Shared(i as u32 + 0x100),
+                },
+                schema: None,
+                id: i as u32 + 1,
+            }).collect()
+    }
+
+    // Pages through a listing the way a client would, returning the entries and number of pages.
+    fn page_through(list: &[ExtensionInfo], budget: usize) -> (Vec<ExtensionInfo>, usize) {
+        let (mut all, mut pages, mut start) = (Vec::new(), 0, 0);
+        loop {
+            let (buf, num, next) = encode_ext_listing(list, start, budget);
+            assert!(num > 0 || list.is_empty());
+            all.extend(parse_ext_listing(&buf, num).unwrap());
+            pages += 1;
+
+            match next {
+                0 => return (all, pages),
+                next => start = next as usize,
+            }
+        }
+    }
+
+    #[test]
+    fn test_ext_listing_single_page() {
+        let list = listing(5);
+        assert_eq!((list.clone(), 1), page_through(&list, 1024));
+    }
+
+    #[test]
+    fn test_ext_listing_empty() {
+        let (buf, num, next) = encode_ext_listing(&[], 0, 1024);
+        assert_eq!((0, 0, 0), (buf.len(), num, next));
+        assert_eq!(Some(vec![]), parse_ext_listing(&buf, 0));
+    }
+
+    #[test]
+    fn test_ext_listing_paginated() {
+        // Each entry is 38 bytes long, so a budget of 80 fits two per page.
+        let list = listing(7);
+        assert_eq!((list.clone(), 4), page_through(&list, 80));
+    }
+
+    #[test]
+    fn test_ext_listing_entry_over_budget() {
+        // A budget smaller than an entry still returns one entry per page.
+        let list = listing(3);
+        assert_eq!((list.clone(), 3), page_through(&list, 8));
+    }
+
+    #[test]
+    fn test_ext_listing_malformed() {
+        let (buf, num, _) = encode_ext_listing(&listing(2), 0, 1024);
+        assert!(parse_ext_listing(&buf[..buf.len() - 1], num).is_none());
+        assert!(parse_ext_listing(&buf, num + 1).is_none());
+        assert!(parse_ext_listing(&buf, num - 1).is_none());
+
+        // The first entry's provenance is at 27 bytes in, after it's 5 byte name.
+        let mut bad = buf.clone();
+        bad[27] = 2;
+        assert!(parse_ext_listing(&bad, num).is_none());
+    }
+
+    #[test]
+    fn test_ext_listing_schema() {
+        let mut list = listing(3);
+        list[1].schema = Some(Schema::new(vec![Field::Table, Field::Key(1, 32)]).unwrap());
+        list[2].schema = Some(Schema::new(vec![Field::Blob(0, 8)]).unwrap());
+        assert_eq!((list.clone(), 1), page_through(&list, 1024));
+        assert_eq!((list.clone(), 3), page_through(&list, 40));
+
+        // The second entry's layout starts 38 + 38 bytes in, after both names; it's length is
+        // right before it.
+        let (buf, num, _) = encode_ext_listing(&list[..2], 0, 1024);
+        assert_eq!(38 + 38 + 8, buf.len());
+        assert_eq!(&list[1].schema.as_ref().unwrap().encode()[..], &buf[76..]);
+        assert!(parse_ext_listing(&buf[..buf.len() - 1], num).is_none());
+
+        // A layout of an unknown version is malformed, as is one that runs past the entry.
+        let mut bad = buf.clone();
+        bad[76] = SCHEMA_VERSION + 1;
+        assert!(parse_ext_listing(&bad, num).is_none());
+        let mut bad = buf.clone();
+        bad[74] += 1;
+        assert!(parse_ext_listing(&bad, num).is_none());
+    }
+
+    #[test]
+    fn test_drain_progress() {
+        let remaining = vec![0, 7, 0x1234_5678];
+        let buf = encode_drain_progress(&remaining);
+        assert_eq!(12, buf.len());
+        assert_eq!(Some(remaining), parse_drain_progress(&buf, 3));
+        assert_eq!(Some(vec![]), parse_drain_progress(&[], 0));
+
+        assert!(parse_drain_progress(&buf[..11], 3).is_none());
+        assert!(parse_drain_progress(&buf, 2).is_none());
+    }
+
+    #[test]
+    fn test_run_stats() {
+        let runs = vec![
+            RunSummary {
+                run: 0xdead_beef_0000_0001,
+                requests: 100,
+                errors: 3,
+                pushbacks: 7,
+                quarantined: 2,
+            },
+            RunSummary {
+                run: 2,
+                requests: 1,
+                errors: 0,
+                pushbacks: 0,
+                quarantined: 0,
+            },
+        ];
+        let buf = encode_run_stats(&runs);
+        assert_eq!(80, buf.len());
+        assert_eq!(Some(runs), parse_run_stats(&buf, 2));
+        assert_eq!(Some(vec![]), parse_run_stats(&[], 0));
+
+        assert!(parse_run_stats(&buf[..79], 2).is_none());
+        assert!(parse_run_stats(&buf, 1).is_none());
+    }
+
+    #[test]
+    fn test_core_stats() {
+        let cores = vec![
+            CoreSummary {
+                core: 0,
+                tiers: [
+                    TierStats {
+                        pauses: 12,
+                        nanos: 3_000,
+                    },
+                    TierStats {
+                        pauses: 0xdead_beef_0000_0001,
+                        nanos: 1 << 40,
+                    },
+                ],
+            },
+            CoreSummary {
+                core: 7,
+                ..CoreSummary::default()
+            },
+        ];
+        let buf = encode_core_stats(&cores);
+        assert_eq!(2 * CORE_STATS_LEN, buf.len());
+        assert_eq!(Some(cores), parse_core_stats(&buf, 2));
+        assert_eq!(Some(vec![]), parse_core_stats(&[], 0));
+
+        assert!(parse_core_stats(&buf[..CORE_STATS_LEN + 1], 2).is_none());
+        assert!(parse_core_stats(&buf, 1).is_none());
+    }
+
+    #[test]
+    fn test_latency_summaries() {
+        let summary = LatencySummary {
+            count: 0xdead_beef_0000_0001,
+            p50: 1_000,
+            p99: 20_000,
+            p999: 1 << 40,
+        };
+        let ops = vec![(0x01, summary), (0x03, LatencySummary::default())];
+        let buf = encode_latency_summaries(&ops);
+        assert_eq!(2 * LATENCY_SUMMARY_LEN, buf.len());
+        assert_eq!(Some(ops), parse_latency_summaries(&buf, 2));
+        assert!(parse_latency_summaries(&buf, 1).is_none());
+
+        let exts = vec![
+            (b"tao".to_vec(), summary),
+            (vec![b'x'; 300], summary),
+            (b"get".to_vec(), summary),
+        ];
+        let (buf, num) = encode_ext_latencies(&exts, 1024);
+        assert_eq!(3, num);
+        let parsed = parse_ext_latencies(&buf, num).expect("Malformed extensions.");
+        assert_eq!(exts[0], parsed[0]);
+        assert_eq!(255, parsed[1].0.len());
+        assert_eq!(exts[2], parsed[2]);
+        assert!(parse_ext_latencies(&buf[..buf.len() - 1], num).is_none());
+        assert!(parse_ext_latencies(&buf, 2).is_none());
+
+        // Extensions that don't fit the budget are left off.
+        let (buf, num) = encode_ext_latencies(&exts, LATENCY_SUMMARY_LEN + 3);
+        assert_eq!((1, LATENCY_SUMMARY_LEN + 3), (num, buf.len()));
+    }
+
+    #[test]
+    fn test_latency_histogram() {
+        let mut hist = LogHistogram::new();
+        hist.record(0);
+        hist.record(5_000);
+        hist.record(5_001);
+        hist.record(1 << 40);
+        let (buf, num) = encode_latency_histogram(&hist);
+        assert_eq!((3, 3 * LATENCY_BUCKET_LEN), (num, buf.len()));
+        assert_eq!(Some(hist), parse_latency_histogram(&buf, num));
+
+        // Every bucket fits in a single response.
+        assert!(LOG_BUCKETS * LATENCY_BUCKET_LEN <= 1200);
+        assert!(LOG_BUCKETS <= 256);
+
+        let bad = [LOG_BUCKETS as u8, 1, 0, 0, 0, 0, 0, 0, 0];
+        assert!(parse_latency_histogram(&bad, 1).is_none());
+        assert!(parse_latency_histogram(&buf, 2).is_none());
+    }
+
+    #[test]
+    fn test_kvs() {
+        let mut buf = Vec::new();
+        append_kv(&mut buf, b"key1", b"value");
+        append_kv(&mut buf, b"k", &[]);
+        append_kv(&mut buf, &[7; 300], &[9; 70000]);
+        assert_eq!(3 * KV_OVERHEAD + 4 + 5 + 1 + 300 + 70000, buf.len());
+
+        let records = parse_kvs(&buf, 3).unwrap();
+        assert_eq!((&b"key1"[..], &b"value"[..]), records[0]);
+        assert_eq!((&b"k"[..], &b""[..]), records[1]);
+        assert_eq!((300, 70000), (records[2].0.len(), records[2].1.len()));
+        assert_eq!(Some(vec![]), parse_kvs(&[], 0));
+
+        assert!(parse_kvs(&buf[..buf.len() - 1], 3).is_none());
+        assert!(parse_kvs(&buf, 2).is_none());
+        assert!(parse_kvs(&buf, 4).is_none());
+    }
+
+    #[test]
+    fn test_scan_page() {
+        let mut buf = Vec::new();
+        append_kv(&mut buf, b"key1", b"value");
+        append_kv(&mut buf, b"key2", b"");
+        buf.extend_from_slice(b"key2");
+
+        let (records, token) = parse_scan(&buf, 2, 4).unwrap();
+        assert_eq!(
+            vec![(&b"key1"[..], &b"value"[..]), (&b"key2"[..], &b""[..])],
+            records
+        );
+        assert_eq!(b"key2", token);
+        assert_eq!(Some((vec![], &b""[..])), parse_scan(&[], 0, 0));
+
+        assert!(parse_scan(&buf, 2, 3).is_none());
+        assert!(parse_scan(&buf, 2, 0).is_none());
+        assert!(parse_scan(&buf[..4], 0, 5).is_none());
+    }
+
+    #[test]
+    fn test_audit_page() {
+        let entries = vec![
+            AuditEntry {
+                seq: 9,
+                stamp: 0x0123_4567_89ab_cdef,
+                name_hash: !0,
+                id: 77,
+                status: RpcStatus::StatusOk as u8,
+                args_len: 0x0102_0304,
+                res_len: 8,
+                cycles: 12_000,
+            },
+            AuditEntry {
+                seq: 8,
+                status: RpcStatus::StatusInvalidExtension as u8,
+                ..Default::default()
+            },
+        ];
+        let buf = encode_audit_page(&entries);
+        assert_eq!(2 * AUDIT_ENTRY_LEN, buf.len());
+        assert_eq!(Some(entries), parse_audit_page(&buf, 2));
+        assert_eq!(Some(vec![]), parse_audit_page(&[], 0));
+
+        assert!(parse_audit_page(&buf[..buf.len() - 1], 2).is_none());
+        assert!(parse_audit_page(&buf, 1).is_none());
+    }
+
+    // Tests that write totals and hot keys round trip through a write_stats() payload, and that
+    // hot keys past the budget are left off.
+    #[test]
+    fn test_write_stats() {
+        let totals = vec![
+            WriteTotals {
+                count: 3,
+                logical: 0x0102_0304_0506,
+                physical: !0,
+            },
+            WriteTotals::default(),
+        ];
+        let buf = encode_write_totals(&totals);
+        assert_eq!(2 * WRITE_TOTALS_LEN, buf.len());
+        assert_eq!(Some(totals), parse_write_totals(&buf, 2));
+        assert!(parse_write_totals(&buf[..buf.len() - 1], 2).is_none());
+
+        let keys = vec![
+            HotKey {
+                key: vec![7; 300],
+                bytes: 9000,
+                error: 12,
+            },
+            HotKey {
+                key: vec![],
+                bytes: 10,
+                error: 0,
+            },
+            HotKey {
+                key: vec![1, 2],
+                bytes: 5,
+                error: 5,
+            },
+        ];
+        let decay = Decay {
+            interval_ms: 60_000,
+            epochs: 0x0102_0304,
+            elapsed_ms: 59_999,
+        };
+        let (buf, num) = encode_hot_keys(&keys, &decay, 1024);
+        assert_eq!(3, num);
+        assert_eq!(Some((decay, keys.clone())), parse_hot_keys(&buf, num));
+        assert!(parse_hot_keys(&buf[..buf.len() - 1], num).is_none());
+        assert!(parse_hot_keys(&buf, num - 1).is_none());
+        assert!(parse_hot_keys(&buf[..HOT_KEYS_DECAY_LEN - 1], 0).is_none());
+
+        let budget = HOT_KEYS_DECAY_LEN + 2 * HOT_KEY_OVERHEAD + 300 + 1;
+        let (buf, num) = encode_hot_keys(&keys, &decay, budget);
+        assert_eq!(2, num);
+        assert_eq!(Some((decay, keys[..2].to_vec())), parse_hot_keys(&buf, num));
+
+        let (buf, num) = encode_hot_keys(&[], &Decay::default(), 1024);
+        assert_eq!((HOT_KEYS_DECAY_LEN, 0), (buf.len(), num));
+        assert_eq!(Some((Decay::default(), vec![])), parse_hot_keys(&buf, num));
+
+        let stats = DedupStats {
+            hits: 999_999,
+            misses: 1,
+            collisions: 0,
+            evicted: 7,
+            bytes_saved: !0,
+            entries: 1,
+        };
+        let buf = encode_dedup_stats(&stats);
+        assert_eq!(DEDUP_STATS_LEN, buf.len());
+        assert_eq!(Some(stats), parse_dedup_stats(&buf));
+        assert!(parse_dedup_stats(&buf[..buf.len() - 1]).is_none());
+
+        let sizing = Sizing {
+            presized: 1 << 20,
+            capacity: 0x0102_0304_0506,
+            objects: 1,
+            resizes: !0,
+        };
+        let buf = encode_sizing(&sizing);
+        assert_eq!(SIZING_LEN, buf.len());
+        assert_eq!(Some(sizing), parse_sizing(&buf));
+        assert!(parse_sizing(&buf[..buf.len() - 1]).is_none());
+
+        let cache = CacheStats {
+            target: CacheTarget::new(1 << 30, 0, EvictPolicy::Read).unwrap(),
+            bytes: 0x0102_0304_0506,
+            objects: 7,
+            evicted: 1,
+            evicted_bytes: !0,
+        };
+        let buf = encode_cache_stats(&cache);
+        assert_eq!(CACHE_STATS_LEN, buf.len());
+        assert_eq!(Some(cache), parse_cache_stats(&buf));
+        assert!(parse_cache_stats(&buf[..buf.len() - 1]).is_none());
+
+        let hot = HotStats {
+            capacity: 1 << 21,
+            used: 0x0102_0304,
+            promotions: 5,
+            demotions: 3,
+            refused: 1,
+            raced: 2,
+            gets: !0,
+            hits: 7,
+        };
+        let buf = encode_hot_stats(&hot);
+        assert_eq!(HOT_STATS_LEN, buf.len());
+        assert_eq!(Some(hot), parse_hot_stats(&buf));
+        assert!(parse_hot_stats(&buf[..buf.len() - 1]).is_none());
+
+        let epochs = EpochStats {
+            server: 3,
+            tenant: 0x0102_0304_0506,
+            table: !0,
+        };
+        let buf = encode_epochs(&epochs);
+        assert_eq!(EPOCH_STATS_LEN, buf.len());
+        assert_eq!(Some(epochs), parse_epochs(&buf));
+        assert!(parse_epochs(&buf[..buf.len() - 1]).is_none());
+    }
+
+    // Tests that a get() response is either complete and consistent, or an error with an empty
+    // payload, no matter where the appends run out of room.
+    #[test]
+    fn test_get_response_append_failure() {
+        let parts: [&[u8]; 4] = [&[1], &[7; 8], b"key0", &[9; 100]];
+        let full: Vec<u8> = parts.iter().flat_map(|p| p.iter().cloned()).collect();
+
+        for room in 0..full.len() + 2 {
+            let hdr = GetResponse::new(1, 2, OpCode::SandstormGetRpc, 3);
+            let mut res = Fallible::new(hdr, room);
+            let status = match append_record(&mut res, &parts) {
+                true => RpcStatus::StatusOk,
+                false => RpcStatus::StatusInternalError,
+            };
+            finish_get_response(&mut res, status);
+
+            assert!(response_length_ok(&res.bytes()), "room {}", room);
+            let len = res.hdr.value_length() as usize;
+            if room >= full.len() {
+                assert_eq!(RpcStatus::StatusOk, res.hdr.common_header.status);
+                assert_eq!(full, res.payload);
+                assert_eq!(full.len(), len);
+            } else {
+                assert_eq!(RpcStatus::StatusInternalError, res.hdr.common_header.status);
+                assert!(res.payload.is_empty());
+                assert_eq!(0, len);
+            }
+        }
+    }
+
+    // Tests that a failed append leaves the records appended before it in place.
+    #[test]
+    fn test_append_record_rollback() {
+        let hdr = GetResponse::new(1, 2, OpCode::SandstormGetRpc, 3);
+        let mut res = Fallible::new(hdr, 10);
+        assert!(append_record(&mut res, &[b"abc", b"def"]));
+        assert!(!append_record(&mut res, &[b"ghi", b"jkl"]));
+        assert_eq!(b"abcdef".to_vec(), res.payload);
+        assert!(append_record(&mut res, &[b"gh", b"ij"]));
+        assert_eq!(b"abcdefghij".to_vec(), res.payload);
+    }
+
+    // Tests that a multiget() response never reports records it does not carry, no matter where
+    // the appends run out of room.
+    #[test]
+    fn test_multiget_response_append_failure() {
+        let (n, len) = (3, 40);
+        for room in 0..n * len + 2 {
+            let hdr = MultiGetResponse::new(1, 2, OpCode::SandstormMultiGetRpc, 3, 0);
+            let mut res = Fallible::new(hdr, room);
+
+            let mut status = RpcStatus::StatusOk;
+            let mut n_recs = 0;
+            for i in 0..n {
+                if !append_record(&mut res, &[&vec![i as u8; len]]) {
+                    status = RpcStatus::StatusInternalError;
+                    break;
+                }
+                n_recs += 1;
+            }
+            finish_multiget_response(&mut res, status, n_recs);
+
+            let num = res.hdr.num_records() as usize;
+            assert_eq!(num * len, res.payload.len(), "room {}", room);
+            if room < n * len {
+                assert_eq!(RpcStatus::StatusInternalError, res.hdr.common_header.status);
+                assert_eq!(0, num);
+            } else {
+                assert_eq!(n, num);
+            }
+        }
+    }
+
+    // Tests that versioned records parse back into the versions and values appended, that
+    // version-only records carry no value bytes, and that a truncated payload does not parse.
+    #[test]
+    fn test_versioned_records() {
+        let hdr = || MultiGetResponse::new(1, 2, OpCode::SandstormMultiGetRpc, 3, 0);
+        let mut res = Fallible::new(hdr(), 1024);
+        assert!(append_versioned_record(&mut res, 7, Some(&b"abc"[..])));
+        assert!(append_versioned_record(&mut res, 1 << 40, Some(&b""[..])));
+        assert_eq!(
+            Some(vec![(7, Some(&b"abc"[..])), (1 << 40, Some(&b""[..]))]),
+            parse_versioned_records(&res.payload, 2, true)
+        );
+        assert_eq!(None, parse_versioned_records(&res.payload[..26], 2, true));
+        assert_eq!(None, parse_versioned_records(&res.payload, 3, true));
+
+        let mut res = Fallible::new(hdr(), 1024);
+        for version in 1..4 {
+            assert!(append_versioned_record(&mut res, version, None));
+        }
+        assert_eq!(24, res.payload.len());
+        assert_eq!(
+            Some(vec![(1, None), (2, None), (3, None)]),
+            parse_versioned_records(&res.payload, 3, false)
+        );
+        assert_eq!(None, parse_versioned_records(&res.payload[..23], 3, false));
+
+        // A record that does not fit is not appended at all.
+        let mut res = Fallible::new(hdr(), 14);
+        assert!(!append_versioned_record(&mut res, 1, Some(&b"abc"[..])));
+        assert!(res.payload.is_empty());
+    }
+
+    // Tests that a failed multiget() never reports a read as racy.
+    #[test]
+    fn test_multiget_response_racy_reset() {
+        let hdr = MultiGetResponse::new(1, 2, OpCode::SandstormMultiGetRpc, 3, 0);
+        let mut res = Fallible::new(hdr, 1024);
+        assert!(append_versioned_record(&mut res, 1, None));
+        res.hdr.racy = 1;
+        finish_multiget_response(&mut res, RpcStatus::StatusOk, 1);
+        assert_eq!(1, res.hdr.racy as usize);
+
+        finish_multiget_response(&mut res, RpcStatus::StatusInternalError, 1);
+        assert_eq!((0, 0), (res.hdr.racy as usize, res.hdr.num_records() as usize));
+    }
+
+    // Tests the consistency check on received responses.
+    #[test]
+    fn test_response_length_ok() {
+        let response = |status: RpcStatus, len: u32, payload: &[u8]| {
+            let mut hdr = GetResponse::new(1, 2, OpCode::SandstormGetRpc, 3);
+            hdr.common_header.status = status;
+            hdr.set_value_length(len);
+            let mut res = Fallible::new(hdr, payload.len());
+            assert!(res.append(payload));
+            res.bytes()
+        };
+        let check =
+            |status, len, payload: &[u8]| response_length_ok(&response(status, len, payload));
+
+        assert!(check(RpcStatus::StatusOk, 4, b"abcd"));
+        assert!(check(RpcStatus::StatusOk, 0, b""));
+        assert!(!check(RpcStatus::StatusOk, 6, b"abcd"));
+        assert!(!check(RpcStatus::StatusOk, 2, b"abcd"));
+        assert!(check(RpcStatus::StatusObjectDoesNotExist, 0, b""));
+        assert!(!check(RpcStatus::StatusInternalError, 0, b"ab"));
+
+        // Responses to other RPCs are not checked, and a truncated get() response is rejected.
+        let put = PutResponse::new(1, 2, OpCode::SandstormPutRpc, 3);
+        assert!(response_length_ok(&Fallible::new(put, 0).bytes()));
+        let get = response(RpcStatus::StatusOk, 0, b"");
+        assert!(!response_length_ok(&get[..get.len() - 1]));
+
+        // Trailers are not counted against the value length, but must be well formed.
+        let mut trailers = TrailerWriter::new(64);
+        trailers.append(TRAILER_TRACE, &Trace::default().encode());
+        let mut get = response(RpcStatus::StatusOk, 4, b"abcd");
+        get.extend_from_slice(&trailers.finish());
+        assert!(!response_length_ok(&get));
+        get[RESPONSE_FLAGS_OFFSET] |= RESPONSE_FLAG_TRAILERS;
+        assert!(response_length_ok(&get));
+        let len = get.len();
+        get[len - 1] = 0xff;
+        assert!(!response_length_ok(&get));
+    }
+}
@@ -0,0 +1,296 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::cycles;
+use super::wireformat::RpcStatus;
+
+use hashbrown::HashMap;
+use spin::RwLock;
+
+/// The number of runs tracked by default.
+pub const RUN_STATS_CAP: usize = 64;
+
+/// The counters of a single run, as reported by RunStats.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RunSummary {
+    /// The run id clients tagged their requests with.
+    pub run: u64,
+
+    /// The number of requests from the run that completed, including failed ones.
+    pub requests: u64,
+
+    /// The number of requests that completed with a status other than StatusOk or
+    /// StatusPushback.
+    pub errors: u64,
+
+    /// The number of requests that were pushed back to the client.
+    pub pushbacks: u64,
+
+    /// The number of requests that were refused because their tenant was quarantined. These are
+    /// counted as errors too.
+    pub quarantined: u64,
+}
+
+// The counters kept for a run. Every field is updated with relaxed atomics under the map's read
+// lock, so requests from the same run on different cores never serialize on each other.
+struct Counters {
+    requests: AtomicU64,
+    errors: AtomicU64,
+    pushbacks: AtomicU64,
+    quarantined: AtomicU64,
+
+    // The cycle counter when a request from the run last completed. Decides which run is
+    // evicted once the map is full.
+    active: AtomicU64,
+
+    // The cycle counter when a warning was last logged for the run.
+    logged: AtomicU64,
+}
+
+impl Counters {
+    fn new() -> Counters {
+        Counters {
+            requests: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            pushbacks: AtomicU64::new(0),
+            quarantined: AtomicU64::new(0),
+            active: AtomicU64::new(0),
+            logged: AtomicU64::new(0),
+        }
+    }
+
+    // Counts a completed request, logging failures atmost once a second.
+    fn update(&self, run: u64, status: &RpcStatus, now: u64) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        self.active.store(now, Ordering::Relaxed);
+
+        match *status {
+            RpcStatus::StatusOk => {}
+
+            RpcStatus::StatusPushback => {
+                self.pushbacks.fetch_add(1, Ordering::Relaxed);
+            }
+
+            RpcStatus::StatusTenantQuarantined => {
+                self.quarantined.fetch_add(1, Ordering::Relaxed);
+                self.errors.fetch_add(1, Ordering::Relaxed);
+            }
+
+            _ => {
+                let errors = self.errors.fetch_add(1, Ordering::Relaxed) + 1;
+                let last = self.logged.load(Ordering::Relaxed);
+                if (last == 0 || now - last >= cycles::cycles_per_second())
+                    && self.logged.compare_and_swap(last, now, Ordering::Relaxed) == last
+                {
+                    warn!(
+                        "run {:016x}: request failed with {:?} ({} errors so far)",
+                        run, status, errors
+                    );
+                }
+            }
+        }
+    }
+
+    fn summary(&self, run: u64) -> RunSummary {
+        RunSummary {
+            run: run,
+            requests: self.requests.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            pushbacks: self.pushbacks.load(Ordering::Relaxed),
+            quarantined: self.quarantined.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Counts the requests, errors, pushbacks and quarantined requests of each client run, so that an operator can ask
+/// what a particular run did on a server shared by many. Runs are identified by the id clients
+/// tag requests with (see REQUEST_FLAG_RUN); untagged requests are never counted.
+///
+/// Atmost `cap` runs are tracked. Once full, the run that was least recently active is evicted
+/// to make room for a new one, and is no longer queryable.
+pub struct RunStats {
+    // The maximum number of runs tracked at once.
+    cap: usize,
+
+    // Counters of every tracked run, keyed by run id.
+    runs: RwLock<HashMap<u64, Counters>>,
+}
+
+impl RunStats {
+    /// Creates an empty RunStats.
+    ///
+    /// # Arguments
+    ///
+    /// * `cap`: The maximum number of runs tracked at once. 0 means RUN_STATS_CAP.
+    pub fn new(cap: usize) -> RunStats {
+        RunStats {
+            cap: if cap == 0 { RUN_STATS_CAP } else { cap },
+            runs: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Counts a completed request against the run it was tagged with. Tracks the run if it isn't
+    /// already, evicting the least recently active run if need be.
+    ///
+    /// # Arguments
+    ///
+    /// * `run`:    The run id on the request.
+    /// * `status`: The status the request completed with.
+    pub fn record(&self, run: u64, status: &RpcStatus) {
+        let now = cycles::rdtsc();
+
+        // Common case: the run is already tracked.
+        if let Some(counters) = self.runs.read().get(&run) {
+            counters.update(run, status, now);
+            return;
+        }
+
+        let mut runs = self.runs.write();
+        if !runs.contains_key(&run) && runs.len() >= self.cap {
+            let lru = runs
+                .iter()
+                .min_by_key(|&(_, c)| c.active.load(Ordering::Relaxed))
+                .map(|(r, _)| *r);
+            if let Some(lru) = lru {
+                runs.remove(&lru);
+            }
+        }
+
+        runs.entry(run)
+            .or_insert_with(Counters::new)
+            .update(run, status, now);
+    }
+
+    /// Returns the counters of a run, or None if the run isn't tracked.
+    ///
+    /// # Arguments
+    ///
+    /// * `run`: The run id.
+    pub fn get(&self, run: u64) -> Option<RunSummary> {
+        self.runs.read().get(&run).map(|c| c.summary(run))
+    }
+
+    /// Returns the counters of the most recently active runs, most recent first.
+    ///
+    /// # Arguments
+    ///
+    /// * `limit`: The maximum number of runs to return.
+    pub fn recent(&self, limit: usize) -> Vec<RunSummary> {
+        let mut runs: Vec<(u64, RunSummary)> = self
+            .runs
+            .read()
+            .iter()
+            .map(|(run, c)| (c.active.load(Ordering::Relaxed), c.summary(*run)))
+            .collect();
+        runs.sort_by(|a, b| b.0.cmp(&a.0));
+        runs.into_iter().take(limit).map(|(_, s)| s).collect()
+    }
+
+    /// Returns the number of runs being tracked.
+    pub fn len(&self) -> usize {
+        self.runs.read().len()
+    }
+
+    /// Returns true if no run is being tracked.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+// This module contains unit tests for RunStats.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use super::super::cycles::virt;
+
+    // Tests that requests from interleaved runs are counted separately.
+    #[test]
+    fn test_runs_separate() {
+        let stats = RunStats::new(4);
+        for i in 0..100 {
+            stats.record(0xa, &RpcStatus::StatusOk);
+            stats.record(
+                0xb,
+                match i % 10 {
+                    0 => &RpcStatus::StatusPushback,
+                    1 => &RpcStatus::StatusObjectDoesNotExist,
+                    2 => &RpcStatus::StatusTenantQuarantined,
+                    _ => &RpcStatus::StatusOk,
+                },
+            );
+        }
+
+        assert_eq!(2, stats.len());
+        assert_eq!(
+            Some(RunSummary {
+                run: 0xa,
+                requests: 100,
+                errors: 0,
+                pushbacks: 0,
+                quarantined: 0,
+            }),
+            stats.get(0xa)
+        );
+        assert_eq!(
+            Some(RunSummary {
+                run: 0xb,
+                requests: 100,
+                errors: 20,
+                pushbacks: 10,
+                quarantined: 10,
+            }),
+            stats.get(0xb)
+        );
+        assert_eq!(None, stats.get(0xc));
+    }
+
+    // Tests that the least recently active run is evicted once the cap is reached.
+    #[test]
+    fn test_runs_evict_lru() {
+        virt::install(1, 1000000000);
+        let stats = RunStats::new(3);
+        for run in 1..4 {
+            stats.record(run, &RpcStatus::StatusOk);
+            virt::advance(10);
+        }
+
+        // Run 1 is now more recently active than 2 and 3.
+        stats.record(1, &RpcStatus::StatusOk);
+        virt::advance(10);
+        stats.record(4, &RpcStatus::StatusOk);
+        virt::uninstall();
+
+        assert_eq!(3, stats.len());
+        assert_eq!(None, stats.get(2));
+        assert_eq!(2, stats.get(1).unwrap().requests);
+
+        let recent: Vec<u64> = stats.recent(2).iter().map(|s| s.run).collect();
+        assert_eq!(vec![4, 1], recent);
+    }
+
+    // Tests that a cap of 0 falls back to the default.
+    #[test]
+    fn test_runs_default_cap() {
+        let stats = RunStats::new(0);
+        for run in 0..(RUN_STATS_CAP as u64 + 1) {
+            stats.record(run, &RpcStatus::StatusOk);
+        }
+        assert!(!stats.is_empty());
+        assert_eq!(RUN_STATS_CAP, stats.len());
+    }
+}
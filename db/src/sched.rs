@@ -13,12 +13,18 @@
  * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
  */
 
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::collections::VecDeque;
 use std::sync::atomic::{AtomicBool, AtomicIsize, AtomicUsize, Ordering};
+use std::sync::Arc;
 
 use super::cycles;
+use super::defer::Deferred;
+use super::fair::{FairPolicy, RunQueue, TenantDelay};
+use super::quarantine::{self, Quarantines, Signal};
+use super::replay;
 use super::rpc;
+use super::runs::RunStats;
 use super::task::Task;
 use super::task::TaskPriority;
 use super::task::TaskState::*;
@@ -27,15 +33,127 @@ use e2d2::common::EmptyMetadata;
 use e2d2::headers::IpHeader;
 use e2d2::interface::Packet;
 
+use sandstorm::common::TenantId;
+
 use spin::RwLock;
 
 /// The maximum number of tasks the dispatcher can take in one go.
-const MAX_RX_PACKETS: usize = 32;
+pub const MAX_RX_PACKETS: usize = 32;
 
 /// Interval in microsecond which each task can use as credit to perform CPU work.
 /// Under load shedding, the task which used more than this credit will be pushed-back.
 const CREDIT_LIMIT_US: f64 = 0.5f64;
 
+/// The knobs of the pushback policy run by the scheduler after each dispatcher invocation.
+#[derive(Clone, Debug)]
+pub struct PushbackPolicy {
+    /// Whether pushback runs at all. Defaults to whether the "pushback" feature was enabled.
+    pub enabled: bool,
+
+    /// Interval in microseconds which each task can use as credit to perform CPU work. Yielded
+    /// tasks that used more than this credit are candidates for pushback.
+    pub credit_us: f64,
+
+    /// Pushback triggers if two dispatcher invocations are more than this many credits apart.
+    pub trigger_credits: u64,
+
+    /// The number of yielded tasks in the queue, or of new tasks received by the dispatcher,
+    /// at which pushback triggers.
+    pub min_tasks: usize,
+
+    /// A yielded task is pushed back if it's estimated remaining time in microseconds, times
+    /// the number of tasks waiting on the core, exceeds this. 0 disables the trigger.
+    pub depth_product: f64,
+
+    /// A yielded task is pushed back if it's estimated remaining time in microseconds, times
+    /// the moving average of the queueing delay on the core in microseconds, exceeds this. 0
+    /// disables the trigger.
+    pub delay_product: f64,
+}
+
+impl PushbackPolicy {
+    /// Returns true if the policy weighs the remaining time of yielded tasks against the load
+    /// on the core, instead of pushing back every task that ran past it's credit. It does if
+    /// either product threshold is set.
+    pub fn queue_aware(&self) -> bool {
+        self.depth_product > 0.0 || self.delay_product > 0.0
+    }
+
+    /// Decides whether a yielded task is pushed back under a queue-aware policy. A task that
+    /// has long to run is worth pushing back only if many tasks are waiting behind it; on an
+    /// idle core it is left to run to completion, however long it is.
+    ///
+    /// # Arguments
+    ///
+    /// * `remaining_us`: The estimated remaining time of the task in microseconds.
+    /// * `depth`:        The number of tasks waiting on the core.
+    /// * `delay_us`:     The moving average of the queueing delay on the core in microseconds.
+    ///
+    /// # Return
+    ///
+    /// The trigger that fired if the task should be pushed back, None otherwise.
+    pub fn decide(&self, remaining_us: f64, depth: usize, delay_us: f64) -> Option<PushbackReason> {
+        if self.depth_product > 0.0 && remaining_us * depth as f64 > self.depth_product {
+            return Some(PushbackReason::Depth);
+        }
+
+        if self.delay_product > 0.0 && remaining_us * delay_us > self.delay_product {
+            return Some(PushbackReason::Delay);
+        }
+
+        None
+    }
+}
+
+impl Default for PushbackPolicy {
+    fn default() -> PushbackPolicy {
+        PushbackPolicy {
+            enabled: cfg!(feature = "pushback"),
+            credit_us: CREDIT_LIMIT_US,
+            trigger_credits: 2000,
+            min_tasks: MAX_RX_PACKETS / 4,
+            depth_product: 0.0,
+            delay_product: 0.0,
+        }
+    }
+}
+
+/// The trigger a task was pushed back on.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PushbackReason {
+    /// The task ran past it's credit while the scheduler was loaded.
+    Credit = 0,
+
+    /// The task's remaining time times the queue depth exceeded `depth_product`.
+    Depth = 1,
+
+    /// The task's remaining time times the queueing delay exceeded `delay_product`.
+    Delay = 2,
+}
+
+/// The number of tasks a scheduler pushed back, by the trigger they were pushed back on.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Pushbacks {
+    /// Tasks pushed back for running past their credit.
+    pub credit: u64,
+
+    /// Tasks pushed back on the queue depth.
+    pub depth: u64,
+
+    /// Tasks pushed back on the queueing delay.
+    pub delay: u64,
+}
+
+impl Pushbacks {
+    /// Returns the number of tasks pushed back on any trigger.
+    pub fn total(&self) -> u64 {
+        self.credit + self.depth + self.delay
+    }
+}
+
+// A new sample is weighed this much in the moving average of the queueing delay on a core.
+const DELAY_EWMA_ALPHA: f64 = 0.125;
+
 /// A simple round robin scheduler for Tasks in Sandstorm.
 pub struct RoundRobin {
     // The time-stamp at which the scheduler last ran. Required to identify whether there is an
@@ -46,6 +164,18 @@ pub struct RoundRobin {
     // scheduler. If true, the scheduler must return down to Netbricks on the next call to poll().
     compromised: AtomicBool,
 
+    // Atomic flag indicating whether the server is draining. If true, the scheduler stops once
+    // the dispatcher is the only task left and every response has been sent out.
+    draining: AtomicBool,
+
+    // Atomic flag indicating that the scheduler has drained. If true, the scheduler must return
+    // down to Netbricks on every call to poll().
+    drained: AtomicBool,
+
+    // The number of tasks other than the dispatcher that were waiting to run the last time the
+    // dispatcher ran during a drain.
+    pending: AtomicUsize,
+
     // Identifier of the thread this scheduler is running on. Required for pre-emption.
     thread: AtomicUsize,
 
@@ -53,16 +183,41 @@ pub struct RoundRobin {
     core: AtomicIsize,
 
     // Run-queue of tasks waiting to execute. Tasks on this queue have either yielded, or have been
-    // recently enqueued and never run before.
-    waiting: RwLock<VecDeque<Box<Task>>>,
+    // recently enqueued and never run before. Shared between tenants as set by with_fairness().
+    waiting: RwLock<RunQueue>,
 
     // Response packets returned by completed tasks. Will be picked up and sent out the network by
     // the Dispatch task.
     responses: RwLock<Vec<Packet<IpHeader, EmptyMetadata>>>,
 
+    // Work deferred by completed tasks. Will be picked up and enqueued by the Dispatch task once
+    // the responses ahead of it have been sent out.
+    deferred: RwLock<Vec<Deferred>>,
+
+    // Failures of requests that completed on this scheduler, along with the tenant that sent
+    // each. Will be picked up and counted against the tenants by the Dispatch task.
+    signals: RwLock<Vec<(TenantId, Signal)>>,
+
     // task_completed is incremented after the completion of each task. Reset to zero
     // after every 1M tasks.
     task_completed: RefCell<u64>,
+
+    // The pushback policy run after each dispatcher invocation.
+    policy: PushbackPolicy,
+
+    // Moving average of the time in cycles tasks wait on the run-queue before they first run.
+    delay: Cell<f64>,
+
+    // The number of tasks pushed back, indexed by PushbackReason.
+    pushed: [AtomicUsize; 3],
+
+    // Counters of each client run. Requests tagged with a run id are counted on these as they
+    // complete. None if runs are not being tracked.
+    runs: Option<Arc<RunStats>>,
+
+    // The tenants' quarantines. Requests that failed are reported to the Dispatch task as they
+    // complete. None if tenants are never quarantined automatically.
+    quarantines: Option<Arc<Quarantines>>,
 }
 
 // Implementation of methods on RoundRobin.
@@ -75,26 +230,89 @@ impl RoundRobin {
     /// * `thread`: Identifier of the thread this scheduler will run on.
     /// * `core`:   Identifier of the core this scheduler will run on.
     pub fn new(thread: u64, core: i32) -> RoundRobin {
+        RoundRobin::with_policy(thread, core, PushbackPolicy::default())
+    }
+
+    /// Creates and returns a round-robin scheduler that runs a particular pushback policy.
+    ///
+    /// # Arguments
+    ///
+    /// * `thread`: Identifier of the thread this scheduler will run on.
+    /// * `core`:   Identifier of the core this scheduler will run on.
+    /// * `policy`: The pushback policy to run after each dispatcher invocation.
+    pub fn with_policy(thread: u64, core: i32, policy: PushbackPolicy) -> RoundRobin {
         RoundRobin {
             latest: AtomicUsize::new(cycles::rdtsc() as usize),
             compromised: AtomicBool::new(false),
+            draining: AtomicBool::new(false),
+            drained: AtomicBool::new(false),
+            pending: AtomicUsize::new(0),
             thread: AtomicUsize::new(thread as usize),
             core: AtomicIsize::new(core as isize),
-            waiting: RwLock::new(VecDeque::new()),
+            waiting: RwLock::new(RunQueue::new(FairPolicy::default())),
             responses: RwLock::new(Vec::new()),
+            deferred: RwLock::new(Vec::new()),
+            signals: RwLock::new(Vec::new()),
             task_completed: RefCell::new(0),
+            policy: policy,
+            delay: Cell::new(0.0),
+            pushed: [
+                AtomicUsize::new(0),
+                AtomicUsize::new(0),
+                AtomicUsize::new(0),
+            ],
+            runs: None,
+            quarantines: None,
         }
     }
 
+    /// Counts requests that complete on this scheduler against the client run they were tagged
+    /// with. Requests that are not tagged are never counted.
+    ///
+    /// # Arguments
+    ///
+    /// * `runs`: The counters of each client run, usually shared with Master.
+    pub fn with_runs(mut self, runs: Arc<RunStats>) -> RoundRobin {
+        self.runs = Some(runs);
+        self
+    }
+
+    /// Reports requests that fail on this scheduler through signals(), so that the tenants that
+    /// keep sending them can be quarantined. Does nothing if tenants are never quarantined
+    /// automatically.
+    ///
+    /// # Arguments
+    ///
+    /// * `quarantines`: The tenants' quarantines, usually shared with Master.
+    pub fn with_quarantines(mut self, quarantines: Arc<Quarantines>) -> RoundRobin {
+        if quarantines.enabled() {
+            self.quarantines = Some(quarantines);
+        }
+        self
+    }
+
+    /// Shares the core between tenants by deficit round-robin, instead of running tasks first
+    /// come first served. Must be called before any task is enqueued.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy`: The quantum, and the share of each tenant. Refer to fair::RunQueue.
+    pub fn with_fairness(mut self, policy: FairPolicy) -> RoundRobin {
+        self.waiting = RwLock::new(RunQueue::new(policy));
+        self
+    }
+
     /// Enqueues a task onto the scheduler. The task is enqueued at the end of the schedulers
-    /// queue.
+    /// queue, or of it's tenant's queue.
     ///
     /// # Arguments
     ///
     /// * `task`: The task to be added to the scheduler. Must implement the `Task` trait.
     #[inline]
-    pub fn enqueue(&self, task: Box<Task>) {
-        self.waiting.write().push_back(task);
+    pub fn enqueue(&self, mut task: Box<Task>) {
+        let now = cycles::rdtsc();
+        task.set_enqueued(now);
+        self.waiting.write().push_back(task, now);
     }
 
     /// Enqueues multiple tasks onto the scheduler.
@@ -105,7 +323,12 @@ impl RoundRobin {
     ///            order that they are provided in, and must implement the `Task` trait.
     #[inline]
     pub fn enqueue_many(&self, mut tasks: VecDeque<Box<Task>>) {
-        self.waiting.write().append(&mut tasks);
+        let now = cycles::rdtsc();
+        let mut waiting = self.waiting.write();
+        for mut task in tasks.drain(..) {
+            task.set_enqueued(now);
+            waiting.push_back(task, now);
+        }
     }
 
     /// Dequeues all waiting tasks from the scheduler.
@@ -117,8 +340,7 @@ impl RoundRobin {
     /// before. If there are no tasks waiting to run, then an empty vector is returned.
     #[inline]
     pub fn dequeue_all(&self) -> VecDeque<Box<Task>> {
-        let mut tasks = self.waiting.write();
-        return tasks.drain(..).collect();
+        self.waiting.write().drain()
     }
 
     /// Returns a list of pending response packets.
@@ -134,6 +356,24 @@ impl RoundRobin {
         return responses.drain(..).collect();
     }
 
+    /// Returns the work deferred by tasks that completed since this method was last called.
+    ///
+    /// # Return
+    ///
+    /// The deferred work, in the order the tasks completed. The responses of those tasks are
+    /// returned by responses(), and should be sent out before the work is enqueued.
+    pub fn deferred(&self) -> Vec<Deferred> {
+        let mut deferred = self.deferred.write();
+        return deferred.drain(..).collect();
+    }
+
+    /// Returns the failures of requests that completed since this method was last called, along
+    /// with the tenant that sent each. Always empty unless with_quarantines() was called.
+    pub fn signals(&self) -> Vec<(TenantId, Signal)> {
+        let mut signals = self.signals.write();
+        return signals.drain(..).collect();
+    }
+
     /// Appends a list of responses to the scheduler.
     ///
     /// # Arguments
@@ -143,6 +383,16 @@ impl RoundRobin {
         self.responses.write().append(resps);
     }
 
+    /// Appends work deferred by tasks that completed on another scheduler. It is handed out by
+    /// deferred() like work deferred by tasks that completed on this one.
+    ///
+    /// # Arguments
+    ///
+    /// * `work`: The deferred work, in the order it should be enqueued.
+    pub fn append_deferred(&self, work: Vec<Deferred>) {
+        self.deferred.write().extend(work);
+    }
+
     /// Returns the time-stamp at which the latest scheduling decision was made.
     #[inline]
     pub fn latest(&self) -> u64 {
@@ -155,6 +405,43 @@ impl RoundRobin {
         self.compromised.store(true, Ordering::Relaxed);
     }
 
+    /// Starts draining the scheduler. Tasks already on the scheduler continue to run; once only
+    /// the dispatcher is left and every response has been sent out, poll() returns.
+    #[inline]
+    pub fn drain(&self) {
+        self.draining.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns true if the scheduler is draining.
+    #[inline]
+    pub fn draining(&self) -> bool {
+        self.draining.load(Ordering::Relaxed)
+    }
+
+    /// Returns true if the scheduler has drained and stopped running tasks.
+    #[inline]
+    pub fn drained(&self) -> bool {
+        self.drained.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of tasks that were yet to complete the last time the dispatcher ran
+    /// during a drain. Zero if the scheduler is not draining.
+    #[inline]
+    pub fn pending(&self) -> usize {
+        self.pending.load(Ordering::Relaxed)
+    }
+
+    /// Returns true if there is nothing for the scheduler to do besides run the dispatcher: no
+    /// other task is waiting to run, every response has been sent out, and all deferred work has
+    /// been enqueued. Only meaningful when called by the dispatcher, which is off the run-queue
+    /// while it runs.
+    #[inline]
+    pub fn idle(&self) -> bool {
+        self.waiting.read().len() == 0
+            && self.responses.read().is_empty()
+            && self.deferred.read().is_empty()
+    }
+
     /// Returns the identifier of the thread this scheduler was configured to run on.
     #[inline]
     pub fn thread(&self) -> u64 {
@@ -167,28 +454,85 @@ impl RoundRobin {
         self.core.load(Ordering::Relaxed) as i32
     }
 
+    /// Returns the moving average of the time, in cycles, tasks wait on this scheduler's
+    /// run-queue before they first run.
+    #[inline]
+    pub fn queueing_delay(&self) -> u64 {
+        self.delay.get() as u64
+    }
+
+    /// Returns the number of tasks this scheduler pushed back, by trigger.
+    pub fn pushbacks(&self) -> Pushbacks {
+        Pushbacks {
+            credit: self.pushed[PushbackReason::Credit as usize].load(Ordering::Relaxed) as u64,
+            depth: self.pushed[PushbackReason::Depth as usize].load(Ordering::Relaxed) as u64,
+            delay: self.pushed[PushbackReason::Delay as usize].load(Ordering::Relaxed) as u64,
+        }
+    }
+
+    /// Returns how long each tenant's requests waited on this scheduler's run-queue before they
+    /// were picked to run, ordered by tenant. Each time a yielded request is picked again counts.
+    pub fn tenant_delays(&self) -> Vec<(TenantId, TenantDelay)> {
+        self.waiting.read().delays()
+    }
+
+    // Runs the pushback policy over the tasks that have yielded, which are the tasks that were
+    // waiting when the dispatcher last ran; the ones it enqueued since have never run. Tasks the
+    // decision picks are stopped and their responses queued up; the rest stay where they were.
+    // Tasks that have streamed back part of their response are never pushed back.
+    fn push_back<F>(&self, decide: F)
+    where
+        F: Fn(&Task) -> Option<PushbackReason>,
+    {
+        let stopped = self.waiting.write().extract(|task| {
+            match task.state() == YIELDED
+                && task.priority() != TaskPriority::BACKGROUND
+                && !task.streamed()
+            {
+                true => decide(task),
+                false => None,
+            }
+        });
+
+        for (reason, mut yeilded_task) in stopped.into_iter() {
+            self.pushed[reason as usize].fetch_add(1, Ordering::Relaxed);
+            yeilded_task.set_state(STOPPED);
+            if let Some((req, res)) = unsafe { yeilded_task.tear() } {
+                if let Some(ref runs) = self.runs {
+                    rpc::record_run(runs, &req, &res);
+                }
+                replay::complete(&req, &res);
+                let res = rpc::fixup_completed(&req, res);
+                req.free_packet();
+                self.responses.write().push(res);
+            }
+        }
+    }
+
     /// Picks up a task from the waiting queue, and runs it until it either yields or completes.
     pub fn poll(&self) {
         let mut total_time: u64 = 0;
         let mut db_time: u64 = 0;
-        let credit = (CREDIT_LIMIT_US / 1000000f64) * (cycles::cycles_per_second() as f64);
+        let cycles_per_us = cycles::cycles_per_second() as f64 / 1e6;
+        let credit = self.policy.credit_us * cycles_per_us;
+        let min_tasks = self.policy.min_tasks;
 
         // XXX: Trigger Pushback if the two dispatcher invocation is 20 us apart.
-        let time_trigger: u64 = 2000 * credit as u64;
+        let time_trigger: u64 = self.policy.trigger_credits * credit as u64;
         let mut previous: u64 = 0;
         loop {
             // Set the time-stamp of the latest scheduling decision.
             let current = cycles::rdtsc();
             self.latest.store(current as usize, Ordering::Relaxed);
 
-            // If the compromised flag was set, then return.
-            if self.compromised.load(Ordering::Relaxed) {
+            // If the compromised or drained flag was set, then return.
+            if self.compromised.load(Ordering::Relaxed) || self.drained.load(Ordering::Relaxed) {
                 return;
             }
 
-            // If there are tasks to run, then pick one from the head of the queue, and run it until it
-            // either completes or yields back.
-            let task = self.waiting.write().pop_front();
+            // If there are tasks to run, then pick the next one, and run it until it either
+            // completes or yields back.
+            let task = self.waiting.write().pop_front(current);
 
             if let Some(mut task) = task {
                 let mut is_dispatcher: bool = false;
@@ -199,23 +543,61 @@ impl RoundRobin {
                         is_dispatcher = true;
                         queue_length = self.waiting.read().len();
 
+                        // The length of the run-queue is a cheap measure of load on this core.
+                        // Responses sent out from here on will carry it back to clients.
+                        rpc::set_core_load(queue_length);
+
                         // The time difference include the dispatcher time to account the native
                         // operations.
                         difference = current - previous;
                         previous = current;
                     }
 
-                    _ => {}
+                    _ => {
+                        // Sample the time the task waited on the queue before it's first run.
+                        if task.state() == INITIALIZED {
+                            if let Some(enqueued) = task.enqueued() {
+                                let sample = current.saturating_sub(enqueued) as f64;
+                                let delay = self.delay.get();
+                                self.delay.set(delay + DELAY_EWMA_ALPHA * (sample - delay));
+                            }
+                        }
+                    }
                 }
 
-                if task.run().0 == COMPLETED {
+                let (state, ran) = task.run();
+                self.waiting.write().charge(&*task, ran);
+
+                // Send out whatever the task streamed back while it ran, ahead of it's final
+                // response.
+                let partials = task.partials();
+                if !partials.is_empty() {
+                    let mut responses = self.responses.write();
+                    for res in partials.into_iter() {
+                        responses.push(rpc::fixup_response(res));
+                    }
+                }
+
+                if state == COMPLETED {
                     // The task finished execution, check for request and response packets. If they
                     // exist, then free the request packet, and enqueue the response packet.
                     if let Some((req, res)) = unsafe { task.tear() } {
+                        if let Some(ref runs) = self.runs {
+                            rpc::record_run(runs, &req, &res);
+                        }
+                        if self.quarantines.is_some() {
+                            if let Some(signal) = quarantine::observe(&req, &res) {
+                                self.signals.write().push(signal);
+                            }
+                        }
+                        replay::complete(&req, &res);
+                        let res = rpc::fixup_completed(&req, res);
                         req.free_packet();
-                        self.responses
-                            .write()
-                            .push(rpc::fixup_header_length_fields(res));
+                        self.responses.write().push(res);
+                    }
+                    let deferred = task.deferred();
+                    if !deferred.is_empty() {
+                        self.deferred.write().extend(deferred);
                     }
                     if cfg!(feature = "execution") {
                         total_time += task.time();
@@ -230,40 +612,78 @@ impl RoundRobin {
                             db_time = 0;
                         }
                     }
+                    task.recycle();
                 } else {
                     // The task did not complete execution. EITHER add it back to the waiting list so that it
                     // gets to run again OR run the pushback mechanism. The pushback starts only after that
-                    // dispatcher task execution. Trigger pushback:-
+                    // dispatcher task execution.
                     //
-                    // if there are MAX_RX_PACKETS /4 yeilded tasks in the queue, OR
-                    // if two dispatcher invocations are 2000 us apart, AND
-                    // if the current dispatcher invocation received MAX_RX_PACKETS /4 new tasks.
-                    if cfg!(feature = "pushback")
-                        && is_dispatcher == true
-                        && (queue_length >= MAX_RX_PACKETS / 4 || difference > time_trigger)
-                        && ((self.waiting.read().len() - queue_length) >= MAX_RX_PACKETS / 4)
-                    {
-                        for _i in 0..queue_length {
-                            let mut yeilded_task = self.waiting.write().pop_front().unwrap();
-
+                    // A queue-aware policy weighs the estimated remaining time of each yielded task against
+                    // the load on the core, so that long tasks are pushed back off busy cores but run to
+                    // completion on idle ones. Otherwise, trigger pushback:-
+                    //
+                    // if there are min_tasks yeilded tasks in the queue, OR
+                    // if two dispatcher invocations are trigger_credits apart, AND
+                    // if the current dispatcher invocation received min_tasks new tasks.
+                    if self.policy.enabled && is_dispatcher == true {
+                        if self.policy.queue_aware() {
+                            let depth = self.waiting.read().len();
+                            let delay_us = self.delay.get() / cycles_per_us;
+                            self.push_back(|task| {
+                                task.remaining().and_then(|remaining| {
+                                    let remaining_us = remaining as f64 / cycles_per_us;
+                                    self.policy.decide(remaining_us, depth, delay_us)
+                                })
+                            });
+                        } else if (queue_length >= min_tasks || difference > time_trigger)
+                            && ((self.waiting.read().len() - queue_length) >= min_tasks)
+                        {
                             // Compute Ranking/Credit on the go for each task to pushback
                             // some of the tasks whose rank/credit is more than the threshold.
-                            if (yeilded_task.state() == YIELDED)
-                                && ((yeilded_task.time() - yeilded_task.db_time()) > credit as u64)
-                            {
-                                yeilded_task.set_state(STOPPED);
-                                if let Some((req, res)) = unsafe { yeilded_task.tear() } {
-                                    req.free_packet();
-                                    self.responses
-                                        .write()
-                                        .push(rpc::fixup_header_length_fields(res));
+                            self.push_back(|task| {
+                                match (task.time() - task.db_time()) > credit as u64 {
+                                    true => Some(PushbackReason::Credit),
+                                    false => None,
                                 }
-                            } else {
-                                self.waiting.write().push_front(yeilded_task);
+                            });
+                        }
+                    }
+                    self.waiting.write().push_back(task, cycles::rdtsc());
+
+                    // If draining, then stop once the dispatcher has sent out every response and
+                    // is the only task left.
+                    if is_dispatcher && self.draining.load(Ordering::Relaxed) {
+                        let pending = self.waiting.read().len() - 1;
+                        self.pending.store(pending, Ordering::Relaxed);
+                        if pending == 0
+                            && self.responses.read().is_empty()
+                            && self.deferred.read().is_empty()
+                        {
+                            let replayed = replay::stats();
+                            if replayed.executed > 0 {
+                                info!("Replay tables on core {}: {:?}", self.core(), replayed);
+                            }
+
+                            let pushed = self.pushbacks();
+                            if pushed.total() > 0 {
+                                info!("Pushbacks on core {}: {:?}", self.core(), pushed);
+                            }
+
+                            for (tenant, delay) in self.tenant_delays() {
+                                info!(
+                                    "Tenant {} on core {}: {} picks, mean delay {} max {} cycles",
+                                    tenant,
+                                    self.core(),
+                                    delay.picks,
+                                    delay.mean(),
+                                    delay.max
+                                );
                             }
+
+                            self.drained.store(true, Ordering::Relaxed);
+                            return;
                         }
                     }
-                    self.waiting.write().push_back(task);
                 }
             }
         }
@@ -0,0 +1,1326 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use std::cell::{Cell, RefCell};
+use std::mem;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use super::cycles;
+use super::cycles::virt;
+use super::defer::{Deferrals, Deferred, DEFAULT_MAX_DEFERRED};
+use super::drain::Drain;
+use super::fair::{FairPolicy, TenantDelay};
+use super::native::Native;
+use super::runs::RunStats;
+use super::sched::{PushbackPolicy, Pushbacks, RoundRobin, MAX_RX_PACKETS};
+use super::task::TaskState::*;
+use super::task::{Task, TaskPriority, TaskState};
+use super::wireformat::RpcStatus;
+
+use e2d2::common::EmptyMetadata;
+use e2d2::headers::UdpHeader;
+use e2d2::interface::Packet;
+
+use rand::{Rng, SeedableRng, XorShiftRng};
+
+use hashbrown::HashMap;
+
+use sandstorm::common::TenantId;
+use sandstorm::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+/// The time-stamp, in virtual cycles, every simulation starts at.
+const START: u64 = 1;
+
+/// The amount of CPU work each simulated request performs on the server.
+#[derive(Clone, Debug)]
+pub enum Service {
+    /// Every request runs for a single slice whose length is exponentially distributed with
+    /// the given mean in microseconds.
+    Exponential(f64),
+
+    /// A fraction `long` of requests run for `slices` slices of `slice_us` microseconds each,
+    /// yielding between slices. The remaining requests run for a single slice of `short_us`.
+    /// Long and short requests invoke different extensions, so that each has it's own cost.
+    Bimodal {
+        long: f64,
+        short_us: f64,
+        slices: usize,
+        slice_us: f64,
+    },
+}
+
+impl Service {
+    /// Returns the mean amount of CPU work per request in microseconds.
+    pub fn mean_us(&self) -> f64 {
+        match *self {
+            Service::Exponential(mean) => mean,
+            Service::Bimodal {
+                long,
+                short_us,
+                slices,
+                slice_us,
+            } => long * (slices as f64) * slice_us + (1.0 - long) * short_us,
+        }
+    }
+
+    // Samples the extension a single request invokes, and it's slices in cycles. Long requests
+    // invoke extension 1, every other request extension 0.
+    fn sample(&self, rng: &mut XorShiftRng, cycles_per_us: f64) -> (usize, Vec<u64>) {
+        match *self {
+            Service::Exponential(mean) => {
+                (0, vec![(exponential(rng, mean) * cycles_per_us) as u64])
+            }
+
+            Service::Bimodal {
+                long,
+                short_us,
+                slices,
+                slice_us,
+            } => {
+                if rng.next_f64() < long {
+                    (1, vec![(slice_us * cycles_per_us) as u64; slices])
+                } else {
+                    (0, vec![(short_us * cycles_per_us) as u64])
+                }
+            }
+        }
+    }
+}
+
+/// Models the client that pushed-back requests are shipped to.
+#[derive(Clone, Debug)]
+pub struct Client {
+    /// The speed at which the client executes pushed-back work relative to the server. A value
+    /// of 0.5 means the client takes twice as long as the server would have.
+    pub speed: f64,
+
+    /// The round-trip delay between the client and server in microseconds. Every request pays
+    /// it once; pushed-back requests pay it once more to fetch their read set.
+    pub rtt_us: f64,
+}
+
+/// The configuration of a single simulation run.
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// The frequency of the virtual clock in cycles per second.
+    pub hz: u64,
+
+    /// The number of requests issued during the run.
+    pub requests: usize,
+
+    /// The offered load as a fraction of the server's capacity. Requests arrive as a Poisson
+    /// process whose rate is `load` divided by the mean service time.
+    pub load: f64,
+
+    /// The amount of CPU work each request performs.
+    pub service: Service,
+
+    /// The number of microseconds each dispatcher invocation consumes.
+    pub dispatch_us: f64,
+
+    /// The client that pushed-back requests are shipped to.
+    pub client: Client,
+
+    /// The pushback policy run by the scheduler.
+    pub policy: PushbackPolicy,
+
+    /// Seed for the arrival process and service times.
+    pub seed: u32,
+
+    /// If set, the server starts draining once this many requests have been handed to the
+    /// scheduler. Requests arriving after that are rejected.
+    pub drain_after: Option<usize>,
+
+    /// Run ids requests are tagged with, assigned round-robin so that runs interleave. Requests
+    /// are not tagged if empty.
+    pub run_ids: Vec<u64>,
+
+    /// The maximum number of runs whose counters are tracked. 0 means runs::RUN_STATS_CAP.
+    pub run_cap: usize,
+
+    /// The tenants requests are issued by, each with it's own offered load as a fraction of the
+    /// server's capacity. Requests are split between tenants in proportion to their load, and
+    /// each tenant's arrive as a Poisson process of their own. If empty, requests are not
+    /// tagged with a tenant and arrive at `load`.
+    pub tenants: Vec<(TenantId, f64)>,
+
+    /// How the scheduler shares the core between tenants.
+    pub fairness: FairPolicy,
+
+    /// If non-zero, every request that completes on the server defers a single slice of this
+    /// many microseconds of work, run in the background once it's response has been sent out.
+    pub deferred_us: f64,
+
+    /// The most deferred work each tenant can have queued or running; see defer::Deferrals.
+    /// 0 means defer::DEFAULT_MAX_DEFERRED.
+    pub deferred_cap: usize,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            hz: 1000000000,
+            requests: 100000,
+            load: 0.5,
+            service: Service::Exponential(1.0),
+            dispatch_us: 0.0,
+            client: Client {
+                speed: 1.0,
+                rtt_us: 0.0,
+            },
+            policy: PushbackPolicy::default(),
+            seed: 1,
+            drain_after: None,
+            run_ids: Vec::new(),
+            run_cap: 0,
+            tenants: Vec::new(),
+            fairness: FairPolicy::default(),
+            deferred_us: 0.0,
+            deferred_cap: 0,
+        }
+    }
+}
+
+/// The measured outcome of a simulation run.
+pub struct Results {
+    // Completion latencies of all requests in cycles, sorted in ascending order.
+    latencies: Vec<u64>,
+
+    // The frequency of the virtual clock the run was simulated with.
+    hz: u64,
+
+    /// The fraction of simulated time the server core spent running requests.
+    pub utilization: f64,
+
+    /// The fraction of requests that were pushed back to the client.
+    pub pushback_rate: f64,
+
+    /// The number of requests that were pushed back, by trigger.
+    pub pushbacks: Pushbacks,
+
+    /// The number of requests that were on the scheduler when the drain started.
+    pub in_flight: usize,
+
+    /// The number of requests that were rejected because the server was draining.
+    pub rejected: usize,
+
+    /// Counters of every run requests were tagged with.
+    pub runs: RunStats,
+
+    /// How long each tenant's requests waited on the scheduler's run-queue, ordered by tenant.
+    pub tenant_delays: Vec<(TenantId, TenantDelay)>,
+
+    // Completion latencies in cycles of requests tagged with a tenant, along with the tenant.
+    tenant_latencies: Vec<(TenantId, u64)>,
+
+    /// The tenant and arguments of every deferred invocation that ran, in the order they
+    /// completed.
+    pub deferred: Vec<(TenantId, Vec<u8>)>,
+
+    /// The number of deferred invocations dropped because their tenant was over the cap.
+    pub deferred_dropped: usize,
+}
+
+impl Results {
+    /// Returns the mean completion latency in microseconds.
+    pub fn mean_us(&self) -> f64 {
+        let sum: u64 = self.latencies.iter().sum();
+        self.to_us(sum) / self.latencies.len() as f64
+    }
+
+    /// Returns the mean completion latency of a tenant's requests in microseconds.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`: The tenant, as configured in `tenants`.
+    pub fn tenant_mean_us(&self, tenant: TenantId) -> f64 {
+        let (count, sum) = self
+            .tenant_latencies
+            .iter()
+            .filter(|&&(t, _)| t == tenant)
+            .fold((0, 0), |(count, sum), &(_, latency)| {
+                (count + 1, sum + latency)
+            });
+        self.to_us(sum) / count as f64
+    }
+
+    /// Returns the mean time in microseconds a tenant's requests waited on the run-queue each
+    /// time they were picked to run.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`: The tenant, as configured in `tenants`.
+    pub fn tenant_delay_us(&self, tenant: TenantId) -> f64 {
+        self.tenant_delays
+            .iter()
+            .find(|&&(t, _)| t == tenant)
+            .map_or(0.0, |&(_, ref delay)| self.to_us(delay.mean()))
+    }
+
+    /// Returns a percentile of the completion latency distribution in microseconds.
+    ///
+    /// # Arguments
+    ///
+    /// * `p`: The percentile, between 0 and 100.
+    pub fn percentile_us(&self, p: f64) -> f64 {
+        let idx = ((self.latencies.len() - 1) as f64 * p / 100.0) as usize;
+        self.to_us(self.latencies[idx])
+    }
+
+    /// Prints the outcome of the run on a single line.
+    ///
+    /// # Arguments
+    ///
+    /// * `label`: Identifies the run, usually the policy it was configured with.
+    pub fn print(&self, label: &str) {
+        println!(
+            "{} mean {:.2} median {:.2} p99 {:.2} utilization {:.3} pushback {:.4}",
+            label,
+            self.mean_us(),
+            self.percentile_us(50.0),
+            self.percentile_us(99.0),
+            self.utilization,
+            self.pushback_rate
+        );
+    }
+
+    // Converts cycles on the run's virtual clock to microseconds.
+    fn to_us(&self, cycles: u64) -> f64 {
+        cycles as f64 * 1e6 / self.hz as f64
+    }
+}
+
+// Everything a run records as requests finish, shared between the dispatcher and requests.
+struct Recorder {
+    // Completion latencies in cycles, and those of requests tagged with a tenant again, along
+    // with the tenant.
+    latencies: Vec<u64>,
+    tenant_latencies: Vec<(TenantId, u64)>,
+
+    // Cycles the server spent running requests.
+    busy: u64,
+
+    // The number of requests that were pushed back.
+    pushed: usize,
+
+    // The number of requests on the scheduler when the drain started, and the number of
+    // requests rejected after that.
+    in_flight: usize,
+    rejected: usize,
+
+    // The client model, with its round-trip delay converted to cycles.
+    speed: f64,
+    rtt: u64,
+
+    // Moving average of the cycles requests to each extension ran for, over requests that
+    // completed on the server, exactly as an Extension keeps it. 0 until one completes.
+    costs: [u64; 2],
+
+    // Counters of every run requests were tagged with, updated as the server would when a
+    // request's response is sent out.
+    runs: RunStats,
+
+    // The tenant and arguments of every deferred invocation that ran, and the deferral
+    // counters of each tenant, as Master keeps them.
+    deferred: Vec<(TenantId, Vec<u8>)>,
+    deferrals: HashMap<TenantId, Arc<Deferrals>>,
+}
+
+impl Recorder {
+    // Records the completion latency of a request.
+    fn latency(&mut self, tenant: Option<TenantId>, latency: u64) {
+        self.latencies.push(latency);
+        if let Some(tenant) = tenant {
+            self.tenant_latencies.push((tenant, latency));
+        }
+    }
+
+    // Records a request that completed on the server.
+    fn completed(&mut self, tenant: Option<TenantId>, arrival: u64, now: u64) {
+        let rtt = self.rtt;
+        self.latency(tenant, now - arrival + rtt);
+    }
+
+    // Folds the cycles a completed request ran for into it's extension's moving average cost.
+    fn cost(&mut self, ext: usize, cycles: u64) {
+        let avg = self.costs[ext];
+        self.costs[ext] = match avg {
+            0 => cycles,
+            avg => avg - (avg >> 3) + (cycles >> 3),
+        };
+    }
+
+    // Records a request that was pushed back to the client with some work left to do.
+    fn pushed_back(&mut self, tenant: Option<TenantId>, arrival: u64, now: u64, remaining: u64) {
+        let client = (remaining as f64 / self.speed) as u64;
+        let rtt = self.rtt;
+        self.latency(tenant, now - arrival + 2 * rtt + client);
+        self.pushed += 1;
+    }
+}
+
+// Returns a native task whose generator consumes each scripted slice on the virtual clock,
+// yielding between slices, and bumps `done` after every slice. The scheduler runs it, and
+// accounts it's time, exactly as it does any other native task.
+fn scripted(
+    prio: TaskPriority,
+    slices: Vec<u64>,
+    done: Rc<Cell<usize>>,
+    recorder: Rc<RefCell<Recorder>>,
+) -> Native {
+    let last = slices.len().saturating_sub(1);
+    Native::new(
+        prio,
+        Box::new(move || {
+            for (idx, slice) in slices.into_iter().enumerate() {
+                virt::advance(slice);
+                recorder.borrow_mut().busy += slice;
+                done.set(idx + 1);
+                if idx != last {
+                    yield 0;
+                }
+            }
+
+            None
+        }),
+    )
+}
+
+/// A synthetic invoke request. Runs a real native task whose generator consumes the scripted
+/// slices on the virtual clock, and adds what the server keeps on an extension's container:
+/// when it was enqueued, it's tenant and run, the work it defers, and the extension's cost.
+struct Request {
+    // The time-stamp at which the request arrived at the server.
+    arrival: u64,
+
+    // The extension the request invokes.
+    ext: usize,
+
+    // The task running the slices, the length in cycles of each slice, and the number of them
+    // that have run so far.
+    task: Native,
+    slices: Vec<u64>,
+    done: Rc<Cell<usize>>,
+
+    // The time-stamp at which the request was enqueued on the scheduler.
+    enqueued: Option<u64>,
+
+    // The run the request was tagged with, if any.
+    run: Option<u64>,
+
+    // The tenant that issued the request, if any.
+    tenant: Option<TenantId>,
+
+    // The index of the request in the run, and the length in cycles of the work it defers once
+    // it completes. Nothing is deferred if 0.
+    seq: usize,
+    deferred: u64,
+
+    recorder: Rc<RefCell<Recorder>>,
+}
+
+// Returns the arguments a request defers it's work with: the index of the request, it's tenant
+// and the length of the work in cycles.
+fn deferred_args(seq: usize, tenant: TenantId, cycles: u64) -> Vec<u8> {
+    let mut args = Vec::with_capacity(20);
+    args.write_u64::<LittleEndian>(seq as u64).unwrap();
+    args.write_u32::<LittleEndian>(tenant).unwrap();
+    args.write_u64::<LittleEndian>(cycles).unwrap();
+    args
+}
+
+impl Request {
+    // Returns a request that arrived at `arrival`, and runs `slices` once scheduled.
+    fn new(
+        arrival: u64,
+        ext: usize,
+        slices: Vec<u64>,
+        tenant: Option<TenantId>,
+        recorder: &Rc<RefCell<Recorder>>,
+    ) -> Request {
+        let done = Rc::new(Cell::new(0));
+        Request {
+            arrival: arrival,
+            ext: ext,
+            task: scripted(
+                TaskPriority::REQUEST,
+                slices.clone(),
+                Rc::clone(&done),
+                Rc::clone(recorder),
+            ),
+            slices: slices,
+            done: done,
+            enqueued: None,
+            run: None,
+            tenant: tenant,
+            seq: 0,
+            deferred: 0,
+            recorder: Rc::clone(recorder),
+        }
+    }
+}
+
+impl Task for Request {
+    fn run(&mut self) -> (TaskState, u64) {
+        let (state, exec) = self.task.run();
+        if state == COMPLETED {
+            self.recorder.borrow_mut().cost(self.ext, self.task.time());
+        }
+        (state, exec)
+    }
+
+    fn state(&self) -> TaskState {
+        self.task.state()
+    }
+
+    fn time(&self) -> u64 {
+        self.task.time()
+    }
+
+    fn db_time(&self) -> u64 {
+        self.task.db_time()
+    }
+
+    fn priority(&self) -> TaskPriority {
+        self.task.priority()
+    }
+
+    unsafe fn tear(
+        &mut self,
+    ) -> Option<(
+        Packet<UdpHeader, EmptyMetadata>,
+        Packet<UdpHeader, EmptyMetadata>,
+    )> {
+        // A native task without packets has nothing to send out.
+        let res = self.task.tear();
+        let now = cycles::rdtsc();
+        let mut recorder = self.recorder.borrow_mut();
+        let status = match self.task.state() {
+            COMPLETED => {
+                recorder.completed(self.tenant, self.arrival, now);
+                RpcStatus::StatusOk
+            }
+            STOPPED => {
+                let remaining = self.slices[self.done.get()..].iter().sum();
+                recorder.pushed_back(self.tenant, self.arrival, now, remaining);
+                RpcStatus::StatusPushback
+            }
+            _ => return None,
+        };
+
+        if let Some(run) = self.run {
+            recorder.runs.record(run, &status);
+        }
+        res
+    }
+
+    fn set_state(&mut self, state: TaskState) {
+        self.task.set_state(state);
+    }
+
+    fn update_cache(&mut self, _record: &[u8], _keylen: usize) {}
+
+    fn set_enqueued(&mut self, at: u64) {
+        self.enqueued = Some(at);
+    }
+
+    fn enqueued(&self) -> Option<u64> {
+        self.enqueued
+    }
+
+    fn tenant(&self) -> Option<TenantId> {
+        self.tenant
+    }
+
+    fn deferred(&mut self) -> Vec<Deferred> {
+        if self.deferred == 0 || self.task.state() != COMPLETED {
+            return Vec::new();
+        }
+
+        let tenant = self.tenant.unwrap_or(0);
+        vec![Deferred {
+            tenant: tenant,
+            name: vec![self.ext as u8],
+            args: deferred_args(self.seq, tenant, self.deferred),
+        }]
+    }
+
+    fn remaining(&self) -> Option<u64> {
+        match self.recorder.borrow().costs[self.ext] {
+            0 => None,
+            cost => Some(cost.saturating_sub(self.task.time())),
+        }
+    }
+}
+
+/// Synthetic deferred work. Runs a native task consuming the single slice encoded in it's
+/// arguments in the background, and sends nothing out, exactly as a deferred invocation would.
+struct Background {
+    // The task running the slice.
+    task: Native,
+
+    // The deferred work, and the tenant's deferral counters it holds a slot on.
+    work: Deferred,
+    deferrals: Arc<Deferrals>,
+
+    recorder: Rc<RefCell<Recorder>>,
+}
+
+impl Background {
+    // Returns a task running deferred work, holding a slot on the tenant's deferral counters.
+    fn new(
+        work: Deferred,
+        deferrals: Arc<Deferrals>,
+        recorder: &Rc<RefCell<Recorder>>,
+    ) -> Background {
+        let slice = (&work.args[12..]).read_u64::<LittleEndian>().unwrap();
+        let done = Rc::new(Cell::new(0));
+        Background {
+            task: scripted(
+                TaskPriority::BACKGROUND,
+                vec![slice],
+                done,
+                Rc::clone(recorder),
+            ),
+            work: work,
+            deferrals: deferrals,
+            recorder: Rc::clone(recorder),
+        }
+    }
+}
+
+impl Task for Background {
+    fn run(&mut self) -> (TaskState, u64) {
+        self.task.run()
+    }
+
+    fn state(&self) -> TaskState {
+        self.task.state()
+    }
+
+    fn time(&self) -> u64 {
+        self.task.time()
+    }
+
+    fn db_time(&self) -> u64 {
+        self.task.db_time()
+    }
+
+    fn priority(&self) -> TaskPriority {
+        self.task.priority()
+    }
+
+    unsafe fn tear(
+        &mut self,
+    ) -> Option<(
+        Packet<UdpHeader, EmptyMetadata>,
+        Packet<UdpHeader, EmptyMetadata>,
+    )> {
+        let res = self.task.tear();
+        let args = mem::replace(&mut self.work.args, Vec::new());
+        self.recorder
+            .borrow_mut()
+            .deferred
+            .push((self.work.tenant, args));
+        self.deferrals.release(true);
+        res
+    }
+
+    fn set_state(&mut self, state: TaskState) {
+        self.task.set_state(state);
+    }
+
+    fn update_cache(&mut self, _record: &[u8], _keylen: usize) {}
+
+    fn tenant(&self) -> Option<TenantId> {
+        Some(self.work.tenant)
+    }
+}
+
+/// A synthetic dispatcher. Hands requests whose arrival time has passed to the scheduler, at
+/// most MAX_RX_PACKETS of them per invocation, and stops the scheduler once every request
+/// has finished. Once draining, requests are rejected instead, as Master would.
+struct Dispatch {
+    sched: Rc<RoundRobin>,
+
+    // Pre-generated arrival time-stamps, extensions, slices and tenants of every request, and
+    // the index of the next request to hand to the scheduler.
+    arrivals: Vec<(u64, usize, Vec<u64>, Option<TenantId>)>,
+    next: usize,
+
+    // The run ids requests are tagged with, round-robin.
+    run_ids: Vec<u64>,
+
+    // The number of cycles each invocation consumes.
+    cost: u64,
+
+    // The drain, and the number of requests after which it is started.
+    drain: Rc<Drain>,
+    drain_after: Option<usize>,
+
+    // The length in cycles of the work each request defers, and the most deferred work each
+    // tenant can have outstanding.
+    deferred: u64,
+    deferred_cap: usize,
+
+    time: u64,
+    recorder: Rc<RefCell<Recorder>>,
+}
+
+impl Task for Dispatch {
+    fn run(&mut self) -> (TaskState, u64) {
+        // Enqueue the work deferred by requests that completed since the last invocation, unless
+        // their tenant is over the cap.
+        for work in self.sched.deferred() {
+            let deferrals = Arc::clone(
+                self.recorder
+                    .borrow_mut()
+                    .deferrals
+                    .entry(work.tenant)
+                    .or_insert_with(|| Arc::new(Deferrals::new())),
+            );
+            if deferrals.acquire(self.deferred_cap) {
+                self.sched
+                    .enqueue(Box::new(Background::new(work, deferrals, &self.recorder)));
+            }
+        }
+
+        let (finished, outstanding) = {
+            let recorder = self.recorder.borrow();
+            (
+                recorder.latencies.len() + recorder.rejected,
+                recorder
+                    .deferrals
+                    .values()
+                    .map(|d| d.queued())
+                    .sum::<usize>(),
+            )
+        };
+        if finished == self.arrivals.len() && outstanding == 0 {
+            self.sched.compromised();
+            return (YIELDED, 0);
+        }
+
+        // If nothing is running on the server, then skip ahead to the next arrival.
+        if finished == self.next && outstanding == 0 {
+            virt::advance_to(self.arrivals[self.next].0);
+        }
+
+        let now = cycles::rdtsc();
+        let mut received = 0;
+        while self.next < self.arrivals.len()
+            && self.arrivals[self.next].0 <= now
+            && received < MAX_RX_PACKETS
+        {
+            // Start draining once enough requests have been handed to the scheduler.
+            if self.drain_after == Some(self.next) && self.drain.start() {
+                self.sched.drain();
+                self.recorder.borrow_mut().in_flight = self.next - finished;
+            }
+
+            let (arrival, ext, ref slices, tenant) = self.arrivals[self.next];
+            let run = match self.run_ids.len() {
+                0 => None,
+                n => Some(self.run_ids[self.next % n]),
+            };
+
+            if self.drain.draining() {
+                let mut recorder = self.recorder.borrow_mut();
+                recorder.rejected += 1;
+                if let Some(run) = run {
+                    recorder.runs.record(run, &RpcStatus::StatusServerDraining);
+                }
+            } else {
+                let mut request =
+                    Request::new(arrival, ext, slices.clone(), tenant, &self.recorder);
+                request.run = run;
+                request.seq = self.next;
+                request.deferred = self.deferred;
+                self.sched.enqueue(Box::new(request));
+            }
+            self.next += 1;
+            received += 1;
+        }
+
+        virt::advance(self.cost);
+        self.time += self.cost;
+        (YIELDED, self.cost)
+    }
+
+    fn state(&self) -> TaskState {
+        YIELDED
+    }
+
+    fn time(&self) -> u64 {
+        self.time
+    }
+
+    fn db_time(&self) -> u64 {
+        0
+    }
+
+    fn priority(&self) -> TaskPriority {
+        TaskPriority::DISPATCH
+    }
+
+    unsafe fn tear(
+        &mut self,
+    ) -> Option<(
+        Packet<UdpHeader, EmptyMetadata>,
+        Packet<UdpHeader, EmptyMetadata>,
+    )> {
+        None
+    }
+
+    fn set_state(&mut self, _state: TaskState) {}
+
+    fn update_cache(&mut self, _record: &[u8], _keylen: usize) {}
+}
+
+// Samples an exponentially distributed value with the given mean.
+fn exponential(rng: &mut XorShiftRng, mean: f64) -> f64 {
+    -(1.0 - rng.next_f64()).ln() * mean
+}
+
+/// Runs a simulation on the calling thread. The scheduler and its pushback policy run
+/// unmodified; only time is virtual, so a run takes as long as the scheduling decisions do.
+///
+/// # Arguments
+///
+/// * `config`: The workload, client model and pushback policy to simulate.
+///
+/// # Return
+///
+/// The completion latency distribution, core utilization and pushback rate of the run.
+pub fn run(config: &Config) -> Results {
+    run_with_drain(config, Rc::new(Drain::new()))
+}
+
+/// Runs a simulation that drains the server as configured by `drain_after`. Once the scheduler
+/// has stopped, the drain is checked on exactly as the server's watchdog would, running it's
+/// shutdown hooks.
+///
+/// # Arguments
+///
+/// * `config`: The workload, client model and pushback policy to simulate.
+/// * `drain`:  The drain to start, with any shutdown hooks registered on it.
+///
+/// # Return
+///
+/// The outcome of the run. Latencies are only recorded for requests that were not rejected.
+pub fn run_with_drain(config: &Config, drain: Rc<Drain>) -> Results {
+    let cycles_per_us = config.hz as f64 / 1e6;
+    let mut rng = XorShiftRng::from_seed([config.seed, 0x193a6754, 0xa8a7d469, 0x97830e05]);
+
+    // Split requests between tenants in proportion to their load.
+    let streams: Vec<(Option<TenantId>, f64, usize)> = match config.tenants.len() {
+        0 => vec![(None, config.load, config.requests)],
+        _ => {
+            let total: f64 = config.tenants.iter().map(|&(_, load)| load).sum();
+            config
+                .tenants
+                .iter()
+                .map(|&(tenant, load)| {
+                    let requests = (config.requests as f64 * load / total).round() as usize;
+                    (Some(tenant), load, requests)
+                })
+                .collect()
+        }
+    };
+
+    // Pre-generate the Poisson arrival processes so that every policy sees the same requests.
+    let mut arrivals: Vec<(u64, usize, Vec<u64>, Option<TenantId>)> = Vec::new();
+    for &(tenant, load, requests) in streams.iter() {
+        let interarrival = config.service.mean_us() / load;
+        let mut arrival = START as f64;
+        for _ in 0..requests {
+            arrival += exponential(&mut rng, interarrival) * cycles_per_us;
+            let (ext, slices) = config.service.sample(&mut rng, cycles_per_us);
+            arrivals.push((arrival as u64, ext, slices, tenant));
+        }
+    }
+    arrivals.sort_by_key(|&(arrival, _, _, _)| arrival);
+    let requests = arrivals.len();
+
+    let recorder = Rc::new(RefCell::new(Recorder {
+        latencies: Vec::with_capacity(requests),
+        tenant_latencies: Vec::new(),
+        busy: 0,
+        pushed: 0,
+        in_flight: 0,
+        rejected: 0,
+        speed: config.client.speed,
+        rtt: (config.client.rtt_us * cycles_per_us) as u64,
+        costs: [0; 2],
+        runs: RunStats::new(config.run_cap),
+        deferred: Vec::new(),
+        deferrals: HashMap::new(),
+    }));
+
+    virt::install(START, config.hz);
+    let sched = Rc::new(
+        RoundRobin::with_policy(0, 0, config.policy.clone()).with_fairness(config.fairness.clone()),
+    );
+    sched.enqueue(Box::new(Dispatch {
+        sched: Rc::clone(&sched),
+        arrivals: arrivals,
+        next: 0,
+        run_ids: config.run_ids.clone(),
+        cost: (config.dispatch_us * cycles_per_us) as u64,
+        drain: Rc::clone(&drain),
+        drain_after: config.drain_after,
+        deferred: (config.deferred_us * cycles_per_us) as u64,
+        deferred_cap: match config.deferred_cap {
+            0 => DEFAULT_MAX_DEFERRED,
+            cap => cap,
+        },
+        time: 0,
+        recorder: Rc::clone(&recorder),
+    }));
+    sched.poll();
+    let elapsed = cycles::rdtsc() - START;
+    virt::uninstall();
+
+    if drain.draining() {
+        drain.check(Some(&*sched), false);
+    }
+
+    // The dispatcher holds a reference to the scheduler; drop it to break the cycle.
+    sched.dequeue_all();
+    let pushbacks = sched.pushbacks();
+    let tenant_delays = sched.tenant_delays();
+
+    let mut recorded = recorder.borrow_mut();
+    recorded.latencies.sort();
+    Results {
+        latencies: recorded.latencies.drain(..).collect(),
+        hz: config.hz,
+        utilization: recorded.busy as f64 / elapsed as f64,
+        pushback_rate: recorded.pushed as f64 / requests as f64,
+        pushbacks: pushbacks,
+        in_flight: recorded.in_flight,
+        rejected: recorded.rejected,
+        runs: mem::replace(&mut recorded.runs, RunStats::new(0)),
+        tenant_delays: tenant_delays,
+        tenant_latencies: recorded.tenant_latencies.drain(..).collect(),
+        deferred: recorded.deferred.drain(..).collect(),
+        deferred_dropped: recorded.deferrals.values().map(|d| d.dropped()).sum(),
+    }
+}
+
+/// Runs the same workload under a range of pushback credits, printing the outcome of each.
+///
+/// # Arguments
+///
+/// * `config`:  The workload, client model and pushback policy to simulate.
+/// * `credits`: The pushback credits, in microseconds, to simulate.
+///
+/// # Return
+///
+/// The outcome of each run, in the order of `credits`.
+pub fn sweep(config: &Config, credits: &[f64]) -> Vec<Results> {
+    credits
+        .iter()
+        .map(|credit| {
+            let mut config = config.clone();
+            config.policy.enabled = true;
+            config.policy.credit_us = *credit;
+            let results = run(&config);
+            results.print(&format!("credit {:.2}us", credit));
+            results
+        })
+        .collect()
+}
+
+/// Runs the same workload under a range of queue-aware pushback policies, printing the outcome
+/// of each.
+///
+/// # Arguments
+///
+/// * `config`:   The workload, client model and pushback policy to simulate.
+/// * `products`: The depth product thresholds to simulate. See PushbackPolicy::depth_product.
+///
+/// # Return
+///
+/// The outcome of each run, in the order of `products`.
+pub fn sweep_products(config: &Config, products: &[f64]) -> Vec<Results> {
+    products
+        .iter()
+        .map(|product| {
+            let mut config = config.clone();
+            config.policy.enabled = true;
+            config.policy.depth_product = *product;
+            let results = run(&config);
+            results.print(&format!("depth product {:.0}", product));
+            results
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    // Tests that a single core serving exponentially distributed requests behaves like an
+    // M/M/1 queue, whose mean completion latency is S / (1 - load).
+    #[test]
+    fn test_sim_mm1() {
+        let mut previous = 0.0;
+        for load in [0.3, 0.5, 0.7, 0.8].iter() {
+            let mut config = Config::default();
+            config.requests = 200000;
+            config.load = *load;
+            config.policy.enabled = false;
+
+            let results = run(&config);
+            results.print(&format!("load {:.1}", load));
+
+            let expected = 1.0 / (1.0 - load);
+            assert!((results.mean_us() - expected).abs() / expected < 0.1);
+            assert!((results.utilization - load).abs() < 0.05);
+            assert_eq!(0.0, results.pushback_rate);
+            assert!(results.mean_us() > previous);
+            previous = results.mean_us();
+        }
+    }
+
+    // Tests that raising the pushback credit never pushes back more requests, and that a
+    // credit larger than any request pushes back none.
+    #[test]
+    fn test_sim_pushback_sweep() {
+        let mut config = Config::default();
+        config.requests = 50000;
+        config.load = 1.2;
+        config.service = Service::Bimodal {
+            long: 0.1,
+            short_us: 0.5,
+            slices: 20,
+            slice_us: 1.0,
+        };
+        config.client = Client {
+            speed: 0.5,
+            rtt_us: 5.0,
+        };
+
+        let credits = [0.5, 2.0, 8.0, 32.0];
+        let results = sweep(&config, &credits);
+        assert!(results[0].pushback_rate > 0.0);
+        for pair in results.windows(2) {
+            assert!(pair[1].pushback_rate <= pair[0].pushback_rate + 0.005);
+        }
+        assert_eq!(0.0, results[credits.len() - 1].pushback_rate);
+    }
+
+    // Returns a workload of short requests and long auth invokes, at the given load, under a
+    // queue-aware pushback policy with both triggers disabled.
+    fn auth(load: f64) -> Config {
+        let mut config = Config::default();
+        config.requests = 20000;
+        config.load = load;
+        config.service = Service::Bimodal {
+            long: 0.1,
+            short_us: 0.5,
+            slices: 20,
+            slice_us: 1.0,
+        };
+        config.client = Client {
+            speed: 0.5,
+            rtt_us: 5.0,
+        };
+        config.policy.enabled = true;
+        config
+    }
+
+    // Tests that the queue depth trigger lets long invokes run to completion on an idle core,
+    // even though every one of them runs well past the credit, but pushes them back on an
+    // overloaded one.
+    #[test]
+    fn test_sim_queue_aware_depth() {
+        let mut config = auth(0.1);
+        config.policy.depth_product = 200.0;
+        let idle = run(&config);
+        idle.print("idle");
+        assert_eq!(0.0, idle.pushback_rate);
+        assert_eq!(Pushbacks::default(), idle.pushbacks);
+
+        config.load = 1.2;
+        let busy = run(&config);
+        busy.print("overloaded");
+        assert!(busy.pushback_rate > 0.01);
+        assert!(busy.pushbacks.depth > 0);
+        assert_eq!(0, busy.pushbacks.credit + busy.pushbacks.delay);
+
+        let pushed = (busy.pushback_rate * config.requests as f64).round() as u64;
+        assert_eq!(pushed, busy.pushbacks.total());
+    }
+
+    // Tests that the queueing delay trigger lets long invokes run to completion on an idle
+    // core, but pushes them back on an overloaded one.
+    #[test]
+    fn test_sim_queue_aware_delay() {
+        let mut config = auth(0.1);
+        config.policy.delay_product = 1000.0;
+        assert_eq!(0.0, run(&config).pushback_rate);
+
+        config.load = 1.2;
+        let busy = run(&config);
+        assert!(busy.pushbacks.delay > 0);
+        assert_eq!(0, busy.pushbacks.credit + busy.pushbacks.depth);
+    }
+
+    // Tests the trade-off the product threshold makes under overload: lower thresholds push
+    // back more long invokes, keeping the queue and so the latency of most requests in check,
+    // while a threshold the load never reaches pushes back none and lets the queue grow.
+    #[test]
+    fn test_sim_queue_aware_sweep() {
+        let products = [50.0, 200.0, 1000.0, 1e12];
+        let results = sweep_products(&auth(1.2), &products);
+        assert!(results[0].pushback_rate > 0.0);
+        for pair in results.windows(2) {
+            assert!(pair[1].pushback_rate <= pair[0].pushback_rate + 0.005);
+        }
+
+        let last = &results[products.len() - 1];
+        assert_eq!(0.0, last.pushback_rate);
+        assert!(results[0].percentile_us(50.0) < last.percentile_us(50.0));
+    }
+
+    // Tests that a run with the same seed is deterministic.
+    #[test]
+    fn test_sim_deterministic() {
+        let config = Config::default();
+        let (a, b) = (run(&config), run(&config));
+        assert_eq!(a.latencies, b.latencies);
+    }
+
+    // Tests that once a drain starts, the slow requests already on the scheduler run to
+    // completion, later requests are rejected, the scheduler stops on it's own, and shutdown
+    // hooks run exactly once.
+    #[test]
+    fn test_sim_drain() {
+        let mut config = Config::default();
+        config.requests = 2000;
+        config.load = 0.9;
+        config.service = Service::Bimodal {
+            long: 0.2,
+            short_us: 0.5,
+            slices: 20,
+            slice_us: 1.0,
+        };
+        config.policy.enabled = false;
+        config.drain_after = Some(1000);
+
+        let runs = Arc::new(AtomicUsize::new(0));
+        let drain = Rc::new(Drain::new());
+        let hook = Arc::clone(&runs);
+        drain.on_shutdown(Box::new(move || {
+            hook.fetch_add(1, Ordering::SeqCst);
+        }));
+
+        let results = run_with_drain(&config, Rc::clone(&drain));
+        assert!(results.in_flight > 0);
+        assert_eq!(1000, results.latencies.len());
+        assert!(results.rejected > 0);
+        assert!(1000 + results.rejected < config.requests);
+        assert_eq!(1, runs.load(Ordering::SeqCst));
+
+        // The watchdog checking on the drain again must not run the hooks again.
+        assert_eq!(0, drain.shutdown());
+        assert_eq!(1, runs.load(Ordering::SeqCst));
+    }
+
+    // Tests that requests from two interleaved runs are counted separately, with pushbacks and
+    // rejections attributed to the run that sent them.
+    #[test]
+    fn test_sim_runs() {
+        let mut config = Config::default();
+        config.requests = 4000;
+        config.load = 1.2;
+        config.service = Service::Bimodal {
+            long: 0.1,
+            short_us: 0.5,
+            slices: 20,
+            slice_us: 1.0,
+        };
+        config.policy.enabled = true;
+        config.drain_after = Some(3000);
+        config.run_ids = vec![0xa, 0xb];
+
+        let results = run(&config);
+        let (a, b) = (
+            results.runs.get(0xa).unwrap(),
+            results.runs.get(0xb).unwrap(),
+        );
+        assert_eq!(2, results.runs.len());
+        assert_eq!(
+            (results.latencies.len() + results.rejected) as u64,
+            a.requests + b.requests
+        );
+        assert!(a.requests >= b.requests && a.requests - b.requests <= 1);
+
+        let pushed = (results.pushback_rate * config.requests as f64).round() as u64;
+        assert!(pushed > 0);
+        assert_eq!(pushed, a.pushbacks + b.pushbacks);
+        assert!(a.pushbacks > 0 && b.pushbacks > 0);
+
+        assert!(results.rejected > 0);
+        assert_eq!(results.rejected as u64, a.errors + b.errors);
+    }
+
+    // Tests that only the most recently active runs are tracked once the cap is reached.
+    #[test]
+    fn test_sim_runs_cap() {
+        let mut config = Config::default();
+        config.requests = 1000;
+        config.policy.enabled = false;
+        config.run_ids = vec![1, 2, 3, 4];
+        config.run_cap = 2;
+
+        let results = run(&config);
+        assert_eq!(2, results.runs.len());
+        let tracked: u64 = results.runs.recent(4).iter().map(|r| r.requests).sum();
+        assert!(tracked < config.requests as u64);
+    }
+
+    // Tests that untagged requests never touch the run counters.
+    #[test]
+    fn test_sim_runs_untagged() {
+        let mut config = Config::default();
+        config.requests = 1000;
+        config.drain_after = Some(500);
+
+        let results = run(&config);
+        assert!(results.rejected > 0);
+        assert!(results.runs.is_empty());
+    }
+
+    // Returns a workload where tenant 1 floods the core at twice it's capacity, and tenant 2
+    // sends a request every 50 microseconds, with a 5 microsecond quantum if `fair`.
+    fn flood(requests: usize, fair: bool) -> Config {
+        let mut config = Config::default();
+        config.requests = requests;
+        config.policy.enabled = false;
+        config.tenants = vec![(1, 2.0), (2, 0.02)];
+        if fair {
+            config.fairness.quantum_us = 5.0;
+        }
+        config
+    }
+
+    // Tests that under deficit round-robin, a flood from one tenant delays another tenant's
+    // requests by about a quantum, no matter how long it goes on for, while first come first
+    // served makes them wait behind the whole backlog.
+    #[test]
+    fn test_sim_fair_flood() {
+        let quantum = 5.0;
+        let mut previous = 0.0;
+        for requests in [10000, 20000].iter() {
+            let fifo = run(&flood(*requests, false));
+            let fair = run(&flood(*requests, true));
+            println!(
+                "{} requests: tenant 2 fifo mean {:.2} delay {:.2} fair mean {:.2} delay {:.2}",
+                requests,
+                fifo.tenant_mean_us(2),
+                fifo.tenant_delay_us(2),
+                fair.tenant_mean_us(2),
+                fair.tenant_delay_us(2)
+            );
+
+            assert_eq!(2, fair.tenant_delays.len());
+            assert!(fair.tenant_delay_us(2) <= 2.0 * quantum);
+            assert!(fair.tenant_mean_us(2) <= 4.0 * quantum);
+            assert!(fifo.tenant_delay_us(2) > fair.tenant_delay_us(2));
+
+            // The backlog keeps growing under first come first served, and so does the time
+            // tenant 2's requests take.
+            assert!(fifo.tenant_mean_us(2) > 100.0 * quantum);
+            assert!(fifo.tenant_mean_us(2) > 1.5 * previous);
+            previous = fifo.tenant_mean_us(2);
+        }
+    }
+
+    // Tests that work deferred by a request does not count towards it's latency, even though
+    // it keeps the core busy.
+    #[test]
+    fn test_sim_deferred_latency() {
+        let mut config = Config::default();
+        config.requests = 20000;
+        config.load = 0.01;
+        config.policy.enabled = false;
+        let inline = run(&config);
+
+        config.deferred_us = 20.0;
+        let deferred = run(&config);
+        deferred.print("deferred");
+
+        assert_eq!(config.requests, deferred.deferred.len());
+        assert_eq!(0, deferred.deferred_dropped);
+        assert!((deferred.utilization - 0.21).abs() < 0.03);
+        assert!(deferred.percentile_us(50.0) < 2.0 * inline.percentile_us(50.0));
+        assert!(deferred.mean_us() < config.deferred_us / 2.0);
+    }
+
+    // Tests that every request's deferred work runs exactly once, with the arguments the
+    // request deferred it with, on behalf of the tenant that issued the request.
+    #[test]
+    fn test_sim_deferred_args() {
+        let mut config = Config::default();
+        config.requests = 4000;
+        config.policy.enabled = false;
+        config.tenants = vec![(1, 0.2), (2, 0.1)];
+        config.deferred_us = 1.0;
+
+        let results = run(&config);
+        assert_eq!(config.requests, results.deferred.len());
+
+        let mut seen = vec![false; config.requests];
+        for &(tenant, ref args) in results.deferred.iter() {
+            let mut args = &args[..];
+            let seq = args.read_u64::<LittleEndian>().unwrap() as usize;
+            assert!(!seen[seq]);
+            seen[seq] = true;
+            assert_eq!(tenant, args.read_u32::<LittleEndian>().unwrap());
+            assert!(tenant == 1 || tenant == 2);
+            assert_eq!(1000, args.read_u64::<LittleEndian>().unwrap());
+        }
+        assert!(seen.iter().all(|&seen| seen));
+    }
+
+    // Tests that work deferred past a tenant's cap is dropped and counted, and that the rest
+    // still runs.
+    #[test]
+    fn test_sim_deferred_cap() {
+        let mut config = Config::default();
+        config.requests = 2000;
+        config.load = 0.5;
+        config.policy.enabled = false;
+        config.tenants = vec![(1, 0.5)];
+        config.deferred_us = 10.0;
+        config.deferred_cap = 4;
+
+        let results = run(&config);
+        assert!(results.deferred_dropped > 0);
+        assert!(results.deferred.len() >= config.deferred_cap);
+        assert_eq!(
+            config.requests,
+            results.deferred.len() + results.deferred_dropped
+        );
+        assert_eq!(config.requests, results.latencies.len());
+    }
+}
@@ -0,0 +1,177 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use super::table::{Entry, Table};
+
+/// The objects a multi-key read found, each along with the version it was read at.
+///
+/// Keys are read one after the other without holding any lock across them, so a write can land
+/// between two reads. Once the last key is read, the version of every key is checked again; if
+/// any changed, the objects may not all have been current at the same instant, and the read is
+/// flagged racy so that the reader can retry it. If none changed, every object was current at
+/// the instant the last key was read.
+pub struct Snapshot {
+    /// The entry read for each key, in the order the keys were passed in.
+    pub entries: Vec<Entry>,
+
+    /// True if any key was written to or deleted while the keys were being read.
+    pub racy: bool,
+}
+
+/// Splits the keys on a multiget() request.
+///
+/// # Arguments
+///
+/// * `payload`:  The keys, back to back.
+/// * `key_len`:  The length of every key.
+/// * `num_keys`: The number of keys.
+///
+/// # Return
+///
+/// The keys, or None if the payload is too short to hold all of them.
+pub fn keys(payload: &[u8], key_len: u16, num_keys: u32) -> Option<Vec<&[u8]>> {
+    let (len, num) = (key_len as usize, num_keys as usize);
+    if payload.len() < len * num {
+        return None;
+    }
+
+    Some((0..num).map(|i| &payload[i * len..(i + 1) * len]).collect())
+}
+
+/// Reads a set of keys off a table, and then checks whether any of them changed while they were
+/// being read.
+///
+/// # Arguments
+///
+/// * `table`: The table to read the keys off.
+/// * `keys`:  The keys to read.
+///
+/// # Return
+///
+/// The objects, with their versions, or None if any of the keys does not exist.
+pub fn read(table: &Table, keys: &[&[u8]]) -> Option<Snapshot> {
+    read_with(table, keys, || {})
+}
+
+// read(), with `window` called after the last key is read but before versions are checked
+// again. Tests widen the window with it, so that a write is sure to land in it.
+fn read_with<F: FnOnce()>(table: &Table, keys: &[&[u8]], window: F) -> Option<Snapshot> {
+    let mut entries = Vec::with_capacity(keys.len());
+    for key in keys.iter() {
+        entries.push(table.get(key)?);
+    }
+
+    window();
+
+    let racy = keys
+        .iter()
+        .zip(entries.iter())
+        .any(|(key, entry)| table.version(key) != Some(entry.version));
+
+    Some(Snapshot {
+        entries: entries,
+        racy: racy,
+    })
+}
+
+// This module contains unit tests for snapshot reads.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::mpsc::channel;
+    use std::sync::Arc;
+    use std::thread;
+
+    use bytes::Bytes;
+
+    fn table() -> Arc<Table> {
+        let table = Arc::new(Table::default());
+        for key in [b"k1", b"k2", b"k3"].iter() {
+            table.put(Bytes::from(&key[..]), Bytes::from(&b"v1"[..]));
+        }
+        table
+    }
+
+    fn versions(snapshot: &Snapshot) -> Vec<u64> {
+        snapshot
+            .entries
+            .iter()
+            .map(|e| e.version.number())
+            .collect()
+    }
+
+    // Tests that a write by another thread, landing while the keys are read, flags the read as
+    // racy, and that the versions returned are the ones that were read.
+    #[test]
+    fn test_snapshot_racy() {
+        let table = table();
+        let (go, started) = channel();
+        let (wrote, done) = channel();
+
+        let writer = Arc::clone(&table);
+        let handle = thread::spawn(move || {
+            started.recv().unwrap();
+            writer.put(Bytes::from(&b"k2"[..]), Bytes::from(&b"v2"[..]));
+            wrote.send(()).unwrap();
+        });
+
+        let keys: [&[u8]; 3] = [b"k1", b"k2", b"k3"];
+        let snapshot = read_with(&table, &keys, || {
+            go.send(()).unwrap();
+            done.recv().unwrap();
+        })
+        .unwrap();
+        handle.join().unwrap();
+
+        assert!(snapshot.racy);
+        assert_eq!(vec![1, 1, 1], versions(&snapshot));
+        assert_eq!(&b"v1"[..], &snapshot.entries[1].value[..]);
+
+        // Read again, the write is picked up, and the read isn't racy.
+        let snapshot = read(&table, &keys).unwrap();
+        assert!(!snapshot.racy);
+        assert_eq!(vec![1, 2, 1], versions(&snapshot));
+    }
+
+    // Tests that a read nothing is written to during is not racy, and that keys deleted during
+    // the read flag it as racy.
+    #[test]
+    fn test_snapshot_quiescent() {
+        let table = table();
+        let keys: [&[u8]; 3] = [b"k3", b"k1", b"k2"];
+        let snapshot = read(&table, &keys).unwrap();
+        assert!(!snapshot.racy);
+        assert_eq!(3, snapshot.entries.len());
+
+        let snapshot = read_with(&table, &keys, || {
+            table.delete(b"k1");
+        })
+        .unwrap();
+        assert!(snapshot.racy);
+
+        // The key is gone now, so the read fails altogether.
+        assert!(read(&table, &keys).is_none());
+    }
+
+    // Tests that keys are split off a multiget() payload.
+    #[test]
+    fn test_snapshot_keys() {
+        let expected: Vec<&[u8]> = vec![&b"ab"[..], &b"cd"[..]];
+        assert_eq!(Some(expected), keys(b"abcde", 2, 2));
+        assert_eq!(None, keys(b"abc", 2, 2));
+        assert_eq!(Some(vec![]), keys(b"", 0, 0));
+    }
+}
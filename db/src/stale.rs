@@ -0,0 +1,202 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use std::cmp;
+
+use super::cycles;
+
+/// Returns the age of a copy of an object, in microseconds. Saturates at u32::max_value(), which
+/// is older than any budget.
+///
+/// # Arguments
+///
+/// * `refreshed`: The time-stamp, in cycles, at which the copy was last refreshed.
+/// * `now`:       The current time-stamp, in cycles.
+/// * `hz`:        The rate the cycle counter the time-stamps were read off ticks at.
+pub fn age_us(refreshed: u64, now: u64, hz: u64) -> u32 {
+    let cycles = now.saturating_sub(refreshed) as u128;
+    let age = cycles * 1_000_000 / cmp::max(hz, 1) as u128;
+    cmp::min(age, u32::max_value() as u128) as u32
+}
+
+/// Returns true if a get() with a staleness budget can be served off a copy of an object. A
+/// budget of 0 is never served off a copy, however recently it was refreshed, so that a client
+/// can always read it's own writes. Every copy that can lag behind the primary, on the server
+/// or on a client, must be checked with this before it is served.
+///
+/// # Arguments
+///
+/// * `age_us`:    The age of the copy, in microseconds; refer to age_us().
+/// * `budget_us`: The staleness budget on the get(), in microseconds.
+pub fn within(age_us: u32, budget_us: u32) -> bool {
+    budget_us != 0 && age_us <= budget_us
+}
+
+/// A copy of an object that can lag behind the primary copy in it's table, such as an entry in
+/// a cache or a replica. The copy remembers when it was last refreshed off the primary copy, so
+/// that a get() can decide whether it is fresh enough to be served, given the staleness budget
+/// on the get() (GetRequest::staleness_us).
+pub struct Refreshed<T> {
+    // The copy of the object.
+    value: T,
+
+    // The time-stamp, in cycles, at which the copy was last refreshed.
+    refreshed: u64,
+}
+
+impl<T> Refreshed<T> {
+    /// Returns a copy that was refreshed off the primary copy just now.
+    ///
+    /// # Arguments
+    ///
+    /// * `value`: The copy of the object.
+    pub fn new(value: T) -> Refreshed<T> {
+        Refreshed {
+            value: value,
+            refreshed: cycles::rdtsc(),
+        }
+    }
+
+    /// Replaces the copy with one that was read off the primary copy just now.
+    ///
+    /// # Arguments
+    ///
+    /// * `value`: The new copy of the object.
+    pub fn refresh(&mut self, value: T) {
+        self.value = value;
+        self.refreshed = cycles::rdtsc();
+    }
+
+    /// Returns how long ago the copy was last refreshed, in microseconds. Saturates at
+    /// u32::max_value(), which is older than any budget.
+    pub fn age_us(&self) -> u32 {
+        age_us(self.refreshed, cycles::rdtsc(), cycles::cycles_per_second())
+    }
+
+    /// Returns the copy if a get() with a staleness budget can be served off it; refer to
+    /// within().
+    ///
+    /// # Arguments
+    ///
+    /// * `budget_us`: The staleness budget on the get(), in microseconds.
+    ///
+    /// # Return
+    ///
+    /// The copy, and it's age in microseconds (to be echoed on the response). None if the copy
+    /// is older than the budget, in which case the get() must fall through to the primary copy.
+    pub fn serve(&self, budget_us: u32) -> Option<(&T, u32)> {
+        let age = self.age_us();
+        match within(age, budget_us) {
+            true => Some((&self.value, age)),
+            false => None,
+        }
+    }
+}
+
+/// Serves a get() off a copy of the object if the copy is fresh enough for the get()'s budget,
+/// and off the primary copy otherwise. Every shortcut on the read path that can serve a stale
+/// object must go through this.
+///
+/// # Arguments
+///
+/// * `copy`:      The copy the get() could be served off, if there is one.
+/// * `budget_us`: The staleness budget on the get(), in microseconds.
+/// * `primary`:   Looks the object up in the table.
+///
+/// # Return
+///
+/// The object, and the age of the copy it was served off in microseconds; 0 if it was served off
+/// the primary copy. None if the primary lookup failed.
+pub fn serve<T, F>(copy: Option<&Refreshed<T>>, budget_us: u32, primary: F) -> Option<(T, u32)>
+where
+    T: Clone,
+    F: FnOnce() -> Option<T>,
+{
+    match copy.and_then(|copy| copy.serve(budget_us)) {
+        Some((value, age)) => Some((value.clone(), age)),
+        None => primary().map(|value| (value, 0)),
+    }
+}
+
+// This module contains unit tests for serving get()s off copies that can lag behind the primary.
+// Time is read off a virtual clock running at a million cycles per second, so that a cycle is a
+// microsecond.
+#[cfg(test)]
+mod tests {
+    use super::super::cycles::virt;
+    use super::*;
+
+    use std::cell::RefCell;
+
+    // Tests that a copy older than the budget falls through to the primary, that one within it
+    // is served, and that the age echoed is the copy's.
+    #[test]
+    fn test_stale_budget() {
+        virt::install(1000, 1_000_000);
+        let primary = RefCell::new(b"fresh".to_vec());
+        let copy = Refreshed::new(b"stale".to_vec());
+        virt::advance(750);
+
+        let lookup = || Some(primary.borrow().clone());
+        assert_eq!(
+            Some((b"fresh".to_vec(), 0)),
+            serve(Some(&copy), 500, lookup)
+        );
+        assert_eq!(
+            Some((b"stale".to_vec(), 750)),
+            serve(Some(&copy), 750, lookup)
+        );
+        assert_eq!(
+            Some((b"stale".to_vec(), 750)),
+            serve(Some(&copy), 2000, lookup)
+        );
+        assert_eq!(Some((b"fresh".to_vec(), 0)), serve(None, 2000, lookup));
+        virt::uninstall();
+    }
+
+    // Tests that a budget of 0 is always served off the primary, and so always matches a value
+    // that was just written, even if the copy was refreshed in the same instant.
+    #[test]
+    fn test_stale_fresh() {
+        virt::install(1000, 1_000_000);
+        let primary = RefCell::new(b"v1".to_vec());
+        let mut copy = Refreshed::new(primary.borrow().clone());
+
+        *primary.borrow_mut() = b"v2".to_vec();
+        assert_eq!(0, copy.age_us());
+        assert!(copy.serve(0).is_none());
+
+        let lookup = || Some(primary.borrow().clone());
+        assert_eq!(Some((b"v2".to_vec(), 0)), serve(Some(&copy), 0, lookup));
+
+        // A refresh picks the write up, and resets the copy's age.
+        virt::advance(300);
+        assert_eq!(300, copy.age_us());
+        copy.refresh(primary.borrow().clone());
+        assert_eq!(Some((&b"v2".to_vec(), 0)), copy.serve(1));
+        virt::uninstall();
+    }
+
+    // Tests that ages too large for the response saturate instead of wrapping around.
+    #[test]
+    fn test_stale_saturate() {
+        virt::install(0, 1_000_000);
+        let copy = Refreshed::new(());
+        virt::advance(u32::max_value() as u64 + 10);
+        assert_eq!(u32::max_value(), copy.age_us());
+        assert!(copy.serve(u32::max_value() - 1).is_none());
+        virt::uninstall();
+    }
+}
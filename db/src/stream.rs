@@ -0,0 +1,208 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::wireformat::{RpcStatus, STREAM_FLAG_END};
+
+/// The most bytes an invocation can stream back across all of it's responses, if the server
+/// config does not say otherwise.
+pub const DEFAULT_MAX_STREAM_BYTES: usize = 1 << 20;
+
+// Counters reported by stats(). Shared by all cores.
+static STREAMS: AtomicUsize = AtomicUsize::new(0);
+static CHUNKS: AtomicUsize = AtomicUsize::new(0);
+static REFUSED: AtomicUsize = AtomicUsize::new(0);
+
+/// Counters of the results streamed back since the server started.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StreamStats {
+    /// The number of invocations that streamed back their result.
+    pub streams: usize,
+
+    /// The number of partial responses sent out, not counting the last one of each stream.
+    pub chunks: usize,
+
+    /// The number of streams that failed for going over the cap on their bytes.
+    pub refused: usize,
+}
+
+/// Returns the counters of streamed results.
+pub fn stats() -> StreamStats {
+    StreamStats {
+        streams: STREAMS.load(Ordering::Relaxed),
+        chunks: CHUNKS.load(Ordering::Relaxed),
+        refused: REFUSED.load(Ordering::Relaxed),
+    }
+}
+
+/// Tracks the responses an invocation streams back to it's tenant. Each time the extension
+/// flushes it's response, the response is sent out as a partial result, stamped with
+/// STREAM_FLAG_MORE and it's position in the stream, and the extension continues on a fresh
+/// one. The response left when the invocation completes is the last one, and is stamped with
+/// STREAM_FLAG_END instead. A result that is never flushed goes out as a single response, with
+/// neither flag set.
+///
+/// The bytes streamed back, including the last response's, are capped. Once a flush would go
+/// over the cap, nothing more is sent out, and the last response fails with
+/// StatusStreamTooLarge.
+pub struct Stream {
+    cap: usize,
+    bytes: usize,
+    seq: u32,
+    overflow: bool,
+}
+
+impl Stream {
+    /// Returns a stream that has not sent anything out yet.
+    ///
+    /// # Arguments
+    ///
+    /// * `cap`: The most bytes of payload the stream can carry across all of it's responses.
+    pub fn new(cap: usize) -> Stream {
+        Stream {
+            cap: cap,
+            bytes: 0,
+            seq: 0,
+            overflow: false,
+        }
+    }
+
+    /// Returns true once a partial response has been sent out. The invocation can no longer
+    /// be pushed back after this.
+    pub fn started(&self) -> bool {
+        self.seq > 0
+    }
+
+    /// Accounts for a partial response that is about to be sent out.
+    ///
+    /// # Arguments
+    ///
+    /// * `len`: The length of the response's payload.
+    ///
+    /// # Return
+    ///
+    /// The position of the response in the stream, or None if it would take the stream over
+    /// it's cap, in which case it must not be sent out.
+    pub fn flush(&mut self, len: usize) -> Option<u32> {
+        if self.overflow || self.bytes + len > self.cap {
+            self.overflow = true;
+            return None;
+        }
+
+        if self.seq == 0 {
+            STREAMS.fetch_add(1, Ordering::Relaxed);
+        }
+        CHUNKS.fetch_add(1, Ordering::Relaxed);
+
+        let seq = self.seq;
+        self.bytes += len;
+        self.seq += 1;
+        Some(seq)
+    }
+
+    /// Accounts for the last response, sent out when the invocation completes.
+    ///
+    /// # Arguments
+    ///
+    /// * `len`: The length of the response's payload.
+    ///
+    /// # Return
+    ///
+    /// The flags and position to stamp onto the response, and StatusStreamTooLarge if the
+    /// stream went over it's cap.
+    pub fn finish(&mut self, len: usize) -> (u8, u32, Result<(), RpcStatus>) {
+        if self.seq == 0 && !self.overflow {
+            return (0, 0, Ok(()));
+        }
+
+        if self.overflow || self.bytes + len > self.cap {
+            REFUSED.fetch_add(1, Ordering::Relaxed);
+            return (
+                STREAM_FLAG_END,
+                self.seq,
+                Err(RpcStatus::StatusStreamTooLarge),
+            );
+        }
+
+        self.bytes += len;
+        (STREAM_FLAG_END, self.seq, Ok(()))
+    }
+}
+
+// This module contains tests for Stream.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Tests that partial responses are numbered in order, and that the last one ends the
+    // stream.
+    #[test]
+    fn test_stream_order() {
+        let mut stream = Stream::new(1000);
+        assert!(!stream.started());
+        assert_eq!(Some(0), stream.flush(100));
+        assert!(stream.started());
+        assert_eq!(Some(1), stream.flush(0));
+        assert_eq!(Some(2), stream.flush(300));
+        assert_eq!((STREAM_FLAG_END, 3, Ok(())), stream.finish(600));
+    }
+
+    // Tests that a result that is never flushed goes out as a plain response, whatever it's
+    // length.
+    #[test]
+    fn test_stream_unflushed() {
+        let mut stream = Stream::new(10);
+        assert_eq!((0, 0, Ok(())), stream.finish(100));
+        assert!(!stream.started());
+    }
+
+    // Tests that the cap covers every response including the last, and that nothing more goes
+    // out once a flush is refused.
+    #[test]
+    fn test_stream_cap() {
+        let before = stats();
+
+        let mut stream = Stream::new(1000);
+        assert_eq!(Some(0), stream.flush(600));
+        assert_eq!(None, stream.flush(401));
+        assert_eq!(None, stream.flush(1));
+        assert_eq!(
+            (STREAM_FLAG_END, 1, Err(RpcStatus::StatusStreamTooLarge)),
+            stream.finish(0)
+        );
+
+        let mut stream = Stream::new(1000);
+        assert_eq!(Some(0), stream.flush(600));
+        assert_eq!(Some(1), stream.flush(400));
+        assert_eq!(
+            (STREAM_FLAG_END, 2, Err(RpcStatus::StatusStreamTooLarge)),
+            stream.finish(1)
+        );
+
+        // A stream refused on it's very first flush still ends with the failure.
+        let mut stream = Stream::new(10);
+        assert_eq!(None, stream.flush(11));
+        assert_eq!(
+            (STREAM_FLAG_END, 0, Err(RpcStatus::StatusStreamTooLarge)),
+            stream.finish(0)
+        );
+
+        let after = stats();
+        assert!(after.streams >= before.streams + 2);
+        assert!(after.chunks >= before.chunks + 3);
+        assert!(after.refused >= before.refused + 3);
+    }
+}
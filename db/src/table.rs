@@ -16,10 +16,26 @@
 use hashbrown::HashMap;
 
 use spin::{RwLock, RwLockReadGuard, RwLockWriteGuard};
-use bytes::{Bytes};
-use std::sync::atomic::{AtomicU64, Ordering};
-use std::ops::Deref;
+use bytes::{Bytes, BytesMut};
+use std::cell::RefCell;
+use std::cmp::max;
+use std::collections::BTreeSet;
+use std::mem::size_of;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::ops::{Bound, Deref};
+use std::sync::Arc;
 
+use sandstorm::ext::Extension;
+
+use super::amplify::WriteStats;
+use super::compress::Compression;
+use super::cycles;
+use super::dedup::{Dedup, DedupStats, Share};
+use super::evict::{Cache, CacheStats, CacheTarget, EVICT_BATCH};
+use super::hint;
+use super::hot;
+use super::image::ReadOnlyTable;
+use super::presize::Sizing;
 use super::tx::{TX};
 use super::wireformat::{Record};
 
@@ -32,7 +48,45 @@ use super::wireformat::{Record};
 //     32 buckets: 14.4 Million ops/s (read-only), 7.2 Million ops/s (50-50)
 //     64 buckets: 17.0 Million ops/s (read-only)
 //    128 buckets: 18.5 Million ops/s (read-only), 12.3 Million ops/s (50-50)
-const N_BUCKETS : usize = 128;
+/// The number of buckets a table is split into.
+pub const N_BUCKETS : usize = 128;
+
+/// The largest object, in bytes, that a table with inlining enabled holds
+/// inside it's index instead of on the heap. Fits a 30 byte key along with
+/// a 48 byte value.
+pub const INLINE_CAP: usize = 96;
+
+// The size of each core's buffer that inlined objects are copied out into on
+// a read. A new one is allocated once it fills up; the old one is freed once
+// every object copied into it is dropped.
+const SCRATCH_SIZE: usize = 16 * 1024;
+
+thread_local! {
+    // The buffer inlined objects read on this core are copied out into.
+    static SCRATCH: RefCell<BytesMut> = RefCell::new(BytesMut::new());
+}
+
+// Counters reported by inline_stats(). Shared by all cores.
+static INLINED: AtomicUsize = AtomicUsize::new(0);
+static HEAPED: AtomicUsize = AtomicUsize::new(0);
+
+/// Counters of the objects written to tables since the server started.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct InlineStats {
+    /// The number of objects held inside a table's index.
+    pub inlined: usize,
+
+    /// The number of objects held on the heap.
+    pub heap: usize,
+}
+
+/// Returns the counters of objects written inline and to the heap.
+pub fn inline_stats() -> InlineStats {
+    InlineStats {
+        inlined: INLINED.load(Ordering::Relaxed),
+        heap: HEAPED.load(Ordering::Relaxed),
+    }
+}
 
 #[derive(Copy,Clone,PartialEq)]
 /// Each Entry in a Table has an associated Version that is per-key monotonic.
@@ -40,6 +94,13 @@ const N_BUCKETS : usize = 128;
 /// with a key has changed.
 pub struct Version(u64);
 
+impl Version {
+    /// Returns the version as a number, as it is sent to clients.
+    pub fn number(&self) -> u64 {
+        self.0
+    }
+}
+
 #[derive(Clone)]
 /// An Entry in a Table which stores metadata about the stored value and a smart
 /// pointer to the value itself.
@@ -47,8 +108,13 @@ pub struct Entry {
   /// A unique, per-table-key monotonic id for the value associated with this
   /// verison.
   pub version: Version,
-  /// A ref-counted smart pointer to a stored value.
+  /// A ref-counted smart pointer to a stored value. Points to a copy if the
+  /// value is inlined in the table, or shares it's value with other objects.
   pub value: Bytes,
+  /// The number of reads counted against the object since it was written,
+  /// including the one that returned this entry. 0 unless reads are counted;
+  /// refer to hint::set_hot_reads() and Table::set_count_reads().
+  pub reads: usize,
 }
 
 
@@ -56,7 +122,144 @@ type Decision = Result<(), ()>;
 const COMMIT: Decision = Result::Ok(());
 const ABORT: Decision = Result::Err(());
 
-type Map = HashMap<Bytes, Entry>;
+/// An object to be written to a table.
+pub enum Object {
+    /// The entire object, in one allocation.
+    Whole(Bytes),
+
+    /// The object's metadata and key, followed by it's value, held apart so
+    /// that the value can be shared with other objects. Refer to Dedup.
+    Split(Bytes, Share),
+}
+
+impl Object {
+    // Returns the length of the entire object.
+    fn len(&self) -> usize {
+        match *self {
+            Object::Whole(ref object) => object.len(),
+            Object::Split(ref head, ref share) => head.len() + share.value().len(),
+        }
+    }
+}
+
+// An object held by a table's index. Inlined objects are copied out on every
+// read instead of being handed out by reference (refer to Slot::entry()), so
+// that a reader never holds on to memory inside a bucket that a writer can
+// modify under it. Objects this small are cheaper to copy than to chase a
+// pointer to. Split objects are copied out too, so that readers always get
+// the entire object in one piece.
+enum Stored {
+    // A ref-counted handle to an object on the heap.
+    Heap(Bytes),
+
+    // An object of upto INLINE_CAP bytes, along with it's length.
+    Inline(u8, [u8; INLINE_CAP]),
+
+    // An object's metadata and key, along with a handle to it's shared value.
+    Shared(Bytes, Share),
+}
+
+impl Stored {
+    // Wraps an object up for a table's index, copying it inline if asked to.
+    // The caller must make sure that inlined objects fit in INLINE_CAP bytes.
+    // Split objects are never inlined.
+    fn new(object: Object, inline: bool) -> Stored {
+        let object = match object {
+            Object::Whole(object) => object,
+            Object::Split(head, share) => return Stored::Shared(head, share),
+        };
+
+        if !inline {
+            HEAPED.fetch_add(1, Ordering::Relaxed);
+            return Stored::Heap(object);
+        }
+
+        INLINED.fetch_add(1, Ordering::Relaxed);
+        let mut buf = [0; INLINE_CAP];
+        buf[..object.len()].copy_from_slice(&object[..]);
+        Stored::Inline(object.len() as u8, buf)
+    }
+
+    // Returns the length of the entire object.
+    fn len(&self) -> usize {
+        match *self {
+            Stored::Heap(ref object) => object.len(),
+            Stored::Inline(len, _) => len as usize,
+            Stored::Shared(ref head, ref share) => head.len() + share.value().len(),
+        }
+    }
+}
+
+// What a table's index maps each key to.
+struct Slot {
+    version: Version,
+    value: Stored,
+
+    // Set if the object failed to verify against it's checksum. Cleared when
+    // the object is overwritten.
+    quarantined: bool,
+
+    // The number of times the object was read by Table::get() since it was
+    // written. Only counted if hint::counting() or the table's count_reads
+    // is set.
+    reads: AtomicUsize,
+
+    // How hot the object was on the hot arena's last few passes over it.
+    // Refer to hot::fold().
+    heat: AtomicUsize,
+
+    // Set when the object is touched on a cache-mode table, and cleared by
+    // the eviction walk as it passes over the object. Refer to evict.
+    referenced: AtomicBool,
+}
+
+impl Slot {
+    // Returns an Entry for the object in this slot. The caller must hold the
+    // slot's bucket lock, since inlined objects are copied out here.
+    fn entry(&self) -> Entry {
+        let value = match self.value {
+            Stored::Heap(ref object) => object.clone(),
+
+            Stored::Inline(len, ref buf) => {
+                let len = len as usize;
+                SCRATCH.with(| scratch | {
+                    let mut scratch = scratch.borrow_mut();
+                    if scratch.capacity() - scratch.len() < len {
+                        *scratch = BytesMut::with_capacity(SCRATCH_SIZE);
+                    }
+
+                    scratch.extend_from_slice(&buf[..len]);
+                    scratch.split_to(len).freeze()
+                })
+            }
+
+            Stored::Shared(ref head, ref share) => {
+                let value = share.value();
+                let len = head.len() + value.len();
+                SCRATCH.with(| scratch | {
+                    let mut scratch = scratch.borrow_mut();
+                    if scratch.capacity() - scratch.len() < len {
+                        // Objects larger than the buffer get one of their own.
+                        *scratch = BytesMut::with_capacity(max(len, SCRATCH_SIZE));
+                    }
+
+                    scratch.extend_from_slice(&head[..]);
+                    scratch.extend_from_slice(&value[..]);
+                    scratch.split_to(len).freeze()
+                })
+            }
+        };
+
+        Entry { version: self.version, value: value, reads: 0 }
+    }
+}
+
+type Map = HashMap<Bytes, Slot>;
+
+/// The bytes of index each slot on a bucket takes: the key, the slot, and the
+/// byte the hash map keeps alongside each. Used to bound how large a table
+/// can be pre-sized; refer to presize::Presize.
+pub const SLOT_BYTES: usize = size_of::<(Bytes, Slot)>() + 1;
 
 /// This struct represents a single table in Sandstorm. A table is indexed using
 /// an unordered map, which hashes an object's key to it's value. Tables can be
@@ -79,6 +282,18 @@ pub struct Table {
     //        be dropped only when this ref-count goes to zero.
     maps: [RwLock<Map>; N_BUCKETS],
 
+    // The keys on each bucket in key order, so that scan() can walk the
+    // table from any key without sorting buckets. A bucket's keys are only
+    // indexed once it is first scanned, so tables that are never scanned
+    // don't pay for the index on writes. Only written with the bucket's lock
+    // held. Keys are copies, so that the index never holds on to an object
+    // through a key that slices into it.
+    keys: Vec<RwLock<Option<BTreeSet<Bytes>>>>,
+
+    // Set once the table is first scanned. Until then, writers don't look
+    // for an index to keep up to date.
+    scanned: AtomicBool,
+
     // Represents the highest version number of any entry that was removed from
     // map. This is used to ensure all future entries associated with that key
     // will have a higher version. An attempt is made to raise this value
@@ -86,6 +301,69 @@ pub struct Table {
     // into map.
     max_deleted_version: AtomicU64,
 
+    // The epoch the table is at. Set to it's tenant's epoch when the table is
+    // added to the tenant, and moved forward when it is truncated or dropped,
+    // so that a handle held past either can tell. Refer to epoch.rs.
+    epoch: AtomicU64,
+
+    // If set, values written to this table through Allocator::store() are
+    // compressed when large and compressible enough.
+    compression: Option<Compression>,
+
+    // If cleared, native get() and multiget() RPCs cannot read this table.
+    // Extensions can always read it.
+    readable_native: AtomicBool,
+
+    // If cleared, native put(), delete() and cas() RPCs cannot write to this table. Extensions
+    // can always write to it.
+    writable_native: AtomicBool,
+
+    // If set, objects written through Allocator::store() are indexed by a
+    // copy of their key instead of by a slice into the object.
+    split_keys: AtomicBool,
+
+    // If set, objects of upto INLINE_CAP bytes are held inside the index.
+    inline_values: AtomicBool,
+
+    // If set, objects written through Allocator::store() carry a checksum.
+    checksums: AtomicBool,
+
+    // If set, reads are counted against objects even if caching hints are
+    // off, so that the hot arena can tell which objects are hot.
+    count_reads: AtomicBool,
+
+    // If set, values written through Allocator::store() are shared with
+    // identical values already on the table instead of being copied.
+    dedup: RwLock<Option<Arc<Dedup>>>,
+
+    // The number of objects in the table that are quarantined. Lets reads
+    // skip looking for a quarantine on tables that have none.
+    quarantined: AtomicUsize,
+
+    // If set, the table is a read-only image mapped in from a file, and
+    // every lookup goes to it instead of to the buckets above.
+    image: Option<ReadOnlyTable>,
+
+    // The name of the extension that merges deltas into this table's values,
+    // along with the extension, resolved when it was registered.
+    merge: RwLock<Option<(Vec<u8>, Arc<Extension>)>>,
+
+    // The bytes written to this table through the Allocator and merges,
+    // against the bytes allocated for them.
+    writes: WriteStats,
+
+    // The time-stamp, in cycles, at which the table was created. The rate
+    // it is written at is measured from here; refer to hint::hint().
+    created: u64,
+
+    // The number of slots the table was pre-sized to, and the number of times
+    // one of it's buckets grew since. Refer to presize().
+    presized: AtomicUsize,
+    resizes: AtomicUsize,
+
+    // The capacity target of the table if it is in cache mode, along with
+    // the counters of the objects on it and evicted off it. Refer to evict.
+    cache: Cache,
 }
 
 // Implementation of the Default trait for Table.
@@ -160,13 +438,379 @@ impl Default for Table {
                    RwLock::new(HashMap::new()), RwLock::new(HashMap::new()),
                    RwLock::new(HashMap::new()), RwLock::new(HashMap::new()),
                 ],
+           keys: (0..N_BUCKETS).map(|_| RwLock::default()).collect(),
+           scanned: AtomicBool::new(false),
            max_deleted_version: AtomicU64::new(0),
+           epoch: AtomicU64::new(0),
+           compression: None,
+           readable_native: AtomicBool::new(true),
+           writable_native: AtomicBool::new(true),
+           split_keys: AtomicBool::new(false),
+           inline_values: AtomicBool::new(false),
+           checksums: AtomicBool::new(false),
+           count_reads: AtomicBool::new(false),
+           dedup: RwLock::new(None),
+           quarantined: AtomicUsize::new(0),
+           image: None,
+           merge: RwLock::new(None),
+           writes: WriteStats::default(),
+           created: cycles::rdtsc(),
+           presized: AtomicUsize::new(0),
+           resizes: AtomicUsize::new(0),
+           cache: Cache::default(),
         }
     }
 }
 
 // Implementation of Table
 impl Table {
+    /// This function returns an empty table whose values are compressed
+    /// according to the passed in settings.
+    ///
+    /// # Arguments
+    ///
+    /// * `compression`: The compression settings for the table. None disables
+    ///                  compression.
+    pub fn with_compression(compression: Option<Compression>) -> Table {
+        let mut table = Table::default();
+        table.compression = compression;
+        table
+    }
+
+    /// This function returns a table backed by a read-only image. Objects are
+    /// read straight out of the image without being copied, and the table
+    /// can never be written to; Tenant::writable_table() turns writers away
+    /// with StatusReadOnlyTable.
+    ///
+    /// # Arguments
+    ///
+    /// * `image`: The image, mapped in and validated.
+    pub fn from_image(image: ReadOnlyTable) -> Table {
+        let mut table = Table::default();
+        table.image = Some(image);
+        table
+    }
+
+    /// This function returns true if the table is backed by a read-only image.
+    pub fn read_only(&self) -> bool {
+        self.image.is_some()
+    }
+
+    /// This function returns the epoch the table is at. Refer to epoch.rs.
+    pub fn epoch(&self) -> u64 {
+        self.epoch.load(Ordering::Relaxed)
+    }
+
+    /// This function moves the table to an epoch.
+    ///
+    /// # Arguments
+    ///
+    /// * `epoch`: The epoch, drawn off the server's clock by epoch::Epochs::advance().
+    pub fn set_epoch(&self, epoch: u64) {
+        self.epoch.store(epoch, Ordering::Relaxed);
+    }
+
+    /// This function returns the compression settings of the table, if any.
+    pub fn compression(&self) -> Option<&Compression> {
+        self.compression.as_ref()
+    }
+
+    /// This function sets whether the table can be accessed by native RPCs
+    /// from the network. The change applies to every request parsed after
+    /// this call returns. Extensions can access the table either way.
+    ///
+    /// # Arguments
+    ///
+    /// * `readable`: If false, native get() and multiget() RPCs are refused.
+    /// * `writable`: If false, native put(), delete() and cas() RPCs are refused.
+    pub fn set_native_access(&self, readable: bool, writable: bool) {
+        self.readable_native.store(readable, Ordering::Release);
+        self.writable_native.store(writable, Ordering::Release);
+    }
+
+    /// This function returns true if native RPCs can read the table.
+    pub fn readable_native(&self) -> bool {
+        self.readable_native.load(Ordering::Acquire)
+    }
+
+    /// This function returns true if native RPCs can write to the table.
+    pub fn writable_native(&self) -> bool {
+        self.writable_native.load(Ordering::Acquire)
+    }
+
+    /// This function sets whether objects written to the table from here on
+    /// are indexed by a copy of their key held apart from the object. Lookups
+    /// then compare keys without touching objects, and an overwritten object
+    /// is freed even though it's key stays in the table. Objects written
+    /// before the change are left as they are; both kinds resolve the same.
+    ///
+    /// # Arguments
+    ///
+    /// * `split`: True if keys should be held apart from objects.
+    pub fn set_split_keys(&self, split: bool) {
+        self.split_keys.store(split, Ordering::Release);
+    }
+
+    /// This function returns true if keys are held apart from objects.
+    pub fn split_keys(&self) -> bool {
+        self.split_keys.load(Ordering::Acquire)
+    }
+
+    /// This function sets whether objects of upto INLINE_CAP bytes written
+    /// to the table from here on are held inside it's index, instead of on
+    /// the heap. A read copies an inlined object out. Objects move between
+    /// the index and the heap as they are overwritten with ones of a
+    /// different size.
+    ///
+    /// # Arguments
+    ///
+    /// * `inline`: True if small objects should be held inside the index.
+    pub fn set_inline_values(&self, inline: bool) {
+        self.inline_values.store(inline, Ordering::Release);
+    }
+
+    /// This function returns true if small objects are held inside the index.
+    pub fn inline_values(&self) -> bool {
+        self.inline_values.load(Ordering::Acquire)
+    }
+
+    /// This function sets whether objects written to the table from here on
+    /// carry a checksum, which integrity::Sweep and verified reads check them
+    /// against. Objects written before the change are left as they are, and
+    /// are never checked.
+    ///
+    /// # Arguments
+    ///
+    /// * `checksums`: True if objects should carry a checksum.
+    pub fn set_checksums(&self, checksums: bool) {
+        self.checksums.store(checksums, Ordering::Release);
+    }
+
+    /// This function returns true if objects written to the table carry a
+    /// checksum.
+    pub fn checksums(&self) -> bool {
+        self.checksums.load(Ordering::Acquire)
+    }
+
+    /// This function sets whether reads are counted against the table's
+    /// objects even if caching hints are off, as the hot arena needs them to
+    /// be. Reads made before counting was turned on are not counted.
+    ///
+    /// # Arguments
+    ///
+    /// * `count`: True if reads should be counted.
+    pub fn set_count_reads(&self, count: bool) {
+        self.count_reads.store(count, Ordering::Relaxed);
+    }
+
+    /// This function sets whether values written to the table from here on
+    /// share memory with identical values already on it, instead of each
+    /// object holding it's own copy. Values are matched through an index
+    /// of the values recently written (refer to Dedup). Objects written
+    /// before the change keep their values, shared or not; turning sharing
+    /// off only forgets the index.
+    ///
+    /// # Arguments
+    ///
+    /// * `entries`: The most values the index remembers. 0 means the
+    ///              default. None turns sharing off.
+    pub fn set_dedup(&self, entries: Option<usize>) {
+        let mut dedup = self.dedup.write();
+        let keep = match (entries, dedup.as_ref()) {
+            (Some(entries), Some(current)) => Dedup::new(entries).cap() == current.cap(),
+            _ => false,
+        };
+        if !keep {
+            *dedup = entries.map(| entries | Arc::new(Dedup::new(entries)));
+        }
+    }
+
+    /// This function returns the table's dedup index, if values written to
+    /// it are shared.
+    pub fn dedup(&self) -> Option<Arc<Dedup>> {
+        self.dedup.read().as_ref().map(| dedup | Arc::clone(dedup))
+    }
+
+    /// This function returns the counters of the table's dedup index, if
+    /// values written to it are shared.
+    pub fn dedup_stats(&self) -> Option<DedupStats> {
+        self.dedup.read().as_ref().map(| dedup | dedup.stats())
+    }
+
+    /// This function quarantines an object that failed to verify against
+    /// it's checksum, so that reads fail instead of returning it. The
+    /// quarantine lasts until the object is overwritten or deleted.
+    ///
+    /// # Arguments
+    ///
+    /// * `key`:     The key of the object.
+    /// * `version`: The version of the object that failed to verify.
+    ///
+    /// # Return
+    ///
+    /// True if the object was quarantined. False if it no longer exists, or
+    /// was overwritten since it was read.
+    pub fn quarantine(&self, key: &[u8], version: Version) -> bool {
+        if self.read_only() {
+            return false;
+        }
+
+        let mut map = self.maps[Self::bucket(key)].write();
+        let slot = match map.get_mut(key) {
+            Some(slot) => slot,
+            None => return false,
+        };
+        if slot.version != version {
+            return false;
+        }
+
+        if !slot.quarantined {
+            slot.quarantined = true;
+            self.quarantined.fetch_add(1, Ordering::Relaxed);
+        }
+        true
+    }
+
+    /// This function returns true if the object under a key is quarantined.
+    pub fn is_quarantined(&self, key: &[u8]) -> bool {
+        if self.quarantined.load(Ordering::Relaxed) == 0 {
+            return false;
+        }
+
+        let map = self.maps[Self::bucket(key)].read();
+        map.get(key).map_or(false, | slot | slot.quarantined)
+    }
+
+    /// This function returns the number of objects in the table that are
+    /// quarantined.
+    pub fn quarantined(&self) -> usize {
+        self.quarantined.load(Ordering::Relaxed)
+    }
+
+    /// This function registers the extension that merge() RPCs and
+    /// DB::merge() use to combine this table's values with deltas. The
+    /// extension is resolved once, by the caller; loading a new extension
+    /// under the same name does not change the table's merge extension until
+    /// it is registered again.
+    ///
+    /// # Arguments
+    ///
+    /// * `merge`: The extension's name and the extension. None clears it.
+    pub fn set_merge(&self, merge: Option<(Vec<u8>, Arc<Extension>)>) {
+        *self.merge.write() = merge;
+    }
+
+    /// This function returns the table's merge extension, if one is
+    /// registered.
+    pub fn merge_extension(&self) -> Option<Arc<Extension>> {
+        self.merge.read().as_ref().map(| &(_, ref ext) | Arc::clone(ext))
+    }
+
+    /// This function returns the name of the table's merge extension, if one
+    /// is registered.
+    pub fn merge_name(&self) -> Option<Vec<u8>> {
+        self.merge.read().as_ref().map(| &(ref name, _) | name.clone())
+    }
+
+    /// This function returns the accounting of the bytes written to the
+    /// table against the bytes allocated for them. Writes are accounted for
+    /// by Allocator::store(), Allocator::store_many() and merges; writes
+    /// straight to the table through put() are not.
+    pub fn writes(&self) -> &WriteStats {
+        &self.writes
+    }
+
+    /// This function returns the time-stamp, in cycles, at which the table
+    /// was created.
+    pub fn created(&self) -> u64 {
+        self.created
+    }
+
+    /// This function reserves room on the table for a number of slots, spread
+    /// evenly across it's buckets, so that the objects expected on it are
+    /// written without the buckets growing under them. Slots already taken
+    /// count towards the room. Tables backed by an image are left alone.
+    ///
+    /// # Arguments
+    ///
+    /// * `slots`: The number of slots, usually out of presize::Presize::slots().
+    pub fn presize(&self, slots: usize) {
+        if self.read_only() {
+            return;
+        }
+
+        let per_bucket = slots / N_BUCKETS;
+        for map in self.maps.iter() {
+            let mut map = map.write();
+            let len = map.len();
+            if per_bucket > len {
+                map.reserve(per_bucket - len);
+            }
+        }
+        self.presized.store(slots, Ordering::Relaxed);
+    }
+
+    /// This function returns how the table's index is sized, against the
+    /// objects it holds.
+    pub fn sizing(&self) -> Sizing {
+        let (mut capacity, mut objects) = (0, 0);
+        for map in self.maps.iter() {
+            let map = map.read();
+            capacity += map.capacity() as u64;
+            objects += map.len() as u64;
+        }
+
+        Sizing {
+            presized: self.presized.load(Ordering::Relaxed) as u64,
+            capacity: capacity,
+            objects: objects,
+            resizes: self.resizes.load(Ordering::Relaxed) as u64,
+        }
+    }
+
+    /// This function puts the table in cache mode, bounding it to a capacity
+    /// target it evicts objects to stay under, or takes it out of cache mode.
+    /// Objects already on the table are counted against the target, and
+    /// evicted right away if they exceed it; objects written while they are
+    /// being counted may be counted twice or not at all, so a target is best
+    /// set on an empty table. Tables backed by an image are left alone.
+    ///
+    /// # Arguments
+    ///
+    /// * `target`: The capacity target, or None to stop evicting objects.
+    pub fn set_cache(&self, target: Option<CacheTarget>) {
+        if self.read_only() {
+            return;
+        }
+
+        let (mut bytes, mut objects) = (0, 0);
+        if target.is_some() {
+            for map in self.maps.iter() {
+                let map = map.read();
+                for slot in map.values() {
+                    slot.referenced.store(true, Ordering::Relaxed);
+                    bytes += slot.value.len();
+                    objects += 1;
+                }
+            }
+        }
+        self.cache.set(target, bytes, objects);
+
+        while self.cache.over(0, 0) && self.evict() > 0 {}
+    }
+
+    /// This function returns the capacity target of the table, if it is in
+    /// cache mode.
+    pub fn cache_target(&self) -> Option<CacheTarget> {
+        self.cache.target()
+    }
+
+    /// This function returns the counters of the objects on the table and
+    /// evicted off it, if it is in cache mode.
+    pub fn cache_stats(&self) -> Option<CacheStats> {
+        self.cache.stats()
+    }
+
     /// This function reads an object from a table.
     ///
     /// # Arguments
@@ -180,11 +824,48 @@ impl Table {
     /// is guaranteed to exist atleast until the returned Bytes is dropped.
     /// If the object does not exist in the Table, this method returns None.
     pub fn get(&self, key: &[u8]) -> Option<Entry> {
+        // Objects on an image never change, so they all have the same version.
+        if let Some(ref image) = self.image {
+            return image.get(key).map(| object | {
+                Entry { version: Version(1), value: object, reads: 0 }
+            });
+        }
+
         // First, identify the bucket the key falls into.
         let map = self.maps[Self::bucket(key)].read();
 
-        // Perform the lookup, and return.
-        return map.get(key).and_then(| entry | { Some((*entry).clone()) });
+        // Perform the lookup, and return. The read is counted against the
+        // object if caching hints or the hot arena are on, and touches it if
+        // the table evicts the least recently read objects.
+        return map.get(key).and_then(| slot | {
+            let mut entry = slot.entry();
+            if hint::counting() || self.count_reads.load(Ordering::Relaxed) {
+                entry.reads = slot.reads.fetch_add(1, Ordering::Relaxed) + 1;
+            }
+            if self.cache.touch_reads() {
+                slot.referenced.store(true, Ordering::Relaxed);
+            }
+            Some(entry)
+        });
+    }
+
+    /// This function returns the version of the object under a key, without
+    /// reading the object.
+    ///
+    /// # Arguments
+    ///
+    /// * `key`: A slice of bytes over the key to be looked up.
+    ///
+    /// # Return
+    ///
+    /// The version of the object if one exists, None otherwise.
+    pub fn version(&self, key: &[u8]) -> Option<Version> {
+        if let Some(ref image) = self.image {
+            return image.get(key).map(| _ | Version(1));
+        }
+
+        let map = self.maps[Self::bucket(key)].read();
+        map.get(key).map(| slot | slot.version)
     }
 
     /// This function writes an object into a table.
@@ -194,23 +875,205 @@ impl Table {
     /// * `key`:    A Bytes wrapping the key for the object.
     /// * `object`: A Bytes wrapping the entire object to be written to
     ///             the table.
+    ///
+    /// # Return
+    ///
+    /// The entry written, whether it replaced an object or was inserted.
+    /// None if the table cannot be written to.
     pub fn put(&self, key: Bytes, value: Bytes) -> Option<Entry> {
-        // First, identify the bucket the key falls into.
-        let mut map = self.maps[Self::bucket(&key[..])].write();
+        self.put_object(key, Object::Whole(value))
+    }
+
+    /// This function writes an object into a table. Refer to put().
+    ///
+    /// # Arguments
+    ///
+    /// * `key`:    A Bytes wrapping the key for the object.
+    /// * `object`: The object to be written to the table, whole or split.
+    pub fn put_object(&self, key: Bytes, value: Object) -> Option<Entry> {
+        if self.read_only() {
+            return None;
+        }
+
+        let inline = value.len() <= INLINE_CAP && self.inline_values();
+
+        // First, identify the bucket the key falls into. Objects are only
+        // evicted once the bucket's lock is released.
+        let entry = {
+            let mut map = self.maps[Self::bucket(&key[..])].write();
+            Some(self.install(&mut map, key, value, inline))
+        };
+        self.maintain();
+        entry
+    }
+
+    /// This function writes a batch of objects into a table. The lock on
+    /// every bucket the batch falls into is acquired, in order of bucket,
+    /// before any object is written, and is only released once all of them
+    /// are. A concurrent reader sees either none or all of the batch.
+    ///
+    /// # Arguments
+    ///
+    /// * `objects`: The key and entire object of each object to be written,
+    ///              in the order they should be written in.
+    ///
+    /// # Return
+    ///
+    /// What put() would have returned for each object, in order, or None if
+    /// the table cannot be written to.
+    pub fn put_many(&self, objects: Vec<(Bytes, Bytes)>) -> Option<Vec<Option<Entry>>> {
+        self.put_objects(objects.into_iter()
+                                .map(| (key, value) | (key, Object::Whole(value)))
+                                .collect())
+    }
+
+    /// This function writes a batch of objects into a table. Refer to
+    /// put_many().
+    ///
+    /// # Arguments
+    ///
+    /// * `objects`: The key and object, whole or split, of each object to be
+    ///              written, in the order they should be written in.
+    pub fn put_objects(&self, objects: Vec<(Bytes, Object)>) -> Option<Vec<Option<Entry>>> {
+        if self.read_only() {
+            return None;
+        }
 
-        if let Some(entry) = map.get_mut(&key) {
+        let inline = self.inline_values();
+
+        // Locking buckets in a fixed order means that two batches, or a batch
+        // and a transaction being validated, never deadlock on each other.
+        let mut buckets: Vec<usize> = objects.iter()
+                                             .map(| &(ref key, _) | Self::bucket(&key[..]))
+                                             .collect();
+        buckets.sort();
+        buckets.dedup();
+        let mut maps: Vec<RwLockWriteGuard<Map>> = buckets.iter()
+                                                          .map(| &b | self.maps[b].write())
+                                                          .collect();
+
+        let mut entries = Vec::with_capacity(objects.len());
+        for (key, value) in objects.into_iter() {
+            let i = buckets.binary_search(&Self::bucket(&key[..])).unwrap();
+            let inline = value.len() <= INLINE_CAP && inline;
+            entries.push(Some(self.install(&mut maps[i], key, value, inline)));
+        }
+
+        drop(maps);
+        self.maintain();
+        Some(entries)
+    }
+
+    /// This function replaces an object with one computed from it. The lock
+    /// on the object's bucket is held from the read until the write, so no
+    /// other write to the object interleaves with the update. Since the lock
+    /// is a spin lock, `update` should be short.
+    ///
+    /// # Arguments
+    ///
+    /// * `key`:    The key of the object. Must not be empty.
+    /// * `update`: Called on the current object (None if there isn't one),
+    ///             and returns the key and entire new object to be written,
+    ///             or an error if nothing should be written.
+    ///
+    /// # Return
+    ///
+    /// What put() would have returned, or the error returned by `update`.
+    /// None if the table cannot be written to.
+    pub fn update<F, E>(&self, key: &[u8], update: F) -> Option<Result<Option<Entry>, E>>
+        where F: FnOnce(Option<Entry>) -> Result<(Bytes, Bytes), E>
+    {
+        if self.read_only() {
+            return None;
+        }
+
+        let updated = {
+            let mut map = self.maps[Self::bucket(key)].write();
+            let old = map.get(key).map(| slot | slot.entry());
+            update(old).map(| (key, value) | {
+                let inline = value.len() <= INLINE_CAP && self.inline_values();
+                Some(self.install(&mut map, key, Object::Whole(value), inline))
+            })
+        };
+        self.maintain();
+        Some(updated)
+    }
+
+    // Writes an object into the bucket it falls into. The caller must hold
+    // the bucket's write lock, and make sure that inlined objects fit.
+    fn install(&self, map: &mut Map, key: Bytes, value: Object, inline: bool) -> Entry {
+        let (len, caching) = (value.len(), self.cache.on());
+
+        // An object moving inline or sharing it's value must not leave the
+        // index holding on to it's old heap object through a key that slices
+        // into it.
+        let split = match value {
+            Object::Split(..) => true,
+            Object::Whole(_) => false,
+        };
+        let rekey = match map.get(&key) {
+            Some(&Slot { value: Stored::Heap(_), .. }) => inline || split,
+            _ => false,
+        };
+        if rekey {
+            if let Some(slot) = map.remove(&key) {
+                map.insert(Bytes::from(&key[..]), slot);
+            }
+        }
+
+        if let Some(slot) = map.get_mut(&key) {
             // If an entry already exists, then update it (we are holding a
             // bucket lock).
-            entry.value = value;
-            entry.version.0 += 1;
-            return Some(entry.clone());
+            if caching {
+                self.cache.written(len, slot.value.len(), true);
+                slot.referenced.store(true, Ordering::Relaxed);
+            }
+            slot.value = Stored::new(value, inline);
+            slot.version.0 += 1;
+            slot.reads = AtomicUsize::new(0);
+            if slot.quarantined {
+                slot.quarantined = false;
+                self.quarantined.fetch_sub(1, Ordering::Relaxed);
+            }
+            return slot.entry();
         }
 
         // If an entry does not exist we need to insert it while making
         // sure that its version number is higher than any version that
         // could have previously been associated with this key.
         let version = Version(self.max_deleted_version.load(Ordering::Relaxed) + 1);
-        return map.insert(key, Entry{version, value});
+        let key = match inline {
+            true => Bytes::from(&key[..]),
+            false => key,
+        };
+        let value = Stored::new(value, inline);
+        let (reads, heat) = (AtomicUsize::new(0), AtomicUsize::new(0));
+        let referenced = AtomicBool::new(caching);
+        if caching {
+            self.cache.written(len, 0, false);
+        }
+
+        self.index(Self::bucket(&key[..]), |keys| {
+            keys.insert(Bytes::from(&key[..]));
+        });
+
+        // Count the insert if it grew the bucket, so that tables that were
+        // not sized for their objects show up in sizing().
+        let capacity = map.capacity();
+        let slot = Slot {
+            version,
+            value,
+            quarantined: false,
+            reads,
+            heat,
+            referenced,
+        };
+        let entry = slot.entry();
+        map.insert(key, slot);
+        if map.capacity() > capacity {
+            self.resizes.fetch_add(1, Ordering::Relaxed);
+        }
+        return entry;
     }
 
     /// This function deletes an object from a table.
@@ -218,12 +1081,25 @@ impl Table {
     /// # Arguments
     ///
     /// * `key`: The key of the object to be deleted, passed in as a slice of bytes.
-    pub fn delete(&self, key: &[u8]) {
+    ///
+    /// # Return
+    ///
+    /// True if the object was deleted. False if it does not exist, or if the
+    /// table is read-only.
+    pub fn delete(&self, key: &[u8]) -> bool {
+        if self.read_only() {
+            return false;
+        }
+
         // First, identify the bucket the key falls into.
         let mut map = self.maps[Self::bucket(&key[..])].write();
 
         // Next, remove the key from the hash map if it already exists.
         if let Some(entry) = map.remove(key) {
+            self.index(Self::bucket(key), |keys| {
+                keys.remove(key);
+            });
+
             // Record the version number so we never use a lower version for any
             // future value associated with the key.
 
@@ -234,14 +1110,349 @@ impl Table {
             // on the removed entry. That invariant has to be maintained .
 
             self.max_deleted_version.fetch_max(entry.version.0, Ordering::Relaxed);
+            if entry.quarantined {
+                self.quarantined.fetch_sub(1, Ordering::Relaxed);
+            }
+            if self.cache.on() {
+                self.cache.removed(entry.value.len(), false);
+            }
+            return true;
+        }
+
+        false
+    }
+
+    // Evicts objects off a cache-mode table that a write took over it's
+    // target. Does nothing on any other table. The caller must not hold any
+    // bucket lock.
+    #[inline]
+    fn maintain(&self) {
+        if self.cache.on() && self.cache.over(0, 0) {
+            self.evict();
+        }
+    }
+
+    // Evicts a batch of atmost EVICT_BATCH objects off a cache-mode table,
+    // stopping as soon as it is back under it's target. Buckets are visited
+    // one at a time from the one the last eviction stopped at, for atmost two
+    // passes over the table: one that clears the bits of objects that were
+    // touched, and one that evicts them if they were not touched since. An
+    // object is removed the way delete() removes it, and it's memory is only
+    // released once the bucket's lock is. Returns the number evicted.
+    fn evict(&self) -> usize {
+        let mut evicted = 0;
+        let mut victims = Vec::new();
+
+        for _ in 0..(2 * N_BUCKETS) {
+            if evicted == EVICT_BATCH || !self.cache.over(0, 0) {
+                break;
+            }
+
+            // Pick the objects first, counting them against the target until
+            // they are removed, so that no more are picked than needed.
+            let mut map = self.maps[self.cache.advance(N_BUCKETS)].write();
+            let (mut keys, mut bytes) = (Vec::new(), 0);
+            for (key, slot) in map.iter() {
+                if evicted + keys.len() == EVICT_BATCH || !self.cache.over(bytes, keys.len()) {
+                    break;
+                }
+                if slot.referenced.swap(false, Ordering::Relaxed) {
+                    continue;
+                }
+                bytes += slot.value.len();
+                keys.push(key.clone());
+            }
+
+            for key in keys.into_iter() {
+                if let Some(slot) = map.remove(&key) {
+                    self.index(Self::bucket(&key[..]), |keys| {
+                        keys.remove(&key[..]);
+                    });
+                    let version = slot.version.0;
+                    self.max_deleted_version.fetch_max(version, Ordering::Relaxed);
+                    if slot.quarantined {
+                        self.quarantined.fetch_sub(1, Ordering::Relaxed);
+                    }
+                    self.cache.removed(slot.value.len(), true);
+                    victims.push((key, slot));
+                    evicted += 1;
+                }
+            }
         }
+
+        drop(victims);
+        evicted
     }
 
-    fn bucket(key: &[u8]) -> usize {
+    /// This function removes every object from a table, each exactly as
+    /// delete() would. Handles to objects that were already handed out
+    /// remain valid. Objects written while the table is being truncated may
+    /// or may not be removed.
+    ///
+    /// # Return
+    ///
+    /// The number of objects removed.
+    pub fn truncate(&self) -> usize {
+        let mut removed = 0;
+        for bucket in 0..N_BUCKETS {
+            let keys: Vec<Bytes> = self.maps[bucket].read().keys().cloned().collect();
+            for key in keys.iter() {
+                self.delete(key);
+                removed += 1;
+            }
+        }
+        removed
+    }
+
+    /// This function returns handles to every entry in one bucket of a table. The
+    /// bucket lock is held only while the handles are collected, so the caller
+    /// can take it's time with them without blocking writers.
+    ///
+    /// # Arguments
+    ///
+    /// * `bucket`: The index of the bucket, less than N_BUCKETS.
+    ///
+    /// # Return
+    ///
+    /// The entries in the bucket, in no particular order. On a table backed
+    /// by an image, they are in key order.
+    pub fn bucket_entries(&self, bucket: usize) -> Vec<Entry> {
+        if let Some(ref image) = self.image {
+            return image.bucket(bucket).into_iter()
+                        .map(| object | Entry { version: Version(1), value: object, reads: 0 })
+                        .collect();
+        }
+
+        let map = self.maps[bucket].read();
+        map.values().map(|slot| slot.entry()).collect()
+    }
+
+    /// This function returns handles to the objects in a table in key order,
+    /// starting from a bound. Keys are ordered byte by byte. Buckets are
+    /// locked one at a time, so objects written while a table is being
+    /// scanned may or may not be returned. Quarantined objects are skipped.
+    /// Takes time proportional to the number of objects returned, not the
+    /// size of the table, except for the first scan of a bucket, which
+    /// indexes the keys on it.
+    ///
+    /// # Arguments
+    ///
+    /// * `from`: Only objects with keys within this bound are returned.
+    /// * `max`:  The most objects returned.
+    ///
+    /// # Return
+    ///
+    /// The key and entry of atmost `max` objects, the ones with the smallest
+    /// keys within the bound, in key order.
+    pub fn scan(&self, from: Bound<&[u8]>, max: usize) -> Vec<(Bytes, Entry)> {
+        let first = match from {
+            Bound::Included(start) | Bound::Excluded(start) => start.first().map_or(0, |b| *b),
+            Bound::Unbounded => 0,
+        };
+
+        // Writers keep the index up to date from here on. Buckets are only
+        // indexed after this, under their lock, so none misses a write.
+        self.scanned.store(true, Ordering::Release);
+
+        // A key's bucket is picked by it's first byte, so the keys starting
+        // with a byte are all on one bucket, and sort together. The table is
+        // walked a first byte at a time.
+        let mut found: Vec<(Bytes, Entry)> = Vec::new();
+        for byte in (first as usize)..256 {
+            if found.len() >= max {
+                break;
+            }
+
+            let (lo, hi) = ([byte as u8], [(byte + 1) as u8]);
+            let lower = match byte == first as usize {
+                true => from,
+                false => Bound::Included(&lo[..]),
+            };
+            let upper = match byte {
+                255 => Bound::Unbounded,
+                _ => Bound::Excluded(&hi[..]),
+            };
+            let (bucket, want) = (byte & (N_BUCKETS - 1), max - found.len());
+
+            if let Some(ref image) = self.image {
+                found.extend(image.range(bucket, lower, upper, want).into_iter().map(
+                    |(key, object)| {
+                        (
+                            key,
+                            Entry {
+                                version: Version(1),
+                                value: object,
+                                reads: 0,
+                            },
+                        )
+                    },
+                ));
+                continue;
+            }
+
+            // The bucket is locked ahead of it's keys, like writers do.
+            let map = self.maps[bucket].read();
+            let keys = self.indexed(bucket, &map);
+            found.extend(
+                keys.as_ref()
+                    .expect("Bucket was not indexed")
+                    .range::<[u8], _>((lower, upper))
+                    .filter_map(|key| map.get(&key[..]).map(|slot| (key, slot)))
+                    .filter(|&(_, slot)| !slot.quarantined)
+                    .take(want)
+                    .map(|(key, slot)| (key.clone(), slot.entry())),
+            );
+        }
+
+        found
+    }
+
+    // Returns the index of a bucket's keys, building it if the bucket was
+    // never scanned before. The caller must hold the bucket's lock, so that
+    // no write to the bucket is missed while it's keys are indexed.
+    fn indexed(&self, bucket: usize, map: &Map) -> RwLockReadGuard<Option<BTreeSet<Bytes>>> {
+        if self.keys[bucket].read().is_none() {
+            let mut keys = self.keys[bucket].write();
+            if keys.is_none() {
+                *keys = Some(map.keys().map(|key| Bytes::from(&key[..])).collect());
+            }
+        }
+        self.keys[bucket].read()
+    }
+
+    // Applies a change to the index of a bucket's keys, if the bucket was
+    // scanned and has one. The caller must hold the bucket's write lock.
+    fn index<F: FnOnce(&mut BTreeSet<Bytes>)>(&self, bucket: usize, change: F) {
+        if !self.scanned.load(Ordering::Acquire) {
+            return;
+        }
+
+        if let Some(ref mut keys) = *self.keys[bucket].write() {
+            change(keys);
+        }
+    }
+
+    /// This function folds the reads counted against each object on one
+    /// bucket of a table into the object's heat, for the hot arena's walk.
+    /// Only objects held on the heap are considered; inlined and quarantined
+    /// objects, and objects sharing their value, are never moved. The bucket
+    /// lock is held only while the objects are collected.
+    ///
+    /// # Arguments
+    ///
+    /// * `bucket`:    The index of the bucket, less than N_BUCKETS.
+    /// * `threshold`: The number of reads after which an object is hot.
+    /// * `want`:      Called on each object along with it's streak (refer to
+    ///                hot::fold()), and returns true if it should be moved.
+    ///
+    /// # Return
+    ///
+    /// The key, version and object of each object that should be moved, to
+    /// be passed to relocate(). Empty on a table backed by an image.
+    pub fn bucket_heat<F>(
+        &self,
+        bucket: usize,
+        threshold: usize,
+        mut want: F,
+    ) -> Vec<(Bytes, Version, Bytes)>
+    where
+        F: FnMut(&Bytes, isize) -> bool,
+    {
+        if self.read_only() {
+            return Vec::new();
+        }
+
+        let map = self.maps[bucket].read();
+        let mut objects = Vec::new();
+        for (key, slot) in map.iter() {
+            let object = match slot.value {
+                Stored::Heap(ref object) if !slot.quarantined => object,
+                _ => continue,
+            };
+
+            let reads = slot.reads.load(Ordering::Relaxed);
+            let (heat, streak) = hot::fold(slot.heat.load(Ordering::Relaxed), reads, threshold);
+            slot.heat.store(heat, Ordering::Relaxed);
+            if want(object, streak) {
+                objects.push((key.clone(), slot.version, object.clone()));
+            }
+        }
+
+        objects
+    }
+
+    /// This function swaps an object for a copy of it, under the bucket's
+    /// write lock so that no write interleaves with the swap. The object
+    /// keeps it's version and the reads counted against it. Readers holding
+    /// on to the old object keep it alive until they drop it.
+    ///
+    /// # Arguments
+    ///
+    /// * `key`:     The key of the object.
+    /// * `version`: The version of the object the copy was made of.
+    /// * `object`:  The object the copy was made of.
+    /// * `copy`:    The copy, byte for byte.
+    ///
+    /// # Return
+    ///
+    /// True if the copy was swapped in. False if the object was written or
+    /// deleted since it was copied, in which case the copy is dropped.
+    pub fn relocate(&self, key: &[u8], version: Version, object: &Bytes, copy: Bytes) -> bool {
+        if self.read_only() {
+            return false;
+        }
+
+        let mut map = self.maps[Self::bucket(key)].write();
+        let (key, mut slot) = match map.remove_entry(key) {
+            Some(entry) => entry,
+            None => return false,
+        };
+
+        let same = match slot.value {
+            Stored::Heap(ref current) => {
+                slot.version == version
+                    && current.as_ptr() == object.as_ptr()
+                    && current.len() == object.len()
+            }
+            _ => false,
+        };
+        if !same {
+            map.insert(key, slot);
+            return false;
+        }
+
+        // A key that slices into the old object is moved onto the copy, so
+        // that the index does not hold on to the old object through it.
+        let (start, at) = (object.as_ptr() as usize, key.as_ptr() as usize);
+        let key = match at >= start && at + key.len() <= start + object.len() {
+            true => copy.slice(at - start, at - start + key.len()),
+            false => key,
+        };
+
+        slot.value = Stored::Heap(copy);
+        map.insert(key, slot);
+        true
+    }
+
+    /// This function returns the bucket a key falls into. Keys must not be
+    /// empty.
+    pub(crate) fn bucket(key: &[u8]) -> usize {
         key[0] as usize & (N_BUCKETS - 1)
     }
 
     pub fn validate(&self, tx: &mut TX) -> Decision {
+        // Records dropped off a full TX can't be validated.
+        if tx.truncated() {
+            return ABORT;
+        }
+
+        // Reads from an image are always valid, and it can't be written to.
+        if let Some(ref image) = self.image {
+            let valid = tx.writes().is_empty() &&
+                        tx.reads().iter().all(| r | image.get(&r.get_key()[..]).is_some());
+            return if valid { COMMIT } else { ABORT };
+        }
+
         tx.sort();
 
         enum Lock<'a> {
@@ -278,8 +1489,8 @@ impl Table {
             where Guard: Deref<Target = Map>
         {
             let map: &Map = &**guard;
-            if let Some(entry) = map.get(&record.get_key()[..]) {
-                if record.get_version() == entry.version {
+            if let Some(slot) = map.get(&record.get_key()[..]) {
+                if record.get_version() == slot.version {
                     COMMIT
                 } else {
                     ABORT
@@ -320,8 +1531,13 @@ impl Table {
 // test basic functionality like reference counting etc.
 #[cfg(test)]
 mod tests {
-    use super::Table;
+    use super::{inline_stats, Slot, Stored, Table, Version, INLINE_CAP, N_BUCKETS};
+    use super::super::evict::{CacheTarget, EvictPolicy, EVICT_BATCH};
     use bytes::{BufMut, Bytes, BytesMut};
+    use std::mem::size_of;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
 
     // This unit test inserts a key-value pair into a table, performs a read
     // on the key, and asserts that the value matches. If the key was not found,
@@ -368,6 +1584,23 @@ mod tests {
         assert_eq!(None, table.get(&[0; 30]));
     }
 
+    // This test verifies that put() returns the entry it wrote, both when
+    // the key is inserted and when it's object is replaced.
+    #[test]
+    fn test_put_entry() {
+        let table = Table::default();
+        let key = Bytes::from(vec![0; 30]);
+
+        let inserted = table.put(key.clone(), Bytes::from(vec![1; 30])).unwrap();
+        assert_eq!(&[1; 30][..], &inserted.value[..]);
+        let version = table.version(&key).unwrap().number();
+        assert_eq!(version, inserted.version.number());
+
+        let replaced = table.put(key.clone(), Bytes::from(vec![2; 30])).unwrap();
+        assert_eq!(&[2; 30][..], &replaced.value[..]);
+        assert_eq!(version + 1, replaced.version.number());
+    }
+
     // This test populates a table with one object and performs a read on
     // the object. It then performs an update on this object and checks
     // if the previously read value is still accessible.
@@ -434,9 +1667,401 @@ mod tests {
         table.put(key_ref, obj);
 
         // Next, delete the key from the table.
-        table.delete(key);
+        assert!(table.delete(key));
 
-        // Assert that the key was deleted.
+        // Assert that the key was deleted, and that it cannot be deleted again.
         assert_eq!(None, table.get(key));
+        assert!(!table.delete(key));
+    }
+
+    // This function tests that scanning every bucket of a table returns each object exactly once.
+    #[test]
+    fn test_bucket_entries() {
+        let table = Table::default();
+
+        // Add objects whose keys fall into a few different buckets.
+        for i in 0..300u32 {
+            let key = Bytes::from(vec![i as u8, (i >> 8) as u8]);
+            table.put(key, Bytes::from(vec![i as u8; 4]));
+        }
+        table.delete(&[0, 0]);
+
+        let mut found = 0;
+        for bucket in 0..N_BUCKETS {
+            found += table.bucket_entries(bucket).len();
+        }
+        assert_eq!(299, found);
+        assert_eq!(2, table.bucket_entries(0).len());
+        assert_eq!(3, table.bucket_entries(1).len());
+    }
+
+    // This function tests that a scan returns objects in key order from
+    // wherever it is bounded, across buckets, and skips quarantined objects.
+    #[test]
+    fn test_scan() {
+        let table = Table::default();
+        for i in 0..300u32 {
+            let key = Bytes::from(vec![(i >> 8) as u8, i as u8]);
+            table.put(key, Bytes::from(vec![i as u8; 4]));
+        }
+        let keys = |found: Vec<(Bytes, Entry)>| {
+            found
+                .into_iter()
+                .map(|(key, _)| key.to_vec())
+                .collect::<Vec<_>>()
+        };
+
+        let all = keys(table.scan(Bound::Unbounded, 1000));
+        assert_eq!(300, all.len());
+        assert!(all.windows(2).all(|pair| pair[0] < pair[1]));
+
+        let first = table.scan(Bound::Included(&[0, 10]), 3);
+        assert_eq!(
+            vec![vec![0, 10], vec![0, 11], vec![0, 12]],
+            keys(first.clone())
+        );
+        assert_eq!(&[10; 4], &first[0].1.value[..]);
+        assert_eq!(
+            vec![vec![0, 11]],
+            keys(table.scan(Bound::Excluded(&[0, 10]), 1))
+        );
+        assert_eq!(
+            vec![vec![1, 0]],
+            keys(table.scan(Bound::Excluded(&[0, 255]), 1))
+        );
+        assert!(table.scan(Bound::Excluded(&[1, 43]), 10).is_empty());
+
+        let version = table.version(&[0, 1]).unwrap();
+        assert!(table.quarantine(&[0, 1], version));
+        assert_eq!(
+            vec![vec![0, 0], vec![0, 2]],
+            keys(table.scan(Bound::Unbounded, 2))
+        );
+
+        // Keys starting with bytes that share a bucket sort apart, and deleted keys are no
+        // longer returned.
+        table.put(Bytes::from(vec![129, 0]), Bytes::from(vec![1; 4]));
+        assert!(table.delete(&[1, 0]));
+        assert_eq!(
+            vec![vec![1, 1], vec![1, 2]],
+            keys(table.scan(Bound::Included(&[1, 0]), 2))
+        );
+        assert_eq!(
+            vec![vec![1, 43], vec![129, 0]],
+            keys(table.scan(Bound::Included(&[1, 43]), 10))
+        );
+    }
+
+    // This function tests that keys are only indexed once their bucket is
+    // scanned, and that writes from then on keep the index up to date.
+    #[test]
+    fn test_scan_index() {
+        let table = Table::default();
+        table.put(Bytes::from(vec![0, 1]), Bytes::from(vec![1; 4]));
+        assert!(table.keys.iter().all(|keys| keys.read().is_none()));
+
+        assert_eq!(1, table.scan(Bound::Unbounded, 10).len());
+        assert_eq!(1, table.keys[0].read().as_ref().unwrap().len());
+
+        table.put(Bytes::from(vec![0, 2]), Bytes::from(vec![2; 4]));
+        assert!(table.delete(&[0, 1]));
+        let found = table.scan(Bound::Unbounded, 10);
+        assert_eq!(1, found.len());
+        assert_eq!(&[0, 2], &found[0].0[..]);
+        assert_eq!(1, table.keys[0].read().as_ref().unwrap().len());
+    }
+
+    // This function tests that native access is permitted by default, and that
+    // changing it does not affect objects already in the table.
+    #[test]
+    fn test_native_access() {
+        let table = Table::default();
+        assert!(table.readable_native() && table.writable_native());
+
+        table.put(Bytes::from(vec![1; 8]), Bytes::from(vec![2; 8]));
+        table.set_native_access(false, true);
+        assert!(!table.readable_native() && table.writable_native());
+        assert!(table.get(&[1; 8]).is_some());
+
+        table.set_native_access(true, false);
+        assert!(table.readable_native() && !table.writable_native());
+    }
+
+    // This function tests that a quarantine only applies to the version of
+    // the object it was asked for, and is lifted by an overwrite or delete.
+    #[test]
+    fn test_quarantine() {
+        let table = Table::default();
+        let key = Bytes::from(vec![1; 8]);
+
+        table.put(key.clone(), Bytes::from(vec![2; 8]));
+        let stale = table.get(&key).unwrap().version;
+        let version = table.put(key.clone(), Bytes::from(vec![3; 8])).unwrap().version;
+        assert!(!table.quarantine(&key, stale));
+        assert!(!table.quarantine(&[2; 8], version));
+        assert!(!table.is_quarantined(&key));
+
+        assert!(table.quarantine(&key, version));
+        assert!(table.quarantine(&key, version));
+        assert!(table.is_quarantined(&key));
+        assert_eq!(1, table.quarantined());
+
+        table.put(key.clone(), Bytes::from(vec![4; 8]));
+        assert!(!table.is_quarantined(&key));
+        assert_eq!(0, table.quarantined());
+
+        let version = table.get(&key).unwrap().version;
+        assert!(table.quarantine(&key, version));
+        table.delete(&key);
+        assert!(!table.is_quarantined(&key));
+        assert_eq!(0, table.quarantined());
+    }
+
+    // This function tests that small objects are inlined once enabled, and
+    // that objects move between the index and the heap as they are
+    // overwritten with ones of a different size.
+    #[test]
+    fn test_inline_values() {
+        let table = Table::default();
+        table.set_inline_values(true);
+        assert!(table.inline_values());
+
+        let key = Bytes::from(vec![3; 8]);
+        let before = inline_stats();
+
+        // Small, large, and then small again.
+        for &len in [16, INLINE_CAP + 1, INLINE_CAP].iter() {
+            table.put(key.clone(), Bytes::from(vec![len as u8; len]));
+            let entry = table.get(&key[..]).expect("Failed to lookup object.");
+            assert_eq!(&vec![len as u8; len][..], &entry.value[..]);
+        }
+
+        let after = inline_stats();
+        assert!(after.inlined - before.inlined >= 2);
+        assert!(after.heap - before.heap >= 1);
+        assert_eq!(3, table.get(&key[..]).unwrap().version.0);
+
+        // Inlined objects are scanned and deleted like any other.
+        assert_eq!(1, (0..N_BUCKETS).map(| b | table.bucket_entries(b).len())
+                                    .sum::<usize>());
+        table.delete(&key[..]);
+        assert!(table.get(&key[..]).is_none());
+    }
+
+    // This function hammers a single inlined object with readers and writers,
+    // and tests that readers never see a partially written object.
+    #[test]
+    fn test_inline_values_concurrent() {
+        let table = Arc::new(Table::default());
+        table.set_inline_values(true);
+        table.put(Bytes::from(vec![5; 8]), Bytes::from(vec![0; 64]));
+
+        let mut threads = Vec::new();
+        for t in 0..4u8 {
+            let table = Arc::clone(&table);
+            threads.push(thread::spawn(move || {
+                for i in 0..20000u32 {
+                    if t % 2 == 0 {
+                        let b = (i as u8).wrapping_add(t);
+                        table.put(Bytes::from(vec![5; 8]), Bytes::from(vec![b; 64]));
+                        continue;
+                    }
+
+                    let entry = table.get(&[5; 8]).expect("Failed to lookup object.");
+                    assert_eq!(64, entry.value.len());
+                    assert!(entry.value.iter().all(| b | *b == entry.value[0]));
+                }
+            }));
+        }
+
+        for thread in threads {
+            thread.join().expect("Thread panicked.");
+        }
+    }
+
+    // This function has a writer repeatedly put two objects in different
+    // buckets as a batch, and tests that readers never see one of them
+    // without the other, nor one ahead of the other.
+    #[test]
+    fn test_put_many_atomic() {
+        let table = Arc::new(Table::default());
+        let (a, b) = (Bytes::from(vec![1; 30]), Bytes::from(vec![2; 30]));
+        assert!(Table::bucket(&a[..]) != Table::bucket(&b[..]));
+
+        // Reads a counter off an object, or None if the object isn't there.
+        fn counter(table: &Table, key: &[u8]) -> Option<u64> {
+            table.get(key).map(| entry | {
+                (0..8).fold(0, | acc, i | acc | (entry.value[i] as u64) << (8 * i))
+            })
+        }
+
+        let mut threads = Vec::new();
+        for t in 0..4 {
+            let table = Arc::clone(&table);
+            let (a, b) = (a.clone(), b.clone());
+            threads.push(thread::spawn(move || {
+                for i in 1..20000u64 {
+                    if t == 0 {
+                        let val: Vec<u8> = (0..8).map(| s | (i >> (8 * s)) as u8).collect();
+                        let batch = vec![(a.clone(), Bytes::from(&val[..])),
+                                         (b.clone(), Bytes::from(&val[..]))];
+                        assert!(table.put_many(batch).is_some());
+                        continue;
+                    }
+
+                    // The batch writes `a` first, so a non-atomic batch would
+                    // let a reader see it ahead of `b`.
+                    if let Some(x) = counter(&table, &a[..]) {
+                        let y = counter(&table, &b[..]).expect("Saw only one of a batch.");
+                        assert!(y >= x, "Saw {} ahead of {}.", x, y);
+                    }
+                }
+            }));
+        }
+
+        for thread in threads {
+            thread.join().expect("Thread panicked.");
+        }
+    }
+
+    // Returns the key of the i'th object on a cache test, spread across buckets.
+    fn cache_key(i: u32) -> Bytes {
+        Bytes::from(&[i as u8, (i >> 8) as u8, (i >> 16) as u8, (i >> 24) as u8][..])
+    }
+
+    // Tests that a cache-mode table filled well past it's target converges to it, and that it's
+    // counters reconcile with the objects left on it.
+    #[test]
+    fn test_cache_converges() {
+        let table = Table::default();
+        let target = CacheTarget::new(64 * 1024, 0, EvictPolicy::Written);
+        table.set_cache(target);
+        assert_eq!(target, table.cache_target());
+
+        let version = table.put(cache_key(0), Bytes::from(vec![1; 100])).unwrap().version;
+        for i in 1..5000 {
+            table.put(cache_key(i), Bytes::from(vec![1; 100]));
+            let stats = table.cache_stats().unwrap();
+            assert!(stats.bytes <= 64 * 1024, "{} bytes after {} puts", stats.bytes, i);
+        }
+
+        let stats = table.cache_stats().unwrap();
+        assert!(stats.bytes > 64 * 1024 - EVICT_BATCH as u64 * 100);
+        assert_eq!(5000, stats.objects + stats.evicted);
+        assert_eq!(100 * stats.evicted, stats.evicted_bytes);
+        assert_eq!(stats.objects, table.sizing().objects);
+        let held: usize = (0..N_BUCKETS).flat_map(| b | table.bucket_entries(b))
+                                        .map(| e | e.value.len())
+                                        .sum();
+        assert_eq!(stats.bytes, held as u64);
+
+        // Reads of evicted keys miss. Deletes are counted, and an evicted key written back gets
+        // a version past the one it was evicted with.
+        let hits = (0..5000).filter(| &i | table.get(&cache_key(i)).is_some()).count();
+        assert_eq!(stats.objects, hits as u64);
+        let gone = (0..5000).find(| &i | table.get(&cache_key(i)).is_none()).unwrap();
+        let back = table.put(cache_key(gone), Bytes::from(vec![2; 100])).unwrap().version;
+        assert!(back.number() > version.number());
+
+        let there = (0..5000).find(| &i | table.get(&cache_key(i)).is_some()).unwrap();
+        let before = table.cache_stats().unwrap();
+        table.delete(&cache_key(there));
+        let after = table.cache_stats().unwrap();
+        assert_eq!((before.objects - 1, before.evicted), (after.objects, after.evicted));
+        assert_eq!(before.bytes - 100, after.bytes);
+
+        // A target set on a full table evicts down to it right away.
+        let full = Table::default();
+        for i in 0..200 {
+            full.put(cache_key(i), Bytes::from(vec![1; 10]));
+        }
+        full.set_cache(CacheTarget::new(0, 100, EvictPolicy::Read));
+        let stats = full.cache_stats().unwrap();
+        assert_eq!((100, 100, 1000), (stats.objects, stats.evicted, stats.evicted_bytes));
+        assert_eq!(100, full.sizing().objects);
+    }
+
+    // Fills a cache-mode table of 1000 objects with 3000 more while reading the first 100 every
+    // 25 puts. Returns how many of the 100 read, and of the next 900, are left on the table.
+    fn cache_survivors(policy: EvictPolicy) -> (usize, usize) {
+        let table = Table::default();
+        table.set_cache(CacheTarget::new(0, 1000, policy));
+        for i in 0..4000 {
+            table.put(cache_key(i), Bytes::from(vec![1; 50]));
+            if i % 25 == 0 {
+                for hot in 0..100 {
+                    table.get(&cache_key(hot));
+                }
+            }
+        }
+
+        assert_eq!(1000, table.cache_stats().unwrap().objects);
+        let left = | keys: ::std::ops::Range<u32> | {
+            keys.filter(| &i | table.get(&cache_key(i)).is_some()).count()
+        };
+        (left(0..100), left(100..1000))
+    }
+
+    // Tests that recently read objects survive eviction preferentially when reads touch them,
+    // and are evicted like any other object when they don't.
+    #[test]
+    fn test_cache_recency() {
+        let (hot, cold) = cache_survivors(EvictPolicy::Read);
+        assert!(hot >= 95, "{} of 100 read objects survived", hot);
+        assert!(cold <= 50, "{} of 900 unread objects survived", cold);
+
+        let (hot, _) = cache_survivors(EvictPolicy::Written);
+        assert!(hot <= 50, "{} of 100 read objects survived", hot);
+    }
+
+    // Tests that tables that are not in cache mode never evict, never touch objects, and pay no
+    // memory for the bit cache-mode tables touch.
+    #[test]
+    fn test_cache_off() {
+        #[allow(dead_code)]
+        struct Untouched {
+            version: Version,
+            value: Stored,
+            quarantined: bool,
+            reads: AtomicUsize,
+        }
+        assert_eq!(size_of::<Untouched>(), size_of::<Slot>());
+
+        let table = Table::default();
+        for i in 0..5000 {
+            table.put(cache_key(i), Bytes::from(vec![1; 100]));
+            table.get(&cache_key(i));
+        }
+
+        assert_eq!(None, table.cache_stats());
+        assert_eq!(5000, table.sizing().objects);
+        for map in table.maps.iter() {
+            assert!(map.read().values().all(| s | !s.referenced.load(Ordering::Relaxed)));
+        }
+
+        // Taking a table out of cache mode stops it evicting.
+        table.set_cache(CacheTarget::new(0, 4000, EvictPolicy::Read));
+        table.set_cache(None);
+        table.put(cache_key(5000), Bytes::from(vec![1; 100]));
+        assert_eq!((None, 4001), (table.cache_stats(), table.sizing().objects));
+    }
+
+    // Tests that truncating a table removes every object off it, and that the
+    // table can be written to again afterwards.
+    #[test]
+    fn test_truncate() {
+        let table = Table::default();
+        for i in 0..16 {
+            table.put(Bytes::from(vec![i; 8]), Bytes::from(vec![i; 8]));
+        }
+
+        assert_eq!(16, table.truncate());
+        assert!(table.get(&[3; 8]).is_none());
+        let left: usize = (0..N_BUCKETS).map(|b| table.bucket_entries(b).len()).sum();
+        assert_eq!(0, left);
+        assert_eq!(0, table.truncate());
+
+        table.put(Bytes::from(vec![3; 8]), Bytes::from(vec![4; 8]));
+        assert_eq!(&[4; 8], &table.get(&[3; 8]).unwrap().value[..]);
     }
 }
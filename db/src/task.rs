@@ -13,6 +13,10 @@
  * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
  */
 
+use super::defer::Deferred;
+
+use sandstorm::common::TenantId;
+
 use e2d2::common::EmptyMetadata;
 use e2d2::headers::UdpHeader;
 use e2d2::interface::Packet;
@@ -57,6 +61,10 @@ pub enum TaskPriority {
 
     /// The priority of a task corresponding to an RPC request.
     REQUEST = 0x02,
+
+    /// The priority of a long running background task, like a durable invocation. These tasks
+    /// are never pushed back to the client.
+    BACKGROUND = 0x03,
 }
 
 /// This trait consists of methods that will allow a type to be run as a task
@@ -129,4 +137,174 @@ pub trait Task {
     ///
     /// * `record`: The record, which will be added to the RW set.
     fn update_cache(&mut self, record: &[u8], keylen: usize);
+
+    /// This method is called by the scheduler when the task is enqueued on it, so that the task
+    /// can remember when. By default, the time-stamp is dropped.
+    ///
+    /// # Arguments
+    ///
+    /// * `at`: The time-stamp, in cycles, at which the task was enqueued.
+    fn set_enqueued(&mut self, _at: u64) {}
+
+    /// When called, this method should return the time-stamp passed to set_enqueued().
+    ///
+    /// # Return
+    ///
+    /// The time-stamp in cycles at which the task was enqueued, or None if the task does not
+    /// remember it. Tasks that don't are left out of the scheduler's queueing delay.
+    fn enqueued(&self) -> Option<u64> {
+        None
+    }
+
+    /// When called, this method should estimate how much longer the task will run outside the
+    /// database before it completes, usually off the moving average cost of it's extension.
+    ///
+    /// # Return
+    ///
+    /// The estimated remaining time in cycles, or None if there is no estimate. Tasks without
+    /// an estimate are never pushed back by a queue-aware pushback policy.
+    fn remaining(&self) -> Option<u64> {
+        None
+    }
+
+    /// This method is called by the dispatcher with the tenant whose request the task serves, so
+    /// that the scheduler can share the core fairly between tenants. By default, the tenant is
+    /// dropped.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`: The tenant that issued the request.
+    fn set_tenant(&mut self, _tenant: TenantId) {}
+
+    /// When called, this method should return the tenant passed to set_tenant().
+    ///
+    /// # Return
+    ///
+    /// The tenant whose request the task serves, or None if the task does not remember it.
+    /// Tasks that don't are queued along with the dispatcher. Refer to fair::RunQueue.
+    fn tenant(&self) -> Option<TenantId> {
+        None
+    }
+
+    /// This method is called by the scheduler after every run of the task, to send out the
+    /// partial responses the task streamed back during that run. By default, a task does not
+    /// stream it's response.
+    ///
+    /// # Return
+    ///
+    /// The partial responses, in the order they should be sent out. Each one is parsed upto it's
+    /// UDP header.
+    fn partials(&mut self) -> Vec<Packet<UdpHeader, EmptyMetadata>> {
+        Vec::new()
+    }
+
+    /// When called, this method should return true if the task has sent out part of it's
+    /// response through partials(), or has deferred work through deferred(). Such a task must
+    /// not be pushed back.
+    fn streamed(&self) -> bool {
+        false
+    }
+
+    /// This method is called by the scheduler on a completed task, after the task's packets have
+    /// been torn out of it, to pick up the work the task deferred until after it's response was
+    /// sent out. By default, a task does not defer any work.
+    ///
+    /// # Return
+    ///
+    /// The deferred work, in the order it was deferred.
+    fn deferred(&mut self) -> Vec<Deferred> {
+        Vec::new()
+    }
+
+    /// This method is called by the scheduler once it is done with a completed task, after the
+    /// task's packets have been torn out of it. Tasks that are pooled return themselves to their
+    /// pool here. By default, the task is simply dropped.
+    fn recycle(self: Box<Self>) {}
+}
+
+// This module contains unit tests for the defaults on the Task trait.
+#[cfg(test)]
+mod tests {
+    use super::TaskState::*;
+    use super::*;
+
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    // A task that completes on it's first run, and marks a flag once it is dropped.
+    struct OneShot {
+        state: TaskState,
+        dropped: Rc<Cell<bool>>,
+    }
+
+    impl Drop for OneShot {
+        fn drop(&mut self) {
+            self.dropped.set(true);
+        }
+    }
+
+    impl Task for OneShot {
+        fn run(&mut self) -> (TaskState, u64) {
+            self.state = COMPLETED;
+            (self.state, 1)
+        }
+
+        fn state(&self) -> TaskState {
+            self.state
+        }
+
+        fn time(&self) -> u64 {
+            0
+        }
+
+        fn db_time(&self) -> u64 {
+            0
+        }
+
+        fn priority(&self) -> TaskPriority {
+            TaskPriority::REQUEST
+        }
+
+        unsafe fn tear(
+            &mut self,
+        ) -> Option<(
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        )> {
+            None
+        }
+
+        fn set_state(&mut self, state: TaskState) {
+            self.state = state;
+        }
+
+        fn update_cache(&mut self, _record: &[u8], _keylen: usize) {}
+    }
+
+    // Tests that a task relying on the defaults drops what it is handed, neither streams nor
+    // defers anything, and is dropped once the scheduler recycles it.
+    #[test]
+    fn test_task_defaults() {
+        let dropped = Rc::new(Cell::new(false));
+        let mut task: Box<Task> = Box::new(OneShot {
+            state: INITIALIZED,
+            dropped: Rc::clone(&dropped),
+        });
+
+        task.set_enqueued(10);
+        task.set_tenant(1);
+        assert_eq!(None, task.enqueued());
+        assert_eq!(None, task.tenant());
+        assert_eq!(None, task.remaining());
+
+        assert!(task.run().0 == COMPLETED);
+        assert!(task.partials().is_empty());
+        assert!(task.deferred().is_empty());
+        assert!(!task.streamed());
+        assert!(unsafe { task.tear() }.is_none());
+
+        assert!(!dropped.get());
+        task.recycle();
+        assert!(dropped.get());
+    }
 }
@@ -13,14 +13,22 @@
  * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
  */
 
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use hashbrown::HashMap;
 
+use super::audit::AuditLog;
+use super::compress::Compression;
+use super::defer::Deferrals;
+use super::quarantine::Quarantine;
+use super::route::Routes;
 use super::table::Table;
+use super::wireformat::RpcStatus;
 
-use spin::RwLock;
+use spin::{RwLock, RwLockReadGuard};
 
 use sandstorm::common::{TableId, TenantId};
+use sandstorm::ext::Extension;
 
 /// This type represents a tenant in Sandstorm. It helps uniquely identify
 /// a tenant, and maintains a map of all the data tables belonging to a
@@ -29,9 +37,41 @@ pub struct Tenant {
     /// A unique identifier for the tenant.
     id: TenantId,
 
+    /// The epoch the tenant is at. Moved forward every time one of it's tables is truncated or
+    /// dropped, or the tenant is removed. Refer to epoch.rs.
+    epoch: AtomicU64,
+
     /// A map of all the data tables belonging to a tenant. Each data table
     /// has a unique identifier.
     tables: RwLock<HashMap<TableId, Arc<Table>>>,
+
+    /// Tables owned by other tenants that this tenant can read, but not write. Each is keyed by
+    /// the identifier it goes by in this tenant's table namespace.
+    aliases: RwLock<HashMap<TableId, Alias>>,
+
+    /// The rules picking the extension a routed invoke() issued by the tenant runs.
+    routes: RwLock<Routes>,
+
+    /// The log invocations issued by the tenant are recorded into. None if the tenant isn't
+    /// being audited.
+    audit: RwLock<Option<Arc<AuditLog>>>,
+
+    /// Counts the work deferred by the tenant's invocations, and caps how much of it can be
+    /// queued up at once.
+    deferrals: Arc<Deferrals>,
+
+    /// The tenant's quarantine, and the failures it's recent requests ran into.
+    quarantine: Quarantine,
+}
+
+/// A read-only alias to a table owned by another tenant. The owner's table is looked up on every
+/// access, so that the alias stops resolving once the owner drops the table.
+struct Alias {
+    /// The tenant that owns the table.
+    owner: Arc<Tenant>,
+
+    /// The identifier of the table in the owner's namespace.
+    table_id: TableId,
 }
 
 // Implementation of methods on tenant.
@@ -50,7 +90,13 @@ impl Tenant {
     pub fn new(id: TenantId) -> Tenant {
         Tenant {
             id: id,
+            epoch: AtomicU64::new(0),
             tables: RwLock::new(HashMap::new()),
+            aliases: RwLock::new(HashMap::new()),
+            routes: RwLock::new(Routes::new()),
+            audit: RwLock::new(None),
+            deferrals: Arc::new(Deferrals::new()),
+            quarantine: Quarantine::new(),
         }
     }
 
@@ -64,6 +110,36 @@ impl Tenant {
         self.id.clone()
     }
 
+    /// This method returns the epoch the tenant is at. Refer to epoch.rs.
+    #[inline]
+    pub fn epoch(&self) -> u64 {
+        self.epoch.load(Ordering::Relaxed)
+    }
+
+    /// This method moves the tenant to an epoch. Tables added to the tenant
+    /// from here on start out at it.
+    ///
+    /// # Arguments
+    ///
+    /// * `epoch`: The epoch, drawn off the server's clock by epoch::Epochs.
+    pub fn set_epoch(&self, epoch: u64) {
+        self.epoch.store(epoch, Ordering::Relaxed);
+    }
+
+    /// This method moves the tenant, along with every table it already has, to an epoch. Used
+    /// when the tenant is added to a server, so that tables created before then start out at
+    /// the same epoch as the ones created after.
+    ///
+    /// # Arguments
+    ///
+    /// * `epoch`: The epoch, drawn off the server's clock by epoch::Epochs.
+    pub fn start_epoch(&self, epoch: u64) {
+        self.set_epoch(epoch);
+        for table in self.tables.read().values() {
+            table.set_epoch(epoch);
+        }
+    }
+
     /// This method creates a new table for the tenant. If a table with the
     /// passed in identifier already exists, then this method does nothing.
     ///
@@ -71,14 +147,83 @@ impl Tenant {
     ///
     /// * `id`: A unique identifier for the new table.
     pub fn create_table(&self, table_id: u64) {
+        self.create_table_with(table_id, None);
+    }
+
+    /// This method creates a new table for the tenant whose values are
+    /// compressed according to the passed in settings. If a table with the
+    /// passed in identifier already exists, then this method does nothing.
+    ///
+    /// # Arguments
+    ///
+    /// * `id`:          A unique identifier for the new table.
+    /// * `compression`: The compression settings for the table. None disables
+    ///                  compression.
+    pub fn create_table_with(&self, table_id: u64, compression: Option<Compression>) {
+        self.create_table_with_access(table_id, compression, true, true);
+    }
+
+    /// This method creates a new table for the tenant, and sets whether it
+    /// can be accessed by native RPCs. If a table with the passed in
+    /// identifier already exists, then this method does nothing; it's access
+    /// can be changed through set_native_access() instead.
+    ///
+    /// # Arguments
+    ///
+    /// * `id`:          A unique identifier for the new table.
+    /// * `compression`: The compression settings for the table. None disables
+    ///                  compression.
+    /// * `readable`:    If false, native get() and multiget() RPCs are refused.
+    /// * `writable`:    If false, native put(), delete() and cas() RPCs are refused.
+    pub fn create_table_with_access(
+        &self,
+        table_id: u64,
+        compression: Option<Compression>,
+        readable: bool,
+        writable: bool,
+    ) {
         // Acquire a write lock.
         let mut map = self.tables.write();
 
-        // Insert a new table and return.
-        map.insert(table_id, Arc::new(Table::default()));
+        // Insert a new table if one does not already exist and return.
+        map.entry(table_id).or_insert_with(|| {
+            let table = Table::with_compression(compression);
+            table.set_native_access(readable, writable);
+            table.set_epoch(self.epoch());
+            Arc::new(table)
+        });
     }
 
-    /// This method returns a table belonging to the tenant if it exists.
+    /// This method adds an already built table to the tenant, such as one
+    /// backed by a read-only image. The table is not added if the tenant
+    /// already has a table or alias with the same identifier.
+    ///
+    /// # Arguments
+    ///
+    /// * `table_id`: A unique identifier for the table.
+    /// * `table`:    The table to be added.
+    ///
+    /// # Return
+    ///
+    /// True if the table was added.
+    pub fn insert_table(&self, table_id: TableId, table: Table) -> bool {
+        // Acquire both write locks so that an alias cannot be attached under
+        // the same identifier in the meantime.
+        let mut map = self.tables.write();
+        let aliases = self.aliases.write();
+        if map.contains_key(&table_id) || aliases.contains_key(&table_id) {
+            return false;
+        }
+
+        table.set_epoch(self.epoch());
+        map.insert(table_id, Arc::new(table));
+        true
+    }
+
+    /// This method returns a table belonging to the tenant if it exists. If
+    /// the tenant does not own a table with the passed in identifier, then
+    /// it's read-only aliases are looked up. Writes must go through
+    /// writable_table() instead.
     ///
     /// # Arguments
     ///
@@ -88,10 +233,543 @@ impl Tenant {
     ///
     /// An atomic reference counted handle to the table if it exists.
     pub fn get_table(&self, table_id: TableId) -> Option<Arc<Table>> {
+        self.own_table(table_id)
+            .or_else(|| self.aliased_table(table_id))
+    }
+
+    /// This method returns a table that the tenant can write to.
+    ///
+    /// # Arguments
+    ///
+    /// * `table_id`: The identifier for the table to be returned.
+    ///
+    /// # Return
+    ///
+    /// An atomic reference counted handle to the table if the tenant owns it.
+    /// StatusReadOnlyTable if the identifier refers to a read-only alias or
+    /// to a table backed by an image, and StatusTableDoesNotExist if it
+    /// refers to nothing.
+    pub fn writable_table(&self, table_id: TableId) -> Result<Arc<Table>, RpcStatus> {
+        if let Some(table) = self.own_table(table_id) {
+            return match table.read_only() {
+                true => Err(RpcStatus::StatusReadOnlyTable),
+                false => Ok(table),
+            };
+        }
+
+        match self.aliased_table(table_id) {
+            Some(_) => Err(RpcStatus::StatusReadOnlyTable),
+            None => Err(RpcStatus::StatusTableDoesNotExist),
+        }
+    }
+
+    /// This method returns a table that a native get() or multiget() RPC
+    /// is about to read. Extensions read tables through get_table() instead,
+    /// and are not subject to the table's native access.
+    ///
+    /// # Arguments
+    ///
+    /// * `table_id`: The identifier for the table to be returned.
+    ///
+    /// # Return
+    ///
+    /// An atomic reference counted handle to the table, StatusPermissionDenied
+    /// if the table cannot be read natively, or StatusTableDoesNotExist.
+    pub fn readable_native_table(&self, table_id: TableId) -> Result<Arc<Table>, RpcStatus> {
+        let table = self
+            .get_table(table_id)
+            .ok_or(RpcStatus::StatusTableDoesNotExist)?;
+        match table.readable_native() {
+            true => Ok(table),
+            false => Err(RpcStatus::StatusPermissionDenied),
+        }
+    }
+
+    /// This method returns a table that a native put() RPC is about to write
+    /// to. Refer to readable_native_table() and writable_table().
+    ///
+    /// # Arguments
+    ///
+    /// * `table_id`: The identifier for the table to be returned.
+    ///
+    /// # Return
+    ///
+    /// An atomic reference counted handle to the table, StatusPermissionDenied
+    /// if the table cannot be written natively, or any error returned by
+    /// writable_table().
+    pub fn writable_native_table(&self, table_id: TableId) -> Result<Arc<Table>, RpcStatus> {
+        let table = self.writable_table(table_id)?;
+        match table.writable_native() {
+            true => Ok(table),
+            false => Err(RpcStatus::StatusPermissionDenied),
+        }
+    }
+
+    /// This method sets whether a table belonging to the tenant can be
+    /// accessed by native RPCs. Only the owner can change a table's access,
+    /// and the change is seen by tenants reading it through aliases too.
+    ///
+    /// # Arguments
+    ///
+    /// * `table_id`: The identifier for the table.
+    /// * `readable`: If false, native get() and multiget() RPCs are refused.
+    /// * `writable`: If false, native put(), delete() and cas() RPCs are refused.
+    ///
+    /// # Return
+    ///
+    /// StatusReadOnlyTable if the identifier refers to a read-only alias,
+    /// and StatusTableDoesNotExist if it does not refer to any table.
+    pub fn set_native_access(
+        &self,
+        table_id: TableId,
+        readable: bool,
+        writable: bool,
+    ) -> Result<(), RpcStatus> {
+        self.writable_table(table_id)
+            .map(|table| table.set_native_access(readable, writable))
+    }
+
+    /// This method registers (or clears) the merge extension of a table
+    /// belonging to the tenant. Refer to Table::set_merge().
+    ///
+    /// # Arguments
+    ///
+    /// * `table_id`: The identifier for the table.
+    /// * `merge`:    The extension's name and the extension. None clears it.
+    ///
+    /// # Return
+    ///
+    /// StatusReadOnlyTable if the identifier refers to a read-only alias,
+    /// and StatusTableDoesNotExist if it does not refer to any table.
+    pub fn set_merge(
+        &self,
+        table_id: TableId,
+        merge: Option<(Vec<u8>, Arc<Extension>)>,
+    ) -> Result<(), RpcStatus> {
+        self.writable_table(table_id)
+            .map(|table| table.set_merge(merge))
+    }
+
+    /// This method installs (or removes) one of the tenant's routing rules.
+    /// Refer to Routes::set().
+    ///
+    /// # Arguments
+    ///
+    /// * `prefix`: The key prefix the rule matches on.
+    /// * `name`:   The name of the extension keys with the prefix are routed
+    ///             to. Empty removes the rule on the prefix.
+    pub fn set_route(&self, prefix: &[u8], name: &[u8]) -> Result<(), RpcStatus> {
+        self.routes.write().set(prefix, name)
+    }
+
+    /// This method returns the tenant's routing rules. Rules cannot be
+    /// installed until the returned guard is dropped.
+    #[inline]
+    pub fn routes(&self) -> RwLockReadGuard<Routes> {
+        self.routes.read()
+    }
+
+    /// This method starts recording the tenant's invocations into an audit
+    /// log. If the tenant is already being audited, then this method does
+    /// nothing. There are no per-tenant memory quotas yet, so the log isn't
+    /// charged to the tenant.
+    ///
+    /// # Arguments
+    ///
+    /// * `capacity`: The number of entries the log holds. Refer to AuditLog::new().
+    pub fn enable_audit(&self, capacity: usize) {
+        let mut audit = self.audit.write();
+        if audit.is_none() {
+            *audit = Some(Arc::new(AuditLog::new(capacity)));
+        }
+    }
+
+    /// This method returns the tenant's audit log, if the tenant is being
+    /// audited.
+    #[inline]
+    pub fn audit(&self) -> Option<Arc<AuditLog>> {
+        self.audit.read().as_ref().map(|log| Arc::clone(log))
+    }
+
+    /// This method returns the counters for the work deferred by the tenant's invocations.
+    #[inline]
+    pub fn deferrals(&self) -> Arc<Deferrals> {
+        Arc::clone(&self.deferrals)
+    }
+
+    /// This method returns the tenant's quarantine. Refer to quarantine::Quarantines for when
+    /// tenants are quarantined.
+    #[inline]
+    pub fn quarantine(&self) -> &Quarantine {
+        &self.quarantine
+    }
+
+    /// This method drops a table belonging to the tenant. Handles to the
+    /// table that were already handed out remain valid, but lookups on the
+    /// table, including those through aliases held by other tenants, fail
+    /// from here on. Aliases held by this tenant cannot be dropped.
+    ///
+    /// # Arguments
+    ///
+    /// * `table_id`: The identifier for the table to be dropped.
+    ///
+    /// # Return
+    ///
+    /// True if the tenant owned the table.
+    pub fn drop_table(&self, table_id: TableId) -> bool {
+        self.remove_table(table_id).is_some()
+    }
+
+    /// This method drops a table belonging to the tenant, exactly like
+    /// drop_table(), and hands it back.
+    ///
+    /// # Arguments
+    ///
+    /// * `table_id`: The identifier for the table to be dropped.
+    ///
+    /// # Return
+    ///
+    /// A handle to the table if the tenant owned it.
+    pub fn remove_table(&self, table_id: TableId) -> Option<Arc<Table>> {
+        self.tables.write().remove(&table_id)
+    }
+
+    /// This method attaches a table owned by another tenant as a read-only
+    /// alias under this tenant. The owner's table must exist, and this
+    /// tenant must not already have a table or alias with the same
+    /// identifier. Aliases are not followed transitively, so the owner's
+    /// table cannot itself be an alias.
+    ///
+    /// # Arguments
+    ///
+    /// * `table_id`:    The identifier the alias goes by under this tenant.
+    /// * `owner`:       The tenant that owns the table.
+    /// * `owner_table`: The identifier of the table under the owner.
+    ///
+    /// # Return
+    ///
+    /// True if the alias was attached.
+    pub fn attach_alias(
+        &self,
+        table_id: TableId,
+        owner: Arc<Tenant>,
+        owner_table: TableId,
+    ) -> bool {
+        if owner.id() == self.id || owner.own_table(owner_table).is_none() {
+            return false;
+        }
+
+        // Acquire both write locks so that a table cannot be created under
+        // the same identifier in the meantime.
+        let tables = self.tables.write();
+        let mut aliases = self.aliases.write();
+        if tables.contains_key(&table_id) || aliases.contains_key(&table_id) {
+            return false;
+        }
+
+        aliases.insert(
+            table_id,
+            Alias {
+                owner: owner,
+                table_id: owner_table,
+            },
+        );
+        true
+    }
+
+    /// This method returns the read-only aliases attached to the tenant.
+    ///
+    /// # Return
+    ///
+    /// A tuple for each alias consisting of the identifier it goes by under
+    /// this tenant, the owner's identifier, the identifier of the table
+    /// under the owner, and whether the owner's table still exists.
+    pub fn aliases(&self) -> Vec<(TableId, TenantId, TableId, bool)> {
+        let aliases = self.aliases.read();
+        let mut list: Vec<_> = aliases
+            .iter()
+            .map(|(id, alias)| {
+                let live = alias.owner.own_table(alias.table_id).is_some();
+                (*id, alias.owner.id(), alias.table_id, live)
+            })
+            .collect();
+        list.sort();
+        list
+    }
+
+    /// This method returns the tables owned by the tenant. Aliases are left
+    /// out, since their tables are returned by their owner.
+    ///
+    /// # Return
+    ///
+    /// A tuple for each table consisting of it's identifier and a handle to
+    /// it, ordered by identifier.
+    pub fn tables(&self) -> Vec<(TableId, Arc<Table>)> {
+        let tables = self.tables.read();
+        let mut list: Vec<_> = tables
+            .iter()
+            .map(|(id, table)| (*id, Arc::clone(table)))
+            .collect();
+        list.sort_by_key(|&(id, _)| id);
+        list
+    }
+
+    // Looks up a table owned by the tenant.
+    fn own_table(&self, table_id: TableId) -> Option<Arc<Table>> {
         // Acquire a read lock.
         let map = self.tables.read();
 
         // Lookup on table_id and return.
         map.get(&table_id).and_then(| table | { Some(Arc::clone(&table)) })
     }
+
+    // Looks up a table owned by another tenant through a read-only alias.
+    fn aliased_table(&self, table_id: TableId) -> Option<Arc<Table>> {
+        let aliases = self.aliases.read();
+        aliases
+            .get(&table_id)
+            .and_then(|alias| alias.owner.own_table(alias.table_id))
+    }
+}
+
+// This module contains unit tests for tables shared read-only across tenants.
+#[cfg(test)]
+mod tests {
+    use super::Tenant;
+
+    use std::io::Cursor;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    use bytes::Bytes;
+
+    use super::super::alloc::Allocator;
+    use super::super::image::{self, ReadOnlyTable};
+    use super::super::kvformat::{Header, OnCorrupt, Reader, Writer};
+    use super::super::table::{Table, N_BUCKETS};
+    use super::super::wireformat::RpcStatus;
+
+    // Returns a tenant owning table 1, filled with `num` objects whose values equal their keys.
+    fn owner(num: u8) -> Arc<Tenant> {
+        let tenant = Tenant::new(1);
+        tenant.create_table(1);
+        let table = tenant.get_table(1).unwrap();
+        for i in 0..num {
+            table.put(Bytes::from(vec![i; 8]), Bytes::from(vec![i; 8]));
+        }
+        Arc::new(tenant)
+    }
+
+    // Tests that two tenants read the same fill, each through it's own table id.
+    #[test]
+    fn test_alias_reads() {
+        let owner = owner(16);
+        let tenant = Tenant::new(2);
+        assert!(tenant.attach_alias(7, Arc::clone(&owner), 1));
+
+        let (a, b) = (owner.get_table(1).unwrap(), tenant.get_table(7).unwrap());
+        assert!(Arc::ptr_eq(&a, &b));
+        for i in 0..16 {
+            let entry = b.get(&[i; 8]).expect("Missing object through alias");
+            assert_eq!(&[i; 8], &entry.value[..]);
+        }
+
+        // The alias is only reachable through the id it was attached under.
+        assert!(tenant.get_table(1).is_none());
+        assert_eq!(vec![(7, 1, 1, true)], tenant.aliases());
+        assert!(owner.aliases().is_empty());
+    }
+
+    // Tests that writes through an alias are refused, and that aliases do not conflict with
+    // tables or other aliases.
+    #[test]
+    fn test_alias_rejects_writes() {
+        let owner = owner(1);
+        let tenant = Arc::new(Tenant::new(2));
+        tenant.create_table(3);
+        assert!(tenant.attach_alias(7, Arc::clone(&owner), 1));
+
+        assert!(owner.writable_table(1).is_ok());
+        assert!(tenant.writable_table(3).is_ok());
+        let (alias, missing) = (tenant.writable_table(7), tenant.writable_table(8));
+        assert_eq!(Some(RpcStatus::StatusReadOnlyTable), alias.err());
+        assert_eq!(Some(RpcStatus::StatusTableDoesNotExist), missing.err());
+
+        // The id is taken, the owner has no such table, or the owner is the tenant itself.
+        assert!(!tenant.attach_alias(7, Arc::clone(&owner), 1));
+        assert!(!tenant.attach_alias(3, Arc::clone(&owner), 1));
+        assert!(!tenant.attach_alias(8, Arc::clone(&owner), 2));
+        assert!(!tenant.attach_alias(8, Arc::clone(&tenant), 3));
+
+        // Aliases are not followed transitively, so an alias cannot be shared onwards.
+        let third = Tenant::new(3);
+        assert!(!third.attach_alias(9, Arc::clone(&tenant), 7));
+        assert!(third.attach_alias(9, Arc::clone(&tenant), 3));
+        assert_eq!(
+            Some(RpcStatus::StatusReadOnlyTable),
+            third.writable_table(9).err()
+        );
+
+        // Dropping only works on owned tables.
+        assert!(!tenant.drop_table(7));
+        assert_eq!(
+            Some(RpcStatus::StatusReadOnlyTable),
+            tenant.writable_table(7).err()
+        );
+    }
+
+    // Tests that native access is set at create time, can only be changed by the owner, and
+    // that changes are seen through handles and aliases that already exist.
+    #[test]
+    fn test_native_access() {
+        let owner = Arc::new(Tenant::new(1));
+        owner.create_table_with_access(1, None, false, false);
+        owner.create_table(2);
+        let table = owner.get_table(1).unwrap();
+        assert!(!table.readable_native() && !table.writable_native());
+        assert!(owner.get_table(2).unwrap().readable_native());
+
+        // Creating an existing table does not reset it's access.
+        owner.create_table(1);
+        assert!(!table.readable_native());
+
+        let tenant = Tenant::new(2);
+        assert!(tenant.attach_alias(7, Arc::clone(&owner), 1));
+        assert_eq!(
+            Err(RpcStatus::StatusReadOnlyTable),
+            tenant.set_native_access(7, true, true)
+        );
+        assert_eq!(
+            Err(RpcStatus::StatusTableDoesNotExist),
+            owner.set_native_access(3, true, true)
+        );
+
+        // Extensions go through get_table() and writable_table(), which ignore native access.
+        assert!(owner.get_table(1).is_some() && owner.writable_table(1).is_ok());
+        let denied = Some(RpcStatus::StatusPermissionDenied);
+        assert_eq!(denied, owner.readable_native_table(1).err());
+        assert_eq!(denied, owner.writable_native_table(1).err());
+        assert_eq!(denied, tenant.readable_native_table(7).err());
+        assert!(owner.readable_native_table(2).is_ok());
+        assert!(owner.writable_native_table(2).is_ok());
+
+        assert_eq!(Ok(()), owner.set_native_access(1, true, false));
+        assert!(table.readable_native() && !table.writable_native());
+        assert!(owner.readable_native_table(1).is_ok());
+        assert_eq!(denied, owner.writable_native_table(1).err());
+        assert!(tenant.readable_native_table(7).is_ok());
+        assert_eq!(
+            Some(RpcStatus::StatusReadOnlyTable),
+            tenant.writable_native_table(7).err()
+        );
+    }
+
+    // Tests that dropping the owner's table invalidates aliases, while handles held by readers
+    // that were in-flight at the time remain valid.
+    #[test]
+    fn test_alias_owner_drop() {
+        let owner = owner(64);
+        let tenant = Arc::new(Tenant::new(2));
+        assert!(tenant.attach_alias(7, Arc::clone(&owner), 1));
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let table = tenant.get_table(7).expect("Alias did not resolve");
+                let stop = Arc::clone(&stop);
+                thread::spawn(move || {
+                    let mut reads = 0;
+                    while !stop.load(Ordering::Relaxed) || reads < 1000 {
+                        let i = (reads % 64) as u8;
+                        let entry = table.get(&[i; 8]).expect("In-flight read failed");
+                        assert_eq!(&[i; 8], &entry.value[..]);
+                        reads += 1;
+                    }
+                })
+            })
+            .collect();
+
+        assert!(owner.drop_table(1));
+        stop.store(true, Ordering::Relaxed);
+
+        assert!(tenant.get_table(7).is_none());
+        assert_eq!(
+            Some(RpcStatus::StatusTableDoesNotExist),
+            tenant.writable_table(7).err()
+        );
+        assert_eq!(vec![(7, 1, 1, false)], tenant.aliases());
+
+        for reader in readers.into_iter() {
+            reader.join().expect("Reader failed");
+        }
+    }
+
+    // Tests that a table backed by an image is read through the tenant like any other, and
+    // that writes to it are refused.
+    #[test]
+    fn test_image_table() {
+        let mut writer = Writer::new(Vec::new(), Header::new(1, 4)).unwrap();
+        for i in 0..64u8 {
+            writer.write(&[i, 1], &[i; 16]).unwrap();
+        }
+        let records = writer.finish().unwrap();
+        let mut reader = Reader::new(&records[..], OnCorrupt::Abort).unwrap();
+        let mut out = Cursor::new(Vec::new());
+        assert_eq!(64, image::build(&mut out, 1, 4, &mut reader).unwrap());
+        let image = Box::leak(out.into_inner().into_boxed_slice());
+        let table = Table::from_image(ReadOnlyTable::from_static(image).unwrap());
+
+        let tenant = Tenant::new(1);
+        tenant.create_table(3);
+        assert!(!tenant.insert_table(3, Table::default()));
+        assert!(tenant.insert_table(4, table));
+
+        let heap = Allocator::new();
+        let table = tenant.get_table(4).unwrap();
+        for i in 0..64u8 {
+            let entry = table.get(&[i, 1]).expect("Missing object on image");
+            let (_, value) = heap.resolve(entry.value).unwrap();
+            assert_eq!(&[i; 16], &value[..]);
+        }
+        assert!(table.get(&[64, 1]).is_none());
+        let scanned: usize = (0..N_BUCKETS).map(|b| table.bucket_entries(b).len()).sum();
+        assert_eq!(64, scanned);
+
+        // Writes are refused through the tenant, and do nothing if they get to the table.
+        assert!(table.read_only());
+        assert_eq!(
+            Some(RpcStatus::StatusReadOnlyTable),
+            tenant.writable_table(4).err()
+        );
+        table.put(Bytes::from(vec![0, 1]), Bytes::from(vec![0; 8]));
+        table.delete(&[1, 1]);
+        assert_eq!(16, heap.resolve(table.get(&[0, 1]).unwrap().value).unwrap().1.len());
+        assert!(table.get(&[1, 1]).is_some());
+    }
+
+    // Tests that tables added to a tenant start out at the tenant's epoch, and that a dropped
+    // table is handed back by remove_table().
+    #[test]
+    fn test_table_epochs() {
+        let tenant = owner(1);
+        tenant.set_epoch(3);
+        tenant.create_table(2);
+        assert_eq!(0, tenant.get_table(1).unwrap().epoch());
+        assert_eq!(3, tenant.get_table(2).unwrap().epoch());
+
+        let table = tenant.remove_table(1).expect("Table not removed");
+        assert!(table.get(&[0; 8]).is_some());
+        assert!(tenant.get_table(1).is_none());
+        assert!(tenant.remove_table(1).is_none());
+        assert!(!tenant.drop_table(1));
+
+        // Tables the tenant already has move along with it when it starts out at an epoch.
+        tenant.start_epoch(5);
+        assert_eq!(
+            (5, 5),
+            (tenant.epoch(), tenant.get_table(2).unwrap().epoch())
+        );
+        assert!(tenant.drop_table(2));
+    }
 }
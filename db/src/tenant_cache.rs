@@ -0,0 +1,318 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use std::cell::{Cell, RefCell};
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use std::sync::Arc;
+
+use super::tenant::Tenant;
+
+use sandstorm::common::TenantId;
+
+/// The number of tenant handles cached per core, unless configured otherwise.
+pub const DEFAULT_ENTRIES: usize = 16;
+
+/// The largest number of tenant handles that can be cached per core.
+pub const MAX_ENTRIES: usize = 4096;
+
+// Hands out an identifier to every tenant map, so that a core never serves one map's handles
+// for another's lookups (ex: in tests, where several Masters run on the same thread).
+static NEXT_MAP: AtomicUsize = ATOMIC_USIZE_INIT;
+
+thread_local! {
+    // The handles cached on this core. Created on the first lookup.
+    static CACHE: RefCell<Option<TenantCache>> = RefCell::new(None);
+
+    // Counters of the lookups on this core.
+    static STATS: Cell<CacheStats> = Cell::new(CacheStats::default());
+}
+
+/// Counters of the tenant lookups made on a core.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CacheStats {
+    /// The number of lookups served off the core's cache.
+    pub hits: u64,
+
+    /// The number of lookups that went to the tenant map.
+    pub misses: u64,
+}
+
+impl CacheStats {
+    /// Returns the fraction of lookups served off the cache, or 0 if there were none.
+    pub fn hit_rate(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            n => self.hits as f64 / n as f64,
+        }
+    }
+}
+
+/// Returns the counters of the calling core.
+pub fn stats() -> CacheStats {
+    STATS.with(|s| s.get())
+}
+
+/// The version of a tenant map; handles cached under an older version are never served. Bumped
+/// every time a tenant is added to or removed from the map.
+pub struct Epoch {
+    // Identifies the map.
+    map: usize,
+
+    // The version of the map.
+    version: AtomicUsize,
+}
+
+impl Epoch {
+    /// Returns the version of a new tenant map.
+    pub fn new() -> Epoch {
+        Epoch {
+            map: NEXT_MAP.fetch_add(1, Ordering::Relaxed),
+            version: AtomicUsize::new(0),
+        }
+    }
+
+    /// Invalidates every handle cached so far, on every core. Must be called after the map was
+    /// changed, so that a core that caches a handle it read before the change caches it under
+    /// the old version.
+    pub fn bump(&self) {
+        self.version.fetch_add(1, Ordering::Release);
+    }
+
+    // Returns the current version.
+    fn load(&self) -> usize {
+        self.version.load(Ordering::Acquire)
+    }
+}
+
+// A tenant handle cached on a core, along with the version of the map it was read under.
+struct Entry {
+    tenant: TenantId,
+    handle: Arc<Tenant>,
+    version: usize,
+}
+
+/// A direct-mapped cache of the tenant handles looked up on a core. Each tenant can only be
+/// cached in the slot picked by the low bits of it's id, so a lookup is a single compare of the
+/// id and version on that slot.
+pub struct TenantCache {
+    // The map handles are cached from.
+    map: usize,
+
+    // The slots, and the mask that picks a tenant's slot.
+    slots: Vec<Option<Entry>>,
+    mask: usize,
+}
+
+impl TenantCache {
+    /// Creates an empty cache.
+    ///
+    /// # Arguments
+    ///
+    /// * `epoch`:   The version of the map handles are cached from.
+    /// * `entries`: The number of handles to cache. Rounded up to a power of two, and capped at
+    ///              MAX_ENTRIES. 0 means DEFAULT_ENTRIES.
+    pub fn new(epoch: &Epoch, entries: usize) -> TenantCache {
+        let entries = match entries {
+            0 => DEFAULT_ENTRIES,
+            n => n.min(MAX_ENTRIES).next_power_of_two(),
+        };
+
+        TenantCache {
+            map: epoch.map,
+            slots: (0..entries).map(|_| None).collect(),
+            mask: entries - 1,
+        }
+    }
+
+    /// Returns the number of handles the cache can hold.
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Looks a tenant up, in the cache first and then in the map.
+    ///
+    /// # Arguments
+    ///
+    /// * `epoch`:  The version of the map.
+    /// * `tenant`: The identifier of the tenant.
+    /// * `miss`:   Looks the tenant up in the map. Called on a miss.
+    ///
+    /// # Return
+    ///
+    /// The tenant's handle, and true if it was served off the cache.
+    pub fn get<F>(
+        &mut self,
+        epoch: &Epoch,
+        tenant: TenantId,
+        miss: F,
+    ) -> (Option<Arc<Tenant>>, bool)
+    where
+        F: FnOnce() -> Option<Arc<Tenant>>,
+    {
+        let version = epoch.load();
+        let slot = &mut self.slots[tenant as usize & self.mask];
+        if let Some(ref entry) = *slot {
+            if entry.tenant == tenant && entry.version == version {
+                return (Some(Arc::clone(&entry.handle)), true);
+            }
+        }
+
+        // Tenants that do not exist are not cached, so that they are found as soon as they are
+        // added.
+        let handle = miss();
+        *slot = handle.as_ref().map(|handle| Entry {
+            tenant: tenant,
+            handle: Arc::clone(handle),
+            version: version,
+        });
+        (handle, false)
+    }
+}
+
+/// Looks a tenant up through the calling core's cache, creating the cache on the first lookup.
+/// A core caches handles from one map at a time; it's cache is emptied when a lookup is made on
+/// a different map.
+///
+/// # Arguments
+///
+/// * `epoch`:   The version of the map.
+/// * `entries`: The number of handles to cache, if the cache is created. Refer to
+///              TenantCache::new().
+/// * `tenant`:  The identifier of the tenant.
+/// * `miss`:    Looks the tenant up in the map. Called on a miss.
+///
+/// # Return
+///
+/// The tenant's handle, if it exists.
+pub fn lookup<F>(epoch: &Epoch, entries: usize, tenant: TenantId, miss: F) -> Option<Arc<Tenant>>
+where
+    F: FnOnce() -> Option<Arc<Tenant>>,
+{
+    let (handle, hit) = CACHE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        if cache.as_ref().map_or(true, |c| c.map != epoch.map) {
+            *cache = Some(TenantCache::new(epoch, entries));
+        }
+
+        cache.as_mut().unwrap().get(epoch, tenant, miss)
+    });
+
+    STATS.with(|s| {
+        let mut stats = s.get();
+        match hit {
+            true => stats.hits += 1,
+            false => stats.misses += 1,
+        }
+        s.set(stats);
+    });
+
+    handle
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    // Returns a lookup of `tenant` in a map that counts the lookups made on it.
+    fn find<'a>(
+        map: &'a HashMap<TenantId, Arc<Tenant>>,
+        tenant: TenantId,
+        calls: &'a Cell<u64>,
+    ) -> impl FnOnce() -> Option<Arc<Tenant>> + 'a {
+        move || {
+            calls.set(calls.get() + 1);
+            map.get(&tenant).cloned()
+        }
+    }
+
+    // Tests that repeated lookups are served off the cache, and that tenants that collide on a
+    // slot evict each other.
+    #[test]
+    fn test_cache_hits() {
+        let epoch = Epoch::new();
+        let mut map = HashMap::new();
+        for t in 1..4 {
+            map.insert(t, Arc::new(Tenant::new(t)));
+        }
+
+        let mut cache = TenantCache::new(&epoch, 2);
+        let calls = Cell::new(0);
+        for _ in 0..4 {
+            let (handle, _) = cache.get(&epoch, 1, find(&map, 1, &calls));
+            assert_eq!(1, handle.unwrap().id());
+        }
+        assert_eq!(1, calls.get());
+
+        // 3 shares a slot with 1.
+        assert!(cache.get(&epoch, 3, find(&map, 3, &calls)).0.is_some());
+        assert_eq!((true, false), {
+            let (_, hit3) = cache.get(&epoch, 3, find(&map, 3, &calls));
+            let (_, hit1) = cache.get(&epoch, 1, find(&map, 1, &calls));
+            (hit3, hit1)
+        });
+        assert!(cache.get(&epoch, 2, find(&map, 2, &calls)).0.is_some());
+        assert!(cache.get(&epoch, 2, find(&map, 2, &calls)).1);
+
+        assert_eq!(2, cache.capacity());
+        assert_eq!(DEFAULT_ENTRIES, TenantCache::new(&epoch, 0).capacity());
+        assert_eq!(8, TenantCache::new(&epoch, 5).capacity());
+        assert_eq!(MAX_ENTRIES, TenantCache::new(&epoch, 1 << 20).capacity());
+    }
+
+    // Tests that a bump invalidates cached handles, and that missing tenants are not cached.
+    #[test]
+    fn test_cache_invalidate() {
+        let epoch = Epoch::new();
+        let mut map = HashMap::new();
+        map.insert(1, Arc::new(Tenant::new(1)));
+
+        let mut cache = TenantCache::new(&epoch, 4);
+        let calls = Cell::new(0);
+        assert!(cache.get(&epoch, 1, find(&map, 1, &calls)).0.is_some());
+        assert!(cache.get(&epoch, 2, find(&map, 2, &calls)).0.is_none());
+
+        map.remove(&1);
+        map.insert(2, Arc::new(Tenant::new(2)));
+        epoch.bump();
+
+        let (handle, hit) = cache.get(&epoch, 1, find(&map, 1, &calls));
+        assert!(handle.is_none() && !hit);
+        let (handle, hit) = cache.get(&epoch, 2, find(&map, 2, &calls));
+        assert_eq!((Some(2), false), (handle.map(|t| t.id()), hit));
+        assert_eq!(4, calls.get());
+    }
+
+    // Tests that a core's cache and counters follow the map lookups are made on.
+    #[test]
+    fn test_lookup() {
+        let (a, b) = (Epoch::new(), Epoch::new());
+        let tenant = Arc::new(Tenant::new(7));
+        let before = stats();
+
+        for _ in 0..3 {
+            assert!(lookup(&a, 4, 7, || Some(Arc::clone(&tenant))).is_some());
+        }
+        assert!(lookup(&b, 4, 7, || None).is_none());
+        assert!(lookup(&a, 4, 7, || Some(Arc::clone(&tenant))).is_some());
+
+        let after = stats();
+        assert_eq!(2, after.hits - before.hits);
+        assert_eq!(3, after.misses - before.misses);
+        assert_eq!(0.0, CacheStats::default().hit_rate());
+        assert_eq!(0.25, CacheStats { hits: 1, misses: 3 }.hit_rate());
+    }
+}
@@ -0,0 +1,517 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Trailers on responses. Features that attach optional metadata to a response (ex: the
+//! records an extension read before it was pushed back, or a trace of when the request was
+//! received and answered) append it after the primary payload as trailers, so that they never
+//! have to agree on who owns the tail of the packet.
+//!
+//! A response carrying trailers has RESPONSE_FLAG_TRAILERS set on it's header, and ends in a
+//! trailer region; a sequence of entries, each a 1 byte type, a 2 byte length and the bytes,
+//! followed by a footer:
+//!
+//! |entries ...|0 1|truncated 1|len 2|
+//!
+//! The type 0 ends the entries. `truncated` has a bit set for every type that was left off
+//! because the response did not have room for it, and `len` is the length of the whole region,
+//! footer included, so that a client can find the region from the end of the packet. Lengths
+//! are little-endian. Clients skip types they don't know.
+
+use std::mem::transmute;
+
+use super::wireformat::RESPONSE_FLAG_TRAILERS;
+
+use sandstorm::common::PACKET_IP_LEN;
+
+/// The type that ends the entries in a trailer region.
+pub const TRAILER_END: u8 = 0;
+
+/// The type of the trailer carrying the records an extension read and wrote before it was
+/// pushed back, each packed as it's optype, version, key and object.
+pub const TRAILER_RWSET: u8 = 1;
+
+/// The type of the trailer carrying a Trace.
+pub const TRAILER_TRACE: u8 = 2;
+
+/// The largest type. Each type has a bit in the footer's truncation mask.
+pub const MAX_TRAILER_TYPE: u8 = 8;
+
+/// The length of the type and length ahead of every entry.
+pub const TRAILER_ENTRY_OVERHEAD: usize = 3;
+
+/// The length of the footer that ends a trailer region.
+pub const TRAILER_FOOTER_LEN: usize = 4;
+
+/// The length of a Trace packed into a trailer.
+pub const TRACE_LEN: usize = 3 * 8;
+
+// The server does not use jumbo frames, so a response and it's trailers must fit in a 1500 Byte
+// IP packet.
+const MAX_IP_LEN: usize = 1500;
+
+// Returns the bit a type has in the truncation mask.
+fn bit(kind: u8) -> u8 {
+    1 << (kind - 1)
+}
+
+/// Builds the trailer region of a response. Features request room for their trailers in a fixed
+/// order, and are refused once the response runs out of room; a feature earlier in the order is
+/// never crowded out by one later in it.
+pub struct TrailerWriter {
+    // The entries written so far, without the footer.
+    buf: Vec<u8>,
+
+    // The most bytes the region can take up, footer included.
+    capacity: usize,
+
+    // The bit of every type that was refused.
+    truncated: u8,
+}
+
+impl TrailerWriter {
+    /// Returns a writer with no trailers on it.
+    ///
+    /// # Arguments
+    ///
+    /// * `capacity`: The most bytes the trailer region can take up, footer included.
+    pub fn new(capacity: usize) -> TrailerWriter {
+        TrailerWriter {
+            buf: Vec::new(),
+            capacity: capacity,
+            truncated: 0,
+        }
+    }
+
+    /// Returns a writer holding the trailers already on a response, so that more can be added.
+    ///
+    /// # Arguments
+    ///
+    /// * `region`:   The trailer region on the response, footer included.
+    /// * `capacity`: The most bytes the trailer region can take up, footer included.
+    ///
+    /// # Return
+    ///
+    /// The writer, or None if the region is malformed.
+    pub fn resume(region: &[u8], capacity: usize) -> Option<TrailerWriter> {
+        let reader = TrailerReader::parse(region)?;
+        Some(TrailerWriter {
+            buf: reader.entries.to_vec(),
+            capacity: capacity,
+            truncated: reader.truncated,
+        })
+    }
+
+    /// Adds a trailer to the region, if there is room left for it.
+    ///
+    /// # Arguments
+    ///
+    /// * `kind`: The type of the trailer, between 1 and MAX_TRAILER_TYPE.
+    /// * `data`: The trailer.
+    ///
+    /// # Return
+    ///
+    /// True if the trailer was added. False if there wasn't room for it, in which case it's
+    /// type is marked truncated on the footer.
+    pub fn append(&mut self, kind: u8, data: &[u8]) -> bool {
+        debug_assert!(kind != TRAILER_END && kind <= MAX_TRAILER_TYPE);
+        let len = self.buf.len() + TRAILER_ENTRY_OVERHEAD + data.len() + TRAILER_FOOTER_LEN;
+        if data.len() > u16::max_value() as usize || len > self.capacity {
+            self.truncated |= bit(kind);
+            return false;
+        }
+
+        self.buf.push(kind);
+        self.buf.push(data.len() as u8);
+        self.buf.push((data.len() >> 8) as u8);
+        self.buf.extend_from_slice(data);
+        true
+    }
+
+    /// Returns true if a trailer of a type was refused for want of room.
+    pub fn truncated(&self, kind: u8) -> bool {
+        self.truncated & bit(kind) != 0
+    }
+
+    /// Returns the trailer region to append to the response, footer included. Empty if no
+    /// trailer was added or refused, in which case the response must not be flagged with
+    /// RESPONSE_FLAG_TRAILERS.
+    pub fn finish(self) -> Vec<u8> {
+        if self.buf.is_empty() && self.truncated == 0 {
+            return Vec::new();
+        }
+
+        let mut buf = self.buf;
+        let len = buf.len() + TRAILER_FOOTER_LEN;
+        buf.push(TRAILER_END);
+        buf.push(self.truncated);
+        buf.push(len as u8);
+        buf.push((len >> 8) as u8);
+        buf
+    }
+}
+
+/// Reads the trailers off a response.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TrailerReader<'a> {
+    // The entries in the region, without the footer.
+    entries: &'a [u8],
+
+    // The bit of every type that was left off the response.
+    truncated: u8,
+}
+
+impl<'a> TrailerReader<'a> {
+    /// Splits the payload of a response into it's primary payload and the trailers after it.
+    ///
+    /// # Arguments
+    ///
+    /// * `flags`:   The flags on the response header.
+    /// * `payload`: The payload of the response, upto the end of the packet.
+    ///
+    /// # Return
+    ///
+    /// The primary payload and a reader over the trailers, or None if the response is flagged
+    /// with RESPONSE_FLAG_TRAILERS and it's trailer region is malformed. The primary payload is
+    /// the whole of `payload` if the response is not flagged.
+    pub fn split(flags: u8, payload: &'a [u8]) -> Option<(&'a [u8], TrailerReader<'a>)> {
+        if flags & RESPONSE_FLAG_TRAILERS == 0 {
+            let none = TrailerReader {
+                entries: &[],
+                truncated: 0,
+            };
+            return Some((payload, none));
+        }
+
+        let len = region_len(payload)?;
+        let (primary, region) = payload.split_at(payload.len() - len);
+        TrailerReader::parse(region).map(|reader| (primary, reader))
+    }
+
+    // Returns a reader over a trailer region, footer included, or None if it is malformed.
+    fn parse(region: &'a [u8]) -> Option<TrailerReader<'a>> {
+        if region_len(region)? != region.len() {
+            return None;
+        }
+
+        let footer = region.len() - TRAILER_FOOTER_LEN;
+        if region[footer] != TRAILER_END {
+            return None;
+        }
+
+        let reader = TrailerReader {
+            entries: &region[..footer],
+            truncated: region[footer + 1],
+        };
+        let mut rest = reader.entries;
+        while !rest.is_empty() {
+            rest = &next(rest)?.2[..];
+        }
+        Some(reader)
+    }
+
+    /// Returns the trailer of a type, or None if the response does not carry one.
+    pub fn get(&self, kind: u8) -> Option<&'a [u8]> {
+        self.iter().find(|&(k, _)| k == kind).map(|(_, data)| data)
+    }
+
+    /// Returns true if the response was meant to carry a trailer of a type, but did not have
+    /// room for it.
+    pub fn truncated(&self, kind: u8) -> bool {
+        kind != TRAILER_END && kind <= MAX_TRAILER_TYPE && self.truncated & bit(kind) != 0
+    }
+
+    /// Returns an iterator over the type and bytes of every trailer, in the order they were
+    /// added.
+    pub fn iter(&self) -> Trailers<'a> {
+        Trailers { rest: self.entries }
+    }
+
+    /// Hands every trailer to the consumer registered for it's type. Trailers of types no
+    /// consumer is registered for are skipped, so that clients keep working against servers
+    /// that add new types.
+    ///
+    /// # Arguments
+    ///
+    /// * `consumers`: The type every consumer is registered for, and the consumer.
+    ///
+    /// # Return
+    ///
+    /// The number of trailers skipped.
+    pub fn dispatch(&self, consumers: &mut [(u8, &mut FnMut(&[u8]))]) -> usize {
+        let mut skipped = 0;
+        for (kind, data) in self.iter() {
+            match consumers.iter_mut().find(|c| c.0 == kind) {
+                Some(consumer) => (consumer.1)(data),
+                None => skipped += 1,
+            }
+        }
+        skipped
+    }
+}
+
+/// An iterator over the trailers on a response. Refer to TrailerReader::iter().
+pub struct Trailers<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> Iterator for Trailers<'a> {
+    type Item = (u8, &'a [u8]);
+
+    fn next(&mut self) -> Option<(u8, &'a [u8])> {
+        let (kind, data, rest) = next(self.rest)?;
+        self.rest = rest;
+        Some((kind, data))
+    }
+}
+
+// Splits the first entry off a sequence of entries, returning it's type and bytes, and the
+// entries after it. None if the sequence is empty or the entry runs past it.
+fn next(entries: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    if entries.len() < TRAILER_ENTRY_OVERHEAD || entries[0] == TRAILER_END {
+        return None;
+    }
+
+    let len = entries[1] as usize | (entries[2] as usize) << 8;
+    let end = TRAILER_ENTRY_OVERHEAD + len;
+    if entries.len() < end {
+        return None;
+    }
+    Some((
+        entries[0],
+        &entries[TRAILER_ENTRY_OVERHEAD..end],
+        &entries[end..],
+    ))
+}
+
+/// Returns the room a response leaves for trailers in a 1500 Byte IP packet.
+///
+/// # Arguments
+///
+/// * `len`: The length of the response from it's RPC header upto the end of it's primary
+///          payload.
+pub fn room(len: usize) -> usize {
+    (MAX_IP_LEN - PACKET_IP_LEN as usize).saturating_sub(len)
+}
+
+/// Returns the length of the trailer region a payload ends in, footer included, read off the
+/// footer. None if the payload is too short to hold the region it claims.
+///
+/// # Arguments
+///
+/// * `payload`: The payload, upto the end of the packet.
+pub fn region_len(payload: &[u8]) -> Option<usize> {
+    if payload.len() < TRAILER_FOOTER_LEN {
+        return None;
+    }
+
+    let end = payload.len();
+    let len = payload[end - 2] as usize | (payload[end - 1] as usize) << 8;
+    match len >= TRAILER_FOOTER_LEN && len <= end {
+        true => Some(len),
+        false => None,
+    }
+}
+
+/// When a request was received and it's response sent out, on the server's clock. Carried by a
+/// TRAILER_TRACE trailer, if the request had REQUEST_FLAG_TRACE set. Unlike the 32 bit stamps
+/// on the response header, these can be compared across requests.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Trace {
+    /// The cycle counter when the request was received off the network.
+    pub rx: u64,
+
+    /// The cycle counter when the response was handed off to be sent out.
+    pub tx: u64,
+
+    /// The number of cycles per second on the server's clock.
+    pub hz: u64,
+}
+
+impl Trace {
+    /// Packs the trace into a trailer, as the little-endian 8 byte rx, tx and hz.
+    pub fn encode(&self) -> [u8; TRACE_LEN] {
+        let mut buf = [0; TRACE_LEN];
+        for (chunk, v) in buf.chunks_mut(8).zip([self.rx, self.tx, self.hz].iter()) {
+            let field: [u8; 8] = unsafe { transmute(v.to_le()) };
+            chunk.copy_from_slice(&field);
+        }
+        buf
+    }
+
+    /// Unpacks a trace off a trailer. None if the trailer is not exactly TRACE_LEN bytes.
+    pub fn parse(data: &[u8]) -> Option<Trace> {
+        if data.len() != TRACE_LEN {
+            return None;
+        }
+
+        let mut fields = [0u64; 3];
+        for (v, chunk) in fields.iter_mut().zip(data.chunks(8)) {
+            let mut field = [0; 8];
+            field.copy_from_slice(chunk);
+            *v = u64::from_le(unsafe { transmute(field) });
+        }
+        Some(Trace {
+            rx: fields[0],
+            tx: fields[1],
+            hz: fields[2],
+        })
+    }
+}
+
+// This module contains tests for packing and reading trailers.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Tests that several trailers are read back in the order they were added, along with the
+    // primary payload ahead of them.
+    #[test]
+    fn test_trailers_pack() {
+        let trace = Trace {
+            rx: 1,
+            tx: 0x0102_0304_0506_0708,
+            hz: 2_400_000_000,
+        };
+        let mut writer = TrailerWriter::new(128);
+        assert!(writer.append(TRAILER_RWSET, b"records"));
+        assert!(writer.append(TRAILER_TRACE, &trace.encode()));
+        assert!(writer.append(7, &[]));
+
+        let mut payload = b"primary".to_vec();
+        let region = writer.finish();
+        assert_eq!(3 * 3 + 7 + TRACE_LEN + TRAILER_FOOTER_LEN, region.len());
+        payload.extend_from_slice(&region);
+
+        let (primary, reader) = TrailerReader::split(RESPONSE_FLAG_TRAILERS, &payload).unwrap();
+        assert_eq!(b"primary", primary);
+        assert_eq!(
+            vec![
+                (TRAILER_RWSET, &b"records"[..]),
+                (TRAILER_TRACE, &trace.encode()[..]),
+                (7, &[][..]),
+            ],
+            reader.iter().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            Some(trace),
+            reader.get(TRAILER_TRACE).and_then(Trace::parse)
+        );
+        assert_eq!(None, reader.get(3));
+        assert!(!reader.truncated(TRAILER_RWSET));
+    }
+
+    // Tests that trailers are refused once the region runs out of room, the ones added first
+    // winning, and that refused types are marked truncated.
+    #[test]
+    fn test_trailers_capacity() {
+        let capacity = 2 * TRAILER_ENTRY_OVERHEAD + 10 + 5 + TRAILER_FOOTER_LEN;
+        let mut writer = TrailerWriter::new(capacity);
+        assert!(writer.append(TRAILER_RWSET, &[1; 10]));
+        assert!(!writer.append(TRAILER_TRACE, &[2; 6]));
+        assert!(writer.append(3, &[3; 5]));
+        assert!(!writer.append(4, &[]));
+        assert!(writer.truncated(TRAILER_TRACE) && writer.truncated(4));
+        assert!(!writer.truncated(TRAILER_RWSET));
+
+        let region = writer.finish();
+        assert_eq!(capacity, region.len());
+        let (primary, reader) = TrailerReader::split(RESPONSE_FLAG_TRAILERS, &region).unwrap();
+        assert!(primary.is_empty());
+        assert_eq!(Some(&[1; 10][..]), reader.get(TRAILER_RWSET));
+        assert_eq!(None, reader.get(TRAILER_TRACE));
+        assert!(reader.truncated(TRAILER_TRACE) && reader.truncated(4));
+        assert!(!reader.truncated(TRAILER_RWSET) && !reader.truncated(3));
+
+        // A region with every trailer refused still tells the client they were.
+        let mut writer = TrailerWriter::new(0);
+        assert!(!writer.append(TRAILER_RWSET, b"records"));
+        let region = writer.finish();
+        let (_, reader) = TrailerReader::split(RESPONSE_FLAG_TRAILERS, &region).unwrap();
+        assert_eq!(0, reader.iter().count());
+        assert!(reader.truncated(TRAILER_RWSET));
+    }
+
+    // Tests that trailers of types without a consumer are skipped, and the rest handed to
+    // their consumers.
+    #[test]
+    fn test_trailers_dispatch() {
+        let mut writer = TrailerWriter::new(128);
+        writer.append(5, b"unknown");
+        writer.append(TRAILER_TRACE, &Trace::default().encode());
+        writer.append(MAX_TRAILER_TYPE, b"unknown too");
+        writer.append(TRAILER_RWSET, b"records");
+        let region = writer.finish();
+        let (_, reader) = TrailerReader::split(RESPONSE_FLAG_TRAILERS, &region).unwrap();
+
+        let (mut traces, mut rwset) = (Vec::new(), Vec::new());
+        {
+            let mut on_trace = |data: &[u8]| traces.push(Trace::parse(data));
+            let mut on_rwset = |data: &[u8]| rwset.extend_from_slice(data);
+            let skipped = reader.dispatch(&mut [
+                (TRAILER_TRACE, &mut on_trace as &mut FnMut(&[u8])),
+                (TRAILER_RWSET, &mut on_rwset),
+            ]);
+            assert_eq!(2, skipped);
+        }
+        assert_eq!(vec![Some(Trace::default())], traces);
+        assert_eq!(b"records".to_vec(), rwset);
+    }
+
+    // Tests that a response without the flag is read exactly as it would be without trailers,
+    // even if it's payload happens to end in what looks like a trailer region, and that a
+    // writer with nothing on it adds nothing.
+    #[test]
+    fn test_trailers_legacy() {
+        let mut writer = TrailerWriter::new(128);
+        writer.append(TRAILER_RWSET, b"records");
+        let mut payload = b"primary".to_vec();
+        payload.extend_from_slice(&writer.finish());
+
+        let (primary, reader) = TrailerReader::split(0, &payload).unwrap();
+        assert_eq!(&payload[..], primary);
+        assert_eq!(0, reader.iter().count());
+        assert!(!reader.truncated(TRAILER_RWSET));
+
+        assert!(TrailerWriter::new(128).finish().is_empty());
+    }
+
+    // Tests that malformed regions are refused, and that trailers can be added to a region
+    // already on a response.
+    #[test]
+    fn test_trailers_resume() {
+        let mut writer = TrailerWriter::new(64);
+        writer.append(TRAILER_RWSET, b"records");
+        writer.append(6, &[0; 64]);
+        let region = writer.finish();
+
+        let mut writer = TrailerWriter::resume(&region, 64).unwrap();
+        assert!(writer.truncated(6));
+        assert!(writer.append(TRAILER_TRACE, &Trace::default().encode()));
+        let region = writer.finish();
+        let (_, reader) = TrailerReader::split(RESPONSE_FLAG_TRAILERS, &region).unwrap();
+        assert_eq!(
+            vec![TRAILER_RWSET, TRAILER_TRACE],
+            reader.iter().map(|(kind, _)| kind).collect::<Vec<_>>()
+        );
+        assert!(reader.truncated(6));
+
+        let flags = RESPONSE_FLAG_TRAILERS;
+        assert!(TrailerReader::split(flags, &region[1..]).is_none());
+        assert!(TrailerReader::split(flags, &region[..region.len() - 1]).is_none());
+        assert!(TrailerReader::split(flags, &[0, 0, 4]).is_none());
+        assert!(TrailerReader::split(flags, &[1, 9, 0, 0, 0, 7, 0]).is_none());
+        assert!(TrailerWriter::resume(&region[2..], 64).is_none());
+    }
+}
@@ -15,15 +15,22 @@
 
 use super::wireformat::Record;
 
+/// The most records a TX tracks across it's read and write sets. Records past it are dropped,
+/// and the TX is marked truncated; refer to TX::truncated().
+pub const MAX_RECORDS: usize = 64;
+
 /// This type is used by the extension invocation to record the read-write set.
 /// And the read-write set is transferred back to the client on Pushback. Also,
 /// it is used by the table type to validate and commit the transaction.
 pub struct TX {
-    // This vector maintains the read-set for a transaction.
+    // The read-set of the transaction, in the order records were read.
     reads: Vec<Record>,
 
-    // This vector maintains the write-set for a transaction.
+    // The write-set of the transaction, in the order records were written.
     writes: Vec<Record>,
+
+    // Set once a record is dropped because the TX already tracks MAX_RECORDS records.
+    truncated: bool,
 }
 
 impl TX {
@@ -32,19 +39,26 @@ impl TX {
         TX {
             reads: Vec::with_capacity(4),
             writes: Vec::with_capacity(2),
+            truncated: false,
         }
     }
 
     /// This method returns the reference to the read-set.
-    pub fn reads(&self) -> &Vec<Record> {
+    pub fn reads(&self) -> &[Record] {
         &self.reads
     }
 
     /// This method returns the reference to the write-set.
-    pub fn writes(&self) -> &Vec<Record> {
+    pub fn writes(&self) -> &[Record] {
         &self.writes
     }
 
+    /// This method returns true if a record was dropped because the TX was full. The read and
+    /// write sets are then incomplete, and cannot be validated or replayed.
+    pub fn truncated(&self) -> bool {
+        self.truncated
+    }
+
     /// This method sorts the read and write set based on the key.
     pub fn sort(&mut self) {
         self.reads.sort_by_key(|record| record.get_key());
@@ -56,6 +70,9 @@ impl TX {
     /// # Arguments
     /// *`record`: The record containing the Optype and key+value.
     pub fn record_get(&mut self, record: Record) {
+        if self.full() {
+            return;
+        }
         self.reads.push(record);
     }
 
@@ -64,6 +81,70 @@ impl TX {
     /// # Arguments
     /// *`record`: The record containing the Optype and key+value.
     pub fn record_put(&mut self, record: Record) {
+        if self.full() {
+            return;
+        }
         self.writes.push(record);
     }
+
+    // Returns true if no more records can be tracked, marking the TX truncated.
+    fn full(&mut self) -> bool {
+        if self.reads.len() + self.writes.len() >= MAX_RECORDS {
+            self.truncated = true;
+        }
+        self.truncated
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::table::Table;
+    use super::super::wireformat::OpType;
+    use super::*;
+
+    use bytes::Bytes;
+
+    // Returns a record of an object written to a table, with the version the table gave it.
+    fn record(optype: OpType, key: u8) -> Record {
+        let (key, value) = (Bytes::from(vec![key]), Bytes::from(vec![key; 4]));
+        let version = Table::default()
+            .put(key.clone(), value.clone())
+            .unwrap()
+            .version;
+        Record::new(optype, 1, version, key, value)
+    }
+
+    // Tests that a TX stops tracking records once it is full, and is then marked truncated.
+    #[test]
+    fn test_tx_truncated() {
+        let mut tx = TX::new();
+        for i in 0..MAX_RECORDS / 2 {
+            tx.record_get(record(OpType::SandstormRead, i as u8));
+            tx.record_put(record(OpType::SandstormWrite, i as u8));
+        }
+        assert!(!tx.truncated());
+        assert_eq!(MAX_RECORDS / 2, tx.reads().len());
+        assert_eq!(MAX_RECORDS / 2, tx.writes().len());
+
+        tx.record_get(record(OpType::SandstormRead, 0));
+        tx.record_put(record(OpType::SandstormWrite, 0));
+        assert!(tx.truncated());
+        assert_eq!(MAX_RECORDS, tx.reads().len() + tx.writes().len());
+    }
+
+    // Tests that records land in the read-set or the write-set in the order they are made in,
+    // whichever way reads and writes are interleaved.
+    #[test]
+    fn test_tx_records() {
+        let mut tx = TX::new();
+        tx.record_put(record(OpType::SandstormWrite, 1));
+        tx.record_get(record(OpType::SandstormRead, 2));
+        tx.record_put(record(OpType::SandstormWrite, 3));
+        tx.record_get(record(OpType::SandstormRead, 4));
+
+        let keys = |set: &[Record]| -> Vec<u8> { set.iter().map(|r| r.get_key()[0]).collect() };
+        assert_eq!(vec![2, 4], keys(tx.reads()));
+        assert_eq!(vec![1, 3], keys(tx.writes()));
+        assert!(tx.reads().iter().all(|r| r.get_table_id() == 1));
+    }
 }
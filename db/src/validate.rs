@@ -0,0 +1,1744 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Startup validation of server and client configs. Every rule below is a small function that
+//! appends the problems it finds to a Report, so that a misconfigured run is refused with a list
+//! of everything that is wrong with it, instead of dying on the first problem (or worse, running
+//! and producing meaningless numbers).
+
+use std::cmp;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::net::Ipv4Addr;
+use std::process;
+use std::str::FromStr;
+
+use super::backoff::MAX_PAUSE_US;
+use super::config::{
+    auth_layout, parse_atypes, parse_cache_tables, parse_cores, parse_mac, parse_opcodes,
+    parse_shared_tables, parse_shares, parse_tables, parse_tenants, ClientConfig, ServerConfig,
+};
+use super::dedup::MAX_DEDUP_ENTRIES;
+use super::fill;
+use super::hint;
+use super::hot;
+use super::limits::{HARD_MAX_KEY_LEN, HARD_MAX_VALUE_LEN};
+use super::master::TEST_EXTENSIONS;
+use super::presize::MIN_LOAD_FACTOR;
+use super::tenant_cache;
+use super::wireformat::OpCode;
+
+use sandstorm::auth;
+use sandstorm::tao::{self, Fanout};
+
+/// A single problem found with a config.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Problem {
+    /// The rule that found the problem.
+    pub rule: &'static str,
+    /// What is wrong, and what to do about it.
+    pub message: String,
+}
+
+/// Every problem found with a config. Errors should stop the binary from starting up; warnings
+/// are for combinations that are legal but probably not what was intended.
+#[derive(Debug, Default)]
+pub struct Report {
+    /// Problems that make the config unusable.
+    pub errors: Vec<Problem>,
+    /// Problems that are suspicious, but don't stop the config from being used.
+    pub warnings: Vec<Problem>,
+}
+
+impl Report {
+    /// Returns an empty report.
+    pub fn new() -> Report {
+        Report::default()
+    }
+
+    // Adds an error found by `rule` to the report.
+    fn error(&mut self, rule: &'static str, message: String) {
+        self.errors.push(Problem {
+            rule: rule,
+            message: message,
+        });
+    }
+
+    // Adds a warning found by `rule` to the report.
+    fn warn(&mut self, rule: &'static str, message: String) {
+        self.warnings.push(Problem {
+            rule: rule,
+            message: message,
+        });
+    }
+
+    /// Returns true if the report has no errors. Warnings don't count.
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    /// Returns true if `rule` found an error or a warning.
+    pub fn fired(&self, rule: &str) -> bool {
+        self.errors
+            .iter()
+            .chain(self.warnings.iter())
+            .any(|p| p.rule == rule)
+    }
+
+    /// Logs every warning in the report, and exits the process with the full report if it has
+    /// any errors.
+    ///
+    /// # Arguments
+    ///
+    /// * `what`: The config the report is for, ex: "server.toml".
+    pub fn enforce(&self, what: &str) {
+        for p in self.warnings.iter() {
+            warn!("{}: {} ({})", what, p.message, p.rule);
+        }
+
+        if !self.is_ok() {
+            error!("Refusing to start with {}:\n{}", what, self);
+            process::exit(1);
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for p in self.errors.iter() {
+            writeln!(f, "  error   [{}] {}", p.rule, p.message)?;
+        }
+        for p in self.warnings.iter() {
+            writeln!(f, "  warning [{}] {}", p.rule, p.message)?;
+        }
+        Ok(())
+    }
+}
+
+/// The lengths of the records a workload populates the server with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Layout {
+    /// The length of every key.
+    pub key_len: usize,
+    /// The length of every value, if all values are the same length.
+    pub value_len: Option<usize>,
+}
+
+/// Returns the layout of the records the server populates under a workload, if the workload
+/// has one that clients need to agree with.
+///
+/// # Arguments
+///
+/// * `workload`: The workload, as named in server.toml.
+pub fn record_layout(workload: &str) -> Option<Layout> {
+    match workload {
+        "YCSB" | "PUSHBACK" | "AGGREGATE" => Some(Layout {
+            key_len: 30,
+            value_len: Some(100),
+        }),
+
+        "AUTH" | "ANALYSIS" => Some(Layout {
+            key_len: 30,
+            value_len: None,
+        }),
+
+        _ => None,
+    }
+}
+
+// The workloads the server knows how to populate. Anything else populates SANITY data.
+const WORKLOADS: [&str; 7] = [
+    "YCSB",
+    "TAO",
+    "AGGREGATE",
+    "PUSHBACK",
+    "ANALYSIS",
+    "AUTH",
+    "MIX",
+];
+
+// The distributions keys and tenants can be drawn from; see splinter::dist.
+const DISTRIBUTIONS: [&str; 5] = ["", "zipf", "uniform", "hotspot", "sequential"];
+
+// The largest number of ranks a hot set rotation permutes; see splinter::dist::ROTATE_RANKS_MAX.
+const ROTATE_RANKS_MAX: usize = 16384;
+
+/// What a client binary needs from it's config and the machine it runs on.
+pub struct Client<'a> {
+    /// The server workload the client issues requests against, as named in server.toml.
+    pub workload: &'a str,
+    /// The number of pipelines the client sends requests on.
+    pub pipelines: usize,
+    /// The cores the client pins threads to. Empty if it doesn't pin any.
+    pub cores: &'a [i32],
+    /// True if the client loads the extensions in master::TEST_EXTENSIONS itself.
+    pub extensions: bool,
+}
+
+/// Checks a server config.
+///
+/// # Arguments
+///
+/// * `config`: The config to check.
+/// * `cores`:  The cores the server pins threads to.
+///
+/// # Return
+///
+/// A report of every problem found.
+pub fn validate_server(config: &ServerConfig, cores: &[i32]) -> Report {
+    let mut report = Report::new();
+
+    check_mac("mac_address", config.mac_address(), &mut report);
+    check_mac("client_mac", config.client_mac(), &mut report);
+    check_workload(config, &mut report);
+    check_records(config, &mut report);
+    check_ml_model(&config.workload, cfg!(feature = "ml-model"), &mut report);
+    check_server_specs(config, &mut report);
+    check_compression(config, &mut report);
+    check_pushback(config, &mut report);
+    check_crash(config, &mut report);
+    check_memcache(config, &mut report);
+    check_audit(config, &mut report);
+    check_limits(config, &mut report);
+    check_fairness(config, &mut report);
+    check_backoff(config, &mut report);
+    check_integrity(config, &mut report);
+    check_dedup(config, &mut report);
+    check_tenant_cache(config, &mut report);
+    check_fill(config, &mut report);
+    check_image(config, &mut report);
+    check_quarantine(config, &mut report);
+    check_hot_keys(config, &mut report);
+    check_hints(config, &mut report);
+    check_hot_arena(config, &mut report);
+    check_presize(config, &mut report);
+    check_mailbox(config, &mut report);
+    check_auth_layout(
+        config.auth_cost,
+        config.auth_hash_len,
+        config.auth_salt_len,
+        &mut report,
+    );
+    check_cores(cores, online_cores().as_ref().map(|c| &c[..]), &mut report);
+
+    // Extensions aren't loaded at all unless invoke() is served.
+    let invoke = OpCode::SandstormInvokeRpc.bit();
+    if parse_opcodes(&config.enabled_opcodes).map_or(true, |ops| ops & invoke != 0) {
+        check_extensions(&TEST_EXTENSIONS, &mut report);
+    }
+
+    report
+}
+
+/// Checks a client config.
+///
+/// # Arguments
+///
+/// * `config`: The config to check.
+/// * `client`: What the client binary needs from the config.
+///
+/// # Return
+///
+/// A report of every problem found.
+pub fn validate_client(config: &ClientConfig, client: &Client) -> Report {
+    let mut report = Report::new();
+
+    check_mac("mac_address", config.mac_address(), &mut report);
+    check_mac(
+        "server_mac_address",
+        config.server_mac_address(),
+        &mut report,
+    );
+    check_transport(config, &mut report);
+    check_layout(config, client.workload, &mut report);
+    check_requests(config, client.pipelines, &mut report);
+    check_key_dist(config, &mut report);
+    check_tenant_dist(config, &mut report);
+    check_rotation(config, &mut report);
+    check_percentages(config, &mut report);
+    check_tao_mix(config, &mut report);
+    check_pacing(config, &mut report);
+    check_faults(config, &mut report);
+    check_failover(config, &mut report);
+    check_auth_puts(config, client.workload, &mut report);
+    check_hint_cache(config, client.workload, &mut report);
+    check_pushback_queue(config, client.workload, &mut report);
+    check_auth_layout(
+        config.auth_cost,
+        config.auth_hash_len,
+        config.auth_salt_len,
+        &mut report,
+    );
+    check_cores(
+        client.cores,
+        online_cores().as_ref().map(|c| &c[..]),
+        &mut report,
+    );
+    if client.extensions {
+        check_extensions(&TEST_EXTENSIONS, &mut report);
+    }
+
+    report
+}
+
+// Reads the cores the OS reports as online, if it can.
+fn online_cores() -> Option<Vec<i32>> {
+    let mut online = String::new();
+    File::open("/sys/devices/system/cpu/online")
+        .and_then(|mut file| file.read_to_string(&mut online))
+        .ok()
+        .and_then(|_| parse_cores(online.trim()))
+}
+
+// A MAC address that doesn't parse binds to nothing, and the run sees zero responses.
+fn check_mac(field: &'static str, mac: &str, report: &mut Report) {
+    if parse_mac(mac).is_err() {
+        report.error(
+            "mac",
+            format!(
+                "{} \"{}\" is not six colon separated hex bytes, ex: \"3c:fd:fe:04:9f:42\"",
+                field, mac
+            ),
+        );
+    }
+}
+
+// An unknown workload silently populates SANITY data instead.
+fn check_workload(config: &ServerConfig, report: &mut Report) {
+    let workload = config.workload.as_str();
+    if workload != "" && !WORKLOADS.iter().any(|w| *w == workload) {
+        report.warn(
+            "workload",
+            format!(
+                "workload \"{}\" is unknown, so SANITY data will be populated; expected one of {:?}",
+                workload, WORKLOADS
+            ),
+        );
+    }
+}
+
+// Every workload other than SANITY needs tenants and records to populate.
+fn check_records(config: &ServerConfig, report: &mut Report) {
+    if !WORKLOADS.iter().any(|w| *w == config.workload) {
+        return;
+    }
+
+    if config.num_tenants == 0 {
+        report.error(
+            "num_tenants",
+            format!("num_tenants must be atleast 1 under {}", config.workload),
+        );
+    }
+
+    if config.num_records == 0 && config.workload != "ANALYSIS" {
+        report.error(
+            "num_records",
+            format!("num_records must be atleast 1 under {}", config.workload),
+        );
+    }
+}
+
+// ANALYSIS and MIX serve a model that is only built with the "ml-model" feature.
+fn check_ml_model(workload: &str, ml_model: bool, report: &mut Report) {
+    if (workload == "ANALYSIS" || workload == "MIX") && !ml_model {
+        report.error(
+            "ml_model",
+            format!(
+                "workload {} requires the server to be built with the \"ml-model\" feature",
+                workload
+            ),
+        );
+    }
+}
+
+// The spec fields of a server config must parse.
+fn check_server_specs(config: &ServerConfig, report: &mut Report) {
+    if config.tao_fanout != "" && Fanout::parse(&config.tao_fanout).is_none() {
+        report.error(
+            "tao_fanout",
+            format!("tao_fanout \"{}\" is malformed", config.tao_fanout),
+        );
+    }
+
+    if parse_shared_tables(&config.shared_tables).is_none() {
+        report.error(
+            "shared_tables",
+            format!(
+                "shared_tables \"{}\" is malformed; expected \"OWNER:TABLE@TENANT:ALIAS,...\"",
+                config.shared_tables
+            ),
+        );
+    }
+
+    if parse_cache_tables(&config.cache_tables).is_none() {
+        report.error(
+            "cache_tables",
+            format!(
+                "cache_tables \"{}\" is malformed; expected \"TENANT:TABLE:BYTES:OBJECTS:POLICY,...\"",
+                config.cache_tables
+            ),
+        );
+    }
+
+    let opcodes = [
+        ("enabled_opcodes", &config.enabled_opcodes),
+        ("replay_opcodes", &config.replay_opcodes),
+    ];
+    for &(field, spec) in opcodes.iter() {
+        if parse_opcodes(spec).is_none() {
+            report.error(
+                field,
+                format!(
+                    "{} \"{}\" is malformed; expected a comma separated list of get, put, \
+                     invoke, install, multiget, list_ext, table_access, run_stats, merge, \
+                     set_merge, routed_invoke, set_route, audit, dump, multiput, write_stats, \
+                     core_stats, latency_stats, quarantine, create_table, fetch_result, \
+                     delete, scan, cas and drop_table",
+                    field, spec
+                ),
+            );
+        }
+    }
+}
+
+// A compression ratio below 1 would store values that compression made larger.
+fn check_compression(config: &ServerConfig, report: &mut Report) {
+    let ratio = config.compress_min_ratio;
+    if ratio != 0.0 && !(ratio >= 1.0) {
+        report.error(
+            "compress_min_ratio",
+            format!("compress_min_ratio {} must be 0 or atleast 1", ratio),
+        );
+    }
+}
+
+// A negative product threshold would push back every yielded invoke, however idle the core.
+fn check_pushback(config: &ServerConfig, report: &mut Report) {
+    let products = [
+        ("pushback_depth_product", config.pushback_depth_product),
+        ("pushback_delay_product", config.pushback_delay_product),
+    ];
+
+    for &(rule, product) in products.iter() {
+        if !(product >= 0.0) {
+            report.error(rule, format!("{} {} must be atleast 0", rule, product));
+        }
+    }
+}
+
+// The server panics at startup on a crash policy it doesn't know.
+fn check_crash(config: &ServerConfig, report: &mut Report) {
+    match config.crash_policy.as_str() {
+        "" | "abort" | "isolate" => {}
+        policy => report.error(
+            "crash_policy",
+            format!(
+                "crash_policy \"{}\" must be \"abort\" or \"isolate\"",
+                policy
+            ),
+        ),
+    }
+}
+
+// Memcached commands served off a tenant that was never populated would all miss or fail.
+fn check_memcache(config: &ServerConfig, report: &mut Report) {
+    let tenant = cmp::max(config.memcache_tenant, 1);
+    if config.memcache_port != 0 && tenant > config.num_tenants {
+        report.error(
+            "memcache_tenant",
+            format!(
+                "memcache_tenant {} must be atmost num_tenants {}",
+                tenant, config.num_tenants
+            ),
+        );
+    }
+}
+
+// The server panics at startup on a malformed list, and a tenant that was never populated can't
+// issue any invokes to audit.
+fn check_audit(config: &ServerConfig, report: &mut Report) {
+    match parse_tenants(&config.audit_tenants) {
+        None => report.error(
+            "audit_tenants",
+            format!(
+                "audit_tenants \"{}\" must be a comma separated list of tenant ids",
+                config.audit_tenants
+            ),
+        ),
+        Some(tenants) => {
+            for tenant in tenants.into_iter() {
+                if tenant == 0 || tenant > config.num_tenants {
+                    report.error(
+                        "audit_tenants",
+                        format!(
+                            "audit_tenants {} must be between 1 and num_tenants {}",
+                            tenant, config.num_tenants
+                        ),
+                    );
+                }
+            }
+        }
+    }
+}
+
+// Limits past the hard maximums would be silently clamped to them.
+fn check_limits(config: &ServerConfig, report: &mut Report) {
+    if config.max_key_len > HARD_MAX_KEY_LEN {
+        report.error(
+            "max_key_len",
+            format!(
+                "max_key_len {} must be atmost {}, the longest key an object can have",
+                config.max_key_len, HARD_MAX_KEY_LEN
+            ),
+        );
+    }
+
+    if config.max_value_len > HARD_MAX_VALUE_LEN {
+        report.error(
+            "max_value_len",
+            format!(
+                "max_value_len {} must be atmost {}, the largest value a get() response can carry",
+                config.max_value_len, HARD_MAX_VALUE_LEN
+            ),
+        );
+    }
+}
+
+// The server panics after populating the workload if the image can't be mapped in.
+fn check_fairness(config: &ServerConfig, report: &mut Report) {
+    if !(config.fair_quantum_us >= 0.0) {
+        report.error(
+            "fair_quantum_us",
+            format!(
+                "fair_quantum_us {} must be atleast 0",
+                config.fair_quantum_us
+            ),
+        );
+    }
+
+    match parse_shares(&config.tenant_shares) {
+        None => report.error(
+            "tenant_shares",
+            format!(
+                "tenant_shares \"{}\" must be a comma separated list of tenant:share, with shares \
+                 of atleast 1",
+                config.tenant_shares
+            ),
+        ),
+        Some(shares) => {
+            for (tenant, _) in shares.into_iter() {
+                if tenant == 0 || tenant > config.num_tenants {
+                    report.error(
+                        "tenant_shares",
+                        format!(
+                            "tenant_shares {} must be between 1 and num_tenants {}",
+                            tenant, config.num_tenants
+                        ),
+                    );
+                }
+            }
+        }
+    }
+}
+
+// A core that pauses for longer than the misbehaving task limit would be taken for stuck.
+fn check_backoff(config: &ServerConfig, report: &mut Report) {
+    if config.poll_backoff_max_us > MAX_PAUSE_US {
+        report.error(
+            "poll_backoff_max_us",
+            format!(
+                "poll_backoff_max_us {} must be atmost {}",
+                config.poll_backoff_max_us, MAX_PAUSE_US
+            ),
+        );
+    }
+}
+
+// A sweep with no checksummed tables to walk never checks anything.
+fn check_integrity(config: &ServerConfig, report: &mut Report) {
+    match parse_tables(&config.checksum_tables) {
+        None => report.error(
+            "checksum_tables",
+            format!(
+                "checksum_tables \"{}\" must be a comma separated list of table ids",
+                config.checksum_tables
+            ),
+        ),
+
+        Some(ref tables) if tables.is_empty() && config.verify_objects_per_sec > 0 => report.warn(
+            "verify_objects_per_sec",
+            format!(
+                "verify_objects_per_sec {} has no effect without checksum_tables",
+                config.verify_objects_per_sec
+            ),
+        ),
+
+        Some(_) => {}
+    }
+}
+
+// A larger index would be silently capped.
+fn check_dedup(config: &ServerConfig, report: &mut Report) {
+    if parse_tables(&config.dedup_tables).is_none() {
+        report.error(
+            "dedup_tables",
+            format!(
+                "dedup_tables \"{}\" must be a comma separated list of table ids",
+                config.dedup_tables
+            ),
+        );
+    }
+
+    if config.dedup_entries > MAX_DEDUP_ENTRIES {
+        report.error(
+            "dedup_entries",
+            format!(
+                "dedup_entries {} must be atmost {}",
+                config.dedup_entries, MAX_DEDUP_ENTRIES
+            ),
+        );
+    }
+}
+
+// A larger cache would be silently capped.
+fn check_tenant_cache(config: &ServerConfig, report: &mut Report) {
+    if config.tenant_cache_entries > tenant_cache::MAX_ENTRIES {
+        report.error(
+            "tenant_cache_entries",
+            format!(
+                "tenant_cache_entries {} must be atmost {}",
+                config.tenant_cache_entries,
+                tenant_cache::MAX_ENTRIES
+            ),
+        );
+    }
+}
+
+// A larger cap would be silently lowered; uncapped fills are configured with 0.
+fn check_fill(config: &ServerConfig, report: &mut Report) {
+    if config.fill_objects_per_sec > fill::MAX_RATE {
+        report.error(
+            "fill_objects_per_sec",
+            format!(
+                "fill_objects_per_sec {} must be atmost {}; 0 leaves fills uncapped",
+                config.fill_objects_per_sec,
+                fill::MAX_RATE
+            ),
+        );
+    }
+}
+
+fn check_image(config: &ServerConfig, report: &mut Report) {
+    if config.image_path.is_empty() {
+        return;
+    }
+
+    if let Err(e) = File::open(&config.image_path) {
+        report.error(
+            "image_path",
+            format!("image at {} cannot be read ({})", config.image_path, e),
+        );
+    }
+}
+
+// The first quarantine would already be cut down to the cap, so quarantines would never escalate.
+fn check_quarantine(config: &ServerConfig, report: &mut Report) {
+    let policy = config.quarantine();
+    if policy.quarantine_ms > policy.max_quarantine_ms {
+        report.error(
+            "quarantine_max_ms",
+            format!(
+                "quarantine_max_ms {} must be atleast quarantine_ms {}",
+                policy.max_quarantine_ms, policy.quarantine_ms
+            ),
+        );
+    }
+}
+
+// Volumes only decay on tables whose keys are tracked.
+fn check_hot_keys(config: &ServerConfig, report: &mut Report) {
+    if config.write_hot_keys_decay_ms > 0 && config.write_hot_keys == 0 {
+        report.warn(
+            "write_hot_keys_decay_ms",
+            format!(
+                "write_hot_keys_decay_ms is {}, but write_hot_keys is 0 so no keys are tracked",
+                config.write_hot_keys_decay_ms
+            ),
+        );
+    }
+}
+
+// A longer TTL would have clients serve objects that changed long after they did.
+fn check_hints(config: &ServerConfig, report: &mut Report) {
+    if config.hint_max_ttl_us > hint::MAX_TTL_US {
+        report.error(
+            "hint_max_ttl_us",
+            format!(
+                "hint_max_ttl_us {} is over the most allowed, {}",
+                config.hint_max_ttl_us,
+                hint::MAX_TTL_US
+            ),
+        );
+    }
+}
+
+// The arena is made of whole segments, so the rest of a size that isn't a multiple of one is never
+// used. The rest of the arena's settings do nothing without it.
+fn check_hot_arena(config: &ServerConfig, report: &mut Report) {
+    let bytes = config.hot_arena_bytes;
+    if bytes > hot::SEGMENT_SIZE && bytes % hot::SEGMENT_SIZE != 0 {
+        report.warn(
+            "hot_arena_bytes",
+            format!(
+                "hot_arena_bytes {} is not a multiple of {}, so only {} bytes are used",
+                bytes,
+                hot::SEGMENT_SIZE,
+                bytes - bytes % hot::SEGMENT_SIZE
+            ),
+        );
+    }
+
+    let tuned = config.hot_reads != 0 || config.hot_window_ms != 0 || config.hot_windows != 0;
+    if bytes == 0 && (tuned || config.hot_arena_huge_pages) {
+        report.warn(
+            "hot_arena_bytes",
+            "hot_arena_bytes is 0, so the rest of the hot arena's settings have no effect"
+                .to_string(),
+        );
+    }
+}
+
+// A lower load factor mostly reserves memory that is never used. The server's own table is filled
+// anyway if it's over the cap, but grows as it is.
+fn check_presize(config: &ServerConfig, report: &mut Report) {
+    let load = config.table_load_factor;
+    if load != 0.0 && (load < MIN_LOAD_FACTOR || load > 1.0) {
+        report.error(
+            "table_load_factor",
+            format!(
+                "table_load_factor {} must be between {} and 1",
+                load, MIN_LOAD_FACTOR
+            ),
+        );
+    }
+
+    let max = config.table_presize_max;
+    if max != 0 && config.num_records as u64 > max {
+        report.warn(
+            "table_presize_max",
+            format!(
+                "num_records {} is over table_presize_max {}, so the table will not be pre-sized",
+                config.num_records, max
+            ),
+        );
+    }
+}
+
+// A tenant's mailbox evicts results to stay under it's quota, so a quota that can't hold a single
+// result of the largest size stored loses every one of them.
+fn check_mailbox(config: &ServerConfig, report: &mut Report) {
+    let mailbox = config.mailbox();
+    if (mailbox.max_result() as u64) > mailbox.quota() {
+        report.error(
+            "mailbox_quota_bytes",
+            format!(
+                "mailbox_quota_bytes {} must be atleast mailbox_max_result {}",
+                mailbox.quota(),
+                mailbox.max_result()
+            ),
+        );
+    }
+}
+
+// Netbricks panics mid startup if it's asked to pin a thread to a core that doesn't exist.
+fn check_cores(required: &[i32], online: Option<&[i32]>, report: &mut Report) {
+    let online = match online {
+        Some(online) => online,
+        None => return,
+    };
+
+    let missing: Vec<i32> = required
+        .iter()
+        .filter(|core| !online.contains(core))
+        .cloned()
+        .collect();
+    if !missing.is_empty() {
+        report.error(
+            "cores",
+            format!(
+                "cores {:?} are needed but not online; online cores are {:?}",
+                missing, online
+            ),
+        );
+    }
+}
+
+// Extensions are loaded after the tables are populated, which can take minutes. A missing
+// extension is better found before that.
+fn check_extensions(extensions: &[(&str, &str)], report: &mut Report) {
+    for &(path, name) in extensions.iter() {
+        if let Err(e) = File::open(path) {
+            report.error(
+                "extensions",
+                format!(
+                    "{}() extension at {} cannot be read ({}); build it, and start from db/",
+                    name, path, e
+                ),
+            );
+        }
+    }
+}
+
+// Transports other than "dpdk" and "udp" panic when the client starts sending.
+fn check_transport(config: &ClientConfig, report: &mut Report) {
+    match config.transport.as_str() {
+        "" | "dpdk" | "udp" => {}
+        transport => report.error(
+            "transport",
+            format!("transport \"{}\" must be \"dpdk\" or \"udp\"", transport),
+        ),
+    }
+
+    if config.tag_run && config.transport == "udp" {
+        report.warn(
+            "tag_run",
+            "tag_run has no effect on the udp transport; requests go out untagged".to_string(),
+        );
+    }
+}
+
+// Keys that don't match the records on the server miss on every request, and values that
+// don't match overwrite them with records of a different length.
+fn check_layout(config: &ClientConfig, workload: &str, report: &mut Report) {
+    if config.key_len < 4 {
+        report.error(
+            "key_len",
+            format!(
+                "key_len {} must be atleast 4, since keys start with a 4 byte record id",
+                config.key_len
+            ),
+        );
+    }
+
+    if config.key_len > HARD_MAX_KEY_LEN {
+        report.error(
+            "key_len",
+            format!(
+                "key_len {} is longer than any key the server can store ({} bytes)",
+                config.key_len, HARD_MAX_KEY_LEN
+            ),
+        );
+    }
+
+    if config.value_len > HARD_MAX_VALUE_LEN {
+        report.error(
+            "value_len",
+            format!(
+                "value_len {} is larger than any value a get() response can carry ({} bytes)",
+                config.value_len, HARD_MAX_VALUE_LEN
+            ),
+        );
+    }
+
+    let layout = match record_layout(workload) {
+        Some(layout) => layout,
+        None => return,
+    };
+
+    if config.key_len != layout.key_len {
+        report.error(
+            "key_len",
+            format!(
+                "key_len {} does not match the {} byte keys the server populates under {}",
+                config.key_len, layout.key_len, workload
+            ),
+        );
+    }
+
+    match layout.value_len {
+        Some(value_len) if value_len != config.value_len => report.error(
+            "value_len",
+            format!(
+                "value_len {} does not match the {} byte values the server populates under {}",
+                config.value_len, value_len, workload
+            ),
+        ),
+
+        _ => {}
+    }
+}
+
+// The run length is num_reqs / req_rate, and some clients split num_reqs across pipelines.
+fn check_requests(config: &ClientConfig, pipelines: usize, report: &mut Report) {
+    if config.req_rate == 0 {
+        report.error("req_rate", "req_rate must be atleast 1".to_string());
+    }
+
+    if config.num_reqs < pipelines {
+        report.error(
+            "num_reqs",
+            format!(
+                "num_reqs {} leaves some of the {} pipelines with nothing to send",
+                config.num_reqs, pipelines
+            ),
+        );
+    } else if pipelines > 1 && config.num_reqs % pipelines != 0 {
+        report.warn(
+            "num_reqs",
+            format!(
+                "num_reqs {} is not a multiple of the {} pipelines; clients that split it will \
+                 send {} fewer requests",
+                config.num_reqs,
+                pipelines,
+                config.num_reqs % pipelines
+            ),
+        );
+    }
+}
+
+// Checks a key or tenant distribution; see splinter::dist::KeyDistribution::parse().
+fn check_dist(
+    name: &'static str,
+    dist: &str,
+    skew: f64,
+    hot: (f64, f64),
+    n: usize,
+    report: &mut Report,
+) {
+    if !DISTRIBUTIONS.iter().any(|d| *d == dist) {
+        report.error(
+            name,
+            format!(
+                "{} \"{}\" is unknown; expected one of {:?}",
+                name,
+                dist,
+                &DISTRIBUTIONS[1..]
+            ),
+        );
+        return;
+    }
+
+    if n == 0 {
+        report.error(name, format!("{} draws from an empty range", name));
+    }
+
+    // zipf::ZipfDistribution refuses exponents that are not strictly positive.
+    if (dist == "" || dist == "zipf") && !(skew > 0.0 && skew.is_finite()) {
+        report.error(
+            name,
+            format!("{} is zipf, which needs a skew above 0, not {}", name, skew),
+        );
+    }
+
+    if dist == "hotspot" && !(hot.0 > 0.0 && hot.0 <= 1.0 && hot.1 >= 0.0 && hot.1 <= 1.0) {
+        report.error(
+            name,
+            format!(
+                "{} is hotspot, which needs a hot fraction in (0, 1] and a hot ops fraction in \
+                 [0, 1], not {} and {}",
+                name, hot.0, hot.1
+            ),
+        );
+    }
+}
+
+// Keys are drawn from [1, n_keys] under `key_dist`.
+fn check_key_dist(config: &ClientConfig, report: &mut Report) {
+    check_dist(
+        "key_dist",
+        &config.key_dist,
+        config.skew,
+        (config.hot_fraction, config.hot_ops_fraction),
+        config.n_keys,
+        report,
+    );
+}
+
+// Tenants are drawn from [1, num_tenants] under `tenant_dist`.
+fn check_tenant_dist(config: &ClientConfig, report: &mut Report) {
+    check_dist(
+        "tenant_dist",
+        &config.tenant_dist,
+        config.tenant_skew,
+        (config.tenant_hot_fraction, config.tenant_hot_ops_fraction),
+        config.num_tenants as usize,
+        report,
+    );
+
+    let skewed = match config.tenant_dist.as_str() {
+        "" | "zipf" => config.tenant_skew != 0.0,
+        "hotspot" => true,
+        _ => false,
+    };
+    if config.num_tenants == 1 && skewed {
+        report.warn(
+            "tenant_skew",
+            "tenant skew has no effect with a single tenant".to_string(),
+        );
+    }
+}
+
+// Hot set rotation needs a bounded number of ranks and an epoch length.
+fn check_rotation(config: &ClientConfig, report: &mut Report) {
+    let secs = config.hot_rotate_secs;
+    if !(secs >= 0.0 && secs.is_finite()) {
+        report.error(
+            "hot_rotate_secs",
+            format!("hot_rotate_secs {} is not a duration", secs),
+        );
+    }
+
+    if config.hot_rotate_ranks == 0 {
+        return;
+    }
+
+    if config.hot_rotate_ranks > ROTATE_RANKS_MAX {
+        report.error(
+            "hot_rotate_ranks",
+            format!(
+                "hot_rotate_ranks {} is above {}; the rank-to-key table would not stay cache \
+                 resident",
+                config.hot_rotate_ranks, ROTATE_RANKS_MAX
+            ),
+        );
+    }
+
+    if secs == 0.0 && config.hot_rotate_ops == 0 {
+        report.warn(
+            "hot_rotate",
+            "hot_rotate_ranks has no effect unless hot_rotate_secs or hot_rotate_ops is set"
+                .to_string(),
+        );
+    } else if secs > 0.0 && config.hot_rotate_ops != 0 {
+        report.warn(
+            "hot_rotate",
+            "both hot_rotate_secs and hot_rotate_ops are set; epochs are timed and \
+             hot_rotate_ops is ignored"
+                .to_string(),
+        );
+    }
+}
+
+// Percentages above 100 make the request mix meaningless.
+fn check_percentages(config: &ClientConfig, report: &mut Report) {
+    let percentages = [
+        ("put_pct", config.put_pct),
+        ("assocs_p", config.assocs_p),
+        ("long_pct", config.long_pct),
+    ];
+    for &(name, pct) in percentages.iter() {
+        if pct > 100 {
+            report.error(name, format!("{} {} is above 100", name, pct));
+        }
+    }
+}
+
+// The TAO client panics on a mix, association types or range limits it can't parse, and a
+// write scale that pushes writes past the whole mix leaves no reads.
+fn check_tao_mix(config: &ClientConfig, report: &mut Report) {
+    let mix = match config.tao_mix.as_str() {
+        "" => Some(tao::published_mix()),
+        spec => tao::parse_mix(spec),
+    };
+    if mix.is_none() {
+        report.error(
+            "tao_mix",
+            format!("tao_mix \"{}\" is malformed", config.tao_mix),
+        );
+    }
+
+    let scale = config.tao_write_scale;
+    if !(scale >= 0.0 && scale.is_finite()) {
+        report.error(
+            "tao_write_scale",
+            format!("tao_write_scale {} is not a factor", scale),
+        );
+    } else if let Some(mix) = mix {
+        let total: f64 = mix.iter().sum();
+        let writes: f64 = tao::OPS
+            .iter()
+            .filter(|op| op.is_write())
+            .map(|op| mix[*op as usize])
+            .sum();
+        let scale = if scale == 0.0 { 1.0 } else { scale };
+        if writes * scale > total && writes < total {
+            report.warn(
+                "tao_write_scale",
+                format!(
+                    "tao_write_scale {} pushes writes past the whole mix; no reads are issued",
+                    scale
+                ),
+            );
+        }
+    }
+
+    if parse_atypes(&config.tao_atypes).is_none() {
+        report.error(
+            "tao_atypes",
+            format!("tao_atypes \"{}\" is malformed", config.tao_atypes),
+        );
+    }
+
+    if config.tao_range_limits != "" && tao::parse_buckets(&config.tao_range_limits).is_none() {
+        report.error(
+            "tao_range_limits",
+            format!(
+                "tao_range_limits \"{}\" is malformed",
+                config.tao_range_limits
+            ),
+        );
+    }
+}
+
+// Congestion aware pacing needs it's bounds the right way around.
+fn check_pacing(config: &ClientConfig, report: &mut Report) {
+    if config.window_min != 0 && config.window_max != 0 && config.window_min > config.window_max {
+        report.error(
+            "window",
+            format!(
+                "window_min {} is above window_max {}",
+                config.window_min, config.window_max
+            ),
+        );
+    }
+
+    if config.load_low != 0 && config.load_high != 0 && config.load_low >= config.load_high {
+        report.error(
+            "load",
+            format!(
+                "load_low {} must be below load_high {}",
+                config.load_low, config.load_high
+            ),
+        );
+    }
+
+    if config.read_your_writes && !config.key_order {
+        report.warn(
+            "read_your_writes",
+            "read_your_writes has no effect unless key_order is also set".to_string(),
+        );
+    }
+}
+
+// Fault injection happens with a probability.
+fn check_faults(config: &ClientConfig, report: &mut Report) {
+    let p = config.fault_probability;
+    if !(p >= 0.0 && p <= 1.0) {
+        report.error(
+            "fault_probability",
+            format!("fault_probability {} is not in [0, 1]", p),
+        );
+    }
+}
+
+// A backup that doesn't parse panics the client once it starts, and one that is the primary
+// itself fails over to the server that just stopped responding.
+fn check_failover(config: &ClientConfig, report: &mut Report) {
+    let backup = config.backup_server_ip_address.as_str();
+    if backup == "" {
+        return;
+    }
+
+    if Ipv4Addr::from_str(backup).is_err() {
+        report.error(
+            "backup_server",
+            format!(
+                "backup_server_ip_address \"{}\" is not an IPv4 address",
+                backup
+            ),
+        );
+        return;
+    }
+
+    let port = match config.backup_udp_server_port {
+        0 => config.udp_server_port,
+        port => port,
+    };
+    if backup == config.server_ip_address && port == config.udp_server_port {
+        report.warn(
+            "backup_server",
+            format!(
+                "backup_server_ip_address {} and it's port are the same as the primary's",
+                backup
+            ),
+        );
+    }
+}
+
+// Puts under AUTH overwrite the credentials the server populated, so later logins fail.
+fn check_auth_puts(config: &ClientConfig, workload: &str, report: &mut Report) {
+    if workload == "AUTH" && config.put_pct > 0 {
+        report.warn(
+            "auth_puts",
+            format!(
+                "put_pct {} under AUTH overwrites the populated credentials",
+                config.put_pct
+            ),
+        );
+    }
+}
+
+// Only native YCSB runs look up the cache, so it would be allocated and never hit.
+fn check_hint_cache(config: &ClientConfig, workload: &str, report: &mut Report) {
+    if config.hint_cache > 0 && (workload != "YCSB" || config.use_invoke) {
+        report.warn(
+            "hint_cache",
+            format!(
+                "hint_cache {} has no effect unless the run is native YCSB",
+                config.hint_cache
+            ),
+        );
+    }
+}
+
+// Policies other than "fifo" and "srf" panic the pushback client when it starts up. Other clients
+// run pushed back invocations in the order they were pushed back, whatever the policy.
+fn check_pushback_queue(config: &ClientConfig, workload: &str, report: &mut Report) {
+    match config.pushback_queue.as_str() {
+        "" | "fifo" => {}
+        "srf" if workload != "PUSHBACK" => report.warn(
+            "pushback_queue",
+            format!(
+                "pushback_queue \"srf\" has no effect on the {} workload",
+                workload
+            ),
+        ),
+        "srf" => {}
+        policy => report.error(
+            "pushback_queue",
+            format!("pushback_queue \"{}\" must be \"fifo\" or \"srf\"", policy),
+        ),
+    }
+}
+
+// An out of range AUTH layout panics the server when it fills the workload, and the client when
+// it starts up. The same rule applies to both configs, which have to agree on the layout.
+fn check_auth_layout(cost: u8, hash_len: usize, salt_len: usize, report: &mut Report) {
+    if auth_layout(cost, hash_len, salt_len).is_none() {
+        report.error(
+            "auth_layout",
+            format!(
+                "auth_cost {}, auth_hash_len {} and auth_salt_len {} must be atmost {}, {} and {}",
+                cost,
+                hash_len,
+                salt_len,
+                auth::MAX_COST,
+                auth::MAX_HASH_LEN,
+                auth::MAX_SALT_LEN
+            ),
+        );
+    }
+}
+
+// This module contains unit tests for the validation rules.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Returns a server config that passes every rule that doesn't depend on the machine.
+    fn server() -> ServerConfig {
+        ::toml::from_str(
+            r#"
+            mac_address = "3c:fd:fe:04:9f:42"
+            ip_address = "192.168.0.2"
+            udp_port = 0
+            nic_pci = "0000:04:00.1"
+            client_mac = "3c:fd:fe:04:9f:43"
+            client_ip = "192.168.0.1"
+            num_tenants = 8
+            install_addr = "127.0.0.1:7700"
+            workload = "YCSB"
+            num_records = 1000
+            "#,
+        )
+        .unwrap()
+    }
+
+    // Returns a client config that passes every rule that doesn't depend on the machine.
+    fn client() -> ClientConfig {
+        let mut config = ClientConfig::default();
+        config.key_len = 30;
+        config.value_len = 100;
+        config.n_keys = 1000;
+        config.num_tenants = 8;
+        config.skew = 0.99;
+        config.tenant_skew = 0.1;
+        config.num_reqs = 4000;
+        config.req_rate = 1000;
+        config.put_pct = 5;
+        config
+    }
+
+    // Runs every client rule that doesn't depend on the machine.
+    fn check_client(config: &ClientConfig, workload: &str) -> Report {
+        let mut report = Report::new();
+        check_mac("mac_address", "3c:fd:fe:04:9f:42", &mut report);
+        check_transport(config, &mut report);
+        check_layout(config, workload, &mut report);
+        check_requests(config, 4, &mut report);
+        check_key_dist(config, &mut report);
+        check_tenant_dist(config, &mut report);
+        check_rotation(config, &mut report);
+        check_percentages(config, &mut report);
+        check_tao_mix(config, &mut report);
+        check_pacing(config, &mut report);
+        check_faults(config, &mut report);
+        check_failover(config, &mut report);
+        check_auth_puts(config, workload, &mut report);
+        check_hint_cache(config, workload, &mut report);
+        check_pushback_queue(config, workload, &mut report);
+        check_auth_layout(
+            config.auth_cost,
+            config.auth_hash_len,
+            config.auth_salt_len,
+            &mut report,
+        );
+        report
+    }
+
+    // Runs every server rule that doesn't depend on the machine.
+    fn check_server(config: &ServerConfig) -> Report {
+        let mut report = Report::new();
+        check_workload(config, &mut report);
+        check_records(config, &mut report);
+        check_ml_model(&config.workload, true, &mut report);
+        check_server_specs(config, &mut report);
+        check_compression(config, &mut report);
+        check_pushback(config, &mut report);
+        check_crash(config, &mut report);
+        check_memcache(config, &mut report);
+        check_audit(config, &mut report);
+        check_limits(config, &mut report);
+        check_fairness(config, &mut report);
+        check_backoff(config, &mut report);
+        check_integrity(config, &mut report);
+        check_dedup(config, &mut report);
+        check_tenant_cache(config, &mut report);
+        check_fill(config, &mut report);
+        check_image(config, &mut report);
+        check_quarantine(config, &mut report);
+        check_hot_keys(config, &mut report);
+        check_hints(config, &mut report);
+        check_hot_arena(config, &mut report);
+        check_presize(config, &mut report);
+        check_mailbox(config, &mut report);
+        check_auth_layout(
+            config.auth_cost,
+            config.auth_hash_len,
+            config.auth_salt_len,
+            &mut report,
+        );
+        report
+    }
+
+    // Tests that valid configs pass untouched.
+    #[test]
+    fn test_valid() {
+        let report = check_client(&client(), "YCSB");
+        assert!(report.is_ok());
+        assert!(report.warnings.is_empty());
+
+        let report = check_server(&server());
+        assert!(report.is_ok());
+        assert!(report.warnings.is_empty());
+
+        let mut report = Report::new();
+        check_mac("mac_address", server().mac_address(), &mut report);
+        check_mac("client_mac", server().client_mac(), &mut report);
+        assert!(report.is_ok());
+    }
+
+    // Tests that malformed MAC addresses are errors.
+    #[test]
+    fn test_mac() {
+        for mac in [
+            "",
+            "3c:fd:fe:04:9f",
+            "3c:fd:fe:04:9f:4g",
+            "3c-fd-fe-04-9f-42",
+        ]
+        .iter()
+        {
+            let mut report = Report::new();
+            check_mac("mac_address", mac, &mut report);
+            assert_eq!(1, report.errors.len());
+            assert!(report.fired("mac"));
+            assert!(report.errors[0].message.contains("mac_address"));
+        }
+    }
+
+    // Tests that a matrix of broken client configs fires the rules it should, and only those.
+    #[test]
+    fn test_broken_clients() {
+        let cases: &[(&str, fn(&mut ClientConfig), &str)] = &[
+            ("key_len", |c| c.key_len = 24, "YCSB"),
+            ("key_len", |c| c.key_len = 2, "TAO"),
+            ("value_len", |c| c.value_len = 64, "YCSB"),
+            (
+                "value_len",
+                |c| c.value_len = HARD_MAX_VALUE_LEN + 1,
+                "AUTH",
+            ),
+            ("req_rate", |c| c.req_rate = 0, "YCSB"),
+            ("num_reqs", |c| c.num_reqs = 3, "YCSB"),
+            ("key_dist", |c| c.key_dist = "pareto".to_string(), "YCSB"),
+            ("key_dist", |c| c.skew = 0.0, "YCSB"),
+            ("key_dist", |c| c.n_keys = 0, "YCSB"),
+            (
+                "key_dist",
+                |c| {
+                    c.key_dist = "hotspot".to_string();
+                    c.hot_fraction = 1.5;
+                },
+                "YCSB",
+            ),
+            ("tenant_dist", |c| c.tenant_skew = -1.0, "YCSB"),
+            ("tenant_dist", |c| c.num_tenants = 0, "YCSB"),
+            ("transport", |c| c.transport = "tcp".to_string(), "YCSB"),
+            (
+                "pushback_queue",
+                |c| c.pushback_queue = "lifo".to_string(),
+                "PUSHBACK",
+            ),
+            ("put_pct", |c| c.put_pct = 101, "YCSB"),
+            ("assocs_p", |c| c.assocs_p = 200, "TAO"),
+            ("tao_mix", |c| c.tao_mix = "obj_get:x".to_string(), "TAO"),
+            ("tao_write_scale", |c| c.tao_write_scale = -1.0, "TAO"),
+            (
+                "tao_atypes",
+                |c| c.tao_atypes = "1,70000".to_string(),
+                "TAO",
+            ),
+            (
+                "tao_range_limits",
+                |c| c.tao_range_limits = "10".to_string(),
+                "TAO",
+            ),
+            ("long_pct", |c| c.long_pct = 101, "YCSB"),
+            (
+                "window",
+                |c| {
+                    c.window_min = 64;
+                    c.window_max = 8;
+                },
+                "YCSB",
+            ),
+            (
+                "load",
+                |c| {
+                    c.load_low = 16;
+                    c.load_high = 16;
+                },
+                "YCSB",
+            ),
+            ("fault_probability", |c| c.fault_probability = 1.5, "YCSB"),
+            (
+                "backup_server",
+                |c| c.backup_server_ip_address = "10.0.0".to_string(),
+                "YCSB",
+            ),
+            ("hot_rotate_secs", |c| c.hot_rotate_secs = -1.0, "YCSB"),
+            ("auth_layout", |c| c.auth_salt_len = 17, "AUTH"),
+            (
+                "hot_rotate_ranks",
+                |c| {
+                    c.hot_rotate_ranks = 1 << 20;
+                    c.hot_rotate_ops = 1000;
+                },
+                "YCSB",
+            ),
+        ];
+
+        for &(rule, breaks, workload) in cases.iter() {
+            let mut config = client();
+            breaks(&mut config);
+            let report = check_client(&config, workload);
+            assert!(!report.is_ok(), "{} did not fire", rule);
+            assert!(
+                report.errors.iter().all(|p| p.rule == rule),
+                "{} fired along with {}",
+                rule,
+                report
+            );
+        }
+    }
+
+    // Tests that every problem is reported, not just the first.
+    #[test]
+    fn test_consolidated() {
+        let mut config = client();
+        config.key_len = 8;
+        config.req_rate = 0;
+        config.put_pct = 150;
+        config.transport = "tcp".to_string();
+
+        let report = check_client(&config, "YCSB");
+        assert_eq!(4, report.errors.len());
+        for rule in ["key_len", "req_rate", "put_pct", "transport"].iter() {
+            assert!(report.fired(rule));
+        }
+
+        let listing = report.to_string();
+        assert_eq!(4, listing.lines().count());
+        assert!(listing.contains("[key_len]"));
+    }
+
+    // Tests that suspicious but legal client configs only warn.
+    #[test]
+    fn test_warnings() {
+        let mut config = client();
+        let report = check_client(&config, "AUTH");
+        assert!(report.is_ok());
+        assert!(report.fired("auth_puts"));
+
+        config.put_pct = 0;
+        assert!(!check_client(&config, "AUTH").fired("auth_puts"));
+
+        let mut config = client();
+        config.num_tenants = 1;
+        let report = check_client(&config, "YCSB");
+        assert!(report.is_ok());
+        assert!(report.fired("tenant_skew"));
+
+        config.tenant_dist = "uniform".to_string();
+        assert!(!check_client(&config, "YCSB").fired("tenant_skew"));
+
+        let mut config = client();
+        config.num_reqs = 4001;
+        let report = check_client(&config, "YCSB");
+        assert!(report.is_ok());
+        assert!(report.fired("num_reqs"));
+
+        let mut config = client();
+        config.read_your_writes = true;
+        let report = check_client(&config, "YCSB");
+        assert!(report.is_ok());
+        assert!(report.fired("read_your_writes"));
+
+        let mut config = client();
+        config.hot_rotate_ranks = 128;
+        let report = check_client(&config, "YCSB");
+        assert!(report.is_ok());
+        assert!(report.fired("hot_rotate"));
+
+        config.hot_rotate_ops = 100000;
+        assert!(!check_client(&config, "YCSB").fired("hot_rotate"));
+
+        config.hot_rotate_secs = 10.0;
+        assert!(check_client(&config, "YCSB").fired("hot_rotate"));
+
+        let mut config = client();
+        config.server_ip_address = "10.0.0.2".to_string();
+        config.backup_server_ip_address = "10.0.0.2".to_string();
+        let report = check_client(&config, "YCSB");
+        assert!(report.is_ok());
+        assert!(report.fired("backup_server"));
+
+        config.backup_udp_server_port = 9100;
+        assert!(!check_client(&config, "YCSB").fired("backup_server"));
+
+        let mut config = client();
+        config.tao_write_scale = 100.0;
+        assert!(!check_client(&config, "TAO").fired("tao_write_scale"));
+
+        config.tao_write_scale = 1000000.0;
+        let report = check_client(&config, "TAO");
+        assert!(report.is_ok());
+        assert!(report.fired("tao_write_scale"));
+
+        let mut config = client();
+        config.hint_cache = 1024;
+        assert!(!check_client(&config, "YCSB").fired("hint_cache"));
+
+        let report = check_client(&config, "TAO");
+        assert!(report.is_ok());
+        assert!(report.fired("hint_cache"));
+
+        config.use_invoke = true;
+        assert!(check_client(&config, "YCSB").fired("hint_cache"));
+
+        let mut config = client();
+        config.pushback_queue = "srf".to_string();
+        assert!(!check_client(&config, "PUSHBACK").fired("pushback_queue"));
+
+        let report = check_client(&config, "YCSB");
+        assert!(report.is_ok());
+        assert!(report.fired("pushback_queue"));
+    }
+
+    // Tests that only workloads with a record layout constrain the lengths of values.
+    #[test]
+    fn test_layout() {
+        let mut config = client();
+        config.value_len = 40;
+        assert!(check_client(&config, "AUTH").is_ok());
+        assert!(check_client(&config, "TAO").is_ok());
+        assert!(check_client(&config, "").is_ok());
+        assert!(check_client(&config, "PUSHBACK").fired("value_len"));
+
+        config.key_len = 16;
+        assert!(check_client(&config, "TAO").is_ok());
+        assert!(check_client(&config, "AUTH").fired("key_len"));
+    }
+
+    // Tests that a matrix of broken server configs fires the rules it should, and only those.
+    #[test]
+    fn test_broken_servers() {
+        let cases: &[(&str, fn(&mut ServerConfig))] = &[
+            ("num_tenants", |c| c.num_tenants = 0),
+            ("num_records", |c| c.num_records = 0),
+            ("tao_fanout", |c| c.tao_fanout = "lognormal".to_string()),
+            ("shared_tables", |c| c.shared_tables = "1:1".to_string()),
+            ("cache_tables", |c| {
+                c.cache_tables = "1:1:0:0:read".to_string()
+            }),
+            ("compress_min_ratio", |c| c.compress_min_ratio = 0.5),
+            ("pushback_depth_product", |c| {
+                c.pushback_depth_product = -1.0
+            }),
+            ("pushback_delay_product", |c| {
+                c.pushback_delay_product = ::std::f64::NAN
+            }),
+            ("crash_policy", |c| c.crash_policy = "restart".to_string()),
+            ("memcache_tenant", |c| {
+                c.memcache_port = 11211;
+                c.memcache_tenant = c.num_tenants + 1
+            }),
+            ("audit_tenants", |c| c.audit_tenants = "1,x".to_string()),
+            ("audit_tenants", |c| {
+                c.audit_tenants = format!("1,{}", c.num_tenants + 1)
+            }),
+            ("max_key_len", |c| c.max_key_len = HARD_MAX_KEY_LEN + 1),
+            ("max_value_len", |c| {
+                c.max_value_len = HARD_MAX_VALUE_LEN + 1
+            }),
+            ("fair_quantum_us", |c| c.fair_quantum_us = -1.0),
+            ("poll_backoff_max_us", |c| {
+                c.poll_backoff_max_us = MAX_PAUSE_US + 1
+            }),
+            ("tenant_shares", |c| c.tenant_shares = "1:0".to_string()),
+            ("tenant_shares", |c| {
+                c.tenant_shares = format!("{}:2", c.num_tenants + 1)
+            }),
+            ("image_path", |c| c.image_path = "/nonexistent".to_string()),
+            ("checksum_tables", |c| c.checksum_tables = "1,x".to_string()),
+            ("dedup_tables", |c| c.dedup_tables = "1;2".to_string()),
+            ("dedup_entries", |c| c.dedup_entries = MAX_DEDUP_ENTRIES + 1),
+            ("auth_layout", |c| c.auth_cost = auth::MAX_COST + 1),
+            ("tenant_cache_entries", |c| {
+                c.tenant_cache_entries = tenant_cache::MAX_ENTRIES + 1
+            }),
+            ("fill_objects_per_sec", |c| {
+                c.fill_objects_per_sec = fill::MAX_RATE + 1
+            }),
+            ("replay_opcodes", |c| c.replay_opcodes = "incr".to_string()),
+            ("enabled_opcodes", |c| {
+                c.enabled_opcodes = "get,range".to_string()
+            }),
+            ("quarantine_max_ms", |c| c.quarantine_ms = 120_000),
+            ("hint_max_ttl_us", |c| {
+                c.hint_max_ttl_us = hint::MAX_TTL_US + 1
+            }),
+            ("table_load_factor", |c| c.table_load_factor = 0.05),
+            ("table_load_factor", |c| c.table_load_factor = 1.5),
+            ("mailbox_quota_bytes", |c| {
+                c.mailbox_max_result = 4096;
+                c.mailbox_quota_bytes = 1024
+            }),
+        ];
+
+        for &(rule, breaks) in cases.iter() {
+            let mut config = server();
+            breaks(&mut config);
+            let report = check_server(&config);
+            assert!(!report.is_ok(), "{} did not fire", rule);
+            assert!(report.errors.iter().all(|p| p.rule == rule));
+        }
+
+        let mut config = server();
+        config.workload = "YSCB".to_string();
+        config.num_records = 0;
+        let report = check_server(&config);
+        assert!(report.is_ok());
+        assert!(report.fired("workload"));
+
+        let mut config = server();
+        config.table_presize_max = 999;
+        let report = check_server(&config);
+        assert!(report.is_ok());
+        assert!(report.fired("table_presize_max"));
+
+        config.table_presize_max = 1000;
+        assert!(!check_server(&config).fired("table_presize_max"));
+
+        let mut config = server();
+        config.write_hot_keys_decay_ms = 1000;
+        let report = check_server(&config);
+        assert!(report.is_ok());
+        assert!(report.fired("write_hot_keys_decay_ms"));
+
+        config.write_hot_keys = 16;
+        assert!(!check_server(&config).fired("write_hot_keys_decay_ms"));
+
+        let mut config = server();
+        config.hot_windows = 2;
+        let report = check_server(&config);
+        assert!(report.is_ok());
+        assert!(report.fired("hot_arena_bytes"));
+
+        config.hot_arena_bytes = 3 << 20;
+        let report = check_server(&config);
+        assert!(report.is_ok());
+        assert!(report.fired("hot_arena_bytes"));
+
+        config.hot_arena_bytes = 4 << 20;
+        assert!(!check_server(&config).fired("hot_arena_bytes"));
+
+        let mut report = Report::new();
+        check_ml_model("MIX", false, &mut report);
+        check_ml_model("YCSB", false, &mut report);
+        assert_eq!(1, report.errors.len());
+        assert!(report.fired("ml_model"));
+    }
+
+    // Tests that cores that aren't online are errors, and that cores are not checked if the
+    // online cores are unknown.
+    #[test]
+    fn test_cores() {
+        let online: Vec<i32> = (0..12).collect();
+
+        let mut report = Report::new();
+        check_cores(&[0, 1, 9], Some(&online[..]), &mut report);
+        check_cores(&[10, 11], None, &mut report);
+        assert!(report.is_ok());
+
+        check_cores(&[10, 11, 12, 19], Some(&online[..]), &mut report);
+        assert_eq!(1, report.errors.len());
+        assert!(report.errors[0].message.contains("[12, 19]"));
+    }
+
+    // Tests that extensions that cannot be read are errors.
+    #[test]
+    fn test_extensions() {
+        let mut report = Report::new();
+        check_extensions(
+            &[("/dev/null", "null"), ("/nonexistent/libget.so", "get")],
+            &mut report,
+        );
+        assert_eq!(1, report.errors.len());
+        assert!(report.errors[0].message.contains("get()"));
+    }
+}
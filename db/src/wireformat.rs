@@ -16,7 +16,37 @@
 use super::bytes::Bytes;
 use super::table::Version;
 use e2d2::headers::{EndOffset, UdpHeader};
-use std::mem::size_of;
+use std::mem::{size_of, transmute};
+
+// Every header below is laid out exactly as it is declared, with no padding between fields
+// (#[repr(C, packed)]), and every multi-byte field on it is little-endian on the wire whatever the
+// byte order of the host. Multi-byte fields must be read and written through the accessors
+// generated by le_fields!(), which convert to and from the host's byte order. Clients and servers
+// built off different layouts can't talk to each other, so the size of every header is pinned by
+// assert_header_sizes(), and it's layout by a golden test at the end of this file; a change that
+// fails either breaks compatibility with every client and server built before it.
+
+// Generates accessors for the multi-byte fields on a header. Each field gets a getter of the same
+// name, and a setter.
+macro_rules! le_fields {
+    ($header:ident, $($field:ident, $set:ident: $ty:ty;)*) => {
+        impl $header {
+            $(
+                /// Returns the field of the same name, in the host's byte order.
+                #[inline]
+                pub fn $field(&self) -> $ty {
+                    <$ty>::from_le(self.$field)
+                }
+
+                /// Sets the field of the same name, storing it little-endian.
+                #[inline]
+                pub fn $set(&mut self, value: $ty) {
+                    self.$field = value.to_le();
+                }
+            )*
+        }
+    };
+}
 
 /// This enum represents the different sets of services that a Sandstorm server
 /// can provide, and helps identify the service an incoming remote procedure
@@ -49,7 +79,7 @@ pub enum Service {
 /// The second field on the header of every rpc request identifies the
 /// operation it should perform within the Sandstorm server.
 #[repr(u8)]
-#[derive(PartialEq)]
+#[derive(PartialEq, Clone, Debug)]
 pub enum OpCode {
     /// A simple operation that looks up the hash table for a given key.
     SandstormGetRpc = 0x01,
@@ -66,15 +96,121 @@ pub enum OpCode {
     /// This operation fetches multiple records in a single round trip.
     SandstormMultiGetRpc = 0x05,
 
+    /// This operation returns the server's clock rate. Sent once by clients that convert time
+    /// stamps on responses from the server's clock domain.
+    SandstormEchoRpc = 0x06,
+
+    /// This operation lists the extensions the requesting tenant can invoke.
+    SandstormListExtRpc = 0x07,
+
+    /// This operation puts the server into drain mode, and reports how many tasks are yet to
+    /// complete on each core. Only accepted from tenant 0.
+    SandstormDrainRpc = 0x08,
+
+    /// This operation sets whether one of the requesting tenant's tables can be read and written
+    /// by native get(), multiget(), put(), delete() and cas() RPCs. Extensions can always access
+    /// the table.
+    SandstormTableAccessRpc = 0x09,
+
+    /// This operation reports the counters the server keeps for client runs, either for a
+    /// single run or for the most recently active ones. Only accepted from tenant 0.
+    SandstormRunStatsRpc = 0x0a,
+
+    /// This operation merges a delta into the value stored under a key, using the merge
+    /// extension registered for the key's table.
+    SandstormMergeRpc = 0x0b,
+
+    /// This operation registers (or clears) the merge extension of one of the requesting
+    /// tenant's tables.
+    SandstormSetMergeRpc = 0x0c,
+
+    /// This operation invokes the extension picked by the requesting tenant's routing rules off
+    /// the prefix of the key on the request. Refer to SandstormSetRouteRpc.
+    SandstormRoutedInvokeRpc = 0x0d,
+
+    /// This operation installs (or removes) one of the requesting tenant's routing rules, which
+    /// map key prefixes to extensions.
+    SandstormSetRouteRpc = 0x0e,
+
+    /// This operation returns a page of the requesting tenant's audit log, newest invocation
+    /// first. Refer to audit::AuditLog.
+    SandstormAuditRpc = 0x0f,
+
+    /// This operation returns a page of the records in one of the requesting tenant's tables,
+    /// in the order they are dumped in. Refer to Master::dump_table().
+    SandstormDumpRpc = 0x10,
+
+    /// This operation writes a batch of records to one of the requesting tenant's tables, as if
+    /// each were written by a put() request.
+    SandstormMultiPutRpc = 0x11,
+
+    /// This operation returns the accounting of the writes made to one of the requesting
+    /// tenant's tables. Refer to amplify::WriteStats.
+    SandstormWriteStatsRpc = 0x12,
+
+    /// This operation returns the time each core of the server spent backing off polling, and
+    /// the CPU time the server has used. Refer to backoff::Backoff.
+    SandstormCoreStatsRpc = 0x13,
+
+    /// This operation returns the time the server took to respond to each opcode and extension,
+    /// merged across it's cores. Refer to latency::Latencies.
+    SandstormLatencyStatsRpc = 0x14,
+
+    /// This operation quarantines a tenant, releases it, or reports it's quarantine, along with
+    /// the quarantines counted across the server. Only accepted from tenant 0. Refer to
+    /// quarantine::Quarantines.
+    SandstormQuarantineRpc = 0x15,
+
+    /// This operation creates one of the requesting tenant's tables, pre-sized for the number
+    /// of objects it is expected to hold. Refer to presize::Presize.
+    SandstormCreateTableRpc = 0x16,
+
+    /// This operation returns the result an invocation asked to store for the requesting tenant,
+    /// and removes it. Refer to mailbox::Mailbox.
+    SandstormFetchResultRpc = 0x17,
+
+    /// A simple operation that removes a key-value pair from the database.
+    SandstormDeleteRpc = 0x18,
+
+    /// This operation reads the records in one of the requesting tenant's tables in key order,
+    /// starting from a key, as many as fit on a single response. The response carries a
+    /// continuation token to resume the scan from.
+    SandstormScanRpc = 0x19,
+
+    /// A conditional put() that only writes a key-value pair if the value currently under the
+    /// key is the one on the request, or if the key is absent when the request expects so.
+    SandstormCasRpc = 0x1a,
+
+    /// This operation drops one of the requesting tenant's tables, along with every object on
+    /// it. Refer to Tenant::drop_table().
+    SandstormDropTableRpc = 0x1b,
+
     /// Any value beyond this represents an invalid rpc.
-    InvalidOperation = 0x06,
+    InvalidOperation = 0x1c,
+}
+
+// Implementation of methods on OpCode.
+impl OpCode {
+    /// Returns the bit that stands for this opcode in a mask of opcodes, such as the set of
+    /// opcodes a server is configured to serve. Bit `i` stands for the opcode with value `i`.
+    pub fn bit(&self) -> u64 {
+        1u64 << (self.clone() as u8)
+    }
 }
 
+/// A mask of opcodes with every opcode in it.
+pub const OPCODES_ALL: u64 = !0;
+
+/// The opcodes a server always serves, whatever it is configured with: echo(), which advertises
+/// the opcodes that are served, and drain(), which shuts the server down.
+pub const OPCODES_REQUIRED: u64 =
+    (1 << OpCode::SandstormEchoRpc as u8) | (1 << OpCode::SandstormDrainRpc as u8);
+
 /// This enum represents the status of a completed RPC. A status of 'StatusOk'
 /// means that the RPC completed successfully, and that the payload on the
 /// response can be safely read and interpreted.
 #[repr(u8)]
-#[derive(PartialEq, Clone)]
+#[derive(PartialEq, Clone, Debug)]
 pub enum RpcStatus {
     /// The RPC completed successfully. The response can be safely unpacked
     /// at the client.
@@ -111,6 +247,87 @@ pub enum RpcStatus {
     /// The RPC was spending too much time on CPU, so the server pushed-back
     /// the extension without completing it.
     StatusPushback = 0x09,
+
+    /// The RPC was not run because the server is draining and about to shut down. Unlike
+    /// other failures, retrying is pointless; clients should stop sending requests.
+    StatusServerDraining = 0x0a,
+
+    /// The RPC failed at the server because it tried to write to a table that the tenant can
+    /// only read, such as a table shared with it by another tenant.
+    StatusReadOnlyTable = 0x0b,
+
+    /// The RPC failed at the server because the table it accessed only permits access from
+    /// inside extensions, and cannot be read or written by native RPCs.
+    StatusPermissionDenied = 0x0c,
+
+    /// The RPC failed at the server because the server is configured not to serve it's opcode,
+    /// or the part of it that was asked for (ex: the hot keys on a write_stats()).
+    StatusOperationDisabled = 0x0d,
+
+    /// The RPC failed at the server because an extension it ran on the tenant's behalf (ex: a
+    /// merge extension) failed. The response payload holds whatever the extension wrote out.
+    StatusExtensionError = 0x0e,
+
+    /// The RPC failed at the server because none of the tenant's routing rules matched the key
+    /// on a routed invoke().
+    StatusNoRouteMatched = 0x0f,
+
+    /// The RPC failed at the server because the tenant already has as many routing rules as it
+    /// is allowed.
+    StatusTooManyRoutes = 0x10,
+
+    /// The RPC failed at the server because the extension it invoked panicked earlier, and was
+    /// disabled so that the server could keep serving everything else.
+    StatusExtensionDisabled = 0x11,
+
+    /// The RPC failed at the server because it asked for the tenant's audit log, and the tenant
+    /// isn't being audited.
+    StatusAuditDisabled = 0x12,
+
+    /// The RPC failed at the server because it tried to write an object whose key is longer
+    /// than the server allows. Refer to limits::Limits.
+    StatusKeyTooLarge = 0x13,
+
+    /// The RPC failed at the server because it tried to write an object whose value is larger
+    /// than the server allows. Refer to limits::Limits.
+    StatusValueTooLarge = 0x14,
+
+    /// The RPC failed at the server because the extension it invoked streamed back more bytes
+    /// than the server allows. Refer to stream::Stream.
+    StatusStreamTooLarge = 0x15,
+
+    /// The RPC failed at the server because it's arguments did not fit the layout the invoked
+    /// extension declared. The extension was not run. Refer to sandstorm::schema.
+    StatusArgsMismatch = 0x16,
+
+    /// The RPC failed at the server because the object it read did not match it's checksum,
+    /// either when the request asked for a verified read, or because a background sweep had
+    /// already quarantined it. Refer to integrity::Sweep.
+    StatusDataCorrupted = 0x17,
+
+    /// The RPC was not run because the tenant that sent it is quarantined, either because too
+    /// many of it's recent requests failed, or by an operator. Every request from the tenant is
+    /// refused this way until the quarantine runs out. Refer to quarantine::Quarantine.
+    StatusTenantQuarantined = 0x18,
+
+    /// The RPC failed at the server because it asked for a table to be pre-sized for more
+    /// objects than the server allows. The table was not created. Refer to presize::Presize.
+    StatusTableTooLarge = 0x19,
+
+    /// The RPC failed at the server because it named the extension to invoke by an id that is
+    /// no longer interned, because the extension was unloaded or the name loaded again since
+    /// the id was listed. The tenant should list it's extensions again to learn the current
+    /// ids. Refer to INVOKE_FLAG_BY_ID.
+    StatusStaleExtensionId = 0x1a,
+
+    /// The cas() RPC did not write it's object because the value under the key was not the one
+    /// the request expected. The tenant should read the key again before retrying.
+    StatusCompareFailed = 0x1b,
+
+    /// The RPC failed at the server because the extension was pushed back after reading and
+    /// writing more records than can be sent back to the tenant, which would not be able to
+    /// resume it correctly. Refer to tx::MAX_RECORDS.
+    StatusRwSetTooLarge = 0x1c,
 }
 
 /// This enum represents the Generator value in the GetRequest header type.
@@ -155,10 +372,65 @@ pub struct RpcRequestHeader {
     /// An identifier for the tenant that sent this RPC request.
     pub tenant: u32,
 
-    /// An identifier for the RPC request.
+    /// An identifier for the RPC request, unique on the client that sent it. Echoed back on the
+    /// response, and never interpreted by the server.
+    pub id: u64,
+
+    /// The time-stamp at which the client sent out the request. Echoed back on the response,
+    /// and never interpreted by the server.
     pub stamp: u64,
+
+    /// An identifier for the client run that sent this RPC request. Only valid if the request
+    /// has REQUEST_FLAG_RUN set.
+    pub run: u64,
+
+    /// Flags modifying how the server handles the request. See REQUEST_FLAG_STAMPS. Must remain
+    /// the last field on the header.
+    pub flags: u8,
 }
 
+le_fields!(
+    RpcRequestHeader,
+    tenant, set_tenant: u32;
+    id, set_id: u64;
+    stamp, set_stamp: u64;
+    run, set_run: u64;
+);
+
+/// Flag on a request asking the server to fill in the receive and transmit time-stamps on it's
+/// response. Time-stamps cost a couple of cycle counter reads, so they are only taken if asked.
+pub const REQUEST_FLAG_STAMPS: u8 = 0x01;
+
+/// Flag on a request indicating that it's `run` field is valid. The server counts such requests
+/// against the run, and includes the run in warnings it logs about them.
+pub const REQUEST_FLAG_RUN: u8 = 0x02;
+
+/// Flag on a request indicating that the client is retransmitting it after not hearing back.
+/// Servers that remember recently executed requests (see replay::ReplayTable) answer such a
+/// request with the original's response, and count it apart from duplicates made by the
+/// network.
+pub const REQUEST_FLAG_RETRY: u8 = 0x04;
+
+/// Flag on a get() request asking the server to check the object against it's checksum before
+/// returning it. Objects on tables without checksums are returned unchecked. Reads are not
+/// verified otherwise, since recomputing a checksum costs a pass over the object.
+pub const REQUEST_FLAG_VERIFY: u8 = 0x08;
+
+/// Flag on a request asking the server to append a trace trailer to it's response, with the
+/// full time-stamps at which the request was received and it's response sent out. Refer to
+/// trailer::Trace.
+pub const REQUEST_FLAG_TRACE: u8 = 0x10;
+
+/// The offset of the tenant on the common request header. Read off requests before they are
+/// parsed upto their header; refer to rpc::parse_rpc_tenant().
+pub const REQUEST_TENANT_OFFSET: usize = 2;
+
+/// The offset of the run id on the common request header; refer to rpc::parse_rpc_run().
+pub const REQUEST_RUN_OFFSET: usize = 22;
+
+/// The offset of the flags on the common request header; refer to rpc::parse_rpc_flags().
+pub const REQUEST_FLAGS_OFFSET: usize = 30;
+
 impl RpcRequestHeader {
     /// This function can be used to construct the header for an RPC request.
     ///
@@ -169,8 +441,10 @@ impl RpcRequestHeader {
     ///     operation must be supported by rpc_service.
     /// \param rpc_tenant
     ///     An identifier for the tenant sending this request.
-    /// \param rpc_stamp
+    /// \param rpc_id
     ///     An identifier for the rpc request.
+    /// \param rpc_stamp
+    ///     The time-stamp at which the rpc request is being sent out.
     ///
     /// \return
     ///     A header identifying the RPC. This header is of type
@@ -179,13 +453,17 @@ impl RpcRequestHeader {
         rpc_service: Service,
         rpc_opcode: OpCode,
         rpc_tenant: u32,
+        rpc_id: u64,
         rpc_stamp: u64,
     ) -> RpcRequestHeader {
         RpcRequestHeader {
             service: rpc_service,
             opcode: rpc_opcode,
-            tenant: rpc_tenant,
-            stamp: rpc_stamp,
+            tenant: rpc_tenant.to_le(),
+            id: rpc_id.to_le(),
+            stamp: rpc_stamp.to_le(),
+            run: 0,
+            flags: 0,
         }
     }
 }
@@ -211,29 +489,108 @@ pub struct RpcResponseHeader {
     pub tenant: u32,
 
     /// Identifier of the RPC request this response is being generated for.
+    pub id: u64,
+
+    /// The time-stamp on the RPC request this response is being generated for.
     pub stamp: u64,
+
+    /// The load on the server core that generated this response, saturated at u16::MAX. Set
+    /// just before the response is sent out, and used by clients to pace requests.
+    pub load: u16,
+
+    /// The low 32 bits of the server's cycle counter when the request was received off the
+    /// network. Only set if the request had REQUEST_FLAG_STAMPS set, 0 otherwise.
+    pub rx_stamp: u32,
+
+    /// The low 32 bits of the server's cycle counter when the response was handed off to be
+    /// sent out. Only set if the request had REQUEST_FLAG_STAMPS set, 0 otherwise.
+    pub tx_stamp: u32,
+
+    /// The epoch of the scope the request failed to find something in, if it failed with
+    /// StatusObjectDoesNotExist, StatusTableDoesNotExist or StatusTenantDoesNotExist, and 0
+    /// otherwise. Set just before the response is sent out. Refer to epoch.rs.
+    pub epoch: u64,
+
+    /// Flags describing the response. See RESPONSE_FLAG_TRAILERS. Must remain the last field on
+    /// the header.
+    pub flags: u8,
 }
 
+le_fields!(
+    RpcResponseHeader,
+    tenant, set_tenant: u32;
+    id, set_id: u64;
+    stamp, set_stamp: u64;
+    load, set_load: u16;
+    rx_stamp, set_rx_stamp: u32;
+    tx_stamp, set_tx_stamp: u32;
+    epoch, set_epoch: u64;
+);
+
+/// The offset of the request id on the common response header. Read off responses before they
+/// are parsed upto their header; refer to rpc::parse_rpc_id().
+pub const RESPONSE_ID_OFFSET: usize = 6;
+
+/// The offset of the flags on the common response header.
+pub const RESPONSE_FLAGS_OFFSET: usize = 40;
+
+/// Flag on a response indicating that it's payload ends in trailers; optional metadata appended
+/// after the primary payload, refer to trailer::TrailerWriter. Length fields on response headers
+/// only ever cover the primary payload, so a client that ignores trailers parses the rest of
+/// the response as it would without them.
+pub const RESPONSE_FLAG_TRAILERS: u8 = 0x01;
+
 impl RpcResponseHeader {
     /// This method returns a header of type RpcResponseHeader that can be
     /// added to an RPC response. The status on the header is set to StatusOk.
     ///
-    /// - `req_stamp`:  RPC identifier.
+    /// - `req_id`:     RPC identifier.
+    /// - `req_stamp`:  Time-stamp on the RPC request.
     /// - `opcode`:     The opcode on the original RPC request.
     /// - `tenant`:     The tenant this response should be sent to.
     ///
     /// - `return`: A header of type RpcResponseHeader with the status field
     ///             set to RpcStatus::StatusOk.
-    pub fn new(req_stamp: u64, opcode: OpCode, tenant: u32) -> RpcResponseHeader {
+    pub fn new(req_id: u64, req_stamp: u64, opcode: OpCode, tenant: u32) -> RpcResponseHeader {
         RpcResponseHeader {
             status: RpcStatus::StatusOk,
             opcode: opcode,
-            tenant: tenant,
-            stamp: req_stamp,
+            tenant: tenant.to_le(),
+            id: req_id.to_le(),
+            stamp: req_stamp.to_le(),
+            load: 0,
+            rx_stamp: 0,
+            tx_stamp: 0,
+            epoch: 0,
+            flags: 0,
         }
     }
 }
 
+// Implementation of the EndOffset trait for RpcResponseHeader. Responses that carry nothing but
+// a status, such as rejections of opcodes a server doesn't serve, are made up of just this
+// header. Refer to GetRequest's implementation of this trait to understand what the methods and
+// types mean.
+impl EndOffset for RpcResponseHeader {
+    type PreviousHeader = UdpHeader;
+
+    fn offset(&self) -> usize {
+        size_of::<RpcResponseHeader>()
+    }
+
+    fn size() -> usize {
+        size_of::<RpcResponseHeader>()
+    }
+
+    fn payload_size(&self, hint: usize) -> usize {
+        hint - self.offset()
+    }
+
+    fn check_correct(&self, _prev: &Self::PreviousHeader) -> bool {
+        true
+    }
+}
+
 /// This type represents the header for a get() RPC request.
 #[repr(C, packed)]
 pub struct GetRequest {
@@ -251,10 +608,34 @@ pub struct GetRequest {
     /// This enum determines the issuer for the GetRequest, which can either be a
     /// Sandstorm client or an extension running on the client side.
     pub generator: GetGenerator,
+
+    /// How stale a copy of the object the issuer tolerates being served, in microseconds.
+    /// 0 means strictly fresh; the get() is served off the primary copy in the table, bypassing
+    /// any copy that can lag behind it (ex: a cached or replicated one). Refer to db::stale.
+    pub staleness_us: u32,
+
+    /// The offset into the value that the response should start at. Together with
+    /// value_length, this projects the value down to the bytes the issuer needs.
+    pub value_offset: u32,
+
+    /// The number of bytes of the value the response should carry, starting at value_offset.
+    /// 0 means upto the end of the value, so a projection of (0, 0) asks for the whole value.
+    /// A projection that runs past the end of the value is clamped to it.
+    pub value_length: u32,
 }
 
+le_fields!(
+    GetRequest,
+    table_id, set_table_id: u64;
+    key_length, set_key_length: u16;
+    staleness_us, set_staleness_us: u32;
+    value_offset, set_value_offset: u32;
+    value_length, set_value_length: u32;
+);
+
 impl GetRequest {
-    /// This method constructs an RPC header for the get() RPC.
+    /// This method constructs an RPC header for the get() RPC. The get() must
+    /// be served strictly fresh; refer to with_staleness().
     ///
     /// \param req_tenant
     ///     An identifier for the tenant sending the RPC.
@@ -262,8 +643,10 @@ impl GetRequest {
     ///     An identifier for the data table the key belongs to.
     /// \param req_key_length
     ///     The length of the key being looked up.
-    /// \param req_stamp
+    /// \param req_id
     ///     RPC identifier.
+    /// \param req_stamp
+    ///     The time-stamp at which the RPC is being sent out.
     ///
     /// \return
     ///     An RPC header for the get() request. The header is of type
@@ -272,6 +655,7 @@ impl GetRequest {
         req_tenant: u32,
         req_table_id: u64,
         req_key_length: u16,
+        req_id: u64,
         req_stamp: u64,
         req_generator: GetGenerator,
     ) -> GetRequest {
@@ -280,13 +664,46 @@ impl GetRequest {
                 Service::MasterService,
                 OpCode::SandstormGetRpc,
                 req_tenant,
+                req_id,
                 req_stamp,
             ),
-            table_id: req_table_id,
-            key_length: req_key_length,
+            table_id: req_table_id.to_le(),
+            key_length: req_key_length.to_le(),
             generator: req_generator,
+            staleness_us: 0,
+            value_offset: 0,
+            value_length: 0,
         }
     }
+
+    /// This method sets how stale a copy of the object the get() tolerates
+    /// being served.
+    ///
+    /// \param staleness_us
+    ///     The staleness budget, in microseconds. 0 means strictly fresh.
+    ///
+    /// \return
+    ///     The RPC header, with the budget on it.
+    pub fn with_staleness(mut self, staleness_us: u32) -> GetRequest {
+        self.set_staleness_us(staleness_us);
+        self
+    }
+
+    /// This method projects the value the get() returns down to a byte range
+    /// of it.
+    ///
+    /// \param value_offset
+    ///     The offset into the value the returned bytes start at.
+    /// \param value_length
+    ///     The number of bytes to return. 0 means upto the end of the value.
+    ///
+    /// \return
+    ///     The RPC header, with the projection on it.
+    pub fn with_projection(mut self, value_offset: u32, value_length: u32) -> GetRequest {
+        self.set_value_offset(value_offset);
+        self.set_value_length(value_length);
+        self
+    }
 }
 
 // Implementation of the 'EndOffset' trait for the GetRequest header.
@@ -355,24 +772,67 @@ pub struct GetResponse {
     pub common_header: RpcResponseHeader,
 
     /// The length of the value returned in the response if the RPC completed
-    /// successfully.
+    /// successfully. This is less than full_length if the request projected
+    /// the value down to a part of it.
     pub value_length: u32,
+
+    /// The length of the whole value, irrespective of any projection on the
+    /// request, if the RPC completed successfully.
+    pub full_length: u32,
+
+    /// How far the copy of the object the value was read off lagged behind the
+    /// primary copy, in microseconds. 0 if it was read off the primary copy.
+    pub age_us: u32,
+
+    /// Caching hints on the object read. See GET_HINT_HOT. 0 if the server
+    /// has no hint for the object.
+    pub hint: u8,
+
+    /// The version of the object read. Only set if `hint` has GET_HINT_HOT
+    /// set, 0 otherwise.
+    pub version: u64,
+
+    /// How long the client can cache the object for before revalidating it,
+    /// in microseconds. Only set if `hint` has GET_HINT_HOT set, 0 otherwise.
+    pub ttl_us: u32,
 }
 
+le_fields!(
+    GetResponse,
+    value_length, set_value_length: u32;
+    full_length, set_full_length: u32;
+    age_us, set_age_us: u32;
+    version, set_version: u64;
+    ttl_us, set_ttl_us: u32;
+);
+
+/// Flag on a get() response marking the object as read often enough for the client to cache it.
+/// The response then carries the object's version, and how long it can be cached for before it
+/// is revalidated with a multiget() that has MULTIGET_FLAG_VERSIONS_ONLY set. Refer to
+/// hint::hint().
+pub const GET_HINT_HOT: u8 = 0x01;
+
 impl GetResponse {
     /// This method returns a header that can be added to the response to a
-    /// get() RPC request. The value_length field is set to zero.
+    /// get() RPC request. The value_length, full_length and age_us fields are
+    /// set to zero, and the response carries no hint.
     ///
-    /// - `req_stamp`: RPC identifier.
+    /// - `req_id`:    RPC identifier.
+    /// - `req_stamp`: Time-stamp on the RPC request.
     /// - `opcode`:    The opcode on the original RPC request.
     /// - `tenant`:    The tenant this response should be sent to.
     ///
     /// - `return`: A header of type GetResponse that can be added to an RPC
     ///             response.
-    pub fn new(req_stamp: u64, opcode: OpCode, tenant: u32) -> GetResponse {
+    pub fn new(req_id: u64, req_stamp: u64, opcode: OpCode, tenant: u32) -> GetResponse {
         GetResponse {
-            common_header: RpcResponseHeader::new(req_stamp, opcode, tenant),
+            common_header: RpcResponseHeader::new(req_id, req_stamp, opcode, tenant),
             value_length: 0,
+            full_length: 0,
+            age_us: 0,
+            hint: 0,
+            version: 0,
+            ttl_us: 0,
         }
     }
 }
@@ -413,6 +873,12 @@ pub struct PutRequest {
     pub key_length: u16,
 }
 
+le_fields!(
+    PutRequest,
+    table_id, set_table_id: u64;
+    key_length, set_key_length: u16;
+);
+
 // Implementation of methods on PutRequest.
 impl PutRequest {
     /// This method returns an RPC header that can be added to a put() request.
@@ -423,23 +889,31 @@ impl PutRequest {
     /// * `req_table`:   An identifier for the table to add the key-value pair
     ///                  to.
     /// * `req_key_len`: The length of the key inside the RPC request's payload.
-    /// * `req_stamp`:   RPC identifier.
+    /// * `req_id`:      RPC identifier.
+    /// * `req_stamp`:   The time-stamp at which the RPC is being sent out.
     ///
     /// # Return
     ///
     /// An RPC header that can be appended to a put() request.
-    pub fn new(req_tenant: u32, req_table: u64, req_key_len: u16, req_stamp: u64) -> PutRequest {
+    pub fn new(
+        req_tenant: u32,
+        req_table: u64,
+        req_key_len: u16,
+        req_id: u64,
+        req_stamp: u64,
+    ) -> PutRequest {
         let common = RpcRequestHeader::new(
             Service::MasterService,
             OpCode::SandstormPutRpc,
             req_tenant,
+            req_id,
             req_stamp,
         );
 
         PutRequest {
             common_header: common,
-            table_id: req_table,
-            key_length: req_key_len,
+            table_id: req_table.to_le(),
+            key_length: req_key_len.to_le(),
         }
     }
 }
@@ -481,12 +955,13 @@ impl PutResponse {
     ///
     /// # Arguments
     ///
-    /// * `req_stamp`: RPC identifier.
+    /// * `req_id`:    RPC identifier.
+    /// * `req_stamp`: Time-stamp on the RPC request.
     /// * `opcode`:    The opcode on the original RPC request.
     /// * `tenant`:    The tenant this response should be sent to.
-    pub fn new(req_stamp: u64, opcode: OpCode, tenant: u32) -> PutResponse {
+    pub fn new(req_id: u64, req_stamp: u64, opcode: OpCode, tenant: u32) -> PutResponse {
         PutResponse {
-            common_header: RpcResponseHeader::new(req_stamp, opcode, tenant),
+            common_header: RpcResponseHeader::new(req_id, req_stamp, opcode, tenant),
         }
     }
 }
@@ -513,66 +988,46 @@ impl EndOffset for PutResponse {
     }
 }
 
-/// This type represents the request header corresponding to an invoke() RPC.
+/// This type represents the header for an echo() RPC request.
 #[repr(C, packed)]
-pub struct InvokeRequest {
-    /// The common RPC header identifying the opcode, service, and tenant.
+pub struct EchoRequest {
+    /// The generic RPC header identifying the request as an echo() RPC.
     pub common_header: RpcRequestHeader,
-
-    /// The length of the name of the procedure to be invoked. Required to
-    /// deserialize the procedure's name from the request packet at the server.
-    pub name_length: u32,
-
-    /// The total length of the args to be passed into the procedure. Required
-    /// to deserialize the arguments to the procedure from the request packet
-    /// at the server.
-    pub args_length: u32,
 }
 
-impl InvokeRequest {
-    /// This method returns a header corresponding to an invoke() RPC request.
-    /// The returned header can be appended onto a request packet.
+// Implementation of methods on EchoRequest.
+impl EchoRequest {
+    /// This method returns a header that can be added to an echo() RPC request.
     ///
     /// # Arguments
     ///
-    /// * `tenant`:      An identifier for the tenant issuing this RPC.
-    /// * `name_length`: The length of the name of the procedure to be invoked.
-    ///                  Required so that the name can be read from the request
-    ///                  packet by the server.
-    /// * `args_length`: The length of the args to be supplied to the procedure.
-    ///                  Required so that the server can unpack them from a
-    ///                  request packet.
-    /// * `req_stamp`:   RPC identifier.
-    ///
-    /// # Return
-    ///
-    /// An RPC request header of type `InvokeRequest`.
-    pub fn new(tenant: u32, name_length: u32, args_length: u32, req_stamp: u64) -> InvokeRequest {
-        InvokeRequest {
+    /// * `tenant`: Identifier of the tenant sending the request.
+    /// * `id`:     RPC identifier.
+    /// * `stamp`:  The time-stamp at which the RPC is being sent out.
+    pub fn new(tenant: u32, id: u64, stamp: u64) -> EchoRequest {
+        EchoRequest {
             common_header: RpcRequestHeader::new(
                 Service::MasterService,
-                OpCode::SandstormInvokeRpc,
+                OpCode::SandstormEchoRpc,
                 tenant,
-                req_stamp,
+                id,
+                stamp,
             ),
-            name_length: name_length,
-            args_length: args_length,
         }
     }
 }
 
-// Implementation of the EndOffset trait for InvokeRequest. Refer to
-// GetRequest's implementation of this trait to understand what the methods
-// and types mean.
-impl EndOffset for InvokeRequest {
+// Implementation of the EndOffset trait for EchoRequest. Refer to GetRequest's
+// implementation of this trait to understand what the methods and types mean.
+impl EndOffset for EchoRequest {
     type PreviousHeader = UdpHeader;
 
     fn offset(&self) -> usize {
-        size_of::<InvokeRequest>()
+        size_of::<EchoRequest>()
     }
 
     fn size() -> usize {
-        size_of::<InvokeRequest>()
+        size_of::<EchoRequest>()
     }
 
     fn payload_size(&self, hint: usize) -> usize {
@@ -584,41 +1039,86 @@ impl EndOffset for InvokeRequest {
     }
 }
 
-/// This type represents the response header for an invoke() RPC request.
+/// This type represents the header on a response to an echo() RPC request.
 #[repr(C, packed)]
-pub struct InvokeResponse {
-    /// A common RPC response header containing the status of the RPC.
+pub struct EchoResponse {
+    /// A generic RPC header indicating whether the RPC request succeeded
+    /// or failed.
     pub common_header: RpcResponseHeader,
+
+    /// The rate at which the server's cycle counter ticks. Required to convert the receive and
+    /// transmit time-stamps on responses into seconds.
+    pub cycles_per_second: u64,
+
+    /// The opcodes the server is serving, as a mask of OpCode::bit(). Clients check that the
+    /// opcodes they are about to issue are in it before starting a run.
+    pub opcodes: u64,
+
+    /// The longest key the server accepts, in bytes. Refer to limits::Limits.
+    pub max_key_len: u32,
+
+    /// The largest value the server accepts, in bytes. Clients check that the objects they are
+    /// about to write fit under both before starting a run.
+    pub max_value_len: u32,
 }
 
-impl InvokeResponse {
+le_fields!(
+    EchoResponse,
+    cycles_per_second, set_cycles_per_second: u64;
+    opcodes, set_opcodes: u64;
+    max_key_len, set_max_key_len: u32;
+    max_value_len, set_max_value_len: u32;
+);
+
+// Implementation of methods on EchoResponse.
+impl EchoResponse {
     /// This method returns a header that can be appended to the response
-    /// packet for an invoke() RPC request.
+    /// to an echo() RPC request.
     ///
     /// # Arguments
     ///
-    /// * `req_stamp`: RPC identifier.
-    /// * `opcode`:    The opcode on the original RPC request.
-    /// * `tenant`:    The tenant this response should be sent to.
-    pub fn new(req_stamp: u64, opcode: OpCode, tenant: u32) -> InvokeResponse {
-        InvokeResponse {
-            common_header: RpcResponseHeader::new(req_stamp, opcode, tenant),
+    /// * `req_id`:            RPC identifier.
+    /// * `req_stamp`:         Time-stamp on the RPC request.
+    /// * `tenant`:            The tenant this response should be sent to.
+    /// * `cycles_per_second`: The rate at which the server's cycle counter ticks.
+    /// * `opcodes`:           The opcodes the server is serving.
+    /// * `max_key_len`:       The longest key the server accepts.
+    /// * `max_value_len`:     The largest value the server accepts.
+    pub fn new(
+        req_id: u64,
+        req_stamp: u64,
+        tenant: u32,
+        cycles_per_second: u64,
+        opcodes: u64,
+        max_key_len: u32,
+        max_value_len: u32,
+    ) -> EchoResponse {
+        EchoResponse {
+            common_header: RpcResponseHeader::new(
+                req_id,
+                req_stamp,
+                OpCode::SandstormEchoRpc,
+                tenant,
+            ),
+            cycles_per_second: cycles_per_second.to_le(),
+            opcodes: opcodes.to_le(),
+            max_key_len: max_key_len.to_le(),
+            max_value_len: max_value_len.to_le(),
         }
     }
 }
 
-// Implementation of the EndOffset trait for InvokeResponse. Refer to
-// GetRequest's implementation of this trait to understand what the methods
-// and types mean.
-impl EndOffset for InvokeResponse {
+// Implementation of the EndOffset trait for EchoResponse. Refer to GetRequest's
+// implementation of this trait to understand what the methods and types mean.
+impl EndOffset for EchoResponse {
     type PreviousHeader = UdpHeader;
 
     fn offset(&self) -> usize {
-        size_of::<InvokeResponse>()
+        size_of::<EchoResponse>()
     }
 
     fn size() -> usize {
-        size_of::<InvokeResponse>()
+        size_of::<EchoResponse>()
     }
 
     fn payload_size(&self, hint: usize) -> usize {
@@ -630,59 +1130,57 @@ impl EndOffset for InvokeResponse {
     }
 }
 
-/// This type represents the header for an install() RPC request.
+/// This type represents the header for a list_extensions() RPC request.
 #[repr(C, packed)]
-pub struct InstallRequest {
-    /// Generic RPC header identifying the service, opcode, and tenant.
+pub struct ListExtRequest {
+    /// The generic RPC header identifying the request as a list_extensions() RPC.
     pub common_header: RpcRequestHeader,
 
-    /// Length of the name in bytes of the extension being installed. The payload of the RPC should
-    /// start with the name of the extension.
-    pub name_length: u32,
-
-    /// Length of the extension in bytes. The extension should follow the name on the RPC's
-    /// payload.
-    pub extn_length: u32,
+    /// The index of the first extension to be listed. 0 on the first request, and the
+    /// continuation index on the previous response otherwise.
+    pub start: u32,
 }
 
-// Implementation of methods on InstallRequest.
-impl InstallRequest {
-    /// Returns a header for the install() RPC request. The header is of type `InstallRequest`.
+le_fields!(
+    ListExtRequest,
+    start, set_start: u32;
+);
+
+// Implementation of methods on ListExtRequest.
+impl ListExtRequest {
+    /// This method returns a header that can be added to a list_extensions() RPC request.
     ///
     /// # Arguments
     ///
-    /// * `tenant`:      Tenant identifier.
-    /// * `name_length`: Length of the name of the extension in bytes. The payload of the RPC
-    ///                  should start with the name of the extension.
-    /// * `extn_length`: Length of the extension in bytes. The extension should follow the name on
-    ///                  the RPC's payload.
-    /// * `req_stamp`:   RPC identifier.
-    pub fn new(tenant: u32, name_length: u32, extn_length: u32, req_stamp: u64) -> InstallRequest {
-        InstallRequest {
+    /// * `tenant`: Identifier of the tenant whose extensions must be listed.
+    /// * `start`:  The index of the first extension to be listed.
+    /// * `id`:     RPC identifier.
+    /// * `stamp`:  The time-stamp at which the RPC is being sent out.
+    pub fn new(tenant: u32, start: u32, id: u64, stamp: u64) -> ListExtRequest {
+        ListExtRequest {
             common_header: RpcRequestHeader::new(
                 Service::MasterService,
-                OpCode::SandstormInstallRpc,
+                OpCode::SandstormListExtRpc,
                 tenant,
-                req_stamp,
+                id,
+                stamp,
             ),
-            name_length: name_length,
-            extn_length: extn_length,
+            start: start.to_le(),
         }
     }
 }
 
-// Implementation of the EndOffset trait for InstallRequest. Refer to
-// GetRequest's implementation of this trait to understand what the methods
-// and types mean.
-impl EndOffset for InstallRequest {
+// Implementation of the EndOffset trait for ListExtRequest. Refer to GetRequest's
+// implementation of this trait to understand what the methods and types mean.
+impl EndOffset for ListExtRequest {
     type PreviousHeader = UdpHeader;
 
     fn offset(&self) -> usize {
-        size_of::<InstallRequest>()
+        size_of::<ListExtRequest>()
     }
 
     fn size() -> usize {
-        size_of::<InstallRequest>()
+        size_of::<ListExtRequest>()
     }
 
     fn payload_size(&self, hint: usize) -> usize {
@@ -694,42 +1192,63 @@ impl EndOffset for InstallRequest {
     }
 }
 
-/// This type represents the response header for an install() RPC request.
+/// This type represents the header on a response to a list_extensions() RPC request. The header
+/// is followed by `num_entries` packed entries, refer to rpc::encode_ext_listing().
 #[repr(C, packed)]
-pub struct InstallResponse {
-    /// A generic response header with the status of the RPC (indicating whether it
-    /// succeeded or failed).
+pub struct ListExtResponse {
+    /// A generic RPC header indicating whether the RPC request succeeded
+    /// or failed.
     pub common_header: RpcResponseHeader,
+
+    /// The number of entries on the response.
+    pub num_entries: u32,
+
+    /// The index to send on the next request to continue the listing. 0 if the listing is
+    /// complete.
+    pub next: u32,
 }
 
-// Implementation of methods on InstallResponse.
-impl InstallResponse {
-    /// Returns a header for the install() RPC request.
+le_fields!(
+    ListExtResponse,
+    num_entries, set_num_entries: u32;
+    next, set_next: u32;
+);
+
+// Implementation of methods on ListExtResponse.
+impl ListExtResponse {
+    /// This method returns a header that can be appended to the response
+    /// to a list_extensions() RPC request.
     ///
     /// # Arguments
     ///
-    /// * `req_stamp`: RPC identifier.
-    /// * `opcode`:    The opcode on the original RPC request.
+    /// * `req_id`:    RPC identifier.
+    /// * `req_stamp`: Time-stamp on the RPC request.
     /// * `tenant`:    The tenant this response should be sent to.
-    pub fn new(req_stamp: u64, opcode: OpCode, tenant: u32) -> InstallResponse {
-        InstallResponse {
-            common_header: RpcResponseHeader::new(req_stamp, opcode, tenant),
+    pub fn new(req_id: u64, req_stamp: u64, tenant: u32) -> ListExtResponse {
+        ListExtResponse {
+            common_header: RpcResponseHeader::new(
+                req_id,
+                req_stamp,
+                OpCode::SandstormListExtRpc,
+                tenant,
+            ),
+            num_entries: 0,
+            next: 0,
         }
     }
 }
 
-// Implementation of the EndOffset trait for InstallResponse. Refer to
-// GetRequest's implementation of this trait to understand what the methods
-// and types mean.
-impl EndOffset for InstallResponse {
+// Implementation of the EndOffset trait for ListExtResponse. Refer to GetRequest's
+// implementation of this trait to understand what the methods and types mean.
+impl EndOffset for ListExtResponse {
     type PreviousHeader = UdpHeader;
 
     fn offset(&self) -> usize {
-        size_of::<InstallResponse>()
+        size_of::<ListExtResponse>()
     }
 
     fn size() -> usize {
-        size_of::<InstallResponse>()
+        size_of::<ListExtResponse>()
     }
 
     fn payload_size(&self, hint: usize) -> usize {
@@ -741,64 +1260,46 @@ impl EndOffset for InstallResponse {
     }
 }
 
-/// This type represents the RPC header on a multiget() request.
+/// This type represents the header for a drain() RPC request.
 #[repr(C, packed)]
-pub struct MultiGetRequest {
-    /// Generic RPC header consisting of service, opcode, and tenant id.
+pub struct DrainRequest {
+    /// The generic RPC header identifying the request as a drain() RPC.
     pub common_header: RpcRequestHeader,
-
-    /// Table that should be looked up for the records.
-    pub table_id: u64,
-
-    /// The length of every key to be looked up. All keys to be looked up are assumed to be of
-    /// equal length.
-    pub key_len: u16,
-
-    /// The number of keys to be looked up at the database. Every key should be `key_len` bytes
-    /// long.
-    pub num_keys: u32,
 }
 
-// Implementation of methods on MultiGetRequest.
-impl MultiGetRequest {
-    /// Constructs an RPC header that can be added to the multiget() request. The header is of type
-    /// `MultiGetRequest`.
+// Implementation of methods on DrainRequest.
+impl DrainRequest {
+    /// This method returns a header that can be added to a drain() RPC request.
     ///
     /// # Arguments
     ///
-    /// * `tenant`: Identifier of the tenant sending the request.
-    /// * `table`:  Identifier of the table to be looked up.
-    /// * `k_len`:  Length of every key to be looked up. All keys are assumed to be of equal
-    ///             length.
-    /// * `n_keys`: The number of keys to be looked up (each of length `k_len`).
-    /// * `stamp`:  Identifier of the RPC. Can be used as a timestamp.
-    pub fn new(tenant: u32, table: u64, k_len: u16, n_keys: u32, stamp: u64) -> MultiGetRequest {
-        MultiGetRequest {
+    /// * `tenant`: Identifier of the tenant issuing the drain. Must be 0.
+    /// * `id`:     RPC identifier.
+    /// * `stamp`:  The time-stamp at which the RPC is being sent out.
+    pub fn new(tenant: u32, id: u64, stamp: u64) -> DrainRequest {
+        DrainRequest {
             common_header: RpcRequestHeader::new(
                 Service::MasterService,
-                OpCode::SandstormMultiGetRpc,
+                OpCode::SandstormDrainRpc,
                 tenant,
+                id,
                 stamp,
             ),
-            table_id: table,
-            key_len: k_len,
-            num_keys: n_keys,
         }
     }
 }
 
-// Implementation of the EndOffset trait for MultiGetRequest. Refer to
-// GetRequest's implementation of this trait to understand what the methods
-// and types mean.
-impl EndOffset for MultiGetRequest {
+// Implementation of the EndOffset trait for DrainRequest. Refer to GetRequest's
+// implementation of this trait to understand what the methods and types mean.
+impl EndOffset for DrainRequest {
     type PreviousHeader = UdpHeader;
 
     fn offset(&self) -> usize {
-        size_of::<MultiGetRequest>()
+        size_of::<DrainRequest>()
     }
 
     fn size() -> usize {
-        size_of::<MultiGetRequest>()
+        size_of::<DrainRequest>()
     }
 
     fn payload_size(&self, hint: usize) -> usize {
@@ -810,16 +1311,3091 @@ impl EndOffset for MultiGetRequest {
     }
 }
 
-/// This type represents the response header for a multiget() RPC request.
+/// This type represents the header on a response to a drain() RPC request. The header is
+/// followed by `num_cores` little-endian u32s, the number of tasks remaining on each core.
 #[repr(C, packed)]
-pub struct MultiGetResponse {
-    /// Generic response header consisting of RPC status and identifier.
-    pub common_header: RpcResponseHeader,
+pub struct DrainResponse {
+    /// A generic RPC header indicating whether the RPC request succeeded
+    /// or failed.
+    pub common_header: RpcResponseHeader,
+
+    /// The number of cores whose progress is reported on the response.
+    pub num_cores: u32,
+
+    /// The number of tasks remaining across all cores.
+    pub remaining: u32,
+}
+
+le_fields!(
+    DrainResponse,
+    num_cores, set_num_cores: u32;
+    remaining, set_remaining: u32;
+);
+
+// Implementation of methods on DrainResponse.
+impl DrainResponse {
+    /// This method returns a header that can be appended to the response
+    /// to a drain() RPC request.
+    ///
+    /// # Arguments
+    ///
+    /// * `req_id`:    RPC identifier.
+    /// * `req_stamp`: Time-stamp on the RPC request.
+    /// * `tenant`:    The tenant this response should be sent to.
+    pub fn new(req_id: u64, req_stamp: u64, tenant: u32) -> DrainResponse {
+        DrainResponse {
+            common_header: RpcResponseHeader::new(
+                req_id,
+                req_stamp,
+                OpCode::SandstormDrainRpc,
+                tenant,
+            ),
+            num_cores: 0,
+            remaining: 0,
+        }
+    }
+}
+
+// Implementation of the EndOffset trait for DrainResponse. Refer to GetRequest's
+// implementation of this trait to understand what the methods and types mean.
+impl EndOffset for DrainResponse {
+    type PreviousHeader = UdpHeader;
+
+    fn offset(&self) -> usize {
+        size_of::<DrainResponse>()
+    }
+
+    fn size() -> usize {
+        size_of::<DrainResponse>()
+    }
+
+    fn payload_size(&self, hint: usize) -> usize {
+        hint - self.offset()
+    }
+
+    fn check_correct(&self, _prev: &Self::PreviousHeader) -> bool {
+        true
+    }
+}
+
+/// This type represents the header for a table_access() RPC request.
+#[repr(C, packed)]
+pub struct TableAccessRequest {
+    /// The generic RPC header identifying the request as a table_access() RPC.
+    pub common_header: RpcRequestHeader,
+
+    /// The table whose access is being set. Must be owned by the tenant issuing the RPC.
+    pub table_id: u64,
+
+    /// If non-zero, native get() and multiget() RPCs can read the table.
+    pub readable_native: u8,
+
+    /// If non-zero, native put(), delete() and cas() RPCs can write to the table.
+    pub writable_native: u8,
+}
+
+le_fields!(
+    TableAccessRequest,
+    table_id, set_table_id: u64;
+);
+
+// Implementation of methods on TableAccessRequest.
+impl TableAccessRequest {
+    /// This method returns a header that can be added to a table_access() RPC request.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`:   Identifier of the tenant owning the table.
+    /// * `table_id`: Identifier of the table whose access is being set.
+    /// * `readable`: Whether native RPCs can read the table.
+    /// * `writable`: Whether native RPCs can write to the table.
+    /// * `id`:       RPC identifier.
+    /// * `stamp`:    The time-stamp at which the RPC is being sent out.
+    pub fn new(
+        tenant: u32,
+        table_id: u64,
+        readable: bool,
+        writable: bool,
+        id: u64,
+        stamp: u64,
+    ) -> TableAccessRequest {
+        TableAccessRequest {
+            common_header: RpcRequestHeader::new(
+                Service::MasterService,
+                OpCode::SandstormTableAccessRpc,
+                tenant,
+                id,
+                stamp,
+            ),
+            table_id: table_id.to_le(),
+            readable_native: readable as u8,
+            writable_native: writable as u8,
+        }
+    }
+}
+
+// Implementation of the EndOffset trait for TableAccessRequest. Refer to GetRequest's
+// implementation of this trait to understand what the methods and types mean.
+impl EndOffset for TableAccessRequest {
+    type PreviousHeader = UdpHeader;
+
+    fn offset(&self) -> usize {
+        size_of::<TableAccessRequest>()
+    }
+
+    fn size() -> usize {
+        size_of::<TableAccessRequest>()
+    }
+
+    fn payload_size(&self, hint: usize) -> usize {
+        hint - self.offset()
+    }
+
+    fn check_correct(&self, _prev: &Self::PreviousHeader) -> bool {
+        true
+    }
+}
+
+/// This type represents the header on a response to a table_access() RPC request.
+#[repr(C, packed)]
+pub struct TableAccessResponse {
+    /// A generic RPC header indicating whether the RPC request succeeded
+    /// or failed.
+    pub common_header: RpcResponseHeader,
+}
+
+// Implementation of methods on TableAccessResponse.
+impl TableAccessResponse {
+    /// This method returns a header that can be appended to the response
+    /// to a table_access() RPC request.
+    ///
+    /// # Arguments
+    ///
+    /// * `req_id`:    RPC identifier.
+    /// * `req_stamp`: Time-stamp on the RPC request.
+    /// * `tenant`:    The tenant this response should be sent to.
+    pub fn new(req_id: u64, req_stamp: u64, tenant: u32) -> TableAccessResponse {
+        TableAccessResponse {
+            common_header: RpcResponseHeader::new(
+                req_id,
+                req_stamp,
+                OpCode::SandstormTableAccessRpc,
+                tenant,
+            ),
+        }
+    }
+}
+
+// Implementation of the EndOffset trait for TableAccessResponse. Refer to GetRequest's
+// implementation of this trait to understand what the methods and types mean.
+impl EndOffset for TableAccessResponse {
+    type PreviousHeader = UdpHeader;
+
+    fn offset(&self) -> usize {
+        size_of::<TableAccessResponse>()
+    }
+
+    fn size() -> usize {
+        size_of::<TableAccessResponse>()
+    }
+
+    fn payload_size(&self, hint: usize) -> usize {
+        hint - self.offset()
+    }
+
+    fn check_correct(&self, _prev: &Self::PreviousHeader) -> bool {
+        true
+    }
+}
+
+/// This type represents the header for a run_stats() RPC request.
+#[repr(C, packed)]
+pub struct RunStatsRequest {
+    /// The generic RPC header identifying the request as a run_stats() RPC.
+    pub common_header: RpcRequestHeader,
+
+    /// The run whose counters are requested. If 0, the counters of the most recently active
+    /// runs are returned instead.
+    pub filter: u64,
+}
+
+le_fields!(
+    RunStatsRequest,
+    filter, set_filter: u64;
+);
+
+// Implementation of methods on RunStatsRequest.
+impl RunStatsRequest {
+    /// This method returns a header that can be added to a run_stats() RPC request.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`: Identifier of the tenant issuing the request. Must be 0.
+    /// * `filter`: The run whose counters are requested, or 0 for the most recent runs.
+    /// * `id`:     RPC identifier.
+    /// * `stamp`:  The time-stamp at which the RPC is being sent out.
+    pub fn new(tenant: u32, filter: u64, id: u64, stamp: u64) -> RunStatsRequest {
+        RunStatsRequest {
+            common_header: RpcRequestHeader::new(
+                Service::MasterService,
+                OpCode::SandstormRunStatsRpc,
+                tenant,
+                id,
+                stamp,
+            ),
+            filter: filter.to_le(),
+        }
+    }
+}
+
+// Implementation of the EndOffset trait for RunStatsRequest. Refer to GetRequest's
+// implementation of this trait to understand what the methods and types mean.
+impl EndOffset for RunStatsRequest {
+    type PreviousHeader = UdpHeader;
+
+    fn offset(&self) -> usize {
+        size_of::<RunStatsRequest>()
+    }
+
+    fn size() -> usize {
+        size_of::<RunStatsRequest>()
+    }
+
+    fn payload_size(&self, hint: usize) -> usize {
+        hint - self.offset()
+    }
+
+    fn check_correct(&self, _prev: &Self::PreviousHeader) -> bool {
+        true
+    }
+}
+
+/// This type represents the header on a response to a run_stats() RPC request. The header is
+/// followed by `num_runs` entries of five little-endian u64s each: the run id, and the number
+/// of requests, errors, pushbacks and quarantined requests counted against it.
+#[repr(C, packed)]
+pub struct RunStatsResponse {
+    /// A generic RPC header indicating whether the RPC request succeeded
+    /// or failed.
+    pub common_header: RpcResponseHeader,
+
+    /// The number of runs on the response.
+    pub num_runs: u32,
+}
+
+le_fields!(
+    RunStatsResponse,
+    num_runs, set_num_runs: u32;
+);
+
+// Implementation of methods on RunStatsResponse.
+impl RunStatsResponse {
+    /// This method returns a header that can be appended to the response
+    /// to a run_stats() RPC request.
+    ///
+    /// # Arguments
+    ///
+    /// * `req_id`:    RPC identifier.
+    /// * `req_stamp`: Time-stamp on the RPC request.
+    /// * `tenant`:    The tenant this response should be sent to.
+    pub fn new(req_id: u64, req_stamp: u64, tenant: u32) -> RunStatsResponse {
+        RunStatsResponse {
+            common_header: RpcResponseHeader::new(
+                req_id,
+                req_stamp,
+                OpCode::SandstormRunStatsRpc,
+                tenant,
+            ),
+            num_runs: 0,
+        }
+    }
+}
+
+// Implementation of the EndOffset trait for RunStatsResponse. Refer to GetRequest's
+// implementation of this trait to understand what the methods and types mean.
+impl EndOffset for RunStatsResponse {
+    type PreviousHeader = UdpHeader;
+
+    fn offset(&self) -> usize {
+        size_of::<RunStatsResponse>()
+    }
+
+    fn size() -> usize {
+        size_of::<RunStatsResponse>()
+    }
+
+    fn payload_size(&self, hint: usize) -> usize {
+        hint - self.offset()
+    }
+
+    fn check_correct(&self, _prev: &Self::PreviousHeader) -> bool {
+        true
+    }
+}
+
+/// This type represents the header for a merge() RPC request.
+#[repr(C, packed)]
+pub struct MergeRequest {
+    /// The generic RPC header identifying the request as a merge() RPC.
+    pub common_header: RpcRequestHeader,
+
+    /// The table holding the key. It's registered merge extension is used.
+    pub table_id: u64,
+
+    /// The length of the key. The key is followed by the delta on the payload.
+    pub key_length: u16,
+}
+
+le_fields!(
+    MergeRequest,
+    table_id, set_table_id: u64;
+    key_length, set_key_length: u16;
+);
+
+// Implementation of methods on MergeRequest.
+impl MergeRequest {
+    /// This method returns a header that can be added to a merge() RPC request.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`:     Identifier of the tenant sending the request.
+    /// * `table_id`:   Identifier of the table holding the key.
+    /// * `key_length`: The length of the key on the payload.
+    /// * `id`:         RPC identifier.
+    /// * `stamp`:      The time-stamp at which the RPC is being sent out.
+    pub fn new(tenant: u32, table_id: u64, key_length: u16, id: u64, stamp: u64) -> MergeRequest {
+        MergeRequest {
+            common_header: RpcRequestHeader::new(
+                Service::MasterService,
+                OpCode::SandstormMergeRpc,
+                tenant,
+                id,
+                stamp,
+            ),
+            table_id: table_id.to_le(),
+            key_length: key_length.to_le(),
+        }
+    }
+}
+
+// Implementation of the EndOffset trait for MergeRequest. Refer to GetRequest's
+// implementation of this trait to understand what the methods and types mean.
+impl EndOffset for MergeRequest {
+    type PreviousHeader = UdpHeader;
+
+    fn offset(&self) -> usize {
+        size_of::<MergeRequest>()
+    }
+
+    fn size() -> usize {
+        size_of::<MergeRequest>()
+    }
+
+    fn payload_size(&self, hint: usize) -> usize {
+        hint - self.offset()
+    }
+
+    fn check_correct(&self, _prev: &Self::PreviousHeader) -> bool {
+        true
+    }
+}
+
+/// This type represents the header on a response to a merge() RPC request.
+/// If the merge extension failed, the status is StatusExtensionError and the payload holds
+/// whatever the extension wrote out (it's error frame).
+#[repr(C, packed)]
+pub struct MergeResponse {
+    /// A generic RPC header indicating whether the RPC request succeeded
+    /// or failed.
+    pub common_header: RpcResponseHeader,
+}
+
+// Implementation of methods on MergeResponse.
+impl MergeResponse {
+    /// This method returns a header that can be appended to the response
+    /// to a merge() RPC request.
+    ///
+    /// # Arguments
+    ///
+    /// * `req_id`:    RPC identifier.
+    /// * `req_stamp`: Time-stamp on the RPC request.
+    /// * `tenant`:    The tenant this response should be sent to.
+    pub fn new(req_id: u64, req_stamp: u64, tenant: u32) -> MergeResponse {
+        MergeResponse {
+            common_header: RpcResponseHeader::new(
+                req_id,
+                req_stamp,
+                OpCode::SandstormMergeRpc,
+                tenant,
+            ),
+        }
+    }
+}
+
+// Implementation of the EndOffset trait for MergeResponse. Refer to GetRequest's
+// implementation of this trait to understand what the methods and types mean.
+impl EndOffset for MergeResponse {
+    type PreviousHeader = UdpHeader;
+
+    fn offset(&self) -> usize {
+        size_of::<MergeResponse>()
+    }
+
+    fn size() -> usize {
+        size_of::<MergeResponse>()
+    }
+
+    fn payload_size(&self, hint: usize) -> usize {
+        hint - self.offset()
+    }
+
+    fn check_correct(&self, _prev: &Self::PreviousHeader) -> bool {
+        true
+    }
+}
+
+/// This type represents the header for a set_merge() RPC request.
+#[repr(C, packed)]
+pub struct SetMergeRequest {
+    /// The generic RPC header identifying the request as a set_merge() RPC.
+    pub common_header: RpcRequestHeader,
+
+    /// The table whose merge extension is being set. Must be owned by the tenant issuing the
+    /// RPC.
+    pub table_id: u64,
+
+    /// The length of the name of the merge extension, which follows on the payload. Zero
+    /// clears the table's merge extension.
+    pub name_length: u32,
+}
+
+le_fields!(
+    SetMergeRequest,
+    table_id, set_table_id: u64;
+    name_length, set_name_length: u32;
+);
+
+// Implementation of methods on SetMergeRequest.
+impl SetMergeRequest {
+    /// This method returns a header that can be added to a set_merge() RPC request.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`:      Identifier of the tenant owning the table.
+    /// * `table_id`:    Identifier of the table whose merge extension is being set.
+    /// * `name_length`: The length of the extension's name on the payload.
+    /// * `id`:          RPC identifier.
+    /// * `stamp`:       The time-stamp at which the RPC is being sent out.
+    pub fn new(
+        tenant: u32,
+        table_id: u64,
+        name_length: u32,
+        id: u64,
+        stamp: u64,
+    ) -> SetMergeRequest {
+        SetMergeRequest {
+            common_header: RpcRequestHeader::new(
+                Service::MasterService,
+                OpCode::SandstormSetMergeRpc,
+                tenant,
+                id,
+                stamp,
+            ),
+            table_id: table_id.to_le(),
+            name_length: name_length.to_le(),
+        }
+    }
+}
+
+// Implementation of the EndOffset trait for SetMergeRequest. Refer to GetRequest's
+// implementation of this trait to understand what the methods and types mean.
+impl EndOffset for SetMergeRequest {
+    type PreviousHeader = UdpHeader;
+
+    fn offset(&self) -> usize {
+        size_of::<SetMergeRequest>()
+    }
+
+    fn size() -> usize {
+        size_of::<SetMergeRequest>()
+    }
+
+    fn payload_size(&self, hint: usize) -> usize {
+        hint - self.offset()
+    }
+
+    fn check_correct(&self, _prev: &Self::PreviousHeader) -> bool {
+        true
+    }
+}
+
+/// This type represents the header on a response to a set_merge() RPC request.
+#[repr(C, packed)]
+pub struct SetMergeResponse {
+    /// A generic RPC header indicating whether the RPC request succeeded
+    /// or failed.
+    pub common_header: RpcResponseHeader,
+}
+
+// Implementation of methods on SetMergeResponse.
+impl SetMergeResponse {
+    /// This method returns a header that can be appended to the response
+    /// to a set_merge() RPC request.
+    ///
+    /// # Arguments
+    ///
+    /// * `req_id`:    RPC identifier.
+    /// * `req_stamp`: Time-stamp on the RPC request.
+    /// * `tenant`:    The tenant this response should be sent to.
+    pub fn new(req_id: u64, req_stamp: u64, tenant: u32) -> SetMergeResponse {
+        SetMergeResponse {
+            common_header: RpcResponseHeader::new(
+                req_id,
+                req_stamp,
+                OpCode::SandstormSetMergeRpc,
+                tenant,
+            ),
+        }
+    }
+}
+
+// Implementation of the EndOffset trait for SetMergeResponse. Refer to GetRequest's
+// implementation of this trait to understand what the methods and types mean.
+impl EndOffset for SetMergeResponse {
+    type PreviousHeader = UdpHeader;
+
+    fn offset(&self) -> usize {
+        size_of::<SetMergeResponse>()
+    }
+
+    fn size() -> usize {
+        size_of::<SetMergeResponse>()
+    }
+
+    fn payload_size(&self, hint: usize) -> usize {
+        hint - self.offset()
+    }
+
+    fn check_correct(&self, _prev: &Self::PreviousHeader) -> bool {
+        true
+    }
+}
+
+/// This type represents the header for a routed invoke() RPC request. The key follows the
+/// header on the payload, and the arguments follow the key. The server picks the extension off
+/// the key's prefix, and invokes it with the arguments laid out by sandstorm::route.
+#[repr(C, packed)]
+pub struct RoutedInvokeRequest {
+    /// The generic RPC header identifying the request as a routed invoke() RPC.
+    pub common_header: RpcRequestHeader,
+
+    /// The table the extension is asked to operate on. Passed on to the extension.
+    pub table_id: u64,
+
+    /// The length of the key on the payload.
+    pub key_length: u16,
+
+    /// The length of the arguments on the payload, after the key.
+    pub args_length: u32,
+}
+
+le_fields!(
+    RoutedInvokeRequest,
+    table_id, set_table_id: u64;
+    key_length, set_key_length: u16;
+    args_length, set_args_length: u32;
+);
+
+// Implementation of methods on RoutedInvokeRequest.
+impl RoutedInvokeRequest {
+    /// This method returns a header that can be added to a routed invoke() RPC request.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`:      Identifier of the tenant sending the request.
+    /// * `table_id`:    Identifier of the table passed on to the extension.
+    /// * `key_length`:  The length of the key on the payload.
+    /// * `args_length`: The length of the arguments on the payload.
+    /// * `id`:          RPC identifier.
+    /// * `stamp`:       The time-stamp at which the RPC is being sent out.
+    pub fn new(
+        tenant: u32,
+        table_id: u64,
+        key_length: u16,
+        args_length: u32,
+        id: u64,
+        stamp: u64,
+    ) -> RoutedInvokeRequest {
+        RoutedInvokeRequest {
+            common_header: RpcRequestHeader::new(
+                Service::MasterService,
+                OpCode::SandstormRoutedInvokeRpc,
+                tenant,
+                id,
+                stamp,
+            ),
+            table_id: table_id.to_le(),
+            key_length: key_length.to_le(),
+            args_length: args_length.to_le(),
+        }
+    }
+}
+
+// Implementation of the EndOffset trait for RoutedInvokeRequest. Refer to GetRequest's
+// implementation of this trait to understand what the methods and types mean.
+impl EndOffset for RoutedInvokeRequest {
+    type PreviousHeader = UdpHeader;
+
+    fn offset(&self) -> usize {
+        size_of::<RoutedInvokeRequest>()
+    }
+
+    fn size() -> usize {
+        size_of::<RoutedInvokeRequest>()
+    }
+
+    fn payload_size(&self, hint: usize) -> usize {
+        hint - self.offset()
+    }
+
+    fn check_correct(&self, _prev: &Self::PreviousHeader) -> bool {
+        true
+    }
+}
+
+/// This type represents the header for a set_route() RPC request. The prefix follows the header
+/// on the payload, and the name of the extension follows the prefix.
+#[repr(C, packed)]
+pub struct SetRouteRequest {
+    /// The generic RPC header identifying the request as a set_route() RPC.
+    pub common_header: RpcRequestHeader,
+
+    /// The length of the key prefix the rule matches on.
+    pub prefix_length: u16,
+
+    /// The length of the name of the extension keys with the prefix are routed to. Zero removes
+    /// the rule for the prefix.
+    pub name_length: u32,
+}
+
+le_fields!(
+    SetRouteRequest,
+    prefix_length, set_prefix_length: u16;
+    name_length, set_name_length: u32;
+);
+
+// Implementation of methods on SetRouteRequest.
+impl SetRouteRequest {
+    /// This method returns a header that can be added to a set_route() RPC request.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`:        Identifier of the tenant whose rule is being set.
+    /// * `prefix_length`: The length of the prefix on the payload.
+    /// * `name_length`:   The length of the extension's name on the payload.
+    /// * `id`:            RPC identifier.
+    /// * `stamp`:         The time-stamp at which the RPC is being sent out.
+    pub fn new(
+        tenant: u32,
+        prefix_length: u16,
+        name_length: u32,
+        id: u64,
+        stamp: u64,
+    ) -> SetRouteRequest {
+        SetRouteRequest {
+            common_header: RpcRequestHeader::new(
+                Service::MasterService,
+                OpCode::SandstormSetRouteRpc,
+                tenant,
+                id,
+                stamp,
+            ),
+            prefix_length: prefix_length.to_le(),
+            name_length: name_length.to_le(),
+        }
+    }
+}
+
+// Implementation of the EndOffset trait for SetRouteRequest. Refer to GetRequest's
+// implementation of this trait to understand what the methods and types mean.
+impl EndOffset for SetRouteRequest {
+    type PreviousHeader = UdpHeader;
+
+    fn offset(&self) -> usize {
+        size_of::<SetRouteRequest>()
+    }
+
+    fn size() -> usize {
+        size_of::<SetRouteRequest>()
+    }
+
+    fn payload_size(&self, hint: usize) -> usize {
+        hint - self.offset()
+    }
+
+    fn check_correct(&self, _prev: &Self::PreviousHeader) -> bool {
+        true
+    }
+}
+
+/// This type represents the header on a response to a set_route() RPC request.
+#[repr(C, packed)]
+pub struct SetRouteResponse {
+    /// A generic RPC header indicating whether the RPC request succeeded
+    /// or failed.
+    pub common_header: RpcResponseHeader,
+}
+
+// Implementation of methods on SetRouteResponse.
+impl SetRouteResponse {
+    /// This method returns a header that can be appended to the response
+    /// to a set_route() RPC request.
+    ///
+    /// # Arguments
+    ///
+    /// * `req_id`:    RPC identifier.
+    /// * `req_stamp`: Time-stamp on the RPC request.
+    /// * `tenant`:    The tenant this response should be sent to.
+    pub fn new(req_id: u64, req_stamp: u64, tenant: u32) -> SetRouteResponse {
+        SetRouteResponse {
+            common_header: RpcResponseHeader::new(
+                req_id,
+                req_stamp,
+                OpCode::SandstormSetRouteRpc,
+                tenant,
+            ),
+        }
+    }
+}
+
+// Implementation of the EndOffset trait for SetRouteResponse. Refer to GetRequest's
+// implementation of this trait to understand what the methods and types mean.
+impl EndOffset for SetRouteResponse {
+    type PreviousHeader = UdpHeader;
+
+    fn offset(&self) -> usize {
+        size_of::<SetRouteResponse>()
+    }
+
+    fn size() -> usize {
+        size_of::<SetRouteResponse>()
+    }
+
+    fn payload_size(&self, hint: usize) -> usize {
+        hint - self.offset()
+    }
+
+    fn check_correct(&self, _prev: &Self::PreviousHeader) -> bool {
+        true
+    }
+}
+
+/// This type represents the header for an audit() RPC request.
+#[repr(C, packed)]
+pub struct AuditRequest {
+    /// The generic RPC header identifying the request as an audit() RPC.
+    pub common_header: RpcRequestHeader,
+
+    /// 0 for the newest entries in the log, and the cursor on the previous response otherwise.
+    pub cursor: u64,
+}
+
+le_fields!(
+    AuditRequest,
+    cursor, set_cursor: u64;
+);
+
+// Implementation of methods on AuditRequest.
+impl AuditRequest {
+    /// This method returns a header that can be added to an audit() RPC request.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`: Identifier of the tenant whose audit log must be paged through.
+    /// * `cursor`: Where the page starts. Refer to AuditLog::page().
+    /// * `id`:     RPC identifier.
+    /// * `stamp`:  The time-stamp at which the RPC is being sent out.
+    pub fn new(tenant: u32, cursor: u64, id: u64, stamp: u64) -> AuditRequest {
+        AuditRequest {
+            common_header: RpcRequestHeader::new(
+                Service::MasterService,
+                OpCode::SandstormAuditRpc,
+                tenant,
+                id,
+                stamp,
+            ),
+            cursor: cursor.to_le(),
+        }
+    }
+}
+
+// Implementation of the EndOffset trait for AuditRequest. Refer to GetRequest's
+// implementation of this trait to understand what the methods and types mean.
+impl EndOffset for AuditRequest {
+    type PreviousHeader = UdpHeader;
+
+    fn offset(&self) -> usize {
+        size_of::<AuditRequest>()
+    }
+
+    fn size() -> usize {
+        size_of::<AuditRequest>()
+    }
+
+    fn payload_size(&self, hint: usize) -> usize {
+        hint - self.offset()
+    }
+
+    fn check_correct(&self, _prev: &Self::PreviousHeader) -> bool {
+        true
+    }
+}
+
+/// This type represents the header on a response to an audit() RPC request. The header is
+/// followed by `num_entries` packed entries, refer to rpc::encode_audit_page().
+#[repr(C, packed)]
+pub struct AuditResponse {
+    /// A generic RPC header indicating whether the RPC request succeeded
+    /// or failed.
+    pub common_header: RpcResponseHeader,
+
+    /// The number of entries on the response.
+    pub num_entries: u32,
+
+    /// The cursor to send on the next request to fetch older entries. 0 if there are none.
+    pub next: u64,
+}
+
+le_fields!(
+    AuditResponse,
+    num_entries, set_num_entries: u32;
+    next, set_next: u64;
+);
+
+// Implementation of methods on AuditResponse.
+impl AuditResponse {
+    /// This method returns a header that can be appended to the response
+    /// to an audit() RPC request.
+    ///
+    /// # Arguments
+    ///
+    /// * `req_id`:    RPC identifier.
+    /// * `req_stamp`: Time-stamp on the RPC request.
+    /// * `tenant`:    The tenant this response should be sent to.
+    pub fn new(req_id: u64, req_stamp: u64, tenant: u32) -> AuditResponse {
+        AuditResponse {
+            common_header: RpcResponseHeader::new(
+                req_id,
+                req_stamp,
+                OpCode::SandstormAuditRpc,
+                tenant,
+            ),
+            num_entries: 0,
+            next: 0,
+        }
+    }
+}
+
+// Implementation of the EndOffset trait for AuditResponse. Refer to GetRequest's
+// implementation of this trait to understand what the methods and types mean.
+impl EndOffset for AuditResponse {
+    type PreviousHeader = UdpHeader;
+
+    fn offset(&self) -> usize {
+        size_of::<AuditResponse>()
+    }
+
+    fn size() -> usize {
+        size_of::<AuditResponse>()
+    }
+
+    fn payload_size(&self, hint: usize) -> usize {
+        hint - self.offset()
+    }
+
+    fn check_correct(&self, _prev: &Self::PreviousHeader) -> bool {
+        true
+    }
+}
+
+/// This type represents the header for a dump() RPC request. The header is
+/// followed by the key the dump continues after.
+#[repr(C, packed)]
+pub struct DumpRequest {
+    /// The generic RPC header identifying the request as a dump() RPC.
+    pub common_header: RpcRequestHeader,
+
+    /// The identifier of the table to be dumped.
+    pub table_id: u64,
+
+    /// The bucket the dump continues in. 0 on the first request.
+    pub bucket: u32,
+
+    /// The length of the key on the payload. Only records in `bucket` with larger keys, and
+    /// records in later buckets, are dumped. 0 on the first request.
+    pub key_length: u16,
+}
+
+le_fields!(
+    DumpRequest,
+    table_id, set_table_id: u64;
+    bucket, set_bucket: u32;
+    key_length, set_key_length: u16;
+);
+
+// Implementation of methods on DumpRequest.
+impl DumpRequest {
+    /// This method returns a header that can be added to a dump() RPC request.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`:      Identifier of the tenant whose table must be dumped.
+    /// * `table_id`:    Identifier of the table to be dumped.
+    /// * `bucket`:      The bucket the dump continues in.
+    /// * `key_length`:  The length of the key the dump continues after.
+    /// * `id`:          RPC identifier.
+    /// * `stamp`:       The time-stamp at which the RPC is being sent out.
+    pub fn new(
+        tenant: u32,
+        table_id: u64,
+        bucket: u32,
+        key_length: u16,
+        id: u64,
+        stamp: u64,
+    ) -> DumpRequest {
+        DumpRequest {
+            common_header: RpcRequestHeader::new(
+                Service::MasterService,
+                OpCode::SandstormDumpRpc,
+                tenant,
+                id,
+                stamp,
+            ),
+            table_id: table_id.to_le(),
+            bucket: bucket.to_le(),
+            key_length: key_length.to_le(),
+        }
+    }
+}
+
+// Implementation of the EndOffset trait for DumpRequest. Refer to GetRequest's
+// implementation of this trait to understand what the methods and types mean.
+impl EndOffset for DumpRequest {
+    type PreviousHeader = UdpHeader;
+
+    fn offset(&self) -> usize {
+        size_of::<DumpRequest>()
+    }
+
+    fn size() -> usize {
+        size_of::<DumpRequest>()
+    }
+
+    fn payload_size(&self, hint: usize) -> usize {
+        hint - self.offset()
+    }
+
+    fn check_correct(&self, _prev: &Self::PreviousHeader) -> bool {
+        true
+    }
+}
+
+/// This type represents the header on a response to a dump() RPC request. The header is
+/// followed by `num_records` records, refer to rpc::append_kv().
+#[repr(C, packed)]
+pub struct DumpResponse {
+    /// A generic RPC header indicating whether the RPC request succeeded
+    /// or failed.
+    pub common_header: RpcResponseHeader,
+
+    /// The number of records on the response. 0 once the whole table has been dumped.
+    pub num_records: u32,
+
+    /// The bucket every record on the response came from. The dump continues in this bucket,
+    /// after the key of the last record.
+    pub bucket: u32,
+}
+
+le_fields!(
+    DumpResponse,
+    num_records, set_num_records: u32;
+    bucket, set_bucket: u32;
+);
+
+// Implementation of methods on DumpResponse.
+impl DumpResponse {
+    /// This method returns a header that can be appended to the response
+    /// to a dump() RPC request.
+    ///
+    /// # Arguments
+    ///
+    /// * `req_id`:    RPC identifier.
+    /// * `req_stamp`: Time-stamp on the RPC request.
+    /// * `tenant`:    The tenant this response should be sent to.
+    pub fn new(req_id: u64, req_stamp: u64, tenant: u32) -> DumpResponse {
+        DumpResponse {
+            common_header: RpcResponseHeader::new(
+                req_id,
+                req_stamp,
+                OpCode::SandstormDumpRpc,
+                tenant,
+            ),
+            num_records: 0,
+            bucket: 0,
+        }
+    }
+}
+
+// Implementation of the EndOffset trait for DumpResponse. Refer to GetRequest's
+// implementation of this trait to understand what the methods and types mean.
+impl EndOffset for DumpResponse {
+    type PreviousHeader = UdpHeader;
+
+    fn offset(&self) -> usize {
+        size_of::<DumpResponse>()
+    }
+
+    fn size() -> usize {
+        size_of::<DumpResponse>()
+    }
+
+    fn payload_size(&self, hint: usize) -> usize {
+        hint - self.offset()
+    }
+
+    fn check_correct(&self, _prev: &Self::PreviousHeader) -> bool {
+        true
+    }
+}
+
+/// This type represents the header for a multiput() RPC request. The header is
+/// followed by `num_records` records, refer to rpc::append_kv().
+#[repr(C, packed)]
+pub struct MultiPutRequest {
+    /// The generic RPC header identifying the request as a multiput() RPC.
+    pub common_header: RpcRequestHeader,
+
+    /// The identifier of the table the records are written to.
+    pub table_id: u64,
+
+    /// The number of records on the payload.
+    pub num_records: u32,
+}
+
+le_fields!(
+    MultiPutRequest,
+    table_id, set_table_id: u64;
+    num_records, set_num_records: u32;
+);
+
+// Implementation of methods on MultiPutRequest.
+impl MultiPutRequest {
+    /// This method returns a header that can be added to a multiput() RPC request.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`:      Identifier of the tenant whose table is written to.
+    /// * `table_id`:    Identifier of the table the records are written to.
+    /// * `num_records`: The number of records on the payload.
+    /// * `id`:          RPC identifier.
+    /// * `stamp`:       The time-stamp at which the RPC is being sent out.
+    pub fn new(
+        tenant: u32,
+        table_id: u64,
+        num_records: u32,
+        id: u64,
+        stamp: u64,
+    ) -> MultiPutRequest {
+        MultiPutRequest {
+            common_header: RpcRequestHeader::new(
+                Service::MasterService,
+                OpCode::SandstormMultiPutRpc,
+                tenant,
+                id,
+                stamp,
+            ),
+            table_id: table_id.to_le(),
+            num_records: num_records.to_le(),
+        }
+    }
+}
+
+// Implementation of the EndOffset trait for MultiPutRequest. Refer to GetRequest's
+// implementation of this trait to understand what the methods and types mean.
+impl EndOffset for MultiPutRequest {
+    type PreviousHeader = UdpHeader;
+
+    fn offset(&self) -> usize {
+        size_of::<MultiPutRequest>()
+    }
+
+    fn size() -> usize {
+        size_of::<MultiPutRequest>()
+    }
+
+    fn payload_size(&self, hint: usize) -> usize {
+        hint - self.offset()
+    }
+
+    fn check_correct(&self, _prev: &Self::PreviousHeader) -> bool {
+        true
+    }
+}
+
+/// This type represents the header on a response to a multiput() RPC request.
+#[repr(C, packed)]
+pub struct MultiPutResponse {
+    /// A generic RPC header indicating whether the RPC request succeeded
+    /// or failed.
+    pub common_header: RpcResponseHeader,
+
+    /// The number of records written. Either every record on the request is written, or none
+    /// of them are.
+    pub num_written: u32,
+}
+
+le_fields!(
+    MultiPutResponse,
+    num_written, set_num_written: u32;
+);
+
+// Implementation of methods on MultiPutResponse.
+impl MultiPutResponse {
+    /// This method returns a header that can be appended to the response
+    /// to a multiput() RPC request.
+    ///
+    /// # Arguments
+    ///
+    /// * `req_id`:    RPC identifier.
+    /// * `req_stamp`: Time-stamp on the RPC request.
+    /// * `tenant`:    The tenant this response should be sent to.
+    pub fn new(req_id: u64, req_stamp: u64, tenant: u32) -> MultiPutResponse {
+        MultiPutResponse {
+            common_header: RpcResponseHeader::new(
+                req_id,
+                req_stamp,
+                OpCode::SandstormMultiPutRpc,
+                tenant,
+            ),
+            num_written: 0,
+        }
+    }
+}
+
+// Implementation of the EndOffset trait for MultiPutResponse. Refer to GetRequest's
+// implementation of this trait to understand what the methods and types mean.
+impl EndOffset for MultiPutResponse {
+    type PreviousHeader = UdpHeader;
+
+    fn offset(&self) -> usize {
+        size_of::<MultiPutResponse>()
+    }
+
+    fn size() -> usize {
+        size_of::<MultiPutResponse>()
+    }
+
+    fn payload_size(&self, hint: usize) -> usize {
+        hint - self.offset()
+    }
+
+    fn check_correct(&self, _prev: &Self::PreviousHeader) -> bool {
+        true
+    }
+}
+
+/// Asks a write_stats() RPC for the totals of each kind of write made to the table. Refer to
+/// rpc::encode_write_totals().
+pub const WRITE_STATS_TOTALS: u8 = 0x00;
+
+/// Asks a write_stats() RPC for the keys of the table with the highest physical write volume.
+/// Refer to rpc::encode_hot_keys().
+pub const WRITE_STATS_HOT_KEYS: u8 = 0x01;
+
+/// Asks a write_stats() RPC for the counters of the table's dedup index. Refer to
+/// rpc::encode_dedup_stats().
+pub const WRITE_STATS_DEDUP: u8 = 0x02;
+
+/// Asks a write_stats() RPC for how the table's index is sized against the objects it holds.
+/// Refer to rpc::encode_sizing().
+pub const WRITE_STATS_SIZING: u8 = 0x03;
+
+/// Asks a write_stats() RPC for the target and eviction counters of a cache-mode table. Refer to
+/// rpc::encode_cache_stats().
+pub const WRITE_STATS_CACHE: u8 = 0x04;
+
+/// Asks a write_stats() RPC for the counters of the server's hot arena. The table only has to
+/// be readable; the counters are the same for every table. Refer to rpc::encode_hot_stats().
+pub const WRITE_STATS_HOT_ARENA: u8 = 0x05;
+
+/// Asks a write_stats() RPC for the epochs of the table, it's tenant and the server's tenant
+/// namespace, so that invalidated requests can be matched up with the truncates and drops that
+/// caused them. Refer to rpc::encode_epochs().
+pub const WRITE_STATS_EPOCHS: u8 = 0x06;
+
+/// This type represents the header for a write_stats() RPC request.
+#[repr(C, packed)]
+pub struct WriteStatsRequest {
+    /// The generic RPC header identifying the request as a write_stats() RPC.
+    pub common_header: RpcRequestHeader,
+
+    /// The identifier of the table whose writes are asked for.
+    pub table_id: u64,
+
+    /// What is asked for. Either WRITE_STATS_TOTALS, WRITE_STATS_HOT_KEYS, WRITE_STATS_DEDUP,
+    /// WRITE_STATS_SIZING, WRITE_STATS_CACHE, WRITE_STATS_HOT_ARENA or WRITE_STATS_EPOCHS.
+    pub query: u8,
+}
+
+le_fields!(
+    WriteStatsRequest,
+    table_id, set_table_id: u64;
+);
+
+// Implementation of methods on WriteStatsRequest.
+impl WriteStatsRequest {
+    /// This method returns a header that can be added to a write_stats() RPC request.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`:   Identifier of the tenant owning the table.
+    /// * `table_id`: Identifier of the table whose writes are asked for.
+    /// * `query`:    What is asked for. Refer to WRITE_STATS_TOTALS.
+    /// * `id`:       RPC identifier.
+    /// * `stamp`:    The time-stamp at which the RPC is being sent out.
+    pub fn new(tenant: u32, table_id: u64, query: u8, id: u64, stamp: u64) -> WriteStatsRequest {
+        WriteStatsRequest {
+            common_header: RpcRequestHeader::new(
+                Service::MasterService,
+                OpCode::SandstormWriteStatsRpc,
+                tenant,
+                id,
+                stamp,
+            ),
+            table_id: table_id.to_le(),
+            query: query,
+        }
+    }
+}
+
+// Implementation of the EndOffset trait for WriteStatsRequest. Refer to GetRequest's
+// implementation of this trait to understand what the methods and types mean.
+impl EndOffset for WriteStatsRequest {
+    type PreviousHeader = UdpHeader;
+
+    fn offset(&self) -> usize {
+        size_of::<WriteStatsRequest>()
+    }
+
+    fn size() -> usize {
+        size_of::<WriteStatsRequest>()
+    }
+
+    fn payload_size(&self, hint: usize) -> usize {
+        hint - self.offset()
+    }
+
+    fn check_correct(&self, _prev: &Self::PreviousHeader) -> bool {
+        true
+    }
+}
+
+/// This type represents the header on a response to a write_stats() RPC request. The header is
+/// followed by `num_entries` packed entries, refer to rpc::encode_write_totals() and
+/// rpc::encode_hot_keys().
+#[repr(C, packed)]
+pub struct WriteStatsResponse {
+    /// A generic RPC header indicating whether the RPC request succeeded
+    /// or failed.
+    pub common_header: RpcResponseHeader,
+
+    /// The number of entries on the response.
+    pub num_entries: u32,
+}
+
+le_fields!(
+    WriteStatsResponse,
+    num_entries, set_num_entries: u32;
+);
+
+// Implementation of methods on WriteStatsResponse.
+impl WriteStatsResponse {
+    /// This method returns a header that can be appended to the response
+    /// to a write_stats() RPC request.
+    ///
+    /// # Arguments
+    ///
+    /// * `req_id`:    RPC identifier.
+    /// * `req_stamp`: Time-stamp on the RPC request.
+    /// * `tenant`:    The tenant this response should be sent to.
+    pub fn new(req_id: u64, req_stamp: u64, tenant: u32) -> WriteStatsResponse {
+        WriteStatsResponse {
+            common_header: RpcResponseHeader::new(
+                req_id,
+                req_stamp,
+                OpCode::SandstormWriteStatsRpc,
+                tenant,
+            ),
+            num_entries: 0,
+        }
+    }
+}
+
+// Implementation of the EndOffset trait for WriteStatsResponse. Refer to GetRequest's
+// implementation of this trait to understand what the methods and types mean.
+impl EndOffset for WriteStatsResponse {
+    type PreviousHeader = UdpHeader;
+
+    fn offset(&self) -> usize {
+        size_of::<WriteStatsResponse>()
+    }
+
+    fn size() -> usize {
+        size_of::<WriteStatsResponse>()
+    }
+
+    fn payload_size(&self, hint: usize) -> usize {
+        hint - self.offset()
+    }
+
+    fn check_correct(&self, _prev: &Self::PreviousHeader) -> bool {
+        true
+    }
+}
+
+/// This type represents the header for a core_stats() RPC request. The request can only be
+/// issued as tenant 0, like run_stats().
+#[repr(C, packed)]
+pub struct CoreStatsRequest {
+    /// The generic RPC header identifying the request as a core_stats() RPC.
+    pub common_header: RpcRequestHeader,
+}
+
+// Implementation of methods on CoreStatsRequest.
+impl CoreStatsRequest {
+    /// This method returns a header that can be added to a core_stats() RPC request.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`: Identifier of the tenant issuing the request. Must be 0.
+    /// * `id`:     RPC identifier.
+    /// * `stamp`:  The time-stamp at which the RPC is being sent out.
+    pub fn new(tenant: u32, id: u64, stamp: u64) -> CoreStatsRequest {
+        CoreStatsRequest {
+            common_header: RpcRequestHeader::new(
+                Service::MasterService,
+                OpCode::SandstormCoreStatsRpc,
+                tenant,
+                id,
+                stamp,
+            ),
+        }
+    }
+}
+
+// Implementation of the EndOffset trait for CoreStatsRequest. Refer to GetRequest's
+// implementation of this trait to understand what the methods and types mean.
+impl EndOffset for CoreStatsRequest {
+    type PreviousHeader = UdpHeader;
+
+    fn offset(&self) -> usize {
+        size_of::<CoreStatsRequest>()
+    }
+
+    fn size() -> usize {
+        size_of::<CoreStatsRequest>()
+    }
+
+    fn payload_size(&self, hint: usize) -> usize {
+        hint - self.offset()
+    }
+
+    fn check_correct(&self, _prev: &Self::PreviousHeader) -> bool {
+        true
+    }
+}
+
+/// This type represents the header on a response to a core_stats() RPC request. The header is
+/// followed by `num_cores` packed entries, refer to rpc::encode_core_stats().
+#[repr(C, packed)]
+pub struct CoreStatsResponse {
+    /// A generic RPC header indicating whether the RPC request succeeded
+    /// or failed.
+    pub common_header: RpcResponseHeader,
+
+    /// The number of cores on the response.
+    pub num_cores: u32,
+
+    /// The CPU time the server process has used in user mode, in microseconds.
+    pub user_us: u64,
+
+    /// The CPU time the server process has used in system mode, in microseconds.
+    pub system_us: u64,
+}
+
+le_fields!(
+    CoreStatsResponse,
+    num_cores, set_num_cores: u32;
+    user_us, set_user_us: u64;
+    system_us, set_system_us: u64;
+);
+
+// Implementation of methods on CoreStatsResponse.
+impl CoreStatsResponse {
+    /// This method returns a header that can be appended to the response
+    /// to a core_stats() RPC request.
+    ///
+    /// # Arguments
+    ///
+    /// * `req_id`:    RPC identifier.
+    /// * `req_stamp`: Time-stamp on the RPC request.
+    /// * `tenant`:    The tenant this response should be sent to.
+    pub fn new(req_id: u64, req_stamp: u64, tenant: u32) -> CoreStatsResponse {
+        CoreStatsResponse {
+            common_header: RpcResponseHeader::new(
+                req_id,
+                req_stamp,
+                OpCode::SandstormCoreStatsRpc,
+                tenant,
+            ),
+            num_cores: 0,
+            user_us: 0,
+            system_us: 0,
+        }
+    }
+}
+
+// Implementation of the EndOffset trait for CoreStatsResponse. Refer to GetRequest's
+// implementation of this trait to understand what the methods and types mean.
+impl EndOffset for CoreStatsResponse {
+    type PreviousHeader = UdpHeader;
+
+    fn offset(&self) -> usize {
+        size_of::<CoreStatsResponse>()
+    }
+
+    fn size() -> usize {
+        size_of::<CoreStatsResponse>()
+    }
+
+    fn payload_size(&self, hint: usize) -> usize {
+        hint - self.offset()
+    }
+
+    fn check_correct(&self, _prev: &Self::PreviousHeader) -> bool {
+        true
+    }
+}
+
+/// Asks a latency_stats() RPC for the percentiles of every opcode the server has responded to.
+/// Refer to rpc::encode_latency_summaries().
+pub const LATENCY_STATS_OPCODES: u8 = 0x00;
+
+/// Asks a latency_stats() RPC for the percentiles of every extension the server's cores track.
+/// Refer to rpc::encode_ext_latencies().
+pub const LATENCY_STATS_EXTENSIONS: u8 = 0x01;
+
+/// Asks a latency_stats() RPC for the histogram of a single opcode, or of a single extension if
+/// one is named on the request. Refer to rpc::encode_latency_histogram().
+pub const LATENCY_STATS_HISTOGRAM: u8 = 0x02;
+
+/// This type represents the header for a latency_stats() RPC request. The request can only be
+/// issued as tenant 0, like core_stats(). A request for the histogram of an extension is followed
+/// by the extension's name.
+#[repr(C, packed)]
+pub struct LatencyStatsRequest {
+    /// The generic RPC header identifying the request as a latency_stats() RPC.
+    pub common_header: RpcRequestHeader,
+
+    /// What is asked for. Either LATENCY_STATS_OPCODES, LATENCY_STATS_EXTENSIONS or
+    /// LATENCY_STATS_HISTOGRAM.
+    pub query: u8,
+
+    /// The opcode whose histogram is asked for. Ignored by other queries, and if an extension
+    /// is named.
+    pub opcode: u8,
+}
+
+// Implementation of methods on LatencyStatsRequest.
+impl LatencyStatsRequest {
+    /// This method returns a header that can be added to a latency_stats() RPC request.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`: Identifier of the tenant issuing the request. Must be 0.
+    /// * `query`:  What is asked for. Refer to LATENCY_STATS_OPCODES.
+    /// * `opcode`: The opcode whose histogram is asked for.
+    /// * `id`:     RPC identifier.
+    /// * `stamp`:  The time-stamp at which the RPC is being sent out.
+    pub fn new(tenant: u32, query: u8, opcode: u8, id: u64, stamp: u64) -> LatencyStatsRequest {
+        LatencyStatsRequest {
+            common_header: RpcRequestHeader::new(
+                Service::MasterService,
+                OpCode::SandstormLatencyStatsRpc,
+                tenant,
+                id,
+                stamp,
+            ),
+            query: query,
+            opcode: opcode,
+        }
+    }
+}
+
+// Implementation of the EndOffset trait for LatencyStatsRequest. Refer to GetRequest's
+// implementation of this trait to understand what the methods and types mean.
+impl EndOffset for LatencyStatsRequest {
+    type PreviousHeader = UdpHeader;
+
+    fn offset(&self) -> usize {
+        size_of::<LatencyStatsRequest>()
+    }
+
+    fn size() -> usize {
+        size_of::<LatencyStatsRequest>()
+    }
+
+    fn payload_size(&self, hint: usize) -> usize {
+        hint - self.offset()
+    }
+
+    fn check_correct(&self, _prev: &Self::PreviousHeader) -> bool {
+        true
+    }
+}
+
+/// This type represents the header on a response to a latency_stats() RPC request. The header
+/// is followed by `num_entries` packed entries, refer to rpc::encode_latency_summaries(),
+/// rpc::encode_ext_latencies() and rpc::encode_latency_histogram().
+#[repr(C, packed)]
+pub struct LatencyStatsResponse {
+    /// A generic RPC header indicating whether the RPC request succeeded
+    /// or failed.
+    pub common_header: RpcResponseHeader,
+
+    /// The number of entries on the response.
+    pub num_entries: u32,
+
+    /// The rate at which the server's cycle counter ticks. Histograms are in cycles, and are
+    /// converted to nanoseconds at this rate.
+    pub cycles_per_second: u64,
+}
+
+le_fields!(
+    LatencyStatsResponse,
+    num_entries, set_num_entries: u32;
+    cycles_per_second, set_cycles_per_second: u64;
+);
+
+// Implementation of methods on LatencyStatsResponse.
+impl LatencyStatsResponse {
+    /// This method returns a header that can be appended to the response
+    /// to a latency_stats() RPC request.
+    ///
+    /// # Arguments
+    ///
+    /// * `req_id`:    RPC identifier.
+    /// * `req_stamp`: Time-stamp on the RPC request.
+    /// * `tenant`:    The tenant this response should be sent to.
+    pub fn new(req_id: u64, req_stamp: u64, tenant: u32) -> LatencyStatsResponse {
+        LatencyStatsResponse {
+            common_header: RpcResponseHeader::new(
+                req_id,
+                req_stamp,
+                OpCode::SandstormLatencyStatsRpc,
+                tenant,
+            ),
+            num_entries: 0,
+            cycles_per_second: 0,
+        }
+    }
+}
+
+// Implementation of the EndOffset trait for LatencyStatsResponse. Refer to GetRequest's
+// implementation of this trait to understand what the methods and types mean.
+impl EndOffset for LatencyStatsResponse {
+    type PreviousHeader = UdpHeader;
+
+    fn offset(&self) -> usize {
+        size_of::<LatencyStatsResponse>()
+    }
+
+    fn size() -> usize {
+        size_of::<LatencyStatsResponse>()
+    }
+
+    fn payload_size(&self, hint: usize) -> usize {
+        hint - self.offset()
+    }
+
+    fn check_correct(&self, _prev: &Self::PreviousHeader) -> bool {
+        true
+    }
+}
+
+/// Asks a quarantine() RPC to only report the target tenant's quarantine.
+pub const QUARANTINE_QUERY: u8 = 0x00;
+
+/// Asks a quarantine() RPC to quarantine the target tenant, whether or not it already is.
+pub const QUARANTINE_ENTER: u8 = 0x01;
+
+/// Asks a quarantine() RPC to release the target tenant, and forget the quarantines it was put
+/// in before.
+pub const QUARANTINE_RELEASE: u8 = 0x02;
+
+/// This type represents the header for a quarantine() RPC request. The request can only be
+/// issued as tenant 0, which can never be quarantined itself.
+#[repr(C, packed)]
+pub struct QuarantineRequest {
+    /// The generic RPC header identifying the request as a quarantine() RPC.
+    pub common_header: RpcRequestHeader,
+
+    /// What is asked for. Either QUARANTINE_QUERY, QUARANTINE_ENTER or QUARANTINE_RELEASE.
+    pub action: u8,
+
+    /// The tenant to quarantine, release, or report on.
+    pub target: u32,
+
+    /// How long to quarantine the tenant for in milliseconds, on QUARANTINE_ENTER. 0 picks the
+    /// duration the server would have, had the tenant's requests kept failing.
+    pub duration_ms: u64,
+}
+
+le_fields!(
+    QuarantineRequest,
+    target, set_target: u32;
+    duration_ms, set_duration_ms: u64;
+);
+
+// Implementation of methods on QuarantineRequest.
+impl QuarantineRequest {
+    /// This method returns a header that can be added to a quarantine() RPC request.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`:      Identifier of the tenant issuing the request. Must be 0.
+    /// * `action`:      What is asked for. Refer to QUARANTINE_QUERY.
+    /// * `target`:      The tenant to quarantine, release, or report on.
+    /// * `duration_ms`: How long to quarantine the tenant for, on QUARANTINE_ENTER.
+    /// * `id`:          RPC identifier.
+    /// * `stamp`:       The time-stamp at which the RPC is being sent out.
+    pub fn new(
+        tenant: u32,
+        action: u8,
+        target: u32,
+        duration_ms: u64,
+        id: u64,
+        stamp: u64,
+    ) -> QuarantineRequest {
+        QuarantineRequest {
+            common_header: RpcRequestHeader::new(
+                Service::MasterService,
+                OpCode::SandstormQuarantineRpc,
+                tenant,
+                id,
+                stamp,
+            ),
+            action: action,
+            target: target.to_le(),
+            duration_ms: duration_ms.to_le(),
+        }
+    }
+}
+
+// Implementation of the EndOffset trait for QuarantineRequest. Refer to GetRequest's
+// implementation of this trait to understand what the methods and types mean.
+impl EndOffset for QuarantineRequest {
+    type PreviousHeader = UdpHeader;
+
+    fn offset(&self) -> usize {
+        size_of::<QuarantineRequest>()
+    }
+
+    fn size() -> usize {
+        size_of::<QuarantineRequest>()
+    }
+
+    fn payload_size(&self, hint: usize) -> usize {
+        hint - self.offset()
+    }
+
+    fn check_correct(&self, _prev: &Self::PreviousHeader) -> bool {
+        true
+    }
+}
+
+/// This type represents the header on a response to a quarantine() RPC request. It carries the
+/// target tenant's quarantine once the action was applied, and the quarantines counted across
+/// the server.
+#[repr(C, packed)]
+pub struct QuarantineResponse {
+    /// A generic RPC header indicating whether the RPC request succeeded
+    /// or failed.
+    pub common_header: RpcResponseHeader,
+
+    /// The number of times the target tenant was quarantined since it was last released by an
+    /// operator. Each quarantine lasts twice as long as the one before it, upto a cap.
+    pub strikes: u32,
+
+    /// The milliseconds left on the target tenant's quarantine. 0 if it isn't quarantined.
+    pub remaining_ms: u64,
+
+    /// The number of times any tenant was quarantined since the server started.
+    pub quarantines: u64,
+
+    /// The number of requests refused with StatusTenantQuarantined since the server started.
+    pub rejected: u64,
+}
+
+le_fields!(
+    QuarantineResponse,
+    strikes, set_strikes: u32;
+    remaining_ms, set_remaining_ms: u64;
+    quarantines, set_quarantines: u64;
+    rejected, set_rejected: u64;
+);
+
+// Implementation of methods on QuarantineResponse.
+impl QuarantineResponse {
+    /// This method returns a header that can be appended to the response
+    /// to a quarantine() RPC request.
+    ///
+    /// # Arguments
+    ///
+    /// * `req_id`:    RPC identifier.
+    /// * `req_stamp`: Time-stamp on the RPC request.
+    /// * `tenant`:    The tenant this response should be sent to.
+    pub fn new(req_id: u64, req_stamp: u64, tenant: u32) -> QuarantineResponse {
+        QuarantineResponse {
+            common_header: RpcResponseHeader::new(
+                req_id,
+                req_stamp,
+                OpCode::SandstormQuarantineRpc,
+                tenant,
+            ),
+            strikes: 0,
+            remaining_ms: 0,
+            quarantines: 0,
+            rejected: 0,
+        }
+    }
+}
+
+// Implementation of the EndOffset trait for QuarantineResponse. Refer to GetRequest's
+// implementation of this trait to understand what the methods and types mean.
+impl EndOffset for QuarantineResponse {
+    type PreviousHeader = UdpHeader;
+
+    fn offset(&self) -> usize {
+        size_of::<QuarantineResponse>()
+    }
+
+    fn size() -> usize {
+        size_of::<QuarantineResponse>()
+    }
+
+    fn payload_size(&self, hint: usize) -> usize {
+        hint - self.offset()
+    }
+
+    fn check_correct(&self, _prev: &Self::PreviousHeader) -> bool {
+        true
+    }
+}
+
+/// Marks a create_table() request as creating an ordinary table, that is never evicted from.
+pub const CACHE_EVICT_NONE: u8 = 0x00;
+
+/// Marks a create_table() request as creating a cache-mode table that evicts the least recently
+/// written objects. Refer to evict::EvictPolicy.
+pub const CACHE_EVICT_WRITTEN: u8 = 0x01;
+
+/// Marks a create_table() request as creating a cache-mode table that evicts the least recently
+/// read or written objects. Refer to evict::EvictPolicy.
+pub const CACHE_EVICT_READ: u8 = 0x02;
+
+/// This type represents the header for a create_table() RPC request.
+#[repr(C, packed)]
+pub struct CreateTableRequest {
+    /// The generic RPC header identifying the request as a create_table() RPC.
+    pub common_header: RpcRequestHeader,
+
+    /// The table to create. If the tenant already owns a table under this identifier, it is
+    /// pre-sized instead.
+    pub table_id: u64,
+
+    /// The number of objects the table is expected to hold. 0 creates the table without
+    /// pre-sizing it.
+    pub records: u64,
+
+    /// The most bytes of objects a cache-mode table holds. 0 does not bound the bytes.
+    pub max_bytes: u64,
+
+    /// The most objects a cache-mode table holds. 0 does not bound the number of objects.
+    pub max_objects: u64,
+
+    /// How the table evicts objects. CACHE_EVICT_NONE creates an ordinary table, and requires
+    /// both bounds to be 0; any other policy requires atleast one of them.
+    pub evict: u8,
+}
+
+le_fields!(
+    CreateTableRequest,
+    table_id, set_table_id: u64;
+    records, set_records: u64;
+    max_bytes, set_max_bytes: u64;
+    max_objects, set_max_objects: u64;
+);
+
+// Implementation of methods on CreateTableRequest.
+impl CreateTableRequest {
+    /// This method returns a header that can be added to a create_table() RPC request. The
+    /// table is an ordinary one, unless a target is set on the header.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`:   Identifier of the tenant the table is created for.
+    /// * `table_id`: Identifier of the table to create.
+    /// * `records`:  The number of objects the table is expected to hold.
+    /// * `id`:       RPC identifier.
+    /// * `stamp`:    The time-stamp at which the RPC is being sent out.
+    pub fn new(
+        tenant: u32,
+        table_id: u64,
+        records: u64,
+        id: u64,
+        stamp: u64,
+    ) -> CreateTableRequest {
+        CreateTableRequest {
+            common_header: RpcRequestHeader::new(
+                Service::MasterService,
+                OpCode::SandstormCreateTableRpc,
+                tenant,
+                id,
+                stamp,
+            ),
+            table_id: table_id.to_le(),
+            records: records.to_le(),
+            max_bytes: 0,
+            max_objects: 0,
+            evict: CACHE_EVICT_NONE,
+        }
+    }
+}
+
+// Implementation of the EndOffset trait for CreateTableRequest. Refer to GetRequest's
+// implementation of this trait to understand what the methods and types mean.
+impl EndOffset for CreateTableRequest {
+    type PreviousHeader = UdpHeader;
+
+    fn offset(&self) -> usize {
+        size_of::<CreateTableRequest>()
+    }
+
+    fn size() -> usize {
+        size_of::<CreateTableRequest>()
+    }
+
+    fn payload_size(&self, hint: usize) -> usize {
+        hint - self.offset()
+    }
+
+    fn check_correct(&self, _prev: &Self::PreviousHeader) -> bool {
+        true
+    }
+}
+
+/// This type represents the header on a response to a create_table() RPC request.
+#[repr(C, packed)]
+pub struct CreateTableResponse {
+    /// A generic RPC header indicating whether the RPC request succeeded
+    /// or failed.
+    pub common_header: RpcResponseHeader,
+
+    /// The number of slots the table was pre-sized to. 0 if it was not pre-sized.
+    pub slots: u64,
+}
+
+le_fields!(
+    CreateTableResponse,
+    slots, set_slots: u64;
+);
+
+// Implementation of methods on CreateTableResponse.
+impl CreateTableResponse {
+    /// This method returns a header that can be appended to the response
+    /// to a create_table() RPC request.
+    ///
+    /// # Arguments
+    ///
+    /// * `req_id`:    RPC identifier.
+    /// * `req_stamp`: Time-stamp on the RPC request.
+    /// * `tenant`:    The tenant this response should be sent to.
+    pub fn new(req_id: u64, req_stamp: u64, tenant: u32) -> CreateTableResponse {
+        CreateTableResponse {
+            common_header: RpcResponseHeader::new(
+                req_id,
+                req_stamp,
+                OpCode::SandstormCreateTableRpc,
+                tenant,
+            ),
+            slots: 0,
+        }
+    }
+}
+
+// Implementation of the EndOffset trait for CreateTableResponse. Refer to GetRequest's
+// implementation of this trait to understand what the methods and types mean.
+impl EndOffset for CreateTableResponse {
+    type PreviousHeader = UdpHeader;
+
+    fn offset(&self) -> usize {
+        size_of::<CreateTableResponse>()
+    }
+
+    fn size() -> usize {
+        size_of::<CreateTableResponse>()
+    }
+
+    fn payload_size(&self, hint: usize) -> usize {
+        hint - self.offset()
+    }
+
+    fn check_correct(&self, _prev: &Self::PreviousHeader) -> bool {
+        true
+    }
+}
+
+/// This type represents the header for a fetch_result() RPC request.
+#[repr(C, packed)]
+pub struct FetchResultRequest {
+    /// The generic RPC header identifying the request as a fetch_result() RPC.
+    pub common_header: RpcRequestHeader,
+
+    /// The id of the invoke() request whose result to fetch.
+    pub request_id: u64,
+}
+
+le_fields!(
+    FetchResultRequest,
+    request_id, set_request_id: u64;
+);
+
+// Implementation of methods on FetchResultRequest.
+impl FetchResultRequest {
+    /// This method returns a header that can be added to a fetch_result() RPC request.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`:     Identifier of the tenant that issued the invoke() request.
+    /// * `request_id`: The id of the invoke() request whose result to fetch.
+    /// * `id`:         RPC identifier.
+    /// * `stamp`:      The time-stamp at which the RPC is being sent out.
+    pub fn new(tenant: u32, request_id: u64, id: u64, stamp: u64) -> FetchResultRequest {
+        FetchResultRequest {
+            common_header: RpcRequestHeader::new(
+                Service::MasterService,
+                OpCode::SandstormFetchResultRpc,
+                tenant,
+                id,
+                stamp,
+            ),
+            request_id: request_id.to_le(),
+        }
+    }
+}
+
+// Implementation of the EndOffset trait for FetchResultRequest. Refer to GetRequest's
+// implementation of this trait to understand what the methods and types mean.
+impl EndOffset for FetchResultRequest {
+    type PreviousHeader = UdpHeader;
+
+    fn offset(&self) -> usize {
+        size_of::<FetchResultRequest>()
+    }
+
+    fn size() -> usize {
+        size_of::<FetchResultRequest>()
+    }
+
+    fn payload_size(&self, hint: usize) -> usize {
+        hint - self.offset()
+    }
+
+    fn check_correct(&self, _prev: &Self::PreviousHeader) -> bool {
+        true
+    }
+}
+
+/// This type represents the header on a response to a fetch_result() RPC request. The payload
+/// is the payload of the invoke() response that was stored.
+#[repr(C, packed)]
+pub struct FetchResultResponse {
+    /// A generic RPC header indicating whether the RPC request succeeded
+    /// or failed. StatusObjectDoesNotExist if no result is stored under the
+    /// request id, or it expired.
+    pub common_header: RpcResponseHeader,
+
+    /// The status the invocation completed with. Only meaningful if the
+    /// fetch_result() succeeded.
+    pub result: RpcStatus,
+}
+
+// Implementation of methods on FetchResultResponse.
+impl FetchResultResponse {
+    /// This method returns a header that can be appended to the response
+    /// to a fetch_result() RPC request.
+    ///
+    /// # Arguments
+    ///
+    /// * `req_id`:    RPC identifier.
+    /// * `req_stamp`: Time-stamp on the RPC request.
+    /// * `tenant`:    The tenant this response should be sent to.
+    pub fn new(req_id: u64, req_stamp: u64, tenant: u32) -> FetchResultResponse {
+        FetchResultResponse {
+            common_header: RpcResponseHeader::new(
+                req_id,
+                req_stamp,
+                OpCode::SandstormFetchResultRpc,
+                tenant,
+            ),
+            result: RpcStatus::StatusOk,
+        }
+    }
+}
+
+// Implementation of the EndOffset trait for FetchResultResponse. Refer to GetRequest's
+// implementation of this trait to understand what the methods and types mean.
+impl EndOffset for FetchResultResponse {
+    type PreviousHeader = UdpHeader;
+
+    fn offset(&self) -> usize {
+        size_of::<FetchResultResponse>()
+    }
+
+    fn size() -> usize {
+        size_of::<FetchResultResponse>()
+    }
+
+    fn payload_size(&self, hint: usize) -> usize {
+        hint - self.offset()
+    }
+
+    fn check_correct(&self, _prev: &Self::PreviousHeader) -> bool {
+        true
+    }
+}
+
+/// This type represents the header for a delete() RPC request. The key to delete is the payload.
+#[repr(C, packed)]
+pub struct DeleteRequest {
+    /// The generic RPC header identifying the request as a delete() RPC.
+    pub common_header: RpcRequestHeader,
+
+    /// The data table to remove the key-value pair from.
+    pub table_id: u64,
+
+    /// The length of the key within the RPC's payload.
+    pub key_length: u16,
+}
+
+le_fields!(
+    DeleteRequest,
+    table_id, set_table_id: u64;
+    key_length, set_key_length: u16;
+);
+
+// Implementation of methods on DeleteRequest.
+impl DeleteRequest {
+    /// This method returns an RPC header that can be added to a delete() request.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`:     Identifier of the tenant issuing the request.
+    /// * `table_id`:   Identifier of the table to remove the key-value pair from.
+    /// * `key_length`: The length of the key inside the RPC request's payload.
+    /// * `id`:         RPC identifier.
+    /// * `stamp`:      The time-stamp at which the RPC is being sent out.
+    pub fn new(tenant: u32, table_id: u64, key_length: u16, id: u64, stamp: u64) -> DeleteRequest {
+        DeleteRequest {
+            common_header: RpcRequestHeader::new(
+                Service::MasterService,
+                OpCode::SandstormDeleteRpc,
+                tenant,
+                id,
+                stamp,
+            ),
+            table_id: table_id.to_le(),
+            key_length: key_length.to_le(),
+        }
+    }
+}
+
+// Implementation of the EndOffset trait for DeleteRequest. Refer to GetRequest's
+// implementation of this trait to understand what the methods and types mean.
+impl EndOffset for DeleteRequest {
+    type PreviousHeader = UdpHeader;
+
+    fn offset(&self) -> usize {
+        size_of::<DeleteRequest>()
+    }
+
+    fn size() -> usize {
+        size_of::<DeleteRequest>()
+    }
+
+    fn payload_size(&self, hint: usize) -> usize {
+        hint - self.offset()
+    }
+
+    fn check_correct(&self, _prev: &Self::PreviousHeader) -> bool {
+        true
+    }
+}
+
+/// This type represents the header on a response to a delete() RPC request.
+#[repr(C, packed)]
+pub struct DeleteResponse {
+    /// A generic RPC header indicating whether the RPC request succeeded
+    /// or failed. StatusObjectDoesNotExist if there was no such key to
+    /// delete.
+    pub common_header: RpcResponseHeader,
+}
+
+// Implementation of methods on DeleteResponse.
+impl DeleteResponse {
+    /// This method returns a header that can be appended to the response
+    /// to a delete() RPC request.
+    ///
+    /// # Arguments
+    ///
+    /// * `req_id`:    RPC identifier.
+    /// * `req_stamp`: Time-stamp on the RPC request.
+    /// * `tenant`:    The tenant this response should be sent to.
+    pub fn new(req_id: u64, req_stamp: u64, tenant: u32) -> DeleteResponse {
+        DeleteResponse {
+            common_header: RpcResponseHeader::new(
+                req_id,
+                req_stamp,
+                OpCode::SandstormDeleteRpc,
+                tenant,
+            ),
+        }
+    }
+}
+
+// Implementation of the EndOffset trait for DeleteResponse. Refer to GetRequest's
+// implementation of this trait to understand what the methods and types mean.
+impl EndOffset for DeleteResponse {
+    type PreviousHeader = UdpHeader;
+
+    fn offset(&self) -> usize {
+        size_of::<DeleteResponse>()
+    }
+
+    fn size() -> usize {
+        size_of::<DeleteResponse>()
+    }
+
+    fn payload_size(&self, hint: usize) -> usize {
+        hint - self.offset()
+    }
+
+    fn check_correct(&self, _prev: &Self::PreviousHeader) -> bool {
+        true
+    }
+}
+
+/// This type represents the header for a scan() RPC request. The header is followed by the key
+/// the scan starts from.
+#[repr(C, packed)]
+pub struct ScanRequest {
+    /// The generic RPC header identifying the request as a scan() RPC.
+    pub common_header: RpcRequestHeader,
+
+    /// The identifier of the table to be scanned.
+    pub table_id: u64,
+
+    /// The most records on the response. 0 for as many as fit on it.
+    pub max_records: u32,
+
+    /// The length of the key on the payload. Only records with keys atleast as large as it,
+    /// or larger than it if SCAN_FLAG_AFTER_START is set, are returned. 0 to start the scan
+    /// at the first key in the table.
+    pub key_length: u16,
+
+    /// Flags modifying where the scan starts. See SCAN_FLAG_AFTER_START.
+    pub flags: u8,
+}
+
+le_fields!(
+    ScanRequest,
+    table_id, set_table_id: u64;
+    max_records, set_max_records: u32;
+    key_length, set_key_length: u16;
+);
+
+/// Flag on a scan() request asking for records with keys larger than the key on the request,
+/// instead of atleast as large. Set when resuming a scan off the continuation token on the
+/// previous response, so that the last record on it isn't returned again.
+pub const SCAN_FLAG_AFTER_START: u8 = 0x01;
+
+// Implementation of methods on ScanRequest.
+impl ScanRequest {
+    /// This method returns a header that can be added to a scan() RPC request. The scan
+    /// includes the key it starts from; refer to with_flags().
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`:      Identifier of the tenant whose table must be scanned.
+    /// * `table_id`:    Identifier of the table to be scanned.
+    /// * `key_length`:  The length of the key the scan starts from.
+    /// * `max_records`: The most records on the response. 0 for as many as fit on it.
+    /// * `id`:          RPC identifier.
+    /// * `stamp`:       The time-stamp at which the RPC is being sent out.
+    pub fn new(
+        tenant: u32,
+        table_id: u64,
+        key_length: u16,
+        max_records: u32,
+        id: u64,
+        stamp: u64,
+    ) -> ScanRequest {
+        ScanRequest {
+            common_header: RpcRequestHeader::new(
+                Service::MasterService,
+                OpCode::SandstormScanRpc,
+                tenant,
+                id,
+                stamp,
+            ),
+            table_id: table_id.to_le(),
+            max_records: max_records.to_le(),
+            key_length: key_length.to_le(),
+            flags: 0,
+        }
+    }
+
+    /// Sets the flags on the request.
+    ///
+    /// # Arguments
+    ///
+    /// * `flags`: SCAN_FLAG_AFTER_START, or 0 to include the key the scan starts from.
+    pub fn with_flags(mut self, flags: u8) -> ScanRequest {
+        self.flags = flags;
+        self
+    }
+}
+
+// Implementation of the EndOffset trait for ScanRequest. Refer to GetRequest's
+// implementation of this trait to understand what the methods and types mean.
+impl EndOffset for ScanRequest {
+    type PreviousHeader = UdpHeader;
+
+    fn offset(&self) -> usize {
+        size_of::<ScanRequest>()
+    }
+
+    fn size() -> usize {
+        size_of::<ScanRequest>()
+    }
+
+    fn payload_size(&self, hint: usize) -> usize {
+        hint - self.offset()
+    }
+
+    fn check_correct(&self, _prev: &Self::PreviousHeader) -> bool {
+        true
+    }
+}
+
+/// This type represents the header on a response to a scan() RPC request. The header is
+/// followed by `num_records` records in key order, refer to rpc::append_kv(), and then by the
+/// continuation token.
+#[repr(C, packed)]
+pub struct ScanResponse {
+    /// A generic RPC header indicating whether the RPC request succeeded
+    /// or failed.
+    pub common_header: RpcResponseHeader,
+
+    /// The number of records on the response.
+    pub num_records: u32,
+
+    /// The length of the continuation token after the records; the key of the last record on
+    /// the response. The scan is resumed by sending it back with SCAN_FLAG_AFTER_START set. 0
+    /// once the scan has reached the end of the table.
+    pub token_length: u16,
+
+    /// The number of records the scan passed over because each was too large to fit on a
+    /// response by itself. The continuation token is past them, so the scan never gets stuck
+    /// on one; they can be read with get().
+    pub skipped: u32,
+}
+
+le_fields!(
+    ScanResponse,
+    num_records, set_num_records: u32;
+    token_length, set_token_length: u16;
+    skipped, set_skipped: u32;
+);
+
+// Implementation of methods on ScanResponse.
+impl ScanResponse {
+    /// This method returns a header that can be appended to the response
+    /// to a scan() RPC request.
+    ///
+    /// # Arguments
+    ///
+    /// * `req_id`:    RPC identifier.
+    /// * `req_stamp`: Time-stamp on the RPC request.
+    /// * `tenant`:    The tenant this response should be sent to.
+    pub fn new(req_id: u64, req_stamp: u64, tenant: u32) -> ScanResponse {
+        ScanResponse {
+            common_header: RpcResponseHeader::new(
+                req_id,
+                req_stamp,
+                OpCode::SandstormScanRpc,
+                tenant,
+            ),
+            num_records: 0,
+            token_length: 0,
+            skipped: 0,
+        }
+    }
+}
+
+// Implementation of the EndOffset trait for ScanResponse. Refer to GetRequest's
+// implementation of this trait to understand what the methods and types mean.
+impl EndOffset for ScanResponse {
+    type PreviousHeader = UdpHeader;
+
+    fn offset(&self) -> usize {
+        size_of::<ScanResponse>()
+    }
+
+    fn size() -> usize {
+        size_of::<ScanResponse>()
+    }
+
+    fn payload_size(&self, hint: usize) -> usize {
+        hint - self.offset()
+    }
+
+    fn check_correct(&self, _prev: &Self::PreviousHeader) -> bool {
+        true
+    }
+}
+
+/// This type represents the header for a cas() RPC request. The header is followed by the key,
+/// then the value expected under it, and then the value to be written.
+#[repr(C, packed)]
+pub struct CasRequest {
+    /// The generic RPC header identifying the request as a cas() RPC.
+    pub common_header: RpcRequestHeader,
+
+    /// The identifier of the table the key-value pair is to be written to.
+    pub table_id: u64,
+
+    /// The length of the key on the payload.
+    pub key_length: u16,
+
+    /// The length of the expected value on the payload. Must be 0 if CAS_FLAG_EXPECT_ABSENT is
+    /// set.
+    pub expected_length: u32,
+
+    /// Flags modifying what the request expects. See CAS_FLAG_EXPECT_ABSENT.
+    pub flags: u8,
+}
+
+le_fields!(
+    CasRequest,
+    table_id, set_table_id: u64;
+    key_length, set_key_length: u16;
+    expected_length, set_expected_length: u32;
+);
+
+/// Flag on a cas() request asking for the key-value pair to be written only if the key does not
+/// exist, instead of only if it's value is the expected one on the request.
+pub const CAS_FLAG_EXPECT_ABSENT: u8 = 0x01;
+
+// Implementation of methods on CasRequest.
+impl CasRequest {
+    /// This method returns a header that can be added to a cas() RPC request. The request
+    /// expects the value on it's payload; refer to with_flags().
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`:          Identifier of the tenant issuing the request.
+    /// * `table_id`:        Identifier of the table to write the key-value pair to.
+    /// * `key_length`:      The length of the key inside the RPC request's payload.
+    /// * `expected_length`: The length of the expected value after the key.
+    /// * `id`:              RPC identifier.
+    /// * `stamp`:           The time-stamp at which the RPC is being sent out.
+    pub fn new(
+        tenant: u32,
+        table_id: u64,
+        key_length: u16,
+        expected_length: u32,
+        id: u64,
+        stamp: u64,
+    ) -> CasRequest {
+        CasRequest {
+            common_header: RpcRequestHeader::new(
+                Service::MasterService,
+                OpCode::SandstormCasRpc,
+                tenant,
+                id,
+                stamp,
+            ),
+            table_id: table_id.to_le(),
+            key_length: key_length.to_le(),
+            expected_length: expected_length.to_le(),
+            flags: 0,
+        }
+    }
+
+    /// Sets the flags on the request.
+    ///
+    /// # Arguments
+    ///
+    /// * `flags`: CAS_FLAG_EXPECT_ABSENT, or 0 to expect the value on the request.
+    pub fn with_flags(mut self, flags: u8) -> CasRequest {
+        self.flags = flags;
+        self
+    }
+}
+
+// Implementation of the EndOffset trait for CasRequest. Refer to GetRequest's
+// implementation of this trait to understand what the methods and types mean.
+impl EndOffset for CasRequest {
+    type PreviousHeader = UdpHeader;
+
+    fn offset(&self) -> usize {
+        size_of::<CasRequest>()
+    }
+
+    fn size() -> usize {
+        size_of::<CasRequest>()
+    }
+
+    fn payload_size(&self, hint: usize) -> usize {
+        hint - self.offset()
+    }
+
+    fn check_correct(&self, _prev: &Self::PreviousHeader) -> bool {
+        true
+    }
+}
+
+/// This type represents the header on a response to a cas() RPC request.
+#[repr(C, packed)]
+pub struct CasResponse {
+    /// A generic RPC header indicating whether the RPC request succeeded
+    /// or failed. StatusCompareFailed if the value under the key was not
+    /// the expected one, in which case nothing was written.
+    pub common_header: RpcResponseHeader,
+}
+
+// Implementation of methods on CasResponse.
+impl CasResponse {
+    /// This method returns a header that can be appended to the response
+    /// to a cas() RPC request.
+    ///
+    /// # Arguments
+    ///
+    /// * `req_id`:    RPC identifier.
+    /// * `req_stamp`: Time-stamp on the RPC request.
+    /// * `tenant`:    The tenant this response should be sent to.
+    pub fn new(req_id: u64, req_stamp: u64, tenant: u32) -> CasResponse {
+        CasResponse {
+            common_header: RpcResponseHeader::new(
+                req_id,
+                req_stamp,
+                OpCode::SandstormCasRpc,
+                tenant,
+            ),
+        }
+    }
+}
+
+// Implementation of the EndOffset trait for CasResponse. Refer to GetRequest's
+// implementation of this trait to understand what the methods and types mean.
+impl EndOffset for CasResponse {
+    type PreviousHeader = UdpHeader;
+
+    fn offset(&self) -> usize {
+        size_of::<CasResponse>()
+    }
+
+    fn size() -> usize {
+        size_of::<CasResponse>()
+    }
+
+    fn payload_size(&self, hint: usize) -> usize {
+        hint - self.offset()
+    }
+
+    fn check_correct(&self, _prev: &Self::PreviousHeader) -> bool {
+        true
+    }
+}
+
+/// This type represents the header for a drop_table() RPC request.
+#[repr(C, packed)]
+pub struct DropTableRequest {
+    /// The generic RPC header identifying the request as a drop_table() RPC.
+    pub common_header: RpcRequestHeader,
+
+    /// The table to drop.
+    pub table_id: u64,
+}
+
+le_fields!(
+    DropTableRequest,
+    table_id, set_table_id: u64;
+);
+
+// Implementation of methods on DropTableRequest.
+impl DropTableRequest {
+    /// This method returns a header that can be added to a drop_table() RPC request.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`:   Identifier of the tenant owning the table.
+    /// * `table_id`: Identifier of the table to drop.
+    /// * `id`:       RPC identifier.
+    /// * `stamp`:    The time-stamp at which the RPC is being sent out.
+    pub fn new(tenant: u32, table_id: u64, id: u64, stamp: u64) -> DropTableRequest {
+        DropTableRequest {
+            common_header: RpcRequestHeader::new(
+                Service::MasterService,
+                OpCode::SandstormDropTableRpc,
+                tenant,
+                id,
+                stamp,
+            ),
+            table_id: table_id.to_le(),
+        }
+    }
+}
+
+// Implementation of the EndOffset trait for DropTableRequest. Refer to GetRequest's
+// implementation of this trait to understand what the methods and types mean.
+impl EndOffset for DropTableRequest {
+    type PreviousHeader = UdpHeader;
+
+    fn offset(&self) -> usize {
+        size_of::<DropTableRequest>()
+    }
+
+    fn size() -> usize {
+        size_of::<DropTableRequest>()
+    }
+
+    fn payload_size(&self, hint: usize) -> usize {
+        hint - self.offset()
+    }
+
+    fn check_correct(&self, _prev: &Self::PreviousHeader) -> bool {
+        true
+    }
+}
+
+/// This type represents the header on a response to a drop_table() RPC request.
+#[repr(C, packed)]
+pub struct DropTableResponse {
+    /// A generic RPC header indicating whether the RPC request succeeded
+    /// or failed.
+    pub common_header: RpcResponseHeader,
+}
+
+// Implementation of methods on DropTableResponse.
+impl DropTableResponse {
+    /// This method returns a header that can be appended to the response
+    /// to a drop_table() RPC request.
+    ///
+    /// # Arguments
+    ///
+    /// * `req_id`:    RPC identifier.
+    /// * `req_stamp`: Time-stamp on the RPC request.
+    /// * `tenant`:    The tenant this response should be sent to.
+    pub fn new(req_id: u64, req_stamp: u64, tenant: u32) -> DropTableResponse {
+        DropTableResponse {
+            common_header: RpcResponseHeader::new(
+                req_id,
+                req_stamp,
+                OpCode::SandstormDropTableRpc,
+                tenant,
+            ),
+        }
+    }
+}
+
+// Implementation of the EndOffset trait for DropTableResponse. Refer to GetRequest's
+// implementation of this trait to understand what the methods and types mean.
+impl EndOffset for DropTableResponse {
+    type PreviousHeader = UdpHeader;
+
+    fn offset(&self) -> usize {
+        size_of::<DropTableResponse>()
+    }
+
+    fn size() -> usize {
+        size_of::<DropTableResponse>()
+    }
+
+    fn payload_size(&self, hint: usize) -> usize {
+        hint - self.offset()
+    }
+
+    fn check_correct(&self, _prev: &Self::PreviousHeader) -> bool {
+        true
+    }
+}
+
+/// This type represents the request header corresponding to an invoke() RPC.
+#[repr(C, packed)]
+pub struct InvokeRequest {
+    /// The common RPC header identifying the opcode, service, and tenant.
+    pub common_header: RpcRequestHeader,
+
+    /// The length of the name of the procedure to be invoked. Required to
+    /// deserialize the procedure's name from the request packet at the server.
+    pub name_length: u32,
+
+    /// The total length of the args to be passed into the procedure. Required
+    /// to deserialize the arguments to the procedure from the request packet
+    /// at the server.
+    pub args_length: u32,
+
+    /// Flags modifying how the invocation is run. See INVOKE_FLAG_DURABLE,
+    /// INVOKE_FLAG_STORE_RESULT and INVOKE_FLAG_BY_ID.
+    pub flags: u8,
+}
+
+le_fields!(
+    InvokeRequest,
+    name_length, set_name_length: u32;
+    args_length, set_args_length: u32;
+);
+
+/// Flag on an invoke() request asking for the invocation to be durable. Checkpoints taken by a
+/// durable invocation survive a server restart, after which it is resumed from it's last
+/// checkpoint. It's result is written to the tenant's DURABLE_RESULTS_TABLE.
+pub const INVOKE_FLAG_DURABLE: u8 = 0x01;
+
+/// The table a durable invocation's result is written to, keyed by the little endian encoding
+/// of the hash over it's name and arguments (db::journal::args_hash()).
+pub const DURABLE_RESULTS_TABLE: u64 = 0xffffffffffffffff;
+
+/// Flag on an invoke() request asking for it's result to be stored in the tenant's mailbox
+/// before the response is sent out, so that a tenant that never receives the response can
+/// fetch it with a fetch_result() instead of invoking the extension again. Ignored on durable
+/// invocations, whose result is in the DURABLE_RESULTS_TABLE already.
+pub const INVOKE_FLAG_STORE_RESULT: u8 = 0x02;
+
+/// Flag on an invoke() request naming the extension by the id it was listed under by a
+/// list_extensions() instead of it's name. The name on the request is then the 4 byte little
+/// endian id, and `name_length` must be 4. An id that is no longer interned fails the request
+/// with StatusStaleExtensionId. Requests without the flag name the extension as before.
+pub const INVOKE_FLAG_BY_ID: u8 = 0x04;
+
+/// The table results stored by INVOKE_FLAG_STORE_RESULT are written to, keyed by the little
+/// endian encoding of the invoke() request's id. Refer to mailbox::Mailbox.
+pub const MAILBOX_TABLE: u64 = 0xfffffffffffffffe;
+
+impl InvokeRequest {
+    /// This method returns a header corresponding to an invoke() RPC request.
+    /// The returned header can be appended onto a request packet.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`:      An identifier for the tenant issuing this RPC.
+    /// * `name_length`: The length of the name of the procedure to be invoked.
+    ///                  Required so that the name can be read from the request
+    ///                  packet by the server.
+    /// * `args_length`: The length of the args to be supplied to the procedure.
+    ///                  Required so that the server can unpack them from a
+    ///                  request packet.
+    /// * `req_id`:      RPC identifier.
+    /// * `req_stamp`:   The time-stamp at which the RPC is being sent out.
+    ///
+    /// # Return
+    ///
+    /// An RPC request header of type `InvokeRequest`.
+    pub fn new(
+        tenant: u32,
+        name_length: u32,
+        args_length: u32,
+        req_id: u64,
+        req_stamp: u64,
+    ) -> InvokeRequest {
+        InvokeRequest {
+            common_header: RpcRequestHeader::new(
+                Service::MasterService,
+                OpCode::SandstormInvokeRpc,
+                tenant,
+                req_id,
+                req_stamp,
+            ),
+            name_length: name_length.to_le(),
+            args_length: args_length.to_le(),
+            flags: 0,
+        }
+    }
+}
+
+// Implementation of the EndOffset trait for InvokeRequest. Refer to
+// GetRequest's implementation of this trait to understand what the methods
+// and types mean.
+impl EndOffset for InvokeRequest {
+    type PreviousHeader = UdpHeader;
+
+    fn offset(&self) -> usize {
+        size_of::<InvokeRequest>()
+    }
+
+    fn size() -> usize {
+        size_of::<InvokeRequest>()
+    }
+
+    fn payload_size(&self, hint: usize) -> usize {
+        hint - self.offset()
+    }
+
+    fn check_correct(&self, _prev: &Self::PreviousHeader) -> bool {
+        true
+    }
+}
+
+/// This type represents the response header for an invoke() RPC request.
+#[repr(C, packed)]
+pub struct InvokeResponse {
+    /// A common RPC response header containing the status of the RPC.
+    pub common_header: RpcResponseHeader,
+
+    /// Flags marking the response as part of a streamed result. See STREAM_FLAG_MORE. 0 if the
+    /// result was not streamed.
+    pub flags: u8,
+
+    /// The position of the response within a streamed result, starting at 0. 0 if the result
+    /// was not streamed.
+    pub seq: u32,
+}
+
+le_fields!(
+    InvokeResponse,
+    seq, set_seq: u32;
+);
+
+/// Flag on an invoke() response carrying part of a streamed result, with more responses to
+/// follow it. Refer to DB::resp_flush().
+pub const STREAM_FLAG_MORE: u8 = 0x01;
+
+/// Flag on the last response of a streamed result. The status on it is the status of the whole
+/// invocation.
+pub const STREAM_FLAG_END: u8 = 0x02;
+
+impl InvokeResponse {
+    /// This method returns a header that can be appended to the response
+    /// packet for an invoke() RPC request.
+    ///
+    /// # Arguments
+    ///
+    /// * `req_id`:    RPC identifier.
+    /// * `req_stamp`: Time-stamp on the RPC request.
+    /// * `opcode`:    The opcode on the original RPC request.
+    /// * `tenant`:    The tenant this response should be sent to.
+    pub fn new(req_id: u64, req_stamp: u64, opcode: OpCode, tenant: u32) -> InvokeResponse {
+        InvokeResponse {
+            common_header: RpcResponseHeader::new(req_id, req_stamp, opcode, tenant),
+            flags: 0,
+            seq: 0,
+        }
+    }
+}
+
+// Implementation of the EndOffset trait for InvokeResponse. Refer to
+// GetRequest's implementation of this trait to understand what the methods
+// and types mean.
+impl EndOffset for InvokeResponse {
+    type PreviousHeader = UdpHeader;
+
+    fn offset(&self) -> usize {
+        size_of::<InvokeResponse>()
+    }
+
+    fn size() -> usize {
+        size_of::<InvokeResponse>()
+    }
+
+    fn payload_size(&self, hint: usize) -> usize {
+        hint - self.offset()
+    }
+
+    fn check_correct(&self, _prev: &Self::PreviousHeader) -> bool {
+        true
+    }
+}
+
+/// This type represents the header for an install() RPC request.
+#[repr(C, packed)]
+pub struct InstallRequest {
+    /// Generic RPC header identifying the service, opcode, and tenant.
+    pub common_header: RpcRequestHeader,
+
+    /// Length of the name in bytes of the extension being installed. The payload of the RPC should
+    /// start with the name of the extension.
+    pub name_length: u32,
+
+    /// Length of the extension in bytes. The extension should follow the name on the RPC's
+    /// payload.
+    pub extn_length: u32,
+}
+
+le_fields!(
+    InstallRequest,
+    name_length, set_name_length: u32;
+    extn_length, set_extn_length: u32;
+);
+
+// Implementation of methods on InstallRequest.
+impl InstallRequest {
+    /// Returns a header for the install() RPC request. The header is of type `InstallRequest`.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`:      Tenant identifier.
+    /// * `name_length`: Length of the name of the extension in bytes. The payload of the RPC
+    ///                  should start with the name of the extension.
+    /// * `extn_length`: Length of the extension in bytes. The extension should follow the name on
+    ///                  the RPC's payload.
+    /// * `req_id`:      RPC identifier.
+    /// * `req_stamp`:   The time-stamp at which the RPC is being sent out.
+    pub fn new(
+        tenant: u32,
+        name_length: u32,
+        extn_length: u32,
+        req_id: u64,
+        req_stamp: u64,
+    ) -> InstallRequest {
+        InstallRequest {
+            common_header: RpcRequestHeader::new(
+                Service::MasterService,
+                OpCode::SandstormInstallRpc,
+                tenant,
+                req_id,
+                req_stamp,
+            ),
+            name_length: name_length.to_le(),
+            extn_length: extn_length.to_le(),
+        }
+    }
+}
+
+// Implementation of the EndOffset trait for InstallRequest. Refer to
+// GetRequest's implementation of this trait to understand what the methods
+// and types mean.
+impl EndOffset for InstallRequest {
+    type PreviousHeader = UdpHeader;
+
+    fn offset(&self) -> usize {
+        size_of::<InstallRequest>()
+    }
+
+    fn size() -> usize {
+        size_of::<InstallRequest>()
+    }
+
+    fn payload_size(&self, hint: usize) -> usize {
+        hint - self.offset()
+    }
+
+    fn check_correct(&self, _prev: &Self::PreviousHeader) -> bool {
+        true
+    }
+}
+
+/// This type represents the response header for an install() RPC request.
+#[repr(C, packed)]
+pub struct InstallResponse {
+    /// A generic response header with the status of the RPC (indicating whether it
+    /// succeeded or failed).
+    pub common_header: RpcResponseHeader,
+}
+
+// Implementation of methods on InstallResponse.
+impl InstallResponse {
+    /// Returns a header for the install() RPC request.
+    ///
+    /// # Arguments
+    ///
+    /// * `req_id`:    RPC identifier.
+    /// * `req_stamp`: Time-stamp on the RPC request.
+    /// * `opcode`:    The opcode on the original RPC request.
+    /// * `tenant`:    The tenant this response should be sent to.
+    pub fn new(req_id: u64, req_stamp: u64, opcode: OpCode, tenant: u32) -> InstallResponse {
+        InstallResponse {
+            common_header: RpcResponseHeader::new(req_id, req_stamp, opcode, tenant),
+        }
+    }
+}
+
+// Implementation of the EndOffset trait for InstallResponse. Refer to
+// GetRequest's implementation of this trait to understand what the methods
+// and types mean.
+impl EndOffset for InstallResponse {
+    type PreviousHeader = UdpHeader;
+
+    fn offset(&self) -> usize {
+        size_of::<InstallResponse>()
+    }
+
+    fn size() -> usize {
+        size_of::<InstallResponse>()
+    }
+
+    fn payload_size(&self, hint: usize) -> usize {
+        hint - self.offset()
+    }
+
+    fn check_correct(&self, _prev: &Self::PreviousHeader) -> bool {
+        true
+    }
+}
+
+/// This type represents the RPC header on a multiget() request.
+#[repr(C, packed)]
+pub struct MultiGetRequest {
+    /// Generic RPC header consisting of service, opcode, and tenant id.
+    pub common_header: RpcRequestHeader,
+
+    /// Table that should be looked up for the records.
+    pub table_id: u64,
+
+    /// The length of every key to be looked up. All keys to be looked up are assumed to be of
+    /// equal length.
+    pub key_len: u16,
+
+    /// The number of keys to be looked up at the database. Every key should be `key_len` bytes
+    /// long.
+    pub num_keys: u32,
+
+    /// Flags asking for the version of each object. See MULTIGET_FLAG_VERSIONS.
+    pub flags: u8,
+}
+
+le_fields!(
+    MultiGetRequest,
+    table_id, set_table_id: u64;
+    key_len, set_key_len: u16;
+    num_keys, set_num_keys: u32;
+);
+
+/// Flag on a multiget() request asking for the version of each object along with it's value.
+/// Each record on the response is then laid out as the object's version (8 bytes), the length of
+/// it's value (4 bytes), and the value, all little-endian; refer to rpc::parse_versioned_records().
+/// The keys are read as a snapshot, and the response is flagged racy if any of them changed
+/// while they were being read.
+pub const MULTIGET_FLAG_VERSIONS: u8 = 0x01;
+
+/// Flag on a multiget() request asking for the version of each object instead of it's value.
+/// Each record on the response is then just the object's version (8 bytes, little-endian).
+/// Implies MULTIGET_FLAG_VERSIONS. Cheap enough to validate cached objects with.
+pub const MULTIGET_FLAG_VERSIONS_ONLY: u8 = 0x02;
+
+// Implementation of methods on MultiGetRequest.
+impl MultiGetRequest {
+    /// Constructs an RPC header that can be added to the multiget() request. The header is of type
+    /// `MultiGetRequest`. Only values are looked up; refer to with_flags().
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`: Identifier of the tenant sending the request.
+    /// * `table`:  Identifier of the table to be looked up.
+    /// * `k_len`:  Length of every key to be looked up. All keys are assumed to be of equal
+    ///             length.
+    /// * `n_keys`: The number of keys to be looked up (each of length `k_len`).
+    /// * `id`:     Identifier of the RPC.
+    /// * `stamp`:  The time-stamp at which the RPC is being sent out.
+    pub fn new(
+        tenant: u32,
+        table: u64,
+        k_len: u16,
+        n_keys: u32,
+        id: u64,
+        stamp: u64,
+    ) -> MultiGetRequest {
+        MultiGetRequest {
+            common_header: RpcRequestHeader::new(
+                Service::MasterService,
+                OpCode::SandstormMultiGetRpc,
+                tenant,
+                id,
+                stamp,
+            ),
+            table_id: table.to_le(),
+            key_len: k_len.to_le(),
+            num_keys: n_keys.to_le(),
+            flags: 0,
+        }
+    }
+
+    /// Sets the flags on the request, asking for the version of each object along with, or
+    /// instead of, it's value.
+    ///
+    /// # Arguments
+    ///
+    /// * `flags`: MULTIGET_FLAG_VERSIONS, MULTIGET_FLAG_VERSIONS_ONLY, or 0 for values only.
+    pub fn with_flags(mut self, flags: u8) -> MultiGetRequest {
+        self.flags = flags;
+        self
+    }
+}
+
+// Implementation of the EndOffset trait for MultiGetRequest. Refer to
+// GetRequest's implementation of this trait to understand what the methods
+// and types mean.
+impl EndOffset for MultiGetRequest {
+    type PreviousHeader = UdpHeader;
+
+    fn offset(&self) -> usize {
+        size_of::<MultiGetRequest>()
+    }
+
+    fn size() -> usize {
+        size_of::<MultiGetRequest>()
+    }
+
+    fn payload_size(&self, hint: usize) -> usize {
+        hint - self.offset()
+    }
+
+    fn check_correct(&self, _prev: &Self::PreviousHeader) -> bool {
+        true
+    }
+}
+
+/// This type represents the response header for a multiget() RPC request.
+#[repr(C, packed)]
+pub struct MultiGetResponse {
+    /// Generic response header consisting of RPC status and identifier.
+    pub common_header: RpcResponseHeader,
 
     /// Number of records returned by the RPC.
     pub num_records: u32,
+
+    /// Non-zero if the request asked for versions, and one of the objects changed while the
+    /// objects were being read. The records may then not all have been current at the same
+    /// instant, and the client should retry if it needs them to be.
+    pub racy: u8,
 }
 
+le_fields!(
+    MultiGetResponse,
+    num_records, set_num_records: u32;
+);
+
 // Implementation of methods on MultiGetResponse.
 impl MultiGetResponse {
     /// Constructs a response header for the multiget() RPC. The header is of type
@@ -827,14 +4403,22 @@ impl MultiGetResponse {
     ///
     /// # Arguments
     ///
-    /// * `stamp`:     RPC identifier. Can be used to timestamp the RPC.
+    /// * `id`:        RPC identifier.
+    /// * `stamp`:     Time-stamp on the RPC request.
     /// * `opcode`:    The opcode on the original RPC request.
     /// * `tenant`:    The tenant this response should be sent to.
     /// * `n_records`: Number of records being returned in the response.
-    pub fn new(stamp: u64, opcode: OpCode, tenant: u32, n_records: u32) -> MultiGetResponse {
+    pub fn new(
+        id: u64,
+        stamp: u64,
+        opcode: OpCode,
+        tenant: u32,
+        n_records: u32,
+    ) -> MultiGetResponse {
         MultiGetResponse {
-            common_header: RpcResponseHeader::new(stamp, opcode, tenant),
-            num_records: n_records,
+            common_header: RpcResponseHeader::new(id, stamp, opcode, tenant),
+            num_records: n_records.to_le(),
+            racy: 0,
         }
     }
 }
@@ -883,10 +4467,14 @@ pub enum OpType {
 
 /// This struct represents a record for a read/write set. Each record in the read/write set will
 /// be of this type.
+#[derive(Clone)]
 pub struct Record {
     /// This variable shows the type of operation for the record, Read or Write.
     optype: OpType,
 
+    /// The identifier of the table the record was read from or written to.
+    table_id: u64,
+
     /// The version number for the record.
     version: Version,
 
@@ -903,14 +4491,23 @@ impl Record {
     ///
     /// # Arguments
     /// * `r_optype`: The type of the record, either a Read or a Write.
+    /// * `table_id`: The table the record was read from or written to.
+    /// * `version`: The version of the record.
     /// * `r_key`: The key for the record.
     /// * `r_object`: The value for the record.
     ///
     /// # Return
-    /// A read-write set record with the operation type, a key and a value.
-    pub fn new(r_optype: OpType, version: Version, r_key: Bytes, r_object: Bytes) -> Record {
+    /// A read-write set record with the operation type, a table, a key and a value.
+    pub fn new(
+        r_optype: OpType,
+        table_id: u64,
+        version: Version,
+        r_key: Bytes,
+        r_object: Bytes,
+    ) -> Record {
         Record {
             optype: r_optype,
+            table_id: table_id,
             version: version,
             key: r_key,
             object: r_object,
@@ -922,6 +4519,11 @@ impl Record {
         self.optype.clone()
     }
 
+    /// Return the table the operation was performed on.
+    pub fn get_table_id(&self) -> u64 {
+        self.table_id
+    }
+
     /// Return the version for the performed operation.
     pub fn get_version(&self) -> Version {
         self.version.clone()
@@ -937,3 +4539,899 @@ impl Record {
         self.object.clone()
     }
 }
+
+// Fails to compile if the size of any header changes, since transmute() only accepts types of the
+// same size. Never called.
+#[allow(dead_code)]
+fn assert_header_sizes() {
+    let _ = |h: RpcRequestHeader| -> [u8; 31] { unsafe { transmute(h) } };
+    let _ = |h: RpcResponseHeader| -> [u8; 41] { unsafe { transmute(h) } };
+    let _ = |h: GetRequest| -> [u8; 54] { unsafe { transmute(h) } };
+    let _ = |h: GetResponse| -> [u8; 66] { unsafe { transmute(h) } };
+    let _ = |h: PutRequest| -> [u8; 41] { unsafe { transmute(h) } };
+    let _ = |h: PutResponse| -> [u8; 41] { unsafe { transmute(h) } };
+    let _ = |h: EchoRequest| -> [u8; 31] { unsafe { transmute(h) } };
+    let _ = |h: EchoResponse| -> [u8; 65] { unsafe { transmute(h) } };
+    let _ = |h: ListExtRequest| -> [u8; 35] { unsafe { transmute(h) } };
+    let _ = |h: ListExtResponse| -> [u8; 49] { unsafe { transmute(h) } };
+    let _ = |h: DrainRequest| -> [u8; 31] { unsafe { transmute(h) } };
+    let _ = |h: DrainResponse| -> [u8; 49] { unsafe { transmute(h) } };
+    let _ = |h: TableAccessRequest| -> [u8; 41] { unsafe { transmute(h) } };
+    let _ = |h: TableAccessResponse| -> [u8; 41] { unsafe { transmute(h) } };
+    let _ = |h: RunStatsRequest| -> [u8; 39] { unsafe { transmute(h) } };
+    let _ = |h: RunStatsResponse| -> [u8; 45] { unsafe { transmute(h) } };
+    let _ = |h: MergeRequest| -> [u8; 41] { unsafe { transmute(h) } };
+    let _ = |h: MergeResponse| -> [u8; 41] { unsafe { transmute(h) } };
+    let _ = |h: SetMergeRequest| -> [u8; 43] { unsafe { transmute(h) } };
+    let _ = |h: SetMergeResponse| -> [u8; 41] { unsafe { transmute(h) } };
+    let _ = |h: RoutedInvokeRequest| -> [u8; 45] { unsafe { transmute(h) } };
+    let _ = |h: SetRouteRequest| -> [u8; 37] { unsafe { transmute(h) } };
+    let _ = |h: SetRouteResponse| -> [u8; 41] { unsafe { transmute(h) } };
+    let _ = |h: AuditRequest| -> [u8; 39] { unsafe { transmute(h) } };
+    let _ = |h: AuditResponse| -> [u8; 53] { unsafe { transmute(h) } };
+    let _ = |h: DumpRequest| -> [u8; 45] { unsafe { transmute(h) } };
+    let _ = |h: DumpResponse| -> [u8; 49] { unsafe { transmute(h) } };
+    let _ = |h: MultiPutRequest| -> [u8; 43] { unsafe { transmute(h) } };
+    let _ = |h: MultiPutResponse| -> [u8; 45] { unsafe { transmute(h) } };
+    let _ = |h: WriteStatsRequest| -> [u8; 40] { unsafe { transmute(h) } };
+    let _ = |h: WriteStatsResponse| -> [u8; 45] { unsafe { transmute(h) } };
+    let _ = |h: CoreStatsRequest| -> [u8; 31] { unsafe { transmute(h) } };
+    let _ = |h: CoreStatsResponse| -> [u8; 61] { unsafe { transmute(h) } };
+    let _ = |h: LatencyStatsRequest| -> [u8; 33] { unsafe { transmute(h) } };
+    let _ = |h: LatencyStatsResponse| -> [u8; 53] { unsafe { transmute(h) } };
+    let _ = |h: QuarantineRequest| -> [u8; 44] { unsafe { transmute(h) } };
+    let _ = |h: QuarantineResponse| -> [u8; 69] { unsafe { transmute(h) } };
+    let _ = |h: CreateTableRequest| -> [u8; 64] { unsafe { transmute(h) } };
+    let _ = |h: CreateTableResponse| -> [u8; 49] { unsafe { transmute(h) } };
+    let _ = |h: FetchResultRequest| -> [u8; 39] { unsafe { transmute(h) } };
+    let _ = |h: FetchResultResponse| -> [u8; 42] { unsafe { transmute(h) } };
+    let _ = |h: DeleteRequest| -> [u8; 41] { unsafe { transmute(h) } };
+    let _ = |h: DeleteResponse| -> [u8; 41] { unsafe { transmute(h) } };
+    let _ = |h: ScanRequest| -> [u8; 46] { unsafe { transmute(h) } };
+    let _ = |h: ScanResponse| -> [u8; 51] { unsafe { transmute(h) } };
+    let _ = |h: CasRequest| -> [u8; 46] { unsafe { transmute(h) } };
+    let _ = |h: CasResponse| -> [u8; 41] { unsafe { transmute(h) } };
+    let _ = |h: DropTableRequest| -> [u8; 39] { unsafe { transmute(h) } };
+    let _ = |h: DropTableResponse| -> [u8; 41] { unsafe { transmute(h) } };
+    let _ = |h: InvokeRequest| -> [u8; 40] { unsafe { transmute(h) } };
+    let _ = |h: InvokeResponse| -> [u8; 46] { unsafe { transmute(h) } };
+    let _ = |h: InstallRequest| -> [u8; 39] { unsafe { transmute(h) } };
+    let _ = |h: InstallResponse| -> [u8; 41] { unsafe { transmute(h) } };
+    let _ = |h: MultiGetRequest| -> [u8; 46] { unsafe { transmute(h) } };
+    let _ = |h: MultiGetResponse| -> [u8; 46] { unsafe { transmute(h) } };
+}
+
+// This module pins the layout of every header to a golden sequence of bytes. Fields are filled
+// with distinct bytes, so that a field that moves, changes width or byte order fails it's test.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::slice;
+
+    // The tenant, id and time-stamp on every header tested.
+    const T: u32 = 0x0403_0201;
+    const I: u64 = 0x1817_1615_1413_1211;
+    const S: u64 = 0x2827_2625_2423_2221;
+
+    // Returns the bytes a header is made up of, in the order they go out on the wire.
+    fn bytes<H>(header: &H) -> Vec<u8> {
+        let ptr = header as *const H as *const u8;
+        unsafe { slice::from_raw_parts(ptr, size_of::<H>()) }.to_vec()
+    }
+
+    // Returns the common request header the constructors build for T, I and S.
+    fn request(opcode: u8) -> Vec<u8> {
+        let mut golden = vec![0x01, opcode];
+        golden.extend_from_slice(&[0x01, 0x02, 0x03, 0x04]);
+        golden.extend_from_slice(&[0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18]);
+        golden.extend_from_slice(&[0x21, 0x22, 0x23, 0x24, 0x25, 0x26, 0x27, 0x28]);
+        golden.extend_from_slice(&[0; 9]);
+        golden
+    }
+
+    // Returns the common response header the constructors build for T, I and S.
+    fn response(opcode: u8) -> Vec<u8> {
+        let mut golden = vec![0x01, opcode];
+        golden.extend_from_slice(&[0x01, 0x02, 0x03, 0x04]);
+        golden.extend_from_slice(&[0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18]);
+        golden.extend_from_slice(&[0x21, 0x22, 0x23, 0x24, 0x25, 0x26, 0x27, 0x28]);
+        golden.extend_from_slice(&[0; 19]);
+        golden
+    }
+
+    // Tests the layout of RpcRequestHeader, and the offsets of the fields read off requests
+    // before they are parsed.
+    #[test]
+    fn test_rpc_request_header_layout() {
+        let mut h = RpcRequestHeader::new(Service::MasterService, OpCode::SandstormGetRpc, T, I, S);
+        h.set_run(0x3837_3635_3433_3231);
+        h.flags = REQUEST_FLAG_RUN;
+        let golden = [
+            0x01, // service
+            0x01, // opcode
+            0x01, 0x02, 0x03, 0x04, // tenant
+            0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18, // id
+            0x21, 0x22, 0x23, 0x24, 0x25, 0x26, 0x27, 0x28, // stamp
+            0x31, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37, 0x38, // run
+            0x02, // flags
+        ];
+        assert_eq!(&golden[..], &bytes(&h)[..]);
+        assert_eq!((T, I, S), (h.tenant(), h.id(), h.stamp()));
+        assert_eq!(0x3837_3635_3433_3231, h.run());
+
+        assert_eq!(0x01, golden[REQUEST_TENANT_OFFSET]);
+        assert_eq!(0x31, golden[REQUEST_RUN_OFFSET]);
+        assert_eq!(REQUEST_FLAG_RUN, golden[REQUEST_FLAGS_OFFSET]);
+    }
+
+    // Tests the layout of RpcResponseHeader.
+    #[test]
+    fn test_rpc_response_header_layout() {
+        let mut h = RpcResponseHeader::new(I, S, OpCode::SandstormEchoRpc, T);
+        h.status = RpcStatus::StatusPushback;
+        h.set_load(0x3231);
+        h.set_rx_stamp(0x4443_4241);
+        h.set_tx_stamp(0x5453_5251);
+        h.set_epoch(0x6867_6665_6463_6261);
+        h.flags = RESPONSE_FLAG_TRAILERS;
+        let golden = [
+            0x09, // status
+            0x06, // opcode
+            0x01, 0x02, 0x03, 0x04, // tenant
+            0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18, // id
+            0x21, 0x22, 0x23, 0x24, 0x25, 0x26, 0x27, 0x28, // stamp
+            0x31, 0x32, // load
+            0x41, 0x42, 0x43, 0x44, // rx_stamp
+            0x51, 0x52, 0x53, 0x54, // tx_stamp
+            0x61, 0x62, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68, // epoch
+            0x01, // flags
+        ];
+        assert_eq!(&golden[..], &bytes(&h)[..]);
+        assert_eq!((T, I, S), (h.tenant(), h.id(), h.stamp()));
+        assert_eq!(0x3231, h.load());
+        assert_eq!((0x4443_4241, 0x5453_5251), (h.rx_stamp(), h.tx_stamp()));
+        assert_eq!(0x6867_6665_6463_6261, h.epoch());
+
+        assert_eq!(0x11, golden[RESPONSE_ID_OFFSET]);
+        assert_eq!(RESPONSE_FLAG_TRAILERS, golden[RESPONSE_FLAGS_OFFSET]);
+    }
+
+    // Tests the layout of GetRequest.
+    #[test]
+    fn test_get_request_layout() {
+        let mut h = GetRequest::new(
+            T,
+            0x3837_3635_3433_3231,
+            0x4241,
+            I,
+            S,
+            GetGenerator::SandstormExtension,
+        );
+        h.set_staleness_us(0x6463_6261);
+        h.set_value_offset(0x7473_7271);
+        h.set_value_length(0x8483_8281);
+        let mut golden = request(0x01);
+        golden.extend_from_slice(&[
+            0x31, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37, 0x38, // table_id
+            0x41, 0x42, // key_length
+            0x02, // generator
+            0x61, 0x62, 0x63, 0x64, // staleness_us
+            0x71, 0x72, 0x73, 0x74, // value_offset
+            0x81, 0x82, 0x83, 0x84, // value_length
+        ]);
+        assert_eq!(golden, bytes(&h));
+        assert_eq!(0x3837_3635_3433_3231, h.table_id());
+        assert_eq!(0x4241, h.key_length());
+        assert_eq!(0x6463_6261, h.staleness_us());
+        assert_eq!(0x7473_7271, h.value_offset());
+        assert_eq!(0x8483_8281, h.value_length());
+    }
+
+    // Tests the layout of GetResponse.
+    #[test]
+    fn test_get_response_layout() {
+        let mut h = GetResponse::new(I, S, OpCode::SandstormGetRpc, T);
+        h.set_value_length(0x3433_3231);
+        h.set_full_length(0x4443_4241);
+        h.set_age_us(0x5453_5251);
+        h.hint = GET_HINT_HOT;
+        h.set_version(0x6867_6665_6463_6261);
+        h.set_ttl_us(0x7473_7271);
+        let mut golden = response(0x01);
+        golden.extend_from_slice(&[
+            0x31, 0x32, 0x33, 0x34, // value_length
+            0x41, 0x42, 0x43, 0x44, // full_length
+            0x51, 0x52, 0x53, 0x54, // age_us
+            0x01, // hint
+            0x61, 0x62, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68, // version
+            0x71, 0x72, 0x73, 0x74, // ttl_us
+        ]);
+        assert_eq!(golden, bytes(&h));
+        assert_eq!(0x3433_3231, h.value_length());
+        assert_eq!(0x4443_4241, h.full_length());
+        assert_eq!(0x5453_5251, h.age_us());
+        assert_eq!(0x6867_6665_6463_6261, h.version());
+        assert_eq!(0x7473_7271, h.ttl_us());
+    }
+
+    // Tests the layout of PutRequest.
+    #[test]
+    fn test_put_request_layout() {
+        let h = PutRequest::new(T, 0x3837_3635_3433_3231, 0x4241, I, S);
+        let mut golden = request(0x02);
+        golden.extend_from_slice(&[
+            0x31, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37, 0x38, // table_id
+            0x41, 0x42, // key_length
+        ]);
+        assert_eq!(golden, bytes(&h));
+        assert_eq!(0x3837_3635_3433_3231, h.table_id());
+        assert_eq!(0x4241, h.key_length());
+    }
+
+    // Tests the layout of PutResponse.
+    #[test]
+    fn test_put_response_layout() {
+        let h = PutResponse::new(I, S, OpCode::SandstormPutRpc, T);
+        assert_eq!(response(0x02), bytes(&h));
+    }
+
+    // Tests the layout of EchoRequest.
+    #[test]
+    fn test_echo_request_layout() {
+        let h = EchoRequest::new(T, I, S);
+        assert_eq!(request(0x06), bytes(&h));
+    }
+
+    // Tests the layout of EchoResponse.
+    #[test]
+    fn test_echo_response_layout() {
+        let h = EchoResponse::new(
+            I,
+            S,
+            T,
+            0x3837_3635_3433_3231,
+            0x4847_4645_4443_4241,
+            0x5453_5251,
+            0x6463_6261,
+        );
+        let mut golden = response(0x06);
+        golden.extend_from_slice(&[
+            0x31, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37, 0x38, // cycles_per_second
+            0x41, 0x42, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48, // opcodes
+            0x51, 0x52, 0x53, 0x54, // max_key_len
+            0x61, 0x62, 0x63, 0x64, // max_value_len
+        ]);
+        assert_eq!(golden, bytes(&h));
+        assert_eq!(0x3837_3635_3433_3231, h.cycles_per_second());
+        assert_eq!(0x4847_4645_4443_4241, h.opcodes());
+        assert_eq!(0x5453_5251, h.max_key_len());
+        assert_eq!(0x6463_6261, h.max_value_len());
+    }
+
+    // Tests the layout of ListExtRequest.
+    #[test]
+    fn test_list_ext_request_layout() {
+        let h = ListExtRequest::new(T, 0x3433_3231, I, S);
+        let mut golden = request(0x07);
+        golden.extend_from_slice(&[
+            0x31, 0x32, 0x33, 0x34, // start
+        ]);
+        assert_eq!(golden, bytes(&h));
+        assert_eq!(0x3433_3231, h.start());
+    }
+
+    // Tests the layout of ListExtResponse.
+    #[test]
+    fn test_list_ext_response_layout() {
+        let mut h = ListExtResponse::new(I, S, T);
+        h.set_num_entries(0x3433_3231);
+        h.set_next(0x4443_4241);
+        let mut golden = response(0x07);
+        golden.extend_from_slice(&[
+            0x31, 0x32, 0x33, 0x34, // num_entries
+            0x41, 0x42, 0x43, 0x44, // next
+        ]);
+        assert_eq!(golden, bytes(&h));
+        assert_eq!(0x3433_3231, h.num_entries());
+        assert_eq!(0x4443_4241, h.next());
+    }
+
+    // Tests the layout of DrainRequest.
+    #[test]
+    fn test_drain_request_layout() {
+        let h = DrainRequest::new(T, I, S);
+        assert_eq!(request(0x08), bytes(&h));
+    }
+
+    // Tests the layout of DrainResponse.
+    #[test]
+    fn test_drain_response_layout() {
+        let mut h = DrainResponse::new(I, S, T);
+        h.set_num_cores(0x3433_3231);
+        h.set_remaining(0x4443_4241);
+        let mut golden = response(0x08);
+        golden.extend_from_slice(&[
+            0x31, 0x32, 0x33, 0x34, // num_cores
+            0x41, 0x42, 0x43, 0x44, // remaining
+        ]);
+        assert_eq!(golden, bytes(&h));
+        assert_eq!(0x3433_3231, h.num_cores());
+        assert_eq!(0x4443_4241, h.remaining());
+    }
+
+    // Tests the layout of TableAccessRequest.
+    #[test]
+    fn test_table_access_request_layout() {
+        let h = TableAccessRequest::new(T, 0x3837_3635_3433_3231, true, false, I, S);
+        let mut golden = request(0x09);
+        golden.extend_from_slice(&[
+            0x31, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37, 0x38, // table_id
+            0x01, // readable_native
+            0x00, // writable_native
+        ]);
+        assert_eq!(golden, bytes(&h));
+        assert_eq!(0x3837_3635_3433_3231, h.table_id());
+    }
+
+    // Tests the layout of TableAccessResponse.
+    #[test]
+    fn test_table_access_response_layout() {
+        let h = TableAccessResponse::new(I, S, T);
+        assert_eq!(response(0x09), bytes(&h));
+    }
+
+    // Tests the layout of RunStatsRequest.
+    #[test]
+    fn test_run_stats_request_layout() {
+        let h = RunStatsRequest::new(T, 0x3837_3635_3433_3231, I, S);
+        let mut golden = request(0x0a);
+        golden.extend_from_slice(&[
+            0x31, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37, 0x38, // filter
+        ]);
+        assert_eq!(golden, bytes(&h));
+        assert_eq!(0x3837_3635_3433_3231, h.filter());
+    }
+
+    // Tests the layout of RunStatsResponse.
+    #[test]
+    fn test_run_stats_response_layout() {
+        let mut h = RunStatsResponse::new(I, S, T);
+        h.set_num_runs(0x3433_3231);
+        let mut golden = response(0x0a);
+        golden.extend_from_slice(&[
+            0x31, 0x32, 0x33, 0x34, // num_runs
+        ]);
+        assert_eq!(golden, bytes(&h));
+        assert_eq!(0x3433_3231, h.num_runs());
+    }
+
+    // Tests the layout of MergeRequest.
+    #[test]
+    fn test_merge_request_layout() {
+        let h = MergeRequest::new(T, 0x3837_3635_3433_3231, 0x4241, I, S);
+        let mut golden = request(0x0b);
+        golden.extend_from_slice(&[
+            0x31, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37, 0x38, // table_id
+            0x41, 0x42, // key_length
+        ]);
+        assert_eq!(golden, bytes(&h));
+        assert_eq!(0x3837_3635_3433_3231, h.table_id());
+        assert_eq!(0x4241, h.key_length());
+    }
+
+    // Tests the layout of MergeResponse.
+    #[test]
+    fn test_merge_response_layout() {
+        let h = MergeResponse::new(I, S, T);
+        assert_eq!(response(0x0b), bytes(&h));
+    }
+
+    // Tests the layout of SetMergeRequest.
+    #[test]
+    fn test_set_merge_request_layout() {
+        let h = SetMergeRequest::new(T, 0x3837_3635_3433_3231, 0x4443_4241, I, S);
+        let mut golden = request(0x0c);
+        golden.extend_from_slice(&[
+            0x31, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37, 0x38, // table_id
+            0x41, 0x42, 0x43, 0x44, // name_length
+        ]);
+        assert_eq!(golden, bytes(&h));
+        assert_eq!(0x3837_3635_3433_3231, h.table_id());
+        assert_eq!(0x4443_4241, h.name_length());
+    }
+
+    // Tests the layout of SetMergeResponse.
+    #[test]
+    fn test_set_merge_response_layout() {
+        let h = SetMergeResponse::new(I, S, T);
+        assert_eq!(response(0x0c), bytes(&h));
+    }
+
+    // Tests the layout of RoutedInvokeRequest.
+    #[test]
+    fn test_routed_invoke_request_layout() {
+        let h = RoutedInvokeRequest::new(T, 0x3837_3635_3433_3231, 0x4241, 0x5453_5251, I, S);
+        let mut golden = request(0x0d);
+        golden.extend_from_slice(&[
+            0x31, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37, 0x38, // table_id
+            0x41, 0x42, // key_length
+            0x51, 0x52, 0x53, 0x54, // args_length
+        ]);
+        assert_eq!(golden, bytes(&h));
+        assert_eq!(0x3837_3635_3433_3231, h.table_id());
+        assert_eq!(0x4241, h.key_length());
+        assert_eq!(0x5453_5251, h.args_length());
+    }
+
+    // Tests the layout of SetRouteRequest.
+    #[test]
+    fn test_set_route_request_layout() {
+        let h = SetRouteRequest::new(T, 0x3231, 0x4443_4241, I, S);
+        let mut golden = request(0x0e);
+        golden.extend_from_slice(&[
+            0x31, 0x32, // prefix_length
+            0x41, 0x42, 0x43, 0x44, // name_length
+        ]);
+        assert_eq!(golden, bytes(&h));
+        assert_eq!(0x3231, h.prefix_length());
+        assert_eq!(0x4443_4241, h.name_length());
+    }
+
+    // Tests the layout of SetRouteResponse.
+    #[test]
+    fn test_set_route_response_layout() {
+        let h = SetRouteResponse::new(I, S, T);
+        assert_eq!(response(0x0e), bytes(&h));
+    }
+
+    // Tests the layout of AuditRequest.
+    #[test]
+    fn test_audit_request_layout() {
+        let h = AuditRequest::new(T, 0x3837_3635_3433_3231, I, S);
+        let mut golden = request(0x0f);
+        golden.extend_from_slice(&[
+            0x31, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37, 0x38, // cursor
+        ]);
+        assert_eq!(golden, bytes(&h));
+        assert_eq!(0x3837_3635_3433_3231, h.cursor());
+    }
+
+    // Tests the layout of AuditResponse.
+    #[test]
+    fn test_audit_response_layout() {
+        let mut h = AuditResponse::new(I, S, T);
+        h.set_num_entries(0x3433_3231);
+        h.set_next(0x4847_4645_4443_4241);
+        let mut golden = response(0x0f);
+        golden.extend_from_slice(&[
+            0x31, 0x32, 0x33, 0x34, // num_entries
+            0x41, 0x42, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48, // next
+        ]);
+        assert_eq!(golden, bytes(&h));
+        assert_eq!(0x3433_3231, h.num_entries());
+        assert_eq!(0x4847_4645_4443_4241, h.next());
+    }
+
+    // Tests the layout of DumpRequest.
+    #[test]
+    fn test_dump_request_layout() {
+        let h = DumpRequest::new(T, 0x3837_3635_3433_3231, 0x4443_4241, 0x5251, I, S);
+        let mut golden = request(0x10);
+        golden.extend_from_slice(&[
+            0x31, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37, 0x38, // table_id
+            0x41, 0x42, 0x43, 0x44, // bucket
+            0x51, 0x52, // key_length
+        ]);
+        assert_eq!(golden, bytes(&h));
+        assert_eq!(0x3837_3635_3433_3231, h.table_id());
+        assert_eq!(0x4443_4241, h.bucket());
+        assert_eq!(0x5251, h.key_length());
+    }
+
+    // Tests the layout of DumpResponse.
+    #[test]
+    fn test_dump_response_layout() {
+        let mut h = DumpResponse::new(I, S, T);
+        h.set_num_records(0x3433_3231);
+        h.set_bucket(0x4443_4241);
+        let mut golden = response(0x10);
+        golden.extend_from_slice(&[
+            0x31, 0x32, 0x33, 0x34, // num_records
+            0x41, 0x42, 0x43, 0x44, // bucket
+        ]);
+        assert_eq!(golden, bytes(&h));
+        assert_eq!(0x3433_3231, h.num_records());
+        assert_eq!(0x4443_4241, h.bucket());
+    }
+
+    // Tests the layout of MultiPutRequest.
+    #[test]
+    fn test_multi_put_request_layout() {
+        let h = MultiPutRequest::new(T, 0x3837_3635_3433_3231, 0x4443_4241, I, S);
+        let mut golden = request(0x11);
+        golden.extend_from_slice(&[
+            0x31, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37, 0x38, // table_id
+            0x41, 0x42, 0x43, 0x44, // num_records
+        ]);
+        assert_eq!(golden, bytes(&h));
+        assert_eq!(0x3837_3635_3433_3231, h.table_id());
+        assert_eq!(0x4443_4241, h.num_records());
+    }
+
+    // Tests the layout of MultiPutResponse.
+    #[test]
+    fn test_multi_put_response_layout() {
+        let mut h = MultiPutResponse::new(I, S, T);
+        h.set_num_written(0x3433_3231);
+        let mut golden = response(0x11);
+        golden.extend_from_slice(&[
+            0x31, 0x32, 0x33, 0x34, // num_written
+        ]);
+        assert_eq!(golden, bytes(&h));
+        assert_eq!(0x3433_3231, h.num_written());
+    }
+
+    // Tests the layout of WriteStatsRequest.
+    #[test]
+    fn test_write_stats_request_layout() {
+        let h = WriteStatsRequest::new(T, 0x3837_3635_3433_3231, 0x5b, I, S);
+        let mut golden = request(0x12);
+        golden.extend_from_slice(&[
+            0x31, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37, 0x38, // table_id
+            0x5b, // query
+        ]);
+        assert_eq!(golden, bytes(&h));
+        assert_eq!(0x3837_3635_3433_3231, h.table_id());
+    }
+
+    // Tests the layout of WriteStatsResponse.
+    #[test]
+    fn test_write_stats_response_layout() {
+        let mut h = WriteStatsResponse::new(I, S, T);
+        h.set_num_entries(0x3433_3231);
+        let mut golden = response(0x12);
+        golden.extend_from_slice(&[
+            0x31, 0x32, 0x33, 0x34, // num_entries
+        ]);
+        assert_eq!(golden, bytes(&h));
+        assert_eq!(0x3433_3231, h.num_entries());
+    }
+
+    // Tests the layout of CoreStatsRequest.
+    #[test]
+    fn test_core_stats_request_layout() {
+        let h = CoreStatsRequest::new(T, I, S);
+        assert_eq!(request(0x13), bytes(&h));
+    }
+
+    // Tests the layout of CoreStatsResponse.
+    #[test]
+    fn test_core_stats_response_layout() {
+        let mut h = CoreStatsResponse::new(I, S, T);
+        h.set_num_cores(0x3433_3231);
+        h.set_user_us(0x4847_4645_4443_4241);
+        h.set_system_us(0x5857_5655_5453_5251);
+        let mut golden = response(0x13);
+        golden.extend_from_slice(&[
+            0x31, 0x32, 0x33, 0x34, // num_cores
+            0x41, 0x42, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48, // user_us
+            0x51, 0x52, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, // system_us
+        ]);
+        assert_eq!(golden, bytes(&h));
+        assert_eq!(0x3433_3231, h.num_cores());
+        assert_eq!(0x4847_4645_4443_4241, h.user_us());
+        assert_eq!(0x5857_5655_5453_5251, h.system_us());
+    }
+
+    // Tests the layout of LatencyStatsRequest.
+    #[test]
+    fn test_latency_stats_request_layout() {
+        let h = LatencyStatsRequest::new(T, 0x5b, 0x5c, I, S);
+        let mut golden = request(0x14);
+        golden.extend_from_slice(&[
+            0x5b, // query
+            0x5c, // opcode
+        ]);
+        assert_eq!(golden, bytes(&h));
+    }
+
+    // Tests the layout of LatencyStatsResponse.
+    #[test]
+    fn test_latency_stats_response_layout() {
+        let mut h = LatencyStatsResponse::new(I, S, T);
+        h.set_num_entries(0x3433_3231);
+        h.set_cycles_per_second(0x4847_4645_4443_4241);
+        let mut golden = response(0x14);
+        golden.extend_from_slice(&[
+            0x31, 0x32, 0x33, 0x34, // num_entries
+            0x41, 0x42, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48, // cycles_per_second
+        ]);
+        assert_eq!(golden, bytes(&h));
+        assert_eq!(0x3433_3231, h.num_entries());
+        assert_eq!(0x4847_4645_4443_4241, h.cycles_per_second());
+    }
+
+    // Tests the layout of QuarantineRequest.
+    #[test]
+    fn test_quarantine_request_layout() {
+        let h = QuarantineRequest::new(T, 0x5b, 0x3433_3231, 0x4847_4645_4443_4241, I, S);
+        let mut golden = request(0x15);
+        golden.extend_from_slice(&[
+            0x5b, // action
+            0x31, 0x32, 0x33, 0x34, // target
+            0x41, 0x42, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48, // duration_ms
+        ]);
+        assert_eq!(golden, bytes(&h));
+        assert_eq!(0x3433_3231, h.target());
+        assert_eq!(0x4847_4645_4443_4241, h.duration_ms());
+    }
+
+    // Tests the layout of QuarantineResponse.
+    #[test]
+    fn test_quarantine_response_layout() {
+        let mut h = QuarantineResponse::new(I, S, T);
+        h.set_strikes(0x3433_3231);
+        h.set_remaining_ms(0x4847_4645_4443_4241);
+        h.set_quarantines(0x5857_5655_5453_5251);
+        h.set_rejected(0x6867_6665_6463_6261);
+        let mut golden = response(0x15);
+        golden.extend_from_slice(&[
+            0x31, 0x32, 0x33, 0x34, // strikes
+            0x41, 0x42, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48, // remaining_ms
+            0x51, 0x52, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, // quarantines
+            0x61, 0x62, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68, // rejected
+        ]);
+        assert_eq!(golden, bytes(&h));
+        assert_eq!(0x3433_3231, h.strikes());
+        assert_eq!(0x4847_4645_4443_4241, h.remaining_ms());
+        assert_eq!(0x5857_5655_5453_5251, h.quarantines());
+        assert_eq!(0x6867_6665_6463_6261, h.rejected());
+    }
+
+    // Tests the layout of CreateTableRequest.
+    #[test]
+    fn test_create_table_request_layout() {
+        let mut h = CreateTableRequest::new(T, 0x3837_3635_3433_3231, 0x4847_4645_4443_4241, I, S);
+        assert_eq!(
+            (0, 0, CACHE_EVICT_NONE),
+            (h.max_bytes(), h.max_objects(), h.evict)
+        );
+        h.set_max_bytes(0x5857_5655_5453_5251);
+        h.set_max_objects(0x6867_6665_6463_6261);
+        h.evict = CACHE_EVICT_READ;
+        let mut golden = request(0x16);
+        golden.extend_from_slice(&[
+            0x31, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37, 0x38, // table_id
+            0x41, 0x42, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48, // records
+            0x51, 0x52, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, // max_bytes
+            0x61, 0x62, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68, // max_objects
+            0x02, // evict
+        ]);
+        assert_eq!(golden, bytes(&h));
+        assert_eq!(0x3837_3635_3433_3231, h.table_id());
+        assert_eq!(0x4847_4645_4443_4241, h.records());
+        assert_eq!(0x5857_5655_5453_5251, h.max_bytes());
+        assert_eq!(0x6867_6665_6463_6261, h.max_objects());
+    }
+
+    // Tests the layout of CreateTableResponse.
+    #[test]
+    fn test_create_table_response_layout() {
+        let mut h = CreateTableResponse::new(I, S, T);
+        h.set_slots(0x4847_4645_4443_4241);
+        let mut golden = response(0x16);
+        golden.extend_from_slice(&[
+            0x41, 0x42, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48, // slots
+        ]);
+        assert_eq!(golden, bytes(&h));
+        assert_eq!(0x4847_4645_4443_4241, h.slots());
+    }
+
+    // Tests the layout of FetchResultRequest.
+    #[test]
+    fn test_fetch_result_request_layout() {
+        let h = FetchResultRequest::new(T, 0x3837_3635_3433_3231, I, S);
+        let mut golden = request(0x17);
+        golden.extend_from_slice(&[
+            0x31, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37, 0x38, // request_id
+        ]);
+        assert_eq!(golden, bytes(&h));
+        assert_eq!(0x3837_3635_3433_3231, h.request_id());
+    }
+
+    // Tests the layout of FetchResultResponse.
+    #[test]
+    fn test_fetch_result_response_layout() {
+        let mut h = FetchResultResponse::new(I, S, T);
+        h.result = RpcStatus::StatusExtensionError;
+        let mut golden = response(0x17);
+        golden.push(0x0e); // result
+        assert_eq!(golden, bytes(&h));
+    }
+
+    // Tests the layout of DeleteRequest.
+    #[test]
+    fn test_delete_request_layout() {
+        let h = DeleteRequest::new(T, 0x3837_3635_3433_3231, 0x4241, I, S);
+        let mut golden = request(0x18);
+        golden.extend_from_slice(&[
+            0x31, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37, 0x38, // table_id
+            0x41, 0x42, // key_length
+        ]);
+        assert_eq!(golden, bytes(&h));
+        assert_eq!(0x3837_3635_3433_3231, h.table_id());
+        assert_eq!(0x4241, h.key_length());
+    }
+
+    // Tests the layout of DeleteResponse.
+    #[test]
+    fn test_delete_response_layout() {
+        let h = DeleteResponse::new(I, S, T);
+        assert_eq!(response(0x18), bytes(&h));
+    }
+
+    // Tests the layout of ScanRequest.
+    #[test]
+    fn test_scan_request_layout() {
+        let h = ScanRequest::new(T, 0x3837_3635_3433_3231, 0x4241, 0x5453_5251, I, S)
+            .with_flags(SCAN_FLAG_AFTER_START);
+        let mut golden = request(0x19);
+        golden.extend_from_slice(&[
+            0x31, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37, 0x38, // table_id
+            0x51, 0x52, 0x53, 0x54, // max_records
+            0x41, 0x42, // key_length
+            0x01, // flags
+        ]);
+        assert_eq!(golden, bytes(&h));
+        assert_eq!(0x3837_3635_3433_3231, h.table_id());
+        assert_eq!(0x5453_5251, h.max_records());
+        assert_eq!(0x4241, h.key_length());
+    }
+
+    // Tests the layout of ScanResponse.
+    #[test]
+    fn test_scan_response_layout() {
+        let mut h = ScanResponse::new(I, S, T);
+        h.set_num_records(0x3433_3231);
+        h.set_token_length(0x4241);
+        h.set_skipped(0x5453_5251);
+        let mut golden = response(0x19);
+        golden.extend_from_slice(&[
+            0x31, 0x32, 0x33, 0x34, // num_records
+            0x41, 0x42, // token_length
+            0x51, 0x52, 0x53, 0x54, // skipped
+        ]);
+        assert_eq!(golden, bytes(&h));
+        assert_eq!(0x3433_3231, h.num_records());
+        assert_eq!(0x4241, h.token_length());
+        assert_eq!(0x5453_5251, h.skipped());
+    }
+
+    // Tests the layout of CasRequest.
+    #[test]
+    fn test_cas_request_layout() {
+        let h = CasRequest::new(T, 0x3837_3635_3433_3231, 0x4241, 0x5453_5251, I, S)
+            .with_flags(CAS_FLAG_EXPECT_ABSENT);
+        let mut golden = request(0x1a);
+        golden.extend_from_slice(&[
+            0x31, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37, 0x38, // table_id
+            0x41, 0x42, // key_length
+            0x51, 0x52, 0x53, 0x54, // expected_length
+            0x01, // flags
+        ]);
+        assert_eq!(golden, bytes(&h));
+        assert_eq!(0x3837_3635_3433_3231, h.table_id());
+        assert_eq!(0x4241, h.key_length());
+        assert_eq!(0x5453_5251, h.expected_length());
+    }
+
+    // Tests the layout of CasResponse.
+    #[test]
+    fn test_cas_response_layout() {
+        let h = CasResponse::new(I, S, T);
+        assert_eq!(response(0x1a), bytes(&h));
+    }
+
+    // Tests the layout of DropTableRequest.
+    #[test]
+    fn test_drop_table_request_layout() {
+        let h = DropTableRequest::new(T, 0x3837_3635_3433_3231, I, S);
+        let mut golden = request(0x1b);
+        golden.extend_from_slice(&[
+            0x31, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37, 0x38, // table_id
+        ]);
+        assert_eq!(golden, bytes(&h));
+        assert_eq!(0x3837_3635_3433_3231, h.table_id());
+    }
+
+    // Tests the layout of DropTableResponse.
+    #[test]
+    fn test_drop_table_response_layout() {
+        let h = DropTableResponse::new(I, S, T);
+        assert_eq!(response(0x1b), bytes(&h));
+    }
+
+    // Tests the layout of InvokeRequest.
+    #[test]
+    fn test_invoke_request_layout() {
+        let mut h = InvokeRequest::new(T, 0x3433_3231, 0x4443_4241, I, S);
+        h.flags = 0x5c;
+        let mut golden = request(0x03);
+        golden.extend_from_slice(&[
+            0x31, 0x32, 0x33, 0x34, // name_length
+            0x41, 0x42, 0x43, 0x44, // args_length
+            0x5c, // flags
+        ]);
+        assert_eq!(golden, bytes(&h));
+        assert_eq!(0x3433_3231, h.name_length());
+        assert_eq!(0x4443_4241, h.args_length());
+    }
+
+    // Tests the layout of InvokeResponse.
+    #[test]
+    fn test_invoke_response_layout() {
+        let mut h = InvokeResponse::new(I, S, OpCode::SandstormInvokeRpc, T);
+        h.flags = 0x5a;
+        h.set_seq(0x4443_4241);
+        let mut golden = response(0x03);
+        golden.extend_from_slice(&[
+            0x5a, // flags
+            0x41, 0x42, 0x43, 0x44, // seq
+        ]);
+        assert_eq!(golden, bytes(&h));
+        assert_eq!(0x4443_4241, h.seq());
+    }
+
+    // Tests the layout of InstallRequest.
+    #[test]
+    fn test_install_request_layout() {
+        let h = InstallRequest::new(T, 0x3433_3231, 0x4443_4241, I, S);
+        let mut golden = request(0x04);
+        golden.extend_from_slice(&[
+            0x31, 0x32, 0x33, 0x34, // name_length
+            0x41, 0x42, 0x43, 0x44, // extn_length
+        ]);
+        assert_eq!(golden, bytes(&h));
+        assert_eq!(0x3433_3231, h.name_length());
+        assert_eq!(0x4443_4241, h.extn_length());
+    }
+
+    // Tests the layout of InstallResponse.
+    #[test]
+    fn test_install_response_layout() {
+        let h = InstallResponse::new(I, S, OpCode::SandstormInstallRpc, T);
+        assert_eq!(response(0x04), bytes(&h));
+    }
+
+    // Tests the layout of MultiGetRequest.
+    #[test]
+    fn test_multi_get_request_layout() {
+        let h = MultiGetRequest::new(T, 0x3837_3635_3433_3231, 0x4241, 0x5453_5251, I, S)
+            .with_flags(0x5d);
+        let mut golden = request(0x05);
+        golden.extend_from_slice(&[
+            0x31, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37, 0x38, // table_id
+            0x41, 0x42, // key_len
+            0x51, 0x52, 0x53, 0x54, // num_keys
+            0x5d, // flags
+        ]);
+        assert_eq!(golden, bytes(&h));
+        assert_eq!(0x3837_3635_3433_3231, h.table_id());
+        assert_eq!(0x4241, h.key_len());
+        assert_eq!(0x5453_5251, h.num_keys());
+    }
+
+    // Tests the layout of MultiGetResponse.
+    #[test]
+    fn test_multi_get_response_layout() {
+        let mut h = MultiGetResponse::new(I, S, OpCode::SandstormMultiGetRpc, T, 0x3433_3231);
+        h.racy = 0x5b;
+        let mut golden = response(0x05);
+        golden.extend_from_slice(&[
+            0x31, 0x32, 0x33, 0x34, // num_records
+            0x5b, // racy
+        ]);
+        assert_eq!(golden, bytes(&h));
+        assert_eq!(0x3433_3231, h.num_records());
+    }
+}
@@ -12,10 +12,10 @@
  * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
  * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
  */
-#![forbid(unsafe_code)]
+#![cfg_attr(not(test), forbid(unsafe_code))]
 #![feature(generators)]
 #![feature(generator_trait)]
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 
 extern crate sandstorm;
 
@@ -25,7 +25,6 @@ use sandstorm::db::DB;
 use sandstorm::pack::pack;
 use sandstorm::rc::Rc;
 use sandstorm::size_of;
-use sandstorm::vec::*;
 use sandstorm::Generator;
 
 /// Status codes for the response to the tenant.
@@ -35,6 +34,11 @@ const INVALIDKEY: u8 = 0x03;
 
 const KEYLENGTH: u16 = 30;
 
+/// The number of keys aggregated across between two yields. The aggregate is checkpointed after
+/// every batch, along with the number of keys it covers, so that a durable invocation resumed
+/// after a restart picks up after the last batch, without counting any key twice.
+const BATCH: usize = 16;
+
 macro_rules! GET1 {
     ($db:ident, $table:ident, $key:ident, $obj:ident) => {
         let (server, _, val) = $db.search_get_in_cache($table, &$key);
@@ -75,8 +79,11 @@ pub fn init(db: Rc<DB>) -> Box<Generator<Yield = u64, Return = u64>> {
         // Error code and response defined upfront so that results are written only
         // at the end of this function.
         let mut err = INVALIDARG;
+        let mut table: u64 = 0;
+        let mut num_k: u32 = 0;
         let mut order: u32 = 0;
         let mut aggr: u64 = 0;
+        let mut done: usize = 0;
         let mut obj: Option<ReadBuf> = None;
         let mut buf: Option<MultiReadBuf> = None;
         {
@@ -86,13 +93,11 @@ pub fn init(db: Rc<DB>) -> Box<Generator<Yield = u64, Return = u64>> {
             let (o, key) = val.split_at(size_of::<u32>());
 
             // Get the table id from the unwrapped arguments.
-            let mut table: u64 = 0;
             for (idx, e) in t.iter().enumerate() {
                 table |= (*e as u64) << (idx << 3);
             }
 
             // Get the number of keys to aggregate across.
-            let mut num_k: u32 = 0;
             for (idx, e) in n.iter().enumerate() {
                 num_k |= (*e as u32) << (idx << 3);
             }
@@ -105,40 +110,66 @@ pub fn init(db: Rc<DB>) -> Box<Generator<Yield = u64, Return = u64>> {
             // Retrieve the list of keys to aggregate across.
             //let obj = db.get(table, key);
             GET1!(db, table, key, obj);
+        }
+
+        // If this is a durable invocation being resumed after a restart, then the checkpoint
+        // holds the number of keys aggregated across so far, and their aggregate.
+        if let Some((progress, state)) = db.restore() {
+            done = progress as usize;
+            for (idx, e) in state.iter().enumerate() {
+                aggr |= (*e as u64) << (idx << 3);
+            }
+        }
+
+        // Aggregate across the keys a batch at a time, if the key list was successfully
+        // retrieved. Yield after every batch.
+        let total = num_k as usize;
+        while obj.is_some() && done < total {
+            let next = if total - done > BATCH {
+                done + BATCH
+            } else {
+                total
+            };
+
+            {
+                let list = match obj {
+                    Some(ref val) => val.read(),
+                    None => break,
+                };
 
-            // Try performing the aggregate if the key list was successfully retrieved.
-            if let Some(val) = obj {
-                let mut col = Vec::new();
-                let value = val
-                    .read()
-                    .split_at((KEYLENGTH as usize) * (num_k as usize))
-                    .0;
+                if list.len() < total * KEYLENGTH as usize {
+                    db.resp(pack(&err));
+                    return 0;
+                }
 
-                MULTIGET1!(db, table, KEYLENGTH, value, buf);
+                let keys = &list[done * KEYLENGTH as usize..next * KEYLENGTH as usize];
+                MULTIGET1!(db, table, KEYLENGTH, keys, buf);
 
+                // Every key must exist for the aggregate to be meaningful.
                 match buf {
-                    Some(vals) => {
+                    Some(ref vals) if vals.missing() == 0 => {
                         if vals.num() > 0 {
-                            col.push(vals.read()[0]);
+                            aggr += vals.read()[0] as u64;
                         }
 
                         while vals.next() {
-                            col.push(vals.read()[0]);
+                            aggr += vals.read()[0] as u64;
                         }
                     }
 
-                    None => {
+                    _ => {
                         err = INVALIDKEY;
                         db.resp(pack(&err));
                         return 0;
                     }
                 }
 
-                // Aggregate the saved column.
-                aggr = col.iter().fold(0, |a, e| a + (*e as u64));
+                // Checkpoint the batch in case the invocation is durable.
+                done = next;
+                db.checkpoint(done as u64, pack(&aggr.to_le()));
             }
+            yield 0;
         }
-        yield 0;
 
         // Compute pow(aggr, order).
         for _mul in 1..order {
@@ -156,3 +187,87 @@ pub fn init(db: Rc<DB>) -> Box<Generator<Yield = u64, Return = u64>> {
         yield 0;
     })
 }
+
+// This module contains unit tests for the aggregate extension, run against a MockDB.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::ops::GeneratorState;
+
+    use sandstorm::mock::MockDB;
+
+    // The number of keys aggregated across; three batches, the last one partial.
+    const NUM: u8 = 40;
+
+    // Returns a database holding NUM objects whose values start with 1 to NUM, and a list of
+    // their keys under "list", with arguments to aggregate across all of them.
+    fn db() -> MockDB {
+        let mut args = vec![1, 0, 0, 0, 0, 0, 0, 0, NUM, 0, 0, 0, 1, 0, 0, 0];
+        args.extend_from_slice(b"list");
+
+        let db = MockDB::with_args(&args);
+        let mut list = Vec::new();
+        for i in 1..(NUM + 1) {
+            let key = [i; KEYLENGTH as usize];
+            db.insert(1, &key, &[i, 0, 0, 0]);
+            list.extend_from_slice(&key);
+        }
+        db.insert(1, b"list", &list);
+        db
+    }
+
+    // Runs the extension on a database until it has yielded `yields` times, or to completion
+    // if None. Returns the database, and true if the extension completed.
+    fn run(db: MockDB, yields: Option<usize>) -> (Rc<MockDB>, bool) {
+        let db = Rc::new(db);
+        let mut gen = init(Rc::clone(&db) as Rc<DB>);
+        let mut yielded = 0;
+        while yields.map_or(true, |yields| yielded < yields) {
+            match unsafe { gen.resume() } {
+                GeneratorState::Yielded(_) => yielded += 1,
+                GeneratorState::Complete(_) => return (db, true),
+            }
+        }
+
+        (db, false)
+    }
+
+    // Returns the response of a successful aggregate.
+    fn response(aggr: u64) -> Vec<u8> {
+        let mut response = vec![SUCCESSFUL];
+        response.extend_from_slice(pack(&aggr));
+        response
+    }
+
+    // Tests that an aggregate interrupted after any number of batches, and resumed off it's
+    // last checkpoint, adds up to the same aggregate as one that ran uninterrupted, without
+    // counting any key twice or leaving any out.
+    #[test]
+    fn test_aggregate_resume() {
+        let sum = (NUM as u64) * (NUM as u64 + 1) / 2;
+        let (whole, completed) = run(db(), None);
+        assert!(completed);
+        assert_eq!(response(sum), whole.response());
+
+        let progress: Vec<u64> = whole.checkpoints().iter().map(|c| c.0).collect();
+        assert_eq!(vec![16, 32, 40], progress);
+
+        for yields in 0..3 {
+            // Kill the extension, dropping everything but it's checkpoints.
+            let (killed, completed) = run(db(), Some(yields));
+            assert!(!completed);
+            assert_eq!(yields, killed.checkpoints().len());
+
+            let resumed = match killed.checkpoints().last() {
+                Some(&(progress, ref state)) => db().resume_from(progress, state),
+                None => db(),
+            };
+
+            let (resumed, completed) = run(resumed, None);
+            assert!(completed);
+            assert_eq!(response(sum), resumed.response());
+            assert_eq!(yields + resumed.checkpoints().len(), 3);
+        }
+    }
+}
@@ -0,0 +1,345 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! A TopK extension that scans a table and returns the keys of the K records
+//! with the largest values.
+//!
+//! Records are ranked by the first 8 bytes of their value, read as a
+//! big-endian integer (values shorter than 8 bytes are padded with zeros).
+//! Records with the same prefix are ranked by key, the smaller key winning,
+//! so the result does not depend on the order the table is scanned in.
+//!
+//! The arguments are the 8 byte (little-endian) table id, the 4 byte
+//! (little-endian) K, and the 2 byte (little-endian) length of the table's
+//! keys; records with keys of any other length are skipped. The response is
+//! a status byte followed, on success, by a 4 byte (little-endian) count and
+//! that many (key, 8 byte big-endian prefix) pairs, largest first.
+//!
+//! The table is scanned a slice at a time, yielding in between, so puts can
+//! land mid-scan. Every record present for the entire scan is considered
+//! exactly once, with whatever value it held when it's slice was scanned.
+//! Records inserted or deleted mid-scan are considered at most once.
+
+#![crate_type = "dylib"]
+#![cfg_attr(not(test), forbid(unsafe_code))]
+#![feature(generators, generator_trait)]
+
+extern crate sandstorm;
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::ops::Generator;
+use std::rc::Rc;
+
+use sandstorm::db::DB;
+
+/// Status written at the head of a response whose scan completed.
+const SUCCESSFUL: u8 = 0x01;
+
+/// Status written at the head of a response whose arguments were malformed.
+const INVALIDARG: u8 = 0x02;
+
+/// Status written at the head of a response whose K would not fit in a response.
+const TOOLARGE: u8 = 0x03;
+
+/// The length of the arguments: table id, K, and key length.
+const ARGS_LEN: usize = 14;
+
+/// The maximum number of bytes of results returned in a response. Requests
+/// and responses are a single packet, so this is the response budget.
+const RESP_BUDGET: usize = 1024;
+
+/// The length of the prefix of each value records are ranked by.
+const PREFIX_LEN: usize = 8;
+
+/// Reads a little-endian integer off the first `n` bytes of a slice.
+fn read_le(b: &[u8], n: usize) -> u64 {
+    b[..n]
+        .iter()
+        .enumerate()
+        .fold(0, |acc, (idx, e)| acc | (*e as u64) << (idx << 3))
+}
+
+/// Reads the big-endian prefix of a value, padding short values with zeros.
+fn prefix(val: &[u8]) -> u64 {
+    (0..PREFIX_LEN).fold(0, |acc, idx| {
+        (acc << 8) | *val.get(idx).unwrap_or(&0) as u64
+    })
+}
+
+/// Returns the largest K that fits in a response for a given key length.
+fn max_k(key_len: usize) -> usize {
+    RESP_BUDGET / (key_len + PREFIX_LEN)
+}
+
+/// The K records seen so far with the largest prefixes, held in a min-heap
+/// so that the smallest of them can be evicted in O(log K).
+struct TopK {
+    k: usize,
+    heap: BinaryHeap<Reverse<(u64, Reverse<Vec<u8>>)>>,
+}
+
+impl TopK {
+    fn new(k: usize) -> TopK {
+        TopK {
+            k: k,
+            heap: BinaryHeap::with_capacity(k + 1),
+        }
+    }
+
+    /// Considers a record for the top K.
+    fn offer(&mut self, key: &[u8], val: &[u8]) {
+        let rank = (prefix(val), Reverse(key.to_vec()));
+
+        // Avoid copying the key of a record that would be evicted straight away.
+        if self.heap.len() == self.k {
+            match self.heap.peek() {
+                Some(&Reverse(ref min)) if rank <= *min => return,
+                _ => {}
+            }
+        }
+
+        self.heap.push(Reverse(rank));
+        if self.heap.len() > self.k {
+            self.heap.pop();
+        }
+    }
+
+    /// Returns the (key, prefix) pairs, largest first.
+    fn into_sorted(self) -> Vec<(Vec<u8>, u64)> {
+        self.heap
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Reverse((p, Reverse(key)))| (key, p))
+            .collect()
+    }
+}
+
+/// This function implements the TopK extension using the sandstorm interface.
+///
+/// # Arguments
+///
+/// * `db`: An argument whose type implements the `DB` trait which can be used
+///         to interact with the database.
+///
+/// # Return
+///
+/// A coroutine that can be run inside the database.
+#[no_mangle]
+#[allow(unreachable_code)]
+#[allow(unused_assignments)]
+pub fn init(db: Rc<DB>) -> Box<Generator<Yield = u64, Return = u64>> {
+    Box::new(move || {
+        let (table, k, key_len) = {
+            let args = db.args();
+            if args.len() != ARGS_LEN {
+                db.resp(&[INVALIDARG]);
+                return 1;
+            }
+
+            (
+                read_le(&args[0..8], 8),
+                read_le(&args[8..12], 4) as usize,
+                read_le(&args[12..14], 2) as usize,
+            )
+        };
+
+        // Validate K up front, before doing any work, so that a request that
+        // could never be answered fails cleanly.
+        if k == 0 || key_len == 0 {
+            db.resp(&[INVALIDARG]);
+            return 1;
+        }
+
+        if k > max_k(key_len) {
+            db.resp(&[TOOLARGE]);
+            return 1;
+        }
+
+        let mut top = TopK::new(k);
+        let mut cursor = Some(0);
+        while let Some(c) = cursor {
+            cursor = db.scan(table, c, &mut |key: &[u8], val: &[u8]| {
+                if key.len() == key_len {
+                    top.offer(key, val);
+                }
+            });
+            yield 0;
+        }
+
+        let top = top.into_sorted();
+        let mut resp = Vec::with_capacity(5 + top.len() * (key_len + PREFIX_LEN));
+        resp.push(SUCCESSFUL);
+        for i in 0..4 {
+            resp.push((top.len() >> (8 * i)) as u8);
+        }
+        for (key, p) in top {
+            resp.extend_from_slice(&key);
+            for i in (0..PREFIX_LEN).rev() {
+                resp.push((p >> (8 * i)) as u8);
+            }
+        }
+        db.resp(&resp);
+
+        return 0;
+
+        // XXX: This yield is required to get the compiler to compile this closure into a
+        // generator. It is unreachable and benign.
+        yield 0;
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::ops::GeneratorState;
+
+    use sandstorm::mock::MockDB;
+
+    // Builds the arguments to the extension.
+    fn args(table: u64, k: u32, key_len: u16) -> Vec<u8> {
+        let mut args = vec![];
+        for i in 0..8 {
+            args.push((table >> (8 * i)) as u8);
+        }
+        for i in 0..4 {
+            args.push((k >> (8 * i)) as u8);
+        }
+        args.push(key_len as u8);
+        args.push((key_len >> 8) as u8);
+        args
+    }
+
+    // Runs the extension to completion, returning it's return value and response.
+    fn run(db: MockDB) -> (u64, Vec<u8>) {
+        let db = Rc::new(db);
+        let mut gen = init(Rc::clone(&db) as Rc<DB>);
+        let ret;
+        loop {
+            match unsafe { gen.resume() } {
+                GeneratorState::Yielded(_) => continue,
+                GeneratorState::Complete(r) => {
+                    ret = r;
+                    break;
+                }
+            }
+        }
+
+        (ret, db.response())
+    }
+
+    // Parses a successful response into (key, prefix) pairs.
+    fn parse(resp: &[u8], key_len: usize) -> Vec<(Vec<u8>, u64)> {
+        assert_eq!(SUCCESSFUL, resp[0]);
+        let n = read_le(&resp[1..5], 4) as usize;
+        let pairs: Vec<(Vec<u8>, u64)> = resp[5..]
+            .chunks(key_len + PREFIX_LEN)
+            .map(|c| (c[..key_len].to_vec(), prefix(&c[key_len..])))
+            .collect();
+        assert_eq!(n, pairs.len());
+        pairs
+    }
+
+    // A value whose big-endian prefix is `p`, followed by some padding.
+    fn value(p: u64) -> Vec<u8> {
+        let mut v: Vec<u8> = (0..8).rev().map(|i| (p >> (8 * i)) as u8).collect();
+        v.extend_from_slice(&[0xff; 4]);
+        v
+    }
+
+    #[test]
+    fn test_topk() {
+        let db = MockDB::with_args(&args(1, 3, 2));
+        for i in 0..20u8 {
+            db.insert(1, &[i, 0], &value((i as u64 * 7) % 20));
+        }
+
+        let (ret, resp) = run(db);
+        assert_eq!(0, ret);
+        assert_eq!(
+            vec![(vec![17, 0], 19), (vec![14, 0], 18), (vec![11, 0], 17)],
+            parse(&resp, 2)
+        );
+    }
+
+    #[test]
+    fn test_topk_k_larger_than_table() {
+        let db = MockDB::with_args(&args(1, 10, 2));
+        for i in 0..5u8 {
+            db.insert(1, &[i, 0], &value(i as u64));
+        }
+
+        let (ret, resp) = run(db);
+        assert_eq!(0, ret);
+        let keys: Vec<Vec<u8>> = parse(&resp, 2).into_iter().map(|(k, _)| k).collect();
+        assert_eq!(
+            vec![vec![4, 0], vec![3, 0], vec![2, 0], vec![1, 0], vec![0, 0]],
+            keys
+        );
+    }
+
+    #[test]
+    fn test_topk_ties() {
+        let db = MockDB::with_args(&args(1, 3, 2));
+        db.insert(1, &[9, 0], &value(1));
+        for i in 0..6u8 {
+            db.insert(1, &[i, 0], &value(5));
+        }
+
+        // Ties are broken by key, the smaller key winning.
+        let (_, resp) = run(db);
+        assert_eq!(
+            vec![(vec![0, 0], 5), (vec![1, 0], 5), (vec![2, 0], 5)],
+            parse(&resp, 2)
+        );
+    }
+
+    #[test]
+    fn test_topk_empty_table() {
+        let db = MockDB::with_args(&args(1, 3, 2));
+        let (ret, resp) = run(db);
+        assert_eq!(0, ret);
+        assert_eq!(vec![SUCCESSFUL, 0, 0, 0, 0], resp);
+    }
+
+    #[test]
+    fn test_topk_short_values_and_keys() {
+        let db = MockDB::with_args(&args(1, 2, 2));
+        db.insert(1, &[1, 0], &[0x01]);
+        db.insert(1, &[2, 0], &[0x02, 0x00]);
+        db.insert(1, &[3, 0, 0], &value(100));
+
+        // Short values are padded with zeros, and keys of the wrong length are skipped.
+        let (_, resp) = run(db);
+        assert_eq!(
+            vec![(vec![2, 0], 0x02u64 << 56), (vec![1, 0], 0x01u64 << 56)],
+            parse(&resp, 2)
+        );
+    }
+
+    #[test]
+    fn test_topk_invalid_args() {
+        let (ret, resp) = run(MockDB::with_args(&args(1, 3, 2)[..10]));
+        assert_eq!((1, vec![INVALIDARG]), (ret, resp));
+
+        let (ret, resp) = run(MockDB::with_args(&args(1, 0, 2)));
+        assert_eq!((1, vec![INVALIDARG]), (ret, resp));
+
+        let too_many = max_k(30) as u32 + 1;
+        let (ret, resp) = run(MockDB::with_args(&args(1, too_many, 30)));
+        assert_eq!((1, vec![TOOLARGE]), (ret, resp));
+    }
+}
@@ -17,15 +17,13 @@
 #![feature(no_unsafe)]
 #![feature(generators, generator_trait)]
 
-extern crate crypto;
 #[macro_use]
 extern crate sandstorm;
 
-use crypto::bcrypt::bcrypt;
-
 use std::ops::Generator;
 use std::rc::Rc;
 
+use sandstorm::auth;
 use sandstorm::db::DB;
 use sandstorm::pack::pack;
 
@@ -35,7 +33,10 @@ const SUCCESSFUL: u8 = 0x02;
 const UNSUCCESSFUL: u8 = 0x03;
 const ABSENTOBJECT: u8 = 0x4;
 
-/// This function implements the get() extension using the sandstorm interface.
+/// This function implements the get() extension using the sandstorm interface. The stored
+/// record names the bcrypt cost it was hashed at (see sandstorm::auth), and the password is
+/// hashed a few rounds at a time, yielding in between, so that expensive hashes don't hold up
+/// the core for their whole duration.
 ///
 /// # Arguments
 ///
@@ -46,15 +47,14 @@ const ABSENTOBJECT: u8 = 0x4;
 ///
 /// A coroutine that can be run inside the database.
 #[no_mangle]
-#[allow(unreachable_code)]
 #[allow(unused_assignments)]
 pub fn init(db: Rc<DB>) -> Box<Generator<Yield = u64, Return = u64>> {
     Box::new(move || {
         let mut obj = None;
         let mut table: u64 = 0;
         let mut status = INVALIDARG;
-        let mut username: Vec<u8> = Vec::with_capacity(30);
-        let mut password: Vec<u8> = Vec::with_capacity(72);
+        let mut username: Vec<u8> = Vec::with_capacity(auth::USERNAME_LEN);
+        let mut password: Vec<u8> = Vec::with_capacity(auth::PASSWORD_LEN);
 
         {
             // First off, retrieve the arguments to the extension.
@@ -64,7 +64,7 @@ pub fn init(db: Rc<DB>) -> Box<Generator<Yield = u64, Return = u64>> {
             // 8 byte table id, a 30 byte key to be looked up and a 72 byte
             // password to match. If not, then write an error message to the
             // response and return to the database.
-            if args.len() != 110 {
+            if args.len() != auth::ARGS_LEN {
                 db.resp(pack(&status));
                 return 1;
             }
@@ -73,7 +73,7 @@ pub fn init(db: Rc<DB>) -> Box<Generator<Yield = u64, Return = u64>> {
             // (first eight bytes), and a view over the key to be looked up.
             // De-serialize the table identifier into a u64.
             let (s_table, remain_args) = args.split_at(8);
-            let (userid, pass) = remain_args.split_at(30);
+            let (userid, pass) = remain_args.split_at(auth::USERNAME_LEN);
             username.extend_from_slice(userid);
             password.extend_from_slice(pass);
 
@@ -87,32 +87,19 @@ pub fn init(db: Rc<DB>) -> Box<Generator<Yield = u64, Return = u64>> {
         GET!(db, table, username, obj);
         yield 0;
 
-        // Populate a response to the tenant.
-        match obj {
-            // If the object was found, find it's hash and write it to the response.
+        // Parse the record, and set up a hasher for the password. The hasher and the hash are
+        // copied out of the record, since neither can borrow it across the yields below.
+        let (mut hasher, hash) = match obj {
             Some(val) => {
-                // The value is 40 bytes long; 24 bytes for hash and 16 bytes for the salt.
-                let bytes = val.read();
-                if bytes.len() != 40 {
-                    db.resp(pack(&status));
-                    return 0;
-                }
-                let hash = &bytes[0..24];
-                let salt = &bytes[24..40];
-
-                // Compute the hash using salt and password, store in output.
-                let output: &mut [u8] = &mut [0; 24];
-                bcrypt(1, salt, &password, output);
-
-                // Compare the calculated hash and DB stored hash.
-                if output == hash {
-                    status = SUCCESSFUL;
-                    db.resp(pack(&status));
-                } else {
-                    status = UNSUCCESSFUL;
-                    db.resp(pack(&status));
+                let parsed = auth::parse(val.read())
+                    .map(|record| (record.hasher(&password), record.hash.to_vec()));
+                match parsed {
+                    Some(parsed) => parsed,
+                    None => {
+                        db.resp(pack(&status));
+                        return 0;
+                    }
                 }
-                return 0;
             }
 
             // If the object was not found, write an error message to the
@@ -122,10 +109,20 @@ pub fn init(db: Rc<DB>) -> Box<Generator<Yield = u64, Return = u64>> {
                 db.resp(pack(&status));
                 return 0;
             }
+        };
+
+        // Run the key schedule a few rounds at a time, yielding to the scheduler in between.
+        while !hasher.step(auth::ROUNDS_PER_STEP) {
+            yield 0;
         }
 
-        // XXX: This yield is required to get the compiler to compile this closure into a
-        // generator. It is unreachable and benign.
-        yield 0;
+        // Compare the calculated hash and DB stored hash.
+        if hasher.matches(&hash) {
+            status = SUCCESSFUL;
+        } else {
+            status = UNSUCCESSFUL;
+        }
+        db.resp(pack(&status));
+        return 0;
     })
 }
@@ -0,0 +1,346 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! An extension that dumps every record of a table, streaming the records
+//! back a range of slices at a time as the table is scanned, instead of
+//! buffering the whole table into a single response.
+//!
+//! The arguments are the 8 byte (little-endian) table id, and the 4 byte
+//! (little-endian) number of scan slices to send back per chunk. Each chunk
+//! is a status byte, the 8 byte (little-endian) cursor of the slice the
+//! chunk's first record came from, a 2 byte (little-endian) count, and that
+//! many records. Each record is a 2 byte (little-endian) key length, a 2 byte
+//! (little-endian) value length, the key and the value.
+//!
+//! A chunk is flushed once it covers the requested number of slices, or
+//! once the next record would not fit in a response; the records of a slice
+//! can therefore span two chunks. Every chunk but the last carries MORE. The
+//! last one carries SUCCESSFUL, and is the response left when the extension
+//! completes. If the server will not stream a chunk (see DB::resp_flush()),
+//! the dump stops, and that chunk is the whole response, still carrying MORE.
+//!
+//! The table is scanned a slice at a time, yielding in between, so puts can
+//! land mid-dump. Every record present for the entire dump is sent back
+//! exactly once, with whatever value it held when it's slice was scanned.
+
+#![crate_type = "dylib"]
+#![cfg_attr(not(test), forbid(unsafe_code))]
+#![feature(generators, generator_trait)]
+
+extern crate sandstorm;
+
+use std::ops::Generator;
+use std::rc::Rc;
+
+use sandstorm::db::DB;
+
+/// Status on the last chunk of a dump that completed.
+const SUCCESSFUL: u8 = 0x01;
+
+/// Status on a chunk whose arguments were malformed.
+const INVALIDARG: u8 = 0x02;
+
+/// Status on a chunk whose next record would not fit in a response by itself.
+const TOOLARGE: u8 = 0x03;
+
+/// Status on every chunk but the last.
+const MORE: u8 = 0x04;
+
+/// The length of the arguments: table id, and slices per chunk.
+const ARGS_LEN: usize = 12;
+
+/// The length of the header on each chunk: status, cursor, and count.
+const CHUNK_HDR_LEN: usize = 11;
+
+/// The maximum number of bytes on a chunk. Each chunk is sent out in a
+/// single packet, so this is the response budget.
+const RESP_BUDGET: usize = 1024;
+
+/// Reads a little-endian integer off the first `n` bytes of a slice.
+fn read_le(b: &[u8], n: usize) -> u64 {
+    b[..n]
+        .iter()
+        .enumerate()
+        .fold(0, |acc, (idx, e)| acc | (*e as u64) << (idx << 3))
+}
+
+/// The records of the chunk being filled, along with it's header.
+struct Chunk {
+    first: u64,
+    count: u16,
+    records: Vec<u8>,
+}
+
+impl Chunk {
+    fn new(first: u64) -> Chunk {
+        Chunk {
+            first: first,
+            count: 0,
+            records: Vec::with_capacity(RESP_BUDGET),
+        }
+    }
+
+    /// Returns true if a record fits on the chunk.
+    fn fits(&self, key: &[u8], val: &[u8]) -> bool {
+        CHUNK_HDR_LEN + self.records.len() + 4 + key.len() + val.len() <= RESP_BUDGET
+    }
+
+    /// Adds a record to the chunk.
+    fn push(&mut self, key: &[u8], val: &[u8]) {
+        self.records.push(key.len() as u8);
+        self.records.push((key.len() >> 8) as u8);
+        self.records.push(val.len() as u8);
+        self.records.push((val.len() >> 8) as u8);
+        self.records.extend_from_slice(key);
+        self.records.extend_from_slice(val);
+        self.count += 1;
+    }
+
+    /// Writes the chunk to the response.
+    fn write(&self, db: &DB, status: u8) {
+        let mut hdr = Vec::with_capacity(CHUNK_HDR_LEN);
+        hdr.push(status);
+        for i in 0..8 {
+            hdr.push((self.first >> (8 * i)) as u8);
+        }
+        hdr.push(self.count as u8);
+        hdr.push((self.count >> 8) as u8);
+        db.resp(&hdr);
+        db.resp(&self.records);
+    }
+}
+
+/// This function implements the dump extension using the sandstorm interface.
+///
+/// # Arguments
+///
+/// * `db`: An argument whose type implements the `DB` trait which can be used
+///         to interact with the database.
+///
+/// # Return
+///
+/// A coroutine that can be run inside the database.
+#[no_mangle]
+#[allow(unreachable_code)]
+#[allow(unused_assignments)]
+pub fn init(db: Rc<DB>) -> Box<Generator<Yield = u64, Return = u64>> {
+    Box::new(move || {
+        let (table, range) = {
+            let args = db.args();
+            if args.len() != ARGS_LEN {
+                db.resp(&[INVALIDARG]);
+                return 1;
+            }
+
+            (read_le(&args[0..8], 8), read_le(&args[8..12], 4))
+        };
+
+        if range == 0 {
+            db.resp(&[INVALIDARG]);
+            return 1;
+        }
+
+        let mut chunk = Chunk::new(0);
+        let mut slices = 0;
+        let mut cursor = Some(0);
+        while let Some(c) = cursor {
+            // Records are copied out of the slice so that chunks can be flushed in between.
+            let mut records: Vec<(Vec<u8>, Vec<u8>)> = Vec::new();
+            cursor = db.scan(table, c, &mut |key: &[u8], val: &[u8]| {
+                records.push((key.to_vec(), val.to_vec()));
+            });
+
+            for (key, val) in records.into_iter() {
+                if chunk.fits(&key, &val) {
+                    chunk.push(&key, &val);
+                    continue;
+                }
+
+                if chunk.count == 0 {
+                    db.resp(&[TOOLARGE]);
+                    return 1;
+                }
+
+                chunk.write(&*db, MORE);
+                if !db.resp_flush() {
+                    return 1;
+                }
+
+                chunk = Chunk::new(c);
+                if !chunk.fits(&key, &val) {
+                    db.resp(&[TOOLARGE]);
+                    return 1;
+                }
+                chunk.push(&key, &val);
+            }
+
+            // Flush the chunk once it covers the requested range of slices, unless the dump is
+            // over, in which case it is the last chunk.
+            slices += 1;
+            if let Some(next) = cursor {
+                if slices >= range {
+                    chunk.write(&*db, MORE);
+                    if !db.resp_flush() {
+                        return 1;
+                    }
+
+                    chunk = Chunk::new(next);
+                    slices = 0;
+                }
+            }
+
+            yield 0;
+        }
+
+        chunk.write(&*db, SUCCESSFUL);
+        return 0;
+
+        // XXX: This yield is required to get the compiler to compile this closure into a
+        // generator. It is unreachable and benign.
+        yield 0;
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::ops::GeneratorState;
+
+    use sandstorm::mock::MockDB;
+
+    // Builds the arguments to the extension.
+    fn args(table: u64, range: u32) -> Vec<u8> {
+        let mut args = vec![];
+        for i in 0..8 {
+            args.push((table >> (8 * i)) as u8);
+        }
+        for i in 0..4 {
+            args.push((range >> (8 * i)) as u8);
+        }
+        args
+    }
+
+    // Runs the extension to completion, returning it's return value, the chunks it flushed, and
+    // it's final response.
+    fn run(db: MockDB) -> (u64, Vec<Vec<u8>>, Vec<u8>) {
+        let db = Rc::new(db);
+        let mut gen = init(Rc::clone(&db) as Rc<DB>);
+        let ret;
+        loop {
+            match unsafe { gen.resume() } {
+                GeneratorState::Yielded(_) => continue,
+                GeneratorState::Complete(r) => {
+                    ret = r;
+                    break;
+                }
+            }
+        }
+
+        (ret, db.flushed(), db.response())
+    }
+
+    // Parses a chunk into it's status, first cursor, and records.
+    fn parse(chunk: &[u8]) -> (u8, u64, Vec<(Vec<u8>, Vec<u8>)>) {
+        let count = read_le(&chunk[9..11], 2) as usize;
+        let mut records = Vec::new();
+        let mut rest = &chunk[CHUNK_HDR_LEN..];
+        for _ in 0..count {
+            let k = read_le(&rest[0..2], 2) as usize;
+            let v = read_le(&rest[2..4], 2) as usize;
+            records.push((rest[4..4 + k].to_vec(), rest[4 + k..4 + k + v].to_vec()));
+            rest = &rest[4 + k + v..];
+        }
+        assert!(rest.is_empty());
+        (chunk[0], read_le(&chunk[1..9], 8), records)
+    }
+
+    // Tests that a table is streamed back in order, a range of slices per chunk. MockDB scans
+    // four records per slice.
+    #[test]
+    fn test_dump_ranges() {
+        let db = MockDB::with_args(&args(1, 2));
+        for i in 0..20u8 {
+            db.insert(1, &[i], &[i, i]);
+        }
+
+        let (ret, flushed, last) = run(db);
+        assert_eq!(0, ret);
+        assert_eq!(2, flushed.len());
+
+        let mut keys = Vec::new();
+        for (chunk, first) in flushed.iter().zip([0u64, 8].iter()) {
+            let (status, cursor, records) = parse(chunk);
+            assert_eq!((MORE, *first, 8), (status, cursor, records.len()));
+            keys.extend(records.into_iter().map(|(k, v)| {
+                assert_eq!(vec![k[0], k[0]], v);
+                k[0]
+            }));
+        }
+
+        let (status, cursor, records) = parse(&last);
+        assert_eq!((SUCCESSFUL, 16, 4), (status, cursor, records.len()));
+        keys.extend(records.into_iter().map(|(k, _)| k[0]));
+        assert_eq!((0..20u8).collect::<Vec<u8>>(), keys);
+    }
+
+    // Tests that a chunk is flushed early once the next record would not fit, and that the
+    // slice then continues on the next chunk.
+    #[test]
+    fn test_dump_budget() {
+        let db = MockDB::with_args(&args(1, 100));
+        for i in 0..8u8 {
+            db.insert(1, &[i], &[i; 300]);
+        }
+
+        let (ret, flushed, last) = run(db);
+        assert_eq!(0, ret);
+
+        let mut chunks: Vec<Vec<u8>> = flushed;
+        chunks.push(last);
+        let parsed: Vec<(u8, u64, usize)> = chunks
+            .iter()
+            .map(|c| {
+                assert!(c.len() <= RESP_BUDGET);
+                let (status, cursor, records) = parse(c);
+                (status, cursor, records.len())
+            }).collect();
+        assert_eq!(vec![(MORE, 0, 3), (MORE, 0, 3), (SUCCESSFUL, 4, 2)], parsed);
+    }
+
+    // Tests that an empty table is a single, empty chunk.
+    #[test]
+    fn test_dump_empty_table() {
+        let (ret, flushed, last) = run(MockDB::with_args(&args(1, 1)));
+        assert_eq!(0, ret);
+        assert!(flushed.is_empty());
+        assert_eq!((SUCCESSFUL, 0, vec![]), parse(&last));
+    }
+
+    #[test]
+    fn test_dump_invalid_args() {
+        let (ret, _, resp) = run(MockDB::with_args(&args(1, 1)[..8]));
+        assert_eq!((1, vec![INVALIDARG]), (ret, resp));
+
+        let (ret, _, resp) = run(MockDB::with_args(&args(1, 0)));
+        assert_eq!((1, vec![INVALIDARG]), (ret, resp));
+
+        let db = MockDB::with_args(&args(1, 1));
+        db.insert(1, &[0], &[0; RESP_BUDGET]);
+        let (ret, flushed, resp) = run(db);
+        assert_eq!((1, vec![TOOLARGE]), (ret, resp));
+        assert!(flushed.is_empty());
+    }
+}
@@ -17,76 +17,39 @@
 #![forbid(unsafe_code)]
 #![feature(generators, generator_trait)]
 
+#[macro_use]
 extern crate sandstorm;
 
-use std::rc::Rc;
-use std::ops::Generator;
+use std::u32;
 
 use sandstorm::db::DB;
+use sandstorm::entry::{Args, ExtError, Response};
+use sandstorm::rc::Rc;
+use sandstorm::schema::Field;
+
+declare_extension!("get", get, [Field::Table, Field::Blob(1, u32::MAX)]);
 
 /// This function implements the get() extension using the sandstorm interface.
 ///
 /// # Arguments
 ///
-/// * `db`: An argument whose type implements the `DB` trait which can be used
-///         to interact with the database.
+/// * `db`:   An argument whose type implements the `DB` trait which can be used
+///           to interact with the database.
+/// * `args`: An 8 byte table id, followed by the key to be looked up.
 ///
 /// # Return
 ///
-/// A coroutine that can be run inside the database.
-#[no_mangle]
-#[allow(unreachable_code)]
-#[allow(unused_assignments)]
-pub fn init(db: Rc<DB>) -> Box<Generator<Yield=u64, Return=u64>> {
-    Box::new(move || {
-        let mut obj = None;
-
-        {
-            // First off, retrieve the arguments to the extension.
-            let args = db.args();
-
-            // Check that the arguments received is long enough to contain an
-            // 8 byte table id and a key to be looked up. If not, then write
-            // an error message to the response and return to the database.
-            if args.len() <= 8 {
-                let error = "Invalid args";
-                db.resp(error.as_bytes());
-                return 1;
-            }
-
-            // Next, split the arguments into a view over the table identifier
-            // (first eight bytes), and a view over the key to be looked up.
-            // De-serialize the table identifier into a u64.
-            let (table, key) = args.split_at(8);
-            let table: u64 = 0 | table[0] as u64 | (table[1] as u64) << 8 |
-                            (table[2] as u64) << 16 | (table[3] as u64) << 24 |
-                            (table[4] as u64) << 32 | (table[5] as u64) << 40 |
-                            (table[6] as u64) << 48 | (table[7] as u64) << 56;
-
-
-            // Finally, lookup the database for the object.
-            obj = db.get(table, key);
-        }
-
-        // Populate a response to the tenant.
-        match obj {
-            // If the object was found, write it to the response.
-            Some(val) => {
-                db.resp(val.read());
-                return 0;
-            }
-
-            // If the object was not found, write an error message to the
-            // response.
-            None => {
-                let error = "Object does not exist";
-                db.resp(error.as_bytes());
-                return 0;
-            }
-        }
-
-        // XXX: This yield is required to get the compiler to compile this closure into a
-        // generator. It is unreachable and benign.
-        yield 0;
-    })
+/// An empty response if the object was found, in which case it's value was written to the
+/// response. An error otherwise.
+fn get(db: &Rc<DB>, mut args: Args) -> Result<Response, ExtError> {
+    let table = args.table()?;
+    let key = args.rest();
+    if key.is_empty() {
+        return Err(ExtError::InvalidArgs);
+    }
+
+    // Lookup the database for the object, and write it to the response.
+    let obj = db.get(table, key).ok_or(ExtError::ObjectDoesNotExist)?;
+    db.resp(obj.read());
+    Ok(Response::Empty)
 }
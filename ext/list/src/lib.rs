@@ -0,0 +1,618 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! A linked list extension whose nodes are kept ordered by a sort key.
+//!
+//! A list is identified by a head record; the value of the head record is
+//! the 8 byte (little-endian) id of the first node, or zero if the list is
+//! empty. Each node is stored under it's 8 byte (little-endian) id, and it's
+//! value is the 8 byte id of the next node (zero at the tail) followed by the
+//! node's data. The first 8 bytes of the data (little-endian) are the node's
+//! sort key.
+
+#![crate_type = "dylib"]
+#![cfg_attr(not(test), forbid(unsafe_code))]
+#![feature(generators, generator_trait)]
+
+extern crate sandstorm;
+
+use std::ops::Generator;
+use std::rc::Rc;
+
+use sandstorm::buf::WriteBuf;
+use sandstorm::db::DB;
+
+/// Opcode for an insert that keeps the list sorted.
+const OP_INSERT_SORTED: u8 = 0;
+
+/// Opcode for a range query over the sort keys.
+const OP_RANGE: u8 = 1;
+
+/// The extension yields back to the database after these many hops down the list.
+const HOPS_PER_YIELD: u64 = 8;
+
+/// The number of bytes at the head of a range response: the status, followed
+/// by the 8 byte continuation id.
+const RANGE_HDR_LEN: usize = 9;
+
+/// Status written at the head of a range response that covered the entire range.
+const RANGE_COMPLETE: u8 = 0;
+
+/// Status written at the head of a range response that was truncated. The
+/// continuation id that follows should be passed in to resume the query.
+const RANGE_TRUNCATED: u8 = 1;
+
+/// Reads a little-endian u64 off the first 8 bytes of a slice.
+fn read_u64(b: &[u8]) -> u64 {
+    0 | b[0] as u64
+        | (b[1] as u64) << 8
+        | (b[2] as u64) << 16
+        | (b[3] as u64) << 24
+        | (b[4] as u64) << 32
+        | (b[5] as u64) << 40
+        | (b[6] as u64) << 48
+        | (b[7] as u64) << 56
+}
+
+/// Writes a u64 in little-endian into a vector.
+fn write_u64(v: &mut Vec<u8>, n: u64) {
+    for i in 0..8 {
+        v.push((n >> (8 * i)) as u8);
+    }
+}
+
+/// Looks up the head record of a list, returning the id of the first node.
+fn load_head(db: &Rc<DB>, table: u64, key: &[u8]) -> u64 {
+    match db.get(table, key) {
+        Some(val) => {
+            if val.len() >= 8 {
+                read_u64(val.read())
+            } else {
+                0
+            }
+        }
+
+        None => 0,
+    }
+}
+
+/// Looks up a node, returning the id of the next node and the node's data.
+fn load_node(db: &Rc<DB>, table: u64, id: u64) -> Option<(u64, Vec<u8>)> {
+    let mut key = Vec::with_capacity(8);
+    write_u64(&mut key, id);
+
+    db.get(table, &key).and_then(|val| {
+        let val = val.read();
+        if val.len() < 16 {
+            None
+        } else {
+            Some((read_u64(&val[0..8]), val[8..].to_vec()))
+        }
+    })
+}
+
+/// Allocates a node with the given id, next pointer, and data. The node is
+/// not written out until the returned buffer is put.
+fn node_buf(db: &Rc<DB>, table: u64, id: u64, next: u64, data: &[u8]) -> Option<WriteBuf> {
+    let mut key = Vec::with_capacity(8);
+    write_u64(&mut key, id);
+
+    db.alloc(table, &key, (8 + data.len()) as u64)
+        .map(|mut buf| {
+            buf.write_u64(next, true);
+            buf.write_slice(data);
+            buf
+        })
+}
+
+/// Allocates the head record of a list. The record is not written out until
+/// the returned buffer is put.
+fn head_buf(db: &Rc<DB>, table: u64, key: &[u8], first: u64) -> Option<WriteBuf> {
+    db.alloc(table, key, 8).map(|mut buf| {
+        buf.write_u64(first, true);
+        buf
+    })
+}
+
+/// Writes an error frame naming a node that is referenced by the list but
+/// could not be found.
+fn missing_node(db: &Rc<DB>, id: u64) {
+    db.resp(format!("Missing node {}", id).as_bytes());
+}
+
+/// Writes an error frame naming a node in range whose data cannot fit in a
+/// range response on it's own.
+fn too_large(db: &Rc<DB>, id: u64) {
+    db.resp(format!("Node {} does not fit in a response", id).as_bytes());
+}
+
+/// This function implements the list() extension using the sandstorm interface.
+///
+/// The first byte of the arguments is an opcode, followed by an 8 byte table
+/// id, a 2 byte list key length, and the list key. For an insert_sorted(),
+/// this is followed by the 8 byte id of the new node and it's data (atleast 8
+/// bytes). For a range(), this is followed by an 8 byte low and high sort
+/// key, and an 8 byte node id to continue from (zero to start at the head).
+///
+/// # Arguments
+///
+/// * `db`: An argument whose type implements the `DB` trait which can be used
+///         to interact with the database.
+///
+/// # Return
+///
+/// A coroutine that can be run inside the database.
+#[no_mangle]
+#[allow(unreachable_code)]
+#[allow(unused_assignments)]
+pub fn init(db: Rc<DB>) -> Box<Generator<Yield = u64, Return = u64>> {
+    Box::new(move || {
+        // Copy the arguments out; they need to remain valid across yields.
+        let args: Vec<u8> = db.args().to_vec();
+
+        if args.len() < 11 {
+            db.resp("Invalid args".as_bytes());
+            return 1;
+        }
+
+        let op = args[0];
+        let table = read_u64(&args[1..9]);
+        let key_len = (args[9] as usize) | (args[10] as usize) << 8;
+        if args.len() < 11 + key_len {
+            db.resp("Invalid args".as_bytes());
+            return 1;
+        }
+        let list = args[11..(11 + key_len)].to_vec();
+        let rest = args[(11 + key_len)..].to_vec();
+
+        let mut hops: u64 = 0;
+
+        match op {
+            OP_INSERT_SORTED => {
+                if rest.len() < 16 {
+                    db.resp("Invalid args".as_bytes());
+                    return 1;
+                }
+                let id = read_u64(&rest[0..8]);
+                let data = &rest[8..];
+                let sort = read_u64(data);
+
+                // Walk the list until a node with a larger sort key is found. Nodes with an equal
+                // sort key are skipped so that the new node is inserted after them.
+                let mut prev: Option<(u64, Vec<u8>)> = None;
+                let mut curr = load_head(&db, table, &list);
+                while curr != 0 {
+                    let (next, node) = match load_node(&db, table, curr) {
+                        Some(node) => node,
+                        None => {
+                            missing_node(&db, curr);
+                            return 1;
+                        }
+                    };
+
+                    if read_u64(&node) > sort {
+                        break;
+                    }
+
+                    prev = Some((curr, node));
+                    curr = next;
+
+                    hops += 1;
+                    if hops % HOPS_PER_YIELD == 0 {
+                        yield 0;
+                    }
+                }
+
+                // The new node and it's predecessor are written out together, so that a
+                // concurrent reader never sees a broken chain.
+                let node = match node_buf(&db, table, id, curr, data) {
+                    Some(buf) => buf,
+                    None => {
+                        db.resp("Failed to write node".as_bytes());
+                        return 1;
+                    }
+                };
+
+                let link = match prev {
+                    None => head_buf(&db, table, &list, id),
+                    Some((pid, pdata)) => node_buf(&db, table, pid, id, &pdata),
+                };
+                let link = match link {
+                    Some(buf) => buf,
+                    None => {
+                        db.resp("Failed to link node".as_bytes());
+                        return 1;
+                    }
+                };
+
+                if db.put_many(vec![node, link]).is_err() {
+                    db.resp("Failed to link node".as_bytes());
+                    return 1;
+                }
+
+                db.resp(&[0]);
+                return 0;
+            }
+
+            OP_RANGE => {
+                if rest.len() < 24 {
+                    db.resp("Invalid args".as_bytes());
+                    return 1;
+                }
+                let low = read_u64(&rest[0..8]);
+                let high = read_u64(&rest[8..16]);
+                let mut curr = read_u64(&rest[16..24]);
+                if curr == 0 {
+                    curr = load_head(&db, table, &list);
+                }
+
+                // Collect data for all nodes in [low, high) until the response runs out of room.
+                let budget = db.resp_capacity().saturating_sub(RANGE_HDR_LEN);
+                let mut out: Vec<u8> = Vec::new();
+                let mut continuation: u64 = 0;
+                while curr != 0 {
+                    let (next, node) = match load_node(&db, table, curr) {
+                        Some(node) => node,
+                        None => {
+                            missing_node(&db, curr);
+                            return 1;
+                        }
+                    };
+
+                    let sort = read_u64(&node);
+                    if sort >= high {
+                        break;
+                    }
+
+                    if sort >= low {
+                        // A node that doesn't fit in an empty page never will, and continuing
+                        // from it would return the same empty page forever. Neither will one
+                        // whose length doesn't fit in it's 2 byte prefix.
+                        let len = 2 + node.len();
+                        if node.len() > 0xffff || (out.is_empty() && len > budget) {
+                            too_large(&db, curr);
+                            return 1;
+                        }
+
+                        if out.len() + len > budget {
+                            continuation = curr;
+                            break;
+                        }
+
+                        out.push(node.len() as u8);
+                        out.push((node.len() >> 8) as u8);
+                        out.extend_from_slice(&node);
+                    }
+
+                    curr = next;
+
+                    hops += 1;
+                    if hops % HOPS_PER_YIELD == 0 {
+                        yield 0;
+                    }
+                }
+
+                let mut resp = Vec::with_capacity(RANGE_HDR_LEN + out.len());
+                if continuation == 0 {
+                    resp.push(RANGE_COMPLETE);
+                } else {
+                    resp.push(RANGE_TRUNCATED);
+                }
+                write_u64(&mut resp, continuation);
+                resp.extend_from_slice(&out);
+                db.resp(&resp);
+                return 0;
+            }
+
+            _ => {
+                db.resp("Invalid opcode".as_bytes());
+                return 1;
+            }
+        }
+
+        // XXX: This yield is required to get the compiler to compile this closure into a
+        // generator. It is unreachable and benign.
+        yield 0;
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate bytes;
+    extern crate util;
+
+    use self::bytes::{BufMut, Bytes, BytesMut};
+    use self::util::model::Model;
+
+    use super::*;
+
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::ops::GeneratorState;
+    use std::sync::Arc;
+
+    use sandstorm::buf::{MultiReadBuf, ReadBuf, WriteBuf};
+    use sandstorm::db::PutManyError;
+
+    // The room on a response, as Context would report it for an empty one.
+    const CAPACITY: usize = 1033;
+
+    // A DB that actually stores objects, so that the list can be walked.
+    struct ListDB {
+        args: Vec<u8>,
+        resp: RefCell<Vec<u8>>,
+        objects: RefCell<HashMap<Vec<u8>, Vec<u8>>>,
+    }
+
+    impl DB for ListDB {
+        fn get(&self, _table: u64, key: &[u8]) -> Option<ReadBuf> {
+            self.objects
+                .borrow()
+                .get(key)
+                .map(|v| unsafe { ReadBuf::new(Bytes::from(v.clone())) })
+        }
+
+        fn multiget(&self, _table: u64, _key_len: u16, _keys: &[u8]) -> Option<MultiReadBuf> {
+            None
+        }
+
+        fn alloc(&self, table: u64, key: &[u8], val_len: u64) -> Option<WriteBuf> {
+            // The key is written in as metadata, and split off again on put().
+            let mut buf = BytesMut::with_capacity(2 + key.len() + val_len as usize);
+            buf.put_u16_le(key.len() as u16);
+            buf.put_slice(key);
+            unsafe { Some(WriteBuf::new(table, buf)) }
+        }
+
+        fn put(&self, buf: WriteBuf) -> bool {
+            let (_, buf) = unsafe { buf.freeze() };
+            let key_len = (buf[0] as usize) | (buf[1] as usize) << 8;
+            let key = buf[2..(2 + key_len)].to_vec();
+            let val = buf[(2 + key_len)..].to_vec();
+            self.objects.borrow_mut().insert(key, val);
+            true
+        }
+
+        fn put_many(&self, bufs: Vec<WriteBuf>) -> Result<(), PutManyError> {
+            for buf in bufs.into_iter() {
+                self.put(buf);
+            }
+            Ok(())
+        }
+
+        fn cas(
+            &self,
+            _table_id: u64,
+            key: &[u8],
+            expected: Option<&[u8]>,
+            buf: WriteBuf,
+        ) -> Result<bool, ()> {
+            let current = self.objects.borrow().get(key).cloned();
+            if current.as_ref().map(|v| &v[..]) != expected {
+                return Ok(false);
+            }
+            Ok(self.put(buf))
+        }
+
+        fn del(&self, _table: u64, key: &[u8]) -> bool {
+            self.objects.borrow_mut().remove(key).is_some()
+        }
+
+        fn args(&self) -> &[u8] {
+            &self.args
+        }
+
+        fn resp(&self, data: &[u8]) {
+            self.resp.borrow_mut().extend_from_slice(data);
+        }
+
+        fn resp_capacity(&self) -> usize {
+            CAPACITY.saturating_sub(self.resp.borrow().len())
+        }
+
+        fn debug_log(&self, _message: &str) {}
+
+        fn search_get_in_cache(&self, table: u64, key: &[u8]) -> (bool, bool, Option<ReadBuf>) {
+            (false, false, self.get(table, key))
+        }
+
+        fn search_multiget_in_cache(
+            &self,
+            _table: u64,
+            _key_len: u16,
+            _keys: &[u8],
+        ) -> (bool, bool, Option<MultiReadBuf>) {
+            (false, false, None)
+        }
+
+        fn get_model(&self) -> Option<Arc<Model>> {
+            None
+        }
+    }
+
+    const LIST: &[u8] = b"list";
+
+    fn header(op: u8) -> Vec<u8> {
+        let mut args = vec![op];
+        write_u64(&mut args, 1);
+        args.push(LIST.len() as u8);
+        args.push(0);
+        args.extend_from_slice(LIST);
+        args
+    }
+
+    // Runs the extension to completion, returning it's return value and response.
+    fn run(
+        objects: HashMap<Vec<u8>, Vec<u8>>,
+        args: Vec<u8>,
+    ) -> (u64, Vec<u8>, HashMap<Vec<u8>, Vec<u8>>) {
+        let db = Rc::new(ListDB {
+            args: args,
+            resp: RefCell::new(Vec::new()),
+            objects: RefCell::new(objects),
+        });
+
+        let mut gen = init(Rc::clone(&db) as Rc<DB>);
+        let ret;
+        loop {
+            match unsafe { gen.resume() } {
+                GeneratorState::Yielded(_) => continue,
+                GeneratorState::Complete(r) => {
+                    ret = r;
+                    break;
+                }
+            }
+        }
+        drop(gen);
+
+        let db = Rc::try_unwrap(db).ok().expect("DB still referenced");
+        (ret, db.resp.into_inner(), db.objects.into_inner())
+    }
+
+    fn insert(objects: HashMap<Vec<u8>, Vec<u8>>, id: u64, sort: u64) -> HashMap<Vec<u8>, Vec<u8>> {
+        let mut args = header(OP_INSERT_SORTED);
+        write_u64(&mut args, id);
+        write_u64(&mut args, sort);
+        write_u64(&mut args, id);
+        let (ret, resp, objects) = run(objects, args);
+        assert_eq!(0, ret);
+        assert_eq!(vec![0], resp);
+        objects
+    }
+
+    // Walks the list, returning node ids in order.
+    fn walk(objects: &HashMap<Vec<u8>, Vec<u8>>) -> Vec<u64> {
+        let mut ids = vec![];
+        let mut curr = read_u64(&objects[LIST]);
+        while curr != 0 {
+            ids.push(curr);
+            let mut key = vec![];
+            write_u64(&mut key, curr);
+            curr = read_u64(&objects[&key]);
+        }
+        ids
+    }
+
+    #[test]
+    fn test_insert_head_middle_tail() {
+        let objects = insert(HashMap::new(), 1, 20);
+        let objects = insert(objects, 2, 10);
+        let objects = insert(objects, 3, 30);
+        let objects = insert(objects, 4, 25);
+        assert_eq!(vec![2, 1, 4, 3], walk(&objects));
+    }
+
+    #[test]
+    fn test_insert_duplicate_sort_key() {
+        let objects = insert(HashMap::new(), 1, 10);
+        let objects = insert(objects, 2, 10);
+        let objects = insert(objects, 3, 10);
+        assert_eq!(vec![1, 2, 3], walk(&objects));
+    }
+
+    #[test]
+    fn test_insert_missing_node() {
+        let mut objects = insert(HashMap::new(), 1, 10);
+        objects = insert(objects, 2, 20);
+        let mut key = vec![];
+        write_u64(&mut key, 2);
+        objects.remove(&key);
+
+        let mut args = header(OP_INSERT_SORTED);
+        write_u64(&mut args, 3);
+        write_u64(&mut args, 30);
+        let (ret, resp, _) = run(objects, args);
+        assert_eq!(1, ret);
+        assert_eq!(b"Missing node 2".to_vec(), resp);
+    }
+
+    #[test]
+    fn test_range_truncated() {
+        // Each node carries 100 bytes of data, so only a handful fit in a response.
+        let mut objects = HashMap::new();
+        for i in 1..41 {
+            let mut args = header(OP_INSERT_SORTED);
+            write_u64(&mut args, i);
+            write_u64(&mut args, i * 10);
+            args.extend_from_slice(&[0; 92]);
+            let (ret, _, o) = run(objects, args);
+            assert_eq!(0, ret);
+            objects = o;
+        }
+
+        let range = |objects: HashMap<Vec<u8>, Vec<u8>>, start: u64| {
+            let mut args = header(OP_RANGE);
+            write_u64(&mut args, 50);
+            write_u64(&mut args, 350);
+            write_u64(&mut args, start);
+            let (ret, resp, objects) = run(objects, args);
+            assert_eq!(0, ret);
+
+            let mut sorts = vec![];
+            let mut data = &resp[9..];
+            while data.len() > 0 {
+                let len = (data[0] as usize) | (data[1] as usize) << 8;
+                sorts.push(read_u64(&data[2..]));
+                data = &data[(2 + len)..];
+            }
+            (resp[0], read_u64(&resp[1..9]), sorts, objects)
+        };
+
+        // The first response is truncated, and the continuation resumes where it left off.
+        let (status, next, first, objects) = range(objects, 0);
+        assert_eq!(RANGE_TRUNCATED, status);
+        assert_eq!(first.len(), (CAPACITY - RANGE_HDR_LEN) / 102);
+
+        let (status, _, second, _) = range(objects, next);
+        assert_eq!(RANGE_COMPLETE, status);
+
+        let mut all = first;
+        all.extend(second);
+        assert_eq!((5..35).map(|i| i * 10).collect::<Vec<u64>>(), all);
+    }
+
+    // Tests that a node in range too large for a response on it's own fails the range query
+    // naming it, instead of returning an empty page that continues from it forever.
+    #[test]
+    fn test_range_node_too_large() {
+        let mut objects = insert(HashMap::new(), 1, 10);
+        let mut args = header(OP_INSERT_SORTED);
+        write_u64(&mut args, 2);
+        write_u64(&mut args, 20);
+        args.extend_from_slice(&[0; CAPACITY]);
+        let (ret, _, o) = run(objects, args);
+        assert_eq!(0, ret);
+        objects = o;
+
+        // The node before it is returned on a page of it's own.
+        let mut args = header(OP_RANGE);
+        write_u64(&mut args, 0);
+        write_u64(&mut args, 30);
+        write_u64(&mut args, 0);
+        let (ret, resp, objects) = run(objects, args);
+        assert_eq!(0, ret);
+        assert_eq!(RANGE_TRUNCATED, resp[0]);
+        assert_eq!(2, read_u64(&resp[1..9]));
+        assert_eq!(RANGE_HDR_LEN + 2 + 16, resp.len());
+
+        let mut args = header(OP_RANGE);
+        write_u64(&mut args, 0);
+        write_u64(&mut args, 30);
+        write_u64(&mut args, 2);
+        let (ret, resp, _) = run(objects, args);
+        assert_eq!(1, ret);
+        assert_eq!(b"Node 2 does not fit in a response".to_vec(), resp);
+    }
+}
@@ -0,0 +1,77 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+#![crate_type = "dylib"]
+#![forbid(unsafe_code)]
+#![feature(generators, generator_trait)]
+
+extern crate sandstorm;
+
+use std::rc::Rc;
+use std::ops::Generator;
+
+use sandstorm::db::DB;
+use sandstorm::merge::decode_args;
+use sandstorm::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+/// This function implements a merge extension that treats values as counters. The old value and
+/// the delta are both little-endian u64s; an empty old value counts as zero. The new value is
+/// their (wrapping) sum.
+///
+/// # Arguments
+///
+/// * `db`: An argument whose type implements the `DB` trait. It's arguments carry the old value
+///         and the delta (see sandstorm::merge).
+///
+/// # Return
+///
+/// A coroutine that can be run inside the database.
+#[no_mangle]
+#[allow(unreachable_code)]
+pub fn init(db: Rc<DB>) -> Box<Generator<Yield=u64, Return=u64>> {
+    Box::new(move || {
+        let (old, delta) = match decode_args(db.args()) {
+            Some(args) => args,
+            None => {
+                db.resp("malformed merge arguments".as_bytes());
+                return 1;
+            }
+        };
+
+        if delta.len() != 8 {
+            db.resp("delta must be 8 bytes".as_bytes());
+            return 1;
+        }
+
+        let old = match old.len() {
+            0 => 0,
+            8 => (&old[..]).read_u64::<LittleEndian>().unwrap(),
+            _ => {
+                db.resp("stored value must be 8 bytes".as_bytes());
+                return 1;
+            }
+        };
+        let delta = (&delta[..]).read_u64::<LittleEndian>().unwrap();
+
+        let mut new = Vec::with_capacity(8);
+        new.write_u64::<LittleEndian>(old.wrapping_add(delta)).unwrap();
+        db.resp(&new);
+        return 0;
+
+        // XXX: This yield is required to get the compiler to compile this closure into a
+        // generator. It is unreachable and benign.
+        yield 0;
+    })
+}
@@ -17,127 +17,45 @@
 #![forbid(unsafe_code)]
 #![feature(generators, generator_trait)]
 
+#[macro_use]
 extern crate sandstorm;
 
-use std::rc::Rc;
-use std::ops::Generator;
-
 use sandstorm::db::DB;
+use sandstorm::entry::{Args, ExtError, Response};
+use sandstorm::put::{FIELDS, SUCCESS};
+use sandstorm::rc::Rc;
+
+declare_extension!("put", put, FIELDS);
 
-/// This function implements the put() extension using the sandstorm interface.
+/// This function implements the put() extension using the sandstorm interface. The layout of
+/// it's arguments and response is in sandstorm::put.
 ///
 /// # Arguments
 ///
-/// * `db`: An argument whose type implements the `DB` trait which can be used
-///         to interact with the database.
+/// * `db`:   An argument whose type implements the `DB` trait which can be used
+///           to interact with the database.
+/// * `args`: An 8 byte table id, a 2 byte key length, the key, and the value to be written.
 ///
 /// # Return
 ///
-/// A coroutine that can be run inside the database.
-#[no_mangle]
-#[allow(unreachable_code)]
-#[allow(unused_assignments)]
-pub fn init(db: Rc<DB>) -> Box<Generator<Yield=u64, Return=u64>> {
-    Box::new(move || {
-        let mut alloc = None;
-        let mut val_offset = 0;
-
-        {
-            // First off, retrieve the arguments to the extension.
-            let args = db.args();
-
-            // Check that the arguments received is long enough to contain an 8
-            // byte table id. If not, then write an error message to the
-            // response and return to the database.
-            if args.len() < 8 {
-                let error = "Invalid args";
-                db.resp(error.as_bytes());
-                return 1;
-            }
-
-            // Next, split the arguments into a view over the table identifier
-            // (first eight bytes), and a view over the key and value to be
-            // written. De-serialize the table identifier into a u64.
-            let (table, rem) = args.split_at(8);
-            let table: u64 = *table.get(0).unwrap() as u64 +
-                            (*table.get(1).unwrap() as u64) * 2^8 +
-                            (*table.get(2).unwrap() as u64) * 2^16 +
-                            (*table.get(3).unwrap() as u64) * 2^24 +
-                            (*table.get(4).unwrap() as u64) * 2^32 +
-                            (*table.get(5).unwrap() as u64) * 2^40 +
-                            (*table.get(6).unwrap() as u64) * 2^48 +
-                            (*table.get(7).unwrap() as u64) * 2^56;
-
-            // Check that the remaining arguments is long enough to contain a
-            // two byte key length. If not, then write an error message to the
-            // response and return to the database.
-            if rem.len() < 2 {
-                let error = "Invalid args";
-                db.resp(error.as_bytes());
-                return 1;
-            }
-
-            // Next, split the remaining arguments into a view over the key
-            // length, and a view over the object to be written.
-            let (key_len, obj) = rem.split_at(2);
-            let key_len: u16 = (*key_len.get(0).unwrap() as u16) +
-                                (*key_len.get(1).unwrap() as u16) * 256;
-
-            // Check that the remaining arguments is long enough to contain the
-            // key. If not, write an error message to the response and return
-            // to the database.
-            if obj.len() < key_len as usize {
-                let error = "Invalid args";
-                db.resp(error.as_bytes());
-                return 1;
-            }
-
-            // Save the offset of the value in the payload for use after the
-            // yield.
-            val_offset += 8 + 2 + key_len;
-
-            // Next, split the remaining arguments into a view over the key and
-            // value to be written, and request an allocation from the database.
-            let (key, val) = obj.split_at(key_len as usize);
-            alloc = db.alloc(table, key, val.len() as u64);
-        }
-
-        match alloc {
-            // If the allocation was successfull, write the value into it, and
-            // invoke the put() interface.
-            Some(mut buf) => {
-                let val = db.args().split_at(val_offset as usize).1;
-                buf.write_slice(val);
-
-                // Hand over the object to the database.
-                match db.put(buf) {
-                    // Indicate success in the response.
-                    true => {
-                        let error = "Success";
-                        db.resp(error.as_bytes());
-                        return 0;
-                    }
-
-                    // If the hand over failed, write an error message to the
-                    // response.
-                    false => {
-                        let error = "put() failed";
-                        db.resp(error.as_bytes());
-                        return 1;
-                    }
-                }
-            }
-
-            // If the allocation failed, write an error message to the response.
-            None => {
-                let error = "Allocation failed";
-                db.resp(error.as_bytes());
-                return 1;
-            }
-        }
-
-        // XXX: This yield is required to get the compiler to compile this closure into a
-        // generator. It is unreachable and benign.
-        yield 0;
-    })
+/// SUCCESS if the object was written. ExtError::AllocationFailed if the database refused to
+/// allocate it, which it does for objects over the server's limits and once the invocation is
+/// over it's quota, and ExtError::PutFailed if the database refused to write it.
+fn put(db: &Rc<DB>, mut args: Args) -> Result<Response, ExtError> {
+    let table = args.table()?;
+    let key_len = args.u16()?;
+    let key = args.bytes(key_len as usize)?;
+    let val = args.rest();
+
+    // Request an allocation from the database, copy the value into it, and hand it over. The
+    // allocation is exactly as long as the value, so the copy fills it.
+    let mut buf = db
+        .alloc(table, key, val.len() as u64)
+        .ok_or(ExtError::AllocationFailed)?;
+    buf.write_slice(val);
+
+    match db.put(buf) {
+        true => Ok(Response::Static(SUCCESS)),
+        false => Err(ExtError::PutFailed),
+    }
 }
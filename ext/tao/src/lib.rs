@@ -4,23 +4,27 @@
 #![no_std]
 #![forbid(unsafe_code)]
 
+#[macro_use]
 extern crate sandstorm;
 
 use sandstorm::buf::WriteBuf;
 use sandstorm::db::DB;
+use sandstorm::defer;
+use sandstorm::entry::{Args, ExtError, Response};
 use sandstorm::{LittleEndian, ReadBytesExt, WriteBytesExt};
 
-use sandstorm::boxed::Box;
+use sandstorm::cell::RefCell;
 use sandstorm::convert::From;
 use sandstorm::rc::Rc;
 use sandstorm::result::Result;
 use sandstorm::size_of;
+use sandstorm::tao::{self, TaoError};
 use sandstorm::time::{SystemTime, UNIX_EPOCH};
 use sandstorm::vec::*;
-use sandstorm::Generator;
 
 type Id = u64;
 type ObjectType = u16;
+type AssociationType = u16;
 type Time = u64;
 
 enum TaoOp {
@@ -31,6 +35,13 @@ enum TaoOp {
     AssocGet = 4,
     AssocAdd = 5,
     AssocDelete = 6,
+    AssocCount = 7,
+    AssocRange = 8,
+    RegisterType = 9,
+    ListTypes = 10,
+    RegisterAssocType = 11,
+    SetRegistryFlags = 12,
+    ReserveIds = 13,
 }
 
 /// Converts a u8 into a TaoOp.
@@ -44,6 +55,13 @@ impl From<u8> for TaoOp {
             4 => TaoOp::AssocGet,
             5 => TaoOp::AssocAdd,
             6 => TaoOp::AssocDelete,
+            7 => TaoOp::AssocCount,
+            8 => TaoOp::AssocRange,
+            9 => TaoOp::RegisterType,
+            10 => TaoOp::ListTypes,
+            11 => TaoOp::RegisterAssocType,
+            12 => TaoOp::SetRegistryFlags,
+            13 => TaoOp::ReserveIds,
             _ => panic!("Invalid Tao opcode."),
         }
     }
@@ -52,42 +70,61 @@ impl From<u8> for TaoOp {
 type ResponseHandler = fn(db: Rc<DB>, otype: &[u8], object: &[u8]);
 type AssocResponseHandler = fn(db: Rc<DB>, assoc: Association);
 
-#[no_mangle]
-#[allow(unreachable_code)]
-#[allow(unused_assignments)]
-pub fn init(db: Rc<DB>) -> Box<Generator<Yield = u64, Return = u64>> {
-    Box::new(move || {
-        {
-            return dispatch(db);
-        }
-        yield 0;
-    })
-}
+declare_extension!("tao", dispatch);
 
 /// Manages the arguments and calls to execute TAO code by the client.
 ///
 /// # Arguments
 /// * `db` - a connection to the database.
-fn dispatch(db: Rc<DB>) -> u64 {
-    // Each packet should contain a 1 byte opcode denoting which method to call.
-    if db.args().len() < 1 {
-        let error = "Invalid args";
-        db.resp(error.as_bytes());
-        return 1;
+/// * `args` - a 1 byte opcode denoting which method to call, followed by it's arguments.
+fn dispatch(db: &Rc<DB>, mut args: Args) -> Result<Response, ExtError> {
+    // Work deferred by an earlier invocation. Nothing is sent out for it.
+    if let Some(ops) = defer::decode_args(db.args()) {
+        deferred_dispatch(Rc::clone(db), ops);
+        return Ok(Response::Empty);
     }
 
-    let (opcode, ops) = db.args().split_at(1);
-    let opcode: u8 = 0 | opcode[0] as u8;
+    let opcode = args.u8()?;
+    let ops = args.rest();
 
     match TaoOp::from(opcode) {
-        TaoOp::ObjGet => obj_get_dispatch(Rc::clone(&db), ops),
-        TaoOp::ObjAdd => obj_add_dispatch(Rc::clone(&db), ops),
-        TaoOp::ObjUpdate => obj_update_dispatch(Rc::clone(&db), ops),
-        TaoOp::ObjDelete => obj_delete_dispatch(Rc::clone(&db), ops),
-        _ => assoc_dispatch(opcode, Rc::clone(&db), ops),
+        TaoOp::ObjGet => obj_get_dispatch(Rc::clone(db), ops),
+        TaoOp::ObjAdd => obj_add_dispatch(Rc::clone(db), ops),
+        TaoOp::ObjUpdate => obj_update_dispatch(Rc::clone(db), ops),
+        TaoOp::ObjDelete => obj_delete_dispatch(Rc::clone(db), ops),
+        TaoOp::AssocCount => assoc_count_dispatch(Rc::clone(db), ops),
+        TaoOp::AssocRange => assoc_range_dispatch(Rc::clone(db), ops),
+        TaoOp::RegisterType => register_type_dispatch(Rc::clone(db), ops),
+        TaoOp::ListTypes => list_types_dispatch(Rc::clone(db), ops),
+        TaoOp::RegisterAssocType => register_assoc_type_dispatch(Rc::clone(db), ops),
+        TaoOp::SetRegistryFlags => set_registry_flags_dispatch(Rc::clone(db), ops),
+        TaoOp::ReserveIds => reserve_ids_dispatch(Rc::clone(db), ops),
+        _ => assoc_dispatch(opcode, Rc::clone(db), ops),
     };
 
-    return 0;
+    Ok(Response::Empty)
+}
+
+/// Runs work deferred by an earlier invocation. Only assoc_count defers work, to bump the hot
+/// counter of the list it counted.
+///
+/// # Packet structure
+/// |opcode = 1|table_id = 8|id1 = 8|assoc_type = 2|
+///
+/// # Arguments
+/// * `db` - a connection to the database.
+/// * `ops` - the arguments the work was deferred with.
+fn deferred_dispatch(db: Rc<DB>, ops: &[u8]) {
+    // |opcode = 1|table_id = 8|id1 = 8|assoc_type = 2|
+    if ops.len() != 1 + 8 + tao::LIST_KEY_LEN || ops[0] != TaoOp::AssocCount as u8 {
+        return;
+    }
+
+    let (table, list_key) = ops[1..].split_at(8);
+    let table: u64 = convert_from_slice(table);
+
+    let tao = TAO::new(Rc::clone(&db), 0, table);
+    tao.bump_hot_count(list_key);
 }
 
 /// Handles the response to a client for an object.
@@ -107,15 +144,7 @@ fn object_response_handler(db: Rc<DB>, _otype: &[u8], object: &[u8]) {
 /// * `db` - a connection to the database.
 /// * `assoc` - the association which needs to be written into the response to the client.
 fn assoc_response_handler(db: Rc<DB>, assoc: Association) {
-    let mut assoc_serialized: Vec<u8> = Vec::with_capacity(Association::size());
-    assoc_serialized
-        .write_u64::<LittleEndian>(assoc.id)
-        .unwrap();
-    assoc_serialized
-        .write_u64::<LittleEndian>(assoc.time)
-        .unwrap();
-
-    db.resp(assoc_serialized.as_slice());
+    db.resp(&tao::encode_assoc(assoc.id, assoc.time));
 }
 
 /// Manages the resquest to perform an object_get. The response is the object retrieved from the
@@ -144,7 +173,8 @@ fn obj_get_dispatch(db: Rc<DB>, ops: &[u8]) {
     }
 }
 
-/// Manages the resquest to perform an object_add. The response is the object_id of the new object.
+/// Manages the resquest to perform an object_add. The response is the object_id of the new object,
+/// or an error frame if the registry rejects the object or no id could be allocated for it.
 ///
 /// # Packet structure
 /// |table_id = 8|obj_type = 2|value = n > 0|
@@ -167,11 +197,14 @@ fn obj_add_dispatch(db: Rc<DB>, ops: &[u8]) {
     let otype: u16 = 0 | otype[0] as u16 | (otype[1] as u16) << 8;
 
     let mut tao = TAO::new(Rc::clone(&db), table, 0);
-    db.resp(tao.object_add(otype, value).as_slice());
+    match tao.object_add(otype, value) {
+        Ok(id) => db.resp(id.as_slice()),
+        Err(e) => db.resp(&tao::encode_error(e, otype)),
+    }
 }
 
 /// Manages the resquest to perform an object_update. The response is empty if the call was
-/// successful, or an error message otherwise.
+/// successful, an error frame if the registry rejects the object, or an error message otherwise.
 ///
 /// # Packet structure
 /// |table_id = 8|obj_id = 8|obj_type = 2|value = n > 0|
@@ -196,8 +229,10 @@ fn obj_update_dispatch(db: Rc<DB>, ops: &[u8]) {
     let obj_type: u16 = 0 | obj_type[0] as u16 | (obj_type[1] as u16) << 8;
 
     let tao = TAO::new(Rc::clone(&db), table, 0);
-    if tao.object_update(obj_id, obj_type, value) == false {
-        db.resp("ERROR: unsuccessful update".as_bytes());
+    match tao.object_update(obj_id, obj_type, value) {
+        Ok(true) => {}
+        Ok(false) => db.resp("ERROR: unsuccessful update".as_bytes()),
+        Err(e) => db.resp(&tao::encode_error(e, obj_type)),
     }
 }
 
@@ -233,8 +268,13 @@ fn obj_delete_dispatch(db: Rc<DB>, ops: &[u8]) {
 ///     delete: empty if successful, error message otherwise.
 ///     get: bytes representing the association if sucessful, error message otherwise.
 ///
+/// An add can be followed by the id of the object table, in which case both endpoints must
+/// exist and have types the registry permits for the association type. The response is an
+/// error frame if they don't.
+///
 /// # Packet structure
 /// |table_id = 8|id1 = 8|assoc_type = 2|id2 = 8|
+/// |table_id = 8|id1 = 8|assoc_type = 2|id2 = 8|object_table_id = 8| (add only)
 ///
 /// # Arguments
 /// * `opcode` - identifier for which association operation should be called.
@@ -242,7 +282,11 @@ fn obj_delete_dispatch(db: Rc<DB>, ops: &[u8]) {
 /// * `ops` - packet information.
 fn assoc_dispatch(opcode: u8, db: Rc<DB>, ops: &[u8]) {
     // |table_id = 8|id1 = 8|assoc_type = 2|id2 = 8|
-    if ops.len() != 26 {
+    let checked = match TaoOp::from(opcode) {
+        TaoOp::AssocAdd => ops.len() == 34,
+        _ => false,
+    };
+    if ops.len() != 26 && !checked {
         db.resp("Invalid packet length.".as_bytes());
         return;
     }
@@ -254,8 +298,14 @@ fn assoc_dispatch(opcode: u8, db: Rc<DB>, ops: &[u8]) {
         | (table[7] as u64) << 56;
 
     let (id1, rest2) = rest.split_at(8);
-    let (assoc_type, id2) = rest2.split_at(2);
-    let tao = TAO::new(Rc::clone(&db), 0, table);
+    let (assoc_type, rest3) = rest2.split_at(2);
+    let (id2, objects) = rest3.split_at(8);
+    let objects: u64 = if checked {
+        convert_from_slice(objects)
+    } else {
+        0
+    };
+    let tao = TAO::new(Rc::clone(&db), objects, table);
 
     match TaoOp::from(opcode) {
         TaoOp::AssocGet => {
@@ -264,6 +314,14 @@ fn assoc_dispatch(opcode: u8, db: Rc<DB>, ops: &[u8]) {
             }
         }
         TaoOp::AssocAdd => {
+            if checked {
+                if let Err(e) = tao.check_endpoints(id1, assoc_type, id2) {
+                    let atype = (&assoc_type[..]).read_u16::<LittleEndian>().unwrap();
+                    db.resp(&tao::encode_error(e, atype));
+                    return;
+                }
+            }
+
             if tao.association_add(id1, assoc_type, id2) == false {
                 db.resp("ERROR: unsuccessful update".as_bytes());
             }
@@ -277,11 +335,213 @@ fn assoc_dispatch(opcode: u8, db: Rc<DB>, ops: &[u8]) {
     };
 }
 
+/// Manages the request to perform an assoc_count. The response is the number of associations on
+/// the list as a 4 byte little endian integer, zero if there is no such list. The list's hot
+/// counter is bumped in work deferred until after the response has been sent out.
+///
+/// # Packet structure
+/// |table_id = 8|id1 = 8|assoc_type = 2|
+///
+/// # Arguments
+/// * `db` - a connection to the database.
+/// * `ops` - packet information.
+fn assoc_count_dispatch(db: Rc<DB>, ops: &[u8]) {
+    // |table_id = 8|id1 = 8|assoc_type = 2|
+    if ops.len() != 8 + tao::LIST_KEY_LEN {
+        db.resp("Invalid packet length.".as_bytes());
+        return;
+    }
+
+    let (table, list_key) = ops.split_at(8);
+    let table: u64 = convert_from_slice(table);
+
+    let tao = TAO::new(Rc::clone(&db), 0, table);
+    let mut count: Vec<u8> = Vec::with_capacity(size_of::<u32>());
+    count
+        .write_u32::<LittleEndian>(tao.association_count(list_key) as u32)
+        .unwrap();
+    db.resp(count.as_slice());
+
+    // The counter is best effort; the work is dropped if the server is backed up.
+    let mut deferred: Vec<u8> = Vec::with_capacity(1 + ops.len());
+    deferred.push(TaoOp::AssocCount as u8);
+    deferred.extend_from_slice(ops);
+    db.defer(&deferred);
+}
+
+/// Manages the request to perform an assoc_range. The response is upto `limit` associations off
+/// the list starting at the `offset`th newest, each one |id2 = 8|time = 8|. The response is empty
+/// if there is no such list, or if it has fewer than `offset` associations.
+///
+/// # Packet structure
+/// |table_id = 8|id1 = 8|assoc_type = 2|offset = 4|limit = 4|
+///
+/// # Arguments
+/// * `db` - a connection to the database.
+/// * `ops` - packet information.
+fn assoc_range_dispatch(db: Rc<DB>, ops: &[u8]) {
+    // |table_id = 8|id1 = 8|assoc_type = 2|offset = 4|limit = 4|
+    if ops.len() != 8 + tao::LIST_KEY_LEN + 8 {
+        db.resp("Invalid packet length.".as_bytes());
+        return;
+    }
+
+    let (table, rest) = ops.split_at(8);
+    let table: u64 = convert_from_slice(table);
+
+    let (list_key, mut range) = rest.split_at(tao::LIST_KEY_LEN);
+    let offset = range.read_u32::<LittleEndian>().unwrap();
+    let limit = range.read_u32::<LittleEndian>().unwrap();
+
+    let tao = TAO::new(Rc::clone(&db), 0, table);
+    tao.association_range(list_key, offset as usize, limit as usize);
+}
+
+/// Manages the request to perform a register_type. Registers an object type, replacing any
+/// earlier registration of it. The response is empty if the call was successful, or an error
+/// frame otherwise.
+///
+/// # Packet structure
+/// |table_id = 8|obj_type = 2|min_len = 4|max_len = 4|flags = 1|
+///
+/// # Arguments
+/// * `db` - a connection to the database.
+/// * `ops` - packet information.
+fn register_type_dispatch(db: Rc<DB>, ops: &[u8]) {
+    // |table_id = 8|obj_type = 2|min_len = 4|max_len = 4|flags = 1|
+    if ops.len() != 8 + TYPE_ENTRY_LEN {
+        db.resp("Invalid packet length.".as_bytes());
+        return;
+    }
+
+    let (table, entry) = ops.split_at(8);
+    let table: u64 = convert_from_slice(table);
+    let entry = TypeEntry::deserialize(entry);
+
+    let tao = TAO::new(Rc::clone(&db), table, 0);
+    if let Err(e) = tao.register_type(entry) {
+        db.resp(&tao::encode_error(e, entry.otype));
+    }
+}
+
+/// Manages the request to perform a register_assoc_type. Registers the types of objects an
+/// association type may connect, replacing any earlier registration of it. The response is
+/// empty if the call was successful, or an error frame otherwise.
+///
+/// # Packet structure
+/// |table_id = 8|assoc_type = 2|id1_type = 2|id2_type = 2|
+///
+/// # Arguments
+/// * `db` - a connection to the database.
+/// * `ops` - packet information. `table_id` is the object table, which holds the registry.
+fn register_assoc_type_dispatch(db: Rc<DB>, ops: &[u8]) {
+    // |table_id = 8|assoc_type = 2|id1_type = 2|id2_type = 2|
+    if ops.len() != 8 + ASSOC_TYPE_ENTRY_LEN {
+        db.resp("Invalid packet length.".as_bytes());
+        return;
+    }
+
+    let (table, entry) = ops.split_at(8);
+    let table: u64 = convert_from_slice(table);
+    let entry = AssociationTypeEntry::deserialize(entry);
+
+    let tao = TAO::new(Rc::clone(&db), table, 0);
+    if let Err(e) = tao.register_assoc_type(entry) {
+        db.resp(&tao::encode_error(e, entry.atype));
+    }
+}
+
+/// Manages the request to set the registry's flags. The response is empty if the call was
+/// successful, or an error frame otherwise.
+///
+/// # Packet structure
+/// |table_id = 8|flags = 1|
+///
+/// # Arguments
+/// * `db` - a connection to the database.
+/// * `ops` - packet information.
+fn set_registry_flags_dispatch(db: Rc<DB>, ops: &[u8]) {
+    // |table_id = 8|flags = 1|
+    if ops.len() != 9 {
+        db.resp("Invalid packet length.".as_bytes());
+        return;
+    }
+
+    let (table, flags) = ops.split_at(8);
+    let table: u64 = convert_from_slice(table);
+
+    let tao = TAO::new(Rc::clone(&db), table, 0);
+    if let Err(e) = tao.set_registry_flags(flags[0]) {
+        db.resp(&tao::encode_error(e, 0));
+    }
+}
+
+/// Manages the request to perform a list_types. The response is the registry in the layout
+/// documented on Registry, or an error frame if it could not be read. A table without a
+/// registry lists as an empty, lenient one.
+///
+/// # Packet structure
+/// |table_id = 8|
+///
+/// # Arguments
+/// * `db` - a connection to the database.
+/// * `ops` - packet information.
+fn list_types_dispatch(db: Rc<DB>, ops: &[u8]) {
+    // |table_id = 8|
+    if ops.len() != 8 {
+        db.resp("Invalid packet length.".as_bytes());
+        return;
+    }
+
+    let table: u64 = convert_from_slice(ops);
+
+    let tao = TAO::new(Rc::clone(&db), table, 0);
+    match tao.registry() {
+        Ok(registry) => db.resp(registry.serialize().as_slice()),
+        Err(e) => db.resp(&tao::encode_error(e, 0)),
+    }
+}
+
+/// Manages the request to perform a reserve_ids. The response is the first id of the reserved
+/// range as an 8 byte little endian integer, or an error frame if the ids could not be reserved.
+///
+/// # Packet structure
+/// |table_id = 8|count = 4|floor = 8|
+///
+/// # Arguments
+/// * `db` - a connection to the database.
+/// * `ops` - packet information. `table_id` is the object table, which holds the id counter.
+fn reserve_ids_dispatch(db: Rc<DB>, ops: &[u8]) {
+    // |table_id = 8|count = 4|floor = 8|
+    if ops.len() != 20 {
+        db.resp("Invalid packet length.".as_bytes());
+        return;
+    }
+
+    let (table, mut rest) = ops.split_at(8);
+    let table: u64 = convert_from_slice(table);
+    let count = rest.read_u32::<LittleEndian>().unwrap();
+    let floor = rest.read_u64::<LittleEndian>().unwrap();
+
+    let tao = TAO::new(Rc::clone(&db), table, 0);
+    match tao.reserve_ids(count as u64, floor) {
+        Ok(first) => {
+            let mut resp: Vec<u8> = Vec::with_capacity(size_of::<Id>());
+            resp.write_u64::<LittleEndian>(first).unwrap();
+            db.resp(resp.as_slice());
+        }
+        Err(e) => db.resp(&tao::encode_error(e, 0)),
+    }
+}
+
 pub struct TAO {
     client: Rc<DB>,
     object_table_id: u64,
     association_table_id: u64,
-    next_id: Id,
+
+    // The object table's registry, read on first use. Every invocation creates a fresh TAO, so
+    // the registry is read atmost once per invocation.
+    registry: RefCell<Option<Result<Rc<Registry>, TaoError>>>,
 }
 
 impl TAO {
@@ -300,29 +560,43 @@ impl TAO {
             client,
             object_table_id,
             association_table_id,
-            next_id: 1, // TODO: this is invalid if the user is creating a new TAO instance, for existing tables. This needs to be read from DB?
+            registry: RefCell::new(None),
         }
     }
 
-    /// Returns the id of the newly created object.
+    /// Returns the id of the newly created object, or an error if the registry rejects it or no
+    /// id could be allocated for it. Ids are allocated off the same counter as reserve_ids().
     ///
     /// # Arguments
     /// * `object_type` - Type of the object being added.
     /// * 'data' - kvpairs which make up the object.
-    pub fn object_add(&mut self, otype: ObjectType, data: &[u8]) -> Vec<u8> {
-        let object_id = self.allocate_unique_id();
+    pub fn object_add(&mut self, otype: ObjectType, data: &[u8]) -> Result<Vec<u8>, TaoError> {
+        self.registry()?.check_object(otype, data.len(), false)?;
 
-        self.object_update(object_id.as_slice(), otype, data);
-        return object_id;
+        let object_id = self.allocate_unique_id()?;
+        self.write_object(object_id.as_slice(), otype, data);
+        return Ok(object_id);
     }
 
-    /// Updates the object with the given id and type to contain the data provided.
+    /// Updates the object with the given id and type to contain the data provided. Returns an
+    /// error if the registry rejects the update, otherwise whether the object was written.
     ///
     /// # Arguments
     /// * `id` - id of the object to be updated.
     /// * `otype` - type of the object to be updated.
     /// * `data` - updated data to replace current data with.
-    pub fn object_update(&self, id: &[u8], otype: ObjectType, data: &[u8]) -> bool {
+    pub fn object_update(
+        &self,
+        id: &[u8],
+        otype: ObjectType,
+        data: &[u8],
+    ) -> Result<bool, TaoError> {
+        self.registry()?.check_object(otype, data.len(), true)?;
+        Ok(self.write_object(id, otype, data))
+    }
+
+    // Writes an object without consulting the registry. Returns true if it was written.
+    fn write_object(&self, id: &[u8], otype: ObjectType, data: &[u8]) -> bool {
         let space_needed = ObjectHeader::size() + data.len();
 
         let mut container = match self.client
@@ -348,8 +622,7 @@ impl TAO {
     /// # Arguments
     /// * `id` - id of the object to be created.
     pub fn object_delete(&self, id: &[u8]) -> bool {
-        self.client.del(self.object_table_id, id);
-        return true;
+        return self.client.del(self.object_table_id, id);
     }
 
     /// Gets the data for the object with the given id. Returns the type of the object.
@@ -412,46 +685,45 @@ impl TAO {
 
         new_assoc.serialize(&mut assoc_container);
 
-        if self.client.put(assoc_container) {
-            // Add the association to the list. (id1, atype) -> (id2)
-            // To do this, assume the list exists. if it doesn't exist, add our entry and add the list
-            // to the db.
-            let mut list_key: Vec<u8> =
-                Vec::with_capacity(id1.len() + association_type.len() + id2.len());
-            list_key.extend_from_slice(id1);
-            list_key.extend_from_slice(association_type);
-
-            let mut list = match self.client
-                .get(self.association_table_id, list_key.as_slice())
-            {
-                Some(list_serialized) => match AssociationList::deserialize(list_serialized.read())
-                {
-                    Ok(ls) => ls,
-                    Err(_) => return false,
-                },
-                None => {
-                    // Create a new AssociationList.
-                    AssociationList::new()
-                }
-            };
+        // Add the association to the list. (id1, atype) -> (id2)
+        // To do this, assume the list exists. if it doesn't exist, add our entry and add the list
+        // to the db.
+        let mut list_key: Vec<u8> =
+            Vec::with_capacity(id1.len() + association_type.len() + id2.len());
+        list_key.extend_from_slice(id1);
+        list_key.extend_from_slice(association_type);
 
-            list.add(new_assoc);
+        let mut list = match self.client
+            .get(self.association_table_id, list_key.as_slice())
+        {
+            Some(list_serialized) => match AssociationList::deserialize(list_serialized.read()) {
+                Ok(ls) => ls,
+                Err(_) => return false,
+            },
+            None => {
+                // Create a new AssociationList.
+                AssociationList::new()
+            }
+        };
 
-            let mut list_container = match self.client.alloc(
-                self.association_table_id,
-                list_key.as_slice(),
-                list.size() as u64,
-            ) {
-                None => return false,
-                Some(o) => o,
-            };
+        list.add(new_assoc);
 
-            list.serialize(&mut list_container);
+        let mut list_container = match self.client.alloc(
+            self.association_table_id,
+            list_key.as_slice(),
+            list.size() as u64,
+        ) {
+            None => return false,
+            Some(o) => o,
+        };
 
-            return self.client.put(list_container);
-        } else {
-            return false;
-        }
+        list.serialize(&mut list_container);
+
+        // The association and the list are written together, so that a reader never finds one
+        // without the other.
+        self.client
+            .put_many(vec![assoc_container, list_container])
+            .is_ok()
     }
 
     /// Deletes the Association (id1, type, id2) and removes it from the List.
@@ -480,7 +752,9 @@ impl TAO {
             None => return false,
         };
 
-        list.remove(assoc.id);
+        if !list.remove(assoc.id) {
+            return false;
+        }
 
         // recommit the list
         let mut list_container = match self.client.alloc(
@@ -501,9 +775,9 @@ impl TAO {
             assoc_key.extend_from_slice(id1);
             assoc_key.extend_from_slice(association_type);
             assoc_key.extend_from_slice(id2);
-            self.client
+            return self
+                .client
                 .del(self.association_table_id, assoc_key.as_slice());
-            return true;
         } else {
             return false;
         }
@@ -539,7 +813,7 @@ impl TAO {
                 let list = a_list.read();
 
                 // Get the number of assocs in the list.
-                let s = size_of::<Association>();
+                let s = tao::ASSOC_LEN;
                 let n = list.len() / s;
 
                 // Construct a key for the database lookup.
@@ -567,8 +841,9 @@ impl TAO {
                     self.client
                         .multiget(self.association_table_id, key_len as u16, &assoc_key);
 
+                // An association missing off the list fails the whole range.
                 match buf {
-                    Some(vals) => {
+                    Some(ref vals) if vals.missing() == 0 => {
                         if vals.num() > 0 {
                             self.client.resp(vals.read());
                         }
@@ -580,7 +855,7 @@ impl TAO {
                         return true;
                     }
 
-                    None => return false,
+                    _ => return false,
                 }
             }
 
@@ -588,6 +863,193 @@ impl TAO {
         }
     }
 
+    /// Returns the number of associations on the list (id1, type), zero if there is no such
+    /// list.
+    ///
+    /// # Arguments
+    /// * `list_key` - the id of the first object followed by the association type.
+    pub fn association_count(&self, list_key: &[u8]) -> usize {
+        match self.client.get(self.association_table_id, list_key) {
+            Some(list) => tao::assoc_count(list.read()),
+            None => 0,
+        }
+    }
+
+    /// Adds one to the number of times the list (id1, type) was counted, in tao::HOT_TABLE.
+    /// Returns true if the counter was written.
+    ///
+    /// # Arguments
+    /// * `list_key` - the id of the first object followed by the association type.
+    pub fn bump_hot_count(&self, list_key: &[u8]) -> bool {
+        let count = match self.client.get(tao::HOT_TABLE, list_key) {
+            Some(count) => {
+                let mut count = count.read();
+                count.read_u64::<LittleEndian>().unwrap_or(0)
+            }
+            None => 0,
+        };
+
+        let mut counter = match self
+            .client
+            .alloc(tao::HOT_TABLE, list_key, size_of::<u64>() as u64)
+        {
+            None => return false,
+            Some(counter) => counter,
+        };
+        let mut bytes: Vec<u8> = Vec::with_capacity(size_of::<u64>());
+        bytes.write_u64::<LittleEndian>(count + 1).unwrap();
+        counter.write_slice(&bytes);
+
+        return self.client.put(counter);
+    }
+
+    /// Writes upto `limit` associations off the list (id1, type) into the response, starting
+    /// at the `offset`th newest. Nothing is written if there is no such list.
+    ///
+    /// # Arguments
+    /// * `list_key` - the id of the first object followed by the association type.
+    /// * `offset` - the number of (newest) associations to skip.
+    /// * `limit` - the maximum number of associations to write into the response.
+    pub fn association_range(&self, list_key: &[u8], offset: usize, limit: usize) {
+        if let Some(list) = self.client.get(self.association_table_id, list_key) {
+            let list = list.read();
+            let n = tao::assoc_count(list);
+            let l = offset.min(n) * tao::ASSOC_LEN;
+            let r = offset.saturating_add(limit).min(n) * tao::ASSOC_LEN;
+            self.client.resp(&list[l..r]);
+        }
+    }
+
+    /// Checks that both endpoints of an association exist in the object table, and have types
+    /// the registry permits for the association type.
+    ///
+    /// # Arguments
+    /// * `id1` - the id of the first object in this Association.
+    /// * `association_type` - the type of this association.
+    /// * `id2` - the id of the second object in this Association.
+    pub fn check_endpoints(
+        &self,
+        id1: &[u8],
+        association_type: &[u8],
+        id2: &[u8],
+    ) -> Result<(), TaoError> {
+        let atype = (&association_type[..]).read_u16::<LittleEndian>().unwrap();
+        let otype1 = self.object_type(id1)?;
+        let otype2 = self.object_type(id2)?;
+        self.registry()?.check_assoc(atype, otype1, otype2)
+    }
+
+    /// Registers an object type, replacing any earlier registration of it.
+    ///
+    /// # Arguments
+    /// * `entry` - the type and the constraints on objects of it.
+    pub fn register_type(&self, entry: TypeEntry) -> Result<(), TaoError> {
+        if entry.min_len > entry.max_len {
+            return Err(TaoError::BadBounds);
+        }
+
+        let mut registry = self.writable_registry()?;
+        match registry
+            .types
+            .binary_search_by_key(&entry.otype, |e| e.otype)
+        {
+            Ok(i) => registry.types[i] = entry,
+            Err(i) => registry.types.insert(i, entry),
+        }
+        self.store_registry(registry)
+    }
+
+    /// Registers the types of objects an association type may connect, replacing any earlier
+    /// registration of it.
+    ///
+    /// # Arguments
+    /// * `entry` - the association type and the types of it's endpoints.
+    pub fn register_assoc_type(&self, entry: AssociationTypeEntry) -> Result<(), TaoError> {
+        let mut registry = self.writable_registry()?;
+        match registry
+            .atypes
+            .binary_search_by_key(&entry.atype, |e| e.atype)
+        {
+            Ok(i) => registry.atypes[i] = entry,
+            Err(i) => registry.atypes.insert(i, entry),
+        }
+        self.store_registry(registry)
+    }
+
+    /// Sets the registry's flags.
+    ///
+    /// # Arguments
+    /// * `flags` - the flags, ex: REGISTRY_STRICT.
+    pub fn set_registry_flags(&self, flags: u8) -> Result<(), TaoError> {
+        let mut registry = self.writable_registry()?;
+        registry.flags = flags;
+        self.store_registry(registry)
+    }
+
+    // Returns the object table's registry, reading it from the table on first use. A table
+    // without a registry has an empty, lenient one.
+    fn registry(&self) -> Result<Rc<Registry>, TaoError> {
+        if let Some(ref cached) = *self.registry.borrow() {
+            return cached.clone();
+        }
+
+        let registry = match self.client.get(self.object_table_id, &tao::REGISTRY_KEY) {
+            Some(record) => Registry::deserialize(record.read()).map(Rc::new),
+            None => Ok(Rc::new(Registry::new())),
+        };
+        *self.registry.borrow_mut() = Some(registry.clone());
+        registry
+    }
+
+    // Returns a copy of the registry that can be modified and stored. Registries written by a
+    // newer version of the extension can be read, but not modified, since storing them would
+    // drop the fields this version does not know about.
+    fn writable_registry(&self) -> Result<Registry, TaoError> {
+        let registry = self.registry()?;
+        if registry.version > REGISTRY_VERSION {
+            return Err(TaoError::Registry);
+        }
+
+        Ok(Registry {
+            version: REGISTRY_VERSION,
+            flags: registry.flags,
+            types: registry.types.clone(),
+            atypes: registry.atypes.clone(),
+        })
+    }
+
+    // Writes the registry to the object table, and caches it.
+    fn store_registry(&self, registry: Registry) -> Result<(), TaoError> {
+        let record = registry.serialize();
+        let mut container = match self.client.alloc(
+            self.object_table_id,
+            &tao::REGISTRY_KEY,
+            record.len() as u64,
+        ) {
+            None => return Err(TaoError::Registry),
+            Some(o) => o,
+        };
+
+        container.write_slice(record.as_slice());
+        if !self.client.put(container) {
+            return Err(TaoError::Registry);
+        }
+
+        *self.registry.borrow_mut() = Some(Ok(Rc::new(registry)));
+        Ok(())
+    }
+
+    // Returns the type of the object with the given id.
+    fn object_type(&self, id: &[u8]) -> Result<ObjectType, TaoError> {
+        match self.client.get(self.object_table_id, id) {
+            Some(obj) => match ObjectHeader::deserialize(obj.read()) {
+                Ok(header) => Ok(header.otype),
+                Err(_) => Err(TaoError::NoEndpoint),
+            },
+            None => Err(TaoError::NoEndpoint),
+        }
+    }
+
     /// Returns seconds since unix epoch.
     fn current_time(&self) -> Time {
         let now = SystemTime::now()
@@ -596,12 +1058,57 @@ impl TAO {
         now.as_secs()
     }
 
-    // Returns a simple unique integer.
-    fn allocate_unique_id(&mut self) -> Vec<u8> {
-        self.next_id += 1;
+    /// Reserves a range of `count` ids off the object table's id counter, and returns the first
+    /// one. The range never starts below `floor`, so that a client can keep it clear of the ids
+    /// of a populated graph. Ids in the range are never handed out again, by a later reservation
+    /// or an object_add.
+    ///
+    /// The counter is read and written back by the same invocation, so reservations on a
+    /// tenant must not race each other on different cores.
+    ///
+    /// # Arguments
+    /// * `count` - the number of ids to reserve.
+    /// * `floor` - the smallest id the range may start at.
+    pub fn reserve_ids(&self, count: u64, floor: Id) -> Result<Id, TaoError> {
+        let next = match self.client.get(self.object_table_id, &tao::NEXT_ID_KEY) {
+            Some(record) => match (&record.read()[..]).read_u64::<LittleEndian>() {
+                Ok(next) => next,
+                Err(_) => return Err(TaoError::Exhausted),
+            },
+            None => 1,
+        };
+
+        // The largest id is the counter's own key, and is never handed out.
+        let first = next.max(floor).max(1);
+        let end = match first.checked_add(count) {
+            Some(end) => end,
+            None => return Err(TaoError::Exhausted),
+        };
+
+        let mut container = match self.client.alloc(
+            self.object_table_id,
+            &tao::NEXT_ID_KEY,
+            size_of::<Id>() as u64,
+        ) {
+            None => return Err(TaoError::Exhausted),
+            Some(o) => o,
+        };
+
+        let mut record: Vec<u8> = Vec::with_capacity(size_of::<Id>());
+        record.write_u64::<LittleEndian>(end).unwrap();
+        container.write_slice(record.as_slice());
+        if !self.client.put(container) {
+            return Err(TaoError::Exhausted);
+        }
+        Ok(first)
+    }
+
+    // Returns an id off the object table's id counter.
+    fn allocate_unique_id(&self) -> Result<Vec<u8>, TaoError> {
+        let first = self.reserve_ids(1, 1)?;
         let mut id = Vec::new();
-        id.write_u64::<LittleEndian>(self.next_id).unwrap();
-        return id;
+        id.write_u64::<LittleEndian>(first).unwrap();
+        Ok(id)
     }
 }
 
@@ -616,6 +1123,235 @@ fn convert_from_slice(val: &[u8]) -> u64 {
     return val;
 }
 
+/// The version of the registry layout written by this extension.
+const REGISTRY_VERSION: u8 = 1;
+
+/// Length of the registry header written by this extension.
+const REGISTRY_HEADER_LEN: usize = 9;
+
+/// Length of a registered object type written by this extension.
+const TYPE_ENTRY_LEN: usize = 11;
+
+/// Length of a registered association type written by this extension.
+const ASSOC_TYPE_ENTRY_LEN: usize = 6;
+
+/// Registry flag; objects of unregistered types, and associations of unregistered types
+/// whose endpoints are checked, are rejected. Without it they are accepted.
+pub const REGISTRY_STRICT: u8 = 0x1;
+
+/// Object type flag; objects of the type can be added, but not updated.
+pub const TYPE_FROZEN: u8 = 0x1;
+
+/// An association endpoint constraint that permits objects of any type.
+pub const ANY_TYPE: ObjectType = 0xffff;
+
+/// A registered object type, and the constraints on objects of it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TypeEntry {
+    pub otype: ObjectType,
+    pub min_len: u32,
+    pub max_len: u32,
+    pub flags: u8,
+}
+
+impl TypeEntry {
+    // |obj_type = 2|min_len = 4|max_len = 4|flags = 1|
+    fn serialize(&self, bytes: &mut Vec<u8>) {
+        bytes.write_u16::<LittleEndian>(self.otype).unwrap();
+        bytes.write_u32::<LittleEndian>(self.min_len).unwrap();
+        bytes.write_u32::<LittleEndian>(self.max_len).unwrap();
+        bytes.push(self.flags);
+    }
+
+    // Reads the fields this version knows about; `bytes` must hold atleast TYPE_ENTRY_LEN.
+    fn deserialize(mut bytes: &[u8]) -> TypeEntry {
+        TypeEntry {
+            otype: bytes.read_u16::<LittleEndian>().unwrap(),
+            min_len: bytes.read_u32::<LittleEndian>().unwrap(),
+            max_len: bytes.read_u32::<LittleEndian>().unwrap(),
+            flags: bytes.read_u8().unwrap(),
+        }
+    }
+}
+
+/// A registered association type, and the types of objects it may connect. Either type can
+/// be ANY_TYPE.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AssociationTypeEntry {
+    pub atype: AssociationType,
+    pub id1_type: ObjectType,
+    pub id2_type: ObjectType,
+}
+
+impl AssociationTypeEntry {
+    // |assoc_type = 2|id1_type = 2|id2_type = 2|
+    fn serialize(&self, bytes: &mut Vec<u8>) {
+        bytes.write_u16::<LittleEndian>(self.atype).unwrap();
+        bytes.write_u16::<LittleEndian>(self.id1_type).unwrap();
+        bytes.write_u16::<LittleEndian>(self.id2_type).unwrap();
+    }
+
+    // Reads the fields this version knows about; `bytes` must hold atleast
+    // ASSOC_TYPE_ENTRY_LEN.
+    fn deserialize(mut bytes: &[u8]) -> AssociationTypeEntry {
+        AssociationTypeEntry {
+            atype: bytes.read_u16::<LittleEndian>().unwrap(),
+            id1_type: bytes.read_u16::<LittleEndian>().unwrap(),
+            id2_type: bytes.read_u16::<LittleEndian>().unwrap(),
+        }
+    }
+}
+
+/// The object types registered on an object table, stored in the table under
+/// tao::REGISTRY_KEY. Since tables belong to a tenant, so does the registry.
+///
+/// # Layout
+/// |version = 1|header_len = 1|flags = 1|type_len = 1|assoc_type_len = 1|num_types = 2|
+/// |num_assoc_types = 2|...|
+/// followed by `num_types` object types of `type_len` bytes each, and then `num_assoc_types`
+/// association types of `assoc_type_len` bytes each.
+///
+/// The header and entries carry their own lengths, so that later versions can append fields
+/// to them. Readers skip fields they do not know about, and so can read registries written by
+/// any version.
+struct Registry {
+    version: u8,
+    flags: u8,
+    types: Vec<TypeEntry>,
+    atypes: Vec<AssociationTypeEntry>,
+}
+
+impl Registry {
+    /// Returns an empty, lenient registry.
+    fn new() -> Registry {
+        Registry {
+            version: REGISTRY_VERSION,
+            flags: 0,
+            types: Vec::new(),
+            atypes: Vec::new(),
+        }
+    }
+
+    /// Returns the registry in the layout above. Always writes REGISTRY_VERSION.
+    fn serialize(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(
+            REGISTRY_HEADER_LEN + self.types.len() * TYPE_ENTRY_LEN
+                + self.atypes.len() * ASSOC_TYPE_ENTRY_LEN,
+        );
+        bytes.push(REGISTRY_VERSION);
+        bytes.push(REGISTRY_HEADER_LEN as u8);
+        bytes.push(self.flags);
+        bytes.push(TYPE_ENTRY_LEN as u8);
+        bytes.push(ASSOC_TYPE_ENTRY_LEN as u8);
+        bytes
+            .write_u16::<LittleEndian>(self.types.len() as u16)
+            .unwrap();
+        bytes
+            .write_u16::<LittleEndian>(self.atypes.len() as u16)
+            .unwrap();
+
+        for entry in self.types.iter() {
+            entry.serialize(&mut bytes);
+        }
+        for entry in self.atypes.iter() {
+            entry.serialize(&mut bytes);
+        }
+        bytes
+    }
+
+    /// Returns the registry in `bytes`, or an error if it is malformed. An empty record is an
+    /// empty, lenient registry.
+    fn deserialize(bytes: &[u8]) -> Result<Registry, TaoError> {
+        if bytes.is_empty() {
+            return Ok(Registry::new());
+        }
+
+        if bytes.len() < REGISTRY_HEADER_LEN {
+            return Err(TaoError::Registry);
+        }
+
+        let (version, header_len, flags) = (bytes[0], bytes[1] as usize, bytes[2]);
+        let (type_len, atype_len) = (bytes[3] as usize, bytes[4] as usize);
+        let num_types = (&bytes[5..7]).read_u16::<LittleEndian>().unwrap() as usize;
+        let num_atypes = (&bytes[7..9]).read_u16::<LittleEndian>().unwrap() as usize;
+
+        // Every version has atleast the fields of the first.
+        if version == 0 || header_len < REGISTRY_HEADER_LEN || type_len < TYPE_ENTRY_LEN
+            || atype_len < ASSOC_TYPE_ENTRY_LEN
+            || bytes.len() < header_len + num_types * type_len + num_atypes * atype_len
+        {
+            return Err(TaoError::Registry);
+        }
+
+        let (types, atypes) = bytes[header_len..].split_at(num_types * type_len);
+        Ok(Registry {
+            version: version,
+            flags: flags,
+            types: types.chunks(type_len).map(TypeEntry::deserialize).collect(),
+            atypes: atypes[..num_atypes * atype_len]
+                .chunks(atype_len)
+                .map(AssociationTypeEntry::deserialize)
+                .collect(),
+        })
+    }
+
+    /// Returns true if unregistered types are rejected.
+    fn strict(&self) -> bool {
+        self.flags & REGISTRY_STRICT != 0
+    }
+
+    /// Checks that an object of the given type and length can be written.
+    ///
+    /// # Arguments
+    /// * `otype` - the type of the object.
+    /// * `len` - the length of the object's data, excluding the header.
+    /// * `update` - true if the object already exists, false if it is being added.
+    fn check_object(&self, otype: ObjectType, len: usize, update: bool) -> Result<(), TaoError> {
+        match self.types.iter().find(|e| e.otype == otype) {
+            Some(entry) => {
+                if len < entry.min_len as usize {
+                    Err(TaoError::TooShort)
+                } else if len > entry.max_len as usize {
+                    Err(TaoError::TooLong)
+                } else if update && entry.flags & TYPE_FROZEN != 0 {
+                    Err(TaoError::Frozen)
+                } else {
+                    Ok(())
+                }
+            }
+
+            None if self.strict() => Err(TaoError::Unregistered),
+
+            None => Ok(()),
+        }
+    }
+
+    /// Checks that an association of the given type can connect objects of the given types.
+    fn check_assoc(
+        &self,
+        atype: AssociationType,
+        otype1: ObjectType,
+        otype2: ObjectType,
+    ) -> Result<(), TaoError> {
+        match self.atypes.iter().find(|e| e.atype == atype) {
+            Some(entry) => {
+                let permits = |allowed: ObjectType, otype: ObjectType| {
+                    allowed == ANY_TYPE || allowed == otype
+                };
+                if permits(entry.id1_type, otype1) && permits(entry.id2_type, otype2) {
+                    Ok(())
+                } else {
+                    Err(TaoError::BadEndpoint)
+                }
+            }
+
+            None if self.strict() => Err(TaoError::Unregistered),
+
+            None => Ok(()),
+        }
+    }
+}
+
 struct ObjectHeader {
     otype: ObjectType,
 }
@@ -652,32 +1388,16 @@ impl PartialEq for Association {
 impl Association {
     /// Returns the space in memory required to serialize this struct.
     fn size() -> usize {
-        let id_size = size_of::<Id>();
-        let time_size = size_of::<Time>();
-        id_size + time_size
+        tao::ASSOC_LEN
     }
 
     fn serialize(&self, bytes: &mut WriteBuf) {
-        bytes.write_u64(self.id, true);
-        bytes.write_u64(self.time, true);
+        bytes.write_slice(&tao::encode_assoc(self.id, self.time));
     }
 
-    fn deserialize(mut bytes: &[u8]) -> Result<Association, sandstorm::io::Error> {
-        Ok(Association {
-            id: bytes.read_u64::<LittleEndian>().unwrap(),
-            time: bytes.read_u64::<LittleEndian>().unwrap(),
-        })
-
-        // match bytes.read_u64::<LittleEndian>() {
-        //     Ok(id) => match bytes.read_u64::<LittleEndian>() {
-        //         Ok(time) => return Ok(Association {
-        //             id: id,
-        //             time: time,
-        //         }),
-        //         Err(e) => return Err(e),
-        //     },
-        //     Err(e) => return Err(e),
-        // };
+    fn deserialize(bytes: &[u8]) -> Result<Association, sandstorm::io::Error> {
+        let (id, time) = tao::decode_assoc(bytes).unwrap();
+        Ok(Association { id: id, time: time })
     }
 }
 
@@ -763,30 +1483,23 @@ impl AssociationList {
     }
 
     /// Removes the association with the given id from this association list.
-    /// (id_1, type, id_2)
+    /// (id_1, type, id_2). Returns false if there is no such association.
     ///
     /// # Costs
-    /// Memory: O(n) -> Allocates structure to return.
+    /// Memory: O(1)
     /// Time: O(n), where n = size of assoc_list
     ///
     /// # Arguments
     /// * `id_2` - the id of the association to be removed.
-    fn remove(&mut self, id_2: Id) {
-        for pos in 0..self.len() {
-            // Find the association.
-            if self.association_at(pos).id == id_2 {
-                // Shift everything down by one.
-                for idx in pos..self.len() - 1 {
-                    //re-arrange references.
-                    self.list[idx] = Association {
-                        id: self.list[idx - 1].id,
-                        time: self.list[idx - 1].time,
-                    };
-                }
-                // Remove extra space at end? This looks to copy data but might save space?
-                self.list.shrink_to_fit();
-                break;
+    fn remove(&mut self, id_2: Id) -> bool {
+        // Find the association, and shift everything after it down by one.
+        match self.list.iter().position(|assoc| assoc.id == id_2) {
+            Some(pos) => {
+                self.list.remove(pos);
+                true
             }
+
+            None => false,
         }
     }
 
@@ -828,38 +1541,630 @@ impl AssociationList {
 mod tests {
     use super::*;
 
+    use sandstorm::entry;
+    use sandstorm::mock::MockDB;
+    use sandstorm::tao::Fanout;
+
+    // The number of objects in graphs populated by the tests.
+    const NUM: u32 = 128;
+
+    // Builds the arguments to an assoc_count, or to an assoc_range if `range` is supplied.
+    fn args(op: TaoOp, id1: Id, range: Option<(u32, u32)>) -> Vec<u8> {
+        let mut args = Vec::new();
+        args.push(op as u8);
+        args.write_u64::<LittleEndian>(tao::ASSOC_TABLE).unwrap();
+        args.extend_from_slice(&tao::list_key(id1, tao::FILL_ATYPE));
+        if let Some((offset, limit)) = range {
+            args.write_u32::<LittleEndian>(offset).unwrap();
+            args.write_u32::<LittleEndian>(limit).unwrap();
+        }
+        args
+    }
+
+    // Returns a database with the given arguments, on which the object, association and hot
+    // tables have been created.
+    fn mock(args: &[u8]) -> Rc<MockDB> {
+        let db = Rc::new(MockDB::with_args(args));
+        for &table in [tao::OBJECT_TABLE, tao::ASSOC_TABLE, tao::HOT_TABLE].iter() {
+            db.create_table(table);
+        }
+        db
+    }
+
+    // Populates a graph exactly as the server's TAO fill would, and invokes the extension on
+    // it. Returns the response.
+    fn invoke(fanout: &Fanout, args: &[u8]) -> Vec<u8> {
+        let db = mock(args);
+        tao::fill(NUM, 1..(NUM + 1), fanout, |table, key, val| {
+            db.insert(table, key, val)
+        });
+
+        assert_eq!(0, entry::run("tao", &(Rc::clone(&db) as Rc<DB>), dispatch));
+        db.response()
+    }
+
+    // Returns the associations the generation model gives an object, newest first.
+    fn expected(fanout: &Fanout, id1: u32) -> Vec<(Id, Time)> {
+        let n = fanout.fanout(id1, NUM);
+        (0..n)
+            .map(|j| (tao::fill_target(id1, j, NUM), tao::fill_time(j, n)))
+            .collect()
+    }
+
+    fn decode(resp: &[u8]) -> Vec<(Id, Time)> {
+        assert_eq!(0, resp.len() % tao::ASSOC_LEN);
+        resp.chunks(tao::ASSOC_LEN)
+            .map(|r| tao::decode_assoc(r).unwrap())
+            .collect()
+    }
+
+    // Builds the arguments to an object or registry operation on the object table.
+    fn obj_args(op: TaoOp, rest: &[u8]) -> Vec<u8> {
+        let mut args = Vec::new();
+        args.push(op as u8);
+        args.write_u64::<LittleEndian>(tao::OBJECT_TABLE).unwrap();
+        args.extend_from_slice(rest);
+        args
+    }
+
+    // Returns an object's key, or the part of the arguments that identifies it.
+    fn key(id: Id) -> Vec<u8> {
+        let mut key = Vec::new();
+        key.write_u64::<LittleEndian>(id).unwrap();
+        key
+    }
+
+    // Returns the arguments to an object_add or object_update (if `id` is supplied).
+    fn write_args(id: Option<Id>, otype: ObjectType, len: usize) -> Vec<u8> {
+        let mut rest = Vec::new();
+        match id {
+            Some(id) => {
+                rest.extend_from_slice(&key(id));
+                rest.write_u16::<LittleEndian>(otype).unwrap();
+                rest.extend_from_slice(&[7; 64][0..len]);
+                obj_args(TaoOp::ObjUpdate, &rest)
+            }
+
+            None => {
+                rest.write_u16::<LittleEndian>(otype).unwrap();
+                rest.extend_from_slice(&[7; 64][0..len]);
+                obj_args(TaoOp::ObjAdd, &rest)
+            }
+        }
+    }
+
+    // Returns a database with the given arguments, whose object table has type 1 registered
+    // with data of 4 to 8 bytes, frozen type 2 registered with data of upto 16 bytes, and
+    // association type 5 registered from objects of type 1 to objects of any type.
+    fn registered(args: &[u8], flags: u8) -> Rc<MockDB> {
+        let db = mock(args);
+        let tao = TAO::new(Rc::clone(&db) as Rc<DB>, tao::OBJECT_TABLE, 0);
+        let types = [
+            TypeEntry {
+                otype: 2,
+                min_len: 0,
+                max_len: 16,
+                flags: TYPE_FROZEN,
+            },
+            TypeEntry {
+                otype: 1,
+                min_len: 4,
+                max_len: 8,
+                flags: 0,
+            },
+        ];
+        for entry in types.iter() {
+            assert_eq!(Ok(()), tao.register_type(*entry));
+        }
+        let assoc = AssociationTypeEntry {
+            atype: 5,
+            id1_type: 1,
+            id2_type: ANY_TYPE,
+        };
+        assert_eq!(Ok(()), tao.register_assoc_type(assoc));
+        assert_eq!(Ok(()), tao.set_registry_flags(flags));
+        db
+    }
+
+    // Invokes the extension on a database, returning the response.
+    fn run(db: &Rc<MockDB>) -> Vec<u8> {
+        assert_eq!(0, entry::run("tao", &(Rc::clone(db) as Rc<DB>), dispatch));
+        db.response()
+    }
+
     #[test]
     fn it_works() {
         assert_eq!(2 + 2, 4);
     }
+
+    // Tests that a panic on an unknown opcode is contained at the entry point, and responded
+    // with, instead of unwinding into the database.
     #[test]
-    fn ser_dser() {
-        let assoc = Association { id: 1, time: 2 };
-        let mut data = vec![];
-        let assoc_des: Association = match assoc.serialize(&mut data) {
-            None => Association::deserialize(data.as_slice()).unwrap(),
-            Some(e) => return,
-        };
-        assert_eq!(assoc, assoc_des);
+    fn test_invalid_opcode() {
+        let db = mock(&[0xff]);
+        assert_eq!(1, entry::run("tao", &(Rc::clone(&db) as Rc<DB>), dispatch));
+        assert_eq!(&b"Extension tao panicked"[..], &db.response()[..]);
     }
 
     #[test]
     fn ser_dser_list() {
-        let mut list: Vec<Association> = Vec::with_capacity(1);
+        let mut data = Vec::new();
+        for i in 0..50000 {
+            data.extend_from_slice(&tao::encode_assoc(i, i + 1));
+        }
+
+        let alist = AssociationList::deserialize(data.as_slice()).unwrap();
+        assert_eq!(50000, alist.len());
+        assert_eq!(data.len(), alist.size());
         for i in 0..50000 {
-            list.push(Association {
-                id: i,
-                time: i as u64,
-            });
+            assert_eq!(i as Id, alist.association_at(i).id);
+            assert_eq!(i as Time + 1, alist.association_at(i).time);
+        }
+    }
+
+    // Tests that assoc_count matches the generation model for every object, under every kind
+    // of fanout distribution.
+    #[test]
+    fn test_assoc_count() {
+        let fanouts = [
+            Fanout::Constant(4),
+            Fanout::Zipf {
+                max: 100,
+                skew: 1.0,
+            },
+            Fanout::Buckets(Vec::from(&[(0, 3), (1, 2), (NUM - 1, 1)][..])),
+        ];
+
+        for fanout in fanouts.iter() {
+            for id1 in 1..(NUM + 1) {
+                let resp = invoke(fanout, &args(TaoOp::AssocCount, id1 as Id, None));
+                let count = (&resp[..]).read_u32::<LittleEndian>().unwrap();
+                assert_eq!(4, resp.len());
+                assert_eq!(fanout.fanout(id1, NUM), count);
+            }
+        }
+    }
+
+    // Tests that assoc_range returns exactly the modeled associations, including on objects
+    // with no associations and on the object with the largest fanout.
+    #[test]
+    fn test_assoc_range() {
+        let zipf = Fanout::Zipf {
+            max: 100,
+            skew: 1.0,
+        };
+
+        // Object 1 has the largest fanout.
+        let all = expected(&zipf, 1);
+        assert_eq!(100, all.len());
+        let resp = invoke(&zipf, &args(TaoOp::AssocRange, 1, Some((0, 1000))));
+        assert_eq!(all, decode(&resp));
+
+        let resp = invoke(&zipf, &args(TaoOp::AssocRange, 1, Some((10, 5))));
+        assert_eq!(&all[10..15], &decode(&resp)[..]);
+
+        let resp = invoke(&zipf, &args(TaoOp::AssocRange, 1, Some((95, 10))));
+        assert_eq!(&all[95..], &decode(&resp)[..]);
+
+        let resp = invoke(&zipf, &args(TaoOp::AssocRange, 1, Some((200, 10))));
+        assert!(resp.is_empty());
+
+        // Every object from 101 onwards has no associations.
+        assert_eq!(0, zipf.fanout(101, NUM));
+        let resp = invoke(&zipf, &args(TaoOp::AssocRange, 101, Some((0, 10))));
+        assert!(resp.is_empty());
+
+        let all = args(TaoOp::AssocRange, 37, Some((0, u32::max_value())));
+        let resp = invoke(&zipf, &all);
+        assert_eq!(expected(&zipf, 37), decode(&resp));
+    }
+
+    // Tests that association_delete takes an association off both it's list and the association
+    // table, and that deleting it again fails.
+    #[test]
+    fn test_assoc_delete() {
+        let fanout = Fanout::Constant(4);
+        let db = mock(&[]);
+        tao::fill(NUM, 1..(NUM + 1), &fanout, |table, key, val| {
+            db.insert(table, key, val)
+        });
+        let tao = TAO::new(
+            Rc::clone(&db) as Rc<DB>,
+            tao::OBJECT_TABLE,
+            tao::ASSOC_TABLE,
+        );
+
+        let mut all = expected(&fanout, 1);
+        let (id2, _) = all.remove(1);
+        let list = tao::list_key(1, tao::FILL_ATYPE);
+        let (id1, atype) = list.split_at(size_of::<Id>());
+        assert!(tao.association_delete(id1, atype, &key(id2)));
+
+        let rest = db.get(tao::ASSOC_TABLE, &list).unwrap();
+        assert_eq!(all, decode(rest.read()));
+        assert_eq!(3, tao.association_count(&list));
+        let assoc = tao::assoc_key(1, tao::FILL_ATYPE, id2);
+        assert!(!db.del(tao::ASSOC_TABLE, &assoc));
+
+        assert!(!tao.association_delete(id1, atype, &key(id2)));
+        assert!(tao.object_delete(&key(2)));
+        assert!(!tao.object_delete(&key(2)));
+    }
+
+    #[test]
+    fn test_assoc_bad_length() {
+        let mut short = args(TaoOp::AssocRange, 1, Some((0, 1)));
+        short.pop();
+        let resp = invoke(&Fanout::Constant(4), &short);
+        assert_eq!("Invalid packet length.".as_bytes(), &resp[..]);
+
+        let long = args(TaoOp::AssocCount, 1, Some((0, 1)));
+        let resp = invoke(&Fanout::Constant(4), &long);
+        assert_eq!("Invalid packet length.".as_bytes(), &resp[..]);
+    }
+
+    // Tests that assoc_count defers bumping the list's hot counter instead of doing it before
+    // responding, and that a malformed request defers nothing.
+    #[test]
+    fn test_assoc_count_defers() {
+        let count = args(TaoOp::AssocCount, 3, None);
+        let db = mock(&count);
+        let resp = run(&db);
+        assert_eq!(4, resp.len());
+        assert_eq!(vec![count], db.deferred());
+
+        let key = tao::list_key(3, tao::FILL_ATYPE);
+        assert!(db.get(tao::HOT_TABLE, &key).is_none());
+
+        let db = mock(&args(TaoOp::AssocCount, 3, Some((0, 1))));
+        run(&db);
+        assert!(db.deferred().is_empty());
+    }
+
+    // Tests that running the deferred work bumps the list's hot counter, creating it if need
+    // be, and sends nothing out.
+    #[test]
+    fn test_deferred_hot_count() {
+        let deferred = defer::encode_args(&args(TaoOp::AssocCount, 3, None));
+        let key = tao::list_key(3, tao::FILL_ATYPE);
+        let counter = |db: &Rc<MockDB>| {
+            let count = db.get(tao::HOT_TABLE, &key).unwrap();
+            let mut count = count.read();
+            count.read_u64::<LittleEndian>().unwrap()
+        };
+
+        let db = mock(&deferred);
+        assert!(run(&db).is_empty());
+        assert_eq!(1, counter(&db));
+        assert!(db.deferred().is_empty());
+
+        let db = mock(&deferred);
+        db.insert(tao::HOT_TABLE, &key, &[41, 0, 0, 0, 0, 0, 0, 0]);
+        assert!(run(&db).is_empty());
+        assert_eq!(42, counter(&db));
+
+        // Deferred work the extension doesn't know of is ignored.
+        let unknown = defer::encode_args(&[TaoOp::ObjGet as u8]);
+        let db = mock(&unknown);
+        assert!(run(&db).is_empty());
+        assert!(db.get(tao::HOT_TABLE, &key).is_none());
+    }
+
+    // Tests that registered types are stored, replaced, and listed in order of type.
+    #[test]
+    fn test_registry_round_trip() {
+        let db = registered(&obj_args(TaoOp::ListTypes, &[]), REGISTRY_STRICT);
+        let tao = TAO::new(Rc::clone(&db) as Rc<DB>, tao::OBJECT_TABLE, 0);
+        let replaced = TypeEntry {
+            otype: 2,
+            min_len: 1,
+            max_len: 2,
+            flags: 0,
+        };
+        assert_eq!(Ok(()), tao.register_type(replaced));
+
+        let resp = run(&db);
+        assert_eq!(
+            REGISTRY_HEADER_LEN + 2 * TYPE_ENTRY_LEN + ASSOC_TYPE_ENTRY_LEN,
+            resp.len()
+        );
+        assert_eq!(&[1, 9, REGISTRY_STRICT, 11, 6, 2, 0, 1, 0], &resp[0..9]);
+
+        let listed = Registry::deserialize(&resp).unwrap();
+        assert_eq!(REGISTRY_VERSION, listed.version);
+        assert!(listed.strict());
+        assert_eq!(
+            Vec::from(&[1, 2][..]),
+            listed.types.iter().map(|e| e.otype).collect::<Vec<_>>()
+        );
+        assert_eq!(8, listed.types[0].max_len);
+        assert_eq!(replaced, listed.types[1]);
+        assert_eq!(1, listed.atypes.len());
+        assert_eq!(
+            AssociationTypeEntry {
+                atype: 5,
+                id1_type: 1,
+                id2_type: ANY_TYPE,
+            },
+            listed.atypes[0]
+        );
+
+        // The listing is exactly what is stored.
+        let stored = db.get(tao::OBJECT_TABLE, &tao::REGISTRY_KEY).unwrap();
+        assert_eq!(&resp[..], stored.read());
+
+        // A table without a registry lists as an empty, lenient one.
+        let empty = mock(&obj_args(TaoOp::ListTypes, &[]));
+        assert_eq!(&[1, 9, 0, 11, 6, 0, 0, 0, 0], &run(&empty)[..]);
+    }
+
+    // Tests that object_add and object_update reject data outside a type's bounds, and
+    // updates to frozen types, without writing anything.
+    #[test]
+    fn test_registry_enforcement() {
+        let cases = [
+            (None, 1, 4, None),
+            (None, 1, 8, None),
+            (None, 1, 3, Some(TaoError::TooShort)),
+            (None, 1, 9, Some(TaoError::TooLong)),
+            (None, 2, 16, None),
+            (None, 2, 17, Some(TaoError::TooLong)),
+            (Some(7), 1, 6, None),
+            (Some(7), 1, 2, Some(TaoError::TooShort)),
+            (Some(7), 1, 40, Some(TaoError::TooLong)),
+            (Some(7), 2, 1, Some(TaoError::Frozen)),
+        ];
+
+        for &(id, otype, len, error) in cases.iter() {
+            let db = registered(&write_args(id, otype, len), 0);
+            let resp = run(&db);
+            let object = db.get(tao::OBJECT_TABLE, &key(id.unwrap_or(1)));
+
+            match error {
+                Some(e) => {
+                    assert_eq!(Some((e, otype)), tao::decode_error(&resp));
+                    assert!(object.is_none());
+                }
+
+                None => {
+                    let object = object.unwrap();
+                    if id.is_none() {
+                        assert_eq!(key(1), resp);
+                    } else {
+                        assert!(resp.is_empty());
+                    }
+                    assert_eq!(ObjectHeader::size() + len, object.len());
+                    assert_eq!(
+                        otype,
+                        ObjectHeader::deserialize(object.read()).unwrap().otype
+                    );
+                }
+            }
         }
-        let alist = AssociationList { list: list };
 
-        let mut data = vec![];
-        let assoc_des = match alist.serialize(&mut data) {
-            None => AssociationList::deserialize(data.as_slice()).unwrap(),
-            Some(e) => return,
+        let db = registered(&[], 0);
+        let mut tao = TAO::new(Rc::clone(&db) as Rc<DB>, tao::OBJECT_TABLE, 0);
+        let bad = TypeEntry {
+            otype: 3,
+            min_len: 9,
+            max_len: 8,
+            flags: 0,
+        };
+        assert_eq!(Err(TaoError::BadBounds), tao.register_type(bad));
+        assert_eq!(Ok(key(1)), tao.object_add(3, &[0; 100]));
+
+        let mut args = Vec::new();
+        bad.serialize(&mut args);
+        let db = mock(&obj_args(TaoOp::RegisterType, &args));
+        assert_eq!(Some((TaoError::BadBounds, 3)), tao::decode_error(&run(&db)));
+
+        args[2] = 8;
+        let db = mock(&obj_args(TaoOp::RegisterType, &args));
+        assert!(run(&db).is_empty());
+        let registry = TAO::new(Rc::clone(&db) as Rc<DB>, tao::OBJECT_TABLE, 0).registry();
+        assert_eq!(8, registry.unwrap().types[0].min_len);
+    }
+
+    // Tests that unregistered types are accepted by a lenient registry, and rejected by a
+    // strict one.
+    #[test]
+    fn test_registry_strictness() {
+        for &flags in [0, REGISTRY_STRICT].iter() {
+            let db = registered(&write_args(None, 9, 4), flags);
+            let resp = run(&db);
+            if flags == 0 {
+                assert_eq!(key(1), resp);
+            } else {
+                assert_eq!(Some((TaoError::Unregistered, 9)), tao::decode_error(&resp));
+            }
+
+            let db = registered(&write_args(Some(7), 9, 4), flags);
+            let resp = run(&db);
+            if flags == 0 {
+                assert!(resp.is_empty());
+            } else {
+                assert_eq!(Some((TaoError::Unregistered, 9)), tao::decode_error(&resp));
+            }
+
+            // Registered types are enforced the same either way.
+            let db = registered(&write_args(None, 1, 5), flags);
+            assert_eq!(key(1), run(&db));
+        }
+    }
+
+    // Tests that registries written by a later version, with longer headers and entries, are
+    // read and enforced but not modified, and that malformed registries are rejected.
+    #[test]
+    fn test_registry_versions() {
+        // A version 2 registry, with a byte appended to the header and two to each entry.
+        let mut v2 = Vec::from(&[2, 10, REGISTRY_STRICT, 13, 8, 1, 0, 1, 0, 0xaa][..]);
+        v2.extend_from_slice(&[1, 0, 4, 0, 0, 0, 8, 0, 0, 0, 0, 0xbb, 0xbb]);
+        v2.extend_from_slice(&[5, 0, 1, 0, 0xff, 0xff, 0xcc, 0xcc]);
+
+        let db = mock(&write_args(None, 1, 9));
+        db.insert(tao::OBJECT_TABLE, &tao::REGISTRY_KEY, &v2);
+        assert_eq!(Some((TaoError::TooLong, 1)), tao::decode_error(&run(&db)));
+
+        let tao = TAO::new(Rc::clone(&db) as Rc<DB>, tao::OBJECT_TABLE, 0);
+        let registry = tao.registry().unwrap();
+        assert_eq!(2, registry.version);
+        assert!(registry.strict());
+        assert_eq!(1, registry.types.len());
+        assert_eq!(
+            TypeEntry {
+                otype: 1,
+                min_len: 4,
+                max_len: 8,
+                flags: 0,
+            },
+            registry.types[0]
+        );
+        assert_eq!(Ok(()), registry.check_assoc(5, 1, 3));
+        assert_eq!(
+            Err(TaoError::Unregistered),
+            registry.check_object(2, 1, false)
+        );
+
+        // Storing it would drop the fields added by version 2.
+        assert_eq!(Err(TaoError::Registry), tao.set_registry_flags(0));
+        let stored = db.get(tao::OBJECT_TABLE, &tao::REGISTRY_KEY).unwrap();
+        assert_eq!(&v2[..], stored.read());
+
+        // A registry this version writes reads back the same.
+        let v1 = registered(&[], REGISTRY_STRICT);
+        let stored = v1.get(tao::OBJECT_TABLE, &tao::REGISTRY_KEY).unwrap();
+        assert_eq!(
+            stored.read(),
+            &Registry::deserialize(stored.read()).unwrap().serialize()[..]
+        );
+
+        let malformed = [
+            Vec::from(&[0, 9, 0, 11, 6, 0, 0, 0, 0][..]),
+            Vec::from(&[1, 8, 0, 11, 6, 0, 0, 0, 0][..]),
+            Vec::from(&[1, 9, 0, 10, 6, 0, 0, 0, 0][..]),
+            Vec::from(&[1, 9, 0, 11, 5, 0, 0, 0, 0][..]),
+            Vec::from(&[1, 9, 0, 11, 6, 1, 0, 0, 0][..]),
+            Vec::from(&[1, 9, 0, 11][..]),
+            Vec::from(&v2[0..v2.len() - 1]),
+        ];
+        for record in malformed.iter() {
+            let db = mock(&write_args(None, 1, 4));
+            db.insert(tao::OBJECT_TABLE, &tao::REGISTRY_KEY, record);
+            assert_eq!(Some((TaoError::Registry, 1)), tao::decode_error(&run(&db)));
+
+            let db = mock(&obj_args(TaoOp::ListTypes, &[]));
+            db.insert(tao::OBJECT_TABLE, &tao::REGISTRY_KEY, record);
+            assert_eq!(Some((TaoError::Registry, 0)), tao::decode_error(&run(&db)));
+        }
+    }
+
+    // Tests that the registry is read once per TAO instance, and kept up to date by writes
+    // through it.
+    #[test]
+    fn test_registry_cached() {
+        let db = registered(&[], 0);
+        let mut tao = TAO::new(Rc::clone(&db) as Rc<DB>, tao::OBJECT_TABLE, 0);
+        assert_eq!(Err(TaoError::TooLong), tao.object_add(1, &[0; 9]));
+
+        // Changes made behind the instance's back are not seen by it, only by new instances.
+        db.insert(
+            tao::OBJECT_TABLE,
+            &tao::REGISTRY_KEY,
+            &Registry::new().serialize(),
+        );
+        assert_eq!(Err(TaoError::TooLong), tao.object_add(1, &[0; 9]));
+        let mut fresh = TAO::new(Rc::clone(&db) as Rc<DB>, tao::OBJECT_TABLE, 0);
+        assert_eq!(Ok(key(1)), fresh.object_add(1, &[0; 9]));
+
+        // Changes made through the instance are.
+        assert_eq!(Ok(()), fresh.set_registry_flags(REGISTRY_STRICT));
+        assert_eq!(Err(TaoError::Unregistered), fresh.object_add(1, &[0; 9]));
+    }
+
+    // Tests that reservations hand out disjoint ranges that start no lower than asked for, and
+    // that object_add allocates past every range reserved before it.
+    #[test]
+    fn test_reserve_ids() {
+        let reserve = |count: u32, floor: Id| {
+            let mut rest = Vec::new();
+            rest.write_u32::<LittleEndian>(count).unwrap();
+            rest.write_u64::<LittleEndian>(floor).unwrap();
+            obj_args(TaoOp::ReserveIds, &rest)
+        };
+
+        // A client that populated a graph of NUM objects reserves past it.
+        let resp = invoke(&Fanout::Constant(4), &reserve(100, NUM as Id + 1));
+        assert_eq!(key(NUM as Id + 1), resp);
+
+        let db = mock(&reserve(100, 1));
+        assert_eq!(key(1), run(&db));
+
+        let tao = TAO::new(Rc::clone(&db) as Rc<DB>, tao::OBJECT_TABLE, 0);
+        assert_eq!(Ok(101), tao.reserve_ids(50, 1));
+        assert_eq!(Ok(1000), tao.reserve_ids(10, 1000));
+        assert_eq!(Ok(1010), tao.reserve_ids(10, 500));
+
+        let mut tao = TAO::new(Rc::clone(&db) as Rc<DB>, tao::OBJECT_TABLE, 0);
+        assert_eq!(Ok(key(1020)), tao.object_add(1, &[0; 4]));
+        assert_eq!(Ok(1021), tao.reserve_ids(1, 1));
+
+        // The counter's own key is never handed out as an id.
+        let max = Id::max_value();
+        assert_eq!(Ok(max - 1), tao.reserve_ids(1, max - 1));
+        assert_eq!(Err(TaoError::Exhausted), tao.reserve_ids(1, 1));
+        assert_eq!(Err(TaoError::Exhausted), tao.object_add(1, &[0; 4]));
+
+        let db = mock(&reserve(2, max - 1));
+        assert_eq!(Some((TaoError::Exhausted, 0)), tao::decode_error(&run(&db)));
+
+        let mut short = reserve(1, 1);
+        short.pop();
+        let db = mock(&short);
+        assert_eq!("Invalid packet length.".as_bytes(), &run(&db)[..]);
+    }
+
+    // Tests that an association add with the object table checks it's endpoints, and one
+    // without it does not.
+    #[test]
+    fn test_assoc_endpoints() {
+        let add = |id1: Id, atype: u16, id2: Id, checked: bool| {
+            let mut args = Vec::new();
+            args.push(TaoOp::AssocAdd as u8);
+            args.write_u64::<LittleEndian>(tao::ASSOC_TABLE).unwrap();
+            args.extend_from_slice(&tao::assoc_key(id1, atype, id2));
+            if checked {
+                args.write_u64::<LittleEndian>(tao::OBJECT_TABLE).unwrap();
+            }
+
+            let db = registered(&args, 0);
+            db.insert(tao::OBJECT_TABLE, &key(10), &[1, 0, 7, 7, 7, 7]);
+            db.insert(tao::OBJECT_TABLE, &key(11), &[2, 0, 7]);
+            db.insert(tao::OBJECT_TABLE, &key(12), &[9, 0, 7]);
+            let resp = run(&db);
+            let list = db.get(tao::ASSOC_TABLE, &tao::list_key(id1, atype));
+            (
+                tao::decode_error(&resp).map(|(e, _)| e),
+                list.map_or(0, |list| list.len()),
+            )
         };
 
-        assert_eq!(alist, assoc_des);
+        assert_eq!((None, tao::ASSOC_LEN), add(10, 5, 11, true));
+        assert_eq!((None, tao::ASSOC_LEN), add(10, 5, 12, true));
+        assert_eq!((Some(TaoError::BadEndpoint), 0), add(11, 5, 10, true));
+        assert_eq!((Some(TaoError::NoEndpoint), 0), add(10, 5, 13, true));
+        assert_eq!((Some(TaoError::NoEndpoint), 0), add(13, 5, 10, true));
+        assert_eq!((None, tao::ASSOC_LEN), add(11, 6, 10, true));
+        assert_eq!((None, tao::ASSOC_LEN), add(11, 5, 13, false));
+
+        let db = registered(&[], REGISTRY_STRICT);
+        db.insert(tao::OBJECT_TABLE, &key(10), &[1, 0, 7, 7, 7, 7]);
+        let tao = TAO::new(
+            Rc::clone(&db) as Rc<DB>,
+            tao::OBJECT_TABLE,
+            tao::ASSOC_TABLE,
+        );
+        let check = |atype: u16| tao.check_endpoints(&key(10), &[atype as u8, 0], &key(10));
+        assert_eq!(Ok(()), check(5));
+        assert_eq!(Err(TaoError::Unregistered), check(6));
     }
 }
@@ -0,0 +1,410 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use std::cmp;
+use std::ops::Range;
+
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use crypto::blowfish::Blowfish;
+
+/// Length of a user's key, their username.
+pub const USERNAME_LEN: usize = 30;
+
+/// Length of the password an auth invocation checks.
+pub const PASSWORD_LEN: usize = 72;
+
+/// Length of the auth extension's arguments; the 8 byte table id, the username, and the
+/// password.
+pub const ARGS_LEN: usize = 8 + USERNAME_LEN + PASSWORD_LEN;
+
+/// Length of a record's header; the 1 byte bcrypt cost followed by the 1 byte hash length.
+pub const HEADER_LEN: usize = 2;
+
+/// The longest hash a record can hold, the whole of bcrypt's output.
+pub const MAX_HASH_LEN: usize = 24;
+
+/// The longest salt a record can hold. Shorter salts are zero padded to this length before
+/// they are hashed with.
+pub const MAX_SALT_LEN: usize = 16;
+
+/// The largest bcrypt cost a record can be hashed at. Each increment doubles the work.
+pub const MAX_COST: u8 = 16;
+
+/// The bcrypt rounds run between yields when checking a password. Two rounds, the whole of
+/// cost 1, take tens of microseconds.
+pub const ROUNDS_PER_STEP: u32 = 2;
+
+// The bcrypt magic, "OrpheanBeholderScryDoubt", as big endian words.
+const MAGIC: [u32; 6] = [
+    0x4f727068, 0x65616e42, 0x65686f6c, 0x64657253, 0x63727944, 0x6f756274,
+];
+
+/// The layout of the records of the AUTH dataset. A record is the bcrypt cost it was hashed at
+/// (1 byte), the length of it's hash (1 byte), the hash, and the salt, which runs to the end of
+/// the record. Records describe themselves, so a reader needs no layout to check a password
+/// against one.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Layout {
+    cost: u8,
+    hash_len: usize,
+    salt_len: usize,
+}
+
+impl Default for Layout {
+    /// Returns the layout of a cost 1 hash, kept whole, with a 16 byte salt.
+    fn default() -> Layout {
+        Layout {
+            cost: 1,
+            hash_len: MAX_HASH_LEN,
+            salt_len: MAX_SALT_LEN,
+        }
+    }
+}
+
+impl Layout {
+    /// Returns a layout, or None if a parameter is out of range.
+    ///
+    /// # Arguments
+    ///
+    /// * `cost`:     The bcrypt cost records are hashed at, between 1 and MAX_COST.
+    /// * `hash_len`: The length of the hash kept on records, between 1 and MAX_HASH_LEN. A
+    ///               shorter hash keeps the leading bytes of bcrypt's output.
+    /// * `salt_len`: The length of the salt on records, between 1 and MAX_SALT_LEN.
+    pub fn new(cost: u8, hash_len: usize, salt_len: usize) -> Option<Layout> {
+        if cost < 1 || cost > MAX_COST {
+            return None;
+        }
+        if hash_len < 1 || hash_len > MAX_HASH_LEN || salt_len < 1 || salt_len > MAX_SALT_LEN {
+            return None;
+        }
+
+        Some(Layout {
+            cost: cost,
+            hash_len: hash_len,
+            salt_len: salt_len,
+        })
+    }
+
+    /// Returns the bcrypt cost records are hashed at.
+    pub fn cost(&self) -> u8 {
+        self.cost
+    }
+
+    /// Returns the length of the hash on records.
+    pub fn hash_len(&self) -> usize {
+        self.hash_len
+    }
+
+    /// Returns the length of the salt on records.
+    pub fn salt_len(&self) -> usize {
+        self.salt_len
+    }
+
+    /// Returns the length of a record.
+    pub fn record_len(&self) -> usize {
+        HEADER_LEN + self.hash_len + self.salt_len
+    }
+
+    /// Hashes a password, and returns the record holding the hash.
+    ///
+    /// # Arguments
+    ///
+    /// * `password`: The password, between 1 and PASSWORD_LEN bytes long.
+    /// * `salt`:     The salt, `salt_len()` bytes long.
+    pub fn record(&self, password: &[u8], salt: &[u8]) -> Vec<u8> {
+        assert_eq!(self.salt_len, salt.len());
+        let mut hasher = Hasher::new(self.cost, salt, password);
+        hasher.step(u32::max_value());
+
+        let mut record = Vec::with_capacity(self.record_len());
+        record.push(self.cost);
+        record.push(self.hash_len as u8);
+        record.extend_from_slice(&hasher.hash()[0..self.hash_len]);
+        record.extend_from_slice(salt);
+        record
+    }
+}
+
+/// A view over a record of the AUTH dataset. Refer to Layout.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Record<'a> {
+    /// The bcrypt cost the record was hashed at.
+    pub cost: u8,
+
+    /// The hash, or the leading bytes of it.
+    pub hash: &'a [u8],
+
+    /// The salt, before it is zero padded.
+    pub salt: &'a [u8],
+}
+
+impl<'a> Record<'a> {
+    /// Returns a hasher that checks a password against the record, a few rounds at a time.
+    pub fn hasher(&self, password: &[u8]) -> Hasher {
+        Hasher::new(self.cost, self.salt, password)
+    }
+
+    /// Returns true if a password hashes to the record's hash. Hashes in one go; see hasher()
+    /// to hash a few rounds at a time.
+    pub fn matches(&self, password: &[u8]) -> bool {
+        let mut hasher = self.hasher(password);
+        hasher.step(u32::max_value());
+        hasher.matches(self.hash)
+    }
+}
+
+/// Parses a record of the AUTH dataset.
+///
+/// # Return
+///
+/// A view over the record, or None if it is malformed; too short for it's hash, with no salt
+/// or too long a salt, or with a cost or hash length out of range.
+pub fn parse(record: &[u8]) -> Option<Record> {
+    if record.len() < HEADER_LEN {
+        return None;
+    }
+
+    let (cost, hash_len) = (record[0], record[1] as usize);
+    let salt_len = record.len().saturating_sub(HEADER_LEN + hash_len);
+    Layout::new(cost, hash_len, salt_len)?;
+
+    let (hash, salt) = record[HEADER_LEN..].split_at(hash_len);
+    Some(Record {
+        cost: cost,
+        hash: hash,
+        salt: salt,
+    })
+}
+
+/// Returns the password of a user; their username, zero padded upto PASSWORD_LEN bytes.
+pub fn password(username: &[u8]) -> [u8; PASSWORD_LEN] {
+    let mut password = [0; PASSWORD_LEN];
+    let len = cmp::min(username.len(), PASSWORD_LEN);
+    password[0..len].copy_from_slice(&username[0..len]);
+    password
+}
+
+/// Generates the AUTH dataset for a range of users, handing every record to a callback. A
+/// user's username and salt hold it's id as a 4 byte little endian integer, zero padded; the
+/// salt is cut short if it is shorter than that. The password is the username (see password()).
+///
+/// # Arguments
+///
+/// * `layout`: The layout of the records.
+/// * `users`:  The ids of the users to generate records for.
+/// * `emit`:   Called with the username and the record of every user.
+pub fn fill<F>(layout: &Layout, users: Range<u32>, mut emit: F)
+where
+    F: FnMut(&[u8], &[u8]),
+{
+    let mut username = [0; USERNAME_LEN];
+    let mut id = [0; 4];
+    let mut salt = vec![0; layout.salt_len];
+
+    for user in users {
+        LittleEndian::write_u32(&mut id, user);
+        username[0..4].copy_from_slice(&id);
+        let len = cmp::min(4, salt.len());
+        salt[0..len].copy_from_slice(&id[0..len]);
+
+        emit(&username, &layout.record(&password(&username), &salt));
+    }
+}
+
+/// Computes the bcrypt hash of a password a few rounds at a time, so that extensions can yield
+/// in between instead of running for the whole of an expensive hash. The result is identical
+/// to rust-crypto's bcrypt().
+pub struct Hasher {
+    // The Blowfish state the key schedule is being run on.
+    state: Blowfish,
+
+    // The password, and the salt zero padded to MAX_SALT_LEN bytes.
+    password: Vec<u8>,
+    salt: [u8; MAX_SALT_LEN],
+
+    // The number of rounds of the key schedule left to run.
+    left: u32,
+}
+
+impl Hasher {
+    /// Returns a hasher that has run the salted key expansion, with every round left to run.
+    ///
+    /// # Arguments
+    ///
+    /// * `cost`:     The bcrypt cost; the key schedule runs 2^cost rounds. Atmost MAX_COST.
+    /// * `salt`:     The salt, atmost MAX_SALT_LEN bytes long.
+    /// * `password`: The password, between 1 and PASSWORD_LEN bytes long.
+    pub fn new(cost: u8, salt: &[u8], password: &[u8]) -> Hasher {
+        assert!(cost <= MAX_COST && salt.len() <= MAX_SALT_LEN);
+        assert!(!password.is_empty() && password.len() <= PASSWORD_LEN);
+
+        let mut padded = [0; MAX_SALT_LEN];
+        padded[0..salt.len()].copy_from_slice(salt);
+
+        let mut state = Blowfish::init_state();
+        state.salted_expand_key(&padded, password);
+        Hasher {
+            state: state,
+            password: password.to_vec(),
+            salt: padded,
+            left: 1 << cost,
+        }
+    }
+
+    /// Runs atmost `rounds` rounds of the key schedule, and returns true once none are left.
+    pub fn step(&mut self, rounds: u32) -> bool {
+        let rounds = cmp::min(rounds, self.left);
+        for _ in 0..rounds {
+            self.state.expand_key(&self.password);
+            self.state.expand_key(&self.salt);
+        }
+
+        self.left -= rounds;
+        self.left == 0
+    }
+
+    /// Returns the hash. Panics if step() has rounds left to run.
+    pub fn hash(&self) -> [u8; MAX_HASH_LEN] {
+        assert_eq!(0, self.left);
+
+        let mut ctext = MAGIC;
+        let mut hash = [0; MAX_HASH_LEN];
+        for pair in 0..3 {
+            let i = 2 * pair;
+            for _ in 0..64 {
+                let (l, r) = self.state.encrypt(ctext[i], ctext[i + 1]);
+                ctext[i] = l;
+                ctext[i + 1] = r;
+            }
+            BigEndian::write_u32(&mut hash[i * 4..(i + 1) * 4], ctext[i]);
+            BigEndian::write_u32(&mut hash[(i + 1) * 4..(i + 2) * 4], ctext[i + 1]);
+        }
+
+        hash
+    }
+
+    /// Returns true if the hash starts with `hash`, which is atleast a byte long. Panics if
+    /// step() has rounds left to run.
+    pub fn matches(&self, hash: &[u8]) -> bool {
+        !hash.is_empty() && hash.len() <= MAX_HASH_LEN && self.hash()[0..hash.len()] == *hash
+    }
+}
+
+// This module contains unit tests for the AUTH layout and the resumable hasher.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crypto::bcrypt::bcrypt;
+
+    // Tests that hashing a few rounds at a time hashes the same as bcrypt() at every cost.
+    #[test]
+    fn test_hasher() {
+        let (salt, password) = ([7; MAX_SALT_LEN], [9; PASSWORD_LEN]);
+        for cost in [0, 1, 4, 8].iter() {
+            let mut expected = [0; MAX_HASH_LEN];
+            bcrypt(*cost as u32, &salt, &password, &mut expected);
+
+            for step in [1, 3, u32::max_value()].iter() {
+                let mut hasher = Hasher::new(*cost, &salt, &password);
+                let mut steps = 1;
+                while !hasher.step(*step) {
+                    steps += 1;
+                }
+                assert_eq!(expected, hasher.hash());
+                assert_eq!(((1 << *cost) - 1) / step + 1, steps);
+            }
+        }
+    }
+
+    // Tests that records round-trip through parse(), and check passwords, at several costs and
+    // lengths.
+    #[test]
+    fn test_record() {
+        let secret = password(b"user");
+        let layouts = [(1, 24, 16), (2, 12, 8), (4, 1, 1), (6, 24, 4)];
+        for &(cost, hash_len, salt_len) in layouts.iter() {
+            let layout = Layout::new(cost, hash_len, salt_len).unwrap();
+            let salt: Vec<u8> = (1..salt_len as u8 + 1).collect();
+            let record = layout.record(&secret, &salt);
+            assert_eq!(layout.record_len(), record.len());
+            assert_eq!(&[cost, hash_len as u8], &record[0..HEADER_LEN]);
+
+            let parsed = parse(&record).unwrap();
+            assert_eq!((cost, hash_len), (parsed.cost, parsed.hash.len()));
+            assert_eq!(&salt[..], parsed.salt);
+            assert!(parsed.matches(&secret));
+            assert!(hash_len == 1 || !parsed.matches(&password(b"resu")));
+
+            let mut expected = [0; MAX_HASH_LEN];
+            let mut padded = [0; MAX_SALT_LEN];
+            padded[0..salt_len].copy_from_slice(&salt);
+            bcrypt(cost as u32, &padded, &secret, &mut expected);
+            assert_eq!(&expected[0..hash_len], parsed.hash);
+        }
+    }
+
+    // Tests that malformed layouts and records are refused.
+    #[test]
+    fn test_malformed() {
+        assert_eq!(Some(Layout::default()), Layout::new(1, 24, 16));
+        for &(cost, hash_len, salt_len) in [
+            (0, 24, 16),
+            (17, 24, 16),
+            (1, 0, 16),
+            (1, 25, 16),
+            (1, 24, 0),
+            (1, 24, 17),
+        ]
+        .iter()
+        {
+            assert_eq!(None, Layout::new(cost, hash_len, salt_len));
+        }
+
+        let record = Layout::default().record(&password(b"user"), &[0; MAX_SALT_LEN]);
+        assert!(parse(&record).is_some());
+        assert_eq!(None, parse(&record[0..1]));
+        assert_eq!(None, parse(&record[0..HEADER_LEN + MAX_HASH_LEN]));
+
+        let mut long = record.clone();
+        long.push(0);
+        assert_eq!(None, parse(&long));
+        for &(i, b) in [(0, 0), (0, MAX_COST + 1), (1, 0), (1, 25)].iter() {
+            let mut bad = record.clone();
+            bad[i] = b;
+            assert_eq!(None, parse(&bad));
+        }
+    }
+
+    // Tests that the dataset's records check the password derived from their username.
+    #[test]
+    fn test_fill() {
+        let layout = Layout::new(2, 8, 2).unwrap();
+        let mut users = Vec::new();
+        fill(&layout, 0x0301..0x0305, |username, record| {
+            users.push((username.to_vec(), record.to_vec()));
+        });
+
+        assert_eq!(4, users.len());
+        for (i, &(ref username, ref record)) in users.iter().enumerate() {
+            assert_eq!(USERNAME_LEN, username.len());
+            assert_eq!(&[1 + i as u8, 3, 0, 0], &username[0..4]);
+
+            let record = parse(record).unwrap();
+            assert_eq!(&[1 + i as u8, 3], record.salt);
+            assert!(record.matches(&password(username)));
+        }
+    }
+}
@@ -16,9 +16,34 @@
 extern crate bytes;
 
 use std::cell::Cell;
+use std::cmp::min;
+use std::ops::Range;
 
 use self::bytes::{BufMut, Bytes, BytesMut};
 
+/// This function returns the range of a value's bytes that a projection covers. A projection
+/// asks for `length` bytes starting at `offset`, where a length of 0 runs upto the end of the
+/// value; (0, 0) is therefore the whole value. A projection that runs past the end of the value
+/// is clamped to it, and one that starts past the end covers nothing.
+///
+/// # Arguments
+///
+/// * `len`:    The length of the value being projected.
+/// * `offset`: The offset into the value the projection starts at.
+/// * `length`: The number of bytes the projection asks for. 0 runs upto the end of the value.
+///
+/// # Return
+/// The range of the value's bytes covered by the projection.
+pub fn projection(len: usize, offset: u32, length: u32) -> Range<usize> {
+    let start = min(offset as usize, len);
+    let end = match length {
+        0 => len,
+        length => min(start.saturating_add(length as usize), len),
+    };
+
+    start..end
+}
+
 /// This type represents a read-only buffer of bytes that can be received from
 /// the database. This type is primarily used to read objects from the database.
 pub struct ReadBuf {
@@ -74,6 +99,25 @@ impl ReadBuf {
     pub fn read(&self) -> &[u8] {
         self.inner.as_ref()
     }
+
+    /// This method narrows the `ReadBuf` down to the bytes covered by a
+    /// projection. Refer to projection() for how the range is clamped.
+    ///
+    /// # Arguments
+    ///
+    /// * `offset`: The offset into the buffer the projection starts at.
+    /// * `length`: The number of bytes the projection asks for. 0 runs upto
+    ///             the end of the buffer.
+    ///
+    /// # Return
+    ///
+    /// A `ReadBuf` over the projected bytes. No data is copied.
+    pub fn project(self, offset: u32, length: u32) -> ReadBuf {
+        let range = projection(self.inner.len(), offset, length);
+        ReadBuf {
+            inner: self.inner.slice(range.start, range.end),
+        }
+    }
 }
 
 /// This type represents a read-write buffer of bytes that can be received from
@@ -151,6 +195,25 @@ impl WriteBuf {
         self.inner.put_slice(data);
     }
 
+    /// This method writes several slices of bytes to the end of the `WriteBuf`,
+    /// one after the other, so that a value assembled from several pieces does
+    /// not have to be copied into a single buffer first.
+    ///
+    /// # Arguments
+    ///
+    /// * `segments`: The slices of bytes to be written into the `WriteBuf`, in
+    ///               order. Slices can be empty.
+    ///
+    /// # Abort
+    ///
+    /// This method will cause the extension to abort if there is insufficent
+    /// space left inside the `WriteBuf` to perform the write.
+    pub fn write_slices(&mut self, segments: &[&[u8]]) {
+        for segment in segments.iter() {
+            self.inner.put_slice(segment);
+        }
+    }
+
     /// This method writes a single byte to the end of the `WriteBuf`.
     ///
     /// # Arguments
@@ -251,10 +314,14 @@ impl WriteBuf {
 
 /// This type represents a read-only buffer of bytes that can be received from
 /// the database. This type is primarily used to read objects from the database
-/// in response to a multiget() operation.
+/// in response to a multiget() operation. Every key looked up has an entry in the
+/// list, in the order the keys were given; keys that were not found are reported
+/// by found() on their entry.
 pub struct MultiReadBuf {
     inner: Vec<Bytes>,
 
+    found: Vec<bool>,
+
     index: Cell<usize>,
 
     panic: Cell<bool>,
@@ -278,12 +345,63 @@ impl MultiReadBuf {
     /// The `MultiReadBuf` wrapping the passed in vector.
     pub unsafe fn new(list: Vec<Bytes>) -> MultiReadBuf {
         MultiReadBuf {
+            found: vec![true; list.len()],
             inner: list,
             index: Cell::new(0),
             panic: Cell::new(false),
         }
     }
 
+    /// This method returns a MultiReadBuf over the values of a list of keys, some of
+    /// which might not have been found. A missing key keeps it's entry in the list,
+    /// so that the index of every value matches the index of it's key. read() returns
+    /// an empty slice on a missing entry, and found() returns false.
+    ///
+    /// Like new(), this function is marked `unsafe` to prevent extensions from
+    /// constructing a `MultiReadBuf` on their own.
+    ///
+    /// # Arguments
+    ///
+    /// * `list`: The value of each key in the order the keys were looked up, or None
+    ///           if the key was not found.
+    ///
+    /// # Return
+    /// The `MultiReadBuf` wrapping the passed in values.
+    pub unsafe fn with_missing(list: Vec<Option<Bytes>>) -> MultiReadBuf {
+        let found = list.iter().map(|value| value.is_some()).collect();
+        MultiReadBuf {
+            inner: list
+                .into_iter()
+                .map(|value| value.unwrap_or_else(Bytes::new))
+                .collect(),
+            found: found,
+            index: Cell::new(0),
+            panic: Cell::new(false),
+        }
+    }
+
+    /// This method returns true if the key on the current index in `MultiReadBuf`
+    /// was found, false if it does not exist.
+    ///
+    /// # Return
+    /// True if there is a value on the current index.
+    pub fn found(&self) -> bool {
+        if self.panic.get() {
+            panic!("Out of bounds on MultiReadBuf.");
+        }
+
+        self.found[self.index.get()]
+    }
+
+    /// This method returns the number of keys in the `MultiReadBuf` that were not
+    /// found.
+    ///
+    /// # Return
+    /// The number of entries for which found() returns false.
+    pub fn missing(&self) -> usize {
+        self.found.iter().filter(|found| !**found).count()
+    }
+
     /// This method return the number of elements wrapped up inside a
     /// `MultiReadBuf` by the extension so far.
     ///
@@ -363,7 +481,7 @@ impl MultiReadBuf {
 // This module implements simple unit tests for MultiReadBuf, ReadBuf, ReadWriteSetBuf and WriteBuf.
 #[cfg(test)]
 mod tests {
-    use super::{MultiReadBuf, OpType, ReadBuf, ReadWriteSetBuf, Record, WriteBuf};
+    use super::{projection, MultiReadBuf, OpType, ReadBuf, ReadWriteSetBuf, Record, WriteBuf};
     use buf::bytes::{BufMut, Bytes, BytesMut};
 
     // This method tests the "len()" method on ReadBuf.
@@ -422,6 +540,41 @@ mod tests {
         }
     }
 
+    // This method tests that projections cover the whole value by default, and are clamped to
+    // the end of the value.
+    #[test]
+    fn test_projection() {
+        assert_eq!(0..10, projection(10, 0, 0));
+        assert_eq!(2..6, projection(10, 2, 4));
+        assert_eq!(4..10, projection(10, 4, 0));
+        assert_eq!(8..10, projection(10, 8, 4));
+        assert_eq!(10..10, projection(10, 12, 4));
+        assert_eq!(10..10, projection(10, 10, 0));
+        assert_eq!(0..0, projection(0, 0, 0));
+        assert_eq!(7..10, projection(10, 7, u32::max_value()));
+    }
+
+    // This method tests that "project()" narrows a ReadBuf down to the
+    // projected bytes.
+    #[test]
+    fn test_readbuf_project() {
+        let data = &[1, 2, 3, 4, 5, 6, 7, 18, 19];
+
+        unsafe {
+            let buf = ReadBuf::new(Bytes::from(&data[..]));
+            assert_eq!(data, buf.project(0, 0).read());
+
+            let buf = ReadBuf::new(Bytes::from(&data[..]));
+            assert_eq!(&data[3..5], buf.project(3, 2).read());
+
+            let buf = ReadBuf::new(Bytes::from(&data[..]));
+            assert_eq!(&data[6..], buf.project(6, 100).read());
+
+            let buf = ReadBuf::new(Bytes::from(&data[..]));
+            assert!(buf.project(20, 2).is_empty());
+        }
+    }
+
     // This method tests the functionality of the "len()" method on WriteBuf.
     #[test]
     fn test_writebuf_len() {
@@ -471,6 +624,41 @@ mod tests {
         }
     }
 
+    // This method tests that the "write_slices()" method on WriteBuf writes
+    // the same bytes as a single write_slice() of the concatenated segments,
+    // however they are split.
+    #[test]
+    fn test_writebuf_writeslices() {
+        let data: Vec<u8> = (0..10).collect();
+        let splits: Vec<Vec<&[u8]>> = vec![
+            vec![&data[..]],
+            vec![&data[..3], &data[3..7], &data[7..]],
+            vec![&data[..0], &data[..5], &data[5..5], &data[5..], &data[10..]],
+            vec![&data[..0], &data[..]],
+        ];
+
+        for segments in splits.iter() {
+            unsafe {
+                let mut buf = WriteBuf::new(1, BytesMut::with_capacity(10));
+                buf.write_slices(segments);
+                assert_eq!(&data[..], &buf.inner[..]);
+            }
+        }
+    }
+
+    // This method tests that the "write_slices()" method on WriteBuf panics
+    // in the case of a write overflow.
+    #[test]
+    #[should_panic]
+    fn test_writebuf_writeslices_overflow() {
+        // Create a WriteBuf, and write one byte more than it's capacity across
+        // two segments.
+        unsafe {
+            let mut buf = WriteBuf::new(1, BytesMut::with_capacity(100));
+            buf.write_slices(&[&[1; 60][..], &[2; 41][..]]);
+        }
+    }
+
     // This method tests that the "write_slice()" method on WriteBuf panics in
     // the case of a write overflow.
     #[test]
@@ -793,6 +981,34 @@ mod tests {
         }
     }
 
+    // This method checks that a missing key keeps it's entry in the list, and that
+    // found() tells it apart from a key with an empty value.
+    #[test]
+    fn test_multireadbuf_missing() {
+        let list = vec![Some(Bytes::from(vec![1; 100])), None, Some(Bytes::new())];
+        unsafe {
+            let multibuf = MultiReadBuf::with_missing(list);
+            assert_eq!((3, 1), (multibuf.num(), multibuf.missing()));
+            assert!(multibuf.found());
+            assert_eq!(100, multibuf.read().len());
+
+            assert!(multibuf.next());
+            assert!(!multibuf.found());
+            assert!(multibuf.read().is_empty());
+
+            assert!(multibuf.next());
+            assert!(multibuf.found());
+            assert!(multibuf.read().is_empty());
+            assert_eq!(false, multibuf.next());
+        }
+
+        unsafe {
+            let multibuf = MultiReadBuf::new(vec![Bytes::from(vec![1; 10])]);
+            assert_eq!(0, multibuf.missing());
+            assert!(multibuf.found());
+        }
+    }
+
     // This method checks the length of the readwrite set before and after
     // adding the record into it.
     #[test]
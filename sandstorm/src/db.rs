@@ -17,6 +17,42 @@ use super::buf::{MultiReadBuf, ReadBuf, WriteBuf};
 use std::sync::Arc;
 use util::model::Model;
 
+/// The reason a call to `DB::put_many` wrote nothing to the database. Each
+/// variant carries the index of the first buffer in the batch that failed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PutManyError {
+    /// The buffer could not be resolved into a key and a value.
+    InvalidBuffer(usize),
+
+    /// The buffer is for a table that does not exist, or that cannot be
+    /// written to (ex: a table mapped in from a read-only image).
+    TableNotWritable(usize),
+}
+
+impl PutManyError {
+    /// Returns the index of the buffer that failed.
+    pub fn index(&self) -> usize {
+        match *self {
+            PutManyError::InvalidBuffer(i) => i,
+            PutManyError::TableNotWritable(i) => i,
+        }
+    }
+}
+
+/// The objects read by a call to `DB::multi_get_versions`, along with the version each was
+/// read at.
+pub struct Versioned {
+    /// A handle to the values, in the order the keys were passed in.
+    pub values: MultiReadBuf,
+
+    /// The version of each object, in the same order.
+    pub versions: Vec<u64>,
+
+    /// True if any of the keys was written to while they were being read, in which case the
+    /// objects might not have all been current at the same instant. The read can be retried.
+    pub racy: bool,
+}
+
 /// Definition of the DB trait that will allow extensions to access
 /// the database.
 pub trait DB {
@@ -36,9 +72,70 @@ pub trait DB {
     /// exists inside the database.
     fn get(&self, table: u64, key: &[u8]) -> Option<ReadBuf>;
 
+    /// This method performs a lookup on a key-value pair that can be served off a copy of it
+    /// that lags behind the primary copy by atmost `staleness_us` microseconds, such as an
+    /// entry in a cache or a replica. get() is this with a budget of 0, ie. strictly fresh,
+    /// which is what the default implementation serves.
+    ///
+    /// # Arguments
+    ///
+    /// * `table`:        An identifier of the data table the key-value pair
+    ///                   belongs to.
+    /// * `key`:          A slice of bytes over the key to be looked up.
+    /// * `staleness_us`: The staleness budget on the lookup, in microseconds.
+    ///
+    /// # Return
+    ///
+    /// A handle that can be used to read the value if the key-value pair
+    /// exists inside the database.
+    fn get_with_staleness(&self, table: u64, key: &[u8], _staleness_us: u32) -> Option<ReadBuf> {
+        self.get(table, key)
+    }
+
+    /// This method performs a lookup on a key-value pair like get(), but only returns the
+    /// `length` bytes of the value starting at `offset`, for when just a field out of a larger
+    /// value is needed. A length of 0 runs upto the end of the value, and a projection that runs
+    /// past the end is clamped to it; refer to buf::projection(). An offset past the end of the
+    /// value returns None. The default implementation narrows down the value returned by get()
+    /// without copying it.
+    ///
+    /// # Arguments
+    ///
+    /// * `table`:  An identifier of the data table the key-value pair belongs to.
+    /// * `key`:    A slice of bytes over the key to be looked up.
+    /// * `offset`: The offset into the value the returned bytes start at.
+    /// * `length`: The number of bytes of the value to return. 0 runs upto the end.
+    ///
+    /// # Return
+    ///
+    /// A handle that can be used to read the projected bytes of the value if the key-value
+    /// pair exists inside the database, and `offset` is within the value.
+    fn get_slice(&self, table: u64, key: &[u8], offset: u32, length: u32) -> Option<ReadBuf> {
+        self.get(table, key)
+            .filter(|value| offset as usize <= value.len())
+            .map(|value| value.project(offset, length))
+    }
+
+    /// This method returns the length of the value stored under a key, for when an extension
+    /// needs to size a read (ex: through get_slice()) before making it. The default
+    /// implementation reads the value through get().
+    ///
+    /// # Arguments
+    ///
+    /// * `table`: An identifier of the data table the key-value pair belongs to.
+    /// * `key`:   A slice of bytes over the key to be looked up.
+    ///
+    /// # Return
+    ///
+    /// The length of the value in bytes if the key-value pair exists inside the database.
+    fn value_len(&self, table: u64, key: &[u8]) -> Option<usize> {
+        self.get(table, key).map(|value| value.len())
+    }
+
     /// This method performs a lookup for a set of keys stored inside the database as
     /// key-value pairs, and returns a hanle that can be used to read the value for each key
-    /// if the key-value pair exists.
+    /// if the key-value pair exists. Keys that do not exist are reported on their own entry
+    /// through MultiReadBuf::found(), instead of failing the whole lookup.
     ///
     /// # Arguments
     /// * `table`: An identifier of the data table the key-value pair
@@ -48,10 +145,28 @@ pub trait DB {
     ///
     /// # Return
     ///
-    /// A handle that can be used to read the value for each key in the list, if the key-value
-    /// pair exists inside the database.
+    /// A handle that can be used to read the value for each key in the list, in the order the
+    /// keys were given. None if the table does not exist.
     fn multiget(&self, table: u64, key_len: u16, keys: &[u8]) -> Option<MultiReadBuf>;
 
+    /// This method performs a multiget() that also returns the version of every object, and
+    /// checks whether any of them changed while they were being read. A read that is not racy
+    /// returns objects that were all current at the same instant.
+    ///
+    /// # Arguments
+    /// * `table`: An identifier of the data table the key-value pairs
+    ///            belong to.
+    /// * `key_len`: Length of each key in the key list.
+    /// * `keys`: A slice which contains multiple keys.
+    ///
+    /// # Return
+    ///
+    /// The objects and their versions, if every key-value pair exists inside the database.
+    /// None if any does not, or on implementations that do not version objects.
+    fn multi_get_versions(&self, _table: u64, _key_len: u16, _keys: &[u8]) -> Option<Versioned> {
+        None
+    }
+
     /// This method will allocate space for a key-value pair inside the
     /// database, and if the allocation was successfull, return a handle that
     /// can be used to write a value into the allocation, and that can be
@@ -72,7 +187,8 @@ pub trait DB {
     /// into the allocated space. This handle will already hold the key, and
     /// contain enough space to hold val_len bytes. The handle is not part of
     /// the database yet. To add it to the database, use the `put` method on
-    /// the DB trait.
+    /// the DB trait. None if the key or value is longer than the database
+    /// allows.
     fn alloc(&self, table: u64, key: &[u8], val_len: u64) -> Option<WriteBuf>;
 
     /// This method will add a previously allocated region of memory to the
@@ -88,6 +204,55 @@ pub trait DB {
     /// False otherwise.
     fn put(&self, buf: WriteBuf) -> bool;
 
+    /// This method will add a batch of previously allocated regions of
+    /// memory to the database. Every buffer is checked before any of them is
+    /// added, so if one fails, nothing is written. The writes to a single
+    /// table are atomic: a concurrent reader sees either none or all of them.
+    /// Writes to different tables are NOT atomic with respect to each other;
+    /// a reader can see the writes to one table before those to another.
+    ///
+    /// # Arguments
+    ///
+    /// * `bufs`: Previously allocated handles to be added to the database.
+    ///           If two are for the same key, the later one wins.
+    ///
+    /// # Return
+    ///
+    /// Ok if every handle was added to the database. Otherwise, the reason
+    /// and index of the first handle that could not be.
+    fn put_many(&self, bufs: Vec<WriteBuf>) -> Result<(), PutManyError>;
+
+    /// This method will add a previously allocated region of memory to the
+    /// database, but only if the value currently under it's key is the one
+    /// expected. No other write to the key interleaves between the comparison
+    /// and the write, so extensions that read, modify and write an object
+    /// (ex: a counter) can retry on a conflict instead of losing an update.
+    ///
+    /// # Arguments
+    ///
+    /// * `table_id`: An identifier of the data table the key-value pair
+    ///               belongs to. Must be the table `buf` was allocated for.
+    /// * `key`:      A slice of bytes over the key of the key-value pair.
+    ///               Must be the key `buf` was allocated for.
+    /// * `expected`: The value expected under the key, or None if the key is
+    ///               expected to not exist.
+    /// * `buf`:      A previously allocated handle to be added to the
+    ///               database.
+    ///
+    /// # Return
+    ///
+    /// True if the handle was added to the database, and false if it was not
+    /// because the value under the key was not the one expected. An error if
+    /// the table cannot be written to, or `buf` was not allocated for
+    /// `table_id` and `key`.
+    fn cas(
+        &self,
+        table_id: u64,
+        key: &[u8],
+        expected: Option<&[u8]>,
+        buf: WriteBuf,
+    ) -> Result<bool, ()>;
+
     /// This method will delete a key-value pair from the database if it exists.
     ///
     /// # Arguments
@@ -95,7 +260,12 @@ pub trait DB {
     /// * `table`: An identifier of the data table the key-value pair
     ///            belongs to.
     /// * `key`:   A slice of bytes over the key of the object to be deleted.
-    fn del(&self, table: u64, key: &[u8]);
+    ///
+    /// # Return
+    ///
+    /// True if the key-value pair was deleted. False if the table or the key
+    /// does not exist.
+    fn del(&self, table: u64, key: &[u8]) -> bool;
 
     /// This method will return a serialized version of the arguments that were
     /// passed in by the tenant invoking the extension.
@@ -106,6 +276,20 @@ pub trait DB {
     /// version, de-serialization is left to the tenant for now.
     fn args(&self) -> &[u8];
 
+    /// This method reads the next field off the arguments passed in by the tenant invoking the
+    /// extension, when they were built with invoke::InvokeArgs. The first call returns the
+    /// first field, and every call after returns the one after it. Refer to sandstorm::invoke.
+    /// The default implementation is for databases whose extensions aren't passed fields, and
+    /// never returns one.
+    ///
+    /// # Return
+    ///
+    /// A slice over the field, which can be empty. None once every field has been read, or if
+    /// the next field is malformed.
+    fn next_arg(&self) -> Option<&[u8]> {
+        None
+    }
+
     /// This method will write a response for the tenant that invoked the
     /// extension.
     ///
@@ -115,6 +299,18 @@ pub trait DB {
     ///               extension should perform said serialization for now.
     fn resp(&self, response: &[u8]);
 
+    /// This method returns the number of bytes that can still be written to the response
+    /// through resp() before it stops fitting. Extensions whose result can run past a single
+    /// response use this to size each page of it. The default implementation is for databases
+    /// that do not bound the response (ex: pushed back extensions running at the client).
+    ///
+    /// # Return
+    ///
+    /// The room left on the response, in bytes.
+    fn resp_capacity(&self) -> usize {
+        usize::max_value()
+    }
+
     /// This method is meant for testing, and will not do anything in the real
     /// system.
     fn debug_log(&self, msg: &str);
@@ -165,4 +361,109 @@ pub trait DB {
     ///
     /// The model if exists; None otherwise.
     fn get_model(&self) -> Option<Arc<Model>>;
+
+    /// This method checkpoints the state of a long running extension. If the extension was
+    /// invoked as durable, the checkpoint survives a server restart, after which the extension
+    /// is invoked again, and can resume from this checkpoint using `restore`. Outside of durable
+    /// invocations this method does nothing.
+    ///
+    /// # Arguments
+    ///
+    /// * `progress`: An opaque progress marker (ex: a cursor into a table).
+    /// * `state`:    The state needed to resume from this point. Size capped by the server.
+    ///
+    /// # Return
+    ///
+    /// True if the checkpoint was persisted. False otherwise.
+    fn checkpoint(&self, _progress: u64, _state: &[u8]) -> bool {
+        false
+    }
+
+    /// This method returns the checkpoint a durable extension is being resumed from.
+    ///
+    /// # Return
+    ///
+    /// The progress marker and state last passed into `checkpoint`, if this invocation is
+    /// resuming a durable extension after a restart. None otherwise.
+    fn restore(&self) -> Option<(u64, Vec<u8>)> {
+        None
+    }
+
+    /// This method scans one slice of a table, calling `visit` on the key and value of every
+    /// record in the slice. Tables are scanned a slice at a time so that an extension can yield
+    /// between slices. Every record present for the entire duration of a scan is visited exactly
+    /// once. Records inserted or deleted while a scan is in progress are visited at most once,
+    /// and a record's value is read when it's slice is scanned, so puts made after that point
+    /// are not seen.
+    ///
+    /// # Arguments
+    ///
+    /// * `table`:  An identifier of the data table to be scanned.
+    /// * `cursor`: The cursor returned by the previous call. Zero starts a new scan.
+    /// * `visit`:  Called on the key and value of each record in the slice.
+    ///
+    /// # Return
+    ///
+    /// The cursor to pass in to scan the next slice, or None once the whole table has been
+    /// scanned. Scans over a table that does not exist return None without visiting anything,
+    /// as do scans on implementations that cannot scan (ex: pushed back extensions).
+    fn scan(&self, _table: u64, _cursor: u64, _visit: &mut FnMut(&[u8], &[u8])) -> Option<u64> {
+        None
+    }
+
+    /// This method merges a delta into the value stored under a key, using the merge extension
+    /// registered for the table (see sandstorm::merge). An absent key is merged into as if it's
+    /// value were empty. No other write to the key interleaves with the merge.
+    ///
+    /// # Arguments
+    ///
+    /// * `table`: An identifier of the data table the key-value pair belongs to.
+    /// * `key`:   A slice of bytes over the key to be merged into.
+    /// * `delta`: The delta passed to the merge extension.
+    ///
+    /// # Return
+    ///
+    /// True if the merged value was written. False if the table does not exist or has no merge
+    /// extension, if the merge extension failed, or on implementations that cannot merge.
+    fn merge(&self, _table: u64, _key: &[u8], _delta: &[u8]) -> bool {
+        false
+    }
+
+    /// This method sends out everything written to the response so far through `resp` as a
+    /// partial result, and starts a fresh response for whatever the extension writes next. The
+    /// tenant receives the partial results in order, followed by the response left when the
+    /// extension completes. An extension whose result does not fit in a single response can
+    /// use this to stream it back a piece at a time.
+    ///
+    /// An extension that has flushed it's response cannot be pushed back to the tenant.
+    ///
+    /// # Return
+    ///
+    /// True if the response was sent out. False if the extension went over the cap the server
+    /// places on the bytes a stream can carry, or on implementations that cannot stream (ex:
+    /// pushed back extensions, or durable invocations). Nothing is sent out in that case, and
+    /// the response is left as it was.
+    fn resp_flush(&self) -> bool {
+        false
+    }
+
+    /// This method defers work until after the response to this invocation has been sent out.
+    /// Once the invocation completes successfully, the server invokes the same extension again
+    /// in the background on behalf of the same tenant, with `args` behind sandstorm::defer's
+    /// marker. Nothing is sent out for the deferred invocation, so anything it computes has to
+    /// be written to a table. Deferred invocations cannot defer work of their own.
+    ///
+    /// # Arguments
+    ///
+    /// * `args`: The arguments to the deferred invocation. Atmost MAX_DEFER_ARGS bytes long.
+    ///
+    /// # Return
+    ///
+    /// True if the work was recorded. False if the invocation has already deferred as much
+    /// work as it can, if it is itself deferred, or on implementations that cannot defer (ex:
+    /// pushed back extensions). Recorded work is still dropped if the invocation fails, or if
+    /// the tenant already has too much deferred work queued up at the server.
+    fn defer(&self, _args: &[u8]) -> bool {
+        false
+    }
 }
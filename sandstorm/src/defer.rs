@@ -0,0 +1,68 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! The arguments the database passes to an extension when it runs work deferred through
+//! DB::defer(). The extension is invoked again once it's response has been sent out, with the
+//! arguments it passed to defer() behind a marker, so that it can tell a deferred invocation
+//! apart from one sent by a client.
+
+/// The marker in front of the arguments to a deferred invocation. The first byte is not a
+/// valid TAO opcode, and is unlikely to start the arguments sent by a client.
+pub const DEFER_MARKER: [u8; 4] = [0xff, b'D', b'F', b'R'];
+
+/// The most bytes an extension can pass to a single call to defer().
+pub const MAX_DEFER_ARGS: usize = 256;
+
+/// Encodes the arguments a deferred invocation receives.
+///
+/// # Arguments
+///
+/// * `args`: The arguments passed to defer().
+pub fn encode_args(args: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(DEFER_MARKER.len() + args.len());
+    buf.extend_from_slice(&DEFER_MARKER);
+    buf.extend_from_slice(args);
+    buf
+}
+
+/// Decodes the arguments to a deferred invocation.
+///
+/// # Return
+///
+/// The arguments that were passed to defer(), or None if the invocation was not deferred.
+pub fn decode_args(buf: &[u8]) -> Option<&[u8]> {
+    if buf.len() < DEFER_MARKER.len() || buf[..DEFER_MARKER.len()] != DEFER_MARKER {
+        return None;
+    }
+
+    Some(&buf[DEFER_MARKER.len()..])
+}
+
+// This module contains unit tests for the deferred argument encoding.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Tests that arguments round trip, and that a client's arguments aren't taken as deferred.
+    #[test]
+    fn test_defer_args() {
+        assert_eq!(Some(&b"args"[..]), decode_args(&encode_args(b"args")));
+        assert_eq!(Some(&b""[..]), decode_args(&encode_args(b"")));
+
+        assert_eq!(None, decode_args(b"args"));
+        assert_eq!(None, decode_args(&DEFER_MARKER[..3]));
+        assert_eq!(None, decode_args(&[0xff, 0, 0, 0, 1]));
+    }
+}
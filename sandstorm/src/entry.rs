@@ -0,0 +1,392 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Generates the entry point of an extension off a handler of a clean signature, so that
+//! extensions don't have to copy the boilerplate around it. An extension that runs to completion
+//! without yielding is written as
+//!
+//! ```ignore
+//! #![feature(generators, generator_trait)]
+//!
+//! #[macro_use]
+//! extern crate sandstorm;
+//!
+//! use sandstorm::db::DB;
+//! use sandstorm::entry::{Args, ExtError, Response};
+//! use sandstorm::rc::Rc;
+//!
+//! declare_extension!("get", get);
+//!
+//! fn get(db: &Rc<DB>, mut args: Args) -> Result<Response, ExtError> {
+//!     let table = args.table()?;
+//!     let obj = db.get(table, args.rest()).ok_or(ExtError::ObjectDoesNotExist)?;
+//!     db.resp(obj.read());
+//!     Ok(Response::Empty)
+//! }
+//! ```
+//!
+//! The handler gets the database as an Rc so that it can hand it to anything that outlives the
+//! call. Errors are written out as the response, and panics are caught before they unwind into
+//! the database, and written out as ExtError::Panicked.
+//!
+//! An extension can also declare the layout of it's arguments, so that the database refuses
+//! invocations that don't fit it before they reach the handler. Refer to sandstorm::schema.
+//!
+//! ```ignore
+//! declare_extension!("get", get, [Field::Table, Field::Blob(1, 0xffff)]);
+//! ```
+
+use std::panic::{self, AssertUnwindSafe};
+use std::rc::Rc;
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use super::db::DB;
+
+/// Generates the `init` symbol the database loads an extension through, off a handler.
+///
+/// # Arguments
+///
+/// * `name`:    The name of the extension, as a string literal. Used in error responses.
+/// * `handler`: A `fn(&Rc<DB>, Args) -> Result<Response, ExtError>`. Refer to run().
+/// * `schema`:  Optional. The fields of the arguments, as an array of sandstorm::schema::Field.
+///              Generates an `args_schema` symbol that the database reads it through.
+#[macro_export]
+macro_rules! declare_extension {
+    ($name:expr, $handler:path, $schema:expr) => {
+        declare_extension!($name, $handler);
+
+        /// The layout of this extension's arguments, generated by declare_extension!.
+        #[no_mangle]
+        pub fn args_schema() -> $crate::vec::Vec<u8> {
+            $crate::schema::encode(&$schema)
+        }
+    };
+
+    ($name:expr, $handler:path) => {
+        /// The entry point of this extension, generated by declare_extension!.
+        #[no_mangle]
+        #[allow(unreachable_code)]
+        pub fn init(
+            db: $crate::rc::Rc<$crate::db::DB>,
+        ) -> $crate::boxed::Box<$crate::Generator<Yield = u64, Return = u64>> {
+            $crate::boxed::Box::new(move || {
+                {
+                    return $crate::entry::run($name, &db, $handler);
+                }
+
+                // Required to get the compiler to compile this closure into a generator. It is
+                // unreachable and benign.
+                yield 0;
+            })
+        }
+    };
+}
+
+/// The response of a handler that succeeded.
+#[derive(Debug, PartialEq)]
+pub enum Response {
+    /// Nothing more is written out. Anything the handler wrote through DB::resp() is the
+    /// response; handlers responding with a value they looked up should write it out that way
+    /// to avoid copying it.
+    Empty,
+
+    /// These bytes are written out.
+    Static(&'static [u8]),
+
+    /// These bytes are written out.
+    Bytes(Vec<u8>),
+}
+
+/// The errors a handler can fail with. Each is written out as the response in place of a
+/// regular one.
+#[derive(Debug, PartialEq)]
+pub enum ExtError {
+    /// The arguments were too short, or malformed.
+    InvalidArgs,
+
+    /// The object looked up does not exist.
+    ObjectDoesNotExist,
+
+    /// The database could not allocate an object to be written.
+    AllocationFailed,
+
+    /// The database did not accept an object that was written.
+    PutFailed,
+
+    /// An error frame specific to the extension (ex: sandstorm::tao::encode_error()), which is
+    /// written out as is.
+    Frame(Vec<u8>),
+
+    /// The handler of the named extension panicked. Generated by run(); handlers don't return
+    /// this themselves.
+    Panicked(&'static str),
+}
+
+impl ExtError {
+    /// Returns the bytes the error is written out as.
+    pub fn frame(&self) -> Vec<u8> {
+        match *self {
+            ExtError::InvalidArgs => b"Invalid args".to_vec(),
+            ExtError::ObjectDoesNotExist => b"Object does not exist".to_vec(),
+            ExtError::AllocationFailed => b"Allocation failed".to_vec(),
+            ExtError::PutFailed => b"put() failed".to_vec(),
+            ExtError::Frame(ref frame) => frame.clone(),
+            ExtError::Panicked(name) => format!("Extension {} panicked", name).into_bytes(),
+        }
+    }
+}
+
+/// Reads the arguments to an extension. The standard layout starts with the 8 byte identifier
+/// of the table to operate on, followed by whatever the extension takes. Integers are
+/// little-endian. Every read fails with ExtError::InvalidArgs if the arguments are too short,
+/// so that handlers can parse them with `?`.
+pub struct Args<'a> {
+    // The arguments that haven't been read yet.
+    buf: &'a [u8],
+}
+
+impl<'a> Args<'a> {
+    /// Returns a reader over the arguments.
+    pub fn new(buf: &'a [u8]) -> Args<'a> {
+        Args { buf: buf }
+    }
+
+    /// Reads the table identifier at the start of the standard layout.
+    pub fn table(&mut self) -> Result<u64, ExtError> {
+        self.u64()
+    }
+
+    /// Reads a byte.
+    pub fn u8(&mut self) -> Result<u8, ExtError> {
+        self.bytes(1).map(|b| b[0])
+    }
+
+    /// Reads a 2 byte integer.
+    pub fn u16(&mut self) -> Result<u16, ExtError> {
+        self.bytes(2).map(LittleEndian::read_u16)
+    }
+
+    /// Reads a 4 byte integer.
+    pub fn u32(&mut self) -> Result<u32, ExtError> {
+        self.bytes(4).map(LittleEndian::read_u32)
+    }
+
+    /// Reads an 8 byte integer.
+    pub fn u64(&mut self) -> Result<u64, ExtError> {
+        self.bytes(8).map(LittleEndian::read_u64)
+    }
+
+    /// Reads `len` bytes.
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], ExtError> {
+        if self.buf.len() < len {
+            return Err(ExtError::InvalidArgs);
+        }
+
+        let (head, rest) = self.buf.split_at(len);
+        self.buf = rest;
+        Ok(head)
+    }
+
+    /// Reads every byte that is left, which can be none.
+    pub fn rest(&mut self) -> &'a [u8] {
+        let rest = self.buf;
+        self.buf = &[];
+        rest
+    }
+}
+
+/// Runs a handler on the arguments the database passed an extension, and writes out it's
+/// response or error. Called by the entry point declare_extension! generates.
+///
+/// The handler runs under catch_unwind(), so that a panic is written out as
+/// ExtError::Panicked instead of unwinding into the database. Anything the handler wrote out
+/// before it panicked stays in the response, ahead of the error.
+///
+/// # Arguments
+///
+/// * `name`:    The name of the extension.
+/// * `db`:      The database the extension was invoked on.
+/// * `handler`: The handler.
+///
+/// # Return
+///
+/// 0 if the handler succeeded, and 1 if it failed or panicked.
+pub fn run<F>(name: &'static str, db: &Rc<DB>, handler: F) -> u64
+where
+    F: FnOnce(&Rc<DB>, Args) -> Result<Response, ExtError>,
+{
+    let res = panic::catch_unwind(AssertUnwindSafe(|| handler(db, Args::new(db.args()))))
+        .unwrap_or_else(|_| Err(ExtError::Panicked(name)));
+
+    match res {
+        Ok(Response::Empty) => 0,
+
+        Ok(Response::Static(resp)) => {
+            db.resp(resp);
+            0
+        }
+
+        Ok(Response::Bytes(resp)) => {
+            db.resp(&resp);
+            0
+        }
+
+        Err(err) => {
+            db.resp(&err.frame());
+            1
+        }
+    }
+}
+
+// This module contains unit tests for the entry points generated by declare_extension!.
+#[cfg(test)]
+mod tests {
+    use super::super::mock::MockDB;
+    use super::super::schema::{Field, Schema};
+    use super::*;
+
+    use std::ops::GeneratorState;
+
+    // A toy get() handler, that writes part of a response and panics if the key is "panic".
+    fn toy(db: &Rc<DB>, mut args: Args) -> Result<Response, ExtError> {
+        let table = args.table()?;
+        let key = args.rest();
+        if key == b"panic" {
+            db.resp(b"partial");
+            panic!("Panicked on table {}", table);
+        }
+
+        let obj = db.get(table, key).ok_or(ExtError::ObjectDoesNotExist)?;
+        db.resp(obj.read());
+        Ok(Response::Empty)
+    }
+
+    // A handler that fails with the error picked by the byte after the table id.
+    fn fail(_db: &Rc<DB>, mut args: Args) -> Result<Response, ExtError> {
+        args.table()?;
+        match args.u8()? {
+            0 => Err(ExtError::ObjectDoesNotExist),
+            1 => Err(ExtError::AllocationFailed),
+            2 => Err(ExtError::PutFailed),
+            3 => Err(ExtError::Frame(args.rest().to_vec())),
+            4 => Ok(Response::Static(b"ok")),
+            _ => Ok(Response::Bytes(args.rest().to_vec())),
+        }
+    }
+
+    // The symbols an extension declared off the toy handler would export, taking a table and a
+    // key of at most 8 bytes. Only one extension can be declared per crate, since each exports
+    // the same symbols.
+    mod ext {
+        use super::super::super::schema::Field;
+        use super::toy;
+        declare_extension!("toy", toy, [Field::Table, Field::Blob(0, 8)]);
+    }
+
+    fn args(table: u64, rest: &[u8]) -> Vec<u8> {
+        let mut args = vec![0; 8];
+        LittleEndian::write_u64(&mut args, table);
+        args.extend_from_slice(rest);
+        args
+    }
+
+    // Runs the generated entry point to completion on a set of arguments, and returns what it
+    // returned and responded.
+    fn invoke(args: &[u8]) -> (u64, Vec<u8>) {
+        let db = Rc::new(MockDB::with_args(args));
+        db.insert(7, b"key", b"value");
+
+        let mut gen = ext::init(Rc::clone(&db) as Rc<DB>);
+        match unsafe { gen.resume() } {
+            GeneratorState::Complete(ret) => (ret, db.response()),
+            GeneratorState::Yielded(_) => panic!("Handler yielded"),
+        }
+    }
+
+    // Tests that the generated entry point runs the handler on the arguments, and responds with
+    // what it wrote out, or with the error it failed with.
+    #[test]
+    fn test_entry_toy() {
+        assert_eq!((0, b"value".to_vec()), invoke(&args(7, b"key")));
+        assert_eq!(
+            (1, b"Object does not exist".to_vec()),
+            invoke(&args(7, b"nokey"))
+        );
+        assert_eq!((1, b"Invalid args".to_vec()), invoke(&[7; 5]));
+    }
+
+    // Tests that a panic in the handler is caught at the entry point, and responded with after
+    // whatever the handler wrote out before it.
+    #[test]
+    fn test_entry_panic() {
+        assert_eq!(
+            (1, b"partialExtension toy panicked".to_vec()),
+            invoke(&args(7, b"panic"))
+        );
+
+        // The panic is contained; the next invocation runs as usual.
+        assert_eq!((0, b"value".to_vec()), invoke(&args(7, b"key")));
+    }
+
+    // Tests that every error, and every kind of response, is written out.
+    #[test]
+    fn test_entry_errors() {
+        let cases: Vec<(Vec<u8>, u64, &[u8])> = vec![
+            (args(1, &[]), 1, b"Invalid args"),
+            (args(1, &[0]), 1, b"Object does not exist"),
+            (args(1, &[1]), 1, b"Allocation failed"),
+            (args(1, &[2]), 1, b"put() failed"),
+            (args(1, &[3, 0xde, 0xad]), 1, &[0xde, 0xad]),
+            (args(1, &[4]), 0, b"ok"),
+            (args(1, &[5, 0xbe, 0xef]), 0, &[0xbe, 0xef]),
+        ];
+
+        for (args, ret, resp) in cases.into_iter() {
+            let db = Rc::new(MockDB::with_args(&args));
+            assert_eq!(ret, run("fail", &(Rc::clone(&db) as Rc<DB>), fail));
+            assert_eq!(resp, &db.response()[..]);
+        }
+    }
+
+    // Tests that an extension declared with a layout exports it. The entry point is the same
+    // as without one; the layout is only enforced by the database.
+    #[test]
+    fn test_entry_schema() {
+        let schema = Schema::parse(&ext::args_schema()).unwrap();
+        assert_eq!(&[Field::Table, Field::Blob(0, 8)], schema.fields());
+        assert_eq!(Ok(()), schema.check(&args(7, b"key")));
+        assert!(schema.check(&args(7, b"too long a key")).is_err());
+        assert_eq!(
+            (1, b"Object does not exist".to_vec()),
+            invoke(&args(7, b"too long a key"))
+        );
+    }
+
+    // Tests that arguments are read in order, and that short ones are refused.
+    #[test]
+    fn test_entry_args() {
+        let buf = args(9, &[1, 2, 0, 3, 0, 0, 0, b'k']);
+        let mut args = Args::new(&buf);
+        assert_eq!(Ok(9), args.table());
+        assert_eq!(Ok(1), args.u8());
+        assert_eq!(Ok(2), args.u16());
+        assert_eq!(Ok(3), args.u32());
+        assert_eq!(Err(ExtError::InvalidArgs), args.bytes(2));
+        assert_eq!(Ok(&b"k"[..]), args.bytes(1));
+        assert_eq!(Err(ExtError::InvalidArgs), args.u8());
+        assert!(args.rest().is_empty());
+    }
+}
@@ -15,11 +15,16 @@
 
 use hashbrown::HashMap;
 use std::ops::Generator;
+use std::path::Path;
 use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use super::common::TenantId;
 use super::db::DB;
+use super::profile::{self, PerfMap, Profile};
+use super::schema::Schema;
 
 use libloading::os::unix::Symbol;
 use libloading::Library;
@@ -28,9 +33,16 @@ use spin::RwLock;
 // Number of buckets in the `extensions` hashmap in Extension Manager.
 const EXT_BUCKETS: usize = 32;
 
+// A new invocation is weighed 1 / 2^COST_EWMA_SHIFT in an extension's moving average cost.
+const COST_EWMA_SHIFT: u64 = 3;
+
 // The type signature of the function that will be searched for inside an so.
 type Proc = unsafe extern "C" fn(Rc<DB>) -> Box<Generator<Yield = u64, Return = u64>>;
 
+// The type signature of the optional function an extension declares the layout of it's
+// arguments through. Refer to sandstorm::schema.
+type SchemaProc = fn() -> Vec<u8>;
+
 /// This type represents an extension that has been successfully loaded into
 /// the database. As long as this type is not dropped, the extension will exist
 /// inside the database's address space, and can be called into.
@@ -45,6 +57,86 @@ pub struct Extension {
     // The actual symbol inside the dynamically loaded library that will be
     // used by the database during an "invoke".
     procedure: Symbol<Proc>,
+
+    // The layout of the extension's arguments, if it declared one.
+    schema: Option<Schema>,
+
+    // The time at which the extension was loaded, in seconds since the UNIX epoch.
+    loaded: u64,
+
+    // The number of times the extension has been invoked. Shared by every
+    // tenant the extension is shared with.
+    invocations: AtomicUsize,
+
+    // Moving average of the cycles an invocation of the extension spends outside the database,
+    // over invocations that ran to completion. 0 until one does.
+    cost: AtomicUsize,
+
+    // Set once an invocation of the extension panics, if the server isolates panics instead of
+    // aborting. Disabled extensions are not invoked again.
+    disabled: AtomicBool,
+
+    // The extension's symbols and the cycles spent in each, if it is being
+    // profiled. The symbols are removed from the perf map on drop.
+    profile: Option<Profile>,
+
+    // The number of tenants the extension is bound to in an extension
+    // manager. Maintained by Binding.
+    bindings: AtomicUsize,
+}
+
+/// How a tenant came to be able to invoke an extension. Returned as part of
+/// `ExtensionInfo`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Provenance {
+    /// The tenant loaded the extension itself.
+    Private,
+
+    /// The extension was shared with the tenant. Carries the tenant that
+    /// loaded it.
+    Shared(TenantId),
+}
+
+/// The reason `ExtensionManager::try_share()` did not share an extension.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ShareError {
+    /// The owner has no extension under the name.
+    NotFound,
+
+    /// The tenant has loaded an extension of it's own under the name, and the
+    /// share was not forced.
+    Private,
+}
+
+/// Metadata describing an extension a tenant can invoke. Returned by
+/// `ExtensionManager::list()`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExtensionInfo {
+    /// The name the extension is invoked by.
+    pub name: Vec<u8>,
+
+    /// The version of the extension. Extensions are not versioned yet, so
+    /// this is always 0.
+    pub version: u32,
+
+    /// The time at which the extension was loaded, in seconds since the UNIX
+    /// epoch.
+    pub loaded: u64,
+
+    /// The number of times the extension has been invoked, across all the
+    /// tenants it is shared with.
+    pub invocations: u64,
+
+    /// Whether the tenant loaded the extension, or had it shared with it.
+    pub provenance: Provenance,
+
+    /// The layout of the extension's arguments, if it declared one.
+    pub schema: Option<Schema>,
+
+    /// The id the tenant's binding to the extension is interned under. An invoke() can name
+    /// the extension by this id instead of it's name, until the name is bound again or the
+    /// binding is removed. Refer to ExtensionManager::get_by_id().
+    pub id: u32,
 }
 
 // Implementation of methods on Extension.
@@ -68,7 +160,9 @@ impl Extension {
     /// # Return
     ///
     /// An `Extension` if the .so file was found, and contains a symbol called
-    /// "init". This handle can then be used to call into the so.
+    /// "init". This handle can then be used to call into the so. If the .so
+    /// file also contains a symbol called "args_schema", it must return a well
+    /// formed layout; the extension is not loaded otherwise.
     pub fn load(name: &str) -> Option<Extension> {
         // First, try to dynamically load the .so file into the database.
         if let Ok(lib) = Library::new(name) {
@@ -82,11 +176,36 @@ impl Extension {
                 }
             }
 
+            // If the extension declared the layout of it's arguments, read it in.
+            // A layout that doesn't parse would let through arguments the
+            // extension doesn't expect, so it fails the load.
+            let mut schema = None;
+            unsafe {
+                if let Ok(declare) = lib.get::<SchemaProc>(b"args_schema") {
+                    match Schema::parse(&declare()) {
+                        Ok(parsed) => schema = Some(parsed),
+                        Err(_) => return None,
+                    }
+                }
+            }
+
             // If the init function was unwrapped, return an extension.
             if let Some(procedure) = procedure {
+                let loaded = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or(0);
+
                 return Some(Extension {
                     library: lib,
                     procedure: procedure,
+                    schema: schema,
+                    loaded: loaded,
+                    invocations: AtomicUsize::new(0),
+                    cost: AtomicUsize::new(0),
+                    disabled: AtomicBool::new(false),
+                    profile: None,
+                    bindings: AtomicUsize::new(0),
                 });
             }
         }
@@ -107,16 +226,176 @@ impl Extension {
     ///
     /// A generator that can be scheduled by the database.
     pub fn get(&self, db: Rc<DB>) -> Box<Generator<Yield = u64, Return = u64>> {
+        self.invocations.fetch_add(1, Ordering::Relaxed);
+
         // Call into the procedure, and return the generator.
         unsafe { (self.procedure)(db) }
     }
+
+    /// Returns the time at which the extension was loaded, in seconds since
+    /// the UNIX epoch.
+    pub fn loaded(&self) -> u64 {
+        self.loaded
+    }
+
+    /// Returns the number of times a generator was retrieved from the
+    /// extension using get().
+    pub fn invocations(&self) -> u64 {
+        self.invocations.load(Ordering::Relaxed) as u64
+    }
+
+    /// Folds the cycles an invocation spent outside the database into the extension's moving
+    /// average cost. Invocations on different cores race on the average, which is fine for an
+    /// estimate.
+    ///
+    /// # Arguments
+    ///
+    /// * `cycles`: The cycles the invocation spent outside the database, from start to completion.
+    pub fn record_cost(&self, cycles: u64) {
+        let avg = self.cost.load(Ordering::Relaxed) as u64;
+        let avg = match avg {
+            0 => cycles,
+            avg => avg - (avg >> COST_EWMA_SHIFT) + (cycles >> COST_EWMA_SHIFT),
+        };
+        self.cost.store(avg as usize, Ordering::Relaxed);
+    }
+
+    /// Returns the moving average of the cycles an invocation of the extension spends outside the
+    /// database. 0 if no invocation has completed yet.
+    pub fn cost(&self) -> u64 {
+        self.cost.load(Ordering::Relaxed) as u64
+    }
+
+    /// Disables the extension. Invocations already running are left to complete.
+    pub fn disable(&self) {
+        self.disabled.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns true if the extension was disabled, and must not be invoked again.
+    pub fn disabled(&self) -> bool {
+        self.disabled.load(Ordering::Relaxed)
+    }
+
+    /// Returns the layout of the extension's arguments, if it declared one. Invocations with
+    /// arguments that don't fit it must be refused before the extension is called into.
+    pub fn schema(&self) -> Option<&Schema> {
+        self.schema.as_ref()
+    }
+
+    /// Starts profiling the extension: adds it's symbols to a perf map, and
+    /// keeps a per-symbol count of the cycles sampled with profile().
+    ///
+    /// # Arguments
+    ///
+    /// * `map`:  The perf map to add the extension's symbols to.
+    /// * `path`: The path the extension was loaded from.
+    pub fn enable_profiling(&mut self, map: &Arc<PerfMap>, path: &str) -> Result<(), String> {
+        let init = *self.procedure as usize;
+        let base = profile::load_address(init)
+            .ok_or_else(|| format!("failed to find where {} was loaded", path))?;
+        self.profile = Some(Profile::new(map, Path::new(path), base)?);
+        Ok(())
+    }
+
+    /// Returns the extension's profile, if it is being profiled.
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    /// Returns the number of tenants that can invoke the extension through an
+    /// extension manager. Once this drops to zero, the .so is unloaded as soon
+    /// as invocations still holding a reference to the extension complete.
+    pub fn bindings(&self) -> usize {
+        self.bindings.load(Ordering::Relaxed)
+    }
+}
+
+// A tenant's binding to an extension under some name. Bindings are counted on
+// the extension, so that it can tell how many tenants still bind it.
+struct Binding {
+    // The extension the name is bound to.
+    ext: Arc<Extension>,
+
+    // Whether the tenant loaded the extension, or had it shared with it.
+    provenance: Provenance,
+
+    // The id the binding is interned under. Never reused by another binding.
+    id: u32,
+}
+
+impl Binding {
+    // Binds an extension, counting the binding on it.
+    fn new(ext: Arc<Extension>, provenance: Provenance, id: u32) -> Binding {
+        ext.bindings.fetch_add(1, Ordering::Relaxed);
+        Binding {
+            ext: ext,
+            provenance: provenance,
+            id: id,
+        }
+    }
+}
+
+// Uncounts the binding when it is replaced or removed.
+impl Drop for Binding {
+    fn drop(&mut self) {
+        self.ext.bindings.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// A tenant's namespace of extension names, along with the ids they are interned under.
+#[derive(Default)]
+struct Namespace {
+    // The bindings, by name.
+    names: HashMap<Vec<u8>, Binding>,
+
+    // The name each binding's id was interned for. Shared with lookups by id, which hand it out
+    // along with the extension without copying it.
+    ids: HashMap<u32, Arc<Vec<u8>>>,
+}
+
+impl Namespace {
+    // Binds a name, replacing whatever it was bound to. The id of a replaced binding is no longer
+    // interned.
+    fn bind(&mut self, name: &[u8], binding: Binding) {
+        self.ids.insert(binding.id, Arc::new(name.to_vec()));
+        if let Some(old) = self.names.insert(name.to_vec(), binding) {
+            self.ids.remove(&old.id);
+        }
+    }
+
+    // Removes a binding, along with it's id.
+    fn unbind(&mut self, name: &[u8]) -> Option<Binding> {
+        let binding = self.names.remove(name)?;
+        self.ids.remove(&binding.id);
+        Some(binding)
+    }
 }
 
 /// This type represents an extension manager which keeps track of extensions
 /// in the database, and the tenants that own them.
+///
+/// Every tenant has it's own namespace of extension names. A name is bound to
+/// an extension the tenant either loaded itself, or had shared with it by
+/// another tenant; binding a name for one tenant never affects what the name
+/// is bound to for any other tenant, and a lookup never falls back to another
+/// tenant's namespace.
+///
+/// Every binding is also interned under a small id, so that requests can name
+/// the extension without carrying and hashing the name. Ids are handed out in
+/// increasing order and never reused, so an id that outlives it's binding
+/// (ex: because the name was bound again) never resolves to another extension.
 pub struct ExtensionManager {
-    // A simple map from tenants and extension names to extensions.
-    extensions: [RwLock<HashMap<(TenantId, String), Arc<Extension>>>; EXT_BUCKETS],
+    // A simple map from tenants to their namespaces. Names are stored as raw bytes so that a
+    // lookup can be performed directly on the name in a request's payload, without validating
+    // or copying it first.
+    extensions: [RwLock<HashMap<TenantId, Namespace>>; EXT_BUCKETS],
+
+    // The id the next binding is interned under. Starts at 1, so that 0 is never valid.
+    next_id: AtomicUsize,
+
+    // The perf map every loaded extension's symbols are added to, if
+    // extensions are being profiled.
+    perf: Option<Arc<PerfMap>>,
 }
 
 // Implementation of methods on ExtensionManager.
@@ -164,9 +443,27 @@ impl ExtensionManager {
                 RwLock::new(HashMap::new()),
                 RwLock::new(HashMap::new()),
             ],
+            next_id: AtomicUsize::new(1),
+            perf: None,
         }
     }
 
+    // Returns the id the next binding is interned under.
+    fn intern(&self) -> u32 {
+        self.next_id.fetch_add(1, Ordering::Relaxed) as u32
+    }
+
+    /// Profiles every extension loaded from here on. Their symbols are added
+    /// to a perf map, and the cycles spent in each are sampled every time an
+    /// extension yields or completes. Refer to Extension::profile().
+    ///
+    /// # Arguments
+    ///
+    /// * `map`: The perf map extension symbols are added to.
+    pub fn enable_profiling(&mut self, map: PerfMap) {
+        self.perf = Some(Arc::new(map));
+    }
+
     /// This method loads an extension for a particular tenant into the
     /// database.
     ///
@@ -176,24 +473,37 @@ impl ExtensionManager {
     ///             the extension.
     /// * `tenant`: The tenant owning the extension.
     /// * `name`:   The name of the extension. Subsequent calls to get must use
-    ///             this name.
+    ///             this name. If the tenant already has an extension under
+    ///             this name (loaded or shared), only the tenant's binding is
+    ///             replaced; other tenants sharing that extension keep it.
     ///
     /// # Return
     ///
     /// True if the extension was successfully loaded. False otherwise.
     pub fn load(&self, path: &str, tenant: TenantId, name: &str) -> bool {
-        // Try to load the extension from the supplied path.
-        Extension::load(path)
-                    // If the extension was loaded successfully, write it into
-                    // the extension manager. The bucket is determined by the
-                    // least significant byte of the tenant id.
-                    .and_then(| ext | {
-                        let bucket = (tenant & 0xff) as usize & (EXT_BUCKETS - 1);
-                        self.extensions[bucket].write()
-                                        .insert((tenant, String::from(name)),
-                                                Arc::new(ext));
-                        Some(()) })
-                    .is_some()
+        // Try to load the extension from the supplied path. An extension that
+        // can't be profiled is still loaded, just without a profile.
+        let mut ext = match Extension::load(path) {
+            Some(ext) => ext,
+            None => return false,
+        };
+        if let Some(ref perf) = self.perf {
+            if let Err(e) = ext.enable_profiling(perf, path) {
+                warn!("Failed to profile {}: {}", path, e);
+            }
+        }
+
+        // If the extension was loaded successfully, write it into the
+        // extension manager. The bucket is determined by the least
+        // significant byte of the tenant id.
+        let binding = Binding::new(Arc::new(ext), Provenance::Private, self.intern());
+        let bucket = (tenant & 0xff) as usize & (EXT_BUCKETS - 1);
+        self.extensions[bucket]
+            .write()
+            .entry(tenant)
+            .or_insert_with(Namespace::default)
+            .bind(name.as_bytes(), binding);
+        true
     }
 
     /// This method retrieves an extension that was previously loaded into the
@@ -202,23 +512,62 @@ impl ExtensionManager {
     /// # Arguments
     ///
     /// * `tenant`: The tenant owning the extension.
-    /// * `name`:   The name of the extension.
+    /// * `name`:   The name of the extension. Need not be valid UTF-8; a name
+    ///             that isn't simply won't match any loaded extension.
     ///
     /// # Return
     ///
-    /// A ref-counted handle to the extension if it was found.
-    pub fn get(&self, tenant: TenantId, name: String) -> Option<Arc<Extension>> {
+    /// A ref-counted handle to the extension if it was found under the
+    /// tenant's namespace. Extensions bound by other tenants are never
+    /// returned.
+    pub fn get(&self, tenant: TenantId, name: &[u8]) -> Option<Arc<Extension>> {
         // Lookup the extension, if it exists, bump up it's refcount, and
         // return it. The bucket is determined by the least significant byte
         // of the tenant id.
         let bucket = (tenant & 0xff) as usize & (EXT_BUCKETS - 1);
         self.extensions[bucket]
             .read()
-            .get(&(tenant, name))
-            .and_then(|ext| Some(Arc::clone(&ext)))
+            .get(&tenant)
+            .and_then(|exts| exts.names.get(name))
+            .and_then(|binding| Some(Arc::clone(&binding.ext)))
     }
 
-    /// Shares a previously loaded extension with another tenant.
+    /// Retrieves an extension by the id a tenant's binding to it is interned
+    /// under, without hashing or copying it's name.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`: The tenant owning the extension.
+    /// * `id`:     The id the binding was interned under. Listed by list().
+    ///
+    /// # Return
+    ///
+    /// A ref-counted handle to the extension along with the name it is bound
+    /// under, if the binding still exists. None if the id is stale; the name
+    /// was bound again or the binding removed since the id was handed out,
+    /// and the tenant must list it's extensions again to learn the new one.
+    pub fn get_by_id(&self, tenant: TenantId, id: u32) -> Option<(Arc<Extension>, Arc<Vec<u8>>)> {
+        let bucket = (tenant & 0xff) as usize & (EXT_BUCKETS - 1);
+        let extensions = self.extensions[bucket].read();
+        let exts = extensions.get(&tenant)?;
+        let name = exts.ids.get(&id)?;
+        exts.names
+            .get(&name[..])
+            .map(|binding| (Arc::clone(&binding.ext), Arc::clone(name)))
+    }
+
+    // Returns a tenant's binding to an extension, along with it's provenance.
+    fn binding(&self, tenant: TenantId, name: &[u8]) -> Option<(Arc<Extension>, Provenance)> {
+        let bucket = (tenant & 0xff) as usize & (EXT_BUCKETS - 1);
+        self.extensions[bucket]
+            .read()
+            .get(&tenant)
+            .and_then(|exts| exts.names.get(name))
+            .map(|binding| (Arc::clone(&binding.ext), binding.provenance))
+    }
+
+    /// Shares a previously loaded extension with another tenant. Refer to
+    /// try_share(); the share is not forced.
     ///
     /// # Arguments
     ///
@@ -228,29 +577,216 @@ impl ExtensionManager {
     ///
     /// # Return
     ///
-    /// True, if the extension was successfully shared. False, if it was not found.
+    /// True, if the extension was successfully shared. False, if it was not found, or if the
+    /// tenant has loaded an extension of it's own under the name.
     pub fn share(&self, owner: TenantId, share: TenantId, name: &str) -> bool {
+        self.try_share(owner, share, name, false).is_ok()
+    }
+
+    /// Shares a previously loaded extension with another tenant, binding it
+    /// under the same name in that tenant's namespace. An extension shared
+    /// onward keeps the tenant that loaded it as it's provenance.
+    ///
+    /// # Arguments
+    ///
+    /// * `owner`: The tenant that owns the extension.
+    /// * `share`: The tenant the extension must be shared with.
+    /// * `name`:  The name of the extension to be shared.
+    /// * `force`: If true, the share replaces an extension the tenant loaded
+    ///            itself under the name. Otherwise, only an extension shared
+    ///            with the tenant earlier can be replaced.
+    ///
+    /// # Return
+    ///
+    /// Ok if the extension was shared, or if `owner` and `share` are the same tenant.
+    pub fn try_share(
+        &self,
+        owner: TenantId,
+        share: TenantId,
+        name: &str,
+        force: bool,
+    ) -> Result<(), ShareError> {
         // First, try to retrieve a copy (Arc) of the extension from the owner.
-        // If successfull, then share it with the tenant identified by `share`.
-        self.get(owner, String::from(name))
-            .and_then(|ext| {
-                let bucket = (share & 0xff) as usize & (EXT_BUCKETS - 1);
-                self.extensions[bucket]
-                    .write()
-                    .insert((share, String::from(name)), ext);
-                Some(())
-            }).is_some()
+        let (ext, provenance) = self
+            .binding(owner, name.as_bytes())
+            .ok_or(ShareError::NotFound)?;
+        if owner == share {
+            return Ok(());
+        }
+
+        let provenance = match provenance {
+            Provenance::Private => Provenance::Shared(owner),
+            shared => shared,
+        };
+
+        // Then, bind it under the tenant identified by `share`, unless that would replace an
+        // extension the tenant loaded itself.
+        let bucket = (share & 0xff) as usize & (EXT_BUCKETS - 1);
+        let mut extensions = self.extensions[bucket].write();
+        let exts = extensions.entry(share).or_insert_with(Namespace::default);
+        let private = match exts.names.get(name.as_bytes()) {
+            Some(binding) => binding.provenance == Provenance::Private,
+            None => false,
+        };
+        if private && !force {
+            return Err(ShareError::Private);
+        }
+
+        let binding = Binding::new(ext, provenance, self.intern());
+        exts.bind(name.as_bytes(), binding);
+        Ok(())
+    }
+
+    /// Removes a tenant's binding to an extension. Other tenants the extension
+    /// is shared with keep it.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`: The tenant whose binding must be removed.
+    /// * `name`:   The name the extension is bound under.
+    ///
+    /// # Return
+    ///
+    /// The number of tenants still bound to the extension, or None if the
+    /// tenant had nothing bound under the name. The extension's .so is
+    /// unloaded once this reaches zero, and invocations still running on it
+    /// complete. The binding's id is stale from here on.
+    pub fn unload(&self, tenant: TenantId, name: &[u8]) -> Option<usize> {
+        let bucket = (tenant & 0xff) as usize & (EXT_BUCKETS - 1);
+        let binding = self.extensions[bucket]
+            .write()
+            .get_mut(&tenant)
+            .and_then(|exts| exts.unbind(name))?;
+
+        let ext = Arc::clone(&binding.ext);
+        drop(binding);
+        Some(ext.bindings())
+    }
+
+    /// Lists the extensions a tenant can invoke, including extensions shared
+    /// with it by other tenants, along with where each came from.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`: The tenant whose extensions must be listed.
+    ///
+    /// # Return
+    ///
+    /// Metadata on each extension, sorted by name so that the list can be
+    /// paged through by index.
+    pub fn list(&self, tenant: TenantId) -> Vec<ExtensionInfo> {
+        let bucket = (tenant & 0xff) as usize & (EXT_BUCKETS - 1);
+        let mut list: Vec<ExtensionInfo> = self.extensions[bucket]
+            .read()
+            .get(&tenant)
+            .map(|exts| {
+                exts.names
+                    .iter()
+                    .map(|(name, binding)| ExtensionInfo {
+                        name: name.clone(),
+                        version: 0,
+                        loaded: binding.ext.loaded(),
+                        invocations: binding.ext.invocations(),
+                        provenance: binding.provenance,
+                        schema: binding.ext.schema().cloned(),
+                        id: binding.id,
+                    }).collect()
+            }).unwrap_or_else(Vec::new);
+
+        list.sort_by(|a, b| a.name.cmp(&b.name));
+        list
+    }
+
+    /// Returns the symbols each profiled extension spent the most cycles in.
+    /// An extension shared across tenants is reported once, under the lowest
+    /// numbered tenant it is visible to.
+    ///
+    /// # Arguments
+    ///
+    /// * `n`: The maximum number of symbols to return per extension.
+    ///
+    /// # Return
+    ///
+    /// A (tenant, extension name, symbols) tuple per profiled extension, with
+    /// the cycles spent in each symbol, most first.
+    pub fn profiles(&self, n: usize) -> Vec<(TenantId, Vec<u8>, Vec<(String, u64)>)> {
+        let mut seen: Vec<*const Extension> = Vec::new();
+        let mut profiles = Vec::new();
+        let mut tenants: Vec<(TenantId, Vec<u8>, Arc<Extension>)> = Vec::new();
+        for bucket in self.extensions.iter() {
+            for (tenant, exts) in bucket.read().iter() {
+                for (name, binding) in exts.names.iter() {
+                    tenants.push((*tenant, name.clone(), Arc::clone(&binding.ext)));
+                }
+            }
+        }
+
+        tenants.sort_by(|a, b| (a.0, &a.1).cmp(&(b.0, &b.1)));
+        for (tenant, name, ext) in tenants {
+            if seen.contains(&(&*ext as *const Extension)) {
+                continue;
+            }
+            seen.push(&*ext as *const Extension);
+
+            if let Some(profile) = ext.profile() {
+                profiles.push((tenant, name, profile.top(n)));
+            }
+        }
+
+        profiles
     }
 }
 
 // This module contains simple tests for Extension and ExtensionManager.
 #[cfg(test)]
 mod tests {
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Read;
     use std::ops::GeneratorState;
+    use std::path::PathBuf;
+    use std::process;
     use std::rc::Rc;
+    use std::thread;
 
-    use super::{Extension, ExtensionManager};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::u32;
+
+    use super::{Extension, ExtensionManager, Provenance, ShareError};
     use super::super::null::NullDB;
+    use super::super::profile::PerfMap;
+    use super::super::schema::Field;
+
+    // A global allocator that counts the number of allocations made by the
+    // current thread. Required to check that lookups do not allocate.
+    mod alloc {
+        use std::alloc::{GlobalAlloc, Layout, System};
+        use std::cell::Cell;
+
+        thread_local!(static ALLOCS: Cell<usize> = Cell::new(0));
+
+        pub struct Counting;
+
+        unsafe impl GlobalAlloc for Counting {
+            unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+                let _ = ALLOCS.try_with(|a| a.set(a.get() + 1));
+                System.alloc(layout)
+            }
+
+            unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+                System.dealloc(ptr, layout)
+            }
+        }
+
+        #[global_allocator]
+        static GLOBAL: Counting = Counting;
+
+        // Returns the number of allocations made by this thread so far.
+        pub fn count() -> usize {
+            ALLOCS.with(|a| a.get())
+        }
+    }
 
     // This function attempts to load and run a test extension, and asserts
     // that both operations were successfull.
@@ -280,6 +816,23 @@ mod tests {
         Extension::load("../ext/err/target/release/libxyz.so").unwrap();
     }
 
+    // This function tests that the layout an extension declares is read in when it is loaded,
+    // and listed, while an extension that declares none takes any arguments.
+    #[test]
+    fn test_ext_load_schema() {
+        let get = Extension::load("../ext/get/target/release/libget.so").unwrap();
+        let schema = get.schema().unwrap();
+        assert_eq!(&[Field::Table, Field::Blob(1, u32::MAX)], schema.fields());
+        assert!(Extension::load(TEST).unwrap().schema().is_none());
+
+        let man = ExtensionManager::new();
+        assert!(man.load("../ext/get/target/release/libget.so", 0, "get"));
+        assert!(man.load(TEST, 0, "test"));
+        let list = man.list(0);
+        assert_eq!(Some(schema), list[0].schema.as_ref());
+        assert_eq!(None, list[1].schema);
+    }
+
     // This function tests that the extension manager can load a valid
     // extension.
     #[test]
@@ -313,7 +866,7 @@ mod tests {
         assert!(man.load("../ext/test/target/release/libtest.so", 0, "test"));
 
         // Retrieve the extension, and the generator.
-        let ext = man.get(0, "test".to_string()).unwrap();
+        let ext = man.get(0, b"test").unwrap();
         let mut gen = ext.get(Rc::new(NullDB::new()));
 
         // Assert that the test extension has one yield statement.
@@ -321,12 +874,345 @@ mod tests {
         unsafe { assert_eq!(GeneratorState::Complete(0), gen.resume()) };
     }
 
+    // This function tests that a lookup with a name that is not valid UTF-8
+    // fails cleanly instead of panicking.
+    #[test]
+    fn test_man_get_non_utf8() {
+        let man = ExtensionManager::new();
+        assert!(man.load("../ext/test/target/release/libtest.so", 0, "test"));
+        assert!(man.get(0, &[0xff, 0xfe, 0x74]).is_none());
+    }
+
+    // This function tests that looking up a loaded extension does not
+    // allocate, i.e. the name is not copied into a String or Vec.
+    #[test]
+    fn test_man_get_no_alloc() {
+        let man = ExtensionManager::new();
+        assert!(man.load("../ext/test/target/release/libtest.so", 0, "test"));
+
+        let name = b"test";
+        let before = alloc::count();
+        for _ in 0..1000 {
+            assert!(man.get(0, name).is_some());
+        }
+        assert_eq!(before, alloc::count());
+    }
+
+    // This function tests that a tenant's listing contains the extensions it
+    // loaded and the extensions shared with it, and nothing else.
+    #[test]
+    fn test_man_list() {
+        let man = ExtensionManager::new();
+        assert!(man.load("../ext/test/target/release/libtest.so", 0, "test"));
+        assert!(man.load("../ext/test/target/release/libtest.so", 0, "alpha"));
+        assert!(man.load("../ext/test/target/release/libtest.so", 1, "beta"));
+        assert!(man.share(0, 1, "test"));
+        assert!(man.share(0, 2, "test"));
+
+        let names =
+            |tenant| -> Vec<Vec<u8>> { man.list(tenant).into_iter().map(|e| e.name).collect() };
+        assert_eq!(vec![b"alpha".to_vec(), b"test".to_vec()], names(0));
+        assert_eq!(vec![b"beta".to_vec(), b"test".to_vec()], names(1));
+        assert_eq!(vec![b"test".to_vec()], names(2));
+        assert!(names(3).is_empty());
+    }
+
+    // This function tests that invocations of a shared extension are counted
+    // once, and show up in the listing of every tenant it is shared with.
+    #[test]
+    fn test_man_list_invocations() {
+        let man = ExtensionManager::new();
+        assert!(man.load("../ext/test/target/release/libtest.so", 0, "test"));
+        assert!(man.share(0, 1, "test"));
+
+        for tenant in 0..2 {
+            let ext = man.get(tenant, b"test").unwrap();
+            let mut gen = ext.get(Rc::new(NullDB::new()));
+            unsafe { assert_eq!(GeneratorState::Complete(0), gen.resume()) };
+        }
+
+        for tenant in 0..2 {
+            let list = man.list(tenant);
+            assert_eq!(1, list.len());
+            assert_eq!(2, list[0].invocations);
+            assert_eq!(0, list[0].version);
+            assert!(list[0].loaded > 0);
+        }
+    }
+
+    // Two extensions that run different code; under a NullDB, "test" completes with 0 and
+    // "merge_counter" (which fails to find it's arguments) completes with 1.
+    const TEST: &str = "../ext/test/target/release/libtest.so";
+    const COUNTER: &str = "../ext/merge_counter/target/release/libmerge_counter.so";
+
+    // Invokes the extension a tenant has bound under a name, and returns what it completed with.
+    fn run(man: &ExtensionManager, tenant: u32, name: &[u8]) -> u64 {
+        let ext = man.get(tenant, name).unwrap();
+        let mut gen = ext.get(Rc::new(NullDB::new()));
+        match unsafe { gen.resume() } {
+            GeneratorState::Complete(ret) => ret,
+            GeneratorState::Yielded(_) => panic!("Extension yielded"),
+        }
+    }
+
+    // Returns the provenance of the extension a tenant has bound under a name.
+    fn provenance(man: &ExtensionManager, tenant: u32, name: &[u8]) -> Option<Provenance> {
+        man.list(tenant)
+            .into_iter()
+            .find(|e| e.name == name)
+            .map(|e| e.provenance)
+    }
+
+    // This function tests that two tenants can load different extensions under the same name,
+    // and that each tenant's invocations run it's own extension.
+    #[test]
+    fn test_man_namespacing() {
+        let man = ExtensionManager::new();
+        assert!(man.load(TEST, 0, "get"));
+        assert!(man.load(COUNTER, 1, "get"));
+
+        assert_eq!(0, run(&man, 0, b"get"));
+        assert_eq!(1, run(&man, 1, b"get"));
+        assert!(man.get(2, b"get").is_none());
+
+        // Tenant 33 shares a bucket with tenant 1, but not it's extensions.
+        assert!(man.get(33, b"get").is_none());
+    }
+
+    // This function tests that loading an extension over one shared with a tenant only replaces
+    // that tenant's binding.
+    #[test]
+    fn test_man_load_over_share() {
+        let man = ExtensionManager::new();
+        assert!(man.load(TEST, 0, "ext"));
+        assert!(man.share(0, 1, "ext"));
+        assert!(man.share(0, 2, "ext"));
+        assert!(man.load(COUNTER, 1, "ext"));
+
+        assert_eq!(0, run(&man, 0, b"ext"));
+        assert_eq!(1, run(&man, 1, b"ext"));
+        assert_eq!(0, run(&man, 2, b"ext"));
+        assert_eq!(Some(Provenance::Private), provenance(&man, 0, b"ext"));
+        assert_eq!(Some(Provenance::Private), provenance(&man, 1, b"ext"));
+        assert_eq!(Some(Provenance::Shared(0)), provenance(&man, 2, b"ext"));
+    }
+
+    // This function tests that a share never replaces an extension a tenant loaded itself unless
+    // it is forced, while it can replace an extension shared earlier.
+    #[test]
+    fn test_man_share_force() {
+        let man = ExtensionManager::new();
+        assert!(man.load(TEST, 0, "ext"));
+        assert!(man.load(COUNTER, 1, "ext"));
+        assert_eq!(Err(ShareError::NotFound), man.try_share(0, 1, "xyz", false));
+        assert_eq!(Err(ShareError::NotFound), man.try_share(2, 1, "ext", true));
+
+        // Tenant 1's own extension survives an unforced share, but not a forced one.
+        assert_eq!(Err(ShareError::Private), man.try_share(0, 1, "ext", false));
+        assert!(!man.share(0, 1, "ext"));
+        assert_eq!(1, run(&man, 1, b"ext"));
+        assert_eq!(Ok(()), man.try_share(0, 1, "ext", true));
+        assert_eq!(0, run(&man, 1, b"ext"));
+        assert_eq!(Some(Provenance::Shared(0)), provenance(&man, 1, b"ext"));
+
+        // A shared extension is replaced by a later share without forcing it.
+        assert!(man.load(COUNTER, 3, "ext"));
+        assert!(man.share(0, 2, "ext"));
+        assert!(man.share(3, 2, "ext"));
+        assert_eq!(1, run(&man, 2, b"ext"));
+        assert_eq!(Some(Provenance::Shared(3)), provenance(&man, 2, b"ext"));
+
+        // Sharing onward keeps the tenant that loaded the extension, and sharing with oneself
+        // changes nothing.
+        assert!(man.share(2, 4, "ext"));
+        assert_eq!(Some(Provenance::Shared(3)), provenance(&man, 4, b"ext"));
+        assert!(man.share(3, 3, "ext"));
+        assert_eq!(Some(Provenance::Private), provenance(&man, 3, b"ext"));
+    }
+
+    // This function tests that an extension counts the tenants bound to it, and that it is only
+    // released once none are.
+    #[test]
+    fn test_man_unload_bindings() {
+        let man = ExtensionManager::new();
+        assert!(man.load(TEST, 0, "ext"));
+        assert!(man.share(0, 1, "ext"));
+        assert!(man.share(0, 2, "ext"));
+        let ext = man.get(0, b"ext").unwrap();
+        assert_eq!(3, ext.bindings());
+
+        assert_eq!(Some(2), man.unload(1, b"ext"));
+        assert_eq!(None, man.unload(1, b"ext"));
+        assert!(man.get(1, b"ext").is_none());
+        assert_eq!(0, run(&man, 2, b"ext"));
+
+        // Replacing the owner's binding leaves the shared one in place.
+        assert!(man.load(COUNTER, 0, "ext"));
+        assert_eq!(1, ext.bindings());
+        assert_eq!(1, man.get(0, b"ext").unwrap().bindings());
+
+        assert_eq!(Some(0), man.unload(2, b"ext"));
+        assert_eq!(1, Arc::strong_count(&ext));
+    }
+
+    // Returns the id a tenant's binding under a name is interned under.
+    fn id(man: &ExtensionManager, tenant: u32, name: &[u8]) -> u32 {
+        man.list(tenant)
+            .into_iter()
+            .find(|e| e.name == name)
+            .map(|e| e.id)
+            .unwrap()
+    }
+
+    // This function tests that an extension is found by the id it's binding is interned under,
+    // that every binding gets an id of it's own, and that an id goes stale once the name is
+    // bound again or unbound.
+    #[test]
+    fn test_man_get_by_id() {
+        let man = ExtensionManager::new();
+        assert!(man.load(TEST, 0, "ext"));
+        assert!(man.share(0, 1, "ext"));
+        let (owner, shared) = (id(&man, 0, b"ext"), id(&man, 1, b"ext"));
+        assert!(owner != 0 && owner != shared);
+
+        let (ext, name) = man.get_by_id(0, owner).unwrap();
+        assert!(Arc::ptr_eq(&ext, &man.get(0, b"ext").unwrap()));
+        assert_eq!(b"ext", &name[..]);
+        assert!(Arc::ptr_eq(&ext, &man.get_by_id(1, shared).unwrap().0));
+
+        // Ids don't resolve under another tenant.
+        assert!(man.get_by_id(1, owner).is_none());
+        assert!(man.get_by_id(0, 0).is_none());
+
+        // Loading over the owner's binding only makes it's id stale.
+        assert!(man.load(COUNTER, 0, "ext"));
+        assert!(man.get_by_id(0, owner).is_none());
+        let reloaded = id(&man, 0, b"ext");
+        assert!(reloaded > shared);
+        assert!(Arc::ptr_eq(
+            &man.get(0, b"ext").unwrap(),
+            &man.get_by_id(0, reloaded).unwrap().0
+        ));
+        assert!(Arc::ptr_eq(&ext, &man.get_by_id(1, shared).unwrap().0));
+
+        assert_eq!(Some(0), man.unload(1, b"ext"));
+        assert!(man.get_by_id(1, shared).is_none());
+        assert_eq!(1, Arc::strong_count(&ext));
+
+        let before = alloc::count();
+        for _ in 0..1000 {
+            assert!(man.get_by_id(0, reloaded).is_some());
+        }
+        assert_eq!(before, alloc::count());
+    }
+
+    // This function tests that lookups by id racing with a name being bound again and again
+    // only ever resolve an id to a binding of the name it was handed out for, and that an id
+    // never resolves again once it has gone stale.
+    #[test]
+    fn test_man_get_by_id_races() {
+        let man = Arc::new(ExtensionManager::new());
+        let done = Arc::new(AtomicBool::new(false));
+        assert!(man.load(TEST, 0, "ext"));
+
+        let loader = {
+            let (man, done) = (Arc::clone(&man), Arc::clone(&done));
+            thread::spawn(move || {
+                for i in 0..32 {
+                    let path = if i % 2 == 0 { COUNTER } else { TEST };
+                    assert!(man.load(path, 0, "ext"));
+                    assert!(man.share(0, 1, "ext"));
+                }
+                done.store(true, Ordering::Relaxed);
+            })
+        };
+
+        let mut stale = Vec::new();
+        while !done.load(Ordering::Relaxed) {
+            let listed = id(&man, 0, b"ext");
+            match man.get_by_id(0, listed) {
+                Some((_, name)) => assert_eq!(b"ext", &name[..]),
+                None if !stale.contains(&listed) => stale.push(listed),
+                None => (),
+            }
+            for id in stale.iter() {
+                assert!(man.get_by_id(0, *id).is_none());
+            }
+        }
+        loader.join().unwrap();
+
+        // Only the last bindings resolve, and both are to the same extension.
+        let (last, shared) = (id(&man, 0, b"ext"), id(&man, 1, b"ext"));
+        let (ext, _) = man.get_by_id(0, last).unwrap();
+        assert!(Arc::ptr_eq(&ext, &man.get_by_id(1, shared).unwrap().0));
+        assert_eq!(2, ext.bindings());
+        assert!((1..last).all(|id| man.get_by_id(0, id).is_none()));
+        assert!((1..shared).all(|id| man.get_by_id(1, id).is_none()));
+    }
+
+    // Returns the (start, size) of every entry for `name` in a perf map.
+    fn perf_entries(path: &PathBuf, name: &str) -> Vec<(u64, u64)> {
+        let mut map = String::new();
+        File::open(path)
+            .and_then(|mut f| f.read_to_string(&mut map))
+            .unwrap();
+        map.lines()
+            .map(|l| l.splitn(3, ' ').collect::<Vec<&str>>())
+            .filter(|l| l.len() == 3 && l[2] == name)
+            .map(|l| {
+                (
+                    u64::from_str_radix(l[0], 16).unwrap(),
+                    u64::from_str_radix(l[1], 16).unwrap(),
+                )
+            }).collect()
+    }
+
+    // This function tests that a profiled extension's entry point shows up
+    // in the perf map at the address it was loaded at, and that reloading
+    // the extension from another copy of the object moves it there.
+    #[test]
+    fn test_man_profile_perf_map() {
+        let dir = env::temp_dir();
+        let path = dir.join(format!("perf-ext-{}.map", process::id()));
+        let copy = dir.join(format!("libtest-{}.so", process::id()));
+        fs::copy("../ext/test/target/release/libtest.so", &copy).unwrap();
+
+        let mut man = ExtensionManager::new();
+        man.enable_profiling(PerfMap::with_path(path.clone()));
+        assert!(man.load("../ext/test/target/release/libtest.so", 0, "test"));
+
+        // The entry is exactly where dlsym() found "init".
+        let init = *man.get(0, b"test").unwrap().procedure as usize as u64;
+        let entries = perf_entries(&path, "init");
+        assert_eq!(1, entries.len());
+        assert_eq!(init, entries[0].0);
+        assert!(entries[0].1 > 0);
+
+        // Reloading from the copy replaces the old object's symbols.
+        assert!(man.load(copy.to_str().unwrap(), 0, "test"));
+        let reloaded = *man.get(0, b"test").unwrap().procedure as usize as u64;
+        assert!(reloaded != init);
+        let starts: Vec<u64> = perf_entries(&path, "init").iter().map(|e| e.0).collect();
+        assert_eq!(vec![reloaded], starts);
+
+        // Running the extension samples cycles into it's profile.
+        let ext = man.get(0, b"test").unwrap();
+        let profile = ext.profile().unwrap();
+        assert!(profile.table().lookup(reloaded).is_some());
+        profile.sample(reloaded as usize, 100);
+        assert_eq!(vec![("init".to_string(), 100)], profile.top(1));
+        assert_eq!(1, man.profiles(1).len());
+
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(&copy);
+    }
+
     // This function tests that a non-existent extension cannot be retrieved
     // from the extension manager.
     #[test]
     #[should_panic]
     fn test_man_get_err() {
         let man = ExtensionManager::new();
-        man.get(0, "test".to_string()).unwrap();
+        man.get(0, b"test").unwrap();
     }
 }
@@ -0,0 +1,184 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! A structured encoding for the arguments to an extension, so that extensions don't have to
+//! parse them off fixed offsets. The arguments are a sequence of fields, each a 4 byte
+//! little-endian length followed by that many bytes. Fields can be empty.
+//!
+//! Clients build the arguments with InvokeArgs, and send them with
+//! dispatch::Sender::send_invoke_args(). Extensions walk them one field at a time with
+//! DB::next_arg(), and can still read them raw through DB::args().
+
+use std::cell::Cell;
+
+use byteorder::{ByteOrder, LittleEndian};
+
+/// The number of bytes the length of each field is encoded in.
+pub const LEN_BYTES: usize = 4;
+
+/// Builds up the arguments to an extension one field at a time.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct InvokeArgs {
+    // The fields encoded so far.
+    buf: Vec<u8>,
+}
+
+impl InvokeArgs {
+    /// Returns arguments without any fields.
+    pub fn new() -> InvokeArgs {
+        InvokeArgs { buf: Vec::new() }
+    }
+
+    /// Appends a field.
+    ///
+    /// # Arguments
+    ///
+    /// * `field`: The bytes of the field. Must be shorter than 4 GB.
+    ///
+    /// # Return
+    ///
+    /// The arguments, so that calls can be chained.
+    pub fn push(&mut self, field: &[u8]) -> &mut InvokeArgs {
+        let mut len = [0; LEN_BYTES];
+        LittleEndian::write_u32(&mut len, field.len() as u32);
+        self.buf.extend_from_slice(&len);
+        self.buf.extend_from_slice(field);
+        self
+    }
+
+    /// Appends an 8 byte little-endian integer as a field, ex: a table identifier.
+    pub fn push_u64(&mut self, val: u64) -> &mut InvokeArgs {
+        let mut buf = [0; 8];
+        LittleEndian::write_u64(&mut buf, val);
+        self.push(&buf)
+    }
+
+    /// Returns the encoded arguments.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf
+    }
+
+    /// Returns the encoded arguments, consuming the builder.
+    pub fn into_vec(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+/// Splits the first field off a set of encoded arguments.
+///
+/// # Return
+///
+/// The first field and the arguments after it, or None if there are no fields left or the
+/// first field runs past the end of the arguments.
+pub fn split_arg(buf: &[u8]) -> Option<(&[u8], &[u8])> {
+    if buf.len() < LEN_BYTES {
+        return None;
+    }
+
+    let len = LittleEndian::read_u32(&buf[0..LEN_BYTES]) as usize;
+    let rest = &buf[LEN_BYTES..];
+    if rest.len() < len {
+        return None;
+    }
+
+    Some(rest.split_at(len))
+}
+
+/// Reads the field at a cursor into a set of encoded arguments, and moves the cursor past it.
+/// Used by implementations of DB::next_arg().
+///
+/// # Arguments
+///
+/// * `args`:   The encoded arguments.
+/// * `cursor`: The offset of the next field on the arguments. Left as is if there isn't one.
+///
+/// # Return
+///
+/// The field, or None if every field has been read or the next one is malformed.
+pub fn next_arg<'a>(args: &'a [u8], cursor: &Cell<usize>) -> Option<&'a [u8]> {
+    let rest = args.get(cursor.get()..)?;
+    let (field, after) = split_arg(rest)?;
+    cursor.set(args.len() - after.len());
+    Some(field)
+}
+
+// This module contains unit tests for the argument encoding.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Walks a set of encoded arguments to the end, and returns every field read off them.
+    fn walk(args: &[u8]) -> Vec<&[u8]> {
+        let cursor = Cell::new(0);
+        let mut fields = Vec::new();
+        while let Some(field) = next_arg(args, &cursor) {
+            fields.push(field);
+        }
+
+        assert_eq!(args.len(), cursor.get());
+        fields
+    }
+
+    // Tests that arguments without any fields are empty, and walk to nothing.
+    #[test]
+    fn test_invoke_args_none() {
+        let args = InvokeArgs::new();
+        assert!(args.as_bytes().is_empty());
+        assert!(walk(args.as_bytes()).is_empty());
+        assert_eq!(None, split_arg(&[]));
+    }
+
+    // Tests that a single field is encoded behind it's length, and read back.
+    #[test]
+    fn test_invoke_args_one() {
+        let mut args = InvokeArgs::new();
+        args.push(b"key");
+        assert_eq!(&[3, 0, 0, 0, b'k', b'e', b'y'], args.as_bytes());
+        assert_eq!(vec![&b"key"[..]], walk(args.as_bytes()));
+    }
+
+    // Tests that many fields, including empty ones at the end, are read back in order.
+    #[test]
+    fn test_invoke_args_many() {
+        let mut args = InvokeArgs::new();
+        args.push_u64(7)
+            .push(b"key")
+            .push(b"")
+            .push(b"value")
+            .push(b"");
+
+        let mut table = [0; 8];
+        LittleEndian::write_u64(&mut table, 7);
+        let expected = vec![&table[..], &b"key"[..], &b""[..], &b"value"[..], &b""[..]];
+        assert_eq!(expected, walk(args.as_bytes()));
+        assert_eq!(5 * LEN_BYTES + 8 + 3 + 5, args.into_vec().len());
+    }
+
+    // Tests that a field that runs past the end of the arguments isn't read, and leaves the
+    // cursor on it.
+    #[test]
+    fn test_invoke_args_truncated() {
+        let mut args = InvokeArgs::new();
+        args.push(b"key").push(b"value");
+        let buf = args.into_vec();
+        let buf = &buf[..buf.len() - 1];
+
+        let cursor = Cell::new(0);
+        assert_eq!(Some(&b"key"[..]), next_arg(buf, &cursor));
+        assert_eq!(None, next_arg(buf, &cursor));
+        assert_eq!(LEN_BYTES + 3, cursor.get());
+        assert_eq!(None, split_arg(&buf[..2]));
+    }
+}
@@ -16,30 +16,51 @@
 //! This crate contains all the traits and common code which
 //! is used on both client and server.
 #![feature(type_ascription)]
-#![feature(generator_trait)]
+#![feature(generators, generator_trait)]
 #![feature(rustc_private)]
 #![warn(missing_docs)]
 
 /// Allocator/deallocator of heap memory for the table.
 pub mod allocator;
+/// Record layout and resumable bcrypt shared by the auth extension, the AUTH dataset and clients.
+pub mod auth;
 /// Module to manipulate various type of buffer for the entire system.
 pub mod buf;
 /// Common constants used in the system, example: PACKET_ETYPE: u16 = 0x0800.
 pub mod common;
 /// DB trait which define the functions for each `DB` implementation.
 pub mod db;
+/// The arguments passed to extensions running work they deferred through DB::defer().
+pub mod defer;
+/// Generates the entry point of an extension off a handler; see declare_extension!.
+pub mod entry;
 /// Module to manage the extensions; load, install, get etc.
 pub mod ext;
+/// The encoding of an extension's arguments as a sequence of length-prefixed fields.
+pub mod invoke;
 /// Module to put all the db related macros like GET(), PUT(), etc.
 pub mod macros;
+/// The arguments passed to merge extensions, which combine stored values with deltas.
+pub mod merge;
 /// Mock implementation of `DB` trait.
 pub mod mock;
 /// Null DB implemention to be used in ext_bench benchmark.
 pub mod null;
 /// Module to serialize bytes which can be transferred over the network.
 pub mod pack;
+/// Symbol maps and sampled cycle breakdowns used to profile loaded extensions.
+pub mod profile;
+/// The arguments to the put extension, shared by the extension and the clients that invoke it.
+pub mod put;
+/// The arguments passed to extensions picked by the server off a key's prefix.
+pub mod route;
+/// The layout of an extension's arguments, checked by the server before it invokes the extension.
+pub mod schema;
+/// Record layouts and the generation model shared by the TAO extension and the TAO dataset.
+pub mod tao;
 
 pub use std::boxed;
+pub use std::cell;
 pub use std::convert;
 pub use std::io;
 pub use std::mem::size_of;
@@ -50,8 +71,11 @@ pub use std::time;
 pub use std::vec;
 
 extern crate byteorder;
+extern crate crypto;
 extern crate hashbrown;
 extern crate libloading;
+#[macro_use]
+extern crate log;
 extern crate spin;
 extern crate util;
 pub use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
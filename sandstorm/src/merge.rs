@@ -0,0 +1,77 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! The arguments the database passes to a merge extension. A merge extension combines the value
+//! stored under a key with a delta, and writes the new value out with `DB::resp()`. It fails by
+//! returning anything but 0 (or by panicking), in which case whatever it wrote out is handed
+//! back to the client as an error frame, and the stored value is left untouched.
+
+use byteorder::{ByteOrder, LittleEndian};
+
+/// The length of the header on a merge extension's arguments: the 4 byte (little-endian)
+/// length of the old value.
+pub const MERGE_HEADER_LEN: usize = 4;
+
+/// Encodes the arguments to a merge extension.
+///
+/// # Arguments
+///
+/// * `old`:   The value stored under the key. Empty if the key does not exist.
+/// * `delta`: The delta to merge into it.
+pub fn encode_args(old: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut args = vec![0; MERGE_HEADER_LEN];
+    LittleEndian::write_u32(&mut args, old.len() as u32);
+    args.extend_from_slice(old);
+    args.extend_from_slice(delta);
+    args
+}
+
+/// Decodes the arguments to a merge extension.
+///
+/// # Return
+///
+/// The old value and the delta, or None if the arguments are malformed.
+pub fn decode_args(args: &[u8]) -> Option<(&[u8], &[u8])> {
+    if args.len() < MERGE_HEADER_LEN {
+        return None;
+    }
+
+    let old = LittleEndian::read_u32(&args[0..MERGE_HEADER_LEN]) as usize;
+    let rest = &args[MERGE_HEADER_LEN..];
+    if rest.len() < old {
+        return None;
+    }
+
+    Some(rest.split_at(old))
+}
+
+// This module contains unit tests for the merge argument encoding.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Tests that arguments round trip, including an absent (empty) old value.
+    #[test]
+    fn test_merge_args() {
+        let args = encode_args(b"old", b"delta");
+        assert_eq!(Some((&b"old"[..], &b"delta"[..])), decode_args(&args));
+
+        let args = encode_args(&[], b"delta");
+        assert_eq!(Some((&b""[..], &b"delta"[..])), decode_args(&args));
+
+        assert_eq!(None, decode_args(&[1, 0]));
+        assert_eq!(None, decode_args(&[4, 0, 0, 0, 1]));
+    }
+}
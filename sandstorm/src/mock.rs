@@ -15,31 +15,117 @@
 
 use std::fmt::Debug;
 
-use super::buf::{MultiReadBuf, ReadBuf, WriteBuf};
-use super::db::DB;
+use super::buf::{projection, MultiReadBuf, ReadBuf, WriteBuf};
+use super::db::{PutManyError, DB};
+use super::invoke;
 
 extern crate bytes;
-use self::bytes::{Bytes, BytesMut};
+use self::bytes::{BufMut, Bytes, BytesMut};
 
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
 use std::sync::Arc;
 use util::model::Model;
 
-/// A mock database of testing purposes.
+/// The number of records visited by each call to scan() on a MockDB.
+const SCAN_SLICE: usize = 4;
+
+/// A mock database of testing purposes. Records written through it are kept in memory, per
+/// table, so that extensions under test read back what they wrote. Like on the real database,
+/// tables have to be created through create_table() before records are written to them.
 pub struct MockDB {
     messages: RefCell<Vec<String>>,
-    args: [u8; 30],
+    args: Vec<u8>,
+    arg_cursor: Cell<usize>,
+    response: RefCell<Vec<u8>>,
+    flushed: RefCell<Vec<Vec<u8>>>,
+    deferred: RefCell<Vec<Vec<u8>>>,
+    tables: RefCell<HashMap<u64, HashMap<Vec<u8>, Vec<u8>>>>,
+    checkpoints: RefCell<Vec<(u64, Vec<u8>)>>,
+    restored: Option<(u64, Vec<u8>)>,
 }
 
 impl MockDB {
     /// This method creates a new instance of MockDB.
     pub fn new() -> MockDB {
+        MockDB::with_args(&[97; 30])
+    }
+
+    /// This method creates a new instance of MockDB whose args() returns the given arguments.
+    pub fn with_args(args: &[u8]) -> MockDB {
         MockDB {
             messages: RefCell::new(Vec::new()),
-            args: [97; 30],
+            args: args.to_vec(),
+            arg_cursor: Cell::new(0),
+            response: RefCell::new(Vec::new()),
+            flushed: RefCell::new(Vec::new()),
+            deferred: RefCell::new(Vec::new()),
+            tables: RefCell::new(HashMap::new()),
+            checkpoints: RefCell::new(Vec::new()),
+            restored: None,
         }
     }
 
+    /// This method makes restore() return a checkpoint, as if the extension were a durable
+    /// invocation being resumed after a restart.
+    pub fn resume_from(mut self, progress: u64, state: &[u8]) -> MockDB {
+        self.restored = Some((progress, state.to_vec()));
+        self
+    }
+
+    /// This method creates an empty table, if one doesn't already exist under the identifier.
+    pub fn create_table(&self, table: u64) {
+        self.tables
+            .borrow_mut()
+            .entry(table)
+            .or_insert_with(HashMap::new);
+    }
+
+    /// This method adds a record to a table, creating the table if needed, for tests to populate
+    /// a database with. Records added here are visible to get(), multiget(), and scan().
+    pub fn insert(&self, table: u64, key: &[u8], val: &[u8]) {
+        self.tables
+            .borrow_mut()
+            .entry(table)
+            .or_insert_with(HashMap::new)
+            .insert(key.to_vec(), val.to_vec());
+    }
+
+    // Returns true if the table was created through create_table() or insert().
+    fn has_table(&self, table: u64) -> bool {
+        self.tables.borrow().contains_key(&table)
+    }
+
+    // Returns a copy of a record added through insert(), if there is one.
+    fn lookup(&self, table: u64, key: &[u8]) -> Option<Vec<u8>> {
+        self.tables
+            .borrow()
+            .get(&table)
+            .and_then(|records| records.get(key))
+            .cloned()
+    }
+
+    /// This method returns everything written to the response so far through resp().
+    pub fn response(&self) -> Vec<u8> {
+        self.response.borrow().clone()
+    }
+
+    /// This method returns the partial results sent out so far through resp_flush(), in order.
+    pub fn flushed(&self) -> Vec<Vec<u8>> {
+        self.flushed.borrow().clone()
+    }
+
+    /// This method returns the progress markers and states passed to checkpoint() so far, in
+    /// order.
+    pub fn checkpoints(&self) -> Vec<(u64, Vec<u8>)> {
+        self.checkpoints.borrow().clone()
+    }
+
+    /// This method returns the arguments passed to defer() so far, in order.
+    pub fn deferred(&self) -> Vec<Vec<u8>> {
+        self.deferred.borrow().clone()
+    }
+
     /// This method compares the given message with the already stored message.
     pub fn assert_messages<S>(&self, messages: &[S])
     where
@@ -63,7 +149,32 @@ impl DB for MockDB {
             table, key
         ));
 
-        unsafe { Some(ReadBuf::new(Bytes::with_capacity(0))) }
+        self.lookup(table, key)
+            .map(|val| unsafe { ReadBuf::new(Bytes::from(val)) })
+    }
+
+    fn get_slice(&self, table: u64, key: &[u8], offset: u32, length: u32) -> Option<ReadBuf> {
+        self.debug_log(&format!(
+            "Invoked get_slice() on table {} for key {:?}, offset {}, length {}",
+            table, key, offset, length
+        ));
+
+        let val = self.lookup(table, key)?;
+        if offset as usize > val.len() {
+            return None;
+        }
+
+        let range = projection(val.len(), offset, length);
+        unsafe { Some(ReadBuf::new(Bytes::from(&val[range]))) }
+    }
+
+    fn value_len(&self, table: u64, key: &[u8]) -> Option<usize> {
+        self.debug_log(&format!(
+            "Invoked value_len() on table {} for key {:?}",
+            table, key
+        ));
+
+        self.lookup(table, key).map(|val| val.len())
     }
 
     fn multiget(&self, table: u64, key_len: u16, keys: &[u8]) -> Option<MultiReadBuf> {
@@ -72,7 +183,13 @@ impl DB for MockDB {
             table, keys, key_len
         ));
 
-        unsafe { Some(MultiReadBuf::new(Vec::new())) }
+        // Only records that were added through insert() are found; every other key is
+        // reported as missing on it's entry.
+        let vals = keys
+            .chunks(key_len as usize)
+            .map(|key| self.lookup(table, key).map(Bytes::from))
+            .collect();
+        unsafe { Some(MultiReadBuf::with_missing(vals)) }
     }
 
     fn alloc(&self, table: u64, key: &[u8], val_len: u64) -> Option<WriteBuf> {
@@ -81,22 +198,95 @@ impl DB for MockDB {
             table, key, val_len
         ));
 
-        unsafe { Some(WriteBuf::new(table, BytesMut::with_capacity(0))) }
+        if !self.has_table(table) {
+            return None;
+        }
+
+        // Like the real heap, the key goes in front of the value, so that put() can find it.
+        let mut buf = BytesMut::with_capacity(2 + key.len() + val_len as usize);
+        buf.put_u16_le(key.len() as u16);
+        buf.put_slice(key);
+        unsafe { Some(WriteBuf::new(table, buf)) }
     }
 
     fn put(&self, buf: WriteBuf) -> bool {
-        unsafe {
-            self.debug_log(&format!("Invoked put(), buf {:?}", &buf.freeze().1[..]));
+        let (table, buf) = unsafe { buf.freeze() };
+        self.debug_log(&format!("Invoked put(), buf {:?}", &buf[..]));
+
+        if !self.has_table(table) {
+            return false;
         }
 
+        // Records written here are visible to get(), multiget(), and scan().
+        let key_len = (buf[0] as usize) | (buf[1] as usize) << 8;
+        let (key, val) = buf[2..].split_at(key_len);
+        self.insert(table, key, val);
         return true;
     }
 
-    fn del(&self, table: u64, key: &[u8]) {
+    fn put_many(&self, bufs: Vec<WriteBuf>) -> Result<(), PutManyError> {
+        self.debug_log(&format!("Invoked put_many(), {} bufs", bufs.len()));
+
+        // Split every buffer before writing any of them, so that a bad one writes nothing.
+        let mut records = Vec::with_capacity(bufs.len());
+        for (i, buf) in bufs.into_iter().enumerate() {
+            let (table, buf) = unsafe { buf.freeze() };
+            if buf.len() < 2 {
+                return Err(PutManyError::InvalidBuffer(i));
+            }
+
+            let key_len = (buf[0] as usize) | (buf[1] as usize) << 8;
+            if buf.len() < 2 + key_len {
+                return Err(PutManyError::InvalidBuffer(i));
+            }
+            if !self.has_table(table) {
+                return Err(PutManyError::TableNotWritable(i));
+            }
+            records.push((table, buf));
+        }
+
+        for (table, buf) in records.into_iter() {
+            let key_len = (buf[0] as usize) | (buf[1] as usize) << 8;
+            let (key, val) = buf[2..].split_at(key_len);
+            self.insert(table, key, val);
+        }
+        Ok(())
+    }
+
+    fn cas(
+        &self,
+        table_id: u64,
+        key: &[u8],
+        expected: Option<&[u8]>,
+        buf: WriteBuf,
+    ) -> Result<bool, ()> {
+        let (table, buf) = unsafe { buf.freeze() };
+        self.debug_log(&format!("Invoked cas(), buf {:?}", &buf[..]));
+
+        let key_len = (buf[0] as usize) | (buf[1] as usize) << 8;
+        let (k, val) = buf[2..].split_at(key_len);
+        if table != table_id || k != key || !self.has_table(table) {
+            return Err(());
+        }
+
+        if self.lookup(table, key).as_ref().map(|v| &v[..]) != expected {
+            return Ok(false);
+        }
+        self.insert(table, key, val);
+        Ok(true)
+    }
+
+    fn del(&self, table: u64, key: &[u8]) -> bool {
         self.debug_log(&format!(
             "Invoked del() on table {} for key {:?}",
             table, key
         ));
+
+        self.tables
+            .borrow_mut()
+            .get_mut(&table)
+            .and_then(|records| records.remove(key))
+            .is_some()
     }
 
     fn args(&self) -> &[u8] {
@@ -105,8 +295,15 @@ impl DB for MockDB {
         return &(self.args);
     }
 
+    fn next_arg(&self) -> Option<&[u8]> {
+        self.debug_log(&format!("Invoked next_arg()"));
+
+        invoke::next_arg(&self.args, &self.arg_cursor)
+    }
+
     fn resp(&self, data: &[u8]) {
         self.debug_log(&format!("Invoked resp(), data {:?}", data));
+        self.response.borrow_mut().extend_from_slice(data);
     }
 
     fn debug_log(&self, message: &str) {
@@ -120,9 +317,8 @@ impl DB for MockDB {
             table, key
         ));
 
-        (false, false, unsafe {
-            Some(ReadBuf::new(Bytes::with_capacity(0)))
-        })
+        // Like on the server, there is no cache; extensions go on to get().
+        (true, false, None)
     }
 
     fn search_multiget_in_cache(
@@ -136,10 +332,215 @@ impl DB for MockDB {
             table, keys, key_len
         ));
 
-        unsafe { (false, false, Some(MultiReadBuf::new(Vec::new()))) }
+        // Like on the server, there is no cache; extensions go on to multiget().
+        (true, false, None)
     }
 
     fn get_model(&self) -> Option<Arc<Model>> {
         None
     }
+
+    fn scan(&self, table: u64, cursor: u64, visit: &mut FnMut(&[u8], &[u8])) -> Option<u64> {
+        self.debug_log(&format!(
+            "Invoked scan() on table {} from cursor {}",
+            table, cursor
+        ));
+
+        // Scan records in key order so that tests are deterministic. Records are copied out
+        // first so that `visit` is free to call back into the database.
+        let mut records: Vec<(Vec<u8>, Vec<u8>)> = match self.tables.borrow().get(&table) {
+            Some(records) => records
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect(),
+            None => return None,
+        };
+        records.sort();
+
+        let start = cursor as usize;
+        for &(ref k, ref v) in records.iter().skip(start).take(SCAN_SLICE) {
+            visit(k, v);
+        }
+
+        if start + SCAN_SLICE < records.len() {
+            Some(cursor + SCAN_SLICE as u64)
+        } else {
+            None
+        }
+    }
+
+    fn resp_flush(&self) -> bool {
+        self.debug_log(&format!("Invoked resp_flush()"));
+        let response = self.response.replace(Vec::new());
+        self.flushed.borrow_mut().push(response);
+        true
+    }
+
+    fn defer(&self, args: &[u8]) -> bool {
+        self.debug_log(&format!("Invoked defer(), args {:?}", args));
+        self.deferred.borrow_mut().push(args.to_vec());
+        true
+    }
+
+    fn checkpoint(&self, progress: u64, state: &[u8]) -> bool {
+        self.debug_log(&format!(
+            "Invoked checkpoint(), progress {}, state {:?}",
+            progress, state
+        ));
+        self.checkpoints
+            .borrow_mut()
+            .push((progress, state.to_vec()));
+        true
+    }
+
+    fn restore(&self) -> Option<(u64, Vec<u8>)> {
+        self.debug_log(&format!("Invoked restore()"));
+        self.restored.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::invoke::InvokeArgs;
+    use super::*;
+
+    // Writes a record through alloc() and put(), like an extension would.
+    fn write(db: &MockDB, table: u64, key: &[u8], val: &[u8]) -> bool {
+        let mut buf = db.alloc(table, key, val.len() as u64).unwrap();
+        buf.write_slice(val);
+        db.put(buf)
+    }
+
+    // Tests that records written through put() are read back by get() and multiget() on the
+    // same table only, and are gone once deleted.
+    #[test]
+    fn test_mock_round_trip() {
+        let db = MockDB::new();
+        db.create_table(1);
+        db.create_table(2);
+        assert!(write(&db, 1, b"k1", b"one"));
+        assert!(write(&db, 1, b"k2", b"two"));
+        assert!(write(&db, 2, b"k1", b"other"));
+        assert!(write(&db, 1, b"k2", b"again"));
+
+        assert_eq!(b"one", db.get(1, b"k1").unwrap().read());
+        assert_eq!(b"again", db.get(1, b"k2").unwrap().read());
+        assert_eq!(b"other", db.get(2, b"k1").unwrap().read());
+
+        let vals = db.multiget(1, 2, b"k1k3k2").unwrap();
+        assert_eq!(1, vals.missing());
+
+        assert!(db.del(1, b"k1"));
+        assert!(!db.del(1, b"k1"));
+        assert!(db.get(1, b"k1").is_none());
+        assert!(db.get(3, b"k1").is_none());
+        assert_eq!(b"other", db.get(2, b"k1").unwrap().read());
+    }
+
+    // Tests that records can't be written to a table before it is created, and that nothing
+    // is read off one.
+    #[test]
+    fn test_mock_create_table() {
+        let db = MockDB::new();
+        assert!(db.alloc(1, b"key", 1).is_none());
+        assert!(db.get(1, b"key").is_none());
+
+        db.create_table(1);
+        assert!(write(&db, 1, b"key", b"v"));
+
+        // Creating a table that exists keeps it's records.
+        db.create_table(1);
+        assert_eq!(b"v", db.get(1, b"key").unwrap().read());
+
+        // Every buffer is checked before any is written.
+        db.create_table(2);
+        let one = db.alloc(1, b"one", 0).unwrap();
+        let two = db.alloc(2, b"two", 0).unwrap();
+        db.tables.borrow_mut().remove(&2);
+        assert_eq!(
+            Err(PutManyError::TableNotWritable(1)),
+            db.put_many(vec![one, two])
+        );
+        assert!(db.get(1, b"one").is_none());
+    }
+
+    // Tests that get_slice() returns a range of the value, upto it's end for a length of 0, and
+    // None for keys that don't exist and offsets past the end of the value.
+    #[test]
+    fn test_mock_get_slice() {
+        let db = MockDB::new();
+        db.create_table(1);
+        assert!(write(&db, 1, b"key", b"0123456789"));
+
+        assert_eq!(b"2345", db.get_slice(1, b"key", 2, 4).unwrap().read());
+        assert_eq!(b"789", db.get_slice(1, b"key", 7, 0).unwrap().read());
+        assert_eq!(b"89", db.get_slice(1, b"key", 8, 100).unwrap().read());
+        assert!(db.get_slice(1, b"key", 10, 0).unwrap().is_empty());
+        assert!(db.get_slice(1, b"key", 11, 0).is_none());
+        assert!(db.get_slice(1, b"other", 0, 0).is_none());
+        assert!(db.get_slice(2, b"key", 0, 0).is_none());
+    }
+
+    // Tests that value_len() returns the length of the value last written under a key, and None
+    // for keys and tables that don't exist.
+    #[test]
+    fn test_mock_value_len() {
+        let db = MockDB::new();
+        db.create_table(1);
+        assert!(write(&db, 1, b"key", b"0123456789"));
+        assert!(write(&db, 1, b"empty", b""));
+
+        assert_eq!(Some(10), db.value_len(1, b"key"));
+        assert_eq!(Some(0), db.value_len(1, b"empty"));
+        assert_eq!(None, db.value_len(1, b"other"));
+        assert_eq!(None, db.value_len(2, b"key"));
+
+        assert!(write(&db, 1, b"key", b"01"));
+        assert_eq!(Some(2), db.value_len(1, b"key"));
+    }
+
+    // Tests that next_arg() walks arguments built with InvokeArgs field by field, while args()
+    // still returns them raw.
+    #[test]
+    fn test_mock_next_arg() {
+        let mut args = InvokeArgs::new();
+        args.push_u64(1).push(b"key").push(b"");
+        let db = MockDB::with_args(args.as_bytes());
+
+        assert_eq!(Some(&[1, 0, 0, 0, 0, 0, 0, 0][..]), db.next_arg());
+        assert_eq!(Some(&b"key"[..]), db.next_arg());
+        assert_eq!(Some(&b""[..]), db.next_arg());
+        assert_eq!(None, db.next_arg());
+        assert_eq!(args.as_bytes(), db.args());
+
+        // Arguments that aren't fields have none to walk.
+        assert_eq!(None, MockDB::with_args(&[]).next_arg());
+        assert_eq!(None, MockDB::with_args(&[9, 0, 0, 0, 1]).next_arg());
+    }
+
+    // Tests that cas() only writes when the record under the key is the expected one, and that
+    // calls are still recorded for assert_messages().
+    #[test]
+    fn test_mock_cas() {
+        let db = MockDB::new();
+        db.create_table(1);
+        let cas = |expected: Option<&[u8]>, val: &[u8]| {
+            let mut buf = db.alloc(1, b"key", val.len() as u64).unwrap();
+            buf.write_slice(val);
+            db.cas(1, b"key", expected, buf)
+        };
+
+        assert_eq!(Ok(false), cas(Some(b"one"), b"two"));
+        assert_eq!(Ok(true), cas(None, b"one"));
+        assert_eq!(Ok(false), cas(None, b"two"));
+        assert_eq!(Ok(true), cas(Some(b"one"), b"two"));
+        assert_eq!(b"two", db.get(1, b"key").unwrap().read());
+
+        db.clear_messages();
+        assert!(write(&db, 1, b"k", b"v"));
+        db.assert_messages(&[
+            "Invoked alloc(), table 1, key [107], val_len 1",
+            "Invoked put(), buf [1, 0, 107, 118]",
+        ]);
+    }
 }
@@ -13,7 +13,7 @@
  * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
  */
 
-use super::db::DB;
+use super::db::{PutManyError, DB};
 use std::fmt::Debug;
 use std::sync::Arc;
 
@@ -57,7 +57,26 @@ impl DB for NullDB {
         return false;
     }
 
-    fn del(&self, _table: u64, _key: &[u8]) {}
+    fn put_many(&self, bufs: Vec<WriteBuf>) -> Result<(), PutManyError> {
+        match bufs.len() {
+            0 => Ok(()),
+            _ => Err(PutManyError::TableNotWritable(0)),
+        }
+    }
+
+    fn cas(
+        &self,
+        _table_id: u64,
+        _key: &[u8],
+        _expected: Option<&[u8]>,
+        _buf: WriteBuf,
+    ) -> Result<bool, ()> {
+        Err(())
+    }
+
+    fn del(&self, _table: u64, _key: &[u8]) -> bool {
+        false
+    }
 
     fn args(&self) -> &[u8] {
         return &[];
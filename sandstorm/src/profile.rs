@@ -0,0 +1,507 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+extern crate libc;
+
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::mem::{transmute, zeroed};
+use std::ops::Generator;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use byteorder::{ByteOrder, LittleEndian};
+use hashbrown::HashMap;
+use spin::Mutex;
+
+// Offsets and constants from the ELF64 specification. Only little endian objects are read.
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const E_SHOFF: usize = 0x28;
+const E_SHENTSIZE: usize = 0x3a;
+const E_SHNUM: usize = 0x3c;
+const SHDR_SIZE: usize = 64;
+const SYM_SIZE: usize = 24;
+const SHT_SYMTAB: u32 = 2;
+const SHT_DYNSYM: u32 = 11;
+const STT_FUNC: u8 = 2;
+const SHN_UNDEF: u16 = 0;
+
+/// A function symbol read out of a shared object.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ElfSymbol {
+    /// The (possibly mangled) name of the symbol.
+    pub name: String,
+
+    /// The offset of the symbol from the address the object is loaded at.
+    pub offset: u64,
+
+    /// The size of the symbol in bytes.
+    pub size: u64,
+}
+
+// A section header, reduced to the fields needed to find symbol tables.
+struct Section {
+    kind: u32,
+    offset: usize,
+    size: usize,
+    link: usize,
+}
+
+// Reads the section header at index `i`.
+fn section(image: &[u8], i: usize) -> Option<Section> {
+    let shoff = LittleEndian::read_u64(image.get(E_SHOFF..(E_SHOFF + 8))?) as usize;
+    let entsize = LittleEndian::read_u16(image.get(E_SHENTSIZE..(E_SHENTSIZE + 2))?) as usize;
+    if entsize < SHDR_SIZE {
+        return None;
+    }
+
+    let start = shoff.checked_add(i.checked_mul(entsize)?)?;
+    let hdr = image.get(start..start.checked_add(SHDR_SIZE)?)?;
+    Some(Section {
+        kind: LittleEndian::read_u32(&hdr[4..8]),
+        offset: LittleEndian::read_u64(&hdr[24..32]) as usize,
+        size: LittleEndian::read_u64(&hdr[32..40]) as usize,
+        link: LittleEndian::read_u32(&hdr[40..44]) as usize,
+    })
+}
+
+/// Reads the defined function symbols out of an ELF64 shared object. The full symbol table
+/// (.symtab) is used if the object has one, and the dynamic symbol table (.dynsym), which only
+/// holds exported symbols, otherwise.
+///
+/// # Arguments
+///
+/// * `image`: The contents of the shared object.
+///
+/// # Return
+///
+/// The function symbols, sorted by offset, or an error if the object could not be parsed.
+pub fn elf_symbols(image: &[u8]) -> Result<Vec<ElfSymbol>, String> {
+    if image.len() < 0x40 || &image[0..4] != ELF_MAGIC {
+        return Err("not an ELF object".to_string());
+    }
+    if image[4] != ELFCLASS64 || image[5] != ELFDATA2LSB {
+        return Err("not a little endian ELF64 object".to_string());
+    }
+
+    let shnum = LittleEndian::read_u16(&image[E_SHNUM..(E_SHNUM + 2)]) as usize;
+    let sections: Vec<Section> = (0..shnum).filter_map(|i| section(image, i)).collect();
+    if sections.len() != shnum {
+        return Err("truncated section headers".to_string());
+    }
+
+    let table = sections
+        .iter()
+        .find(|s| s.kind == SHT_SYMTAB)
+        .or_else(|| sections.iter().find(|s| s.kind == SHT_DYNSYM))
+        .ok_or_else(|| "no symbol table".to_string())?;
+    let strtab = sections
+        .get(table.link)
+        .ok_or_else(|| "symbol table without a string table".to_string())?;
+
+    let syms = image
+        .get(table.offset..(table.offset + table.size))
+        .ok_or_else(|| "truncated symbol table".to_string())?;
+    let strs = image
+        .get(strtab.offset..(strtab.offset + strtab.size))
+        .ok_or_else(|| "truncated string table".to_string())?;
+
+    let mut symbols = Vec::new();
+    for sym in syms.chunks(SYM_SIZE).filter(|s| s.len() == SYM_SIZE) {
+        let shndx = LittleEndian::read_u16(&sym[6..8]);
+        if sym[4] & 0xf != STT_FUNC || shndx == SHN_UNDEF {
+            continue;
+        }
+
+        let name = LittleEndian::read_u32(&sym[0..4]) as usize;
+        let name = match strs.get(name..) {
+            Some(s) => s.split(|b| *b == 0).next().unwrap_or(&[]),
+            None => continue,
+        };
+        if name.is_empty() {
+            continue;
+        }
+
+        symbols.push(ElfSymbol {
+            name: String::from_utf8_lossy(name).into_owned(),
+            offset: LittleEndian::read_u64(&sym[8..16]),
+            size: LittleEndian::read_u64(&sym[16..24]),
+        });
+    }
+
+    symbols.sort_by(|a, b| a.offset.cmp(&b.offset));
+    symbols.dedup_by(|a, b| a.offset == b.offset);
+    Ok(symbols)
+}
+
+/// Returns the address a shared object was loaded at, given the address of anything inside it
+/// (ex: a symbol looked up with dlsym()).
+pub fn load_address(addr: usize) -> Option<u64> {
+    unsafe {
+        let mut info: libc::Dl_info = zeroed();
+        match libc::dladdr(addr as *const libc::c_void, &mut info) {
+            0 => None,
+            _ => Some(info.dli_fbase as u64),
+        }
+    }
+}
+
+/// Returns the address of the function a boxed generator is resumed through. This is the
+/// generator's own code, and so lies inside the shared object that created it.
+///
+/// Reads the generator's vtable, which starts with the destructor, size and alignment of the
+/// generator, followed by it's only method, resume().
+pub fn resume_address(gen: &Generator<Yield = u64, Return = u64>) -> usize {
+    let (_, vtable): (usize, *const usize) = unsafe { transmute(gen) };
+    unsafe { *vtable.offset(3) }
+}
+
+/// The function symbols of a loaded shared object, at the addresses they were loaded at.
+pub struct SymbolTable {
+    // The address the object was loaded at.
+    base: u64,
+
+    // The symbols, sorted by offset.
+    symbols: Vec<ElfSymbol>,
+}
+
+impl SymbolTable {
+    /// Reads the symbol table of a shared object that was loaded at `base`.
+    ///
+    /// # Arguments
+    ///
+    /// * `path`: The path of the shared object.
+    /// * `base`: The address it was loaded at. Refer to load_address().
+    pub fn read(path: &Path, base: u64) -> Result<SymbolTable, String> {
+        let mut image = Vec::new();
+        File::open(path)
+            .and_then(|mut f| f.read_to_end(&mut image))
+            .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+        Ok(SymbolTable::new(base, elf_symbols(&image)?))
+    }
+
+    /// Creates a table out of symbols sorted by offset.
+    pub fn new(base: u64, symbols: Vec<ElfSymbol>) -> SymbolTable {
+        SymbolTable {
+            base: base,
+            symbols: symbols,
+        }
+    }
+
+    /// Returns the address the object was loaded at.
+    pub fn base(&self) -> u64 {
+        self.base
+    }
+
+    /// Returns the symbols in the table, sorted by offset.
+    pub fn symbols(&self) -> &[ElfSymbol] {
+        &self.symbols
+    }
+
+    /// Returns the index of the symbol an address falls inside, if any.
+    pub fn lookup(&self, addr: u64) -> Option<usize> {
+        let offset = addr.checked_sub(self.base)?;
+        let i = match self.symbols.binary_search_by(|s| s.offset.cmp(&offset)) {
+            Ok(i) => i,
+            Err(0) => return None,
+            Err(i) => i - 1,
+        };
+
+        let sym = &self.symbols[i];
+        match offset < sym.offset + sym.size.max(1) {
+            true => Some(i),
+            false => None,
+        }
+    }
+
+    // Appends the table to a perf map, one "START SIZE NAME" line per symbol.
+    fn write(&self, out: &mut String) {
+        for sym in self.symbols.iter().filter(|s| s.size > 0) {
+            out.push_str(&format!(
+                "{:x} {:x} {}\n",
+                self.base + sym.offset,
+                sym.size,
+                sym.name
+            ));
+        }
+    }
+}
+
+/// A perf map file (/tmp/perf-<pid>.map) naming the functions of every loaded extension, so that
+/// perf and flame graphs resolve frames inside extensions by name. The file is rewritten
+/// whenever an object is added or removed.
+pub struct PerfMap {
+    // The path of the map file.
+    path: PathBuf,
+
+    // Every object in the map keyed by the address it was loaded at, along with the number of
+    // extensions using it. The same object loaded twice is mapped at the same address.
+    objects: Mutex<HashMap<u64, (usize, Arc<SymbolTable>)>>,
+}
+
+impl PerfMap {
+    /// Creates an empty map at the path perf looks for it at.
+    pub fn new() -> PerfMap {
+        PerfMap::with_path(PathBuf::from(format!("/tmp/perf-{}.map", process::id())))
+    }
+
+    /// Creates an empty map at a given path.
+    pub fn with_path(path: PathBuf) -> PerfMap {
+        PerfMap {
+            path: path,
+            objects: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the path of the map file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Adds a loaded object to the map, and rewrites the file.
+    pub fn add(&self, table: Arc<SymbolTable>) {
+        let mut objects = self.objects.lock();
+        objects
+            .entry(table.base())
+            .or_insert_with(|| (0, Arc::clone(&table)))
+            .0 += 1;
+        self.flush(&objects);
+    }
+
+    /// Removes an object added with add(), and rewrites the file once no extension uses it.
+    pub fn remove(&self, base: u64) {
+        let mut objects = self.objects.lock();
+        let unused = match objects.get_mut(&base) {
+            Some(entry) => {
+                entry.0 -= 1;
+                entry.0 == 0
+            }
+            None => false,
+        };
+
+        if unused {
+            objects.remove(&base);
+            self.flush(&objects);
+        }
+    }
+
+    // Writes out the map. A temporary file is renamed over the map, so that perf never reads a
+    // partially written one.
+    fn flush(&self, objects: &HashMap<u64, (usize, Arc<SymbolTable>)>) {
+        let mut bases: Vec<&u64> = objects.keys().collect();
+        bases.sort();
+
+        let mut out = String::new();
+        for base in bases {
+            objects[base].1.write(&mut out);
+        }
+
+        let tmp = self.path.with_extension("map.tmp");
+        let res = File::create(&tmp)
+            .and_then(|mut f| f.write_all(out.as_bytes()))
+            .and_then(|_| fs::rename(&tmp, &self.path));
+        if let Err(e) = res {
+            warn!("Failed to write {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// The profile of an extension: it's symbols, and the cycles spent inside each one. Cycles are
+/// bucketed by sampling the extension's address every time it yields or completes.
+pub struct Profile {
+    // The map the extension's symbols were added to. The symbols are removed when the profile
+    // is dropped, i.e. when the extension is unloaded.
+    map: Arc<PerfMap>,
+
+    // The extension's symbols.
+    table: Arc<SymbolTable>,
+
+    // Cycles per symbol, indexed like the table. The last bucket holds cycles at addresses that
+    // matched no symbol.
+    cycles: Vec<AtomicUsize>,
+}
+
+impl Profile {
+    /// Profiles a shared object that was loaded at `base`, adding it to a perf map.
+    ///
+    /// # Arguments
+    ///
+    /// * `map`:  The perf map to add the object's symbols to.
+    /// * `path`: The path of the shared object.
+    /// * `base`: The address it was loaded at.
+    pub fn new(map: &Arc<PerfMap>, path: &Path, base: u64) -> Result<Profile, String> {
+        let table = Arc::new(SymbolTable::read(path, base)?);
+        map.add(Arc::clone(&table));
+        Ok(Profile {
+            map: Arc::clone(map),
+            cycles: (0..(table.symbols().len() + 1))
+                .map(|_| AtomicUsize::new(0))
+                .collect(),
+            table: table,
+        })
+    }
+
+    /// Returns the extension's symbols.
+    pub fn table(&self) -> &SymbolTable {
+        &self.table
+    }
+
+    /// Counts cycles spent by the extension against the symbol an address falls inside.
+    ///
+    /// # Arguments
+    ///
+    /// * `addr`:   The sampled address.
+    /// * `cycles`: The number of cycles spent.
+    pub fn sample(&self, addr: usize, cycles: u64) {
+        let i = self
+            .table
+            .lookup(addr as u64)
+            .unwrap_or(self.cycles.len() - 1);
+        self.cycles[i].fetch_add(cycles as usize, Ordering::Relaxed);
+    }
+
+    /// Returns the symbols the most cycles were spent in, most first. Cycles at addresses that
+    /// matched no symbol are reported under "[unknown]".
+    ///
+    /// # Arguments
+    ///
+    /// * `n`: The maximum number of symbols to return.
+    pub fn top(&self, n: usize) -> Vec<(String, u64)> {
+        let symbols = self.table.symbols();
+        let mut top: Vec<(String, u64)> = self
+            .cycles
+            .iter()
+            .enumerate()
+            .map(|(i, c)| (i, c.load(Ordering::Relaxed) as u64))
+            .filter(|&(_, c)| c > 0)
+            .map(|(i, c)| match symbols.get(i) {
+                Some(sym) => (sym.name.clone(), c),
+                None => ("[unknown]".to_string(), c),
+            }).collect();
+
+        top.sort_by(|a, b| b.1.cmp(&a.1));
+        top.truncate(n);
+        top
+    }
+}
+
+impl Drop for Profile {
+    fn drop(&mut self) {
+        self.map.remove(self.table.base());
+    }
+}
+
+// This module contains unit tests for the symbol reader and lookups.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sym(name: &str, offset: u64, size: u64) -> ElfSymbol {
+        ElfSymbol {
+            name: name.to_string(),
+            offset: offset,
+            size: size,
+        }
+    }
+
+    // Tests that addresses resolve to the symbol they fall inside, and nothing else.
+    #[test]
+    fn test_symbol_lookup() {
+        let table = SymbolTable::new(
+            0x1000,
+            vec![sym("a", 0x10, 0x10), sym("b", 0x20, 0x8), sym("c", 0x40, 0)],
+        );
+        assert_eq!(None, table.lookup(0x800));
+        assert_eq!(None, table.lookup(0x1008));
+        assert_eq!(Some(0), table.lookup(0x1010));
+        assert_eq!(Some(0), table.lookup(0x101f));
+        assert_eq!(Some(1), table.lookup(0x1020));
+        assert_eq!(None, table.lookup(0x1028));
+        assert_eq!(Some(2), table.lookup(0x1040));
+        assert_eq!(None, table.lookup(0x1041));
+    }
+
+    // Tests that anything but an ELF64 object is rejected.
+    #[test]
+    fn test_elf_symbols_invalid() {
+        assert!(elf_symbols(b"not an object").is_err());
+        let mut image = vec![0u8; 0x40];
+        image[0..4].copy_from_slice(ELF_MAGIC);
+        image[4] = 1;
+        assert!(elf_symbols(&image).is_err());
+        image[4] = ELFCLASS64;
+        image[5] = ELFDATA2LSB;
+        assert!(elf_symbols(&image).is_err());
+    }
+
+    // Tests that the symbols of the test extension are read, and include it's entry point.
+    #[test]
+    fn test_elf_symbols_fixture() {
+        let mut image = Vec::new();
+        File::open("../ext/test/target/release/libtest.so")
+            .and_then(|mut f| f.read_to_end(&mut image))
+            .unwrap();
+        let symbols = elf_symbols(&image).unwrap();
+        let init = symbols.iter().find(|s| s.name == "init").unwrap();
+        assert!(init.offset > 0 && init.size > 0);
+    }
+
+    // Tests that cycles are reported against the symbols they were sampled in, most first.
+    #[test]
+    fn test_profile_top() {
+        let path = ::std::env::temp_dir().join(format!("perf-test-{}.map", process::id()));
+        let map = Arc::new(PerfMap::with_path(path.clone()));
+        let table = Arc::new(SymbolTable::new(
+            0x1000,
+            vec![sym("a", 0x10, 0x10), sym("b", 0x20, 0x8)],
+        ));
+        map.add(Arc::clone(&table));
+
+        let profile = Profile {
+            map: Arc::clone(&map),
+            cycles: (0..3).map(|_| AtomicUsize::new(0)).collect(),
+            table: table,
+        };
+        profile.sample(0x1010, 5);
+        profile.sample(0x1024, 20);
+        profile.sample(0x1014, 10);
+        profile.sample(0x9000, 1);
+
+        assert_eq!(
+            vec![("b".to_string(), 20), ("a".to_string(), 15)],
+            profile.top(2)
+        );
+        assert_eq!(("[unknown]".to_string(), 1), profile.top(3)[2]);
+
+        let mut contents = String::new();
+        File::open(&path)
+            .and_then(|mut f| f.read_to_string(&mut contents))
+            .unwrap();
+        assert_eq!("1010 10 a\n1020 8 b\n", contents);
+
+        // Dropping the profile removes the symbols from the map.
+        drop(profile);
+        contents.clear();
+        File::open(&path)
+            .and_then(|mut f| f.read_to_string(&mut contents))
+            .unwrap();
+        assert!(contents.is_empty());
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,187 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! The arguments to the put extension, shared by the extension and the clients that invoke it.
+//! The arguments are the 8 byte table identifier, the 2 byte length of the key, the key, and the
+//! value, which runs to the end of the arguments. Integers are little-endian.
+//!
+//! The extension writes out SUCCESS once the object is written, and fails with
+//! ExtError::AllocationFailed if the database refused to allocate it; the object is over the
+//! server's limits, or the invocation is over it's allocation quota. If the table is read only,
+//! the server fails the whole invocation with StatusReadOnlyTable instead.
+//!
+//! Puts are never pushed back. The extension runs to completion without yielding, and the
+//! server only pushes back invocations that have yielded, so the object is written exactly once,
+//! on the server. A client that did re-execute a put would write nothing, since writes on the
+//! client side are dropped. A retransmitted put is answered off the replay table if the server
+//! replays invoke(), and is written again otherwise, leaving the last value written in place.
+
+use std::ops::Range;
+use std::{u16, u32};
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use super::schema::Field;
+
+/// The name the put extension is invoked by.
+pub const NAME: &[u8] = b"put";
+
+/// The length of the header on the arguments: the 8 byte table identifier and the 2 byte
+/// length of the key.
+pub const HEADER_LEN: usize = 10;
+
+/// The layout of the arguments, as declared by the extension. Empty values are refused, like
+/// they are by a native put().
+pub const FIELDS: [Field; 3] = [
+    Field::Table,
+    Field::Key(1, u16::MAX),
+    Field::Blob(1, u32::MAX),
+];
+
+/// What the extension writes out once the object is written.
+pub const SUCCESS: &[u8] = b"Success";
+
+/// Returns where the key is on the arguments.
+///
+/// # Arguments
+///
+/// * `key_len`: The length of the key.
+pub fn key_range(key_len: usize) -> Range<usize> {
+    HEADER_LEN..HEADER_LEN + key_len
+}
+
+/// Returns where the value is on the arguments.
+///
+/// # Arguments
+///
+/// * `key_len`: The length of the key.
+/// * `val_len`: The length of the value.
+pub fn val_range(key_len: usize, val_len: usize) -> Range<usize> {
+    HEADER_LEN + key_len..HEADER_LEN + key_len + val_len
+}
+
+/// Encodes the arguments to a put.
+///
+/// # Arguments
+///
+/// * `table_id`: The table to write the object to.
+/// * `key`:      The object's key. Must be shorter than 64 KB.
+/// * `val`:      The object's value.
+pub fn encode_args(table_id: u64, key: &[u8], val: &[u8]) -> Vec<u8> {
+    let mut buf = vec![0; HEADER_LEN];
+    LittleEndian::write_u64(&mut buf[0..8], table_id);
+    LittleEndian::write_u16(&mut buf[8..10], key.len() as u16);
+    buf.extend_from_slice(key);
+    buf.extend_from_slice(val);
+    buf
+}
+
+/// Returns the payload of an invoke() of the put extension, the name followed by the arguments,
+/// with the key and value zeroed out. Clients build one up front, and write each request's key
+/// and value into it with fill().
+///
+/// # Arguments
+///
+/// * `table_id`: The table to write objects to.
+/// * `key_len`:  The length of every key written. Must be shorter than 64 KB.
+/// * `val_len`:  The length of every value written.
+pub fn payload(table_id: u64, key_len: usize, val_len: usize) -> Vec<u8> {
+    let mut buf = NAME.to_vec();
+    buf.extend_from_slice(&encode_args(table_id, &vec![0; key_len], &vec![0; val_len]));
+    buf
+}
+
+/// Writes a key and value into a payload built by payload().
+///
+/// # Arguments
+///
+/// * `payload`: The payload. Must have been built for keys and values of these lengths.
+/// * `key`:     The object's key.
+/// * `val`:     The object's value.
+///
+/// # Panic
+///
+/// Panics if the payload was built for keys or values of other lengths.
+pub fn fill(payload: &mut [u8], key: &[u8], val: &[u8]) {
+    let args = &mut payload[NAME.len()..];
+    assert_eq!(LittleEndian::read_u16(&args[8..10]) as usize, key.len());
+    args[key_range(key.len())].copy_from_slice(key);
+    args[val_range(key.len(), val.len())].copy_from_slice(val);
+}
+
+/// Decodes the arguments to a put.
+///
+/// # Return
+///
+/// The table identifier, key and value, or None if the arguments are malformed.
+pub fn decode_args(buf: &[u8]) -> Option<(u64, &[u8], &[u8])> {
+    if buf.len() < HEADER_LEN {
+        return None;
+    }
+
+    let table_id = LittleEndian::read_u64(&buf[0..8]);
+    let key_len = LittleEndian::read_u16(&buf[8..10]) as usize;
+    let rest = &buf[HEADER_LEN..];
+    if rest.len() < key_len {
+        return None;
+    }
+
+    let (key, val) = rest.split_at(key_len);
+    Some((table_id, key, val))
+}
+
+// This module contains unit tests for the put argument encoding.
+#[cfg(test)]
+mod tests {
+    use super::super::schema::Schema;
+    use super::*;
+
+    // Tests that arguments round trip for several key and value lengths, and fit the layout the
+    // extension declares.
+    #[test]
+    fn test_put_args() {
+        let schema = Schema::new(FIELDS.to_vec()).unwrap();
+        for &(key_len, val_len) in [(1, 1), (4, 100), (30, 1), (250, 1024)].iter() {
+            let key: Vec<u8> = (0..key_len).map(|i| i as u8).collect();
+            let val: Vec<u8> = (0..val_len).map(|i| !(i as u8)).collect();
+
+            let args = encode_args(7, &key, &val);
+            assert_eq!(HEADER_LEN + key_len + val_len, args.len());
+            assert_eq!(Some((7, &key[..], &val[..])), decode_args(&args));
+            assert_eq!(Ok(()), schema.check(&args));
+
+            // A payload filled in place is exactly the name followed by the arguments.
+            let mut buf = payload(7, key_len, val_len);
+            fill(&mut buf, &key, &val);
+            assert_eq!([NAME, &args[..]].concat(), buf);
+            assert_eq!(&key[..], &args[key_range(key_len)]);
+            assert_eq!(&val[..], &args[val_range(key_len, val_len)]);
+        }
+
+        // The key must fit, and neither it nor the value can be empty.
+        let args = encode_args(7, b"key", b"value");
+        assert_eq!(None, decode_args(&args[..HEADER_LEN + 2]));
+        assert_eq!(None, decode_args(&args[..HEADER_LEN - 1]));
+        assert!(schema.check(&encode_args(7, b"key", b"")).is_err());
+        assert!(schema.check(&encode_args(7, b"", b"value")).is_err());
+    }
+
+    // Tests that a payload can't be filled with a key of another length.
+    #[test]
+    #[should_panic]
+    fn test_put_fill_key_len() {
+        fill(&mut payload(7, 4, 8), b"abc", &[0; 8]);
+    }
+}
@@ -0,0 +1,128 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! The arguments the database passes to an extension invoked through a routed invoke(). The
+//! server picks the extension off the tenant's routing rules by the longest prefix of the key,
+//! and passes it the key and the client's arguments, along with the length of the prefix that
+//! matched so that the extension can strip the namespace off the key if it wants to.
+
+use byteorder::{ByteOrder, LittleEndian};
+
+/// The length of the header on a routed extension's arguments: the 2 byte length of the
+/// prefix that matched, the 8 byte table identifier, and the 2 byte length of the key, all
+/// little-endian.
+pub const ROUTE_HEADER_LEN: usize = 12;
+
+/// The arguments to an extension invoked through a routed invoke().
+#[derive(Debug, PartialEq)]
+pub struct RoutedArgs<'a> {
+    /// The length of the prefix of the key that picked the extension.
+    pub prefix_length: usize,
+
+    /// The table the client asked the extension to operate on.
+    pub table_id: u64,
+
+    /// The key, with the prefix still on it.
+    pub key: &'a [u8],
+
+    /// The arguments sent by the client.
+    pub args: &'a [u8],
+}
+
+/// Writes the header on a routed extension's arguments. Used by the server to lay out a routed
+/// invoke() in place, and by encode_args().
+///
+/// # Arguments
+///
+/// * `buf`:           The buffer to write to. Must be atleast ROUTE_HEADER_LEN bytes long.
+/// * `prefix_length`: The length of the prefix that matched.
+/// * `table_id`:      The table identifier on the request.
+/// * `key_length`:    The length of the key on the request.
+pub fn write_header(buf: &mut [u8], prefix_length: u16, table_id: u64, key_length: u16) {
+    LittleEndian::write_u16(&mut buf[0..2], prefix_length);
+    LittleEndian::write_u64(&mut buf[2..10], table_id);
+    LittleEndian::write_u16(&mut buf[10..12], key_length);
+}
+
+/// Encodes the arguments a routed extension receives. A direct invoke() of the extension with
+/// these arguments runs exactly like the routed one.
+///
+/// # Arguments
+///
+/// * `prefix_length`: The length of the prefix that matched.
+/// * `table_id`:      The table identifier on the request.
+/// * `key`:           The key on the request.
+/// * `args`:          The arguments on the request.
+pub fn encode_args(prefix_length: u16, table_id: u64, key: &[u8], args: &[u8]) -> Vec<u8> {
+    let mut buf = vec![0; ROUTE_HEADER_LEN];
+    write_header(&mut buf, prefix_length, table_id, key.len() as u16);
+    buf.extend_from_slice(key);
+    buf.extend_from_slice(args);
+    buf
+}
+
+/// Decodes the arguments to a routed extension.
+///
+/// # Return
+///
+/// The prefix length, table identifier, key and arguments, or None if the arguments are
+/// malformed.
+pub fn decode_args(buf: &[u8]) -> Option<RoutedArgs> {
+    if buf.len() < ROUTE_HEADER_LEN {
+        return None;
+    }
+
+    let prefix_length = LittleEndian::read_u16(&buf[0..2]) as usize;
+    let table_id = LittleEndian::read_u64(&buf[2..10]);
+    let key_length = LittleEndian::read_u16(&buf[10..12]) as usize;
+    let rest = &buf[ROUTE_HEADER_LEN..];
+    if rest.len() < key_length || key_length < prefix_length {
+        return None;
+    }
+
+    let (key, args) = rest.split_at(key_length);
+    Some(RoutedArgs {
+        prefix_length: prefix_length,
+        table_id: table_id,
+        key: key,
+        args: args,
+    })
+}
+
+// This module contains unit tests for the routed argument encoding.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Tests that arguments round trip, and that malformed ones are refused.
+    #[test]
+    fn test_route_args() {
+        let buf = encode_args(4, 7, b"usr:alice", b"args");
+        assert_eq!(
+            Some(RoutedArgs {
+                prefix_length: 4,
+                table_id: 7,
+                key: b"usr:alice",
+                args: b"args",
+            }),
+            decode_args(&buf)
+        );
+
+        // The prefix can't be longer than the key, and the key must fit.
+        assert_eq!(None, decode_args(&encode_args(5, 7, b"usr:", b"")));
+        assert_eq!(None, decode_args(&buf[..ROUTE_HEADER_LEN + 3]));
+        assert_eq!(None, decode_args(&buf[..ROUTE_HEADER_LEN - 1]));
+    }
+}
@@ -0,0 +1,525 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! The layout an extension expects its arguments in. An extension can declare one through
+//! declare_extension!, or by exporting an `args_schema` function that returns a descriptor
+//! encoded with encode(). The server refuses invocations whose arguments don't fit the declared
+//! layout before running the extension, and lists the descriptor so that clients can check the
+//! arguments they are about to send. Extensions that declare nothing take any arguments.
+//!
+//! A descriptor is the 1 byte SCHEMA_VERSION, the 1 byte number of fields, and each field as a
+//! 1 byte tag followed by its bounds, if it has any. Integers are little-endian:
+//!
+//! | Field       | Tag  | Bounds               | Arguments                                    |
+//! |-------------|------|----------------------|----------------------------------------------|
+//! | `Table`     | 0x01 |                      | an 8 byte table identifier                   |
+//! | `U8`..`U64` | 0x02 |                      | a 1, 2, 4 or 8 byte integer (tags 0x02-0x05) |
+//! | `Bytes`     | 0x06 | 2 byte length        | exactly that many bytes                      |
+//! | `Key`       | 0x07 | 2 byte min, max      | a 2 byte length, and that many bytes         |
+//! | `Blob`      | 0x08 | 4 byte min, max      | every byte that is left; only ever last      |
+//!
+//! Arguments that run past the last field only fit a layout that ends with a `Blob`.
+
+use std::fmt;
+
+use byteorder::{ByteOrder, LittleEndian};
+
+/// The version of the descriptor format. Descriptors of any other version are refused.
+pub const SCHEMA_VERSION: u8 = 1;
+
+/// The most fields a layout can have.
+pub const MAX_FIELDS: usize = 32;
+
+/// A field of an extension's arguments.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Field {
+    /// The 8 byte identifier of a table.
+    Table,
+
+    /// A byte.
+    U8,
+
+    /// A 2 byte integer.
+    U16,
+
+    /// A 4 byte integer.
+    U32,
+
+    /// An 8 byte integer.
+    U64,
+
+    /// Exactly this many bytes.
+    Bytes(u16),
+
+    /// A 2 byte length followed by that many bytes, with a length between the two bounds.
+    Key(u16, u16),
+
+    /// The rest of the arguments, with a length between the two bounds. Only ever last.
+    Blob(u32, u32),
+}
+
+impl Field {
+    // Returns the tag the field is encoded with.
+    fn tag(&self) -> u8 {
+        match *self {
+            Field::Table => 0x01,
+            Field::U8 => 0x02,
+            Field::U16 => 0x03,
+            Field::U32 => 0x04,
+            Field::U64 => 0x05,
+            Field::Bytes(_) => 0x06,
+            Field::Key(..) => 0x07,
+            Field::Blob(..) => 0x08,
+        }
+    }
+
+    // Returns the number of bytes the field takes up if it has a fixed length.
+    fn fixed_len(&self) -> Option<usize> {
+        match *self {
+            Field::Table | Field::U64 => Some(8),
+            Field::U8 => Some(1),
+            Field::U16 => Some(2),
+            Field::U32 => Some(4),
+            Field::Bytes(len) => Some(len as usize),
+            Field::Key(..) | Field::Blob(..) => None,
+        }
+    }
+}
+
+/// The reason a descriptor could not be parsed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SchemaError {
+    /// The descriptor ends in the middle of its header or a field.
+    Truncated,
+
+    /// The descriptor is of a version other than SCHEMA_VERSION.
+    Version(u8),
+
+    /// The layout has no fields, or more than MAX_FIELDS.
+    FieldCount(usize),
+
+    /// The field at this index has a tag that isn't known.
+    UnknownField(usize, u8),
+
+    /// The field at this index is a Blob, but isn't the last field.
+    BlobNotLast(usize),
+
+    /// The field at this index has a lower bound above its upper bound.
+    EmptyRange(usize),
+
+    /// The descriptor runs past its last field.
+    TrailingBytes,
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SchemaError::Truncated => write!(f, "descriptor is truncated"),
+            SchemaError::Version(v) => write!(
+                f,
+                "descriptor is version {}, expected {}",
+                v, SCHEMA_VERSION
+            ),
+            SchemaError::FieldCount(n) => write!(
+                f,
+                "descriptor has {} fields, expected 1 to {}",
+                n, MAX_FIELDS
+            ),
+            SchemaError::UnknownField(i, tag) => {
+                write!(f, "field {} has unknown tag {:#04x}", i, tag)
+            }
+            SchemaError::BlobNotLast(i) => write!(f, "field {} is a blob but not last", i),
+            SchemaError::EmptyRange(i) => write!(f, "field {} has min above max", i),
+            SchemaError::TrailingBytes => write!(f, "descriptor runs past its last field"),
+        }
+    }
+}
+
+/// The reason arguments don't fit a layout.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ArgsError {
+    /// The arguments end before the field at this index does.
+    Short(usize),
+
+    /// The field at this index is a Key or Blob of this length, which is out of its bounds.
+    Length(usize, usize),
+
+    /// The arguments run this many bytes past the last field.
+    Trailing(usize),
+}
+
+impl fmt::Display for ArgsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ArgsError::Short(i) => write!(f, "arguments end before field {}", i),
+            ArgsError::Length(i, len) => write!(f, "field {} is {} bytes, out of bounds", i, len),
+            ArgsError::Trailing(n) => write!(f, "arguments run {} bytes past the last field", n),
+        }
+    }
+}
+
+/// The layout of an extension's arguments. Refer to the module documentation for the format it
+/// is encoded in.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Schema {
+    fields: Vec<Field>,
+}
+
+impl Schema {
+    /// Returns a layout of the fields, or why they don't make up one.
+    pub fn new(fields: Vec<Field>) -> Result<Schema, SchemaError> {
+        if fields.is_empty() || fields.len() > MAX_FIELDS {
+            return Err(SchemaError::FieldCount(fields.len()));
+        }
+
+        for (i, field) in fields.iter().enumerate() {
+            match *field {
+                Field::Key(min, max) if min > max => return Err(SchemaError::EmptyRange(i)),
+                Field::Blob(min, max) if min > max => return Err(SchemaError::EmptyRange(i)),
+                Field::Blob(..) if i + 1 < fields.len() => return Err(SchemaError::BlobNotLast(i)),
+                _ => {}
+            }
+        }
+
+        Ok(Schema { fields: fields })
+    }
+
+    /// Parses a descriptor, refusing anything that isn't exactly one well formed layout.
+    pub fn parse(buf: &[u8]) -> Result<Schema, SchemaError> {
+        if buf.len() < 2 {
+            return Err(SchemaError::Truncated);
+        }
+        if buf[0] != SCHEMA_VERSION {
+            return Err(SchemaError::Version(buf[0]));
+        }
+
+        let num = buf[1] as usize;
+        if num == 0 || num > MAX_FIELDS {
+            return Err(SchemaError::FieldCount(num));
+        }
+
+        let mut fields = Vec::with_capacity(num);
+        let mut rest = &buf[2..];
+        for i in 0..num {
+            let tag = *rest.first().ok_or(SchemaError::Truncated)?;
+            let bounds = match tag {
+                0x06 => 2,
+                0x07 => 4,
+                0x08 => 8,
+                _ => 0,
+            };
+            if rest.len() < 1 + bounds {
+                return Err(SchemaError::Truncated);
+            }
+
+            let b = &rest[1..1 + bounds];
+            fields.push(match tag {
+                0x01 => Field::Table,
+                0x02 => Field::U8,
+                0x03 => Field::U16,
+                0x04 => Field::U32,
+                0x05 => Field::U64,
+                0x06 => Field::Bytes(LittleEndian::read_u16(b)),
+                0x07 => Field::Key(LittleEndian::read_u16(b), LittleEndian::read_u16(&b[2..])),
+                0x08 => Field::Blob(LittleEndian::read_u32(b), LittleEndian::read_u32(&b[4..])),
+                _ => return Err(SchemaError::UnknownField(i, tag)),
+            });
+            rest = &rest[1 + bounds..];
+        }
+
+        if !rest.is_empty() {
+            return Err(SchemaError::TrailingBytes);
+        }
+        Schema::new(fields)
+    }
+
+    /// Returns the fields of the layout, in order.
+    pub fn fields(&self) -> &[Field] {
+        &self.fields
+    }
+
+    /// Returns the descriptor of the layout. Refer to encode().
+    pub fn encode(&self) -> Vec<u8> {
+        encode(&self.fields)
+    }
+
+    /// Returns the fewest bytes arguments that fit the layout can have.
+    pub fn min_len(&self) -> usize {
+        self.fields
+            .iter()
+            .map(|field| match *field {
+                Field::Key(min, _) => 2 + min as usize,
+                Field::Blob(min, _) => min as usize,
+                _ => field.fixed_len().unwrap_or(0),
+            })
+            .sum()
+    }
+
+    /// Checks that arguments fit the layout.
+    ///
+    /// # Arguments
+    ///
+    /// * `args`: The arguments, as the extension would receive them.
+    ///
+    /// # Return
+    ///
+    /// The first field the arguments don't fit, if any.
+    pub fn check(&self, args: &[u8]) -> Result<(), ArgsError> {
+        let mut rest = args;
+        for (i, field) in self.fields.iter().enumerate() {
+            let len = match *field {
+                Field::Key(min, max) => {
+                    if rest.len() < 2 {
+                        return Err(ArgsError::Short(i));
+                    }
+                    let len = LittleEndian::read_u16(rest);
+                    if len < min || len > max {
+                        return Err(ArgsError::Length(i, len as usize));
+                    }
+                    2 + len as usize
+                }
+
+                Field::Blob(min, max) => {
+                    let len = rest.len();
+                    if len < min as usize || len > max as usize {
+                        return Err(ArgsError::Length(i, len));
+                    }
+                    len
+                }
+
+                _ => field.fixed_len().unwrap_or(0),
+            };
+
+            if rest.len() < len {
+                return Err(ArgsError::Short(i));
+            }
+            rest = &rest[len..];
+        }
+
+        match rest.len() {
+            0 => Ok(()),
+            n => Err(ArgsError::Trailing(n)),
+        }
+    }
+}
+
+/// Encodes a layout into a descriptor, without checking that it is well formed. Used by the
+/// `args_schema` function declare_extension! generates; the server parses the descriptor when it
+/// loads the extension, and refuses to load it if the layout is malformed.
+///
+/// # Arguments
+///
+/// * `fields`: The fields of the layout, in order.
+pub fn encode(fields: &[Field]) -> Vec<u8> {
+    let mut buf = vec![SCHEMA_VERSION, fields.len() as u8];
+    for field in fields.iter() {
+        buf.push(field.tag());
+        match *field {
+            Field::Bytes(len) => {
+                let mut b = [0; 2];
+                LittleEndian::write_u16(&mut b, len);
+                buf.extend_from_slice(&b);
+            }
+
+            Field::Key(min, max) => {
+                let mut b = [0; 4];
+                LittleEndian::write_u16(&mut b[0..2], min);
+                LittleEndian::write_u16(&mut b[2..4], max);
+                buf.extend_from_slice(&b);
+            }
+
+            Field::Blob(min, max) => {
+                let mut b = [0; 8];
+                LittleEndian::write_u32(&mut b[0..4], min);
+                LittleEndian::write_u32(&mut b[4..8], max);
+                buf.extend_from_slice(&b);
+            }
+
+            _ => {}
+        }
+    }
+
+    buf
+}
+
+// This module contains unit tests for argument schemas.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::u32;
+
+    // The layout of a put(): a table, a key, and a value of any length.
+    fn put() -> Schema {
+        Schema::new(vec![
+            Field::Table,
+            Field::Key(1, 64),
+            Field::Blob(0, u32::MAX),
+        ])
+        .unwrap()
+    }
+
+    fn args(table: u64, key: &[u8], rest: &[u8]) -> Vec<u8> {
+        let mut args = vec![0; 10];
+        LittleEndian::write_u64(&mut args[0..8], table);
+        LittleEndian::write_u16(&mut args[8..10], key.len() as u16);
+        args.extend_from_slice(key);
+        args.extend_from_slice(rest);
+        args
+    }
+
+    // Tests that a descriptor of every kind of field parses back into the layout it encodes.
+    #[test]
+    fn test_schema_roundtrip() {
+        let fields = vec![
+            Field::Table,
+            Field::U8,
+            Field::U16,
+            Field::U32,
+            Field::U64,
+            Field::Bytes(3),
+            Field::Key(0, 0xffff),
+            Field::Blob(2, 100),
+        ];
+        let schema = Schema::new(fields.clone()).unwrap();
+        let buf = schema.encode();
+        assert_eq!(2 + 8 + 2 + 4 + 8, buf.len());
+        assert_eq!(&[SCHEMA_VERSION, 8, 0x01, 0x02], &buf[..4]);
+        assert_eq!(Ok(schema), Schema::parse(&buf));
+        assert_eq!(&fields[..], Schema::parse(&buf).unwrap().fields());
+        assert_eq!(encode(&fields), buf);
+    }
+
+    // Tests that every way a descriptor can be malformed is refused.
+    #[test]
+    fn test_schema_malformed() {
+        let good = put().encode();
+        let cases: Vec<(Vec<u8>, SchemaError)> = vec![
+            (vec![], SchemaError::Truncated),
+            (vec![SCHEMA_VERSION], SchemaError::Truncated),
+            (vec![2, 1, 0x01], SchemaError::Version(2)),
+            (vec![0, 1, 0x01], SchemaError::Version(0)),
+            (vec![SCHEMA_VERSION, 0], SchemaError::FieldCount(0)),
+            (vec![SCHEMA_VERSION, 33], SchemaError::FieldCount(33)),
+            (vec![SCHEMA_VERSION, 2, 0x01], SchemaError::Truncated),
+            (vec![SCHEMA_VERSION, 1, 0x06, 1], SchemaError::Truncated),
+            (
+                vec![SCHEMA_VERSION, 1, 0x00],
+                SchemaError::UnknownField(0, 0x00),
+            ),
+            (
+                vec![SCHEMA_VERSION, 2, 0x01, 0x09],
+                SchemaError::UnknownField(1, 0x09),
+            ),
+            (
+                vec![SCHEMA_VERSION, 1, 0x07, 2, 0, 1, 0],
+                SchemaError::EmptyRange(0),
+            ),
+            (
+                vec![SCHEMA_VERSION, 1, 0x01, 0x01],
+                SchemaError::TrailingBytes,
+            ),
+            (good[..good.len() - 1].to_vec(), SchemaError::Truncated),
+            (
+                [&good[..], &[0x01][..]].concat(),
+                SchemaError::TrailingBytes,
+            ),
+        ];
+        for (buf, err) in cases.into_iter() {
+            assert_eq!(Err(err), Schema::parse(&buf), "{:?}", buf);
+        }
+
+        let blob = encode(&[Field::Blob(0, 1), Field::Table]);
+        assert_eq!(Err(SchemaError::BlobNotLast(0)), Schema::parse(&blob));
+        let blob = encode(&[Field::Blob(2, 1)]);
+        assert_eq!(Err(SchemaError::EmptyRange(0)), Schema::parse(&blob));
+        assert_eq!(Err(SchemaError::FieldCount(0)), Schema::new(vec![]));
+        assert_eq!(
+            Err(SchemaError::FieldCount(33)),
+            Schema::new(vec![Field::U8; 33])
+        );
+        assert!(Schema::new(vec![Field::U8; 32]).is_ok());
+    }
+
+    // Tests that no prefix of a well formed descriptor parses, and that no byte of it can be
+    // flipped into another well formed descriptor without being caught or changing the layout.
+    #[test]
+    fn test_schema_fuzz() {
+        let good = put().encode();
+        for len in 0..good.len() {
+            assert!(Schema::parse(&good[..len]).is_err(), "{:?}", &good[..len]);
+        }
+
+        for i in 0..good.len() {
+            for bit in 0..8 {
+                let mut buf = good.clone();
+                buf[i] ^= 1 << bit;
+                if let Ok(schema) = Schema::parse(&buf) {
+                    assert_ne!(put(), schema);
+                    assert_eq!(buf, schema.encode());
+                }
+            }
+        }
+    }
+
+    // Tests that arguments that fit a layout are accepted, and that ones that don't are refused
+    // at the first field they don't fit.
+    #[test]
+    fn test_schema_check() {
+        let schema = put();
+        assert_eq!(11, schema.min_len());
+        assert_eq!(Ok(()), schema.check(&args(1, b"k", b"")));
+        assert_eq!(Ok(()), schema.check(&args(1, &[7; 64], &[0; 1000])));
+
+        assert_eq!(Err(ArgsError::Short(0)), schema.check(&[0; 7]));
+        assert_eq!(Err(ArgsError::Short(1)), schema.check(&[0; 9]));
+        assert_eq!(
+            Err(ArgsError::Length(1, 0)),
+            schema.check(&args(1, b"", b"v"))
+        );
+        assert_eq!(
+            Err(ArgsError::Length(1, 65)),
+            schema.check(&args(1, &[7; 65], b""))
+        );
+        assert_eq!(
+            Err(ArgsError::Short(1)),
+            schema.check(&args(1, b"key", b"")[..12])
+        );
+
+        let fixed = Schema::new(vec![Field::Table, Field::U16, Field::Bytes(4)]).unwrap();
+        assert_eq!(14, fixed.min_len());
+        assert_eq!(Ok(()), fixed.check(&[0; 14]));
+        assert_eq!(Err(ArgsError::Short(2)), fixed.check(&[0; 13]));
+        assert_eq!(Err(ArgsError::Trailing(2)), fixed.check(&[0; 16]));
+
+        let bounded = Schema::new(vec![Field::Blob(1, 8)]).unwrap();
+        assert_eq!(Err(ArgsError::Length(0, 0)), bounded.check(b""));
+        assert_eq!(Err(ArgsError::Length(0, 9)), bounded.check(&[0; 9]));
+        assert_eq!(Ok(()), bounded.check(b"abc"));
+    }
+
+    // Tests that errors read the way they are reported to clients.
+    #[test]
+    fn test_schema_errors_display() {
+        assert_eq!(
+            "descriptor is version 2, expected 1",
+            SchemaError::Version(2).to_string()
+        );
+        assert_eq!(
+            "field 1 is 65 bytes, out of bounds",
+            ArgsError::Length(1, 65).to_string()
+        );
+    }
+}
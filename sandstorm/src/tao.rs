@@ -0,0 +1,684 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use std::ops::Range;
+
+use byteorder::{ByteOrder, LittleEndian};
+
+/// Identifier of a TAO object.
+pub type Id = u64;
+
+/// Time-stamp on an association.
+pub type Time = u64;
+
+/// The table holding TAO objects.
+pub const OBJECT_TABLE: u64 = 1;
+
+/// The table holding TAO associations and association lists.
+pub const ASSOC_TABLE: u64 = 2;
+
+/// The table holding the number of times each association list was counted, as an 8 byte
+/// little endian integer keyed by the list's key. The TAO extension maintains it in work it
+/// defers off assoc_count, so that counting a list isn't slowed down by it.
+pub const HOT_TABLE: u64 = 3;
+
+/// Length of an object's key, it's id.
+pub const OBJECT_KEY_LEN: usize = 8;
+
+/// Length of an object populated by fill(); a 4 byte otype, 8 byte version, 4 byte update
+/// time, and 16 byte payload, all of which are zero.
+pub const OBJECT_LEN: usize = 32;
+
+/// Length of an association list's key; the 8 byte id of the source object followed by the
+/// 2 byte association type.
+pub const LIST_KEY_LEN: usize = 10;
+
+/// Length of an association's key; an association list's key followed by the 8 byte id of
+/// the destination object.
+pub const ASSOC_KEY_LEN: usize = 18;
+
+/// Length of an association record; the 8 byte id of the destination object followed by an
+/// 8 byte time-stamp. Association lists are a sequence of these, newest first.
+pub const ASSOC_LEN: usize = 16;
+
+/// The association type populated by fill().
+pub const FILL_ATYPE: u16 = 0;
+
+/// Key of the object type registry in the object table. Object ids start at 1, so no object
+/// is ever stored under it.
+pub const REGISTRY_KEY: [u8; OBJECT_KEY_LEN] = [0; OBJECT_KEY_LEN];
+
+/// Key of the id counter in the object table; the next id the extension hands out, as an 8
+/// byte little endian integer. Ids stop short of the largest one, so no object is ever stored
+/// under it.
+pub const NEXT_ID_KEY: [u8; OBJECT_KEY_LEN] = [0xff; OBJECT_KEY_LEN];
+
+/// Leading bytes of an error frame, which distinguish it from a regular response.
+pub const ERROR_MAGIC: [u8; 4] = [b'T', b'E', b'R', b'R'];
+
+/// Length of an error frame; ERROR_MAGIC, a 1 byte error code, and the 2 byte object or
+/// association type the error concerns.
+pub const ERROR_LEN: usize = 7;
+
+/// Errors the TAO extension returns to clients in an error frame.
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TaoError {
+    /// The object's data is shorter than it's type allows.
+    TooShort = 1,
+
+    /// The object's data is longer than it's type allows.
+    TooLong = 2,
+
+    /// The type is not registered, and the registry is strict.
+    Unregistered = 3,
+
+    /// Objects of the type cannot be updated once added.
+    Frozen = 4,
+
+    /// One of the association's endpoints does not exist.
+    NoEndpoint = 5,
+
+    /// One of the association's endpoints has a type the association type does not permit.
+    BadEndpoint = 6,
+
+    /// The registry could not be read or written; it is malformed, or was written by a newer
+    /// version of the extension.
+    Registry = 7,
+
+    /// A type was registered with a minimum length above it's maximum length.
+    BadBounds = 8,
+
+    /// The id counter could not be read or written, or has no room left for the ids asked for.
+    Exhausted = 9,
+}
+
+impl TaoError {
+    /// Returns the error with the given code, or None if there is no such error.
+    pub fn from_code(code: u8) -> Option<TaoError> {
+        match code {
+            1 => Some(TaoError::TooShort),
+            2 => Some(TaoError::TooLong),
+            3 => Some(TaoError::Unregistered),
+            4 => Some(TaoError::Frozen),
+            5 => Some(TaoError::NoEndpoint),
+            6 => Some(TaoError::BadEndpoint),
+            7 => Some(TaoError::Registry),
+            8 => Some(TaoError::BadBounds),
+            9 => Some(TaoError::Exhausted),
+            _ => None,
+        }
+    }
+}
+
+/// Returns the key of an association list.
+///
+/// # Arguments
+///
+/// * `id1`:   The id of the source object.
+/// * `atype`: The association type.
+pub fn list_key(id1: Id, atype: u16) -> [u8; LIST_KEY_LEN] {
+    let mut key = [0; LIST_KEY_LEN];
+    LittleEndian::write_u64(&mut key[0..8], id1);
+    LittleEndian::write_u16(&mut key[8..10], atype);
+    key
+}
+
+/// Returns the key of an association.
+///
+/// # Arguments
+///
+/// * `id1`:   The id of the source object.
+/// * `atype`: The association type.
+/// * `id2`:   The id of the destination object.
+pub fn assoc_key(id1: Id, atype: u16, id2: Id) -> [u8; ASSOC_KEY_LEN] {
+    let mut key = [0; ASSOC_KEY_LEN];
+    key[0..LIST_KEY_LEN].copy_from_slice(&list_key(id1, atype));
+    LittleEndian::write_u64(&mut key[LIST_KEY_LEN..], id2);
+    key
+}
+
+/// Encodes an association record.
+///
+/// # Arguments
+///
+/// * `id2`:  The id of the destination object.
+/// * `time`: The association's time-stamp.
+pub fn encode_assoc(id2: Id, time: Time) -> [u8; ASSOC_LEN] {
+    let mut record = [0; ASSOC_LEN];
+    LittleEndian::write_u64(&mut record[0..8], id2);
+    LittleEndian::write_u64(&mut record[8..16], time);
+    record
+}
+
+/// Decodes an association record.
+///
+/// # Return
+///
+/// The destination object's id and the time-stamp, or None if the record is too short.
+pub fn decode_assoc(record: &[u8]) -> Option<(Id, Time)> {
+    if record.len() < ASSOC_LEN {
+        return None;
+    }
+
+    Some((
+        LittleEndian::read_u64(&record[0..8]),
+        LittleEndian::read_u64(&record[8..16]),
+    ))
+}
+
+/// Encodes an error frame.
+///
+/// # Arguments
+///
+/// * `error`: The error.
+/// * `otype`: The object or association type the error concerns.
+pub fn encode_error(error: TaoError, otype: u16) -> [u8; ERROR_LEN] {
+    let mut frame = [0; ERROR_LEN];
+    frame[0..4].copy_from_slice(&ERROR_MAGIC);
+    frame[4] = error as u8;
+    LittleEndian::write_u16(&mut frame[5..7], otype);
+    frame
+}
+
+/// Decodes an error frame.
+///
+/// # Return
+///
+/// The error and the type it concerns, or None if the response is not an error frame.
+pub fn decode_error(resp: &[u8]) -> Option<(TaoError, u16)> {
+    if resp.len() != ERROR_LEN || resp[0..4] != ERROR_MAGIC {
+        return None;
+    }
+
+    TaoError::from_code(resp[4]).map(|error| (error, LittleEndian::read_u16(&resp[5..7])))
+}
+
+/// Returns the number of associations on a list.
+pub fn assoc_count(list: &[u8]) -> usize {
+    list.len() / ASSOC_LEN
+}
+
+/// Parses weighted buckets of values, as "VALUExWEIGHT,..." (ex: "1x90,100x9,5000x1").
+///
+/// # Return
+///
+/// The (value, weight) buckets, or None if the string is malformed or every weight is zero.
+pub fn parse_buckets(spec: &str) -> Option<Vec<(u32, u32)>> {
+    let mut parsed = Vec::new();
+    for bucket in spec.split(',') {
+        let mut b = bucket.split('x');
+        match (b.next(), b.next(), b.next()) {
+            (Some(v), Some(w), None) => match (v.parse(), w.parse()) {
+                (Ok(v), Ok(w)) => parsed.push((v, w)),
+                _ => return None,
+            },
+            _ => return None,
+        }
+    }
+
+    if parsed.iter().all(|&(_, w)| w == 0) {
+        return None;
+    }
+    Some(parsed)
+}
+
+/// The number of associations each object gets when the TAO dataset is populated. Fanout is
+/// a pure function of an object's id, so that a populated graph can be regenerated (and
+/// checked) without storing it.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Fanout {
+    /// Every object gets the same number of associations.
+    Constant(u32),
+
+    /// Fanout follows a power law over object ids; object `i` gets `max / i^skew`
+    /// associations rounded down. Object 1 gets `max`, and once the fanout drops below one
+    /// the remaining objects get none.
+    Zipf {
+        /// The fanout of object 1.
+        max: u32,
+
+        /// The skew of the distribution.
+        skew: f64,
+    },
+
+    /// Explicit buckets of (fanout, weight). Objects are dealt out to buckets in proportion
+    /// to their weights, in order, round robin over the object ids.
+    Buckets(Vec<(u32, u32)>),
+}
+
+impl Fanout {
+    /// Parses a fanout distribution. The format is one of "constant:N", "zipf:MAX:SKEW", or
+    /// "buckets:FANOUTxWEIGHT,..." (ex: "buckets:1x90,100x9,5000x1").
+    ///
+    /// # Return
+    ///
+    /// The distribution, or None if the string is malformed.
+    pub fn parse(spec: &str) -> Option<Fanout> {
+        let mut parts = spec.split(':');
+        match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some("constant"), Some(n), None, None) => n.parse().ok().map(Fanout::Constant),
+
+            (Some("zipf"), Some(max), Some(skew), None) => {
+                match (max.parse(), skew.parse::<f64>()) {
+                    (Ok(max), Ok(skew)) if skew >= 0.0 => Some(Fanout::Zipf {
+                        max: max,
+                        skew: skew,
+                    }),
+                    _ => None,
+                }
+            }
+
+            (Some("buckets"), Some(buckets), None, None) => {
+                parse_buckets(buckets).map(Fanout::Buckets)
+            }
+
+            _ => None,
+        }
+    }
+
+    /// Returns the number of associations an object gets.
+    ///
+    /// # Arguments
+    ///
+    /// * `id`:  The object's id, between 1 and `num`.
+    /// * `num`: The number of objects in the dataset. An object never gets more than `num - 1`
+    ///          associations, since it's associations go to distinct objects other than itself.
+    pub fn fanout(&self, id: u32, num: u32) -> u32 {
+        let fanout = match *self {
+            Fanout::Constant(n) => n,
+
+            Fanout::Zipf { max, skew } => (max as f64 / (id as f64).powf(skew)).floor() as u32,
+
+            Fanout::Buckets(ref buckets) => {
+                let total: u64 = buckets.iter().map(|&(_, w)| w as u64).sum();
+                let mut slot = (id as u64 - 1) % total;
+                let mut fanout = 0;
+                for &(f, w) in buckets.iter() {
+                    if slot < w as u64 {
+                        fanout = f;
+                        break;
+                    }
+                    slot -= w as u64;
+                }
+                fanout
+            }
+        };
+
+        fanout.min(num.saturating_sub(1))
+    }
+}
+
+/// Returns the destination of an object's `j`th association. An object's associations go to
+/// the objects that follow it, wrapping around at `num`.
+///
+/// # Arguments
+///
+/// * `id`:  The id of the source object, between 1 and `num`.
+/// * `j`:   The index of the association on the source object's list.
+/// * `num`: The number of objects in the dataset.
+pub fn fill_target(id: u32, j: u32, num: u32) -> Id {
+    ((id as u64 + j as u64) % num as u64) + 1
+}
+
+/// Returns the time-stamp on the `j`th association of an object with `fanout` associations.
+/// Lists are ordered newest first, so time-stamps count down from `fanout` to 1.
+pub fn fill_time(j: u32, fanout: u32) -> Time {
+    (fanout - j) as Time
+}
+
+/// Generates the TAO dataset for a range of objects, handing every record to a callback.
+/// Each object gets an object record, and if it's fanout is non-zero, an association record
+/// per association and an association list. Objects with no associations have no list.
+///
+/// # Arguments
+///
+/// * `num`:     The number of objects in the dataset; ids go from 1 to `num`.
+/// * `objects`: The ids of the objects to generate records for. Disjoint ranges can be
+///              generated independently.
+/// * `fanout`:  The number of associations each object gets.
+/// * `emit`:    Called with the table, key, and value of every record.
+///
+/// # Return
+///
+/// The number of associations generated.
+pub fn fill<F>(num: u32, objects: Range<u32>, fanout: &Fanout, mut emit: F) -> u64
+where
+    F: FnMut(u64, &[u8], &[u8]),
+{
+    let object = [0; OBJECT_LEN];
+    let mut list = Vec::new();
+    let mut edges = 0;
+
+    for id in objects {
+        let mut key = [0; OBJECT_KEY_LEN];
+        LittleEndian::write_u64(&mut key, id as Id);
+        emit(OBJECT_TABLE, &key, &object);
+
+        let n = fanout.fanout(id, num);
+        if n == 0 {
+            continue;
+        }
+
+        list.clear();
+        for j in 0..n {
+            let (id2, time) = (fill_target(id, j, num), fill_time(j, n));
+            let record = encode_assoc(id2, time);
+            emit(ASSOC_TABLE, &assoc_key(id as Id, FILL_ATYPE, id2), &record);
+            list.extend_from_slice(&record);
+        }
+
+        emit(ASSOC_TABLE, &list_key(id as Id, FILL_ATYPE), &list);
+        edges += n as u64;
+    }
+
+    edges
+}
+
+/// An operation in the TAO workload mix. Every one is served by the tao extension.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Op {
+    /// Looks up an object.
+    ObjGet = 0,
+
+    /// Creates an object under an id the client reserved.
+    ObjAdd = 1,
+
+    /// Overwrites an existing object.
+    ObjUpdate = 2,
+
+    /// Looks up an association.
+    AssocGet = 3,
+
+    /// Adds an association between two existing objects.
+    AssocAdd = 4,
+
+    /// Deletes an association.
+    AssocDel = 5,
+
+    /// Counts the associations on a list.
+    AssocCount = 6,
+
+    /// Reads the newest associations off a list.
+    AssocRange = 7,
+}
+
+/// Every operation in the TAO workload mix, in order of their discriminants.
+pub const OPS: [Op; 8] = [
+    Op::ObjGet,
+    Op::ObjAdd,
+    Op::ObjUpdate,
+    Op::AssocGet,
+    Op::AssocAdd,
+    Op::AssocDel,
+    Op::AssocCount,
+    Op::AssocRange,
+];
+
+/// The operation mix measured on Facebook's TAO (Bronson et al., ATC '13), in percent of all
+/// operations. Reads are 99.8% of the mix and writes 0.2%. assoc_time_range is counted as an
+/// assoc_range and assoc_change_type as an assoc_add. obj_delete is left out.
+pub const PUBLISHED_MIX: [(Op, f64); 8] = [
+    (Op::AssocRange, 43.6126),
+    (Op::ObjGet, 28.8422),
+    (Op::AssocGet, 15.6686),
+    (Op::AssocCount, 11.6766),
+    (Op::AssocAdd, 0.1068),
+    (Op::ObjUpdate, 0.0414),
+    (Op::ObjAdd, 0.033),
+    (Op::AssocDel, 0.0166),
+];
+
+impl Op {
+    /// Returns the operation's name, as it appears in an operation mix.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Op::ObjGet => "obj_get",
+            Op::ObjAdd => "obj_add",
+            Op::ObjUpdate => "obj_update",
+            Op::AssocGet => "assoc_get",
+            Op::AssocAdd => "assoc_add",
+            Op::AssocDel => "assoc_del",
+            Op::AssocCount => "assoc_count",
+            Op::AssocRange => "assoc_range",
+        }
+    }
+
+    /// Returns the operation with the given name, or None if there is no such operation.
+    pub fn parse(name: &str) -> Option<Op> {
+        OPS.iter().find(|op| op.name() == name).cloned()
+    }
+
+    /// Returns true if the operation modifies the graph.
+    pub fn is_write(&self) -> bool {
+        match *self {
+            Op::ObjAdd | Op::ObjUpdate | Op::AssocAdd | Op::AssocDel => true,
+            _ => false,
+        }
+    }
+}
+
+/// Parses an operation mix, as "OP:PERCENTAGE,..." (ex: "obj_get:30,assoc_range:70").
+/// Operations left out are never issued. Percentages need not add up to 100; each operation
+/// is issued in proportion to it's share of their sum.
+///
+/// # Return
+///
+/// The percentage of each operation, in order of OPS, or None if the string is malformed, an
+/// operation appears twice, or every percentage is zero.
+pub fn parse_mix(spec: &str) -> Option<[f64; 8]> {
+    let mut mix = [0.0; 8];
+    let mut seen = [false; 8];
+    for entry in spec.split(',') {
+        let mut e = entry.split(':');
+        let (op, pct) = match (e.next(), e.next(), e.next()) {
+            (Some(op), Some(pct), None) => {
+                match (Op::parse(op.trim()), pct.trim().parse::<f64>()) {
+                    (Some(op), Ok(pct)) if pct >= 0.0 && pct.is_finite() => (op, pct),
+                    _ => return None,
+                }
+            }
+            _ => return None,
+        };
+
+        if seen[op as usize] {
+            return None;
+        }
+        seen[op as usize] = true;
+        mix[op as usize] = pct;
+    }
+
+    if mix.iter().all(|pct| *pct == 0.0) {
+        return None;
+    }
+    Some(mix)
+}
+
+/// Returns PUBLISHED_MIX as the percentage of each operation, in order of OPS.
+pub fn published_mix() -> [f64; 8] {
+    let mut mix = [0.0; 8];
+    for &(op, pct) in PUBLISHED_MIX.iter() {
+        mix[op as usize] = pct;
+    }
+    mix
+}
+
+// This module contains unit tests for the TAO layout and generation model.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_keys() {
+        let key = assoc_key(0x0102, 7, 0x0304);
+        assert_eq!(&list_key(0x0102, 7)[..], &key[0..LIST_KEY_LEN]);
+        assert_eq!(&[0x02, 0x01, 0, 0, 0, 0, 0, 0, 7, 0], &key[0..10]);
+        assert_eq!(&[0x04, 0x03, 0, 0, 0, 0, 0, 0], &key[10..18]);
+    }
+
+    #[test]
+    fn test_assoc_record() {
+        let record = encode_assoc(1, 2);
+        assert_eq!(Some((1, 2)), decode_assoc(&record));
+        assert_eq!(None, decode_assoc(&record[0..15]));
+
+        let mut list = Vec::new();
+        for i in 0..50000 {
+            list.extend_from_slice(&encode_assoc(i, i + 1));
+        }
+        assert_eq!(50000, assoc_count(&list));
+        for (i, record) in list.chunks(ASSOC_LEN).enumerate() {
+            assert_eq!(Some((i as Id, i as Time + 1)), decode_assoc(record));
+        }
+    }
+
+    #[test]
+    fn test_error_frame() {
+        let frame = encode_error(TaoError::TooLong, 0x0102);
+        assert_eq!(&[b'T', b'E', b'R', b'R', 2, 0x02, 0x01], &frame);
+        assert_eq!(Some((TaoError::TooLong, 0x0102)), decode_error(&frame));
+
+        for code in 1..10 {
+            let error = TaoError::from_code(code).unwrap();
+            assert_eq!(code, error as u8);
+            assert_eq!(Some((error, 7)), decode_error(&encode_error(error, 7)));
+        }
+        assert_eq!(None, TaoError::from_code(0));
+        assert_eq!(None, TaoError::from_code(10));
+
+        let mut bad = frame;
+        bad[4] = 0;
+        assert_eq!(None, decode_error(&bad));
+        bad[0] = b'X';
+        assert_eq!(None, decode_error(&bad));
+        assert_eq!(None, decode_error(&frame[0..6]));
+        assert_eq!(None, decode_error(&encode_assoc(1, 2)));
+    }
+
+    #[test]
+    fn test_fanout_parse() {
+        assert_eq!(Some(Fanout::Constant(4)), Fanout::parse("constant:4"));
+        assert_eq!(
+            Some(Fanout::Zipf {
+                max: 1000,
+                skew: 0.99,
+            }),
+            Fanout::parse("zipf:1000:0.99")
+        );
+        assert_eq!(
+            Some(Fanout::Buckets(vec![(1, 90), (5000, 1)])),
+            Fanout::parse("buckets:1x90,5000x1")
+        );
+
+        assert_eq!(None, Fanout::parse(""));
+        assert_eq!(None, Fanout::parse("constant"));
+        assert_eq!(None, Fanout::parse("constant:4:1"));
+        assert_eq!(None, Fanout::parse("zipf:1000"));
+        assert_eq!(None, Fanout::parse("zipf:1000:-1"));
+        assert_eq!(None, Fanout::parse("buckets:1x0"));
+        assert_eq!(None, Fanout::parse("buckets:1x2,3"));
+    }
+
+    #[test]
+    fn test_fanout() {
+        assert_eq!(4, Fanout::Constant(4).fanout(7, 100));
+
+        // Fanout is capped so that associations go to distinct objects.
+        assert_eq!(9, Fanout::Constant(20).fanout(1, 10));
+        assert_eq!(0, Fanout::Constant(20).fanout(1, 1));
+
+        let zipf = Fanout::Zipf {
+            max: 1000,
+            skew: 1.0,
+        };
+        assert_eq!(1000, zipf.fanout(1, 10000));
+        assert_eq!(500, zipf.fanout(2, 10000));
+        assert_eq!(1, zipf.fanout(1000, 10000));
+        assert_eq!(0, zipf.fanout(1001, 10000));
+
+        let buckets = Fanout::Buckets(vec![(0, 2), (3, 1), (7, 0)]);
+        let fanouts: Vec<u32> = (1..7).map(|i| buckets.fanout(i, 100)).collect();
+        assert_eq!(vec![0, 0, 3, 0, 0, 3], fanouts);
+    }
+
+    // Tests that mixes parse into percentages in order of OPS, and that malformed ones don't.
+    #[test]
+    fn test_parse_mix() {
+        let mix = parse_mix("obj_get:30, assoc_range:69.5,obj_add:0.5").unwrap();
+        assert_eq!([30.0, 0.5, 0.0, 0.0, 0.0, 0.0, 0.0, 69.5], mix);
+
+        assert_eq!(None, parse_mix(""));
+        assert_eq!(None, parse_mix("obj_get"));
+        assert_eq!(None, parse_mix("obj_get:30:1"));
+        assert_eq!(None, parse_mix("obj_delete:30"));
+        assert_eq!(None, parse_mix("obj_get:-1,assoc_get:10"));
+        assert_eq!(None, parse_mix("obj_get:10,obj_get:10"));
+        assert_eq!(None, parse_mix("obj_get:0,assoc_get:0"));
+
+        for op in OPS.iter() {
+            assert_eq!(Some(*op), Op::parse(op.name()));
+        }
+
+        // The published mix covers every operation, and is almost entirely reads.
+        let published = published_mix();
+        let total: f64 = published.iter().sum();
+        let writes: f64 = OPS
+            .iter()
+            .filter(|op| op.is_write())
+            .map(|op| published[*op as usize])
+            .sum();
+        assert!(published.iter().all(|pct| *pct > 0.0));
+        assert!((total - 100.0).abs() < 0.01);
+        assert!((writes - 0.1978).abs() < 1e-9);
+    }
+
+    // Tests that the generated records match the model, and that generating disjoint ranges
+    // separately produces the same records as generating them all at once.
+    #[test]
+    fn test_fill() {
+        let fanout = Fanout::Buckets(vec![(0, 1), (2, 1), (9, 1)]);
+        let mut all = HashMap::new();
+        let edges = fill(10, 1..11, &fanout, |t, k, v| {
+            assert!(all.insert((t, k.to_vec()), v.to_vec()).is_none());
+        });
+        assert_eq!(3 * (0 + 2 + 9), edges);
+
+        let mut split = HashMap::new();
+        for range in [1..4, 4..11].iter() {
+            fill(10, range.clone(), &fanout, |t, k, v| {
+                split.insert((t, k.to_vec()), v.to_vec());
+            });
+        }
+        assert_eq!(all, split);
+
+        // 10 objects, 33 associations, and a list for each of the 6 objects with a fanout.
+        assert_eq!(10 + 33 + 6, all.len());
+        assert!(!all.contains_key(&(ASSOC_TABLE, list_key(1, FILL_ATYPE).to_vec())));
+
+        let list = &all[&(ASSOC_TABLE, list_key(3, FILL_ATYPE).to_vec())];
+        assert_eq!(9, assoc_count(list));
+        for (j, record) in list.chunks(ASSOC_LEN).enumerate() {
+            let (id2, time) = decode_assoc(record).unwrap();
+            assert_eq!(fill_target(3, j as u32, 10), id2);
+            assert_eq!(9 - j as Time, time);
+            let assoc = &all[&(ASSOC_TABLE, assoc_key(3, 0, id2).to_vec())];
+            assert_eq!(record, &assoc[..]);
+        }
+        assert_eq!(Some((4, 9)), decode_assoc(list));
+        assert_eq!(Some((2, 1)), decode_assoc(&list[8 * ASSOC_LEN..]));
+    }
+}
@@ -0,0 +1,465 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use super::compare::Histogram;
+
+/// How the load is raised from one epoch of a sweep to the next.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Step {
+    /// The load is raised by a constant.
+    Add(usize),
+
+    /// The load is multiplied by a factor.
+    Multiply(f64),
+}
+
+impl Step {
+    /// Returns the load that follows `load`. Always larger than `load`.
+    pub fn next(&self, load: usize) -> usize {
+        let next = match *self {
+            Step::Add(n) => load.saturating_add(n),
+            Step::Multiply(f) => (load as f64 * f) as usize,
+        };
+
+        next.max(load.saturating_add(1))
+    }
+}
+
+/// The parameters of a sweep.
+#[derive(Clone, Debug)]
+pub struct TuneConfig {
+    /// The load of the first epoch; ex: the outstanding window.
+    pub start: usize,
+
+    /// The largest load the sweep goes upto.
+    pub max: usize,
+
+    /// How the load is raised between epochs.
+    pub step: Step,
+
+    /// The sweep stops once an epoch's 99th percentile latency goes above this, in nanoseconds.
+    pub p99_ceiling: u64,
+
+    /// The sweep stops once raising the load gains less than this fraction of throughput.
+    pub min_gain: f64,
+
+    /// The number of steps in a row that must gain less than `min_gain` before the sweep stops,
+    /// so that a single noisy epoch doesn't stop it early. 0 means 1.
+    pub patience: usize,
+
+    /// The number of epochs spent refining the knee after the sweep, by binary search between
+    /// the knee and the load the sweep stopped at. 0 skips refinement.
+    pub refine: usize,
+}
+
+impl Default for TuneConfig {
+    fn default() -> TuneConfig {
+        TuneConfig {
+            start: 1,
+            max: 1024,
+            step: Step::Multiply(2.0),
+            p99_ceiling: u64::max_value(),
+            min_gain: 0.05,
+            patience: 1,
+            refine: 0,
+        }
+    }
+}
+
+/// The summary of a measurement epoch at a single load setting.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Epoch {
+    /// The load the epoch ran at.
+    pub load: usize,
+
+    /// Operations completed per second.
+    pub throughput: f64,
+
+    /// The median and 99th percentile latency, in nanoseconds.
+    pub p50: u64,
+    pub p99: u64,
+}
+
+impl Epoch {
+    /// Summarizes an epoch out of it's latency histogram.
+    ///
+    /// # Arguments
+    ///
+    /// * `load`:      The load the epoch ran at.
+    /// * `completed`: The number of operations completed during the epoch.
+    /// * `seconds`:   The length of the epoch.
+    /// * `latency`:   The latencies of the operations completed during the epoch.
+    pub fn new(load: usize, completed: u64, seconds: f64, latency: &Histogram) -> Epoch {
+        Epoch {
+            load: load,
+            throughput: completed as f64 / seconds,
+            p50: latency.percentile(0.5),
+            p99: latency.percentile(0.99),
+        }
+    }
+}
+
+/// Why a sweep stopped raising the load.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum Stop {
+    /// An epoch's 99th percentile latency went above the ceiling.
+    Ceiling,
+
+    /// Raising the load stopped gaining throughput.
+    Plateau,
+
+    /// The sweep reached the largest load.
+    MaxLoad,
+}
+
+/// The outcome of a sweep.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Sweep {
+    /// Every epoch of the sweep, in the order they ran. Their loads only go up.
+    pub epochs: Vec<Epoch>,
+
+    /// The epochs run to refine the knee, in the order they ran.
+    #[serde(default)]
+    pub probes: Vec<Epoch>,
+
+    /// The highest load that gained throughput while keeping latency under the ceiling. None if
+    /// not even the first epoch did.
+    pub knee: Option<Epoch>,
+
+    /// Why the sweep stopped.
+    pub stop: Option<Stop>,
+}
+
+impl Sweep {
+    /// Returns true if the load went up on every epoch of the sweep.
+    pub fn monotone(&self) -> bool {
+        self.epochs.windows(2).all(|w| w[0].load < w[1].load)
+    }
+}
+
+/// Decides the load of every epoch of a sweep, from the summaries of the epochs before it.
+///
+/// The load starts at `start` and is raised a step at a time. The last epoch that kept it's
+/// 99th percentile under the ceiling and gained atleast `min_gain` over the one before it is
+/// the knee. Gains are measured against the knee, so that a slow climb over several noisy
+/// epochs still counts. Once the sweep stops, the load between the knee and the epoch that
+/// stopped the sweep can be binary searched for a finer knee; a probe improves on the knee if
+/// it stays under the ceiling and gains it's share of `min_gain` for the part of a step it
+/// took.
+pub struct Tuner {
+    // The parameters of the sweep.
+    config: TuneConfig,
+
+    // The outcome so far.
+    sweep: Sweep,
+
+    // The load of the next epoch, or None once the sweep is done.
+    next: Option<usize>,
+
+    // Steps in a row that gained less than min_gain, and the load of the first of them.
+    stalls: usize,
+    stalled: usize,
+
+    // The loads being binary searched between, and the probes left.
+    lo: usize,
+    hi: usize,
+    probes: usize,
+}
+
+impl Tuner {
+    /// Creates a tuner for a sweep.
+    pub fn new(config: TuneConfig) -> Tuner {
+        let start = config.start.max(1);
+        Tuner {
+            next: Some(start.min(config.max.max(1))),
+            config: config,
+            sweep: Sweep::default(),
+            stalls: 0,
+            stalled: 0,
+            lo: 0,
+            hi: 0,
+            probes: 0,
+        }
+    }
+
+    /// Returns the load to run the next epoch at, or None if the sweep is done.
+    pub fn next(&self) -> Option<usize> {
+        self.next
+    }
+
+    /// Accounts for an epoch run at the load returned by next(), and decides the next load.
+    pub fn record(&mut self, epoch: Epoch) {
+        match self.sweep.stop {
+            None => self.sweep_step(epoch),
+            Some(_) => self.probe_step(epoch),
+        }
+    }
+
+    /// Returns the outcome of the sweep.
+    pub fn finish(self) -> Sweep {
+        self.sweep
+    }
+
+    // Returns the gain in throughput of an epoch over the knee, as a fraction of the knee's.
+    fn gain(&self, epoch: &Epoch) -> f64 {
+        match self.sweep.knee {
+            Some(ref knee) if knee.throughput > 0.0 => {
+                (epoch.throughput - knee.throughput) / knee.throughput
+            }
+            Some(_) => match epoch.throughput > 0.0 {
+                true => 1.0,
+                false => 0.0,
+            },
+            None => 1.0,
+        }
+    }
+
+    fn sweep_step(&mut self, epoch: Epoch) {
+        self.sweep.epochs.push(epoch);
+
+        if epoch.p99 > self.config.p99_ceiling {
+            let hi = epoch.load;
+            return self.stop(Stop::Ceiling, hi);
+        }
+
+        if self.gain(&epoch) >= self.config.min_gain {
+            self.sweep.knee = Some(epoch);
+            self.stalls = 0;
+        } else {
+            if self.stalls == 0 {
+                self.stalled = epoch.load;
+            }
+            self.stalls += 1;
+            if self.stalls >= self.config.patience.max(1) {
+                let hi = self.stalled;
+                return self.stop(Stop::Plateau, hi);
+            }
+        }
+
+        if epoch.load >= self.config.max {
+            return self.stop(Stop::MaxLoad, epoch.load);
+        }
+
+        let next = self.config.step.next(epoch.load).min(self.config.max);
+        self.next = Some(next);
+    }
+
+    // Stops raising the load, and starts refining the knee between it and `hi` if asked to.
+    fn stop(&mut self, stop: Stop, hi: usize) {
+        self.sweep.stop = Some(stop);
+        self.next = None;
+        if stop == Stop::MaxLoad {
+            return;
+        }
+
+        if let Some(knee) = self.sweep.knee {
+            self.lo = knee.load;
+            self.hi = hi;
+            self.probes = self.config.refine;
+            self.probe();
+        }
+    }
+
+    // Picks the next probe, if there are any left and anything left to search.
+    fn probe(&mut self) {
+        self.next = match self.probes > 0 && self.hi > self.lo + 1 {
+            true => Some(self.lo + (self.hi - self.lo) / 2),
+            false => None,
+        };
+    }
+
+    fn probe_step(&mut self, epoch: Epoch) {
+        self.sweep.probes.push(epoch);
+        self.probes -= 1;
+
+        // A probe part of the way from the knee to the top of the search must gain that part of
+        // a step's min_gain.
+        let part = (epoch.load - self.lo) as f64 / (self.hi - self.lo) as f64;
+        let good = epoch.p99 <= self.config.p99_ceiling
+            && self.gain(&epoch) >= self.config.min_gain * part;
+        match good {
+            true => {
+                self.lo = epoch.load;
+                self.sweep.knee = Some(epoch);
+            }
+            false => self.hi = epoch.load,
+        }
+
+        self.probe();
+    }
+}
+
+/// Runs a sweep.
+///
+/// # Arguments
+///
+/// * `config`: The parameters of the sweep.
+/// * `run`:    Runs an epoch at a load, and returns it's summary. Every epoch must start from a
+///             quiesced system, with nothing left in flight from the epoch before it.
+pub fn sweep<F: FnMut(usize) -> Epoch>(config: TuneConfig, mut run: F) -> Sweep {
+    let mut tuner = Tuner::new(config);
+    while let Some(load) = tuner.next() {
+        let mut epoch = run(load);
+        epoch.load = load;
+        tuner.record(epoch);
+    }
+
+    tuner.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::{Rng, SeedableRng, XorShiftRng};
+
+    // A closed loop system with a soft knee. Throughput approaches `capacity` as the window
+    // grows, and latency follows from Little's law, with the tail at twice the mean.
+    fn curve(window: usize, capacity: f64, knee: f64) -> Epoch {
+        let throughput = capacity * (1.0 - (-(window as f64) / knee).exp());
+        let mean = window as f64 / throughput * 1e9;
+        Epoch {
+            load: window,
+            throughput: throughput,
+            p50: mean as u64,
+            p99: 2 * mean as u64,
+        }
+    }
+
+    fn loads(epochs: &[Epoch]) -> Vec<usize> {
+        epochs.iter().map(|e| e.load).collect()
+    }
+
+    // Tests that a sweep stops once gains flatten out.
+    #[test]
+    fn test_plateau() {
+        let config = TuneConfig::default();
+        let result = sweep(config, |w| curve(w, 1e6, 8.0));
+
+        // 16 -> 32 gains 13.5%, and 32 -> 64 gains 1.8%.
+        assert_eq!(vec![1, 2, 4, 8, 16, 32, 64], loads(&result.epochs));
+        assert_eq!(Some(32), result.knee.map(|k| k.load));
+        assert_eq!(Some(Stop::Plateau), result.stop);
+        assert!(result.probes.is_empty());
+        assert!(result.monotone());
+    }
+
+    // Tests that a sweep stops once the tail goes above the ceiling, even while still gaining.
+    #[test]
+    fn test_ceiling() {
+        let mut config = TuneConfig::default();
+        config.step = Step::Add(4);
+        config.start = 4;
+        config.p99_ceiling = curve(20, 1e6, 100.0).p99;
+        let result = sweep(config, |w| curve(w, 1e6, 100.0));
+
+        assert_eq!(vec![4, 8, 12, 16, 20, 24], loads(&result.epochs));
+        assert_eq!(Some(20), result.knee.map(|k| k.load));
+        assert_eq!(Some(Stop::Ceiling), result.stop);
+
+        // Nothing is under the ceiling.
+        let mut config = TuneConfig::default();
+        config.p99_ceiling = 1;
+        let result = sweep(config, |w| curve(w, 1e6, 8.0));
+        assert_eq!(vec![1], loads(&result.epochs));
+        assert_eq!(None, result.knee);
+    }
+
+    // Tests that a sweep never goes past the largest load.
+    #[test]
+    fn test_max_load() {
+        let mut config = TuneConfig::default();
+        config.max = 40;
+        config.refine = 4;
+        let result = sweep(config, |w| curve(w, 1e6, 1000.0));
+
+        assert_eq!(vec![1, 2, 4, 8, 16, 32, 40], loads(&result.epochs));
+        assert_eq!(Some(40), result.knee.map(|k| k.load));
+        assert_eq!(Some(Stop::MaxLoad), result.stop);
+        assert!(result.probes.is_empty());
+    }
+
+    // Tests that refinement binary searches between the knee and the epoch that stopped the
+    // sweep, without changing the sweep's own epochs.
+    #[test]
+    fn test_refine() {
+        let mut config = TuneConfig::default();
+        config.refine = 8;
+        config.p99_ceiling = curve(40, 1e6, 1000.0).p99;
+        let result = sweep(config.clone(), |w| curve(w, 1e6, 1000.0));
+
+        assert_eq!(vec![1, 2, 4, 8, 16, 32, 64], loads(&result.epochs));
+        assert!(result.monotone());
+        assert_eq!(vec![48, 40, 44, 42, 41], loads(&result.probes));
+        assert_eq!(Some(40), result.knee.map(|k| k.load));
+
+        // Probes stop once they run out.
+        config.refine = 2;
+        let result = sweep(config, |w| curve(w, 1e6, 1000.0));
+        assert_eq!(vec![48, 40], loads(&result.probes));
+        assert_eq!(Some(40), result.knee.map(|k| k.load));
+    }
+
+    // Tests that noisy epochs still lead to a knee close to the true one, with patience.
+    #[test]
+    fn test_noisy() {
+        for seed in 1..20 {
+            let mut rng = XorShiftRng::from_seed([seed, 1, 2, 3]);
+            let mut config = TuneConfig::default();
+            config.step = Step::Add(2);
+            config.max = 200;
+            config.patience = 3;
+            config.min_gain = 0.02;
+            let result = sweep(config, |w| {
+                let mut epoch = curve(w, 1e6, 20.0);
+                epoch.throughput *= 1.0 + rng.gen_range(-0.01, 0.01);
+                epoch
+            });
+
+            // Gains fall under 2% a step around a window of 40 to 60.
+            let knee = result.knee.unwrap().load;
+            assert!(knee >= 30 && knee <= 90, "knee {} with seed {}", knee, seed);
+            assert_eq!(Some(Stop::Plateau), result.stop);
+            assert!(result.monotone());
+        }
+    }
+
+    // Tests how steps raise the load.
+    #[test]
+    fn test_step() {
+        assert_eq!(5, Step::Add(4).next(1));
+        assert_eq!(2, Step::Add(0).next(1));
+        assert_eq!(3, Step::Multiply(1.5).next(2));
+        assert_eq!(2, Step::Multiply(1.1).next(1));
+        assert_eq!(
+            usize::max_value(),
+            Step::Add(4).next(usize::max_value() - 1)
+        );
+    }
+
+    // Tests that an epoch is summarized off it's histogram.
+    #[test]
+    fn test_epoch() {
+        let latency = Histogram {
+            bounds: vec![100, 200, 300],
+            counts: vec![50, 49, 1],
+        };
+        let epoch = Epoch::new(8, 100, 2.0, &latency);
+        assert_eq!(
+            (8, 50.0, 100, 200),
+            (epoch.load, epoch.throughput, epoch.p50, epoch.p99)
+        );
+    }
+}
@@ -37,6 +37,7 @@ use db::log::*;
 use db::master::Master;
 use db::rpc::parse_rpc_opcode;
 use db::task::TaskState::*;
+use db::trailer::{TrailerReader, TRAILER_RWSET};
 use db::wireformat::*;
 
 use rand::distributions::Sample;
@@ -44,6 +45,7 @@ use rand::{SeedableRng, XorShiftRng};
 
 use zipf::ZipfDistribution;
 
+use splinter::latency::SampleFilter;
 use splinter::manager::TaskManager;
 use splinter::*;
 
@@ -96,6 +98,9 @@ struct AggregateSendRecv {
     /// have been received.
     latencies: Vec<u64>,
 
+    /// Drops latency samples the clock got wrong, and counts them.
+    filter: SampleFilter,
+
     /// Number of keys to aggregate across. Required for the native case.
     num: u32,
 
@@ -190,6 +195,7 @@ impl AggregateSendRecv {
             responses: resps,
             recvd: 0,
             latencies: Vec::with_capacity(2 * 1000 * 1000),
+            filter: SampleFilter::default(),
             num: num,
             ord: ord,
             outstanding: 0,
@@ -223,19 +229,20 @@ impl AggregateSendRecv {
     #[inline]
     fn generate(&mut self, curr: u64) {
         let (t, k) = self.sample();
+        let id = self.sender.next_id();
 
         match self.native {
             // Native get() request.
             true => {
                 self.n_buff[0..size_of::<u32>()].copy_from_slice(&k);
-                self.sender.send_get(t, 1, &self.n_buff, curr);
+                self.sender.send_get(t, 1, &self.n_buff, id, curr);
             }
 
             // Invoke request. Add the key to the pre-populated payload.
             false => {
                 self.i_buff[25..29].copy_from_slice(&k);
-                self.add_request(&self.i_buff, t, 9, curr);
-                self.sender.send_invoke(t, 9, &self.i_buff, curr);
+                self.add_request(&self.i_buff, t, 9, id, curr);
+                self.sender.send_invoke(t, 9, &self.i_buff, id, curr);
             }
         }
     }
@@ -305,6 +312,14 @@ impl AggregateSendRecv {
             cycles::to_seconds(tail) * 1e9,
             self.recvd as f64 / cycles::to_seconds(stop - self.start)
         );
+        info!("{}", self.filter);
+    }
+
+    /// Records the latency of a request sent out at `stamp` that completed at `curr`.
+    fn record(&mut self, stamp: u64, curr: u64) {
+        if let Some(latency) = self.filter.sample(stamp, curr) {
+            self.latencies.push(latency);
+        }
     }
 
     fn recv(&mut self) {
@@ -321,12 +336,13 @@ impl AggregateSendRecv {
                         OpCode::SandstormGetRpc => {
                             let p = packet.parse_header::<GetResponse>();
                             self.sender.send_multiget(
-                                p.get_header().common_header.tenant,
+                                p.get_header().common_header.tenant(),
                                 1,
                                 30,
                                 self.num,
                                 p.get_payload(),
-                                p.get_header().common_header.stamp,
+                                p.get_header().common_header.id(),
+                                p.get_header().common_header.stamp(),
                             );
                             p.free_packet();
                         }
@@ -338,8 +354,7 @@ impl AggregateSendRecv {
                             let p = packet.parse_header::<MultiGetResponse>();
                             let _s = self.aggregate(0, p.get_payload());
                             if self.recvd & 0xf == 0 {
-                                self.latencies
-                                    .push(cycles::rdtsc() - p.get_header().common_header.stamp);
+                                self.record(p.get_header().common_header.stamp(), cycles::rdtsc());
                             }
                             p.free_packet();
                         }
@@ -356,20 +371,23 @@ impl AggregateSendRecv {
                             self.recvd += 1;
                             self.outstanding -= 1;
                             if self.recvd & 0xf == 0 {
-                                self.latencies
-                                    .push(cycles::rdtsc() - p.get_header().common_header.stamp);
+                                self.record(p.get_header().common_header.stamp(), cycles::rdtsc());
                             }
-                            self.remove_request(p.get_header().common_header.stamp);
+                            self.remove_request(p.get_header().common_header.id());
                         }
 
                         RpcStatus::StatusPushback => {
-                            let records = p.get_payload();
+                            // The read-write set comes back as a trailer on the response.
+                            let flags = p.get_header().common_header.flags;
+                            let records = TrailerReader::split(flags, p.get_payload())
+                                .and_then(|(_, trailers)| trailers.get(TRAILER_RWSET))
+                                .unwrap_or(&[]);
                             let (key, record) = records.split_at(369); // 1B for type, 8B for key, and 12 * 30B for value
                             let hdr = &p.get_header();
-                            let timestamp = hdr.common_header.stamp;
+                            let id = hdr.common_header.id();
 
                             // Create task and run the generator.
-                            match self.manager.borrow_mut().remove(&timestamp) {
+                            match self.manager.borrow_mut().remove(&id) {
                                 Some(mut manager) => {
                                     manager.create_generator(Arc::clone(&self.sender));
                                     manager.update_rwset(key, 369, 8);
@@ -378,7 +396,7 @@ impl AggregateSendRecv {
                                 }
 
                                 None => {
-                                    info!("No manager with {} timestamp", timestamp);
+                                    info!("No manager with {} id", id);
                                 }
                             }
                             self.outstanding -= 1;
@@ -398,13 +416,14 @@ impl AggregateSendRecv {
         }
     }
 
-    fn add_request(&self, req: &[u8], tenant: u32, name_length: u32, id: u64) {
+    fn add_request(&self, req: &[u8], tenant: u32, name_length: u32, id: u64, stamp: u64) {
         let req = TaskManager::new(
             Arc::clone(&self.master_service),
             &req,
             tenant,
             name_length,
             id,
+            stamp,
         );
         match self.manager.borrow_mut().insert(id, req) {
             Some(_) => {
@@ -522,6 +541,7 @@ fn main() {
 
     let config = config::ClientConfig::load();
     info!("Starting up Sandstorm client with config {:?}", config);
+    setup::validate(&config, "AGGREGATE", 8, true);
 
     let masterservice = Arc::new(Master::new());
 
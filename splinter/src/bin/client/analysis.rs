@@ -45,12 +45,14 @@ use db::log::*;
 use db::master::Master;
 use db::rpc::*;
 use db::task::TaskState::*;
+use db::trailer::{TrailerReader, TRAILER_RWSET};
 use db::wireformat::*;
 
 use rand::distributions::{Normal, Sample};
 use rand::{Rng, SeedableRng, XorShiftRng};
 use rustlearn::prelude::*;
 use rustlearn::traits::SupervisedModel;
+use splinter::latency::SampleFilter;
 use splinter::manager::TaskManager;
 use splinter::*;
 use util::model::{insert_global_model, insert_model, run_ml_application, GLOBAL_MODEL, MODEL};
@@ -194,6 +196,9 @@ where
     // Time stamp in cycles at which measurement stopped.
     stop: u64,
 
+    // Drops latency samples the clock got wrong, and counts them.
+    filter: SampleFilter,
+
     // The actual Analysis workload. Required to generate keys and values for get() and put() requests.
     workload: RefCell<Analysis>,
 
@@ -321,6 +326,7 @@ where
             latencies: Vec::with_capacity(resps as usize),
             master: master,
             stop: 0,
+            filter: SampleFilter::default(),
             workload: RefCell::new(Analysis::new(
                 config.key_len,
                 config.value_len,
@@ -349,13 +355,14 @@ where
         }
     }
 
-    fn add_request(&self, req: &[u8], tenant: u32, name_length: u32, id: u64) {
+    fn add_request(&self, req: &[u8], tenant: u32, name_length: u32, id: u64, stamp: u64) {
         let req = TaskManager::new(
             Arc::clone(&self.master_service),
             &req,
             tenant,
             name_length,
             id,
+            stamp,
         );
         match self.manager.borrow_mut().insert(id, req) {
             Some(_) => {
@@ -379,14 +386,15 @@ where
         while self.outstanding < 32 {
             // Get the current time stamp so that we can determine if it is time to issue the next RPC.
             let curr = cycles::rdtsc();
+            let id = self.sender.next_id();
 
             if self.native == true {
                 // Configured to issue native RPCs, issue a regular get()/put() operation.
                 self.workload.borrow_mut().abc(
-                    |tenant, key, _ord| self.sender.send_get(tenant, 1, key, curr),
-                    |tenant, key, val, _ord| self.sender.send_put(tenant, 1, key, val, curr),
+                    |tenant, key, _ord| self.sender.send_get(tenant, 1, key, id, curr),
+                    |tenant, key, val, _ord| self.sender.send_put(tenant, 1, key, val, id, curr),
                 );
-                self.native_state.borrow_mut().entry(curr).or_insert(1);
+                self.native_state.borrow_mut().entry(id).or_insert(1);
                 self.outstanding += 1;
             } else {
                 // Configured to issue invoke() RPCs.
@@ -401,8 +409,8 @@ where
                         // extension name (8 bytes), the table id (8 bytes), the number of
                         // gets(4 bytes). Just write in the first 4 bytes of the key.
                         p_get[20..24].copy_from_slice(&key[0..4]);
-                        self.add_request(&p_get, tenant, 8, curr);
-                        self.sender.send_invoke(tenant, 8, &p_get, curr)
+                        self.add_request(&p_get, tenant, 8, id, curr);
+                        self.sender.send_invoke(tenant, 8, &p_get, id, curr)
                     },
                     |tenant, key, _val, _ord| {
                         // First 18 bytes on the payload were already pre-populated with the
@@ -410,8 +418,8 @@ where
                         // bytes). Just write in the first 4 bytes of the key. The value is anyway
                         // always zero.
                         p_put[18..22].copy_from_slice(&key[0..4]);
-                        self.add_request(&p_put, tenant, 8, curr);
-                        self.sender.send_invoke(tenant, 8, &p_put, curr)
+                        self.add_request(&p_put, tenant, 8, id, curr);
+                        self.sender.send_invoke(tenant, 8, &p_put, id, curr)
                     },
                 );
                 self.outstanding += 1;
@@ -430,6 +438,13 @@ where
         }
     }
 
+    // Records the latency of a request sent out at `stamp` that completed at `curr`.
+    fn record(&mut self, stamp: u64, curr: u64) {
+        if let Some(latency) = self.filter.sample(stamp, curr) {
+            self.latencies.push(latency);
+        }
+    }
+
     fn recv(&mut self) {
         // Don't do anything after all responses have been received.
         if self.finished == true {
@@ -452,21 +467,24 @@ where
                                 // free the packet.
                                 RpcStatus::StatusOk => {
                                     self.recvd += 1;
-                                    self.latencies
-                                        .push(curr - p.get_header().common_header.stamp);
+                                    self.record(p.get_header().common_header.stamp(), curr);
                                     self.outstanding -= 1;
-                                    self.remove_request(p.get_header().common_header.stamp);
+                                    self.remove_request(p.get_header().common_header.id());
                                 }
 
                                 // If the status is StatusAnalysis then compelete the task, add the
                                 // stamp to the latencies, and free the packet.
                                 RpcStatus::StatusPushback => {
-                                    let records = p.get_payload();
+                                    // The read-write set comes back as a trailer on the response.
+                                    let flags = p.get_header().common_header.flags;
+                                    let records = TrailerReader::split(flags, p.get_payload())
+                                        .and_then(|(_, trailers)| trailers.get(TRAILER_RWSET))
+                                        .unwrap_or(&[]);
                                     let hdr = &p.get_header();
-                                    let timestamp = hdr.common_header.stamp;
+                                    let id = hdr.common_header.id();
 
                                     // Create task and run the generator.
-                                    match self.manager.borrow_mut().remove(&timestamp) {
+                                    match self.manager.borrow_mut().remove(&id) {
                                         Some(mut manager) => {
                                             manager.create_generator(Arc::clone(&self.sender));
                                             manager.update_rwset(records, RECORD_SIZE, 30);
@@ -474,7 +492,7 @@ where
                                         }
 
                                         None => {
-                                            info!("No manager with {} timestamp", timestamp);
+                                            info!("No manager with {} id", id);
                                         }
                                     }
                                     self.outstanding -= 1;
@@ -489,18 +507,17 @@ where
                         // The opcode on the response identifies the RPC type.
                         OpCode::SandstormGetRpc => {
                             let p = packet.parse_header::<GetResponse>();
-                            self.latencies
-                                .push(curr - p.get_header().common_header.stamp);
+                            self.record(p.get_header().common_header.stamp(), curr);
                             unsafe {
                                 if self
                                     .manager
                                     .borrow()
-                                    .contains_key(&p.get_header().common_header.stamp)
+                                    .contains_key(&p.get_header().common_header.id())
                                 {
                                     let manager = self
                                         .manager
                                         .borrow_mut()
-                                        .remove(&p.get_header().common_header.stamp);
+                                        .remove(&p.get_header().common_header.id());
                                     if let Some(mut manager) = manager {
                                         self.waiting.push_back(manager);
                                     }
@@ -511,8 +528,7 @@ where
 
                         OpCode::SandstormPutRpc => {
                             let p = packet.parse_header::<PutResponse>();
-                            self.latencies
-                                .push(curr - p.get_header().common_header.stamp);
+                            self.record(p.get_header().common_header.stamp(), curr);
                             p.free_packet();
                         }
 
@@ -523,24 +539,23 @@ where
                     match parse_rpc_opcode(&packet) {
                         OpCode::SandstormGetRpc => {
                             let p = packet.parse_header::<GetResponse>();
-                            let timestamp = p.get_header().common_header.stamp;
-                            let count = *self.native_state.borrow().get(&timestamp).unwrap();
+                            let id = p.get_header().common_header.id();
+                            let timestamp = p.get_header().common_header.stamp();
+                            let count = *self.native_state.borrow().get(&id).unwrap();
                             if count == self.number as u8 {
                                 self.recvd += 1;
                                 self.outstanding -= 1;
-                                self.native_state.borrow_mut().remove(&timestamp);
+                                self.native_state.borrow_mut().remove(&id);
                             } else {
                                 self.workload.borrow_mut().abc(
                                     |tenant, key, _ord| {
-                                        self.sender.send_get(tenant, 1, key, timestamp)
+                                        self.sender.send_get(tenant, 1, key, id, timestamp)
                                     },
                                     |tenant, key, val, _ord| {
-                                        self.sender.send_put(tenant, 1, key, val, timestamp)
+                                        self.sender.send_put(tenant, 1, key, val, id, timestamp)
                                     },
                                 );
-                                if let Some(count) =
-                                    self.native_state.borrow_mut().get_mut(&timestamp)
-                                {
+                                if let Some(count) = self.native_state.borrow_mut().get_mut(&id) {
                                     *count += 1;
                                 }
                             }
@@ -562,8 +577,7 @@ where
                                                 .data()[0];
                                         }
                                     });
-                                    self.latencies
-                                        .push(cycles::rdtsc() - timestamp - response as u64);
+                                    self.record(timestamp - response as u64, cycles::rdtsc());
                                 }
 
                                 _ => {
@@ -603,7 +617,7 @@ where
             } else if taskstate == WAITING {
                 self.manager.borrow_mut().insert(manager.get_id(), manager);
             } else if taskstate == COMPLETED {
-                self.latencies.push(cycles::rdtsc() - manager.get_id());
+                self.record(manager.get_stamp(), cycles::rdtsc());
                 self.recvd += 1;
                 if cfg!(feature = "execution") {
                     self.cycle_counter.total_cycles(_time, 1);
@@ -646,6 +660,7 @@ where
 
         // Calculate & print median & tail latency only on the master thread.
         if self.master {
+            println!("Analysis {}", self.filter);
             self.latencies.sort();
 
             let m;
@@ -738,6 +753,7 @@ fn main() {
 
     let config = config::ClientConfig::load();
     info!("Starting up Sandstorm client with config {:?}", config);
+    setup::validate(&config, "ANALYSIS", 8, true);
 
     let masterservice = Arc::new(Master::new());
 
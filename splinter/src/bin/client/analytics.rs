@@ -0,0 +1,454 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+#![feature(use_extern_macros)]
+
+extern crate db;
+extern crate splinter;
+
+mod setup;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use db::config;
+use db::cycles;
+use db::e2d2::allocators::CacheAligned;
+use db::e2d2::interface::PortQueue;
+use db::e2d2::scheduler::*;
+use db::log::*;
+use db::wireformat::*;
+
+use splinter::latency::SampleFilter;
+use splinter::*;
+
+// The name of the TopK extension, and the table it is run over.
+const NAME: &str = "analytics";
+const TABLE: u64 = 1;
+
+// The tenant whose table is analyzed. Every tenant's table is filled identically.
+const TENANT: u32 = 1;
+
+// The length of the prefix of each value records are ranked by.
+const PREFIX_LEN: usize = 8;
+
+// Status written by the extension at the head of a successful response.
+const SUCCESSFUL: u8 = 0x01;
+
+// The maximum number of outstanding get() requests while dumping the table.
+const DUMP_WINDOW: u64 = 32;
+
+// The maximum number of outstanding TopK invocations. Each one scans the whole table, so keep
+// this low; it also keeps the server from ever seeing enough new requests at once to push them
+// back, which matters because a pushed back scan cannot be completed on the client.
+const INVOKE_WINDOW: u64 = 4;
+
+/// Reads the big-endian prefix of a value, padding short values with zeros.
+fn prefix(val: &[u8]) -> u64 {
+    (0..PREFIX_LEN).fold(0, |acc, idx| {
+        (acc << 8) | *val.get(idx).unwrap_or(&0) as u64
+    })
+}
+
+/// Computes the response the TopK extension should return over a dump of a table.
+///
+/// # Arguments
+///
+/// * `dump`: The (key, value prefix) pair of every record in the table.
+/// * `k`:    The number of records to return.
+///
+/// # Return
+///
+/// The expected response payload.
+fn expected(mut dump: Vec<(Vec<u8>, u64)>, k: usize) -> Vec<u8> {
+    // Largest prefix first, ties broken by the smaller key.
+    dump.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    dump.truncate(k);
+
+    let mut resp = vec![SUCCESSFUL];
+    for i in 0..4 {
+        resp.push((dump.len() >> (8 * i)) as u8);
+    }
+    for (key, p) in dump {
+        resp.extend_from_slice(&key);
+        for i in (0..PREFIX_LEN).rev() {
+            resp.push((p >> (8 * i)) as u8);
+        }
+    }
+    resp
+}
+
+/// A client that repeatedly invokes the TopK extension. In validation mode, it first dumps the
+/// table with native get()s, and then checks every response against a TopK recomputed over the
+/// dump. Validation assumes nothing else writes to the table during the run.
+struct AnalyticsSendRecv {
+    // Network stack that can actually send an RPC over the network.
+    sender: dispatch::Sender,
+
+    // The network stack required to receives RPC response packets from a network port.
+    receiver: dispatch::Receiver<CacheAligned<PortQueue>>,
+
+    // The length of the keys in the table, and the number of keys in it.
+    key_len: usize,
+    n_keys: u32,
+
+    // If true, every response is checked against the expected TopK.
+    validate: bool,
+
+    // The next key to dump, and the keys of outstanding dump requests keyed on request id.
+    next_key: u32,
+    pending: HashMap<u64, Vec<u8>>,
+
+    // The (key, value prefix) pairs dumped so far.
+    dump: Vec<(Vec<u8>, u64)>,
+
+    // The expected response payload, once the dump has completed.
+    expected: Option<Vec<u8>>,
+
+    // Payload of every TopK invocation: the extension name, table id, K, and key length.
+    payload: Vec<u8>,
+
+    // The total number of invocations to send, and the number sent and completed so far.
+    requests: u64,
+    sent: u64,
+    recvd: u64,
+    outstanding: u64,
+
+    // Responses that matched and did not match the expected TopK, and responses that were
+    // pushed back and could not be checked.
+    passed: u64,
+    failed: u64,
+    pushed: u64,
+
+    // The time-stamp at which invocations started, and sampled invocation latencies along with
+    // the samples dropped for the clock getting them wrong.
+    start: u64,
+    latencies: Vec<u64>,
+    filter: SampleFilter,
+}
+
+impl AnalyticsSendRecv {
+    /// Constructs an AnalyticsSendRecv.
+    ///
+    /// # Arguments
+    ///
+    /// * `config`:    Client configuration with the table's shape, K, and network parameters.
+    /// * `port`:      Network port on which requests will be sent.
+    /// * `reqs`:      The number of TopK invocations to be issued to the server.
+    /// * `dst_ports`: The total number of UDP ports the server is listening on.
+    /// * `recv`:      Network port on which responses will be received.
+    fn new(
+        config: &config::ClientConfig,
+        port: CacheAligned<PortQueue>,
+        reqs: u64,
+        dst_ports: u16,
+        recv: CacheAligned<PortQueue>,
+    ) -> AnalyticsSendRecv {
+        let mut payload = Vec::with_capacity(NAME.len() + 14);
+        payload.extend_from_slice(NAME.as_bytes());
+        for i in 0..8 {
+            payload.push((TABLE >> (8 * i)) as u8);
+        }
+        for i in 0..4 {
+            payload.push((config.topk >> (8 * i)) as u8);
+        }
+        payload.push(config.key_len as u8);
+        payload.push((config.key_len >> 8) as u8);
+
+        AnalyticsSendRecv {
+            sender: dispatch::Sender::new(config, port, dst_ports),
+            receiver: dispatch::Receiver::new(recv),
+            key_len: config.key_len,
+            n_keys: config.n_keys as u32,
+            validate: config.validate,
+            next_key: 1,
+            pending: HashMap::new(),
+            dump: Vec::with_capacity(config.n_keys),
+            expected: None,
+            payload: payload,
+            requests: reqs,
+            sent: 0,
+            recvd: 0,
+            outstanding: 0,
+            passed: 0,
+            failed: 0,
+            pushed: 0,
+            start: 0,
+            latencies: Vec::with_capacity(1000 * 1000),
+            filter: SampleFilter::default(),
+        }
+    }
+
+    // Returns true once the table has been dumped, or immediately if not validating.
+    fn dumped(&self) -> bool {
+        !self.validate || self.expected.is_some()
+    }
+
+    // Sends out get() requests for keys that haven't been dumped yet. The server's YCSB dataset
+    // holds keys 1 through n_keys, each one little-endian in the first four bytes of the key.
+    fn send_dump(&mut self) {
+        while self.next_key <= self.n_keys && (self.pending.len() as u64) < DUMP_WINDOW {
+            let mut key = vec![0; self.key_len];
+            for i in 0..4 {
+                key[i] = (self.next_key >> (8 * i)) as u8;
+            }
+
+            let id = self.sender.next_id();
+            self.sender
+                .send_get(TENANT, TABLE, &key, id, cycles::rdtsc());
+            self.pending.insert(id, key);
+            self.next_key += 1;
+        }
+    }
+
+    // Sends out TopK invocations, keeping at most INVOKE_WINDOW outstanding.
+    fn send_invokes(&mut self) {
+        if self.start == 0 {
+            self.start = cycles::rdtsc();
+        }
+
+        while self.sent < self.requests && self.outstanding < INVOKE_WINDOW {
+            let id = self.sender.next_id();
+            self.sender.send_invoke(
+                TENANT,
+                NAME.len() as u32,
+                &self.payload,
+                id,
+                cycles::rdtsc(),
+            );
+            self.sent += 1;
+            self.outstanding += 1;
+        }
+    }
+
+    // Handles a get() response received while dumping the table.
+    fn recv_dump(&mut self, id: u64, status: RpcStatus, payload: &[u8]) {
+        if let Some(key) = self.pending.remove(&id) {
+            if status == RpcStatus::StatusOk {
+                self.dump.push((key, prefix(payload)));
+            }
+        }
+
+        if self.next_key > self.n_keys && self.pending.is_empty() {
+            let dump = std::mem::replace(&mut self.dump, Vec::new());
+            info!("Dumped {} records from table {}", dump.len(), TABLE);
+            self.expected = Some(expected(dump, self.payload_k()));
+        }
+    }
+
+    // Handles a TopK response.
+    fn recv_invoke(&mut self, stamp: u64, status: RpcStatus, payload: &[u8]) {
+        self.recvd += 1;
+        self.outstanding -= 1;
+        if let Some(latency) = self.filter.sample(stamp, cycles::rdtsc()) {
+            self.latencies.push(latency);
+        }
+
+        match status {
+            RpcStatus::StatusOk => {
+                if let Some(ref expected) = self.expected {
+                    if payload == &expected[..] {
+                        self.passed += 1;
+                    } else {
+                        self.failed += 1;
+                        debug!("TopK mismatch, got {:?}", payload);
+                    }
+                }
+            }
+
+            RpcStatus::StatusPushback => self.pushed += 1,
+
+            _ => self.failed += 1,
+        }
+    }
+
+    // Returns the K encoded in the invoke payload.
+    fn payload_k(&self) -> usize {
+        let k = &self.payload[NAME.len() + 8..NAME.len() + 12];
+        k.iter()
+            .enumerate()
+            .fold(0, |acc, (idx, e)| acc | (*e as usize) << (idx << 3))
+    }
+
+    fn recv(&mut self) {
+        if let Some(mut resps) = self.receiver.recv_res() {
+            while let Some(packet) = resps.pop() {
+                if !self.dumped() {
+                    let p = packet.parse_header::<GetResponse>();
+                    let (id, status) = {
+                        let hdr = &p.get_header().common_header;
+                        (hdr.id(), hdr.status.clone())
+                    };
+                    self.recv_dump(id, status, p.get_payload());
+                    p.free_packet();
+                } else {
+                    let p = packet.parse_header::<InvokeResponse>();
+                    let (stamp, status) = {
+                        let hdr = &p.get_header().common_header;
+                        (hdr.stamp(), hdr.status.clone())
+                    };
+                    self.recv_invoke(stamp, status, p.get_payload());
+                    p.free_packet();
+                }
+            }
+        }
+
+        if self.recvd == self.requests && self.requests > 0 {
+            self.measurements();
+            self.requests = 0;
+        }
+    }
+
+    // Prints out the measured latency distribution, throughput, and validation results.
+    fn measurements(&mut self) {
+        let stop = cycles::rdtsc();
+
+        self.latencies.sort();
+        let median = self.latencies[self.latencies.len() / 2];
+        let tail = self.latencies[(self.latencies.len() * 99) / 100];
+
+        info!(
+            "TopK Median(ns): {} Tail(ns): {} Throughput: {}",
+            cycles::to_seconds(median) * 1e9,
+            cycles::to_seconds(tail) * 1e9,
+            self.recvd as f64 / cycles::to_seconds(stop - self.start)
+        );
+        info!("TopK {}", self.filter);
+
+        if self.validate {
+            info!(
+                "TopK Validation {} passed {} failed {} pushed back",
+                self.passed, self.failed, self.pushed
+            );
+        }
+    }
+}
+
+// Executable trait allowing AnalyticsSendRecv to be scheduled by Netbricks.
+impl Executable for AnalyticsSendRecv {
+    // Called internally by Netbricks.
+    fn execute(&mut self) {
+        if self.dumped() {
+            self.send_invokes();
+        } else {
+            self.send_dump();
+        }
+        self.recv();
+    }
+
+    fn dependencies(&mut self) -> Vec<usize> {
+        vec![]
+    }
+}
+
+/// Sets up AnalyticsSendRecv by adding it to a Netbricks scheduler.
+///
+/// # Arguments
+///
+/// * `config`:    Network related configuration such as the MAC and IP address.
+/// * `ports`:     Network port on which packets will be sent.
+/// * `scheduler`: Netbricks scheduler to which AnalyticsSendRecv will be added.
+/// * `recv`:      Network port on which packets will be received.
+fn setup_send_recv<S>(
+    config: &config::ClientConfig,
+    ports: Vec<CacheAligned<PortQueue>>,
+    scheduler: &mut S,
+    _core: i32,
+    recv: Vec<CacheAligned<PortQueue>>,
+) where
+    S: Scheduler + Sized,
+{
+    if ports.len() != 1 {
+        error!("Client should be configured with exactly 1 port!");
+        std::process::exit(1);
+    }
+
+    match scheduler.add_task(AnalyticsSendRecv::new(
+        config,
+        ports[0].clone(),
+        config.num_reqs as u64,
+        config.server_udp_ports as u16,
+        recv[0].clone(),
+    )) {
+        Ok(_) => {
+            info!(
+                "Successfully added AnalyticsSendRecv with tx queue {}.",
+                ports[0].txq()
+            );
+        }
+
+        Err(ref err) => {
+            error!("Error while adding to Netbricks pipeline {}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn main() {
+    db::env_logger::init().expect("ERROR: failed to initialize logger!");
+
+    let config = config::ClientConfig::load();
+    info!("Starting up Sandstorm client with config {:?}", config);
+    setup::validate(&config, "YCSB", 1, false);
+
+    if config.topk == 0 {
+        error!("topk must be greater than zero.");
+        std::process::exit(1);
+    }
+
+    // Based on the supplied client configuration, compute the amount of time it will take to send
+    // out `num_reqs` requests at a rate of `req_rate` requests per second.
+    let exec = config.num_reqs / config.req_rate;
+
+    // Setup Netbricks.
+    let mut net_context = setup::config_and_init_netbricks(&config);
+
+    // Setup the client pipeline.
+    net_context.start_schedulers();
+
+    // A single pipeline on core 0, so that the dump and the invocations share a view of the
+    // table.
+    let port = net_context
+        .rx_queues
+        .get(&0)
+        .expect("Failed to retrieve network port!")
+        .clone();
+
+    net_context
+        .add_pipeline_to_core(
+            0,
+            Arc::new(
+                move |send, sched: &mut StandaloneScheduler, core: i32, _sibling| {
+                    setup_send_recv(
+                        &config::ClientConfig::load(),
+                        port.clone(),
+                        sched,
+                        core,
+                        send,
+                    )
+                },
+            ),
+        ).expect("Failed to initialize send and receive side.");
+
+    // Run the client.
+    net_context.execute();
+
+    // Sleep for an amount of time approximately equal to the estimated execution time, and then
+    // shutdown the client.
+    std::thread::sleep(std::time::Duration::from_secs(exec as u64 + 10));
+
+    // Stop the client.
+    net_context.stop();
+}
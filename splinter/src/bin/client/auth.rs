@@ -15,7 +15,6 @@
 
 #![feature(use_extern_macros)]
 
-extern crate crypto;
 extern crate db;
 extern crate rand;
 extern crate sandstorm;
@@ -26,15 +25,13 @@ extern crate zipf;
 
 mod setup;
 
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::collections::{HashMap, VecDeque};
 use std::fmt::Display;
 use std::mem;
 use std::mem::transmute;
 use std::sync::Arc;
 
-use crypto::bcrypt::bcrypt;
-
 use db::config;
 use db::cyclecounter::CycleCounter;
 use db::cycles;
@@ -45,22 +42,34 @@ use db::log::*;
 use db::master::Master;
 use db::rpc::*;
 use db::task::TaskState::*;
+use db::trailer::{TrailerReader, TRAILER_RWSET};
 use db::wireformat::*;
 
-use rand::distributions::Sample;
-use rand::{Rng, SeedableRng, XorShiftRng};
-use splinter::manager::TaskManager;
+use rand::Rng;
+use sandstorm::auth::{self, Layout};
+use sandstorm::put;
+use splinter::dist;
+use splinter::latency::SampleFilter;
+use splinter::manager::{ManagerPool, TaskManager};
+use splinter::rng::WorkloadRng;
+use splinter::verify::{OpClass, RequestMeta, Verifier, Verify, VerifyOutcome};
 use splinter::*;
-use zipf::ZipfDistribution;
 
 // Flag to indicate that the client has finished sending and receiving the packets.
 static mut FINISHED: bool = false;
 
-pub const KEY_LENGTH: usize = 30;
-pub const VAL_LENGTH: usize = 72;
+pub const KEY_LENGTH: usize = auth::USERNAME_LEN;
+pub const VAL_LENGTH: usize = auth::PASSWORD_LEN;
+
+// Maximum number of requests whose metadata can be stashed for verification at any point.
+const STASH_CAPACITY: usize = 1024;
+
+// Maximum number of TaskManagers of completed invoke() requests held for reuse.
+const POOL_CAPACITY: usize = 1024;
 
-// Type: 1, KeySize: 30, ValueSize:40
-const RECORD_SIZE: usize = 71;
+// Number of responses after which the client's own cycles per response are logged, if the
+// "client" feature is enabled.
+const CLIENT_CYCLES_EPOCH: u64 = 1000000;
 
 // AUTH benchmark.
 // The benchmark is created and parameterized with `new()`. Many threads
@@ -73,11 +82,13 @@ const RECORD_SIZE: usize = 71;
 // The tests below give an example of how to use it and how to aggregate the results.
 pub struct Auth {
     put_pct: usize,
-    rng: Box<Rng>,
-    key_rng: Box<ZipfDistribution>,
-    tenant_rng: Box<ZipfDistribution>,
+    rng: WorkloadRng,
     key_buf: Vec<u8>,
     value_buf: Vec<u8>,
+
+    // The layout of the records on the server, and the bcrypt cost they are expected to be
+    // hashed at. Refer to sandstorm::auth.
+    layout: Layout,
 }
 
 impl Auth {
@@ -87,24 +98,18 @@ impl Auth {
     //  - key_len: Length of the keys to generate per get/put. Most bytes will be zero, since
     //             the benchmark poplates them from a random 32-bit value.
     //  - value_len: Length of the values to store per put. Always all zero bytes.
-    //  - n_keys: Number of keys from which random keys are drawn.
     //  - put_pct: Number between 0 and 100 indicating percent of ops that are sets.
-    //  - skew: Zipfian skew parameter. 0.99 is AUTH default.
-    //  - n_tenants: The number of tenants from which the tenant id is chosen.
-    //  - tenant_skew: The skew in the Zipfian distribution from which tenant id's are drawn.
+    //  - rng: Random numbers ops, keys, and tenant id's are drawn from. See splinter::rng.
+    //  - layout: The layout of the records on the server; see sandstorm::auth.
     // # Return
     //  A new instance of AUTH that threads can call `abc()` on to run.
     fn new(
         key_len: usize,
         value_len: usize,
-        n_keys: usize,
         put_pct: usize,
-        skew: f64,
-        n_tenants: u32,
-        tenant_skew: f64,
+        rng: WorkloadRng,
+        layout: Layout,
     ) -> Auth {
-        let seed: [u32; 4] = rand::random::<[u32; 4]>();
-
         let mut key_buf: Vec<u8> = Vec::with_capacity(key_len);
         key_buf.resize(key_len, 0);
         let mut value_buf: Vec<u8> = Vec::with_capacity(value_len);
@@ -112,16 +117,10 @@ impl Auth {
 
         Auth {
             put_pct: put_pct,
-            rng: Box::new(XorShiftRng::from_seed(seed)),
-            key_rng: Box::new(
-                ZipfDistribution::new(n_keys, skew).expect("Couldn't create key RNG."),
-            ),
-            tenant_rng: Box::new(
-                ZipfDistribution::new(n_tenants as usize, tenant_skew)
-                    .expect("Couldn't create tenant RNG."),
-            ),
+            rng: rng,
             key_buf: key_buf,
             value_buf: value_buf,
+            layout: layout,
         }
     }
 
@@ -143,10 +142,10 @@ impl Auth {
         let is_get = (self.rng.gen::<u32>() % 100) >= self.put_pct as u32;
 
         // Sample a tenant.
-        let t = self.tenant_rng.sample(&mut self.rng) as u32;
+        let t = self.rng.tenant() as u32;
 
         // Sample a key, and convert into a little endian byte array.
-        let k = self.key_rng.sample(&mut self.rng) as u32;
+        let k = self.rng.key() as u32;
         let k: [u8; 4] = unsafe { transmute(k.to_le()) };
         self.key_buf[0..mem::size_of::<u32>()].copy_from_slice(&k);
 
@@ -158,6 +157,51 @@ impl Auth {
     }
 }
 
+// Verifies native get() responses by recomputing the bcrypt hash of the password (derived from
+// the key stashed at send time) with the salt on the record returned by the server, and
+// comparing it to the hash on the record. Records hashed at a cost other than the configured one
+// fail verification.
+impl Verify for Auth {
+    fn verify(&mut self, meta: &RequestMeta, status: RpcStatus, payload: &[u8]) -> VerifyOutcome {
+        if status != RpcStatus::StatusOk {
+            return VerifyOutcome::SoftFail(format!("Response had status {:?}", status));
+        }
+
+        // Invoke() responses and pushed back tasks authenticate on the server/in the extension.
+        if meta.op != OpClass::Get {
+            return VerifyOutcome::Ok;
+        }
+
+        if payload.len() != self.layout.record_len() {
+            return VerifyOutcome::SoftFail(format!(
+                "Something is wrong with the size of the response ({} bytes)",
+                payload.len()
+            ));
+        }
+
+        let record = match auth::parse(payload) {
+            Some(record) => record,
+            None => return VerifyOutcome::SoftFail(String::from("Malformed AUTH record")),
+        };
+
+        if record.cost != self.layout.cost() {
+            return VerifyOutcome::SoftFail(format!(
+                "Record was hashed at cost {} instead of {}",
+                record.cost,
+                self.layout.cost()
+            ));
+        }
+
+        // The password is the stashed key, zero padded upto VAL_LENGTH bytes.
+        let len = std::cmp::min(meta.stash.len(), KEY_LENGTH);
+        if record.matches(&auth::password(&meta.stash[0..len])) {
+            VerifyOutcome::Ok
+        } else {
+            VerifyOutcome::SoftFail(String::from("Password hash mismatch"))
+        }
+    }
+}
+
 /// Receives responses to AUTH requests sent out by AuthSend.
 struct AuthRecvSend<T>
 where
@@ -186,6 +230,9 @@ where
     // Time stamp in cycles at which measurement stopped.
     stop: u64,
 
+    // Drops latency samples the clock got wrong, and counts them.
+    filter: SampleFilter,
+
     // The actual AUTH workload. Required to generate keys and values for get() and put() requests.
     workload: RefCell<Auth>,
 
@@ -202,6 +249,10 @@ where
     // false, invoke() based RPC requests are sent out.
     native: bool,
 
+    // The length of each record on the server, out of the configured layout. Native gets ask
+    // for the whole record, and pushed back responses hold a type byte, key and record each.
+    record_len: usize,
+
     // Payload for an invoke() based get operation. Required in order to avoid making intermediate
     // copies of the extension name, table id, and key.
     payload_auth: RefCell<Vec<u8>>,
@@ -225,6 +276,19 @@ where
     // when the server pushes back the extension.
     manager: RefCell<HashMap<u64, TaskManager>>,
 
+    // TaskManagers of completed requests. Reused for new invoke() requests instead of
+    // allocating a TaskManager and a copy of the payload per request.
+    pool: RefCell<ManagerPool>,
+
+    // Number of requests whose id was already mapped to a TaskManager.
+    duplicates: Cell<u64>,
+
+    // Number of pushed back responses that had no TaskManager to resume the extension with.
+    orphans: Cell<u64>,
+
+    // Number of responses that completed with an error.
+    failed: u64,
+
     // Run-queue of tasks waiting to execute. Tasks on this queue have either yielded, or have been
     // recently enqueued and never run before.
     waiting: VecDeque<TaskManager>,
@@ -237,10 +301,19 @@ where
     // extensions on its end.
     cycle_counter: CycleCounter,
 
-    // Keeps track of the state of a multi-operation request. For example, an extension performs
-    // four get operations before performing aggregation and all these get operations are dependent
-    // on the previous value.
-    native_state: RefCell<HashMap<u64, Vec<u8>>>,
+    // Runs the workload's verify() on every completed response before its latency is recorded.
+    // Also holds whatever the workload needs to verify a response, stashed at send time.
+    verifier: RefCell<Verifier>,
+
+    // Counts the CPU cycles this pipeline spends sending, receiving, and completing requests,
+    // per response received. Only used if the "client" feature is enabled.
+    client_counter: CycleCounter,
+
+    // The total number of CPU cycles counted by client_counter.
+    client_cycles: u64,
+
+    // The index of this pipeline among all pipelines on the client.
+    pipeline: usize,
 }
 
 // Implementation of methods on AuthRecv.
@@ -256,6 +329,8 @@ where
     /// * `resps`:  The number of responses to wait for before calculating statistics.
     /// * `master`: Boolean indicating if the receiver should make latency measurements.
     /// * `native`: If true, responses will be considered to correspond to native gets and puts.
+    /// * `pipeline`:  The index of this sender among all senders on the client.
+    /// * `pipelines`: The total number of senders on the client.
     ///
     /// # Return
     ///
@@ -270,6 +345,8 @@ where
         reqs: u64,
         dst_ports: u16,
         masterservice: Arc<Master>,
+        pipeline: usize,
+        pipelines: usize,
     ) -> AuthRecvSend<T> {
         // The payload on an invoke() based get request consists of the extensions name ("auth"),
         // the table id to perform the lookup on, key to lookup and value to compare the password.
@@ -279,68 +356,86 @@ where
         payload_auth.extend_from_slice(&unsafe { transmute::<u64, [u8; 8]>(1u64.to_le()) });
         payload_auth.resize(payload_len, 0);
 
-        // Ignore this as put_pct = 0.
-        let payload_len = "auth".as_bytes().len()
-            + mem::size_of::<u64>()
-            + mem::size_of::<u16>()
-            + KEY_LENGTH
-            + VAL_LENGTH;
-        let mut payload_put = Vec::with_capacity(payload_len);
-        payload_put.resize(payload_len, 0);
+        // The payload on an invoke() based put request consists of the extensions name ("put"),
+        // the table id to write to, the length of the key, the key, and the value to be inserted
+        // into the database. Refer to sandstorm::put.
+        let payload_put = put::payload(1, KEY_LENGTH, VAL_LENGTH);
+
+        // Requests are paced off the load the server reports on their responses, if configured.
+        let pacer = pacing::pipeline(config, pipeline);
+        let sender =
+            Arc::new(dispatch::Sender::new(config, tx_port, dst_ports).with_pacer(pacer.clone()));
+        let layout = config.auth_layout();
+
+        // The server fills the auth table invoke-only. Native gets, including those issued by
+        // extensions pushed back to the client, need it to be readable again. Every pipeline
+        // does so ahead of it's first request; responses are dropped by recv().
+        if config.auth_native_override {
+            for tenant in 1..(config.num_tenants + 1) {
+                let id = sender.next_id();
+                sender.send_table_access(tenant, 1, true, true, id, cycles::rdtsc());
+            }
+        }
 
         AuthRecvSend {
-            receiver: dispatch::Receiver::new(rx_port),
+            receiver: dispatch::Receiver::new(rx_port).with_pacer(pacer),
             responses: resps,
             start: cycles::rdtsc(),
             recvd: 0,
             latencies: Vec::with_capacity(resps as usize),
             master: master,
             stop: 0,
+            filter: SampleFilter::default(),
             workload: RefCell::new(Auth::new(
                 KEY_LENGTH,
                 VAL_LENGTH,
-                config.n_keys,
                 0, //config.put_pct,
-                config.skew,
-                config.num_tenants,
-                config.tenant_skew,
+                WorkloadRng::from_config(config, pipeline, pipelines),
+                layout,
             )),
-            sender: Arc::new(dispatch::Sender::new(config, tx_port, dst_ports)),
+            sender: sender,
             requests: reqs,
             sent: 0,
             native: !config.use_invoke,
+            record_len: layout.record_len(),
             payload_auth: RefCell::new(payload_auth),
             payload_put: RefCell::new(payload_put),
             finished: false,
             outstanding: 0,
             master_service: Arc::clone(&masterservice),
-            manager: RefCell::new(HashMap::new()),
+            manager: RefCell::new(HashMap::with_capacity(STASH_CAPACITY)),
+            pool: RefCell::new(ManagerPool::new(Arc::clone(&masterservice), POOL_CAPACITY)),
+            duplicates: Cell::new(0),
+            orphans: Cell::new(0),
+            failed: 0,
             waiting: VecDeque::new(),
             pushback_completed: 0,
             cycle_counter: CycleCounter::new(),
-            native_state: RefCell::new(HashMap::with_capacity(32)),
+            verifier: RefCell::new(Verifier::new(
+                STASH_CAPACITY,
+                cycles::cycles_per_second(),
+            )),
+            client_counter: CycleCounter::new(),
+            client_cycles: 0,
+            pipeline: pipeline,
         }
     }
 
-    fn add_request(&self, req: &[u8], tenant: u32, name_length: u32, id: u64) {
-        let req = TaskManager::new(
-            Arc::clone(&self.master_service),
-            &req,
-            tenant,
-            name_length,
-            id,
-        );
-        match self.manager.borrow_mut().insert(id, req) {
-            Some(_) => {
-                info!("Already present in the Hashmap");
-            }
-
-            None => {}
+    fn add_request(&self, req: &[u8], tenant: u32, name_length: u32, id: u64, stamp: u64) {
+        let req = self
+            .pool
+            .borrow_mut()
+            .get(&req, tenant, name_length, id, stamp);
+        if let Some(old) = self.manager.borrow_mut().insert(id, req) {
+            self.pool.borrow_mut().put(old);
+            count("Already present in the Hashmap", &self.duplicates);
         }
     }
 
     fn remove_request(&self, id: u64) {
-        self.manager.borrow_mut().remove(&id);
+        if let Some(manager) = self.manager.borrow_mut().remove(&id) {
+            self.pool.borrow_mut().put(manager);
+        }
     }
 
     fn send(&mut self) {
@@ -349,20 +444,28 @@ where
             return;
         }
 
-        while self.outstanding < 32 {
+        while self.outstanding < 32 && self.sender.room() > 0 {
             // Get the current time stamp so that we can determine if it is time to issue the next RPC.
             let curr = cycles::rdtsc();
+            let id = self.sender.next_id();
 
             if self.native == true {
-                // Configured to issue native RPCs, issue a regular get()/put() operation.
+                // Configured to issue native RPCs, issue a regular get()/put() operation. A get()
+                // only asks for the record at the head of the value.
+                let projection = (0, self.record_len as u32);
                 self.workload.borrow_mut().abc(
                     |tenant, key| {
-                        self.sender.send_get(tenant, 1, key, curr);
-                        self.native_state.borrow_mut().insert(curr, key.to_vec());
+                        self.sender
+                            .send_get_with_projection(tenant, 1, key, projection, id, curr);
+                        self.verifier
+                            .borrow_mut()
+                            .stash(id, curr, tenant, OpClass::Get, key);
                     },
                     |tenant, key, val| {
-                        self.sender.send_put(tenant, 1, key, val, curr);
-                        self.native_state.borrow_mut().insert(curr, key.to_vec());
+                        self.sender.send_put(tenant, 1, key, val, id, curr);
+                        self.verifier
+                            .borrow_mut()
+                            .stash(id, curr, tenant, OpClass::Put, key);
                     },
                 );
                 self.outstanding += 1;
@@ -372,7 +475,7 @@ where
                 let mut p_put = self.payload_put.borrow_mut();
 
                 // XXX Heavily dependent on how `Auth` creates a key. Only the first four
-                // bytes of the key matter, the rest are zero. The value is always zero.
+                // bytes of the key matter for an auth(), the rest are zero.
                 self.workload.borrow_mut().abc(
                     |tenant, key| {
                         // First 12 bytes on the payload were already pre-populated with the
@@ -380,14 +483,30 @@ where
                         // in the first 4 bytes of the key and first 4 bytes of value.
                         p_get[12..16].copy_from_slice(&key[0..4]);
                         p_get[42..46].copy_from_slice(&key[0..4]);
-                        self.add_request(&p_get, tenant, 4, curr);
-                        self.sender.send_invoke(tenant, 4, &p_get, curr)
+                        self.add_request(&p_get, tenant, 4, id, curr);
+                        self.verifier.borrow_mut().stash(
+                            id,
+                            curr,
+                            tenant,
+                            OpClass::Invoke,
+                            &key[0..4],
+                        );
+                        self.sender.send_invoke(tenant, 4, &p_get, id, curr)
                     },
-                    |tenant, key, _val| {
-                        // Ignore this as put_pct = 0.
-                        p_put[18..22].copy_from_slice(&key[0..4]);
-                        self.add_request(&p_put, tenant, 4, curr);
-                        self.sender.send_invoke(tenant, 4, &p_put, curr)
+                    |tenant, key, val| {
+                        // The extension name, the table id, and the key length were already
+                        // pre-populated on the payload. Write in the whole key and value.
+                        put::fill(&mut p_put, key, val);
+                        let name_len = put::NAME.len() as u32;
+                        self.add_request(&p_put, tenant, name_len, id, curr);
+                        self.verifier.borrow_mut().stash(
+                            id,
+                            curr,
+                            tenant,
+                            OpClass::Invoke,
+                            &key[0..4],
+                        );
+                        self.sender.send_invoke(tenant, name_len, &p_put, id, curr)
                     },
                 );
                 self.outstanding += 1;
@@ -404,6 +523,16 @@ where
                 break;
             }
         }
+
+        // Reclaim stashed metadata for requests whose responses never arrived.
+        self.verifier.borrow_mut().reclaim(cycles::rdtsc());
+    }
+
+    // Records the latency of a request sent out at `stamp` that completed at `curr`.
+    fn record(&mut self, stamp: u64, curr: u64) {
+        if let Some(latency) = self.filter.sample(stamp, curr) {
+            self.latencies.push(latency);
+        }
     }
 
     fn recv(&mut self) {
@@ -427,36 +556,62 @@ where
                                 // If the status is StatusOk then add the stamp to the latencies and
                                 // free the packet.
                                 RpcStatus::StatusOk => {
+                                    self.verifier.borrow_mut().check(
+                                        &mut *self.workload.borrow_mut(),
+                                        p.get_header().common_header.id(),
+                                        RpcStatus::StatusOk,
+                                        p.get_payload(),
+                                    );
                                     self.recvd += 1;
-                                    self.latencies
-                                        .push(curr - p.get_header().common_header.stamp);
+                                    self.record(p.get_header().common_header.stamp(), curr);
                                     self.outstanding -= 1;
-                                    self.remove_request(p.get_header().common_header.stamp);
+                                    self.remove_request(p.get_header().common_header.id());
                                 }
 
                                 // If the status is StatusPushback then compelete the task, add the
                                 // stamp to the latencies, and free the packet.
                                 RpcStatus::StatusPushback => {
-                                    let records = p.get_payload();
+                                    // The read-write set comes back as a trailer on the response.
+                                    let flags = p.get_header().common_header.flags;
+                                    let records = TrailerReader::split(flags, p.get_payload())
+                                        .and_then(|(_, trailers)| trailers.get(TRAILER_RWSET))
+                                        .unwrap_or(&[]);
                                     let hdr = &p.get_header();
-                                    let timestamp = hdr.common_header.stamp;
+                                    let id = hdr.common_header.id();
 
                                     // Create task and run the generator.
-                                    match self.manager.borrow_mut().remove(&timestamp) {
+                                    match self.manager.borrow_mut().remove(&id) {
                                         Some(mut manager) => {
                                             manager.create_generator(Arc::clone(&self.sender));
-                                            manager.update_rwset(records, RECORD_SIZE, 30);
+                                            let record_size = 1 + KEY_LENGTH + self.record_len;
+                                            manager.update_rwset(records, record_size, KEY_LENGTH);
                                             self.waiting.push_back(manager);
                                         }
 
                                         None => {
-                                            info!("No manager with {} timestamp", timestamp);
+                                            count(
+                                                "No manager for a pushed back response",
+                                                &self.orphans,
+                                            );
                                         }
                                     }
                                     self.outstanding -= 1;
                                 }
 
-                                _ => {}
+                                // Any other status completes the request with an error. Verify
+                                // it like any other response, and free the packet.
+                                ref status => {
+                                    let id = p.get_header().common_header.id();
+                                    self.verifier.borrow_mut().check(
+                                        &mut *self.workload.borrow_mut(),
+                                        id,
+                                        status.clone(),
+                                        p.get_payload(),
+                                    );
+                                    self.outstanding -= 1;
+                                    self.failed += 1;
+                                    self.remove_request(id);
+                                }
                             }
                             p.free_packet();
                         }
@@ -465,18 +620,17 @@ where
                         // The opcode on the response identifies the RPC type.
                         OpCode::SandstormGetRpc => {
                             let p = packet.parse_header::<GetResponse>();
-                            self.latencies
-                                .push(curr - p.get_header().common_header.stamp);
+                            self.record(p.get_header().common_header.stamp(), curr);
                             unsafe {
                                 if self
                                     .manager
                                     .borrow()
-                                    .contains_key(&p.get_header().common_header.stamp)
+                                    .contains_key(&p.get_header().common_header.id())
                                 {
                                     let manager = self
                                         .manager
                                         .borrow_mut()
-                                        .remove(&p.get_header().common_header.stamp);
+                                        .remove(&p.get_header().common_header.id());
                                     if let Some(mut manager) = manager {
                                         self.waiting.push_back(manager);
                                     }
@@ -487,8 +641,7 @@ where
 
                         OpCode::SandstormPutRpc => {
                             let p = packet.parse_header::<PutResponse>();
-                            self.latencies
-                                .push(curr - p.get_header().common_header.stamp);
+                            self.record(p.get_header().common_header.stamp(), curr);
                             p.free_packet();
                         }
 
@@ -503,42 +656,26 @@ where
                                 // If the status is StatusOk then add the stamp to the latencies and
                                 // free the packet.
                                 RpcStatus::StatusOk => {
-                                    let timestamp = p.get_header().common_header.stamp;
-                                    let value = p.get_payload();
-                                    if value.len() != 40 {
-                                        info!("Something is wrong with the size of the response");
-                                    } else {
-                                        let mut password: Vec<
-                                            u8,
-                                        > = vec![0; 72];
-                                        if let Some(key) =
-                                            self.native_state.borrow().get(&timestamp)
-                                        {
-                                            password[0..30].copy_from_slice(&key);
-                                        }
-                                        let hash = &value[0..24];
-                                        let salt = &value[24..40];
-
-                                        let output: &mut [u8] = &mut [0; 24];
-                                        bcrypt(1, salt, &password, output);
-
-                                        // Compare the calculated hash and DB stored hash.
-                                        let mut status: u64;
-                                        if output == hash {
-                                            status = 1;
-                                        } else {
-                                            status = 0;
-                                        }
-
-                                        self.latencies.push(cycles::rdtsc() - timestamp - status);
-                                        self.native_state.borrow_mut().remove(&timestamp);
-                                        self.recvd += 1;
-                                        self.outstanding -= 1;
-                                    }
+                                    let timestamp = p.get_header().common_header.stamp();
+                                    self.verifier.borrow_mut().check(
+                                        &mut *self.workload.borrow_mut(),
+                                        p.get_header().common_header.id(),
+                                        RpcStatus::StatusOk,
+                                        p.get_payload(),
+                                    );
+                                    self.record(timestamp, cycles::rdtsc());
+                                    self.recvd += 1;
+                                    self.outstanding -= 1;
                                 }
-                                _ => {
+                                ref status => {
+                                    self.verifier.borrow_mut().check(
+                                        &mut *self.workload.borrow_mut(),
+                                        p.get_header().common_header.id(),
+                                        status.clone(),
+                                        p.get_payload(),
+                                    );
                                     self.outstanding -= 1;
-                                    info!("Couldn't parse the response");
+                                    self.failed += 1;
                                 }
                             }
                             p.free_packet();
@@ -548,6 +685,7 @@ where
                     }
                 }
             }
+            self.receiver.recycle(packets);
         }
 
         // The moment all response packets have been received, set the value of the
@@ -573,7 +711,18 @@ where
             } else if taskstate == WAITING {
                 self.manager.borrow_mut().insert(manager.get_id(), manager);
             } else if taskstate == COMPLETED {
-                self.latencies.push(cycles::rdtsc() - manager.get_id());
+                // Pushed back tasks complete on the client; verify them like any other response,
+                // with whatever status and response the extension completed with.
+                {
+                    let (status, result) = manager.result();
+                    self.verifier.borrow_mut().check(
+                        &mut *self.workload.borrow_mut(),
+                        manager.get_id(),
+                        status,
+                        result,
+                    );
+                }
+                self.record(manager.get_stamp(), cycles::rdtsc());
                 self.recvd += 1;
                 if cfg!(feature = "execution") {
                     self.cycle_counter.total_cycles(_time, 1);
@@ -586,6 +735,7 @@ where
                         self.pushback_completed = 0;
                     }
                 }
+                self.pool.borrow_mut().put(manager);
             }
         }
 
@@ -614,8 +764,38 @@ where
             panic!("The client thread received only {} packets", self.recvd);
         }
 
+        {
+            let verifier = self.verifier.borrow();
+            println!(
+                "AUTH Verification {} passed {} soft-failed {} reclaimed",
+                verifier.passed(),
+                verifier.soft_failed(),
+                verifier.reclaimed()
+            );
+        }
+
+        println!(
+            "AUTH Bookkeeping {} duplicate-ids {} orphaned-pushbacks {} failed",
+            self.duplicates.get(),
+            self.orphans.get(),
+            self.failed
+        );
+
+        if cfg!(feature = "client") {
+            println!(
+                "AUTH Client cycles per response {}",
+                self.client_cycles / std::cmp::max(self.recvd, 1)
+            );
+        }
+
+        // Print how the window moved, if requests were paced.
+        if let Some(trajectory) = self.sender.pacing() {
+            pacing::print_trajectory("AUTH", self.pipeline, &trajectory);
+        }
+
         // Calculate & print median & tail latency only on the master thread.
         if self.master {
+            println!("AUTH {}", self.filter);
             self.latencies.sort();
 
             let m;
@@ -645,9 +825,25 @@ where
 {
     // Called internally by Netbricks.
     fn execute(&mut self) {
+        let recvd = self.recvd;
+        if cfg!(feature = "client") {
+            self.client_counter.start();
+        }
+
         self.send();
         self.recv();
         self.execute_task();
+
+        if cfg!(feature = "client") {
+            self.client_cycles += self.client_counter.stop(self.recvd - recvd);
+            if recvd / CLIENT_CYCLES_EPOCH != self.recvd / CLIENT_CYCLES_EPOCH {
+                info!(
+                    "Client cycles per response {}",
+                    self.client_counter.get_average()
+                );
+            }
+        }
+
         if self.finished == true {
             unsafe { FINISHED = true }
             return;
@@ -659,6 +855,17 @@ where
     }
 }
 
+// Counts an event that is unexpected, but not worth stopping the run for. Events are logged the
+// first time they occur, and then each time their count doubles, so that logging doesn't slow
+// down a client that sees many of them.
+fn count(event: &str, counter: &Cell<u64>) {
+    let n = counter.get() + 1;
+    counter.set(n);
+    if n.is_power_of_two() {
+        info!("{} ({} times so far)", event, n);
+    }
+}
+
 fn setup_send_recv<S>(
     ports: Vec<CacheAligned<PortQueue>>,
     scheduler: &mut S,
@@ -666,6 +873,8 @@ fn setup_send_recv<S>(
     master: bool,
     config: &config::ClientConfig,
     masterservice: Arc<Master>,
+    pipeline: usize,
+    pipelines: usize,
 ) where
     S: Scheduler + Sized,
 {
@@ -684,6 +893,8 @@ fn setup_send_recv<S>(
         config.num_reqs as u64,
         config.server_udp_ports as u16,
         masterservice,
+        pipeline,
+        pipelines,
     )) {
         Ok(_) => {
             info!(
@@ -704,6 +915,11 @@ fn main() {
 
     let config = config::ClientConfig::load();
     info!("Starting up Sandstorm client with config {:?}", config);
+    setup::validate(&config, "AUTH", 8, true);
+
+    if !config.use_invoke && !config.auth_native_override {
+        warn!("The auth table is invoke-only; native gets need auth_native_override to be set");
+    }
 
     let masterservice = Arc::new(Master::new());
 
@@ -757,6 +973,8 @@ fn main() {
                             master,
                             &config::ClientConfig::load(),
                             Arc::clone(&master_service),
+                            i,
+                            senders_receivers.len(),
                         )
                     },
                 ),
@@ -780,10 +998,20 @@ fn main() {
 
     // Stop the client.
     net_context.stop();
+
+    // When each epoch of the hot set rotation started, relative to the first request.
+    if let Some(rotation) = dist::rotation(&config) {
+        for (epoch, at) in rotation.epochs() {
+            println!("AUTH Rotation {} {}", epoch, cycles::to_seconds(at));
+        }
+    }
 }
 
 #[cfg(test)]
 mod test {
+    use sandstorm::auth::Layout;
+    use splinter::dist::KeyDistribution;
+    use splinter::rng::WorkloadRng;
     use std;
     use std::collections::HashMap;
     use std::sync::atomic::{AtomicBool, Ordering};
@@ -800,7 +1028,18 @@ mod test {
         for _ in 0..n_threads {
             let done = done.clone();
             threads.push(thread::spawn(move || {
-                let mut b = super::Auth::new(10, 100, 1000000, 5, 0.99, 1024, 0.1);
+                let mut b = super::Auth::new(
+                    10,
+                    100,
+                    5,
+                    WorkloadRng::new(
+                        1,
+                        0,
+                        KeyDistribution::Zipf(0.99).sampler(1000000, 0, 1),
+                        KeyDistribution::Zipf(0.1).sampler(1024, 0, 1),
+                    ),
+                    Layout::default(),
+                );
                 let mut n_gets = 0u64;
                 let mut n_puts = 0u64;
                 let start = Instant::now();
@@ -865,7 +1104,18 @@ mod test {
             let hist = hist.clone();
             let done = done.clone();
             threads.push(thread::spawn(move || {
-                let mut b = super::Auth::new(4, 100, n_keys, 5, 0.99, 1024, 0.1);
+                let mut b = super::Auth::new(
+                    4,
+                    100,
+                    5,
+                    WorkloadRng::new(
+                        1,
+                        0,
+                        KeyDistribution::Zipf(0.99).sampler(n_keys, 0, 1),
+                        KeyDistribution::Zipf(0.1).sampler(1024, 0, 1),
+                    ),
+                    Layout::default(),
+                );
                 let mut n_gets = 0u64;
                 let mut n_puts = 0u64;
                 let start = Instant::now();
@@ -41,6 +41,7 @@ use rand::distributions::Sample;
 use rand::{Rng, SeedableRng, XorShiftRng};
 use zipf::ZipfDistribution;
 
+use splinter::latency::SampleFilter;
 use splinter::*;
 
 // Bad benchmark.
@@ -240,6 +241,7 @@ impl Executable for BadSend {
         // If it is either time to send out a request, or if a request has never been sent out,
         // then, do so.
         if curr >= self.next || self.next == 0 {
+            let id = self.sender.next_id();
             // Configured to issue invoke() RPCs.
             let mut p_get = self.payload_get.borrow_mut();
             let mut p_bad = self.payload_bad.borrow_mut();
@@ -252,14 +254,14 @@ impl Executable for BadSend {
                     // extension name (3 bytes), and the table id (8 bytes). Just write in the
                     // first 4 bytes of the key.
                     p_get[11..15].copy_from_slice(&key[0..4]);
-                    self.sender.send_invoke(tenant, 3, &p_get, curr)
+                    self.sender.send_invoke(tenant, 3, &p_get, id, curr)
                 },
                 |tenant, key| {
                     // First 11 bytes on the payload were already pre-populated with the
                     // extension name (3 bytes), and the table id (8 bytes). Just write in the
                     // first 4 bytes of the key.
                     p_bad[11..15].copy_from_slice(&key[0..4]);
-                    self.sender.send_invoke(tenant, 3, &p_bad, curr)
+                    self.sender.send_invoke(tenant, 3, &p_bad, id, curr)
                 },
             );
 
@@ -302,6 +304,9 @@ where
 
     // Time stamp in cycles at which measurement stopped.
     stop: u64,
+
+    // Drops latency samples the clock got wrong, and counts them.
+    filter: SampleFilter,
 }
 
 // Implementation of methods on BadRecv.
@@ -330,6 +335,7 @@ where
             latencies: Vec::with_capacity(resps as usize),
             master: master,
             stop: 0,
+            filter: SampleFilter::default(),
         }
     }
 }
@@ -348,6 +354,7 @@ where
 
         // Calculate & print median & tail latency only on the master thread.
         if self.master {
+            println!("BAD {}", self.filter);
             self.latencies.sort();
 
             let m;
@@ -393,9 +400,12 @@ where
                 if self.recvd > 2 * 1000 * 1000 && self.master {
                     let curr = cycles::rdtsc();
 
+                    // The stamp was read by the sender, on another core.
                     let p = packet.parse_header::<InvokeResponse>();
-                    self.latencies
-                        .push(curr - p.get_header().common_header.stamp);
+                    let stamp = p.get_header().common_header.stamp();
+                    if let Some(latency) = self.filter.sample(stamp, curr) {
+                        self.latencies.push(latency);
+                    }
                     p.free_packet();
                 } else {
                     packet.free_packet();
@@ -497,6 +507,7 @@ fn main() {
 
     let config = config::ClientConfig::load();
     info!("Starting up Sandstorm client with config {:?}", config);
+    setup::validate(&config, "YCSB", 4, false);
 
     // Based on the supplied client configuration, compute the amount of time it will take to send
     // out `num_reqs` requests at a rate of `req_rate` requests per second.
@@ -26,7 +26,7 @@ extern crate zipf;
 mod setup;
 
 use std::cell::RefCell;
-use std::collections::{HashMap, VecDeque};
+use std::collections::HashMap;
 use std::fmt::Display;
 use std::mem;
 use std::mem::transmute;
@@ -42,11 +42,13 @@ use db::log::*;
 use db::master::Master;
 use db::rpc::*;
 use db::task::TaskState::*;
+use db::trailer::{TrailerReader, TRAILER_RWSET};
 use db::wireformat::*;
 
 use rand::distributions::{Normal, Sample};
 use rand::{Rng, SeedableRng, XorShiftRng};
-use splinter::manager::TaskManager;
+use splinter::latency::SampleFilter;
+use splinter::manager::{TaskManager, WaitQueue};
 use splinter::*;
 use zipf::ZipfDistribution;
 
@@ -192,6 +194,9 @@ where
     // Time stamp in cycles at which measurement stopped.
     stop: u64,
 
+    // Drops latency samples the clock got wrong, and counts them.
+    filter: SampleFilter,
+
     // The actual PUSHBACK workload. Required to generate keys and values for get() and put() requests.
     workload: RefCell<Pushback>,
 
@@ -232,8 +237,8 @@ where
     manager: RefCell<HashMap<u64, TaskManager>>,
 
     // Run-queue of tasks waiting to execute. Tasks on this queue have either yielded, or have been
-    // recently enqueued and never run before.
-    waiting: VecDeque<TaskManager>,
+    // recently enqueued and never run before. Tracks how long tasks wait on it and execute for.
+    waiting: WaitQueue,
 
     // Number of tasks completed on the client, after server pushback. Wraps around
     // after each 1L such tasks.
@@ -322,6 +327,7 @@ where
             latencies: Vec::with_capacity(resps as usize),
             master: master,
             stop: 0,
+            filter: SampleFilter::default(),
             workload: RefCell::new(Pushback::new(
                 config.key_len,
                 config.value_len,
@@ -341,7 +347,7 @@ where
             outstanding: 0,
             master_service: Arc::clone(&masterservice),
             manager: RefCell::new(HashMap::new()),
-            waiting: VecDeque::new(),
+            waiting: WaitQueue::new(config.pushback_queue()),
             pushback_completed: 0,
             cycle_counter: CycleCounter::new(),
             native_state: RefCell::new(HashMap::with_capacity(32)),
@@ -350,13 +356,14 @@ where
         }
     }
 
-    fn add_request(&self, req: &[u8], tenant: u32, name_length: u32, id: u64) {
+    fn add_request(&self, req: &[u8], tenant: u32, name_length: u32, id: u64, stamp: u64) {
         let req = TaskManager::new(
             Arc::clone(&self.master_service),
             &req,
             tenant,
             name_length,
             id,
+            stamp,
         );
         match self.manager.borrow_mut().insert(id, req) {
             Some(_) => {
@@ -380,14 +387,15 @@ where
         while self.outstanding < MAX_CREDIT as u64 && self.waiting.len() < MAX_CREDIT {
             // Get the current time stamp so that we can determine if it is time to issue the next RPC.
             let curr = cycles::rdtsc();
+            let id = self.sender.next_id();
 
             if self.native == true {
                 // Configured to issue native RPCs, issue a regular get()/put() operation.
                 self.workload.borrow_mut().abc(
-                    |tenant, key, _ord| self.sender.send_get(tenant, 1, key, curr),
-                    |tenant, key, val, _ord| self.sender.send_put(tenant, 1, key, val, curr),
+                    |tenant, key, _ord| self.sender.send_get(tenant, 1, key, id, curr),
+                    |tenant, key, val, _ord| self.sender.send_put(tenant, 1, key, val, id, curr),
                 );
-                self.native_state.borrow_mut().entry(curr).or_insert(1);
+                self.native_state.borrow_mut().entry(id).or_insert(1);
                 self.outstanding += 1;
             } else {
                 // Configured to issue invoke() RPCs.
@@ -409,8 +417,8 @@ where
                         // (4 bytes), and number of CPU cycles compute(4 bytes). Just write
                         // in the first 4 bytes of the key.
                         p_get[24..28].copy_from_slice(&key[0..4]);
-                        self.add_request(&p_get, tenant, 8, curr);
-                        self.sender.send_invoke(tenant, 8, &p_get, curr)
+                        self.add_request(&p_get, tenant, 8, id, curr);
+                        self.sender.send_invoke(tenant, 8, &p_get, id, curr)
                     },
                     |tenant, key, _val, _ord| {
                         // First 18 bytes on the payload were already pre-populated with the
@@ -418,8 +426,8 @@ where
                         // bytes). Just write in the first 4 bytes of the key. The value is anyway
                         // always zero.
                         p_put[18..22].copy_from_slice(&key[0..4]);
-                        self.add_request(&p_put, tenant, 8, curr);
-                        self.sender.send_invoke(tenant, 8, &p_put, curr)
+                        self.add_request(&p_put, tenant, 8, id, curr);
+                        self.sender.send_invoke(tenant, 8, &p_put, id, curr)
                     },
                 );
                 self.outstanding += 1;
@@ -431,6 +439,13 @@ where
         }
     }
 
+    // Records the latency of a request sent out at `stamp` that completed at `curr`.
+    fn record(&mut self, stamp: u64, curr: u64) {
+        if let Some(latency) = self.filter.sample(stamp, curr) {
+            self.latencies.push(latency);
+        }
+    }
+
     fn recv(&mut self) {
         // Don't do anything after all responses have been received.
         if self.finished == true {
@@ -453,29 +468,32 @@ where
                                 // free the packet.
                                 RpcStatus::StatusOk => {
                                     self.recvd += 1;
-                                    self.latencies
-                                        .push(curr - p.get_header().common_header.stamp);
+                                    self.record(p.get_header().common_header.stamp(), curr);
                                     self.outstanding -= 1;
-                                    self.remove_request(p.get_header().common_header.stamp);
+                                    self.remove_request(p.get_header().common_header.id());
                                 }
 
                                 // If the status is StatusPushback then compelete the task, add the
                                 // stamp to the latencies, and free the packet.
                                 RpcStatus::StatusPushback => {
-                                    let records = p.get_payload();
+                                    // The read-write set comes back as a trailer on the response.
+                                    let flags = p.get_header().common_header.flags;
+                                    let records = TrailerReader::split(flags, p.get_payload())
+                                        .and_then(|(_, trailers)| trailers.get(TRAILER_RWSET))
+                                        .unwrap_or(&[]);
                                     let hdr = &p.get_header();
-                                    let timestamp = hdr.common_header.stamp;
+                                    let id = hdr.common_header.id();
 
                                     // Create task and run the generator.
-                                    match self.manager.borrow_mut().remove(&timestamp) {
+                                    match self.manager.borrow_mut().remove(&id) {
                                         Some(mut manager) => {
                                             manager.create_generator(Arc::clone(&self.sender));
                                             manager.update_rwset(records, RECORD_SIZE, 30);
-                                            self.waiting.push_back(manager);
+                                            self.waiting.push(manager, curr);
                                         }
 
                                         None => {
-                                            info!("No manager with {} timestamp", timestamp);
+                                            info!("No manager with {} id", id);
                                         }
                                     }
                                     self.outstanding -= 1;
@@ -494,15 +512,15 @@ where
                                 if self
                                     .manager
                                     .borrow()
-                                    .contains_key(&p.get_header().common_header.stamp)
+                                    .contains_key(&p.get_header().common_header.id())
                                 {
                                     let manager = self
                                         .manager
                                         .borrow_mut()
-                                        .remove(&p.get_header().common_header.stamp);
+                                        .remove(&p.get_header().common_header.id());
                                     if let Some(mut manager) = manager {
                                         manager.update_rwset(p.get_payload(), RECORD_SIZE, 30);
-                                        self.waiting.push_back(manager);
+                                        self.waiting.push(manager, curr);
                                     }
                                 }
                             }
@@ -521,23 +539,22 @@ where
                     match parse_rpc_opcode(&packet) {
                         OpCode::SandstormGetRpc => {
                             let p = packet.parse_header::<GetResponse>();
-                            let timestamp = p.get_header().common_header.stamp;
-                            let count = *self.native_state.borrow().get(&timestamp).unwrap();
+                            let id = p.get_header().common_header.id();
+                            let timestamp = p.get_header().common_header.stamp();
+                            let count = *self.native_state.borrow().get(&id).unwrap();
                             if count == self.num as u8 {
                                 self.recvd += 1;
                                 let start = cycles::rdtsc();
                                 while cycles::rdtsc() - start < self.ord as u64 {}
-                                self.latencies.push(cycles::rdtsc() - timestamp);
-                                self.native_state.borrow_mut().remove(&timestamp);
+                                self.record(timestamp, cycles::rdtsc());
+                                self.native_state.borrow_mut().remove(&id);
                                 self.outstanding -= 1;
                             } else {
                                 // Send the packet with same tenantid, curr etc.
-                                let tenant = p.get_header().common_header.tenant;
+                                let tenant = p.get_header().common_header.tenant();
                                 let val = p.get_payload();
-                                self.sender.send_get(tenant, 1, &val[0..30], timestamp);
-                                if let Some(count) =
-                                    self.native_state.borrow_mut().get_mut(&timestamp)
-                                {
+                                self.sender.send_get(tenant, 1, &val[0..30], id, timestamp);
+                                if let Some(count) = self.native_state.borrow_mut().get_mut(&id) {
                                     *count += 1;
                                 }
                             }
@@ -564,16 +581,12 @@ where
             return;
         }
 
-        //Execute the pushed-back task.
-        let manager = self.waiting.pop_front();
-        if let Some(mut manager) = manager {
-            let (taskstate, _time) = manager.execute_task();
-            if taskstate == YIELDED {
-                self.waiting.push_back(manager);
-            } else if taskstate == WAITING {
+        //Execute the pushed-back task. Tasks that yield are put back on the queue.
+        if let Some((manager, taskstate, _time)) = self.waiting.execute(cycles::rdtsc()) {
+            if taskstate == WAITING {
                 self.manager.borrow_mut().insert(manager.get_id(), manager);
             } else if taskstate == COMPLETED {
-                self.latencies.push(cycles::rdtsc() - manager.get_id());
+                self.record(manager.get_stamp(), cycles::rdtsc());
                 self.recvd += 1;
                 if cfg!(feature = "execution") {
                     self.cycle_counter.total_cycles(_time, 1);
@@ -616,6 +629,7 @@ where
 
         // Calculate & print median & tail latency only on the master thread.
         if self.master {
+            println!("PUSHBACK {}", self.filter);
             self.latencies.sort();
 
             let m;
@@ -634,6 +648,35 @@ where
                 cycles::to_seconds(m) * 1e9,
                 cycles::to_seconds(t) * 1e9
             );
+
+            // Split the latency of pushed back requests into the time they waited on the client
+            // to run, and the time they ran for, and print how deep the queue got over time.
+            let label = self.waiting.policy().label();
+            let hz = cycles::cycles_per_second();
+            let queued = self.waiting.queued().to_nanos(hz);
+            let executed = self.waiting.executed().to_nanos(hz);
+            println!(
+                "PUSHBACK ({}) Queue wait {} {}",
+                label,
+                queued.percentile(0.5),
+                queued.percentile(0.99)
+            );
+            println!(
+                "PUSHBACK ({}) Execute {} {}",
+                label,
+                executed.percentile(0.5),
+                executed.percentile(0.99)
+            );
+
+            let first = self.waiting.depths().first().map_or(0, |&(stamp, _)| stamp);
+            for &(stamp, depth) in self.waiting.depths() {
+                println!(
+                    "PUSHBACK ({}) Depth {:.3} {}",
+                    label,
+                    cycles::to_seconds(stamp - first),
+                    depth
+                );
+            }
         }
     }
 }
@@ -710,6 +753,7 @@ fn main() {
 
     let config = config::ClientConfig::load();
     info!("Starting up Sandstorm client with config {:?}", config);
+    setup::validate(&config, "PUSHBACK", 8, true);
 
     let masterservice = Arc::new(Master::new());
 
@@ -100,7 +100,7 @@ impl Executable for SanitySend {
             let _ = get.read_to_end(&mut buf);
 
             // Next, construct the RPC (header and payload).
-            let hdr = InstallRequest::new(100, 4, buf.len() as u32, 0);
+            let hdr = InstallRequest::new(100, 4, buf.len() as u32, 0, 0);
             let hdr: [u8; size_of::<InstallRequest>()] = unsafe { transmute(hdr) };
             let mut req: Vec<u8> = Vec::new();
             req.extend_from_slice(&hdr);
@@ -135,7 +135,8 @@ impl Executable for SanitySend {
 
             // Send out either a put() or invoke().
             if self.native == true {
-                self.sender.send_put(100, 100, &temp, &temp, self.puts);
+                self.sender
+                    .send_put(100, 100, &temp, &temp, self.sender.next_id(), self.puts);
             } else {
                 let mut payload = Vec::new();
                 let table: [u8; 8] = unsafe { transmute(100u64.to_le()) };
@@ -144,7 +145,8 @@ impl Executable for SanitySend {
                 payload.extend_from_slice(&[8, 0]); // Key Length
                 payload.extend_from_slice(&temp); // Key
                 payload.extend_from_slice(&temp); // Value
-                self.sender.send_invoke(100, 3, &payload, self.puts);
+                self.sender
+                    .send_invoke(100, 3, &payload, self.sender.next_id(), self.puts);
             }
 
             self.puts -= 1;
@@ -158,14 +160,16 @@ impl Executable for SanitySend {
 
             // Send out either a get() or invoke().
             if self.native == true {
-                self.sender.send_get(100, 100, &temp, self.gets);
+                self.sender
+                    .send_get(100, 100, &temp, self.sender.next_id(), self.gets);
             } else {
                 let mut payload = Vec::new();
                 let table: [u8; 8] = unsafe { transmute(100u64.to_le()) };
                 payload.extend_from_slice("iget".as_bytes()); // Name
                 payload.extend_from_slice(&table); // Table Id
                 payload.extend_from_slice(&temp); // Key
-                self.sender.send_invoke(100, 4, &payload, self.gets);
+                self.sender
+                    .send_invoke(100, 4, &payload, self.sender.next_id(), self.gets);
             }
 
             self.gets -= 1;
@@ -297,6 +301,7 @@ fn main() {
 
     let config = config::ClientConfig::load();
     info!("Starting up Sandstorm client with config {:?}", config);
+    setup::validate(&config, "", 1, false);
 
     // Setup Netbricks.
     let mut net_context = setup::config_and_init_netbricks(&config);
@@ -14,10 +14,18 @@
  */
 
 use db::config::ClientConfig;
+use db::cycles;
+use db::validate::{validate_client, Client};
 
 use db::e2d2::config::{NetbricksConfiguration, PortConfiguration};
 use db::e2d2::scheduler::*;
 
+/// The core Netbricks runs the client's parent thread on.
+const NET_PRIMARY_CORE: i32 = 9;
+
+/// The cores Netbricks runs pipelines on.
+const NET_CORES: [i32; 8] = [0, 1, 2, 3, 4, 5, 6, 7];
+
 /// Returns a struct of type NetbricksConfiguration which can be used to
 /// initialize Netbricks with a default set of parameters.
 ///
@@ -33,8 +41,8 @@ fn get_default_netbricks_config(config: &ClientConfig) -> NetbricksConfiguration
     // General arguments supplied to netbricks.
     let net_config_name = String::from("client");
     let dpdk_secondary: bool = false;
-    let net_primary_core: i32 = 9;
-    let net_cores: Vec<i32> = (0i32..8i32).collect();
+    let net_primary_core: i32 = NET_PRIMARY_CORE;
+    let net_cores: Vec<i32> = NET_CORES.to_vec();
     let net_strict_cores: bool = true;
     let net_pool_size: u32 = 8192 - 1;
     let net_cache_size: u32 = 128;
@@ -84,7 +92,32 @@ fn get_default_netbricks_config(config: &ClientConfig) -> NetbricksConfiguration
 ///
 /// Netbricks context which can be used to setup and start the client.
 pub fn config_and_init_netbricks(config: &ClientConfig) -> NetBricksContext {
+    // Pick and calibrate the clock before any pipeline reads it.
+    cycles::init();
+
     // Initialize Netbricks and return a handle.
     let net_config = get_default_netbricks_config(config);
     initialize_system(&net_config).expect("Failed to initialize Netbricks")
 }
+
+/// Validates a client config against the server workload the client runs against, and exits
+/// with a report of every problem found if it cannot be used.
+///
+/// # Arguments
+///
+/// * `config`:     The config to validate.
+/// * `workload`:   The server workload the client issues requests against, ex: "YCSB".
+/// * `pipelines`:  The number of pipelines the client sends requests on.
+/// * `extensions`: True if the client loads the test extensions itself.
+pub fn validate(config: &ClientConfig, workload: &str, pipelines: usize, extensions: bool) {
+    let mut cores = NET_CORES.to_vec();
+    cores.push(NET_PRIMARY_CORE);
+
+    let client = Client {
+        workload: workload,
+        pipelines: pipelines,
+        cores: &cores,
+        extensions: extensions,
+    };
+    validate_client(config, &client).enforce("client.toml");
+}
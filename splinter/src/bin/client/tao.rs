@@ -17,8 +17,8 @@
 
 extern crate db;
 extern crate rand;
+extern crate sandstorm;
 extern crate splinter;
-extern crate zipf;
 
 mod setup;
 
@@ -34,11 +34,14 @@ use db::log::*;
 use db::rpc::parse_rpc_opcode;
 use db::wireformat::*;
 
-use rand::distributions::Sample;
-use rand::{Rng, SeedableRng, XorShiftRng};
+use rand::Rng;
 
-use zipf::ZipfDistribution;
+use sandstorm::tao::{self, OPS};
 
+use splinter::chain::{Abort, Chains, Completion, Request};
+use splinter::latency::SampleFilter;
+use splinter::rng::WorkloadRng;
+use splinter::tao_mix::TaoMix;
 use splinter::*;
 
 // Flag to indicate that the client has finished sending and receiving the packets.
@@ -47,16 +50,13 @@ static mut FINISHED: bool = false;
 // The max number of outstanding packet allowed at any time per thread.
 static MAX_OUTSTANDING: u64 = 32;
 
+// The number of associations fetched by a native assoc_get.
+const ASSOC_FETCH: usize = 4;
+
 /// This type implements the send and receive of a TAO client.
 struct TaoSendRecv {
-    /// Random number generator required to seed the Zipfian distribution.
-    random: XorShiftRng,
-
-    /// Zipfian distribution from which keys are drawn.
-    k_dist: ZipfDistribution,
-
-    /// Zipfian distribution from which tenants are drawn.
-    t_dist: ZipfDistribution,
+    /// Random numbers tenants, keys, and ops are drawn from.
+    rng: WorkloadRng,
 
     /// Flag indicating whether requests should be native (true) or invocations (false).
     native: bool,
@@ -82,13 +82,8 @@ struct TaoSendRecv {
     /// Request buffer for a native assoc_get operation. Helps reduce heap allocations.
     na_buff: Vec<u8>,
 
-    /// Request buffer for an `obj_get` invoke operation. Again, helps reduce heap allocations.
-    io_buff: Vec<u8>,
-
-    /// Request buffer for an `assoc_get` invoke operation. Again, helps reduce heap allocations.
-    ia_buff: Vec<u8>,
-
-    /// The percentage of operations that are assoc_gets. The rest are obj_gets.
+    /// The percentage of operations that are assoc_gets. The rest are obj_gets. Only used by
+    /// native runs.
     assoc_p: usize,
 
     /// The network stack required to receives RPC response packets from a network port.
@@ -112,11 +107,16 @@ struct TaoSendRecv {
     /// have been received. This vector is for the assoc_get RPC.
     a_latencies: Vec<u64>,
 
-    /// Pre-allocated vector to hold assoc keys. Required for the native mode.
-    assoc_keys: Vec<u8>,
+    /// Drops latency samples the clock got wrong, and counts them.
+    filter: SampleFilter,
+
+    /// Native assoc_gets are a get of the association list, chained with a multiget of the
+    /// associations on it.
+    chains: Chains,
 
-    /// If true and native is false, then obj_gets are sent out as native gets.
-    combine: bool,
+    /// Invoke based runs draw operations from the configured TAO mix, and account for them
+    /// per operation.
+    mix: TaoMix,
 
     // Flag to indicate if the procedure is finished or not.
     finished: bool,
@@ -124,6 +124,9 @@ struct TaoSendRecv {
     // To keep the mapping between sent and received packets. The client doesn't want to send
     // more than 32(???) outstanding packets.
     outstanding: u64,
+
+    // The index of this pipeline among all pipelines on the client.
+    pipeline: usize,
 }
 
 // Implementation of methods on TaoSendRecv.
@@ -139,6 +142,8 @@ impl TaoSendRecv {
     /// * `dst_ports`: The total number of UDP ports the server is listening on.
     /// * `config`:    Client configuration with Workload related (key and value length etc.) as
     ///                well as network related (Server and Client MAC address etc.) parameters.
+    /// * `pipeline`:  The index of this pipeline among all pipelines on the client.
+    /// * `pipelines`: The total number of pipelines on the client.
     pub fn new(
         port: CacheAligned<PortQueue>,
         resps: u64,
@@ -146,29 +151,9 @@ impl TaoSendRecv {
         send: CacheAligned<PortQueue>,
         dst_ports: u16,
         config: &config::ClientConfig,
+        pipeline: usize,
+        pipelines: usize,
     ) -> TaoSendRecv {
-        // Allocate a vector for the obj_get invoke() RPC's payload. The payload consists of the
-        // name of the extension, an opcode, the table id (8 bytes) and the key length.
-        let len = "tao".as_bytes().len() + 1 + size_of::<u64>() + 8;
-        let mut io_buff = Vec::with_capacity(len);
-
-        // Pre-populate the extension name, opcode, and table id.
-        io_buff.extend_from_slice("tao".as_bytes());
-        io_buff.extend_from_slice(&[0u8]);
-        io_buff.extend_from_slice(&unsafe { transmute::<u64, [u8; 8]>(1u64.to_le()) });
-        io_buff.resize(len, 0);
-
-        // Allocate a vector for the assoc_get invoke() RPC's payload. The payload consists of the
-        // name of the extension, an opcode, the table id (8 bytes) and the key length.
-        let len = "tao".as_bytes().len() + 1 + size_of::<u64>() + 18;
-        let mut ia_buff = Vec::with_capacity(len);
-
-        // Pre-populate the extension name, opcode, and table id.
-        ia_buff.extend_from_slice("tao".as_bytes());
-        ia_buff.extend_from_slice(&[4u8]);
-        ia_buff.extend_from_slice(&unsafe { transmute::<u64, [u8; 8]>(2u64.to_le()) });
-        ia_buff.resize(len, 0);
-
         // Allocate and init a buffer into which keys for a native obj_get will be generated.
         let mut no_buff = Vec::with_capacity(8);
         no_buff.resize(8, 0);
@@ -177,37 +162,41 @@ impl TaoSendRecv {
         let mut na_buff = Vec::with_capacity(10);
         na_buff.resize(10, 0);
 
-        // Pre-populate a vector for assoc keys.
-        let mut a_keys = Vec::with_capacity(72);
-        a_keys.resize(72, 0);
+        // Requests are paced off the load the server reports on their responses, if configured.
+        // Responses arrive on either receiver.
+        let pacer = pacing::pipeline(config, pipeline);
 
         TaoSendRecv {
-            random: XorShiftRng::from_seed(rand::random::<[u32; 4]>()),
-            k_dist: ZipfDistribution::new(config.n_keys, config.skew)
-                .expect("Failed to init key generator."),
-            t_dist: ZipfDistribution::new(config.num_tenants as usize, config.tenant_skew)
-                .expect("Failed to init tenant generator."),
+            rng: WorkloadRng::from_config(config, pipeline, pipelines),
             native: native,
             requests: config.num_reqs as u64,
             sent: 0,
             start: cycles::rdtsc(),
             stop: 0,
-            multi_rx: dispatch::Receiver::new(send.clone()),
-            sender: dispatch::Sender::new(config, send, dst_ports),
+            multi_rx: dispatch::Receiver::new(send.clone()).with_pacer(pacer.clone()),
+            sender: dispatch::Sender::new(config, send, dst_ports).with_pacer(pacer.clone()),
             no_buff: no_buff,
             na_buff: na_buff,
-            io_buff: io_buff,
-            ia_buff: ia_buff,
             assoc_p: config.assocs_p,
-            combine: config.combined,
-            receiver: dispatch::Receiver::new(port),
+            receiver: dispatch::Receiver::new(port).with_pacer(pacer),
             responses: resps,
             recvd: 0,
             o_latencies: Vec::with_capacity(2 * 1000 * 1000),
             a_latencies: Vec::with_capacity(2 * 1000 * 1000),
-            assoc_keys: a_keys,
+            filter: SampleFilter::default(),
+            chains: Chains::from_config(
+                config,
+                2 * MAX_OUTSTANDING as usize,
+                cycles::cycles_per_second(),
+            ),
+            mix: TaoMix::from_config(
+                config,
+                4 * MAX_OUTSTANDING as usize,
+                cycles::cycles_per_second(),
+            ),
             finished: false,
             outstanding: 0,
+            pipeline: pipeline,
         }
     }
 
@@ -218,12 +207,12 @@ impl TaoSendRecv {
     /// true, the op should be an obj_get.
     #[inline]
     fn sample(&mut self) -> (u32, [u8; 4], bool) {
-        let t = self.t_dist.sample(&mut self.random) as u32;
+        let t = self.rng.tenant() as u32;
 
-        let k = self.k_dist.sample(&mut self.random) as u32;
+        let k = self.rng.key() as u32;
         let k: [u8; 4] = unsafe { transmute(k.to_le()) };
 
-        let o = self.random.gen::<u32>() % 100 >= self.assoc_p as u32;
+        let o = self.rng.gen::<u32>() % 100 >= self.assoc_p as u32;
 
         (t, k, o)
     }
@@ -233,44 +222,36 @@ impl TaoSendRecv {
     /// # Arguments
     ///
     /// * `curr`: Timestamp to attach onto the RPC.
+    ///
+    /// # Return
+    /// True if the request performs an operation of the workload, false if it reserves ids for
+    /// the objects the workload adds.
     #[inline]
-    fn generate(&mut self, curr: u64) {
-        let (t, k, o) = self.sample();
-
-        match self.native {
-            // Native request.
-            true => match o {
-                true => {
-                    self.no_buff[0..size_of::<u32>()].copy_from_slice(&k);
-                    self.sender.send_get(t, 1, &self.no_buff, curr);
-                }
+    fn generate(&mut self, curr: u64) -> bool {
+        // Invoke request, drawn from the TAO mix.
+        if !self.native {
+            return self.mix.send(&self.sender, &mut self.rng, curr).is_some();
+        }
 
-                false => {
-                    self.na_buff[0..size_of::<u32>()].copy_from_slice(&k);
-                    self.sender.send_get(t, 2, &self.na_buff, curr);
-                }
-            },
-
-            // Invoke request. Add the key to the pre-populated payload.
-            false => match o {
-                true => match self.combine {
-                    true => {
-                        self.no_buff[0..size_of::<u32>()].copy_from_slice(&k);
-                        self.sender.send_get(t, 1, &self.no_buff, curr);
-                    }
+        let (t, k, o) = self.sample();
 
-                    false => {
-                        self.io_buff[12..16].copy_from_slice(&k);
-                        self.sender.send_invoke(t, 3, &self.io_buff, curr);
-                    }
-                },
+        // Native assoc_get. Lookup the association list, and then the associations on it.
+        if !o {
+            self.na_buff[0..size_of::<u32>()].copy_from_slice(&k);
+            let mut list_key = [0; tao::LIST_KEY_LEN];
+            list_key.copy_from_slice(&self.na_buff);
 
-                false => {
-                    self.ia_buff[12..16].copy_from_slice(&k);
-                    self.sender.send_invoke(t, 3, &self.ia_buff, curr);
-                }
-            },
+            let req = Request::get(t, tao::ASSOC_TABLE, &self.na_buff)
+                .then(move |list: &[u8]| assoc_multiget(t, list_key, list));
+            self.chains.send(&self.sender, req, curr);
+            return true;
         }
+
+        // Native obj_get.
+        let id = self.sender.next_id();
+        self.no_buff[0..size_of::<u32>()].copy_from_slice(&k);
+        self.sender.send_get(t, 1, &self.no_buff, id, curr);
+        true
     }
 
     /// The function which intiate the sending of the requests.
@@ -281,41 +262,45 @@ impl TaoSendRecv {
             return;
         }
 
-        // Send when the outstanding packets are less than `MAX_OUTSTANDING`.
-        while self.outstanding < MAX_OUTSTANDING {
+        // Send when the outstanding packets are less than `MAX_OUTSTANDING`, and the pacer's
+        // window (if requests are paced) has room.
+        while self.outstanding < MAX_OUTSTANDING && self.sender.room() > 0 {
             // Send out a request at the configured request rate.
             let curr = cycles::rdtsc();
 
-            self.generate(curr);
-
-            self.sent += 1;
+            if self.generate(curr) {
+                self.sent += 1;
+            }
             self.outstanding += 1;
         }
     }
 
-    fn assoc_keys(&mut self, list: &[u8]) {
-        let mut left: u64 = 0;
-        for (idx, e) in list[0..8].iter().enumerate() {
-            left |= (*e as u64) << (idx << 3);
-        }
-
-        if left > 0 {
-            left -= 1;
+    /// Accounts for a response to a native request.
+    ///
+    /// # Arguments
+    ///
+    /// * `done`:  What the response meant for the operation it belongs to.
+    /// * `stamp`: The time-stamp on the response.
+    fn finish(&mut self, done: Completion, stamp: u64) {
+        // The chain is still going, and still holds on to it's slot in the window.
+        if done == Completion::Continued {
+            return;
         }
 
-        let left: [u8; 8] = unsafe { transmute(left) };
-
-        let mut n = 0;
-        for id in list.chunks(16) {
-            let mut l = n * 18;
-            let mut r = l + 8;
-            self.assoc_keys[l..r].copy_from_slice(&left);
-
-            l = r + 2;
-            r = l + 8;
-            self.assoc_keys[l..r].copy_from_slice(&id[0..8]);
-
-            n += 1;
+        self.recvd += 1;
+        self.outstanding -= 1;
+
+        // Aborted chains are counted by self.chains, and left out of the latencies.
+        if self.recvd & 0xf == 0 {
+            let latency = match self.filter.sample(stamp, cycles::rdtsc()) {
+                Some(latency) => latency,
+                None => return,
+            };
+            match done {
+                Completion::Single => self.o_latencies.push(latency),
+                Completion::Ended(_) => self.a_latencies.push(latency),
+                _ => {}
+            }
         }
     }
 
@@ -327,17 +312,16 @@ impl TaoSendRecv {
             if let Some(mut resps) = self.receiver.recv_res() {
                 while let Some(packet) = resps.pop() {
                     self.outstanding -= 1;
-                    if self.native {
-                        let p = packet.parse_header::<GetResponse>();
-                        p.free_packet();
-                    } else {
-                        if self.combine && packet.get_payload().len() <= 50 {
+                    match parse_rpc_opcode(&packet) {
+                        OpCode::SandstormInvokeRpc => {
+                            let p = packet.parse_header::<InvokeResponse>();
+                            p.free_packet();
+                        }
+
+                        _ => {
                             let p = packet.parse_header::<GetResponse>();
                             p.free_packet();
-                            continue;
                         }
-                        let p = packet.parse_header::<InvokeResponse>();
-                        p.free_packet();
                     }
                 }
             }
@@ -359,46 +343,36 @@ impl TaoSendRecv {
         if let Some(mut resps) = self.receiver.recv_res() {
             while let Some(packet) = resps.pop() {
                 if self.native {
+                    // Responses to obj_gets are not chained. Responses to association list
+                    // lookups continue into a multiget, which ends the chain.
                     match parse_rpc_opcode(&packet) {
                         OpCode::SandstormGetRpc => {
                             let p = packet.parse_header::<GetResponse>();
-
-                            // Response to obj_get.
-                            if p.get_payload().len() < 50 {
-                                self.recvd += 1;
-                                self.outstanding -= 1;
-
-                                if self.recvd & 0xf == 0 {
-                                    self.o_latencies
-                                        .push(cycles::rdtsc() - p.get_header().common_header.stamp);
-                                }
-
-                                p.free_packet();
-                                continue;
-                            }
-
-                            self.assoc_keys(p.get_payload());
-
-                            self.sender.send_multiget(
-                                p.get_header().common_header.tenant,
-                                2,
-                                18,
-                                4,
-                                &self.assoc_keys,
-                                p.get_header().common_header.stamp,
+                            let stamp = p.get_header().common_header.stamp();
+                            let done = self.chains.complete(
+                                &self.sender,
+                                p.get_header().common_header.id(),
+                                stamp,
+                                &p.get_header().common_header.status,
+                                p.get_payload(),
+                                cycles::rdtsc(),
                             );
+                            self.finish(done, stamp);
                             p.free_packet();
                         }
 
                         OpCode::SandstormMultiGetRpc => {
-                            self.recvd += 1;
-                            self.outstanding -= 1;
-
                             let p = packet.parse_header::<MultiGetResponse>();
-                            if self.recvd & 0xf == 0 {
-                                self.a_latencies
-                                    .push(cycles::rdtsc() - p.get_header().common_header.stamp);
-                            }
+                            let stamp = p.get_header().common_header.stamp();
+                            let done = self.chains.complete(
+                                &self.sender,
+                                p.get_header().common_header.id(),
+                                stamp,
+                                &p.get_header().common_header.status,
+                                p.get_payload(),
+                                cycles::rdtsc(),
+                            );
+                            self.finish(done, stamp);
                             p.free_packet();
                         }
 
@@ -408,34 +382,54 @@ impl TaoSendRecv {
                         }
                     }
                 } else {
-                    self.recvd += 1;
+                    // obj_gets may have been sent out as native gets. Responses to reservations
+                    // hold on to a slot in the window, but are not counted as responses.
                     self.outstanding -= 1;
+                    let now = cycles::rdtsc();
+                    let op = match parse_rpc_opcode(&packet) {
+                        OpCode::SandstormGetRpc => {
+                            let p = packet.parse_header::<GetResponse>();
+                            let op = self.mix.complete(
+                                p.get_header().common_header.id(),
+                                &p.get_header().common_header.status,
+                                p.get_payload(),
+                                now,
+                            );
+                            p.free_packet();
+                            op
+                        }
 
-                    if self.combine && packet.get_payload().len() <= 50 {
-                        let p = packet.parse_header::<GetResponse>();
-                        if self.recvd & 0xf == 0 {
-                            self.o_latencies
-                                .push(cycles::rdtsc() - p.get_header().common_header.stamp);
+                        OpCode::SandstormInvokeRpc => {
+                            let p = packet.parse_header::<InvokeResponse>();
+                            let op = self.mix.complete(
+                                p.get_header().common_header.id(),
+                                &p.get_header().common_header.status,
+                                p.get_payload(),
+                                now,
+                            );
+                            p.free_packet();
+                            op
                         }
-                        p.free_packet();
-                        continue;
-                    }
 
-                    let p = packet.parse_header::<InvokeResponse>();
-                    if self.recvd & 0xf == 0 {
-                        if p.get_payload().len() < 50 {
-                            self.o_latencies
-                                .push(cycles::rdtsc() - p.get_header().common_header.stamp);
-                        } else {
-                            self.a_latencies
-                                .push(cycles::rdtsc() - p.get_header().common_header.stamp);
+                        _ => {
+                            packet.free_packet();
+                            info!("Something is wrong");
+                            None
                         }
+                    };
+
+                    if op.is_some() {
+                        self.recvd += 1;
                     }
-                    p.free_packet();
                 }
             }
         }
 
+        // Drop chains whose responses were lost. Their window slots are not released, since
+        // a late response still releases it.
+        self.chains.reclaim(cycles::rdtsc());
+        self.mix.reclaim(cycles::rdtsc());
+
         // Print out measurements after all responses have been received.
         if self.responses <= self.recvd {
             self.stop = cycles::rdtsc();
@@ -443,6 +437,37 @@ impl TaoSendRecv {
     }
 }
 
+/// Returns a multiget of the first few associations on an association list, or None if the
+/// list is empty.
+///
+/// # Arguments
+///
+/// * `tenant`:   The tenant the list belongs to.
+/// * `list_key`: The key the list was looked up with.
+/// * `list`:     The list.
+fn assoc_multiget(tenant: u32, list_key: [u8; tao::LIST_KEY_LEN], list: &[u8]) -> Option<Request> {
+    let n = tao::assoc_count(list).min(ASSOC_FETCH);
+    if n == 0 {
+        return None;
+    }
+
+    // An association's key is the list's key followed by the id of the destination object,
+    // which is the first 8 bytes of the association's record on the list.
+    let mut keys = Vec::with_capacity(n * tao::ASSOC_KEY_LEN);
+    for record in list.chunks(tao::ASSOC_LEN).take(n) {
+        keys.extend_from_slice(&list_key);
+        keys.extend_from_slice(&record[0..tao::ASSOC_KEY_LEN - tao::LIST_KEY_LEN]);
+    }
+
+    Some(Request::multiget(
+        tenant,
+        tao::ASSOC_TABLE,
+        tao::ASSOC_KEY_LEN as u16,
+        n as u32,
+        &keys,
+    ))
+}
+
 // Implementation of the Executable trait so that we can use Netbrick's DPDK bindings.
 impl Executable for TaoSendRecv {
     fn execute(&mut self) {
@@ -462,15 +487,22 @@ impl Executable for TaoSendRecv {
 impl Drop for TaoSendRecv {
     /// Prints out the measured latency distribution and throughput.
     fn drop(&mut self) {
-        self.o_latencies.sort();
-        let o_median = self.o_latencies[self.o_latencies.len() / 2];
-        let o_tail = self.o_latencies[(self.o_latencies.len() * 99) / 100];
-        let o_mean = self.o_latencies.iter().sum::<u64>() as f64 / self.o_latencies.len() as f64;
+        // Invoke based runs report obj_* and assoc_* operations together, so that the summary
+        // line reads the same as that of native runs.
+        if !self.native {
+            let filter = *self.mix.stats().filter();
+            self.filter.merge(&filter);
+            for op in OPS.iter() {
+                let count = self.mix.stats().get(*op).clone();
+                match op.name().starts_with("obj") {
+                    true => self.o_latencies.extend_from_slice(&count.latencies),
+                    false => self.a_latencies.extend_from_slice(&count.latencies),
+                }
+            }
+        }
 
-        self.a_latencies.sort();
-        let a_median = self.a_latencies[self.a_latencies.len() / 2];
-        let a_tail = self.a_latencies[(self.a_latencies.len() * 99) / 100];
-        let a_mean = self.a_latencies.iter().sum::<u64>() as f64 / self.a_latencies.len() as f64;
+        let (o_mean, o_median, o_tail) = percentiles(&mut self.o_latencies);
+        let (a_mean, a_median, a_tail) = percentiles(&mut self.a_latencies);
 
         println!(
             "AMean(ns) {} AMedian(ns): {} ATail(ns) {} OMean(ns) {} OMedian(ns): {} OTail(ns): {} Throughput(Kops/s): {}",
@@ -482,9 +514,64 @@ impl Drop for TaoSendRecv {
             cycles::to_seconds(o_tail) * 1e9,
             self.recvd as f64 / cycles::to_seconds(self.stop - self.start)
         );
+        println!("TAO {}", self.filter);
+
+        // Print how the window moved, if requests were paced.
+        if let Some(trajectory) = self.sender.pacing() {
+            pacing::print_trajectory("TAO", self.pipeline, &trajectory);
+        }
+
+        if self.native {
+            println!(
+                "Chains ended {} failed {} pushed-back {} too-deep {} reclaimed {}",
+                self.chains.ended(),
+                self.chains.aborted(Abort::Failed),
+                self.chains.aborted(Abort::Pushback),
+                self.chains.aborted(Abort::Depth),
+                self.chains.reclaimed()
+            );
+            return;
+        }
+
+        for op in OPS.iter() {
+            let count = self.mix.stats().get_mut(*op);
+            let (mean, median, tail) = count.percentiles().unwrap_or((0.0, 0, 0));
+            println!(
+                "Op {} Sent {} Completed {} Failed {} Malformed {} Mean(ns) {} Median(ns) {} Tail(ns) {}",
+                op.name(),
+                count.sent,
+                count.completed,
+                count.failed,
+                count.malformed,
+                cycles::to_seconds(mean as u64) * 1e9,
+                cycles::to_seconds(median) * 1e9,
+                cycles::to_seconds(tail) * 1e9,
+            );
+        }
+
+        let (reserved, failed) = self.mix.reservations();
+        println!(
+            "Reservations {} failed {} reclaimed {}",
+            reserved,
+            failed,
+            self.mix.reclaimed()
+        );
     }
 }
 
+/// Returns the mean, median and 99th percentile of a set of latencies, or zeros if there are
+/// none.
+fn percentiles(latencies: &mut Vec<u64>) -> (f64, u64, u64) {
+    if latencies.is_empty() {
+        return (0.0, 0, 0);
+    }
+
+    latencies.sort();
+    let n = latencies.len();
+    let mean = latencies.iter().sum::<u64>() as f64 / n as f64;
+    (mean, latencies[n / 2], latencies[(n * 99) / 100])
+}
+
 /// Sets up TaoRecv by adding it to a Netbricks scheduler.
 ///
 /// # Arguments
@@ -495,6 +582,8 @@ impl Drop for TaoSendRecv {
 ///                RPCs.
 /// * `send`:      Network port on which packets will be sent.
 /// * `config`:    Network related configuration such as the MAC and IP address.
+/// * `pipeline`:  The index of this pipeline among all pipelines on the client.
+/// * `pipelines`: The total number of pipelines on the client.
 fn setup_send_recv<S>(
     ports: Vec<CacheAligned<PortQueue>>,
     scheduler: &mut S,
@@ -502,6 +591,8 @@ fn setup_send_recv<S>(
     native: bool,
     send: Vec<CacheAligned<PortQueue>>,
     config: &config::ClientConfig,
+    pipeline: usize,
+    pipelines: usize,
 ) where
     S: Scheduler + Sized,
 {
@@ -518,6 +609,8 @@ fn setup_send_recv<S>(
         send[0].clone(),
         config.server_udp_ports as u16,
         config,
+        pipeline,
+        pipelines,
     )) {
         Ok(_) => {
             info!(
@@ -538,6 +631,7 @@ fn main() {
 
     let config = config::ClientConfig::load();
     info!("Starting up Sandstorm client with config {:?}", config);
+    setup::validate(&config, "TAO", 8, false);
 
     // Setup Netbricks.
     let mut net_context = setup::config_and_init_netbricks(&config);
@@ -576,6 +670,8 @@ fn main() {
                             native,
                             send,
                             &config::ClientConfig::load(),
+                            i,
+                            senders_receivers.len(),
                         )
                     },
                 ),
@@ -42,6 +42,7 @@ use db::wireformat::*;
 
 use rand::distributions::Sample;
 use rand::{Rng, SeedableRng, XorShiftRng};
+use splinter::latency::SampleFilter;
 use splinter::*;
 use zipf::ZipfDistribution;
 
@@ -172,6 +173,9 @@ where
     // Time stamp in cycles at which measurement stopped.
     stop: u64,
 
+    // Drops latency samples the clock got wrong, and counts them.
+    filter: SampleFilter,
+
     // The actual Ycsb workload. Required to generate keys and values for get() and put() requests.
     workload: RefCell<Ycsb>,
 
@@ -284,6 +288,7 @@ where
             latencies: Vec::with_capacity(resps as usize),
             master: master,
             stop: 0,
+            filter: SampleFilter::default(),
             workload: RefCell::new(Ycsb::new(
                 config.key_len,
                 config.value_len,
@@ -317,12 +322,13 @@ where
         while self.outstanding < 32 {
             // Get the current time stamp so that we can determine if it is time to issue the next RPC.
             let curr = cycles::rdtsc();
+            let id = self.sender.next_id();
 
             if self.native == true {
                 // Configured to issue native RPCs, issue a regular get()/put() operation.
                 self.workload.borrow_mut().abc(
-                    |tenant, key| self.sender.send_get(tenant, 1, key, curr),
-                    |tenant, key, val| self.sender.send_put(tenant, 1, key, val, curr),
+                    |tenant, key| self.sender.send_get(tenant, 1, key, id, curr),
+                    |tenant, key, val| self.sender.send_put(tenant, 1, key, val, id, curr),
                 );
                 self.outstanding += 1;
             } else {
@@ -342,13 +348,13 @@ where
                             // extension name (3 bytes), and the table id (8 bytes). Just write in the
                             // first 4 bytes of the key.
                             p_get[11..15].copy_from_slice(&key[0..4]);
-                            self.sender.send_invoke(tenant, 3, &p_get, curr)
+                            self.sender.send_invoke(tenant, 3, &p_get, id, curr)
                         } else {
                             // First 16 bytes on the payload were already pre-populated with the
                             // extension name (4 bytes), the table id (8 bytes) and range order
                             // (4 bytes). Just write in the first 4 bytes of the key.
                             p_scan[16..20].copy_from_slice(&key[0..4]);
-                            self.sender.send_invoke(tenant, 4, &p_scan, curr)
+                            self.sender.send_invoke(tenant, 4, &p_scan, id, curr)
                         }
                     },
                     |tenant, key, _val| {
@@ -357,7 +363,7 @@ where
                         // bytes). Just write in the first 4 bytes of the key. The value is anyway
                         // always zero.
                         p_put[13..17].copy_from_slice(&key[0..4]);
-                        self.sender.send_invoke(tenant, 3, &p_put, curr)
+                        self.sender.send_invoke(tenant, 3, &p_put, id, curr)
                     },
                 );
                 self.outstanding += 1;
@@ -366,6 +372,13 @@ where
         }
     }
 
+    // Records the latency of a request sent out at `stamp` whose response came in at `curr`.
+    fn record(&mut self, stamp: u64, curr: u64) {
+        if let Some(latency) = self.filter.sample(stamp, curr) {
+            self.latencies.push(latency);
+        }
+    }
+
     fn recv(&mut self) {
         // Don't do anything after all responses have been received.
         if self.responses <= self.recvd {
@@ -383,8 +396,7 @@ where
                     // The response corresponds to an invoke() RPC.
                     false => {
                         let p = packet.parse_header::<InvokeResponse>();
-                        self.latencies
-                            .push(curr - p.get_header().common_header.stamp);
+                        self.record(p.get_header().common_header.stamp(), curr);
                         p.free_packet();
                         self.outstanding -= 1;
                     }
@@ -395,8 +407,7 @@ where
                         OpCode::SandstormGetRpc => {
                             if !self.enable_scan {
                                 let p = packet.parse_header::<GetResponse>();
-                                self.latencies
-                                    .push(curr - p.get_header().common_header.stamp);
+                                self.record(p.get_header().common_header.stamp(), curr);
                                 p.free_packet();
                             } else {
                                 //TODO: Implement range-scan for native case as part of ycsb-e benchmark.
@@ -409,8 +420,7 @@ where
 
                         OpCode::SandstormPutRpc => {
                             let p = packet.parse_header::<PutResponse>();
-                            self.latencies
-                                .push(curr - p.get_header().common_header.stamp);
+                            self.record(p.get_header().common_header.stamp(), curr);
                             p.free_packet();
                             self.outstanding -= 1;
                         }
@@ -447,6 +457,7 @@ where
 
         // Calculate & print median & tail latency only on the master thread.
         if self.master {
+            println!("YCSB {}", self.filter);
             self.latencies.sort();
 
             let m;
@@ -532,6 +543,7 @@ fn main() {
 
     let config = config::ClientConfig::load();
     info!("Starting up Sandstorm client with config {:?}", config);
+    setup::validate(&config, "YCSB", 8, false);
 
     // Setup Netbricks.
     let mut net_context = setup::config_and_init_netbricks(&config);
@@ -0,0 +1,551 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! A YCSB client that talks to the server over kernel UDP sockets instead of
+//! DPDK. Meant for trying out Splinter on machines without DPDK NICs; the
+//! numbers it reports are not comparable to the DPDK clients.
+
+#![feature(use_extern_macros)]
+
+extern crate db;
+extern crate rand;
+extern crate splinter;
+extern crate zipf;
+
+use std::mem::transmute;
+use std::process;
+use std::thread;
+use std::time::Duration;
+
+use db::config;
+use db::cycles;
+use db::log::*;
+use db::validate;
+use db::wireformat::*;
+
+use rand::{Rng, SeedableRng, XorShiftRng};
+
+use splinter::dedup::Dedup;
+use splinter::dist;
+use splinter::failover::{self, Action, Monitor, Reply};
+use splinter::fault::FaultInjector;
+use splinter::latency::SampleFilter;
+use splinter::order::{Admit, KeyOrder, OrderOp};
+use splinter::pacing::{AimdConfig, Pacer};
+use splinter::preflight;
+use splinter::sample::{self, LatencySampler};
+use splinter::udp::{self, UdpReceiver, UdpSender};
+
+// The maximum number of requests a pipeline keeps outstanding at the server.
+const MAX_OUTSTANDING: u64 = 32;
+
+// The number of pipelines (threads, each with it's own socket) to run.
+const PIPELINES: u16 = 4;
+
+// How long a pipeline waits for the server to answer the echo() it checks the server with.
+const HANDSHAKE_TIMEOUT_SECS: u64 = 5;
+
+// A request generated by a pipeline. Requests can be held back to preserve per-key ordering, so
+// they carry everything needed to send them out later.
+struct Request {
+    tenant: u32,
+    key: [u8; 4],
+    put: bool,
+    id: u64,
+    stamp: u64,
+}
+
+// Sends out a request, injecting a fault into it if enabled, and watching for it's response if
+// failover is. `key` is scratch space of the configured key length.
+fn send(
+    sender: &UdpSender,
+    faults: &mut Option<FaultInjector>,
+    monitor: &mut Option<Monitor>,
+    key: &mut [u8],
+    val: &[u8],
+    req: &Request,
+) {
+    key[0..4].copy_from_slice(&req.key);
+    if let Some(ref mut monitor) = *monitor {
+        monitor.sent(req.id, cycles::rdtsc());
+    }
+
+    if let Some(ref mut faults) = *faults {
+        let mut bytes = match req.put {
+            true => udp::encode_put(req.tenant, 1, key, val, req.id, req.stamp),
+            false => udp::encode_get(req.tenant, 1, key, req.id, req.stamp),
+        };
+        faults.inject(req.id, &mut bytes);
+        sender.send_encoded(req.tenant, &bytes);
+        return;
+    }
+
+    if req.put {
+        sender.send_put(req.tenant, 1, key, val, req.id, req.stamp);
+    } else {
+        sender.send_get(req.tenant, 1, key, req.id, req.stamp);
+    }
+}
+
+// Checks that the server the sender points at serves the workload, before it is started or
+// resumed against it.
+fn handshake(
+    config: &config::ClientConfig,
+    sender: &UdpSender,
+    receiver: &UdpReceiver,
+) -> Result<(), String> {
+    let timeout = Duration::from_secs(HANDSHAKE_TIMEOUT_SECS);
+    let (served, limits) = udp::echo(sender, receiver, 1, timeout).map_err(|e| e.to_string())?;
+
+    // ycsb-udp never invokes extensions, whatever the config says.
+    let needed = match config.put_pct {
+        0 => vec![OpCode::SandstormGetRpc],
+        _ => vec![OpCode::SandstormGetRpc, OpCode::SandstormPutRpc],
+    };
+    preflight::check_opcodes(served, &needed)
+        .and_then(|_| preflight::check_sizes(preflight::ycsb_sizes(config), limits))
+}
+
+/// Runs a single closed loop YCSB pipeline over a UDP sender/receiver pair.
+///
+/// # Return
+///
+/// The number of responses received, the cycles taken, sampled latencies in cycles along with
+/// the samples dropped for the clock getting them wrong, the trajectory of the outstanding window if congestion aware pacing was enabled, the number
+/// of requests deferred for per-key ordering along with the cycles they spent deferred, and
+/// the number of requests that timed out and that were abandoned on a failover.
+fn run(
+    config: &config::ClientConfig,
+    sender: UdpSender,
+    receiver: UdpReceiver,
+    reqs: u64,
+    pipeline: usize,
+) -> (
+    u64,
+    u64,
+    (Vec<u64>, SampleFilter),
+    Vec<(u64, u32, f64)>,
+    (u64, u64),
+    (u64, u64),
+) {
+    if let Err(e) = handshake(config, &sender, &receiver) {
+        error!("Pipeline {}: {}", pipeline, e);
+        process::exit(1);
+    }
+
+    let seed: [u32; 4] = rand::random::<[u32; 4]>();
+    let mut rng = XorShiftRng::from_seed(seed);
+    let pipelines = PIPELINES as usize;
+    let mut key_rng = dist::key_sampler(config, pipeline, pipelines);
+    let mut tenant_rng =
+        dist::tenant_distribution(config).sampler(config.num_tenants as usize, pipeline, pipelines);
+
+    let mut key = vec![0; config.key_len];
+    let val = vec![0; config.value_len];
+
+    // Only sampled requests have their latency recorded; the rest are just counted.
+    let mut sampler = LatencySampler::from_config(config);
+    let mut latencies = Vec::with_capacity((reqs / sampler.rate()) as usize + 1);
+    let mut filter = SampleFilter::default();
+    let (mut sent, mut recvd, mut outstanding) = (0u64, 0u64, 0u64);
+
+    // If pacing is enabled, the window moves with the load the server reports on responses.
+    let mut pacer = match config.congestion_aware {
+        true => Some(Pacer::new(AimdConfig::from_config(
+            config,
+            MAX_OUTSTANDING as u32,
+        ))),
+        false => None,
+    };
+    let mut window = pacer
+        .as_ref()
+        .map_or(MAX_OUTSTANDING, |p| p.window() as u64);
+
+    // Set once the server reports that it is draining. No requests are sent out after that.
+    let mut draining = false;
+
+    // If enabled, puts (and gets in read-your-writes mode) to a key with an outstanding put are
+    // held back until it completes. Deferred requests count against the window. A request that
+    // could not be deferred is held here, and no new requests are generated until it is.
+    let mut order: Option<KeyOrder<Request>> = KeyOrder::from_config(config);
+    let mut held: Option<Request> = None;
+
+    // If enabled, a sampled fraction of requests are corrupted. Responses to them are checked
+    // against the statuses the server should have rejected them with, and left out of latencies.
+    let mut faults = FaultInjector::from_config(config);
+
+    // Duplicate responses are dropped before they can be counted against the window or in
+    // latencies a second time.
+    let mut dedup = Dedup::from_config(config);
+
+    // If a backup is configured, requests that go unanswered are given up on and counted as
+    // lost, and every pipeline moves to the backup together once the server stops responding.
+    let mut monitor = failover::failover(config).map(|f| Monitor::new(f, cycles::rdtsc()));
+    let mut lost = 0u64;
+
+    let start = cycles::rdtsc();
+
+    while recvd + lost < reqs && !(draining && outstanding == 0) {
+        // Send out requests until the window is full.
+        while !draining && sent < reqs && outstanding < window {
+            let curr = cycles::rdtsc();
+            let req = match held.take() {
+                Some(req) => req,
+                None => {
+                    let tenant = tenant_rng.sample(&mut rng) as u32;
+                    let k = key_rng.sample(&mut rng) as u32;
+                    let id = sender.next_id();
+                    sampler.send(id);
+                    Request {
+                        tenant: tenant,
+                        key: unsafe { transmute(k.to_le()) },
+                        put: (rng.gen::<u32>() % 100) < config.put_pct as u32,
+                        id: id,
+                        stamp: curr,
+                    }
+                }
+            };
+
+            let req = match order {
+                Some(ref mut order) => {
+                    let op = if req.put { OrderOp::Put } else { OrderOp::Get };
+                    let (k, id) = (req.key, req.id);
+                    match order.admit(&k, op, id, req, curr) {
+                        Admit::Send(req) => Some(req),
+                        Admit::Deferred => None,
+                        Admit::Full(req) => {
+                            held = Some(req);
+                            break;
+                        }
+                    }
+                }
+                None => Some(req),
+            };
+
+            if let Some(req) = req {
+                send(&sender, &mut faults, &mut monitor, &mut key, &val, &req);
+            }
+
+            sent += 1;
+            outstanding += 1;
+        }
+
+        if let Some(mut responses) = receiver.recv_res() {
+            let curr = cycles::rdtsc();
+            while let Some(response) = responses.pop() {
+                // Drop duplicates before they touch any state.
+                let fresh = response
+                    .parse_header::<RpcResponseHeader>()
+                    .map_or(true, |p| {
+                        dedup.complete(p.get_header().id(), response.opcode())
+                    });
+                if !fresh {
+                    receiver.recycle(response);
+                    continue;
+                }
+
+                // Responses to requests given up on were already counted as lost, and responses
+                // to echo() probes are not part of the workload.
+                let reply = match (
+                    monitor.as_mut(),
+                    response.parse_header::<RpcResponseHeader>(),
+                ) {
+                    (Some(monitor), Some(p)) => monitor.received(p.get_header().id(), curr),
+                    _ => Reply::Request,
+                };
+                if reply != Reply::Request {
+                    receiver.recycle(response);
+                    continue;
+                }
+
+                match response.parse_header::<RpcResponseHeader>() {
+                    Some(p) => {
+                        let (id, status) = (p.get_header().id(), &p.get_header().status);
+                        let injected = faults.as_mut().and_then(|f| f.complete(id, status));
+                        let sampled = sampler.complete(id, status);
+                        if injected == Some(false) {
+                            warn!("Injected request {} failed with status {:?}", id, status);
+                        }
+
+                        match p.get_header().status {
+                            RpcStatus::StatusOk => {}
+                            RpcStatus::StatusServerDraining => {
+                                if !draining {
+                                    info!("Server is draining, no more requests will be sent");
+                                }
+                                draining = true;
+                            }
+                            _ => debug!("Request failed with status {:?}", p.get_header().status),
+                        }
+                        if injected.is_none() {
+                            if sampled {
+                                if let Some(l) = filter.sample(p.get_header().stamp(), curr) {
+                                    latencies.push(l);
+                                }
+                            }
+
+                            if let Some(ref mut pacer) = pacer {
+                                let dst = sender.get_dst_port(p.get_header().tenant());
+                                window = pacer.observe_response(dst, p.get_header(), curr) as u64;
+                            }
+                        }
+
+                        // Send out whatever was waiting on this response.
+                        if let Some(ref mut order) = order {
+                            order.complete(p.get_header().id(), curr);
+                            while let Some(req) = order.ready() {
+                                send(&sender, &mut faults, &mut monitor, &mut key, &val, &req);
+                            }
+                        }
+                    }
+
+                    None => warn!("Received a malformed response"),
+                }
+
+                recvd += 1;
+                outstanding -= 1;
+                receiver.recycle(response);
+            }
+        }
+
+        // Give up on requests that timed out, and on the server if it stopped responding.
+        let curr = cycles::rdtsc();
+        let poll = match monitor {
+            Some(ref mut monitor) => monitor.poll(curr),
+            None => continue,
+        };
+
+        lost += poll.lost.len() as u64;
+        outstanding -= poll.lost.len() as u64;
+        if let Some(ref mut order) = order {
+            for id in poll.lost.iter() {
+                order.complete(*id, curr);
+            }
+        }
+
+        match poll.action {
+            Action::Continue => {}
+
+            Action::Probe => {
+                let id = sender.next_id();
+                sender.send_echo(1, id, curr);
+                if let Some(ref mut monitor) = monitor {
+                    monitor.probe_sent(id, curr);
+                }
+            }
+
+            Action::Switch(epoch) => {
+                let (ip, port) = failover::backup(config).expect("Failed over without a backup.");
+                info!(
+                    "Pipeline {} failing over to {}:{} (epoch {})",
+                    pipeline, ip, port, epoch
+                );
+                sender.redirect(ip, port);
+                if let Err(e) = handshake(config, &sender, &receiver) {
+                    error!("Pipeline {} can't resume on the backup: {}", pipeline, e);
+                    break;
+                }
+            }
+        }
+
+        // Send out whatever was waiting on the requests given up on.
+        if let Some(ref mut order) = order {
+            while let Some(req) = order.ready() {
+                send(&sender, &mut faults, &mut monitor, &mut key, &val, &req);
+            }
+        }
+    }
+
+    // Responses whose length did not match their payload point at truncation on the server.
+    if receiver.length_mismatches() > 0 {
+        warn!(
+            "Pipeline {} received {} responses with mismatched lengths",
+            pipeline,
+            receiver.length_mismatches()
+        );
+    }
+
+    // Duplicate responses point at a bug on the server, or a misbehaving network.
+    for (opcode, count) in dedup.counts().into_iter() {
+        println!("Duplicates {} {:?} {}", pipeline, opcode, count);
+    }
+    if dedup.excessive() {
+        warn!(
+            "Pipeline {} dropped {} duplicate responses out of {}",
+            pipeline,
+            dedup.duplicates(),
+            dedup.duplicates() + dedup.completed()
+        );
+    }
+
+    // Injected faults, and how the server responded to them. Unexpected responses are bugs.
+    if let Some(ref faults) = faults {
+        for (fault, counts) in faults.counts().into_iter() {
+            println!(
+                "Faults {} {:?} Injected {} Expected {} Unexpected {}",
+                pipeline, fault, counts.injected, counts.expected, counts.unexpected
+            );
+        }
+        if faults.unexpected() > 0 {
+            warn!(
+                "Pipeline {} received {} unexpected responses to injected faults",
+                pipeline,
+                faults.unexpected()
+            );
+        }
+    }
+
+    let trajectory = pacer.map_or(vec![], |p| p.trajectory().to_vec());
+    let deferred = order.map_or((0, 0), |o| (o.deferred(), o.delay()));
+    let losses = monitor.map_or((0, 0), |m| (m.timeouts(), m.abandoned()));
+    let elapsed = cycles::rdtsc() - start;
+    (
+        recvd,
+        elapsed,
+        (latencies, filter),
+        trajectory,
+        deferred,
+        losses,
+    )
+}
+
+fn main() {
+    db::env_logger::init().expect("ERROR: failed to initialize logger!");
+
+    let config = config::ClientConfig::load();
+    info!("Starting up Sandstorm client with config {:?}", config);
+
+    let client = validate::Client {
+        workload: "YCSB",
+        pipelines: PIPELINES as usize,
+        cores: &[],
+        extensions: false,
+    };
+    validate::validate_client(&config, &client).enforce("client.toml");
+
+    // Pick and calibrate the clock before any pipeline reads it.
+    cycles::init();
+
+    if config.transport() != config::Transport::Udp {
+        warn!("ycsb-udp always uses the udp transport; ignoring transport in client.toml.");
+    }
+
+    let reqs = config.num_reqs as u64 / PIPELINES as u64;
+    let mut threads = Vec::with_capacity(PIPELINES as usize);
+    for pipeline in 0..PIPELINES {
+        let config = config::ClientConfig::load();
+        threads.push(thread::spawn(move || {
+            let (sender, receiver) = udp::udp_pipeline(&config, pipeline, config.server_udp_ports)
+                .expect("Failed to setup udp pipeline.");
+            run(&config, sender, receiver, reqs, pipeline as usize)
+        }));
+    }
+
+    let mut latencies = Vec::new();
+    let mut filter = SampleFilter::default();
+    let (mut throughput, mut completed) = (0.0, 0);
+    let (mut deferred, mut delay) = (0, 0);
+    let (mut timeouts, mut abandoned) = (0, 0);
+    for (pipeline, thread) in threads.into_iter().enumerate() {
+        let (recvd, cycles, (mut l, f), trajectory, (d, c), (t, a)) =
+            thread.join().expect("ERROR: Thread join failed.");
+        throughput += recvd as f64 / cycles::to_seconds(cycles);
+        completed += recvd;
+        latencies.append(&mut l);
+        filter.merge(&f);
+        deferred += d;
+        delay += c;
+        timeouts += t;
+        abandoned += a;
+
+        // Time series of the window on each pipeline; time is relative to the first change.
+        if let Some(&(first, _, _)) = trajectory.first() {
+            for (stamp, window, load) in trajectory.into_iter() {
+                println!(
+                    "Window {} {} {} {}",
+                    pipeline,
+                    cycles::to_seconds(stamp - first),
+                    window,
+                    load
+                );
+            }
+        }
+    }
+
+    // The transport is part of the label so that numbers are never compared across transports.
+    let label = config::Transport::Udp.label();
+    println!("YCSB ({}) Throughput {}", label, throughput);
+    println!("YCSB ({}) {}", label, filter);
+
+    latencies.sort();
+    if latencies.len() > 0 {
+        let m = latencies[latencies.len() / 2];
+        let t = latencies[(latencies.len() * 99) / 100];
+        println!(
+            ">>> ({}) {} {}",
+            label,
+            cycles::to_seconds(m) * 1e9,
+            cycles::to_seconds(t) * 1e9
+        );
+
+        // The percentiles above are only as good as the number of samples behind them.
+        let r = sample::resolution(latencies.len() as u64, completed);
+        println!(
+            ">>> ({}) Samples {} of {} (1 in {:.1})",
+            label, r.samples, r.completed, r.rate
+        );
+    }
+
+    // When each epoch of the hot set rotation started, relative to the first request.
+    if let Some(rotation) = dist::rotation(&config) {
+        for (epoch, at) in rotation.epochs() {
+            println!(
+                "YCSB ({}) Rotation {} {}",
+                label,
+                epoch,
+                cycles::to_seconds(at)
+            );
+        }
+    }
+
+    // When the client failed over relative to the first request, how long it took to notice
+    // the server stopped responding, and how long it went without responses. Requests given up
+    // on are left out of throughput and latencies.
+    if let Some(failover) = failover::failover(&config) {
+        for switch in failover.switches() {
+            println!(
+                "YCSB ({}) Failover {} {} {} {}",
+                label,
+                switch.epoch,
+                cycles::to_seconds(switch.declared),
+                cycles::to_seconds(switch.detect),
+                cycles::to_seconds(switch.gap)
+            );
+        }
+        println!(
+            "YCSB ({}) Failover Losses Timeouts {} Abandoned {}",
+            label, timeouts, abandoned
+        );
+    }
+
+    // Latency added by per-key ordering, averaged over the requests that had to wait.
+    if config.key_order {
+        let mean = match deferred {
+            0 => 0.0,
+            n => cycles::to_seconds(delay) * 1e9 / n as f64,
+        };
+        println!("Ordering ({}) Deferred {} Delay {}", label, deferred, mean);
+    }
+}
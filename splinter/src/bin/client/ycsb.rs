@@ -17,6 +17,7 @@
 
 extern crate db;
 extern crate rand;
+extern crate sandstorm;
 extern crate time;
 extern crate splinter;
 extern crate zipf;
@@ -24,10 +25,11 @@ extern crate zipf;
 mod setup;
 
 use std::cell::RefCell;
+use std::cmp;
 use std::fmt::Display;
 use std::mem;
 use std::mem::transmute;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 use db::config;
 use db::cycles;
@@ -38,10 +40,17 @@ use db::log::*;
 use db::rpc::*;
 use db::wireformat::*;
 
-use rand::distributions::Sample;
-use rand::{Rng, SeedableRng, XorShiftRng};
-use zipf::ZipfDistribution;
+use rand::Rng;
 
+use sandstorm::put;
+
+use splinter::burst::{BurstConfig, OpenLoop};
+use splinter::cache::{self, HintCache, Lookup};
+use splinter::dedup::Dedup;
+use splinter::dist;
+use splinter::latency::{SampleFilter, ServerLatency};
+use splinter::perf::{Counts, Tracker};
+use splinter::rng::WorkloadRng;
 use splinter::*;
 
 // YCSB A, B, and C benchmark.
@@ -55,9 +64,7 @@ use splinter::*;
 // The tests below give an example of how to use it and how to aggregate the results.
 pub struct Ycsb {
     put_pct: usize,
-    rng: Box<Rng>,
-    key_rng: Box<ZipfDistribution>,
-    tenant_rng: Box<ZipfDistribution>,
+    rng: WorkloadRng,
     key_buf: Vec<u8>,
     value_buf: Vec<u8>,
 }
@@ -69,24 +76,11 @@ impl Ycsb {
     //  - key_len: Length of the keys to generate per get/put. Most bytes will be zero, since
     //             the benchmark poplates them from a random 32-bit value.
     //  - value_len: Length of the values to store per put. Always all zero bytes.
-    //  - n_keys: Number of keys from which random keys are drawn.
     //  - put_pct: Number between 0 and 100 indicating percent of ops that are sets.
-    //  - skew: Zipfian skew parameter. 0.99 is YCSB default.
-    //  - n_tenants: The number of tenants from which the tenant id is chosen.
-    //  - tenant_skew: The skew in the Zipfian distribution from which tenant id's are drawn.
+    //  - rng: Random numbers ops, keys, and tenant id's are drawn from. See splinter::rng.
     // # Return
     //  A new instance of YCSB that threads can call `abc()` on to run.
-    fn new(
-        key_len: usize,
-        value_len: usize,
-        n_keys: usize,
-        put_pct: usize,
-        skew: f64,
-        n_tenants: u32,
-        tenant_skew: f64,
-    ) -> Ycsb {
-        let seed: [u32; 4] = rand::random::<[u32; 4]>();
-
+    fn new(key_len: usize, value_len: usize, put_pct: usize, rng: WorkloadRng) -> Ycsb {
         let mut key_buf: Vec<u8> = Vec::with_capacity(key_len);
         key_buf.resize(key_len, 0);
         let mut value_buf: Vec<u8> = Vec::with_capacity(value_len);
@@ -94,14 +88,7 @@ impl Ycsb {
 
         Ycsb {
             put_pct: put_pct,
-            rng: Box::new(XorShiftRng::from_seed(seed)),
-            key_rng: Box::new(
-                ZipfDistribution::new(n_keys, skew).expect("Couldn't create key RNG."),
-            ),
-            tenant_rng: Box::new(
-                ZipfDistribution::new(n_tenants as usize, tenant_skew)
-                    .expect("Couldn't create tenant RNG."),
-            ),
+            rng: rng,
             key_buf: key_buf,
             value_buf: value_buf,
         }
@@ -125,10 +112,10 @@ impl Ycsb {
         let is_get = (self.rng.gen::<u32>() % 100) >= self.put_pct as u32;
 
         // Sample a tenant.
-        let t = self.tenant_rng.sample(&mut self.rng) as u32;
+        let t = self.rng.tenant() as u32;
 
         // Sample a key, and convert into a little endian byte array.
-        let k = self.key_rng.sample(&mut self.rng) as u32;
+        let k = self.rng.key() as u32;
         let k: [u8; 4] = unsafe { transmute(k.to_le()) };
         self.key_buf[0..mem::size_of::<u32>()].copy_from_slice(&k);
 
@@ -151,18 +138,13 @@ struct YcsbSend {
     // Total number of requests to be sent out.
     requests: u64,
 
-    // Number of requests that have been sent out so far.
-    sent: u64,
-
-    // The inverse of the rate at which requests are to be generated. Basically, the time interval
-    // between two request generations in cycles.
-    rate_inv: u64,
-
-    // The time stamp at which the workload started generating requests in cycles.
-    start: u64,
+    // The times at which requests are to be generated, at the configured rate, starting from
+    // when the workload was created. Also counts the requests generated so far.
+    schedule: OpenLoop,
 
-    // The time stamp at which the next request must be issued in cycles.
-    next: u64,
+    // The most requests sent out the network together. Requests that are due are generated back
+    // to back, upto these many at a time.
+    burst: u64,
 
     // If true, RPC requests corresponding to native get() and put() operations are sent out. If
     // false, invoke() based RPC requests are sent out.
@@ -175,6 +157,21 @@ struct YcsbSend {
     // Payload for an invoke() based put operation. Required in order to avoid making intermediate
     // copies of the extension name, table id, key length, key, and value.
     payload_put: RefCell<Vec<u8>>,
+
+    // If true, an echo() is sent out before the first request to learn the rate of the server's
+    // clock.
+    server_stamps: bool,
+
+    // Objects the server hinted hot, shared with the pipeline's receiver. Native gets of them are
+    // served off the cache. None if caching is off, or requests are invoke() based.
+    cache: Option<Arc<Mutex<HintCache>>>,
+
+    // The index of this sender among all senders on the client.
+    pipeline: usize,
+
+    // The staleness budget on gets, in microseconds. Only objects cached within it are served off
+    // the cache.
+    staleness_us: u32,
 }
 
 // Implementation of methods on YcsbSend.
@@ -188,6 +185,8 @@ impl YcsbSend {
     /// * `port`:      Network port over which requests will be sent out.
     /// * `reqs`:      The number of requests to be issued to the server.
     /// * `dst_ports`: The total number of UDP ports the server is listening on.
+    /// * `pipeline`:  The index of this sender among all senders on the client.
+    /// * `pipelines`: The total number of senders on the client.
     ///
     /// # Return
     ///
@@ -197,6 +196,8 @@ impl YcsbSend {
         port: CacheAligned<PortQueue>,
         reqs: u64,
         dst_ports: u16,
+        pipeline: usize,
+        pipelines: usize,
     ) -> YcsbSend {
         // The payload on an invoke() based get request consists of the extensions name ("get"),
         // the table id to perform the lookup on, and the key to lookup.
@@ -207,40 +208,147 @@ impl YcsbSend {
         payload_get.resize(payload_len, 0);
 
         // The payload on an invoke() based put request consists of the extensions name ("put"),
-        // the table id to perform the lookup on, the length of the key to lookup, the key, and the
-        // value to be inserted into the database.
-        let payload_len = "put".as_bytes().len()
-            + mem::size_of::<u64>()
-            + mem::size_of::<u16>()
-            + config.key_len
-            + config.value_len;
-        let mut payload_put = Vec::with_capacity(payload_len);
-        payload_put.extend_from_slice("put".as_bytes());
-        payload_put.extend_from_slice(&unsafe { transmute::<u64, [u8; 8]>(1u64.to_le()) });
-        payload_put.extend_from_slice(&unsafe {
-            transmute::<u16, [u8; 2]>((config.key_len as u16).to_le())
-        });
-        payload_put.resize(payload_len, 0);
+        // the table id to write to, the length of the key, the key, and the value to be inserted
+        // into the database. Refer to sandstorm::put.
+        let payload_put = put::payload(1, config.key_len, config.value_len);
+
+        let burst = BurstConfig::from_config(config, cycles::cycles_per_second());
 
         YcsbSend {
             workload: RefCell::new(Ycsb::new(
                 config.key_len,
                 config.value_len,
-                config.n_keys,
                 config.put_pct,
-                config.skew,
-                config.num_tenants,
-                config.tenant_skew,
+                WorkloadRng::from_config(config, pipeline, pipelines),
             )),
-            sender: dispatch::Sender::new(config, port, dst_ports),
+            sender: dispatch::Sender::new(config, port, dst_ports)
+                .with_burst(burst)
+                .with_pacer(pacing::pipeline(config, pipeline)),
             requests: reqs,
-            sent: 0,
-            rate_inv: cycles::cycles_per_second() / config.req_rate as u64,
-            start: cycles::rdtsc(),
-            next: 0,
+            schedule: OpenLoop::new(
+                cycles::rdtsc(),
+                cycles::cycles_per_second() / config.req_rate as u64,
+            ),
+            burst: burst.max_len as u64,
             native: !config.use_invoke,
             payload_get: RefCell::new(payload_get),
             payload_put: RefCell::new(payload_put),
+            server_stamps: config.server_stamps,
+            cache: match config.use_invoke {
+                false => cache::pipeline(config, pipeline),
+                true => None,
+            },
+            pipeline: pipeline,
+            staleness_us: config.get_staleness_us,
+        }
+    }
+
+    /// Generates a get() or put() request, and hands it to the sender.
+    ///
+    /// # Arguments
+    ///
+    /// * `curr`: The time-stamp at which the request is being generated.
+    fn send(&self, curr: u64) {
+        let id = self.sender.next_id();
+        if self.native == true {
+            // Configured to issue native RPCs, issue a regular get()/put() operation.
+            self.workload.borrow_mut().abc(
+                |tenant, key| match self.cache {
+                    Some(ref cache) => self.send_cached(cache, tenant, key, id, curr),
+                    None => self.sender.send_get(tenant, 1, key, id, curr),
+                },
+                |tenant, key, val| {
+                    // Drop the object before the put goes out, so that it isn't served stale.
+                    if let Some(ref cache) = self.cache {
+                        cache.lock().unwrap().invalidate(tenant, 1, key);
+                    }
+                    self.sender.send_put(tenant, 1, key, val, id, curr)
+                },
+            );
+        } else {
+            // Configured to issue invoke() RPCs.
+            let mut p_get = self.payload_get.borrow_mut();
+            let mut p_put = self.payload_put.borrow_mut();
+
+            // XXX Heavily dependent on how `Ycsb` creates a key. Only the first four
+            // bytes of the key matter for a get(), the rest are zero.
+            self.workload.borrow_mut().abc(
+                |tenant, key| {
+                    // First 11 bytes on the payload were already pre-populated with the
+                    // extension name (3 bytes), and the table id (8 bytes). Just write in the
+                    // first 4 bytes of the key.
+                    p_get[11..15].copy_from_slice(&key[0..4]);
+                    self.sender.send_invoke(tenant, 3, &p_get, id, curr)
+                },
+                |tenant, key, val| {
+                    // The extension name, the table id, and the key length were already
+                    // pre-populated on the payload. Write in the whole key and value, which
+                    // can be of any length.
+                    put::fill(&mut p_put, key, val);
+                    let name_len = put::NAME.len() as u32;
+                    self.sender.send_invoke(tenant, name_len, &p_put, id, curr)
+                },
+            );
+        }
+    }
+
+    /// Serves a native get() off the cache if the object is cached, it's hint has not run out,
+    /// and it is within the get()'s staleness budget. Otherwise sends out the get(), or a
+    /// version-only multiget() to revalidate the object.
+    ///
+    /// # Arguments
+    ///
+    /// * `cache`:  The pipeline's cache.
+    /// * `tenant`: The tenant the get() is issued for.
+    /// * `key`:    The key the get() looks up.
+    /// * `id`:     The id of the request.
+    /// * `curr`:   The time-stamp at which the request is being generated.
+    fn send_cached(&self, cache: &Mutex<HintCache>, tenant: u32, key: &[u8], id: u64, curr: u64) {
+        // The request is tracked before it goes out, so that the receiver can't see it's
+        // response first.
+        let lookup = {
+            let mut cache = cache.lock().unwrap();
+            let lookup = cache.lookup(tenant, 1, key, self.staleness_us, curr);
+            match lookup {
+                Lookup::Hit(_, _) => cache.record_hit(cycles::rdtsc() - curr),
+                _ => cache.track(id, tenant, 1, key),
+            }
+            lookup
+        };
+
+        // Send outside the lock, so that the receiver isn't held up on the network.
+        match lookup {
+            Lookup::Hit(_, _) => {}
+
+            Lookup::Revalidate => {
+                let k_len = key.len() as u16;
+                let flags = MULTIGET_FLAG_VERSIONS_ONLY;
+                self.sender
+                    .send_multiget_with_flags(tenant, 1, k_len, 1, key, flags, id, curr)
+            }
+
+            Lookup::Miss => self.sender.send_get(tenant, 1, key, id, curr),
+        }
+    }
+}
+
+// Implementation of the `Drop` trait on YcsbSend.
+impl Drop for YcsbSend {
+    fn drop(&mut self) {
+        // Print how requests were batched only if they were held back at all.
+        if self.burst > 1 {
+            let bursts = self.sender.bursts();
+            println!(
+                "YCSB Bursts {} {} {}",
+                bursts.average_len(),
+                bursts.held,
+                cycles::to_seconds(bursts.average_hold() as u64) * 1e9
+            );
+        }
+
+        // Print how the window moved, if requests were paced.
+        if let Some(trajectory) = self.sender.pacing() {
+            pacing::print_trajectory("YCSB", self.pipeline, &trajectory);
         }
     }
 }
@@ -249,53 +357,39 @@ impl YcsbSend {
 impl Executable for YcsbSend {
     // Called internally by Netbricks.
     fn execute(&mut self) {
+        // Send out requests held back on a burst if they were held too long, even if no more
+        // requests are due.
+        self.sender.poll();
+
         // Return if there are no more requests to generate.
-        if self.requests <= self.sent {
+        if self.requests <= self.schedule.issued() {
             return;
         }
 
         // Get the current time stamp so that we can determine if it is time to issue the next RPC.
         let curr = cycles::rdtsc();
 
-        // If it is either time to send out a request, or if a request has never been sent out,
-        // then, do so.
-        if curr >= self.next || self.next == 0 {
-            if self.native == true {
-                // Configured to issue native RPCs, issue a regular get()/put() operation.
-                self.workload.borrow_mut().abc(
-                    |tenant, key| self.sender.send_get(tenant, 1, key, curr),
-                    |tenant, key, val| self.sender.send_put(tenant, 1, key, val, curr),
-                );
-            } else {
-                // Configured to issue invoke() RPCs.
-                let mut p_get = self.payload_get.borrow_mut();
-                let mut p_put = self.payload_put.borrow_mut();
-
-                // XXX Heavily dependent on how `Ycsb` creates a key. Only the first four
-                // bytes of the key matter, the rest are zero. The value is always zero.
-                self.workload.borrow_mut().abc(
-                    |tenant, key| {
-                        // First 11 bytes on the payload were already pre-populated with the
-                        // extension name (3 bytes), and the table id (8 bytes). Just write in the
-                        // first 4 bytes of the key.
-                        p_get[11..15].copy_from_slice(&key[0..4]);
-                        self.sender.send_invoke(tenant, 3, &p_get, curr)
-                    },
-                    |tenant, key, _val| {
-                        // First 13 bytes on the payload were already pre-populated with the
-                        // extension name (3 bytes), the table id (8 bytes), and the key length (2
-                        // bytes). Just write in the first 4 bytes of the key. The value is anyway
-                        // always zero.
-                        p_put[13..17].copy_from_slice(&key[0..4]);
-                        self.sender.send_invoke(tenant, 3, &p_put, curr)
-                    },
-                );
-            }
+        // Before the first request, ask the server for the rate of it's clock so that the
+        // time-stamps it puts on responses can be converted.
+        if self.schedule.issued() == 0 && self.server_stamps {
+            self.sender.send_echo(1, self.sender.next_id(), curr);
+        }
+
+        // Generate every request that is due, back to back, upto a burst's worth. Requests are
+        // due at fixed intervals from when the workload started, so requests that were
+        // generated late (or held back on a burst, or by the pacer's window) don't hold back the
+        // ones after them.
+        let left = self.requests - self.schedule.issued();
+        let max = cmp::min(cmp::min(left, self.burst), self.sender.room());
+        let due = self.schedule.due(curr, max);
+        for _ in 0..due {
+            self.send(curr);
+        }
+        self.schedule.issue(due);
 
-            // Update the time stamp at which the next request should be generated, assuming that
-            // the first request was sent out at self.start.
-            self.sent += 1;
-            self.next = self.start + self.sent * self.rate_inv;
+        // Don't hold back the last few requests.
+        if self.requests <= self.schedule.issued() {
+            self.sender.flush();
         }
     }
 
@@ -334,6 +428,37 @@ where
 
     // Time stamp in cycles at which measurement stopped.
     stop: u64,
+
+    // Latency split into server-side and network latency, sampled off responses that carry
+    // server time-stamps.
+    breakdown: ServerLatency,
+
+    // Drops latency samples the clock got wrong, and counts them.
+    filter: SampleFilter,
+
+    // Drops duplicate responses before they are counted.
+    dedup: Dedup,
+
+    // The opcodes the run issues. The run is aborted if the server's echo() response says it
+    // doesn't serve one of them.
+    needed: Vec<OpCode>,
+
+    // The longest key and largest value the run writes. The run is aborted if the server's
+    // echo() response says it doesn't accept them.
+    sizes: (usize, usize),
+
+    // If true, hardware events are counted on the receiver's thread until all responses have
+    // been received. The counters are opened on the first call to execute(), so that they count
+    // the thread the receiver runs on.
+    perf_counters: bool,
+    perf: Option<Tracker>,
+
+    // The hardware events counted, or why they couldn't be, once all responses were received.
+    counts: Option<Result<Counts, String>>,
+
+    // The cache shared with the pipeline's sender, if caching is on. Every response is handed to
+    // it, and gets served off it count towards the responses waited for.
+    cache: Option<Arc<Mutex<HintCache>>>,
 }
 
 // Implementation of methods on YcsbRecv.
@@ -349,14 +474,31 @@ where
     /// * `resps`:  The number of responses to wait for before calculating statistics.
     /// * `master`: Boolean indicating if the receiver should make latency measurements.
     /// * `native`: If true, responses will be considered to correspond to native gets and puts.
+    /// * `dedup`:  Detector that duplicate responses are dropped by.
+    /// * `needed`: The opcodes the run issues.
+    /// * `sizes`:  The longest key and largest value the run writes.
+    /// * `perf`:   If true, hardware events are counted on the receiver's thread.
+    /// * `cache`:  The cache shared with the pipeline's sender, if caching is on.
+    /// * `pacer`:  The pacer shared with the pipeline's sender, if requests are paced.
     ///
     /// # Return
     ///
     /// A YCSB response receiver that measures the median latency and throughput of a Sandstorm
     /// server.
-    fn new(port: T, resps: u64, master: bool, native: bool) -> YcsbRecv<T> {
+    fn new(
+        port: T,
+        resps: u64,
+        master: bool,
+        native: bool,
+        dedup: Dedup,
+        needed: Vec<OpCode>,
+        sizes: (usize, usize),
+        perf: bool,
+        cache: Option<Arc<Mutex<HintCache>>>,
+        pacer: Option<Arc<pacing::SharedPacer>>,
+    ) -> YcsbRecv<T> {
         YcsbRecv {
-            receiver: dispatch::Receiver::new(port),
+            receiver: dispatch::Receiver::new(port).with_pacer(pacer),
             responses: resps,
             start: cycles::rdtsc(),
             recvd: 0,
@@ -364,8 +506,44 @@ where
             master: master,
             native: native,
             stop: 0,
+            breakdown: ServerLatency::new(if master { resps as usize } else { 0 }),
+            filter: SampleFilter::default(),
+            dedup: dedup,
+            needed: needed,
+            sizes: sizes,
+            perf_counters: perf,
+            perf: None,
+            counts: None,
+            cache: cache,
         }
     }
+
+    /// Returns the number of requests completed so far: the responses received, and the gets
+    /// served off the cache.
+    fn completed(&self) -> u64 {
+        let hits = match self.cache {
+            Some(ref cache) => cache.lock().unwrap().stats().hits,
+            None => 0,
+        };
+        self.recvd + hits
+    }
+
+    /// Records the latency of a request off the common header on it's response. The stamp on the
+    /// header was read by the pipeline's YcsbSend, on another core; samples the clock got wrong
+    /// across the two are counted instead.
+    ///
+    /// # Arguments
+    ///
+    /// * `curr`: The time-stamp at which the response was received.
+    /// * `hdr`:  The common header on the response.
+    fn record(&mut self, curr: u64, hdr: &RpcResponseHeader) {
+        let e2e = match self.filter.sample(hdr.stamp(), curr) {
+            Some(e2e) => e2e,
+            None => return,
+        };
+        self.latencies.push(e2e);
+        self.breakdown.record(e2e, hdr.rx_stamp(), hdr.tx_stamp());
+    }
 }
 
 // Implementation of the `Drop` trait on YcsbRecv.
@@ -380,8 +558,62 @@ where
             self.recvd as f64 / cycles::to_seconds(self.stop - self.start)
         );
 
+        // Duplicate responses point at a bug on the server, or a misbehaving network.
+        for (opcode, count) in self.dedup.counts().into_iter() {
+            println!("YCSB Duplicates {:?} {}", opcode, count);
+        }
+        if self.dedup.excessive() {
+            warn!(
+                "Dropped {} duplicate responses out of {}",
+                self.dedup.duplicates(),
+                self.dedup.duplicates() + self.dedup.completed()
+            );
+        }
+
+        // Print the hardware events counted on this receiver's thread. If the run was stopped
+        // before all responses were received, they are counted upto now.
+        let recvd = self.recvd;
+        let counts = match self.counts.take() {
+            Some(counts) => Some(counts),
+            None => self.perf.as_mut().map(|tracker| tracker.phase(recvd)),
+        };
+        match counts {
+            Some(Ok(counts)) => println!("YCSB Perf {}", counts),
+            Some(Err(note)) => println!("YCSB Perf note {}", note),
+            None => {}
+        }
+
+        // Print how gets fared on the cache. The latency of hits is printed apart from that of
+        // requests that went to the server, which is all the throughput above counts.
+        if let Some(ref cache) = self.cache {
+            let cache = cache.lock().unwrap();
+            let s = cache.stats();
+            println!(
+                "YCSB Cache {} {} {} {} {} {} {} {} {}",
+                s.hits,
+                s.misses,
+                s.over_budget,
+                s.revalidations,
+                s.refreshed,
+                s.stale,
+                s.fills,
+                s.evictions,
+                s.invalidations
+            );
+
+            if self.master {
+                let hits = cache.hit_latency().to_nanos(cycles::cycles_per_second());
+                println!(
+                    ">>> cache {} {}",
+                    hits.percentile(0.5),
+                    hits.percentile(0.99)
+                );
+            }
+        }
+
         // Calculate & print median & tail latency only on the master thread.
         if self.master {
+            println!("YCSB {}", self.filter);
             self.latencies.sort();
 
             let m;
@@ -400,6 +632,14 @@ where
                 cycles::to_seconds(m) * 1e9,
                 cycles::to_seconds(t) * 1e9
             );
+
+            // Print the breakdown only if the server stamped responses.
+            if let Some(b) = self.breakdown.breakdown() {
+                println!(
+                    ">>> server {} {} network {} {}",
+                    b.server.median, b.server.p99, b.network.median, b.network.p99
+                );
+            }
         }
     }
 }
@@ -412,14 +652,58 @@ where
     // Called internally by Netbricks.
     fn execute(&mut self) {
         // Don't do anything after all responses have been received.
-        if self.responses <= self.recvd {
+        if self.responses <= self.completed() {
             return;
         }
 
+        if self.perf_counters && self.perf.is_none() {
+            self.perf = Some(Tracker::start());
+        }
+
         // Try to receive packets from the network port.
         // If there are packets, sample the latency of the server.
         if let Some(mut packets) = self.receiver.recv_res() {
             while let Some(packet) = packets.pop() {
+                // The response to the echo() sent out at the start of the run carries the rate
+                // of the server's clock, the opcodes it serves, and the largest objects it
+                // accepts. It is not counted as a response to a YCSB request.
+                if parse_rpc_opcode(&packet) == OpCode::SandstormEchoRpc {
+                    let p = packet.parse_header::<EchoResponse>();
+                    let (hz, served, limits) = {
+                        let hdr = p.get_header();
+                        (
+                            hdr.cycles_per_second(),
+                            hdr.opcodes(),
+                            (hdr.max_key_len() as usize, hdr.max_value_len() as usize),
+                        )
+                    };
+                    p.free_packet();
+
+                    if let Err(e) = preflight::check_opcodes(served, &self.needed)
+                        .and_then(|_| preflight::check_sizes(self.sizes, limits))
+                    {
+                        error!("{}", e);
+                        std::process::exit(1);
+                    }
+
+                    self.breakdown.set_server_hz(hz);
+                    continue;
+                }
+
+                // Drop duplicates before they are counted, or their latency is recorded.
+                if let Some(id) = parse_rpc_id(&packet) {
+                    if !self.dedup.complete(id, parse_rpc_opcode(&packet)) {
+                        packet.free_packet();
+                        continue;
+                    }
+                }
+
+                // Cache objects hinted hot, and revalidate expired ones.
+                if let Some(ref cache) = self.cache {
+                    let mut cache = cache.lock().unwrap();
+                    cache.observe(packet.get_payload(), cycles::rdtsc());
+                }
+
                 self.recvd += 1;
 
                 // Measure latency on the master client after the first 2 million requests.
@@ -431,8 +715,7 @@ where
                         // The response corresponds to an invoke() RPC.
                         false => {
                             let p = packet.parse_header::<InvokeResponse>();
-                            self.latencies
-                                .push(curr - p.get_header().common_header.stamp);
+                            self.record(curr, &p.get_header().common_header);
                             p.free_packet();
                         }
 
@@ -441,15 +724,13 @@ where
                         true => match parse_rpc_opcode(&packet) {
                             OpCode::SandstormGetRpc => {
                                 let p = packet.parse_header::<GetResponse>();
-                                self.latencies
-                                    .push(curr - p.get_header().common_header.stamp);
+                                self.record(curr, &p.get_header().common_header);
                                 p.free_packet();
                             }
 
                             OpCode::SandstormPutRpc => {
                                 let p = packet.parse_header::<PutResponse>();
-                                self.latencies
-                                    .push(curr - p.get_header().common_header.stamp);
+                                self.record(curr, &p.get_header().common_header);
                                 p.free_packet();
                             }
 
@@ -464,8 +745,11 @@ where
 
         // The moment all response packets have been received, set the value of the
         // stop timestamp so that throughput can be estimated later.
-        if self.responses <= self.recvd {
+        if self.responses <= self.completed() {
             self.stop = cycles::rdtsc();
+
+            let recvd = self.recvd;
+            self.counts = self.perf.as_mut().map(|tracker| tracker.phase(recvd));
         }
     }
 
@@ -481,11 +765,15 @@ where
 /// * `config`:    Network related configuration such as the MAC and IP address.
 /// * `ports`:     Network port on which packets will be sent.
 /// * `scheduler`: Netbricks scheduler to which YcsbSend will be added.
+/// * `pipeline`:  The index of this sender among all senders on the client.
+/// * `pipelines`: The total number of senders on the client.
 fn setup_send<S>(
     config: &config::ClientConfig,
     ports: Vec<CacheAligned<PortQueue>>,
     scheduler: &mut S,
     _core: i32,
+    pipeline: usize,
+    pipelines: usize,
 ) where
     S: Scheduler + Sized,
 {
@@ -500,6 +788,8 @@ fn setup_send<S>(
         ports[0].clone(),
         config.num_reqs as u64,
         config.server_udp_ports as u16,
+        pipeline,
+        pipelines,
     )) {
         Ok(_) => {
             info!(
@@ -524,12 +814,14 @@ fn setup_send<S>(
 /// * `master`:    If true, the added YcsbRecv will make latency measurements.
 /// * `native`:    If true, the added YcsbRecv will assume that responses correspond to gets
 ///                and puts.
+/// * `pipeline`:  The index of the sender whose responses the YcsbRecv receives.
 fn setup_recv<S>(
     ports: Vec<CacheAligned<PortQueue>>,
     scheduler: &mut S,
     _core: i32,
     master: bool,
     native: bool,
+    pipeline: usize,
 ) where
     S: Scheduler + Sized,
 {
@@ -539,11 +831,21 @@ fn setup_recv<S>(
     }
 
     // Add the receiver to a netbricks pipeline.
+    let config = config::ClientConfig::load();
     match scheduler.add_task(YcsbRecv::new(
         ports[0].clone(),
         34 * 1000 * 1000 as u64,
         master,
         native,
+        Dedup::from_config(&config),
+        preflight::ycsb_opcodes(&config),
+        preflight::ycsb_sizes(&config),
+        config.perf_counters,
+        match native {
+            true => cache::pipeline(&config, pipeline),
+            false => None,
+        },
+        pacing::pipeline(&config, pipeline),
     )) {
         Ok(_) => {
             info!(
@@ -564,6 +866,7 @@ fn main() {
 
     let config = config::ClientConfig::load();
     info!("Starting up Sandstorm client with config {:?}", config);
+    setup::validate(&config, "YCSB", 4, false);
 
     // Based on the supplied client configuration, compute the amount of time it will take to send
     // out `num_reqs` requests at a rate of `req_rate` requests per second.
@@ -604,7 +907,7 @@ fn main() {
                 receive[i],
                 Arc::new(
                     move |_ports, sched: &mut StandaloneScheduler, core: i32, _sibling| {
-                        setup_recv(port.clone(), sched, core, master, native)
+                        setup_recv(port.clone(), sched, core, master, native, i)
                     },
                 ),
             ).expect("Failed to initialize receive side.");
@@ -615,7 +918,14 @@ fn main() {
                 senders[i],
                 Arc::new(
                     move |ports, sched: &mut StandaloneScheduler, core: i32, _sibling| {
-                        setup_send(&config::ClientConfig::load(), ports, sched, core)
+                        setup_send(
+                            &config::ClientConfig::load(),
+                            ports,
+                            sched,
+                            core,
+                            i,
+                            senders.len(),
+                        )
                     },
                 ),
             ).expect("Failed to initialize send side.");
@@ -633,10 +943,19 @@ fn main() {
 
     // Stop the client.
     net_context.stop();
+
+    // When each epoch of the hot set rotation started, relative to the first request.
+    if let Some(rotation) = dist::rotation(&config) {
+        for (epoch, at) in rotation.epochs() {
+            println!("YCSB Rotation {} {}", epoch, cycles::to_seconds(at));
+        }
+    }
 }
 
 #[cfg(test)]
 mod test {
+    use splinter::dist::KeyDistribution;
+    use splinter::rng::WorkloadRng;
     use std;
     use std::collections::HashMap;
     use std::sync::atomic::{AtomicBool, Ordering};
@@ -653,7 +972,17 @@ mod test {
         for _ in 0..n_threads {
             let done = done.clone();
             threads.push(thread::spawn(move || {
-                let mut b = super::Ycsb::new(10, 100, 1000000, 5, 0.99, 1024, 0.1);
+                let mut b = super::Ycsb::new(
+                    10,
+                    100,
+                    5,
+                    WorkloadRng::new(
+                        1,
+                        0,
+                        KeyDistribution::Zipf(0.99).sampler(1000000, 0, 1),
+                        KeyDistribution::Zipf(0.1).sampler(1024, 0, 1),
+                    ),
+                );
                 let mut n_gets = 0u64;
                 let mut n_puts = 0u64;
                 let start = Instant::now();
@@ -715,7 +1044,17 @@ mod test {
             let hist = hist.clone();
             let done = done.clone();
             threads.push(thread::spawn(move || {
-                let mut b = super::Ycsb::new(4, 100, n_keys, 5, 0.99, 1024, 0.1);
+                let mut b = super::Ycsb::new(
+                    4,
+                    100,
+                    5,
+                    WorkloadRng::new(
+                        1,
+                        0,
+                        KeyDistribution::Zipf(0.99).sampler(n_keys, 0, 1),
+                        KeyDistribution::Zipf(0.1).sampler(1024, 0, 1),
+                    ),
+                );
                 let mut n_gets = 0u64;
                 let mut n_puts = 0u64;
                 let start = Instant::now();
@@ -0,0 +1,180 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Compares the results of client runs. Usage:
+//!
+//! `compare [--force] [--tput-pct=5] [--ks=0.05] <old> <new>`
+//!
+//! `old` and `new` are either two JSON result files, or two directories of them. Files in
+//! directories are paired up by the signature of their config; files without a counterpart are
+//! reported and skipped. Runs whose configs differ are refused unless `--force` is passed.
+//!
+//! Exits with 0 if nothing regressed, 1 if something did, and 2 if the runs could not be
+//! compared.
+
+extern crate splinter;
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use splinter::compare::{compare, RunResult, Thresholds, Verdict};
+
+// Parsed command line.
+struct Args {
+    force: bool,
+    thresholds: Thresholds,
+    old: PathBuf,
+    new: PathBuf,
+}
+
+// Parses the command line, excluding the name of the binary.
+fn parse_args<I: Iterator<Item = String>>(args: I) -> Result<Args, String> {
+    let mut force = false;
+    let mut thresholds = Thresholds::default();
+    let mut paths = Vec::new();
+
+    for arg in args {
+        if !arg.starts_with("--") {
+            paths.push(PathBuf::from(arg));
+            continue;
+        }
+
+        let mut parts = arg.trim_left_matches("--").splitn(2, '=');
+        let bad = || format!("Invalid flag {}", arg);
+        match (parts.next(), parts.next()) {
+            (Some("force"), None) => force = true,
+            (Some("tput-pct"), Some(v)) => thresholds.tput_pct = v.parse().map_err(|_| bad())?,
+            (Some("ks"), Some(v)) => thresholds.ks = v.parse().map_err(|_| bad())?,
+            _ => return Err(bad()),
+        }
+    }
+
+    if paths.len() != 2 {
+        return Err("Expected two result files or directories".to_string());
+    }
+    let new = paths.pop().unwrap();
+    let old = paths.pop().unwrap();
+
+    Ok(Args {
+        force: force,
+        thresholds: thresholds,
+        old: old,
+        new: new,
+    })
+}
+
+// Loads every JSON result file in a directory, keyed by the signature of it's config.
+fn load_dir(dir: &Path) -> Result<BTreeMap<String, (PathBuf, RunResult)>, String> {
+    let mut results = BTreeMap::new();
+    let entries = fs::read_dir(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    for entry in entries {
+        let path = entry.map_err(|e| e.to_string())?.path();
+        if path.extension().map_or(true, |e| e != "json") {
+            continue;
+        }
+
+        let result = RunResult::load(&path)?;
+        let signature = result.config.signature();
+        if let Some((other, _)) = results.insert(signature, (path.clone(), result)) {
+            return Err(format!(
+                "{} and {} have the same config",
+                other.display(),
+                path.display()
+            ));
+        }
+    }
+
+    Ok(results)
+}
+
+// Compares a pair of runs, prints every delta, and returns whether anything regressed.
+fn report(
+    old: &RunResult,
+    new: &RunResult,
+    thresholds: &Thresholds,
+    force: bool,
+) -> Result<bool, String> {
+    let deltas = compare(old, new, thresholds, force)?;
+    for d in deltas.iter() {
+        let verdict = match d.verdict {
+            Verdict::Unchanged => "",
+            Verdict::Improved => "IMPROVED",
+            Verdict::Regressed => "REGRESSED",
+        };
+        match d.metric {
+            "throughput" => println!(
+                "{:>10} {:>8} throughput {:+7.2}% {}",
+                d.phase, d.class, d.change, verdict
+            ),
+            _ => println!(
+                "{:>10} {:>8} latency    KS {:.3}, p99 {:+} ns {}",
+                d.phase, d.class, d.change, d.p99, verdict
+            ),
+        }
+    }
+
+    Ok(deltas.iter().any(|d| d.verdict == Verdict::Regressed))
+}
+
+// Compares the runs named on the command line.
+fn run(args: &Args) -> Result<bool, String> {
+    if !(args.old.is_dir() && args.new.is_dir()) {
+        let old = RunResult::load(&args.old)?;
+        let new = RunResult::load(&args.new)?;
+        return report(&old, &new, &args.thresholds, args.force);
+    }
+
+    let (old, new) = (load_dir(&args.old)?, load_dir(&args.new)?);
+    let mut regressed = false;
+    for (signature, &(ref path, ref result)) in old.iter() {
+        match new.get(signature) {
+            Some(&(ref other, ref new)) => {
+                println!("{} vs {}", path.display(), other.display());
+                regressed |= report(result, new, &args.thresholds, args.force)?;
+            }
+
+            None => println!("{}: no counterpart for {}", path.display(), signature),
+        }
+    }
+    for (signature, &(ref path, _)) in new.iter() {
+        if !old.contains_key(signature) {
+            println!("{}: no counterpart for {}", path.display(), signature);
+        }
+    }
+
+    Ok(regressed)
+}
+
+fn main() {
+    let args = match parse_args(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            println!("{}", e);
+            process::exit(2);
+        }
+    };
+
+    match run(&args) {
+        Ok(false) => process::exit(0),
+        Ok(true) => process::exit(1),
+        Err(e) => {
+            println!("{}", e);
+            process::exit(2);
+        }
+    }
+}
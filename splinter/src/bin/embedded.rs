@@ -0,0 +1,1101 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Runs a server and a workload in a single process, with no network in between. Usage:
+//!
+//! `embedded [--workload ycsb|auth|tao] [--seconds 5] [--threads 2] [--window 8]
+//!           [--records N] [--put-pct 50] [--invoke-pct 50] [--assoc-pct 40] [--seed N]
+//!           [--autotune <max window>] [--p99-ceiling-us N] [--min-gain 0.05] [--refine N]
+//!           [--fill-rates r1,r2,..] [--fill-records 1000000] [--perf-counters true]
+//!           [--out <file>]`
+//!
+//! Requests are built as packets and handed straight to Master's handlers, and the tasks they
+//! return are run to completion on a round-robin scheduler on each of `threads` threads. Every
+//! thread keeps `window` requests in flight. Extensions are loaded through the ExtensionManager
+//! from ../ext, exactly as the server loads them, so run this from splinter/ after building
+//! them (see `make embedded`). Packets still live in DPDK memory, so DPDK's memory pools are
+//! initialized without attaching to a NIC; hugepages must be set up, but no NIC is needed.
+//!
+//! Results are written in the JSON format read by `compare`, to `out` if given or to stdout
+//! otherwise. They are labeled with the "embedded" transport, so that they are never compared
+//! against runs over the network. Each thread draws it's requests off `seed` (random if not
+//! given), which is recorded in the results so that the run can be reproduced. Exits with 1 if
+//! no operation completed, or if any failed.
+//!
+//! With `--autotune`, the window is instead swept upwards from `window`, doubling every epoch of
+//! `seconds` until the 99th percentile latency goes above `p99-ceiling-us` (no ceiling if not
+//! given), throughput gains less than `min-gain` a step, or the window reaches it's max. Every
+//! epoch is drained before the next one starts. If `refine` is set, that many more epochs binary
+//! search for a finer knee. Each epoch is reported as a phase named after it's window, and the
+//! sweep and it's knee are added to the results. Also exits with 1 if the windows swept did not
+//! go up on every epoch.
+//!
+//! With `--fill-rates`, a YCSB run instead measures how populating the table it reads holds up
+//! it's requests. A fill of `fill-records` more objects runs on a thread of it's own alongside
+//! the workload, and an epoch of `seconds` is run at each fill rate (in objects a second, 0 for
+//! uncapped), which is changed on the running fill between epochs. Each epoch is reported as a
+//! phase named after it's rate, and a row with the rate the fill got and the latency of gets is
+//! printed for it. Also exits with 1 if the fill ran faster than it's rate on any epoch.
+//!
+//! With `--perf-counters true`, every thread counts cycles, instructions, and cache and branch
+//! misses on itself over each phase using the CPU's hardware counters, and the counts of every
+//! thread and their total are added to the phase's results. Threads that can't open the
+//! counters (no PMU, or not permitted to) leave a note in the results instead, and the run goes
+//! on.
+
+extern crate db;
+extern crate rand;
+extern crate sandstorm;
+extern crate splinter;
+
+use std::env;
+use std::mem::transmute;
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use db::cycles;
+use db::e2d2::common::EmptyMetadata;
+use db::e2d2::headers::*;
+use db::e2d2::interface::dpdk::init_system_wl;
+use db::e2d2::interface::{new_packet, Packet};
+use db::fill;
+use db::master::Master;
+use db::rpc;
+use db::service::Service;
+use db::task::{Task, TaskState};
+use db::wireformat::{GetGenerator, OpCode, RpcResponseHeader, RpcStatus};
+
+use rand::{Rng, XorShiftRng};
+
+use sandstorm::auth;
+use sandstorm::common::{TableId, TenantId, PACKET_UDP_LEN};
+use sandstorm::tao;
+
+use splinter::autotune::{self, Epoch, Step, TuneConfig};
+use splinter::compare::{Class, Histogram, Phase, RunConfig, RunResult};
+use splinter::perf::{Counts, Report, Tracker};
+use splinter::rng;
+
+// The tenant every table is added to, and requests are issued by.
+const TENANT: TenantId = 1;
+
+// The table YCSB and AUTH records are added to.
+const TABLE: TableId = 1;
+
+// Key and value lengths of the YCSB records (see Master::fill_test()).
+const YCSB_KEY_LEN: usize = 30;
+const YCSB_VAL_LEN: usize = 100;
+
+// The width of each latency bucket in nanoseconds, and the number of buckets. Latencies beyond
+// the last bucket are counted in it.
+const BUCKET_NS: u64 = 250;
+const N_BUCKETS: usize = 4000;
+
+// The workloads that can be run.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Workload {
+    // Native and invoke() based gets and puts on a single table.
+    Ycsb,
+
+    // invoke() based password checks. The server fills the table invoke-only.
+    Auth,
+
+    // invoke() based obj_gets and assoc_gets on the TAO graph.
+    Tao,
+}
+
+impl Workload {
+    fn parse(name: &str) -> Option<Workload> {
+        match name {
+            "ycsb" => Some(Workload::Ycsb),
+            "auth" => Some(Workload::Auth),
+            "tao" => Some(Workload::Tao),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match *self {
+            Workload::Ycsb => "ycsb",
+            Workload::Auth => "auth",
+            Workload::Tao => "tao",
+        }
+    }
+
+    // The number of records populated if not set on the command line. Every AUTH record is
+    // hashed with bcrypt, so it gets fewer.
+    fn records(&self) -> u32 {
+        match *self {
+            Workload::Auth => 10 * 1000,
+            _ => 100 * 1000,
+        }
+    }
+}
+
+// Parsed command line.
+struct Args {
+    workload: Workload,
+    seconds: f64,
+    threads: usize,
+    window: usize,
+    records: u32,
+    put_pct: u32,
+    invoke_pct: u32,
+    assoc_pct: u32,
+    seed: u64,
+    out: Option<PathBuf>,
+
+    // The largest window autotuning sweeps upto; 0 if the window isn't autotuned.
+    autotune: usize,
+    p99_ceiling_us: u64,
+    min_gain: f64,
+    refine: usize,
+
+    // The rates, in objects a second, to run an epoch alongside a fill at; empty if the run
+    // doesn't fill. The number of objects the fill writes.
+    fill_rates: Vec<u64>,
+    fill_records: u32,
+
+    // If true, every thread counts hardware events on itself over each phase.
+    perf: bool,
+}
+
+impl Args {
+    // Returns the parameters of the autotuning sweep.
+    fn tune(&self) -> TuneConfig {
+        TuneConfig {
+            start: self.window,
+            max: self.autotune,
+            step: Step::Multiply(2.0),
+            p99_ceiling: match self.p99_ceiling_us {
+                0 => u64::max_value(),
+                us => us * 1000,
+            },
+            min_gain: self.min_gain,
+            patience: 1,
+            refine: self.refine,
+        }
+    }
+}
+
+// Parses the command line, excluding the name of the binary. Flags take their value either as
+// the next argument, or after an '='.
+fn parse_args<I: Iterator<Item = String>>(args: I) -> Result<Args, String> {
+    let mut parsed = Args {
+        workload: Workload::Ycsb,
+        seconds: 5.0,
+        threads: 2,
+        window: 8,
+        records: 0,
+        put_pct: 50,
+        invoke_pct: 50,
+        assoc_pct: 40,
+        seed: 0,
+        out: None,
+        autotune: 0,
+        p99_ceiling_us: 0,
+        min_gain: 0.05,
+        refine: 0,
+        fill_rates: Vec::new(),
+        fill_records: 1000 * 1000,
+        perf: false,
+    };
+
+    let mut args = args;
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            return Err(format!("Unexpected argument {}", arg));
+        }
+
+        let mut parts = arg.trim_left_matches("--").splitn(2, '=');
+        let flag = parts.next().unwrap_or("").to_string();
+        let value = match parts.next() {
+            Some(v) => v.to_string(),
+            None => args
+                .next()
+                .ok_or_else(|| format!("Missing value for {}", arg))?,
+        };
+
+        let bad = || format!("Invalid value {} for --{}", value, flag);
+        match flag.as_str() {
+            "workload" => parsed.workload = Workload::parse(&value).ok_or_else(bad)?,
+            "seconds" => parsed.seconds = value.parse().map_err(|_| bad())?,
+            "threads" => parsed.threads = value.parse().map_err(|_| bad())?,
+            "window" => parsed.window = value.parse().map_err(|_| bad())?,
+            "records" => parsed.records = value.parse().map_err(|_| bad())?,
+            "put-pct" => parsed.put_pct = value.parse().map_err(|_| bad())?,
+            "invoke-pct" => parsed.invoke_pct = value.parse().map_err(|_| bad())?,
+            "assoc-pct" => parsed.assoc_pct = value.parse().map_err(|_| bad())?,
+            "seed" => parsed.seed = value.parse().map_err(|_| bad())?,
+            "out" => parsed.out = Some(PathBuf::from(value.as_str())),
+            "autotune" => parsed.autotune = value.parse().map_err(|_| bad())?,
+            "p99-ceiling-us" => parsed.p99_ceiling_us = value.parse().map_err(|_| bad())?,
+            "min-gain" => parsed.min_gain = value.parse().map_err(|_| bad())?,
+            "refine" => parsed.refine = value.parse().map_err(|_| bad())?,
+            "fill-rates" => {
+                parsed.fill_rates = value
+                    .split(',')
+                    .map(|r| r.trim().parse())
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| bad())?
+            }
+            "fill-records" => parsed.fill_records = value.parse().map_err(|_| bad())?,
+            "perf-counters" => parsed.perf = value.parse().map_err(|_| bad())?,
+            _ => return Err(format!("Invalid flag {}", arg)),
+        }
+    }
+
+    if parsed.seconds <= 0.0 || parsed.threads == 0 || parsed.window == 0 {
+        return Err("--seconds, --threads and --window must be positive".to_string());
+    }
+    if parsed.put_pct > 100 || parsed.invoke_pct > 100 || parsed.assoc_pct > 100 {
+        return Err("Percentages must be atmost 100".to_string());
+    }
+    if parsed.autotune != 0 && parsed.autotune < parsed.window {
+        return Err("--autotune must be atleast --window".to_string());
+    }
+    if parsed.min_gain < 0.0 {
+        return Err("--min-gain must not be negative".to_string());
+    }
+    if !parsed.fill_rates.is_empty() && parsed.workload != Workload::Ycsb {
+        return Err("--fill-rates needs the ycsb workload".to_string());
+    }
+    if !parsed.fill_rates.is_empty() && parsed.autotune != 0 {
+        return Err("--fill-rates and --autotune cannot be combined".to_string());
+    }
+    if parsed.records == 0 {
+        parsed.records = parsed.workload.records();
+    }
+
+    Ok(parsed)
+}
+
+// The classes of operations issued, as reported in the results.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Op {
+    Get,
+    Put,
+    InvokeGet,
+    InvokePut,
+    Auth,
+    ObjGet,
+    AssocGet,
+}
+
+// Every class, in the order they are reported.
+const OPS: [Op; 7] = [
+    Op::Get,
+    Op::Put,
+    Op::InvokeGet,
+    Op::InvokePut,
+    Op::Auth,
+    Op::ObjGet,
+    Op::AssocGet,
+];
+
+impl Op {
+    fn name(&self) -> &'static str {
+        match *self {
+            Op::Get => "get",
+            Op::Put => "put",
+            Op::InvokeGet => "invoke_get",
+            Op::InvokePut => "invoke_put",
+            Op::Auth => "auth",
+            Op::ObjGet => "obj_get",
+            Op::AssocGet => "assoc_get",
+        }
+    }
+
+    fn index(&self) -> usize {
+        OPS.iter().position(|op| op == self).unwrap()
+    }
+}
+
+// The counters kept for a class of operations.
+#[derive(Clone)]
+struct Counters {
+    completed: u64,
+    errors: u64,
+
+    // Bucket `i` counts the operations that took atmost `(i + 1) * BUCKET_NS` nanoseconds.
+    buckets: Vec<u64>,
+}
+
+impl Counters {
+    fn new() -> Counters {
+        Counters {
+            completed: 0,
+            errors: 0,
+            buckets: vec![0; N_BUCKETS],
+        }
+    }
+
+    // Counts an operation that completed with a status, after some nanoseconds.
+    fn record(&mut self, status: RpcStatus, ns: u64) {
+        if status != RpcStatus::StatusOk {
+            self.errors += 1;
+            return;
+        }
+
+        self.completed += 1;
+        let bucket = (ns.saturating_sub(1) / BUCKET_NS) as usize;
+        self.buckets[bucket.min(N_BUCKETS - 1)] += 1;
+    }
+
+    fn merge(&mut self, other: &Counters) {
+        self.completed += other.completed;
+        self.errors += other.errors;
+        for (a, b) in self.buckets.iter_mut().zip(other.buckets.iter()) {
+            *a += *b;
+        }
+    }
+
+    // Returns the histogram of latencies, with empty buckets past the last sample trimmed.
+    fn histogram(&self) -> Histogram {
+        let len = self
+            .buckets
+            .iter()
+            .rposition(|c| *c > 0)
+            .map_or(0, |i| i + 1);
+        Histogram {
+            bounds: (1..(len as u64 + 1)).map(|i| i * BUCKET_NS).collect(),
+            counts: self.buckets[..len].to_vec(),
+        }
+    }
+}
+
+// Builds the requests of a workload. Buffers are reused across requests.
+struct Generator {
+    workload: Workload,
+    rng: XorShiftRng,
+    records: u32,
+    put_pct: u32,
+    invoke_pct: u32,
+    assoc_pct: u32,
+    key: Vec<u8>,
+    val: Vec<u8>,
+    payload: Vec<u8>,
+}
+
+impl Generator {
+    // Every stream off the same seed draws a different sequence of requests.
+    fn new(args: &Args, stream: usize) -> Generator {
+        Generator {
+            workload: args.workload,
+            rng: rng::xorshift(args.seed, stream),
+            records: args.records,
+            put_pct: args.put_pct,
+            invoke_pct: args.invoke_pct,
+            assoc_pct: args.assoc_pct,
+            key: Vec::with_capacity(YCSB_KEY_LEN),
+            val: vec![0; YCSB_VAL_LEN],
+            payload: Vec::new(),
+        }
+    }
+
+    // Returns true `pct` percent of the time.
+    fn chance(&mut self, pct: u32) -> bool {
+        self.rng.gen::<u32>() % 100 < pct
+    }
+
+    // Picks the next operation, and builds the request for it. Records are numbered from 1.
+    fn next(&mut self, id: u64, stamp: u64) -> (Op, OpCode, Packet<IpHeader, EmptyMetadata>) {
+        let k: [u8; 4] = unsafe { transmute((self.rng.gen_range(0, self.records) + 1).to_le()) };
+
+        let (invoke_pct, put_pct, assoc_pct) = (self.invoke_pct, self.put_pct, self.assoc_pct);
+        let op = match self.workload {
+            Workload::Ycsb => match (self.chance(invoke_pct), self.chance(put_pct)) {
+                (false, false) => Op::Get,
+                (false, true) => Op::Put,
+                (true, false) => Op::InvokeGet,
+                (true, true) => Op::InvokePut,
+            },
+
+            Workload::Auth => Op::Auth,
+
+            Workload::Tao => match self.chance(assoc_pct) {
+                true => Op::AssocGet,
+                false => Op::ObjGet,
+            },
+        };
+
+        self.key.clear();
+        self.key.extend_from_slice(&k);
+        self.payload.clear();
+
+        let (mac, ip, udp) = (MacHeader::new(), IpHeader::new(), UdpHeader::new());
+        let (opcode, req) = match op {
+            Op::Get => {
+                self.key.resize(YCSB_KEY_LEN, 0);
+                let gen = GetGenerator::SandstormClient;
+                let req = rpc::create_get_rpc(
+                    &mac, &ip, &udp, TENANT, TABLE, &self.key, id, stamp, 0, gen,
+                );
+                (OpCode::SandstormGetRpc, req)
+            }
+
+            Op::Put => {
+                self.key.resize(YCSB_KEY_LEN, 0);
+                let req = rpc::create_put_rpc(
+                    &mac, &ip, &udp, TENANT, TABLE, &self.key, &self.val, id, stamp, 0,
+                );
+                (OpCode::SandstormPutRpc, req)
+            }
+
+            // The extension's name, the table id, and the key.
+            Op::InvokeGet => {
+                self.key.resize(YCSB_KEY_LEN, 0);
+                self.payload.extend_from_slice(b"get");
+                self.payload.extend_from_slice(&le64(TABLE));
+                self.payload.extend_from_slice(&self.key);
+                (OpCode::SandstormInvokeRpc, self.invoke(3, id, stamp))
+            }
+
+            // The extension's name, the table id, the key length, the key, and the value.
+            Op::InvokePut => {
+                self.key.resize(YCSB_KEY_LEN, 0);
+                let len: [u8; 2] = unsafe { transmute((YCSB_KEY_LEN as u16).to_le()) };
+                self.payload.extend_from_slice(b"put");
+                self.payload.extend_from_slice(&le64(TABLE));
+                self.payload.extend_from_slice(&len);
+                self.payload.extend_from_slice(&self.key);
+                self.payload.extend_from_slice(&self.val);
+                (OpCode::SandstormInvokeRpc, self.invoke(3, id, stamp))
+            }
+
+            // The extension's name, the table id, the username, and the password. The password
+            // of record `k` starts with `k`, and is otherwise zeroed.
+            Op::Auth => {
+                self.key.resize(auth::USERNAME_LEN, 0);
+                self.payload.extend_from_slice(b"auth");
+                self.payload.extend_from_slice(&le64(TABLE));
+                self.payload.extend_from_slice(&self.key);
+                self.payload.extend_from_slice(&k);
+                let len = self.payload.len() + auth::PASSWORD_LEN - k.len();
+                self.payload.resize(len, 0);
+                (OpCode::SandstormInvokeRpc, self.invoke(4, id, stamp))
+            }
+
+            // The extension's name, the TAO opcode, the table id, and the object's key.
+            Op::ObjGet => {
+                self.key.resize(tao::OBJECT_KEY_LEN, 0);
+                self.payload.extend_from_slice(b"tao");
+                self.payload.push(0);
+                self.payload.extend_from_slice(&le64(tao::OBJECT_TABLE));
+                self.payload.extend_from_slice(&self.key);
+                (OpCode::SandstormInvokeRpc, self.invoke(3, id, stamp))
+            }
+
+            // The extension's name, the TAO opcode, the table id, and the association's key.
+            Op::AssocGet => {
+                self.key.resize(tao::ASSOC_KEY_LEN, 0);
+                self.payload.extend_from_slice(b"tao");
+                self.payload.push(4);
+                self.payload.extend_from_slice(&le64(tao::ASSOC_TABLE));
+                self.payload.extend_from_slice(&self.key);
+                (OpCode::SandstormInvokeRpc, self.invoke(3, id, stamp))
+            }
+        };
+
+        (op, opcode, req)
+    }
+
+    // Builds an invoke() request out of the payload.
+    fn invoke(&self, name_len: u32, id: u64, stamp: u64) -> Packet<IpHeader, EmptyMetadata> {
+        rpc::create_invoke_rpc(
+            &MacHeader::new(),
+            &IpHeader::new(),
+            &UdpHeader::new(),
+            TENANT,
+            name_len,
+            &self.payload,
+            id,
+            stamp,
+            0,
+        )
+    }
+}
+
+// Returns the little endian encoding of a u64.
+fn le64(v: u64) -> [u8; 8] {
+    unsafe { transmute(v.to_le()) }
+}
+
+// Allocates a response packet with headers upto UDP, as the server's dispatcher would.
+fn response() -> Packet<UdpHeader, EmptyMetadata> {
+    new_packet()
+        .expect("Failed to allocate response packet.")
+        .push_header(&MacHeader::new())
+        .expect("Failed to push MAC header into response.")
+        .push_header(&IpHeader::new())
+        .expect("Failed to push IP header into response.")
+        .push_header(&UdpHeader::new())
+        .expect("Failed to push UDP header into response.")
+}
+
+// Frees a completed task's packets, and returns the status on it's response.
+fn finish(task: Box<Task>) -> RpcStatus {
+    let (req, res) = unsafe { task.tear() }.expect("Task returned no packets.");
+    task.recycle();
+    req.free_packet();
+
+    let res = res.parse_header::<RpcResponseHeader>();
+    let status = res.get_header().status.clone();
+    res.deparse_header(PACKET_UDP_LEN as usize).free_packet();
+    status
+}
+
+// Drives the workload against a master from a single thread until the deadline, keeping
+// `window` requests in flight, drawn off a generator `stream`. Tasks are run round-robin, a
+// single resume at a time, so that yielding extensions interleave the way they would on a server
+// core.
+//
+// # Return
+//
+// The counters of every class of operations, and the hardware events counted on the thread if
+// --perf-counters is set (or why they couldn't be).
+fn run(
+    master: &Master,
+    args: &Args,
+    thread: usize,
+    stream: usize,
+    window: usize,
+    deadline: Instant,
+) -> (Vec<Counters>, Option<Result<Counts, String>>) {
+    // Hardware counters count the thread that opens them, so they are opened here.
+    let mut perf = match args.perf {
+        true => Some(Tracker::start()),
+        false => None,
+    };
+
+    let mut gen = Generator::new(args, stream);
+    let mut counters = vec![Counters::new(); OPS.len()];
+    let mut tasks: Vec<(Box<Task>, Op, u64)> = Vec::with_capacity(window);
+    let mut next = 0;
+    let mut id = (thread as u64) << 48;
+
+    loop {
+        // Keep the window full until the deadline, then drain it.
+        let open = Instant::now() < deadline;
+        while open && tasks.len() < window {
+            id += 1;
+            let stamp = cycles::rdtsc();
+            let (op, opcode, req) = gen.next(id, stamp);
+            match master.dispatch(opcode, req.parse_header::<UdpHeader>(), response()) {
+                Ok(task) => tasks.push((task, op, stamp)),
+
+                Err((req, res)) => {
+                    req.free_packet();
+                    res.free_packet();
+                    counters[op.index()].record(RpcStatus::StatusInternalError, 0);
+                }
+            }
+        }
+
+        if tasks.is_empty() {
+            break;
+        }
+
+        next %= tasks.len();
+        let state = tasks[next].0.run().0;
+        match state {
+            TaskState::COMPLETED => {
+                let (task, op, stamp) = tasks.swap_remove(next);
+                let ns = (cycles::to_seconds(cycles::rdtsc() - stamp) * 1e9) as u64;
+                counters[op.index()].record(finish(task), ns);
+            }
+
+            TaskState::YIELDED | TaskState::INITIALIZED | TaskState::RUNNING => next += 1,
+
+            // Nothing here stops tasks or waits on IO, so these are bugs.
+            state => panic!("Task in unexpected state {}", state as u8),
+        }
+    }
+
+    let responses = counters.iter().map(|c| c.completed + c.errors).sum();
+    let perf = perf.as_mut().map(|tracker| tracker.phase(responses));
+    (counters, perf)
+}
+
+// Runs the workload on every thread for `seconds` at a window, and waits for every thread to
+// drain it's requests. Epoch `n` numbers the generator streams threads draw off, so that no two
+// epochs draw the same requests.
+//
+// # Return
+//
+// The counters of every class of operations, merged across threads, the seconds elapsed, and
+// the hardware events counted on every thread if --perf-counters is set.
+fn epoch(
+    master: &Arc<Master>,
+    args: &Arc<Args>,
+    window: usize,
+    n: usize,
+) -> (Vec<Counters>, f64, Option<Report>) {
+    let start = Instant::now();
+    let deadline = start + Duration::from_millis((args.seconds * 1000.0) as u64);
+    let threads: Vec<_> = (0..args.threads)
+        .map(|thread| {
+            let (master, args) = (Arc::clone(master), Arc::clone(args));
+            let stream = n * args.threads + thread;
+            thread::spawn(move || run(&master, &args, thread, stream, window, deadline))
+        })
+        .collect();
+
+    let mut counters = vec![Counters::new(); OPS.len()];
+    let mut perf = Vec::new();
+    for thread in threads {
+        let (c, p) = thread.join().expect("ERROR: Thread join failed.");
+        for (total, c) in counters.iter_mut().zip(c.iter()) {
+            total.merge(c);
+        }
+        perf.extend(p);
+    }
+    let elapsed = start.elapsed();
+    let elapsed = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+
+    let perf = match args.perf {
+        true => Some(Report::new(perf)),
+        false => None,
+    };
+    (counters, elapsed, perf)
+}
+
+// Returns the results of every class of operations that was issued.
+fn classes(counters: &[Counters], elapsed: f64) -> Vec<Class> {
+    OPS.iter()
+        .zip(counters.iter())
+        .filter(|&(_, c)| c.completed + c.errors > 0)
+        .map(|(op, c)| Class {
+            name: op.name().to_string(),
+            throughput: c.completed as f64 / elapsed,
+            latency: c.histogram(),
+            duplicates: 0,
+        })
+        .collect()
+}
+
+// Summarizes an epoch of the autotuning sweep across every class of operations.
+fn summary(window: usize, counters: &[Counters], elapsed: f64) -> Epoch {
+    let mut total = Counters::new();
+    for c in counters.iter() {
+        total.merge(c);
+    }
+
+    Epoch::new(window, total.completed, elapsed, &total.histogram())
+}
+
+// Builds the results of a run out of the counters of every class of operations.
+fn results(args: &Args, counters: &[Counters], elapsed: f64) -> RunResult {
+    RunResult {
+        run_id: 0,
+        seed: args.seed,
+        config: RunConfig {
+            workload: args.workload.name().to_string(),
+            key_len: match args.workload {
+                Workload::Tao => tao::OBJECT_KEY_LEN,
+                _ => YCSB_KEY_LEN,
+            },
+            value_len: match args.workload {
+                Workload::Ycsb => YCSB_VAL_LEN,
+                Workload::Auth => auth::PASSWORD_LEN,
+                Workload::Tao => tao::OBJECT_LEN,
+            },
+            put_pct: match args.workload {
+                Workload::Ycsb => args.put_pct as usize,
+                _ => 0,
+            },
+            use_invoke: args.workload != Workload::Ycsb || args.invoke_pct == 100,
+            transport: "embedded".to_string(),
+        },
+        phases: vec![Phase {
+            name: "steady".to_string(),
+            classes: classes(counters, elapsed),
+            perf: None,
+        }],
+        sweep: None,
+        server: vec![],
+    }
+}
+
+// Sweeps the window to find the knee of the latency-throughput curve, running an epoch at every
+// window the sweep picks. Prints a row per epoch, and the knee.
+//
+// # Return
+//
+// The results, with a phase per epoch, and the counters of every class of operations across
+// every epoch.
+fn tune(master: &Arc<Master>, args: &Arc<Args>) -> (RunResult, Vec<Counters>) {
+    let mut counters = vec![Counters::new(); OPS.len()];
+    let mut phases = Vec::new();
+    let sweep = autotune::sweep(args.tune(), |window| {
+        let (c, elapsed, perf) = epoch(master, args, window, phases.len());
+        for (total, c) in counters.iter_mut().zip(c.iter()) {
+            total.merge(c);
+        }
+        phases.push(Phase {
+            name: format!("window-{}", window),
+            classes: classes(&c, elapsed),
+            perf: perf,
+        });
+
+        summary(window, &c, elapsed)
+    });
+
+    eprintln!(
+        "{:>8} {:>12} {:>10} {:>10}",
+        "Window", "Ops/s", "p50(us)", "p99(us)"
+    );
+    for e in sweep.epochs.iter().chain(sweep.probes.iter()) {
+        eprintln!(
+            "{:>8} {:>12.0} {:>10.2} {:>10.2}",
+            e.load,
+            e.throughput,
+            e.p50 as f64 / 1e3,
+            e.p99 as f64 / 1e3
+        );
+    }
+    eprintln!(
+        "Embedded autotuning stopped on {:?}, knee at window {}",
+        sweep.stop,
+        sweep
+            .knee
+            .map_or("none".to_string(), |k| k.load.to_string())
+    );
+
+    // The single phase covering the whole run is replaced by the epochs'.
+    let mut result = results(args, &counters, 1.0);
+    result.phases = phases;
+    result.sweep = Some(sweep);
+    (result, counters)
+}
+
+// Runs an epoch at every fill rate while a fill populates the table the workload runs on, past
+// the records the workload picks keys from. The rate is changed on the running fill, the way an
+// operator would, rather than a new fill being started for each epoch. Prints a row per epoch.
+//
+// # Return
+//
+// The results, with a phase per epoch, the counters of every class of operations across every
+// epoch, and true if the fill ran faster than it's rate on any epoch.
+fn fills(master: &Arc<Master>, args: &Arc<Args>) -> (RunResult, Vec<Counters>, bool) {
+    let objects = fill::numbered(
+        args.records + 1,
+        args.fill_records,
+        YCSB_KEY_LEN,
+        YCSB_VAL_LEN,
+    );
+    let mut fill = master
+        .fill(TENANT, TABLE, objects)
+        .expect("Failed to start fill.");
+    master.fill_rate().set(args.fill_rates[0]);
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let filler = {
+        let (master, stop) = (Arc::clone(master), Arc::clone(&stop));
+        thread::spawn(move || {
+            while !fill.done() && !stop.load(Ordering::Relaxed) {
+                if fill.step(master.heap()) == 0 {
+                    thread::sleep(fill.delay());
+                }
+            }
+            fill.written()
+        })
+    };
+
+    let mut counters = vec![Counters::new(); OPS.len()];
+    let mut phases = Vec::new();
+    let mut rows = Vec::new();
+    for (n, rate) in args.fill_rates.iter().enumerate() {
+        master.fill_rate().set(*rate);
+        let before = fill::filled();
+        let (c, elapsed, perf) = epoch(master, args, args.window, n);
+        let filled = (fill::filled() - before) as f64 / elapsed;
+
+        for (total, c) in counters.iter_mut().zip(c.iter()) {
+            total.merge(c);
+        }
+        let gets = c[Op::Get.index()].histogram();
+        rows.push((*rate, filled, gets.percentile(0.5), gets.percentile(0.99)));
+        phases.push(Phase {
+            name: format!("fill-{}", rate),
+            classes: classes(&c, elapsed),
+            perf: perf,
+        });
+    }
+
+    stop.store(true, Ordering::Relaxed);
+    let written = filler.join().expect("ERROR: Fill thread panicked.");
+
+    eprintln!(
+        "{:>10} {:>12} {:>10} {:>10}",
+        "Cap/s", "Filled/s", "p50(us)", "p99(us)"
+    );
+    let mut over = false;
+    for &(rate, filled, p50, p99) in rows.iter() {
+        eprintln!(
+            "{:>10} {:>12.0} {:>10.2} {:>10.2}",
+            match rate {
+                0 => "none".to_string(),
+                rate => rate.to_string(),
+            },
+            filled,
+            p50 as f64 / 1e3,
+            p99 as f64 / 1e3
+        );
+
+        // A batch may be let through on top of the rate.
+        let slack = fill::FILL_BATCH as f64 / args.seconds;
+        over |= rate > 0 && filled > rate as f64 * 1.1 + slack;
+    }
+    eprintln!(
+        "Embedded fill wrote {} of {} objects",
+        written, args.fill_records
+    );
+
+    // The single phase covering the whole run is replaced by the epochs'.
+    let mut result = results(args, &counters, 1.0);
+    result.phases = phases;
+    (result, counters, over)
+}
+
+fn main() {
+    db::env_logger::init().expect("ERROR: failed to initialize logger!");
+
+    let mut args = match parse_args(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            println!("{}", e);
+            process::exit(2);
+        }
+    };
+
+    // Every thread's requests are drawn off the seed, which is reported with the results so that
+    // the run can be reproduced.
+    while args.seed == 0 {
+        args.seed = rand::random();
+    }
+    eprintln!("Embedded workload seed {}", args.seed);
+    let args = Arc::new(args);
+
+    // Initialize DPDK's memory pools without attaching to any NIC.
+    init_system_wl("embedded", 0, &[]);
+
+    let master = Arc::new(Master::new());
+    match args.workload {
+        Workload::Ycsb => master.fill_test(TENANT, TABLE, args.records),
+        Workload::Auth => master.fill_auth(TENANT, TABLE, args.records),
+        Workload::Tao => master.fill_tao(TENANT, args.records),
+    }
+    master.load_test(TENANT);
+
+    let (result, counters, over) = match (args.autotune, args.fill_rates.is_empty()) {
+        (0, true) => {
+            let (counters, elapsed, perf) = epoch(&master, &args, args.window, 0);
+            let mut result = results(&args, &counters, elapsed);
+            result.phases[0].perf = perf;
+            (result, counters, false)
+        }
+
+        (0, false) => fills(&master, &args),
+
+        _ => {
+            let (result, counters) = tune(&master, &args);
+            (result, counters, false)
+        }
+    };
+
+    let (mut completed, mut errors) = (0, 0);
+    for (op, c) in OPS.iter().zip(counters.iter()) {
+        if c.completed + c.errors > 0 {
+            eprintln!(
+                "Embedded {} {} Completed {} Errors {}",
+                args.workload.name(),
+                op.name(),
+                c.completed,
+                c.errors
+            );
+        }
+        completed += c.completed;
+        errors += c.errors;
+    }
+
+    // Hardware events are summed across threads; the results carry them per thread.
+    for phase in result.phases.iter() {
+        if let Some(ref perf) = phase.perf {
+            for note in perf.notes.iter() {
+                eprintln!("Embedded perf {} {}", phase.name, note);
+            }
+            eprintln!("Embedded perf {} {}", phase.name, perf.total);
+        }
+    }
+
+    let written = match args.out {
+        Some(ref path) => result.save(path),
+        None => result.write(&mut ::std::io::stdout()),
+    };
+    if let Err(e) = written {
+        println!("{}", e);
+        process::exit(2);
+    }
+
+    if completed == 0 || errors > 0 {
+        eprintln!(
+            "Embedded run completed {} ops with {} errors",
+            completed, errors
+        );
+        process::exit(1);
+    }
+
+    if !result.sweep.as_ref().map_or(true, |sweep| sweep.monotone()) {
+        eprintln!("Embedded autotuning did not raise the window on every epoch");
+        process::exit(1);
+    }
+
+    if over {
+        eprintln!("Embedded fill ran faster than it's rate");
+        process::exit(1);
+    }
+}
+
+// This module contains tests for the parts of the embedded runner that don't need DPDK.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(s: &str) -> Result<Args, String> {
+        parse_args(s.split_whitespace().map(|a| a.to_string()))
+    }
+
+    // Tests that flags are parsed whether their value is separate or after an '='.
+    #[test]
+    fn test_parse_args() {
+        let a = args("--workload tao --seconds=0.5 --threads 4").unwrap();
+        assert_eq!(Workload::Tao, a.workload);
+        assert_eq!(0.5, a.seconds);
+        assert_eq!(4, a.threads);
+        assert_eq!(Workload::Tao.records(), a.records);
+
+        assert!(args("--workload bogus").is_err());
+        assert!(args("--seconds").is_err());
+        assert!(args("--put-pct 101").is_err());
+        assert!(args("--threads 0").is_err());
+        assert!(args("ycsb").is_err());
+
+        let a = args("--window 4 --autotune=64 --p99-ceiling-us 50 --refine 3").unwrap();
+        let tune = a.tune();
+        assert_eq!((4, 64, 3), (tune.start, tune.max, tune.refine));
+        assert_eq!(50 * 1000, tune.p99_ceiling);
+        assert_eq!(0.05, tune.min_gain);
+        assert_eq!(
+            u64::max_value(),
+            args("--autotune 8").unwrap().tune().p99_ceiling
+        );
+        assert!(args("--window 16 --autotune 8").is_err());
+        assert!(args("--min-gain -1").is_err());
+
+        let a = args("--fill-rates 0,1000,50000 --fill-records 10").unwrap();
+        assert_eq!(vec![0, 1000, 50000], a.fill_rates);
+        assert_eq!(10, a.fill_records);
+        assert!(args("--fill-rates 1000,x").is_err());
+        assert!(args("--workload tao --fill-rates 1000").is_err());
+        assert!(args("--autotune 64 --fill-rates 1000").is_err());
+
+        assert!(!args("--seconds 1").unwrap().perf);
+        assert!(args("--perf-counters true").unwrap().perf);
+        assert!(args("--perf-counters yes").is_err());
+    }
+
+    // Tests that the seed lands in the results, and that threads run off it draw the same
+    // requests every time.
+    #[test]
+    fn test_seed() {
+        let a = args("--workload tao --seed 1234").unwrap();
+        assert_eq!(1234, a.seed);
+        assert_eq!(1234, results(&a, &[], 1.0).seed);
+
+        let draws = |thread| {
+            let mut gen = Generator::new(&a, thread);
+            (0..100).map(|_| gen.rng.gen::<u32>()).collect::<Vec<u32>>()
+        };
+        assert_eq!(draws(0), draws(0));
+        assert!(draws(0) != draws(1));
+    }
+
+    // Tests that latencies land in the right buckets, errors are kept out of them, and the
+    // histogram reported is trimmed past the last sample.
+    #[test]
+    fn test_counters() {
+        let mut c = Counters::new();
+        c.record(RpcStatus::StatusOk, 1);
+        c.record(RpcStatus::StatusOk, BUCKET_NS);
+        c.record(RpcStatus::StatusOk, BUCKET_NS + 1);
+        c.record(RpcStatus::StatusOk, u64::max_value());
+        c.record(RpcStatus::StatusObjectDoesNotExist, 10);
+        assert_eq!(4, c.completed);
+        assert_eq!(1, c.errors);
+
+        let h = c.histogram();
+        assert_eq!(N_BUCKETS, h.bounds.len());
+        assert_eq!(&[2, 1], &h.counts[0..2]);
+        assert_eq!(1, h.counts[N_BUCKETS - 1]);
+        assert_eq!(4, h.total());
+
+        let mut other = Counters::new();
+        other.record(RpcStatus::StatusOk, 1);
+        other.merge(&c);
+        assert_eq!(5, other.completed);
+        assert_eq!(3, other.histogram().counts[0]);
+    }
+
+    // Tests that results only report classes that were issued, and are labeled embedded.
+    #[test]
+    fn test_results() {
+        let a = args("--workload ycsb --invoke-pct 0 --put-pct 5").unwrap();
+        let mut counters = vec![Counters::new(); OPS.len()];
+        for _ in 0..10 {
+            counters[Op::Get.index()].record(RpcStatus::StatusOk, 500);
+        }
+
+        let r = results(&a, &counters, 2.0);
+        assert_eq!(0, r.seed);
+        assert_eq!("ycsb-k30-v100-p5-native-embedded", r.config.signature());
+        assert_eq!(1, r.phases[0].classes.len());
+        assert_eq!("get", r.phases[0].classes[0].name);
+        assert_eq!(5.0, r.phases[0].classes[0].throughput);
+        assert_eq!(vec![0, 10], r.phases[0].classes[0].latency.counts);
+        assert_eq!(None, r.phases[0].perf);
+        assert_eq!(None, r.sweep);
+    }
+
+    // Tests that an epoch is summarized across every class of operations.
+    #[test]
+    fn test_summary() {
+        let mut counters = vec![Counters::new(); OPS.len()];
+        for _ in 0..99 {
+            counters[Op::Get.index()].record(RpcStatus::StatusOk, 500);
+        }
+        counters[Op::Put.index()].record(RpcStatus::StatusOk, 10 * BUCKET_NS);
+        counters[Op::Put.index()].record(RpcStatus::StatusInternalError, 0);
+
+        let e = summary(16, &counters, 0.5);
+        assert_eq!(16, e.load);
+        assert_eq!(200.0, e.throughput);
+        assert_eq!((500, 500), (e.p50, e.p99));
+    }
+}
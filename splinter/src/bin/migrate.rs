@@ -0,0 +1,243 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Migrates a tenant's table from one server to another over UDP. Usage:
+//!
+//! `migrate --src=<ip:port> --dst=<ip:port> [--tenant=1] [--table=1] [--batch=32]
+//!          [--batch-bytes=1024] [--in-flight=8] [--timeout-ms=1000] [--sample=100]
+//!          [--cutover] [--resume=<cursor>] [--bind=0.0.0.0]`
+//!
+//! The table must already exist on the destination. Progress is printed to stderr once a
+//! second. If the migration stops, the cursor it can be resumed from is printed; pass it back
+//! through `--resume` to pick up from there. Writes to the table on the source during the copy
+//! may or may not make it to the destination; pass `--cutover` to refuse native writes to the
+//! source once the copy completes, and stop writes before starting for an exact copy.
+//! Refer to splinter::migrate::migrate().
+//!
+//! `--export=<file>` in place of `--dst` saves the table on the source to a file of records
+//! instead, and `--import=<file>` in place of `--src` loads one onto the destination's table;
+//! refer to splinter::migrate::export() and import(). Only the table, tenant, batch and timeout
+//! flags apply to them. Imports are not verified, and can't be resumed.
+//!
+//! Exits with 0 once the copy was verified (or the file saved or loaded), 1 if the migration
+//! stopped, and 2 on bad arguments.
+
+extern crate db;
+extern crate splinter;
+
+use std::env;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::process;
+use std::time::{Duration, Instant};
+
+use db::config::ClientConfig;
+
+use splinter::migrate::{
+    export, import, migrate, Cursor, Endpoint, MigrateConfig, MigrateError, Progress,
+};
+
+// One side of a migration: a server's address, or a file of records.
+enum Side {
+    Server((String, u16)),
+    File(String),
+}
+
+// Parsed command line.
+struct Args {
+    src: Side,
+    dst: Side,
+    bind: String,
+    config: MigrateConfig,
+}
+
+// Parses a server address of the form "ip:port".
+fn parse_addr(addr: &str) -> Option<(String, u16)> {
+    let mut parts = addr.rsplitn(2, ':');
+    let port = parts.next().and_then(|p| p.parse().ok())?;
+    let ip = parts.next()?;
+    Some((ip.to_string(), port))
+}
+
+// Parses the command line, excluding the name of the binary.
+fn parse_args<I: Iterator<Item = String>>(args: I) -> Result<Args, String> {
+    let (mut src, mut dst) = (None, None);
+    let mut bind = String::from("0.0.0.0");
+    let mut config = MigrateConfig::default();
+
+    for arg in args {
+        let mut parts = arg.trim_left_matches("--").splitn(2, '=');
+        let bad = || format!("Invalid flag {}", arg);
+        match (parts.next(), parts.next()) {
+            (Some("src"), Some(v)) => src = Some(Side::Server(parse_addr(v).ok_or_else(bad)?)),
+            (Some("dst"), Some(v)) => dst = Some(Side::Server(parse_addr(v).ok_or_else(bad)?)),
+            (Some("import"), Some(v)) => src = Some(Side::File(v.to_string())),
+            (Some("export"), Some(v)) => dst = Some(Side::File(v.to_string())),
+            (Some("bind"), Some(v)) => bind = v.to_string(),
+            (Some("tenant"), Some(v)) => config.tenant = v.parse().map_err(|_| bad())?,
+            (Some("table"), Some(v)) => config.table = v.parse().map_err(|_| bad())?,
+            (Some("batch"), Some(v)) => config.batch_records = v.parse().map_err(|_| bad())?,
+            (Some("batch-bytes"), Some(v)) => config.batch_bytes = v.parse().map_err(|_| bad())?,
+            (Some("in-flight"), Some(v)) => config.in_flight = v.parse().map_err(|_| bad())?,
+            (Some("sample"), Some(v)) => config.sample = v.parse().map_err(|_| bad())?,
+            (Some("cutover"), None) => config.cutover = true,
+            (Some("resume"), Some(v)) => config.resume = Cursor::parse(v).ok_or_else(bad)?,
+            (Some("timeout-ms"), Some(v)) => {
+                config.timeout = Duration::from_millis(v.parse().map_err(|_| bad())?)
+            }
+            _ => return Err(bad()),
+        }
+    }
+
+    if config.batch_records == 0 || config.in_flight == 0 {
+        return Err("--batch and --in-flight must be atleast 1".to_string());
+    }
+
+    if let (&Some(Side::File(_)), &Some(Side::File(_))) = (&src, &dst) {
+        return Err("--import and --export can't be used together".to_string());
+    }
+
+    Ok(Args {
+        src: src.ok_or("Missing --src or --import")?,
+        dst: dst.ok_or("Missing --dst or --export")?,
+        bind: bind,
+        config: config,
+    })
+}
+
+// Connects to the server at `addr`, from an ephemeral port on `bind`.
+fn connect(bind: &str, addr: &(String, u16)) -> Endpoint {
+    let mut config = ClientConfig::default();
+    config.ip_address = bind.to_string();
+    config.server_ip_address = addr.0.clone();
+    config.udp_client_port = 0;
+    config.udp_server_port = addr.1;
+
+    Endpoint::connect(&config).unwrap_or_else(|e| {
+        eprintln!("Failed to connect to {}:{}: {}", addr.0, addr.1, e);
+        process::exit(1);
+    })
+}
+
+fn print(p: &Progress) {
+    eprintln!(
+        "{} records in {} batches, {:.0} records/s, {:.2} MB/s, at {}",
+        p.records,
+        p.batches,
+        p.records_per_sec(),
+        p.mb_per_sec(),
+        p.acked
+    );
+}
+
+// Saves the table on a server to a file, and exits.
+fn save(src: &Endpoint, config: &MigrateConfig, path: &str) -> ! {
+    let out = File::create(path).unwrap_or_else(|e| {
+        eprintln!("Failed to create {}: {}", path, e);
+        process::exit(1);
+    });
+
+    let res = export(src, config, BufWriter::new(out)).and_then(|(mut out, n)| {
+        let flushed = out.flush().map_err(|e| MigrateError::File(e.to_string()));
+        flushed.map(|_| n)
+    });
+    match res {
+        Ok(n) => println!("Exported {} records to {}", n, path),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+    process::exit(0);
+}
+
+// Loads a file onto the table on a server, and exits.
+fn load(dst: &Endpoint, config: &MigrateConfig, path: &str) -> ! {
+    let input = File::open(path).unwrap_or_else(|e| {
+        eprintln!("Failed to open {}: {}", path, e);
+        process::exit(1);
+    });
+
+    let mut last = Instant::now();
+    let res = import(dst, config, BufReader::new(input), &mut |p| {
+        if last.elapsed() >= Duration::from_secs(1) {
+            print(p);
+            last = Instant::now();
+        }
+    });
+
+    match res {
+        Ok(p) => {
+            print(&p);
+            println!("Imported {} records from {}", p.records, path);
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+    process::exit(0);
+}
+
+fn main() {
+    let args = match parse_args(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
+    };
+
+    let (src, dst) = match (&args.src, &args.dst) {
+        (&Side::Server(ref src), &Side::Server(ref dst)) => (src, dst),
+        (&Side::Server(ref src), &Side::File(ref path)) => {
+            save(&connect(&args.bind, src), &args.config, path)
+        }
+        (&Side::File(ref path), &Side::Server(ref dst)) => {
+            load(&connect(&args.bind, dst), &args.config, path)
+        }
+        _ => unreachable!(),
+    };
+    let (src, dst) = (connect(&args.bind, src), connect(&args.bind, dst));
+
+    let mut last = Instant::now();
+    let res = migrate(&src, &dst, &args.config, &mut |p| {
+        if last.elapsed() >= Duration::from_secs(1) {
+            print(p);
+            last = Instant::now();
+        }
+    });
+
+    match res {
+        Ok(summary) => {
+            print(&summary.progress);
+            println!(
+                "Verified {} records, {} sampled{}",
+                summary.records,
+                summary.sampled,
+                if summary.cutover {
+                    ", source is read-only"
+                } else {
+                    ""
+                }
+            );
+        }
+
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+}
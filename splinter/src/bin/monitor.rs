@@ -0,0 +1,225 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Prints what a server's cores have been doing: how long each backed off polling, and how long
+//! the server took to respond to each opcode and extension, as measured by the server itself.
+//! Usage:
+//!
+//! `monitor --server=<ip:port> [--timeout-ms=500] [--save=<result.json>] [--bind=0.0.0.0]`
+//!
+//! Pass `--save` with the result file of a client run against the server to add the server's
+//! latency histogram of every opcode to it, so that the compare tool can diff them across runs.
+//! The server's latencies accumulate from the time it started, so it should only have served
+//! the one run.
+//!
+//! Exits with 0 on success, 1 if the server did not respond, and 2 on bad arguments.
+
+extern crate db;
+extern crate splinter;
+
+use std::env;
+use std::io;
+use std::mem::transmute;
+use std::path::PathBuf;
+use std::process;
+use std::time::Duration;
+
+use db::backoff::Tier;
+use db::config::ClientConfig;
+use db::wireformat::OpCode;
+
+use splinter::compare::{Class, RunResult};
+use splinter::migrate::Endpoint;
+
+// Parsed command line.
+struct Args {
+    server: (String, u16),
+    bind: String,
+    timeout: Duration,
+    save: Option<PathBuf>,
+}
+
+// Parses a server address of the form "ip:port".
+fn parse_addr(addr: &str) -> Option<(String, u16)> {
+    let mut parts = addr.rsplitn(2, ':');
+    let port = parts.next().and_then(|p| p.parse().ok())?;
+    let ip = parts.next()?;
+    Some((ip.to_string(), port))
+}
+
+// Parses the command line, excluding the name of the binary.
+fn parse_args<I: Iterator<Item = String>>(args: I) -> Result<Args, String> {
+    let mut server = None;
+    let mut bind = String::from("0.0.0.0");
+    let mut timeout = Duration::from_millis(500);
+    let mut save = None;
+
+    for arg in args {
+        let mut parts = arg.trim_left_matches("--").splitn(2, '=');
+        let bad = || format!("Invalid flag {}", arg);
+        match (parts.next(), parts.next()) {
+            (Some("server"), Some(v)) => server = Some(parse_addr(v).ok_or_else(bad)?),
+            (Some("bind"), Some(v)) => bind = v.to_string(),
+            (Some("save"), Some(v)) => save = Some(PathBuf::from(v)),
+            (Some("timeout-ms"), Some(v)) => {
+                timeout = Duration::from_millis(v.parse().map_err(|_| bad())?)
+            }
+            _ => return Err(bad()),
+        }
+    }
+
+    Ok(Args {
+        server: server.ok_or("Missing --server")?,
+        bind: bind,
+        timeout: timeout,
+        save: save,
+    })
+}
+
+// Reports a request the server did not respond to, and exits.
+fn failed(what: &str, addr: &str, e: io::Error) -> ! {
+    eprintln!("No {} from {}: {}", what, addr, e);
+    process::exit(1);
+}
+
+// Returns the opcode with a value off a latency_stats() response, if it is a valid one.
+fn opcode(value: u8) -> Option<OpCode> {
+    match value < OpCode::InvalidOperation as u8 {
+        true => Some(unsafe { transmute(value) }),
+        false => None,
+    }
+}
+
+fn main() {
+    let args = match parse_args(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
+    };
+
+    // Load the results up front, so that a bad path is reported before the server is asked.
+    let mut result = args.save.as_ref().map(|path| {
+        RunResult::load(path).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            process::exit(2);
+        })
+    });
+
+    let mut config = ClientConfig::default();
+    config.ip_address = args.bind.clone();
+    config.server_ip_address = args.server.0.clone();
+    config.udp_client_port = 0;
+    config.udp_server_port = args.server.1;
+
+    let addr = format!("{}:{}", args.server.0, args.server.1);
+    let server = Endpoint::connect(&config).unwrap_or_else(|e| {
+        eprintln!("Failed to connect to {}: {}", addr, e);
+        process::exit(1);
+    });
+
+    let (cores, (user, system)) = server
+        .core_stats(args.timeout)
+        .unwrap_or_else(|e| failed("core_stats", &addr, e));
+    println!(
+        "{:<6} {:>12} {:>12} {:>12} {:>12}",
+        "core", "spins", "spin us", "sleeps", "sleep us"
+    );
+    for core in cores.iter() {
+        let spin = core.tiers[Tier::Spin as usize];
+        let sleep = core.tiers[Tier::Sleep as usize];
+        println!(
+            "{:<6} {:>12} {:>12} {:>12} {:>12}",
+            core.core,
+            spin.pauses,
+            spin.nanos / 1000,
+            sleep.pauses,
+            sleep.nanos / 1000
+        );
+    }
+    println!(
+        "CPU time {} ms user, {} ms system",
+        user / 1000,
+        system / 1000
+    );
+
+    let ops = server
+        .latency_stats(args.timeout)
+        .unwrap_or_else(|e| failed("latency_stats", &addr, e));
+    println!();
+    println!(
+        "{:<28} {:>12} {:>12} {:>12} {:>12}",
+        "opcode", "count", "p50 ns", "p99 ns", "p99.9 ns"
+    );
+    for &(op, ref summary) in ops.iter() {
+        let name = match opcode(op) {
+            Some(op) => format!("{:?}", op),
+            None => format!("{:#04x}", op),
+        };
+        println!(
+            "{:<28} {:>12} {:>12} {:>12} {:>12}",
+            name, summary.count, summary.p50, summary.p99, summary.p999
+        );
+    }
+
+    let exts = server
+        .ext_latency_stats(args.timeout)
+        .unwrap_or_else(|e| failed("latency_stats", &addr, e));
+    if !exts.is_empty() {
+        println!();
+        println!(
+            "{:<28} {:>12} {:>12} {:>12} {:>12}",
+            "extension", "count", "p50 ns", "p99 ns", "p99.9 ns"
+        );
+        for &(ref name, ref summary) in exts.iter() {
+            println!(
+                "{:<28} {:>12} {:>12} {:>12} {:>12}",
+                String::from_utf8_lossy(name),
+                summary.count,
+                summary.p50,
+                summary.p99,
+                summary.p999
+            );
+        }
+    }
+
+    if let (Some(path), Some(result)) = (args.save.as_ref(), result.as_mut()) {
+        result.server.clear();
+        for op in ops.iter().filter_map(|&(op, _)| opcode(op)) {
+            let name = format!("{:?}", op);
+            let latency = server
+                .latency_histogram(op, &[], args.timeout)
+                .unwrap_or_else(|e| failed("latency_stats", &addr, e));
+            result.server.push(Class {
+                name: name,
+                throughput: 0.0,
+                latency: latency,
+                duplicates: 0,
+            });
+        }
+
+        if let Err(e) = result.save(path) {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+        println!();
+        println!(
+            "Saved the latency of {} opcodes to {}",
+            result.server.len(),
+            path.display()
+        );
+    }
+}
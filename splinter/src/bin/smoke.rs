@@ -0,0 +1,147 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Runs a short deterministic script of requests against a server over UDP, and checks that
+//! every response is exactly what it should be. Usage:
+//!
+//! `smoke --server=<ip:port> [--tenant=1] [--table=1] [--fill=0] [--timeout-ms=500]
+//!        [--invoke=<name:args[:output]>]... [--bind=0.0.0.0]`
+//!
+//! The table must already exist on the server; a few records with keys starting with "smoke:"
+//! are written to it. Pass `--fill` if the server filled the table with test records, to read
+//! the first and last of them back. Each `--invoke` calls an extension with the arguments, and
+//! compares what it writes out against the output if there is one; both are in hex. Invocations
+//! whose arguments don't fit the layout their extension declared are refused before any check
+//! runs. Checks on opcodes the server does not serve are skipped. Refer to
+//! splinter::smoke::script().
+//!
+//! Exits with 0 if every check passed, 1 if any failed, and 2 on bad arguments.
+
+extern crate db;
+extern crate splinter;
+
+use std::env;
+use std::process;
+use std::time::Duration;
+
+use db::config::ClientConfig;
+
+use splinter::migrate::Endpoint;
+use splinter::smoke::{check_invokes, run, script, Canned, Outcome, SmokeConfig};
+
+// Parsed command line.
+struct Args {
+    server: (String, u16),
+    bind: String,
+    config: SmokeConfig,
+}
+
+// Parses a server address of the form "ip:port".
+fn parse_addr(addr: &str) -> Option<(String, u16)> {
+    let mut parts = addr.rsplitn(2, ':');
+    let port = parts.next().and_then(|p| p.parse().ok())?;
+    let ip = parts.next()?;
+    Some((ip.to_string(), port))
+}
+
+// Parses the command line, excluding the name of the binary.
+fn parse_args<I: Iterator<Item = String>>(args: I) -> Result<Args, String> {
+    let mut server = None;
+    let mut bind = String::from("0.0.0.0");
+    let mut config = SmokeConfig::default();
+
+    for arg in args {
+        let mut parts = arg.trim_left_matches("--").splitn(2, '=');
+        let bad = || format!("Invalid flag {}", arg);
+        match (parts.next(), parts.next()) {
+            (Some("server"), Some(v)) => server = Some(parse_addr(v).ok_or_else(bad)?),
+            (Some("bind"), Some(v)) => bind = v.to_string(),
+            (Some("tenant"), Some(v)) => config.tenant = v.parse().map_err(|_| bad())?,
+            (Some("table"), Some(v)) => config.table = v.parse().map_err(|_| bad())?,
+            (Some("fill"), Some(v)) => config.fill = v.parse().map_err(|_| bad())?,
+            (Some("invoke"), Some(v)) => config.invokes.push(Canned::parse(v).ok_or_else(bad)?),
+            (Some("timeout-ms"), Some(v)) => {
+                config.timeout = Duration::from_millis(v.parse().map_err(|_| bad())?)
+            }
+            _ => return Err(bad()),
+        }
+    }
+
+    Ok(Args {
+        server: server.ok_or("Missing --server")?,
+        bind: bind,
+        config: config,
+    })
+}
+
+fn main() {
+    let args = match parse_args(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
+    };
+
+    let mut config = ClientConfig::default();
+    config.ip_address = args.bind.clone();
+    config.server_ip_address = args.server.0.clone();
+    config.udp_client_port = 0;
+    config.udp_server_port = args.server.1;
+
+    let addr = format!("{}:{}", args.server.0, args.server.1);
+    let server = Endpoint::connect(&config).unwrap_or_else(|e| {
+        eprintln!("Failed to connect to {}: {}", addr, e);
+        process::exit(1);
+    });
+
+    let mismatches = check_invokes(&server, &args.config).unwrap_or_else(|e| {
+        eprintln!("No echo from {}: {}", addr, e);
+        process::exit(1);
+    });
+    if !mismatches.is_empty() {
+        for mismatch in mismatches.iter() {
+            eprintln!("{}", mismatch);
+        }
+        process::exit(2);
+    }
+
+    let report = run(&server, &script(&args.config), &args.config).unwrap_or_else(|e| {
+        eprintln!("No echo from {}: {}", addr, e);
+        process::exit(1);
+    });
+
+    for &(ref name, ref outcome) in report.outcomes.iter() {
+        println!("{:<24} {}", name, outcome);
+    }
+
+    let skipped = report
+        .outcomes
+        .iter()
+        .filter(|&&(_, ref o)| *o == Outcome::Skipped)
+        .count();
+    let failures = report.failures();
+    println!(
+        "{} checks, {} failed, {} skipped (opcodes {:#x})",
+        report.outcomes.len(),
+        failures,
+        skipped,
+        report.opcodes
+    );
+
+    if failures > 0 {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,422 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use std::cmp;
+use std::mem;
+
+use db::config::ClientConfig;
+
+/// Bounds on the bursts requests are sent out the NIC in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BurstConfig {
+    /// The number of requests a burst is flushed at. 1 sends every request out on it's own.
+    pub max_len: usize,
+
+    /// The number of cycles a burst is flushed at, counted from when it's oldest request was
+    /// enqueued.
+    pub hold_cycles: u64,
+}
+
+impl BurstConfig {
+    /// Builds burst bounds off a client config; `tx_burst` of 0 means 1.
+    ///
+    /// # Arguments
+    ///
+    /// * `config`: The client config to read `tx_burst` and `tx_hold_us` from.
+    /// * `hz`:     The rate at which the cycle counter ticks.
+    pub fn from_config(config: &ClientConfig, hz: u64) -> BurstConfig {
+        BurstConfig {
+            max_len: cmp::max(config.tx_burst, 1),
+            hold_cycles: (config.tx_hold_us as f64 * hz as f64 / 1e6) as u64,
+        }
+    }
+}
+
+/// What a Burst did over a run.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BurstStats {
+    /// The number of bursts flushed.
+    pub bursts: u64,
+
+    /// The number of requests flushed.
+    pub requests: u64,
+
+    /// The number of bursts flushed because their oldest request was held too long, instead of
+    /// because they filled up.
+    pub held: u64,
+
+    /// The sum, over every request, of the cycles between it being enqueued and flushed.
+    pub hold_cycles: u64,
+}
+
+impl BurstStats {
+    /// Returns the average number of requests on a burst.
+    pub fn average_len(&self) -> f64 {
+        match self.bursts {
+            0 => 0.0,
+            n => self.requests as f64 / n as f64,
+        }
+    }
+
+    /// Returns the average number of cycles a request was held between being enqueued and
+    /// flushed.
+    pub fn average_hold(&self) -> f64 {
+        match self.requests {
+            0 => 0.0,
+            n => self.hold_cycles as f64 / n as f64,
+        }
+    }
+}
+
+/// Accumulates outgoing requests into bursts. A burst is flushed when it holds `max_len`
+/// requests, or when it's oldest request has been held for `hold_cycles`, whichever comes first.
+/// The hold time is checked on every push(), and on every poll(); senders should poll whenever
+/// they are scheduled, so that a burst on an idle sender still goes out in time.
+///
+/// Nothing is ever held with a `max_len` of 1, so requests go out exactly as they would without
+/// bursting.
+pub struct Burst<P> {
+    // Bounds on a burst.
+    config: BurstConfig,
+
+    // The requests on the burst, each with the time-stamp it was enqueued at.
+    pending: Vec<(P, u64)>,
+
+    // An empty vector handed back through recycle(). Becomes `pending` once the current burst
+    // is handed out, so that a sender does not allocate one per burst.
+    spare: Vec<(P, u64)>,
+
+    // What this instance did so far.
+    stats: BurstStats,
+}
+
+impl<P> Burst<P> {
+    /// Creates an empty burst.
+    ///
+    /// # Arguments
+    ///
+    /// * `config`: Bounds on a burst.
+    pub fn new(config: BurstConfig) -> Burst<P> {
+        assert!(config.max_len > 0);
+        Burst {
+            config: config,
+            pending: Vec::with_capacity(config.max_len),
+            spare: Vec::new(),
+            stats: BurstStats::default(),
+        }
+    }
+
+    /// Returns the bounds on a burst.
+    pub fn config(&self) -> BurstConfig {
+        self.config
+    }
+
+    /// Returns what this instance did so far.
+    pub fn stats(&self) -> BurstStats {
+        self.stats
+    }
+
+    /// Returns the number of requests currently held.
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Enqueues a request on the burst.
+    ///
+    /// # Arguments
+    ///
+    /// * `request`: The request.
+    /// * `now`:     The current time-stamp.
+    ///
+    /// # Return
+    ///
+    /// The requests to send out now, with the time-stamps they were enqueued at, if the burst
+    /// filled up or was held too long.
+    pub fn push(&mut self, request: P, now: u64) -> Option<Vec<(P, u64)>> {
+        self.pending.push((request, now));
+        if self.pending.len() >= self.config.max_len {
+            return Some(self.take(now, false));
+        }
+
+        self.poll(now)
+    }
+
+    /// Checks whether the burst has been held too long.
+    ///
+    /// # Arguments
+    ///
+    /// * `now`: The current time-stamp.
+    ///
+    /// # Return
+    ///
+    /// The requests to send out now, with the time-stamps they were enqueued at, if the oldest
+    /// one has been held for `hold_cycles`.
+    pub fn poll(&mut self, now: u64) -> Option<Vec<(P, u64)>> {
+        let expired = match self.pending.first() {
+            Some(&(_, enqueued)) => now.saturating_sub(enqueued) >= self.config.hold_cycles,
+            None => false,
+        };
+
+        if expired {
+            Some(self.take(now, true))
+        } else {
+            None
+        }
+    }
+
+    /// Flushes whatever is held, regardless of the bounds; ex: at the end of a run.
+    ///
+    /// # Arguments
+    ///
+    /// * `now`: The current time-stamp.
+    pub fn flush(&mut self, now: u64) -> Vec<(P, u64)> {
+        match self.pending.len() {
+            0 => Vec::new(),
+            _ => self.take(now, false),
+        }
+    }
+
+    /// Hands back a burst that was sent out, so that it's vector can hold the next one.
+    ///
+    /// # Arguments
+    ///
+    /// * `burst`: A burst handed out by push(), poll(), or flush(). Anything left on it is
+    ///            dropped.
+    pub fn recycle(&mut self, mut burst: Vec<(P, u64)>) {
+        burst.clear();
+        if burst.capacity() >= self.config.max_len {
+            self.spare = burst;
+        }
+    }
+
+    // Hands out the burst, and counts it.
+    fn take(&mut self, now: u64, held: bool) -> Vec<(P, u64)> {
+        let next = match self.spare.capacity() {
+            0 => Vec::with_capacity(self.config.max_len),
+            _ => mem::replace(&mut self.spare, Vec::new()),
+        };
+        let burst = mem::replace(&mut self.pending, next);
+
+        self.stats.bursts += 1;
+        self.stats.requests += burst.len() as u64;
+        if held {
+            self.stats.held += 1;
+        }
+        for &(_, enqueued) in burst.iter() {
+            self.stats.hold_cycles += now.saturating_sub(enqueued);
+        }
+
+        burst
+    }
+}
+
+/// Schedules requests at a fixed rate for an open-loop client. Request `i` is due `i` intervals
+/// after the first one, so that requests delayed by the client (ex: held back to fill a burst)
+/// don't push back the ones after them, and the rate offered over a run stays on target.
+pub struct OpenLoop {
+    // The time-stamp the first request is due at.
+    start: u64,
+
+    // The cycles between two requests.
+    interval: u64,
+
+    // The number of requests issued so far.
+    issued: u64,
+}
+
+impl OpenLoop {
+    /// Creates a schedule whose first request is due right away.
+    ///
+    /// # Arguments
+    ///
+    /// * `start`:    The current time-stamp.
+    /// * `interval`: The cycles between two requests; the inverse of the rate.
+    pub fn new(start: u64, interval: u64) -> OpenLoop {
+        OpenLoop {
+            start: start,
+            interval: interval,
+            issued: 0,
+        }
+    }
+
+    /// Returns the number of requests issued so far.
+    pub fn issued(&self) -> u64 {
+        self.issued
+    }
+
+    /// Returns the number of requests due at a time-stamp that haven't been issued yet, upto a
+    /// limit. A sender should issue all of them back to back instead of one per call, so that
+    /// requests on the same burst aren't spread over several calls.
+    ///
+    /// # Arguments
+    ///
+    /// * `now`: The current time-stamp.
+    /// * `max`: The most requests to return.
+    pub fn due(&self, now: u64, max: u64) -> u64 {
+        if now < self.start {
+            return 0;
+        }
+
+        let due = (now - self.start) / cmp::max(self.interval, 1) + 1;
+        cmp::min(due.saturating_sub(self.issued), max)
+    }
+
+    /// Marks a number of requests as issued.
+    pub fn issue(&mut self, n: u64) {
+        self.issued += n;
+    }
+}
+
+// This module contains unit tests for Burst and OpenLoop.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn burst(max_len: usize, hold_cycles: u64) -> Burst<u64> {
+        Burst::new(BurstConfig {
+            max_len: max_len,
+            hold_cycles: hold_cycles,
+        })
+    }
+
+    // Tests that a burst is flushed once it fills up, with the time-stamps requests were
+    // enqueued at.
+    #[test]
+    fn test_burst_flush_on_len() {
+        let mut b = burst(3, 1000);
+        assert_eq!(None, b.push(1, 10));
+        assert_eq!(None, b.push(2, 20));
+        assert_eq!(None, b.poll(30));
+        assert_eq!(Some(vec![(1, 10), (2, 20), (3, 40)]), b.push(3, 40));
+        assert_eq!(0, b.len());
+
+        let stats = b.stats();
+        assert_eq!((1, 3, 0), (stats.bursts, stats.requests, stats.held));
+        assert_eq!(30 + 20, stats.hold_cycles);
+        assert_eq!(3.0, stats.average_len());
+    }
+
+    // Tests that a burst is flushed once it's oldest request has been held too long, both on
+    // poll() and on push().
+    #[test]
+    fn test_burst_flush_on_hold() {
+        let mut b = burst(8, 100);
+        assert_eq!(None, b.push(1, 0));
+        assert_eq!(None, b.poll(99));
+        assert_eq!(Some(vec![(1, 0)]), b.poll(100));
+        assert_eq!(None, b.poll(1000));
+
+        assert_eq!(None, b.push(2, 1000));
+        assert_eq!(Some(vec![(2, 1000), (3, 1150)]), b.push(3, 1150));
+
+        let stats = b.stats();
+        assert_eq!((2, 3, 2), (stats.bursts, stats.requests, stats.held));
+        assert_eq!(100 + 150, stats.hold_cycles);
+        assert_eq!(1.5, stats.average_len());
+    }
+
+    // Tests that a burst of one never holds anything, and that flush() hands out what's held.
+    #[test]
+    fn test_burst_single() {
+        let mut b = burst(1, 1 << 40);
+        for i in 0..10 {
+            assert_eq!(Some(vec![(i, i)]), b.push(i, i));
+        }
+        assert_eq!(0.0, b.stats().average_hold());
+        assert!(b.flush(10).is_empty());
+
+        let mut b = burst(4, 1 << 40);
+        b.push(1, 0);
+        assert_eq!(vec![(1, 0)], b.flush(5));
+        assert_eq!(0, b.stats().held);
+    }
+
+    // Tests that a recycled burst's vector holds a later burst instead of a fresh vector.
+    #[test]
+    fn test_burst_recycle() {
+        let mut b = burst(2, 1 << 40);
+        b.push(1, 0);
+        let first = b.push(2, 0).unwrap();
+        let ptr = first.as_ptr();
+        b.recycle(first);
+
+        b.push(3, 0);
+        assert_eq!(Some(vec![(3, 0), (4, 0)]), b.push(4, 0));
+        b.push(5, 0);
+        let third = b.push(6, 0).unwrap();
+        assert_eq!(vec![(5, 0), (6, 0)], third);
+        assert_eq!(ptr, third.as_ptr());
+    }
+
+    // Tests that requests are due on schedule, and that a late sender catches up.
+    #[test]
+    fn test_open_loop_due() {
+        let mut s = OpenLoop::new(100, 10);
+        assert_eq!(0, s.due(99, 8));
+        assert_eq!(1, s.due(100, 8));
+        s.issue(1);
+        assert_eq!(0, s.due(109, 8));
+        assert_eq!(5, s.due(150, 8));
+        assert_eq!(2, s.due(150, 2));
+    }
+
+    // Simulates a sender scheduled at irregular intervals, issuing what's due onto a burst and
+    // polling it, and checks that the rate requests go out the NIC at matches the target across
+    // burst settings, and that no request is held longer than allowed.
+    #[test]
+    fn test_open_loop_rate() {
+        let (interval, requests) = (1000, 100000);
+        for &(max_len, hold) in [(1, 0), (4, 2500), (32, 5000), (32, 500000)].iter() {
+            let mut schedule = OpenLoop::new(0, interval);
+            let mut b = burst(max_len, hold);
+            let (mut now, mut sent, mut last) = (0u64, 0, 0);
+            let mut worst = 0;
+
+            while sent < requests {
+                let due = schedule.due(now, requests - schedule.issued());
+                let mut flushed = Vec::new();
+                for _ in 0..due {
+                    flushed.extend(b.push(schedule.issued(), now).unwrap_or_default());
+                    schedule.issue(1);
+                }
+                if schedule.issued() == requests {
+                    flushed.extend(b.flush(now));
+                }
+                flushed.extend(b.poll(now).unwrap_or_default());
+
+                for &(_, enqueued) in flushed.iter() {
+                    worst = cmp::max(worst, now - enqueued);
+                }
+                if !flushed.is_empty() {
+                    sent += flushed.len() as u64;
+                    last = now;
+                }
+
+                // Scheduled anywhere between 0.1 and 1.9 intervals later.
+                now += 100 + (now * 7919) % 1800;
+            }
+
+            let rate = sent as f64 / last as f64;
+            let target = 1.0 / interval as f64;
+            assert!(
+                (rate - target).abs() / target < 0.01,
+                "{} {}",
+                max_len,
+                rate
+            );
+            assert!(worst <= cmp::max(hold, 1) + 1900, "{} {}", max_len, worst);
+        }
+    }
+}
@@ -0,0 +1,683 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::mem::size_of;
+use std::sync::{Arc, Mutex, Once, ONCE_INIT};
+
+use db::config::ClientConfig;
+use db::cycles;
+use db::histogram::LogHistogram;
+use db::rpc;
+use db::stale;
+use db::wireformat::*;
+
+// The most requests whose responses a cache waits on at once. Requests sent while this many are
+// outstanding are not tracked, so their responses are never cached.
+const MAX_PENDING: usize = 1 << 16;
+
+/// What a cache holds an object under: the tenant, the table, and a hash of the key.
+pub type CacheKey = (u32, u64, u64);
+
+/// Returns the key a cache holds an object under.
+///
+/// # Arguments
+///
+/// * `tenant`: The tenant that owns the object.
+/// * `table`:  The table the object is on.
+/// * `key`:    The object's key.
+pub fn cache_key(tenant: u32, table: u64, key: &[u8]) -> CacheKey {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    (tenant, table, hasher.finish())
+}
+
+/// What to do with a get(), as decided by HintCache::lookup().
+#[derive(Clone, Debug, PartialEq)]
+pub enum Lookup {
+    /// The object is cached, it's hint has not run out, and it is within the get()'s staleness
+    /// budget. The get() is served with this value, without going to the server. Carries the
+    /// age of the cached copy in microseconds, as the server would have echoed it.
+    Hit(Vec<u8>, u32),
+
+    /// The object is cached, but it's hint ran out. Send a multiget() for the key with
+    /// MULTIGET_FLAG_VERSIONS_ONLY set instead of the get(), and pass it's id to track().
+    Revalidate,
+
+    /// Send the get() to the server, and pass it's id to track().
+    Miss,
+}
+
+/// Counters of the gets a cache served, and of how it's objects came and went.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CacheStats {
+    /// The number of gets served off the cache.
+    pub hits: u64,
+
+    /// The number of gets sent to the server because the object was not cached.
+    pub misses: u64,
+
+    /// The number of gets sent to the server because the cached object was older than their
+    /// staleness budget, or because they asked to be served strictly fresh.
+    pub over_budget: u64,
+
+    /// The number of cached objects revalidated once their hint ran out.
+    pub revalidations: u64,
+
+    /// The number of revalidated objects that were still current, and were kept.
+    pub refreshed: u64,
+
+    /// The number of revalidated objects that had changed or were gone, and were dropped.
+    pub stale: u64,
+
+    /// The number of objects cached off get() responses.
+    pub fills: u64,
+
+    /// The number of objects dropped to make room for others.
+    pub evictions: u64,
+
+    /// The number of objects dropped because the client wrote to them.
+    pub invalidations: u64,
+}
+
+// An object held by a cache.
+struct Cached {
+    // The object's key, checked on every lookup in case another key hashes the same.
+    key: Vec<u8>,
+
+    // The object's value and version, as of when it was cached or last revalidated.
+    value: Vec<u8>,
+    version: u64,
+
+    // How long the object can be served for after it is cached or revalidated, in cycles.
+    ttl: u64,
+
+    // The time-stamp, in cycles, past which the object has to be revalidated.
+    expires: u64,
+
+    // The time-stamp, in cycles, at which the object was cached or last revalidated. Gets are
+    // only served off it if it's age is within their staleness budget.
+    refreshed: u64,
+
+    // Set once a revalidation was sent out, until it's response arrives.
+    revalidating: bool,
+}
+
+// A get() or revalidation sent to the server, whose response a cache is waiting on.
+struct Pending {
+    key: CacheKey,
+    bytes: Vec<u8>,
+}
+
+/// A bounded cache of objects the server hinted were read often (see GET_HINT_HOT), shared by a
+/// pipeline's sender and receiver. The sender looks each get() up, serving hits itself and
+/// tracking the requests it sends out; the receiver hands every response to observe(), which
+/// caches hinted objects and revalidates expired ones.
+///
+/// An object is served until the TTL on it's hint runs out, and only to gets whose staleness
+/// budget covers how long ago it was cached or revalidated (see db::stale). A get() with a budget
+/// of 0 always goes to the server. Once the TTL runs out, the object is revalidated with a
+/// version-only multiget(), and served again for another TTL if it's version did not change.
+/// Puts made by the client drop the object, along with any response to it still in flight. Puts
+/// made by other clients are only noticed on revalidation.
+pub struct HintCache {
+    // The most objects held at once.
+    capacity: usize,
+
+    // The rate of the client's cycle counter, to convert TTLs with.
+    hz: u64,
+
+    // The objects held, and the order they were cached in. The oldest is evicted first.
+    objects: HashMap<CacheKey, Cached>,
+    order: VecDeque<CacheKey>,
+
+    // The requests whose responses are yet to arrive, by request id.
+    pending: HashMap<u64, Pending>,
+
+    stats: CacheStats,
+
+    // The cycles taken to serve each hit, kept apart from the latency of requests that went to
+    // the server.
+    hits: LogHistogram,
+}
+
+impl HintCache {
+    /// Creates an empty cache.
+    ///
+    /// # Arguments
+    ///
+    /// * `capacity`: The most objects held at once. Atleast 1.
+    /// * `hz`:       The rate of the cycle counter time-stamps are passed in off.
+    pub fn new(capacity: usize, hz: u64) -> HintCache {
+        HintCache {
+            capacity: capacity.max(1),
+            hz: hz,
+            objects: HashMap::with_capacity(capacity),
+            order: VecDeque::with_capacity(capacity),
+            pending: HashMap::new(),
+            stats: CacheStats::default(),
+            hits: LogHistogram::new(),
+        }
+    }
+
+    /// Decides what to do with a get().
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`: The tenant the get() is issued for.
+    /// * `table`:  The table the get() reads.
+    /// * `key`:    The key the get() looks up.
+    /// * `budget`: The staleness budget on the get(), in microseconds. 0 means strictly fresh.
+    /// * `now`:    The current time-stamp, in cycles.
+    pub fn lookup(&mut self, tenant: u32, table: u64, key: &[u8], budget: u32, now: u64) -> Lookup {
+        let hz = self.hz;
+        let mut over_budget = false;
+        let lookup = match self.objects.get_mut(&cache_key(tenant, table, key)) {
+            Some(cached) => {
+                let age = stale::age_us(cached.refreshed, now, hz);
+                if cached.key != key {
+                    Lookup::Miss
+                } else if !stale::within(age, budget) {
+                    // The cached copy is too old for the get(), which falls through to the server.
+                    over_budget = true;
+                    Lookup::Miss
+                } else if now < cached.expires {
+                    Lookup::Hit(cached.value.clone(), age)
+                } else if cached.revalidating {
+                    // Gets that come in while a revalidation is in flight go to the server.
+                    Lookup::Miss
+                } else {
+                    cached.revalidating = true;
+                    Lookup::Revalidate
+                }
+            }
+
+            None => Lookup::Miss,
+        };
+
+        match lookup {
+            Lookup::Hit(_, _) => self.stats.hits += 1,
+            Lookup::Revalidate => self.stats.revalidations += 1,
+            Lookup::Miss if over_budget => self.stats.over_budget += 1,
+            Lookup::Miss => self.stats.misses += 1,
+        }
+        lookup
+    }
+
+    /// Records the time taken to serve a hit.
+    ///
+    /// # Arguments
+    ///
+    /// * `cycles`: The cycles from when the get() was generated to when it was served.
+    pub fn record_hit(&mut self, cycles: u64) {
+        self.hits.record(cycles);
+    }
+
+    /// Waits on the response to a get() or revalidation sent out after a lookup().
+    ///
+    /// # Arguments
+    ///
+    /// * `id`:     The id of the request.
+    /// * `tenant`: The tenant the request was issued for.
+    /// * `table`:  The table the request reads.
+    /// * `key`:    The key the request looks up.
+    pub fn track(&mut self, id: u64, tenant: u32, table: u64, key: &[u8]) {
+        if self.pending.len() >= MAX_PENDING {
+            return;
+        }
+
+        self.pending.insert(
+            id,
+            Pending {
+                key: cache_key(tenant, table, key),
+                bytes: key.to_vec(),
+            },
+        );
+    }
+
+    /// Drops an object the client is writing to, and ignores the responses to any get() or
+    /// revalidation of it still in flight, since they could carry the value being overwritten.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`: The tenant the put() is issued for.
+    /// * `table`:  The table the put() writes to.
+    /// * `key`:    The key the put() writes to.
+    pub fn invalidate(&mut self, tenant: u32, table: u64, key: &[u8]) {
+        let key = cache_key(tenant, table, key);
+        self.pending.retain(|_, p| p.key != key);
+        if self.remove(&key) {
+            self.stats.invalidations += 1;
+        }
+    }
+
+    /// Handles a response. Hinted objects on get() responses are cached, and expired objects
+    /// are served again or dropped off version-only multiget() responses. Responses to requests
+    /// that were not tracked are ignored.
+    ///
+    /// # Arguments
+    ///
+    /// * `buf`: The response, starting at the RPC response header.
+    /// * `now`: The time-stamp, in cycles, at which the response was received.
+    pub fn observe(&mut self, buf: &[u8], now: u64) {
+        if buf.len() < size_of::<RpcResponseHeader>() {
+            return;
+        }
+
+        // Wireformat headers are packed, so the pointer does not have to be aligned.
+        let id = unsafe { &*(buf.as_ptr() as *const RpcResponseHeader) }.id();
+        let pending = match self.pending.remove(&id) {
+            Some(pending) => pending,
+            None => return,
+        };
+
+        if buf[1] == OpCode::SandstormGetRpc as u8 {
+            self.fill(pending, buf, now);
+        } else if buf[1] == OpCode::SandstormMultiGetRpc as u8 {
+            self.revalidate(pending, buf, now);
+        }
+    }
+
+    /// Returns the counters of the cache.
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    /// Returns the cycles taken to serve each hit.
+    pub fn hit_latency(&self) -> &LogHistogram {
+        &self.hits
+    }
+
+    // Caches the object on a get() response, if the server hinted it and the response carries
+    // all of it's value.
+    fn fill(&mut self, pending: Pending, buf: &[u8], now: u64) {
+        if buf.len() < size_of::<GetResponse>() || buf[0] != RpcStatus::StatusOk as u8 {
+            return;
+        }
+
+        let hdr = unsafe { &*(buf.as_ptr() as *const GetResponse) };
+        let value = &buf[size_of::<GetResponse>()..];
+        let whole = hdr.full_length() as usize == value.len();
+        if hdr.hint & GET_HINT_HOT == 0 || hdr.ttl_us() == 0 || !whole {
+            return;
+        }
+
+        let ttl = hdr.ttl_us() as u64 * self.hz / 1_000_000;
+        if !self.objects.contains_key(&pending.key) {
+            if self.objects.len() >= self.capacity {
+                if let Some(oldest) = self.order.pop_front() {
+                    self.objects.remove(&oldest);
+                    self.stats.evictions += 1;
+                }
+            }
+            self.order.push_back(pending.key);
+        }
+
+        let cached = Cached {
+            key: pending.bytes,
+            value: value.to_vec(),
+            version: hdr.version(),
+            ttl: ttl,
+            expires: now + ttl,
+            refreshed: now,
+            revalidating: false,
+        };
+        self.objects.insert(pending.key, cached);
+        self.stats.fills += 1;
+    }
+
+    // Serves an expired object for another TTL if the version on a revalidation's response
+    // matches the cached one, and drops it otherwise.
+    fn revalidate(&mut self, pending: Pending, buf: &[u8], now: u64) {
+        let version = HintCache::version(buf);
+        let current = match self.objects.get_mut(&pending.key) {
+            Some(cached) => {
+                let current = cached.key == pending.bytes && Some(cached.version) == version;
+                if current {
+                    cached.expires = now + cached.ttl;
+                    cached.refreshed = now;
+                    cached.revalidating = false;
+                }
+                current
+            }
+
+            None => return,
+        };
+
+        match current {
+            true => self.stats.refreshed += 1,
+            false => {
+                self.remove(&pending.key);
+                self.stats.stale += 1;
+            }
+        }
+    }
+
+    // Returns the version on the response to a version-only multiget() of a single key, or None
+    // if the response failed.
+    fn version(buf: &[u8]) -> Option<u64> {
+        if buf.len() < size_of::<MultiGetResponse>() || buf[0] != RpcStatus::StatusOk as u8 {
+            return None;
+        }
+
+        let hdr = unsafe { &*(buf.as_ptr() as *const MultiGetResponse) };
+        let payload = &buf[size_of::<MultiGetResponse>()..];
+        rpc::parse_versioned_records(payload, hdr.num_records(), false)
+            .and_then(|records| records.first().map(|&(version, _)| version))
+    }
+
+    // Drops an object. Returns true if it was cached.
+    fn remove(&mut self, key: &CacheKey) -> bool {
+        if self.objects.remove(key).is_none() {
+            return false;
+        }
+
+        self.order.retain(|k| k != key);
+        true
+    }
+}
+
+// The caches of the pipelines on this client, created by pipeline().
+static CACHES_INIT: Once = ONCE_INIT;
+static mut CACHES: Option<Mutex<HashMap<usize, Arc<Mutex<HintCache>>>>> = None;
+
+/// Returns the cache shared by a pipeline's sender and receiver, or None if the config turns
+/// caching off. The first call for a pipeline creates it's cache; every later call returns the
+/// same one.
+///
+/// # Arguments
+///
+/// * `config`:   Client configuration.
+/// * `pipeline`: The index of the pipeline.
+pub fn pipeline(config: &ClientConfig, pipeline: usize) -> Option<Arc<Mutex<HintCache>>> {
+    if config.hint_cache == 0 {
+        return None;
+    }
+
+    unsafe {
+        CACHES_INIT.call_once(|| {
+            CACHES = Some(Mutex::new(HashMap::new()));
+        });
+
+        let caches = CACHES.as_ref().unwrap();
+        let mut caches = caches.lock().unwrap();
+        let cache = caches.entry(pipeline).or_insert_with(|| {
+            let cache = HintCache::new(config.hint_cache, cycles::cycles_per_second());
+            Arc::new(Mutex::new(cache))
+        });
+        Some(Arc::clone(cache))
+    }
+}
+
+// This module contains unit tests for HintCache, driven by scripted responses.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::mem::transmute;
+    use std::slice;
+
+    // The client's clock ticks once a nanosecond, so that a microsecond is 1000 cycles.
+    const HZ: u64 = 1_000_000_000;
+
+    const TENANT: u32 = 1;
+    const TABLE: u64 = 1;
+
+    // A staleness budget that every cached object is within.
+    const ANY: u32 = u32::max_value();
+
+    // Returns the bytes a header is made up of, in the order they go out on the wire.
+    fn bytes<H>(header: &H) -> Vec<u8> {
+        let ptr = header as *const H as *const u8;
+        unsafe { slice::from_raw_parts(ptr, size_of::<H>()) }.to_vec()
+    }
+
+    // Returns a get() response carrying a value, hinted hot if `hint` is set.
+    fn get(id: u64, value: &[u8], hint: Option<(u64, u32)>) -> Vec<u8> {
+        let mut hdr = GetResponse::new(id, 0, OpCode::SandstormGetRpc, TENANT);
+        hdr.set_value_length(value.len() as u32);
+        hdr.set_full_length(value.len() as u32);
+        if let Some((version, ttl_us)) = hint {
+            hdr.hint = GET_HINT_HOT;
+            hdr.set_version(version);
+            hdr.set_ttl_us(ttl_us);
+        }
+
+        let mut buf = bytes(&hdr);
+        buf.extend_from_slice(value);
+        buf
+    }
+
+    // Returns the response to a version-only multiget() of a single key. None if the object is
+    // gone.
+    fn versions(id: u64, version: Option<u64>) -> Vec<u8> {
+        let mut hdr = MultiGetResponse::new(id, 0, OpCode::SandstormMultiGetRpc, TENANT, 0);
+        match version {
+            Some(_) => hdr.set_num_records(1),
+            None => hdr.common_header.status = RpcStatus::StatusObjectDoesNotExist,
+        }
+
+        let mut buf = bytes(&hdr);
+        if let Some(version) = version {
+            let version: [u8; 8] = unsafe { transmute(version.to_le()) };
+            buf.extend_from_slice(&version);
+        }
+        buf
+    }
+
+    // Tests that a hinted object is served off the cache until it's TTL runs out, and that
+    // objects that were not hinted are never cached.
+    #[test]
+    fn test_cache_hit_miss() {
+        let mut cache = HintCache::new(16, HZ);
+
+        assert_eq!(Lookup::Miss, cache.lookup(TENANT, TABLE, b"a", ANY, 0));
+        cache.track(1, TENANT, TABLE, b"a");
+        cache.observe(&get(1, b"value", Some((7, 100))), 1000);
+
+        // Served for 100 microseconds from when the response was received, aging all along.
+        for &(now, age) in [(1000, 0), (50_000, 49), (100_999, 99)].iter() {
+            let hit = cache.lookup(TENANT, TABLE, b"a", ANY, now);
+            assert_eq!(Lookup::Hit(b"value".to_vec(), age), hit);
+        }
+
+        // Other keys, tables and tenants miss.
+        assert_eq!(Lookup::Miss, cache.lookup(TENANT, TABLE, b"b", ANY, 2000));
+        assert_eq!(
+            Lookup::Miss,
+            cache.lookup(TENANT, TABLE + 1, b"a", ANY, 2000)
+        );
+        assert_eq!(
+            Lookup::Miss,
+            cache.lookup(TENANT + 1, TABLE, b"a", ANY, 2000)
+        );
+
+        // Objects that were not hinted, and responses that were not tracked, aren't cached.
+        cache.track(2, TENANT, TABLE, b"b");
+        cache.observe(&get(2, b"value", None), 3000);
+        cache.observe(&get(3, b"value", Some((1, 100))), 3000);
+        assert_eq!(Lookup::Miss, cache.lookup(TENANT, TABLE, b"b", ANY, 4000));
+
+        let stats = cache.stats();
+        assert_eq!((3, 5, 1), (stats.hits, stats.misses, stats.fills));
+    }
+
+    // Tests that an expired object is revalidated once, served for another TTL if it did not
+    // change, and dropped if it did.
+    #[test]
+    fn test_cache_revalidate() {
+        let mut cache = HintCache::new(16, HZ);
+        cache.lookup(TENANT, TABLE, b"a", ANY, 0);
+        cache.track(1, TENANT, TABLE, b"a");
+        cache.observe(&get(1, b"value", Some((7, 100))), 0);
+
+        // Gets that come in while the revalidation is in flight go to the server.
+        assert_eq!(
+            Lookup::Revalidate,
+            cache.lookup(TENANT, TABLE, b"a", ANY, 100_000)
+        );
+        cache.track(2, TENANT, TABLE, b"a");
+        assert_eq!(
+            Lookup::Miss,
+            cache.lookup(TENANT, TABLE, b"a", ANY, 100_500)
+        );
+
+        // The version did not change, so the object is served for another 100 microseconds.
+        cache.observe(&versions(2, Some(7)), 101_000);
+        let hit = cache.lookup(TENANT, TABLE, b"a", ANY, 200_999);
+        assert_eq!(Lookup::Hit(b"value".to_vec(), 99), hit);
+
+        // The version changed, so the object is dropped.
+        assert_eq!(
+            Lookup::Revalidate,
+            cache.lookup(TENANT, TABLE, b"a", ANY, 201_000)
+        );
+        cache.track(3, TENANT, TABLE, b"a");
+        cache.observe(&versions(3, Some(8)), 202_000);
+        assert_eq!(
+            Lookup::Miss,
+            cache.lookup(TENANT, TABLE, b"a", ANY, 202_000)
+        );
+
+        // An object that is gone is dropped too.
+        cache.track(4, TENANT, TABLE, b"a");
+        cache.observe(&get(4, b"other", Some((8, 100))), 300_000);
+        assert_eq!(
+            Lookup::Revalidate,
+            cache.lookup(TENANT, TABLE, b"a", ANY, 400_000)
+        );
+        cache.track(5, TENANT, TABLE, b"a");
+        cache.observe(&versions(5, None), 401_000);
+        assert_eq!(
+            Lookup::Miss,
+            cache.lookup(TENANT, TABLE, b"a", ANY, 401_000)
+        );
+
+        let stats = cache.stats();
+        assert_eq!(
+            (3, 1, 2),
+            (stats.revalidations, stats.refreshed, stats.stale)
+        );
+    }
+
+    // Tests that a put drops the object, and that responses to it already in flight are not
+    // cached.
+    #[test]
+    fn test_cache_invalidate() {
+        let mut cache = HintCache::new(16, HZ);
+        cache.track(1, TENANT, TABLE, b"a");
+        cache.observe(&get(1, b"value", Some((7, 100))), 0);
+
+        cache.track(2, TENANT, TABLE, b"b");
+        cache.invalidate(TENANT, TABLE, b"a");
+        cache.invalidate(TENANT, TABLE, b"b");
+        assert_eq!(Lookup::Miss, cache.lookup(TENANT, TABLE, b"a", ANY, 1000));
+
+        // The get() of "b" was sent before the put, so it's response could be older than it.
+        cache.observe(&get(2, b"value", Some((7, 100))), 2000);
+        assert_eq!(Lookup::Miss, cache.lookup(TENANT, TABLE, b"b", ANY, 3000));
+
+        // Only cached objects count as invalidated.
+        assert_eq!(1, cache.stats().invalidations);
+    }
+
+    // Tests that the cache holds atmost it's capacity, evicting the oldest object first.
+    #[test]
+    fn test_cache_capacity() {
+        let mut cache = HintCache::new(2, HZ);
+        for (id, key) in [b"a", b"b", b"c"].iter().enumerate() {
+            cache.track(id as u64, TENANT, TABLE, &key[..]);
+            cache.observe(&get(id as u64, b"value", Some((1, 100))), 0);
+        }
+
+        assert_eq!(Lookup::Miss, cache.lookup(TENANT, TABLE, b"a", ANY, 10));
+        assert!(cache.lookup(TENANT, TABLE, b"b", ANY, 10) != Lookup::Miss);
+        assert!(cache.lookup(TENANT, TABLE, b"c", ANY, 10) != Lookup::Miss);
+        assert_eq!(1, cache.stats().evictions);
+    }
+
+    // Tests that only hits are recorded on the cache's histogram. Responses that revalidate or
+    // fill the cache went to the server, and are left to the client's latencies.
+    #[test]
+    fn test_cache_accounting() {
+        let mut cache = HintCache::new(16, HZ);
+        cache.lookup(TENANT, TABLE, b"a", ANY, 0);
+        cache.track(1, TENANT, TABLE, b"a");
+        cache.observe(&get(1, b"value", Some((7, 100))), 0);
+
+        for now in 0..10 {
+            if let Lookup::Hit(_, _) = cache.lookup(TENANT, TABLE, b"a", ANY, now) {
+                cache.record_hit(50);
+            }
+        }
+        cache.lookup(TENANT, TABLE, b"a", ANY, 100_000);
+        cache.track(2, TENANT, TABLE, b"a");
+        cache.observe(&versions(2, Some(7)), 100_000);
+
+        assert_eq!(10, cache.stats().hits);
+        assert_eq!(10, cache.hit_latency().total());
+        assert_eq!(10, cache.hit_latency().counts()[LogHistogram::bucket(50)]);
+    }
+
+    // Tests that a cached object is refused to gets once it is older than their staleness
+    // budget, that a budget of 0 always goes to the server and so sees the latest write, and
+    // that revalidating the object makes it fresh again.
+    #[test]
+    fn test_cache_budget() {
+        let mut cache = HintCache::new(16, HZ);
+        cache.track(1, TENANT, TABLE, b"a");
+        cache.observe(&get(1, b"v1", Some((7, 1000))), 0);
+
+        // 300 microseconds old: served to budgets atleast that, refused to the rest.
+        let hit = cache.lookup(TENANT, TABLE, b"a", 500, 300_000);
+        assert_eq!(Lookup::Hit(b"v1".to_vec(), 300), hit);
+        let hit = cache.lookup(TENANT, TABLE, b"a", 300, 300_000);
+        assert_eq!(Lookup::Hit(b"v1".to_vec(), 300), hit);
+        assert_eq!(
+            Lookup::Miss,
+            cache.lookup(TENANT, TABLE, b"a", 299, 300_000)
+        );
+        assert_eq!(
+            Lookup::Miss,
+            cache.lookup(TENANT, TABLE, b"a", 100, 300_000)
+        );
+
+        // Another client wrote v2. A strictly fresh get goes to the server and reads it, even
+        // though the cached copy was refreshed just now.
+        assert_eq!(Lookup::Miss, cache.lookup(TENANT, TABLE, b"a", 0, 0));
+        cache.track(2, TENANT, TABLE, b"a");
+        cache.observe(&get(2, b"v2", Some((8, 1000))), 400_000);
+        assert_eq!(Lookup::Miss, cache.lookup(TENANT, TABLE, b"a", 0, 400_000));
+
+        // The response refreshed the cached copy, which is now within the small budget again.
+        let hit = cache.lookup(TENANT, TABLE, b"a", 100, 450_000);
+        assert_eq!(Lookup::Hit(b"v2".to_vec(), 50), hit);
+
+        // Revalidating the object resets it's age too.
+        assert_eq!(
+            Lookup::Revalidate,
+            cache.lookup(TENANT, TABLE, b"a", ANY, 1_400_000)
+        );
+        cache.track(3, TENANT, TABLE, b"a");
+        cache.observe(&versions(3, Some(8)), 1_500_000);
+        let hit = cache.lookup(TENANT, TABLE, b"a", 100, 1_500_000);
+        assert_eq!(Lookup::Hit(b"v2".to_vec(), 0), hit);
+
+        let stats = cache.stats();
+        assert_eq!((4, 4, 0), (stats.hits, stats.over_budget, stats.misses));
+    }
+}
@@ -0,0 +1,793 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use std::boxed::FnBox;
+
+use db::config::ClientConfig;
+use db::wireformat::RpcStatus;
+
+use super::dispatch::Sender;
+use super::udp::UdpSender;
+use super::verify::{RequestStash, Stashable};
+
+/// The maximum number of requests in a chain, if not set in the client config.
+pub const DEFAULT_MAX_DEPTH: usize = 8;
+
+/// The operation a request performs.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Op {
+    /// A native get() of a key.
+    Get {
+        /// The table the key is looked up in.
+        table: u64,
+
+        /// The key.
+        key: Vec<u8>,
+    },
+
+    /// A native multiget() of a set of keys, all of the same length.
+    MultiGet {
+        /// The table the keys are looked up in.
+        table: u64,
+
+        /// The length of each key.
+        k_len: u16,
+
+        /// The number of keys.
+        n_keys: u32,
+
+        /// The keys, back to back.
+        keys: Vec<u8>,
+    },
+
+    /// An invoke() of an extension.
+    Invoke {
+        /// The number of bytes at the head of the payload that make up the extension's name.
+        name_len: u32,
+
+        /// The extension's name followed by it's arguments.
+        payload: Vec<u8>,
+    },
+}
+
+/// Called with the payload of a successful response, returns the next request in the chain,
+/// or None if the chain ends here.
+pub type Continuation = Box<FnBox(&[u8]) -> Option<Request>>;
+
+/// A request a workload wants sent out, optionally followed by more requests that depend on
+/// it's response.
+pub struct Request {
+    // The tenant the request is issued on behalf of.
+    tenant: u32,
+
+    // The operation the request performs.
+    op: Op,
+
+    // Produces the next request once the response to this one arrives.
+    then: Option<Continuation>,
+}
+
+impl Request {
+    /// Returns a native get() request.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`: The tenant the request is issued on behalf of.
+    /// * `table`:  The table the key is looked up in.
+    /// * `key`:    The key.
+    pub fn get(tenant: u32, table: u64, key: &[u8]) -> Request {
+        Request::new(
+            tenant,
+            Op::Get {
+                table: table,
+                key: key.to_vec(),
+            },
+        )
+    }
+
+    /// Returns a native multiget() request.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`: The tenant the request is issued on behalf of.
+    /// * `table`:  The table the keys are looked up in.
+    /// * `k_len`:  The length of each key.
+    /// * `n_keys`: The number of keys.
+    /// * `keys`:   The keys, back to back.
+    pub fn multiget(tenant: u32, table: u64, k_len: u16, n_keys: u32, keys: &[u8]) -> Request {
+        Request::new(
+            tenant,
+            Op::MultiGet {
+                table: table,
+                k_len: k_len,
+                n_keys: n_keys,
+                keys: keys.to_vec(),
+            },
+        )
+    }
+
+    /// Returns an invoke() request.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`:   The tenant the request is issued on behalf of.
+    /// * `name_len`: The number of bytes at the head of the payload that make up the
+    ///               extension's name.
+    /// * `payload`:  The extension's name followed by it's arguments.
+    pub fn invoke(tenant: u32, name_len: u32, payload: &[u8]) -> Request {
+        Request::new(
+            tenant,
+            Op::Invoke {
+                name_len: name_len,
+                payload: payload.to_vec(),
+            },
+        )
+    }
+
+    // Returns a request without a continuation.
+    fn new(tenant: u32, op: Op) -> Request {
+        Request {
+            tenant: tenant,
+            op: op,
+            then: None,
+        }
+    }
+
+    /// Chains another request after this one.
+    ///
+    /// # Arguments
+    ///
+    /// * `then`: Called with the payload of this request's response once it arrives, unless
+    ///           the request failed. Returns the next request, or None if the chain ends here.
+    pub fn then<F>(mut self, then: F) -> Request
+    where
+        F: FnOnce(&[u8]) -> Option<Request> + 'static,
+    {
+        self.then = Some(Box::new(then));
+        self
+    }
+
+    /// Returns the tenant the request is issued on behalf of.
+    pub fn tenant(&self) -> u32 {
+        self.tenant
+    }
+
+    /// Returns the operation the request performs.
+    pub fn op(&self) -> &Op {
+        &self.op
+    }
+
+    /// Returns true if another request is chained after this one.
+    pub fn is_chained(&self) -> bool {
+        self.then.is_some()
+    }
+}
+
+/// Sends requests out on behalf of Chains. Implemented by both client transports.
+pub trait Transport {
+    /// Returns a new request id.
+    fn next_id(&self) -> u64;
+
+    /// Sends out a request.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`: The tenant the request is issued on behalf of.
+    /// * `op`:     The operation the request performs.
+    /// * `id`:     The id to send the request out with.
+    /// * `stamp`:  The time-stamp to send the request out with.
+    fn send(&self, tenant: u32, op: &Op, id: u64, stamp: u64);
+}
+
+impl Transport for Sender {
+    fn next_id(&self) -> u64 {
+        Sender::next_id(self)
+    }
+
+    fn send(&self, tenant: u32, op: &Op, id: u64, stamp: u64) {
+        match *op {
+            Op::Get { table, ref key } => self.send_get(tenant, table, key, id, stamp),
+
+            Op::MultiGet {
+                table,
+                k_len,
+                n_keys,
+                ref keys,
+            } => self.send_multiget(tenant, table, k_len, n_keys, keys, id, stamp),
+
+            Op::Invoke {
+                name_len,
+                ref payload,
+            } => self.send_invoke(tenant, name_len, payload, id, stamp),
+        }
+    }
+}
+
+impl Transport for UdpSender {
+    fn next_id(&self) -> u64 {
+        UdpSender::next_id(self)
+    }
+
+    fn send(&self, tenant: u32, op: &Op, id: u64, stamp: u64) {
+        match *op {
+            Op::Get { table, ref key } => self.send_get(tenant, table, key, id, stamp),
+
+            Op::MultiGet {
+                table,
+                k_len,
+                n_keys,
+                ref keys,
+            } => self.send_multiget(tenant, table, k_len, n_keys, keys, id, stamp),
+
+            Op::Invoke {
+                name_len,
+                ref payload,
+            } => self.send_invoke(tenant, name_len, payload, id, stamp),
+        }
+    }
+}
+
+/// Why a chain was cut short.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Abort {
+    /// A request in the chain completed with a status other than StatusOk or StatusPushback.
+    Failed,
+
+    /// A request in the chain was pushed back by the server.
+    Pushback,
+
+    /// The chain tried to grow past the maximum depth.
+    Depth,
+}
+
+/// What a response means for the operation it belongs to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Completion {
+    /// The response is to a request that was not part of a chain, or whose chain was
+    /// reclaimed. The caller handles it as it would have without chaining.
+    Single,
+
+    /// The response completes a chain of this many requests.
+    Ended(usize),
+
+    /// The response was handed to the chain's continuation, and the next request in the chain
+    /// was sent out. The operation is still outstanding.
+    Continued,
+
+    /// The chain was cut short; the operation is over.
+    Aborted(Abort),
+}
+
+// A chained request that is outstanding.
+struct Link {
+    // The id the request was sent out with.
+    id: u64,
+
+    // The time-stamp (in cycles) the request was sent out at. Used to reclaim the link if the
+    // response never arrives.
+    sent: u64,
+
+    // The number of requests in the chain so far, including this one.
+    hops: usize,
+
+    // Produces the next request. None if this is the last request in the chain.
+    then: Option<Continuation>,
+}
+
+impl Stashable for Link {
+    fn id(&self) -> u64 {
+        self.id
+    }
+
+    fn stamp(&self) -> u64 {
+        self.sent
+    }
+}
+
+/// Sends out chains of dependent requests, where each request after the first is produced
+/// from the response to the one before it (ex: look up an object, then the associations of an
+/// id found in it). A chain is one logical operation:
+///
+/// * Every request in it is sent out with the time-stamp of the first, so the latency on the
+///   last response spans the whole chain.
+/// * It takes up a single slot in the caller's outstanding window, which should only be
+///   released once complete() reports that the operation is over.
+///
+/// Continuations are held per request id in a RequestStash, and are dropped along with the
+/// chain when it is aborted, times out, or is evicted to make room.
+pub struct Chains {
+    // The maximum number of requests in a chain.
+    max_depth: usize,
+
+    // Outstanding chained requests, keyed on the request id.
+    links: RequestStash<Link>,
+
+    // The number of chains that ended, and the number of requests sent out after the first
+    // one of a chain.
+    ended: u64,
+    continued: u64,
+
+    // The number of chains aborted, by reason.
+    failed: u64,
+    pushback: u64,
+    depth: u64,
+}
+
+impl Chains {
+    /// Creates a Chains.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_depth`: The maximum number of requests in a chain. Atleast 1.
+    /// * `capacity`:  The maximum number of chains outstanding at once. The oldest chain is
+    ///                dropped to make room for a new one.
+    /// * `timeout`:   Cycles after which a chain whose response hasn't arrived is dropped.
+    pub fn new(max_depth: usize, capacity: usize, timeout: u64) -> Chains {
+        Chains {
+            max_depth: max_depth.max(1),
+            links: RequestStash::new(capacity, timeout),
+            ended: 0,
+            continued: 0,
+            failed: 0,
+            pushback: 0,
+            depth: 0,
+        }
+    }
+
+    /// Creates a Chains with the maximum depth set in the client config.
+    ///
+    /// # Arguments
+    ///
+    /// * `config`:   The client config.
+    /// * `capacity`: The maximum number of chains outstanding at once.
+    /// * `timeout`:  Cycles after which a chain whose response hasn't arrived is dropped.
+    pub fn from_config(config: &ClientConfig, capacity: usize, timeout: u64) -> Chains {
+        let max_depth = match config.max_chain_depth {
+            0 => DEFAULT_MAX_DEPTH,
+            d => d,
+        };
+        Chains::new(max_depth, capacity, timeout)
+    }
+
+    /// Sends out the first request of an operation.
+    ///
+    /// # Arguments
+    ///
+    /// * `transport`: The transport to send the request out on.
+    /// * `req`:       The request, along with the requests chained after it if any.
+    /// * `stamp`:     The time-stamp to send the request out with.
+    ///
+    /// # Return
+    ///
+    /// The id the request was sent out with.
+    pub fn send<T: Transport>(&mut self, transport: &T, req: Request, stamp: u64) -> u64 {
+        self.send_hop(transport, req, stamp, stamp, 1)
+    }
+
+    /// Handles the response to a request. If the request is part of a chain, and the chain
+    /// continues, the next request is sent out with the same time-stamp.
+    ///
+    /// # Arguments
+    ///
+    /// * `transport`: The transport to send the next request out on.
+    /// * `id`:        The id on the response.
+    /// * `stamp`:     The time-stamp on the response.
+    /// * `status`:    The status on the response.
+    /// * `payload`:   The payload on the response.
+    /// * `now`:       The current time-stamp in cycles.
+    ///
+    /// # Return
+    ///
+    /// What the response means for the operation it belongs to.
+    pub fn complete<T: Transport>(
+        &mut self,
+        transport: &T,
+        id: u64,
+        stamp: u64,
+        status: &RpcStatus,
+        payload: &[u8],
+        now: u64,
+    ) -> Completion {
+        let link = match self.links.take(id) {
+            Some(link) => link,
+            None => return Completion::Single,
+        };
+
+        match *status {
+            RpcStatus::StatusOk => {}
+
+            RpcStatus::StatusPushback => {
+                self.pushback += 1;
+                return Completion::Aborted(Abort::Pushback);
+            }
+
+            _ => {
+                self.failed += 1;
+                return Completion::Aborted(Abort::Failed);
+            }
+        }
+
+        let next = match link.then {
+            Some(then) => then.call_box((payload,)),
+            None => None,
+        };
+
+        match next {
+            Some(_) if link.hops >= self.max_depth => {
+                self.depth += 1;
+                Completion::Aborted(Abort::Depth)
+            }
+
+            Some(req) => {
+                self.send_hop(transport, req, stamp, now, link.hops + 1);
+                self.continued += 1;
+                Completion::Continued
+            }
+
+            None => {
+                self.ended += 1;
+                Completion::Ended(link.hops)
+            }
+        }
+    }
+
+    /// Drops chains whose responses haven't arrived within the timeout.
+    ///
+    /// # Arguments
+    ///
+    /// * `now`: The current time-stamp in cycles.
+    ///
+    /// # Return
+    ///
+    /// The number of chains dropped. The caller should release their window slots.
+    pub fn reclaim(&mut self, now: u64) -> u64 {
+        self.links.reclaim(now)
+    }
+
+    /// Returns the number of chains currently outstanding.
+    pub fn outstanding(&self) -> usize {
+        self.links.len()
+    }
+
+    /// Returns the number of chains that ran to completion.
+    pub fn ended(&self) -> u64 {
+        self.ended
+    }
+
+    /// Returns the number of requests sent out after the first one of a chain.
+    pub fn continued(&self) -> u64 {
+        self.continued
+    }
+
+    /// Returns the number of chains aborted for a reason.
+    pub fn aborted(&self, reason: Abort) -> u64 {
+        match reason {
+            Abort::Failed => self.failed,
+            Abort::Pushback => self.pushback,
+            Abort::Depth => self.depth,
+        }
+    }
+
+    /// Returns the number of chains dropped because they timed out, or to make room.
+    pub fn reclaimed(&self) -> u64 {
+        self.links.reclaimed()
+    }
+
+    // Sends out a request, and remembers it if the chain can continue after it. Every request
+    // but the first of a chain is remembered, so that it's response ends the chain.
+    fn send_hop<T: Transport>(
+        &mut self,
+        transport: &T,
+        req: Request,
+        stamp: u64,
+        now: u64,
+        hops: usize,
+    ) -> u64 {
+        let id = transport.next_id();
+        transport.send(req.tenant, &req.op, id, stamp);
+
+        if hops > 1 || req.then.is_some() {
+            self.links.insert(Link {
+                id: id,
+                sent: now,
+                hops: hops,
+                then: req.then,
+            });
+        }
+
+        id
+    }
+}
+
+// This module contains unit tests for Chains.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+
+    // A stubbed transport that records requests instead of sending them out.
+    struct StubTransport {
+        ids: Cell<u64>,
+        sent: RefCell<Vec<(u32, Op, u64, u64)>>,
+    }
+
+    impl StubTransport {
+        fn new() -> StubTransport {
+            StubTransport {
+                ids: Cell::new(0),
+                sent: RefCell::new(Vec::new()),
+            }
+        }
+
+        // Returns the last request sent out.
+        fn last(&self) -> (u32, Op, u64, u64) {
+            self.sent.borrow().last().unwrap().clone()
+        }
+    }
+
+    impl Transport for StubTransport {
+        fn next_id(&self) -> u64 {
+            self.ids.set(self.ids.get() + 1);
+            self.ids.get()
+        }
+
+        fn send(&self, tenant: u32, op: &Op, id: u64, stamp: u64) {
+            self.sent.borrow_mut().push((tenant, op.clone(), id, stamp));
+        }
+    }
+
+    // Returns a chain that looks up an object, and then invokes "tao" with the first 8 bytes
+    // of the object as arguments. If `more`, the invoke is followed by a get of the first 8
+    // bytes of it's response.
+    fn lookup(tenant: u32, more: bool) -> Request {
+        Request::get(tenant, 1, &[1; 8]).then(move |object: &[u8]| {
+            let mut args = b"tao".to_vec();
+            args.extend_from_slice(&object[0..8]);
+            let invoke = Request::invoke(tenant, 3, &args);
+            if !more {
+                return Some(invoke);
+            }
+
+            Some(invoke.then(move |resp: &[u8]| Some(Request::get(tenant, 2, &resp[0..8]))))
+        })
+    }
+
+    // Returns a chain that never ends on it's own.
+    fn forever(tenant: u32) -> Request {
+        Request::get(tenant, 1, &[0]).then(move |_: &[u8]| Some(forever(tenant)))
+    }
+
+    // Tests that a two step chain sends the second request with the first one's time-stamp,
+    // and ends on it's response.
+    #[test]
+    fn test_chain_two_steps() {
+        let transport = StubTransport::new();
+        let mut chains = Chains::new(DEFAULT_MAX_DEPTH, 16, 1000);
+
+        let id = chains.send(&transport, lookup(7, false), 100);
+        assert_eq!(1, chains.outstanding());
+        let get = Op::Get {
+            table: 1,
+            key: vec![1; 8],
+        };
+        assert_eq!((7, get, id, 100), transport.last());
+
+        let ok = RpcStatus::StatusOk;
+        let object = [9, 8, 7, 6, 5, 4, 3, 2, 1, 0];
+        assert_eq!(
+            Completion::Continued,
+            chains.complete(&transport, id, 100, &ok, &object, 150)
+        );
+
+        let (tenant, op, next, stamp) = transport.last();
+        assert_eq!((7, 100), (tenant, stamp));
+        assert!(next != id);
+        let mut args = b"tao".to_vec();
+        args.extend_from_slice(&object[0..8]);
+        assert_eq!(
+            Op::Invoke {
+                name_len: 3,
+                payload: args,
+            },
+            op
+        );
+
+        assert_eq!(
+            Completion::Ended(2),
+            chains.complete(&transport, next, 100, &ok, &[], 200)
+        );
+        assert_eq!(0, chains.outstanding());
+        assert_eq!(2, transport.sent.borrow().len());
+        assert_eq!((1, 1), (chains.ended(), chains.continued()));
+    }
+
+    // Tests a three step chain, where the second request also has a continuation.
+    #[test]
+    fn test_chain_three_steps() {
+        let transport = StubTransport::new();
+        let mut chains = Chains::new(3, 16, 1000);
+        let ok = RpcStatus::StatusOk;
+
+        let id = chains.send(&transport, lookup(1, true), 5);
+        let c = chains.complete(&transport, id, 5, &ok, &[1; 8], 6);
+        assert_eq!(Completion::Continued, c);
+
+        let id = transport.last().2;
+        let c = chains.complete(&transport, id, 5, &ok, &[3; 16], 7);
+        assert_eq!(Completion::Continued, c);
+        let get = Op::Get {
+            table: 2,
+            key: vec![3; 8],
+        };
+        assert_eq!((1, get, 3, 5), transport.last());
+
+        let c = chains.complete(&transport, 3, 5, &ok, &[], 8);
+        assert_eq!(Completion::Ended(3), c);
+        assert_eq!((1, 2), (chains.ended(), chains.continued()));
+        assert_eq!(0, chains.outstanding());
+    }
+
+    // Tests that a failure mid-chain aborts it without running the rest of the chain.
+    #[test]
+    fn test_chain_failure() {
+        let transport = StubTransport::new();
+        let mut chains = Chains::new(DEFAULT_MAX_DEPTH, 16, 1000);
+        let ok = RpcStatus::StatusOk;
+
+        let ran = Rc::new(Cell::new(false));
+        let flag = Rc::clone(&ran);
+        let req = Request::get(1, 1, &[1]).then(move |_: &[u8]| {
+            Some(Request::get(1, 1, &[2]).then(move |_: &[u8]| {
+                flag.set(true);
+                None
+            }))
+        });
+
+        let id = chains.send(&transport, req, 0);
+        assert_eq!(
+            Completion::Continued,
+            chains.complete(&transport, id, 0, &ok, &[], 1)
+        );
+
+        let failed = RpcStatus::StatusObjectDoesNotExist;
+        let id = transport.last().2;
+        assert_eq!(
+            Completion::Aborted(Abort::Failed),
+            chains.complete(&transport, id, 0, &failed, &[], 2)
+        );
+        assert!(!ran.get());
+        assert_eq!(1, Rc::strong_count(&ran));
+        assert_eq!(2, transport.sent.borrow().len());
+        assert_eq!(1, chains.aborted(Abort::Failed));
+        assert_eq!(0, chains.ended());
+
+        // A failure on the first request of a chain aborts it too.
+        let id = chains.send(&transport, lookup(1, false), 3);
+        assert_eq!(
+            Completion::Aborted(Abort::Failed),
+            chains.complete(&transport, id, 3, &failed, &[], 4)
+        );
+        assert_eq!(2, chains.aborted(Abort::Failed));
+        assert_eq!(0, chains.outstanding());
+    }
+
+    // Tests that a pushed back request aborts it's chain, and is counted apart from failures.
+    #[test]
+    fn test_chain_pushback() {
+        let transport = StubTransport::new();
+        let mut chains = Chains::new(DEFAULT_MAX_DEPTH, 16, 1000);
+        let ok = RpcStatus::StatusOk;
+        let pushback = RpcStatus::StatusPushback;
+
+        let first = chains.send(&transport, lookup(1, true), 0);
+        let second = chains.send(&transport, lookup(2, true), 0);
+        assert_eq!(
+            Completion::Aborted(Abort::Pushback),
+            chains.complete(&transport, first, 0, &pushback, &[], 1)
+        );
+
+        assert_eq!(
+            Completion::Continued,
+            chains.complete(&transport, second, 0, &ok, &[1; 8], 1)
+        );
+        let id = transport.last().2;
+        assert_eq!(
+            Completion::Aborted(Abort::Pushback),
+            chains.complete(&transport, id, 0, &pushback, &[], 2)
+        );
+
+        assert_eq!(2, chains.aborted(Abort::Pushback));
+        assert_eq!(0, chains.aborted(Abort::Failed));
+        assert_eq!(3, transport.sent.borrow().len());
+        assert_eq!(0, chains.outstanding());
+
+        // Unchained requests are left to the caller, whatever their status.
+        let id = chains.send(&transport, Request::get(1, 1, &[1]), 3);
+        assert_eq!(
+            Completion::Single,
+            chains.complete(&transport, id, 3, &pushback, &[], 4)
+        );
+        assert_eq!(2, chains.aborted(Abort::Pushback));
+    }
+
+    // Tests that a chain is aborted once it tries to grow past the maximum depth.
+    #[test]
+    fn test_chain_depth() {
+        for max in 1..5 {
+            let transport = StubTransport::new();
+            let mut chains = Chains::new(max, 16, 1000);
+            let ok = RpcStatus::StatusOk;
+
+            let mut id = chains.send(&transport, forever(1), 0);
+            for _ in 1..max {
+                let c = chains.complete(&transport, id, 0, &ok, &[], 1);
+                assert_eq!(Completion::Continued, c);
+                id = transport.last().2;
+            }
+
+            let c = chains.complete(&transport, id, 0, &ok, &[], 1);
+            assert_eq!(Completion::Aborted(Abort::Depth), c);
+            assert_eq!(max, transport.sent.borrow().len());
+            assert_eq!(1, chains.aborted(Abort::Depth));
+            assert_eq!(0, chains.outstanding());
+        }
+
+        let config = ClientConfig::default();
+        assert_eq!(
+            DEFAULT_MAX_DEPTH,
+            Chains::from_config(&config, 1, 1).max_depth
+        );
+    }
+
+    // Tests that continuations are dropped when their chains time out or are evicted, and that
+    // late responses to them are left to the caller.
+    #[test]
+    fn test_chain_reclaim() {
+        let transport = StubTransport::new();
+        let mut chains = Chains::new(DEFAULT_MAX_DEPTH, 2, 100);
+        let held = Rc::new(());
+
+        let chain = |held: &Rc<()>| {
+            let held = Rc::clone(held);
+            Request::get(1, 1, &[1]).then(move |_: &[u8]| {
+                let _ = held;
+                None
+            })
+        };
+
+        let first = chains.send(&transport, chain(&held), 0);
+        chains.send(&transport, chain(&held), 50);
+        assert_eq!(3, Rc::strong_count(&held));
+
+        assert_eq!(1, chains.reclaim(120));
+        assert_eq!(2, Rc::strong_count(&held));
+        assert_eq!(
+            Completion::Single,
+            chains.complete(&transport, first, 0, &RpcStatus::StatusOk, &[], 121)
+        );
+
+        // The stash holds two chains; the oldest is evicted to make room for a third.
+        chains.send(&transport, chain(&held), 130);
+        chains.send(&transport, chain(&held), 140);
+        assert_eq!(2, chains.outstanding());
+        assert_eq!(3, Rc::strong_count(&held));
+        assert_eq!(2, chains.reclaimed());
+
+        assert_eq!(2, chains.reclaim(1000));
+        assert_eq!(1, Rc::strong_count(&held));
+    }
+}
@@ -0,0 +1,539 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+use serde_json;
+
+use super::autotune::Sweep;
+use super::perf::Report;
+
+pub use db::histogram::Histogram;
+
+/// The parts of a client's config that decide whether two of it's runs can be compared.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct RunConfig {
+    /// The workload that was run, ex: "ycsb" or "tao".
+    pub workload: String,
+
+    /// Length of the keys in Bytes.
+    pub key_len: usize,
+
+    /// Length of the values in Bytes.
+    pub value_len: usize,
+
+    /// Percentage of put() requests.
+    pub put_pct: usize,
+
+    /// True if requests were invoke() based, false if they were native.
+    pub use_invoke: bool,
+
+    /// How requests reached the server. Empty for runs over the network, and "embedded" for
+    /// runs that called into a server in the same process, which are never comparable to them.
+    pub transport: String,
+}
+
+impl RunConfig {
+    /// Returns a string that is equal for two configs exactly when their runs are comparable.
+    /// Used to match up result files across directories.
+    pub fn signature(&self) -> String {
+        let signature = format!(
+            "{}-k{}-v{}-p{}-{}",
+            self.workload,
+            self.key_len,
+            self.value_len,
+            self.put_pct,
+            if self.use_invoke { "invoke" } else { "native" }
+        );
+
+        match self.transport.is_empty() {
+            true => signature,
+            false => format!("{}-{}", signature, self.transport),
+        }
+    }
+
+    /// Checks whether runs under this config and another can be compared.
+    ///
+    /// # Return
+    ///
+    /// A description of every field that differs, if any.
+    pub fn compatible(&self, other: &RunConfig) -> Result<(), String> {
+        let mut diffs = Vec::new();
+        if self.workload != other.workload {
+            diffs.push(format!("workload {} vs {}", self.workload, other.workload));
+        }
+        if self.key_len != other.key_len {
+            diffs.push(format!("key_len {} vs {}", self.key_len, other.key_len));
+        }
+        if self.value_len != other.value_len {
+            diffs.push(format!(
+                "value_len {} vs {}",
+                self.value_len, other.value_len
+            ));
+        }
+        if self.put_pct != other.put_pct {
+            diffs.push(format!("put_pct {} vs {}", self.put_pct, other.put_pct));
+        }
+        if self.use_invoke != other.use_invoke {
+            diffs.push(format!(
+                "use_invoke {} vs {}",
+                self.use_invoke, other.use_invoke
+            ));
+        }
+        if self.transport != other.transport {
+            diffs.push(format!(
+                "transport {:?} vs {:?}",
+                self.transport, other.transport
+            ));
+        }
+
+        match diffs.is_empty() {
+            true => Ok(()),
+            false => Err(diffs.join(", ")),
+        }
+    }
+}
+
+/// Results for one class of operations, ex: gets or puts, within a phase.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Class {
+    /// The name of the class.
+    pub name: String,
+
+    /// Operations completed per second.
+    pub throughput: f64,
+
+    /// End-to-end latency of the completed operations.
+    pub latency: Histogram,
+
+    /// Responses dropped as duplicates of ones already received. Not counted in throughput or
+    /// latency.
+    #[serde(default)]
+    pub duplicates: u64,
+}
+
+/// Results for one phase of a run.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Phase {
+    /// The name of the phase.
+    pub name: String,
+
+    /// Results for each class of operations issued during the phase.
+    pub classes: Vec<Class>,
+
+    /// The hardware events counted on each pipeline over the phase, if the run counted them.
+    #[serde(default)]
+    pub perf: Option<Report>,
+}
+
+/// The results of a single client run, as written to a result file.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct RunResult {
+    /// The id the run tagged it's requests with, so that the server's counters for the run can
+    /// be joined with these results. 0 if requests were not tagged.
+    #[serde(default)]
+    pub run_id: u64,
+
+    /// The seed the run's workload was drawn off, so that it can be reproduced. See rng::seed().
+    /// 0 if the run didn't record one.
+    #[serde(default)]
+    pub seed: u64,
+
+    /// The config the run was made with.
+    pub config: RunConfig,
+
+    /// Results of each phase, in the order they ran.
+    pub phases: Vec<Phase>,
+
+    /// The epochs and knee of an autotuning sweep, if the run swept it's load.
+    #[serde(default)]
+    pub sweep: Option<Sweep>,
+
+    /// The time the server took to respond to each opcode over the run, as measured by the
+    /// server itself. Classes are named after opcodes and carry no throughput. Empty if the
+    /// server's latencies were not collected.
+    #[serde(default)]
+    pub server: Vec<Class>,
+}
+
+impl RunResult {
+    /// Reads results from a JSON file.
+    pub fn load(path: &Path) -> Result<RunResult, String> {
+        let file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        serde_json::from_reader(file).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// Writes results out to a JSON file, replacing it if it exists.
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let mut file = File::create(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        self.write(&mut file)
+            .map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// Writes results out as JSON.
+    pub fn write<W: Write>(&self, out: &mut W) -> Result<(), String> {
+        serde_json::to_writer_pretty(&mut *out, self).map_err(|e| e.to_string())?;
+        writeln!(out).map_err(|e| e.to_string())
+    }
+}
+
+/// How large a change must be to be flagged.
+#[derive(Clone, Copy, Debug)]
+pub struct Thresholds {
+    /// Smallest change in throughput, in percent, that is flagged.
+    pub tput_pct: f64,
+
+    /// Smallest KS statistic between latency histograms that is flagged. Changes must also be
+    /// significant given the number of samples.
+    pub ks: f64,
+}
+
+impl Default for Thresholds {
+    fn default() -> Thresholds {
+        Thresholds {
+            tput_pct: 5.0,
+            ks: 0.05,
+        }
+    }
+}
+
+/// Whether a metric changed between two runs, and in which direction.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Verdict {
+    /// The change is within the thresholds.
+    Unchanged,
+
+    /// The metric got better.
+    Improved,
+
+    /// The metric got worse.
+    Regressed,
+}
+
+/// The change in one metric of one class of operations between two runs.
+#[derive(Clone, Debug)]
+pub struct Delta {
+    /// The phase the class ran in.
+    pub phase: String,
+
+    /// The class of operations.
+    pub class: String,
+
+    /// The metric; "throughput" or "latency".
+    pub metric: &'static str,
+
+    /// Percent change for throughput, and the KS statistic for latency.
+    pub change: f64,
+
+    /// For latency, the change in 99th percentile in nanoseconds. 0 for throughput.
+    pub p99: i64,
+
+    /// Whether the change was flagged.
+    pub verdict: Verdict,
+}
+
+// Compares the throughput of a class across two runs.
+fn throughput(phase: &str, old: &Class, new: &Class, t: &Thresholds) -> Delta {
+    let change = match old.throughput {
+        x if x > 0.0 => (new.throughput - x) / x * 100.0,
+        _ => 0.0,
+    };
+    let verdict = match change {
+        c if c >= t.tput_pct => Verdict::Improved,
+        c if c <= -t.tput_pct => Verdict::Regressed,
+        _ => Verdict::Unchanged,
+    };
+
+    Delta {
+        phase: phase.to_string(),
+        class: old.name.clone(),
+        metric: "throughput",
+        change: change,
+        p99: 0,
+        verdict: verdict,
+    }
+}
+
+// Compares the latency distribution of a class across two runs. A flagged change counts as a
+// regression if the tail got longer, or, with the tail unchanged, if the bulk of the
+// distribution got slower. A distribution that only widened is hence a regression.
+fn latency(phase: &str, old: &Class, new: &Class, t: &Thresholds) -> Delta {
+    let (a, b) = (&old.latency, &new.latency);
+    let ks = a.ks(b);
+    let p99 = b.percentile(0.99) as i64 - a.percentile(0.99) as i64;
+
+    let verdict = match ks >= t.ks && ks >= a.ks_critical(b) {
+        false => Verdict::Unchanged,
+        true if p99 > 0 => Verdict::Regressed,
+        true if p99 < 0 => Verdict::Improved,
+        true if a.shift(b) < 0.0 => Verdict::Regressed,
+        true => Verdict::Improved,
+    };
+
+    Delta {
+        phase: phase.to_string(),
+        class: old.name.clone(),
+        metric: "latency",
+        change: ks,
+        p99: p99,
+        verdict: verdict,
+    }
+}
+
+/// Compares two runs phase by phase, and class by class within each phase. The latency the
+/// server measured is compared under a "server" phase, for the opcodes both runs collected.
+///
+/// # Arguments
+///
+/// * `old`:   The baseline run.
+/// * `new`:   The run compared against the baseline.
+/// * `t`:     How large a change must be to be flagged.
+/// * `force`: If true, runs are compared even if their configs differ.
+///
+/// # Return
+///
+/// The throughput and latency delta of every class, or a description of why the runs cannot be
+/// compared.
+pub fn compare(
+    old: &RunResult,
+    new: &RunResult,
+    t: &Thresholds,
+    force: bool,
+) -> Result<Vec<Delta>, String> {
+    if !force {
+        old.config
+            .compatible(&new.config)
+            .map_err(|e| format!("Configs differ: {}", e))?;
+    }
+
+    if old.phases.len() != new.phases.len() {
+        return Err(format!(
+            "Runs have {} and {} phases",
+            old.phases.len(),
+            new.phases.len()
+        ));
+    }
+
+    let mut deltas = Vec::new();
+    for (a, b) in old.phases.iter().zip(new.phases.iter()) {
+        if a.name != b.name {
+            return Err(format!("Phase {} does not match phase {}", a.name, b.name));
+        }
+        if a.classes.len() != b.classes.len() {
+            return Err(format!("Phase {} has different classes", a.name));
+        }
+
+        for class in a.classes.iter() {
+            let other = b
+                .classes
+                .iter()
+                .find(|c| c.name == class.name)
+                .ok_or_else(|| format!("Class {} missing from phase {}", class.name, b.name))?;
+            deltas.push(throughput(&a.name, class, other, t));
+            deltas.push(latency(&a.name, class, other, t));
+        }
+    }
+
+    for class in old.server.iter() {
+        if let Some(other) = new.server.iter().find(|c| c.name == class.name) {
+            deltas.push(latency("server", class, other, t));
+        }
+    }
+
+    Ok(deltas)
+}
+
+// This module contains tests for the comparison math and config checks.
+#[cfg(test)]
+mod tests {
+    use super::{compare, Class, Histogram, Phase, RunConfig, RunResult, Thresholds, Verdict};
+
+    // Returns a histogram with 10ns buckets holding `n` samples around `mean`, spread `width`
+    // buckets to either side.
+    fn hist(mean: u64, width: u64, n: u64) -> Histogram {
+        let mut h = Histogram::default();
+        for i in 0..200 {
+            let bound = (i + 1) * 10;
+            let dist = (bound as i64 - mean as i64).abs() as u64 / 10;
+            h.bounds.push(bound);
+            h.counts.push(if dist <= width {
+                n / (2 * width + 1)
+            } else {
+                0
+            });
+        }
+        h
+    }
+
+    // Returns a run with one phase and one class.
+    fn run(throughput: f64, latency: Histogram) -> RunResult {
+        RunResult {
+            run_id: 0,
+            seed: 0,
+            config: RunConfig {
+                workload: "ycsb".to_string(),
+                key_len: 30,
+                value_len: 100,
+                put_pct: 5,
+                use_invoke: false,
+                transport: String::new(),
+            },
+            phases: vec![Phase {
+                name: "steady".to_string(),
+                classes: vec![Class {
+                    name: "get".to_string(),
+                    throughput: throughput,
+                    latency: latency,
+                    duplicates: 0,
+                }],
+                perf: None,
+            }],
+            sweep: None,
+            server: vec![],
+        }
+    }
+
+    // Tests that identical runs are unchanged.
+    #[test]
+    fn test_identical() {
+        let a = run(1e6, hist(500, 5, 110000));
+        let deltas = compare(&a, &a.clone(), &Thresholds::default(), false).unwrap();
+        assert_eq!(2, deltas.len());
+        assert!(deltas.iter().all(|d| d.verdict == Verdict::Unchanged));
+        assert_eq!(0.0, deltas[1].change);
+    }
+
+    // Tests that shifted latency distributions are flagged in the right direction.
+    #[test]
+    fn test_shifted() {
+        let (a, b) = (hist(500, 5, 110000), hist(700, 5, 110000));
+        assert!(a.ks(&b) > 0.9);
+        assert!(a.shift(&b) < -150.0 && b.shift(&a) > 150.0);
+
+        let t = Thresholds::default();
+        let slower = compare(&run(1e6, a.clone()), &run(1e6, b.clone()), &t, false).unwrap();
+        assert_eq!(Verdict::Regressed, slower[1].verdict);
+        assert_eq!(200, slower[1].p99);
+
+        let faster = compare(&run(1e6, b), &run(1e6, a), &t, false).unwrap();
+        assert_eq!(Verdict::Improved, faster[1].verdict);
+    }
+
+    // Tests that a distribution that widens around the same median is a regression.
+    #[test]
+    fn test_widened() {
+        let (a, b) = (hist(500, 2, 100000), hist(500, 20, 100000));
+        assert_eq!(a.percentile(0.5), b.percentile(0.5));
+        assert!(a.shift(&b).abs() < 10.0);
+
+        let t = Thresholds::default();
+        let deltas = compare(&run(1e6, a), &run(1e6, b), &t, false).unwrap();
+        assert_eq!(Verdict::Regressed, deltas[1].verdict);
+        assert!(deltas[1].p99 > 0);
+    }
+
+    // Tests that small samples are not flagged even if they differ beyond the threshold.
+    #[test]
+    fn test_insignificant() {
+        let (a, b) = (hist(500, 5, 11), hist(520, 5, 11));
+        assert!(a.ks(&b) > Thresholds::default().ks);
+        assert!(a.ks(&b) < a.ks_critical(&b));
+
+        let deltas = compare(&run(1e6, a), &run(1e6, b), &Thresholds::default(), false).unwrap();
+        assert_eq!(Verdict::Unchanged, deltas[1].verdict);
+    }
+
+    // Tests throughput deltas against the threshold.
+    #[test]
+    fn test_throughput() {
+        let h = hist(500, 5, 110000);
+        let t = Thresholds::default();
+        let delta = |x: f64| compare(&run(1e6, h.clone()), &run(x, h.clone()), &t, false).unwrap();
+
+        assert_eq!(Verdict::Unchanged, delta(1.04e6)[0].verdict);
+        assert_eq!(Verdict::Improved, delta(1.1e6)[0].verdict);
+        assert_eq!(Verdict::Regressed, delta(0.9e6)[0].verdict);
+        assert!((delta(0.9e6)[0].change + 10.0).abs() < 1e-9);
+    }
+
+    // Tests that mismatched configs and structures are refused unless forced.
+    #[test]
+    fn test_mismatched() {
+        let a = run(1e6, hist(500, 5, 110000));
+        let mut b = a.clone();
+        b.config.value_len = 40;
+        b.config.use_invoke = true;
+
+        let e = compare(&a, &b, &Thresholds::default(), false).unwrap_err();
+        assert!(e.contains("value_len 100 vs 40") && e.contains("use_invoke"));
+        assert!(compare(&a, &b, &Thresholds::default(), true).is_ok());
+        assert!(a.config.signature() != b.config.signature());
+
+        let mut c = a.clone();
+        c.phases[0].classes[0].name = "put".to_string();
+        assert!(compare(&a, &c, &Thresholds::default(), false).is_err());
+
+        c.phases.push(a.phases[0].clone());
+        assert!(compare(&a, &c, &Thresholds::default(), false).is_err());
+    }
+
+    // Tests that embedded runs are never matched up with, or compared against, network runs.
+    #[test]
+    fn test_embedded_transport() {
+        let a = run(1e6, hist(500, 5, 110000));
+        let mut b = a.clone();
+        b.config.transport = "embedded".to_string();
+
+        let e = compare(&a, &b, &Thresholds::default(), false).unwrap_err();
+        assert!(e.contains("transport \"\" vs \"embedded\""));
+        assert_eq!("ycsb-k30-v100-p5-native", a.config.signature());
+        assert_eq!("ycsb-k30-v100-p5-native-embedded", b.config.signature());
+    }
+
+    // Tests that the server's latencies are compared for the opcodes both runs collected, and
+    // that results without them still compare.
+    #[test]
+    fn test_server_latency() {
+        let server = |name: &str, mean: u64| Class {
+            name: name.to_string(),
+            throughput: 0.0,
+            latency: hist(mean, 5, 110000),
+            duplicates: 0,
+        };
+        let mut a = run(1e6, hist(500, 5, 110000));
+        let mut b = a.clone();
+        a.server = vec![
+            server("SandstormGetRpc", 300),
+            server("SandstormPutRpc", 300),
+        ];
+        b.server = vec![server("SandstormGetRpc", 600)];
+
+        let deltas = compare(&a, &b, &Thresholds::default(), false).unwrap();
+        assert_eq!(3, deltas.len());
+        assert_eq!(
+            ("server", "SandstormGetRpc"),
+            (&deltas[2].phase[..], &deltas[2].class[..])
+        );
+        assert_eq!(Verdict::Regressed, deltas[2].verdict);
+        assert_eq!(300, deltas[2].p99);
+
+        b.server.clear();
+        let deltas = compare(&a, &b, &Thresholds::default(), false).unwrap();
+        assert_eq!(2, deltas.len());
+    }
+}
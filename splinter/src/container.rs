@@ -119,6 +119,7 @@ impl Task for Container {
         // Resume the task if need be. The task needs to be run/resumed only
         // if it is in the INITIALIZED or YIELDED state. Nothing needs to be
         // done if it has already completed, or was aborted.
+        let mut finished = false;
         if self.state == INITIALIZED || self.state == YIELDED || self.state == WAITING {
             self.state = RUNNING;
 
@@ -146,8 +147,14 @@ impl Task for Container {
 
                 // If there was a panic thrown, then mark the container as COMPLETED so that it
                 // does not get run again.
-                if let Err(_) = res {
-                    self.state = COMPLETED;
+                match res {
+                    Ok(_) => finished = self.state == COMPLETED,
+                    Err(_) => {
+                        self.state = COMPLETED;
+                        if let Some(proxydb) = self.db.get_mut() {
+                            proxydb.set_panicked();
+                        }
+                    }
                 }
             }
         }
@@ -158,6 +165,13 @@ impl Task for Container {
         // Update the total execution time of the task.
         self.time += exec;
 
+        // Fold the time spent inside the extension into it's cost once it completes, so that the
+        // client can estimate how long pushed back invocations of it have left to run. Panics
+        // are left out; they don't say how long it usually runs for.
+        if finished {
+            self.ext.record_cost(self.time.saturating_sub(self.db_time));
+        }
+
         // Return the state and the amount of time the task executed for.
         return (self.state, exec);
     }
@@ -203,6 +217,14 @@ impl Task for Container {
         self.state = state;
     }
 
+    /// Refer to the `Task` trait for Documentation.
+    fn remaining(&self) -> Option<u64> {
+        match self.ext.cost() {
+            0 => None,
+            cost => Some(cost.saturating_sub(self.time.saturating_sub(self.db_time))),
+        }
+    }
+
     /// Refer to the `Task` trait for Documentation.
     fn update_cache(&mut self, record: &[u8], keylen: usize) {
         if let Some(proxydb) = self.db.get_mut() {
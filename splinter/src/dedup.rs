@@ -0,0 +1,238 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use std::collections::HashSet;
+
+use db::config::ClientConfig;
+use db::wireformat::OpCode;
+
+/// The number of completions a duplicate is guaranteed to be caught within, if not set in the
+/// client config.
+pub const DEFAULT_WINDOW: usize = 4096;
+
+/// Duplicates are warned about once they make up more than this fraction of the responses
+/// received. Any duplicate at all points at a bug on the server or in the network, but a few
+/// can be put down to a flaky switch.
+pub const WARN_RATE: f64 = 0.0001;
+
+// The number of sets the window is split into. One of these is cleared each time the newest
+// fills up, so the window slides in steps of a set.
+const GENERATIONS: usize = 4;
+
+/// Detects responses to requests that have already completed, so that they can be dropped
+/// before they touch a workload's latencies, outstanding window, or any other state.
+///
+/// Ids of completed requests are held in a ring of `GENERATIONS` sets. Ids go into the newest
+/// set; once it is full, the oldest set is cleared and becomes the newest. Memory is therefore
+/// fixed, but the window is not exact:
+///
+/// * A duplicate that arrives within `window()` completions of the original is always caught.
+/// * A duplicate that arrives later than that may be missed, and treated as a new response.
+///   Anything later than `window() + window() / (GENERATIONS - 1)` completions always is.
+pub struct Dedup {
+    // Ids of recently completed requests. `sets[newest]` is the one being filled.
+    sets: Vec<HashSet<u64>>,
+
+    // The index of the set being filled.
+    newest: usize,
+
+    // The number of ids each set holds before the window slides.
+    per_set: usize,
+
+    // The number of responses that were not duplicates.
+    completed: u64,
+
+    // The number of duplicates dropped, by opcode.
+    duplicates: Vec<(OpCode, u64)>,
+}
+
+impl Dedup {
+    /// Creates a detector.
+    ///
+    /// # Arguments
+    ///
+    /// * `window`: The number of completions within which a duplicate must be caught. Should
+    ///             cover the largest plausible reordering between responses. Atleast 1.
+    pub fn new(window: usize) -> Dedup {
+        let window = window.max(1);
+        let per_set = (window + GENERATIONS - 2) / (GENERATIONS - 1);
+        Dedup {
+            sets: (0..GENERATIONS)
+                .map(|_| HashSet::with_capacity(per_set))
+                .collect(),
+            newest: 0,
+            per_set: per_set,
+            completed: 0,
+            duplicates: Vec::new(),
+        }
+    }
+
+    /// Creates a detector as specified by the client config.
+    pub fn from_config(config: &ClientConfig) -> Dedup {
+        Dedup::new(match config.dup_window {
+            0 => DEFAULT_WINDOW,
+            w => w,
+        })
+    }
+
+    /// Returns the number of completions within which a duplicate is guaranteed to be caught.
+    /// Can be slightly larger than the window the detector was created with.
+    pub fn window(&self) -> usize {
+        self.per_set * (GENERATIONS - 1)
+    }
+
+    /// Records the response to a request, unless the request already completed.
+    ///
+    /// # Arguments
+    ///
+    /// * `id`:     The request id on the response.
+    /// * `opcode`: The opcode on the response. Duplicates are counted by opcode.
+    ///
+    /// # Return
+    ///
+    /// True if this is the first response to the request, and should be processed. False if
+    /// it's a duplicate, and should be dropped.
+    pub fn complete(&mut self, id: u64, opcode: OpCode) -> bool {
+        if self.sets.iter().any(|s| s.contains(&id)) {
+            match self.duplicates.iter_mut().find(|d| d.0 == opcode) {
+                Some(d) => d.1 += 1,
+                None => self.duplicates.push((opcode, 1)),
+            }
+            return false;
+        }
+
+        // Slide the window once the newest set is full.
+        if self.sets[self.newest].len() >= self.per_set {
+            self.newest = (self.newest + 1) % GENERATIONS;
+            self.sets[self.newest].clear();
+        }
+
+        self.sets[self.newest].insert(id);
+        self.completed += 1;
+        true
+    }
+
+    /// Returns the number of responses that were not duplicates.
+    pub fn completed(&self) -> u64 {
+        self.completed
+    }
+
+    /// Returns each opcode that duplicates were received for, along with how many.
+    pub fn counts(&self) -> Vec<(OpCode, u64)> {
+        self.duplicates.clone()
+    }
+
+    /// Returns the total number of duplicates dropped.
+    pub fn duplicates(&self) -> u64 {
+        self.duplicates.iter().map(|&(_, n)| n).sum()
+    }
+
+    /// Returns true if duplicates make up more than WARN_RATE of the responses received.
+    pub fn excessive(&self) -> bool {
+        let dups = self.duplicates();
+        dups > 0 && dups as f64 / (dups + self.completed) as f64 > WARN_RATE
+    }
+}
+
+// This module contains unit tests for Dedup.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Tests that a duplicate is dropped and counted under it's opcode, and nothing else is.
+    #[test]
+    fn test_dedup_counts() {
+        let mut dedup = Dedup::new(16);
+        assert!(dedup.complete(1, OpCode::SandstormGetRpc));
+        assert!(dedup.complete(2, OpCode::SandstormPutRpc));
+        assert!(!dedup.complete(1, OpCode::SandstormGetRpc));
+        assert!(!dedup.complete(1, OpCode::SandstormGetRpc));
+        assert!(!dedup.complete(2, OpCode::SandstormPutRpc));
+        assert!(dedup.complete(3, OpCode::SandstormGetRpc));
+
+        assert_eq!(3, dedup.completed());
+        assert_eq!(3, dedup.duplicates());
+        assert_eq!(
+            vec![(OpCode::SandstormGetRpc, 2), (OpCode::SandstormPutRpc, 1)],
+            dedup.counts()
+        );
+    }
+
+    // Tests that every duplicate within the window is caught, however the window has slid.
+    #[test]
+    fn test_dedup_within_window() {
+        let mut dedup = Dedup::new(100);
+        let window = dedup.window() as u64;
+        assert!(window >= 100);
+
+        for id in 0..10 * window {
+            assert!(dedup.complete(id, OpCode::SandstormGetRpc));
+
+            // The oldest id still within the window.
+            if id >= window - 1 {
+                assert!(!dedup.complete(id + 1 - window, OpCode::SandstormGetRpc));
+            }
+        }
+
+        assert_eq!(10 * window, dedup.completed());
+        assert_eq!(9 * window + 1, dedup.duplicates());
+    }
+
+    // Tests that duplicates far enough outside the window are treated as new responses, and
+    // that memory stays bounded by the window.
+    #[test]
+    fn test_dedup_outside_window() {
+        let mut dedup = Dedup::new(30);
+        let window = dedup.window() as u64;
+        let limit = window + window / (GENERATIONS as u64 - 1);
+
+        for id in 0..(limit + 1) {
+            assert!(dedup.complete(id, OpCode::SandstormGetRpc));
+        }
+        assert!(dedup.complete(0, OpCode::SandstormGetRpc));
+        assert_eq!(0, dedup.duplicates());
+
+        let held: usize = dedup.sets.iter().map(|s| s.len()).sum();
+        assert!(held as u64 <= limit);
+    }
+
+    // Tests the window a detector is created with.
+    #[test]
+    fn test_dedup_window() {
+        assert_eq!(3, Dedup::new(0).window());
+        assert_eq!(3, Dedup::new(3).window());
+        assert_eq!(6, Dedup::new(4).window());
+        assert_eq!(4098, Dedup::new(DEFAULT_WINDOW).window());
+        assert_eq!(4098, Dedup::from_config(&ClientConfig::default()).window());
+    }
+
+    // Tests that only a rate of duplicates above WARN_RATE is excessive.
+    #[test]
+    fn test_dedup_excessive() {
+        let mut dedup = Dedup::new(DEFAULT_WINDOW);
+        assert!(!dedup.excessive());
+
+        for id in 0..20000 {
+            dedup.complete(id, OpCode::SandstormGetRpc);
+        }
+        dedup.complete(19999, OpCode::SandstormGetRpc);
+        assert!(!dedup.excessive());
+
+        dedup.complete(19998, OpCode::SandstormGetRpc);
+        dedup.complete(19997, OpCode::SandstormGetRpc);
+        assert_eq!(3, dedup.duplicates());
+        assert!(dedup.excessive());
+    }
+}
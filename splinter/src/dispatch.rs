@@ -13,20 +13,31 @@
  * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
  */
 
-use std::cell::Cell;
+use std::cell::{Cell, RefCell};
 use std::fmt::Display;
+use std::mem;
 use std::net::Ipv4Addr;
 use std::str::FromStr;
+use std::sync::Arc;
 
 use db::config;
+use db::cycles;
 use db::e2d2::allocators::*;
 use db::e2d2::common::EmptyMetadata;
 use db::e2d2::headers::*;
 use db::e2d2::interface::*;
+use db::e2d2::native::zcsi::MBuf;
+use db::evict::CacheTarget;
 use db::log::*;
 use db::rpc;
 use db::wireformat::*;
 
+use sandstorm::invoke::InvokeArgs;
+
+use super::burst::{Burst, BurstConfig, BurstStats};
+use super::ids::{self, RequestIds};
+use super::pacing::SharedPacer;
+
 /// A simple RPC request generator for Sandstorm.
 pub struct Sender {
     // The network interface over which requests will be sent out.
@@ -47,6 +58,29 @@ pub struct Sender {
 
     // The number of destination UDP ports a packet can be sent to.
     dst_ports: u16,
+
+    // Generates ids for requests sent out by this instance.
+    ids: RequestIds,
+
+    // Flags set on the common header of every request sent out by this instance.
+    flags: u8,
+
+    // The run id every request sent out by this instance is tagged with. 0 if requests are not
+    // tagged.
+    run: u64,
+    // The staleness budget on get()s sent out without one, in microseconds.
+    staleness_us: u32,
+
+    // Requests held back to be sent out the network interface together. Every request is sent
+    // out on it's own unless configured otherwise by with_burst().
+    burst: RefCell<Burst<Packet<IpHeader, EmptyMetadata>>>,
+
+    // MBuf pointers a burst is handed to the network interface in. Reused across bursts.
+    mbufs: RefCell<Vec<*mut MBuf>>,
+
+    // The pacer every request sent out is counted against, if congestion aware pacing is on.
+    // Shared with the pipeline's Receiver.
+    pacer: Option<Arc<SharedPacer>>,
 }
 
 impl Sender {
@@ -108,9 +142,99 @@ impl Sender {
             req_mac_header: mac_header,
             requests_sent: Cell::new(0),
             dst_ports: dst_ports,
+            ids: RequestIds::next_pipeline(),
+            flags: if config.server_stamps {
+                REQUEST_FLAG_STAMPS
+            } else {
+                0
+            },
+            run: if config.tag_run {
+                ids::run_id(config)
+            } else {
+                0
+            },
+            staleness_us: config.get_staleness_us,
+            burst: RefCell::new(Burst::new(BurstConfig {
+                max_len: 1,
+                hold_cycles: 0,
+            })),
+            mbufs: RefCell::new(Vec::with_capacity(1)),
+            pacer: None,
+        }
+    }
+
+    /// Makes this Sender hold requests back and send them out in bursts. Requests are
+    /// time-stamped when their burst is sent out, overwriting the time-stamp they were created
+    /// with. poll() must be called whenever the Sender's pipeline is scheduled, so that a held
+    /// burst goes out in time even if no more requests are sent.
+    ///
+    /// # Arguments
+    ///
+    /// * `config`: Bounds on a burst. A `max_len` of 1 sends every request out on it's own.
+    pub fn with_burst(self, config: BurstConfig) -> Sender {
+        *self.burst.borrow_mut() = Burst::new(config);
+        self
+    }
+
+    /// Makes this Sender count every request it sends out against a pacer's window. The pacer
+    /// must be handed to the pipeline's Receiver too (see Receiver::with_pacer()), so that
+    /// responses free their slots up. Generators should send out atmost room() requests at a
+    /// time.
+    ///
+    /// # Arguments
+    ///
+    /// * `pacer`: The pipeline's pacer; see pacing::pipeline(). None turns pacing off.
+    pub fn with_pacer(mut self, pacer: Option<Arc<SharedPacer>>) -> Sender {
+        self.pacer = pacer;
+        self
+    }
+
+    /// Returns the number of requests that can be sent out before the pacer's window fills up,
+    /// or u64::max_value() if requests are not paced.
+    #[inline]
+    pub fn room(&self) -> u64 {
+        match self.pacer {
+            Some(ref pacer) => pacer.room(),
+            None => u64::max_value(),
+        }
+    }
+
+    /// Returns the (time-stamp, window, smoothed load) tuples recorded every time the pacer's
+    /// window changed, or None if requests are not paced.
+    pub fn pacing(&self) -> Option<Vec<(u64, u32, f64)>> {
+        self.pacer.as_ref().map(|pacer| pacer.trajectory())
+    }
+
+    /// Sends out the held burst if it's oldest request has been held too long.
+    #[inline]
+    pub fn poll(&self) {
+        let now = cycles::rdtsc();
+        let burst = self.burst.borrow_mut().poll(now);
+        if let Some(burst) = burst {
+            self.transmit(burst, now);
         }
     }
 
+    /// Sends out whatever is held, regardless of how long it has been held for.
+    pub fn flush(&self) {
+        let now = cycles::rdtsc();
+        let burst = self.burst.borrow_mut().flush(now);
+        self.transmit(burst, now);
+    }
+
+    /// Returns the number and length of the bursts sent out so far, and how long requests were
+    /// held back for.
+    pub fn bursts(&self) -> BurstStats {
+        self.burst.borrow().stats()
+    }
+
+    /// Returns a new request id. Ids are unique across all Senders on the client, and should be
+    /// used (instead of the time-stamp) to match responses to requests.
+    #[inline]
+    pub fn next_id(&self) -> u64 {
+        self.ids.next()
+    }
+
     /// Creates and sends out a get() RPC request. Network headers are populated based on arguments
     /// passed into new() above.
     ///
@@ -120,9 +244,34 @@ impl Sender {
     /// * `table`:  Id of the table from which the key is looked up.
     /// * `key`:    Byte string of key whose value is to be fetched. Limit 64 KB.
     /// * `id`:     RPC identifier.
+    /// * `stamp`:  The time-stamp at which the RPC is being sent out.
+    #[allow(dead_code)]
+    pub fn send_get(&self, tenant: u32, table: u64, key: &[u8], id: u64, stamp: u64) {
+        self.send_get_with_staleness(tenant, table, key, self.staleness_us, id, stamp);
+    }
+
+    /// Creates and sends out a get() RPC request with a staleness budget, overriding the one in
+    /// the config. Network headers are populated based on arguments passed into new() above.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`:       Id of the tenant requesting the item.
+    /// * `table`:        Id of the table from which the key is looked up.
+    /// * `key`:          Byte string of key whose value is to be fetched. Limit 64 KB.
+    /// * `staleness_us`: The staleness budget on the get() in microseconds. 0 is strictly fresh.
+    /// * `id`:           RPC identifier.
+    /// * `stamp`:        The time-stamp at which the RPC is being sent out.
     #[allow(dead_code)]
-    pub fn send_get(&self, tenant: u32, table: u64, key: &[u8], id: u64) {
-        let request = rpc::create_get_rpc(
+    pub fn send_get_with_staleness(
+        &self,
+        tenant: u32,
+        table: u64,
+        key: &[u8],
+        staleness_us: u32,
+        id: u64,
+        stamp: u64,
+    ) {
+        let request = rpc::create_get_rpc_with_staleness(
             &self.req_mac_header,
             &self.req_ip_header,
             &self.req_udp_header,
@@ -130,8 +279,52 @@ impl Sender {
             table,
             key,
             id,
+            stamp,
             self.get_dst_port(tenant),
             GetGenerator::SandstormClient,
+            staleness_us,
+        );
+
+        self.send_req(request);
+    }
+
+    /// Creates and sends out a get() RPC request that only asks for part of the value, ex: a
+    /// field at a fixed offset in it. The response carries the requested bytes, and reports the
+    /// full length of the value. Network headers are populated based on arguments passed into
+    /// new() above.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`:     Id of the tenant requesting the item.
+    /// * `table`:      Id of the table from which the key is looked up.
+    /// * `key`:        Byte string of key whose value is to be fetched. Limit 64 KB.
+    /// * `projection`: The offset into the value to start at, and the number of bytes to fetch.
+    ///                 A length of 0 runs upto the end of the value.
+    /// * `id`:         RPC identifier.
+    /// * `stamp`:      The time-stamp at which the RPC is being sent out.
+    #[allow(dead_code)]
+    pub fn send_get_with_projection(
+        &self,
+        tenant: u32,
+        table: u64,
+        key: &[u8],
+        projection: (u32, u32),
+        id: u64,
+        stamp: u64,
+    ) {
+        let request = rpc::create_get_rpc_with_projection(
+            &self.req_mac_header,
+            &self.req_ip_header,
+            &self.req_udp_header,
+            tenant,
+            table,
+            key,
+            id,
+            stamp,
+            self.get_dst_port(tenant),
+            GetGenerator::SandstormClient,
+            self.staleness_us,
+            projection,
         );
 
         self.send_req(request);
@@ -146,9 +339,17 @@ impl Sender {
     /// * `table`:  Id of the table from which the key is looked up.
     /// * `key`:    Byte string of key whose value is to be fetched. Limit 64 KB.
     /// * `id`:     RPC identifier.
+    /// * `stamp`:  The time-stamp at which the RPC is being sent out.
     #[allow(dead_code)]
-    pub fn send_get_from_extension(&self, tenant: u32, table: u64, key: &[u8], id: u64) {
-        let request = rpc::create_get_rpc(
+    pub fn send_get_from_extension(
+        &self,
+        tenant: u32,
+        table: u64,
+        key: &[u8],
+        id: u64,
+        stamp: u64,
+    ) {
+        let request = rpc::create_get_rpc_with_staleness(
             &self.req_mac_header,
             &self.req_ip_header,
             &self.req_udp_header,
@@ -156,8 +357,10 @@ impl Sender {
             table,
             key,
             id,
+            stamp,
             self.get_dst_port(tenant),
             GetGenerator::SandstormExtension,
+            self.staleness_us,
         );
         self.send_req(request);
     }
@@ -172,8 +375,9 @@ impl Sender {
     /// * `key`:    Byte string of key whose value is to be inserted. Limit 64 KB.
     /// * `val`:    Byte string of the value to be inserted.
     /// * `id`:     RPC identifier.
+    /// * `stamp`:  The time-stamp at which the RPC is being sent out.
     #[allow(dead_code)]
-    pub fn send_put(&self, tenant: u32, table: u64, key: &[u8], val: &[u8], id: u64) {
+    pub fn send_put(&self, tenant: u32, table: u64, key: &[u8], val: &[u8], id: u64, stamp: u64) {
         let request = rpc::create_put_rpc(
             &self.req_mac_header,
             &self.req_ip_header,
@@ -183,6 +387,111 @@ impl Sender {
             key,
             val,
             id,
+            stamp,
+            self.get_dst_port(tenant),
+        );
+
+        self.send_req(request);
+    }
+
+    /// Creates and sends out a put() RPC request on a value made up of several segments, ex: a
+    /// header template, a variable body and a checksum trailer. The segments are written into
+    /// the packet one after the other, without first being copied into a single buffer. Network
+    /// headers are populated based on arguments passed into new() above.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`:   Id of the tenant requesting the insertion.
+    /// * `table`:    Id of the table into which the key-value pair is to be inserted.
+    /// * `key`:      Byte string of key whose value is to be inserted. Limit 64 KB.
+    /// * `segments`: The segments of the value to be inserted, in order.
+    /// * `id`:       RPC identifier.
+    /// * `stamp`:    The time-stamp at which the RPC is being sent out.
+    pub fn send_put_gather(
+        &self,
+        tenant: u32,
+        table: u64,
+        key: &[u8],
+        segments: &[&[u8]],
+        id: u64,
+        stamp: u64,
+    ) {
+        let request = rpc::create_put_rpc_gather(
+            &self.req_mac_header,
+            &self.req_ip_header,
+            &self.req_udp_header,
+            tenant,
+            table,
+            key,
+            segments,
+            id,
+            stamp,
+            self.get_dst_port(tenant),
+        );
+
+        self.send_req(request);
+    }
+
+    /// Creates and sends out a delete() RPC request. Network headers are populated based on
+    /// arguments passed into new() above.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`: Id of the tenant requesting the deletion.
+    /// * `table`:  Id of the table the key-value pair is to be removed from.
+    /// * `key`:    Byte string of key whose key-value pair is to be removed. Limit 64 KB.
+    /// * `id`:     RPC identifier.
+    /// * `stamp`:  The time-stamp at which the RPC is being sent out.
+    pub fn send_delete(&self, tenant: u32, table: u64, key: &[u8], id: u64, stamp: u64) {
+        let request = rpc::create_delete_rpc(
+            &self.req_mac_header,
+            &self.req_ip_header,
+            &self.req_udp_header,
+            tenant,
+            table,
+            key,
+            id,
+            stamp,
+            self.get_dst_port(tenant),
+        );
+
+        self.send_req(request);
+    }
+
+    /// Creates and sends out a cas() RPC request. Network headers are populated based on
+    /// arguments passed into new() above.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`:   Id of the tenant requesting the write.
+    /// * `table`:    Id of the table into which the key-value pair is to be written.
+    /// * `key`:      Byte string of key whose value is to be written. Limit 64 KB.
+    /// * `expected`: The value expected under the key, or None if the key is expected to not
+    ///               exist.
+    /// * `val`:      Byte string of the value to be written.
+    /// * `id`:       RPC identifier.
+    /// * `stamp`:    The time-stamp at which the RPC is being sent out.
+    pub fn send_cas(
+        &self,
+        tenant: u32,
+        table: u64,
+        key: &[u8],
+        expected: Option<&[u8]>,
+        val: &[u8],
+        id: u64,
+        stamp: u64,
+    ) {
+        let request = rpc::create_cas_rpc(
+            &self.req_mac_header,
+            &self.req_ip_header,
+            &self.req_udp_header,
+            tenant,
+            table,
+            key,
+            expected,
+            val,
+            id,
+            stamp,
             self.get_dst_port(tenant),
         );
 
@@ -201,6 +510,7 @@ impl Sender {
     /// * `n_keys`: The number of keys to be looked up at the server.
     /// * `keys`:   Byte string of keys whose values are to be fetched.
     /// * `id`:     RPC identifier.
+    /// * `stamp`:  The time-stamp at which the RPC is being sent out.
     #[allow(dead_code)]
     pub fn send_multiget(
         &self,
@@ -210,6 +520,7 @@ impl Sender {
         n_keys: u32,
         keys: &[u8],
         id: u64,
+        stamp: u64,
     ) {
         let request = rpc::create_multiget_rpc(
             &self.req_mac_header,
@@ -221,6 +532,52 @@ impl Sender {
             n_keys,
             keys,
             id,
+            stamp,
+            self.get_dst_port(tenant),
+        );
+
+        self.send_req(request);
+    }
+
+    /// Creates and sends out a multiget() RPC request that asks for the version of each object
+    /// along with, or instead of, it's value. Network headers are populated based on arguments
+    /// passed into new() above.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`: Id of the tenant requesting the item.
+    /// * `table`:  Id of the table from which the key is looked up.
+    /// * `k_len`:  The length of each key to be looked up at the server. All keys are
+    ///               assumed to be of equal length.
+    /// * `n_keys`: The number of keys to be looked up at the server.
+    /// * `keys`:   Byte string of keys whose values are to be fetched.
+    /// * `flags`:  MULTIGET_FLAG_VERSIONS or MULTIGET_FLAG_VERSIONS_ONLY. See wireformat.
+    /// * `id`:     RPC identifier.
+    /// * `stamp`:  The time-stamp at which the RPC is being sent out.
+    #[allow(dead_code)]
+    pub fn send_multiget_with_flags(
+        &self,
+        tenant: u32,
+        table: u64,
+        k_len: u16,
+        n_keys: u32,
+        keys: &[u8],
+        flags: u8,
+        id: u64,
+        stamp: u64,
+    ) {
+        let request = rpc::create_multiget_rpc_with_flags(
+            &self.req_mac_header,
+            &self.req_ip_header,
+            &self.req_udp_header,
+            tenant,
+            table,
+            k_len,
+            n_keys,
+            keys,
+            flags,
+            id,
+            stamp,
             self.get_dst_port(tenant),
         );
 
@@ -238,7 +595,8 @@ impl Sender {
     /// * `payload`:  The RPC payload to be written into the packet. Must contain the name of the
     ///               extension followed by it's arguments.
     /// * `id`:       RPC identifier.
-    pub fn send_invoke(&self, tenant: u32, name_len: u32, payload: &[u8], id: u64) {
+    /// * `stamp`:    The time-stamp at which the RPC is being sent out.
+    pub fn send_invoke(&self, tenant: u32, name_len: u32, payload: &[u8], id: u64, stamp: u64) {
         let request = rpc::create_invoke_rpc(
             &self.req_mac_header,
             &self.req_ip_header,
@@ -247,6 +605,7 @@ impl Sender {
             name_len,
             payload,
             id,
+            stamp,
             self.get_dst_port(tenant),
             // (id & 0xffff) as u16 & (self.dst_ports - 1),
         );
@@ -254,6 +613,555 @@ impl Sender {
         self.send_req(request);
     }
 
+    /// Creates and sends out an invoke() RPC request on a payload made up of several segments,
+    /// ex: a template with the extension's name and fixed arguments followed by the arguments
+    /// that vary per request. The segments are written into the packet one after the other,
+    /// without first being copied into a single buffer. Network headers are populated based on
+    /// arguments passed into new() above.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`:   Id of the tenant requesting the invocation.
+    /// * `name_len`: The number of bytes at the head of the payload corresponding to the
+    ///               extensions name.
+    /// * `segments`: The segments of the RPC payload, in order. Together, they must contain the
+    ///               name of the extension followed by it's arguments.
+    /// * `id`:       RPC identifier.
+    /// * `stamp`:    The time-stamp at which the RPC is being sent out.
+    pub fn send_invoke_gather(
+        &self,
+        tenant: u32,
+        name_len: u32,
+        segments: &[&[u8]],
+        id: u64,
+        stamp: u64,
+    ) {
+        let request = rpc::create_invoke_rpc_gather(
+            &self.req_mac_header,
+            &self.req_ip_header,
+            &self.req_udp_header,
+            tenant,
+            name_len,
+            segments,
+            id,
+            stamp,
+            self.get_dst_port(tenant),
+        );
+
+        self.send_req(request);
+    }
+
+    /// Creates and sends out an invoke() RPC request whose arguments were built up field by field,
+    /// for extensions that read them with DB::next_arg(). The name and the arguments are written
+    /// into the packet without first being copied into a single buffer.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`: Id of the tenant requesting the invocation.
+    /// * `name`:   The name of the extension.
+    /// * `args`:   The arguments to the extension.
+    /// * `id`:     RPC identifier.
+    /// * `stamp`:  The time-stamp at which the RPC is being sent out.
+    pub fn send_invoke_args(
+        &self,
+        tenant: u32,
+        name: &[u8],
+        args: &InvokeArgs,
+        id: u64,
+        stamp: u64,
+    ) {
+        let segments = [name, args.as_bytes()];
+        self.send_invoke_gather(tenant, name.len() as u32, &segments, id, stamp);
+    }
+
+    /// Creates and sends out an echo() RPC request. The response carries the rate at which the
+    /// server's cycle counter ticks.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`: Id of the tenant requesting the echo.
+    /// * `id`:     RPC identifier.
+    /// * `stamp`:  The time-stamp at which the RPC is being sent out.
+    pub fn send_echo(&self, tenant: u32, id: u64, stamp: u64) {
+        let request = rpc::create_echo_rpc(
+            &self.req_mac_header,
+            &self.req_ip_header,
+            &self.req_udp_header,
+            tenant,
+            id,
+            stamp,
+            self.get_dst_port(tenant),
+        );
+
+        self.send_req(request);
+    }
+
+    /// Creates and sends out a list_extensions() RPC request. The response lists the extensions
+    /// the tenant can invoke, starting at `start`.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`: Id of the tenant whose extensions must be listed.
+    /// * `start`:  The index of the first extension to be listed; the continuation index on the
+    ///             previous response, or 0.
+    /// * `id`:     RPC identifier.
+    /// * `stamp`:  The time-stamp at which the RPC is being sent out.
+    pub fn send_list_ext(&self, tenant: u32, start: u32, id: u64, stamp: u64) {
+        let request = rpc::create_list_ext_rpc(
+            &self.req_mac_header,
+            &self.req_ip_header,
+            &self.req_udp_header,
+            tenant,
+            start,
+            id,
+            stamp,
+            self.get_dst_port(tenant),
+        );
+
+        self.send_req(request);
+    }
+
+    /// Creates and sends out a drain() RPC request, putting the server into drain mode. The
+    /// response reports how many tasks are yet to complete on each core.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`: Id of the tenant issuing the drain. The server only accepts drains from
+    ///             tenant 0.
+    /// * `id`:     RPC identifier.
+    /// * `stamp`:  The time-stamp at which the RPC is being sent out.
+    pub fn send_drain(&self, tenant: u32, id: u64, stamp: u64) {
+        let request = rpc::create_drain_rpc(
+            &self.req_mac_header,
+            &self.req_ip_header,
+            &self.req_udp_header,
+            tenant,
+            id,
+            stamp,
+            self.get_dst_port(tenant),
+        );
+
+        self.send_req(request);
+    }
+
+    /// Creates and sends out a run_stats() RPC request. The response carries the counters the
+    /// server keeps for client runs.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`: Id of the tenant issuing the request. The server only accepts it from
+    ///             tenant 0.
+    /// * `filter`: The run whose counters are requested, or 0 for the most recently active runs.
+    /// * `id`:     RPC identifier.
+    /// * `stamp`:  The time-stamp at which the RPC is being sent out.
+    pub fn send_run_stats(&self, tenant: u32, filter: u64, id: u64, stamp: u64) {
+        let request = rpc::create_run_stats_rpc(
+            &self.req_mac_header,
+            &self.req_ip_header,
+            &self.req_udp_header,
+            tenant,
+            filter,
+            id,
+            stamp,
+            self.get_dst_port(tenant),
+        );
+
+        self.send_req(request);
+    }
+
+    /// Creates and sends out an audit() RPC request. The response carries a page of the tenant's
+    /// audit log, newest invocation first.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`: Id of the tenant whose audit log must be paged through.
+    /// * `cursor`: The cursor on the previous response, or 0 for the newest entries.
+    /// * `id`:     RPC identifier.
+    /// * `stamp`:  The time-stamp at which the RPC is being sent out.
+    pub fn send_audit(&self, tenant: u32, cursor: u64, id: u64, stamp: u64) {
+        let request = rpc::create_audit_rpc(
+            &self.req_mac_header,
+            &self.req_ip_header,
+            &self.req_udp_header,
+            tenant,
+            cursor,
+            id,
+            stamp,
+            self.get_dst_port(tenant),
+        );
+
+        self.send_req(request);
+    }
+
+    /// Creates and sends out a dump() RPC request. The response carries a page of the records in
+    /// one of the tenant's tables.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`: Id of the tenant whose table must be dumped.
+    /// * `table`:  Id of the table to be dumped.
+    /// * `bucket`: The bucket on the previous response, or 0 to start the dump.
+    /// * `after`:  The key of the last record on the previous response, or empty to start the
+    ///             dump.
+    /// * `id`:     RPC identifier.
+    /// * `stamp`:  The time-stamp at which the RPC is being sent out.
+    pub fn send_dump(
+        &self,
+        tenant: u32,
+        table: u64,
+        bucket: u32,
+        after: &[u8],
+        id: u64,
+        stamp: u64,
+    ) {
+        let request = rpc::create_dump_rpc(
+            &self.req_mac_header,
+            &self.req_ip_header,
+            &self.req_udp_header,
+            tenant,
+            table,
+            bucket,
+            after,
+            id,
+            stamp,
+            self.get_dst_port(tenant),
+        );
+
+        self.send_req(request);
+    }
+
+    /// Creates and sends out a scan() RPC request. The response carries as many of the records in
+    /// one of the tenant's tables as fit on it, in key order, and a continuation token.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`: Id of the tenant whose table must be scanned.
+    /// * `table`:  Id of the table to be scanned.
+    /// * `start`:  The continuation token on the previous response, or the key to start the scan
+    ///             from.
+    /// * `after`:  True if `start` is a continuation token, so that it's record isn't returned
+    ///             again.
+    /// * `max`:    The most records on the response. 0 for as many as fit on it.
+    /// * `id`:     RPC identifier.
+    /// * `stamp`:  The time-stamp at which the RPC is being sent out.
+    pub fn send_scan(
+        &self,
+        tenant: u32,
+        table: u64,
+        start: &[u8],
+        after: bool,
+        max: u32,
+        id: u64,
+        stamp: u64,
+    ) {
+        let request = rpc::create_scan_rpc(
+            &self.req_mac_header,
+            &self.req_ip_header,
+            &self.req_udp_header,
+            tenant,
+            table,
+            start,
+            after,
+            max,
+            id,
+            stamp,
+            self.get_dst_port(tenant),
+        );
+
+        self.send_req(request);
+    }
+
+    /// Creates and sends out a multiput() RPC request, writing a batch of records to one of the
+    /// tenant's tables.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`:  Id of the tenant whose table is written to.
+    /// * `table`:   Id of the table the records are written to.
+    /// * `records`: The records, each packed by rpc::append_kv().
+    /// * `num`:     The number of records packed into `records`.
+    /// * `id`:      RPC identifier.
+    /// * `stamp`:   The time-stamp at which the RPC is being sent out.
+    pub fn send_multiput(
+        &self,
+        tenant: u32,
+        table: u64,
+        records: &[u8],
+        num: u32,
+        id: u64,
+        stamp: u64,
+    ) {
+        let request = rpc::create_multiput_rpc(
+            &self.req_mac_header,
+            &self.req_ip_header,
+            &self.req_udp_header,
+            tenant,
+            table,
+            records,
+            num,
+            id,
+            stamp,
+            self.get_dst_port(tenant),
+        );
+
+        self.send_req(request);
+    }
+
+    /// Creates and sends out a write_stats() RPC request. The response carries the accounting of
+    /// the writes made to one of the tenant's tables.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`: Id of the tenant owning the table.
+    /// * `table`:  Id of the table whose writes are asked for.
+    /// * `query`:  WRITE_STATS_TOTALS for the totals of each kind of write,
+    ///             WRITE_STATS_HOT_KEYS for the keys with the highest physical write volume,
+    ///             WRITE_STATS_DEDUP for the counters of the table's dedup index,
+    ///             WRITE_STATS_CACHE for the size and eviction counters of a cache-mode table, or
+    ///             WRITE_STATS_HOT_ARENA for the counters of the server's hot arena.
+    /// * `id`:     RPC identifier.
+    /// * `stamp`:  The time-stamp at which the RPC is being sent out.
+    pub fn send_write_stats(&self, tenant: u32, table: u64, query: u8, id: u64, stamp: u64) {
+        let request = rpc::create_write_stats_rpc(
+            &self.req_mac_header,
+            &self.req_ip_header,
+            &self.req_udp_header,
+            tenant,
+            table,
+            query,
+            id,
+            stamp,
+            self.get_dst_port(tenant),
+        );
+
+        self.send_req(request);
+    }
+
+    /// Creates and sends out a core_stats() RPC request. The response carries the time each core
+    /// of the server spent backing off polling, and the CPU time the server has used. Only
+    /// served if sent as tenant 0.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`: Id of the tenant issuing the request.
+    /// * `id`:     RPC identifier.
+    /// * `stamp`:  The time-stamp at which the RPC is being sent out.
+    pub fn send_core_stats(&self, tenant: u32, id: u64, stamp: u64) {
+        let request = rpc::create_core_stats_rpc(
+            &self.req_mac_header,
+            &self.req_ip_header,
+            &self.req_udp_header,
+            tenant,
+            id,
+            stamp,
+            self.get_dst_port(tenant),
+        );
+
+        self.send_req(request);
+    }
+
+    /// Creates and sends out a latency_stats() RPC request. The response carries the time the
+    /// server took to respond to each opcode or extension, merged across it's cores. Only served
+    /// if sent as tenant 0.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`: Id of the tenant issuing the request.
+    /// * `query`:  LATENCY_STATS_OPCODES for the percentiles of every opcode,
+    ///             LATENCY_STATS_EXTENSIONS for the percentiles of every extension, or
+    ///             LATENCY_STATS_HISTOGRAM for the histogram of an opcode or extension.
+    /// * `opcode`: The opcode whose histogram is asked for.
+    /// * `name`:   The extension whose histogram is asked for. Empty for an opcode's histogram.
+    /// * `id`:     RPC identifier.
+    /// * `stamp`:  The time-stamp at which the RPC is being sent out.
+    pub fn send_latency_stats(
+        &self,
+        tenant: u32,
+        query: u8,
+        opcode: u8,
+        name: &[u8],
+        id: u64,
+        stamp: u64,
+    ) {
+        let request = rpc::create_latency_stats_rpc(
+            &self.req_mac_header,
+            &self.req_ip_header,
+            &self.req_udp_header,
+            tenant,
+            query,
+            opcode,
+            name,
+            id,
+            stamp,
+            self.get_dst_port(tenant),
+        );
+
+        self.send_req(request);
+    }
+
+    /// Creates and sends out a quarantine() RPC request. The response carries the target
+    /// tenant's quarantine once the action was applied, and the quarantines counted across the
+    /// server. Only served if sent as tenant 0.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`:      Id of the tenant issuing the request.
+    /// * `action`:      QUARANTINE_ENTER to quarantine the target, QUARANTINE_RELEASE to
+    ///                  release it, or QUARANTINE_QUERY to only report on it.
+    /// * `target`:      The tenant to quarantine, release, or report on.
+    /// * `duration_ms`: How long to quarantine the target for, on QUARANTINE_ENTER. 0 leaves it
+    ///                  to the server.
+    /// * `id`:          RPC identifier.
+    /// * `stamp`:       The time-stamp at which the RPC is being sent out.
+    pub fn send_quarantine(
+        &self,
+        tenant: u32,
+        action: u8,
+        target: u32,
+        duration_ms: u64,
+        id: u64,
+        stamp: u64,
+    ) {
+        let request = rpc::create_quarantine_rpc(
+            &self.req_mac_header,
+            &self.req_ip_header,
+            &self.req_udp_header,
+            tenant,
+            action,
+            target,
+            duration_ms,
+            id,
+            stamp,
+            self.get_dst_port(tenant),
+        );
+
+        self.send_req(request);
+    }
+
+    /// Creates and sends out a create_table() RPC request, creating one of the tenant's tables
+    /// pre-sized for the number of objects it is expected to hold. The response carries the
+    /// number of slots reserved on the table, or StatusTableTooLarge if the server refused to
+    /// size it for these many objects.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`:   Id of the tenant the table is created for.
+    /// * `table_id`: Id of the table to create.
+    /// * `records`:  The number of objects the table is expected to hold. 0 creates the table
+    ///               without pre-sizing it.
+    /// * `cache`:    The table's capacity target, if it is to be created in cache mode; writes
+    ///               past it evict objects off the table.
+    /// * `id`:       RPC identifier.
+    /// * `stamp`:    The time-stamp at which the RPC is being sent out.
+    pub fn send_create_table(
+        &self,
+        tenant: u32,
+        table_id: u64,
+        records: u64,
+        cache: Option<CacheTarget>,
+        id: u64,
+        stamp: u64,
+    ) {
+        let request = rpc::create_create_table_rpc(
+            &self.req_mac_header,
+            &self.req_ip_header,
+            &self.req_udp_header,
+            tenant,
+            table_id,
+            records,
+            cache,
+            id,
+            stamp,
+            self.get_dst_port(tenant),
+        );
+
+        self.send_req(request);
+    }
+
+    /// Creates and sends out a drop_table() RPC request, dropping one of the tenant's tables
+    /// along with every object on it.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`:   Id of the tenant owning the table.
+    /// * `table_id`: Id of the table to drop.
+    /// * `id`:       RPC identifier.
+    /// * `stamp`:    The time-stamp at which the RPC is being sent out.
+    pub fn send_drop_table(&self, tenant: u32, table_id: u64, id: u64, stamp: u64) {
+        let request = rpc::create_drop_table_rpc(
+            &self.req_mac_header,
+            &self.req_ip_header,
+            &self.req_udp_header,
+            tenant,
+            table_id,
+            id,
+            stamp,
+            self.get_dst_port(tenant),
+        );
+
+        self.send_req(request);
+    }
+
+    /// Creates and sends out a fetch_result() RPC request, asking for the result an invoke()
+    /// request stored in the tenant's mailbox. Refer to db::mailbox::Mailbox.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`:     Id of the tenant that issued the invoke() request.
+    /// * `request_id`: The id of the invoke() request whose result to fetch.
+    /// * `id`:         RPC identifier.
+    /// * `stamp`:      The time-stamp at which the RPC is being sent out.
+    pub fn send_fetch_result(&self, tenant: u32, request_id: u64, id: u64, stamp: u64) {
+        let request = rpc::create_fetch_result_rpc(
+            &self.req_mac_header,
+            &self.req_ip_header,
+            &self.req_udp_header,
+            tenant,
+            request_id,
+            id,
+            stamp,
+            self.get_dst_port(tenant),
+        );
+
+        self.send_req(request);
+    }
+
+    /// Creates and sends out a table_access() RPC request, setting whether one of the tenant's
+    /// tables can be read and written by native RPCs. Extensions can access the table either way.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`:   Id of the tenant owning the table.
+    /// * `table_id`: Id of the table whose access is being set.
+    /// * `readable`: Whether native get() and multiget() RPCs can read the table.
+    /// * `writable`: Whether native put(), delete() and cas() RPCs can write to the table.
+    /// * `id`:       RPC identifier.
+    /// * `stamp`:    The time-stamp at which the RPC is being sent out.
+    pub fn send_table_access(
+        &self,
+        tenant: u32,
+        table_id: u64,
+        readable: bool,
+        writable: bool,
+        id: u64,
+        stamp: u64,
+    ) {
+        let request = rpc::create_table_access_rpc(
+            &self.req_mac_header,
+            &self.req_ip_header,
+            &self.req_udp_header,
+            tenant,
+            table_id,
+            readable,
+            writable,
+            id,
+            stamp,
+            self.get_dst_port(tenant),
+        );
+
+        self.send_req(request);
+    }
+
     /// Computes the destination UDP port given a tenant identifier.
     #[inline]
     fn get_dst_port(&self, tenant: u32) -> u16 {
@@ -265,26 +1173,69 @@ impl Sender {
     /// Sends a request/packet parsed upto IP out the network interface.
     #[inline]
     fn send_req(&self, request: Packet<IpHeader, EmptyMetadata>) {
-        // Send the request out the network.
+        let request = match self.flags {
+            0 => request,
+            flags => rpc::set_rpc_flags(request, flags),
+        };
+        let request = match self.run {
+            0 => request,
+            run => rpc::set_rpc_run(request, run),
+        };
+
+        // The request holds a slot on the pacer's window until it's response is received.
+        if let Some(ref pacer) = self.pacer {
+            pacer.sent(1);
+        }
+
+        // Hold the request back on the burst, and send the burst out if it is due.
+        let now = cycles::rdtsc();
+        let burst = self.burst.borrow_mut().push(request, now);
+        if let Some(burst) = burst {
+            self.transmit(burst, now);
+        }
+    }
+
+    /// Sends a burst of requests/packets parsed upto IP out the network interface.
+    #[inline]
+    fn transmit(&self, mut burst: Vec<(Packet<IpHeader, EmptyMetadata>, u64)>, now: u64) {
+        if burst.is_empty() {
+            return;
+        }
+
+        // Requests that were held back are time-stamped now, so that the latency recorded off
+        // their responses leaves out the time they spent held.
+        let restamp = self.burst.borrow().config().max_len > 1;
+
+        // Send the requests out the network.
+        let num = burst.len() as u64;
         unsafe {
-            let mut pkts = [request.get_mbuf()];
+            let mut pkts = self.mbufs.borrow_mut();
+            pkts.clear();
+            for (request, _) in burst.drain(..) {
+                if restamp {
+                    pkts.push(rpc::set_rpc_stamp(request, now).get_mbuf());
+                } else {
+                    pkts.push(request.get_mbuf());
+                }
+            }
 
             let sent = self
                 .net_port
-                .send(&mut pkts)
+                .send(&mut pkts[..])
                 .expect("Failed to send packet!");
 
             if sent < pkts.len() as u32 {
                 warn!("Failed to send all packets!");
             }
         }
+        self.burst.borrow_mut().recycle(burst);
 
         // Update the number of requests sent out by this generator.
         let r = self.requests_sent.get();
-        if r & 0xffffff == 0 {
+        if r == 0 || (r + num) >> 24 != r >> 24 {
             info!("Sent many requests...");
         }
-        self.requests_sent.set(r + 1);
+        self.requests_sent.set(r + num);
     }
 }
 
@@ -301,6 +1252,20 @@ where
 
     // The total number of responses received.
     responses_recv: Cell<u64>,
+
+    // The number of responses received whose length fields did not match their payload.
+    length_mismatches: Cell<u64>,
+
+    // MBuf pointers raw packets are received into. Reused across calls to recv_res().
+    mbufs: RefCell<Vec<*mut MBuf>>,
+
+    // An empty vector handed back through recycle(). Holds the packets parsed by the next call
+    // to recv_res(), instead of allocating a new one.
+    spare: RefCell<Vec<Packet<UdpHeader, EmptyMetadata>>>,
+
+    // The pacer the load on every response is fed into, if congestion aware pacing is on.
+    // Shared with the pipeline's Sender.
+    pacer: Option<Arc<SharedPacer>>,
 }
 
 // Implementation of methods on Receiver.
@@ -322,19 +1287,50 @@ where
             net_port: port.clone(),
             max_rx_packets: 32,
             responses_recv: Cell::new(0),
+            length_mismatches: Cell::new(0),
+            mbufs: RefCell::new(Vec::with_capacity(32)),
+            spare: RefCell::new(Vec::new()),
+            pacer: None,
+        }
+    }
+
+    /// Makes this Receiver feed the load on every response into a pacer, and free up the slot
+    /// on it's window held by the response's request. Refer to Sender::with_pacer().
+    ///
+    /// # Arguments
+    ///
+    /// * `pacer`: The pipeline's pacer; see pacing::pipeline(). None turns pacing off.
+    pub fn with_pacer(mut self, pacer: Option<Arc<SharedPacer>>) -> Receiver<T> {
+        self.pacer = pacer;
+        self
+    }
+
+    /// Returns the number of responses received so far whose length fields did not match the
+    /// payload they were received with. Such responses are still handed out by recv_res().
+    pub fn length_mismatches(&self) -> u64 {
+        self.length_mismatches.get()
+    }
+
+    /// Hands back a vector returned by recv_res() once every packet on it has been taken off
+    /// and freed, so that the next call to recv_res() can reuse it.
+    pub fn recycle(&self, packets: Vec<Packet<UdpHeader, EmptyMetadata>>) {
+        if packets.is_empty() {
+            *self.spare.borrow_mut() = packets;
         }
     }
 
     /// Receives responses/packets from the network interface.
     #[inline]
     pub fn recv_res(&self) -> Option<Vec<Packet<UdpHeader, EmptyMetadata>>> {
-        // Allocate a vector of mutable MBuf pointers into which raw packets will be received.
-        let mut mbuf_vector = Vec::with_capacity(self.max_rx_packets as usize);
+        // A vector of mutable MBuf pointers into which raw packets will be received.
+        let mut mbuf_vector = self.mbufs.borrow_mut();
 
         // This unsafe block is needed in order to populate mbuf_vector with a bunch of pointers,
         // and subsequently manipulate these pointers. DPDK will take care of assigning these to
         // actual MBuf's.
         unsafe {
+            mbuf_vector.clear();
+            mbuf_vector.reserve(self.max_rx_packets as usize);
             mbuf_vector.set_len(self.max_rx_packets as usize);
 
             // Try to receive packets from the network port.
@@ -356,10 +1352,11 @@ where
             self.responses_recv.set(r + 1);
 
             // Clear out any dangling pointers in mbuf_vector.
-            mbuf_vector.drain(recvd..self.max_rx_packets as usize);
+            mbuf_vector.truncate(recvd);
 
-            // Vector to hold packets parsed from mbufs.
-            let mut packets = Vec::with_capacity(mbuf_vector.len());
+            // Vector to hold packets parsed from mbufs. Reuses the one last handed to recycle().
+            let mut packets = mem::replace(&mut *self.spare.borrow_mut(), Vec::new());
+            packets.reserve(recvd);
 
             // Wrap up the received Mbuf's into Packets. The refcount on the mbuf's were set by
             // DPDK, and do not need to be bumped up here. Hence, the call to
@@ -370,6 +1367,20 @@ where
                     .parse_header::<IpHeader>()
                     .parse_header::<UdpHeader>();
 
+                if !rpc::response_length_ok(packet.get_payload()) {
+                    self.length_mismatches.set(self.length_mismatches.get() + 1);
+                }
+
+                // Wireformat headers are packed, so the pointer does not have to be aligned.
+                if let Some(ref pacer) = self.pacer {
+                    let payload = packet.get_payload();
+                    if payload.len() >= mem::size_of::<RpcResponseHeader>() {
+                        let hdr = &*(payload.as_ptr() as *const RpcResponseHeader);
+                        let now = cycles::rdtsc();
+                        pacer.received(packet.get_header().src_port(), hdr, now);
+                    }
+                }
+
                 packets.push(packet);
             }
 
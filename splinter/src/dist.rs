@@ -0,0 +1,677 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Once, ONCE_INIT};
+
+use db::config::ClientConfig;
+use db::cycles;
+
+use rand::distributions::Sample;
+use rand::{Rng, SeedableRng, XorShiftRng};
+use zipf::ZipfDistribution;
+
+/// The largest number of ranks a hot set rotation permutes. Bounds the rank-to-key table so that
+/// it stays cache resident.
+pub const ROTATE_RANKS_MAX: usize = 16384;
+
+/// A distribution that keys (or tenants) are drawn from by the workload generators.
+#[derive(Clone, Debug, PartialEq)]
+pub enum KeyDistribution {
+    /// Zipfian with the given skew. 0.99 is the YCSB default.
+    Zipf(f64),
+
+    /// Every key is equally likely.
+    Uniform,
+
+    /// `hot_ops_fraction` of all draws go to the first `hot_fraction` of the keys. The rest
+    /// are spread uniformly over the remaining keys.
+    Hotspot {
+        /// Fraction of the keyspace that is hot.
+        hot_fraction: f64,
+
+        /// Fraction of draws that go to the hot keys.
+        hot_ops_fraction: f64,
+    },
+
+    /// Keys are drawn in order, wrapping around at the end of the keyspace. Useful for cache
+    /// adversarial tests.
+    Sequential,
+}
+
+/// Draws keys from a distribution. Keys are in the range [1, n], same as zipf::ZipfDistribution.
+pub trait Sampler {
+    /// Draws a key.
+    ///
+    /// # Arguments
+    ///
+    /// * `rng`: The random number generator to draw with.
+    fn sample(&mut self, rng: &mut Rng) -> usize;
+}
+
+impl KeyDistribution {
+    /// Parses a distribution from it's name in the client config. Panics if the name is unknown.
+    ///
+    /// # Arguments
+    ///
+    /// * `name`:             One of "zipf" (the default if empty), "uniform", "hotspot" and
+    ///                       "sequential".
+    /// * `skew`:             The skew of a Zipfian distribution.
+    /// * `hot_fraction`:     The fraction of keys that are hot under a hotspot distribution.
+    /// * `hot_ops_fraction`: The fraction of draws that go to hot keys.
+    pub fn parse(name: &str, skew: f64, hot_fraction: f64, hot_ops_fraction: f64) -> Self {
+        match name {
+            "" | "zipf" => KeyDistribution::Zipf(skew),
+            "uniform" => KeyDistribution::Uniform,
+            "hotspot" => KeyDistribution::Hotspot {
+                hot_fraction: hot_fraction,
+                hot_ops_fraction: hot_ops_fraction,
+            },
+            "sequential" => KeyDistribution::Sequential,
+            _ => panic!("Unknown key distribution {} in client config.", name),
+        }
+    }
+
+    /// Creates a sampler for this distribution. Done once when a workload is constructed, so
+    /// that drawing a key does not have to branch on the kind of distribution.
+    ///
+    /// # Arguments
+    ///
+    /// * `n`:         The number of keys to draw from.
+    /// * `pipeline`:  The index of the pipeline the sampler is for. Sequential samplers on
+    ///                different pipelines start at different keys so that they don't collide.
+    /// * `pipelines`: The total number of pipelines. Sequential samplers stride by this much.
+    ///
+    /// # Return
+    ///
+    /// A sampler that draws keys in the range [1, n].
+    pub fn sampler(&self, n: usize, pipeline: usize, pipelines: usize) -> Box<Sampler> {
+        assert!(n > 0);
+        match *self {
+            KeyDistribution::Zipf(skew) => Box::new(Zipf(
+                ZipfDistribution::new(n, skew).expect("Couldn't create key RNG."),
+            )),
+
+            KeyDistribution::Uniform => Box::new(Uniform(n)),
+
+            KeyDistribution::Hotspot {
+                hot_fraction,
+                hot_ops_fraction,
+            } => {
+                let hot = ((n as f64 * hot_fraction).round() as usize).max(1).min(n);
+                Box::new(Hotspot {
+                    n: n,
+                    hot: hot,
+                    hot_ops_fraction: hot_ops_fraction,
+                })
+            }
+
+            KeyDistribution::Sequential => {
+                let stride = pipelines.max(1);
+                let start = pipeline % n;
+                Box::new(Sequential {
+                    n: n,
+                    start: start,
+                    stride: stride,
+                    next: start,
+                })
+            }
+        }
+    }
+}
+
+/// Returns the distribution keys should be drawn from under a client config.
+pub fn key_distribution(config: &ClientConfig) -> KeyDistribution {
+    KeyDistribution::parse(
+        &config.key_dist,
+        config.skew,
+        config.hot_fraction,
+        config.hot_ops_fraction,
+    )
+}
+
+/// Returns a sampler that draws keys under a client config, with it's hot set rotating if the
+/// config asks for it. Pipelines on the same client share the rotation; see rotation().
+///
+/// # Arguments
+///
+/// * `config`:    Client configuration.
+/// * `pipeline`:  The index of the pipeline the sampler is for.
+/// * `pipelines`: The total number of pipelines.
+pub fn key_sampler(config: &ClientConfig, pipeline: usize, pipelines: usize) -> Box<Sampler> {
+    rotate(
+        key_distribution(config).sampler(config.n_keys, pipeline, pipelines),
+        config.n_keys,
+        rotation(config).as_ref(),
+    )
+}
+
+// The rotation shared by every pipeline on this client, set exactly once by rotation().
+static ROTATION_INIT: Once = ONCE_INIT;
+static mut ROTATION: Option<Arc<Rotation>> = None;
+
+/// Returns the hot set rotation shared by every pipeline on this client, or None if the config
+/// doesn't rotate the hot set. The first call creates it from the config; every later call
+/// returns the same rotation, no matter the config passed in.
+///
+/// # Arguments
+///
+/// * `config`: Client configuration.
+pub fn rotation(config: &ClientConfig) -> Option<Arc<Rotation>> {
+    unsafe {
+        ROTATION_INIT.call_once(|| {
+            ROTATION = Rotation::from_config(config);
+        });
+
+        ROTATION.clone()
+    }
+}
+
+/// Returns the distribution tenants should be drawn from under a client config.
+pub fn tenant_distribution(config: &ClientConfig) -> KeyDistribution {
+    KeyDistribution::parse(
+        &config.tenant_dist,
+        config.tenant_skew,
+        config.tenant_hot_fraction,
+        config.tenant_hot_ops_fraction,
+    )
+}
+
+/// Rotates the hot set of a key distribution over the course of a run. Every epoch, the keys
+/// drawn for the top ranks of the distribution are permuted, so which keys are hot changes while
+/// the shape of the distribution does not. Ranks beyond the top ones are left alone.
+///
+/// A single Rotation is shared by every pipeline on a client. Epochs are derived from it's start
+/// time or from the number of keys drawn across all pipelines, and the permutation for an epoch
+/// only depends on the epoch, so all pipelines (and clients) shift their hot set together.
+pub struct Rotation {
+    // The number of top ranks permuted every epoch.
+    ranks: usize,
+
+    // The length of an epoch in cycles. 0 if epochs are counted in draws instead.
+    period_cycles: u64,
+
+    // The length of an epoch in draws across all pipelines. 0 if epochs are timed instead.
+    period_ops: u64,
+
+    // The cycle counter when the first key was drawn. 0 until then.
+    start: AtomicU64,
+
+    // The number of keys drawn across all pipelines. Only counted if epochs are in draws.
+    ops: AtomicU64,
+
+    // The latest epoch any pipeline has moved to.
+    current: AtomicU64,
+
+    // Every epoch after the first, along with the cycles from the start of the run to when a
+    // pipeline first moved to it.
+    log: Mutex<Vec<(u64, u64)>>,
+}
+
+impl Rotation {
+    /// Creates a rotation. Exactly one of `period_cycles` and `period_ops` should be non-zero.
+    ///
+    /// # Arguments
+    ///
+    /// * `ranks`:         The number of top ranks to permute. Capped at ROTATE_RANKS_MAX.
+    /// * `period_cycles`: The length of an epoch in cycles.
+    /// * `period_ops`:    The length of an epoch in keys drawn across all pipelines.
+    pub fn new(ranks: usize, period_cycles: u64, period_ops: u64) -> Rotation {
+        Rotation {
+            ranks: ranks.min(ROTATE_RANKS_MAX),
+            period_cycles: period_cycles,
+            period_ops: period_ops,
+            start: AtomicU64::new(0),
+            ops: AtomicU64::new(0),
+            current: AtomicU64::new(0),
+            log: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Returns the rotation configured on a client, or None if `hot_rotate_ranks` is 0 or
+    /// neither an epoch length in seconds nor in operations was configured.
+    ///
+    /// # Arguments
+    ///
+    /// * `config`: Client configuration. Uses `hot_rotate_ranks`, `hot_rotate_secs` and
+    ///             `hot_rotate_ops`.
+    pub fn from_config(config: &ClientConfig) -> Option<Arc<Rotation>> {
+        let period_cycles =
+            (config.hot_rotate_secs.max(0.0) * cycles::cycles_per_second() as f64) as u64;
+        let period_ops = match period_cycles {
+            0 => config.hot_rotate_ops,
+            _ => 0,
+        };
+
+        if config.hot_rotate_ranks == 0 || (period_cycles == 0 && period_ops == 0) {
+            return None;
+        }
+
+        Some(Arc::new(Rotation::new(
+            config.hot_rotate_ranks,
+            period_cycles,
+            period_ops,
+        )))
+    }
+
+    /// Returns every epoch the run moved to after the first, along with the cycles from the start
+    /// of the run to when it did. Ordered by epoch.
+    pub fn epochs(&self) -> Vec<(u64, u64)> {
+        let mut log = self.log.lock().unwrap().clone();
+        log.sort();
+        log
+    }
+
+    // Counts a draw, and returns the epoch it falls in.
+    fn tick(&self, now: u64) -> u64 {
+        let start = match self.start.load(Ordering::Relaxed) {
+            0 => match self.start.compare_and_swap(0, now, Ordering::Relaxed) {
+                0 => now,
+                start => start,
+            },
+            start => start,
+        };
+
+        let epoch = match self.period_ops {
+            0 => now.saturating_sub(start) / self.period_cycles,
+            period => self.ops.fetch_add(1, Ordering::Relaxed) / period,
+        };
+
+        // The first pipeline to move to an epoch logs it.
+        let current = self.current.load(Ordering::Relaxed);
+        if epoch > current
+            && self
+                .current
+                .compare_and_swap(current, epoch, Ordering::Relaxed)
+                == current
+        {
+            self.log
+                .lock()
+                .unwrap()
+                .push((epoch, now.saturating_sub(start)));
+        }
+
+        epoch
+    }
+
+    // Fills in the rank-to-key table for an epoch, permuting the top `ranks` ranks. The first
+    // epoch is the identity, so a run starts out with the same hot set as an unrotated one.
+    fn permute(epoch: u64, ranks: usize, table: &mut Vec<usize>) {
+        table.clear();
+        table.extend(1..(ranks + 1));
+        if epoch == 0 {
+            return;
+        }
+
+        // Fisher-Yates, seeded by the epoch alone so that every pipeline agrees on it.
+        let mut rng = XorShiftRng::from_seed([
+            0x9e3779b9 ^ epoch as u32,
+            0x7f4a7c15 ^ (epoch >> 32) as u32,
+            0x85ebca6b,
+            0xc2b2ae35,
+        ]);
+        for i in (1..table.len()).rev() {
+            let j = (rng.next_u64() % (i as u64 + 1)) as usize;
+            table.swap(i, j);
+        }
+    }
+}
+
+/// Wraps a key sampler so that it's hot set rotates with a Rotation. Returns the sampler as is if
+/// there is no rotation.
+///
+/// # Arguments
+///
+/// * `sampler`:  The sampler to wrap. Must draw keys in the range [1, n].
+/// * `n`:        The number of keys the sampler draws from. Caps the number of ranks permuted.
+/// * `rotation`: The rotation shared by all pipelines on the client, if any.
+pub fn rotate(sampler: Box<Sampler>, n: usize, rotation: Option<&Arc<Rotation>>) -> Box<Sampler> {
+    match rotation {
+        Some(rotation) => {
+            let ranks = rotation.ranks.min(n);
+            let mut table = Vec::with_capacity(ranks);
+            Rotation::permute(0, ranks, &mut table);
+            Box::new(Rotating {
+                inner: sampler,
+                rotation: Arc::clone(rotation),
+                epoch: 0,
+                table: table,
+            })
+        }
+
+        None => sampler,
+    }
+}
+
+// Sampler whose top ranks are mapped to keys through a table that is permuted every epoch.
+struct Rotating {
+    inner: Box<Sampler>,
+    rotation: Arc<Rotation>,
+
+    // The epoch `table` was permuted for.
+    epoch: u64,
+
+    // Maps rank i + 1 to a key, for the top ranks.
+    table: Vec<usize>,
+}
+
+impl Sampler for Rotating {
+    fn sample(&mut self, rng: &mut Rng) -> usize {
+        let epoch = self.rotation.tick(cycles::rdtsc());
+        if epoch != self.epoch {
+            let ranks = self.table.len();
+            Rotation::permute(epoch, ranks, &mut self.table);
+            self.epoch = epoch;
+        }
+
+        let rank = self.inner.sample(rng);
+        match rank <= self.table.len() {
+            true => self.table[rank - 1],
+            false => rank,
+        }
+    }
+}
+
+// Zipfian sampler.
+struct Zipf(ZipfDistribution);
+
+impl Sampler for Zipf {
+    fn sample(&mut self, mut rng: &mut Rng) -> usize {
+        self.0.sample(&mut rng)
+    }
+}
+
+// Uniform sampler over [1, n].
+struct Uniform(usize);
+
+impl Sampler for Uniform {
+    fn sample(&mut self, rng: &mut Rng) -> usize {
+        1 + (rng.next_u64() % self.0 as u64) as usize
+    }
+}
+
+// Hotspot sampler. Keys [1, hot] are hot.
+struct Hotspot {
+    n: usize,
+    hot: usize,
+    hot_ops_fraction: f64,
+}
+
+impl Sampler for Hotspot {
+    fn sample(&mut self, rng: &mut Rng) -> usize {
+        // First decide between the hot and cold region, then pick a key within it.
+        if rng.next_f64() < self.hot_ops_fraction || self.hot == self.n {
+            1 + (rng.next_u64() % self.hot as u64) as usize
+        } else {
+            self.hot + 1 + (rng.next_u64() % (self.n - self.hot) as u64) as usize
+        }
+    }
+}
+
+// Sequential sampler. Visits keys start, start + stride, ... wrapping back to start.
+struct Sequential {
+    n: usize,
+    start: usize,
+    stride: usize,
+    next: usize,
+}
+
+impl Sampler for Sequential {
+    fn sample(&mut self, _rng: &mut Rng) -> usize {
+        let key = self.next;
+        self.next += self.stride;
+        if self.next >= self.n {
+            self.next = self.start;
+        }
+        key + 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{SeedableRng, XorShiftRng};
+
+    const DRAWS: usize = 300000;
+
+    // Draws DRAWS keys, returning how many times each key in [1, n] was drawn (index 0 unused).
+    fn histogram(dist: KeyDistribution, n: usize) -> Vec<usize> {
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        let mut sampler = dist.sampler(n, 0, 1);
+        let mut hist = vec![0; n + 1];
+        for _ in 0..DRAWS {
+            let k = sampler.sample(&mut rng);
+            assert!(k >= 1 && k <= n);
+            hist[k] += 1;
+        }
+        hist
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            KeyDistribution::Zipf(0.5),
+            KeyDistribution::parse("", 0.5, 0.0, 0.0)
+        );
+        assert_eq!(
+            KeyDistribution::Hotspot {
+                hot_fraction: 0.2,
+                hot_ops_fraction: 0.8,
+            },
+            KeyDistribution::parse("hotspot", 0.5, 0.2, 0.8)
+        );
+        assert_eq!(
+            KeyDistribution::Sequential,
+            KeyDistribution::parse("sequential", 0.5, 0.0, 0.0)
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_parse_unknown() {
+        KeyDistribution::parse("normal", 0.5, 0.0, 0.0);
+    }
+
+    // Tests that lower keys are drawn more often under a Zipfian distribution.
+    #[test]
+    fn test_zipf_shape() {
+        let hist = histogram(KeyDistribution::Zipf(0.99), 1000);
+        assert!(hist[1] > hist[2]);
+        assert!(hist[2] > hist[10]);
+        assert!(hist[10] > hist[1000]);
+    }
+
+    // Chi-square sanity test on the uniform distribution.
+    #[test]
+    fn test_uniform_chi_square() {
+        let n = 100;
+        let hist = histogram(KeyDistribution::Uniform, n);
+        let expected = DRAWS as f64 / n as f64;
+        let chi: f64 = hist[1..]
+            .iter()
+            .map(|&o| (o as f64 - expected).powi(2) / expected)
+            .sum();
+
+        // The critical value for 99 degrees of freedom at p = 0.001 is ~148.
+        assert!(chi < 148.0, "chi-square statistic {}", chi);
+    }
+
+    // Tests that the hot region gets the configured fraction of draws, and that draws are
+    // spread over both regions.
+    #[test]
+    fn test_hotspot_fraction() {
+        let n = 1000;
+        let hist = histogram(
+            KeyDistribution::Hotspot {
+                hot_fraction: 0.1,
+                hot_ops_fraction: 0.9,
+            },
+            n,
+        );
+
+        let hot: usize = hist[1..101].iter().sum();
+        let frac = hot as f64 / DRAWS as f64;
+        assert!((frac - 0.9).abs() < 0.01, "hot fraction {}", frac);
+
+        // Every hot key gets ~2700 draws, and every cold key gets ~33.
+        assert!(hist[1..101].iter().all(|&c| c > 2000));
+        assert!(hist[101..].iter().all(|&c| c > 0 && c < 100));
+    }
+
+    // Tests that sequential samplers on different pipelines together visit every key exactly
+    // once per wrap, and that each wraps back to where it started.
+    #[test]
+    fn test_sequential_covers_keys() {
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        let (n, pipelines) = (10, 3);
+
+        let mut seen = vec![0; n + 1];
+        for pipeline in 0..pipelines {
+            let mut sampler = KeyDistribution::Sequential.sampler(n, pipeline, pipelines);
+            let wrap: Vec<usize> = (pipeline..n)
+                .filter(|k| (k - pipeline) % pipelines == 0)
+                .map(|k| k + 1)
+                .collect();
+
+            let first: Vec<usize> = (0..wrap.len()).map(|_| sampler.sample(&mut rng)).collect();
+            let second: Vec<usize> = (0..wrap.len()).map(|_| sampler.sample(&mut rng)).collect();
+            assert_eq!(wrap, first);
+            assert_eq!(wrap, second);
+
+            for k in first.into_iter() {
+                seen[k] += 1;
+            }
+        }
+
+        assert_eq!(vec![1; n], seen[1..].to_vec());
+    }
+
+    // Sampler that always draws the same rank.
+    struct Fixed(usize);
+
+    impl Sampler for Fixed {
+        fn sample(&mut self, _rng: &mut Rng) -> usize {
+            self.0
+        }
+    }
+
+    // Tests that rotating the hot set changes which keys are hot, but leaves the shape of the
+    // distribution and the ranks beyond the rotated ones alone.
+    #[test]
+    fn test_rotate_shape() {
+        let (n, ranks) = (1000, 100);
+        let rotation = Arc::new(Rotation::new(ranks, 0, DRAWS as u64 / 2));
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        let mut sampler = rotate(
+            KeyDistribution::Zipf(0.99).sampler(n, 0, 1),
+            n,
+            Some(&rotation),
+        );
+
+        let mut hists = vec![vec![0usize; n + 1]; 2];
+        for i in 0..DRAWS {
+            let k = sampler.sample(&mut rng);
+            assert!(k >= 1 && k <= n);
+            hists[i / (DRAWS / 2)][k] += 1;
+        }
+
+        // Before the rotation key 1 is the hottest. After it, most of the rotated keys are drawn
+        // at a very different rate.
+        assert_eq!(1, (1..(n + 1)).max_by_key(|&k| hists[0][k]).unwrap());
+        let moved = (1..(ranks + 1))
+            .filter(|&k| hists[0][k] > 2 * hists[1][k] || hists[1][k] > 2 * hists[0][k])
+            .count();
+        assert!(moved > ranks / 4, "only {} keys moved", moved);
+
+        // The most popular keys get the same share of draws in both epochs.
+        let top: Vec<usize> = hists
+            .iter()
+            .map(|h| {
+                let mut c = h[1..].to_vec();
+                c.sort_by(|a, b| b.cmp(a));
+                c[..10].iter().sum()
+            })
+            .collect();
+        let diff = (top[0] as f64 - top[1] as f64).abs() / top[0] as f64;
+        assert!(diff < 0.05, "top keys differ by {}", diff);
+
+        // Keys beyond the rotated ranks are untouched.
+        let cold: Vec<usize> = hists
+            .iter()
+            .map(|h| h[(ranks + 1)..].iter().sum())
+            .collect();
+        let diff = (cold[0] as f64 - cold[1] as f64).abs() / cold[0] as f64;
+        assert!(diff < 0.05, "cold keys differ by {}", diff);
+
+        let epochs: Vec<u64> = rotation.epochs().iter().map(|e| e.0).collect();
+        assert_eq!(vec![1], epochs);
+    }
+
+    // Tests that pipelines sharing a rotation counted in draws always agree on the hot set, and
+    // that every epoch is logged once.
+    #[test]
+    fn test_rotate_coherent_ops() {
+        let (pipelines, period) = (4, 100);
+        let rotation = Arc::new(Rotation::new(16, 0, period));
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        let mut samplers: Vec<Box<Sampler>> = (0..pipelines)
+            .map(|_| rotate(Box::new(Fixed(1)), 1000, Some(&rotation)))
+            .collect();
+
+        let mut keys = Vec::new();
+        for _ in 0..1000 {
+            let round: Vec<usize> = samplers.iter_mut().map(|s| s.sample(&mut rng)).collect();
+            assert!(round.iter().all(|&k| k == round[0]), "{:?}", round);
+            keys.push(round[0]);
+        }
+
+        // The hottest key starts out at 1 and moves around within the rotated ranks.
+        assert_eq!(1, keys[0]);
+        assert!(keys.iter().all(|&k| k >= 1 && k <= 16));
+        assert!(keys.iter().any(|&k| k != 1));
+
+        let epochs: Vec<u64> = rotation.epochs().iter().map(|e| e.0).collect();
+        assert_eq!((1..40).collect::<Vec<u64>>(), epochs);
+    }
+
+    // Tests that timed epochs are counted from the first draw, and logged relative to it.
+    #[test]
+    fn test_rotate_timed() {
+        let rotation = Rotation::new(16, 1000, 0);
+        assert_eq!(0, rotation.tick(5000));
+        assert_eq!(0, rotation.tick(5999));
+        assert_eq!(1, rotation.tick(6000));
+        assert_eq!(1, rotation.tick(6500));
+        assert_eq!(3, rotation.tick(8500));
+        assert_eq!(vec![(1, 1000), (3, 3500)], rotation.epochs());
+    }
+
+    // Tests that the permutation only depends on the epoch, and that the first epoch doesn't
+    // permute at all.
+    #[test]
+    fn test_rotate_permute() {
+        let (mut a, mut b) = (Vec::new(), Vec::new());
+        Rotation::permute(0, 8, &mut a);
+        assert_eq!((1..9).collect::<Vec<usize>>(), a);
+
+        Rotation::permute(7, 8, &mut a);
+        Rotation::permute(7, 8, &mut b);
+        assert_eq!(a, b);
+
+        let mut sorted = a.clone();
+        sorted.sort();
+        assert_eq!((1..9).collect::<Vec<usize>>(), sorted);
+    }
+}
@@ -0,0 +1,764 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Once, ONCE_INIT};
+
+use db::config::ClientConfig;
+use db::cycles;
+
+/// How long a request waits for it's response before it is given up on, if the client config
+/// does not say otherwise. In microseconds.
+pub const DEFAULT_FAILOVER_TIMEOUT_US: u64 = 100_000;
+
+/// The number of timeouts in a row after which the primary is declared dead, if the client
+/// config does not say otherwise.
+pub const DEFAULT_FAILOVER_THRESHOLD: u32 = 3;
+
+/// The epoch a client is in once it has failed over. There is a single backup, so a client
+/// fails over atmost once; epoch 0 is the primary.
+pub const BACKUP_EPOCH: u64 = 1;
+
+/// When a client gives up on the server it is talking to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FailoverConfig {
+    /// How long a request (or an echo() probe) waits for it's response, in cycles.
+    pub timeout: u64,
+
+    /// The number of timeouts in a row, with nothing answered in between, after which the
+    /// server is declared dead.
+    pub threshold: u32,
+}
+
+impl FailoverConfig {
+    /// Returns the failover settings of a client, or None if it has no backup to fail over to.
+    ///
+    /// # Arguments
+    ///
+    /// * `config`: Client configuration. Uses `backup_server_ip_address`, `failover_timeout_us`
+    ///             and `failover_threshold`.
+    /// * `hz`:     The rate at which the client's cycle counter ticks.
+    pub fn from_config(config: &ClientConfig, hz: u64) -> Option<FailoverConfig> {
+        backup(config)?;
+
+        let timeout_us = match config.failover_timeout_us {
+            0 => DEFAULT_FAILOVER_TIMEOUT_US,
+            us => us,
+        };
+        let threshold = match config.failover_threshold {
+            0 => DEFAULT_FAILOVER_THRESHOLD,
+            n => n,
+        };
+
+        Some(FailoverConfig {
+            timeout: (timeout_us as f64 * hz as f64 / 1e6) as u64,
+            threshold: threshold,
+        })
+    }
+}
+
+/// Returns the address and first UDP port of the backup server configured on a client, or None
+/// if `backup_server_ip_address` is empty. Panics if it is malformed.
+pub fn backup(config: &ClientConfig) -> Option<(Ipv4Addr, u16)> {
+    if config.backup_server_ip_address.is_empty() {
+        return None;
+    }
+
+    let ip = Ipv4Addr::from_str(&config.backup_server_ip_address)
+        .expect("Malformed backup_server_ip_address in client config.");
+    let port = match config.backup_udp_server_port {
+        0 => config.udp_server_port,
+        port => port,
+    };
+    Some((ip, port))
+}
+
+/// A failover, as seen across every pipeline on a client. All times are in cycles.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Switch {
+    /// The epoch the client moved to.
+    pub epoch: u64,
+
+    /// The time from the start of the run to when the server was declared dead.
+    pub declared: u64,
+
+    /// The time from the last response the declaring pipeline received to the declaration.
+    pub detect: u64,
+
+    /// The time from the last response the declaring pipeline received to the first response
+    /// any pipeline received from the new server. 0 if none was received.
+    pub gap: u64,
+
+    // The time from the start of the run to the last response received before the
+    // declaration. Used to compute the gap once the first response arrives.
+    last: u64,
+}
+
+/// Decides when a client fails over, shared by every pipeline on it so that they all move to
+/// the backup together instead of splitting the workload between servers.
+///
+/// The server each pipeline talks to is named by an epoch. The first pipeline to give up on the
+/// server of the current epoch moves the shared epoch forward and logs the failover; every other
+/// pipeline notices the new epoch on it's next Monitor::poll() and follows.
+pub struct Failover {
+    // When to give up on a server.
+    config: FailoverConfig,
+
+    // The cycle counter when the first pipeline started. 0 until then.
+    start: AtomicU64,
+
+    // The epoch every pipeline should be in.
+    epoch: AtomicU64,
+
+    // Every failover, in the order they happened.
+    log: Mutex<Vec<Switch>>,
+}
+
+impl Failover {
+    /// Creates a failover decision shared between pipelines, starting on the primary.
+    ///
+    /// # Arguments
+    ///
+    /// * `config`: When to give up on a server.
+    pub fn new(config: FailoverConfig) -> Failover {
+        Failover {
+            config: config,
+            start: AtomicU64::new(0),
+            epoch: AtomicU64::new(0),
+            log: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Returns the failover decision configured on a client, or None if it has no backup.
+    pub fn from_config(config: &ClientConfig) -> Option<Arc<Failover>> {
+        FailoverConfig::from_config(config, cycles::cycles_per_second())
+            .map(|config| Arc::new(Failover::new(config)))
+    }
+
+    /// Returns when to give up on a server.
+    pub fn config(&self) -> FailoverConfig {
+        self.config
+    }
+
+    /// Returns the epoch every pipeline should be in.
+    pub fn epoch(&self) -> u64 {
+        self.epoch.load(Ordering::Acquire)
+    }
+
+    /// Returns every failover so far, in the order they happened.
+    pub fn switches(&self) -> Vec<Switch> {
+        self.log.lock().unwrap().clone()
+    }
+
+    // Marks the start of the run, if no pipeline did already.
+    fn begin(&self, now: u64) {
+        self.start.compare_and_swap(0, now, Ordering::Relaxed);
+    }
+
+    // Returns the cycles from the start of the run to `now`.
+    fn since_start(&self, now: u64) -> u64 {
+        now.saturating_sub(self.start.load(Ordering::Relaxed))
+    }
+
+    // Declares the server of an epoch dead, and returns the epoch every pipeline should move
+    // to. Only the first pipeline to declare an epoch dead logs the failover.
+    fn declare(&self, epoch: u64, now: u64, last: u64) -> u64 {
+        let next = (epoch + 1).min(BACKUP_EPOCH);
+        if next > epoch && self.epoch.compare_and_swap(epoch, next, Ordering::AcqRel) == epoch {
+            self.log.lock().unwrap().push(Switch {
+                epoch: next,
+                declared: self.since_start(now),
+                detect: now.saturating_sub(last),
+                gap: 0,
+                last: self.since_start(last),
+            });
+        }
+
+        self.epoch()
+    }
+
+    // Records the first response received from the server of an epoch.
+    fn resumed(&self, epoch: u64, now: u64) {
+        let at = self.since_start(now);
+        let mut log = self.log.lock().unwrap();
+        if let Some(switch) = log.iter_mut().find(|s| s.epoch == epoch && s.gap == 0) {
+            switch.gap = at.saturating_sub(switch.last).max(1);
+        }
+    }
+}
+
+// The failover decision shared by every pipeline on this client, set exactly once by
+// failover().
+static FAILOVER_INIT: Once = ONCE_INIT;
+static mut FAILOVER: Option<Arc<Failover>> = None;
+
+/// Returns the failover decision shared by every pipeline on this client, or None if the config
+/// has no backup. The first call creates it from the config; every later call returns the same
+/// one, no matter the config passed in.
+///
+/// # Arguments
+///
+/// * `config`: Client configuration.
+pub fn failover(config: &ClientConfig) -> Option<Arc<Failover>> {
+    unsafe {
+        FAILOVER_INIT.call_once(|| {
+            FAILOVER = Failover::from_config(config);
+        });
+
+        FAILOVER.clone()
+    }
+}
+
+/// What a pipeline should do after polling it's Monitor.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    /// Carry on.
+    Continue,
+
+    /// Send an echo() to the server, and pass it's id to Monitor::probe_sent(). The server
+    /// has not answered anything for a while.
+    Probe,
+
+    /// Every outstanding request was abandoned. Send requests to the server of this epoch from
+    /// now on, after checking that it serves the workload.
+    Switch(u64),
+}
+
+/// What a response received by a pipeline was to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Reply {
+    /// An outstanding request. It should be handled as usual.
+    Request,
+
+    /// The echo() probe. It is not a response to the workload, and should not be counted.
+    Probe,
+
+    /// A request that timed out or was abandoned, and was already counted as lost. It should
+    /// be dropped.
+    Stale,
+}
+
+/// The result of polling a Monitor.
+#[derive(Debug, PartialEq)]
+pub struct Poll {
+    /// The ids of requests given up on since the last poll. Each must be completed wherever
+    /// the pipeline is waiting on it (ex: order::KeyOrder), and counted as lost; none will be
+    /// answered.
+    pub lost: Vec<u64>,
+
+    /// What the pipeline should do next.
+    pub action: Action,
+}
+
+/// Watches the requests a single pipeline has outstanding, to tell when the server stopped
+/// answering.
+///
+/// A request that goes unanswered for a timeout is given up on. A timeout passing with requests
+/// outstanding and nothing answered is a miss; after each miss the server is probed with an
+/// echo(), so that misses keep being counted even once every request has been given up on. A
+/// response to anything clears the misses. Once they reach the threshold the server is declared
+/// dead, and the pipeline abandons everything outstanding and moves to the next server, along
+/// with every other pipeline sharing the Failover.
+///
+/// Responses that arrive after their request was given up on do not count as the server being
+/// alive, so the timeout should be well over the latency the server is expected to respond in.
+pub struct Monitor {
+    // The decision shared with the other pipelines.
+    shared: Arc<Failover>,
+
+    // The epoch of the server this pipeline is talking to.
+    epoch: u64,
+
+    // Outstanding requests, along with the time they were sent out.
+    inflight: HashMap<u64, u64>,
+
+    // The outstanding echo() probe, along with the time it was sent out.
+    probe: Option<(u64, u64)>,
+
+    // The number of timeouts in a row with nothing answered.
+    misses: u32,
+
+    // When the server was last heard from, or when the current miss started.
+    quiet: u64,
+
+    // When the server was last heard from.
+    last: u64,
+
+    // Set once a response was received from the server of this epoch.
+    resumed: bool,
+
+    // The number of requests given up on after timing out.
+    timeouts: u64,
+
+    // The number of requests abandoned on a failover.
+    abandoned: u64,
+}
+
+impl Monitor {
+    /// Creates a monitor for a pipeline starting out on the server every pipeline is in.
+    ///
+    /// # Arguments
+    ///
+    /// * `shared`: The decision shared with the other pipelines.
+    /// * `now`:    The current time-stamp in cycles.
+    pub fn new(shared: Arc<Failover>, now: u64) -> Monitor {
+        shared.begin(now);
+        Monitor {
+            epoch: shared.epoch(),
+            shared: shared,
+            inflight: HashMap::new(),
+            probe: None,
+            misses: 0,
+            quiet: now,
+            last: now,
+            resumed: true,
+            timeouts: 0,
+            abandoned: 0,
+        }
+    }
+
+    /// Returns the epoch of the server this pipeline is talking to.
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// Records that a request was sent out.
+    ///
+    /// # Arguments
+    ///
+    /// * `id`:  The id of the request.
+    /// * `now`: The current time-stamp in cycles.
+    pub fn sent(&mut self, id: u64, now: u64) {
+        // The server can't be missed while it has nothing to answer.
+        if self.idle() {
+            self.quiet = now;
+        }
+        self.inflight.insert(id, now);
+    }
+
+    /// Records that the echo() asked for by Action::Probe was sent out.
+    ///
+    /// # Arguments
+    ///
+    /// * `id`:  The id of the echo().
+    /// * `now`: The current time-stamp in cycles.
+    pub fn probe_sent(&mut self, id: u64, now: u64) {
+        if self.idle() {
+            self.quiet = now;
+        }
+        self.probe = Some((id, now));
+    }
+
+    /// Records that a response was received.
+    ///
+    /// # Arguments
+    ///
+    /// * `id`:  The request id on the response.
+    /// * `now`: The current time-stamp in cycles.
+    ///
+    /// # Return
+    ///
+    /// What the response was to.
+    pub fn received(&mut self, id: u64, now: u64) -> Reply {
+        let reply = match self.probe {
+            Some((probe, _)) if probe == id => {
+                self.probe = None;
+                Reply::Probe
+            }
+            _ => match self.inflight.remove(&id) {
+                Some(_) => Reply::Request,
+                None => return Reply::Stale,
+            },
+        };
+
+        self.misses = 0;
+        self.quiet = now;
+        self.last = now;
+        if !self.resumed {
+            self.resumed = true;
+            self.shared.resumed(self.epoch, now);
+        }
+        reply
+    }
+
+    /// Gives up on requests that timed out, and decides whether to probe or leave the server.
+    /// Should be called regularly, whether or not responses are arriving.
+    ///
+    /// # Arguments
+    ///
+    /// * `now`: The current time-stamp in cycles.
+    pub fn poll(&mut self, now: u64) -> Poll {
+        let timeout = self.shared.config().timeout;
+
+        // Another pipeline may have given up on the server already.
+        let epoch = self.shared.epoch();
+        if epoch > self.epoch {
+            return self.switch(epoch, now);
+        }
+
+        // A request timing out is a miss in itself, so whether anything was outstanding is
+        // checked before giving up on it.
+        let idle = self.idle();
+        let mut lost: Vec<u64> = self
+            .inflight
+            .iter()
+            .filter(|&(_, &sent)| now.saturating_sub(sent) >= timeout)
+            .map(|(&id, _)| id)
+            .collect();
+        lost.sort();
+        for id in lost.iter() {
+            self.inflight.remove(id);
+        }
+        self.timeouts += lost.len() as u64;
+
+        if idle || now.saturating_sub(self.quiet) < timeout {
+            return Poll {
+                lost: lost,
+                action: Action::Continue,
+            };
+        }
+
+        self.misses += 1;
+        self.quiet = now;
+        self.probe = None;
+
+        if self.misses >= self.shared.config().threshold {
+            let epoch = self.shared.declare(self.epoch, now, self.last);
+            if epoch > self.epoch {
+                let mut poll = self.switch(epoch, now);
+                lost.append(&mut poll.lost);
+                poll.lost = lost;
+                return poll;
+            }
+        }
+
+        Poll {
+            lost: lost,
+            action: Action::Probe,
+        }
+    }
+
+    /// Returns the number of requests given up on after timing out.
+    pub fn timeouts(&self) -> u64 {
+        self.timeouts
+    }
+
+    /// Returns the number of requests abandoned on a failover.
+    pub fn abandoned(&self) -> u64 {
+        self.abandoned
+    }
+
+    // Returns true if nothing is outstanding at the server.
+    fn idle(&self) -> bool {
+        self.inflight.is_empty() && self.probe.is_none()
+    }
+
+    // Abandons everything outstanding, and moves to the server of an epoch.
+    fn switch(&mut self, epoch: u64, now: u64) -> Poll {
+        let mut lost: Vec<u64> = self.inflight.drain().map(|(id, _)| id).collect();
+        lost.sort();
+        self.abandoned += lost.len() as u64;
+
+        self.epoch = epoch;
+        self.probe = None;
+        self.misses = 0;
+        self.quiet = now;
+        self.resumed = false;
+
+        Poll {
+            lost: lost,
+            action: Action::Switch(epoch),
+        }
+    }
+}
+
+// This module contains unit tests for Failover and Monitor.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::VecDeque;
+
+    const TIMEOUT: u64 = 1000;
+    const THRESHOLD: u32 = 3;
+
+    // The time a stub server takes to answer a request.
+    const LATENCY: u64 = 10;
+
+    fn shared() -> Arc<Failover> {
+        Arc::new(Failover::new(FailoverConfig {
+            timeout: TIMEOUT,
+            threshold: THRESHOLD,
+        }))
+    }
+
+    // A stand-in for a server. Answers every request after LATENCY, unless it went silent.
+    struct Stub {
+        alive: bool,
+        pending: VecDeque<(u64, u64)>,
+        served: u64,
+    }
+
+    impl Stub {
+        fn new() -> Stub {
+            Stub {
+                alive: true,
+                pending: VecDeque::new(),
+                served: 0,
+            }
+        }
+
+        // Stops answering, dropping the requests that were not answered yet.
+        fn silence(&mut self) {
+            self.alive = false;
+            self.pending.clear();
+        }
+
+        fn send(&mut self, id: u64, now: u64) {
+            if self.alive {
+                self.pending.push_back((id, now + LATENCY));
+            }
+        }
+
+        // Returns the ids of the requests answered by `now`.
+        fn recv(&mut self, now: u64) -> Vec<u64> {
+            let mut ids = Vec::new();
+            while self.pending.front().map_or(false, |&(_, at)| at <= now) {
+                ids.push(self.pending.pop_front().unwrap().0);
+            }
+            self.served += ids.len() as u64;
+            ids
+        }
+    }
+
+    // A closed loop pipeline over a pair of stub servers, primary and backup.
+    struct Pipeline {
+        monitor: Monitor,
+        servers: [Stub; 2],
+        next: u64,
+        sent: u64,
+        window: usize,
+        outstanding: usize,
+        recvd: u64,
+        lost: u64,
+        heard: u64,
+        switched: Option<u64>,
+        probes: u64,
+    }
+
+    impl Pipeline {
+        fn new(shared: &Arc<Failover>, pipeline: u64, window: usize) -> Pipeline {
+            Pipeline {
+                monitor: Monitor::new(shared.clone(), 0),
+                servers: [Stub::new(), Stub::new()],
+                next: pipeline << 32,
+                sent: 0,
+                window: window,
+                outstanding: 0,
+                recvd: 0,
+                lost: 0,
+                heard: 0,
+                switched: None,
+                probes: 0,
+            }
+        }
+
+        fn send(&mut self, now: u64) -> u64 {
+            self.next += 1;
+            let server = self.monitor.epoch() as usize;
+            self.servers[server].send(self.next, now);
+            self.next
+        }
+
+        fn tick(&mut self, now: u64) {
+            while self.outstanding < self.window {
+                let id = self.send(now);
+                self.monitor.sent(id, now);
+                self.sent += 1;
+                self.outstanding += 1;
+            }
+
+            for server in 0..2 {
+                for id in self.servers[server].recv(now) {
+                    match self.monitor.received(id, now) {
+                        Reply::Request => {
+                            self.recvd += 1;
+                            self.outstanding -= 1;
+                            self.heard = now;
+                        }
+                        Reply::Probe => self.heard = now,
+                        Reply::Stale => {}
+                    }
+                }
+            }
+
+            let poll = self.monitor.poll(now);
+            self.lost += poll.lost.len() as u64;
+            self.outstanding -= poll.lost.len();
+            match poll.action {
+                Action::Continue => {}
+                Action::Probe => {
+                    let id = self.send(now);
+                    self.monitor.probe_sent(id, now);
+                    self.probes += 1;
+                }
+                Action::Switch(epoch) => {
+                    assert_eq!(BACKUP_EPOCH, epoch);
+                    self.switched = Some(now);
+                }
+            }
+        }
+    }
+
+    // Tests that a pipeline answered on time never fails over.
+    #[test]
+    fn test_failover_healthy() {
+        let shared = shared();
+        let mut pipeline = Pipeline::new(&shared, 0, 8);
+        for now in 0..100 * TIMEOUT {
+            pipeline.tick(now);
+        }
+
+        assert_eq!(None, pipeline.switched);
+        assert_eq!(0, pipeline.lost);
+        assert_eq!(0, pipeline.probes);
+        assert_eq!(0, shared.epoch());
+        assert!(shared.switches().is_empty());
+        assert!(pipeline.recvd > 0);
+    }
+
+    // Tests that a primary that goes silent is declared dead after exactly `threshold`
+    // timeouts, and that every request outstanding at it is counted lost exactly once.
+    #[test]
+    fn test_failover_detect() {
+        let shared = shared();
+        let mut pipeline = Pipeline::new(&shared, 0, 8);
+        let silent = 10 * TIMEOUT;
+        for now in 0..silent {
+            pipeline.tick(now);
+        }
+
+        pipeline.servers[0].silence();
+        let last = pipeline.heard;
+        let mut now = silent;
+        while pipeline.switched.is_none() {
+            pipeline.tick(now);
+            now += 1;
+            assert!(now < silent + 10 * TIMEOUT, "Never failed over");
+        }
+
+        // The window is refilled on the tick after the last response, and the misses count from
+        // there; the server can't be missed while it has nothing to answer.
+        let declared = pipeline.switched.unwrap();
+        assert_eq!(last + 1 + THRESHOLD as u64 * TIMEOUT, declared);
+        assert_eq!(THRESHOLD as u64 - 1, pipeline.probes);
+
+        // Every request the primary left unanswered timed out, and nothing went unaccounted.
+        let monitor = &pipeline.monitor;
+        assert_eq!(pipeline.lost, monitor.timeouts() + monitor.abandoned());
+        assert_eq!(pipeline.sent, pipeline.recvd + pipeline.lost);
+        assert!(pipeline.lost >= 8);
+
+        let switches = shared.switches();
+        assert_eq!(1, switches.len());
+        assert_eq!(BACKUP_EPOCH, switches[0].epoch);
+        assert_eq!(declared, switches[0].declared);
+        assert_eq!(declared - last, switches[0].detect);
+        assert_eq!(0, switches[0].gap);
+    }
+
+    // Tests that pipelines follow the first one to give up on the primary, abandoning what
+    // they had outstanding, and that the run resumes against the backup.
+    #[test]
+    fn test_failover_coordinated() {
+        let shared = shared();
+
+        // Pipeline 1 runs a much smaller window, so it only notices the silence on it's own
+        // later than pipeline 0 does.
+        let mut pipelines = vec![Pipeline::new(&shared, 0, 8), Pipeline::new(&shared, 1, 1)];
+        let silent = 5 * TIMEOUT;
+        for now in 0..silent {
+            for p in pipelines.iter_mut() {
+                p.tick(now);
+            }
+        }
+
+        // The primary goes silent for pipeline 0 first; pipeline 1 keeps being answered.
+        pipelines[0].servers[0].silence();
+        let before = pipelines[1].servers[0].served;
+        let mut now = silent;
+        while pipelines.iter().any(|p| p.switched.is_none()) {
+            for p in pipelines.iter_mut() {
+                p.tick(now);
+            }
+            now += 1;
+            assert!(now < silent + 10 * TIMEOUT, "Pipelines never failed over");
+        }
+
+        // Pipeline 1 never timed out, but moved on the very next poll after pipeline 0.
+        let first = pipelines[0].switched.unwrap();
+        assert_eq!(first, pipelines[1].switched.unwrap());
+        assert_eq!(0, pipelines[1].monitor.timeouts());
+        assert_eq!(1, pipelines[1].monitor.abandoned());
+        assert!(pipelines[1].servers[0].served > before);
+        assert_eq!(1, shared.switches().len());
+
+        // Both pipelines now run against the backup, and nothing more is lost.
+        let resumed = now;
+        let lost: Vec<u64> = pipelines.iter().map(|p| p.lost).collect();
+        for now in resumed..resumed + 10 * TIMEOUT {
+            for p in pipelines.iter_mut() {
+                p.tick(now);
+            }
+        }
+
+        for (p, lost) in pipelines.iter().zip(lost.into_iter()) {
+            assert_eq!(lost, p.lost);
+            assert_eq!(BACKUP_EPOCH, p.monitor.epoch());
+            assert!(p.servers[1].served > 0);
+            assert_eq!(p.sent, p.recvd + p.lost + p.outstanding as u64);
+        }
+
+        // The gap runs from the last response off the primary to the first off the backup.
+        // Requests go out to the backup on the tick after the switch.
+        let switch = shared.switches()[0];
+        assert_eq!(switch.detect + 1 + LATENCY, switch.gap);
+    }
+
+    // Tests that once on the backup there is nowhere left to go, so a silent backup only has
+    // it's requests time out instead of wedging the pipeline.
+    #[test]
+    fn test_failover_backup_silent() {
+        let shared = shared();
+        let mut pipeline = Pipeline::new(&shared, 0, 4);
+        pipeline.servers[0].silence();
+        pipeline.servers[1].silence();
+
+        for now in 0..20 * TIMEOUT {
+            pipeline.tick(now);
+        }
+
+        assert_eq!(BACKUP_EPOCH, shared.epoch());
+        assert_eq!(1, shared.switches().len());
+        assert_eq!(0, pipeline.recvd);
+
+        // The window kept being refilled as requests timed out.
+        assert!(pipeline.monitor.timeouts() > 4 * 10);
+        assert_eq!(pipeline.sent, pipeline.lost + pipeline.outstanding as u64);
+    }
+}
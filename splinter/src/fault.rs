@@ -0,0 +1,548 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use std::collections::HashMap;
+use std::mem::{size_of, transmute};
+
+use db::config::ClientConfig;
+use db::wireformat::*;
+
+use rand::{self, Rng, SeedableRng, XorShiftRng};
+
+/// The tenant id requests are redirected to by Fault::BadTenant. No workload populates it.
+pub const BAD_TENANT: u32 = u32::max_value();
+
+/// The table id requests are redirected to by Fault::BadTable. No workload populates it.
+pub const BAD_TABLE: u64 = u64::max_value();
+
+// Statuses a request with a fault injected into it can legitimately complete with.
+const MALFORMED: &[RpcStatus] = &[RpcStatus::StatusMalformedRequest];
+const BAD_NAME: &[RpcStatus] = &[
+    RpcStatus::StatusMalformedRequest,
+    RpcStatus::StatusInvalidExtension,
+];
+const NO_TENANT: &[RpcStatus] = &[RpcStatus::StatusTenantDoesNotExist];
+const NO_TENANT_EXT: &[RpcStatus] = &[
+    RpcStatus::StatusTenantDoesNotExist,
+    RpcStatus::StatusInvalidExtension,
+];
+const NO_TABLE: &[RpcStatus] = &[RpcStatus::StatusTableDoesNotExist];
+
+/// A way of corrupting a request after it has been encoded. A fault is only applied to a request
+/// if the server is guaranteed to reject the corrupted request, so that injected requests never
+/// modify data. Requests carry no deadline, so there is no fault that expires one.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Fault {
+    /// Drops these many bytes off the end of the payload. Only applied to a put if this cuts
+    /// into it's key.
+    Truncate(usize),
+
+    /// Claims a longer key (or extension name) than the payload holds.
+    KeyLength,
+
+    /// Overwrites the extension name on an invoke with bytes that are not valid UTF-8.
+    GarbleName,
+
+    /// Redirects the request to BAD_TENANT.
+    BadTenant,
+
+    /// Redirects a get, put, or multiget to BAD_TABLE.
+    BadTable,
+}
+
+impl Fault {
+    /// Parses a fault formatted as "truncate:N", "key_length", "garble_name", "bad_tenant", or
+    /// "bad_table".
+    pub fn parse(spec: &str) -> Option<Fault> {
+        let mut parts = spec.trim().split(':');
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some("truncate"), Some(n), None) => n.parse().ok().map(|n| Fault::Truncate(n)),
+            (Some("key_length"), None, None) => Some(Fault::KeyLength),
+            (Some("garble_name"), None, None) => Some(Fault::GarbleName),
+            (Some("bad_tenant"), None, None) => Some(Fault::BadTenant),
+            (Some("bad_table"), None, None) => Some(Fault::BadTable),
+            _ => None,
+        }
+    }
+
+    /// Parses a comma separated list of faults. Refer to parse().
+    pub fn parse_list(spec: &str) -> Option<Vec<Fault>> {
+        spec.split(',')
+            .filter(|s| !s.trim().is_empty())
+            .map(|s| Fault::parse(s))
+            .collect()
+    }
+
+    /// Corrupts an encoded request.
+    ///
+    /// # Arguments
+    ///
+    /// * `req`: The request, starting at the RPC request header. Refer to udp::encode_get().
+    /// * `rng`: Source of the bytes garbled extension names are made up of.
+    ///
+    /// # Return
+    ///
+    /// True if the request was corrupted. False if the fault does not apply to the request, in
+    /// which case it is left untouched.
+    pub fn apply<R: Rng>(&self, req: &mut Vec<u8>, rng: &mut R) -> bool {
+        let hdr_len = match opcode(req) {
+            Some(OpCode::SandstormGetRpc) => size_of::<GetRequest>(),
+            Some(OpCode::SandstormPutRpc) => size_of::<PutRequest>(),
+            Some(OpCode::SandstormMultiGetRpc) => size_of::<MultiGetRequest>(),
+            Some(OpCode::SandstormInvokeRpc) => size_of::<InvokeRequest>(),
+            _ => return false,
+        };
+        if req.len() < hdr_len {
+            return false;
+        }
+        let payload = req.len() - hdr_len;
+
+        // Wireformat headers are packed, so the pointers do not have to be aligned.
+        let ptr = req.as_mut_ptr();
+        match (self, opcode(req).unwrap()) {
+            (&Fault::Truncate(n), op) => {
+                let n = n.min(payload);
+                let cuts_key = match op {
+                    OpCode::SandstormPutRpc => {
+                        let hdr = unsafe { &*(ptr as *const PutRequest) };
+                        payload - n < hdr.key_length() as usize
+                    }
+                    _ => n > 0,
+                };
+                if cuts_key {
+                    req.truncate(hdr_len + payload - n);
+                }
+                cuts_key
+            }
+
+            (&Fault::KeyLength, OpCode::SandstormGetRpc) if payload < u16::max_value() as usize => {
+                unsafe { (*(ptr as *mut GetRequest)).set_key_length(payload as u16 + 1) };
+                true
+            }
+
+            (&Fault::KeyLength, OpCode::SandstormPutRpc) if payload < u16::max_value() as usize => {
+                unsafe { (*(ptr as *mut PutRequest)).set_key_length(payload as u16 + 1) };
+                true
+            }
+
+            (&Fault::KeyLength, OpCode::SandstormMultiGetRpc) => {
+                let hdr = unsafe { &mut *(ptr as *mut MultiGetRequest) };
+                if hdr.key_len() == 0 || hdr.num_keys() == u32::max_value() {
+                    return false;
+                }
+                hdr.set_num_keys(hdr.num_keys() + 1);
+                true
+            }
+
+            (&Fault::KeyLength, OpCode::SandstormInvokeRpc) => {
+                let hdr = unsafe { &mut *(ptr as *mut InvokeRequest) };
+                hdr.set_name_length(payload as u32 + 1);
+                hdr.set_args_length(0);
+                true
+            }
+
+            (&Fault::GarbleName, OpCode::SandstormInvokeRpc) => {
+                let len = unsafe { (*(ptr as *const InvokeRequest)).name_length() as usize };
+                if len == 0 || len > payload {
+                    return false;
+                }
+
+                // 0xff never appears in UTF-8, so the name can never be valid.
+                let name = &mut req[hdr_len..hdr_len + len];
+                rng.fill_bytes(name);
+                name[0] = 0xff;
+                true
+            }
+
+            (&Fault::BadTenant, _) => {
+                unsafe { (*(ptr as *mut RpcRequestHeader)).set_tenant(BAD_TENANT) };
+                true
+            }
+
+            (&Fault::BadTable, OpCode::SandstormGetRpc) => {
+                unsafe { (*(ptr as *mut GetRequest)).set_table_id(BAD_TABLE) };
+                true
+            }
+
+            (&Fault::BadTable, OpCode::SandstormPutRpc) => {
+                unsafe { (*(ptr as *mut PutRequest)).set_table_id(BAD_TABLE) };
+                true
+            }
+
+            (&Fault::BadTable, OpCode::SandstormMultiGetRpc) => {
+                unsafe { (*(ptr as *mut MultiGetRequest)).set_table_id(BAD_TABLE) };
+                true
+            }
+
+            _ => false,
+        }
+    }
+
+    /// Returns the statuses a request of type `op` can complete with once this fault was applied
+    /// to it. A draining server rejects every request, so StatusServerDraining is always expected
+    /// as well.
+    pub fn expected(&self, op: OpCode) -> &'static [RpcStatus] {
+        match (self, op) {
+            (&Fault::Truncate(_), _) | (&Fault::KeyLength, _) => MALFORMED,
+            (&Fault::GarbleName, _) => BAD_NAME,
+            (&Fault::BadTenant, OpCode::SandstormInvokeRpc) => NO_TENANT_EXT,
+            (&Fault::BadTenant, _) => NO_TENANT,
+            (&Fault::BadTable, _) => NO_TABLE,
+        }
+    }
+}
+
+// Reads the opcode off an encoded request, if it is a valid one.
+fn opcode(req: &[u8]) -> Option<OpCode> {
+    if req.len() < size_of::<RpcRequestHeader>() {
+        return None;
+    }
+
+    // The opcode is the second byte of the request header.
+    let opcode: u8 = req[1];
+    match opcode.lt(&(OpCode::InvalidOperation as u8)) {
+        true => Some(unsafe { transmute(opcode) }),
+        false => None,
+    }
+}
+
+/// The number of requests a fault was injected into, and how the server responded to them.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FaultCounts {
+    /// Requests the fault was injected into.
+    pub injected: u64,
+
+    /// Responses with one of the statuses the fault is expected to produce.
+    pub expected: u64,
+
+    /// Responses with any other status. Each of these points at a bug in the server.
+    pub unexpected: u64,
+}
+
+/// Injects faults into a sampled fraction of the requests sent out by a client pipeline, and
+/// checks that the server rejects them the way it should. The workload must keep responses to
+/// injected requests out of it's latencies and validation.
+pub struct FaultInjector {
+    // The probability with which a request has a fault injected into it.
+    probability: f64,
+
+    // The faults to pick from, uniformly at random.
+    faults: Vec<Fault>,
+
+    // Counts for each entry in `faults`.
+    counts: Vec<FaultCounts>,
+
+    // Requests with a fault injected into them that are awaiting a response, keyed by request
+    // id. Holds the index of the fault, and the opcode on the request.
+    pending: HashMap<u64, (usize, OpCode)>,
+
+    rng: XorShiftRng,
+}
+
+impl FaultInjector {
+    /// Creates an injector.
+    ///
+    /// # Arguments
+    ///
+    /// * `probability`: The probability with which a request has a fault injected into it.
+    /// * `faults`:      The faults to pick from.
+    /// * `seed`:        Seed for sampling requests, picking faults, and garbling names. The
+    ///                  same seed injects the same faults into the same sequence of requests.
+    pub fn new(probability: f64, faults: Vec<Fault>, seed: [u32; 4]) -> FaultInjector {
+        FaultInjector {
+            probability: probability,
+            counts: vec![FaultCounts::default(); faults.len()],
+            faults: faults,
+            pending: HashMap::new(),
+            rng: XorShiftRng::from_seed(seed),
+        }
+    }
+
+    /// Creates an injector as specified by the client config.
+    ///
+    /// # Return
+    ///
+    /// None if fault injection is disabled. Panics if the list of faults is malformed.
+    pub fn from_config(config: &ClientConfig) -> Option<FaultInjector> {
+        let faults =
+            Fault::parse_list(&config.faults).expect("Malformed faults field in client config.");
+        if config.fault_probability <= 0.0 || faults.len() == 0 {
+            return None;
+        }
+
+        let seed = match config.fault_seed {
+            0 => rand::random::<[u32; 4]>(),
+            s => [s, s, s, s],
+        };
+        Some(FaultInjector::new(config.fault_probability, faults, seed))
+    }
+
+    /// Samples whether a request should have a fault injected into it, and if so, injects one.
+    ///
+    /// # Arguments
+    ///
+    /// * `id`:  The id the request is sent out with. complete() must be called with it once the
+    ///          response arrives.
+    /// * `req`: The encoded request, starting at the RPC request header.
+    ///
+    /// # Return
+    ///
+    /// The fault injected into the request, if any.
+    pub fn inject(&mut self, id: u64, req: &mut Vec<u8>) -> Option<Fault> {
+        if self.faults.len() == 0 || self.rng.gen::<f64>() >= self.probability {
+            return None;
+        }
+
+        let i = self.rng.gen_range(0, self.faults.len());
+        let op = opcode(req)?;
+        if !self.faults[i].apply(req, &mut self.rng) {
+            return None;
+        }
+
+        self.counts[i].injected += 1;
+        self.pending.insert(id, (i, op));
+        Some(self.faults[i].clone())
+    }
+
+    /// Returns true if a fault was injected into the request with this id, and it's response
+    /// hasn't arrived yet.
+    pub fn injected(&self, id: u64) -> bool {
+        self.pending.contains_key(&id)
+    }
+
+    /// Checks the status on a response to a request.
+    ///
+    /// # Arguments
+    ///
+    /// * `id`:     The id on the response.
+    /// * `status`: The status on the response.
+    ///
+    /// # Return
+    ///
+    /// None if no fault was injected into the request. Otherwise, whether the status was one of
+    /// the statuses the fault is expected to produce.
+    pub fn complete(&mut self, id: u64, status: &RpcStatus) -> Option<bool> {
+        self.pending.remove(&id).map(|(i, op)| {
+            let ok = *status == RpcStatus::StatusServerDraining
+                || self.faults[i].expected(op).contains(status);
+            match ok {
+                true => self.counts[i].expected += 1,
+                false => self.counts[i].unexpected += 1,
+            }
+            ok
+        })
+    }
+
+    /// Returns each fault along with it's counts.
+    pub fn counts(&self) -> Vec<(Fault, FaultCounts)> {
+        self.faults
+            .iter()
+            .cloned()
+            .zip(self.counts.iter().cloned())
+            .collect()
+    }
+
+    /// Returns the total number of responses to injected requests with an unexpected status.
+    pub fn unexpected(&self) -> u64 {
+        self.counts.iter().map(|c| c.unexpected).sum()
+    }
+}
+
+// This module contains unit tests for FaultInjector.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use udp::{encode_get, encode_invoke, encode_multiget, encode_put};
+
+    fn rng() -> XorShiftRng {
+        XorShiftRng::from_seed([1, 2, 3, 4])
+    }
+
+    // Returns a header of type H read off an encoded request.
+    fn header<H>(req: &[u8]) -> &H {
+        assert!(req.len() >= size_of::<H>());
+        unsafe { &*(req.as_ptr() as *const H) }
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Some(Fault::Truncate(8)), Fault::parse("truncate:8"));
+        assert_eq!(Some(Fault::GarbleName), Fault::parse(" garble_name "));
+        assert_eq!(None, Fault::parse("truncate"));
+        assert_eq!(None, Fault::parse("key_length:1"));
+        assert_eq!(None, Fault::parse("deadline"));
+
+        let list = Fault::parse_list("key_length, bad_tenant,bad_table").unwrap();
+        assert_eq!(
+            vec![Fault::KeyLength, Fault::BadTenant, Fault::BadTable],
+            list
+        );
+        assert_eq!(Some(vec![]), Fault::parse_list(""));
+        assert_eq!(None, Fault::parse_list("key_length,bogus"));
+    }
+
+    #[test]
+    fn test_truncate() {
+        let mut req = encode_get(1, 1, &[7; 30], 1, 0);
+        assert!(Fault::Truncate(4).apply(&mut req, &mut rng()));
+        assert_eq!(size_of::<GetRequest>() + 26, req.len());
+        let len = header::<GetRequest>(&req).key_length;
+        assert_eq!(30, len);
+
+        // A put is only truncated if the cut reaches into it's key.
+        let orig = encode_put(1, 1, &[7; 30], &[8; 100], 1, 0);
+        let mut req = orig.clone();
+        assert!(!Fault::Truncate(100).apply(&mut req, &mut rng()));
+        assert_eq!(orig, req);
+        assert!(Fault::Truncate(101).apply(&mut req, &mut rng()));
+        assert_eq!(size_of::<PutRequest>() + 29, req.len());
+
+        // Nothing to truncate.
+        let mut req = encode_get(1, 1, &[], 1, 0);
+        assert!(!Fault::Truncate(4).apply(&mut req, &mut rng()));
+    }
+
+    #[test]
+    fn test_key_length() {
+        let mut req = encode_get(1, 1, &[7; 30], 1, 0);
+        assert!(Fault::KeyLength.apply(&mut req, &mut rng()));
+        let len = header::<GetRequest>(&req).key_length;
+        assert_eq!(31, len);
+
+        let mut req = encode_put(1, 1, &[7; 30], &[8; 100], 1, 0);
+        assert!(Fault::KeyLength.apply(&mut req, &mut rng()));
+        let len = header::<PutRequest>(&req).key_length;
+        assert_eq!(131, len);
+
+        let mut req = encode_multiget(1, 1, 30, 2, &[7; 60], 1, 0);
+        assert!(Fault::KeyLength.apply(&mut req, &mut rng()));
+        let n = header::<MultiGetRequest>(&req).num_keys;
+        assert_eq!(3, n);
+
+        let mut req = encode_invoke(1, 3, b"getargs", 1, 0);
+        assert!(Fault::KeyLength.apply(&mut req, &mut rng()));
+        let hdr = header::<InvokeRequest>(&req);
+        let (name, args) = (hdr.name_length(), hdr.args_length());
+        assert_eq!((8, 0), (name, args));
+    }
+
+    #[test]
+    fn test_garble_name() {
+        let orig = encode_invoke(1, 3, b"getargs", 1, 0);
+        let (mut a, mut b) = (orig.clone(), orig.clone());
+        assert!(Fault::GarbleName.apply(&mut a, &mut rng()));
+        assert!(Fault::GarbleName.apply(&mut b, &mut rng()));
+
+        // The same seed garbles the same way, and only the name is touched.
+        assert_eq!(a, b);
+        let hdr_len = size_of::<InvokeRequest>();
+        assert_eq!(0xff, a[hdr_len]);
+        assert!(::std::str::from_utf8(&a[hdr_len..hdr_len + 3]).is_err());
+        assert_eq!(&orig[..hdr_len], &a[..hdr_len]);
+        assert_eq!(&orig[hdr_len + 3..], &a[hdr_len + 3..]);
+
+        // Only invokes have names.
+        let mut req = encode_get(1, 1, &[7; 30], 1, 0);
+        assert!(!Fault::GarbleName.apply(&mut req, &mut rng()));
+    }
+
+    #[test]
+    fn test_bad_ids() {
+        let mut req = encode_put(1, 1, &[7; 30], &[8; 100], 1, 0);
+        assert!(Fault::BadTenant.apply(&mut req, &mut rng()));
+        let tenant = header::<RpcRequestHeader>(&req).tenant;
+        assert_eq!(BAD_TENANT, tenant);
+
+        let mut req = encode_multiget(1, 1, 30, 2, &[7; 60], 1, 0);
+        assert!(Fault::BadTable.apply(&mut req, &mut rng()));
+        let table = header::<MultiGetRequest>(&req).table_id;
+        assert_eq!(BAD_TABLE, table);
+
+        // Invokes have no table.
+        let mut req = encode_invoke(1, 3, b"getargs", 1, 0);
+        assert!(!Fault::BadTable.apply(&mut req, &mut rng()));
+    }
+
+    // Tests that the same seed injects the same faults into the same requests.
+    #[test]
+    fn test_inject_deterministic() {
+        let faults = vec![Fault::Truncate(4), Fault::KeyLength, Fault::BadTenant];
+        let run = || {
+            let mut injector = FaultInjector::new(0.5, faults.clone(), [5, 6, 7, 8]);
+            (0..100)
+                .map(|id| {
+                    let mut req = encode_get(1, 1, &[id as u8; 30], id, 0);
+                    let fault = injector.inject(id, &mut req);
+                    (fault, req)
+                }).collect::<Vec<_>>()
+        };
+
+        let (a, b) = (run(), run());
+        assert_eq!(a, b);
+        let n = a.iter().filter(|&&(ref f, _)| f.is_some()).count();
+        assert!(n > 25 && n < 75);
+    }
+
+    // Tests that responses to injected requests are classified against the expected statuses.
+    #[test]
+    fn test_classify() {
+        let faults = vec![Fault::BadTenant];
+        let mut injector = FaultInjector::new(1.0, faults, [1, 2, 3, 4]);
+
+        let mut get = encode_get(1, 1, &[7; 30], 1, 0);
+        let mut invoke = encode_invoke(1, 3, b"getargs", 2, 0);
+        assert_eq!(Some(Fault::BadTenant), injector.inject(1, &mut get));
+        assert_eq!(Some(Fault::BadTenant), injector.inject(2, &mut invoke));
+        let mut get = encode_get(1, 1, &[7; 30], 3, 0);
+        injector.inject(3, &mut get);
+        let mut get = encode_get(1, 1, &[7; 30], 4, 0);
+        injector.inject(4, &mut get);
+        assert!(injector.injected(1));
+
+        // Requests that were not injected are not classified.
+        assert_eq!(None, injector.complete(9, &RpcStatus::StatusOk));
+
+        let ok = RpcStatus::StatusOk;
+        let ext = RpcStatus::StatusInvalidExtension;
+        let tenant = RpcStatus::StatusTenantDoesNotExist;
+        let draining = RpcStatus::StatusServerDraining;
+        assert_eq!(Some(true), injector.complete(1, &tenant));
+        assert_eq!(Some(true), injector.complete(2, &ext));
+        assert_eq!(Some(false), injector.complete(3, &ok));
+        assert_eq!(Some(true), injector.complete(4, &draining));
+        assert_eq!(None, injector.complete(1, &tenant));
+        assert!(!injector.injected(1));
+
+        let counts = FaultCounts {
+            injected: 4,
+            expected: 3,
+            unexpected: 1,
+        };
+        assert_eq!(vec![(Fault::BadTenant, counts)], injector.counts());
+        assert_eq!(1, injector.unexpected());
+    }
+
+    #[test]
+    fn test_expected() {
+        let get = OpCode::SandstormGetRpc;
+        let invoke = OpCode::SandstormInvokeRpc;
+        let (ok, ext) = (RpcStatus::StatusOk, RpcStatus::StatusInvalidExtension);
+        assert!(!Fault::BadTenant.expected(get).contains(&ext));
+        assert!(Fault::BadTenant.expected(invoke).contains(&ext));
+        assert!(Fault::GarbleName.expected(invoke).contains(&ext));
+        assert!(!Fault::Truncate(1).expected(get).contains(&ok));
+    }
+}
@@ -0,0 +1,145 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use std::cell::Cell;
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use std::sync::{Once, ONCE_INIT};
+
+use db::config::ClientConfig;
+
+use rand;
+
+/// The number of low order bits on a request id that hold the per-pipeline sequence number.
+/// The pipeline index occupies the bits above these.
+pub const SEQUENCE_BITS: u32 = 48;
+
+// The next pipeline index handed out by RequestIds::next_pipeline().
+static NEXT_PIPELINE: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Generates request ids for a single client pipeline. Ids carry the pipeline index in their
+/// high bits and a sequence number in their low bits, so ids generated by different pipelines
+/// on the same client never collide.
+pub struct RequestIds {
+    // The pipeline index, already shifted into the high bits.
+    pipeline: u64,
+
+    // The sequence number to be handed out on the next call to next().
+    next: Cell<u64>,
+}
+
+impl RequestIds {
+    /// Creates an id generator for a pipeline.
+    ///
+    /// # Arguments
+    ///
+    /// * `pipeline`: The index of the pipeline. Must be unique on the client.
+    pub fn new(pipeline: u16) -> RequestIds {
+        RequestIds {
+            pipeline: (pipeline as u64) << SEQUENCE_BITS,
+            next: Cell::new(0),
+        }
+    }
+
+    /// Creates an id generator for a pipeline whose index isn't known to the caller. Every call
+    /// hands out a new pipeline index, unique for the lifetime of the process.
+    pub fn next_pipeline() -> RequestIds {
+        RequestIds::new(NEXT_PIPELINE.fetch_add(1, Ordering::Relaxed) as u16)
+    }
+
+    /// Returns the next request id on this pipeline.
+    #[inline]
+    pub fn next(&self) -> u64 {
+        let seq = self.next.get();
+        self.next.set((seq + 1) & ((1 << SEQUENCE_BITS) - 1));
+        self.pipeline | seq
+    }
+}
+
+/// Returns the index of the pipeline that generated a request id.
+pub fn pipeline(id: u64) -> u16 {
+    (id >> SEQUENCE_BITS) as u16
+}
+
+// The id of this client run, set exactly once by run_id().
+static RUN_ID_INIT: Once = ONCE_INIT;
+static mut RUN_ID: u64 = 0;
+
+/// Returns the id of this client run, which requests are tagged with if `tag_run` is set. The
+/// first call takes the id from the config, or picks a random one if the config leaves it 0,
+/// and logs it. Every later call returns the same id, no matter the config passed in.
+///
+/// # Arguments
+///
+/// * `config`: Client configuration. `run_id` is used if non-zero.
+pub fn run_id(config: &ClientConfig) -> u64 {
+    unsafe {
+        RUN_ID_INIT.call_once(|| {
+            let mut id = config.run_id;
+            while id == 0 {
+                id = rand::random();
+            }
+
+            info!("Run id {:016x}", id);
+            RUN_ID = id;
+        });
+
+        RUN_ID
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Tests that ids are sequential within a pipeline and carry the pipeline index.
+    #[test]
+    fn test_ids_sequential() {
+        let ids = RequestIds::new(3);
+        let a = ids.next();
+        let b = ids.next();
+        assert_eq!(a + 1, b);
+        assert_eq!(3, pipeline(a));
+        assert_eq!(3, pipeline(b));
+    }
+
+    // Tests that ids from different pipelines never collide.
+    #[test]
+    fn test_ids_unique_across_pipelines() {
+        let (x, y) = (RequestIds::new(0), RequestIds::new(1));
+        let xs: Vec<u64> = (0..1000).map(|_| x.next()).collect();
+        let ys: Vec<u64> = (0..1000).map(|_| y.next()).collect();
+        assert!(xs.iter().all(|id| !ys.contains(id)));
+    }
+
+    // Tests that the run id is picked once, and is never 0.
+    #[test]
+    fn test_ids_run_id() {
+        let mut config = ClientConfig::default();
+        let id = run_id(&config);
+        assert!(id != 0);
+
+        config.run_id = id + 1;
+        assert_eq!(id, run_id(&config));
+    }
+
+    // Tests that the sequence number wraps without spilling into the pipeline index.
+    #[test]
+    fn test_ids_wrap() {
+        let ids = RequestIds::new(7);
+        ids.next.set((1 << SEQUENCE_BITS) - 1);
+        assert_eq!(7, pipeline(ids.next()));
+        assert_eq!(7 << SEQUENCE_BITS, ids.next());
+    }
+}
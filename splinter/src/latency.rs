@@ -0,0 +1,358 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use std::fmt;
+
+use db::cycles;
+
+/// The longest end-to-end latency a sample can have, in seconds. Anything longer can only be a
+/// clock that jumped between the request going out and it's response coming in.
+pub const MAX_PLAUSIBLE_SECS: u64 = 10;
+
+/// Turns the time-stamp a request went out at and the one it's response came in at into a
+/// latency sample, counting, and never recording, samples no request could have taken: negative
+/// ones, where the clock ran backwards in between, and implausibly long ones. Both stamps must be
+/// read off db::cycles, which only reads the time-stamp counter if stamps taken on different
+/// cores can be compared; these counts are how often that still went wrong.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SampleFilter {
+    // The longest plausible sample, in cycles.
+    max: u64,
+
+    // The number of samples dropped for being negative.
+    negative: u64,
+
+    // The number of samples dropped for being longer than `max`.
+    implausible: u64,
+}
+
+impl SampleFilter {
+    /// Constructs a SampleFilter.
+    ///
+    /// # Arguments
+    ///
+    /// * `hz`: The rate at which the clock the stamps are read off ticks.
+    pub fn new(hz: u64) -> SampleFilter {
+        SampleFilter {
+            max: hz.saturating_mul(MAX_PLAUSIBLE_SECS),
+            negative: 0,
+            implausible: 0,
+        }
+    }
+
+    /// Returns the latency between two time-stamps, or None if it is negative or implausibly
+    /// long, in which case it is counted instead.
+    ///
+    /// # Arguments
+    ///
+    /// * `sent`: The time-stamp the request went out at.
+    /// * `now`:  The time-stamp it's response came in at.
+    pub fn sample(&mut self, sent: u64, now: u64) -> Option<u64> {
+        if now < sent {
+            self.negative += 1;
+            return None;
+        }
+
+        let latency = now - sent;
+        if latency > self.max {
+            self.implausible += 1;
+            return None;
+        }
+
+        Some(latency)
+    }
+
+    /// Returns the number of samples dropped for being negative.
+    pub fn negative(&self) -> u64 {
+        self.negative
+    }
+
+    /// Returns the number of samples dropped for being implausibly long.
+    pub fn implausible(&self) -> u64 {
+        self.implausible
+    }
+
+    /// Adds the counts of another filter to this one's.
+    pub fn merge(&mut self, other: &SampleFilter) {
+        self.negative += other.negative;
+        self.implausible += other.implausible;
+    }
+}
+
+impl Default for SampleFilter {
+    fn default() -> SampleFilter {
+        SampleFilter::new(cycles::cycles_per_second())
+    }
+}
+
+impl fmt::Display for SampleFilter {
+    /// Formats the clock time-stamps were read off and the samples dropped, as printed with a
+    /// client's results: "Clock SOURCE Dropped NEGATIVE IMPLAUSIBLE".
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Clock {} Dropped {} {}",
+            cycles::source(),
+            self.negative,
+            self.implausible
+        )
+    }
+}
+
+/// Median and 99th percentile of a latency distribution, in nanoseconds.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Percentiles {
+    /// The median latency.
+    pub median: f64,
+
+    /// The 99th percentile latency.
+    pub p99: f64,
+}
+
+/// End-to-end latency split into the time spent at the server, and the time spent on the network
+/// (and in the client and server network stacks) on either side of it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Breakdown {
+    /// The distribution of time between the server receiving a request and sending out it's
+    /// response.
+    pub server: Percentiles,
+
+    /// The distribution of end-to-end latency minus the time spent at the server.
+    pub network: Percentiles,
+}
+
+/// Records latency samples off responses carrying server receive and transmit time-stamps.
+///
+/// Samples are recorded in raw cycles, client cycles for end-to-end latency and server cycles
+/// for time spent at the server, and are only converted to nanoseconds when they are
+/// aggregated. The two clocks tick at different rates, so the rate of the server's clock must be
+/// learned (using an echo() RPC) before samples can be aggregated.
+pub struct ServerLatency {
+    // The rate at which the server's cycle counter ticks, once known.
+    server_hz: Option<u64>,
+
+    // Pairs of (end-to-end latency in client cycles, time spent at the server in server cycles).
+    samples: Vec<(u64, u32)>,
+}
+
+impl ServerLatency {
+    /// Constructs a ServerLatency.
+    ///
+    /// # Arguments
+    ///
+    /// * `capacity`: The number of samples to reserve space for upfront.
+    pub fn new(capacity: usize) -> ServerLatency {
+        ServerLatency {
+            server_hz: None,
+            samples: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Sets the rate at which the server's cycle counter ticks, as reported on an echo() response.
+    pub fn set_server_hz(&mut self, hz: u64) {
+        if hz > 0 {
+            self.server_hz = Some(hz);
+        }
+    }
+
+    /// Records a sample.
+    ///
+    /// # Arguments
+    ///
+    /// * `e2e`: End-to-end latency of the request in client cycles.
+    /// * `rx`:  The receive time-stamp on the response.
+    /// * `tx`:  The transmit time-stamp on the response.
+    ///
+    /// # Return
+    ///
+    /// False if the response did not carry time-stamps, in which case nothing is recorded.
+    pub fn record(&mut self, e2e: u64, rx: u32, tx: u32) -> bool {
+        if rx == 0 && tx == 0 {
+            return false;
+        }
+
+        self.samples.push((e2e, server_cycles(rx, tx)));
+        true
+    }
+
+    /// Returns the number of samples recorded so far.
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    /// Aggregates the samples recorded so far.
+    ///
+    /// # Return
+    ///
+    /// The breakdown of latency, or None if there are no samples or if the rate of the server's
+    /// clock is not known.
+    pub fn breakdown(&self) -> Option<Breakdown> {
+        let hz = self.server_hz? as f64;
+        if self.samples.is_empty() {
+            return None;
+        }
+
+        let mut server = Vec::with_capacity(self.samples.len());
+        let mut network = Vec::with_capacity(self.samples.len());
+        for &(e2e, s) in self.samples.iter() {
+            let e2e = cycles::to_seconds(e2e) * 1e9;
+            let s = s as f64 / hz * 1e9;
+            server.push(s);
+
+            // The two clocks are calibrated independently, so the difference can come out
+            // slightly negative on very short round trips.
+            network.push((e2e - s).max(0.0));
+        }
+
+        Some(Breakdown {
+            server: percentiles(server),
+            network: percentiles(network),
+        })
+    }
+}
+
+/// Returns the number of server cycles between a receive and a transmit time-stamp. Only the low
+/// 32 bits of the server's cycle counter are sent, so the counter may have wrapped in between.
+///
+/// # Arguments
+///
+/// * `rx`: The receive time-stamp on a response.
+/// * `tx`: The transmit time-stamp on a response.
+#[inline]
+pub fn server_cycles(rx: u32, tx: u32) -> u32 {
+    tx.wrapping_sub(rx)
+}
+
+// Returns the median and 99th percentile of a non-empty set of samples.
+fn percentiles(mut samples: Vec<f64>) -> Percentiles {
+    samples.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+    let n = samples.len();
+    Percentiles {
+        median: samples[n / 2],
+        p99: samples[(n * 99) / 100],
+    }
+}
+
+// This module contains unit tests for ServerLatency.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use db::cycles;
+    use db::wireformat::{OpCode, RpcResponseHeader};
+
+    // Returns a response header with the supplied time-stamps, as a server would have sent it.
+    fn header(rx: u32, tx: u32) -> RpcResponseHeader {
+        let mut hdr = RpcResponseHeader::new(1, 0, OpCode::SandstormGetRpc, 1);
+        hdr.set_rx_stamp(rx);
+        hdr.set_tx_stamp(tx);
+        hdr
+    }
+
+    // Records a sample off a synthesized response header.
+    fn record(l: &mut ServerLatency, e2e: u64, hdr: RpcResponseHeader) -> bool {
+        let (rx, tx) = (hdr.rx_stamp(), hdr.tx_stamp());
+        l.record(e2e, rx, tx)
+    }
+
+    // Tests that negative and implausibly long samples are counted instead of returned.
+    #[test]
+    fn test_sample_filter() {
+        let mut f = SampleFilter::new(1000);
+        assert_eq!(Some(0), f.sample(100, 100));
+        assert_eq!(Some(10_000), f.sample(100, 10_100));
+        assert_eq!(None, f.sample(100, 10_101));
+        assert_eq!(None, f.sample(100, 99));
+        assert_eq!(None, f.sample(u64::max_value(), 0));
+        assert_eq!((2, 1), (f.negative(), f.implausible()));
+
+        let mut total = SampleFilter::new(1000);
+        total.merge(&f);
+        total.merge(&f);
+        assert_eq!((4, 2), (total.negative(), total.implausible()));
+
+        let line = total.to_string();
+        assert!(line.starts_with("Clock "));
+        assert!(line.ends_with(" Dropped 4 2"));
+
+        // A clock fast enough to overflow the bound never drops a positive sample.
+        let mut f = SampleFilter::new(u64::max_value());
+        assert_eq!(Some(u64::max_value()), f.sample(0, u64::max_value()));
+    }
+
+    #[test]
+    fn test_server_cycles() {
+        assert_eq!(100, server_cycles(1000, 1100));
+        assert_eq!(0, server_cycles(1000, 1000));
+    }
+
+    #[test]
+    fn test_server_cycles_wrap_around() {
+        assert_eq!(0x20, server_cycles(0xffff_fff0, 0x10));
+        assert_eq!(1, server_cycles(u32::max_value(), 0));
+    }
+
+    #[test]
+    fn test_unstamped_responses_skipped() {
+        let mut l = ServerLatency::new(1);
+        assert!(!record(&mut l, 100, header(0, 0)));
+        assert_eq!(0, l.len());
+    }
+
+    #[test]
+    fn test_breakdown_requires_server_hz() {
+        let mut l = ServerLatency::new(1);
+        assert!(record(&mut l, 100, header(10, 20)));
+        assert!(l.breakdown().is_none());
+
+        l.set_server_hz(0);
+        assert!(l.breakdown().is_none());
+    }
+
+    #[test]
+    fn test_breakdown() {
+        // A server clock at 1 GHz makes every server cycle a nanosecond.
+        let mut l = ServerLatency::new(100);
+        l.set_server_hz(1_000_000_000);
+
+        // End-to-end latency of 10 microseconds, of which 1 to 100 nanoseconds at the server. The
+        // last sample straddles the server's counter wrapping around.
+        let e2e = cycles::cycles_per_second() / 100_000;
+        for i in 1..100 {
+            assert!(record(&mut l, e2e, header(1000, 1000 + i)));
+        }
+        assert!(record(&mut l, e2e, header(0xffff_ffce, 0x32)));
+
+        let b = l.breakdown().unwrap();
+        assert_eq!(51.0, b.server.median);
+        assert_eq!(100.0, b.server.p99);
+
+        let e2e = cycles::to_seconds(e2e) * 1e9;
+        assert!((b.network.median - (e2e - 50.0)).abs() < 1e-6);
+        assert!((b.network.p99 - (e2e - 1.0)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_breakdown_network_clamped() {
+        // Server time longer than end-to-end time can only be clock error.
+        let mut l = ServerLatency::new(1);
+        l.set_server_hz(1_000_000_000);
+        assert!(record(&mut l, 0, header(1, 1001)));
+
+        let b = l.breakdown().unwrap();
+        assert_eq!(0.0, b.network.median);
+    }
+}
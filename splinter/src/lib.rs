@@ -15,12 +15,19 @@
 
 //! This crate is useful in writing a new client and handling pushback
 //! extension on the client side.
-#![feature(generators, generator_trait, asm)]
+#![feature(generators, generator_trait, asm, fnbox)]
 #![warn(missing_docs)]
 
 extern crate db;
+extern crate libc;
+extern crate rand;
 extern crate sandstorm;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
 extern crate util;
+extern crate zipf;
 pub extern crate env_logger;
 #[macro_use]
 pub extern crate log;
@@ -36,3 +43,53 @@ pub mod manager;
 /// Proxy to the database on the client side, searches the local cache for
 /// data and if not present on the cache then issues a request to the server.
 pub mod proxy;
+/// Hooks that let a workload verify responses before their latency is recorded.
+pub mod verify;
+/// Sender and receiver that use kernel UDP sockets instead of DPDK.
+pub mod udp;
+/// Paces a client's outstanding window using the load servers report on responses.
+pub mod pacing;
+/// Key and tenant distributions shared by the workload generators.
+pub mod dist;
+/// Generates request ids that are unique across the pipelines on a client.
+pub mod ids;
+/// Splits end-to-end latency into server-side and network latency using server time-stamps.
+pub mod latency;
+/// Checks a client runs against the server before starting a workload.
+pub mod preflight;
+/// Orders requests to the same key issued by a single client pipeline.
+pub mod order;
+/// Injects faults into outgoing requests to exercise the server's error paths.
+pub mod fault;
+/// Retries requests that failed to find what they named, until it is found or destroyed.
+pub mod retry;
+/// Compares the results of two client runs, and flags significant changes.
+pub mod compare;
+/// Drops duplicate responses to requests that already completed.
+pub mod dedup;
+/// Chains dependent requests, sending each one out once the response it depends on arrives.
+pub mod chain;
+/// Samples which requests have their latency recorded, to cut client overhead at high rates.
+pub mod sample;
+/// Seeds the random numbers workload generators draw, so that a run can be reproduced.
+pub mod rng;
+/// Holds outgoing requests back to send them out the NIC in bursts, and schedules open-loop
+/// requests.
+pub mod burst;
+/// Copies a tenant's table from one server to another, and verifies the copy.
+pub mod migrate;
+/// Reassembles invoke() results streamed back over several responses.
+pub mod stream;
+/// Detects a server that stopped responding mid-run, and moves every pipeline to a backup.
+pub mod failover;
+/// Runs a short deterministic script of requests against a server, and checks every response.
+pub mod smoke;
+/// Generates the TAO operation mix, and accounts for it's responses per operation.
+pub mod tao_mix;
+/// Sweeps a client's load to find the knee of it's latency-throughput curve.
+pub mod autotune;
+/// Counts cycles, instructions and cache and branch misses on each pipeline using the CPU's
+/// hardware performance counters.
+pub mod perf;
+/// Caches objects the server hinted hot, and serves gets of them without going to the server.
+pub mod cache;
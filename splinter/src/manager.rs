@@ -13,15 +13,21 @@
  * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
  */
 
+use std::collections::VecDeque;
 use std::rc::Rc;
+use std::str::from_utf8;
 use std::sync::Arc;
 
 use super::container::Container;
 use super::dispatch::*;
 use super::proxy::ProxyDB;
 
+use db::config::QueuePolicy;
+use db::cycles;
+use db::histogram::LogHistogram;
 use db::master::Master;
 use db::task::{Task, TaskPriority, TaskState, TaskState::*};
+use db::wireformat::RpcStatus;
 
 use sandstorm::common::TenantId;
 use util::model::GLOBAL_MODEL;
@@ -41,16 +47,38 @@ pub struct TaskManager {
     // a key and table identifier to be passed in.
     payload: Arc<Vec<u8>>,
 
-    // Identifier for each request, which is the timestamp for the packet generation.
-    // This is used to identify native requests associated with an extension.
+    // Identifier for each request. This is used to identify native requests associated with an
+    // extension.
     id: u64,
 
+    // The time-stamp at which the request was sent out. Used to measure latency.
+    stamp: u64,
+
     // The reference to the task generator, which is used to suspend/resume the generator.
     task: Vec<Box<Task>>,
 
     // A ref counted pointer to a master service. The master service
     // implements the primary interface to the database.
     master: Arc<Master>,
+
+    // The time-stamp at which the request was last put on a WaitQueue.
+    enqueued: u64,
+
+    // The cycles the request has spent on a WaitQueue, waiting to run, since it was pushed back.
+    queued: u64,
+
+    // The cycles the request's task has spent executing on the client.
+    executed: u64,
+
+    // The execution context of the request's task. Required to read back the task's result once
+    // it completes.
+    proxy: Option<Rc<ProxyDB>>,
+
+    // The status the request's task completed with. StatusPushback until it completes.
+    status: RpcStatus,
+
+    // The response written by the request's task, once it completes.
+    result: Vec<u8>,
 }
 
 impl TaskManager {
@@ -65,7 +93,8 @@ impl TaskManager {
     ///          if the requested is pushed back.
     /// * `tenant_id`: Tenant id will be needed reuqest generation.
     /// * `name_len`: This will be useful in parsing the request and find out the argument for consecutive requests.
-    /// * `timestamp`: This is unique-id for the request and consecutive requests will have same id.
+    /// * `id`: This is unique-id for the request and consecutive requests will have same id.
+    /// * `stamp`: The time-stamp at which the request was sent out.
     ///
     /// # Return
     ///
@@ -75,23 +104,92 @@ impl TaskManager {
         req: &[u8],
         tenant_id: u32,
         name_len: u32,
-        timestamp: u64,
+        id: u64,
+        stamp: u64,
     ) -> TaskManager {
         TaskManager {
             tenant: tenant_id,
             name_length: name_len,
             payload: Arc::new(req.to_vec()),
-            id: timestamp,
+            id: id,
+            stamp: stamp,
             task: Vec::with_capacity(1),
             master: master_service,
+            enqueued: 0,
+            queued: 0,
+            executed: 0,
+            proxy: None,
+            status: RpcStatus::StatusPushback,
+            result: Vec::new(),
         }
     }
 
+    /// This method reuses a TaskManager for a new request, keeping the buffers it allocated for
+    /// the previous one. The payload is copied into the old payload's buffer unless a task
+    /// created off the previous request still holds on to it.
+    ///
+    /// # Arguments
+    ///
+    /// * `req`: A reference to the request sent by the client.
+    /// * `tenant_id`: Tenant id will be needed reuqest generation.
+    /// * `name_len`: The length of the extension's name at the head of the request.
+    /// * `id`: This is unique-id for the request and consecutive requests will have same id.
+    /// * `stamp`: The time-stamp at which the request was sent out.
+    pub fn reset(&mut self, req: &[u8], tenant_id: u32, name_len: u32, id: u64, stamp: u64) {
+        match Arc::get_mut(&mut self.payload) {
+            Some(payload) => {
+                payload.clear();
+                payload.extend_from_slice(req);
+            }
+
+            None => self.payload = Arc::new(req.to_vec()),
+        }
+
+        self.tenant = tenant_id;
+        self.name_length = name_len;
+        self.id = id;
+        self.stamp = stamp;
+        self.task.clear();
+        self.enqueued = 0;
+        self.queued = 0;
+        self.executed = 0;
+        self.proxy = None;
+        self.status = RpcStatus::StatusPushback;
+        self.result.clear();
+    }
+
     /// This method returns the unique id, which was used for the request.
     pub fn get_id(&self) -> u64 {
         self.id.clone()
     }
 
+    /// This method returns the time-stamp at which the request was sent out.
+    pub fn get_stamp(&self) -> u64 {
+        self.stamp
+    }
+
+    /// This method returns the cycles the request spent on a WaitQueue waiting to run.
+    pub fn queued(&self) -> u64 {
+        self.queued
+    }
+
+    /// This method returns the cycles the request's task spent executing on the client.
+    pub fn executed(&self) -> u64 {
+        self.executed
+    }
+
+    /// This method returns an estimate of how much longer the request's task will execute for,
+    /// or None if there is no estimate or no task.
+    pub fn remaining(&self) -> Option<u64> {
+        self.task.last().and_then(|task| task.remaining())
+    }
+
+    /// This method returns the status and response the request's task completed with. The status
+    /// is StatusPushback if the task hasn't completed yet.
+    pub fn result(&self) -> (RpcStatus, &[u8]) {
+        (self.status.clone(), &self.result)
+    }
+
     /// This method returns the payload used in the request.
     fn get_payload(&self) -> &[u8] {
         &self.payload
@@ -107,30 +205,38 @@ impl TaskManager {
         let name_length: usize = self.name_length as usize;
 
         // Read the extension's name from the request payload.
-        let mut name = Vec::new();
-        name.extend_from_slice(self.get_payload().split_at(name_length).0);
-        let name: String = String::from_utf8(name).expect("ERROR: Failed to get ext name.");
+        let payload = Arc::clone(&self.payload);
+        let name: &[u8] = payload.split_at(name_length).0;
 
         // Get the model for the given extension.
         let mut model = None;
         // If the extension doesn't need an ML model, don't waste CPU cycles in lookup.
         if cfg!(feature = "ml-model") {
-            GLOBAL_MODEL.with(|a_model| {
-                if let Some(a_model) = (*a_model).borrow().get(&name) {
-                    model = Some(Arc::clone(a_model));
-                }
-            });
+            if let Ok(name) = from_utf8(name) {
+                GLOBAL_MODEL.with(|a_model| {
+                    if let Some(a_model) = (*a_model).borrow().get(name) {
+                        model = Some(Arc::clone(a_model));
+                    }
+                });
+            }
         }
 
-        if let Some(ext) = self.master.extensions.get(tenant_id, name) {
+        if let Some(ext) = self
+            .master
+            .extensions
+            .as_ref()
+            .and_then(|e| e.get(tenant_id, name))
+        {
             let db = Rc::new(ProxyDB::new(
                 self.tenant,
                 self.id,
+                self.stamp,
                 Arc::clone(&self.payload),
                 self.name_length as usize,
                 sender_service,
                 model,
             ));
+            self.proxy = Some(Rc::clone(&db));
             self.task
                 .push(Box::new(Container::new(TaskPriority::REQUEST, db, ext)));
         } else {
@@ -160,12 +266,19 @@ impl TaskManager {
         let mut taskstate: TaskState = INITIALIZED;
         let mut time: u64 = 0;
         if let Some(mut task) = task {
-            if task.run().0 == COMPLETED {
+            let (state, exec) = task.run();
+            self.executed += exec;
+            if state == COMPLETED {
                 taskstate = task.state();
                 time = task.time();
                 unsafe {
                     task.tear();
                 }
+                if let Some(proxy) = self.proxy.take() {
+                    self.status = proxy.status();
+                    self.result.clear();
+                    self.result.extend_from_slice(&proxy.response());
+                }
             // Do something for commit(Transaction commit?)
             } else {
                 taskstate = task.state();
@@ -176,3 +289,462 @@ impl TaskManager {
         (taskstate, time)
     }
 }
+
+/// The interval at which the depth of a WaitQueue is sampled, in microseconds.
+const DEPTH_INTERVAL_US: u64 = 1000;
+
+/// The run-queue of pushed-back requests on a client, waiting for their tasks to execute. A
+/// request is on the queue from when it is pushed back until it's task first runs, and again
+/// every time it's task yields; time spent waiting on the server for a get() is not counted.
+/// The queue tracks how long completed requests waited on it and executed, and how deep it was.
+pub struct WaitQueue {
+    // The order in which requests are taken off the queue.
+    policy: QueuePolicy,
+
+    // The requests waiting to run, in the order they were put on the queue.
+    tasks: VecDeque<TaskManager>,
+
+    // The cycles each completed request spent waiting on the queue.
+    queued: LogHistogram,
+
+    // The cycles each completed request spent executing.
+    executed: LogHistogram,
+
+    // The depth of the queue, sampled atmost once every `interval` cycles.
+    depths: Vec<(u64, usize)>,
+
+    // The interval at which the depth is sampled, in cycles.
+    interval: u64,
+}
+
+impl WaitQueue {
+    /// Creates an empty queue.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy`: The order in which requests are taken off the queue.
+    pub fn new(policy: QueuePolicy) -> WaitQueue {
+        WaitQueue {
+            policy: policy,
+            tasks: VecDeque::with_capacity(32),
+            queued: LogHistogram::new(),
+            executed: LogHistogram::new(),
+            depths: Vec::new(),
+            interval: cycles::cycles_per_second() * DEPTH_INTERVAL_US / 1_000_000,
+        }
+    }
+
+    /// Puts a request on the queue.
+    ///
+    /// # Arguments
+    ///
+    /// * `manager`: The request's TaskManager. It's task must have been created.
+    /// * `now`:     The current time-stamp, in cycles.
+    pub fn push(&mut self, mut manager: TaskManager, now: u64) {
+        manager.enqueued = now;
+        self.tasks.push_back(manager);
+        self.sample(now);
+    }
+
+    /// Takes the next request off the queue. Under FIFO, that's the request put on the queue
+    /// first. Under shortest-remaining-first, it's the request whose task is estimated to
+    /// complete soonest; requests without an estimate are taken last, and ties in the order
+    /// they were put on the queue.
+    ///
+    /// # Arguments
+    ///
+    /// * `now`: The current time-stamp, in cycles. The request is charged the time since it was
+    ///          put on the queue.
+    pub fn pop(&mut self, now: u64) -> Option<TaskManager> {
+        let next = match self.policy {
+            QueuePolicy::Fifo => 0,
+            QueuePolicy::ShortestRemaining => {
+                let mut next = 0;
+                let mut shortest = u64::max_value();
+                for (i, manager) in self.tasks.iter().enumerate() {
+                    let remaining = manager.remaining().unwrap_or(u64::max_value());
+                    if remaining < shortest {
+                        next = i;
+                        shortest = remaining;
+                    }
+                }
+                next
+            }
+        };
+
+        let manager = self.tasks.remove(next).map(|mut manager| {
+            manager.queued += now.saturating_sub(manager.enqueued);
+            manager
+        });
+        self.sample(now);
+        manager
+    }
+
+    /// Takes the next request off the queue and runs it's task. A task that yields is put back
+    /// on the queue as of when it yielded.
+    ///
+    /// # Arguments
+    ///
+    /// * `now`: The current time-stamp, in cycles.
+    ///
+    /// # Return
+    ///
+    /// The request, the state it's task is in, and the total cycles the task has run for, or
+    /// None if the queue is empty or the task yielded.
+    pub fn execute(&mut self, now: u64) -> Option<(TaskManager, TaskState, u64)> {
+        let mut manager = match self.pop(now) {
+            Some(manager) => manager,
+            None => return None,
+        };
+
+        let executed = manager.executed;
+        let (state, time) = manager.execute_task();
+        match state {
+            YIELDED => {
+                let yielded = now + manager.executed - executed;
+                self.push(manager, yielded);
+                None
+            }
+
+            COMPLETED => {
+                self.queued.record(manager.queued);
+                self.executed.record(manager.executed);
+                Some((manager, state, time))
+            }
+
+            _ => Some((manager, state, time)),
+        }
+    }
+
+    /// Returns the number of requests on the queue.
+    pub fn len(&self) -> usize {
+        self.tasks.len()
+    }
+
+    /// Returns the order in which requests are taken off the queue.
+    pub fn policy(&self) -> QueuePolicy {
+        self.policy
+    }
+
+    /// Returns the distribution of the cycles completed requests spent waiting on the queue.
+    pub fn queued(&self) -> &LogHistogram {
+        &self.queued
+    }
+
+    /// Returns the distribution of the cycles completed requests spent executing.
+    pub fn executed(&self) -> &LogHistogram {
+        &self.executed
+    }
+
+    /// Returns the depth of the queue over time, as (time-stamp, depth) samples.
+    pub fn depths(&self) -> &[(u64, usize)] {
+        &self.depths
+    }
+
+    // Samples the depth of the queue, unless it was sampled less than an interval ago.
+    fn sample(&mut self, now: u64) {
+        let due = match self.depths.last() {
+            Some(&(stamp, _)) => now >= stamp + self.interval,
+            None => true,
+        };
+
+        if due {
+            self.depths.push((now, self.tasks.len()));
+        }
+    }
+}
+
+/// A pool of TaskManagers for requests that completed. Lets a client hand out a TaskManager per
+/// invoke() request without allocating one, along with it's payload, every time.
+pub struct ManagerPool {
+    // The master service that every TaskManager handed out refers to.
+    master: Arc<Master>,
+
+    // TaskManagers of completed requests, ready to be handed out again.
+    free: Vec<TaskManager>,
+}
+
+impl ManagerPool {
+    /// Creates an empty pool.
+    ///
+    /// # Arguments
+    ///
+    /// * `master_service`: The Master that tasks created by the TaskManagers will run against.
+    /// * `capacity`: The maximum number of completed TaskManagers to hold on to.
+    pub fn new(master_service: Arc<Master>, capacity: usize) -> ManagerPool {
+        ManagerPool {
+            master: master_service,
+            free: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Hands out a TaskManager for a request, reusing one that was put back if there is any.
+    /// Takes the same arguments as TaskManager::new(), less the master service.
+    pub fn get(
+        &mut self,
+        req: &[u8],
+        tenant_id: u32,
+        name_len: u32,
+        id: u64,
+        stamp: u64,
+    ) -> TaskManager {
+        match self.free.pop() {
+            Some(mut manager) => {
+                manager.reset(req, tenant_id, name_len, id, stamp);
+                manager
+            }
+
+            None => TaskManager::new(
+                Arc::clone(&self.master),
+                req,
+                tenant_id,
+                name_len,
+                id,
+                stamp,
+            ),
+        }
+    }
+
+    /// Puts back the TaskManager of a request that completed. Dropped if the pool is full.
+    pub fn put(&mut self, manager: TaskManager) {
+        if self.free.len() < self.free.capacity() {
+            self.free.push(manager);
+        }
+    }
+
+    /// Returns the number of TaskManagers that can be handed out without allocating.
+    pub fn len(&self) -> usize {
+        self.free.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use db::e2d2::common::EmptyMetadata;
+    use db::e2d2::headers::UdpHeader;
+    use db::e2d2::interface::Packet;
+
+    // A task that runs for a scripted number of cycles every time it is run, yielding until the
+    // script runs out. It estimates it's remaining time like a Container does, as it's estimated
+    // total less the time it has run for.
+    struct Scripted {
+        costs: VecDeque<u64>,
+        estimate: Option<u64>,
+        state: TaskState,
+        time: u64,
+    }
+
+    impl Task for Scripted {
+        fn run(&mut self) -> (TaskState, u64) {
+            let cost = self.costs.pop_front().unwrap_or(0);
+            self.time += cost;
+            self.state = match self.costs.len() {
+                0 => COMPLETED,
+                _ => YIELDED,
+            };
+            (self.state, cost)
+        }
+
+        fn state(&self) -> TaskState {
+            self.state
+        }
+
+        fn time(&self) -> u64 {
+            self.time
+        }
+
+        fn db_time(&self) -> u64 {
+            0
+        }
+
+        fn priority(&self) -> TaskPriority {
+            TaskPriority::REQUEST
+        }
+
+        unsafe fn tear(
+            &mut self,
+        ) -> Option<(
+            Packet<UdpHeader, EmptyMetadata>,
+            Packet<UdpHeader, EmptyMetadata>,
+        )> {
+            None
+        }
+
+        fn set_state(&mut self, state: TaskState) {
+            self.state = state;
+        }
+
+        fn update_cache(&mut self, _record: &[u8], _keylen: usize) {}
+
+        fn remaining(&self) -> Option<u64> {
+            self.estimate
+                .map(|estimate| estimate.saturating_sub(self.time))
+        }
+    }
+
+    // Returns a pushed-back request whose task runs for `costs` cycles, one run at a time.
+    fn scripted(
+        master: &Arc<Master>,
+        id: u64,
+        costs: &[u64],
+        estimate: Option<u64>,
+    ) -> TaskManager {
+        let mut manager = TaskManager::new(Arc::clone(master), &[0; 8], 1, 8, id, 0);
+        manager.task.push(Box::new(Scripted {
+            costs: costs.iter().cloned().collect(),
+            estimate: estimate,
+            state: INITIALIZED,
+            time: 0,
+        }));
+        manager
+    }
+
+    // Runs every request on a queue to completion, one cycle after another, starting at `now`.
+    // Returns the ids of the requests in the order they completed.
+    fn drain(queue: &mut WaitQueue, mut now: u64) -> Vec<u64> {
+        let mut order = Vec::new();
+        while queue.len() > 0 {
+            if let Some((manager, state, _)) = queue.execute(now) {
+                assert!(state == COMPLETED);
+                order.push(manager.get_id());
+                now += manager.executed();
+            }
+            now += 1;
+        }
+        order
+    }
+
+    // Tests that a request is charged the time it waited on the queue, including after it's task
+    // yielded, separately from the time it's task executed.
+    #[test]
+    fn test_wait_queue_attribution() {
+        let master = Arc::new(Master::new());
+        let mut queue = WaitQueue::new(QueuePolicy::Fifo);
+        queue.push(scripted(&master, 1, &[100, 50], None), 0);
+        queue.push(scripted(&master, 2, &[30], None), 10);
+
+        // The first request yields after 100 cycles, and goes to the back of the queue.
+        assert!(queue.execute(1000).is_none());
+        assert_eq!(2, queue.len());
+
+        let (manager, state, time) = queue.execute(1100).unwrap();
+        assert!(state == COMPLETED);
+        assert_eq!(
+            (2, 1090, 30, 30),
+            (manager.get_id(), manager.queued(), manager.executed(), time)
+        );
+
+        // The first request waited 1000 cycles before it first ran, and 100 after it yielded.
+        let (manager, _, time) = queue.execute(1200).unwrap();
+        assert_eq!(
+            (1, 1100, 150, 150),
+            (manager.get_id(), manager.queued(), manager.executed(), time)
+        );
+        assert!(queue.execute(1300).is_none());
+
+        assert_eq!(2, queue.queued().total());
+        assert_eq!(2, queue.queued().counts()[LogHistogram::bucket(1100)]);
+        assert_eq!(1, queue.executed().counts()[LogHistogram::bucket(30)]);
+        assert_eq!(1, queue.executed().counts()[LogHistogram::bucket(150)]);
+
+        // A reused TaskManager starts over.
+        let mut manager = manager;
+        manager.reset(&[0; 8], 1, 8, 3, 0);
+        assert_eq!(
+            (0, 0, None),
+            (manager.queued(), manager.executed(), manager.remaining())
+        );
+    }
+
+    // Tests that shortest-remaining-first runs the request estimated to complete soonest, with
+    // requests without an estimate last, and that FIFO runs them in the order they were queued.
+    #[test]
+    fn test_wait_queue_order() {
+        let master = Arc::new(Master::new());
+        let requests = [(1, Some(500)), (2, None), (3, Some(100)), (4, Some(100))];
+        let fill = |policy| {
+            let mut queue = WaitQueue::new(policy);
+            for &(id, estimate) in requests.iter() {
+                queue.push(scripted(&master, id, &[10], estimate), 0);
+            }
+            queue
+        };
+
+        assert_eq!(vec![1, 2, 3, 4], drain(&mut fill(QueuePolicy::Fifo), 0));
+        assert_eq!(
+            vec![3, 4, 1, 2],
+            drain(&mut fill(QueuePolicy::ShortestRemaining), 0)
+        );
+
+        // A task's estimate shrinks as it runs, so a task that yielded is run ahead of one queued
+        // behind it with a shorter estimate than it started out with.
+        let yielded = |policy| {
+            let mut queue = WaitQueue::new(policy);
+            queue.push(scripted(&master, 1, &[10, 10, 5], Some(25)), 0);
+            assert!(queue.execute(0).is_none());
+            queue.push(scripted(&master, 2, &[18], Some(18)), 10);
+            drain(&mut queue, 10)
+        };
+
+        assert_eq!(vec![2, 1], yielded(QueuePolicy::Fifo));
+        assert_eq!(vec![1, 2], yielded(QueuePolicy::ShortestRemaining));
+    }
+
+    // Tests that the depth of the queue is sampled atmost once an interval.
+    #[test]
+    fn test_wait_queue_depth() {
+        let master = Arc::new(Master::new());
+        let mut queue = WaitQueue::new(QueuePolicy::Fifo);
+        let interval = queue.interval;
+        for id in 0..3 {
+            queue.push(scripted(&master, id, &[10], None), id);
+        }
+        queue.pop(interval);
+        queue.pop(interval + 1);
+        queue.push(scripted(&master, 3, &[10], None), 3 * interval);
+
+        assert_eq!(&[(0, 1), (interval, 2), (3 * interval, 2)], queue.depths());
+    }
+
+    // Tests that a TaskManager put back into the pool is handed out again, with it's payload
+    // copied into the same buffer.
+    #[test]
+    fn test_pool_reuse() {
+        let mut pool = ManagerPool::new(Arc::new(Master::new()), 2);
+        let manager = pool.get(&[1, 2, 3, 4, 5], 1, 4, 7, 100);
+        let ptr = manager.get_payload().as_ptr();
+        pool.put(manager);
+        assert_eq!(1, pool.len());
+
+        let manager = pool.get(&[6, 7, 8, 9, 10], 2, 4, 8, 200);
+        assert_eq!(0, pool.len());
+        assert_eq!(ptr, manager.get_payload().as_ptr());
+        assert_eq!(&[6, 7, 8, 9, 10], manager.get_payload());
+        assert_eq!(
+            (2, 8, 200),
+            (manager.tenant, manager.get_id(), manager.get_stamp())
+        );
+    }
+
+    // Tests that a payload still held by a task is not overwritten when it's TaskManager is
+    // reused, and that the pool holds atmost `capacity` TaskManagers.
+    #[test]
+    fn test_pool_shared_payload() {
+        let mut pool = ManagerPool::new(Arc::new(Master::new()), 2);
+        let manager = pool.get(&[1, 2, 3, 4, 5], 1, 4, 7, 100);
+        let held = Arc::clone(&manager.payload);
+        pool.put(manager);
+
+        let manager = pool.get(&[6, 7, 8, 9, 10], 1, 4, 8, 200);
+        assert_eq!(&[1, 2, 3, 4, 5], &held[..]);
+        assert_eq!(&[6, 7, 8, 9, 10], manager.get_payload());
+
+        let others = (pool.get(&[1], 1, 0, 9, 0), pool.get(&[1], 1, 0, 10, 0));
+        pool.put(manager);
+        pool.put(others.0);
+        pool.put(others.1);
+        assert_eq!(2, pool.len());
+    }
+}
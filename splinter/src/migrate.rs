@@ -0,0 +1,1168 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+
+use db::backoff::CoreSummary;
+use db::config;
+use db::histogram::Histogram;
+use db::kvformat::{Header, OnCorrupt, Reader, Writer, FLAG_DUMP_ORDER};
+use db::latency::LatencySummary;
+use db::rpc;
+use db::wireformat::*;
+
+use sandstorm::ext::ExtensionInfo;
+
+use super::udp::{
+    core_stats, echo, ext_latency_stats, latency_histogram, latency_stats, list_extensions,
+    udp_pipeline, Response, UdpReceiver, UdpSender,
+};
+
+/// Where a migration has got to in the source table. A table is dumped bucket by bucket, and in
+/// key order within a bucket; every record upto and including the cursor has been copied.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Cursor {
+    /// The bucket the dump continues in.
+    pub bucket: u32,
+
+    /// The key of the last record copied from the bucket. Empty at the start of the bucket.
+    pub key: Vec<u8>,
+}
+
+impl Cursor {
+    /// Parses a cursor in the format it is displayed in, "BUCKET:KEY" with the key in hex.
+    pub fn parse(spec: &str) -> Option<Cursor> {
+        let mut parts = spec.splitn(2, ':');
+        let bucket = parts.next().and_then(|b| b.parse().ok())?;
+        let digits = parts.next()?;
+        if digits.len() % 2 != 0 {
+            return None;
+        }
+
+        let mut key = Vec::with_capacity(digits.len() / 2);
+        for i in (0..digits.len()).step_by(2) {
+            key.push(u8::from_str_radix(digits.get(i..i + 2)?, 16).ok()?);
+        }
+
+        Some(Cursor {
+            bucket: bucket,
+            key: key,
+        })
+    }
+}
+
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.bucket, hex(&self.key))
+    }
+}
+
+/// How a table is migrated.
+#[derive(Clone, Debug)]
+pub struct MigrateConfig {
+    /// The tenant the table belongs to, on both servers.
+    pub tenant: u32,
+
+    /// The table to be migrated. It must already exist on the destination.
+    pub table: u64,
+
+    /// The most records on a multiput() batch.
+    pub batch_records: usize,
+
+    /// The most bytes of records on a multiput() batch. A record larger than this is sent in a
+    /// batch by itself.
+    pub batch_bytes: usize,
+
+    /// The most multiput() batches outstanding on the destination.
+    pub in_flight: usize,
+
+    /// How long to wait for a response before giving up.
+    pub timeout: Duration,
+
+    /// Every `sample`th record is compared between the source and destination once the copy
+    /// completes. 0 only compares the number of records.
+    pub sample: usize,
+
+    /// If true, native writes to the table on the source are refused once the copy completes.
+    pub cutover: bool,
+
+    /// Where to start copying from. The default starts at the beginning of the table.
+    pub resume: Cursor,
+}
+
+impl Default for MigrateConfig {
+    fn default() -> MigrateConfig {
+        MigrateConfig {
+            tenant: 1,
+            table: 1,
+            batch_records: 32,
+            batch_bytes: 1024,
+            in_flight: 8,
+            timeout: Duration::from_secs(1),
+            sample: 100,
+            cutover: false,
+            resume: Cursor::default(),
+        }
+    }
+}
+
+/// Why a migration stopped. Every error except a failed verification or a bad file carries the
+/// cursor the migration can be resumed from.
+#[derive(Debug, PartialEq)]
+pub enum MigrateError {
+    /// A request of the given kind got no response in time.
+    Timeout { what: &'static str, resume: Cursor },
+
+    /// The source refused a dump() request.
+    Source { status: RpcStatus, resume: Cursor },
+
+    /// The destination refused a batch holding the keys from `first` to `last`. Both are empty
+    /// if the destination refused the table before anything was copied.
+    Destination {
+        status: RpcStatus,
+        first: Vec<u8>,
+        last: Vec<u8>,
+        resume: Cursor,
+    },
+
+    /// A server sent back a response that could not be parsed.
+    Malformed { resume: Cursor },
+
+    /// The copy completed, but the destination does not match the source.
+    Mismatch(String),
+
+    /// A file of records could not be written by export(), or read by import().
+    File(String),
+}
+
+impl MigrateError {
+    /// Returns the cursor the migration can be resumed from, if it can be resumed.
+    pub fn resume(&self) -> Option<&Cursor> {
+        match *self {
+            MigrateError::Timeout { ref resume, .. } => Some(resume),
+            MigrateError::Source { ref resume, .. } => Some(resume),
+            MigrateError::Destination { ref resume, .. } => Some(resume),
+            MigrateError::Malformed { ref resume } => Some(resume),
+            MigrateError::Mismatch(_) => None,
+            MigrateError::File(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for MigrateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MigrateError::Timeout { what, ref resume } => write!(
+                f,
+                "Timed out waiting on a {} response, resume from {}",
+                what, resume
+            ),
+            MigrateError::Source {
+                ref status,
+                ref resume,
+            } => write!(
+                f,
+                "Source refused to dump with {:?}, resume from {}",
+                status, resume
+            ),
+            MigrateError::Destination {
+                ref status,
+                ref first,
+                ref last,
+                ref resume,
+            } => write!(
+                f,
+                "Destination refused keys {}..{} with {:?}, resume from {}",
+                hex(first),
+                hex(last),
+                status,
+                resume
+            ),
+            MigrateError::Malformed { ref resume } => {
+                write!(f, "Received a malformed response, resume from {}", resume)
+            }
+            MigrateError::Mismatch(ref why) => write!(f, "Verification failed: {}", why),
+            MigrateError::File(ref why) => write!(f, "Bad file of records: {}", why),
+        }
+    }
+}
+
+/// How far a migration has got. Only counts batches the destination acknowledged in order.
+#[derive(Clone, Debug, Default)]
+pub struct Progress {
+    /// The number of records copied.
+    pub records: u64,
+
+    /// The number of bytes of keys and values copied.
+    pub bytes: u64,
+
+    /// The number of multiput() batches copied.
+    pub batches: u64,
+
+    /// The cursor the migration would resume from if it stopped now.
+    pub acked: Cursor,
+
+    /// The time since the migration started.
+    pub elapsed: Duration,
+}
+
+impl Progress {
+    /// Returns the number of records copied per second.
+    pub fn records_per_sec(&self) -> f64 {
+        self.records as f64 / secs(self.elapsed)
+    }
+
+    /// Returns the number of MB of keys and values copied per second.
+    pub fn mb_per_sec(&self) -> f64 {
+        self.bytes as f64 / (1024.0 * 1024.0) / secs(self.elapsed)
+    }
+}
+
+/// What a completed migration did.
+#[derive(Clone, Debug)]
+pub struct Summary {
+    /// How much was copied, excluding anything copied before the migration was resumed.
+    pub progress: Progress,
+
+    /// The number of records on the source and destination when they were verified.
+    pub records: u64,
+
+    /// The number of records whose checksums were compared.
+    pub sampled: u64,
+
+    /// True if native writes to the table on the source are now refused.
+    pub cutover: bool,
+}
+
+/// One of the two servers in a migration.
+pub struct Endpoint {
+    sender: UdpSender,
+    receiver: UdpReceiver,
+}
+
+impl Endpoint {
+    /// Sets up a UDP socket to the server in `config`. Refer to udp::udp_pipeline().
+    pub fn connect(config: &config::ClientConfig) -> io::Result<Endpoint> {
+        let (sender, receiver) = udp_pipeline(config, 0, 1)?;
+        Ok(Endpoint {
+            sender: sender,
+            receiver: receiver,
+        })
+    }
+
+    /// Asks the server what it serves. Refer to udp::echo().
+    pub fn echo(&self, tenant: u32, timeout: Duration) -> io::Result<(u64, (usize, usize))> {
+        echo(&self.sender, &self.receiver, tenant, timeout)
+    }
+
+    /// Lists the extensions a tenant can invoke. Refer to udp::list_extensions().
+    pub fn list_extensions(
+        &self,
+        tenant: u32,
+        timeout: Duration,
+    ) -> io::Result<Vec<ExtensionInfo>> {
+        list_extensions(&self.sender, &self.receiver, tenant, timeout)
+    }
+
+    /// Asks the server how long it's cores backed off polling. Refer to udp::core_stats().
+    pub fn core_stats(&self, timeout: Duration) -> io::Result<(Vec<CoreSummary>, (u64, u64))> {
+        core_stats(&self.sender, &self.receiver, timeout)
+    }
+
+    /// Asks the server how long it took to respond to each opcode. Refer to
+    /// udp::latency_stats().
+    pub fn latency_stats(&self, timeout: Duration) -> io::Result<Vec<(u8, LatencySummary)>> {
+        latency_stats(&self.sender, &self.receiver, timeout)
+    }
+
+    /// Asks the server how long it took to respond to invocations of each extension. Refer to
+    /// udp::ext_latency_stats().
+    pub fn ext_latency_stats(
+        &self,
+        timeout: Duration,
+    ) -> io::Result<Vec<(Vec<u8>, LatencySummary)>> {
+        ext_latency_stats(&self.sender, &self.receiver, timeout)
+    }
+
+    /// Asks the server for the histogram of the time it took to respond to an opcode or
+    /// extension. Refer to udp::latency_histogram().
+    pub fn latency_histogram(
+        &self,
+        opcode: OpCode,
+        name: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Histogram> {
+        latency_histogram(&self.sender, &self.receiver, opcode, name, timeout)
+    }
+
+    // Sends out a request through `send`, and waits upto `timeout` for the response to it.
+    // Responses to any other request are dropped.
+    pub(crate) fn call<F: FnOnce(&UdpSender, u64)>(
+        &self,
+        send: F,
+        timeout: Duration,
+    ) -> Option<Response> {
+        let id = self.sender.next_id();
+        send(&self.sender, id);
+
+        let begin = Instant::now();
+        while begin.elapsed() < timeout {
+            for res in self.receiver.recv_res().unwrap_or_else(Vec::new) {
+                if response_id(&res) == Some(id) {
+                    return Some(res);
+                }
+                self.receiver.recycle(res);
+            }
+        }
+
+        None
+    }
+}
+
+// A multiput() batch sent to the destination.
+struct Batch {
+    // The identifier on the request.
+    id: u64,
+
+    // The number of records and bytes of keys and values on the batch.
+    num: u32,
+    bytes: usize,
+
+    // The key of the first record on the batch, and the cursor after it's last record.
+    first: Vec<u8>,
+    last: Cursor,
+
+    // When the batch was sent out, and whether it has been acknowledged.
+    sent: Instant,
+    acked: bool,
+}
+
+/// Copies a table from one server to another, and verifies the copy.
+///
+/// The source is dumped while the destination is written to, with upto `in_flight` batches
+/// outstanding on the destination. The cursor only advances past a batch once it and every
+/// batch before it were acknowledged, so a migration that stopped can be resumed from the
+/// cursor on it's error; batches written after the cursor are written again, which is harmless.
+///
+/// The source can keep taking writes during the copy, so the destination holds a fuzzy snapshot
+/// of the table: a write to a record after it was copied is lost, and one before it may or may
+/// not be. The verification then fails, or passes by chance. Pass `cutover` or stop writes to
+/// the table before migrating it for an exact copy; `cutover` refuses native writes to the
+/// source as soon as the copy completes, before verifying it. Writes from extensions are not
+/// refused.
+///
+/// # Arguments
+///
+/// * `src`:      The server the table is copied from.
+/// * `dst`:      The server the table is copied to.
+/// * `config`:   What to copy, and how.
+/// * `progress`: Called after every batch acknowledged in order.
+///
+/// # Return
+///
+/// A summary of the migration, or why it stopped.
+pub fn migrate(
+    src: &Endpoint,
+    dst: &Endpoint,
+    config: &MigrateConfig,
+    progress: &mut FnMut(&Progress),
+) -> Result<Summary, MigrateError> {
+    let mut stats = Progress {
+        acked: config.resume.clone(),
+        ..Progress::default()
+    };
+
+    // An empty batch checks that the destination has a table it can be written to.
+    let send = |s: &UdpSender, id| s.send_multiput(config.tenant, config.table, &[], 0, id, 0);
+    match dst
+        .call(send, config.timeout)
+        .map(|res| parse_multiput(&dst.receiver, res))
+    {
+        Some(Ok(())) => {}
+        Some(Err(status)) => return Err(refused(status, &[], &[], &config.resume)),
+        None => return Err(timeout("multiput", &config.resume)),
+    }
+
+    copy(src, dst, config, &mut stats, progress)?;
+
+    if config.cutover {
+        let send = |s: &UdpSender, id| {
+            s.send_table_access(config.tenant, config.table, true, false, id, 0)
+        };
+        let res = src
+            .call(send, config.timeout)
+            .ok_or_else(|| timeout("table_access", &stats.acked))?;
+        let status = res
+            .parse_header::<TableAccessResponse>()
+            .map(|p| p.get_header().common_header.status.clone());
+        src.receiver.recycle(res);
+        match status {
+            Some(RpcStatus::StatusOk) => {}
+            Some(status) => return Err(source(status, &stats.acked)),
+            None => return Err(malformed(&stats.acked)),
+        }
+    }
+
+    let (records, sampled) = verify(src, dst, config, &stats.acked)?;
+
+    Ok(Summary {
+        progress: stats,
+        records: records,
+        sampled: sampled,
+        cutover: config.cutover,
+    })
+}
+
+/// Saves a table on a server to a file of records (refer to db::kvformat), that import() can
+/// load back onto any server. The table is dumped from the beginning, the way verify() dumps
+/// it; writes made to the table during the export may or may not make it onto the file.
+///
+/// # Arguments
+///
+/// * `src`:    The server the table is saved from.
+/// * `config`: The table to save. Only `tenant`, `table` and `timeout` are used.
+/// * `out`:    Where the records are written to.
+///
+/// # Return
+///
+/// The output and the number of records saved, or why the export stopped.
+pub fn export<W: Write>(
+    src: &Endpoint,
+    config: &MigrateConfig,
+    out: W,
+) -> Result<(W, u64), MigrateError> {
+    let mut header = Header::new(config.tenant, config.table);
+    header.flags = FLAG_DUMP_ORDER;
+    let mut writer = Writer::new(out, header).map_err(file)?;
+
+    let start = Cursor::default();
+    let mut failed = None;
+    scan(
+        src,
+        config,
+        &start,
+        &|s| source(s, &start),
+        &mut |key, val| {
+            if failed.is_none() {
+                failed = writer.write(key, val).err();
+            }
+        },
+    )?;
+    if let Some(e) = failed {
+        return Err(file(e));
+    }
+
+    let records = writer.records();
+    writer.finish().map(|out| (out, records)).map_err(file)
+}
+
+/// Loads a file of records (refer to db::kvformat) into a table on a server; a file saved by
+/// export(), a table checkpoint, or the input to mkimage. The file's header need not name the
+/// table it is loaded into. Batches are sent one at a time, each read off the file only once the
+/// one before it was acknowledged, so an import that stopped leaves the records before the batch
+/// it stopped at written. Imports can't be resumed; the cursor on their errors is the start of
+/// the table.
+///
+/// # Arguments
+///
+/// * `dst`:      The server the records are written to.
+/// * `config`:   The table to write to, and how to batch records. Only `tenant`, `table`,
+///               `batch_records`, `batch_bytes` and `timeout` are used.
+/// * `input`:    Where the records are read from.
+/// * `progress`: Called after every batch acknowledged.
+///
+/// # Return
+///
+/// How much was loaded, or why the import stopped. A corrupt or truncated file stops it with
+/// MigrateError::File.
+pub fn import<R: Read>(
+    dst: &Endpoint,
+    config: &MigrateConfig,
+    input: R,
+    progress: &mut FnMut(&Progress),
+) -> Result<Progress, MigrateError> {
+    let start = Instant::now();
+    let mut stats = Progress::default();
+    let mut reader = Reader::new(input, OnCorrupt::Abort).map_err(file)?;
+
+    let (mut records, mut num, mut bytes) = (Vec::new(), 0, 0);
+    let (mut first, mut last) = (Vec::new(), Vec::new());
+
+    loop {
+        let next = reader.next().map_err(file)?;
+
+        // Send out the batch once the next record does not fit on it, or the file ends.
+        let fits = match next {
+            Some((key, val)) => {
+                let len = records.len() + rpc::KV_OVERHEAD + key.len() + val.len();
+                num < config.batch_records && len <= config.batch_bytes
+            }
+            None => false,
+        };
+        if num > 0 && !fits {
+            put_batch(dst, config, &records, num, &first, &last)?;
+            stats.records += num as u64;
+            stats.bytes += bytes;
+            stats.batches += 1;
+            stats.elapsed = start.elapsed();
+            progress(&stats);
+            records.clear();
+            num = 0;
+            bytes = 0;
+        }
+
+        match next {
+            Some((key, val)) => {
+                rpc::append_kv(&mut records, key, val);
+                if num == 0 {
+                    first = key.to_vec();
+                }
+                last = key.to_vec();
+                num += 1;
+                bytes += (key.len() + val.len()) as u64;
+            }
+
+            None => {
+                stats.elapsed = start.elapsed();
+                return Ok(stats);
+            }
+        }
+    }
+}
+
+// Sends a batch of records to the destination, and waits for it to be acknowledged. `first` and
+// `last` are the keys of the first and last records on the batch.
+fn put_batch(
+    dst: &Endpoint,
+    config: &MigrateConfig,
+    records: &[u8],
+    num: usize,
+    first: &[u8],
+    last: &[u8],
+) -> Result<(), MigrateError> {
+    let resume = Cursor::default();
+    let send = |s: &UdpSender, id| {
+        s.send_multiput(config.tenant, config.table, records, num as u32, id, 0)
+    };
+    match dst
+        .call(send, config.timeout)
+        .map(|res| parse_multiput(&dst.receiver, res))
+    {
+        Some(Ok(())) => Ok(()),
+        Some(Err(status)) => Err(refused(status, first, last, &resume)),
+        None => Err(timeout("multiput", &resume)),
+    }
+}
+
+// Streams the source into the destination, starting at the cursor in `stats`.
+fn copy(
+    src: &Endpoint,
+    dst: &Endpoint,
+    config: &MigrateConfig,
+    stats: &mut Progress,
+    progress: &mut FnMut(&Progress),
+) -> Result<(), MigrateError> {
+    let start = Instant::now();
+
+    // The cursor after the last record dumped, and the dump request outstanding on the source.
+    let mut dumped = stats.acked.clone();
+    let mut dump: Option<(u64, Instant)> = None;
+    let mut done = false;
+
+    // Records dumped but not yet sent to the destination, and batches not yet acknowledged in
+    // order.
+    let mut staged: VecDeque<(u32, Vec<u8>, Vec<u8>)> = VecDeque::new();
+    let mut batches: VecDeque<Batch> = VecDeque::new();
+
+    loop {
+        // Keep a dump() outstanding while there is room to stage the records on it.
+        if !done && dump.is_none() && staged.len() < config.batch_records * config.in_flight {
+            let id = src.sender.next_id();
+            let (bucket, after) = (dumped.bucket, &dumped.key);
+            src.sender
+                .send_dump(config.tenant, config.table, bucket, after, id, 0);
+            dump = Some((id, Instant::now()));
+        }
+
+        // Only send out a partial batch once there is nothing left to dump.
+        while batches.len() < config.in_flight
+            && (staged.len() >= config.batch_records || (done && !staged.is_empty()))
+        {
+            batches.push_back(send_batch(dst, config, &mut staged));
+        }
+
+        if done && staged.is_empty() && batches.is_empty() {
+            return Ok(());
+        }
+
+        for res in src.receiver.recv_res().unwrap_or_else(Vec::new) {
+            let id = dump.as_ref().map(|d| d.0);
+            if id.is_none() || response_id(&res) != id {
+                src.receiver.recycle(res);
+                continue;
+            }
+
+            dump = None;
+            let page = parse_dump(&res);
+            src.receiver.recycle(res);
+            match page {
+                Ok((_, ref records)) if records.is_empty() => done = true,
+                Ok((bucket, records)) => {
+                    dumped = Cursor {
+                        bucket: bucket,
+                        key: records[records.len() - 1].0.clone(),
+                    };
+                    staged.extend(records.into_iter().map(|(k, v)| (bucket, k, v)));
+                }
+                Err(Some(status)) => return Err(source(status, &stats.acked)),
+                Err(None) => return Err(malformed(&stats.acked)),
+            }
+        }
+
+        // A refused batch stops the copy, but only once the batches before it were accounted
+        // for, so that the migration resumes from right before it.
+        let mut failed = None;
+        for res in dst.receiver.recv_res().unwrap_or_else(Vec::new) {
+            let id = response_id(&res);
+            match batches.iter_mut().find(|b| Some(b.id) == id) {
+                Some(batch) => match parse_multiput(&dst.receiver, res) {
+                    Ok(()) => batch.acked = true,
+                    Err(status) => {
+                        failed = Some((status, batch.first.clone(), batch.last.key.clone()));
+                        break;
+                    }
+                },
+                None => dst.receiver.recycle(res),
+            }
+        }
+
+        // Advance the cursor past the batches acknowledged in order.
+        while batches.front().map_or(false, |b| b.acked) {
+            let batch = batches.pop_front().unwrap();
+            stats.records += batch.num as u64;
+            stats.bytes += batch.bytes as u64;
+            stats.batches += 1;
+            stats.acked = batch.last;
+            stats.elapsed = start.elapsed();
+            progress(stats);
+        }
+
+        if let Some((status, first, last)) = failed {
+            return Err(refused(status, &first, &last, &stats.acked));
+        }
+
+        if dump
+            .as_ref()
+            .map_or(false, |d| d.1.elapsed() > config.timeout)
+        {
+            return Err(timeout("dump", &stats.acked));
+        }
+        if batches
+            .front()
+            .map_or(false, |b| b.sent.elapsed() > config.timeout)
+        {
+            return Err(timeout("multiput", &stats.acked));
+        }
+    }
+}
+
+// Sends out a batch of the records at the front of `staged`, and removes them from it.
+fn send_batch(
+    dst: &Endpoint,
+    config: &MigrateConfig,
+    staged: &mut VecDeque<(u32, Vec<u8>, Vec<u8>)>,
+) -> Batch {
+    let (mut records, mut num, mut bytes) = (Vec::new(), 0, 0);
+    let (mut first, mut last) = (Vec::new(), Cursor::default());
+
+    loop {
+        let fits = match staged.front() {
+            Some(&(_, ref key, ref val)) => {
+                let len = records.len() + rpc::KV_OVERHEAD + key.len() + val.len();
+                num == 0 || (num < config.batch_records && len <= config.batch_bytes)
+            }
+            None => false,
+        };
+        if !fits {
+            break;
+        }
+
+        let (bucket, key, val) = staged.pop_front().unwrap();
+        rpc::append_kv(&mut records, &key, &val);
+        if num == 0 {
+            first = key.clone();
+        }
+        num += 1;
+        bytes += key.len() + val.len();
+        last = Cursor {
+            bucket: bucket,
+            key: key,
+        };
+    }
+
+    let id = dst.sender.next_id();
+    dst.sender
+        .send_multiput(config.tenant, config.table, &records, num as u32, id, 0);
+
+    Batch {
+        id: id,
+        num: num as u32,
+        bytes: bytes,
+        first: first,
+        last: last,
+        sent: Instant::now(),
+        acked: false,
+    }
+}
+
+// Dumps the whole table on both servers, and compares the number of records on them along with
+// the checksums of every `sample`th record on the source. Returns both numbers.
+fn verify(
+    src: &Endpoint,
+    dst: &Endpoint,
+    config: &MigrateConfig,
+    resume: &Cursor,
+) -> Result<(u64, u64), MigrateError> {
+    let mut samples = HashMap::new();
+    let mut i = 0;
+    let records = scan(
+        src,
+        config,
+        resume,
+        &|s| source(s, resume),
+        &mut |key, val| {
+            if config.sample > 0 && i % config.sample == 0 {
+                samples.insert(key.to_vec(), checksum(val));
+            }
+            i += 1;
+        },
+    )?;
+    let sampled = samples.len() as u64;
+
+    let mut differs = None;
+    let destination = |s| refused(s, &[], &[], resume);
+    let copied = scan(dst, config, resume, &destination, &mut |key, val| {
+        if let Some(sum) = samples.remove(key) {
+            if sum != checksum(val) && differs.is_none() {
+                differs = Some(key.to_vec());
+            }
+        }
+    })?;
+
+    if records != copied {
+        let why = format!("Source has {} records, destination has {}", records, copied);
+        return Err(MigrateError::Mismatch(why));
+    }
+    if let Some(key) = differs {
+        let why = format!("Record {} differs on the destination", hex(&key));
+        return Err(MigrateError::Mismatch(why));
+    }
+    if let Some(key) = samples.keys().next() {
+        let why = format!("Record {} is missing on the destination", hex(key));
+        return Err(MigrateError::Mismatch(why));
+    }
+
+    Ok((records, sampled))
+}
+
+// Dumps the whole table off a server, handing every record to `f`. Returns the number of
+// records, or the error `refused` turns the status the server refused the dump with into.
+fn scan(
+    end: &Endpoint,
+    config: &MigrateConfig,
+    resume: &Cursor,
+    refused: &Fn(RpcStatus) -> MigrateError,
+    f: &mut FnMut(&[u8], &[u8]),
+) -> Result<u64, MigrateError> {
+    let (mut bucket, mut after, mut num) = (0, Vec::new(), 0);
+
+    loop {
+        let send =
+            |s: &UdpSender, id| s.send_dump(config.tenant, config.table, bucket, &after, id, 0);
+        let res = end
+            .call(send, config.timeout)
+            .ok_or_else(|| timeout("dump", resume))?;
+        let page = parse_dump(&res);
+        end.receiver.recycle(res);
+
+        let (b, records) = match page {
+            Ok(page) => page,
+            Err(Some(status)) => return Err(refused(status)),
+            Err(None) => return Err(malformed(resume)),
+        };
+        if records.is_empty() {
+            return Ok(num);
+        }
+
+        for &(ref key, ref val) in records.iter() {
+            f(key, val);
+        }
+        num += records.len() as u64;
+        bucket = b;
+        after = records[records.len() - 1].0.clone();
+    }
+}
+
+// Parses a response to a dump() request into the bucket the records on it came from, and the
+// records. Fails with the status on the response, or None if it is malformed.
+fn parse_dump(res: &Response) -> Result<(u32, Vec<(Vec<u8>, Vec<u8>)>), Option<RpcStatus>> {
+    if res.opcode() != OpCode::SandstormDumpRpc {
+        return Err(None);
+    }
+
+    let p = res.parse_header::<DumpResponse>().ok_or(None)?;
+    let hdr = p.get_header();
+    let (num, bucket) = (hdr.num_records(), hdr.bucket());
+    if hdr.common_header.status != RpcStatus::StatusOk {
+        return Err(Some(hdr.common_header.status.clone()));
+    }
+
+    let records = rpc::parse_kvs(p.get_payload(), num).ok_or(None)?;
+    let records = records
+        .into_iter()
+        .map(|(k, v)| (k.to_vec(), v.to_vec()))
+        .collect();
+    Ok((bucket, records))
+}
+
+// Parses a response to a multiput() request, and hands it back to the receiver. Fails with the
+// status on the response; a malformed response fails with StatusMalformedRequest.
+fn parse_multiput(receiver: &UdpReceiver, res: Response) -> Result<(), RpcStatus> {
+    let status = match res.opcode() {
+        OpCode::SandstormMultiPutRpc => res
+            .parse_header::<MultiPutResponse>()
+            .map(|p| p.get_header().common_header.status.clone()),
+        _ => None,
+    };
+    receiver.recycle(res);
+
+    match status.unwrap_or(RpcStatus::StatusMalformedRequest) {
+        RpcStatus::StatusOk => Ok(()),
+        status => Err(status),
+    }
+}
+
+// Returns the identifier on a response, if it is long enough to have one.
+fn response_id(res: &Response) -> Option<u64> {
+    res.parse_header::<RpcResponseHeader>()
+        .map(|p| p.get_header().id())
+}
+
+fn timeout(what: &'static str, resume: &Cursor) -> MigrateError {
+    MigrateError::Timeout {
+        what: what,
+        resume: resume.clone(),
+    }
+}
+
+fn source(status: RpcStatus, resume: &Cursor) -> MigrateError {
+    MigrateError::Source {
+        status: status,
+        resume: resume.clone(),
+    }
+}
+
+fn refused(status: RpcStatus, first: &[u8], last: &[u8], resume: &Cursor) -> MigrateError {
+    MigrateError::Destination {
+        status: status,
+        first: first.to_vec(),
+        last: last.to_vec(),
+        resume: resume.clone(),
+    }
+}
+
+fn malformed(resume: &Cursor) -> MigrateError {
+    MigrateError::Malformed {
+        resume: resume.clone(),
+    }
+}
+
+fn file<E: fmt::Display>(e: E) -> MigrateError {
+    MigrateError::File(e.to_string())
+}
+
+// FNV-1a over a value, compared between the source and destination.
+fn checksum(val: &[u8]) -> u64 {
+    val.iter().fold(0xcbf29ce484222325, |h, b| {
+        (h ^ *b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn secs(d: Duration) -> f64 {
+    let secs = d.as_secs() as f64 + d.subsec_nanos() as f64 / 1e9;
+    secs.max(1e-9)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::mem::size_of;
+    use std::net::UdpSocket;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    use db::master::Master;
+
+    use udp::encode;
+
+    // A loopback stand-in for a server, wrapping a Master. Answers dump(), multiput() and
+    // table_access() requests until it is dropped. Refuses every multiput() with
+    // StatusInternalError once `fail_after` batches were written, the way a server that ran
+    // out of memory would.
+    struct StandIn {
+        port: u16,
+        stop: Arc<AtomicBool>,
+        handle: Option<thread::JoinHandle<()>>,
+    }
+
+    impl StandIn {
+        fn new(master: Arc<Master>, fail_after: Option<usize>) -> StandIn {
+            let socket = UdpSocket::bind("127.0.0.1:0").expect("Failed to bind server");
+            socket
+                .set_read_timeout(Some(Duration::from_millis(10)))
+                .expect("Failed to set timeout");
+            let port = socket.local_addr().unwrap().port();
+
+            let stop = Arc::new(AtomicBool::new(false));
+            let flag = stop.clone();
+            let handle = thread::spawn(move || serve(socket, &master, fail_after, &flag));
+
+            StandIn {
+                port: port,
+                stop: stop,
+                handle: Some(handle),
+            }
+        }
+
+        fn connect(&self) -> Endpoint {
+            let mut config = config::ClientConfig::default();
+            config.ip_address = String::from("127.0.0.1");
+            config.server_ip_address = String::from("127.0.0.1");
+            config.udp_client_port = 0;
+            config.udp_server_port = self.port;
+            Endpoint::connect(&config).expect("Failed to connect")
+        }
+    }
+
+    impl Drop for StandIn {
+        fn drop(&mut self) {
+            self.stop.store(true, Ordering::Relaxed);
+            self.handle.take().map(|h| h.join());
+        }
+    }
+
+    fn serve(socket: UdpSocket, master: &Master, fail_after: Option<usize>, stop: &AtomicBool) {
+        let mut written = 0;
+        let mut buf = vec![0; 65536];
+
+        while !stop.load(Ordering::Relaxed) {
+            let (len, src) = match socket.recv_from(&mut buf) {
+                Ok(r) => r,
+                Err(_) => continue,
+            };
+            let req = &buf[..len];
+            let hdr: &RpcRequestHeader = unsafe { &*(req.as_ptr() as *const RpcRequestHeader) };
+            let (tenant, id, stamp) = (hdr.tenant(), hdr.id(), hdr.stamp());
+
+            let res = match req[1] {
+                op if op == OpCode::SandstormDumpRpc as u8 => {
+                    let hdr: &DumpRequest = unsafe { &*(req.as_ptr() as *const DumpRequest) };
+                    let (table, bucket) = (hdr.table_id(), hdr.bucket());
+                    let after = &req[size_of::<DumpRequest>()..];
+
+                    let mut res = DumpResponse::new(id, stamp, tenant);
+                    match master.dump_table(tenant, table, bucket, after, 256) {
+                        Ok((records, num, bucket)) => {
+                            res.set_num_records(num);
+                            res.set_bucket(bucket);
+                            encode(res, &[&records])
+                        }
+                        Err(status) => {
+                            res.common_header.status = status;
+                            encode(res, &[])
+                        }
+                    }
+                }
+
+                op if op == OpCode::SandstormMultiPutRpc as u8 => {
+                    let hdr: &MultiPutRequest =
+                        unsafe { &*(req.as_ptr() as *const MultiPutRequest) };
+                    let (table, num) = (hdr.table_id(), hdr.num_records());
+                    let records = &req[size_of::<MultiPutRequest>()..];
+
+                    let mut res = MultiPutResponse::new(id, stamp, tenant);
+                    res.common_header.status = match fail_after {
+                        Some(n) if num > 0 && written >= n => RpcStatus::StatusInternalError,
+                        _ => master.put_records(tenant, table, records, num),
+                    };
+                    if res.common_header.status == RpcStatus::StatusOk && num > 0 {
+                        written += 1;
+                        res.set_num_written(num);
+                    }
+                    encode(res, &[])
+                }
+
+                _ => {
+                    let hdr: &TableAccessRequest =
+                        unsafe { &*(req.as_ptr() as *const TableAccessRequest) };
+                    let (table, r, w) = (hdr.table_id(), hdr.readable_native, hdr.writable_native);
+
+                    let mut res = TableAccessResponse::new(id, stamp, tenant);
+                    let tenant = master.get_tenant(tenant).expect("No such tenant");
+                    if let Err(status) = tenant.set_native_access(table, r != 0, w != 0) {
+                        res.common_header.status = status;
+                    }
+                    encode(res, &[])
+                }
+            };
+
+            socket.send_to(&res, src).expect("Server send failed");
+        }
+    }
+
+    fn migrate_config() -> MigrateConfig {
+        let mut config = MigrateConfig::default();
+        config.batch_records = 16;
+        config.in_flight = 4;
+        config.sample = 7;
+        config
+    }
+
+    // Tests that a migration that stopped on a destination failure can be resumed off the cursor
+    // on it's error, and that the resumed migration copies the whole table.
+    #[test]
+    fn test_migrate_resume() {
+        let (from, to) = (Arc::new(Master::new()), Arc::new(Master::new()));
+        from.fill_test(1, 1, 500);
+        to.fill_test(1, 1, 0);
+
+        let src = StandIn::new(from.clone(), None);
+        let mut config = migrate_config();
+
+        let err = {
+            let dst = StandIn::new(to.clone(), Some(5));
+            let mut last = 0;
+            let res = migrate(&src.connect(), &dst.connect(), &config, &mut |p| {
+                assert!(p.records > last);
+                last = p.records;
+            });
+            assert!(last <= 5 * 16);
+            res.expect_err("Migration should have failed")
+        };
+
+        match err {
+            MigrateError::Destination {
+                ref status,
+                ref first,
+                ref last,
+                ..
+            } => {
+                assert_eq!(RpcStatus::StatusInternalError, *status);
+                assert!(!first.is_empty() && !last.is_empty());
+            }
+            ref e => panic!("Unexpected error {}", e),
+        }
+
+        let resume = err.resume().cloned().expect("No resume cursor");
+        assert_eq!(Some(resume.clone()), Cursor::parse(&resume.to_string()));
+        config.resume = resume;
+        config.cutover = true;
+
+        let dst = StandIn::new(to.clone(), None);
+        let summary = migrate(&src.connect(), &dst.connect(), &config, &mut |_| {})
+            .expect("Resumed migration failed");
+        assert_eq!(500, summary.records);
+        assert!(summary.sampled >= 500 / 7);
+        assert!(summary.progress.records < 500 && summary.cutover);
+
+        let mut batch = Vec::new();
+        rpc::append_kv(&mut batch, b"key", b"value");
+        assert_eq!(
+            RpcStatus::StatusPermissionDenied,
+            from.put_records(1, 1, &batch, 1)
+        );
+    }
+
+    // Tests that a migration to a server without the table stops before copying anything.
+    #[test]
+    fn test_migrate_missing_table() {
+        let (from, to) = (Arc::new(Master::new()), Arc::new(Master::new()));
+        from.fill_test(1, 1, 100);
+        to.fill_test(1, 2, 0);
+
+        let (src, dst) = (StandIn::new(from, None), StandIn::new(to, None));
+        let mut copied = 0;
+        let err = migrate(
+            &src.connect(),
+            &dst.connect(),
+            &migrate_config(),
+            &mut |p| copied = p.records,
+        );
+
+        assert_eq!(0, copied);
+        assert_eq!(
+            Err(MigrateError::Destination {
+                status: RpcStatus::StatusTableDoesNotExist,
+                first: vec![],
+                last: vec![],
+                resume: Cursor::default(),
+            }),
+            err.map(|s| s.records)
+        );
+    }
+
+    // Tests that a table exported off one server and imported onto another holds exactly the
+    // records on the original, and that a corrupt file stops the import.
+    #[test]
+    fn test_export_import() {
+        let (from, to) = (Arc::new(Master::new()), Arc::new(Master::new()));
+        from.fill_test(1, 1, 500);
+        to.fill_test(1, 1, 0);
+
+        let (src, dst) = (StandIn::new(from, None), StandIn::new(to, None));
+        let config = migrate_config();
+        let (exported, saved) = export(&src.connect(), &config, Vec::new()).expect("Export failed");
+        assert_eq!(500, saved);
+
+        let mut batches = 0;
+        let stats = import(&dst.connect(), &config, &exported[..], &mut |p| {
+            batches = p.batches
+        })
+        .expect("Import failed");
+        assert_eq!((500, batches), (stats.records, stats.batches));
+        assert!(batches >= 500 / 16);
+
+        // Both tables hold the same keys, so they are dumped in the same order.
+        let (copy, _) = export(&dst.connect(), &config, Vec::new()).expect("Export failed");
+        assert_eq!(exported, copy);
+
+        let mut corrupt = exported.clone();
+        let len = corrupt.len();
+        corrupt[len / 2] ^= 0x1;
+        match import(&dst.connect(), &config, &corrupt[..], &mut |_| {}) {
+            Err(ref e @ MigrateError::File(_)) => assert_eq!(None, e.resume()),
+            res => panic!("Unexpected result {:?}", res.map(|p| p.records)),
+        }
+    }
+}
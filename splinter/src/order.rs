@@ -0,0 +1,493 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use std::collections::{HashMap, VecDeque};
+
+use db::config::ClientConfig;
+
+/// The number of operations that can be deferred at once, if not set in the client config.
+const MAX_DEFERRED: usize = 1024;
+
+/// The kind of operation being ordered.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OrderOp {
+    /// A read. Only ordered after outstanding writes in read-your-writes mode.
+    Get,
+
+    /// A write. Always ordered after outstanding writes to the same key.
+    Put,
+}
+
+/// The outcome of admitting an operation.
+#[derive(Debug, PartialEq)]
+pub enum Admit<R> {
+    /// Nothing to the same key is outstanding; the request must be sent out right away.
+    Send(R),
+
+    /// The request was queued behind an outstanding write to the same key. It will be handed
+    /// back by ready() once it's predecessors complete.
+    Deferred,
+
+    /// The request had to be deferred, but the deferred queue is full. Nothing was recorded; the
+    /// caller must stop generating requests, and retry this one after a response arrives.
+    Full(R),
+}
+
+// A request waiting for a write to it's key to complete.
+struct Waiting<R> {
+    id: u64,
+    op: OrderOp,
+    req: R,
+
+    // The time-stamp at which the request was deferred.
+    at: u64,
+}
+
+// The state of a key that has a write outstanding.
+struct Slot<R> {
+    // Requests to the key that were issued after the outstanding write, in issue order.
+    queue: VecDeque<Waiting<R>>,
+}
+
+/// Orders operations to the same key issued by a single client pipeline. A put is only sent
+/// out once the previous put to the same key has completed, so that the server cannot apply
+/// them out of order. In read-your-writes mode, gets wait for outstanding puts too.
+///
+/// Keys are tracked by a 64 bit hash; a collision only orders two unrelated keys, which is
+/// safe. Admitting an operation to a key without an outstanding write costs one hash probe.
+pub struct KeyOrder<R> {
+    // If true, gets are ordered after outstanding puts to the same key.
+    read_your_writes: bool,
+
+    // The maximum number of requests that can be deferred at once.
+    capacity: usize,
+
+    // Keys with an outstanding write, keyed on the key's hash.
+    slots: HashMap<u64, Slot<R>>,
+
+    // The key hash of every outstanding write, keyed on the write's request id.
+    writers: HashMap<u64, u64>,
+
+    // Deferred requests that can now be sent out, in the order they must be sent.
+    ready: VecDeque<R>,
+
+    // The number of requests currently deferred, including ones in `ready`.
+    queued: usize,
+
+    // The number of requests deferred so far, the number of times the deferred queue was
+    // full, and the total number of cycles deferred requests waited.
+    deferred: u64,
+    full: u64,
+    delay: u64,
+}
+
+impl<R> KeyOrder<R> {
+    /// Creates a KeyOrder.
+    ///
+    /// # Arguments
+    ///
+    /// * `read_your_writes`: If true, gets are also ordered after outstanding puts.
+    /// * `capacity`:         The maximum number of requests that can be deferred at once.
+    pub fn new(read_your_writes: bool, capacity: usize) -> KeyOrder<R> {
+        KeyOrder {
+            read_your_writes: read_your_writes,
+            capacity: capacity,
+            slots: HashMap::new(),
+            writers: HashMap::new(),
+            ready: VecDeque::new(),
+            queued: 0,
+            deferred: 0,
+            full: 0,
+            delay: 0,
+        }
+    }
+
+    /// Creates a KeyOrder as configured in the client config.
+    ///
+    /// # Return
+    ///
+    /// None if per-key ordering is disabled.
+    pub fn from_config(config: &ClientConfig) -> Option<KeyOrder<R>> {
+        if !config.key_order {
+            return None;
+        }
+
+        let capacity = match config.key_order_deferred {
+            0 => MAX_DEFERRED,
+            n => n,
+        };
+        Some(KeyOrder::new(config.read_your_writes, capacity))
+    }
+
+    /// Admits an operation that is about to be issued.
+    ///
+    /// # Arguments
+    ///
+    /// * `key`: The key the operation is for.
+    /// * `op`:  The kind of operation.
+    /// * `id`:  The id the request will be sent out with. complete() must be called with it
+    ///          once the response arrives.
+    /// * `req`: The request, handed back when it can be sent out.
+    /// * `now`: The current time-stamp in cycles.
+    ///
+    /// # Return
+    ///
+    /// Whether the request must be sent out, was deferred, or could not be deferred.
+    pub fn admit(&mut self, key: &[u8], op: OrderOp, id: u64, req: R, now: u64) -> Admit<R> {
+        if op == OrderOp::Get && !self.read_your_writes {
+            return Admit::Send(req);
+        }
+
+        let hash = hash(key);
+        if let Some(slot) = self.slots.get_mut(&hash) {
+            if self.queued >= self.capacity {
+                self.full += 1;
+                return Admit::Full(req);
+            }
+
+            slot.queue.push_back(Waiting {
+                id: id,
+                op: op,
+                req: req,
+                at: now,
+            });
+            self.queued += 1;
+            self.deferred += 1;
+            return Admit::Deferred;
+        }
+
+        if op == OrderOp::Put {
+            let queue = VecDeque::new();
+            self.slots.insert(hash, Slot { queue: queue });
+            self.writers.insert(id, hash);
+        }
+
+        Admit::Send(req)
+    }
+
+    /// Records that a response arrived for a request, or that it was given up on. If it was an
+    /// outstanding put, the requests deferred behind it are released upto and including the
+    /// next put to the same key, and can be picked up using ready().
+    ///
+    /// # Arguments
+    ///
+    /// * `id`:  The id on the response.
+    /// * `now`: The current time-stamp in cycles.
+    pub fn complete(&mut self, id: u64, now: u64) {
+        let hash = match self.writers.remove(&id) {
+            Some(hash) => hash,
+            None => return,
+        };
+
+        let mut slot = match self.slots.remove(&hash) {
+            Some(slot) => slot,
+            None => return,
+        };
+
+        while let Some(w) = slot.queue.pop_front() {
+            self.delay += now.saturating_sub(w.at);
+            self.ready.push_back(w.req);
+
+            // The released put is the key's new outstanding write. Anything behind it keeps
+            // waiting.
+            if w.op == OrderOp::Put {
+                self.writers.insert(w.id, hash);
+                self.slots.insert(hash, slot);
+                return;
+            }
+        }
+    }
+
+    /// Returns the next deferred request that can be sent out, if any.
+    pub fn ready(&mut self) -> Option<R> {
+        let req = self.ready.pop_front();
+        if req.is_some() {
+            self.queued -= 1;
+        }
+
+        req
+    }
+
+    /// Returns the number of requests currently deferred.
+    pub fn queued(&self) -> usize {
+        self.queued
+    }
+
+    /// Returns the number of requests deferred so far.
+    pub fn deferred(&self) -> u64 {
+        self.deferred
+    }
+
+    /// Returns the number of times a request could not be deferred because the queue was full.
+    pub fn full(&self) -> u64 {
+        self.full
+    }
+
+    /// Returns the total number of cycles deferred requests spent waiting on their
+    /// predecessors. This is the latency added by ordering.
+    pub fn delay(&self) -> u64 {
+        self.delay
+    }
+}
+
+// FNV-1a over a key. Cheap, and good enough to tell keys apart; a collision only costs ordering
+// two unrelated keys.
+fn hash(key: &[u8]) -> u64 {
+    key.iter().fold(0xcbf29ce484222325, |h, b| {
+        (h ^ *b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+// This module contains unit tests for KeyOrder.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::{Rng, SeedableRng, XorShiftRng};
+
+    // A request as seen by the stubbed transport: the key, whether it is a put, the sequence
+    // number it was issued with, and it's id.
+    #[derive(Clone, Debug, PartialEq)]
+    struct Req {
+        key: u8,
+        put: bool,
+        seq: u64,
+        id: u64,
+    }
+
+    // A stubbed transport and server. Requests sent out are applied in a random order, as a
+    // server with several cores could, and responses come back in the order they are applied.
+    struct StubServer {
+        rng: XorShiftRng,
+        inflight: Vec<Req>,
+
+        // The sequence number of the last put applied to each key.
+        store: HashMap<u8, u64>,
+
+        // The sequence numbers of puts to each key, in the order they went out on the wire.
+        wire: HashMap<u8, Vec<u64>>,
+
+        // The number of puts applied out of issue order.
+        alarms: u64,
+    }
+
+    impl StubServer {
+        fn new() -> StubServer {
+            StubServer {
+                rng: XorShiftRng::from_seed([1, 2, 3, 4]),
+                inflight: Vec::new(),
+                store: HashMap::new(),
+                wire: HashMap::new(),
+                alarms: 0,
+            }
+        }
+
+        fn send(&mut self, req: Req) {
+            if req.put {
+                self.wire.entry(req.key).or_insert(Vec::new()).push(req.seq);
+            }
+            self.inflight.push(req);
+        }
+
+        // Applies a random in-flight request, returning it as it's response.
+        fn recv(&mut self) -> Option<Req> {
+            if self.inflight.is_empty() {
+                return None;
+            }
+
+            let idx = self.rng.gen_range(0, self.inflight.len());
+            let req = self.inflight.swap_remove(idx);
+            let last = self.store.get(&req.key).cloned().unwrap_or(0);
+            if req.put {
+                if req.seq < last {
+                    self.alarms += 1;
+                }
+                self.store.insert(req.key, req.seq.max(last));
+            }
+
+            Some(req)
+        }
+    }
+
+    // Drives a conflict heavy sequence over 4 keys through the stubbed server with a window of
+    // 16 outstanding requests, returning the server and the ordering state.
+    fn drive(order: Option<KeyOrder<Req>>, n: u64) -> (StubServer, Option<KeyOrder<Req>>) {
+        let mut order = order;
+        let mut server = StubServer::new();
+        let mut rng = XorShiftRng::from_seed([5, 6, 7, 8]);
+
+        let (mut seq, mut outstanding, mut now) = (0, 0, 0);
+        let mut held: Option<Req> = None;
+
+        while seq < n || outstanding > 0 {
+            now += 1;
+
+            // Issue requests until the window is full, or ordering pushes back.
+            while outstanding < 16 && (held.is_some() || seq < n) {
+                let req = held.take().unwrap_or_else(|| {
+                    seq += 1;
+                    Req {
+                        key: rng.gen_range(0, 4),
+                        put: rng.gen_weighted_bool(2),
+                        seq: seq,
+                        id: seq,
+                    }
+                });
+                let (key, op) = (req.key, if req.put { OrderOp::Put } else { OrderOp::Get });
+                let admitted = match order {
+                    Some(ref mut order) => order.admit(&[key], op, req.id, req, now),
+                    None => Admit::Send(req),
+                };
+
+                match admitted {
+                    Admit::Send(req) => server.send(req),
+                    Admit::Deferred => {}
+                    Admit::Full(req) => {
+                        held = Some(req);
+                        break;
+                    }
+                }
+                outstanding += 1;
+            }
+
+            // Receive a single response, and send out whatever it released.
+            if let Some(res) = server.recv() {
+                outstanding -= 1;
+                if let Some(ref mut order) = order {
+                    order.complete(res.id, now);
+                    while let Some(req) = order.ready() {
+                        server.send(req);
+                    }
+                }
+            }
+        }
+
+        (server, order)
+    }
+
+    #[test]
+    fn test_hash() {
+        assert_eq!(0xcbf29ce484222325, hash(&[]));
+        assert_eq!(hash(b"key"), hash(b"key"));
+        assert!(hash(b"key1") != hash(b"key2"));
+    }
+
+    #[test]
+    fn test_order_no_conflict() {
+        let mut order = KeyOrder::new(true, 4);
+        assert_eq!(Admit::Send(1), order.admit(b"a", OrderOp::Put, 1, 1, 0));
+        assert_eq!(Admit::Send(2), order.admit(b"b", OrderOp::Put, 2, 2, 0));
+        assert_eq!(Admit::Send(3), order.admit(b"c", OrderOp::Get, 3, 3, 0));
+
+        // Gets are never tracked, so completing them is a no-op.
+        order.complete(3, 1);
+        order.complete(1, 1);
+        order.complete(2, 1);
+        assert_eq!(None, order.ready());
+        assert_eq!(0, order.deferred());
+        assert!(order.slots.is_empty() && order.writers.is_empty());
+    }
+
+    // Tests that puts to a key go out one at a time, and that gets are only ordered after
+    // puts in read-your-writes mode.
+    #[test]
+    fn test_order_fifo() {
+        let mut order = KeyOrder::new(false, 8);
+        assert_eq!(Admit::Send(1), order.admit(b"a", OrderOp::Put, 1, 1, 0));
+        assert_eq!(Admit::Deferred, order.admit(b"a", OrderOp::Put, 2, 2, 1));
+        assert_eq!(Admit::Send(3), order.admit(b"a", OrderOp::Get, 3, 3, 1));
+        assert_eq!(Admit::Deferred, order.admit(b"a", OrderOp::Put, 4, 4, 2));
+        assert_eq!(2, order.queued());
+
+        order.complete(1, 10);
+        assert_eq!(Some(2), order.ready());
+        assert_eq!(None, order.ready());
+
+        order.complete(2, 20);
+        assert_eq!(Some(4), order.ready());
+        order.complete(4, 30);
+        assert_eq!(None, order.ready());
+
+        assert_eq!(0, order.queued());
+        assert_eq!(2, order.deferred());
+        assert_eq!(9 + 18, order.delay());
+        assert!(order.slots.is_empty() && order.writers.is_empty());
+    }
+
+    #[test]
+    fn test_order_read_your_writes() {
+        let mut order = KeyOrder::new(true, 8);
+        assert_eq!(Admit::Send(1), order.admit(b"a", OrderOp::Put, 1, 1, 0));
+        assert_eq!(Admit::Deferred, order.admit(b"a", OrderOp::Get, 2, 2, 0));
+        assert_eq!(Admit::Deferred, order.admit(b"a", OrderOp::Get, 3, 3, 0));
+        assert_eq!(Admit::Deferred, order.admit(b"a", OrderOp::Put, 4, 4, 0));
+        assert_eq!(Admit::Deferred, order.admit(b"a", OrderOp::Get, 5, 5, 0));
+
+        // Both gets are released along with the put behind them; the last get waits on it.
+        order.complete(1, 1);
+        assert_eq!(Some(2), order.ready());
+        assert_eq!(Some(3), order.ready());
+        assert_eq!(Some(4), order.ready());
+        assert_eq!(None, order.ready());
+
+        order.complete(4, 2);
+        assert_eq!(Some(5), order.ready());
+        assert!(order.slots.is_empty());
+    }
+
+    // Tests that the deferred queue is bounded, and that a full queue pushes back.
+    #[test]
+    fn test_order_bounded() {
+        let mut order = KeyOrder::new(false, 2);
+        assert_eq!(Admit::Send(1), order.admit(b"a", OrderOp::Put, 1, 1, 0));
+        assert_eq!(Admit::Deferred, order.admit(b"a", OrderOp::Put, 2, 2, 0));
+        assert_eq!(Admit::Deferred, order.admit(b"a", OrderOp::Put, 3, 3, 0));
+        assert_eq!(Admit::Full(4), order.admit(b"a", OrderOp::Put, 4, 4, 0));
+        assert_eq!(Admit::Send(5), order.admit(b"b", OrderOp::Put, 5, 5, 0));
+        assert_eq!(1, order.full());
+
+        // Room frees up only once a released request has been picked up.
+        order.complete(1, 1);
+        assert_eq!(Admit::Full(4), order.admit(b"a", OrderOp::Put, 4, 4, 1));
+        assert_eq!(Some(2), order.ready());
+        assert_eq!(Admit::Deferred, order.admit(b"a", OrderOp::Put, 4, 4, 1));
+    }
+
+    // Tests that the stubbed server reorders puts to the same key when nothing orders them.
+    #[test]
+    fn test_order_disabled_reorders() {
+        let (server, _) = drive(None, 2000);
+        assert!(server.alarms > 0);
+    }
+
+    // Tests that with ordering enabled, puts to every key go out on the wire in issue order,
+    // and the server never applies them out of order, even though it reorders responses.
+    #[test]
+    fn test_order_conflict_heavy() {
+        for &ryw in [false, true].iter() {
+            let (server, order) = drive(Some(KeyOrder::new(ryw, 4)), 2000);
+            let order = order.unwrap();
+
+            for seqs in server.wire.values() {
+                assert!(seqs.windows(2).all(|w| w[0] < w[1]));
+            }
+            assert_eq!(0, server.alarms);
+            assert!(order.deferred() > 0);
+            assert!(order.full() > 0);
+            assert_eq!(0, order.queued());
+            assert!(order.slots.is_empty());
+        }
+    }
+}
@@ -0,0 +1,444 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, Once, ONCE_INIT};
+
+use db::config::ClientConfig;
+use db::cycles;
+use db::wireformat::RpcResponseHeader;
+
+/// The largest window DPDK clients pace upto, if `window_max` is not set in the client config.
+pub const DEFAULT_WINDOW: u32 = 32;
+
+/// Server load below which the window grows, if not set in the client config.
+const DEFAULT_LOAD_LOW: u16 = 4;
+
+/// Server load above which the window is cut, if not set in the client config.
+const DEFAULT_LOAD_HIGH: u16 = 16;
+
+/// The weight given to a new load sample in the per-destination moving average.
+const EWMA_ALPHA: f64 = 0.25;
+
+/// The factor the window is multiplied by on a backoff.
+const BACKOFF: f64 = 0.5;
+
+/// Parameters of the AIMD controller.
+#[derive(Clone, Debug)]
+pub struct AimdConfig {
+    /// The smallest the window is allowed to get.
+    pub min: u32,
+
+    /// The largest the window is allowed to get.
+    pub max: u32,
+
+    /// Smoothed load below which the window is grown by one.
+    pub low: u16,
+
+    /// Smoothed load above which the window is cut by BACKOFF.
+    pub high: u16,
+}
+
+impl AimdConfig {
+    /// Builds AIMD parameters from a client config, filling in defaults for the fields that
+    /// were left unset.
+    ///
+    /// # Arguments
+    ///
+    /// * `config`: The client config to read the window bounds and watermarks from.
+    /// * `window`: The fixed window the client uses when pacing is disabled. This is the
+    ///             default upper bound on the window.
+    pub fn from_config(config: &ClientConfig, window: u32) -> AimdConfig {
+        let or = |v: u32, d: u32| if v == 0 { d } else { v };
+        AimdConfig {
+            min: or(config.window_min, 1),
+            max: or(config.window_max, window),
+            low: or(config.load_low as u32, DEFAULT_LOAD_LOW as u32) as u16,
+            high: or(config.load_high as u32, DEFAULT_LOAD_HIGH as u32) as u16,
+        }
+    }
+}
+
+/// Paces the number of requests a client keeps outstanding using the load servers piggyback
+/// on responses. Maintains a moving average of the load reported by each destination, and
+/// adjusts a single window AIMD style: +1 on every response while the destination's average
+/// is below the low watermark, and a multiplicative cut when it is above the high watermark.
+/// Backoffs are limited to one per window's worth of responses, so that a burst of responses
+/// from one overloaded period does not collapse the window to the minimum.
+pub struct Pacer {
+    // Window bounds and watermarks.
+    config: AimdConfig,
+
+    // The current window.
+    window: u32,
+
+    // The number of responses observed since the window was last cut.
+    since_backoff: u32,
+
+    // Moving average of the load reported by each destination (keyed by UDP port).
+    loads: HashMap<u16, f64>,
+
+    // (time-stamp, window, smoothed load) every time the window changed.
+    trajectory: Vec<(u64, u32, f64)>,
+}
+
+impl Pacer {
+    /// Creates a pacer whose window starts out at the configured maximum.
+    ///
+    /// # Arguments
+    ///
+    /// * `config`: Window bounds and watermarks. `min` must be at most `max`, and `low` must be
+    ///             at most `high`.
+    pub fn new(config: AimdConfig) -> Pacer {
+        assert!(config.min > 0 && config.min <= config.max);
+        assert!(config.low <= config.high);
+
+        let window = config.max;
+        Pacer {
+            config: config,
+            window: window,
+            since_backoff: window,
+            loads: HashMap::new(),
+            trajectory: Vec::new(),
+        }
+    }
+
+    /// Returns the number of requests that may currently be outstanding.
+    #[inline]
+    pub fn window(&self) -> u32 {
+        self.window
+    }
+
+    /// Returns the moving average of the load reported by a destination, if it has responded.
+    pub fn load(&self, dst: u16) -> Option<f64> {
+        self.loads.get(&dst).cloned()
+    }
+
+    /// Returns the (time-stamp, window, smoothed load) tuples recorded every time the window
+    /// changed, in the order they were recorded.
+    pub fn trajectory(&self) -> &[(u64, u32, f64)] {
+        &self.trajectory
+    }
+
+    /// Feeds the load on a response header into the pacer. See observe().
+    pub fn observe_response(&mut self, dst: u16, hdr: &RpcResponseHeader, now: u64) -> u32 {
+        let load = hdr.load();
+        self.observe(dst, load, now)
+    }
+
+    /// Feeds a load sample reported by a destination into the pacer, and adjusts the window.
+    ///
+    /// # Arguments
+    ///
+    /// * `dst`:  The destination (UDP port) that reported the load.
+    /// * `load`: The load it reported.
+    /// * `now`:  The current time-stamp, recorded on the trajectory if the window changes.
+    ///
+    /// # Return
+    ///
+    /// The new window.
+    pub fn observe(&mut self, dst: u16, load: u16, now: u64) -> u32 {
+        let load = load as f64;
+        let avg = {
+            let avg = self.loads.entry(dst).or_insert(load);
+            *avg = EWMA_ALPHA * load + (1.0 - EWMA_ALPHA) * *avg;
+            *avg
+        };
+
+        self.since_backoff = self.since_backoff.saturating_add(1);
+
+        let old = self.window;
+        if avg < self.config.low as f64 {
+            self.window = (self.window + 1).min(self.config.max);
+        } else if avg > self.config.high as f64 && self.since_backoff >= self.window {
+            self.window = ((self.window as f64 * BACKOFF) as u32).max(self.config.min);
+            self.since_backoff = 0;
+        }
+
+        if self.window != old {
+            self.trajectory.push((now, self.window, avg));
+        }
+
+        self.window
+    }
+}
+
+/// A Pacer shared by a pipeline's dispatch::Sender and dispatch::Receiver. The sender counts
+/// every request it sends out against the window, and the receiver feeds the load on every
+/// response into the pacer and frees up the slot it's request held. Open-loop generators issue
+/// atmost room() requests at a time, so that requests due while the window is full are sent out
+/// late, once responses free it up.
+pub struct SharedPacer {
+    // The pacer, and the number of requests outstanding on it's window.
+    inner: Mutex<(Pacer, u64)>,
+}
+
+impl SharedPacer {
+    /// Creates a shared pacer with no requests outstanding.
+    ///
+    /// # Arguments
+    ///
+    /// * `pacer`: The pacer whose window requests are counted against.
+    pub fn new(pacer: Pacer) -> SharedPacer {
+        SharedPacer {
+            inner: Mutex::new((pacer, 0)),
+        }
+    }
+
+    /// Returns the number of requests that can be sent out before the window fills up.
+    pub fn room(&self) -> u64 {
+        let inner = self.inner.lock().unwrap();
+        (inner.0.window() as u64).saturating_sub(inner.1)
+    }
+
+    /// Counts requests sent out against the window.
+    ///
+    /// # Arguments
+    ///
+    /// * `num`: The number of requests sent out.
+    pub fn sent(&self, num: u64) {
+        self.inner.lock().unwrap().1 += num;
+    }
+
+    /// Feeds the load on a response into the pacer, and frees up the slot it's request held.
+    ///
+    /// # Arguments
+    ///
+    /// * `dst`: The destination (UDP port) that sent the response.
+    /// * `hdr`: The response's header.
+    /// * `now`: The current time-stamp, recorded on the trajectory if the window changes.
+    ///
+    /// # Return
+    ///
+    /// The new window.
+    pub fn received(&self, dst: u16, hdr: &RpcResponseHeader, now: u64) -> u32 {
+        let mut inner = self.inner.lock().unwrap();
+        inner.1 = inner.1.saturating_sub(1);
+        inner.0.observe_response(dst, hdr, now)
+    }
+
+    /// Returns the number of requests outstanding on the window.
+    pub fn outstanding(&self) -> u64 {
+        self.inner.lock().unwrap().1
+    }
+
+    /// Returns the current window.
+    pub fn window(&self) -> u32 {
+        self.inner.lock().unwrap().0.window()
+    }
+
+    /// Returns the (time-stamp, window, smoothed load) tuples recorded every time the window
+    /// changed. Refer to Pacer::trajectory().
+    pub fn trajectory(&self) -> Vec<(u64, u32, f64)> {
+        self.inner.lock().unwrap().0.trajectory().to_vec()
+    }
+}
+
+// The pacers of the pipelines on this client, created by pipeline().
+static PACERS_INIT: Once = ONCE_INIT;
+static mut PACERS: Option<Mutex<HashMap<usize, Arc<SharedPacer>>>> = None;
+
+/// Returns the pacer shared by a pipeline's sender and receiver, or None if the config does not
+/// turn congestion aware pacing on. The first call for a pipeline creates it's pacer; every later
+/// call returns the same one.
+///
+/// # Arguments
+///
+/// * `config`:   Client configuration.
+/// * `pipeline`: The index of the pipeline.
+pub fn pipeline(config: &ClientConfig, pipeline: usize) -> Option<Arc<SharedPacer>> {
+    if !config.congestion_aware {
+        return None;
+    }
+
+    unsafe {
+        PACERS_INIT.call_once(|| {
+            PACERS = Some(Mutex::new(HashMap::new()));
+        });
+
+        let pacers = PACERS.as_ref().unwrap();
+        let mut pacers = pacers.lock().unwrap();
+        let pacer = pacers.entry(pipeline).or_insert_with(|| {
+            let pacer = Pacer::new(AimdConfig::from_config(config, DEFAULT_WINDOW));
+            Arc::new(SharedPacer::new(pacer))
+        });
+        Some(Arc::clone(pacer))
+    }
+}
+
+/// Prints the trajectory of a pipeline's window, one line per change, with time-stamps in
+/// microseconds relative to the first change.
+///
+/// # Arguments
+///
+/// * `label`:      Prefixed to every line, ex: the name of the client.
+/// * `pipeline`:   The index of the pipeline.
+/// * `trajectory`: The trajectory, as returned by Pacer::trajectory().
+pub fn print_trajectory(label: &str, pipeline: usize, trajectory: &[(u64, u32, f64)]) {
+    let start = trajectory.first().map_or(0, |&(stamp, _, _)| stamp);
+    let hz = cycles::cycles_per_second() as f64;
+    for &(stamp, window, load) in trajectory.iter() {
+        let us = (stamp - start) as f64 * 1e6 / hz;
+        println!(
+            "{} Window {} {:.0} {} {:.2}",
+            label, pipeline, us, window, load
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use db::wireformat::{OpCode, RpcResponseHeader};
+    use std::mem::size_of;
+    use std::slice;
+    use udp::Response;
+
+    fn config(min: u32, max: u32) -> AimdConfig {
+        AimdConfig {
+            min: min,
+            max: max,
+            low: 4,
+            high: 16,
+        }
+    }
+
+    // A stand-in for a receiver that hands out responses carrying a scripted sequence of loads.
+    fn stub_responses(loads: &[u16]) -> Vec<Response> {
+        loads
+            .iter()
+            .enumerate()
+            .map(|(i, &load)| {
+                let mut hdr = RpcResponseHeader::new(i as u64, 0, OpCode::SandstormGetRpc, 1);
+                hdr.set_load(load);
+                let bytes = unsafe {
+                    slice::from_raw_parts(
+                        &hdr as *const RpcResponseHeader as *const u8,
+                        size_of::<RpcResponseHeader>(),
+                    )
+                };
+                Response::new(bytes.to_vec())
+            })
+            .collect()
+    }
+
+    // Runs scripted responses through a pacer, returning the window after each one.
+    fn run(pacer: &mut Pacer, loads: &[u16]) -> Vec<u32> {
+        stub_responses(loads)
+            .iter()
+            .enumerate()
+            .map(|(i, r)| {
+                let p = r.parse_header::<RpcResponseHeader>().unwrap();
+                pacer.observe_response(0, p.get_header(), i as u64)
+            })
+            .collect()
+    }
+
+    // Tests that the window grows by one per response under low load, and stops at max.
+    #[test]
+    fn test_pacer_additive_increase_clamped() {
+        let mut pacer = Pacer::new(config(1, 8));
+        run(&mut pacer, &[20; 16]);
+        assert_eq!(1, pacer.window());
+
+        let windows = run(&mut pacer, &[0; 64]);
+        // The average has to fall below the low watermark before the window grows.
+        let first = windows.iter().position(|&w| w > 1).unwrap();
+        assert_eq!(
+            vec![2, 3, 4, 5, 6, 7, 8, 8, 8],
+            windows[first..first + 9].to_vec()
+        );
+        assert_eq!(8, *windows.last().unwrap());
+    }
+
+    // Tests that the window is halved at most once per window of responses, and stops at min.
+    #[test]
+    fn test_pacer_multiplicative_backoff_clamped() {
+        let mut pacer = Pacer::new(config(2, 32));
+        let windows = run(&mut pacer, &[100; 64]);
+
+        // The first sample seeds the average, so the first response backs off immediately.
+        assert_eq!(16, windows[0]);
+        assert_eq!(vec![16; 15], windows[1..16].to_vec());
+        assert_eq!(8, windows[16]);
+        assert_eq!(vec![8; 7], windows[17..24].to_vec());
+        assert_eq!(4, windows[24]);
+        assert_eq!(2, windows[28]);
+        assert_eq!(2, *windows.last().unwrap());
+
+        let traj: Vec<u32> = pacer.trajectory().iter().map(|&(_, w, _)| w).collect();
+        assert_eq!(vec![16, 8, 4, 2], traj);
+    }
+
+    // Tests that the window holds steady between the watermarks.
+    #[test]
+    fn test_pacer_hold_between_watermarks() {
+        let mut pacer = Pacer::new(config(1, 16));
+        let windows = run(&mut pacer, &[10; 32]);
+        assert_eq!(vec![16; 32], windows);
+        assert!(pacer.trajectory().is_empty());
+    }
+
+    // Tests that loads are smoothed per destination.
+    #[test]
+    fn test_pacer_per_destination_ewma() {
+        let mut pacer = Pacer::new(config(1, 16));
+        pacer.observe(1, 8, 0);
+        pacer.observe(2, 0, 0);
+        pacer.observe(1, 0, 0);
+        assert_eq!(Some(6.0), pacer.load(1));
+        assert_eq!(Some(0.0), pacer.load(2));
+        assert_eq!(None, pacer.load(3));
+    }
+
+    // Tests that unset config fields fall back to defaults.
+    #[test]
+    fn test_aimd_config_defaults() {
+        let c = AimdConfig::from_config(&ClientConfig::default(), 32);
+        assert_eq!(1, c.min);
+        assert_eq!(32, c.max);
+        assert_eq!(DEFAULT_LOAD_LOW, c.low);
+        assert_eq!(DEFAULT_LOAD_HIGH, c.high);
+    }
+
+    // Tests that requests sent out through a shared pacer are held to it's window, and that
+    // scripted responses free up their slots while moving the window.
+    #[test]
+    fn test_shared_pacer_room() {
+        let shared = SharedPacer::new(Pacer::new(config(2, 8)));
+        assert_eq!(8, shared.room());
+
+        shared.sent(8);
+        assert_eq!(0, shared.room());
+
+        // An overloaded response frees up a slot, but halves the window under the 7 left
+        // outstanding.
+        let responses = stub_responses(&[100, 0, 0]);
+        let receive = |i: usize| {
+            let p = responses[i].parse_header::<RpcResponseHeader>().unwrap();
+            shared.received(0, p.get_header(), i as u64)
+        };
+        assert_eq!(4, receive(0));
+        assert_eq!((7, 0), (shared.outstanding(), shared.room()));
+
+        // Responses under load keep freeing slots, but the window only opens once the average
+        // load falls under the low watermark.
+        assert_eq!(vec![4, 4], vec![receive(1), receive(2)]);
+        assert_eq!((5, 4), (shared.outstanding(), shared.window()));
+
+        let traj: Vec<u32> = shared.trajectory().iter().map(|&(_, w, _)| w).collect();
+        assert_eq!(vec![4], traj);
+    }
+}
@@ -0,0 +1,703 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use std::fmt;
+use std::io;
+use std::mem;
+use std::os::unix::io::RawFd;
+
+use libc;
+use serde_json;
+
+// The type of the generalized hardware events on perf_event_attr.
+const PERF_TYPE_HARDWARE: u32 = 0;
+
+// The config on perf_event_attr for each of the generalized hardware events counted.
+const PERF_COUNT_HW_CPU_CYCLES: u64 = 0;
+const PERF_COUNT_HW_INSTRUCTIONS: u64 = 1;
+const PERF_COUNT_HW_CACHE_MISSES: u64 = 3;
+const PERF_COUNT_HW_BRANCH_MISSES: u64 = 5;
+
+// Bits of the flags on perf_event_attr. Events in the kernel and hypervisor are not counted, so
+// that counters can be opened with the default perf_event_paranoid of 2.
+const ATTR_DISABLED: u64 = 1 << 0;
+const ATTR_EXCLUDE_KERNEL: u64 = 1 << 5;
+const ATTR_EXCLUDE_HV: u64 = 1 << 6;
+
+// A read() off the group's leader returns the number of counters, the time the group was
+// enabled and running for, and then the value of every counter.
+const PERF_FORMAT_TOTAL_TIME_ENABLED: u64 = 1 << 0;
+const PERF_FORMAT_TOTAL_TIME_RUNNING: u64 = 1 << 1;
+const PERF_FORMAT_GROUP: u64 = 1 << 3;
+
+// Flags to perf_event_open(), and the ioctls that start and stop a group of counters.
+const PERF_FLAG_FD_CLOEXEC: libc::c_ulong = 1 << 3;
+const PERF_EVENT_IOC_ENABLE: libc::c_ulong = 0x2400;
+const PERF_EVENT_IOC_DISABLE: libc::c_ulong = 0x2401;
+const PERF_IOC_FLAG_GROUP: libc::c_ulong = 1;
+
+// The size of perf_event_attr the kernel is passed (PERF_ATTR_SIZE_VER5). Kernels older than
+// 4.1 reject it with E2BIG, and counters are then reported as unsupported.
+const ATTR_SIZE: usize = 112;
+
+/// A hardware event counted on a pipeline.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Event {
+    /// Core cycles.
+    Cycles,
+
+    /// Instructions retired.
+    Instructions,
+
+    /// Misses in the last level cache.
+    LlcMisses,
+
+    /// Mispredicted branches.
+    BranchMisses,
+}
+
+/// Every event counted on a pipeline. Cycles come first so that they lead the group; the group
+/// cannot be opened without them.
+pub const EVENTS: [Event; 4] = [
+    Event::Cycles,
+    Event::Instructions,
+    Event::LlcMisses,
+    Event::BranchMisses,
+];
+
+impl Event {
+    /// Returns the name of the event, as it appears in the results.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Event::Cycles => "cycles",
+            Event::Instructions => "instructions",
+            Event::LlcMisses => "llc_misses",
+            Event::BranchMisses => "branch_misses",
+        }
+    }
+
+    // Returns the config on perf_event_attr for the event.
+    fn config(&self) -> u64 {
+        match *self {
+            Event::Cycles => PERF_COUNT_HW_CPU_CYCLES,
+            Event::Instructions => PERF_COUNT_HW_INSTRUCTIONS,
+            Event::LlcMisses => PERF_COUNT_HW_CACHE_MISSES,
+            Event::BranchMisses => PERF_COUNT_HW_BRANCH_MISSES,
+        }
+    }
+}
+
+// The kernel's struct perf_event_attr, upto PERF_ATTR_SIZE_VER5. Most fields are only ever
+// read by the kernel.
+#[repr(C)]
+#[derive(Default)]
+#[allow(dead_code)]
+struct Attr {
+    kind: u32,
+    size: u32,
+    config: u64,
+    sample_period: u64,
+    sample_type: u64,
+    read_format: u64,
+    flags: u64,
+    wakeup_events: u32,
+    bp_type: u32,
+    config1: u64,
+    config2: u64,
+    branch_sample_type: u64,
+    sample_regs_user: u64,
+    sample_stack_user: u32,
+    clockid: i32,
+    sample_regs_intr: u64,
+    aux_watermark: u32,
+    sample_max_stack: u16,
+    reserved: u16,
+}
+
+// Opens a counter for an event on the calling thread, on whichever core it runs. The counter
+// joins the group led by `leader` if given, and otherwise leads a new group that starts out
+// disabled.
+fn open(event: Event, leader: Option<RawFd>) -> io::Result<RawFd> {
+    let attr = Attr {
+        kind: PERF_TYPE_HARDWARE,
+        size: ATTR_SIZE as u32,
+        config: event.config(),
+        read_format: PERF_FORMAT_TOTAL_TIME_ENABLED
+            | PERF_FORMAT_TOTAL_TIME_RUNNING
+            | PERF_FORMAT_GROUP,
+        flags: ATTR_EXCLUDE_KERNEL
+            | ATTR_EXCLUDE_HV
+            | match leader {
+                Some(_) => 0,
+                None => ATTR_DISABLED,
+            },
+        ..Default::default()
+    };
+
+    // There is no wrapper for perf_event_open() in libc. A pid of 0 and a cpu of -1 count the
+    // calling thread wherever it runs.
+    let (pid, cpu): (libc::pid_t, libc::c_int) = (0, -1);
+    let fd = unsafe {
+        libc::syscall(
+            libc::SYS_perf_event_open,
+            &attr as *const Attr,
+            pid,
+            cpu,
+            leader.unwrap_or(-1),
+            PERF_FLAG_FD_CLOEXEC,
+        )
+    };
+
+    match fd {
+        fd if fd < 0 => Err(io::Error::last_os_error()),
+        fd => Ok(fd as RawFd),
+    }
+}
+
+/// A group of counters on a single thread. The counters in a group are scheduled onto the PMU
+/// together, so that their counts cover the exact same instructions. The counters are closed
+/// when the group is dropped.
+pub struct Group {
+    // The event counted by, and the fd of, every counter in the group. The first leads it.
+    fds: Vec<(Event, RawFd)>,
+
+    // A description of every event that could not be counted, and why.
+    skipped: Vec<String>,
+}
+
+impl Group {
+    /// Opens counters for a set of events on the calling thread. The counters only start
+    /// counting once the group is enabled.
+    ///
+    /// # Arguments
+    ///
+    /// * `events`: The events to count. The first leads the group; if it can't be counted, the
+    ///             group can't be opened. Any other event that can't be counted is skipped.
+    ///
+    /// # Return
+    ///
+    /// The group, or why it's leader could not be opened.
+    pub fn open(events: &[Event]) -> Result<Group, String> {
+        let leader = match events.first() {
+            Some(event) => *event,
+            None => return Err("No hardware events to count".to_string()),
+        };
+
+        let fd = open(leader, None)
+            .map_err(|e| format!("Failed to open a {} counter: {}", leader.name(), e))?;
+
+        let mut group = Group {
+            fds: vec![(leader, fd)],
+            skipped: Vec::new(),
+        };
+        for event in events[1..].iter() {
+            match open(*event, Some(fd)) {
+                Ok(member) => group.fds.push((*event, member)),
+                Err(e) => {
+                    group
+                        .skipped
+                        .push(format!("Failed to open a {} counter: {}", event.name(), e))
+                }
+            }
+        }
+
+        Ok(group)
+    }
+
+    /// Returns the events counted by the group, in the order they were opened.
+    pub fn events(&self) -> Vec<Event> {
+        self.fds.iter().map(|&(event, _)| event).collect()
+    }
+
+    /// Returns a description of every event that could not be counted, and why.
+    pub fn skipped(&self) -> &[String] {
+        &self.skipped
+    }
+
+    /// Starts every counter in the group.
+    pub fn enable(&self) -> Result<(), String> {
+        self.ioctl(PERF_EVENT_IOC_ENABLE)
+    }
+
+    /// Stops every counter in the group. Counters keep their counts until they are enabled
+    /// again.
+    pub fn disable(&self) -> Result<(), String> {
+        self.ioctl(PERF_EVENT_IOC_DISABLE)
+    }
+
+    // Applies an ioctl to every counter in the group, through it's leader.
+    fn ioctl(&self, request: libc::c_ulong) -> Result<(), String> {
+        match unsafe { libc::ioctl(self.fds[0].1, request, PERF_IOC_FLAG_GROUP) } {
+            -1 => Err(format!(
+                "Failed to control hardware counters: {}",
+                io::Error::last_os_error()
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Reads every counter in the group.
+    ///
+    /// # Return
+    ///
+    /// The count of every event since the group was opened. Events the group doesn't count are
+    /// None. If the PMU was shared with other groups while the group was enabled, counts are
+    /// scaled up by the fraction of time the group was actually counting.
+    pub fn read(&self) -> Result<Counts, String> {
+        let mut buf = vec![0u64; 3 + self.fds.len()];
+        let len = buf.len() * mem::size_of::<u64>();
+        let read = unsafe { libc::read(self.fds[0].1, buf.as_mut_ptr() as *mut libc::c_void, len) };
+
+        if read < 0 {
+            return Err(format!(
+                "Failed to read hardware counters: {}",
+                io::Error::last_os_error()
+            ));
+        }
+        if read as usize != len || buf[0] as usize != self.fds.len() {
+            return Err(format!(
+                "Read {} bytes off {} hardware counters",
+                read,
+                self.fds.len()
+            ));
+        }
+
+        let (enabled, running) = (buf[1], buf[2]);
+        let mut counts = Counts::default();
+        for (&(event, _), value) in self.fds.iter().zip(buf[3..].iter()) {
+            let value = match running {
+                0 => *value,
+                running if running < enabled => {
+                    (*value as f64 * enabled as f64 / running as f64) as u64
+                }
+                _ => *value,
+            };
+            *counts.event(event) = Some(value);
+        }
+
+        Ok(counts)
+    }
+}
+
+// Implementation of the `Drop` trait on Group.
+impl Drop for Group {
+    fn drop(&mut self) {
+        // Members are closed before the leader.
+        for &(_, fd) in self.fds.iter().rev() {
+            unsafe {
+                libc::close(fd);
+            }
+        }
+    }
+}
+
+/// Hardware events counted over a phase of a run on a pipeline, or summed across pipelines, and
+/// metrics derived from them. Events that were not counted are None, as are metrics derived
+/// from them.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct Counts {
+    /// Core cycles.
+    pub cycles: Option<u64>,
+
+    /// Instructions retired.
+    pub instructions: Option<u64>,
+
+    /// Misses in the last level cache.
+    pub llc_misses: Option<u64>,
+
+    /// Mispredicted branches.
+    pub branch_misses: Option<u64>,
+
+    /// The responses received over the phase, that the metrics below are normalized by.
+    pub responses: u64,
+
+    /// Instructions retired per cycle.
+    pub ipc: Option<f64>,
+
+    /// Last level cache misses per response.
+    pub llc_misses_per_response: Option<f64>,
+
+    /// Mispredicted branches per response.
+    pub branch_misses_per_response: Option<f64>,
+}
+
+impl Counts {
+    /// Returns the count of an event.
+    pub fn get(&self, event: Event) -> Option<u64> {
+        match event {
+            Event::Cycles => self.cycles,
+            Event::Instructions => self.instructions,
+            Event::LlcMisses => self.llc_misses,
+            Event::BranchMisses => self.branch_misses,
+        }
+    }
+
+    // Returns the count of an event, so that it can be set.
+    fn event(&mut self, event: Event) -> &mut Option<u64> {
+        match event {
+            Event::Cycles => &mut self.cycles,
+            Event::Instructions => &mut self.instructions,
+            Event::LlcMisses => &mut self.llc_misses,
+            Event::BranchMisses => &mut self.branch_misses,
+        }
+    }
+
+    /// Returns the events counted between two reads of a group, over which some responses were
+    /// received.
+    ///
+    /// # Arguments
+    ///
+    /// * `earlier`:   The counts read off the group at the start of the phase.
+    /// * `responses`: The number of responses received over the phase.
+    pub fn since(&self, earlier: &Counts, responses: u64) -> Counts {
+        let mut delta = Counts {
+            responses: responses,
+            ..Default::default()
+        };
+        for event in EVENTS.iter() {
+            *delta.event(*event) = match (self.get(*event), earlier.get(*event)) {
+                (Some(now), Some(then)) => Some(now.saturating_sub(then)),
+                _ => None,
+            };
+        }
+
+        delta.derive()
+    }
+
+    /// Sums the counts of several pipelines over the same phase. An event is only summed if
+    /// every pipeline counted it.
+    pub fn total(counts: &[Counts]) -> Counts {
+        let mut total = match counts.first() {
+            Some(first) => first.clone(),
+            None => return Counts::default(),
+        };
+
+        for c in counts[1..].iter() {
+            total.responses += c.responses;
+            for event in EVENTS.iter() {
+                let sum = match (total.get(*event), c.get(*event)) {
+                    (Some(a), Some(b)) => Some(a + b),
+                    _ => None,
+                };
+                *total.event(*event) = sum;
+            }
+        }
+
+        total.derive()
+    }
+
+    // Fills in the metrics derived from the counts.
+    fn derive(mut self) -> Counts {
+        self.ipc = match (self.cycles, self.instructions) {
+            (Some(cycles), Some(instructions)) if cycles > 0 => {
+                Some(instructions as f64 / cycles as f64)
+            }
+            _ => None,
+        };
+
+        let responses = self.responses;
+        let per_response = |count: Option<u64>| match responses {
+            0 => None,
+            n => count.map(|c| c as f64 / n as f64),
+        };
+        self.llc_misses_per_response = per_response(self.llc_misses);
+        self.branch_misses_per_response = per_response(self.branch_misses);
+
+        self
+    }
+}
+
+// Counts are printed as a single line of JSON.
+impl fmt::Display for Counts {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let json = serde_json::to_string(self).map_err(|_| fmt::Error)?;
+        write!(f, "{}", json)
+    }
+}
+
+/// Counts hardware events on a single thread across the phases of a run, reading the counters
+/// at every phase boundary. Never fails a run; if the counters could not be opened, every phase
+/// reports why instead.
+pub struct Tracker {
+    // The counters, or why they could not be opened.
+    group: Result<Group, String>,
+
+    // The counts read off the counters at the start of the current phase.
+    last: Counts,
+}
+
+impl Tracker {
+    /// Opens and starts counters for every event in `EVENTS` on the calling thread, and starts
+    /// the first phase. The tracker must only be used from this thread. Events that can't be
+    /// counted are logged, and reported as None.
+    pub fn start() -> Tracker {
+        let group = match Group::open(&EVENTS) {
+            Ok(group) => match group.enable() {
+                Ok(_) => Ok(group),
+                Err(e) => Err(e),
+            },
+            Err(e) => Err(e),
+        };
+
+        let last = match group {
+            Ok(ref group) => {
+                for skipped in group.skipped() {
+                    warn!("{}", skipped);
+                }
+                group.read().unwrap_or_default()
+            }
+            Err(ref e) => {
+                warn!("{}", e);
+                Counts::default()
+            }
+        };
+
+        Tracker {
+            group: group,
+            last: last,
+        }
+    }
+
+    /// Ends the current phase, and starts the next.
+    ///
+    /// # Arguments
+    ///
+    /// * `responses`: The number of responses received over the phase.
+    ///
+    /// # Return
+    ///
+    /// The events counted over the phase, or why they could not be.
+    pub fn phase(&mut self, responses: u64) -> Result<Counts, String> {
+        let now = match self.group {
+            Ok(ref group) => group.read()?,
+            Err(ref e) => return Err(e.clone()),
+        };
+
+        let counts = now.since(&self.last, responses);
+        self.last = now;
+        Ok(counts)
+    }
+}
+
+/// Hardware events counted over a phase of a run, on every pipeline and summed across them.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct Report {
+    /// The events counted on each pipeline, in the order pipelines are numbered. None for
+    /// pipelines whose counters could not be opened or read.
+    pub pipelines: Vec<Option<Counts>>,
+
+    /// The events summed across the pipelines that counted them.
+    pub total: Counts,
+
+    /// Why counters could not be opened or read on some pipelines, once per distinct reason.
+    pub notes: Vec<String>,
+}
+
+impl Report {
+    /// Builds a report out of what each pipeline's tracker returned for a phase.
+    pub fn new(pipelines: Vec<Result<Counts, String>>) -> Report {
+        let mut report = Report::default();
+        for pipeline in pipelines.into_iter() {
+            match pipeline {
+                Ok(counts) => report.pipelines.push(Some(counts)),
+
+                Err(note) => {
+                    if !report.notes.contains(&note) {
+                        report.notes.push(note);
+                    }
+                    report.pipelines.push(None);
+                }
+            }
+        }
+
+        let counted: Vec<Counts> = report.pipelines.iter().filter_map(|c| c.clone()).collect();
+        report.total = Counts::total(&counted);
+        report
+    }
+}
+
+// This module contains tests for hardware counters. Tests that need a PMU are skipped on
+// machines that don't have one, or don't let the test count it's own thread.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ptr;
+
+    // Burns through `n` iterations of a loop the compiler can't optimize away.
+    fn spin(n: u64) -> u64 {
+        let mut x: u64 = 1;
+        for i in 0..n {
+            x = unsafe { ptr::read_volatile(&x) }
+                .wrapping_mul(31)
+                .wrapping_add(i);
+        }
+        x
+    }
+
+    // Returns true if the fd is open.
+    fn is_open(fd: RawFd) -> bool {
+        unsafe { libc::fcntl(fd, libc::F_GETFD) != -1 }
+    }
+
+    // Tests that perf_event_attr is laid out the way the kernel expects.
+    #[test]
+    fn test_attr() {
+        assert_eq!(ATTR_SIZE, mem::size_of::<Attr>());
+        let names: Vec<&str> = EVENTS.iter().map(|e| e.name()).collect();
+        assert_eq!(
+            vec!["cycles", "instructions", "llc_misses", "branch_misses"],
+            names
+        );
+    }
+
+    // Tests that a group needs atleast one event.
+    #[test]
+    fn test_open_empty() {
+        assert!(Group::open(&[]).is_err());
+    }
+
+    // Tests that a group's counters are opened on open, and closed on drop.
+    #[test]
+    fn test_group_lifecycle() {
+        let group = match Group::open(&EVENTS) {
+            Ok(group) => group,
+            Err(e) => {
+                println!("Skipping, hardware counters unsupported: {}", e);
+                return;
+            }
+        };
+
+        let fds: Vec<RawFd> = group.fds.iter().map(|&(_, fd)| fd).collect();
+        assert_eq!(EVENTS.len(), fds.len() + group.skipped().len());
+        assert_eq!(Event::Cycles, group.events()[0]);
+        assert!(fds.iter().all(|fd| is_open(*fd)));
+
+        // The group starts out disabled, so nothing is counted until it is enabled.
+        assert_eq!(Some(0), group.read().unwrap().cycles);
+        group.enable().unwrap();
+        spin(1000);
+        group.disable().unwrap();
+        let stopped = group.read().unwrap();
+        spin(1000);
+        assert_eq!(stopped, group.read().unwrap());
+
+        drop(group);
+        assert!(fds.iter().all(|fd| !is_open(*fd)));
+    }
+
+    // Tests that deltas are taken per event, and metrics are only derived off events that were
+    // counted.
+    #[test]
+    fn test_counts() {
+        let earlier = Counts {
+            cycles: Some(100),
+            instructions: Some(50),
+            llc_misses: Some(5),
+            ..Default::default()
+        };
+        let now = Counts {
+            cycles: Some(1100),
+            instructions: Some(2050),
+            llc_misses: Some(25),
+            ..Default::default()
+        };
+
+        let c = now.since(&earlier, 10);
+        assert_eq!(Some(1000), c.cycles);
+        assert_eq!(Some(2000), c.instructions);
+        assert_eq!(Some(20), c.llc_misses);
+        assert_eq!(None, c.branch_misses);
+        assert_eq!(Some(2.0), c.ipc);
+        assert_eq!(Some(2.0), c.llc_misses_per_response);
+        assert_eq!(None, c.branch_misses_per_response);
+
+        // Nothing is normalized by zero.
+        let idle = earlier.since(&earlier, 0);
+        assert_eq!(None, idle.ipc);
+        assert_eq!(None, idle.llc_misses_per_response);
+
+        // Events are only summed if every pipeline counted them.
+        let other = Counts {
+            cycles: Some(3000),
+            instructions: Some(1000),
+            responses: 30,
+            ..Default::default()
+        };
+        let total = Counts::total(&[c, other]);
+        assert_eq!(Some(4000), total.cycles);
+        assert_eq!(Some(3000), total.instructions);
+        assert_eq!(None, total.llc_misses);
+        assert_eq!(40, total.responses);
+        assert_eq!(Some(0.75), total.ipc);
+        assert_eq!(Counts::default(), Counts::total(&[]));
+    }
+
+    // Tests that pipelines without counters are reported as a note, and left out of the total.
+    #[test]
+    fn test_report() {
+        let counts = Counts {
+            cycles: Some(10),
+            responses: 1,
+            ..Default::default()
+        };
+        let note = "Failed to open a cycles counter: ENOENT".to_string();
+
+        let report = Report::new(vec![
+            Ok(counts.clone()),
+            Err(note.clone()),
+            Err(note.clone()),
+        ]);
+        assert_eq!(vec![Some(counts.clone()), None, None], report.pipelines);
+        assert_eq!(vec![note], report.notes);
+        assert_eq!(counts, report.total);
+
+        let json = format!("{}", counts);
+        assert!(json.contains("\"cycles\":10"));
+        assert_eq!(counts, serde_json::from_str(&json).unwrap());
+    }
+
+    // Tests that a tracker whose counters could not be opened reports why on every phase.
+    #[test]
+    fn test_tracker_unsupported() {
+        let mut tracker = Tracker {
+            group: Err("No PMU".to_string()),
+            last: Counts::default(),
+        };
+        assert_eq!(Err("No PMU".to_string()), tracker.phase(10));
+        assert_eq!(Err("No PMU".to_string()), tracker.phase(10));
+    }
+
+    // Tests that cycles are counted across phases in proportion to the work done in each, on
+    // machines that can count them.
+    #[test]
+    fn test_tracker_phases() {
+        let mut tracker = Tracker::start();
+        if let Err(ref e) = tracker.group {
+            println!("Skipping, hardware counters unsupported: {}", e);
+            return;
+        }
+
+        spin(1000 * 1000);
+        let small = tracker.phase(1).unwrap();
+        spin(8 * 1000 * 1000);
+        let large = tracker.phase(8).unwrap();
+
+        let (small, large) = (small.cycles.unwrap(), large.cycles.unwrap());
+        assert!(small > 0);
+        assert!(large > 2 * small && large < 32 * small);
+    }
+}
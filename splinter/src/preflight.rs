@@ -0,0 +1,287 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use db::config::ClientConfig;
+use db::wireformat::OpCode;
+
+use sandstorm::ext::ExtensionInfo;
+
+/// Checks that every extension a client is about to invoke is loaded for a tenant.
+///
+/// # Arguments
+///
+/// * `tenant`:     The tenant the extensions will be invoked as.
+/// * `configured`: The names of the extensions the client will invoke.
+/// * `available`:  The tenant's listing, as returned by a list_extensions() RPC.
+///
+/// # Return
+///
+/// An error message naming the missing extensions and listing the ones that are actually
+/// available, if any of the configured extensions is missing.
+pub fn check_extensions(
+    tenant: u32,
+    configured: &[&str],
+    available: &[ExtensionInfo],
+) -> Result<(), String> {
+    let missing: Vec<&str> = configured
+        .iter()
+        .filter(|name| !available.iter().any(|e| e.name == name.as_bytes()))
+        .map(|name| *name)
+        .collect();
+
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    let names: Vec<String> = available
+        .iter()
+        .map(|e| String::from_utf8_lossy(&e.name).into_owned())
+        .collect();
+    Err(format!(
+        "Extension(s) {:?} not loaded for tenant {}. Available: {:?}",
+        missing, tenant, names
+    ))
+}
+
+/// Checks that the arguments a client is about to invoke an extension with fit the layout the
+/// extension declared, so that a client built against a different version of the extension
+/// fails right away instead of collecting StatusArgsMismatch on every invoke. Extensions that
+/// declared no layout take any arguments.
+///
+/// # Arguments
+///
+/// * `name`:      The name of the extension the client will invoke.
+/// * `args`:      The arguments the client will invoke it with, or a sample of them.
+/// * `available`: The tenant's listing, as returned by a list_extensions() RPC.
+///
+/// # Return
+///
+/// An error message naming the extension and the first field the arguments don't fit, if they
+/// don't fit. Refer to check_extensions() for extensions that aren't listed.
+pub fn check_args(name: &str, args: &[u8], available: &[ExtensionInfo]) -> Result<(), String> {
+    let schema = available
+        .iter()
+        .find(|e| e.name == name.as_bytes())
+        .and_then(|e| e.schema.as_ref());
+
+    match schema {
+        Some(schema) => schema.check(args).map_err(|e| {
+            format!(
+                "Arguments to {} don't fit it's declared layout {:?}: {}",
+                name,
+                schema.fields(),
+                e
+            )
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Returns the opcodes a YCSB run issues under a client config: invoke() if it invokes
+/// extensions, otherwise get() and, unless the run is read-only, put().
+pub fn ycsb_opcodes(config: &ClientConfig) -> Vec<OpCode> {
+    match (config.use_invoke, config.put_pct) {
+        (true, _) => vec![OpCode::SandstormInvokeRpc],
+        (false, 0) => vec![OpCode::SandstormGetRpc],
+        (false, _) => vec![OpCode::SandstormGetRpc, OpCode::SandstormPutRpc],
+    }
+}
+
+/// Checks that a server serves every opcode a client is about to issue, so that a run against a
+/// server configured for a different mode fails right away instead of collecting rejections.
+///
+/// # Arguments
+///
+/// * `served`: The opcodes the server serves, as advertised on it's echo() response.
+/// * `needed`: The opcodes the client will issue.
+///
+/// # Return
+///
+/// An error message naming the opcodes that aren't served, if any.
+pub fn check_opcodes(served: u64, needed: &[OpCode]) -> Result<(), String> {
+    let missing: Vec<&OpCode> = needed.iter().filter(|op| served & op.bit() == 0).collect();
+
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    Err(format!(
+        "Server does not serve {:?} (served opcodes {:#018x}); check enabled_opcodes in it's \
+         config",
+        missing, served
+    ))
+}
+
+/// Returns the longest key and largest value a YCSB run writes under a client config. Read-only
+/// runs write no values.
+pub fn ycsb_sizes(config: &ClientConfig) -> (usize, usize) {
+    match config.put_pct {
+        0 => (config.key_len, 0),
+        _ => (config.key_len, config.value_len),
+    }
+}
+
+/// Checks that the objects a client is about to write fit under a server's limits, so that a
+/// run with keys or values that are too large fails right away instead of collecting
+/// StatusKeyTooLarge or StatusValueTooLarge on every put.
+///
+/// # Arguments
+///
+/// * `sizes`:  The longest key and largest value the client will write, as from ycsb_sizes().
+/// * `limits`: The longest key and largest value the server accepts, as advertised on it's
+///             echo() response.
+///
+/// # Return
+///
+/// An error message naming the limit that would be exceeded, if any.
+pub fn check_sizes(sizes: (usize, usize), limits: (usize, usize)) -> Result<(), String> {
+    let ((key_len, value_len), (max_key_len, max_value_len)) = (sizes, limits);
+    if key_len > max_key_len {
+        return Err(format!(
+            "key_len {} is longer than the server allows ({}); check max_key_len in it's config",
+            key_len, max_key_len
+        ));
+    }
+
+    if value_len > max_value_len {
+        return Err(format!(
+            "value_len {} is larger than the server allows ({}); check max_value_len in it's \
+             config",
+            value_len, max_value_len
+        ));
+    }
+
+    Ok(())
+}
+
+// This module contains unit tests for the preflight checks.
+#[cfg(test)]
+mod tests {
+    use super::{
+        check_args, check_extensions, check_opcodes, check_sizes, ycsb_opcodes, ycsb_sizes,
+    };
+
+    use db::config::ClientConfig;
+    use db::wireformat::{OpCode, OPCODES_ALL, OPCODES_REQUIRED};
+
+    use sandstorm::ext::{ExtensionInfo, Provenance};
+    use sandstorm::schema::{Field, Schema};
+
+    fn info(name: &str) -> ExtensionInfo {
+        ExtensionInfo {
+            name: name.as_bytes().to_vec(),
+            version: 0,
+            loaded: 0,
+            invocations: 0,
+            provenance: Provenance::Private,
+            schema: None,
+            id: 0,
+        }
+    }
+
+    #[test]
+    fn test_check_extensions_ok() {
+        let available = vec![info("get"), info("put"), info("tao")];
+        assert_eq!(Ok(()), check_extensions(1, &["get", "tao"], &available));
+        assert_eq!(Ok(()), check_extensions(1, &[], &[]));
+    }
+
+    #[test]
+    fn test_check_extensions_missing() {
+        let available = vec![info("get"), info("put")];
+        let err = check_extensions(7, &["get", "auth"], &available).unwrap_err();
+        assert!(err.contains("\"auth\""));
+        assert!(err.contains("tenant 7"));
+        assert!(err.contains("[\"get\", \"put\"]"));
+    }
+
+    #[test]
+    fn test_check_args() {
+        let mut get = info("get");
+        get.schema = Some(Schema::new(vec![Field::Table, Field::Blob(1, 16)]).unwrap());
+        let available = vec![get, info("put")];
+
+        let mut args = vec![1, 0, 0, 0, 0, 0, 0, 0];
+        args.extend_from_slice(b"key");
+        assert_eq!(Ok(()), check_args("get", &args, &available));
+        assert_eq!(Ok(()), check_args("put", b"", &available));
+        assert_eq!(Ok(()), check_args("auth", b"", &available));
+
+        let err = check_args("get", &args[..8], &available).unwrap_err();
+        assert!(err.contains("Arguments to get"));
+        assert!(err.contains("field 1 is 0 bytes"));
+        let err = check_args("get", &args[..4], &available).unwrap_err();
+        assert!(err.contains("end before field 0"));
+    }
+
+    #[test]
+    fn test_check_opcodes_ok() {
+        let get = OpCode::SandstormGetRpc.bit();
+        let put = OpCode::SandstormPutRpc.bit();
+        let needed = [OpCode::SandstormGetRpc, OpCode::SandstormPutRpc];
+        assert_eq!(Ok(()), check_opcodes(OPCODES_REQUIRED | get | put, &needed));
+        assert_eq!(Ok(()), check_opcodes(OPCODES_ALL, &needed));
+        assert_eq!(Ok(()), check_opcodes(OPCODES_REQUIRED, &[]));
+    }
+
+    #[test]
+    fn test_check_opcodes_missing() {
+        let get = OpCode::SandstormGetRpc.bit();
+        let put = OpCode::SandstormPutRpc.bit();
+        let err =
+            check_opcodes(OPCODES_REQUIRED | get | put, &[OpCode::SandstormInvokeRpc]).unwrap_err();
+        assert!(err.contains("SandstormInvokeRpc"));
+
+        let needed = [OpCode::SandstormGetRpc, OpCode::SandstormPutRpc];
+        let err = check_opcodes(OPCODES_REQUIRED | get, &needed).unwrap_err();
+        assert!(err.contains("SandstormPutRpc"));
+        assert!(!err.contains("SandstormGetRpc"));
+    }
+
+    #[test]
+    fn test_ycsb_opcodes() {
+        let mut config = ClientConfig::default();
+        config.put_pct = 5;
+        assert_eq!(
+            vec![OpCode::SandstormGetRpc, OpCode::SandstormPutRpc],
+            ycsb_opcodes(&config)
+        );
+
+        config.put_pct = 0;
+        assert_eq!(vec![OpCode::SandstormGetRpc], ycsb_opcodes(&config));
+
+        config.use_invoke = true;
+        assert_eq!(vec![OpCode::SandstormInvokeRpc], ycsb_opcodes(&config));
+    }
+
+    #[test]
+    fn test_check_sizes() {
+        assert_eq!(Ok(()), check_sizes((30, 100), (30, 100)));
+        assert_eq!(Ok(()), check_sizes((0, 0), (0, 0)));
+
+        let err = check_sizes((31, 100), (30, 100)).unwrap_err();
+        assert!(err.contains("key_len 31"));
+        let err = check_sizes((30, 101), (30, 100)).unwrap_err();
+        assert!(err.contains("value_len 101"));
+
+        let mut config = ClientConfig::default();
+        config.key_len = 30;
+        config.value_len = 2000;
+        config.put_pct = 0;
+        assert_eq!(Ok(()), check_sizes(ycsb_sizes(&config), (30, 100)));
+        config.put_pct = 5;
+        assert!(check_sizes(ycsb_sizes(&config), (30, 100)).is_err());
+    }
+}
@@ -13,13 +13,15 @@
  * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
  */
 
-use std::cell::RefCell;
+use std::cell::{Cell, Ref, RefCell};
 use std::sync::Arc;
 
 use db::cycles::*;
+use db::wireformat::RpcStatus;
 
 use sandstorm::buf::{MultiReadBuf, ReadBuf, WriteBuf};
-use sandstorm::db::DB;
+use sandstorm::db::{PutManyError, DB};
+use sandstorm::invoke;
 
 use super::dispatch::*;
 
@@ -56,6 +58,20 @@ impl KV {
     }
 }
 
+/// Sends out the gets issued by an extension running on the client for keys missing from it's
+/// read-set. Implemented by dispatch::Sender.
+pub trait Fetcher {
+    /// Sends out a get() for a key on behalf of an extension. Refer to
+    /// Sender::send_get_from_extension().
+    fn fetch(&self, tenant: u32, table: u64, key: &[u8], id: u64, stamp: u64);
+}
+
+impl Fetcher for Sender {
+    fn fetch(&self, tenant: u32, table: u64, key: &[u8], id: u64, stamp: u64) {
+        self.send_get_from_extension(tenant, table, key, id, stamp);
+    }
+}
+
 /// A proxy to the database on the client side; which searches the
 /// local cache before issuing the operations to the server.
 pub struct ProxyDB {
@@ -66,6 +82,10 @@ pub struct ProxyDB {
     // as the first request.
     parent_id: u64,
 
+    // The time-stamp on the first request. Subsequent requests carry it too, so that latency is
+    // measured from when the extension was first invoked.
+    parent_stamp: u64,
+
     // The buffer consisting of the RPC payload that invoked the extension. This is required
     // to potentially pass in arguments to an extension. For example, a get() extension might
     // require a key and table identifier to be passed in.
@@ -75,12 +95,15 @@ pub struct ProxyDB {
     // arguments to the extension begin.
     args_offset: usize,
 
+    // The offset inside the arguments of the field next_arg() reads next.
+    arg_cursor: Cell<usize>,
+
     // The flag to indicate if the current extension is waiting for the DB operation to complete.
     // This flag will be used by the scheduler to avoid scheduling the task until the response comes.
     waiting: RefCell<bool>,
 
     // Network stack required to actually send RPC requests out the network.
-    sender: Arc<Sender>,
+    sender: Arc<Fetcher>,
 
     // A list of the records in Read-set for the extension.
     readset: RefCell<Vec<KV>>,
@@ -93,6 +116,13 @@ pub struct ProxyDB {
 
     // The model for a given extension which is stored based on the name of the extension.
     model: Option<Arc<Model>>,
+
+    // The response written by the extension through resp(), handed back to the client once the
+    // extension completes.
+    response: RefCell<Vec<u8>>,
+
+    // Set if the extension panicked. It's response then has status StatusExtensionError.
+    panicked: Cell<bool>,
 }
 
 impl ProxyDB {
@@ -103,6 +133,7 @@ impl ProxyDB {
     ///
     /// * `tenant_id`: Tenant id will be needed reuqest generation.
     /// * `id`: This is unique-id for the request and consecutive requests will have same id.
+    /// * `stamp`: The time-stamp on the request, carried by consecutive requests too.
     /// * `request`: A reference to the request sent by the client, it will be helpful in task creation
     ///             if the requested is pushed back.
     /// * `name_length`: This will be useful in parsing the request and find out the argument for consecutive requests.
@@ -114,22 +145,27 @@ impl ProxyDB {
     pub fn new(
         tenant_id: u32,
         id: u64,
+        stamp: u64,
         request: Arc<Vec<u8>>,
         name_length: usize,
-        sender_service: Arc<Sender>,
+        sender_service: Arc<Fetcher>,
         model: Option<Arc<Model>>,
     ) -> ProxyDB {
         ProxyDB {
             tenant: tenant_id,
             parent_id: id,
+            parent_stamp: stamp,
             req: request,
             args_offset: name_length,
+            arg_cursor: Cell::new(0),
             waiting: RefCell::new(false),
             sender: sender_service,
             readset: RefCell::new(Vec::with_capacity(4)),
             writeset: RefCell::new(Vec::with_capacity(4)),
             db_credit: RefCell::new(0),
             model: model,
+            response: RefCell::new(Vec::new()),
+            panicked: Cell::new(false),
         }
     }
 
@@ -205,6 +241,28 @@ impl ProxyDB {
     pub fn db_credit(&self) -> u64 {
         self.db_credit.borrow().clone()
     }
+
+    /// This method marks the extension as having panicked, and drops whatever it responded with
+    /// so far. Called by the container running it.
+    pub fn set_panicked(&self) {
+        self.panicked.set(true);
+        self.response.borrow_mut().clear();
+    }
+
+    /// This method returns the status the extension completed with; the same status the server
+    /// would have responded with had the extension completed there.
+    pub fn status(&self) -> RpcStatus {
+        if self.panicked.get() {
+            RpcStatus::StatusExtensionError
+        } else {
+            RpcStatus::StatusOk
+        }
+    }
+
+    /// This method returns the response written by the extension so far.
+    pub fn response(&self) -> Ref<Vec<u8>> {
+        self.response.borrow()
+    }
 }
 
 impl DB for ProxyDB {
@@ -218,6 +276,16 @@ impl DB for ProxyDB {
         unsafe { Some(ReadBuf::new(value)) }
     }
 
+    /// Lookup the `DB` trait for documentation on this method. Only the lengths of values on
+    /// the read-set are known on the client.
+    fn value_len(&self, _table: u64, key: &[u8]) -> Option<usize> {
+        self.readset
+            .borrow()
+            .iter()
+            .find(|record| record.key == key)
+            .map(|record| record.value.len())
+    }
+
     /// Lookup the `DB` trait for documentation on this method.
     fn multiget(&self, _table: u64, _key_len: u16, _keys: &[u8]) -> Option<MultiReadBuf> {
         unsafe { Some(MultiReadBuf::new(Vec::new())) }
@@ -234,7 +302,27 @@ impl DB for ProxyDB {
     }
 
     /// Lookup the `DB` trait for documentation on this method.
-    fn del(&self, _table: u64, _key: &[u8]) {}
+    fn put_many(&self, _bufs: Vec<WriteBuf>) -> Result<(), PutManyError> {
+        Ok(())
+    }
+
+    /// Lookup the `DB` trait for documentation on this method. Like put(), writes are not
+    /// applied on the client, so the value under the key is always taken to be the expected one.
+    fn cas(
+        &self,
+        _table_id: u64,
+        _key: &[u8],
+        _expected: Option<&[u8]>,
+        _buf: WriteBuf,
+    ) -> Result<bool, ()> {
+        Ok(true)
+    }
+
+    /// Lookup the `DB` trait for documentation on this method. Like put(), deletes are not
+    /// applied on the client, so the key is always taken to have been deleted.
+    fn del(&self, _table: u64, _key: &[u8]) -> bool {
+        true
+    }
 
     /// Lookup the `DB` trait for documentation on this method.
     fn args(&self) -> &[u8] {
@@ -242,7 +330,14 @@ impl DB for ProxyDB {
     }
 
     /// Lookup the `DB` trait for documentation on this method.
-    fn resp(&self, _data: &[u8]) {}
+    fn next_arg(&self) -> Option<&[u8]> {
+        invoke::next_arg(self.args(), &self.arg_cursor)
+    }
+
+    /// Lookup the `DB` trait for documentation on this method.
+    fn resp(&self, data: &[u8]) {
+        self.response.borrow_mut().extend_from_slice(data);
+    }
 
     /// Lookup the `DB` trait for documentation on this method.
     fn debug_log(&self, _message: &str) {}
@@ -258,7 +353,7 @@ impl DB for ProxyDB {
         }
         self.set_waiting(true);
         self.sender
-            .send_get_from_extension(self.tenant, table, key, self.parent_id);
+            .fetch(self.tenant, table, key, self.parent_id, self.parent_stamp);
         *self.db_credit.borrow_mut() += rdtsc() - start;
         (false, false, None)
     }
@@ -284,7 +379,7 @@ impl DB for ProxyDB {
             } else {
                 self.set_waiting(true);
                 self.sender
-                    .send_get_from_extension(self.tenant, table, key, self.parent_id);
+                    .fetch(self.tenant, table, key, self.parent_id, self.parent_stamp);
                 *self.db_credit.borrow_mut() += rdtsc() - start;
                 return (false, false, None);
             }
@@ -301,3 +396,56 @@ impl DB for ProxyDB {
         }
     }
 }
+
+// This module contains unit tests for ProxyDB.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Records the gets sent out on behalf of an extension, as the table and key of each.
+    #[derive(Default)]
+    struct Fetches(RefCell<Vec<(u64, Vec<u8>)>>);
+
+    impl Fetcher for Fetches {
+        fn fetch(&self, _tenant: u32, table: u64, key: &[u8], _id: u64, _stamp: u64) {
+            self.0.borrow_mut().push((table, key.to_vec()));
+        }
+    }
+
+    // Tests that writes and deletes made by an extension on the client are taken to have been
+    // applied, and that only keys missing from the read-set are fetched from the server.
+    #[test]
+    fn test_proxy_writes() {
+        let fetches = Arc::new(Fetches::default());
+        let db = ProxyDB::new(1, 7, 0, Arc::new(Vec::new()), 0, fetches.clone(), None);
+        db.set_read_record(&[1, 1, 2, 2], 2);
+
+        assert!(db.del(1, &[1, 1]));
+        assert!(db.del(1, &[3]));
+        assert!(db.put(db.alloc(1, &[3], 4).unwrap()));
+
+        let (_, found, value) = db.search_get_in_cache(1, &[1, 1]);
+        assert!(found && !db.get_waiting());
+        assert_eq!(&[2, 2][..], value.unwrap().read());
+        assert!(fetches.0.borrow().is_empty());
+
+        let (_, found, value) = db.search_get_in_cache(1, &[3]);
+        assert!(!found && value.is_none() && db.get_waiting());
+        assert_eq!(vec![(1, vec![3])], *fetches.0.borrow());
+    }
+
+    // Tests that the lengths of values on the read-set are known on the client, and that reading
+    // them does not fetch anything from the server.
+    #[test]
+    fn test_proxy_value_len() {
+        let fetches = Arc::new(Fetches::default());
+        let db = ProxyDB::new(1, 7, 0, Arc::new(Vec::new()), 0, fetches.clone(), None);
+        db.set_read_record(&[1, 1, 2, 2, 2], 2);
+        db.set_read_record(&[3], 1);
+
+        assert_eq!(Some(3), db.value_len(1, &[1, 1]));
+        assert_eq!(Some(0), db.value_len(1, &[3]));
+        assert_eq!(None, db.value_len(1, &[4]));
+        assert!(fetches.0.borrow().is_empty() && !db.get_waiting());
+    }
+}
@@ -0,0 +1,247 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Retries of requests that failed to find the key, table or tenant they named. What is missing
+//! may yet be written or created, so such requests are retried upto a budget of attempts. But a
+//! table truncated or dropped, or a tenant removed, between two attempts will not come back, and
+//! retrying only burns the budget, so the request is given up on as invalidated instead. An
+//! attempt tells that the request was invalidated if
+//!
+//! - it failed to find a wider scope than the attempt before it did (ex: the key was missing,
+//!   and then the whole table), since that scope existed at the time, or
+//! - it failed to find the same scope, but it's response carried a later epoch on it's header
+//!   than the response before it. Refer to db::epoch.
+
+use db::wireformat::RpcStatus;
+
+/// What a client does about a request after an attempt at it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Verdict {
+    /// The attempt found what it named, or failed in a way retrying does not help with.
+    Done,
+
+    /// The attempt did not find what it named, and the request has attempts left.
+    Retry,
+
+    /// The last attempt the request had did not find what it named.
+    Exhausted,
+
+    /// The table or tenant the request named was truncated, dropped or removed since an earlier
+    /// attempt.
+    Invalidated,
+}
+
+/// Counts retries, and requests given up on, across requests.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RetryStats {
+    /// The number of attempts that were retried.
+    pub retried: u64,
+
+    /// The number of requests that ran out of attempts.
+    pub exhausted: u64,
+
+    /// The number of requests given up on because what they named was destroyed.
+    pub invalidated: u64,
+}
+
+// Returns how wide the scope a status failed to find is; the key, it's table or it's tenant.
+// None if the status isn't a not-found.
+fn scope(status: &RpcStatus) -> Option<u8> {
+    match *status {
+        RpcStatus::StatusObjectDoesNotExist => Some(0),
+        RpcStatus::StatusTableDoesNotExist => Some(1),
+        RpcStatus::StatusTenantDoesNotExist => Some(2),
+        _ => None,
+    }
+}
+
+/// Tracks the attempts at a single request.
+pub struct Retries {
+    // The number of attempts the request gets, including the first.
+    budget: u32,
+
+    // The number of attempts observed so far.
+    attempts: u32,
+
+    // The scope the last attempt failed to find, and the epoch on it's response if there was
+    // one. None if no attempt failed to find anything yet.
+    last: Option<(u8, Option<u64>)>,
+}
+
+impl Retries {
+    /// Returns the tracker for a request that has not been attempted yet.
+    ///
+    /// # Arguments
+    ///
+    /// * `budget`: The number of attempts the request gets, including the first. Requests get
+    ///             atleast one.
+    pub fn new(budget: u32) -> Retries {
+        Retries {
+            budget: budget,
+            attempts: 0,
+            last: None,
+        }
+    }
+
+    /// Observes the response to an attempt, and decides what to do about the request.
+    ///
+    /// # Arguments
+    ///
+    /// * `status`: The status on the response.
+    /// * `epoch`:  The epoch on the response's header, if it carried one.
+    /// * `stats`:  Counts the retry, or the request being given up on.
+    ///
+    /// # Return
+    ///
+    /// Retry if the request should be attempted again. Otherwise, why it shouldn't.
+    pub fn observe(
+        &mut self,
+        status: &RpcStatus,
+        epoch: Option<u64>,
+        stats: &mut RetryStats,
+    ) -> Verdict {
+        self.attempts += 1;
+        let scope = match scope(status) {
+            Some(scope) => scope,
+            None => return Verdict::Done,
+        };
+
+        let invalidated = match self.last {
+            Some((last, _)) if scope > last => true,
+            Some((last, Some(before))) if scope == last => epoch.map_or(false, |e| e > before),
+            _ => false,
+        };
+        self.last = Some((scope, epoch));
+
+        if invalidated {
+            stats.invalidated += 1;
+            return Verdict::Invalidated;
+        }
+
+        if self.attempts >= self.budget {
+            stats.exhausted += 1;
+            return Verdict::Exhausted;
+        }
+
+        stats.retried += 1;
+        Verdict::Retry
+    }
+}
+
+// This module contains unit tests for Retries.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Tests that a key that stays missing is retried until the budget runs out, and that a
+    // retry that finds it is done.
+    #[test]
+    fn test_retries_missing_key() {
+        let missing = RpcStatus::StatusObjectDoesNotExist;
+        let mut stats = RetryStats::default();
+        let mut retries = Retries::new(3);
+        assert_eq!(Verdict::Retry, retries.observe(&missing, None, &mut stats));
+        assert_eq!(Verdict::Retry, retries.observe(&missing, None, &mut stats));
+        assert_eq!(
+            Verdict::Exhausted,
+            retries.observe(&missing, None, &mut stats)
+        );
+
+        let mut retries = Retries::new(3);
+        assert_eq!(Verdict::Retry, retries.observe(&missing, None, &mut stats));
+        assert_eq!(
+            Verdict::Done,
+            retries.observe(&RpcStatus::StatusOk, None, &mut stats)
+        );
+
+        // Requests get atleast one attempt, and failures other than a not-found are never
+        // retried.
+        let mut retries = Retries::new(0);
+        assert_eq!(
+            Verdict::Exhausted,
+            retries.observe(&missing, None, &mut stats)
+        );
+        let mut retries = Retries::new(3);
+        let refused = RpcStatus::StatusPermissionDenied;
+        assert_eq!(Verdict::Done, retries.observe(&refused, None, &mut stats));
+        assert_eq!(
+            RetryStats {
+                retried: 3,
+                exhausted: 2,
+                invalidated: 0,
+            },
+            stats
+        );
+    }
+
+    // Tests that a request is invalidated once it's epoch moves forward between attempts, or
+    // once it stops finding a scope it found before, but not while the scope stays put.
+    #[test]
+    fn test_retries_invalidated() {
+        let table = RpcStatus::StatusTableDoesNotExist;
+        let tenant = RpcStatus::StatusTenantDoesNotExist;
+        let mut stats = RetryStats::default();
+
+        let mut retries = Retries::new(10);
+        assert_eq!(Verdict::Retry, retries.observe(&table, Some(4), &mut stats));
+        assert_eq!(Verdict::Retry, retries.observe(&table, Some(4), &mut stats));
+        assert_eq!(Verdict::Retry, retries.observe(&table, None, &mut stats));
+        assert_eq!(Verdict::Retry, retries.observe(&table, Some(5), &mut stats));
+        assert_eq!(
+            Verdict::Invalidated,
+            retries.observe(&table, Some(6), &mut stats)
+        );
+
+        // The key was missing, and then missing at a later epoch; it's table was truncated.
+        let mut retries = Retries::new(10);
+        let missing = RpcStatus::StatusObjectDoesNotExist;
+        assert_eq!(
+            Verdict::Retry,
+            retries.observe(&missing, Some(4), &mut stats)
+        );
+        assert_eq!(
+            Verdict::Invalidated,
+            retries.observe(&missing, Some(5), &mut stats)
+        );
+
+        // The key was missing, and then the table.
+        let mut retries = Retries::new(10);
+        assert_eq!(Verdict::Retry, retries.observe(&missing, None, &mut stats));
+        assert_eq!(
+            Verdict::Invalidated,
+            retries.observe(&table, Some(1), &mut stats)
+        );
+
+        // The tenant was missing, and then created without the table.
+        let mut retries = Retries::new(10);
+        assert_eq!(
+            Verdict::Retry,
+            retries.observe(&tenant, Some(2), &mut stats)
+        );
+        assert_eq!(Verdict::Retry, retries.observe(&table, Some(2), &mut stats));
+        assert_eq!(
+            Verdict::Invalidated,
+            retries.observe(&tenant, Some(3), &mut stats)
+        );
+        assert_eq!(
+            RetryStats {
+                retried: 8,
+                exhausted: 0,
+                invalidated: 4,
+            },
+            stats
+        );
+    }
+}
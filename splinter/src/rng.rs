@@ -0,0 +1,211 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use std::sync::{Once, ONCE_INIT};
+
+use db::config::ClientConfig;
+
+use rand::{self, Rng, SeedableRng, XorShiftRng};
+
+use super::dist::{self, Sampler};
+
+// The seed of this client run, set exactly once by seed().
+static SEED_INIT: Once = ONCE_INIT;
+static mut SEED: u64 = 0;
+
+/// Returns the seed every workload generator on this client derives it's random numbers from.
+/// The first call takes the seed from the config, or picks a random one if the config leaves it
+/// 0, and logs it. Every later call returns the same seed, no matter the config passed in.
+///
+/// # Arguments
+///
+/// * `config`: Client configuration. `seed` is used if non-zero.
+pub fn seed(config: &ClientConfig) -> u64 {
+    unsafe {
+        SEED_INIT.call_once(|| {
+            let mut seed = config.seed;
+            while seed == 0 {
+                seed = rand::random();
+            }
+
+            info!("Workload seed {}", seed);
+            SEED = seed;
+        });
+
+        SEED
+    }
+}
+
+/// Returns a generator for a pipeline, derived from the seed of a run. Pipelines draw different
+/// sequences off the same seed, but each one draws the same sequence every time it is run off it.
+///
+/// # Arguments
+///
+/// * `seed`:     The seed of the run.
+/// * `pipeline`: The index of the pipeline.
+pub fn xorshift(seed: u64, pipeline: usize) -> XorShiftRng {
+    // SplitMix64, so that seeds and pipelines that differ in a single bit still end up with
+    // unrelated generators.
+    let mut state = seed ^ (pipeline as u64).wrapping_mul(0x9e3779b97f4a7c15);
+    let mut next = || {
+        state = state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    };
+
+    let (a, b) = (next(), next());
+    let mut words = [a as u32, (a >> 32) as u32, b as u32, (b >> 32) as u32];
+
+    // XorShift can't be seeded with all zeros.
+    if words.iter().all(|w| *w == 0) {
+        words[0] = 1;
+    }
+
+    XorShiftRng::from_seed(words)
+}
+
+/// The random numbers a workload generator draws on a pipeline: keys, tenants, and anything
+/// else (ex: the kind of operation to issue) off the underlying generator, which WorkloadRng
+/// derefs to through Rng.
+///
+/// Everything is drawn off a single generator seeded from the run's seed and the index of the
+/// pipeline, so a run can be reproduced down to the exact sequence of keys, tenants and
+/// operations on every pipeline by rerunning with the same seed and config.
+pub struct WorkloadRng {
+    // The generator every draw is made off.
+    rng: XorShiftRng,
+
+    // The sampler keys are drawn from.
+    keys: Box<Sampler>,
+
+    // The sampler tenants are drawn from.
+    tenants: Box<Sampler>,
+}
+
+impl WorkloadRng {
+    /// Creates the random numbers for a pipeline.
+    ///
+    /// # Arguments
+    ///
+    /// * `seed`:     The seed of the run.
+    /// * `pipeline`: The index of the pipeline.
+    /// * `keys`:     The sampler keys are drawn from. See dist.
+    /// * `tenants`:  The sampler tenants are drawn from.
+    pub fn new(seed: u64, pipeline: usize, keys: Box<Sampler>, tenants: Box<Sampler>) -> Self {
+        WorkloadRng {
+            rng: xorshift(seed, pipeline),
+            keys: keys,
+            tenants: tenants,
+        }
+    }
+
+    /// Creates the random numbers for a pipeline as specified by the client config. Keys and
+    /// tenants are drawn from dist::key_sampler() and dist::tenant_distribution().
+    ///
+    /// # Arguments
+    ///
+    /// * `config`:    Client configuration. The seed is picked off it by seed().
+    /// * `pipeline`:  The index of the pipeline.
+    /// * `pipelines`: The total number of pipelines on the client.
+    pub fn from_config(config: &ClientConfig, pipeline: usize, pipelines: usize) -> Self {
+        WorkloadRng::new(
+            seed(config),
+            pipeline,
+            dist::key_sampler(config, pipeline, pipelines),
+            dist::tenant_distribution(config).sampler(
+                config.num_tenants as usize,
+                pipeline,
+                pipelines,
+            ),
+        )
+    }
+
+    /// Draws a key, in the range [1, n].
+    #[inline]
+    pub fn key(&mut self) -> usize {
+        self.keys.sample(&mut self.rng)
+    }
+
+    /// Draws a tenant, in the range [1, n].
+    #[inline]
+    pub fn tenant(&mut self) -> usize {
+        self.tenants.sample(&mut self.rng)
+    }
+}
+
+impl Rng for WorkloadRng {
+    #[inline]
+    fn next_u32(&mut self) -> u32 {
+        self.rng.next_u32()
+    }
+
+    #[inline]
+    fn next_u64(&mut self) -> u64 {
+        self.rng.next_u64()
+    }
+}
+
+// This module contains unit tests for WorkloadRng.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use super::super::dist::KeyDistribution;
+
+    fn workload(seed: u64, pipeline: usize) -> WorkloadRng {
+        WorkloadRng::new(
+            seed,
+            pipeline,
+            KeyDistribution::Zipf(0.99).sampler(100000, pipeline, 4),
+            KeyDistribution::Uniform.sampler(16, pipeline, 4),
+        )
+    }
+
+    // Draws the (tenant, key, op) sequence a workload generator would.
+    fn draw(rng: &mut WorkloadRng, n: usize) -> Vec<(usize, usize, u32)> {
+        (0..n)
+            .map(|_| (rng.tenant(), rng.key(), rng.gen::<u32>() % 100))
+            .collect()
+    }
+
+    // Tests that two constructions off the same seed and pipeline draw the same sequence.
+    #[test]
+    fn test_rng_replay() {
+        let (mut a, mut b) = (workload(42, 1), workload(42, 1));
+        assert_eq!(draw(&mut a, 1000), draw(&mut b, 1000));
+    }
+
+    // Tests that pipelines and seeds draw different sequences.
+    #[test]
+    fn test_rng_diverge() {
+        let seq = draw(&mut workload(42, 0), 100);
+        assert!(seq != draw(&mut workload(42, 1), 100));
+        assert!(seq != draw(&mut workload(43, 0), 100));
+        assert!(seq != draw(&mut workload(0, 0), 100));
+    }
+
+    // Tests that the seed is picked once, and is never 0.
+    #[test]
+    fn test_rng_seed() {
+        let mut config = ClientConfig::default();
+        let s = seed(&config);
+        assert!(s != 0);
+
+        config.seed = s + 1;
+        assert_eq!(s, seed(&config));
+    }
+}
@@ -0,0 +1,290 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use std::collections::HashSet;
+
+use db::config::ClientConfig;
+use db::wireformat::RpcStatus;
+
+/// How finely a latency distribution was sampled, reported alongside it's percentiles.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Resolution {
+    /// The number of responses whose latency was recorded.
+    pub samples: u64,
+
+    /// The number of responses received, sampled or not.
+    pub completed: u64,
+
+    /// The number of responses received per sample recorded. Can come out below the configured
+    /// rate, since pushbacks and errors are always sampled.
+    pub rate: f64,
+}
+
+/// Decides which requests have their latency recorded, so that a client running at extreme
+/// rates doesn't spend it's time on bookkeeping instead of sending requests.
+///
+/// One in every `rate` requests is sampled when it is sent out, by counting down to the next
+/// sample; no random numbers are drawn. Ids of sampled requests are held until their responses
+/// arrive. Responses to unsampled requests are only counted. Responses that were pushed back or
+/// failed are always sampled, since they are rare and worth looking at.
+///
+/// A retransmit sent out under the id of the original is sampled exactly when the original was.
+/// A retransmit sent out under a new id must go through retry(), which carries over the
+/// original's decision without counting towards the next sample.
+pub struct LatencySampler {
+    // One in these many requests is sampled.
+    rate: u64,
+
+    // The number of requests left to send out before the next one is sampled.
+    countdown: u64,
+
+    // Ids of outstanding requests that were sampled. Unused if every request is sampled.
+    sampled: HashSet<u64>,
+
+    // The number of responses received.
+    completed: u64,
+
+    // The number of responses whose latency is to be recorded.
+    samples: u64,
+}
+
+impl LatencySampler {
+    /// Creates a sampler.
+    ///
+    /// # Arguments
+    ///
+    /// * `rate`: One in these many requests is sampled. 0 means 1, ie. every request.
+    pub fn new(rate: u64) -> LatencySampler {
+        let rate = rate.max(1);
+        LatencySampler {
+            rate: rate,
+            countdown: 1,
+            sampled: HashSet::new(),
+            completed: 0,
+            samples: 0,
+        }
+    }
+
+    /// Creates a sampler as specified by the client config.
+    pub fn from_config(config: &ClientConfig) -> LatencySampler {
+        LatencySampler::new(config.latency_sample)
+    }
+
+    /// Returns the rate requests are sampled at; one in these many is.
+    pub fn rate(&self) -> u64 {
+        self.rate
+    }
+
+    /// Decides whether a request that is being sent out for the first time is sampled.
+    ///
+    /// # Arguments
+    ///
+    /// * `id`: The id of the request.
+    ///
+    /// # Return
+    ///
+    /// True if the latency of the request is to be recorded.
+    #[inline]
+    pub fn send(&mut self, id: u64) -> bool {
+        self.countdown -= 1;
+        if self.countdown > 0 {
+            return false;
+        }
+
+        self.countdown = self.rate;
+        if self.rate > 1 {
+            self.sampled.insert(id);
+        }
+        true
+    }
+
+    /// Carries over the decision on a request to a retransmit of it sent out under a new id.
+    /// Does not count towards the next sample.
+    ///
+    /// # Arguments
+    ///
+    /// * `orig`: The id the request was originally sent out under.
+    /// * `id`:   The id of the retransmit.
+    ///
+    /// # Return
+    ///
+    /// True if the original, and therefore the retransmit, was sampled.
+    pub fn retry(&mut self, orig: u64, id: u64) -> bool {
+        if self.rate == 1 {
+            return true;
+        }
+
+        if !self.sampled.remove(&orig) {
+            return false;
+        }
+        self.sampled.insert(id);
+        true
+    }
+
+    /// Counts the response to a request. Must be called exactly once per request, so
+    /// duplicates must be dropped beforehand (see dedup::Dedup).
+    ///
+    /// # Arguments
+    ///
+    /// * `id`:     The request id on the response.
+    /// * `status`: The status on the response.
+    ///
+    /// # Return
+    ///
+    /// True if the latency of the request is to be recorded, and the response verified.
+    #[inline]
+    pub fn complete(&mut self, id: u64, status: &RpcStatus) -> bool {
+        self.completed += 1;
+
+        let sampled = self.rate == 1 || self.sampled.remove(&id) || *status != RpcStatus::StatusOk;
+        if sampled {
+            self.samples += 1;
+        }
+        sampled
+    }
+
+    /// Returns the number of responses received, sampled or not.
+    pub fn completed(&self) -> u64 {
+        self.completed
+    }
+
+    /// Returns the number of responses whose latency was to be recorded.
+    pub fn samples(&self) -> u64 {
+        self.samples
+    }
+
+    /// Returns the number of sampled requests still waiting on a response.
+    pub fn outstanding(&self) -> usize {
+        self.sampled.len()
+    }
+
+    /// Returns how finely latency was sampled so far.
+    pub fn resolution(&self) -> Resolution {
+        resolution(self.samples, self.completed)
+    }
+}
+
+/// Returns the resolution of a distribution sampled off some number of responses. Used to
+/// combine samplers across pipelines.
+///
+/// # Arguments
+///
+/// * `samples`:   The number of responses sampled.
+/// * `completed`: The number of responses received.
+pub fn resolution(samples: u64, completed: u64) -> Resolution {
+    Resolution {
+        samples: samples,
+        completed: completed,
+        rate: match samples {
+            0 => 0.0,
+            n => completed as f64 / n as f64,
+        },
+    }
+}
+
+// This module contains unit tests for LatencySampler.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Tests that exactly one in every `rate` requests is sampled, and that every response is
+    // counted whether it was sampled or not.
+    #[test]
+    fn test_sampler_rate() {
+        let mut s = LatencySampler::new(4);
+        let sampled: Vec<u64> = (0..16).filter(|&id| s.send(id)).collect();
+        assert_eq!(vec![0, 4, 8, 12], sampled);
+        assert_eq!(4, s.outstanding());
+
+        let recorded: Vec<u64> = (0..16)
+            .filter(|&id| s.complete(id, &RpcStatus::StatusOk))
+            .collect();
+        assert_eq!(sampled, recorded);
+        assert_eq!(16, s.completed());
+        assert_eq!(4, s.samples());
+        assert_eq!(0, s.outstanding());
+        assert_eq!(resolution(4, 16), s.resolution());
+        assert_eq!(4.0, s.resolution().rate);
+    }
+
+    // Tests that every request is sampled at the default rate, and no ids are held on to.
+    #[test]
+    fn test_sampler_every() {
+        let mut s = LatencySampler::new(0);
+        assert_eq!(1, s.rate());
+        for id in 0..100 {
+            assert!(s.send(id));
+        }
+        assert_eq!(0, s.outstanding());
+
+        for id in 0..100 {
+            assert!(s.complete(id, &RpcStatus::StatusOk));
+        }
+        assert_eq!(100, s.samples());
+    }
+
+    // Tests that pushbacks and errors are sampled even if the request wasn't.
+    #[test]
+    fn test_sampler_errors() {
+        let mut s = LatencySampler::new(1000);
+        assert!(s.send(1));
+        assert!(!s.send(2));
+        assert!(!s.send(3));
+
+        assert!(s.complete(2, &RpcStatus::StatusPushback));
+        assert!(s.complete(3, &RpcStatus::StatusObjectDoesNotExist));
+        assert!(s.complete(1, &RpcStatus::StatusOk));
+        assert_eq!(3, s.samples());
+        assert_eq!(1.0, s.resolution().rate);
+    }
+
+    // Tests that retransmits inherit the decision on the original, whether they reuse it's id
+    // or not, without shifting which later requests are sampled.
+    #[test]
+    fn test_sampler_retries() {
+        let mut s = LatencySampler::new(2);
+        assert!(s.send(10));
+        assert!(!s.send(11));
+
+        // Both are retransmitted under new ids.
+        assert!(s.retry(10, 20));
+        assert!(!s.retry(11, 21));
+        assert_eq!(1, s.outstanding());
+
+        // The decision on later requests is unaffected by the retransmits.
+        assert!(s.send(12));
+        assert!(!s.send(13));
+
+        // 12 is retransmitted under it's own id, which needs no bookkeeping; whichever response
+        // arrives first is sampled, as the original's would have been.
+        assert!(s.complete(12, &RpcStatus::StatusOk));
+        assert!(s.complete(20, &RpcStatus::StatusOk));
+        assert!(!s.complete(21, &RpcStatus::StatusOk));
+        assert!(!s.complete(13, &RpcStatus::StatusOk));
+
+        // The original ids were handed over, so late responses to them are not sampled.
+        assert!(!s.complete(10, &RpcStatus::StatusOk));
+        assert_eq!(5, s.completed());
+        assert_eq!(2, s.samples());
+        assert_eq!(0, s.outstanding());
+    }
+
+    // Tests that resolutions without samples don't divide by zero.
+    #[test]
+    fn test_resolution_empty() {
+        assert_eq!(0.0, resolution(0, 0).rate);
+        assert_eq!(0.0, resolution(0, 10).rate);
+    }
+}
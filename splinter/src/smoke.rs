@@ -0,0 +1,1085 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use std::fmt;
+use std::io;
+use std::mem::{size_of, transmute};
+use std::time::Duration;
+
+use db::rpc;
+use db::wireformat::*;
+
+use super::fault::Fault;
+use super::migrate::Endpoint;
+use super::preflight;
+use super::rng;
+use super::udp::{self, Response};
+
+// The records the script writes, and then reads back. Every key is as long as MISSING, so that
+// they can all be looked up by a single multiget().
+const KEYS: [&[u8]; 3] = [b"smoke:key:0", b"smoke:key:1", b"smoke:key:2"];
+const VALUES: [&[u8]; 3] = [
+    b"smoke-value-zero",
+    b"smoke-value-one",
+    b"smoke-value-two-is-longer",
+];
+
+// A record that is only ever written by multiput().
+const MULTIPUT: (&[u8], &[u8]) = (b"smoke:key:m", b"smoke-value-multiput");
+
+// A key the script never writes.
+const MISSING: &[u8] = b"smoke:key:x";
+
+// The name of an extension no server loads.
+const NO_EXTENSION: &[u8] = b"smoke-no-such-extension";
+
+/// What the smoke test is run against.
+#[derive(Clone, Debug)]
+pub struct SmokeConfig {
+    /// The tenant every check is sent as.
+    pub tenant: u32,
+
+    /// The table records are written to and read from. Must already exist on the server.
+    pub table: u64,
+
+    /// If non-zero, the table was filled with these many records by Master::fill_test(), and
+    /// the first and last of them are read back.
+    pub fill: u32,
+
+    /// The extensions to invoke, with their arguments and expected output.
+    pub invokes: Vec<Canned>,
+
+    /// How long to wait for each response.
+    pub timeout: Duration,
+}
+
+impl Default for SmokeConfig {
+    fn default() -> SmokeConfig {
+        SmokeConfig {
+            tenant: 1,
+            table: 1,
+            fill: 0,
+            invokes: Vec::new(),
+            timeout: Duration::from_millis(500),
+        }
+    }
+}
+
+/// An invoke() of an extension with canned arguments.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Canned {
+    /// The name of the extension.
+    pub name: String,
+
+    /// The arguments passed to the extension.
+    pub args: Vec<u8>,
+
+    /// What the extension is expected to write out. Only it's status is checked if None.
+    pub output: Option<Vec<u8>>,
+}
+
+impl Canned {
+    /// Parses an invocation formatted as "NAME:ARGS" or "NAME:ARGS:OUTPUT", with the arguments
+    /// and output in hex.
+    pub fn parse(spec: &str) -> Option<Canned> {
+        let mut parts = spec.splitn(3, ':');
+        let name = match parts.next() {
+            Some(name) if !name.is_empty() => name,
+            _ => return None,
+        };
+        let args = parse_hex(parts.next()?)?;
+        let output = match parts.next() {
+            Some(hex) => Some(parse_hex(hex)?),
+            None => None,
+        };
+
+        Some(Canned {
+            name: name.to_string(),
+            args: args,
+            output: output,
+        })
+    }
+}
+
+// Parses a string of hex digits into the bytes it stands for.
+fn parse_hex(digits: &str) -> Option<Vec<u8>> {
+    if digits.len() % 2 != 0 {
+        return None;
+    }
+
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(digits.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// A request a check sends to the server.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Request {
+    /// A get() of a key in a table.
+    Get(u64, Vec<u8>),
+
+    /// A get() of a byte range of the value under a key, as (offset, length).
+    GetRange(u64, Vec<u8>, (u32, u32)),
+
+    /// A put() of a key and value into a table.
+    Put(u64, Vec<u8>, Vec<u8>),
+
+    /// A multiget() of keys of equal length from a table.
+    MultiGet(u64, Vec<Vec<u8>>),
+
+    /// A multiput() of records into a table.
+    MultiPut(u64, Vec<(Vec<u8>, Vec<u8>)>),
+
+    /// A dump() of a table, starting at it's first bucket.
+    Dump(u64),
+
+    /// An invoke() of an extension by name, with arguments.
+    Invoke(Vec<u8>, Vec<u8>),
+
+    /// The first page of a list_extensions().
+    ListExt,
+
+    /// A write_stats() asking for the totals of a table.
+    WriteStats(u64),
+}
+
+impl Request {
+    /// Returns the opcode the request is sent with.
+    pub fn opcode(&self) -> OpCode {
+        match *self {
+            Request::Get(..) | Request::GetRange(..) => OpCode::SandstormGetRpc,
+            Request::Put(..) => OpCode::SandstormPutRpc,
+            Request::MultiGet(..) => OpCode::SandstormMultiGetRpc,
+            Request::MultiPut(..) => OpCode::SandstormMultiPutRpc,
+            Request::Dump(..) => OpCode::SandstormDumpRpc,
+            Request::Invoke(..) => OpCode::SandstormInvokeRpc,
+            Request::ListExt => OpCode::SandstormListExtRpc,
+            Request::WriteStats(..) => OpCode::SandstormWriteStatsRpc,
+        }
+    }
+
+    /// Builds the wire bytes of the request. Refer to udp::encode_get().
+    pub fn encode(&self, tenant: u32, id: u64) -> Vec<u8> {
+        match *self {
+            Request::Get(table, ref key) => udp::encode_get(tenant, table, key, id, 0),
+
+            Request::GetRange(table, ref key, range) => {
+                udp::encode_get_with_projection(tenant, table, key, 0, range, id, 0)
+            }
+
+            Request::Put(table, ref key, ref val) => {
+                udp::encode_put(tenant, table, key, val, id, 0)
+            }
+
+            Request::MultiGet(table, ref keys) => {
+                let k_len = keys.first().map_or(0, |k| k.len()) as u16;
+                let keys: Vec<u8> = keys.iter().flat_map(|k| k.iter().cloned()).collect();
+                let n_keys = keys.len() as u32 / (k_len as u32).max(1);
+                udp::encode_multiget(tenant, table, k_len, n_keys, &keys, id, 0)
+            }
+
+            Request::MultiPut(table, ref records) => {
+                let mut buf = Vec::new();
+                for &(ref key, ref val) in records.iter() {
+                    rpc::append_kv(&mut buf, key, val);
+                }
+                udp::encode_multiput(tenant, table, &buf, records.len() as u32, id, 0)
+            }
+
+            Request::Dump(table) => udp::encode_dump(tenant, table, 0, &[], id, 0),
+
+            Request::Invoke(ref name, ref args) => {
+                let payload = [&name[..], &args[..]].concat();
+                udp::encode_invoke(tenant, name.len() as u32, &payload, id, 0)
+            }
+
+            Request::ListExt => udp::encode_list_ext(tenant, 0, id, 0),
+
+            Request::WriteStats(table) => {
+                udp::encode_write_stats(tenant, table, WRITE_STATS_TOTALS, id, 0)
+            }
+        }
+    }
+}
+
+/// What a check expects the server to respond with.
+#[derive(Clone)]
+pub enum Expect {
+    /// Exactly this status. The payload is not looked at.
+    Status(RpcStatus),
+
+    /// Any one of these statuses. The payload is not looked at.
+    OneOf(&'static [RpcStatus]),
+
+    /// StatusOk, with exactly these bytes in the payload.
+    Bytes(Vec<u8>),
+
+    /// StatusOk, with a payload the predicate holds for. The string describes the predicate.
+    Holds(&'static str, fn(&[u8]) -> bool),
+}
+
+impl Expect {
+    // Returns None if a response with `status` and `payload` is what was expected, and what was
+    // wrong with it otherwise.
+    fn verify(&self, status: &RpcStatus, payload: &[u8]) -> Option<String> {
+        let ok = RpcStatus::StatusOk;
+        match *self {
+            Expect::Status(ref s) if s == status => None,
+            Expect::OneOf(statuses) if statuses.contains(status) => None,
+            Expect::Bytes(ref b) if *status == ok && &b[..] == payload => None,
+            Expect::Holds(_, f) if *status == ok && f(payload) => None,
+
+            Expect::Status(ref s) => Some(format!("expected {:?}, got {:?}", s, status)),
+            Expect::OneOf(statuses) => Some(format!("expected {:?}, got {:?}", statuses, status)),
+            Expect::Bytes(ref b) if *status == ok => Some(format!(
+                "expected {} bytes {:?}, got {} bytes {:?}",
+                b.len(),
+                String::from_utf8_lossy(b),
+                payload.len(),
+                String::from_utf8_lossy(payload)
+            )),
+            Expect::Holds(what, _) if *status == ok => {
+                Some(format!("expected a payload that {}", what))
+            }
+            _ => Some(format!("expected StatusOk, got {:?}", status)),
+        }
+    }
+}
+
+/// A single step of the smoke test: a request, and the response expected for it.
+#[derive(Clone)]
+pub struct Check {
+    /// The name the outcome of the check is reported under.
+    pub name: String,
+
+    /// The request sent to the server.
+    pub request: Request,
+
+    /// A fault injected into the request before it is sent out, if any.
+    pub fault: Option<Fault>,
+
+    /// The response expected for the request.
+    pub expect: Expect,
+}
+
+impl Check {
+    /// Returns a check that sends out `request`, and expects `expect` back.
+    pub fn new(name: &str, request: Request, expect: Expect) -> Check {
+        Check {
+            name: name.to_string(),
+            request: request,
+            fault: None,
+            expect: expect,
+        }
+    }
+
+    /// Returns a check that injects `fault` into `request`, and expects the server to reject it
+    /// with one of the statuses the fault calls for. Refer to Fault::expected().
+    pub fn faulty(name: &str, request: Request, fault: Fault) -> Check {
+        let expect = Expect::OneOf(fault.expected(request.opcode()));
+        Check {
+            name: name.to_string(),
+            request: request,
+            fault: Some(fault),
+            expect: expect,
+        }
+    }
+}
+
+/// Returns the checks the smoke test runs, in the order they are run in. Reads come after the
+/// writes they depend on. Covering another opcode needs a Request variant and an entry here.
+pub fn script(config: &SmokeConfig) -> Vec<Check> {
+    let t = config.table;
+    let (k, v) = (|i: usize| KEYS[i].to_vec(), |i: usize| VALUES[i].to_vec());
+    let (mp_key, mp_val) = (MULTIPUT.0.to_vec(), MULTIPUT.1.to_vec());
+    let ok = || Expect::Status(RpcStatus::StatusOk);
+
+    let mut checks = vec![
+        Check::new("put 0", Request::Put(t, k(0), v(0)), ok()),
+        Check::new("put 1", Request::Put(t, k(1), v(1)), ok()),
+        Check::new("put 2", Request::Put(t, k(2), v(2)), ok()),
+        Check::new("get 0", Request::Get(t, k(0)), Expect::Bytes(v(0))),
+        Check::new("get 1", Request::Get(t, k(1)), Expect::Bytes(v(1))),
+        Check::new("get 2", Request::Get(t, k(2)), Expect::Bytes(v(2))),
+        Check::new(
+            "get range",
+            Request::GetRange(t, k(2), (6, 5)),
+            Expect::Bytes(VALUES[2][6..11].to_vec()),
+        ),
+        Check::new(
+            "get miss",
+            Request::Get(t, MISSING.to_vec()),
+            Expect::Status(RpcStatus::StatusObjectDoesNotExist),
+        ),
+        Check::faulty("get bad table", Request::Get(t, k(0)), Fault::BadTable),
+        Check::faulty("get bad tenant", Request::Get(t, k(0)), Fault::BadTenant),
+        Check::faulty("get malformed", Request::Get(t, k(0)), Fault::KeyLength),
+        Check::new(
+            "put empty value",
+            Request::Put(t, MISSING.to_vec(), Vec::new()),
+            Expect::Status(RpcStatus::StatusMalformedRequest),
+        ),
+        Check::faulty(
+            "put bad table",
+            Request::Put(t, k(0), v(0)),
+            Fault::BadTable,
+        ),
+        Check::faulty(
+            "put malformed",
+            Request::Put(t, k(0), v(0)),
+            Fault::Truncate(VALUES[0].len() + 1),
+        ),
+        Check::new(
+            "multiget",
+            Request::MultiGet(t, vec![k(0), k(1), k(2)]),
+            Expect::Bytes([VALUES[0], VALUES[1], VALUES[2]].concat()),
+        ),
+        Check::new(
+            "multiget miss",
+            Request::MultiGet(t, vec![k(0), MISSING.to_vec()]),
+            Expect::Status(RpcStatus::StatusObjectDoesNotExist),
+        ),
+        Check::faulty(
+            "multiget bad table",
+            Request::MultiGet(t, vec![k(0)]),
+            Fault::BadTable,
+        ),
+        Check::new(
+            "multiput",
+            Request::MultiPut(t, vec![(mp_key.clone(), mp_val.clone())]),
+            ok(),
+        ),
+        Check::new(
+            "get multiput",
+            Request::Get(t, mp_key),
+            Expect::Bytes(mp_val),
+        ),
+        Check::new(
+            "dump",
+            Request::Dump(t),
+            Expect::Holds("holds records", |p| !p.is_empty()),
+        ),
+        Check::new("write stats", Request::WriteStats(t), ok()),
+        Check::new("list extensions", Request::ListExt, ok()),
+        Check::new(
+            "invoke missing",
+            Request::Invoke(NO_EXTENSION.to_vec(), Vec::new()),
+            Expect::Status(RpcStatus::StatusInvalidExtension),
+        ),
+        Check::faulty(
+            "invoke malformed",
+            Request::Invoke(NO_EXTENSION.to_vec(), Vec::new()),
+            Fault::KeyLength,
+        ),
+    ];
+
+    // Records written by Master::fill_test() have their index in the first four bytes of the
+    // key and value, and are zeroed out from there.
+    if config.fill > 0 {
+        for &i in [1, config.fill].iter() {
+            let (mut key, mut val) = (vec![0; 30], vec![0; 100]);
+            let index: [u8; 4] = unsafe { transmute(i.to_le()) };
+            key[..4].copy_from_slice(&index);
+            val[..4].copy_from_slice(&index);
+
+            let name = format!("get fill {}", i);
+            checks.push(Check::new(&name, Request::Get(1, key), Expect::Bytes(val)));
+        }
+    }
+
+    for canned in config.invokes.iter() {
+        let request = Request::Invoke(canned.name.as_bytes().to_vec(), canned.args.clone());
+        let expect = match canned.output {
+            Some(ref output) => Expect::Bytes(output.clone()),
+            None => ok(),
+        };
+        checks.push(Check::new(
+            &format!("invoke {}", canned.name),
+            request,
+            expect,
+        ));
+    }
+
+    checks
+}
+
+/// How a check turned out.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome {
+    /// The server responded as expected.
+    Pass,
+
+    /// The server responded with something else, or did not respond at all.
+    Fail(String),
+
+    /// The server does not serve the check's opcode, so the check was not run.
+    Skipped,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Outcome::Pass => write!(f, "pass"),
+            Outcome::Fail(ref why) => write!(f, "FAIL: {}", why),
+            Outcome::Skipped => write!(f, "skipped"),
+        }
+    }
+}
+
+/// The outcome of every check run by the smoke test.
+#[derive(Clone, Debug, Default)]
+pub struct Report {
+    /// The opcodes the server said it serves, as a mask of OpCode::bit().
+    pub opcodes: u64,
+
+    /// The name and outcome of each check, in the order they were run in.
+    pub outcomes: Vec<(String, Outcome)>,
+}
+
+impl Report {
+    /// Returns the number of checks that failed.
+    pub fn failures(&self) -> usize {
+        self.outcomes
+            .iter()
+            .filter(|&&(_, ref o)| match *o {
+                Outcome::Fail(_) => true,
+                _ => false,
+            })
+            .count()
+    }
+}
+
+/// Runs checks against a server, one at a time. Checks whose opcode the server does not serve
+/// are skipped; which opcodes are served is found out through an echo() before any check runs.
+///
+/// # Arguments
+///
+/// * `server`: The server the checks are run against.
+/// * `checks`: The checks to run, usually from script().
+/// * `config`: The tenant the checks are sent as, and how long to wait for each response.
+///
+/// # Return
+///
+/// The outcome of each check, or an error if the server did not respond to the echo().
+pub fn run(server: &Endpoint, checks: &[Check], config: &SmokeConfig) -> io::Result<Report> {
+    let (opcodes, _) = server.echo(config.tenant, config.timeout)?;
+    let mut rng = rng::xorshift(0, 0);
+
+    let mut report = Report {
+        opcodes: opcodes,
+        outcomes: Vec::with_capacity(checks.len()),
+    };
+
+    for check in checks.iter() {
+        let op = check.request.opcode();
+        if opcodes & op.bit() == 0 {
+            report.outcomes.push((check.name.clone(), Outcome::Skipped));
+            continue;
+        }
+
+        let mut applied = true;
+        let res = {
+            let send = |s: &udp::UdpSender, id| {
+                let mut req = check.request.encode(config.tenant, id);
+                if let Some(ref fault) = check.fault {
+                    applied = fault.apply(&mut req, &mut rng);
+                }
+                s.send_encoded(config.tenant, &req);
+            };
+            server.call(send, config.timeout)
+        };
+
+        let outcome = match res {
+            _ if !applied => Outcome::Fail(String::from("fault does not apply to the request")),
+            Some(res) => match verify(&res, op, &check.expect) {
+                None => Outcome::Pass,
+                Some(why) => Outcome::Fail(why),
+            },
+            None => Outcome::Fail(String::from("timed out")),
+        };
+        report.outcomes.push((check.name.clone(), outcome));
+    }
+
+    Ok(report)
+}
+
+/// Checks the arguments of every canned invocation against the layout it's extension declared,
+/// as listed for the tenant, so that invocations the server would refuse with
+/// StatusArgsMismatch are caught before any check runs. Nothing is checked if the server does
+/// not serve list_extensions(); extensions that aren't listed are left to their check.
+///
+/// # Arguments
+///
+/// * `server`: The server the invocations will be sent to.
+/// * `config`: The invocations, and the tenant they are sent as.
+///
+/// # Return
+///
+/// A message per invocation whose arguments don't fit, or an error if the server did not
+/// respond.
+pub fn check_invokes(server: &Endpoint, config: &SmokeConfig) -> io::Result<Vec<String>> {
+    if config.invokes.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let (opcodes, _) = server.echo(config.tenant, config.timeout)?;
+    if opcodes & OpCode::SandstormListExtRpc.bit() == 0 {
+        return Ok(Vec::new());
+    }
+
+    let available = server.list_extensions(config.tenant, config.timeout)?;
+    Ok(config
+        .invokes
+        .iter()
+        .filter_map(|c| preflight::check_args(&c.name, &c.args, &available).err())
+        .collect())
+}
+
+// Returns None if `res` is a well formed response to a request with opcode `op` that was
+// expected, and what was wrong with it otherwise.
+fn verify(res: &Response, op: OpCode, expect: &Expect) -> Option<String> {
+    let hdr_len = match op {
+        OpCode::SandstormGetRpc => size_of::<GetResponse>(),
+        OpCode::SandstormPutRpc => size_of::<PutResponse>(),
+        OpCode::SandstormMultiGetRpc => size_of::<MultiGetResponse>(),
+        OpCode::SandstormMultiPutRpc => size_of::<MultiPutResponse>(),
+        OpCode::SandstormDumpRpc => size_of::<DumpResponse>(),
+        OpCode::SandstormInvokeRpc => size_of::<InvokeResponse>(),
+        OpCode::SandstormListExtRpc => size_of::<ListExtResponse>(),
+        OpCode::SandstormWriteStatsRpc => size_of::<WriteStatsResponse>(),
+        _ => size_of::<RpcResponseHeader>(),
+    };
+
+    if res.opcode() != op {
+        return Some(format!(
+            "expected a {:?} response, got {:?}",
+            op,
+            res.opcode()
+        ));
+    }
+
+    if !res.length_ok() {
+        return Some(String::from("value length does not match the payload"));
+    }
+
+    // Responses that failed may not carry the opcode's full header.
+    let parsed = match res.parse_header::<RpcResponseHeader>() {
+        Some(parsed) => parsed,
+        None => return Some(String::from("malformed response header")),
+    };
+    let payload = parsed.get_payload();
+    let payload = &payload[(hdr_len - size_of::<RpcResponseHeader>()).min(payload.len())..];
+
+    expect.verify(&parsed.get_header().status, payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::UdpSocket;
+    use std::str;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    use db::config;
+    use db::limits;
+    use db::master::Master;
+
+    use sandstorm::buf;
+    use sandstorm::entry::ExtError;
+    use sandstorm::ext::{ExtensionInfo, Provenance};
+    use sandstorm::put;
+    use sandstorm::schema::{Field, Schema};
+
+    use udp::encode;
+
+    // A regression seeded into the stand-in.
+    enum Seed {
+        // Every response is correct.
+        Nothing,
+
+        // Every response to the opcode carries this status instead.
+        Status(OpCode, RpcStatus),
+
+        // The last byte of every response to the opcode is flipped.
+        Flip(OpCode),
+    }
+
+    // A loopback stand-in for a server, wrapping a Master filled by fill_test(). Answers the
+    // opcodes in `opcodes` until it is dropped, and rejects every other one the way a server
+    // configured not to serve it would. It serves two extensions: "reverse", which writes out
+    // it's arguments in reverse, and takes 1 to 8 bytes of them, and "put", which writes an
+    // object like the put extension does.
+    struct StandIn {
+        port: u16,
+        stop: Arc<AtomicBool>,
+        handle: Option<thread::JoinHandle<()>>,
+    }
+
+    impl StandIn {
+        fn new(opcodes: u64, seed: Seed) -> StandIn {
+            let master = Master::new();
+            master.fill_test(1, 1, 10);
+
+            let socket = UdpSocket::bind("127.0.0.1:0").expect("Failed to bind server");
+            socket
+                .set_read_timeout(Some(Duration::from_millis(10)))
+                .expect("Failed to set timeout");
+            let port = socket.local_addr().unwrap().port();
+
+            let stop = Arc::new(AtomicBool::new(false));
+            let flag = stop.clone();
+            let handle = thread::spawn(move || serve(socket, &master, opcodes, &seed, &flag));
+
+            StandIn {
+                port: port,
+                stop: stop,
+                handle: Some(handle),
+            }
+        }
+
+        fn connect(&self) -> Endpoint {
+            let mut config = config::ClientConfig::default();
+            config.ip_address = String::from("127.0.0.1");
+            config.server_ip_address = String::from("127.0.0.1");
+            config.udp_client_port = 0;
+            config.udp_server_port = self.port;
+            Endpoint::connect(&config).expect("Failed to connect")
+        }
+    }
+
+    impl Drop for StandIn {
+        fn drop(&mut self) {
+            self.stop.store(true, Ordering::Relaxed);
+            self.handle.take().map(|h| h.join());
+        }
+    }
+
+    fn serve(socket: UdpSocket, master: &Master, opcodes: u64, seed: &Seed, stop: &AtomicBool) {
+        let mut buf = vec![0; 65536];
+
+        while !stop.load(Ordering::Relaxed) {
+            let (len, src) = match socket.recv_from(&mut buf) {
+                Ok(r) => r,
+                Err(_) => continue,
+            };
+
+            let req = &buf[..len];
+            let op: OpCode = unsafe { transmute(req[1]) };
+            let mut res = match opcodes & op.bit() {
+                0 => {
+                    let hdr: &RpcRequestHeader =
+                        unsafe { &*(req.as_ptr() as *const RpcRequestHeader) };
+                    let mut res =
+                        RpcResponseHeader::new(hdr.id(), hdr.stamp(), op.clone(), hdr.tenant());
+                    res.status = RpcStatus::StatusOperationDisabled;
+                    encode(res, &[])
+                }
+                _ => respond(master, opcodes, req),
+            };
+
+            match *seed {
+                Seed::Status(ref o, ref status) if *o == op => res[0] = status.clone() as u8,
+                Seed::Flip(ref o) if *o == op => *res.last_mut().unwrap() ^= 0xff,
+                _ => {}
+            }
+
+            socket.send_to(&res, src).expect("Server send failed");
+        }
+    }
+
+    // The listing of the extensions the stand-in serves.
+    fn extensions() -> Vec<ExtensionInfo> {
+        vec![
+            ExtensionInfo {
+                name: b"reverse".to_vec(),
+                version: 0,
+                loaded: 1_500_000_000,
+                invocations: 0,
+                provenance: Provenance::Private,
+                schema: Some(Schema::new(vec![Field::Blob(1, 8)]).unwrap()),
+                id: 1,
+            },
+            ExtensionInfo {
+                name: put::NAME.to_vec(),
+                version: 0,
+                loaded: 1_500_000_000,
+                invocations: 0,
+                provenance: Provenance::Private,
+                schema: Some(Schema::new(put::FIELDS.to_vec()).unwrap()),
+                id: 2,
+            },
+        ]
+    }
+
+    // Handles a request the way the server would.
+    fn respond(master: &Master, opcodes: u64, req: &[u8]) -> Vec<u8> {
+        let hdr: &RpcRequestHeader = unsafe { &*(req.as_ptr() as *const RpcRequestHeader) };
+        let (tenant, id, stamp) = (hdr.tenant(), hdr.id(), hdr.stamp());
+        let malformed = RpcStatus::StatusMalformedRequest;
+
+        match req[1] {
+            op if op == OpCode::SandstormEchoRpc as u8 => {
+                encode(EchoResponse::new(id, stamp, tenant, 0, opcodes, 0, 0), &[])
+            }
+
+            op if op == OpCode::SandstormGetRpc as u8 => {
+                let hdr: &GetRequest = unsafe { &*(req.as_ptr() as *const GetRequest) };
+                let (table, k_len) = (hdr.table_id(), hdr.key_length() as usize);
+                let (offset, length) = (hdr.value_offset(), hdr.value_length());
+                let key = &req[size_of::<GetRequest>()..];
+
+                let mut res = GetResponse::new(id, stamp, OpCode::SandstormGetRpc, tenant);
+                let value = match key.len() < k_len {
+                    true => Err(malformed),
+                    false => master.get_value(tenant, table, &key[..k_len]),
+                };
+                match value {
+                    Ok(value) => {
+                        let value = &value[buf::projection(value.len(), offset, length)];
+                        res.set_value_length(value.len() as u32);
+                        encode(res, &[value])
+                    }
+                    Err(status) => {
+                        res.common_header.status = status;
+                        encode(res, &[])
+                    }
+                }
+            }
+
+            op if op == OpCode::SandstormPutRpc as u8 => {
+                let hdr: &PutRequest = unsafe { &*(req.as_ptr() as *const PutRequest) };
+                let (table, k_len) = (hdr.table_id(), hdr.key_length() as usize);
+                let payload = &req[size_of::<PutRequest>()..];
+
+                let mut res = PutResponse::new(id, stamp, OpCode::SandstormPutRpc, tenant);
+                res.common_header.status = match payload.len() < k_len {
+                    true => malformed,
+                    false => {
+                        let (key, val) = payload.split_at(k_len);
+                        master.put_value(tenant, table, key, val)
+                    }
+                };
+                encode(res, &[])
+            }
+
+            op if op == OpCode::SandstormMultiGetRpc as u8 => {
+                let hdr: &MultiGetRequest = unsafe { &*(req.as_ptr() as *const MultiGetRequest) };
+                let (table, k_len, num) = (hdr.table_id(), hdr.key_len() as usize, hdr.num_keys());
+                let keys = &req[size_of::<MultiGetRequest>()..];
+
+                let mut res =
+                    MultiGetResponse::new(id, stamp, OpCode::SandstormMultiGetRpc, tenant, 0);
+                let mut values = Vec::new();
+                if k_len == 0 || keys.len() < k_len * num as usize {
+                    res.common_header.status = malformed;
+                }
+                for key in keys.chunks(k_len.max(1)).take(num as usize) {
+                    if res.common_header.status != RpcStatus::StatusOk {
+                        break;
+                    }
+                    match master.get_value(tenant, table, key) {
+                        Ok(value) => values.extend_from_slice(&value),
+                        Err(status) => res.common_header.status = status,
+                    }
+                }
+                match res.common_header.status {
+                    RpcStatus::StatusOk => {
+                        res.set_num_records(num);
+                        encode(res, &[&values])
+                    }
+                    _ => encode(res, &[]),
+                }
+            }
+
+            op if op == OpCode::SandstormMultiPutRpc as u8 => {
+                let hdr: &MultiPutRequest = unsafe { &*(req.as_ptr() as *const MultiPutRequest) };
+                let (table, num) = (hdr.table_id(), hdr.num_records());
+                let records = &req[size_of::<MultiPutRequest>()..];
+
+                let mut res = MultiPutResponse::new(id, stamp, tenant);
+                res.common_header.status = master.put_records(tenant, table, records, num);
+                encode(res, &[])
+            }
+
+            op if op == OpCode::SandstormDumpRpc as u8 => {
+                let hdr: &DumpRequest = unsafe { &*(req.as_ptr() as *const DumpRequest) };
+                let (table, bucket) = (hdr.table_id(), hdr.bucket());
+                let after = &req[size_of::<DumpRequest>()..];
+
+                let mut res = DumpResponse::new(id, stamp, tenant);
+                match master.dump_table(tenant, table, bucket, after, 256) {
+                    Ok((records, num, bucket)) => {
+                        res.set_num_records(num);
+                        res.set_bucket(bucket);
+                        encode(res, &[&records])
+                    }
+                    Err(status) => {
+                        res.common_header.status = status;
+                        encode(res, &[])
+                    }
+                }
+            }
+
+            op if op == OpCode::SandstormWriteStatsRpc as u8 => {
+                let hdr: &WriteStatsRequest =
+                    unsafe { &*(req.as_ptr() as *const WriteStatsRequest) };
+                let (table, query) = (hdr.table_id(), hdr.query);
+
+                let mut res = WriteStatsResponse::new(id, stamp, tenant);
+                match master.write_stats(tenant, table, query) {
+                    Ok((entries, num)) => {
+                        res.set_num_entries(num);
+                        encode(res, &[&entries])
+                    }
+                    Err(status) => {
+                        res.common_header.status = status;
+                        encode(res, &[])
+                    }
+                }
+            }
+
+            op if op == OpCode::SandstormListExtRpc as u8 => {
+                let (entries, num, _) = rpc::encode_ext_listing(&extensions(), 0, 1024);
+                let mut res = ListExtResponse::new(id, stamp, tenant);
+                res.set_num_entries(num);
+                encode(res, &[&entries])
+            }
+
+            _ => {
+                let hdr: &InvokeRequest = unsafe { &*(req.as_ptr() as *const InvokeRequest) };
+                let (n_len, a_len) = (hdr.name_length() as usize, hdr.args_length() as usize);
+                let payload = &req[size_of::<InvokeRequest>()..];
+
+                let mut res = InvokeResponse::new(id, stamp, OpCode::SandstormInvokeRpc, tenant);
+                if payload.len() < n_len + a_len {
+                    res.common_header.status = malformed;
+                    return encode(res, &[]);
+                }
+
+                let (name, args) = payload.split_at(n_len);
+                let args = &args[..a_len];
+                let schema = extensions()
+                    .into_iter()
+                    .find(|e| e.name == name)
+                    .and_then(|e| e.schema);
+                if schema.map_or(false, |schema| schema.check(args).is_err()) {
+                    res.common_header.status = RpcStatus::StatusArgsMismatch;
+                    return encode(res, &[]);
+                }
+
+                match str::from_utf8(name) {
+                    Ok("reverse") => {
+                        let output: Vec<u8> = args.iter().rev().cloned().collect();
+                        encode(res, &[&output])
+                    }
+                    Ok("put") => {
+                        // The arguments fit the layout, so they always decode.
+                        let (table, key, val) = put::decode_args(args).unwrap();
+                        match master.put_value(tenant, table, key, val) {
+                            RpcStatus::StatusOk => encode(res, &[put::SUCCESS]),
+                            RpcStatus::StatusReadOnlyTable => {
+                                res.common_header.status = RpcStatus::StatusReadOnlyTable;
+                                encode(res, &[])
+                            }
+                            _ => encode(res, &[&ExtError::AllocationFailed.frame()]),
+                        }
+                    }
+                    Ok(_) => {
+                        res.common_header.status = RpcStatus::StatusInvalidExtension;
+                        encode(res, &[])
+                    }
+                    Err(_) => {
+                        res.common_header.status = malformed;
+                        encode(res, &[])
+                    }
+                }
+            }
+        }
+    }
+
+    fn smoke_config() -> SmokeConfig {
+        let mut config = SmokeConfig::default();
+        config.fill = 10;
+        config.invokes = vec![Canned::parse("reverse:616263:636261").unwrap()];
+        config
+    }
+
+    // Runs the script against a stand-in, and returns the names of the checks that failed and
+    // that were skipped.
+    fn smoke(opcodes: u64, seed: Seed) -> (Vec<String>, Vec<String>) {
+        let server = StandIn::new(opcodes, seed);
+        let config = smoke_config();
+        let report = run(&server.connect(), &script(&config), &config).expect("No echo");
+        assert_eq!(opcodes, report.opcodes);
+
+        let (mut failed, mut skipped) = (Vec::new(), Vec::new());
+        for &(ref name, ref outcome) in report.outcomes.iter() {
+            match *outcome {
+                Outcome::Fail(_) => failed.push(name.clone()),
+                Outcome::Skipped => skipped.push(name.clone()),
+                Outcome::Pass => {}
+            }
+        }
+        assert_eq!(failed.len(), report.failures());
+        (failed, skipped)
+    }
+
+    // Tests that every check passes against a server that behaves.
+    #[test]
+    fn test_smoke_passes() {
+        let (failed, skipped) = smoke(OPCODES_ALL, Seed::Nothing);
+        assert!(failed.is_empty(), "{:?}", failed);
+        assert!(skipped.is_empty(), "{:?}", skipped);
+    }
+
+    // Tests that checks on opcodes the server does not serve are skipped, and don't fail.
+    #[test]
+    fn test_smoke_skips_disabled() {
+        let opcodes =
+            OPCODES_ALL & !OpCode::SandstormMultiGetRpc.bit() & !OpCode::SandstormInvokeRpc.bit();
+        let (failed, skipped) = smoke(opcodes, Seed::Nothing);
+        assert!(failed.is_empty(), "{:?}", failed);
+        assert_eq!(
+            vec![
+                "multiget",
+                "multiget miss",
+                "multiget bad table",
+                "invoke missing",
+                "invoke malformed",
+                "invoke reverse",
+            ],
+            skipped
+        );
+    }
+
+    // Tests that a handler returning the wrong status is caught.
+    #[test]
+    fn test_smoke_catches_status() {
+        let seed = Seed::Status(OpCode::SandstormPutRpc, RpcStatus::StatusOk);
+        let (failed, _) = smoke(OPCODES_ALL, seed);
+        assert_eq!(
+            vec!["put empty value", "put bad table", "put malformed"],
+            failed
+        );
+    }
+
+    // Tests that a handler returning the wrong bytes is caught.
+    #[test]
+    fn test_smoke_catches_bytes() {
+        let (failed, _) = smoke(OPCODES_ALL, Seed::Flip(OpCode::SandstormInvokeRpc));
+        assert_eq!(vec!["invoke reverse"], failed);
+    }
+
+    // Tests that a canned invocation that doesn't fit the layout it's extension declared is
+    // caught before any check runs, while ones that fit are let through to run.
+    #[test]
+    fn test_smoke_check_invokes() {
+        let server = StandIn::new(OPCODES_ALL, Seed::Nothing);
+        let endpoint = server.connect();
+        let mut config = smoke_config();
+        assert!(check_invokes(&endpoint, &config).unwrap().is_empty());
+
+        config.invokes = vec![
+            Canned::parse("reverse:").unwrap(),
+            Canned::parse("reverse:0102").unwrap(),
+            Canned::parse("reverse:000102030405060708").unwrap(),
+            Canned::parse("smoke-no-such-extension:").unwrap(),
+        ];
+        let mismatches = check_invokes(&endpoint, &config).unwrap();
+        assert_eq!(2, mismatches.len(), "{:?}", mismatches);
+        assert!(mismatches[0].contains("field 0 is 0 bytes"));
+        assert!(mismatches[1].contains("field 0 is 9 bytes"));
+
+        // Had they been sent, the server would have refused them without running the extension.
+        let report = run(&endpoint, &script(&config), &config).expect("No echo");
+        let outcome = |name: &str| {
+            report
+                .outcomes
+                .iter()
+                .filter(|&&(ref n, _)| n == name)
+                .map(|&(_, ref o)| o.clone())
+                .collect::<Vec<Outcome>>()
+        };
+        let reverse = outcome("invoke reverse");
+        assert_eq!(3, reverse.len());
+        assert_eq!(Outcome::Pass, reverse[1]);
+        for o in [&reverse[0], &reverse[2]].iter() {
+            match **o {
+                Outcome::Fail(ref why) => assert!(why.contains("StatusArgsMismatch"), "{}", why),
+                _ => panic!("{:?} passed", o),
+            }
+        }
+
+        // Nothing is checked against a server that doesn't list extensions.
+        let opcodes = OPCODES_ALL & !OpCode::SandstormListExtRpc.bit();
+        let server = StandIn::new(opcodes, Seed::Nothing);
+        let mismatches = check_invokes(&server.connect(), &config).unwrap();
+        assert!(mismatches.is_empty(), "{:?}", mismatches);
+    }
+
+    // Tests that objects written by invoke()s of the put extension, from a byte up to the
+    // largest an invoke() can carry, are read back by native get()s. The arguments are laid out
+    // the way the clients lay them out, and every put overwrites the one before it.
+    #[test]
+    fn test_smoke_invoke_put() {
+        let server = StandIn::new(OPCODES_ALL, Seed::Nothing);
+        let config = smoke_config();
+        let key = b"smoke:key:put".to_vec();
+        let max = limits::max_invoke_put_len(key.len());
+
+        let mut checks = Vec::new();
+        for &len in [1, 2, 100, max].iter() {
+            let val: Vec<u8> = (0..len).map(|i| (i % 251) as u8 + 1).collect();
+            let mut payload = put::payload(config.table, key.len(), len);
+            put::fill(&mut payload, &key, &val);
+
+            let name = format!("invoke put {}", len);
+            let request = Request::Invoke(put::NAME.to_vec(), payload[put::NAME.len()..].to_vec());
+            let expect = Expect::Bytes(put::SUCCESS.to_vec());
+            checks.push(Check::new(&name, request, expect));
+
+            let name = format!("get put {}", len);
+            let request = Request::Get(config.table, key.clone());
+            checks.push(Check::new(&name, request, Expect::Bytes(val)));
+        }
+
+        let report = run(&server.connect(), &checks, &config).expect("No echo");
+        assert_eq!(checks.len(), report.outcomes.len());
+        for &(ref name, ref outcome) in report.outcomes.iter() {
+            assert_eq!(Outcome::Pass, *outcome, "{}", name);
+        }
+    }
+
+    // Tests that invocations are parsed off the command line format.
+    #[test]
+    fn test_canned_parse() {
+        let canned = Canned::parse("get:0102").unwrap();
+        assert_eq!("get", canned.name);
+        assert_eq!(vec![1, 2], canned.args);
+        assert_eq!(None, canned.output);
+
+        let canned = Canned::parse("tao:ff:00aB").unwrap();
+        assert_eq!(Some(vec![0x00, 0xab]), canned.output);
+
+        assert_eq!(None, Canned::parse("get"));
+        assert_eq!(None, Canned::parse(":00"));
+        assert_eq!(None, Canned::parse("get:012"));
+        assert_eq!(None, Canned::parse("get:zz"));
+    }
+}
@@ -0,0 +1,308 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use std::collections::HashMap;
+
+use db::wireformat::{RpcStatus, STREAM_FLAG_END, STREAM_FLAG_MORE};
+
+/// What became of a stream after one of it's responses was received.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Progress {
+    /// The response was not part of a stream. It carries the whole result, and should be
+    /// handled as usual. Nothing was delivered.
+    Unstreamed,
+
+    /// The response was delivered, and more are to follow.
+    Pending,
+
+    /// The last response of the stream was received, and every response was delivered in
+    /// order. The request has completed.
+    Complete,
+
+    /// The stream ended, but part of it was lost or arrived out of order, or the server failed
+    /// it. What was delivered before the failure is all the result there is; the request has
+    /// completed, and should be counted as an error.
+    Failed,
+}
+
+// What is known about a stream whose last response has not been received yet.
+struct Partial {
+    // The sequence number the next response should carry.
+    next: u32,
+
+    // The number of payload bytes delivered so far.
+    bytes: usize,
+
+    // Set once a response was missed. Nothing more is delivered after that.
+    failed: bool,
+}
+
+/// Reassembles invoke() results that an extension streamed back over several responses (see
+/// DB::resp_flush()), and delivers their pieces in order as they arrive.
+///
+/// Responses are not retransmitted, so a stream whose responses are lost or reordered cannot be
+/// repaired. Once a response is missed the stream is marked failed, and nothing more of it is
+/// delivered. The request completes once the last response arrives; if that is lost too, the
+/// request times out like any other, and the caller should forget() it. The same goes for a
+/// response that arrives after the last one: it looks like the start of a new stream, and
+/// should be forgotten if the request is no longer outstanding.
+///
+/// Every response of a stream carries the same request id, so streams should be fed responses
+/// before they are handed to a dedup::Dedup, and only requests that completed (including
+/// unstreamed ones) should be completed there.
+pub struct Streams {
+    // Streams that have started but not ended, by request id.
+    partials: HashMap<u64, Partial>,
+
+    // The number of streams that failed.
+    failed: u64,
+}
+
+impl Streams {
+    /// Creates a reassembler with no streams in progress.
+    pub fn new() -> Streams {
+        Streams {
+            partials: HashMap::new(),
+            failed: 0,
+        }
+    }
+
+    /// Receives a response to an invoke() request.
+    ///
+    /// # Arguments
+    ///
+    /// * `id`:      The request id on the response.
+    /// * `status`:  The status on the response. Only the last response of a stream carries the
+    ///              status of the whole invocation.
+    /// * `flags`:   The stream flags on the response. See STREAM_FLAG_MORE.
+    /// * `seq`:     The position of the response within it's stream.
+    /// * `payload`: The payload of the response.
+    /// * `deliver`: Called with the request id and payload of each response that is delivered.
+    ///
+    /// # Return
+    ///
+    /// What became of the stream the response belongs to.
+    pub fn receive(
+        &mut self,
+        id: u64,
+        status: RpcStatus,
+        flags: u8,
+        seq: u32,
+        payload: &[u8],
+        deliver: &mut FnMut(u64, &[u8]),
+    ) -> Progress {
+        if flags & (STREAM_FLAG_MORE | STREAM_FLAG_END) == 0 {
+            return Progress::Unstreamed;
+        }
+
+        let failed = {
+            let partial = self.partials.entry(id).or_insert(Partial {
+                next: 0,
+                bytes: 0,
+                failed: false,
+            });
+
+            // A gap means a response was lost, and one that arrives late can't be put back
+            // where it belonged.
+            if seq != partial.next {
+                partial.failed = true;
+            }
+
+            if !partial.failed {
+                partial.next += 1;
+                partial.bytes += payload.len();
+                deliver(id, payload);
+            }
+
+            partial.failed
+        };
+
+        if flags & STREAM_FLAG_END == 0 {
+            return Progress::Pending;
+        }
+
+        self.partials.remove(&id);
+        match !failed && status == RpcStatus::StatusOk {
+            true => Progress::Complete,
+            false => {
+                self.failed += 1;
+                Progress::Failed
+            }
+        }
+    }
+
+    /// Drops what is known about a stream, usually because it's request timed out.
+    ///
+    /// # Arguments
+    ///
+    /// * `id`: The request id of the stream.
+    ///
+    /// # Return
+    ///
+    /// The number of payload bytes delivered for the stream, if it was in progress.
+    pub fn forget(&mut self, id: u64) -> Option<usize> {
+        self.partials.remove(&id).map(|p| {
+            self.failed += 1;
+            p.bytes
+        })
+    }
+
+    /// Returns the number of streams in progress.
+    pub fn pending(&self) -> usize {
+        self.partials.len()
+    }
+
+    /// Returns the number of streams that failed, including those forgotten while in progress.
+    pub fn failed(&self) -> u64 {
+        self.failed
+    }
+}
+
+// This module contains unit tests for Streams.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use db::wireformat::RpcStatus::*;
+
+    // Feeds responses to a reassembler, returning what became of each, and everything
+    // delivered.
+    fn feed(
+        streams: &mut Streams,
+        responses: &[(u64, RpcStatus, u8, u32, &[u8])],
+    ) -> (Vec<Progress>, Vec<(u64, Vec<u8>)>) {
+        let mut delivered = Vec::new();
+        let progress = responses
+            .iter()
+            .map(|&(id, ref status, flags, seq, payload)| {
+                streams.receive(
+                    id,
+                    status.clone(),
+                    flags,
+                    seq,
+                    payload,
+                    &mut |id, payload| delivered.push((id, payload.to_vec())),
+                )
+            }).collect();
+        (progress, delivered)
+    }
+
+    const MORE: u8 = STREAM_FLAG_MORE;
+    const END: u8 = STREAM_FLAG_END;
+
+    // Tests that two interleaved streams are each delivered in order, and complete on their
+    // last response.
+    #[test]
+    fn test_streams_order() {
+        let mut streams = Streams::new();
+        let (progress, delivered) = feed(
+            &mut streams,
+            &[
+                (1, StatusOk, MORE, 0, b"a0"),
+                (2, StatusOk, MORE, 0, b"b0"),
+                (1, StatusOk, MORE, 1, b"a1"),
+                (2, StatusOk, END, 1, b"b1"),
+                (1, StatusOk, END, 2, b""),
+            ],
+        );
+
+        use self::Progress::*;
+        assert_eq!(
+            vec![Pending, Pending, Pending, Complete, Complete],
+            progress
+        );
+        assert_eq!(
+            vec![
+                (1, b"a0".to_vec()),
+                (2, b"b0".to_vec()),
+                (1, b"a1".to_vec()),
+                (2, b"b1".to_vec()),
+                (1, b"".to_vec()),
+            ],
+            delivered
+        );
+        assert_eq!(0, streams.pending());
+        assert_eq!(0, streams.failed());
+    }
+
+    // Tests that a stream missing an intermediate response is failed, and that nothing past
+    // the gap is delivered.
+    #[test]
+    fn test_streams_lost_chunk() {
+        let mut streams = Streams::new();
+        let (progress, delivered) = feed(
+            &mut streams,
+            &[
+                (7, StatusOk, MORE, 0, b"x0"),
+                (7, StatusOk, MORE, 2, b"x2"),
+                (7, StatusOk, MORE, 3, b"x3"),
+                (7, StatusOk, END, 4, b"x4"),
+            ],
+        );
+
+        use self::Progress::*;
+        assert_eq!(vec![Pending, Pending, Pending, Failed], progress);
+        assert_eq!(vec![(7, b"x0".to_vec())], delivered);
+        assert_eq!(0, streams.pending());
+        assert_eq!(1, streams.failed());
+
+        // A stream whose first response is lost fails the same way.
+        let (progress, delivered) = feed(
+            &mut streams,
+            &[(8, StatusOk, MORE, 1, b"y1"), (8, StatusOk, END, 2, b"y2")],
+        );
+        assert_eq!(vec![Pending, Failed], progress);
+        assert!(delivered.is_empty());
+        assert_eq!(2, streams.failed());
+    }
+
+    // Tests that a stream the server failed, say for going over the cap, is failed even
+    // though every response arrived.
+    #[test]
+    fn test_streams_server_failed() {
+        let mut streams = Streams::new();
+        let (progress, delivered) = feed(
+            &mut streams,
+            &[
+                (3, StatusOk, MORE, 0, b"z0"),
+                (3, StatusStreamTooLarge, END, 1, b""),
+            ],
+        );
+
+        assert_eq!(vec![Progress::Pending, Progress::Failed], progress);
+        assert_eq!(2, delivered.len());
+        assert_eq!(1, streams.failed());
+    }
+
+    // Tests that unstreamed responses are left alone, and that streams in progress can be
+    // forgotten.
+    #[test]
+    fn test_streams_unstreamed_and_forget() {
+        let mut streams = Streams::new();
+        let (progress, delivered) = feed(
+            &mut streams,
+            &[(4, StatusOk, 0, 0, b"whole"), (5, StatusOk, MORE, 0, b"p0")],
+        );
+
+        assert_eq!(vec![Progress::Unstreamed, Progress::Pending], progress);
+        assert_eq!(vec![(5, b"p0".to_vec())], delivered);
+        assert_eq!(1, streams.pending());
+
+        assert_eq!(Some(2), streams.forget(5));
+        assert_eq!(None, streams.forget(4));
+        assert_eq!(0, streams.pending());
+        assert_eq!(1, streams.failed());
+    }
+}
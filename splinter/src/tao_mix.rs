@@ -0,0 +1,1218 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use std::collections::{HashMap, HashSet};
+
+use db::config::{ClientConfig, DEFAULT_TAO_RESERVE_IDS};
+use db::wireformat::RpcStatus;
+
+use rand::Rng;
+
+use sandstorm::tao::{self, Id, Op, Time, OPS};
+use sandstorm::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use super::chain::{Request, Transport};
+use super::latency::SampleFilter;
+use super::rng::WorkloadRng;
+use super::verify::{RequestStash, Stashable};
+
+// The name of the extension every TAO operation is an invoke() of.
+const EXT: &[u8] = b"tao";
+
+// Opcodes of the tao extension's operations.
+const OBJ_GET: u8 = 0;
+const OBJ_UPDATE: u8 = 2;
+const ASSOC_GET: u8 = 4;
+const ASSOC_ADD: u8 = 5;
+const ASSOC_DEL: u8 = 6;
+const ASSOC_COUNT: u8 = 7;
+const ASSOC_RANGE: u8 = 8;
+const RESERVE_IDS: u8 = 13;
+
+// The type of the objects written by obj_adds and obj_updates, and the length of their data.
+const OBJECT_TYPE: u16 = 0;
+const OBJECT_DATA_LEN: usize = 16;
+
+/// The operation mix a TAO workload draws operations from.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mix {
+    // The weight of each operation, in order of tao::OPS.
+    weights: [f64; 8],
+
+    // The sum of the weights.
+    total: f64,
+}
+
+impl Mix {
+    /// Creates a mix.
+    ///
+    /// # Arguments
+    ///
+    /// * `weights`: The weight of each operation, in order of tao::OPS. Operations are drawn in
+    ///              proportion to their weights, which must not all be zero.
+    pub fn new(weights: [f64; 8]) -> Mix {
+        let total = weights.iter().sum();
+        assert!(total > 0.0);
+        Mix {
+            weights: weights,
+            total: total,
+        }
+    }
+
+    /// Creates the mix specified by the client config; `tao_mix` with it's share of writes
+    /// scaled by `tao_write_scale`.
+    pub fn from_config(config: &ClientConfig) -> Mix {
+        let mut scale = config.tao_write_scale;
+        if scale == 0.0 {
+            scale = 1.0;
+        }
+        Mix::new(config.tao_mix()).scale_writes(scale)
+    }
+
+    /// Returns a copy of this mix with it's share of writes multiplied by a factor, and reads
+    /// shrunk to make room. Reads keep their proportions to each other, and so do writes.
+    /// Writes are capped at the whole mix.
+    ///
+    /// # Arguments
+    ///
+    /// * `factor`: The factor to multiply the share of writes by.
+    pub fn scale_writes(&self, factor: f64) -> Mix {
+        let writes = self.write_fraction();
+        if writes == 0.0 || writes == 1.0 {
+            return self.clone();
+        }
+
+        let scaled = (writes * factor).min(1.0);
+        let (w, r) = (scaled / writes, (1.0 - scaled) / (1.0 - writes));
+        let mut weights = [0.0; 8];
+        for op in OPS.iter() {
+            let scale = if op.is_write() { w } else { r };
+            weights[*op as usize] = self.fraction(*op) * scale;
+        }
+        Mix::new(weights)
+    }
+
+    /// Returns the fraction of operations that are `op`.
+    pub fn fraction(&self, op: Op) -> f64 {
+        self.weights[op as usize] / self.total
+    }
+
+    /// Returns the fraction of operations that are writes.
+    pub fn write_fraction(&self) -> f64 {
+        OPS.iter()
+            .filter(|op| op.is_write())
+            .map(|op| self.fraction(*op))
+            .sum()
+    }
+
+    /// Draws an operation.
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> Op {
+        let mut x = rng.gen::<f64>() * self.total;
+        let mut last = Op::ObjGet;
+        for op in OPS.iter() {
+            let w = self.weights[*op as usize];
+            if w == 0.0 {
+                continue;
+            }
+
+            if x < w {
+                return *op;
+            }
+            x -= w;
+            last = *op;
+        }
+
+        // Rounding can leave a draw just past the last operation.
+        last
+    }
+}
+
+/// A range of object ids the tao extension reserved for a tenant, from `next` upto (but not
+/// including) `end`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct IdRange {
+    /// The next id to hand out.
+    pub next: Id,
+
+    /// The end of the range.
+    pub end: Id,
+}
+
+impl IdRange {
+    /// Hands out the next id in the range, or None if the range is used up.
+    pub fn take(&mut self) -> Option<Id> {
+        if self.next >= self.end {
+            return None;
+        }
+
+        self.next += 1;
+        Some(self.next - 1)
+    }
+
+    /// Returns the number of ids left in the range.
+    pub fn remaining(&self) -> u64 {
+        self.end.saturating_sub(self.next)
+    }
+}
+
+/// Returns an invoke() of the tao extension that reserves a range of ids on a tenant's object
+/// table. The response is parsed by parse_reservation().
+///
+/// # Arguments
+///
+/// * `tenant`: The tenant to reserve ids on.
+/// * `count`:  The number of ids to reserve.
+/// * `floor`:  The smallest id the range may start at. Ranges start past the populated graph
+///             if this is one more than the number of objects in it.
+pub fn reserve(tenant: u32, count: u32, floor: Id) -> Request {
+    let mut args = payload(RESERVE_IDS, tao::OBJECT_TABLE);
+    args.write_u32::<LittleEndian>(count).unwrap();
+    args.write_u64::<LittleEndian>(floor).unwrap();
+    Request::invoke(tenant, EXT.len() as u32, &args)
+}
+
+/// Parses the response to a reserve().
+///
+/// # Arguments
+///
+/// * `count`: The number of ids that were asked for.
+/// * `resp`:  The payload of the response; the first id of the range.
+///
+/// # Return
+///
+/// The range reserved, or None if the extension could not reserve it.
+pub fn parse_reservation(count: u32, resp: &[u8]) -> Option<IdRange> {
+    if resp.len() != 8 {
+        return None;
+    }
+
+    let next = (&resp[..]).read_u64::<LittleEndian>().unwrap();
+    next.checked_add(count as u64).map(|end| IdRange {
+        next: next,
+        end: end,
+    })
+}
+
+// Returns the head of an invoke() of the tao extension; it's name, an opcode, and the table the
+// operation is on.
+fn payload(opcode: u8, table: u64) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(64);
+    payload.extend_from_slice(EXT);
+    payload.push(opcode);
+    payload.write_u64::<LittleEndian>(table).unwrap();
+    payload
+}
+
+/// Generates TAO operations over a graph the server populated with tao::fill().
+pub struct Workload {
+    // The mix operations are drawn from.
+    mix: Mix,
+
+    // The association types operations are drawn from.
+    atypes: Vec<u16>,
+
+    // The (limit, weight) buckets assoc_range limits are drawn from, and the sum of the weights.
+    limits: Vec<(u32, u32)>,
+    limits_total: u64,
+
+    // The number of objects in the populated graph. Their ids go from 1 upto this.
+    objects: u32,
+
+    // If true, obj_gets are issued as native get()s instead of invoke()s.
+    native_gets: bool,
+
+    // Ids reserved for the objects added on each tenant.
+    ids: HashMap<u32, Vec<IdRange>>,
+
+    // Tenants that are running low on reserved ids, and tenants that were reported so and are
+    // yet to be refilled.
+    starved: Vec<u32>,
+    asked: HashSet<u32>,
+
+    // A tenant is starved once it has fewer than these many ids left.
+    low: u64,
+}
+
+impl Workload {
+    /// Creates a workload.
+    ///
+    /// # Arguments
+    ///
+    /// * `mix`:         The mix operations are drawn from.
+    /// * `atypes`:      The association types operations are drawn from. Must not be empty.
+    /// * `limits`:      The (limit, weight) buckets assoc_range limits are drawn from.
+    /// * `objects`:     The number of objects in the populated graph.
+    /// * `native_gets`: If true, obj_gets are issued as native get()s.
+    /// * `low`:         A tenant is starved once it has fewer than these many ids left.
+    pub fn new(
+        mix: Mix,
+        atypes: Vec<u16>,
+        limits: Vec<(u32, u32)>,
+        objects: u32,
+        native_gets: bool,
+        low: u64,
+    ) -> Workload {
+        assert!(!atypes.is_empty());
+        let limits_total = limits.iter().map(|&(_, w)| w as u64).sum();
+        assert!(limits_total > 0);
+        Workload {
+            mix: mix,
+            atypes: atypes,
+            limits: limits,
+            limits_total: limits_total,
+            objects: objects.max(1),
+            native_gets: native_gets,
+            ids: HashMap::new(),
+            starved: Vec::new(),
+            asked: HashSet::new(),
+            low: low.max(1),
+        }
+    }
+
+    /// Creates the workload specified by the client config. The graph is taken to have
+    /// `n_keys` objects.
+    pub fn from_config(config: &ClientConfig) -> Workload {
+        Workload::new(
+            Mix::from_config(config),
+            config.tao_atypes(),
+            config.tao_range_limits(),
+            config.n_keys as u32,
+            config.combined,
+            reserve_count(config) as u64 / 4,
+        )
+    }
+
+    /// Returns the mix operations are drawn from.
+    pub fn mix(&self) -> &Mix {
+        &self.mix
+    }
+
+    /// Hands the workload a range of ids reserved on a tenant, for the objects it adds.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`: The tenant the ids were reserved on.
+    /// * `ids`:    The ids.
+    pub fn refill(&mut self, tenant: u32, ids: IdRange) {
+        self.ids.entry(tenant).or_insert_with(Vec::new).push(ids);
+        self.asked.remove(&tenant);
+    }
+
+    /// Forgets that a tenant was reported as running low, so that it is reported again the
+    /// next time it hands out an id (ex: because the reservation for it failed).
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`: The tenant.
+    pub fn release(&mut self, tenant: u32) {
+        self.asked.remove(&tenant);
+    }
+
+    /// Returns the number of ids left for the objects added on a tenant.
+    pub fn ids_left(&self, tenant: u32) -> u64 {
+        self.ids
+            .get(&tenant)
+            .map_or(0, |ranges| ranges.iter().map(|r| r.remaining()).sum())
+    }
+
+    /// Returns a tenant that is running low on ids, if any. Each tenant is returned once per
+    /// time it runs low.
+    pub fn starved(&mut self) -> Option<u32> {
+        self.starved.pop()
+    }
+
+    // Hands out an id for an object added on a tenant, and marks the tenant starved if it is
+    // running low. A tenant is marked only once until it is refilled or released.
+    fn take_id(&mut self, tenant: u32) -> Option<Id> {
+        let id = match self.ids.get_mut(&tenant) {
+            Some(ranges) => {
+                while ranges.last().map_or(false, |r| r.remaining() == 0) {
+                    ranges.pop();
+                }
+                ranges.last_mut().and_then(|r| r.take())
+            }
+
+            None => None,
+        };
+
+        if self.ids_left(tenant) < self.low && self.asked.insert(tenant) {
+            self.starved.push(tenant);
+        }
+        id
+    }
+
+    /// Draws the next operation, and builds the request that performs it. Objects are drawn
+    /// with `rng.key()`, so they follow the key distribution over the populated graph.
+    ///
+    /// # Arguments
+    ///
+    /// * `rng`:    The random numbers to draw with.
+    /// * `tenant`: The tenant to issue the operation on behalf of.
+    ///
+    /// # Return
+    ///
+    /// The operation and it's request. An obj_add drawn on a tenant with no ids left is issued
+    /// as an obj_update of an existing object instead.
+    pub fn next(&mut self, rng: &mut WorkloadRng, tenant: u32) -> (Op, Request) {
+        let op = self.mix.sample(rng);
+        let id1 = rng.key() as u32;
+        let atype = self.atypes[rng.gen::<usize>() % self.atypes.len()];
+
+        match op {
+            Op::ObjGet => {
+                let mut key = Vec::with_capacity(tao::OBJECT_KEY_LEN);
+                key.write_u64::<LittleEndian>(id1 as Id).unwrap();
+                if self.native_gets {
+                    return (op, Request::get(tenant, tao::OBJECT_TABLE, &key));
+                }
+
+                let mut args = payload(OBJ_GET, tao::OBJECT_TABLE);
+                args.extend_from_slice(&key);
+                (op, Request::invoke(tenant, EXT.len() as u32, &args))
+            }
+
+            Op::ObjAdd | Op::ObjUpdate => {
+                let (op, id) = match op {
+                    Op::ObjAdd => match self.take_id(tenant) {
+                        Some(id) => (Op::ObjAdd, id),
+                        None => (Op::ObjUpdate, id1 as Id),
+                    },
+                    _ => (Op::ObjUpdate, id1 as Id),
+                };
+
+                let mut args = payload(OBJ_UPDATE, tao::OBJECT_TABLE);
+                args.write_u64::<LittleEndian>(id).unwrap();
+                args.write_u16::<LittleEndian>(OBJECT_TYPE).unwrap();
+                args.extend_from_slice(&[0; OBJECT_DATA_LEN]);
+                (op, Request::invoke(tenant, EXT.len() as u32, &args))
+            }
+
+            Op::AssocGet | Op::AssocAdd | Op::AssocDel => {
+                // Gets and deletes go after an association the graph was populated with, adds
+                // connect two existing objects.
+                let (opcode, id2) = match op {
+                    Op::AssocGet => (ASSOC_GET, tao::fill_target(id1, 0, self.objects)),
+                    Op::AssocDel => (ASSOC_DEL, tao::fill_target(id1, 0, self.objects)),
+                    _ => (ASSOC_ADD, rng.key() as Id),
+                };
+
+                let mut args = payload(opcode, tao::ASSOC_TABLE);
+                args.extend_from_slice(&tao::assoc_key(id1 as Id, atype, id2));
+                (op, Request::invoke(tenant, EXT.len() as u32, &args))
+            }
+
+            Op::AssocCount => {
+                let mut args = payload(ASSOC_COUNT, tao::ASSOC_TABLE);
+                args.extend_from_slice(&tao::list_key(id1 as Id, atype));
+                (op, Request::invoke(tenant, EXT.len() as u32, &args))
+            }
+
+            Op::AssocRange => {
+                let mut args = payload(ASSOC_RANGE, tao::ASSOC_TABLE);
+                args.extend_from_slice(&tao::list_key(id1 as Id, atype));
+                args.write_u32::<LittleEndian>(0).unwrap();
+                args.write_u32::<LittleEndian>(self.limit(rng)).unwrap();
+                (op, Request::invoke(tenant, EXT.len() as u32, &args))
+            }
+        }
+    }
+
+    // Draws the limit on an assoc_range.
+    fn limit(&self, rng: &mut WorkloadRng) -> u32 {
+        let mut x = rng.gen::<u64>() % self.limits_total;
+        for &(limit, weight) in self.limits.iter() {
+            if x < weight as u64 {
+                return limit;
+            }
+            x -= weight as u64;
+        }
+
+        self.limits[self.limits.len() - 1].0
+    }
+}
+
+// Returns the number of ids reserved at a time under a client config.
+fn reserve_count(config: &ClientConfig) -> u32 {
+    match config.tao_reserve_ids {
+        0 => DEFAULT_TAO_RESERVE_IDS,
+        n => n,
+    }
+}
+
+/// What became of a TAO operation.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome {
+    /// The operation succeeded.
+    Ok,
+
+    /// The operation failed; the request was rejected, or the extension responded with an
+    /// error (ex: the object does not exist).
+    Failed(String),
+
+    /// The operation completed, but it's result is not well-formed.
+    Malformed(String),
+}
+
+/// Checks that an assoc_range result is well-formed; whole association records, newest first
+/// (time-stamps never increase), and no object more than once.
+///
+/// # Return
+///
+/// What is wrong with the result, if anything.
+pub fn check_range(resp: &[u8]) -> Result<(), String> {
+    if resp.len() % tao::ASSOC_LEN != 0 {
+        return Err(format!(
+            "{} bytes is not a whole number of associations",
+            resp.len()
+        ));
+    }
+
+    let mut seen = HashSet::new();
+    let mut last = Time::max_value();
+    for (i, record) in resp.chunks(tao::ASSOC_LEN).enumerate() {
+        let (id2, time) = tao::decode_assoc(record).unwrap();
+        if time > last {
+            return Err(format!("association {} is newer than the one before it", i));
+        }
+
+        if !seen.insert(id2) {
+            return Err(format!("object {} is on the range twice", id2));
+        }
+        last = time;
+    }
+
+    Ok(())
+}
+
+/// Decides what became of a TAO operation from it's response.
+///
+/// # Arguments
+///
+/// * `op`:       The operation.
+/// * `status`:   The status on the response.
+/// * `resp`:     The payload on the response.
+/// * `validate`: If true, assoc_range and assoc_count results are checked to be well-formed.
+pub fn outcome(op: Op, status: &RpcStatus, resp: &[u8], validate: bool) -> Outcome {
+    if *status != RpcStatus::StatusOk {
+        return Outcome::Failed(format!("{:?}", status));
+    }
+
+    if let Some((error, otype)) = tao::decode_error(resp) {
+        return Outcome::Failed(format!("{:?} on type {}", error, otype));
+    }
+
+    if resp.starts_with(b"ERROR") || resp == b"Invalid packet length." {
+        return Outcome::Failed(String::from_utf8_lossy(resp).into_owned());
+    }
+
+    if !validate {
+        return Outcome::Ok;
+    }
+
+    match op {
+        Op::AssocRange => match check_range(resp) {
+            Ok(()) => Outcome::Ok,
+            Err(reason) => Outcome::Malformed(reason),
+        },
+
+        Op::AssocCount if resp.len() != 4 => {
+            Outcome::Malformed(format!("a count of {} bytes", resp.len()))
+        }
+
+        _ => Outcome::Ok,
+    }
+}
+
+/// Counts and sampled latencies of one kind of operation in a TAO run.
+#[derive(Clone, Debug, Default)]
+pub struct OpCount {
+    /// The number of operations sent out.
+    pub sent: u64,
+
+    /// The number of operations that succeeded.
+    pub completed: u64,
+
+    /// The number of operations that failed.
+    pub failed: u64,
+
+    /// The number of operations whose result was not well-formed.
+    pub malformed: u64,
+
+    /// Latencies (in cycles) of the sampled operations that succeeded.
+    pub latencies: Vec<u64>,
+
+    // Operations left to send out before the next one is sampled.
+    countdown: u64,
+}
+
+impl OpCount {
+    /// Returns the mean, median and 99th percentile of the sampled latencies, or None if none
+    /// were sampled.
+    pub fn percentiles(&mut self) -> Option<(f64, u64, u64)> {
+        if self.latencies.is_empty() {
+            return None;
+        }
+
+        self.latencies.sort();
+        let n = self.latencies.len();
+        let mean = self.latencies.iter().sum::<u64>() as f64 / n as f64;
+        Some((mean, self.latencies[n / 2], self.latencies[(n * 99) / 100]))
+    }
+}
+
+/// Counts and sampled latencies of a TAO run, kept separately for every kind of operation.
+/// Operations of each kind are sampled on their own, so that rare ones (ex: writes) are
+/// sampled as densely as common ones.
+pub struct OpStats {
+    // Counts of each operation, in order of tao::OPS.
+    ops: Vec<OpCount>,
+
+    // One in these many operations of each kind is sampled.
+    rate: u64,
+
+    // Drops sampled latencies the clock got wrong, and counts them.
+    filter: SampleFilter,
+}
+
+impl OpStats {
+    /// Creates empty stats.
+    ///
+    /// # Arguments
+    ///
+    /// * `rate`: One in these many operations of each kind has it's latency recorded. 0
+    ///           means 1, ie. every operation.
+    pub fn new(rate: u64) -> OpStats {
+        let rate = rate.max(1);
+        let mut count = OpCount::default();
+        count.countdown = 1;
+        OpStats {
+            ops: vec![count; OPS.len()],
+            rate: rate,
+            filter: SampleFilter::default(),
+        }
+    }
+
+    /// Counts an operation being sent out.
+    ///
+    /// # Return
+    ///
+    /// True if the operation's latency is to be recorded.
+    pub fn send(&mut self, op: Op) -> bool {
+        let count = &mut self.ops[op as usize];
+        count.sent += 1;
+        count.countdown -= 1;
+        if count.countdown > 0 {
+            return false;
+        }
+
+        count.countdown = self.rate;
+        true
+    }
+
+    /// Accounts for an operation that completed.
+    ///
+    /// # Arguments
+    ///
+    /// * `op`:      The operation.
+    /// * `outcome`: What became of it.
+    /// * `latency`: It's latency in cycles, if it was sampled.
+    pub fn complete(&mut self, op: Op, outcome: &Outcome, latency: Option<u64>) {
+        let count = &mut self.ops[op as usize];
+        match *outcome {
+            Outcome::Ok => {
+                count.completed += 1;
+                if let Some(latency) = latency {
+                    count.latencies.push(latency);
+                }
+            }
+
+            Outcome::Failed(_) => count.failed += 1,
+
+            Outcome::Malformed(_) => count.malformed += 1,
+        }
+    }
+
+    /// Returns the counts of an operation.
+    pub fn get(&self, op: Op) -> &OpCount {
+        &self.ops[op as usize]
+    }
+
+    /// Returns the counts of an operation, so that it's percentiles can be computed.
+    pub fn get_mut(&mut self, op: Op) -> &mut OpCount {
+        &mut self.ops[op as usize]
+    }
+
+    /// Returns the sampled latencies dropped for the clock getting them wrong.
+    pub fn filter(&self) -> &SampleFilter {
+        &self.filter
+    }
+}
+
+// A request the TAO mix sent out and is waiting on a response to.
+struct Pending {
+    // The id and time-stamp the request was sent out with.
+    id: u64,
+    stamp: u64,
+
+    // The tenant the request was issued on behalf of.
+    tenant: u32,
+
+    // The operation, or None if the request reserves ids.
+    op: Option<Op>,
+
+    // True if the operation's latency is to be recorded.
+    sampled: bool,
+}
+
+impl Stashable for Pending {
+    fn id(&self) -> u64 {
+        self.id
+    }
+
+    fn stamp(&self) -> u64 {
+        self.stamp
+    }
+}
+
+/// Drives a TAO workload over a transport: sends operations out, reserves ids for the objects
+/// it adds, and accounts for responses per operation.
+///
+/// Ids are reserved on a tenant the first time the mix adds an object on it, and again
+/// whenever the tenant runs low, so that objects a client adds never collide with those of
+/// other clients or the populated graph. Until a tenant's first reservation arrives, it's
+/// obj_adds are issued as obj_updates.
+pub struct TaoMix {
+    // The operations sent out.
+    workload: Workload,
+
+    // Counts and latencies of every operation.
+    stats: OpStats,
+
+    // Requests waiting on a response.
+    pending: RequestStash<Pending>,
+
+    // If true, results are checked to be well-formed.
+    validate: bool,
+
+    // The number of ids reserved at a time, and the smallest id a range may start at.
+    count: u32,
+    floor: Id,
+
+    // Tenants with a reservation outstanding, and when it was sent out.
+    reserving: HashMap<u32, u64>,
+
+    // Cycles after which an outstanding reservation is given up on.
+    timeout: u64,
+
+    // The number of reservations that succeeded, and that failed.
+    reserved: u64,
+    reserve_failed: u64,
+}
+
+impl TaoMix {
+    /// Creates a driver.
+    ///
+    /// # Arguments
+    ///
+    /// * `workload`: The workload to drive.
+    /// * `stats`:    The stats to account responses in.
+    /// * `capacity`: The largest number of requests outstanding at once.
+    /// * `timeout`:  Cycles after which a request without a response is given up on.
+    /// * `count`:    The number of ids to reserve at a time.
+    /// * `floor`:    The smallest id a reserved range may start at.
+    /// * `validate`: If true, results are checked to be well-formed.
+    pub fn new(
+        workload: Workload,
+        stats: OpStats,
+        capacity: usize,
+        timeout: u64,
+        count: u32,
+        floor: Id,
+        validate: bool,
+    ) -> TaoMix {
+        TaoMix {
+            workload: workload,
+            stats: stats,
+            pending: RequestStash::new(capacity, timeout),
+            validate: validate,
+            count: count,
+            floor: floor,
+            reserving: HashMap::new(),
+            timeout: timeout,
+            reserved: 0,
+            reserve_failed: 0,
+        }
+    }
+
+    /// Creates the driver specified by the client config. Ranges are reserved past the `n_keys`
+    /// objects of the populated graph.
+    ///
+    /// # Arguments
+    ///
+    /// * `config`:   Client configuration.
+    /// * `capacity`: The largest number of requests outstanding at once.
+    /// * `timeout`:  Cycles after which a request without a response is given up on.
+    pub fn from_config(config: &ClientConfig, capacity: usize, timeout: u64) -> TaoMix {
+        TaoMix::new(
+            Workload::from_config(config),
+            OpStats::new(config.latency_sample),
+            capacity,
+            timeout,
+            reserve_count(config),
+            config.n_keys as Id + 1,
+            config.validate,
+        )
+    }
+
+    /// Sends out the next request. A reservation of ids on a tenant that is running low goes
+    /// out before anything else.
+    ///
+    /// # Arguments
+    ///
+    /// * `transport`: The transport to send the request out on.
+    /// * `rng`:       The random numbers to draw the tenant and operation with.
+    /// * `stamp`:     The time-stamp to send the request out with.
+    ///
+    /// # Return
+    ///
+    /// The operation sent out, or None if it was a reservation.
+    pub fn send<T: Transport>(
+        &mut self,
+        transport: &T,
+        rng: &mut WorkloadRng,
+        stamp: u64,
+    ) -> Option<Op> {
+        // A reservation that went unanswered for too long is given up on, and sent out again.
+        let timeout = self.timeout;
+        let lost = self
+            .reserving
+            .iter()
+            .find(|&(_, &sent)| stamp.saturating_sub(sent) > timeout)
+            .map(|(&tenant, _)| tenant);
+
+        if let Some(tenant) = lost.or_else(|| self.workload.starved()) {
+            let req = reserve(tenant, self.count, self.floor);
+            self.issue(transport, &req, tenant, None, false, stamp);
+            self.reserving.insert(tenant, stamp);
+            return None;
+        }
+
+        let tenant = rng.tenant() as u32;
+        let (op, req) = self.workload.next(rng, tenant);
+        let sampled = self.stats.send(op);
+        self.issue(transport, &req, tenant, Some(op), sampled, stamp);
+        Some(op)
+    }
+
+    // Sends a request out, and stashes what is needed to account for it's response.
+    fn issue<T: Transport>(
+        &mut self,
+        transport: &T,
+        req: &Request,
+        tenant: u32,
+        op: Option<Op>,
+        sampled: bool,
+        stamp: u64,
+    ) {
+        let id = transport.next_id();
+        self.pending.insert(Pending {
+            id: id,
+            stamp: stamp,
+            tenant: tenant,
+            op: op,
+            sampled: sampled,
+        });
+        transport.send(tenant, req.op(), id, stamp);
+    }
+
+    /// Accounts for a response.
+    ///
+    /// # Arguments
+    ///
+    /// * `id`:      The id on the response.
+    /// * `status`:  The status on the response.
+    /// * `payload`: The payload on the response.
+    /// * `now`:     The current time in cycles.
+    ///
+    /// # Return
+    ///
+    /// The operation the response completed, or None if it completed a reservation or no
+    /// request is waiting on it (ex: the request was given up on).
+    pub fn complete(
+        &mut self,
+        id: u64,
+        status: &RpcStatus,
+        payload: &[u8],
+        now: u64,
+    ) -> Option<Op> {
+        let pending = self.pending.take(id)?;
+        let op = match pending.op {
+            Some(op) => op,
+
+            None => {
+                self.reserving.remove(&pending.tenant);
+                let ids = match *status {
+                    RpcStatus::StatusOk => parse_reservation(self.count, payload),
+                    _ => None,
+                };
+
+                match ids {
+                    Some(ids) => {
+                        self.reserved += 1;
+                        self.workload.refill(pending.tenant, ids);
+                    }
+
+                    None => {
+                        self.reserve_failed += 1;
+                        self.workload.release(pending.tenant);
+                        warn!(
+                            "Failed to reserve ids on tenant {}: {:?}",
+                            pending.tenant, status
+                        );
+                    }
+                }
+                return None;
+            }
+        };
+
+        let outcome = outcome(op, status, payload, self.validate);
+        if let Outcome::Malformed(ref reason) = outcome {
+            debug!(
+                "Malformed {} on tenant {}: {}",
+                op.name(),
+                pending.tenant,
+                reason
+            );
+        }
+
+        let latency = match pending.sampled {
+            true => self.stats.filter.sample(pending.stamp, now),
+            false => None,
+        };
+        self.stats.complete(op, &outcome, latency);
+        Some(op)
+    }
+
+    /// Gives up on requests that have been waiting on a response for too long.
+    ///
+    /// # Arguments
+    ///
+    /// * `now`: The current time in cycles.
+    pub fn reclaim(&mut self, now: u64) -> u64 {
+        self.pending.reclaim(now)
+    }
+
+    /// Returns the workload being driven.
+    pub fn workload(&self) -> &Workload {
+        &self.workload
+    }
+
+    /// Returns the counts and latencies of every operation.
+    pub fn stats(&mut self) -> &mut OpStats {
+        &mut self.stats
+    }
+
+    /// Returns the number of reservations that succeeded, and that failed.
+    pub fn reservations(&self) -> (u64, u64) {
+        (self.reserved, self.reserve_failed)
+    }
+
+    /// Returns the number of requests given up on without a response.
+    pub fn reclaimed(&self) -> u64 {
+        self.pending.reclaimed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::{Cell, RefCell};
+
+    use chain::Op as ChainOp;
+    use dist::KeyDistribution;
+
+    // Records requests instead of sending them out.
+    struct StubTransport {
+        ids: Cell<u64>,
+        sent: RefCell<Vec<(u32, ChainOp, u64, u64)>>,
+    }
+
+    impl StubTransport {
+        fn new() -> StubTransport {
+            StubTransport {
+                ids: Cell::new(0),
+                sent: RefCell::new(Vec::new()),
+            }
+        }
+
+        // Returns the last request sent out.
+        fn last(&self) -> (u32, ChainOp, u64, u64) {
+            self.sent.borrow().last().unwrap().clone()
+        }
+    }
+
+    impl Transport for StubTransport {
+        fn next_id(&self) -> u64 {
+            self.ids.set(self.ids.get() + 1);
+            self.ids.get()
+        }
+
+        fn send(&self, tenant: u32, op: &ChainOp, id: u64, stamp: u64) {
+            self.sent.borrow_mut().push((tenant, op.clone(), id, stamp));
+        }
+    }
+
+    // Returns a generator over 1000 objects and a single tenant.
+    fn rng() -> WorkloadRng {
+        WorkloadRng::new(
+            42,
+            0,
+            KeyDistribution::Uniform.sampler(1000, 0, 1),
+            KeyDistribution::Uniform.sampler(1, 0, 1),
+        )
+    }
+
+    // Returns a mix of only `op`.
+    fn only(op: Op) -> Mix {
+        let mut weights = [0.0; 8];
+        weights[op as usize] = 1.0;
+        Mix::new(weights)
+    }
+
+    // Tests that operations are drawn in the proportions of the published mix.
+    #[test]
+    fn test_mix_sample() {
+        let mix = Mix::new(tao::published_mix());
+        let mut rng = rng();
+        let n = 1000000;
+        let mut counts = [0u64; 8];
+        for _ in 0..n {
+            counts[mix.sample(&mut rng) as usize] += 1;
+        }
+
+        for op in OPS.iter() {
+            let p = mix.fraction(*op);
+            let sigma = (n as f64 * p * (1.0 - p)).sqrt();
+            let expected = n as f64 * p;
+            let got = counts[*op as usize] as f64;
+            assert!((got - expected).abs() <= 5.0 * sigma + 1.0, "{}", op.name());
+        }
+    }
+
+    // Tests that scaling writes keeps reads and writes in proportion among themselves.
+    #[test]
+    fn test_mix_scale_writes() {
+        let mix = Mix::new(tao::published_mix());
+        let scaled = mix.scale_writes(10.0);
+        assert!((scaled.write_fraction() - 10.0 * mix.write_fraction()).abs() < 1e-9);
+
+        let ratio = |m: &Mix, a: Op, b: Op| m.fraction(a) / m.fraction(b);
+        assert!(
+            (ratio(&scaled, Op::ObjAdd, Op::AssocAdd) - ratio(&mix, Op::ObjAdd, Op::AssocAdd))
+                .abs()
+                < 1e-9
+        );
+        assert!(
+            (ratio(&scaled, Op::ObjGet, Op::AssocRange) - ratio(&mix, Op::ObjGet, Op::AssocRange))
+                .abs()
+                < 1e-9
+        );
+
+        // Writes are capped at the whole mix.
+        let all = mix.scale_writes(1e9);
+        assert!((all.write_fraction() - 1.0).abs() < 1e-9);
+        assert_eq!(0.0, all.fraction(Op::ObjGet));
+
+        let same = mix.scale_writes(1.0);
+        for op in OPS.iter() {
+            assert!((same.fraction(*op) - mix.fraction(*op)).abs() < 1e-9);
+        }
+    }
+
+    // Tests the layout of a reservation and it's response.
+    #[test]
+    fn test_reserve() {
+        let req = reserve(3, 16, 1001);
+        match *req.op() {
+            ChainOp::Invoke {
+                name_len,
+                ref payload,
+            } => {
+                assert_eq!(3, name_len);
+                assert_eq!(b"tao", &payload[..3]);
+                assert_eq!(RESERVE_IDS, payload[3]);
+                assert_eq!(
+                    tao::OBJECT_TABLE,
+                    (&payload[4..12]).read_u64::<LittleEndian>().unwrap()
+                );
+                assert_eq!(16, (&payload[12..16]).read_u32::<LittleEndian>().unwrap());
+                assert_eq!(1001, (&payload[16..24]).read_u64::<LittleEndian>().unwrap());
+            }
+
+            _ => panic!("A reservation must be an invoke()."),
+        }
+
+        let mut resp = Vec::new();
+        resp.write_u64::<LittleEndian>(1001).unwrap();
+        let mut ids = parse_reservation(16, &resp).unwrap();
+        assert_eq!(16, ids.remaining());
+        assert_eq!(Some(1001), ids.take());
+        assert_eq!(15, ids.remaining());
+
+        assert_eq!(
+            None,
+            parse_reservation(16, &tao::encode_error(tao::TaoError::Exhausted, 0))
+        );
+        assert_eq!(None, parse_reservation(16, b"Invalid packet length."));
+    }
+
+    // Tests that obj_adds wait for a reservation, and then write to the reserved ids.
+    #[test]
+    fn test_mix_reservations() {
+        let workload = Workload::new(only(Op::ObjAdd), vec![0], vec![(10, 1)], 1000, false, 2);
+        let mut mix = TaoMix::new(workload, OpStats::new(1), 64, 1000, 4, 1001, false);
+        let transport = StubTransport::new();
+        let mut rng = rng();
+
+        // Without ids, an obj_add is issued as an obj_update of an existing object, and the
+        // tenant asks for ids.
+        assert_eq!(Some(Op::ObjUpdate), mix.send(&transport, &mut rng, 0));
+        assert_eq!(None, mix.send(&transport, &mut rng, 0));
+        let (tenant, op, id, _) = transport.last();
+        assert_eq!(1, tenant);
+        assert_eq!(*reserve(1, 4, 1001).op(), op);
+
+        // Only one reservation is outstanding per tenant.
+        assert_eq!(Some(Op::ObjUpdate), mix.send(&transport, &mut rng, 1));
+
+        let mut resp = Vec::new();
+        resp.write_u64::<LittleEndian>(1001).unwrap();
+        assert_eq!(None, mix.complete(id, &RpcStatus::StatusOk, &resp, 2));
+        assert_eq!(4, mix.workload().ids_left(1));
+        assert_eq!((1, 0), mix.reservations());
+
+        // Objects are now added at the reserved ids, and the tenant asks for more once it is
+        // left with fewer than 2.
+        for expected in 1001..1004 {
+            assert_eq!(Some(Op::ObjAdd), mix.send(&transport, &mut rng, 3));
+            match transport.last().1 {
+                ChainOp::Invoke { ref payload, .. } => {
+                    assert_eq!(OBJ_UPDATE, payload[3]);
+                    assert_eq!(
+                        expected,
+                        (&payload[12..20]).read_u64::<LittleEndian>().unwrap()
+                    );
+                }
+
+                _ => panic!("An obj_add must be an invoke()."),
+            }
+        }
+        assert_eq!(None, mix.send(&transport, &mut rng, 4));
+
+        // A failed reservation is retried.
+        let id = transport.last().2;
+        assert_eq!(
+            None,
+            mix.complete(id, &RpcStatus::StatusOk, b"Invalid packet length.", 5)
+        );
+        assert_eq!((1, 1), mix.reservations());
+        assert_eq!(Some(Op::ObjAdd), mix.send(&transport, &mut rng, 6));
+        assert_eq!(None, mix.send(&transport, &mut rng, 6));
+
+        // So is one that goes unanswered.
+        assert_eq!(Some(Op::ObjUpdate), mix.send(&transport, &mut rng, 7));
+        assert_eq!(None, mix.send(&transport, &mut rng, 2000));
+    }
+
+    // Tests that responses are accounted against the operation they complete.
+    #[test]
+    fn test_mix_accounting() {
+        let workload = Workload::new(only(Op::AssocRange), vec![0], vec![(10, 1)], 1000, false, 1);
+        let mut mix = TaoMix::new(workload, OpStats::new(2), 64, 1000, 4, 1001, true);
+        let transport = StubTransport::new();
+        let mut rng = rng();
+
+        for i in 0..4 {
+            assert_eq!(Some(Op::AssocRange), mix.send(&transport, &mut rng, i * 10));
+        }
+
+        let mut ok = Vec::new();
+        ok.extend_from_slice(&tao::encode_assoc(7, 2));
+        ok.extend_from_slice(&tao::encode_assoc(8, 1));
+        let mut bad = ok.clone();
+        bad.extend_from_slice(&tao::encode_assoc(9, 3));
+
+        assert_eq!(
+            Some(Op::AssocRange),
+            mix.complete(1, &RpcStatus::StatusOk, &ok, 5)
+        );
+        assert_eq!(
+            Some(Op::AssocRange),
+            mix.complete(2, &RpcStatus::StatusOk, &ok, 100)
+        );
+        assert_eq!(
+            Some(Op::AssocRange),
+            mix.complete(3, &RpcStatus::StatusOk, &bad, 100)
+        );
+        assert_eq!(
+            Some(Op::AssocRange),
+            mix.complete(4, &RpcStatus::StatusTableDoesNotExist, &[], 100)
+        );
+        assert_eq!(None, mix.complete(4, &RpcStatus::StatusOk, &ok, 100));
+
+        let count = mix.stats().get_mut(Op::AssocRange);
+        assert_eq!(
+            (4, 2, 1, 1),
+            (count.sent, count.completed, count.failed, count.malformed)
+        );
+
+        // One in two operations is sampled, starting from the first.
+        assert_eq!(vec![5], count.latencies);
+        assert_eq!(Some((5.0, 5, 5)), count.percentiles());
+        assert_eq!(0, mix.stats().get(Op::ObjGet).sent);
+
+        // A sampled response that came in before it's request went out is counted, not recorded.
+        assert_eq!(Some(Op::AssocRange), mix.send(&transport, &mut rng, 40));
+        assert_eq!(
+            Some(Op::AssocRange),
+            mix.complete(5, &RpcStatus::StatusOk, &ok, 30)
+        );
+        assert_eq!(1, mix.stats().filter().negative());
+        assert_eq!(vec![5], mix.stats().get(Op::AssocRange).latencies);
+    }
+
+    // Tests that malformed assoc_range results are caught.
+    #[test]
+    fn test_check_range() {
+        let mut resp = Vec::new();
+        assert_eq!(Ok(()), check_range(&resp));
+
+        resp.extend_from_slice(&tao::encode_assoc(7, 5));
+        resp.extend_from_slice(&tao::encode_assoc(8, 5));
+        resp.extend_from_slice(&tao::encode_assoc(9, 1));
+        assert_eq!(Ok(()), check_range(&resp));
+
+        assert!(check_range(&resp[..20]).is_err());
+
+        let mut newer = resp.clone();
+        newer.extend_from_slice(&tao::encode_assoc(10, 2));
+        assert!(check_range(&newer).is_err());
+
+        let mut twice = resp.clone();
+        twice.extend_from_slice(&tao::encode_assoc(8, 0));
+        assert!(check_range(&twice).is_err());
+    }
+}
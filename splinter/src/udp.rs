@@ -0,0 +1,2901 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use std::cell::{Cell, RefCell};
+use std::io;
+use std::marker::PhantomData;
+use std::mem::{size_of, transmute};
+use std::net::{Ipv4Addr, SocketAddrV4, UdpSocket};
+use std::os::unix::io::FromRawFd;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use libc;
+
+use db::backoff::CoreSummary;
+use db::config;
+use db::epoch;
+use db::evict::CacheTarget;
+use db::histogram::Histogram;
+use db::latency::LatencySummary;
+use db::log::*;
+use db::rpc;
+use db::trailer::TrailerReader;
+use db::wireformat::*;
+
+use sandstorm::ext::ExtensionInfo;
+
+use super::ids::RequestIds;
+use super::retry::{Retries, RetryStats, Verdict};
+
+/// The largest response that can be received over the UDP transport.
+const MAX_RESPONSE_LEN: usize = 2048;
+
+/// The maximum number of responses returned by a single call to recv_res().
+const MAX_RX_RESPONSES: usize = 32;
+
+/// Serializes a request header followed by it's payload into the exact bytes
+/// the DPDK transport would have placed after the UDP header.
+pub(crate) fn encode<H>(hdr: H, payloads: &[&[u8]]) -> Vec<u8> {
+    let len = payloads.iter().fold(size_of::<H>(), |l, p| l + p.len());
+    let mut req = Vec::with_capacity(len);
+
+    let hdr: &[u8] = unsafe {
+        ::std::slice::from_raw_parts(&hdr as *const H as *const u8, size_of::<H>())
+    };
+    req.extend_from_slice(hdr);
+    for payload in payloads.iter() {
+        req.extend_from_slice(payload);
+    }
+
+    req
+}
+
+/// Builds the wire bytes of an echo() RPC request. Refer to rpc::create_echo_rpc().
+pub fn encode_echo(tenant: u32, id: u64, stamp: u64) -> Vec<u8> {
+    encode(EchoRequest::new(tenant, id, stamp), &[])
+}
+
+/// Builds the wire bytes of a get() RPC request. Refer to rpc::create_get_rpc().
+pub fn encode_get(tenant: u32, table: u64, key: &[u8], id: u64, stamp: u64) -> Vec<u8> {
+    encode_get_with_staleness(tenant, table, key, 0, id, stamp)
+}
+
+/// Builds the wire bytes of a get() RPC request with a staleness budget. Refer to
+/// rpc::create_get_rpc_with_staleness().
+pub fn encode_get_with_staleness(
+    tenant: u32,
+    table: u64,
+    key: &[u8],
+    staleness_us: u32,
+    id: u64,
+    stamp: u64,
+) -> Vec<u8> {
+    encode_get_with_projection(tenant, table, key, staleness_us, (0, 0), id, stamp)
+}
+
+/// Builds the wire bytes of a get() RPC request that only asks for part of the value. Refer to
+/// rpc::create_get_rpc_with_projection().
+pub fn encode_get_with_projection(
+    tenant: u32,
+    table: u64,
+    key: &[u8],
+    staleness_us: u32,
+    projection: (u32, u32),
+    id: u64,
+    stamp: u64,
+) -> Vec<u8> {
+    if key.len() > u16::max_value() as usize {
+        panic!("Key too long ({} bytes).", key.len());
+    }
+
+    let hdr = GetRequest::new(
+        tenant,
+        table,
+        key.len() as u16,
+        id,
+        stamp,
+        GetGenerator::SandstormClient,
+    );
+    let hdr = hdr
+        .with_staleness(staleness_us)
+        .with_projection(projection.0, projection.1);
+    encode(hdr, &[key])
+}
+
+/// Builds the wire bytes of a put() RPC request. Refer to rpc::create_put_rpc().
+pub fn encode_put(tenant: u32, table: u64, key: &[u8], val: &[u8], id: u64, stamp: u64) -> Vec<u8> {
+    encode_put_gather(tenant, table, key, &[val], id, stamp)
+}
+
+/// Builds the wire bytes of a put() RPC request on a value made up of several segments. Refer to
+/// rpc::create_put_rpc_gather().
+pub fn encode_put_gather(
+    tenant: u32,
+    table: u64,
+    key: &[u8],
+    segments: &[&[u8]],
+    id: u64,
+    stamp: u64,
+) -> Vec<u8> {
+    if key.len() > u16::max_value() as usize {
+        panic!("Key too long ({} bytes).", key.len());
+    }
+
+    let mut payloads = Vec::with_capacity(1 + segments.len());
+    payloads.push(key);
+    payloads.extend_from_slice(segments);
+    encode(
+        PutRequest::new(tenant, table, key.len() as u16, id, stamp),
+        &payloads,
+    )
+}
+
+/// Builds the wire bytes of a multiget() RPC request. Refer to rpc::create_multiget_rpc().
+pub fn encode_multiget(
+    tenant: u32,
+    table: u64,
+    k_len: u16,
+    n_keys: u32,
+    keys: &[u8],
+    id: u64,
+    stamp: u64,
+) -> Vec<u8> {
+    encode_multiget_with_flags(tenant, table, k_len, n_keys, keys, 0, id, stamp)
+}
+
+/// Builds the wire bytes of a multiget() RPC request that asks for versions. Refer to
+/// rpc::create_multiget_rpc_with_flags().
+pub fn encode_multiget_with_flags(
+    tenant: u32,
+    table: u64,
+    k_len: u16,
+    n_keys: u32,
+    keys: &[u8],
+    flags: u8,
+    id: u64,
+    stamp: u64,
+) -> Vec<u8> {
+    let hdr = MultiGetRequest::new(tenant, table, k_len, n_keys, id, stamp);
+    encode(hdr.with_flags(flags), &[keys])
+}
+
+/// Builds the wire bytes of an invoke() RPC request. Refer to rpc::create_invoke_rpc().
+pub fn encode_invoke(tenant: u32, name_len: u32, payload: &[u8], id: u64, stamp: u64) -> Vec<u8> {
+    encode_invoke_gather(tenant, name_len, &[payload], id, stamp)
+}
+
+/// Builds the wire bytes of an invoke() RPC request on a payload made up of several segments.
+/// Refer to rpc::create_invoke_rpc_gather().
+pub fn encode_invoke_gather(
+    tenant: u32,
+    name_len: u32,
+    segments: &[&[u8]],
+    id: u64,
+    stamp: u64,
+) -> Vec<u8> {
+    let len = segments.iter().fold(0, |len, s| len + s.len());
+    encode(
+        InvokeRequest::new(
+            tenant,
+            name_len,
+            (len - name_len as usize) as u32,
+            id,
+            stamp,
+        ),
+        segments,
+    )
+}
+
+/// Builds the wire bytes of an invoke() RPC request that names the extension by the id it was
+/// listed under (ExtensionInfo::id) instead of it's name, by setting INVOKE_FLAG_BY_ID on it. A
+/// request that fails with StatusStaleExtensionId should be sent again after listing the
+/// tenant's extensions again.
+pub fn encode_invoke_by_id(tenant: u32, ext: u32, args: &[u8], id: u64, stamp: u64) -> Vec<u8> {
+    let ext: [u8; 4] = unsafe { transmute(ext.to_le()) };
+    let mut req = encode_invoke_gather(tenant, 4, &[&ext, args], id, stamp);
+    req[size_of::<InvokeRequest>() - 1] |= INVOKE_FLAG_BY_ID;
+    req
+}
+
+/// Marks an encoded request as a retransmit of one that got no response, by setting
+/// REQUEST_FLAG_RETRY on it. The request must go out with the id of the original, so that a
+/// server replaying responses (see db::replay) answers it without executing it again.
+pub fn mark_retry(req: &mut [u8]) {
+    let flags = size_of::<RpcRequestHeader>() - 1;
+    if req.len() > flags {
+        req[flags] |= REQUEST_FLAG_RETRY;
+    }
+}
+
+/// Marks an encoded invoke() request as asking for it's result to be stored in the tenant's
+/// mailbox, by setting INVOKE_FLAG_STORE_RESULT on it. A tenant whose response is lost can then
+/// fetch the result with a fetch_result() instead of invoking the extension again.
+pub fn mark_store_result(req: &mut [u8]) {
+    let flags = size_of::<InvokeRequest>() - 1;
+    if req.len() > flags {
+        req[flags] |= INVOKE_FLAG_STORE_RESULT;
+    }
+}
+
+/// Marks an encoded get() request as asking for a verified read, by setting REQUEST_FLAG_VERIFY
+/// on it. The server checks the object against it's checksum, and fails the request with
+/// StatusDataCorrupted instead of returning bytes that do not match.
+pub fn mark_verify(req: &mut [u8]) {
+    let flags = size_of::<RpcRequestHeader>() - 1;
+    if req.len() > flags {
+        req[flags] |= REQUEST_FLAG_VERIFY;
+    }
+}
+
+/// Marks an encoded request as asking for a trace trailer on it's response, by setting
+/// REQUEST_FLAG_TRACE on it. Refer to db::trailer::Trace, and Response::trailers().
+pub fn mark_trace(req: &mut [u8]) {
+    let flags = size_of::<RpcRequestHeader>() - 1;
+    if req.len() > flags {
+        req[flags] |= REQUEST_FLAG_TRACE;
+    }
+}
+
+/// Builds the wire bytes of a list_extensions() RPC request. Refer to rpc::create_list_ext_rpc().
+pub fn encode_list_ext(tenant: u32, start: u32, id: u64, stamp: u64) -> Vec<u8> {
+    encode(ListExtRequest::new(tenant, start, id, stamp), &[])
+}
+
+/// Builds the wire bytes of an audit() RPC request. Refer to rpc::create_audit_rpc().
+pub fn encode_audit(tenant: u32, cursor: u64, id: u64, stamp: u64) -> Vec<u8> {
+    encode(AuditRequest::new(tenant, cursor, id, stamp), &[])
+}
+
+/// Builds the wire bytes of a dump() RPC request. Refer to rpc::create_dump_rpc().
+pub fn encode_dump(
+    tenant: u32,
+    table: u64,
+    bucket: u32,
+    after: &[u8],
+    id: u64,
+    stamp: u64,
+) -> Vec<u8> {
+    if after.len() > u16::max_value() as usize {
+        panic!("Key too long ({} bytes).", after.len());
+    }
+
+    encode(
+        DumpRequest::new(tenant, table, bucket, after.len() as u16, id, stamp),
+        &[after],
+    )
+}
+
+/// Builds the wire bytes of a multiput() RPC request. Refer to rpc::create_multiput_rpc().
+pub fn encode_multiput(
+    tenant: u32,
+    table: u64,
+    records: &[u8],
+    num: u32,
+    id: u64,
+    stamp: u64,
+) -> Vec<u8> {
+    encode(
+        MultiPutRequest::new(tenant, table, num, id, stamp),
+        &[records],
+    )
+}
+
+/// Builds the wire bytes of a write_stats() RPC request. Refer to rpc::create_write_stats_rpc().
+pub fn encode_write_stats(tenant: u32, table: u64, query: u8, id: u64, stamp: u64) -> Vec<u8> {
+    encode(WriteStatsRequest::new(tenant, table, query, id, stamp), &[])
+}
+
+/// Builds the wire bytes of a core_stats() RPC request. Refer to rpc::create_core_stats_rpc().
+pub fn encode_core_stats(tenant: u32, id: u64, stamp: u64) -> Vec<u8> {
+    encode(CoreStatsRequest::new(tenant, id, stamp), &[])
+}
+
+/// Builds the wire bytes of a latency_stats() RPC request. Refer to
+/// rpc::create_latency_stats_rpc().
+pub fn encode_latency_stats(
+    tenant: u32,
+    query: u8,
+    opcode: u8,
+    name: &[u8],
+    id: u64,
+    stamp: u64,
+) -> Vec<u8> {
+    encode(
+        LatencyStatsRequest::new(tenant, query, opcode, id, stamp),
+        &[name],
+    )
+}
+
+/// Builds the wire bytes of a drain() RPC request. Refer to rpc::create_drain_rpc().
+pub fn encode_drain(tenant: u32, id: u64, stamp: u64) -> Vec<u8> {
+    encode(DrainRequest::new(tenant, id, stamp), &[])
+}
+
+/// Builds the wire bytes of a table_access() RPC request. Refer to rpc::create_table_access_rpc().
+pub fn encode_table_access(
+    tenant: u32,
+    table_id: u64,
+    readable: bool,
+    writable: bool,
+    id: u64,
+    stamp: u64,
+) -> Vec<u8> {
+    encode(
+        TableAccessRequest::new(tenant, table_id, readable, writable, id, stamp),
+        &[],
+    )
+}
+
+/// Builds the wire bytes of a create_table() RPC request. Refer to rpc::create_create_table_rpc().
+pub fn encode_create_table(
+    tenant: u32,
+    table_id: u64,
+    records: u64,
+    cache: Option<CacheTarget>,
+    id: u64,
+    stamp: u64,
+) -> Vec<u8> {
+    encode(
+        rpc::create_table_header(tenant, table_id, records, cache, id, stamp),
+        &[],
+    )
+}
+
+/// Builds the wire bytes of a drop_table() RPC request. Refer to rpc::create_drop_table_rpc().
+pub fn encode_drop_table(tenant: u32, table_id: u64, id: u64, stamp: u64) -> Vec<u8> {
+    encode(DropTableRequest::new(tenant, table_id, id, stamp), &[])
+}
+
+/// Builds the wire bytes of a fetch_result() RPC request. Refer to rpc::create_fetch_result_rpc().
+pub fn encode_fetch_result(tenant: u32, request_id: u64, id: u64, stamp: u64) -> Vec<u8> {
+    encode(FetchResultRequest::new(tenant, request_id, id, stamp), &[])
+}
+
+/// Builds the wire bytes of a merge() RPC request. Refer to rpc::create_merge_rpc().
+pub fn encode_merge(
+    tenant: u32,
+    table: u64,
+    key: &[u8],
+    delta: &[u8],
+    id: u64,
+    stamp: u64,
+) -> Vec<u8> {
+    if key.len() > u16::max_value() as usize {
+        panic!("Key too long ({} bytes).", key.len());
+    }
+
+    encode(
+        MergeRequest::new(tenant, table, key.len() as u16, id, stamp),
+        &[key, delta],
+    )
+}
+
+/// Builds the wire bytes of a set_merge() RPC request. Refer to rpc::create_set_merge_rpc().
+pub fn encode_set_merge(tenant: u32, table: u64, name: &[u8], id: u64, stamp: u64) -> Vec<u8> {
+    encode(
+        SetMergeRequest::new(tenant, table, name.len() as u32, id, stamp),
+        &[name],
+    )
+}
+
+/// Builds the wire bytes of a routed invoke() RPC request. Refer to
+/// rpc::create_routed_invoke_rpc().
+pub fn encode_routed_invoke(
+    tenant: u32,
+    table: u64,
+    key: &[u8],
+    args: &[u8],
+    id: u64,
+    stamp: u64,
+) -> Vec<u8> {
+    if key.len() > u16::max_value() as usize {
+        panic!("Key too long ({} bytes).", key.len());
+    }
+
+    encode(
+        RoutedInvokeRequest::new(
+            tenant,
+            table,
+            key.len() as u16,
+            args.len() as u32,
+            id,
+            stamp,
+        ),
+        &[key, args],
+    )
+}
+
+/// Builds the wire bytes of a set_route() RPC request. Refer to rpc::create_set_route_rpc().
+pub fn encode_set_route(tenant: u32, prefix: &[u8], name: &[u8], id: u64, stamp: u64) -> Vec<u8> {
+    if prefix.len() > u16::max_value() as usize {
+        panic!("Prefix too long ({} bytes).", prefix.len());
+    }
+
+    encode(
+        SetRouteRequest::new(tenant, prefix.len() as u16, name.len() as u32, id, stamp),
+        &[prefix, name],
+    )
+}
+
+/// A response received over the UDP transport. This is a shim mirroring the
+/// parts of Netbricks' Packet interface that the response handling code uses,
+/// but operates over an owned buffer instead of an mbuf.
+pub struct Response {
+    // The bytes received, starting at the RPC response header.
+    buf: Vec<u8>,
+}
+
+/// A view of a Response parsed upto a header of type H.
+pub struct ParsedResponse<'a, H> {
+    // The bytes of the response, starting at the RPC response header.
+    buf: &'a [u8],
+
+    // The type of header the response was parsed upto.
+    hdr: PhantomData<H>,
+}
+
+impl Response {
+    /// Wraps up a received buffer into a Response.
+    ///
+    /// # Arguments
+    ///
+    /// * `buf`: The bytes received, starting at the RPC response header.
+    pub fn new(buf: Vec<u8>) -> Response {
+        Response { buf: buf }
+    }
+
+    /// Returns the opcode on the response, or InvalidOperation if it is too
+    /// short or carries an unknown opcode. Mirrors rpc::parse_rpc_opcode().
+    pub fn opcode(&self) -> OpCode {
+        if self.buf.len() < size_of::<RpcResponseHeader>() {
+            return OpCode::InvalidOperation;
+        }
+
+        let opcode: u8 = self.buf[1];
+        match opcode.lt(&(OpCode::InvalidOperation as u8)) {
+            true => unsafe { transmute(opcode) },
+            false => OpCode::InvalidOperation,
+        }
+    }
+
+    /// Parses the response upto a header of type H.
+    ///
+    /// # Return
+    ///
+    /// The parsed response if it is long enough to hold the header, and the
+    /// status on it is valid.
+    pub fn parse_header<H>(&self) -> Option<ParsedResponse<H>> {
+        if self.buf.len() < size_of::<H>() || self.buf.len() < size_of::<RpcResponseHeader>() {
+            return None;
+        }
+
+        // The status is the first byte of the response header. Reject anything that would not
+        // transmute into a valid RpcStatus.
+        if self.buf[0] == 0 || self.buf[0] > RpcStatus::StatusRwSetTooLarge as u8 {
+            return None;
+        }
+
+        Some(ParsedResponse {
+            buf: &self.buf,
+            hdr: PhantomData,
+        })
+    }
+
+    /// Returns false if this is a get() response whose value length does not match it's payload.
+    /// Refer to rpc::response_length_ok().
+    pub fn length_ok(&self) -> bool {
+        rpc::response_length_ok(&self.buf)
+    }
+
+    /// Returns the trailers at the end of the response. None if the response is too short to
+    /// hold a header, or it's trailers are malformed. A response without any is read as having
+    /// none.
+    pub fn trailers(&self) -> Option<TrailerReader> {
+        let flags = *self.buf.get(RESPONSE_FLAGS_OFFSET)?;
+        TrailerReader::split(flags, &self.buf).map(|(_, trailers)| trailers)
+    }
+
+    /// Consumes the response, returning the underlying buffer so that it can be reused.
+    pub fn into_inner(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+impl<'a, H> ParsedResponse<'a, H> {
+    /// Returns a reference to the parsed header.
+    pub fn get_header(&self) -> &H {
+        // Wireformat headers are packed, so the pointer does not have to be aligned.
+        unsafe { &*(self.buf.as_ptr() as *const H) }
+    }
+
+    /// Returns the payload following the parsed header.
+    pub fn get_payload(&self) -> &[u8] {
+        &self.buf[size_of::<H>()..]
+    }
+}
+
+/// Creates a UDP socket bound to an address with SO_REUSEPORT set, so that a
+/// restarted client can immediately rebind to the same ports.
+fn bind_reuseport(addr: SocketAddrV4) -> io::Result<UdpSocket> {
+    unsafe {
+        let fd = libc::socket(libc::AF_INET, libc::SOCK_DGRAM, 0);
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // Wrap the descriptor right away so that it gets closed on an error below.
+        let socket = UdpSocket::from_raw_fd(fd);
+
+        let one: libc::c_int = 1;
+        if libc::setsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_REUSEPORT,
+            &one as *const libc::c_int as *const libc::c_void,
+            size_of::<libc::c_int>() as libc::socklen_t,
+        ) < 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut sin: libc::sockaddr_in = ::std::mem::zeroed();
+        sin.sin_family = libc::AF_INET as libc::sa_family_t;
+        sin.sin_port = addr.port().to_be();
+        sin.sin_addr = libc::in_addr {
+            s_addr: u32::from(*addr.ip()).to_be(),
+        };
+        if libc::bind(
+            fd,
+            &sin as *const libc::sockaddr_in as *const libc::sockaddr,
+            size_of::<libc::sockaddr_in>() as libc::socklen_t,
+        ) < 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(socket)
+    }
+}
+
+/// A simple RPC request generator that sends requests over a kernel UDP
+/// socket. Exposes the same interface as dispatch::Sender.
+pub struct UdpSender {
+    // The socket requests are sent out on.
+    socket: UdpSocket,
+
+    // The IP address of the server. Changes if the client fails over, see redirect().
+    server_ip: Cell<Ipv4Addr>,
+
+    // The first server port requests are sent to.
+    server_port: Cell<u16>,
+
+    // The number of destination UDP ports a packet can be sent to.
+    dst_ports: u16,
+
+    // Tracks number of packets sent to the server for occasional debug messages.
+    requests_sent: Cell<u64>,
+
+    // Generates ids for requests sent out by this instance.
+    ids: RequestIds,
+
+    // The staleness budget on get()s sent out without one, in microseconds.
+    staleness_us: u32,
+}
+
+impl UdpSender {
+    /// Returns a new request id. Refer to dispatch::Sender::next_id().
+    #[inline]
+    pub fn next_id(&self) -> u64 {
+        self.ids.next()
+    }
+
+    /// Sends every request from now on to a different server, ex: a backup once the server
+    /// stopped responding. Responses from the old server are still received.
+    ///
+    /// # Arguments
+    ///
+    /// * `ip`:   The IP address of the server.
+    /// * `port`: The first UDP port of the server.
+    pub fn redirect(&self, ip: Ipv4Addr, port: u16) {
+        self.server_ip.set(ip);
+        self.server_port.set(port);
+    }
+
+    /// Sends out an echo() RPC request. Refer to dispatch::Sender::send_echo().
+    pub fn send_echo(&self, tenant: u32, id: u64, stamp: u64) {
+        let req = encode_echo(tenant, id, stamp);
+        self.send_req(tenant, &req);
+    }
+
+    /// Sends out a get() RPC request. Refer to dispatch::Sender::send_get().
+    pub fn send_get(&self, tenant: u32, table: u64, key: &[u8], id: u64, stamp: u64) {
+        self.send_get_with_staleness(tenant, table, key, self.staleness_us, id, stamp);
+    }
+
+    /// Sends out a get() RPC request with a staleness budget. Refer to
+    /// dispatch::Sender::send_get_with_staleness().
+    pub fn send_get_with_staleness(
+        &self,
+        tenant: u32,
+        table: u64,
+        key: &[u8],
+        staleness_us: u32,
+        id: u64,
+        stamp: u64,
+    ) {
+        let req = encode_get_with_staleness(tenant, table, key, staleness_us, id, stamp);
+        self.send_req(tenant, &req);
+    }
+
+    /// Sends out a get() RPC request for part of the value. Refer to
+    /// dispatch::Sender::send_get_with_projection().
+    pub fn send_get_with_projection(
+        &self,
+        tenant: u32,
+        table: u64,
+        key: &[u8],
+        projection: (u32, u32),
+        id: u64,
+        stamp: u64,
+    ) {
+        let staleness_us = self.staleness_us;
+        let req =
+            encode_get_with_projection(tenant, table, key, staleness_us, projection, id, stamp);
+        self.send_req(tenant, &req);
+    }
+
+    /// Sends out a put() RPC request. Refer to dispatch::Sender::send_put().
+    pub fn send_put(&self, tenant: u32, table: u64, key: &[u8], val: &[u8], id: u64, stamp: u64) {
+        let req = encode_put(tenant, table, key, val, id, stamp);
+        self.send_req(tenant, &req);
+    }
+
+    /// Sends out a put() RPC request on a value made up of several segments. Refer to
+    /// dispatch::Sender::send_put_gather().
+    pub fn send_put_gather(
+        &self,
+        tenant: u32,
+        table: u64,
+        key: &[u8],
+        segments: &[&[u8]],
+        id: u64,
+        stamp: u64,
+    ) {
+        let req = encode_put_gather(tenant, table, key, segments, id, stamp);
+        self.send_req(tenant, &req);
+    }
+
+    /// Sends out a multiget() RPC request. Refer to dispatch::Sender::send_multiget().
+    pub fn send_multiget(
+        &self,
+        tenant: u32,
+        table: u64,
+        k_len: u16,
+        n_keys: u32,
+        keys: &[u8],
+        id: u64,
+        stamp: u64,
+    ) {
+        let req = encode_multiget(tenant, table, k_len, n_keys, keys, id, stamp);
+        self.send_req(tenant, &req);
+    }
+
+    /// Sends out a multiget() RPC request that asks for versions. Refer to
+    /// dispatch::Sender::send_multiget_with_flags().
+    pub fn send_multiget_with_flags(
+        &self,
+        tenant: u32,
+        table: u64,
+        k_len: u16,
+        n_keys: u32,
+        keys: &[u8],
+        flags: u8,
+        id: u64,
+        stamp: u64,
+    ) {
+        let req = encode_multiget_with_flags(tenant, table, k_len, n_keys, keys, flags, id, stamp);
+        self.send_req(tenant, &req);
+    }
+
+    /// Sends out an invoke() RPC request. Refer to dispatch::Sender::send_invoke().
+    pub fn send_invoke(&self, tenant: u32, name_len: u32, payload: &[u8], id: u64, stamp: u64) {
+        let req = encode_invoke(tenant, name_len, payload, id, stamp);
+        self.send_req(tenant, &req);
+    }
+
+    /// Sends out an invoke() RPC request on a payload made up of several segments. Refer to
+    /// dispatch::Sender::send_invoke_gather().
+    pub fn send_invoke_gather(
+        &self,
+        tenant: u32,
+        name_len: u32,
+        segments: &[&[u8]],
+        id: u64,
+        stamp: u64,
+    ) {
+        let req = encode_invoke_gather(tenant, name_len, segments, id, stamp);
+        self.send_req(tenant, &req);
+    }
+
+    /// Sends out an invoke() RPC request naming the extension by it's interned id. Refer to
+    /// encode_invoke_by_id().
+    pub fn send_invoke_by_id(&self, tenant: u32, ext: u32, args: &[u8], id: u64, stamp: u64) {
+        let req = encode_invoke_by_id(tenant, ext, args, id, stamp);
+        self.send_req(tenant, &req);
+    }
+
+    /// Sends out a list_extensions() RPC request. Refer to dispatch::Sender::send_list_ext().
+    pub fn send_list_ext(&self, tenant: u32, start: u32, id: u64, stamp: u64) {
+        let req = encode_list_ext(tenant, start, id, stamp);
+        self.send_req(tenant, &req);
+    }
+
+    /// Sends out an audit() RPC request. Refer to dispatch::Sender::send_audit().
+    pub fn send_audit(&self, tenant: u32, cursor: u64, id: u64, stamp: u64) {
+        let req = encode_audit(tenant, cursor, id, stamp);
+        self.send_req(tenant, &req);
+    }
+
+    /// Sends out a dump() RPC request. Refer to dispatch::Sender::send_dump().
+    pub fn send_dump(
+        &self,
+        tenant: u32,
+        table: u64,
+        bucket: u32,
+        after: &[u8],
+        id: u64,
+        stamp: u64,
+    ) {
+        let req = encode_dump(tenant, table, bucket, after, id, stamp);
+        self.send_req(tenant, &req);
+    }
+
+    /// Sends out a multiput() RPC request. Refer to dispatch::Sender::send_multiput().
+    pub fn send_multiput(
+        &self,
+        tenant: u32,
+        table: u64,
+        records: &[u8],
+        num: u32,
+        id: u64,
+        stamp: u64,
+    ) {
+        let req = encode_multiput(tenant, table, records, num, id, stamp);
+        self.send_req(tenant, &req);
+    }
+
+    /// Sends out a write_stats() RPC request. Refer to dispatch::Sender::send_write_stats().
+    pub fn send_write_stats(&self, tenant: u32, table: u64, query: u8, id: u64, stamp: u64) {
+        let req = encode_write_stats(tenant, table, query, id, stamp);
+        self.send_req(tenant, &req);
+    }
+
+    /// Sends out a core_stats() RPC request. Refer to dispatch::Sender::send_core_stats().
+    pub fn send_core_stats(&self, tenant: u32, id: u64, stamp: u64) {
+        let req = encode_core_stats(tenant, id, stamp);
+        self.send_req(tenant, &req);
+    }
+
+    /// Sends out a latency_stats() RPC request. Refer to dispatch::Sender::send_latency_stats().
+    pub fn send_latency_stats(
+        &self,
+        tenant: u32,
+        query: u8,
+        opcode: u8,
+        name: &[u8],
+        id: u64,
+        stamp: u64,
+    ) {
+        let req = encode_latency_stats(tenant, query, opcode, name, id, stamp);
+        self.send_req(tenant, &req);
+    }
+
+    /// Sends out a drain() RPC request. Refer to dispatch::Sender::send_drain().
+    pub fn send_drain(&self, tenant: u32, id: u64, stamp: u64) {
+        let req = encode_drain(tenant, id, stamp);
+        self.send_req(tenant, &req);
+    }
+
+    /// Sends out a table_access() RPC request. Refer to dispatch::Sender::send_table_access().
+    pub fn send_table_access(
+        &self,
+        tenant: u32,
+        table_id: u64,
+        readable: bool,
+        writable: bool,
+        id: u64,
+        stamp: u64,
+    ) {
+        let req = encode_table_access(tenant, table_id, readable, writable, id, stamp);
+        self.send_req(tenant, &req);
+    }
+
+    /// Sends out a create_table() RPC request. Refer to dispatch::Sender::send_create_table().
+    pub fn send_create_table(
+        &self,
+        tenant: u32,
+        table_id: u64,
+        records: u64,
+        cache: Option<CacheTarget>,
+        id: u64,
+        stamp: u64,
+    ) {
+        let req = encode_create_table(tenant, table_id, records, cache, id, stamp);
+        self.send_req(tenant, &req);
+    }
+
+    /// Sends out a drop_table() RPC request. Refer to dispatch::Sender::send_drop_table().
+    pub fn send_drop_table(&self, tenant: u32, table_id: u64, id: u64, stamp: u64) {
+        let req = encode_drop_table(tenant, table_id, id, stamp);
+        self.send_req(tenant, &req);
+    }
+
+    /// Sends out a fetch_result() RPC request. Refer to dispatch::Sender::send_fetch_result().
+    pub fn send_fetch_result(&self, tenant: u32, request_id: u64, id: u64, stamp: u64) {
+        let req = encode_fetch_result(tenant, request_id, id, stamp);
+        self.send_req(tenant, &req);
+    }
+
+    /// Sends out a merge() RPC request. Refer to rpc::create_merge_rpc().
+    pub fn send_merge(
+        &self,
+        tenant: u32,
+        table: u64,
+        key: &[u8],
+        delta: &[u8],
+        id: u64,
+        stamp: u64,
+    ) {
+        let req = encode_merge(tenant, table, key, delta, id, stamp);
+        self.send_req(tenant, &req);
+    }
+
+    /// Sends out a set_merge() RPC request. Refer to rpc::create_set_merge_rpc().
+    pub fn send_set_merge(&self, tenant: u32, table: u64, name: &[u8], id: u64, stamp: u64) {
+        let req = encode_set_merge(tenant, table, name, id, stamp);
+        self.send_req(tenant, &req);
+    }
+
+    /// Sends out a routed invoke() RPC request. Refer to rpc::create_routed_invoke_rpc().
+    pub fn send_routed_invoke(
+        &self,
+        tenant: u32,
+        table: u64,
+        key: &[u8],
+        args: &[u8],
+        id: u64,
+        stamp: u64,
+    ) {
+        let req = encode_routed_invoke(tenant, table, key, args, id, stamp);
+        self.send_req(tenant, &req);
+    }
+
+    /// Sends out a set_route() RPC request. Refer to rpc::create_set_route_rpc().
+    pub fn send_set_route(&self, tenant: u32, prefix: &[u8], name: &[u8], id: u64, stamp: u64) {
+        let req = encode_set_route(tenant, prefix, name, id, stamp);
+        self.send_req(tenant, &req);
+    }
+
+    /// Sends out a request that was already encoded, ex: by encode_get(). Lets a request be
+    /// modified before it goes out.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant`: The tenant the request is for. Determines the destination port.
+    /// * `req`:    The request, starting at the RPC request header.
+    pub fn send_encoded(&self, tenant: u32, req: &[u8]) {
+        self.send_req(tenant, req);
+    }
+
+    /// Computes the destination UDP port given a tenant identifier.
+    #[inline]
+    pub fn get_dst_port(&self, tenant: u32) -> u16 {
+        self.server_port.get() + ((tenant & 0xffff) as u16 & (self.dst_ports - 1))
+    }
+
+    /// Sends a request out the socket.
+    fn send_req(&self, tenant: u32, req: &[u8]) {
+        let dst = SocketAddrV4::new(self.server_ip.get(), self.get_dst_port(tenant));
+        if let Err(e) = self.socket.send_to(req, dst) {
+            warn!("Failed to send request over udp: {}", e);
+        }
+
+        // Update the number of requests sent out by this generator.
+        let r = self.requests_sent.get();
+        if r & 0xffffff == 0 {
+            info!("Sent many requests...");
+        }
+        self.requests_sent.set(r + 1);
+    }
+}
+
+/// A Receiver of responses to RPC requests sent over a kernel UDP socket.
+pub struct UdpReceiver {
+    // The socket responses are received on. Shared with the corresponding UdpSender.
+    socket: UdpSocket,
+
+    // Buffers that responses are received into. Responses handed back through recycle() are
+    // reused instead of allocating a new buffer per response.
+    pool: RefCell<Vec<Vec<u8>>>,
+
+    // The total number of responses received.
+    responses_recv: Cell<u64>,
+
+    // The number of responses received whose length fields did not match their payload.
+    length_mismatches: Cell<u64>,
+}
+
+impl UdpReceiver {
+    /// Receives responses from the socket without blocking.
+    ///
+    /// # Return
+    ///
+    /// Upto 32 responses, if there were any available on the socket.
+    pub fn recv_res(&self) -> Option<Vec<Response>> {
+        let mut responses = Vec::with_capacity(MAX_RX_RESPONSES);
+
+        while responses.len() < MAX_RX_RESPONSES {
+            let mut buf = self
+                .pool
+                .borrow_mut()
+                .pop()
+                .unwrap_or_else(|| Vec::with_capacity(MAX_RESPONSE_LEN));
+            buf.resize(MAX_RESPONSE_LEN, 0);
+
+            match self.socket.recv_from(&mut buf) {
+                Ok((len, _)) => {
+                    buf.truncate(len);
+                    let response = Response::new(buf);
+                    if !response.length_ok() {
+                        self.length_mismatches.set(self.length_mismatches.get() + 1);
+                    }
+                    responses.push(response);
+                }
+
+                Err(e) => {
+                    if e.kind() != io::ErrorKind::WouldBlock {
+                        warn!("Failed to receive response over udp: {}", e);
+                    }
+                    self.pool.borrow_mut().push(buf);
+                    break;
+                }
+            }
+        }
+
+        if responses.len() == 0 {
+            return None;
+        }
+
+        let r = self.responses_recv.get();
+        if r & 0xffffff == 0 {
+            info!("Received many responses...");
+        }
+        self.responses_recv.set(r + responses.len() as u64);
+
+        Some(responses)
+    }
+
+    /// Returns the number of responses received so far whose length fields did not match the
+    /// payload they were received with. Such responses are still handed out by recv_res(), and
+    /// can be told apart with Response::length_ok().
+    pub fn length_mismatches(&self) -> u64 {
+        self.length_mismatches.get()
+    }
+
+    /// Hands a response's buffer back so that it can be reused by recv_res().
+    pub fn recycle(&self, response: Response) {
+        let mut pool = self.pool.borrow_mut();
+        if pool.len() < MAX_RX_RESPONSES {
+            pool.push(response.into_inner());
+        }
+    }
+}
+
+/// Creates a sender and receiver pair for one client pipeline, sharing a
+/// single non-blocking UDP socket.
+///
+/// # Arguments
+///
+/// * `config`:    Client configuration. `ip_address`, `udp_client_port`, `server_ip_address`,
+///                and `udp_server_port` are used to setup the socket.
+/// * `pipeline`:  The index of the pipeline. The socket is bound to `udp_client_port + pipeline`,
+///                and request ids sent out by the pipeline carry this index.
+/// * `dst_ports`: The number of destination UDP ports a packet can be sent to.
+///
+/// # Return
+///
+/// A sender and receiver that talk to the server over kernel UDP.
+pub fn udp_pipeline(
+    config: &config::ClientConfig,
+    pipeline: u16,
+    dst_ports: u16,
+) -> io::Result<(UdpSender, UdpReceiver)> {
+    let ip = Ipv4Addr::from_str(&config.ip_address).expect("Failed to create source IP.");
+    let server_ip =
+        Ipv4Addr::from_str(&config.server_ip_address).expect("Failed to create destination IP.");
+
+    let socket = bind_reuseport(SocketAddrV4::new(ip, config.udp_client_port + pipeline))?;
+    socket.set_nonblocking(true)?;
+
+    let sender = UdpSender {
+        socket: socket.try_clone()?,
+        server_ip: Cell::new(server_ip),
+        server_port: Cell::new(config.udp_server_port),
+        dst_ports: dst_ports,
+        requests_sent: Cell::new(0),
+        ids: RequestIds::new(pipeline),
+        staleness_us: config.get_staleness_us,
+    };
+
+    let receiver = UdpReceiver {
+        socket: socket,
+        pool: RefCell::new(Vec::with_capacity(MAX_RX_RESPONSES)),
+        responses_recv: Cell::new(0),
+        length_mismatches: Cell::new(0),
+    };
+
+    Ok((sender, receiver))
+}
+
+/// Lists the extensions a tenant can invoke, paging through the listing until it is complete.
+/// Meant to be called before a workload starts; responses to any other request received in the
+/// meantime are dropped.
+///
+/// # Arguments
+///
+/// * `sender`:   The sender the list_extensions() requests are sent out on.
+/// * `receiver`: The receiver paired with `sender`.
+/// * `tenant`:   The tenant whose extensions must be listed.
+/// * `timeout`:  How long to wait for each page of the listing.
+///
+/// # Return
+///
+/// The extensions, or an error if a page timed out or was malformed.
+pub fn list_extensions(
+    sender: &UdpSender,
+    receiver: &UdpReceiver,
+    tenant: u32,
+    timeout: Duration,
+) -> io::Result<Vec<ExtensionInfo>> {
+    let mut list = Vec::new();
+    let mut start = 0;
+
+    loop {
+        let id = sender.next_id();
+        sender.send_list_ext(tenant, start, id, 0);
+
+        // Wait for the response to this page.
+        let begin = Instant::now();
+        let mut page = None;
+        while page.is_none() {
+            if begin.elapsed() > timeout {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "Timed out listing extensions",
+                ));
+            }
+
+            for res in receiver.recv_res().unwrap_or_else(Vec::new) {
+                if page.is_none() && res.opcode() == OpCode::SandstormListExtRpc {
+                    page = res.parse_header::<ListExtResponse>().and_then(|p| {
+                        let hdr = p.get_header();
+                        let (r_id, num, next) =
+                            (hdr.common_header.id(), hdr.num_entries(), hdr.next());
+                        if r_id != id {
+                            return None;
+                        }
+                        Some(rpc::parse_ext_listing(p.get_payload(), num).map(|l| (l, next)))
+                    });
+                }
+                receiver.recycle(res);
+            }
+        }
+
+        match page.unwrap() {
+            Some((entries, next)) => {
+                list.extend(entries);
+                if next == 0 {
+                    return Ok(list);
+                }
+                start = next;
+            }
+
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Malformed extension listing",
+                ));
+            }
+        }
+    }
+}
+
+/// Asks the server what it serves with an echo(), so that a client can check it before
+/// starting (or resuming) a workload. Responses to any other request received in the meantime
+/// are dropped.
+///
+/// # Arguments
+///
+/// * `sender`:   The sender the echo() is sent out on.
+/// * `receiver`: The receiver paired with `sender`.
+/// * `tenant`:   The tenant the echo() is sent as.
+/// * `timeout`:  How long to wait for the response.
+///
+/// # Return
+///
+/// The opcodes the server serves, as a mask of OpCode::bit(), and the longest key and largest
+/// value it accepts. An error if the response timed out.
+pub fn echo(
+    sender: &UdpSender,
+    receiver: &UdpReceiver,
+    tenant: u32,
+    timeout: Duration,
+) -> io::Result<(u64, (usize, usize))> {
+    let id = sender.next_id();
+    sender.send_echo(tenant, id, 0);
+
+    let begin = Instant::now();
+    loop {
+        if begin.elapsed() > timeout {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "Timed out on echo"));
+        }
+
+        let mut echoed = None;
+        for res in receiver.recv_res().unwrap_or_else(Vec::new) {
+            if echoed.is_none() && res.opcode() == OpCode::SandstormEchoRpc {
+                echoed = res.parse_header::<EchoResponse>().and_then(|p| {
+                    let hdr = p.get_header();
+                    let (r_id, served) = (hdr.common_header.id(), hdr.opcodes());
+                    let limits = (hdr.max_key_len() as usize, hdr.max_value_len() as usize);
+                    match r_id == id {
+                        true => Some((served, limits)),
+                        false => None,
+                    }
+                });
+            }
+            receiver.recycle(res);
+        }
+
+        if let Some(echoed) = echoed {
+            return Ok(echoed);
+        }
+    }
+}
+
+/// Asks the server how long each of it's cores spent backing off polling, and how much CPU time
+/// it has used, with a core_stats() sent as tenant 0. Responses to any other request received
+/// in the meantime are dropped.
+///
+/// # Arguments
+///
+/// * `sender`:   The sender the core_stats() is sent out on.
+/// * `receiver`: The receiver paired with `sender`.
+/// * `timeout`:  How long to wait for the response.
+///
+/// # Return
+///
+/// The backoff counters of each core, and the CPU time the server has used in user and in
+/// system mode, in microseconds. An error if the response timed out, was malformed, or was
+/// refused.
+pub fn core_stats(
+    sender: &UdpSender,
+    receiver: &UdpReceiver,
+    timeout: Duration,
+) -> io::Result<(Vec<CoreSummary>, (u64, u64))> {
+    let id = sender.next_id();
+    sender.send_core_stats(0, id, 0);
+
+    let begin = Instant::now();
+    loop {
+        if begin.elapsed() > timeout {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "Timed out on core_stats",
+            ));
+        }
+
+        let mut stats = None;
+        for res in receiver.recv_res().unwrap_or_else(Vec::new) {
+            if stats.is_none() && res.opcode() == OpCode::SandstormCoreStatsRpc {
+                stats = res.parse_header::<CoreStatsResponse>().and_then(|p| {
+                    let hdr = p.get_header();
+                    let (r_id, status, num) = (
+                        hdr.common_header.id(),
+                        hdr.common_header.status.clone(),
+                        hdr.num_cores(),
+                    );
+                    let cpu = (hdr.user_us(), hdr.system_us());
+                    if r_id != id {
+                        return None;
+                    }
+                    match status {
+                        RpcStatus::StatusOk => Some(
+                            rpc::parse_core_stats(p.get_payload(), num)
+                                .map(|cores| (cores, cpu))
+                                .ok_or_else(|| "Malformed core_stats response".to_string()),
+                        ),
+                        status => Some(Err(format!("core_stats refused with {:?}", status))),
+                    }
+                });
+            }
+            receiver.recycle(res);
+        }
+
+        if let Some(stats) = stats {
+            return stats.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e));
+        }
+    }
+}
+
+/// Creates one of a tenant's tables, pre-sized for the number of objects it is expected to
+/// hold and bounded to a capacity target if it is a cache, with a create_table(). Responses to any other request received in the meantime are
+/// dropped.
+///
+/// # Arguments
+///
+/// * `sender`:   The sender the create_table() is sent out on.
+/// * `receiver`: The receiver paired with `sender`.
+/// * `tenant`:   Id of the tenant the table is created for.
+/// * `table_id`: Id of the table to create.
+/// * `records`:  The number of objects the table is expected to hold. 0 does not pre-size it.
+/// * `cache`:    The table's capacity target, if it is created in cache mode.
+/// * `timeout`:  How long to wait for the response.
+///
+/// # Return
+///
+/// The number of slots reserved on the table, or the status the server refused to create it
+/// with (ex: StatusTableTooLarge). An error if the response timed out.
+pub fn create_table(
+    sender: &UdpSender,
+    receiver: &UdpReceiver,
+    tenant: u32,
+    table_id: u64,
+    records: u64,
+    cache: Option<CacheTarget>,
+    timeout: Duration,
+) -> io::Result<Result<u64, RpcStatus>> {
+    let id = sender.next_id();
+    sender.send_create_table(tenant, table_id, records, cache, id, 0);
+
+    let begin = Instant::now();
+    loop {
+        if begin.elapsed() > timeout {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "Timed out on create_table",
+            ));
+        }
+
+        let mut created = None;
+        for res in receiver.recv_res().unwrap_or_else(Vec::new) {
+            if created.is_none() && res.opcode() == OpCode::SandstormCreateTableRpc {
+                created = res.parse_header::<CreateTableResponse>().and_then(|p| {
+                    let hdr = p.get_header();
+                    if hdr.common_header.id() != id {
+                        return None;
+                    }
+                    match hdr.common_header.status.clone() {
+                        RpcStatus::StatusOk => Some(Ok(hdr.slots())),
+                        status => Some(Err(status)),
+                    }
+                });
+            }
+            receiver.recycle(res);
+        }
+
+        if let Some(created) = created {
+            return Ok(created);
+        }
+    }
+}
+
+/// Drops one of a tenant's tables with a drop_table(). Responses to any other request received
+/// in the meantime are dropped.
+///
+/// # Arguments
+///
+/// * `sender`:   The sender the drop_table() is sent out on.
+/// * `receiver`: The receiver paired with `sender`.
+/// * `tenant`:   Id of the tenant owning the table.
+/// * `table_id`: Id of the table to drop.
+/// * `timeout`:  How long to wait for the response.
+///
+/// # Return
+///
+/// The status on the response; StatusOk if the table was dropped. An error if the response
+/// timed out.
+pub fn drop_table(
+    sender: &UdpSender,
+    receiver: &UdpReceiver,
+    tenant: u32,
+    table_id: u64,
+    timeout: Duration,
+) -> io::Result<RpcStatus> {
+    let id = sender.next_id();
+    sender.send_drop_table(tenant, table_id, id, 0);
+
+    let begin = Instant::now();
+    loop {
+        if begin.elapsed() > timeout {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "Timed out on drop_table",
+            ));
+        }
+
+        let mut dropped = None;
+        for res in receiver.recv_res().unwrap_or_else(Vec::new) {
+            if dropped.is_none() && res.opcode() == OpCode::SandstormDropTableRpc {
+                dropped = res.parse_header::<DropTableResponse>().and_then(|p| {
+                    let hdr = p.get_header();
+                    match hdr.common_header.id() == id {
+                        true => Some(hdr.common_header.status.clone()),
+                        false => None,
+                    }
+                });
+            }
+            receiver.recycle(res);
+        }
+
+        if let Some(dropped) = dropped {
+            return Ok(dropped);
+        }
+    }
+}
+
+/// How invocations that asked for their result to be stored completed, across calls to
+/// invoke_stored().
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Recoveries {
+    /// Invocations answered by their response.
+    pub answered: u64,
+
+    /// Invocations whose response timed out, whose result was fetched off the mailbox.
+    pub recovered: u64,
+
+    /// Invocations whose response timed out with nothing in the mailbox, that were invoked again.
+    pub missing: u64,
+
+    /// Invocations whose response and fetch_result() both timed out, that were invoked again.
+    pub unreachable: u64,
+}
+
+/// Invokes an extension, asking for it's result to be stored in the tenant's mailbox. If the
+/// response times out, the result is fetched off the mailbox with a fetch_result(), and the
+/// extension is invoked again only if that fails; the retry carries the original's id, marked
+/// with mark_retry(), so that a server replaying responses does not run it twice either.
+/// Responses to any other request received in the meantime are dropped.
+///
+/// # Arguments
+///
+/// * `sender`:     The sender requests are sent out on.
+/// * `receiver`:   The receiver paired with `sender`.
+/// * `tenant`:     Id of the tenant invoking the extension.
+/// * `name_len`:   Number of bytes at the head of the payload identifying the extension.
+/// * `payload`:    The name of the extension, followed by it's arguments.
+/// * `timeout`:    How long to wait for each response.
+/// * `recoveries`: Counts how the invocation completed.
+///
+/// # Return
+///
+/// The status the invocation completed with, and the payload of it's response. An error if the
+/// retry timed out too.
+pub fn invoke_stored(
+    sender: &UdpSender,
+    receiver: &UdpReceiver,
+    tenant: u32,
+    name_len: u32,
+    payload: &[u8],
+    timeout: Duration,
+    recoveries: &mut Recoveries,
+) -> io::Result<(RpcStatus, Vec<u8>)> {
+    let id = sender.next_id();
+    let mut req = encode_invoke(tenant, name_len, payload, id, 0);
+    mark_store_result(&mut req);
+    sender.send_encoded(tenant, &req);
+    if let Some(result) = wait_invoke(receiver, id, timeout) {
+        recoveries.answered += 1;
+        return Ok(result);
+    }
+
+    match fetch_result(sender, receiver, tenant, id, timeout) {
+        Ok(Ok(result)) => {
+            recoveries.recovered += 1;
+            return Ok(result);
+        }
+        Ok(Err(_)) => recoveries.missing += 1,
+        Err(_) => recoveries.unreachable += 1,
+    }
+
+    mark_retry(&mut req);
+    sender.send_encoded(tenant, &req);
+    wait_invoke(receiver, id, timeout)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::TimedOut, "Timed out on invoke"))
+}
+
+/// Fetches the result an invoke() request stored in the tenant's mailbox, removing it, with a
+/// fetch_result(). Responses to any other request received in the meantime are dropped.
+///
+/// # Arguments
+///
+/// * `sender`:     The sender the fetch_result() is sent out on.
+/// * `receiver`:   The receiver paired with `sender`.
+/// * `tenant`:     Id of the tenant that issued the invoke() request.
+/// * `request_id`: The id of the invoke() request.
+/// * `timeout`:    How long to wait for the response.
+///
+/// # Return
+///
+/// The status the invocation completed with and the payload of it's response, or the status
+/// the fetch failed with (ex: StatusObjectDoesNotExist if nothing was stored under the id, or it
+/// expired). An error if the response timed out.
+pub fn fetch_result(
+    sender: &UdpSender,
+    receiver: &UdpReceiver,
+    tenant: u32,
+    request_id: u64,
+    timeout: Duration,
+) -> io::Result<Result<(RpcStatus, Vec<u8>), RpcStatus>> {
+    let id = sender.next_id();
+    sender.send_fetch_result(tenant, request_id, id, 0);
+
+    let fetched = wait_for(receiver, timeout, |res| {
+        if res.opcode() != OpCode::SandstormFetchResultRpc {
+            return None;
+        }
+        res.parse_header::<FetchResultResponse>().and_then(|p| {
+            let hdr = p.get_header();
+            if hdr.common_header.id() != id {
+                return None;
+            }
+            match hdr.common_header.status.clone() {
+                RpcStatus::StatusOk => Some(Ok((hdr.result.clone(), p.get_payload().to_vec()))),
+                status => Some(Err(status)),
+            }
+        })
+    });
+    fetched.ok_or_else(|| io::Error::new(io::ErrorKind::TimedOut, "Timed out on fetch_result"))
+}
+
+// Waits for the response to the invoke() with an id, and returns it's status and payload. None if
+// it timed out.
+fn wait_invoke(receiver: &UdpReceiver, id: u64, timeout: Duration) -> Option<(RpcStatus, Vec<u8>)> {
+    wait_for(receiver, timeout, |res| {
+        if res.opcode() != OpCode::SandstormInvokeRpc {
+            return None;
+        }
+        res.parse_header::<InvokeResponse>().and_then(|p| {
+            let hdr = p.get_header();
+            match hdr.common_header.id() == id {
+                true => Some((hdr.common_header.status.clone(), p.get_payload().to_vec())),
+                false => None,
+            }
+        })
+    })
+}
+
+// Waits for the first response `pick` returns something for, dropping every other response
+// received in the meantime. None if none arrived within the timeout.
+fn wait_for<T, F>(receiver: &UdpReceiver, timeout: Duration, mut pick: F) -> Option<T>
+where
+    F: FnMut(&Response) -> Option<T>,
+{
+    let begin = Instant::now();
+    loop {
+        if begin.elapsed() > timeout {
+            return None;
+        }
+
+        let mut picked = None;
+        for res in receiver.recv_res().unwrap_or_else(Vec::new) {
+            if picked.is_none() {
+                picked = pick(&res);
+            }
+            receiver.recycle(res);
+        }
+
+        if picked.is_some() {
+            return picked;
+        }
+    }
+}
+
+// Sends out a latency_stats() as tenant 0 and waits for it's response, dropping responses to any
+// other request received in the meantime. Returns the entries on the response, the number of
+// them, and the rate of the server's clock.
+fn fetch_latency_stats(
+    sender: &UdpSender,
+    receiver: &UdpReceiver,
+    query: u8,
+    opcode: u8,
+    name: &[u8],
+    timeout: Duration,
+) -> io::Result<(Vec<u8>, u32, u64)> {
+    let id = sender.next_id();
+    sender.send_latency_stats(0, query, opcode, name, id, 0);
+
+    let begin = Instant::now();
+    loop {
+        if begin.elapsed() > timeout {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "Timed out on latency_stats",
+            ));
+        }
+
+        let mut stats = None;
+        for res in receiver.recv_res().unwrap_or_else(Vec::new) {
+            if stats.is_none() && res.opcode() == OpCode::SandstormLatencyStatsRpc {
+                stats = res.parse_header::<LatencyStatsResponse>().and_then(|p| {
+                    let hdr = p.get_header();
+                    let (r_id, status, num, hz) = (
+                        hdr.common_header.id(),
+                        hdr.common_header.status.clone(),
+                        hdr.num_entries(),
+                        hdr.cycles_per_second(),
+                    );
+                    if r_id != id {
+                        return None;
+                    }
+                    match status {
+                        RpcStatus::StatusOk => Some(Ok((p.get_payload().to_vec(), num, hz))),
+                        status => Some(Err(format!("latency_stats refused with {:?}", status))),
+                    }
+                });
+            }
+            receiver.recycle(res);
+        }
+
+        if let Some(stats) = stats {
+            return stats.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e));
+        }
+    }
+}
+
+// Returns an error for a latency_stats() response whose entries could not be parsed.
+fn malformed_latency_stats() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "Malformed latency_stats response",
+    )
+}
+
+/// Asks the server how long it took to respond to each opcode, merged across it's cores.
+///
+/// # Arguments
+///
+/// * `sender`:   The sender the latency_stats() is sent out on.
+/// * `receiver`: The receiver paired with `sender`.
+/// * `timeout`:  How long to wait for the response.
+///
+/// # Return
+///
+/// The percentiles of every opcode the server responded to, in nanoseconds. An error if the
+/// response timed out, was malformed, or was refused.
+pub fn latency_stats(
+    sender: &UdpSender,
+    receiver: &UdpReceiver,
+    timeout: Duration,
+) -> io::Result<Vec<(u8, LatencySummary)>> {
+    let (entries, num, _) =
+        fetch_latency_stats(sender, receiver, LATENCY_STATS_OPCODES, 0, &[], timeout)?;
+    rpc::parse_latency_summaries(&entries, num).ok_or_else(malformed_latency_stats)
+}
+
+/// Asks the server how long it took to respond to invocations of each extension, merged across
+/// it's cores. Refer to latency_stats().
+///
+/// # Return
+///
+/// The percentiles of the extensions invoked most, in nanoseconds.
+pub fn ext_latency_stats(
+    sender: &UdpSender,
+    receiver: &UdpReceiver,
+    timeout: Duration,
+) -> io::Result<Vec<(Vec<u8>, LatencySummary)>> {
+    let (entries, num, _) =
+        fetch_latency_stats(sender, receiver, LATENCY_STATS_EXTENSIONS, 0, &[], timeout)?;
+    rpc::parse_ext_latencies(&entries, num).ok_or_else(malformed_latency_stats)
+}
+
+/// Asks the server for the histogram of the time it took to respond to an opcode, or to
+/// invocations of an extension, merged across it's cores. Refer to latency_stats().
+///
+/// # Arguments
+///
+/// * `opcode`: The opcode whose histogram is asked for.
+/// * `name`:   The extension whose histogram is asked for. Empty for an opcode's histogram.
+///
+/// # Return
+///
+/// The histogram, in nanoseconds.
+pub fn latency_histogram(
+    sender: &UdpSender,
+    receiver: &UdpReceiver,
+    opcode: OpCode,
+    name: &[u8],
+    timeout: Duration,
+) -> io::Result<Histogram> {
+    let (entries, num, hz) = fetch_latency_stats(
+        sender,
+        receiver,
+        LATENCY_STATS_HISTOGRAM,
+        opcode as u8,
+        name,
+        timeout,
+    )?;
+    rpc::parse_latency_histogram(&entries, num)
+        .map(|hist| hist.to_nanos(hz))
+        .ok_or_else(malformed_latency_stats)
+}
+
+/// Reads a key with a get(), retrying it while the key, table or tenant is not found, until it
+/// runs out of attempts or what it named is destroyed. Refer to retry.rs. Responses to any other
+/// request received in the meantime are dropped.
+///
+/// # Arguments
+///
+/// * `sender`:   The sender the get() is sent out on.
+/// * `receiver`: The receiver paired with `sender`.
+/// * `tenant`:   Id of the tenant owning the table.
+/// * `table`:    Id of the table to read the key off.
+/// * `key`:      The key to read.
+/// * `budget`:   The number of attempts the get() gets, including the first.
+/// * `timeout`:  How long to wait for each response.
+/// * `stats`:    Counts retries, and gets given up on.
+///
+/// # Return
+///
+/// What became of the get(), along with the status and value on the response to it's last
+/// attempt. An error if a response timed out.
+pub fn get_retried(
+    sender: &UdpSender,
+    receiver: &UdpReceiver,
+    tenant: u32,
+    table: u64,
+    key: &[u8],
+    budget: u32,
+    timeout: Duration,
+    stats: &mut RetryStats,
+) -> io::Result<(Verdict, RpcStatus, Vec<u8>)> {
+    let mut retries = Retries::new(budget);
+    loop {
+        let id = sender.next_id();
+        sender.send_get(tenant, table, key, id, 0);
+
+        // Wait for the response to this attempt.
+        let begin = Instant::now();
+        let mut got = None;
+        while got.is_none() {
+            if begin.elapsed() > timeout {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "Timed out on get"));
+            }
+
+            for res in receiver.recv_res().unwrap_or_else(Vec::new) {
+                if got.is_none() && res.opcode() == OpCode::SandstormGetRpc {
+                    got = res.parse_header::<GetResponse>().and_then(|p| {
+                        let hdr = p.get_header();
+                        if hdr.common_header.id() != id {
+                            return None;
+                        }
+                        let len = hdr.value_length() as usize;
+                        let value = p.get_payload().get(..len).unwrap_or(&[]).to_vec();
+                        let epoch = match hdr.common_header.epoch() {
+                            0 => None,
+                            epoch => Some(epoch),
+                        };
+                        Some((hdr.common_header.status.clone(), epoch, value))
+                    });
+                }
+                receiver.recycle(res);
+            }
+        }
+
+        let (status, epoch, value) = got.unwrap();
+        match retries.observe(&status, epoch, stats) {
+            Verdict::Retry => continue,
+            verdict => return Ok((verdict, status, value)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use db::cycles;
+    use db::evict::EvictPolicy;
+    use db::histogram::LogHistogram;
+    use db::mailbox::Mailbox;
+    use db::master::Master;
+    use db::presize::Presize;
+    use db::replay::{Admit, Client, ReplayTable};
+    use db::trailer::{Trace, TrailerWriter, TRAILER_TRACE};
+    use dedup::Dedup;
+
+    use sandstorm::ext::Provenance;
+
+    // Header of a response to a request in `req`, with a status of StatusOk.
+    fn respond(req: &[u8], payload: &[u8]) -> Vec<u8> {
+        let hdr: &RpcRequestHeader = unsafe { &*(req.as_ptr() as *const RpcRequestHeader) };
+        let (tenant, id, stamp) = (hdr.tenant(), hdr.id(), hdr.stamp());
+
+        let mut res = match req[1] {
+            1 => {
+                let mut r = GetResponse::new(id, stamp, OpCode::SandstormGetRpc, tenant);
+                r.set_value_length(payload.len() as u32);
+                encode(r, &[])
+            }
+            2 => encode(
+                PutResponse::new(id, stamp, OpCode::SandstormPutRpc, tenant),
+                &[],
+            ),
+            _ => encode(
+                InvokeResponse::new(id, stamp, OpCode::SandstormInvokeRpc, tenant),
+                &[],
+            ),
+        };
+        res.extend_from_slice(payload);
+        res
+    }
+
+    // A loopback stand-in for the server. Stores puts, answers gets from what was stored, and
+    // echoes back the arguments to invokes. Runs until `n` requests have been handled.
+    fn serve(socket: UdpSocket, n: usize) {
+        let mut store: HashMap<Vec<u8>, Vec<u8>> = HashMap::new();
+        let mut buf = vec![0; MAX_RESPONSE_LEN];
+        for _ in 0..n {
+            let (len, src) = socket.recv_from(&mut buf).expect("Server recv failed");
+            let req = &buf[..len];
+            let res = match req[1] {
+                1 => {
+                    let key = &req[size_of::<GetRequest>()..];
+                    let val = store.get(key).cloned().unwrap_or(vec![]);
+                    respond(req, &val)
+                }
+                2 => {
+                    let hdr: &PutRequest = unsafe { &*(req.as_ptr() as *const PutRequest) };
+                    let key_len = hdr.key_length() as usize;
+                    let (key, val) = req[size_of::<PutRequest>()..].split_at(key_len);
+                    store.insert(key.to_vec(), val.to_vec());
+                    respond(req, &[])
+                }
+                _ => respond(req, &req[size_of::<InvokeRequest>()..]),
+            };
+            socket.send_to(&res, src).expect("Server send failed");
+        }
+    }
+
+    // A loopback stand-in for the server that only answers list_extensions() requests. Tenant 1
+    // loaded "auth" and "get", and shares "get" with tenant 2; tenant 3 has nothing loaded.
+    // Listings are paginated on a budget of `budget` bytes.
+    fn serve_listing(socket: UdpSocket, n: usize, budget: usize) {
+        let info = |name: &str, invocations, provenance| ExtensionInfo {
+            name: name.as_bytes().to_vec(),
+            version: 0,
+            loaded: 1_500_000_000,
+            invocations: invocations,
+            provenance: provenance,
+            schema: None,
+            id: 0,
+        };
+        let mut listings = HashMap::new();
+        listings.insert(
+            1,
+            vec![
+                info("auth", 3, Provenance::Private),
+                info("get", 10, Provenance::Private),
+            ],
+        );
+        listings.insert(2, vec![info("get", 10, Provenance::Shared(1))]);
+
+        let mut buf = vec![0; MAX_RESPONSE_LEN];
+        for _ in 0..n {
+            let (len, src) = socket.recv_from(&mut buf).expect("Server recv failed");
+            let req: &ListExtRequest = unsafe { &*(buf[..len].as_ptr() as *const ListExtRequest) };
+            let (tenant, start) = (req.common_header.tenant(), req.start());
+            let (id, stamp) = (req.common_header.id(), req.common_header.stamp());
+
+            let list = listings.get(&tenant).cloned().unwrap_or(vec![]);
+            let (entries, num, next) = rpc::encode_ext_listing(&list, start as usize, budget);
+            let mut hdr = ListExtResponse::new(id, stamp, tenant);
+            hdr.set_num_entries(num);
+            hdr.set_next(next);
+
+            let res = encode(hdr, &[&entries]);
+            socket.send_to(&res, src).expect("Server send failed");
+        }
+    }
+
+    fn config(client_port: u16, server_port: u16) -> config::ClientConfig {
+        let mut config = config::ClientConfig::default();
+        config.ip_address = String::from("127.0.0.1");
+        config.server_ip_address = String::from("127.0.0.1");
+        config.udp_client_port = client_port;
+        config.udp_server_port = server_port;
+        config
+    }
+
+    // Waits for a single response to arrive.
+    fn wait(receiver: &UdpReceiver) -> Response {
+        let start = Instant::now();
+        loop {
+            if let Some(mut r) = receiver.recv_res() {
+                assert_eq!(1, r.len());
+                return r.pop().unwrap();
+            }
+            assert!(start.elapsed() < Duration::from_secs(5), "Timed out on response");
+        }
+    }
+
+    #[test]
+    fn test_udp_loopback() {
+        let n = 1000;
+        let server = UdpSocket::bind("127.0.0.1:0").expect("Failed to bind server");
+        let server_port = server.local_addr().unwrap().port();
+        let handle = thread::spawn(move || serve(server, 3 * n));
+
+        let (sender, receiver) =
+            udp_pipeline(&config(0, server_port), 0, 1).expect("Failed to setup udp pipeline");
+
+        for i in 0..n as u64 {
+            let key = [i as u8, (i >> 8) as u8, 0, 0];
+            let val = [i as u8; 100];
+
+            let id = sender.next_id();
+            sender.send_put(1, 1, &key, &val, id, i);
+            let res = wait(&receiver);
+            assert_eq!(OpCode::SandstormPutRpc, res.opcode());
+            {
+                let p = res.parse_header::<PutResponse>().expect("Bad put response");
+                assert_eq!(RpcStatus::StatusOk, p.get_header().common_header.status);
+                let (r_id, stamp) = (
+                    p.get_header().common_header.id(),
+                    p.get_header().common_header.stamp(),
+                );
+                assert_eq!(id, r_id);
+                assert_eq!(i, stamp);
+            }
+            receiver.recycle(res);
+
+            let id = sender.next_id();
+            sender.send_get(1, 1, &key, id, i);
+            let res = wait(&receiver);
+            assert_eq!(OpCode::SandstormGetRpc, res.opcode());
+            {
+                let p = res.parse_header::<GetResponse>().expect("Bad get response");
+                assert_eq!(RpcStatus::StatusOk, p.get_header().common_header.status);
+                let r_id = p.get_header().common_header.id();
+                assert_eq!(id, r_id);
+                assert_eq!(&val[..], p.get_payload());
+            }
+            receiver.recycle(res);
+
+            let mut payload = b"get".to_vec();
+            payload.extend_from_slice(&key);
+            let id = sender.next_id();
+            sender.send_invoke(1, 3, &payload, id, i);
+            let res = wait(&receiver);
+            assert_eq!(OpCode::SandstormInvokeRpc, res.opcode());
+            {
+                let p = res.parse_header::<InvokeResponse>().expect("Bad invoke response");
+                assert_eq!(RpcStatus::StatusOk, p.get_header().common_header.status);
+                let r_id = p.get_header().common_header.id();
+                assert_eq!(id, r_id);
+                assert_eq!(&payload[..], p.get_payload());
+            }
+            receiver.recycle(res);
+        }
+
+        handle.join().expect("Server thread failed");
+    }
+
+    // Tests that two requests sent out with the same time-stamp are told apart by their ids.
+    #[test]
+    fn test_udp_same_stamp() {
+        let server = UdpSocket::bind("127.0.0.1:0").expect("Failed to bind server");
+        let server_port = server.local_addr().unwrap().port();
+        let handle = thread::spawn(move || serve(server, 4));
+
+        let (sender, receiver) =
+            udp_pipeline(&config(0, server_port), 1, 1).expect("Failed to setup udp pipeline");
+
+        let (a, b) = ([1, 0, 0, 0], [2, 0, 0, 0]);
+        sender.send_put(1, 1, &a, &[1; 8], sender.next_id(), 0);
+        receiver.recycle(wait(&receiver));
+        sender.send_put(1, 1, &b, &[2; 8], sender.next_id(), 0);
+        receiver.recycle(wait(&receiver));
+
+        // Both gets go out in the same cycle, and responses are matched on the id alone.
+        let mut expected = HashMap::new();
+        let (id_a, id_b) = (sender.next_id(), sender.next_id());
+        expected.insert(id_a, vec![1; 8]);
+        expected.insert(id_b, vec![2; 8]);
+        sender.send_get(1, 1, &a, id_a, 42);
+        sender.send_get(1, 1, &b, id_b, 42);
+
+        for _ in 0..2 {
+            let res = wait(&receiver);
+            {
+                let p = res.parse_header::<GetResponse>().expect("Bad get response");
+                let (id, stamp) = (
+                    p.get_header().common_header.id(),
+                    p.get_header().common_header.stamp(),
+                );
+                assert_eq!(42, stamp);
+                assert_eq!(1, ::ids::pipeline(id));
+                let val = expected.remove(&id).expect("Response with unknown id");
+                assert_eq!(&val[..], p.get_payload());
+            }
+            receiver.recycle(res);
+        }
+        assert!(expected.is_empty());
+
+        handle.join().expect("Server thread failed");
+    }
+
+    // Tests that a get() response carrying less of the value than it's header claims is counted
+    // as a length mismatch, and that consistent responses are not.
+    #[test]
+    fn test_udp_length_mismatch() {
+        let server = UdpSocket::bind("127.0.0.1:0").expect("Failed to bind server");
+        let server_port = server.local_addr().unwrap().port();
+        let handle = thread::spawn(move || {
+            let mut buf = vec![0; MAX_RESPONSE_LEN];
+            for i in 0..3 {
+                let (len, src) = server.recv_from(&mut buf).expect("Server recv failed");
+                let mut res = respond(&buf[..len], &[7; 100]);
+                // The second response lost the tail of it's value.
+                if i == 1 {
+                    let len = res.len() - 10;
+                    res.truncate(len);
+                }
+                server.send_to(&res, src).expect("Server send failed");
+            }
+        });
+
+        let (sender, receiver) =
+            udp_pipeline(&config(0, server_port), 3, 1).expect("Failed to setup udp pipeline");
+
+        for expected in [(true, 0), (false, 1), (true, 1)].iter() {
+            sender.send_get(1, 1, &[1, 0, 0, 0], sender.next_id(), 0);
+            let res = wait(&receiver);
+            assert_eq!(expected.0, res.length_ok());
+            assert_eq!(expected.1, receiver.length_mismatches());
+            receiver.recycle(res);
+        }
+
+        handle.join().expect("Server thread failed");
+    }
+
+    // Tests that duplicate responses within the window are dropped before they touch the
+    // outstanding count or latencies, and that one from far outside the window slips through.
+    #[test]
+    fn test_udp_duplicates() {
+        let n = 64u64;
+        let server = UdpSocket::bind("127.0.0.1:0").expect("Failed to bind server");
+        let server_port = server.local_addr().unwrap().port();
+        let handle = thread::spawn(move || {
+            let mut buf = vec![0; MAX_RESPONSE_LEN];
+            let mut sent = Vec::new();
+            let mut client = None;
+            for i in 0..n {
+                let (len, src) = server.recv_from(&mut buf).expect("Server recv failed");
+                let res = respond(&buf[..len], &[]);
+                server.send_to(&res, src).expect("Server send failed");
+                // Every fourth response goes out twice in a row.
+                if i % 4 == 1 {
+                    server.send_to(&res, src).expect("Server send failed");
+                }
+                sent.push(res);
+                client = Some(src);
+            }
+
+            // Replay a recent response, and then the very first one.
+            let src = client.unwrap();
+            server
+                .send_to(&sent[n as usize - 2], src)
+                .expect("Server send failed");
+            server.send_to(&sent[0], src).expect("Server send failed");
+        });
+
+        let (sender, receiver) =
+            udp_pipeline(&config(0, server_port), 4, 1).expect("Failed to setup udp pipeline");
+        let mut dedup = Dedup::new(8);
+        let (mut outstanding, mut received) = (0u64, 0u64);
+        let mut latencies = Vec::new();
+
+        // A closed loop with a single request outstanding.
+        let start = Instant::now();
+        for i in 0..(n + 1) {
+            if i < n {
+                sender.send_get(1, 1, &[i as u8, 0, 0, 0], sender.next_id(), i);
+                outstanding += 1;
+            }
+
+            // After the last request, wait for the two replayed responses.
+            while (i < n && outstanding > 0) || (i == n && received < n + n / 4 + 2) {
+                assert!(
+                    start.elapsed() < Duration::from_secs(5),
+                    "Timed out on response"
+                );
+                for res in receiver.recv_res().unwrap_or_else(Vec::new) {
+                    received += 1;
+                    let (id, stamp) = {
+                        let p = res.parse_header::<RpcResponseHeader>().unwrap();
+                        (p.get_header().id(), p.get_header().stamp())
+                    };
+                    if dedup.complete(id, res.opcode()) {
+                        outstanding = outstanding.saturating_sub(1);
+                        latencies.push(stamp);
+                    }
+                    receiver.recycle(res);
+                }
+            }
+        }
+
+        // The replayed first response is the documented false negative: it came back more
+        // than window() + window() / 3 completions after the original.
+        assert!(n as usize > dedup.window() + dedup.window() / 3);
+        assert_eq!(n + 1, dedup.completed());
+        assert_eq!(n / 4 + 1, dedup.duplicates());
+        assert_eq!(vec![(OpCode::SandstormGetRpc, n / 4 + 1)], dedup.counts());
+
+        let mut expected: Vec<u64> = (0..n).collect();
+        expected.push(0);
+        assert_eq!(expected, latencies);
+
+        handle.join().expect("Server thread failed");
+    }
+
+    // Tests that a retransmitted increment is answered from the server's replay table instead of
+    // being executed again, and that a retransmit evicted from the table is executed again.
+    #[test]
+    fn test_udp_replay() {
+        // The stand-in runs every invoke as an increment of a counter, and loses the response
+        // to the first request it sees. Time advances one tick per request.
+        let server = UdpSocket::bind("127.0.0.1:0").expect("Failed to bind server");
+        let server_port = server.local_addr().unwrap().port();
+        let handle = thread::spawn(move || {
+            let mut table = ReplayTable::new(2, 1000, 256);
+            let mut counter = 0u64;
+            let mut buf = vec![0; MAX_RESPONSE_LEN];
+            for i in 0..5 {
+                let (len, src) = server.recv_from(&mut buf).expect("Server recv failed");
+                let req = &buf[..len];
+                let id = unsafe { &*(req.as_ptr() as *const RpcRequestHeader) }.id();
+                let retry = req[size_of::<RpcRequestHeader>() - 1] & REQUEST_FLAG_RETRY != 0;
+                // Every request comes from the loopback address; the port is what tells clients
+                // apart.
+                let client = Client::Addr(0, src.port());
+                let res = match table.admit(client, id, retry, i) {
+                    Admit::Execute => {
+                        counter += 1;
+                        let val: [u8; 8] = unsafe { transmute(counter.to_le()) };
+                        let res = respond(req, &val);
+                        table.complete(client, id, &res);
+                        res
+                    }
+                    Admit::Replay(res) => res,
+                    Admit::InFlight => continue,
+                };
+                if i > 0 {
+                    server.send_to(&res, src).expect("Server send failed");
+                }
+            }
+            (counter, table.stats())
+        });
+
+        let (sender, receiver) =
+            udp_pipeline(&config(0, server_port), 0, 1).expect("Failed to setup udp pipeline");
+        let value = |res: &Response| -> (u64, u64) {
+            let p = res.parse_header::<InvokeResponse>().unwrap();
+            let mut val = [0u8; 8];
+            val.copy_from_slice(&p.get_payload()[..8]);
+            (
+                p.get_header().common_header.id(),
+                u64::from_le(unsafe { transmute(val) }),
+            )
+        };
+
+        // The response to the first increment is lost, so it's retransmitted.
+        let first = sender.next_id();
+        let mut req = encode_invoke(1, 4, b"incr", first, 0);
+        sender.send_encoded(1, &req);
+        mark_retry(&mut req);
+        sender.send_encoded(1, &req);
+        assert_eq!((first, 1), value(&wait(&receiver)));
+
+        // Two more increments push the first out of the table, so it runs again if retried.
+        for i in 2..4 {
+            let id = sender.next_id();
+            sender.send_encoded(1, &encode_invoke(1, 4, b"incr", id, 0));
+            assert_eq!((id, i), value(&wait(&receiver)));
+        }
+        sender.send_encoded(1, &req);
+        assert_eq!((first, 4), value(&wait(&receiver)));
+
+        let (counter, stats) = handle.join().expect("Server thread failed");
+        assert_eq!(4, counter);
+        assert_eq!(4, stats.executed);
+        assert_eq!(1, stats.replayed);
+        assert_eq!(1, stats.misses);
+        assert_eq!(2, stats.evicted);
+    }
+
+    // A loopback stand-in for the server that answers get() requests off `master`, with the
+    // epochs the server would stamp onto them. Before handling request `i`, runs `between(i,
+    // &master)`, so that tables can be truncated or dropped, and tenants removed, in between
+    // attempts. Runs until `n` requests have been handled.
+    fn serve_epochs<F>(socket: UdpSocket, n: usize, master: Master, mut between: F) -> Master
+    where
+        F: FnMut(usize, &Master),
+    {
+        epoch::install(Arc::clone(master.epochs()));
+        let mut buf = vec![0; MAX_RESPONSE_LEN];
+        for i in 0..n {
+            let (len, src) = socket.recv_from(&mut buf).expect("Server recv failed");
+            between(i, &master);
+
+            let req: &GetRequest = unsafe { &*(buf[..len].as_ptr() as *const GetRequest) };
+            let (tenant, table) = (req.common_header.tenant(), req.table_id());
+            let key = &buf[size_of::<GetRequest>()..len];
+            let found = master
+                .get_tenant(tenant)
+                .ok_or(RpcStatus::StatusTenantDoesNotExist)
+                .and_then(|tenant| tenant.readable_native_table(table))
+                .and_then(|table| table.get(key).ok_or(RpcStatus::StatusObjectDoesNotExist));
+
+            let id = req.common_header.id();
+            let mut hdr = GetResponse::new(id, 0, OpCode::SandstormGetRpc, tenant);
+            let value = match found {
+                Ok(entry) => {
+                    hdr.set_value_length(entry.value.len() as u32);
+                    entry.value.to_vec()
+                }
+
+                Err(status) => {
+                    let epoch = epoch::lookup(&status, tenant).unwrap_or(0);
+                    hdr.common_header.set_epoch(epoch);
+                    hdr.common_header.status = status;
+                    vec![]
+                }
+            };
+            socket
+                .send_to(&encode(hdr, &[&value]), src)
+                .expect("Server send failed");
+        }
+        master
+    }
+
+    // Tests that a get() of a key that is missing is retried until it runs out of attempts, as
+    // it may yet be written, and that one of a key that is there is done on the first attempt.
+    #[test]
+    fn test_udp_get_retried_missing() {
+        let server = UdpSocket::bind("127.0.0.1:0").expect("Failed to bind server");
+        let server_port = server.local_addr().unwrap().port();
+        let handle = thread::spawn(move || {
+            let master = Master::new();
+            master.fill_test(1, 1, 1);
+            serve_epochs(server, 4, master, |_, _| {})
+        });
+
+        let (sender, receiver) =
+            udp_pipeline(&config(0, server_port), 6, 1).expect("Failed to setup udp pipeline");
+        let timeout = Duration::from_secs(5);
+        let mut stats = RetryStats::default();
+        let mut get = |key: &[u8], stats: &mut RetryStats| {
+            let (verdict, status, value) =
+                get_retried(&sender, &receiver, 1, 1, key, 3, timeout, stats)
+                    .expect("Failed to get");
+            (verdict, status, value.is_empty())
+        };
+        let mut key = vec![0; 30];
+        assert_eq!(
+            (
+                Verdict::Exhausted,
+                RpcStatus::StatusObjectDoesNotExist,
+                true
+            ),
+            get(&key, &mut stats)
+        );
+        key[0] = 1;
+        assert_eq!(
+            (Verdict::Done, RpcStatus::StatusOk, false),
+            get(&key, &mut stats)
+        );
+        assert_eq!(
+            RetryStats {
+                retried: 2,
+                exhausted: 1,
+                invalidated: 0,
+            },
+            stats
+        );
+        handle.join().expect("Server thread failed");
+    }
+
+    // Tests that a get() whose table is truncated between two attempts is given up on as
+    // invalidated, instead of being retried until it runs out of attempts, and that a key that
+    // stays missing after the truncate is retried as before.
+    #[test]
+    fn test_udp_get_retried_truncated() {
+        // Requests, in the order the stand-in sees them:
+        // 0: key missing. Table 1 is truncated before 1: key missing, at a later epoch.
+        // 2, 3, 4: the truncated key missing, at the same epoch.
+        let server = UdpSocket::bind("127.0.0.1:0").expect("Failed to bind server");
+        let server_port = server.local_addr().unwrap().port();
+        let handle = thread::spawn(move || {
+            let master = Master::new();
+            master.fill_test(1, 1, 1);
+            serve_epochs(server, 5, master, |i, master| {
+                if i == 1 {
+                    assert_eq!(RpcStatus::StatusOk, master.truncate_table(1, 1));
+                }
+            })
+        });
+
+        let (sender, receiver) =
+            udp_pipeline(&config(0, server_port), 6, 1).expect("Failed to setup udp pipeline");
+        let timeout = Duration::from_secs(5);
+        let mut stats = RetryStats::default();
+        let mut get = |key: &[u8], stats: &mut RetryStats| {
+            let (verdict, status, value) =
+                get_retried(&sender, &receiver, 1, 1, key, 3, timeout, stats)
+                    .expect("Failed to get");
+            assert!(value.is_empty());
+            (verdict, status)
+        };
+        let missing = RpcStatus::StatusObjectDoesNotExist;
+        let mut key = vec![0; 30];
+        assert_eq!(
+            (Verdict::Invalidated, missing.clone()),
+            get(&key, &mut stats)
+        );
+        key[0] = 1;
+        assert_eq!((Verdict::Exhausted, missing), get(&key, &mut stats));
+        assert_eq!(
+            RetryStats {
+                retried: 3,
+                exhausted: 1,
+                invalidated: 1,
+            },
+            stats
+        );
+
+        // The table and it's tenant moved to the epoch the table was truncated at.
+        let master = handle.join().expect("Server thread failed");
+        let tenant = master.get_tenant(1).unwrap();
+        assert_eq!(
+            (2, 2),
+            (tenant.epoch(), tenant.get_table(1).unwrap().epoch())
+        );
+    }
+
+    // Tests that a get() is given up on as invalidated as soon as the table or tenant it named is
+    // dropped or removed between attempts, and that a tenant that stays missing is retried like
+    // a key.
+    #[test]
+    fn test_udp_get_retried_invalidated() {
+        // Requests, in the order the stand-in sees them:
+        // 0: key missing off table 1. Table 1 is dropped before 1: table missing.
+        // 2: table 2 missing. Table 3 is dropped before 3: table missing, at a later epoch.
+        // 4: table 2 missing. Tenant 1 is removed before 5: tenant missing.
+        // 6, 7, 8: tenant missing, at the same epoch.
+        let server = UdpSocket::bind("127.0.0.1:0").expect("Failed to bind server");
+        let server_port = server.local_addr().unwrap().port();
+        let handle = thread::spawn(move || {
+            let master = Master::new();
+            master.fill_test(1, 1, 0);
+            master.get_tenant(1).unwrap().create_table(3);
+            serve_epochs(server, 9, master, |i, master| match i {
+                1 => assert_eq!(RpcStatus::StatusOk, master.drop_table(1, 1)),
+                3 => assert_eq!(RpcStatus::StatusOk, master.drop_table(1, 3)),
+                5 => assert!(master.remove_tenant(1).is_some()),
+                _ => {}
+            })
+        });
+
+        let (sender, receiver) =
+            udp_pipeline(&config(0, server_port), 6, 1).expect("Failed to setup udp pipeline");
+        let timeout = Duration::from_secs(5);
+        let mut stats = RetryStats::default();
+        let mut get = |table, stats: &mut RetryStats| {
+            let (verdict, status, value) =
+                get_retried(&sender, &receiver, 1, table, b"key", 3, timeout, stats)
+                    .expect("Failed to get");
+            assert!(value.is_empty());
+            (verdict, status)
+        };
+        let (table, tenant) = (
+            RpcStatus::StatusTableDoesNotExist,
+            RpcStatus::StatusTenantDoesNotExist,
+        );
+        assert_eq!((Verdict::Invalidated, table.clone()), get(1, &mut stats));
+        assert_eq!((Verdict::Invalidated, table), get(2, &mut stats));
+        assert_eq!((Verdict::Invalidated, tenant.clone()), get(2, &mut stats));
+        assert_eq!((Verdict::Exhausted, tenant), get(2, &mut stats));
+        assert_eq!(
+            RetryStats {
+                retried: 5,
+                exhausted: 1,
+                invalidated: 3,
+            },
+            stats
+        );
+
+        // The server's tenant namespace moved to the epoch the tenant was removed at.
+        let master = handle.join().expect("Server thread failed");
+        assert_eq!(4, master.epochs().server());
+        assert_eq!(None, master.epochs().tenant(1));
+    }
+
+    // Tests that each tenant's listing holds the extensions loaded for and shared with it, and
+    // that a listing spanning several responses is put back together.
+    #[test]
+    fn test_udp_list_extensions() {
+        // A budget of 40 bytes fits one 36 or 37 byte entry per response, so tenant 1's listing
+        // takes two round trips.
+        let server = UdpSocket::bind("127.0.0.1:0").expect("Failed to bind server");
+        let server_port = server.local_addr().unwrap().port();
+        let handle = thread::spawn(move || serve_listing(server, 4, 40));
+
+        let (sender, receiver) =
+            udp_pipeline(&config(0, server_port), 2, 1).expect("Failed to setup udp pipeline");
+        let timeout = Duration::from_secs(5);
+        let names = |tenant| -> Vec<(Vec<u8>, Provenance)> {
+            list_extensions(&sender, &receiver, tenant, timeout)
+                .expect("Failed to list extensions")
+                .into_iter()
+                .map(|e| (e.name, e.provenance))
+                .collect()
+        };
+
+        assert_eq!(
+            vec![
+                (b"auth".to_vec(), Provenance::Private),
+                (b"get".to_vec(), Provenance::Private),
+            ],
+            names(1)
+        );
+        assert_eq!(vec![(b"get".to_vec(), Provenance::Shared(1))], names(2));
+        assert!(names(3).is_empty());
+
+        handle.join().expect("Server thread failed");
+    }
+
+    // A loopback stand-in for the server that only answers echo() requests, advertising a mask
+    // of opcodes. Runs until `n` requests have been handled.
+    fn serve_echo(socket: UdpSocket, n: usize, opcodes: u64) {
+        let mut buf = vec![0; MAX_RESPONSE_LEN];
+        for _ in 0..n {
+            let (len, src) = socket.recv_from(&mut buf).expect("Server recv failed");
+            let req: &EchoRequest = unsafe { &*(buf[..len].as_ptr() as *const EchoRequest) };
+            let (tenant, id) = (req.common_header.tenant(), req.common_header.id());
+
+            let res = encode(EchoResponse::new(id, 0, tenant, 1, opcodes, 64, 1024), &[]);
+            socket.send_to(&res, src).expect("Server send failed");
+        }
+    }
+
+    // Tests that a sender redirected to a backup sends everything there from then on, and that
+    // the backup's echo() is what comes back.
+    #[test]
+    fn test_udp_redirect() {
+        let primary = UdpSocket::bind("127.0.0.1:0").expect("Failed to bind primary");
+        let backup = UdpSocket::bind("127.0.0.1:0").expect("Failed to bind backup");
+        let primary_port = primary.local_addr().unwrap().port();
+        let backup_port = backup.local_addr().unwrap().port();
+        let primary = thread::spawn(move || serve_echo(primary, 1, 0x3));
+        let backup = thread::spawn(move || serve_echo(backup, 2, 0x5));
+
+        let (sender, receiver) =
+            udp_pipeline(&config(0, primary_port), 5, 1).expect("Failed to setup udp pipeline");
+        let timeout = Duration::from_secs(5);
+        assert_eq!(
+            (0x3, (64, 1024)),
+            echo(&sender, &receiver, 1, timeout).expect("Primary did not echo")
+        );
+
+        sender.redirect(Ipv4Addr::new(127, 0, 0, 1), backup_port);
+        for _ in 0..2 {
+            let (served, _) = echo(&sender, &receiver, 1, timeout).expect("Backup did not echo");
+            assert_eq!(0x5, served);
+        }
+
+        primary.join().expect("Primary thread failed");
+        backup.join().expect("Backup thread failed");
+    }
+
+    // A loopback stand-in for the server that answers core_stats() requests with `cores`, and
+    // then refuses them. Runs until `n` requests have been handled.
+    fn serve_core_stats(socket: UdpSocket, n: usize, cores: Vec<CoreSummary>) {
+        let mut buf = vec![0; MAX_RESPONSE_LEN];
+        for i in 0..n {
+            let (len, src) = socket.recv_from(&mut buf).expect("Server recv failed");
+            let req: &CoreStatsRequest =
+                unsafe { &*(buf[..len].as_ptr() as *const CoreStatsRequest) };
+            let (tenant, id) = (req.common_header.tenant(), req.common_header.id());
+
+            let mut hdr = CoreStatsResponse::new(id, 0, tenant);
+            let payload = match i {
+                0 => {
+                    hdr.set_num_cores(cores.len() as u32);
+                    hdr.set_user_us(1500);
+                    hdr.set_system_us(20);
+                    rpc::encode_core_stats(&cores)
+                }
+                _ => {
+                    hdr.common_header.status = RpcStatus::StatusInvalidOperation;
+                    vec![]
+                }
+            };
+            socket
+                .send_to(&encode(hdr, &[&payload]), src)
+                .expect("Server send failed");
+        }
+    }
+
+    // Tests that the backoff counters and CPU time come back off a core_stats() response, and
+    // that a refused request is an error.
+    #[test]
+    fn test_udp_core_stats() {
+        let mut cores = vec![CoreSummary::default(), CoreSummary::default()];
+        cores[1].core = 4;
+        cores[1].tiers[1].pauses = 9;
+        cores[1].tiers[1].nanos = 90_000;
+
+        let server = UdpSocket::bind("127.0.0.1:0").expect("Failed to bind server");
+        let server_port = server.local_addr().unwrap().port();
+        let served = cores.clone();
+        let handle = thread::spawn(move || serve_core_stats(server, 2, served));
+
+        let (sender, receiver) =
+            udp_pipeline(&config(0, server_port), 6, 1).expect("Failed to setup udp pipeline");
+        let timeout = Duration::from_secs(5);
+        assert_eq!(
+            (cores, (1500, 20)),
+            core_stats(&sender, &receiver, timeout).expect("Failed to fetch core stats")
+        );
+
+        let refused = core_stats(&sender, &receiver, timeout).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, refused.kind());
+
+        handle.join().expect("Server thread failed");
+    }
+
+    // A loopback stand-in for the server that creates tables on `master` for create_table()
+    // requests. Runs until `n` requests have been handled, and returns `master`.
+    fn serve_create_table(socket: UdpSocket, n: usize, master: Master) -> Master {
+        let mut buf = vec![0; MAX_RESPONSE_LEN];
+        for _ in 0..n {
+            let (len, src) = socket.recv_from(&mut buf).expect("Server recv failed");
+            let req: &CreateTableRequest =
+                unsafe { &*(buf[..len].as_ptr() as *const CreateTableRequest) };
+            let tenant = req.common_header.tenant();
+
+            let mut hdr = CreateTableResponse::new(req.common_header.id(), 0, tenant);
+            let cache = CacheTarget::from_wire(req.max_bytes(), req.max_objects(), req.evict)
+                .map_err(|_| RpcStatus::StatusMalformedRequest);
+            let created = cache.and_then(|cache| {
+                master.create_table_sized(tenant, req.table_id(), req.records(), cache)
+            });
+            match created {
+                Ok(slots) => hdr.set_slots(slots as u64),
+                Err(status) => hdr.common_header.status = status,
+            }
+            socket
+                .send_to(&encode(hdr, &[]), src)
+                .expect("Server send failed");
+        }
+        master
+    }
+
+    // Tests that create_table() returns the slots reserved on the table, and the status the
+    // server refused a table too large for it's cap with, and that a cache-mode table is created
+    // with the target it was asked for.
+    #[test]
+    fn test_udp_create_table() {
+        let server = UdpSocket::bind("127.0.0.1:0").expect("Failed to bind server");
+        let server_port = server.local_addr().unwrap().port();
+        let handle = thread::spawn(move || {
+            let mut master = Master::new();
+            master.set_presize(Presize::new(0.75, 1000));
+            master.fill_test(1, 1, 0);
+            serve_create_table(server, 4, master)
+        });
+
+        let (sender, receiver) =
+            udp_pipeline(&config(0, server_port), 6, 1).expect("Failed to setup udp pipeline");
+        let timeout = Duration::from_secs(5);
+        let create = |tenant, table, records, cache| {
+            create_table(&sender, &receiver, tenant, table, records, cache, timeout)
+                .expect("Failed to create table")
+        };
+        assert_eq!(
+            Err(RpcStatus::StatusTableTooLarge),
+            create(1, 2, 1001, None)
+        );
+        assert_eq!(Ok(2048), create(1, 2, 1000, None));
+        assert_eq!(
+            Err(RpcStatus::StatusTenantDoesNotExist),
+            create(2, 2, 10, None)
+        );
+
+        let target = CacheTarget::new(1 << 16, 0, EvictPolicy::Read);
+        assert_eq!(Ok(0), create(1, 3, 0, target));
+
+        let master = handle.join().expect("Server thread failed");
+        let tenant = master.get_tenant(1).expect("Tenant does not exist");
+        let table = tenant.get_table(3).expect("Table does not exist");
+        assert_eq!(target, table.cache_target());
+        assert_eq!(None, tenant.get_table(2).unwrap().cache_target());
+    }
+
+    // A loopback stand-in for the server that drops tables on `master` for drop_table()
+    // requests. Runs until `n` requests have been handled.
+    fn serve_drop_table(socket: UdpSocket, n: usize, master: Master) -> Master {
+        let mut buf = vec![0; MAX_RESPONSE_LEN];
+        for _ in 0..n {
+            let (len, src) = socket.recv_from(&mut buf).expect("Server recv failed");
+            let req: &DropTableRequest =
+                unsafe { &*(buf[..len].as_ptr() as *const DropTableRequest) };
+            let tenant = req.common_header.tenant();
+
+            let mut hdr = DropTableResponse::new(req.common_header.id(), 0, tenant);
+            hdr.common_header.status = master.drop_table(tenant, req.table_id());
+            socket
+                .send_to(&encode(hdr, &[]), src)
+                .expect("Server send failed");
+        }
+        master
+    }
+
+    // Tests that drop_table() drops a table once, and returns the status the server refused to
+    // drop a table that does not exist with.
+    #[test]
+    fn test_udp_drop_table() {
+        let server = UdpSocket::bind("127.0.0.1:0").expect("Failed to bind server");
+        let server_port = server.local_addr().unwrap().port();
+        let handle = thread::spawn(move || {
+            let master = Master::new();
+            master.fill_test(1, 1, 0);
+            serve_drop_table(server, 3, master)
+        });
+
+        let (sender, receiver) =
+            udp_pipeline(&config(0, server_port), 6, 1).expect("Failed to setup udp pipeline");
+        let timeout = Duration::from_secs(5);
+        let drop_one = |tenant, table| {
+            drop_table(&sender, &receiver, tenant, table, timeout).expect("Failed to drop table")
+        };
+        assert_eq!(RpcStatus::StatusOk, drop_one(1, 1));
+        assert_eq!(RpcStatus::StatusTableDoesNotExist, drop_one(1, 1));
+        assert_eq!(RpcStatus::StatusTenantDoesNotExist, drop_one(2, 1));
+
+        let master = handle.join().expect("Server thread failed");
+        assert!(master.resolve_table(1, 1).is_err());
+    }
+
+    // A loopback stand-in for the server that runs every invoke as an increment of a counter,
+    // storing the result in the tenant's mailbox on `master` if asked to, and answers
+    // fetch_result() requests off the mailbox. Requests numbered in `ignore` are dropped before
+    // they are run, and the responses to those numbered in `lose` are lost. Returns the counter
+    // and `master`.
+    fn serve_mailbox(
+        socket: UdpSocket,
+        n: usize,
+        ignore: Vec<usize>,
+        lose: Vec<usize>,
+        mut master: Master,
+    ) -> (u64, Master) {
+        let mailbox = Mailbox::new(10_000, 64, 1 << 16);
+        master.set_mailbox(mailbox);
+        let mut counter = 0u64;
+        let mut buf = vec![0; MAX_RESPONSE_LEN];
+        for i in 0..n {
+            let (len, src) = socket.recv_from(&mut buf).expect("Server recv failed");
+            let req = &buf[..len];
+            if ignore.contains(&i) {
+                continue;
+            }
+
+            let res = match req[1] == OpCode::SandstormFetchResultRpc as u8 {
+                true => {
+                    let req: &FetchResultRequest =
+                        unsafe { &*(req.as_ptr() as *const FetchResultRequest) };
+                    let tenant = req.common_header.tenant();
+                    let mut hdr = FetchResultResponse::new(req.common_header.id(), 0, tenant);
+                    match master.fetch_result(tenant, req.request_id()) {
+                        Ok((result, payload)) => {
+                            hdr.result = result;
+                            encode(hdr, &[&payload])
+                        }
+                        Err(status) => {
+                            hdr.common_header.status = status;
+                            encode(hdr, &[])
+                        }
+                    }
+                }
+
+                false => {
+                    counter += 1;
+                    let val: [u8; 8] = unsafe { transmute(counter.to_le()) };
+                    let hdr: &InvokeRequest = unsafe { &*(req.as_ptr() as *const InvokeRequest) };
+                    if hdr.flags & INVOKE_FLAG_STORE_RESULT != 0 {
+                        let tenant = master
+                            .get_tenant(hdr.common_header.tenant())
+                            .expect("Tenant does not exist");
+                        let (id, now) = (hdr.common_header.id(), cycles::rdtsc());
+                        let status = RpcStatus::StatusOk;
+                        assert!(mailbox.store(&tenant, master.heap(), id, status, &val, now));
+                    }
+                    respond(req, &val)
+                }
+            };
+            if !lose.contains(&i) {
+                socket.send_to(&res, src).expect("Server send failed");
+            }
+        }
+        (counter, master)
+    }
+
+    // Tests that an invocation whose response was lost is recovered off the mailbox without
+    // being run again, and that it is invoked again if the mailbox has nothing for it, or can't
+    // be reached.
+    #[test]
+    fn test_udp_invoke_stored() {
+        // Requests, in the order the stand-in sees them:
+        // 0: answered.
+        // 1: run, response lost. 2: fetch_result() finds it.
+        // 3: dropped. 4: fetch_result() finds nothing. 5: run again, answered.
+        // 6: run, response lost. 7: fetch_result() lost. 8: run again, answered.
+        let server = UdpSocket::bind("127.0.0.1:0").expect("Failed to bind server");
+        let server_port = server.local_addr().unwrap().port();
+        let handle = thread::spawn(move || {
+            let master = Master::new();
+            master.fill_test(1, 1, 0);
+            serve_mailbox(server, 9, vec![3], vec![1, 6, 7], master)
+        });
+
+        let (sender, receiver) =
+            udp_pipeline(&config(0, server_port), 7, 1).expect("Failed to setup udp pipeline");
+        let timeout = Duration::from_millis(200);
+        let mut recoveries = Recoveries::default();
+        let invoke = |recoveries: &mut Recoveries| -> u64 {
+            let (status, payload) =
+                invoke_stored(&sender, &receiver, 1, 4, b"incr", timeout, recoveries)
+                    .expect("Failed to invoke");
+            assert_eq!(RpcStatus::StatusOk, status);
+            let mut val = [0u8; 8];
+            val.copy_from_slice(&payload[..8]);
+            u64::from_le(unsafe { transmute(val) })
+        };
+
+        assert_eq!(1, invoke(&mut recoveries));
+        assert_eq!(2, invoke(&mut recoveries));
+        assert_eq!(3, invoke(&mut recoveries));
+        assert_eq!(5, invoke(&mut recoveries));
+        assert_eq!(
+            Recoveries {
+                answered: 1,
+                recovered: 1,
+                missing: 1,
+                unreachable: 1,
+            },
+            recoveries
+        );
+
+        // Every result that was never fetched is still in the mailbox. The fetch_result() whose
+        // response was lost still removed the last invocation's result, which it's retry stored
+        // again.
+        let (counter, master) = handle.join().expect("Server thread failed");
+        assert_eq!(5, counter);
+        let tenant = master.get_tenant(1).expect("Tenant does not exist");
+        let table = tenant
+            .get_table(MAILBOX_TABLE)
+            .expect("Mailbox does not exist");
+        let stats = table.cache_stats().expect("Mailbox is not a cache");
+        assert_eq!(3, stats.objects);
+        assert_eq!(0, stats.evicted);
+    }
+
+    // A loopback stand-in for the server that answers latency_stats() requests for the
+    // percentiles of opcodes with `ops`, and for a histogram with `hist` on a clock that ticks
+    // once a nanosecond. Refuses any other query. Runs until `n` requests have been handled.
+    fn serve_latency_stats(
+        socket: UdpSocket,
+        n: usize,
+        ops: Vec<(u8, LatencySummary)>,
+        hist: LogHistogram,
+    ) {
+        let mut buf = vec![0; MAX_RESPONSE_LEN];
+        for _ in 0..n {
+            let (len, src) = socket.recv_from(&mut buf).expect("Server recv failed");
+            let req: &LatencyStatsRequest =
+                unsafe { &*(buf[..len].as_ptr() as *const LatencyStatsRequest) };
+            let (tenant, id, query) = (
+                req.common_header.tenant(),
+                req.common_header.id(),
+                req.query,
+            );
+            let name = &buf[size_of::<LatencyStatsRequest>()..len];
+
+            let mut hdr = LatencyStatsResponse::new(id, 0, tenant);
+            hdr.set_cycles_per_second(1_000_000_000);
+            let (payload, num) = match query {
+                LATENCY_STATS_OPCODES => (rpc::encode_latency_summaries(&ops), ops.len() as u32),
+                LATENCY_STATS_HISTOGRAM if name == b"tao" => rpc::encode_latency_histogram(&hist),
+                _ => {
+                    hdr.common_header.status = RpcStatus::StatusMalformedRequest;
+                    (vec![], 0)
+                }
+            };
+            hdr.set_num_entries(num);
+            socket
+                .send_to(&encode(hdr, &[&payload]), src)
+                .expect("Server send failed");
+        }
+    }
+
+    // Tests that percentiles and histograms come back off latency_stats() responses, that a
+    // histogram is converted to nanoseconds off the server's clock, and that a refused request
+    // is an error.
+    #[test]
+    fn test_udp_latency_stats() {
+        let summary = LatencySummary {
+            count: 10,
+            p50: 1_000,
+            p99: 9_000,
+            p999: 9_000,
+        };
+        let ops = vec![(OpCode::SandstormGetRpc as u8, summary)];
+        let mut hist = LogHistogram::new();
+        hist.record(1_000);
+        hist.record(1_000);
+        hist.record(40_000);
+
+        let server = UdpSocket::bind("127.0.0.1:0").expect("Failed to bind server");
+        let server_port = server.local_addr().unwrap().port();
+        let (served, expected) = (ops.clone(), hist.to_nanos(1_000_000_000));
+        let handle = thread::spawn(move || serve_latency_stats(server, 3, served, hist));
+
+        let (sender, receiver) =
+            udp_pipeline(&config(0, server_port), 6, 1).expect("Failed to setup udp pipeline");
+        let timeout = Duration::from_secs(5);
+        assert_eq!(
+            ops,
+            latency_stats(&sender, &receiver, timeout).expect("Failed to fetch latencies")
+        );
+
+        let fetched = latency_histogram(
+            &sender,
+            &receiver,
+            OpCode::SandstormInvokeRpc,
+            b"tao",
+            timeout,
+        ).expect("Failed to fetch histogram");
+        assert_eq!(expected, fetched);
+        assert_eq!(3, fetched.total());
+
+        let refused = ext_latency_stats(&sender, &receiver, timeout).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, refused.kind());
+
+        handle.join().expect("Server thread failed");
+    }
+
+    // Tests that a put() or invoke() request built off segments is byte for byte the request
+    // built off the concatenated segments, however the segments are split.
+    #[test]
+    fn test_encode_gather() {
+        let val: Vec<u8> = (0..1024).map(|i| i as u8).collect();
+        let splits: Vec<Vec<&[u8]>> = vec![
+            vec![&val[..]],
+            vec![&val[..16], &val[16..1020], &val[1020..]],
+            vec![
+                &val[..0],
+                &val[..512],
+                &val[512..512],
+                &val[512..],
+                &val[1024..],
+            ],
+            vec![&val[..0], &val[..]],
+        ];
+
+        let put = encode_put(7, 9, b"key", &val, 11, 13);
+        let invoke = encode_invoke(7, 3, &val, 11, 13);
+        for segments in splits.iter() {
+            assert_eq!(put, encode_put_gather(7, 9, b"key", segments, 11, 13));
+            assert_eq!(invoke, encode_invoke_gather(7, 3, segments, 11, 13));
+        }
+
+        // Nothing but empty segments is an empty value.
+        assert_eq!(
+            encode_put(7, 9, b"key", &[], 11, 13),
+            encode_put_gather(7, 9, b"key", &[&val[..0], &val[..0]], 11, 13)
+        );
+    }
+
+    // Tests that an invoke() naming the extension by id is the invoke() naming it by the 4 byte
+    // id, with only INVOKE_FLAG_BY_ID set on top.
+    #[test]
+    fn test_encode_invoke_by_id() {
+        let req = encode_invoke_by_id(7, 0x01020304, b"args", 11, 13);
+        let mut by_name = encode_invoke(7, 4, b"\x04\x03\x02\x01args", 11, 13);
+        assert_eq!(by_name.len(), req.len());
+        by_name[size_of::<InvokeRequest>() - 1] |= INVOKE_FLAG_BY_ID;
+        assert_eq!(by_name, req);
+    }
+
+    #[test]
+    fn test_response_too_short() {
+        let res = Response::new(vec![1, 1]);
+        assert_eq!(OpCode::InvalidOperation, res.opcode());
+        assert!(res.parse_header::<GetResponse>().is_none());
+    }
+
+    #[test]
+    fn test_response_draining() {
+        let mut hdr = RpcResponseHeader::new(1, 0, OpCode::SandstormGetRpc, 1);
+        hdr.status = RpcStatus::StatusServerDraining;
+        let res = Response::new(encode(hdr, &[]));
+        let p = res.parse_header::<RpcResponseHeader>().unwrap();
+        assert_eq!(RpcStatus::StatusServerDraining, p.get_header().status);
+
+        let mut buf = res.into_inner();
+        buf[0] = RpcStatus::StatusRwSetTooLarge as u8 + 1;
+        assert!(Response::new(buf).parse_header::<RpcResponseHeader>().is_none());
+    }
+
+    // Tests that a trace trailer is read off a get() response without changing how the value
+    // is read, and that the flag asking for it is set on the request.
+    #[test]
+    fn test_response_trailers() {
+        let mut req = encode_get(1, 1, b"key", 1, 0);
+        mark_trace(&mut req);
+        assert_eq!(REQUEST_FLAG_TRACE, req[REQUEST_FLAGS_OFFSET]);
+
+        let trace = Trace {
+            rx: 10,
+            tx: 20,
+            hz: 1000,
+        };
+        let mut trailers = TrailerWriter::new(64);
+        trailers.append(TRAILER_TRACE, &trace.encode());
+        let mut hdr = GetResponse::new(1, 0, OpCode::SandstormGetRpc, 1);
+        hdr.set_value_length(4);
+        hdr.common_header.flags = RESPONSE_FLAG_TRAILERS;
+        let res = Response::new(encode(hdr, &[b"abcd", &trailers.finish()]));
+        assert!(res.length_ok());
+
+        let trailers = res.trailers().expect("Malformed trailers");
+        let got = trailers.get(TRAILER_TRACE).and_then(Trace::parse);
+        assert_eq!(Some(trace), got);
+        let p = res.parse_header::<GetResponse>().unwrap();
+        let len = p.get_header().value_length() as usize;
+        assert_eq!(b"abcd", &p.get_payload()[..len]);
+
+        let hdr = GetResponse::new(1, 0, OpCode::SandstormGetRpc, 1);
+        let res = Response::new(encode(hdr, &[]));
+        assert_eq!(0, res.trailers().unwrap().iter().count());
+        assert!(Response::new(vec![1, 1]).trailers().is_none());
+    }
+}
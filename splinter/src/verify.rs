@@ -0,0 +1,678 @@
+/* Copyright (c) 2019 University of Utah
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR(S) DISCLAIM ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL AUTHORS BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use std::collections::{HashMap, VecDeque};
+
+use db::wireformat::RpcStatus;
+
+/// The class of operation a request belonged to. Lets a workload's verify()
+/// decide how to interpret a response payload without re-parsing headers.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OpClass {
+    /// A native get() request.
+    Get,
+
+    /// A native put() request.
+    Put,
+
+    /// A native multiget() request.
+    MultiGet,
+
+    /// An invoke() request, or a pushed back invoke() completed on the client.
+    Invoke,
+}
+
+/// Everything the framework knows about a request at the time its response
+/// is handed to a workload for verification.
+#[derive(Clone, Debug)]
+pub struct RequestMeta {
+    /// The id the request was sent out with. Used to match the response.
+    pub id: u64,
+
+    /// The time-stamp the request was sent out at.
+    pub stamp: u64,
+
+    /// The tenant the request was issued on behalf of.
+    pub tenant: u32,
+
+    /// The class of operation the request belonged to.
+    pub op: OpClass,
+
+    /// Whatever the workload stashed at send time (ex: the key it looked up).
+    pub stash: Vec<u8>,
+}
+
+/// Implemented by the entries a RequestStash can hold.
+pub trait Stashable {
+    /// Returns the id of the request the entry belongs to.
+    fn id(&self) -> u64;
+
+    /// Returns the time-stamp (in cycles) the request was sent out at.
+    fn stamp(&self) -> u64;
+}
+
+impl Stashable for RequestMeta {
+    fn id(&self) -> u64 {
+        self.id
+    }
+
+    fn stamp(&self) -> u64 {
+        self.stamp
+    }
+}
+
+/// The outcome of verifying a single response.
+#[derive(Clone, Debug, PartialEq)]
+pub enum VerifyOutcome {
+    /// The response is what the workload expected.
+    Ok,
+
+    /// The response is wrong, but not wrong enough to stop the run. These are
+    /// counted, and logged at debug level.
+    SoftFail(String),
+
+    /// The response is wrong enough that continuing the run makes no sense.
+    HardFail(String),
+}
+
+/// Implemented by workloads that want to check responses before the client
+/// records their latency. The default implementation accepts everything.
+pub trait Verify {
+    /// Verifies a completed response.
+    ///
+    /// # Arguments
+    ///
+    /// * `req_meta`: Metadata about the request this is a response to.
+    /// * `status`:   The status the response completed with.
+    /// * `payload`:  The payload on the response, if any.
+    ///
+    /// # Return
+    ///
+    /// The outcome of the verification.
+    fn verify(
+        &mut self,
+        _req_meta: &RequestMeta,
+        _status: RpcStatus,
+        _payload: &[u8],
+    ) -> VerifyOutcome {
+        VerifyOutcome::Ok
+    }
+}
+
+/// A bounded, per-request stash of metadata keyed on the request's id.
+/// Entries are inserted at send time, and taken out when a response (or
+/// pushed back task) completes. Entries whose responses never arrive are
+/// reclaimed once they are older than the configured timeout, or once the
+/// stash is full. Entries are RequestMeta unless another Stashable is given.
+pub struct RequestStash<M = RequestMeta> {
+    // Maximum number of requests that can be stashed at any point of time.
+    capacity: usize,
+
+    // Number of cycles after which a stashed request is considered lost.
+    timeout: u64,
+
+    // Stashed metadata, keyed on the request id.
+    entries: HashMap<u64, M>,
+
+    // Ids in the order they were inserted. Used to reclaim the oldest entries.
+    order: VecDeque<u64>,
+
+    // Number of entries reclaimed so far because they timed out or the stash was full.
+    reclaimed: u64,
+}
+
+impl<M: Stashable> RequestStash<M> {
+    /// Creates a new stash.
+    ///
+    /// # Arguments
+    ///
+    /// * `capacity`: The maximum number of outstanding requests to stash.
+    /// * `timeout`:  Cycles after which a request without a response is reclaimed.
+    ///
+    /// # Return
+    ///
+    /// An empty stash.
+    pub fn new(capacity: usize, timeout: u64) -> RequestStash<M> {
+        RequestStash {
+            capacity: capacity,
+            timeout: timeout,
+            entries: HashMap::with_capacity(capacity),
+            order: VecDeque::with_capacity(capacity),
+            reclaimed: 0,
+        }
+    }
+
+    /// Stashes metadata for a request that is about to be sent out. If the
+    /// stash is full, the oldest entry is reclaimed to make room.
+    ///
+    /// # Arguments
+    ///
+    /// * `meta`: Metadata for the request. `meta.id()` is used as the key.
+    pub fn insert(&mut self, meta: M) {
+        while self.entries.len() >= self.capacity {
+            match self.order.pop_front() {
+                Some(id) => {
+                    if self.entries.remove(&id).is_some() {
+                        self.reclaimed += 1;
+                    }
+                }
+
+                None => break,
+            }
+        }
+
+        self.order.push_back(meta.id());
+        self.entries.insert(meta.id(), meta);
+    }
+
+    /// Removes and returns the metadata stashed for a request.
+    ///
+    /// # Arguments
+    ///
+    /// * `id`: The id on the response.
+    ///
+    /// # Return
+    ///
+    /// The stashed metadata if it hasn't been reclaimed yet.
+    pub fn take(&mut self, id: u64) -> Option<M> {
+        self.entries.remove(&id)
+    }
+
+    /// Reclaims all entries that were stashed more than `timeout` cycles
+    /// before `now`. Stamps are rdtsc() values, so they double as send times.
+    ///
+    /// # Arguments
+    ///
+    /// * `now`: The current time stamp in cycles.
+    ///
+    /// # Return
+    ///
+    /// The number of entries reclaimed by this call.
+    pub fn reclaim(&mut self, now: u64) -> u64 {
+        let mut count = 0;
+        while let Some(&id) = self.order.front() {
+            // Entries that were already taken only need to be popped off.
+            let stamp = match self.entries.get(&id) {
+                Some(meta) => meta.stamp(),
+
+                None => {
+                    self.order.pop_front();
+                    continue;
+                }
+            };
+
+            if now.saturating_sub(stamp) <= self.timeout {
+                break;
+            }
+
+            self.order.pop_front();
+            self.entries.remove(&id);
+            count += 1;
+        }
+
+        self.reclaimed += count;
+        count
+    }
+
+    /// Returns the number of requests currently stashed.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns the total number of entries reclaimed so far.
+    pub fn reclaimed(&self) -> u64 {
+        self.reclaimed
+    }
+}
+
+/// Runs a workload's verify() on completed responses, and keeps track of
+/// the outcomes. Aborts the run on a hard failure.
+pub struct Verifier {
+    // Per request metadata stashed at send time.
+    stash: RequestStash,
+
+    // Number of responses that passed verification.
+    passed: u64,
+
+    // Number of responses that soft failed verification.
+    soft_failed: u64,
+
+    // Buffers of entries that were verified, reused by stash() instead of allocating one per
+    // request. Holds atmost as many buffers as the stash holds entries.
+    spare: Vec<Vec<u8>>,
+}
+
+impl Verifier {
+    /// Creates a new verifier.
+    ///
+    /// # Arguments
+    ///
+    /// * `capacity`: The maximum number of outstanding requests to stash.
+    /// * `timeout`:  Cycles after which a request without a response is reclaimed.
+    pub fn new(capacity: usize, timeout: u64) -> Verifier {
+        Verifier {
+            stash: RequestStash::new(capacity, timeout),
+            passed: 0,
+            soft_failed: 0,
+            spare: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Stashes metadata for a request about to be sent out.
+    ///
+    /// # Arguments
+    ///
+    /// * `id`:     The id the request will be sent out with.
+    /// * `stamp`:  The time-stamp the request will be sent out with.
+    /// * `tenant`: The tenant the request is issued on behalf of.
+    /// * `op`:     The class of the request.
+    /// * `stash`:  Workload specific state required to verify the response.
+    pub fn stash(&mut self, id: u64, stamp: u64, tenant: u32, op: OpClass, stash: &[u8]) {
+        let mut buf = self.spare.pop().unwrap_or_else(Vec::new);
+        buf.clear();
+        buf.extend_from_slice(stash);
+
+        self.stash.insert(RequestMeta {
+            id: id,
+            stamp: stamp,
+            tenant: tenant,
+            op: op,
+            stash: buf,
+        });
+    }
+
+    /// Verifies a completed response. Must be called before the response's
+    /// latency is recorded.
+    ///
+    /// # Arguments
+    ///
+    /// * `workload`: The workload whose verify() should be run.
+    /// * `id`:       The id on the response.
+    /// * `status`:   The status on the response.
+    /// * `payload`:  The payload on the response.
+    ///
+    /// # Return
+    ///
+    /// The outcome of verification. Responses to requests that were never
+    /// stashed (or have been reclaimed) are verified with empty metadata.
+    /// Panics if the workload reports a hard failure.
+    pub fn check<W: Verify>(
+        &mut self,
+        workload: &mut W,
+        id: u64,
+        status: RpcStatus,
+        payload: &[u8],
+    ) -> VerifyOutcome {
+        let meta = self.stash.take(id).unwrap_or(RequestMeta {
+            id: id,
+            stamp: 0,
+            tenant: 0,
+            op: OpClass::Invoke,
+            stash: Vec::new(),
+        });
+
+        let outcome = workload.verify(&meta, status.clone(), payload);
+        match outcome {
+            VerifyOutcome::Ok => self.passed += 1,
+
+            VerifyOutcome::SoftFail(ref reason) => {
+                self.soft_failed += 1;
+                debug!(
+                    "Verification failed for request {} tenant {} op {:?}: {}",
+                    meta.id, meta.tenant, meta.op, reason
+                );
+            }
+
+            VerifyOutcome::HardFail(ref reason) => {
+                panic!(
+                    "Verification aborted the run on request {} tenant {} op {:?} status {:?} \
+                     ({} passed, {} soft failures): {}",
+                    meta.id, meta.tenant, meta.op, status, self.passed, self.soft_failed, reason
+                );
+            }
+        }
+
+        if meta.stash.capacity() > 0 && self.spare.len() < self.spare.capacity() {
+            self.spare.push(meta.stash);
+        }
+
+        outcome
+    }
+
+    /// Reclaims stashed metadata for requests that have timed out.
+    ///
+    /// # Arguments
+    ///
+    /// * `now`: The current time stamp in cycles.
+    pub fn reclaim(&mut self, now: u64) -> u64 {
+        self.stash.reclaim(now)
+    }
+
+    /// Returns the number of responses that passed verification.
+    pub fn passed(&self) -> u64 {
+        self.passed
+    }
+
+    /// Returns the number of responses that soft failed verification.
+    pub fn soft_failed(&self) -> u64 {
+        self.soft_failed
+    }
+
+    /// Returns the number of stash entries reclaimed without a response.
+    pub fn reclaimed(&self) -> u64 {
+        self.stash.reclaimed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+
+    use db::master::Master;
+
+    use super::super::manager::ManagerPool;
+
+    // A global allocator that counts the allocations made by each thread. Lets a test assert
+    // that the client's per-response bookkeeping doesn't allocate once it has warmed up.
+    mod alloc {
+        use std::alloc::{GlobalAlloc, Layout, System};
+        use std::cell::Cell;
+
+        thread_local!(static ALLOCS: Cell<usize> = Cell::new(0));
+
+        pub struct Counting;
+
+        unsafe impl GlobalAlloc for Counting {
+            unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+                let _ = ALLOCS.try_with(|a| a.set(a.get() + 1));
+                System.alloc(layout)
+            }
+
+            unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+                System.dealloc(ptr, layout)
+            }
+        }
+
+        #[global_allocator]
+        static GLOBAL: Counting = Counting;
+
+        // Returns the number of allocations made by this thread so far.
+        pub fn count() -> usize {
+            ALLOCS.with(|a| a.get())
+        }
+    }
+
+    // A stubbed transport that hands out crafted responses instead of
+    // polling a network port.
+    struct StubTransport {
+        responses: VecDeque<(u64, RpcStatus, Vec<u8>)>,
+    }
+
+    impl StubTransport {
+        fn recv(&mut self) -> Option<(u64, RpcStatus, Vec<u8>)> {
+            self.responses.pop_front()
+        }
+    }
+
+    // Expects every payload to equal whatever was stashed at send time, and
+    // hard fails on anything that didn't complete with StatusOk.
+    struct EchoWorkload;
+
+    impl Verify for EchoWorkload {
+        fn verify(
+            &mut self,
+            meta: &RequestMeta,
+            status: RpcStatus,
+            payload: &[u8],
+        ) -> VerifyOutcome {
+            match status {
+                RpcStatus::StatusOk => {}
+                _ => return VerifyOutcome::HardFail(String::from("bad status")),
+            }
+
+            if meta.stash.as_slice() == payload {
+                VerifyOutcome::Ok
+            } else {
+                VerifyOutcome::SoftFail(String::from("payload mismatch"))
+            }
+        }
+    }
+
+    fn drain(verifier: &mut Verifier, transport: &mut StubTransport) -> Vec<VerifyOutcome> {
+        let mut workload = EchoWorkload;
+        let mut outcomes = Vec::new();
+        while let Some((id, status, payload)) = transport.recv() {
+            outcomes.push(verifier.check(&mut workload, id, status, &payload));
+        }
+        outcomes
+    }
+
+    #[test]
+    fn test_verify_ok() {
+        let mut verifier = Verifier::new(8, 100);
+        verifier.stash(1, 0, 1, OpClass::Get, &[1, 2, 3]);
+
+        let mut transport = StubTransport {
+            responses: vec![(1, RpcStatus::StatusOk, vec![1, 2, 3])].into_iter().collect(),
+        };
+
+        assert_eq!(vec![VerifyOutcome::Ok], drain(&mut verifier, &mut transport));
+        assert_eq!(1, verifier.passed());
+        assert_eq!(0, verifier.soft_failed());
+    }
+
+    #[test]
+    fn test_verify_soft_fail() {
+        let mut verifier = Verifier::new(8, 100);
+        verifier.stash(1, 0, 1, OpClass::Get, &[1, 2, 3]);
+        verifier.stash(2, 0, 1, OpClass::Get, &[4, 5, 6]);
+
+        let mut transport = StubTransport {
+            responses: vec![
+                (1, RpcStatus::StatusOk, vec![1, 2, 3]),
+                (2, RpcStatus::StatusOk, vec![0, 0, 0]),
+            ].into_iter()
+            .collect(),
+        };
+
+        let outcomes = drain(&mut verifier, &mut transport);
+        assert_eq!(VerifyOutcome::Ok, outcomes[0]);
+        assert_eq!(
+            VerifyOutcome::SoftFail(String::from("payload mismatch")),
+            outcomes[1]
+        );
+        assert_eq!(1, verifier.passed());
+        assert_eq!(1, verifier.soft_failed());
+    }
+
+    #[test]
+    #[should_panic(expected = "bad status")]
+    fn test_verify_hard_fail() {
+        let mut verifier = Verifier::new(8, 100);
+        verifier.stash(1, 0, 1, OpClass::Invoke, &[1]);
+
+        let mut transport = StubTransport {
+            responses: vec![(1, RpcStatus::StatusInternalError, vec![])]
+                .into_iter()
+                .collect(),
+        };
+
+        drain(&mut verifier, &mut transport);
+    }
+
+    #[test]
+    fn test_stash_bounded() {
+        let mut stash = RequestStash::new(2, 1000);
+        for id in 0..4 {
+            stash.insert(RequestMeta {
+                id: id,
+                stamp: 0,
+                tenant: 1,
+                op: OpClass::Get,
+                stash: vec![],
+            });
+        }
+
+        assert_eq!(2, stash.len());
+        assert_eq!(2, stash.reclaimed());
+        assert!(stash.take(0).is_none());
+        assert!(stash.take(3).is_some());
+    }
+
+    #[test]
+    fn test_stash_reclaim_timeout() {
+        let mut stash = RequestStash::new(8, 10);
+        for (id, stamp) in vec![(1, 100), (2, 105), (3, 200)] {
+            stash.insert(RequestMeta {
+                id: id,
+                stamp: stamp,
+                tenant: 1,
+                op: OpClass::Get,
+                stash: vec![],
+            });
+        }
+
+        // The entry at 105 completes; the one at 100 never does.
+        assert!(stash.take(2).is_some());
+        assert_eq!(1, stash.reclaim(205));
+        assert_eq!(1, stash.len());
+        assert!(stash.take(3).is_some());
+    }
+
+    // Tests that two requests sent out at the same time-stamp are told apart by their ids.
+    #[test]
+    fn test_verify_same_stamp() {
+        let mut verifier = Verifier::new(8, 100);
+        verifier.stash(1, 50, 1, OpClass::Get, &[1, 2, 3]);
+        verifier.stash(2, 50, 1, OpClass::Get, &[4, 5, 6]);
+
+        let mut transport = StubTransport {
+            responses: vec![
+                (2, RpcStatus::StatusOk, vec![4, 5, 6]),
+                (1, RpcStatus::StatusOk, vec![1, 2, 3]),
+            ].into_iter()
+            .collect(),
+        };
+
+        let outcomes = drain(&mut verifier, &mut transport);
+        assert_eq!(vec![VerifyOutcome::Ok, VerifyOutcome::Ok], outcomes);
+        assert_eq!(2, verifier.passed());
+        assert_eq!(0, verifier.reclaimed());
+    }
+
+    // Verifies responses the way the AUTH client does, copying the stashed key into a scratch
+    // buffer instead of allocating one per response.
+    struct ScratchWorkload {
+        password: [u8; 72],
+    }
+
+    impl Verify for ScratchWorkload {
+        fn verify(
+            &mut self,
+            meta: &RequestMeta,
+            _status: RpcStatus,
+            payload: &[u8],
+        ) -> VerifyOutcome {
+            let len = meta.stash.len();
+            self.password[0..len].copy_from_slice(&meta.stash);
+            for b in self.password[len..].iter_mut() {
+                *b = 0;
+            }
+
+            if &self.password[0..payload.len()] == payload {
+                VerifyOutcome::Ok
+            } else {
+                VerifyOutcome::SoftFail(String::from("payload mismatch"))
+            }
+        }
+    }
+
+    // Tests that stashing and verifying native get() responses doesn't allocate once the
+    // verifier has warmed up.
+    #[test]
+    fn test_verify_no_alloc() {
+        let mut verifier = Verifier::new(64, 1 << 40);
+        let mut workload = ScratchWorkload { password: [0; 72] };
+        let key = [7u8; 30];
+
+        let mut run = |verifier: &mut Verifier, from: u64| {
+            for id in from..(from + 1000) {
+                verifier.stash(id, id, 1, OpClass::Get, &key);
+                if id >= from + 32 {
+                    let outcome = verifier.check(&mut workload, id - 32, RpcStatus::StatusOk, &key);
+                    assert_eq!(VerifyOutcome::Ok, outcome);
+                }
+                verifier.reclaim(id);
+            }
+            for id in (from + 1000 - 32)..(from + 1000) {
+                verifier.check(&mut workload, id, RpcStatus::StatusOk, &key);
+            }
+        };
+
+        run(&mut verifier, 0);
+        let before = alloc::count();
+        run(&mut verifier, 1000);
+        assert_eq!(before, alloc::count());
+        assert_eq!(2000, verifier.passed());
+    }
+
+    // Tests that the bookkeeping for invoke() requests, a TaskManager and a stash entry per
+    // request, doesn't allocate once it has warmed up.
+    #[test]
+    fn test_invoke_no_alloc() {
+        let mut verifier = Verifier::new(64, 1 << 40);
+        let mut workload = EchoWorkload;
+        let mut pool = ManagerPool::new(Arc::new(Master::new()), 64);
+        let mut managers = HashMap::with_capacity(64);
+        let mut req = vec![0u8; 118];
+
+        let mut run = |verifier: &mut Verifier, pool: &mut ManagerPool, from: u64| {
+            for id in from..(from + 1000) {
+                req[12] = id as u8;
+                managers.insert(id, pool.get(&req, 1, 4, id, id));
+                verifier.stash(id, id, 1, OpClass::Invoke, &req[12..16]);
+                if id >= from + 32 {
+                    let done = id - 32;
+                    verifier.check(
+                        &mut workload,
+                        done,
+                        RpcStatus::StatusOk,
+                        &[done as u8, 0, 0, 0],
+                    );
+                    pool.put(managers.remove(&done).unwrap());
+                }
+                verifier.reclaim(id);
+            }
+            for done in (from + 1000 - 32)..(from + 1000) {
+                verifier.check(
+                    &mut workload,
+                    done,
+                    RpcStatus::StatusOk,
+                    &[done as u8, 0, 0, 0],
+                );
+                pool.put(managers.remove(&done).unwrap());
+            }
+        };
+
+        run(&mut verifier, &mut pool, 0);
+        let before = alloc::count();
+        run(&mut verifier, &mut pool, 1000);
+        assert_eq!(before, alloc::count());
+        assert_eq!(2000, verifier.passed());
+        assert_eq!(33, pool.len());
+    }
+}